@@ -0,0 +1,546 @@
+﻿use std::cell::RefCell;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Context, Result};
+use axum::body::Body;
+use axum::http::{header, Request, StatusCode};
+use axum::Router;
+use retasync_contract::schemas::{EmergencyActionMessage, MeshCommandEnvelope, MeshResultEnvelope};
+use retasync_contract::{decode_canonical, encode_canonical};
+use retasync_control_plane::{build_router, AppState, NodeConfig};
+use retasync_mesh_bridge::InMemoryRpcMeshBridge;
+use retasync_storage::{RetasyncStorage, StorageConfig};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tower::ServiceExt;
+
+const BENCH_OPERATION: &str = "emergency_action_message.create";
+const LARGE_LIST_LEN: usize = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum BenchSuite {
+    Storage,
+    Codec,
+    Http,
+    All,
+}
+
+impl BenchSuite {
+    fn includes(self, suite: BenchSuite) -> bool {
+        self == BenchSuite::All || self == suite
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct BenchOptions {
+    pub suite: BenchSuite,
+    // Time budget for each suite, shared evenly by its cases.
+    pub duration: Duration,
+    pub storage_path: Option<PathBuf>,
+    pub allow_live: bool,
+}
+
+// What the benchmarks run against: the configured database and the contract.
+#[derive(Debug, Clone)]
+pub(crate) struct BenchTarget {
+    pub sqlite_path: String,
+    pub encryption_key_path: Option<String>,
+    pub read_pool_size: u32,
+    pub contract_doc: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct BenchResult {
+    pub suite: String,
+    pub case: String,
+    pub ops: u64,
+    pub ops_per_sec: f64,
+    pub p50_us: u64,
+    pub p95_us: u64,
+    pub p99_us: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bytes_per_op: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct BenchReport {
+    pub version: String,
+    pub os: String,
+    pub arch: String,
+    pub cpus: usize,
+    pub duration_ms: u64,
+    pub results: Vec<BenchResult>,
+}
+
+pub(crate) fn parse_duration(raw: &str) -> Result<Duration> {
+    let split = raw.find(|c: char| !c.is_ascii_digit()).unwrap_or(raw.len());
+    let (amount, unit) = raw.split_at(split);
+    let amount: u64 = amount
+        .parse()
+        .map_err(|_| anyhow!("--duration {raw:?} is not a duration like 30s"))?;
+    match unit {
+        "ms" => Ok(Duration::from_millis(amount)),
+        "s" | "" => Ok(Duration::from_secs(amount)),
+        "m" => Ok(Duration::from_secs(amount * 60)),
+        _ => bail!("--duration {raw:?} has an unknown unit; use ms, s or m"),
+    }
+}
+
+fn plain_path(sqlite_path: &str) -> &Path {
+    Path::new(
+        sqlite_path
+            .trim_start_matches("sqlite://")
+            .trim_start_matches("sqlite:"),
+    )
+}
+
+// A scratch database beside the live one, so the benchmark sees the same disk.
+fn scratch_path(sqlite_path: &str, label: &str) -> PathBuf {
+    let live = plain_path(sqlite_path);
+    let stem = live
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "retasync".to_string());
+    live.with_file_name(format!(
+        "{stem}.bench-{label}-{}.sqlite",
+        std::process::id()
+    ))
+}
+
+fn same_file(left: &Path, right: &Path) -> bool {
+    match (left.canonicalize(), right.canonicalize()) {
+        (Ok(left), Ok(right)) => left == right,
+        _ => left == right,
+    }
+}
+
+// Removes a scratch database and its journal files when dropped.
+struct ScratchDatabase(PathBuf);
+
+impl Drop for ScratchDatabase {
+    fn drop(&mut self) {
+        for suffix in ["", "-journal", "-wal", "-shm"] {
+            let mut path = self.0.clone().into_os_string();
+            path.push(suffix);
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+async fn open_database(
+    target: &BenchTarget,
+    path: &Path,
+    scratch: bool,
+) -> Result<(RetasyncStorage, Option<ScratchDatabase>)> {
+    let guard = scratch.then(|| ScratchDatabase(path.to_path_buf()));
+    let storage = RetasyncStorage::connect(&StorageConfig {
+        sqlite_path: path.to_string_lossy().into_owned(),
+        encryption_key_path: target.encryption_key_path.clone(),
+        read_pool_size: target.read_pool_size,
+    })
+    .await
+    .with_context(|| format!("failed to open benchmark database {}", path.display()))?;
+    Ok((storage, guard))
+}
+
+// Runs `op` back to back until `budget` is spent, at least once, timing every call.
+async fn measure<F, Fut>(budget: Duration, mut op: F) -> Result<(Vec<Duration>, Duration)>
+where
+    F: FnMut(usize) -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    let started = Instant::now();
+    let mut latencies = Vec::new();
+    loop {
+        let call = Instant::now();
+        op(latencies.len()).await?;
+        latencies.push(call.elapsed());
+        if started.elapsed() >= budget {
+            return Ok((latencies, started.elapsed()));
+        }
+    }
+}
+
+fn percentile(sorted: &[Duration], percent: usize) -> u64 {
+    let index = (sorted.len() * percent).div_ceil(100).saturating_sub(1);
+    sorted
+        .get(index.min(sorted.len().saturating_sub(1)))
+        .map_or(0, |latency| latency.as_micros() as u64)
+}
+
+fn summarize(
+    suite: &str,
+    case: &str,
+    (mut latencies, elapsed): (Vec<Duration>, Duration),
+    bytes_per_op: Option<usize>,
+) -> BenchResult {
+    latencies.sort_unstable();
+    BenchResult {
+        suite: suite.to_string(),
+        case: case.to_string(),
+        ops: latencies.len() as u64,
+        ops_per_sec: latencies.len() as f64 / elapsed.as_secs_f64().max(f64::EPSILON),
+        p50_us: percentile(&latencies, 50),
+        p95_us: percentile(&latencies, 95),
+        p99_us: percentile(&latencies, 99),
+        bytes_per_op: bytes_per_op.map(|bytes| bytes as u64),
+    }
+}
+
+fn example_message() -> Value {
+    serde_json::to_value(EmergencyActionMessage::example()).unwrap_or(Value::Null)
+}
+
+// Small, medium and large payloads built from the contract's examples: one message, a
+// command envelope carrying it, and a result envelope listing many of them.
+fn codec_payloads() -> Vec<(&'static str, Value)> {
+    let message = example_message();
+    let command = MeshCommandEnvelope {
+        message_id: "0190f1b4-8a5c-7d3e-9f21-4c6b8e2d1a07".to_string(),
+        operation: BENCH_OPERATION.to_string(),
+        sent_at: "2026-03-01T10:00:00Z".to_string(),
+        source_identity: "a1b2c3d4e5f60718293a4b5c6d7e8f90".to_string(),
+        destination_identity: "0f1e2d3c4b5a69788796a5b4c3d2e1f0".to_string(),
+        payload: message.clone(),
+        ttl_ms: Some(30_000),
+        ..MeshCommandEnvelope::example()
+    };
+    let result = MeshResultEnvelope {
+        message_id: "0190f1b4-8a5c-7d3e-9f21-4c6b8e2d1a08".to_string(),
+        correlation_id: command.message_id.clone(),
+        operation: "emergency_action_message.list".to_string(),
+        sent_at: command.sent_at.clone(),
+        source_identity: command.destination_identity.clone(),
+        destination_identity: command.source_identity.clone(),
+        payload: Value::Array(vec![message.clone(); LARGE_LIST_LEN]),
+        ..MeshResultEnvelope::example()
+    };
+    vec![
+        ("small", message),
+        (
+            "medium",
+            serde_json::to_value(command).unwrap_or(Value::Null),
+        ),
+        ("large", serde_json::to_value(result).unwrap_or(Value::Null)),
+    ]
+}
+
+async fn bench_codec(budget: Duration) -> Result<Vec<BenchResult>> {
+    let payloads = codec_payloads();
+    let slice = budget / (payloads.len() as u32 * 2);
+    let mut results = Vec::new();
+    for (size, payload) in &payloads {
+        let encoded = encode_canonical(payload)?;
+        let timings = measure(slice, |_| async {
+            encode_canonical(payload)?;
+            Ok(())
+        })
+        .await?;
+        results.push(summarize(
+            "codec",
+            &format!("encode_{size}"),
+            timings,
+            Some(encoded.len()),
+        ));
+        let timings = measure(slice, |_| async {
+            decode_canonical::<Value>(&encoded)?;
+            Ok(())
+        })
+        .await?;
+        results.push(summarize(
+            "codec",
+            &format!("decode_{size}"),
+            timings,
+            Some(encoded.len()),
+        ));
+    }
+    Ok(results)
+}
+
+async fn bench_storage(storage: &RetasyncStorage, budget: Duration) -> Result<Vec<BenchResult>> {
+    let slice = budget / 3;
+    let payload = &example_message();
+    let created = RefCell::new(Vec::new());
+    let timings = measure(slice, |_| async {
+        let job = storage.create_job(BENCH_OPERATION, payload.clone()).await?;
+        created.borrow_mut().push(job.job_id);
+        Ok(())
+    })
+    .await?;
+    let mut results = vec![summarize("storage", "job_create", timings, None)];
+
+    let job_ids = &created.into_inner();
+    let timings = measure(slice, |call| async move {
+        let job_id = &job_ids[call % job_ids.len()];
+        Ok(storage.update_job_status(job_id, "running", None).await?)
+    })
+    .await?;
+    results.push(summarize("storage", "job_update", timings, None));
+
+    let result = json!({ "status": "ok", "items": [example_message()] });
+    let result = &result;
+    let timings = measure(slice, |call| async move {
+        let job_id = &job_ids[call % job_ids.len()];
+        Ok(storage.insert_job_result(job_id, result.clone()).await?)
+    })
+    .await?;
+    results.push(summarize("storage", "job_result", timings, None));
+    Ok(results)
+}
+
+async fn send(router: &Router, request: Request<Body>) -> Result<Value> {
+    let response = router.clone().oneshot(request).await?;
+    let status = response.status();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await?;
+    if !(status.is_success() || status == StatusCode::SERVICE_UNAVAILABLE) {
+        bail!("{status}: {}", String::from_utf8_lossy(&body));
+    }
+    Ok(serde_json::from_slice(&body).unwrap_or(Value::Null))
+}
+
+fn get(uri: &str) -> Result<Request<Body>> {
+    Ok(Request::get(uri).body(Body::empty())?)
+}
+
+// The hot endpoints, served by the in-process router with an in-memory bridge.
+async fn bench_http(
+    storage: RetasyncStorage,
+    target: &BenchTarget,
+    budget: Duration,
+) -> Result<Vec<BenchResult>> {
+    let config: NodeConfig = serde_json::from_value(json!({
+        "rpc_endpoint": "tcp://127.0.0.1:31337",
+        "http_bind": "127.0.0.1:0",
+        "http_auth_token": null,
+        "sqlite_path": "",
+        "acl_mode": "allowlist",
+        "prefer_link": true
+    }))?;
+    let bridge = Arc::new(InMemoryRpcMeshBridge::new(true, true));
+    let state = AppState::new(
+        storage.clone(),
+        bridge,
+        config,
+        target.contract_doc.clone(),
+        false,
+    );
+    let router = build_router(state);
+    let slice = budget / 4;
+    let mut results = Vec::new();
+
+    for (case, uri) in [
+        ("health_live", "/health/live"),
+        ("node_status", "/v1/node/status"),
+    ] {
+        let router = &router;
+        let timings = measure(slice, |_| async move {
+            send(router, get(uri)?).await?;
+            Ok(())
+        })
+        .await?;
+        results.push(summarize("http", case, timings, None));
+    }
+
+    let body = &example_message().to_string();
+    let submitted = RefCell::new(Vec::new());
+    let timings = measure(slice, |_| async {
+        let request = Request::post(format!("/v1/jobs/commands/{BENCH_OPERATION}"))
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.clone()))?;
+        let accepted = send(&router, request).await?;
+        let job_id = accepted["job_id"]
+            .as_str()
+            .ok_or_else(|| anyhow!("job submission returned {accepted}"))?;
+        submitted.borrow_mut().push(job_id.to_string());
+        Ok(())
+    })
+    .await?;
+    results.push(summarize("http", "job_submit", timings, Some(body.len())));
+
+    let (router, job_ids) = (&router, &submitted.into_inner());
+    let timings = measure(slice, |call| async move {
+        send(
+            router,
+            get(&format!("/v1/jobs/{}", job_ids[call % job_ids.len()]))?,
+        )
+        .await?;
+        Ok(())
+    })
+    .await?;
+    results.push(summarize("http", "job_get", timings, None));
+    // Submitted jobs are still being processed in the background; stop them writing to the
+    // scratch file before it is removed.
+    storage.close().await;
+    Ok(results)
+}
+
+pub(crate) async fn run_suites(
+    target: &BenchTarget,
+    options: &BenchOptions,
+) -> Result<BenchReport> {
+    let live = plain_path(&target.sqlite_path);
+    let storage_path = options
+        .storage_path
+        .clone()
+        .unwrap_or_else(|| scratch_path(&target.sqlite_path, "storage"));
+    let on_live = same_file(&storage_path, live);
+    if options.suite.includes(BenchSuite::Storage) && on_live && !options.allow_live {
+        bail!(
+            "refusing to benchmark storage against the live database {}; pass --allow-live",
+            live.display()
+        );
+    }
+
+    let mut results = Vec::new();
+    if options.suite.includes(BenchSuite::Storage) {
+        let scratch = !on_live && options.storage_path.is_none();
+        let (storage, guard) = open_database(target, &storage_path, scratch).await?;
+        results.extend(bench_storage(&storage, options.duration).await?);
+        // Close the pool before the scratch files go.
+        drop(storage);
+        drop(guard);
+    }
+    if options.suite.includes(BenchSuite::Codec) {
+        results.extend(bench_codec(options.duration).await?);
+    }
+    if options.suite.includes(BenchSuite::Http) {
+        let path = scratch_path(&target.sqlite_path, "http");
+        let (storage, guard) = open_database(target, &path, true).await?;
+        results.extend(bench_http(storage, target, options.duration).await?);
+        drop(guard);
+    }
+
+    Ok(BenchReport {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        os: std::env::consts::OS.to_string(),
+        arch: std::env::consts::ARCH.to_string(),
+        cpus: std::thread::available_parallelism().map_or(1, |cpus| cpus.get()),
+        duration_ms: options.duration.as_millis() as u64,
+        results,
+    })
+}
+
+fn latency(micros: u64) -> String {
+    if micros < 1000 {
+        format!("{micros}us")
+    } else {
+        format!("{:.2}ms", micros as f64 / 1000.0)
+    }
+}
+
+pub(crate) fn print_report(report: &BenchReport, json: bool) {
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(report).unwrap_or_else(|_| "{}".to_string())
+        );
+        return;
+    }
+
+    println!(
+        "retasyncd {} on {}/{} with {} cpus, {}ms per suite",
+        report.version, report.os, report.arch, report.cpus, report.duration_ms
+    );
+    println!(
+        "{:<8} {:<14} {:>9} {:>11} {:>9} {:>9} {:>9} {:>9}",
+        "SUITE", "CASE", "OPS", "OPS/SEC", "P50", "P95", "P99", "BYTES"
+    );
+    for result in &report.results {
+        println!(
+            "{:<8} {:<14} {:>9} {:>11.1} {:>9} {:>9} {:>9} {:>9}",
+            result.suite,
+            result.case,
+            result.ops,
+            result.ops_per_sec,
+            latency(result.p50_us),
+            latency(result.p95_us),
+            latency(result.p99_us),
+            result
+                .bytes_per_op
+                .map_or("-".to_string(), |bytes| bytes.to_string()),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_duration, run_suites, BenchOptions, BenchSuite, BenchTarget};
+    use retasync_control_plane::test_support::scratch_dir;
+    use retasync_storage::DEFAULT_READ_POOL_SIZE;
+    use serde_json::Value;
+    use std::time::Duration;
+
+    fn target() -> BenchTarget {
+        let dir = scratch_dir();
+        BenchTarget {
+            sqlite_path: dir.join("live.sqlite").to_string_lossy().into_owned(),
+            encryption_key_path: None,
+            read_pool_size: DEFAULT_READ_POOL_SIZE,
+            contract_doc: include_str!("../../../contracts/retasyncapi-v1.asyncapi.yaml")
+                .to_string(),
+        }
+    }
+
+    fn options(suite: BenchSuite) -> BenchOptions {
+        BenchOptions {
+            suite,
+            duration: Duration::from_millis(30),
+            storage_path: None,
+            allow_live: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn each_suite_runs_briefly_and_reports_json() {
+        let target = target();
+        for (suite, name, cases) in [
+            (BenchSuite::Storage, "storage", 3),
+            (BenchSuite::Codec, "codec", 6),
+            (BenchSuite::Http, "http", 4),
+        ] {
+            let report = run_suites(&target, &options(suite)).await.unwrap();
+            let json: Value =
+                serde_json::from_str(&serde_json::to_string(&report).unwrap()).unwrap();
+            let results = json["results"].as_array().unwrap();
+            assert_eq!(results.len(), cases, "{json}");
+            for result in results {
+                assert_eq!(result["suite"], name);
+                assert!(result["ops"].as_u64().unwrap() >= 1, "{result}");
+                assert!(result["ops_per_sec"].as_f64().unwrap() > 0.0, "{result}");
+                assert!(result["p50_us"].as_u64().unwrap() <= result["p99_us"].as_u64().unwrap());
+            }
+        }
+
+        // Scratch databases are removed and the live path is never created.
+        let dir = std::path::Path::new(&target.sqlite_path).parent().unwrap();
+        let left: Vec<_> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert!(left.is_empty(), "{left:?}");
+    }
+
+    #[tokio::test]
+    async fn storage_suite_refuses_the_live_database_without_allow_live() {
+        let target = target();
+        let mut live = options(BenchSuite::All);
+        live.storage_path = Some(target.sqlite_path.clone().into());
+        let err = run_suites(&target, &live)
+            .await
+            .expect_err("live database refused");
+        assert!(err.to_string().contains("--allow-live"), "{err}");
+
+        live.suite = BenchSuite::Codec;
+        assert!(run_suites(&target, &live).await.is_ok());
+        live.suite = BenchSuite::Storage;
+        live.allow_live = true;
+        assert_eq!(run_suites(&target, &live).await.unwrap().results.len(), 3);
+
+        assert_eq!(parse_duration("30s").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_duration("250ms").unwrap(), Duration::from_millis(250));
+        assert!(parse_duration("soon").is_err());
+    }
+}
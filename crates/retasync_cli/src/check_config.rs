@@ -0,0 +1,178 @@
+﻿use std::path::Path;
+
+use clap::ValueEnum;
+use retasync_control_plane::config_schema::{
+    runtime_config_schema, validate_config_value, FieldError,
+};
+use retasync_control_plane::profiles::EffectiveConfig;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::layout::rename_legacy_keys;
+use crate::RuntimeConfig;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum ReportFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct ConfigReport {
+    pub valid: bool,
+    pub errors: Vec<FieldError>,
+}
+
+impl RuntimeConfig {
+    pub(crate) fn json_schema() -> Value {
+        runtime_config_schema()
+    }
+}
+
+// Validates a candidate node.toml without starting anything: schema rules first, then the
+// deserializer the daemon itself uses.
+pub(crate) fn check_file(config_path: &Path) -> ConfigReport {
+    let errors = match std::fs::read_to_string(config_path) {
+        Ok(source) => check_source(&source),
+        Err(err) => vec![FieldError {
+            pointer: String::new(),
+            message: format!("failed to read {}: {err}", config_path.display()),
+        }],
+    };
+    ConfigReport {
+        valid: errors.is_empty(),
+        errors,
+    }
+}
+
+fn check_source(source: &str) -> Vec<FieldError> {
+    let mut document = match toml::from_str::<toml::Value>(source) {
+        Ok(document) => document,
+        Err(err) => return vec![root_error(format!("invalid TOML: {}", err.message()))],
+    };
+    // The daemon reads renamed keys under their current names, so the check does too.
+    let renamed = match rename_legacy_keys(&mut document) {
+        Ok(notes) => !notes.is_empty(),
+        Err(err) => return vec![root_error(err.to_string())],
+    };
+    let config = document.clone();
+    let document = match serde_json::to_value(document) {
+        Ok(document) => document,
+        Err(err) => return vec![root_error(err.to_string())],
+    };
+    // With a profile, what the daemon runs is the preset under the file, so that is checked.
+    if document.get("profile").is_some() {
+        return match EffectiveConfig::resolve(&document, None) {
+            Ok(effective) => check_merged(effective.config),
+            Err(err) => vec![FieldError {
+                pointer: "/profile".to_string(),
+                message: err.to_string(),
+            }],
+        };
+    }
+
+    let errors = validate_config_value(&RuntimeConfig::json_schema(), &document);
+    if !errors.is_empty() {
+        return errors;
+    }
+    let parsed = if renamed {
+        config.try_into::<RuntimeConfig>()
+    } else {
+        toml::from_str::<RuntimeConfig>(source)
+    };
+    match parsed {
+        Ok(_) => Vec::new(),
+        Err(err) => vec![root_error(err.message().to_string())],
+    }
+}
+
+fn check_merged(config: Value) -> Vec<FieldError> {
+    let errors = validate_config_value(&RuntimeConfig::json_schema(), &config);
+    if !errors.is_empty() {
+        return errors;
+    }
+    match serde_json::from_value::<RuntimeConfig>(config) {
+        Ok(_) => Vec::new(),
+        Err(err) => vec![root_error(err.to_string())],
+    }
+}
+
+fn root_error(message: String) -> FieldError {
+    FieldError {
+        pointer: String::new(),
+        message,
+    }
+}
+
+pub(crate) fn print_report(report: &ConfigReport, format: ReportFormat) {
+    if format == ReportFormat::Json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(report).unwrap_or_else(|_| "{}".to_string())
+        );
+        return;
+    }
+
+    if report.valid {
+        println!("config is valid");
+    }
+    for error in &report.errors {
+        let pointer = if error.pointer.is_empty() { "/" } else { &error.pointer };
+        println!("{pointer}: {}", error.message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::check_source;
+    use crate::RuntimeConfig;
+    use retasync_control_plane::config_schema::{schema_defaults, validate_config_value};
+    use retasync_control_plane::profiles::NODE_PROFILES;
+
+    #[test]
+    fn schema_defaults_deserialize_and_validate() {
+        let schema = RuntimeConfig::json_schema();
+        let defaults = schema_defaults(&schema).expect("defaults");
+        assert_eq!(validate_config_value(&schema, &defaults), Vec::new());
+
+        let source = toml::to_string(&defaults).expect("defaults as TOML");
+        let config: RuntimeConfig = toml::from_str(&source).expect("defaults deserialize");
+        assert_eq!(config.transport.max_lxmf_bytes, Some(64 * 1024));
+        assert_eq!(config.health.sample_interval_secs, 30);
+        assert!(check_source(&source).is_empty());
+    }
+
+    #[test]
+    fn misspelled_fields_are_named() {
+        let source = "[rpc]\nendpoint = \"sim://\"\n\n[http]\nbind = \"127.0.0.1:0\"\n\n\
+                      [storage]\nsqlite_path = \"x.sqlite\"\n\n[acl]\nmode = \"allowlist\"\n\n\
+                      [transport]\nprefer_lnk = true\n";
+
+        let err = toml::from_str::<RuntimeConfig>(source).unwrap_err();
+        assert!(err.message().contains("unknown field `prefer_lnk`"), "{err}");
+
+        let errors = check_source(source);
+        let pointers: Vec<&str> = errors.iter().map(|error| error.pointer.as_str()).collect();
+        assert_eq!(pointers, ["/transport/prefer_link", "/transport/prefer_lnk"]);
+        assert_eq!(errors[1].message, "unknown field `prefer_lnk`");
+    }
+
+    #[test]
+    fn init_output_checks_clean_for_every_profile() {
+        for profile in NODE_PROFILES {
+            let source = profile.init_document().unwrap();
+            assert!(source.starts_with(&format!("profile = \"{}\"", profile.name)), "{source}");
+            assert_eq!(check_source(&source), Vec::new(), "{}", profile.name);
+            let document: toml::Table = toml::from_str(&source).unwrap();
+            assert_eq!(document.len(), 1 + usize::from(!profile.secrets.is_empty()));
+        }
+
+        let errors = check_source("profile = \"gatway\"\n");
+        assert_eq!(errors[0].pointer, "/profile");
+        assert!(errors[0].message.contains("gateway, field_client, relay"), "{errors:?}");
+        // The merged config is what is checked, so a bad override still points at its key.
+        let errors = check_source("profile = \"relay\"\n\n[inbound]\ncapacity = \"many\"\n");
+        let pointers: Vec<&str> = errors.iter().map(|error| error.pointer.as_str()).collect();
+        assert_eq!(pointers, ["/inbound/capacity"]);
+    }
+}
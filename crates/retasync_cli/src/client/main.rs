@@ -0,0 +1,175 @@
+﻿use std::{
+    fs,
+    io::{self, Write},
+    path::PathBuf,
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use clap::{Args, Parser, Subcommand};
+use retasync_contract::{
+    encode_canonical, IdentityHash, MeshCommandEnvelope, CONTENT_TYPE_MSGPACK,
+    LOCAL_NODE_IDENTITY,
+};
+use retasync_mesh_bridge::{
+    RpcMeshBridge, TcpPoolSettings, TcpRpcMeshBridge, DEFAULT_REQUEST_TIMEOUT_SECS,
+};
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::queue::FileQueue;
+
+mod queue;
+
+// A field build of the client side alone: no HTTP server and no database, only the envelope
+// codec, a TCP bridge and a directory of queued submissions.
+#[derive(Debug, Parser)]
+#[command(author, version, about = "Reticulum AsyncAPI minimal mesh client")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    // Writes the canonical MessagePack encoding of a command envelope.
+    Encode {
+        #[command(flatten)]
+        command: CommandArgs,
+        // Defaults to standard output.
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+    // Queues a command until the next `flush`.
+    Submit {
+        #[arg(long, default_value = "spool")]
+        queue: PathBuf,
+        #[command(flatten)]
+        command: CommandArgs,
+    },
+    // Sends queued commands over the bridge in submission order, stopping at the first one
+    // the bridge does not answer.
+    Flush {
+        #[arg(long, default_value = "spool")]
+        queue: PathBuf,
+        #[arg(long, default_value = "127.0.0.1:4242")]
+        bridge: String,
+        #[arg(long, default_value_t = DEFAULT_REQUEST_TIMEOUT_SECS)]
+        timeout_secs: u64,
+    },
+    Pending {
+        #[arg(long, default_value = "spool")]
+        queue: PathBuf,
+    },
+}
+
+#[derive(Debug, Args)]
+struct CommandArgs {
+    #[arg(long)]
+    operation: String,
+    #[arg(long)]
+    destination: IdentityHash,
+    #[arg(long, default_value = LOCAL_NODE_IDENTITY)]
+    source: IdentityHash,
+    // A JSON document; `@path` reads it from a file.
+    #[arg(long, default_value = "{}")]
+    payload: String,
+    #[arg(long)]
+    ttl_ms: Option<u64>,
+}
+
+impl CommandArgs {
+    fn envelope(self) -> Result<MeshCommandEnvelope<Value>> {
+        let payload = match self.payload.strip_prefix('@') {
+            Some(path) => fs::read_to_string(path).with_context(|| format!("reading {path}"))?,
+            None => self.payload,
+        };
+        let payload = serde_json::from_str(&payload).context("payload is not JSON")?;
+        Ok(MeshCommandEnvelope {
+            message_id: Uuid::now_v7().to_string(),
+            operation: self.operation,
+            sent_at: Utc::now(),
+            source_identity: self.source,
+            destination_identity: self.destination,
+            content_type: CONTENT_TYPE_MSGPACK.to_string(),
+            payload,
+            ttl_ms: self.ttl_ms,
+            transport_hint: None,
+            trace: Vec::new(),
+            trace_truncated: false,
+            meta: None,
+        })
+    }
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<()> {
+    let cli = Cli::parse();
+    match cli.command {
+        Command::Encode { command, out } => {
+            let bytes = encode_canonical(&command.envelope()?)?;
+            match out {
+                Some(path) => {
+                    fs::write(&path, bytes)
+                        .with_context(|| format!("writing {}", path.display()))?;
+                }
+                None => io::stdout().lock().write_all(&bytes)?,
+            }
+        }
+        Command::Submit { queue, command } => {
+            let envelope = command.envelope()?;
+            FileQueue::open(queue)?.push(&envelope)?;
+            println!("{}", envelope.message_id);
+        }
+        Command::Flush {
+            queue,
+            bridge,
+            timeout_secs,
+        } => {
+            let queue = FileQueue::open(queue)?;
+            let settings = TcpPoolSettings {
+                pool_size: 1,
+                request_timeout: Duration::from_secs(timeout_secs),
+                ..TcpPoolSettings::default()
+            };
+            let bridge = TcpRpcMeshBridge::new(bridge, settings);
+            flush(&queue, &bridge).await?;
+        }
+        Command::Pending { queue } => {
+            for path in FileQueue::open(queue)?.pending()? {
+                let envelope = FileQueue::read(&path)?;
+                println!(
+                    "{}\t{}\t{}",
+                    envelope.message_id, envelope.operation, envelope.destination_identity
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+// A command that no longer decodes is set aside so it cannot hold up the rest; a bridge
+// failure leaves it queued for the next flush.
+async fn flush(queue: &FileQueue, bridge: &dyn RpcMeshBridge) -> Result<()> {
+    let (mut sent, mut rejected) = (0, 0);
+    for path in queue.pending()? {
+        let envelope = match FileQueue::read(&path) {
+            Ok(envelope) => envelope,
+            Err(error) => {
+                let moved = queue.reject(&path)?;
+                eprintln!("{error:#}; moved to {}", moved.display());
+                rejected += 1;
+                continue;
+            }
+        };
+        let message_id = envelope.message_id.clone();
+        let result = bridge.send_command(envelope).await.with_context(|| {
+            format!("sending {message_id} ({sent} sent before it, {rejected} rejected)")
+        })?;
+        queue.settle(&path, &result)?;
+        sent += 1;
+    }
+    println!("{sent} sent, {rejected} rejected");
+    Ok(())
+}
@@ -0,0 +1,168 @@
+﻿use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use retasync_contract::{
+    decode_canonical, encode_canonical, MeshCommandEnvelope, MeshResultEnvelope,
+};
+use serde_json::Value;
+
+const PENDING_DIR: &str = "pending";
+const RESULTS_DIR: &str = "results";
+const REJECTED_DIR: &str = "rejected";
+const ENVELOPE_EXTENSION: &str = "msgpack";
+
+// Submissions waiting for a bridge, one canonical MessagePack file per command under
+// `pending/`. Message ids are UUIDv7, so file names sort in submission order; results land in
+// `results/<message_id>.json` and files that no longer decode move to `rejected/`.
+#[derive(Debug, Clone)]
+pub struct FileQueue {
+    root: PathBuf,
+}
+
+impl FileQueue {
+    pub fn open(root: impl Into<PathBuf>) -> Result<Self> {
+        let root = root.into();
+        for dir in [PENDING_DIR, RESULTS_DIR, REJECTED_DIR] {
+            let path = root.join(dir);
+            fs::create_dir_all(&path)
+                .with_context(|| format!("creating queue directory {}", path.display()))?;
+        }
+        Ok(Self { root })
+    }
+
+    // Writes beside the final name and renames, so a flush never reads half a file.
+    pub fn push(&self, envelope: &MeshCommandEnvelope<Value>) -> Result<PathBuf> {
+        let bytes = encode_canonical(envelope).context("encoding command envelope")?;
+        let pending = self.root.join(PENDING_DIR);
+        let path = pending.join(format!("{}.{ENVELOPE_EXTENSION}", envelope.message_id));
+        let staging = pending.join(format!(".{}.tmp", envelope.message_id));
+        fs::write(&staging, bytes).with_context(|| format!("writing {}", staging.display()))?;
+        fs::rename(&staging, &path).with_context(|| format!("queueing {}", path.display()))?;
+        Ok(path)
+    }
+
+    pub fn pending(&self) -> Result<Vec<PathBuf>> {
+        let dir = self.root.join(PENDING_DIR);
+        let mut paths = Vec::new();
+        for entry in fs::read_dir(&dir).with_context(|| format!("reading {}", dir.display()))? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|extension| extension == ENVELOPE_EXTENSION) {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+        Ok(paths)
+    }
+
+    pub fn read(path: &Path) -> Result<MeshCommandEnvelope<Value>> {
+        let bytes = fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+        decode_canonical(&bytes).with_context(|| format!("decoding {}", path.display()))
+    }
+
+    // Records the answer before dropping the submission: a crash in between sends it again
+    // rather than losing it.
+    pub fn settle(&self, path: &Path, result: &MeshResultEnvelope<Value>) -> Result<PathBuf> {
+        let stored = self.root.join(RESULTS_DIR).join(format!("{}.json", result.correlation_id));
+        let body = serde_json::to_vec_pretty(result).context("encoding result envelope")?;
+        fs::write(&stored, body).with_context(|| format!("writing {}", stored.display()))?;
+        fs::remove_file(path).with_context(|| format!("removing {}", path.display()))?;
+        Ok(stored)
+    }
+
+    pub fn reject(&self, path: &Path) -> Result<PathBuf> {
+        let name = path.file_name().context("queued file has no name")?;
+        let moved = self.root.join(REJECTED_DIR).join(name);
+        fs::rename(path, &moved).with_context(|| format!("moving {}", path.display()))?;
+        Ok(moved)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use retasync_contract::{IdentityHash, CONTENT_TYPE_MSGPACK};
+    use serde_json::json;
+    use uuid::Uuid;
+
+    use super::*;
+
+    fn scratch() -> PathBuf {
+        std::env::temp_dir().join(format!("retasync-client-{}", Uuid::now_v7()))
+    }
+
+    fn command(operation: &str) -> MeshCommandEnvelope<Value> {
+        MeshCommandEnvelope {
+            message_id: Uuid::now_v7().to_string(),
+            operation: operation.to_string(),
+            sent_at: Utc::now(),
+            source_identity: IdentityHash::lenient("local-node"),
+            destination_identity: IdentityHash::lenient("aa00000000000000000000000000000a"),
+            content_type: CONTENT_TYPE_MSGPACK.to_string(),
+            payload: json!({ "operation": operation }),
+            ttl_ms: None,
+            transport_hint: None,
+            trace: Vec::new(),
+            trace_truncated: false,
+            meta: None,
+        }
+    }
+
+    fn answer(envelope: &MeshCommandEnvelope<Value>) -> MeshResultEnvelope<Value> {
+        MeshResultEnvelope {
+            message_id: Uuid::now_v7().to_string(),
+            correlation_id: envelope.message_id.clone(),
+            operation: envelope.operation.clone(),
+            sent_at: Utc::now(),
+            source_identity: envelope.destination_identity.clone(),
+            destination_identity: envelope.source_identity.clone(),
+            content_type: CONTENT_TYPE_MSGPACK.to_string(),
+            payload: json!({ "ok": true }),
+            ttl_ms: None,
+            transport_hint: None,
+            trace: Vec::new(),
+            trace_truncated: false,
+        }
+    }
+
+    #[test]
+    fn pending_submissions_come_back_in_order_until_settled() {
+        let root = scratch();
+        let queue = FileQueue::open(&root).unwrap();
+        let first = command("mesh.ping");
+        let second = command("jobs.submit");
+        queue.push(&first).unwrap();
+        queue.push(&second).unwrap();
+
+        let pending = queue.pending().unwrap();
+        assert_eq!(pending.len(), 2);
+        let read = FileQueue::read(&pending[0]).unwrap();
+        assert_eq!(read.message_id, first.message_id);
+        assert_eq!(read.payload, first.payload);
+
+        let stored = queue.settle(&pending[0], &answer(&read)).unwrap();
+        assert!(stored.ends_with(format!("{}.json", first.message_id)));
+        let remaining = queue.pending().unwrap();
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(FileQueue::read(&remaining[0]).unwrap().message_id, second.message_id);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn files_that_do_not_decode_can_be_set_aside() {
+        let root = scratch();
+        let queue = FileQueue::open(&root).unwrap();
+        let broken = root.join(PENDING_DIR).join("broken.msgpack");
+        fs::write(&broken, b"not msgpack").unwrap();
+        fs::write(root.join(PENDING_DIR).join(".staged.tmp"), b"partial").unwrap();
+
+        assert_eq!(queue.pending().unwrap(), vec![broken.clone()]);
+        assert!(FileQueue::read(&broken).is_err());
+        let moved = queue.reject(&broken).unwrap();
+        assert!(moved.exists());
+        assert!(queue.pending().unwrap().is_empty());
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,243 @@
+﻿use std::net::SocketAddr;
+use std::path::Path;
+use std::time::Duration;
+
+use retasync_control_plane::diagnostics::{
+    check_bridge, check_clock, check_contract, check_disk_space, CheckResult, CheckStatus,
+    DiagnosticsReport, BRIDGE_PROBE_TIMEOUT,
+};
+use retasync_storage::RetasyncStorage;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use crate::listeners::plan_listeners;
+use crate::{load_runtime_config, select_bridge, RuntimeConfig};
+
+const MIN_FREE_DISK_BYTES: u64 = 100 * 1024 * 1024;
+const HEALTH_PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub(crate) async fn run_checks(config_path: &Path, contract_path: &Path) -> DiagnosticsReport {
+    let mut report = DiagnosticsReport::default();
+
+    let config = match load_runtime_config(config_path) {
+        Ok(config) => config,
+        Err(err) => {
+            report.push(CheckResult::fail("config", format!("{err:#}")));
+            return report;
+        }
+    };
+    report.push(validate_config(&config));
+
+    report.push(check_sqlite(&config.storage.sqlite_path).await);
+    report.push(match std::fs::read_to_string(contract_path) {
+        Ok(doc) => check_contract(&doc),
+        Err(err) => CheckResult::fail(
+            "contract",
+            format!("failed to read {}: {err}", contract_path.display()),
+        ),
+    });
+    report.push(match select_bridge(&config) {
+        Ok(_) if config.rpc.endpoint.starts_with("replay://") => {
+            CheckResult::pass("bridge", "replay recording loaded")
+        }
+        Ok((bridge, _)) => check_bridge(bridge.as_ref(), BRIDGE_PROBE_TIMEOUT).await,
+        Err(err) => CheckResult::fail("bridge", format!("{err:#}")),
+    });
+    report.push(check_http_bind(&config.http.bind).await);
+
+    let sqlite_path = Path::new(
+        config
+            .storage
+            .sqlite_path
+            .trim_start_matches("sqlite://")
+            .trim_start_matches("sqlite:"),
+    );
+    report.push(check_disk_space(sqlite_path, MIN_FREE_DISK_BYTES));
+    report.push(check_clock(&[config_path, contract_path, sqlite_path]));
+    report
+}
+
+pub(crate) fn print_report(report: &DiagnosticsReport, json: bool) {
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(report).unwrap_or_else(|_| "{}".to_string())
+        );
+        return;
+    }
+
+    for check in &report.checks {
+        let (color, label) = match check.status {
+            CheckStatus::Pass => ("\x1b[32m", "PASS"),
+            CheckStatus::Warn => ("\x1b[33m", "WARN"),
+            CheckStatus::Fail => ("\x1b[31m", "FAIL"),
+        };
+        println!("{color}[{label}]\x1b[0m {:<10} {}", check.name, check.detail);
+    }
+}
+
+pub(crate) fn exit_code(report: &DiagnosticsReport) -> i32 {
+    match report.worst() {
+        CheckStatus::Pass => 0,
+        CheckStatus::Warn => 1,
+        CheckStatus::Fail => 2,
+    }
+}
+
+fn validate_config(config: &RuntimeConfig) -> CheckResult {
+    if config.http.bind.parse::<SocketAddr>().is_err() {
+        return CheckResult::fail(
+            "config",
+            format!("http.bind {} is not a socket address", config.http.bind),
+        );
+    }
+    if let Err(err) = plan_listeners(&config.http) {
+        return CheckResult::fail("config", format!("{err:#}"));
+    }
+    let known_scheme = ["tcp://", "sim://", "replay://"]
+        .iter()
+        .any(|scheme| config.rpc.endpoint.starts_with(scheme));
+    if !known_scheme {
+        return CheckResult::warn(
+            "config",
+            format!("rpc.endpoint {} uses an unknown scheme", config.rpc.endpoint),
+        );
+    }
+    CheckResult::pass("config", "config parses and validates")
+}
+
+async fn check_sqlite(sqlite_path: &str) -> CheckResult {
+    let plain_path = !sqlite_path.starts_with("sqlite:");
+    if plain_path && !Path::new(sqlite_path).exists() {
+        return CheckResult::warn(
+            "storage",
+            format!("{sqlite_path} does not exist yet; it will be created on first start"),
+        );
+    }
+
+    match RetasyncStorage::pending_migrations(sqlite_path).await {
+        Ok(missing) if missing.is_empty() => {
+            CheckResult::pass("storage", "sqlite opens and schema is current")
+        }
+        Ok(missing) => CheckResult::warn(
+            "storage",
+            format!(
+                "pending migrations will be applied on start: {}",
+                missing.join(", ")
+            ),
+        ),
+        Err(err) => CheckResult::fail("storage", format!("{err:#}")),
+    }
+}
+
+async fn check_http_bind(bind: &str) -> CheckResult {
+    let Ok(addr) = bind.parse::<SocketAddr>() else {
+        return CheckResult::fail("http_bind", format!("{bind} is not a socket address"));
+    };
+
+    match tokio::net::TcpListener::bind(addr).await {
+        Ok(_) => CheckResult::pass("http_bind", format!("{bind} is free")),
+        Err(err) if err.kind() == std::io::ErrorKind::AddrInUse => {
+            if probe_health_live(addr).await {
+                CheckResult::pass("http_bind", format!("{bind} is served by a healthy retasyncd"))
+            } else {
+                CheckResult::fail("http_bind", format!("{bind} is in use by another process"))
+            }
+        }
+        Err(err) => CheckResult::fail("http_bind", format!("cannot bind {bind}: {err}")),
+    }
+}
+
+async fn probe_health_live(addr: SocketAddr) -> bool {
+    let probe = async {
+        let mut stream = tokio::net::TcpStream::connect(addr).await.ok()?;
+        let request =
+            format!("GET /health/live HTTP/1.1\r\nHost: {addr}\r\nConnection: close\r\n\r\n");
+        stream.write_all(request.as_bytes()).await.ok()?;
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await.ok()?;
+        let response = String::from_utf8_lossy(&response);
+        Some(response.starts_with("HTTP/1.1 200") && response.contains("\"status\":\"live\""))
+    };
+
+    matches!(
+        tokio::time::timeout(HEALTH_PROBE_TIMEOUT, probe).await,
+        Ok(Some(true))
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::run_checks;
+    use retasync_control_plane::diagnostics::CheckStatus;
+    use std::path::PathBuf;
+
+    fn temp_file(name: &str, contents: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("retasyncd-doctor-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("temp dir");
+        let path = dir.join(name);
+        std::fs::write(&path, contents).expect("write temp file");
+        path
+    }
+
+    fn node_toml(endpoint: &str) -> String {
+        format!(
+            r#"
+[rpc]
+endpoint = "{endpoint}"
+
+[http]
+bind = "127.0.0.1:0"
+
+[storage]
+sqlite_path = "{}"
+
+[acl]
+mode = "allowlist"
+
+[transport]
+prefer_link = true
+"#,
+            std::env::temp_dir()
+                .join("retasyncd-doctor-missing.sqlite")
+                .display()
+        )
+    }
+
+    fn contract() -> PathBuf {
+        temp_file(
+            "contract.yaml",
+            "asyncapi: 3.0.0\nx-retasync:\n  operations:\n    commands: [event.create]\n",
+        )
+    }
+
+    #[tokio::test]
+    async fn invalid_config_fails() {
+        let config = temp_file("invalid.toml", "[rpc\nendpoint =");
+        let report = run_checks(&config, &contract()).await;
+
+        assert_eq!(report.check("config").unwrap().status, CheckStatus::Fail);
+        assert_eq!(report.worst(), CheckStatus::Fail);
+    }
+
+    #[tokio::test]
+    async fn missing_contract_fails() {
+        let config = temp_file("missing-contract.toml", &node_toml("sim://"));
+        let report = run_checks(&config, &PathBuf::from("does/not/exist.yaml")).await;
+
+        assert_eq!(report.check("config").unwrap().status, CheckStatus::Pass);
+        assert_eq!(report.check("contract").unwrap().status, CheckStatus::Fail);
+    }
+
+    #[tokio::test]
+    async fn unreachable_bridge_fails() {
+        let profile = temp_file("partitioned.toml", "[loss]\nquery_receipt = 1.0\n");
+        let config = temp_file(
+            "unreachable.toml",
+            &node_toml(&format!("sim://{}", profile.display())),
+        );
+        let report = run_checks(&config, &contract()).await;
+
+        assert_eq!(report.check("bridge").unwrap().status, CheckStatus::Fail);
+        assert_eq!(report.check("contract").unwrap().status, CheckStatus::Pass);
+    }
+}
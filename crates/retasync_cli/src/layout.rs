@@ -0,0 +1,408 @@
+﻿use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use chrono::Utc;
+use clap::ValueEnum;
+use tracing::{info, warn};
+
+use crate::{RuntimeConfig, CONTRACT_PATH};
+
+pub(crate) const LAYOUT_MIGRATION_REASON: &str = "layout_migration";
+const SQLITE_SUFFIXES: [&str; 3] = ["", "-wal", "-shm"];
+
+// Config keys renamed since earlier releases, as (table, old name, new name).
+const RENAMED_KEYS: [(&str, &str, &str); 3] = [
+    ("storage", "db_path", "sqlite_path"),
+    ("http", "listen", "bind"),
+    ("rpc", "daemon_endpoint", "endpoint"),
+];
+
+// How `--migrate-layout` settles a legacy file whose counterpart already exists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum LayoutChoice {
+    // Use the file where the config puts it and leave the legacy one untouched.
+    KeepCurrent,
+    // Set the configured file aside as a backup and put the legacy one in its place.
+    AdoptLegacy,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum LayoutStep {
+    // Only the legacy location holds a database.
+    MoveDatabase { from: PathBuf, to: PathBuf },
+    // Both locations hold one, and only the operator knows which is live.
+    DatabaseConflict { legacy: PathBuf, current: PathBuf },
+    CopyContract { from: PathBuf, to: PathBuf },
+    ContractConflict { legacy: PathBuf, current: PathBuf },
+}
+
+// Moves each renamed key to its current name, returning a deprecation note per key.
+pub(crate) fn rename_legacy_keys(document: &mut toml::Value) -> Result<Vec<String>> {
+    let mut notes = Vec::new();
+    for (table, old, new) in RENAMED_KEYS {
+        let Some(section) = document.get_mut(table).and_then(toml::Value::as_table_mut) else {
+            continue;
+        };
+        let Some(value) = section.remove(old) else {
+            continue;
+        };
+        if section.contains_key(new) {
+            bail!(
+                "`{table}.{old}` is the old name of `{table}.{new}` and both are set; remove `{old}`"
+            );
+        }
+        section.insert(new.to_string(), value);
+        notes.push(format!(
+            "config key `{table}.{old}` is deprecated; read it as `{table}.{new}`"
+        ));
+    }
+    Ok(notes)
+}
+
+// Relative paths used to be taken from whatever directory the daemon started in; they now name
+// files beside the config. The old reading of `sqlite_path` is kept to find databases left there.
+pub(crate) fn anchor_paths(config_path: &Path, config: &mut RuntimeConfig) {
+    let base = config_path.parent().unwrap_or(Path::new(""));
+    let storage = &mut config.storage;
+    if is_relative_file(&storage.sqlite_path) {
+        storage.legacy_sqlite_path = Some(storage.sqlite_path.clone());
+        storage.sqlite_path = base.join(&storage.sqlite_path).display().to_string();
+    }
+    if let Some(dir) = &mut config.contract.contracts_dir {
+        *dir = base.join(&*dir).display().to_string();
+    }
+}
+
+fn is_relative_file(sqlite_path: &str) -> bool {
+    !sqlite_path.starts_with("sqlite:")
+        && !sqlite_path.starts_with(':')
+        && Path::new(sqlite_path).is_relative()
+}
+
+// `contracts_dir` when set, else the path the daemon has always read.
+pub(crate) fn contract_path(config: &RuntimeConfig) -> PathBuf {
+    match &config.contract.contracts_dir {
+        Some(dir) => {
+            let file = Path::new(CONTRACT_PATH).file_name().unwrap_or_default();
+            Path::new(dir).join(file)
+        }
+        None => PathBuf::from(CONTRACT_PATH),
+    }
+}
+
+// What it takes to bring files left by an older layout, relative to `cwd`, to where `config`
+// now expects them. Nothing is touched.
+pub(crate) fn plan(config: &RuntimeConfig, cwd: &Path) -> Vec<LayoutStep> {
+    let mut steps = Vec::new();
+    if let Some(legacy) = &config.storage.legacy_sqlite_path {
+        let (legacy, current) = (cwd.join(legacy), cwd.join(&config.storage.sqlite_path));
+        if holds_data(&legacy) && !same_file(&legacy, &current) {
+            steps.push(if holds_data(&current) {
+                LayoutStep::DatabaseConflict { legacy, current }
+            } else {
+                LayoutStep::MoveDatabase {
+                    from: legacy,
+                    to: current,
+                }
+            });
+        }
+    }
+    if config.contract.contracts_dir.is_some() {
+        let (legacy, current) = (cwd.join(CONTRACT_PATH), cwd.join(contract_path(config)));
+        if legacy.is_file() && !same_file(&legacy, &current) {
+            match std::fs::read(&current) {
+                Err(_) => steps.push(LayoutStep::CopyContract {
+                    from: legacy,
+                    to: current,
+                }),
+                Ok(doc) if std::fs::read(&legacy).ok().as_ref() != Some(&doc) => {
+                    steps.push(LayoutStep::ContractConflict { legacy, current })
+                }
+                Ok(_) => {}
+            }
+        }
+    }
+    steps
+}
+
+// Carries out `steps`, returning a line per change made or legacy file left alone. A conflict
+// without `choice` refuses before anything moves.
+pub(crate) fn apply(steps: &[LayoutStep], choice: Option<LayoutChoice>) -> Result<Vec<String>> {
+    if choice.is_none() {
+        if let Some(refusal) = steps.iter().find_map(refusal) {
+            bail!(refusal);
+        }
+    }
+    let stamp = Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+    let mut notes = Vec::new();
+    for step in steps {
+        notes.push(match (step, choice) {
+            (LayoutStep::MoveDatabase { from, to }, _) => move_database(from, to, &stamp)?,
+            (LayoutStep::CopyContract { from, to }, _) => {
+                if let Some(parent) = to.parent() {
+                    std::fs::create_dir_all(parent)
+                        .with_context(|| format!("create {}", parent.display()))?;
+                }
+                copy(from, to)?;
+                format!("copied contract {} to {}", from.display(), to.display())
+            }
+            (
+                LayoutStep::DatabaseConflict { legacy, current }
+                | LayoutStep::ContractConflict { legacy, current },
+                Some(LayoutChoice::KeepCurrent) | None,
+            ) => format!(
+                "kept {}; left legacy {} untouched",
+                current.display(),
+                legacy.display()
+            ),
+            (LayoutStep::DatabaseConflict { legacy, current }, Some(LayoutChoice::AdoptLegacy)) => {
+                let aside = backup_path(current, &stamp);
+                for suffix in SQLITE_SUFFIXES {
+                    rename_if_present(&with_suffix(current, suffix), &with_suffix(&aside, suffix))?;
+                }
+                let moved = move_database(legacy, current, &stamp)?;
+                format!("set database {} aside as {}; {moved}", current.display(), aside.display())
+            }
+            (LayoutStep::ContractConflict { legacy, current }, Some(LayoutChoice::AdoptLegacy)) => {
+                let aside = backup_path(current, &stamp);
+                copy(current, &aside)?;
+                copy(legacy, current)?;
+                format!(
+                    "replaced contract {} with {}; the one it replaced is at {}",
+                    current.display(),
+                    legacy.display(),
+                    aside.display()
+                )
+            }
+        });
+    }
+    Ok(notes)
+}
+
+// Plans and applies in one go, logging every line it returns.
+pub(crate) fn upgrade(
+    config: &RuntimeConfig,
+    cwd: &Path,
+    choice: Option<LayoutChoice>,
+) -> Result<Vec<String>> {
+    let steps = plan(config, cwd);
+    for step in &steps {
+        warn!(step = ?step, "legacy layout detected");
+    }
+    let notes = apply(&steps, choice)?;
+    for note in &notes {
+        info!("layout migration: {note}");
+    }
+    Ok(notes)
+}
+
+fn refusal(step: &LayoutStep) -> Option<String> {
+    let (what, legacy, current) = match step {
+        LayoutStep::DatabaseConflict { legacy, current } => ("database", legacy, current),
+        LayoutStep::ContractConflict { legacy, current } => ("contract", legacy, current),
+        _ => return None,
+    };
+    Some(format!(
+        "refusing to start: both {legacy} (legacy location) and {current} hold a {what}. Restart \
+         with `--migrate-layout keep-current` to use {current} and leave {legacy} untouched, or \
+         `--migrate-layout adopt-legacy` to set {current} aside as a backup and use {legacy} in \
+         its place",
+        legacy = legacy.display(),
+        current = current.display(),
+    ))
+}
+
+// Copies the legacy database, WAL included, to a backup beside it before moving it.
+fn move_database(from: &Path, to: &Path, stamp: &str) -> Result<String> {
+    let backup = backup_path(from, stamp);
+    for suffix in SQLITE_SUFFIXES {
+        let source = with_suffix(from, suffix);
+        if source.exists() {
+            copy(&source, &with_suffix(&backup, suffix))?;
+        }
+    }
+    if let Some(parent) = to.parent() {
+        std::fs::create_dir_all(parent).with_context(|| format!("create {}", parent.display()))?;
+    }
+    for suffix in SQLITE_SUFFIXES {
+        // Whatever sits at the destination holds no data, such as a stray empty file.
+        let target = with_suffix(to, suffix);
+        if target.exists() {
+            std::fs::remove_file(&target)
+                .with_context(|| format!("remove empty {}", target.display()))?;
+        }
+        rename_if_present(&with_suffix(from, suffix), &target)?;
+    }
+    Ok(format!(
+        "moved database {} to {}; backup at {}",
+        from.display(),
+        to.display(),
+        backup.display()
+    ))
+}
+
+fn holds_data(sqlite_path: &Path) -> bool {
+    SQLITE_SUFFIXES[..2].iter().any(|suffix| {
+        std::fs::metadata(with_suffix(sqlite_path, suffix)).is_ok_and(|meta| meta.len() > 0)
+    })
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (std::fs::canonicalize(a), std::fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+fn backup_path(path: &Path, stamp: &str) -> PathBuf {
+    with_suffix(path, &format!(".{stamp}.bak"))
+}
+
+fn copy(from: &Path, to: &Path) -> Result<()> {
+    std::fs::copy(from, to)
+        .with_context(|| format!("copy {} to {}", from.display(), to.display()))?;
+    Ok(())
+}
+
+// Falls back to copying when the two paths are on different filesystems.
+fn rename_if_present(from: &Path, to: &Path) -> Result<()> {
+    if !from.exists() {
+        return Ok(());
+    }
+    if std::fs::rename(from, to).is_err() {
+        copy(from, to)?;
+        std::fs::remove_file(from).with_context(|| format!("remove {}", from.display()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::read_runtime_config;
+
+    fn fixture(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "retasyncd-layout-{name}-{}-{}",
+            std::process::id(),
+            Utc::now().timestamp_nanos_opt().unwrap_or_default()
+        ));
+        std::fs::create_dir_all(dir.join("config")).unwrap();
+        dir
+    }
+
+    // The node config an older release shipped: renamed keys, a database path relative to the
+    // working directory.
+    fn write_config(dir: &Path, extra: &str) -> PathBuf {
+        let path = dir.join("config/node.toml");
+        std::fs::write(
+            &path,
+            format!(
+                r#"
+[rpc]
+daemon_endpoint = "sim://"
+
+[http]
+listen = "127.0.0.1:0"
+
+[storage]
+db_path = "retasync.sqlite"
+
+[acl]
+mode = "allowlist"
+
+[transport]
+prefer_link = true
+{extra}"#
+            ),
+        )
+        .unwrap();
+        path
+    }
+
+    #[test]
+    fn legacy_database_and_keys_move_to_the_current_layout() {
+        let dir = fixture("move");
+        let config_path = write_config(&dir, "");
+        std::fs::write(dir.join("retasync.sqlite"), b"jobs").unwrap();
+        std::fs::write(dir.join("retasync.sqlite-wal"), b"pending").unwrap();
+
+        let (config, notes) = read_runtime_config(&config_path).unwrap();
+        assert_eq!(config.rpc.endpoint, "sim://");
+        assert_eq!(config.http.bind, "127.0.0.1:0");
+        assert_eq!(notes.len(), 3, "{notes:?}");
+        assert!(notes[0].contains("`storage.db_path` is deprecated"));
+
+        let notes = upgrade(&config, &dir, None).unwrap();
+        let moved = dir.join("config/retasync.sqlite");
+        assert_eq!(std::fs::read(&moved).unwrap(), b"jobs");
+        assert_eq!(std::fs::read(with_suffix(&moved, "-wal")).unwrap(), b"pending");
+        assert!(!dir.join("retasync.sqlite").exists());
+        assert!(notes[0].starts_with("moved database"), "{notes:?}");
+        let backups = std::fs::read_dir(&dir)
+            .unwrap()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_name().to_string_lossy().ends_with(".bak"))
+            .count();
+        assert_eq!(backups, 1);
+        // Once moved, a second start finds nothing to do.
+        assert_eq!(plan(&config, &dir), Vec::new());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn databases_in_both_locations_refuse_until_an_explicit_choice() {
+        let dir = fixture("conflict");
+        let config_path = write_config(&dir, "");
+        std::fs::write(dir.join("retasync.sqlite"), b"old jobs").unwrap();
+        std::fs::write(dir.join("config/retasync.sqlite"), b"new jobs").unwrap();
+        let (config, _) = read_runtime_config(&config_path).unwrap();
+
+        let err = upgrade(&config, &dir, None).unwrap_err().to_string();
+        assert!(err.contains("--migrate-layout keep-current"), "{err}");
+        assert_eq!(std::fs::read(dir.join("retasync.sqlite")).unwrap(), b"old jobs");
+        assert_eq!(std::fs::read(dir.join("config/retasync.sqlite")).unwrap(), b"new jobs");
+
+        upgrade(&config, &dir, Some(LayoutChoice::KeepCurrent)).unwrap();
+        assert_eq!(std::fs::read(dir.join("retasync.sqlite")).unwrap(), b"old jobs");
+
+        let notes = upgrade(&config, &dir, Some(LayoutChoice::AdoptLegacy)).unwrap();
+        assert_eq!(std::fs::read(dir.join("config/retasync.sqlite")).unwrap(), b"old jobs");
+        assert!(notes[0].contains("aside"), "{notes:?}");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn contract_at_the_legacy_path_is_copied_into_contracts_dir() {
+        let dir = fixture("contract");
+        let config_path = write_config(&dir, "\n[contract]\ncontracts_dir = \"contracts\"\n");
+        std::fs::create_dir_all(dir.join("contracts")).unwrap();
+        std::fs::write(dir.join(CONTRACT_PATH), "asyncapi: 3.0.0\n").unwrap();
+        let (config, _) = read_runtime_config(&config_path).unwrap();
+        let current = dir.join("config").join(CONTRACT_PATH);
+        assert_eq!(dir.join(contract_path(&config)), current);
+
+        let notes = upgrade(&config, &dir, None).unwrap();
+        assert_eq!(std::fs::read_to_string(&current).unwrap(), "asyncapi: 3.0.0\n");
+        assert!(notes[0].starts_with("copied contract"), "{notes:?}");
+
+        std::fs::write(&current, "asyncapi: 3.1.0\n").unwrap();
+        let err = upgrade(&config, &dir, None).unwrap_err().to_string();
+        assert!(err.contains("hold a contract"), "{err}");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn renamed_key_set_alongside_its_new_name_is_refused() {
+        let mut document: toml::Value =
+            toml::from_str("[storage]\ndb_path = \"a\"\nsqlite_path = \"b\"\n").unwrap();
+        let err = rename_legacy_keys(&mut document).unwrap_err().to_string();
+        assert!(err.contains("remove `db_path`"), "{err}");
+    }
+}
@@ -0,0 +1,700 @@
+﻿use std::collections::BTreeSet;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use axum::{Extension, Router};
+use retasync_control_plane::RequestListener;
+use serde::Deserialize;
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+use tracing::{info, warn};
+
+use crate::{tls, HttpSection};
+
+pub(crate) const UNIX_BIND_PREFIX: &str = "unix:";
+const DEFAULT_SOCKET_MODE: u32 = 0o660;
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ListenerAuth {
+    Bearer,
+    None,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ListenerSection {
+    // A TCP socket address or `unix:/path/to.sock`.
+    pub bind: String,
+    pub name: Option<String>,
+    pub auth: Option<ListenerAuth>,
+    pub tls: Option<tls::TlsSection>,
+    // Octal permissions, owner and group (names or numeric ids) for a unix socket file.
+    pub socket_mode: Option<String>,
+    pub socket_owner: Option<String>,
+    pub socket_group: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Bind {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SocketSettings {
+    pub mode: u32,
+    pub owner: Option<u32>,
+    pub group: Option<u32>,
+}
+
+impl Default for SocketSettings {
+    fn default() -> Self {
+        Self {
+            mode: DEFAULT_SOCKET_MODE,
+            owner: None,
+            group: None,
+        }
+    }
+}
+
+// Where a listener binds and what it asks of callers before they may write.
+#[derive(Debug, Clone)]
+pub(crate) struct ListenerPlan {
+    pub name: String,
+    pub bind: Bind,
+    pub require_bearer: bool,
+    pub tls: Option<tls::TlsSection>,
+    pub socket: SocketSettings,
+}
+
+impl ListenerPlan {
+    fn tag(&self) -> RequestListener {
+        RequestListener {
+            name: self.name.clone(),
+            require_bearer: self.require_bearer,
+        }
+    }
+}
+
+pub(crate) fn parse_bind(raw: &str) -> Result<Bind> {
+    match raw.strip_prefix(UNIX_BIND_PREFIX) {
+        Some("") => bail!("unix listener {raw} needs a socket path"),
+        Some(path) => Ok(Bind::Unix(PathBuf::from(path))),
+        None => raw
+            .parse()
+            .map(Bind::Tcp)
+            .with_context(|| format!("invalid socket address {raw}")),
+    }
+}
+
+// `http.bind` (with `http.tls`) is always the first listener; `[[http.listeners]]` adds more.
+pub(crate) fn plan_listeners(http: &HttpSection) -> Result<Vec<ListenerPlan>> {
+    let primary = match parse_bind(&http.bind)? {
+        Bind::Tcp(addr) => addr,
+        Bind::Unix(_) => bail!(
+            "http.bind {} must be a TCP socket address; add unix sockets under [[http.listeners]]",
+            http.bind
+        ),
+    };
+    let require_bearer = !primary.ip().is_loopback();
+    if require_bearer && !http.secures_remote_access() {
+        bail!(
+            "non-loopback bind {} requires http.tls, http.auth_token or http.api_tokens",
+            http.bind
+        );
+    }
+    let mut plans = vec![ListenerPlan {
+        name: http.bind.clone(),
+        bind: Bind::Tcp(primary),
+        require_bearer,
+        tls: http.tls.clone(),
+        socket: SocketSettings::default(),
+    }];
+    for section in &http.listeners {
+        plans.push(plan_listener(http, section)?);
+    }
+
+    let mut names = BTreeSet::new();
+    let mut binds = Vec::new();
+    for plan in &plans {
+        if !names.insert(plan.name.as_str()) {
+            bail!("more than one listener is named {}", plan.name);
+        }
+        if binds.contains(&&plan.bind) {
+            bail!("more than one listener binds {}", plan.name);
+        }
+        binds.push(&plan.bind);
+    }
+    Ok(plans)
+}
+
+fn plan_listener(http: &HttpSection, section: &ListenerSection) -> Result<ListenerPlan> {
+    let bind = parse_bind(&section.bind)?;
+    let name = section.name.clone().unwrap_or_else(|| section.bind.clone());
+    let require_bearer = match (&bind, section.auth) {
+        (Bind::Tcp(_), Some(ListenerAuth::None)) => {
+            bail!("listener {name}: auth = \"none\" is only allowed on unix socket listeners")
+        }
+        (Bind::Tcp(addr), None) => !addr.ip().is_loopback(),
+        (Bind::Unix(_), Some(ListenerAuth::None)) => false,
+        (_, _) => true,
+    };
+    if require_bearer && !http.has_tokens() && !http.bootstraps() && section.tls.is_none() {
+        bail!("listener {name} requires tls, http.auth_token or http.api_tokens");
+    }
+
+    let socket = match &bind {
+        Bind::Unix(_) => {
+            if section.tls.is_some() {
+                bail!("listener {name}: tls is only supported on TCP listeners");
+            }
+            SocketSettings {
+                mode: match &section.socket_mode {
+                    Some(raw) => parse_mode(raw)
+                        .with_context(|| format!("listener {name}: invalid socket_mode"))?,
+                    None => DEFAULT_SOCKET_MODE,
+                },
+                owner: section
+                    .socket_owner
+                    .as_deref()
+                    .map(resolve_user)
+                    .transpose()?,
+                group: section
+                    .socket_group
+                    .as_deref()
+                    .map(resolve_group)
+                    .transpose()?,
+            }
+        }
+        Bind::Tcp(_) => {
+            let socket_options = [
+                &section.socket_mode,
+                &section.socket_owner,
+                &section.socket_group,
+            ];
+            if socket_options.iter().any(|option| option.is_some()) {
+                bail!("listener {name}: socket_mode, socket_owner and socket_group need unix:");
+            }
+            SocketSettings::default()
+        }
+    };
+
+    Ok(ListenerPlan {
+        name,
+        bind,
+        require_bearer,
+        tls: section.tls.clone(),
+        socket,
+    })
+}
+
+fn parse_mode(raw: &str) -> Result<u32> {
+    let mode = u32::from_str_radix(raw.trim_start_matches("0o"), 8)
+        .with_context(|| format!("{raw} is not an octal mode"))?;
+    if mode > 0o777 {
+        bail!("{raw} is not a permission mode");
+    }
+    Ok(mode)
+}
+
+// A numeric id, or a name looked up through the system user and group databases.
+#[cfg(unix)]
+fn resolve_user(spec: &str) -> Result<u32> {
+    if let Ok(id) = spec.parse() {
+        return Ok(id);
+    }
+    nix::unistd::User::from_name(spec)
+        .with_context(|| format!("failed to look up user {spec}"))?
+        .map(|user| user.uid.as_raw())
+        .ok_or_else(|| anyhow!("unknown user {spec}"))
+}
+
+#[cfg(unix)]
+fn resolve_group(spec: &str) -> Result<u32> {
+    if let Ok(id) = spec.parse() {
+        return Ok(id);
+    }
+    nix::unistd::Group::from_name(spec)
+        .with_context(|| format!("failed to look up group {spec}"))?
+        .map(|group| group.gid.as_raw())
+        .ok_or_else(|| anyhow!("unknown group {spec}"))
+}
+
+#[cfg(not(unix))]
+fn resolve_user(spec: &str) -> Result<u32> {
+    bail!("socket_owner {spec} is not supported on this platform")
+}
+
+#[cfg(not(unix))]
+fn resolve_group(spec: &str) -> Result<u32> {
+    bail!("socket_group {spec} is not supported on this platform")
+}
+
+// Removes its socket file when dropped, whether serving stopped cleanly or failed.
+pub(crate) struct SocketFile(PathBuf);
+
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        match std::fs::remove_file(&self.0) {
+            Ok(()) => info!(path = %self.0.display(), "removed unix socket"),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => {
+                warn!(path = %self.0.display(), error = %err, "failed to remove unix socket")
+            }
+        }
+    }
+}
+
+// A socket file left behind by a daemon that died refuses connections and is replaced; one
+// that still accepts them belongs to a running daemon and is left alone.
+#[cfg(unix)]
+fn remove_stale_socket(path: &Path) -> Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => {
+            return Err(err).with_context(|| format!("failed to inspect {}", path.display()))
+        }
+    };
+    if !metadata.file_type().is_socket() {
+        bail!("{} exists and is not a socket", path.display());
+    }
+    if std::os::unix::net::UnixStream::connect(path).is_ok() {
+        bail!("another process is already listening on {}", path.display());
+    }
+    warn!(path = %path.display(), "replacing stale unix socket");
+    std::fs::remove_file(path)
+        .with_context(|| format!("failed to remove stale socket {}", path.display()))
+}
+
+// The socket is bound inside a fresh owner-only directory next to `path` and only renamed
+// into place once its mode and ownership are set, so nobody can connect to it before then.
+#[cfg(unix)]
+pub(crate) fn bind_unix(
+    path: &Path,
+    settings: &SocketSettings,
+) -> Result<(tokio::net::UnixListener, SocketFile)> {
+    use std::os::unix::fs::DirBuilderExt;
+
+    remove_stale_socket(path)?;
+    let parent = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| anyhow!("{} has no file name", path.display()))?;
+    let private = parent.join(format!(".retasyncd-bind-{}", uuid::Uuid::now_v7()));
+    std::fs::DirBuilder::new()
+        .mode(0o700)
+        .create(&private)
+        .with_context(|| format!("failed to create {}", private.display()))?;
+    let bound = stage_socket(&private.join(file_name), path, settings);
+    if let Err(err) = std::fs::remove_dir_all(&private) {
+        warn!(path = %private.display(), error = %err, "failed to remove socket staging dir");
+    }
+    Ok((bound?, SocketFile(path.to_path_buf())))
+}
+
+#[cfg(unix)]
+fn stage_socket(
+    staged: &Path,
+    path: &Path,
+    settings: &SocketSettings,
+) -> Result<tokio::net::UnixListener> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let listener = tokio::net::UnixListener::bind(staged)
+        .with_context(|| format!("failed to bind unix socket {}", path.display()))?;
+    std::fs::set_permissions(staged, std::fs::Permissions::from_mode(settings.mode))
+        .with_context(|| format!("failed to set permissions on {}", path.display()))?;
+    if settings.owner.is_some() || settings.group.is_some() {
+        std::os::unix::fs::chown(staged, settings.owner, settings.group)
+            .with_context(|| format!("failed to set ownership of {}", path.display()))?;
+    }
+    std::fs::rename(staged, path)
+        .with_context(|| format!("failed to move unix socket to {}", path.display()))?;
+    Ok(listener)
+}
+
+enum Bound {
+    Tcp(TcpListener),
+    Tls(tls::TlsListener),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener, SocketFile),
+}
+
+pub(crate) struct BoundListener {
+    plan: ListenerPlan,
+    bound: Bound,
+}
+
+impl BoundListener {
+    #[cfg(test)]
+    fn local_addr(&self) -> Option<SocketAddr> {
+        match &self.bound {
+            Bound::Tcp(listener) => listener.local_addr().ok(),
+            _ => None,
+        }
+    }
+}
+
+pub(crate) async fn bind_listeners(plans: Vec<ListenerPlan>) -> Result<Vec<BoundListener>> {
+    let mut bound = Vec::with_capacity(plans.len());
+    for plan in plans {
+        let listener = match (&plan.bind, &plan.tls) {
+            (Bind::Tcp(addr), tls) => {
+                let listener = TcpListener::bind(addr)
+                    .await
+                    .with_context(|| format!("failed to bind {addr}"))?;
+                match tls {
+                    Some(tls) => {
+                        let certificates = tls::TlsCertificates::load(tls.clone())?;
+                        tls::spawn_reload_on_sighup(certificates.clone())?;
+                        Bound::Tls(tls::TlsListener::start(listener, certificates)?)
+                    }
+                    None => Bound::Tcp(listener),
+                }
+            }
+            #[cfg(unix)]
+            (Bind::Unix(path), _) => {
+                let (listener, file) = bind_unix(path, &plan.socket)?;
+                Bound::Unix(listener, file)
+            }
+            #[cfg(not(unix))]
+            (Bind::Unix(path), _) => {
+                bail!(
+                    "unix socket {} is not supported on this platform",
+                    path.display()
+                )
+            }
+        };
+        bound.push(BoundListener {
+            plan,
+            bound: listener,
+        });
+    }
+    Ok(bound)
+}
+
+// Serves the same router on every listener, each request tagged with the listener it came
+// in on. Returns once `shutdown` fires (after a short drain) or any listener fails; socket
+// files are removed either way.
+pub(crate) async fn serve_listeners(
+    app: Router,
+    listeners: Vec<BoundListener>,
+    shutdown: watch::Receiver<bool>,
+) -> Result<()> {
+    let mut servers = JoinSet::new();
+    let mut sockets = Vec::new();
+    for BoundListener { plan, bound } in listeners {
+        let tagged = app.clone().layer(Extension(plan.tag()));
+        let signal = stopped(shutdown.clone());
+        match bound {
+            Bound::Tcp(listener) => {
+                info!(
+                    listener = %plan.name,
+                    require_bearer = plan.require_bearer,
+                    "retasyncd control-plane listening"
+                );
+                servers.spawn(async move {
+                    axum::serve(
+                        listener,
+                        tagged.into_make_service_with_connect_info::<SocketAddr>(),
+                    )
+                    .with_graceful_shutdown(signal)
+                    .await
+                });
+            }
+            Bound::Tls(listener) => {
+                info!(
+                    listener = %plan.name,
+                    mutual_tls = plan.tls.as_ref().is_some_and(|tls| tls.client_ca_path.is_some()),
+                    "retasyncd control-plane listening over TLS"
+                );
+                let tagged = tagged.layer(axum::middleware::from_fn(tls::attach_principal));
+                servers.spawn(async move {
+                    axum::serve(
+                        listener,
+                        tagged.into_make_service_with_connect_info::<tls::TlsPeer>(),
+                    )
+                    .with_graceful_shutdown(signal)
+                    .await
+                });
+            }
+            #[cfg(unix)]
+            Bound::Unix(listener, file) => {
+                info!(
+                    listener = %plan.name,
+                    require_bearer = plan.require_bearer,
+                    "retasyncd control-plane listening on unix socket"
+                );
+                sockets.push(file);
+                servers.spawn(async move {
+                    axum::serve(listener, tagged)
+                        .with_graceful_shutdown(signal)
+                        .await
+                });
+            }
+        }
+    }
+
+    let stop = stopped(shutdown);
+    tokio::pin!(stop);
+    loop {
+        tokio::select! {
+            joined = servers.join_next() => match joined {
+                Some(result) => result
+                    .context("listener task failed")?
+                    .context("axum server failed")?,
+                None => break,
+            },
+            () = &mut stop => {
+                // Long-lived streams never finish on their own, so draining is bounded.
+                let drained = async { while servers.join_next().await.is_some() {} };
+                let _ = tokio::time::timeout(SHUTDOWN_GRACE, drained).await;
+                break;
+            }
+        }
+    }
+    drop(sockets);
+    Ok(())
+}
+
+async fn stopped(mut shutdown: watch::Receiver<bool>) {
+    let _ = shutdown.wait_for(|stop| *stop).await;
+}
+
+// Flips to `true` on ctrl-c or SIGTERM.
+pub(crate) fn shutdown_signal() -> Result<watch::Receiver<bool>> {
+    let (sender, receiver) = watch::channel(false);
+    #[cfg(unix)]
+    let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+        .context("failed to listen for SIGTERM")?;
+    tokio::spawn(async move {
+        #[cfg(unix)]
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+        #[cfg(not(unix))]
+        let _ = tokio::signal::ctrl_c().await;
+        info!("shutting down");
+        let _ = sender.send(true);
+    });
+    Ok(receiver)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::{
+        bind_listeners, bind_unix, parse_bind, plan_listeners, resolve_group, resolve_user,
+        serve_listeners, Bind, ListenerAuth, ListenerSection, SocketSettings,
+    };
+    use crate::HttpSection;
+    use retasync_control_plane::bootstrap::DEFAULT_BOOTSTRAP_TOKEN_TTL_SECS;
+    use retasync_control_plane::test_support::{
+        node_config, scratch_dir, scratch_sqlite, test_storage,
+    };
+    use retasync_control_plane::{build_router, AppState};
+    use retasync_mesh_bridge::InMemoryRpcMeshBridge;
+    use serde_json::json;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpStream, UnixStream};
+    use tokio::sync::watch;
+
+    fn scratch(name: &str) -> PathBuf {
+        scratch_dir().join(name)
+    }
+
+    fn unix_listener(path: &Path, auth: Option<ListenerAuth>) -> ListenerSection {
+        ListenerSection {
+            bind: format!("unix:{}", path.display()),
+            name: Some("local".to_string()),
+            auth,
+            tls: None,
+            socket_mode: Some("0600".to_string()),
+            socket_owner: None,
+            socket_group: None,
+        }
+    }
+
+    fn http(bind: &str, listeners: Vec<ListenerSection>) -> HttpSection {
+        HttpSection {
+            bind: bind.to_string(),
+            auth_token: Some("secret".to_string()),
+            api_tokens: Vec::new(),
+            tls: None,
+            listeners,
+            bootstrap: false,
+            bootstrap_token_ttl_secs: DEFAULT_BOOTSTRAP_TOKEN_TTL_SECS,
+            status_page: false,
+        }
+    }
+
+    async fn router() -> axum::Router {
+        let sqlite_path = scratch_sqlite();
+        let storage = test_storage(&sqlite_path).await;
+        let config = node_config(json!({
+            "http_bind": "127.0.0.1:0",
+            "http_auth_token": "secret",
+            "sqlite_path": sqlite_path
+        }));
+        let bridge = Arc::new(InMemoryRpcMeshBridge::new(true, true));
+        build_router(AppState::new(storage, bridge, config, String::new(), true))
+    }
+
+    const ALPHA: &str = "a1fa0000000000000000000000000001";
+    const BRAVO: &str = "b4a0000000000000000000000000000b";
+
+    fn add_allowlist(identity_hash: &str) -> String {
+        let body = json!({ "identity_hash": identity_hash }).to_string();
+        format!(
+            "POST /v1/security/allowlist HTTP/1.1\r\nHost: localhost\r\n\
+             Content-Type: application/json\r\nContent-Length: {}\r\n\
+             Connection: close\r\n\r\n{body}",
+            body.len()
+        )
+    }
+
+    async fn exchange<S>(mut stream: S, request: String) -> String
+    where
+        S: AsyncReadExt + AsyncWriteExt + Unpin,
+    {
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await.unwrap();
+        String::from_utf8_lossy(&response).into_owned()
+    }
+
+    #[tokio::test]
+    async fn tcp_and_unix_listeners_serve_together_with_their_own_auth() {
+        let socket_path = scratch("listener").with_extension("sock");
+        let mut http = http("127.0.0.1:0", vec![unix_listener(&socket_path, None)]);
+        // The unix socket defaults to bearer auth like any other listener.
+        assert!(plan_listeners(&http).unwrap()[1].require_bearer);
+        http.listeners[0].auth = Some(ListenerAuth::None);
+        let plans = plan_listeners(&http).unwrap();
+        assert_eq!(plans[1].bind, Bind::Unix(socket_path.clone()));
+
+        let listeners = bind_listeners(plans).await.unwrap();
+        let tcp_addr = listeners[0].local_addr().unwrap();
+        // The TCP listener is on loopback; make it enforce tokens like a public bind would.
+        let mut listeners = listeners;
+        listeners[0].plan.require_bearer = true;
+        let mode = std::fs::metadata(&socket_path)
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        let (stop, shutdown) = watch::channel(false);
+        let server = tokio::spawn(serve_listeners(router().await, listeners, shutdown));
+
+        let tcp = TcpStream::connect(tcp_addr).await.unwrap();
+        let response = exchange(tcp, add_allowlist(ALPHA)).await;
+        assert!(response.starts_with("HTTP/1.1 401"), "{response}");
+
+        let unix = UnixStream::connect(&socket_path).await.unwrap();
+        let response = exchange(unix, add_allowlist(BRAVO)).await;
+        assert!(response.starts_with("HTTP/1.1 201"), "{response}");
+        assert!(response.contains(r#""status":"active""#), "{response}");
+
+        stop.send(true).unwrap();
+        tokio::time::timeout(Duration::from_secs(10), server)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert!(!socket_path.exists());
+    }
+
+    #[tokio::test]
+    async fn stale_sockets_are_replaced_but_live_ones_are_left_alone() {
+        let socket_path = scratch("stale").with_extension("sock");
+        let live = std::os::unix::net::UnixListener::bind(&socket_path).unwrap();
+        let err = bind_unix(&socket_path, &SocketSettings::default())
+            .err()
+            .expect("live socket kept");
+        assert!(err.to_string().contains("already listening"), "{err}");
+
+        // Dropping the listener leaves its file behind, as a crashed daemon would.
+        drop(live);
+        assert!(socket_path.exists());
+        let (listener, file) = bind_unix(&socket_path, &SocketSettings::default()).unwrap();
+        let mode = std::fs::metadata(&socket_path)
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o660);
+        assert!(UnixStream::connect(&socket_path).await.is_ok());
+
+        drop((listener, file));
+        assert!(!socket_path.exists());
+
+        let regular = scratch("not-a-socket");
+        std::fs::write(&regular, b"keep me").unwrap();
+        let err = bind_unix(&regular, &SocketSettings::default())
+            .err()
+            .expect("regular file kept");
+        assert!(err.to_string().contains("is not a socket"), "{err}");
+        assert_eq!(std::fs::read(&regular).unwrap(), b"keep me");
+    }
+
+    #[test]
+    fn socket_owners_resolve_by_name_or_id() {
+        assert_eq!(resolve_user("root").unwrap(), 0);
+        assert_eq!(resolve_user("1234").unwrap(), 1234);
+        assert_eq!(resolve_group("0").unwrap(), 0);
+        let err = resolve_user("no-such-retasync-user").expect_err("unknown user");
+        assert!(err.to_string().contains("unknown user"), "{err}");
+    }
+
+    #[test]
+    fn listener_plans_reject_unsafe_combinations() {
+        assert!(parse_bind("unix:").is_err());
+        let mut tcp_without_auth = unix_listener(Path::new("/unused"), Some(ListenerAuth::None));
+        tcp_without_auth.bind = "127.0.0.1:9090".to_string();
+        tcp_without_auth.socket_mode = None;
+        let err = plan_listeners(&http("127.0.0.1:8080", vec![tcp_without_auth]))
+            .expect_err("auth none rejected on tcp");
+        assert!(err.to_string().contains("only allowed on unix"), "{err}");
+
+        let mut untokened = http(
+            "127.0.0.1:8080",
+            vec![unix_listener(Path::new("/a.sock"), None)],
+        );
+        untokened.auth_token = None;
+        let err = plan_listeners(&untokened).expect_err("bearer without tokens rejected");
+        assert!(err.to_string().contains("requires tls"), "{err}");
+
+        let err =
+            plan_listeners(&http("unix:/b.sock", Vec::new())).expect_err("primary must be tcp");
+        assert!(
+            err.to_string().contains("must be a TCP socket address"),
+            "{err}"
+        );
+
+        let duplicate = http(
+            "127.0.0.1:8080",
+            vec![
+                unix_listener(Path::new("/c.sock"), None),
+                unix_listener(Path::new("/d.sock"), None),
+            ],
+        );
+        let err = plan_listeners(&duplicate).expect_err("duplicate names rejected");
+        assert!(err.to_string().contains("named local"), "{err}");
+    }
+}
@@ -1,13 +1,81 @@
-﻿use std::{net::SocketAddr, path::PathBuf, sync::Arc};
+﻿use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use clap::{Parser, Subcommand};
-use retasync_control_plane::{build_router, AppState, NodeConfig};
-use retasync_mesh_bridge::InMemoryRpcMeshBridge;
-use retasync_storage::{RetasyncStorage, StorageConfig};
+use retasync_contract::{
+    CodecLimits, ContractRegistry, IdentityValidation, DEFAULT_COMPRESSION_THRESHOLD,
+};
+use retasync_control_plane::{
+    aggregates::AggregateSettings,
+    archive::{find_job, find_job_transfers, RetentionSettings},
+    attachments::AttachmentSettings,
+    bootstrap::{bootstrap_router, ProvisioningToken, DEFAULT_BOOTSTRAP_TOKEN_TTL_SECS},
+    build_router,
+    bundles::BundleSettings,
+    clock::ClockSettings,
+    coalescing::EventCoalescingSettings,
+    config_apply::ConfigApplySettings,
+    consistency::ConsistencySettings,
+    crash::{install_panic_hook, CrashReportSettings},
+    cursor::PaginationSettings,
+    dedup::TransferDedupSettings,
+    delivery::DeliverySettings,
+    dependencies::DependencySettings,
+    dispatch::{validate_dispatch_config, IdentitySettings, OperationDefaults},
+    dispatch_queue::DispatchQueueSettings,
+    feed::EventFeedSettings,
+    files::FileSettings,
+    fleet::FleetSettings,
+    handlers::HandlerRegistry,
+    inbound::InboundSettings,
+    job_replay::replay_records,
+    leases::JobWatchdogSettings,
+    liveness::LivenessSettings,
+    migrations::{migrate_stored_payloads, stored_tables, MigrationRegistry, PayloadMigrationSettings},
+    profiles::{find_profile, EffectiveConfig},
+    quotas::QuotaSettings,
+    receipts::ReceiptSettings,
+    result_cache::ResultCacheSettings,
+    result_validation::ResultValidationSettings,
+    runtime::ControlPlaneRuntime,
+    sealing::SecuritySettings,
+    sizing::{DEFAULT_MAX_LINK_BYTES, DEFAULT_MAX_LXMF_BYTES},
+    sneakernet::SneakernetSettings,
+    spool::TransferSpoolSettings,
+    submission_spool::SubmissionSpoolSettings,
+    submissions::SubmissionSettings,
+    trace::RoutingSettings,
+    transforms::TransformSettings,
+    webhooks::WebhookSettings,
+    ApiToken, AppState, NodeConfig, NotificationSettings, DEFAULT_RESULT_STREAM_TIMEOUT_SECS,
+    DEFAULT_TRANSFER_STALL_AFTER_SECS,
+};
+use retasync_mesh_bridge::{
+    read_recording, summarize, BridgeStack, InMemoryRpcMeshBridge, LoggingLayer, MetricsLayer,
+    MockResults, RecordingLayer, ReplayBridge, ReplayMatching, RpcMeshBridge, SimulatedMeshBridge,
+    SimulationProfile, TcpPoolSettings, TcpRpcMeshBridge, DEFAULT_PING_INTERVAL_SECS,
+    DEFAULT_POOL_SIZE, DEFAULT_REQUEST_TIMEOUT_SECS,
+};
+use retasync_storage::{RetasyncStorage, StorageConfig, DEFAULT_READ_POOL_SIZE};
 use serde::Deserialize;
 use tracing::{info, warn};
 
+mod bench;
+mod check_config;
+mod doctor;
+mod layout;
+mod listeners;
+mod migrations;
+mod replay_jobs;
+mod tail;
+mod tls;
+
+const CONTRACT_PATH: &str = "contracts/retasyncapi-v1.asyncapi.yaml";
+
 #[derive(Debug, Parser)]
 #[command(author, version, about = "Reticulum AsyncAPI control-plane daemon")]
 struct Cli {
@@ -20,42 +88,359 @@ enum Command {
     Serve {
         #[arg(long, default_value = "config/node.toml")]
         config: PathBuf,
+        // Settles files found in both a legacy and the current location, which otherwise stop
+        // the daemon from starting.
+        #[arg(long, value_enum)]
+        migrate_layout: Option<layout::LayoutChoice>,
+        // Replaces the `profile` the config file names.
+        #[arg(long)]
+        profile: Option<String>,
+    },
+    Init {
+        #[arg(long, default_value = "config/node.toml")]
+        config: PathBuf,
+        #[arg(long)]
+        profile: String,
+        #[arg(long)]
+        force: bool,
+    },
+    EncryptDb {
+        #[arg(long, default_value = "config/node.toml")]
+        config: PathBuf,
+    },
+    RotateDbKey {
+        #[arg(long, default_value = "config/node.toml")]
+        config: PathBuf,
+        #[arg(long)]
+        old: PathBuf,
+        #[arg(long)]
+        new: PathBuf,
+    },
+    Doctor {
+        #[arg(long, default_value = "config/node.toml")]
+        config: PathBuf,
+        #[arg(long)]
+        json: bool,
+    },
+    ReplayInfo {
+        file: PathBuf,
+        #[arg(long)]
+        json: bool,
+    },
+    CheckConfig {
+        #[arg(long, default_value = "config/node.toml")]
+        config: PathBuf,
+        #[arg(long, value_enum, default_value = "text")]
+        format: check_config::ReportFormat,
+    },
+    ArchiveQuery {
+        #[arg(long)]
+        dir: PathBuf,
+        #[arg(long)]
+        job_id: String,
+    },
+    Bench {
+        #[arg(long, default_value = "config/node.toml")]
+        config: PathBuf,
+        #[arg(long, value_enum, default_value = "all")]
+        suite: bench::BenchSuite,
+        #[arg(long, default_value = "30s")]
+        duration: String,
+        // Benchmark database; defaults to a scratch file beside the configured one.
+        #[arg(long)]
+        storage_path: Option<PathBuf>,
+        #[arg(long)]
+        allow_live: bool,
+        #[arg(long)]
+        json: bool,
+    },
+    // Runs the inbound commands recorded under `inbound.record_inbound` through this build's
+    // handlers and reports every answer that differs from the recorded one.
+    ReplayJobs {
+        #[arg(long, default_value = "config/node.toml")]
+        config: PathBuf,
+        // Database holding the records; defaults to the configured one.
+        #[arg(long)]
+        db: Option<PathBuf>,
+        // An RFC 3339 time, or a span back from now such as `7d`.
+        #[arg(long)]
+        since: String,
+        #[arg(long)]
+        against_handlers: bool,
+        #[arg(long)]
+        json: bool,
+    },
+    MigratePayloads {
+        #[arg(long, default_value = "config/node.toml")]
+        config: PathBuf,
+        #[arg(long)]
+        dry_run: bool,
+        // Also migrate cached events, whatever `payload_migrations.cached_events` says.
+        #[arg(long)]
+        include_cached_events: bool,
+        #[arg(long)]
+        json: bool,
+    },
+    Tail {
+        #[arg(long, default_value = "http://127.0.0.1:8080")]
+        base_url: String,
+        #[arg(long)]
+        token: Option<String>,
+        #[arg(long)]
+        ca_cert: Option<PathBuf>,
+        #[arg(long, value_delimiter = ',')]
+        events: Vec<String>,
+        #[arg(long)]
+        since: Option<String>,
+        #[arg(long)]
+        json: bool,
     },
 }
 
 #[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
 struct RuntimeConfig {
+    // Filled in from the effective config, since the merged document no longer carries it.
+    profile: Option<String>,
     rpc: RpcSection,
     http: HttpSection,
     storage: StorageSection,
     acl: AclSection,
     transport: TransportSection,
+    #[serde(default)]
+    notifications: NotificationSettings,
+    #[serde(default)]
+    inbound: InboundSettings,
+    #[serde(default)]
+    codec: CodecLimits,
+    #[serde(default)]
+    identity: IdentitySettings,
+    #[serde(default)]
+    operation_defaults: BTreeMap<String, OperationDefaults>,
+    #[serde(default)]
+    contract: ContractSection,
+    #[serde(default)]
+    attachments: AttachmentSettings,
+    #[serde(default)]
+    health: HealthSection,
+    #[serde(default)]
+    retention: RetentionSettings,
+    #[serde(default)]
+    submissions: SubmissionSettings,
+    #[serde(default)]
+    delivery: DeliverySettings,
+    #[serde(default)]
+    aggregates: AggregateSettings,
+    #[serde(default)]
+    liveness: LivenessSettings,
+    #[serde(default)]
+    job_watchdog: JobWatchdogSettings,
+    #[serde(default)]
+    transfer_dedup: TransferDedupSettings,
+    #[serde(default)]
+    transfer_bundles: BundleSettings,
+    #[serde(default)]
+    quotas: QuotaSettings,
+    #[serde(default)]
+    routing: RoutingSettings,
+    #[serde(default)]
+    dependencies: DependencySettings,
+    #[serde(default)]
+    transforms: Vec<TransformSettings>,
+    #[serde(default)]
+    event_feed: EventFeedSettings,
+    #[serde(default)]
+    consistency: ConsistencySettings,
+    #[serde(default)]
+    clock: ClockSettings,
+    #[serde(default)]
+    payload_migrations: PayloadMigrationSettings,
+    #[serde(default)]
+    files: FileSettings,
+    #[serde(default)]
+    transfer_spool: TransferSpoolSettings,
+    #[serde(default)]
+    submission_spool: SubmissionSpoolSettings,
+    #[serde(default)]
+    result_validation: ResultValidationSettings,
+    #[serde(default)]
+    dispatch_queue: DispatchQueueSettings,
+    #[serde(default)]
+    sneakernet: SneakernetSettings,
+    #[serde(default)]
+    crash_reports: CrashReportSettings,
+    #[serde(default)]
+    config_apply: ConfigApplySettings,
+    #[serde(default)]
+    fleet: FleetSettings,
+    #[serde(default)]
+    pagination: PaginationSettings,
+    #[serde(default)]
+    webhooks: WebhookSettings,
+    #[serde(default)]
+    result_cache: ResultCacheSettings,
+    #[serde(default)]
+    event_coalescing: EventCoalescingSettings,
+    #[serde(default)]
+    security: SecuritySettings,
+    #[serde(default)]
+    receipts: ReceiptSettings,
+    #[serde(default)]
+    bridge: BridgeSection,
+    #[serde(default)]
+    debug: DebugSection,
 }
 
 #[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
 struct RpcSection {
     endpoint: String,
+    #[serde(default = "default_pool_size")]
+    pool_size: usize,
+    #[serde(default = "default_request_timeout_secs")]
+    request_timeout_secs: u64,
+    #[serde(default = "default_ping_interval_secs")]
+    ping_interval_secs: u64,
+}
+
+impl RpcSection {
+    fn pool_settings(&self, codec_limits: CodecLimits) -> TcpPoolSettings {
+        TcpPoolSettings {
+            pool_size: self.pool_size,
+            request_timeout: std::time::Duration::from_secs(self.request_timeout_secs),
+            ping_interval: std::time::Duration::from_secs(self.ping_interval_secs),
+            codec_limits,
+        }
+    }
+}
+
+fn default_pool_size() -> usize {
+    DEFAULT_POOL_SIZE
+}
+
+fn default_request_timeout_secs() -> u64 {
+    DEFAULT_REQUEST_TIMEOUT_SECS
+}
+
+fn default_ping_interval_secs() -> u64 {
+    DEFAULT_PING_INTERVAL_SECS
 }
 
 #[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
 struct HttpSection {
     bind: String,
     auth_token: Option<String>,
+    #[serde(default)]
+    api_tokens: Vec<ApiToken>,
+    tls: Option<tls::TlsSection>,
+    #[serde(default)]
+    listeners: Vec<listeners::ListenerSection>,
+    #[serde(default)]
+    bootstrap: bool,
+    #[serde(default = "default_bootstrap_token_ttl_secs")]
+    bootstrap_token_ttl_secs: u64,
+    #[serde(default)]
+    status_page: bool,
+}
+
+fn default_bootstrap_token_ttl_secs() -> u64 {
+    DEFAULT_BOOTSTRAP_TOKEN_TTL_SECS
+}
+
+impl HttpSection {
+    fn has_tokens(&self) -> bool {
+        self.auth_token.is_some() || !self.api_tokens.is_empty()
+    }
+
+    // Bootstrap mode only applies until a token exists; the tokens it collects replace the
+    // one-time token it guards the node with meanwhile.
+    fn bootstraps(&self) -> bool {
+        self.bootstrap && !self.has_tokens()
+    }
+
+    // Off loopback, plain HTTP needs a bearer token; TLS may stand in for one.
+    fn secures_remote_access(&self) -> bool {
+        self.has_tokens() || self.bootstraps() || self.tls.is_some()
+    }
 }
 
 #[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
 struct StorageSection {
     sqlite_path: String,
+    encryption_key_path: Option<String>,
+    #[serde(default = "default_read_pool_size")]
+    read_pool_size: u32,
+    // `sqlite_path` as written, when relative; see `layout::anchor_paths`.
+    #[serde(skip)]
+    legacy_sqlite_path: Option<String>,
+}
+
+fn default_read_pool_size() -> u32 {
+    DEFAULT_READ_POOL_SIZE
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ContractSection {
+    #[serde(default)]
+    allow_sunset_operations: bool,
+    // Directory holding the contract; unset reads `CONTRACT_PATH`.
+    contracts_dir: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct HealthSection {
+    sample_interval_secs: u64,
+}
+
+impl Default for HealthSection {
+    fn default() -> Self {
+        Self {
+            sample_interval_secs: 30,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct BridgeSection {
+    layers: Vec<String>,
+}
+
+impl Default for BridgeSection {
+    fn default() -> Self {
+        Self {
+            layers: vec!["logging".to_string()],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DebugSection {
+    record_bridge: Option<String>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
 struct AclSection {
     mode: String,
+    #[serde(default)]
+    identity_validation: IdentityValidation,
 }
 
 #[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
 struct TransportSection {
     prefer_link: bool,
+    compression_threshold_bytes: Option<usize>,
+    transfer_stall_after_secs: Option<u64>,
+    result_stream_timeout_secs: Option<u64>,
+    max_link_bytes: Option<usize>,
+    max_lxmf_bytes: Option<usize>,
 }
 
 #[tokio::main]
@@ -69,68 +454,570 @@ async fn main() -> Result<()> {
 
     let cli = Cli::parse();
     match cli.command {
-        Command::Serve { config } => serve(config).await,
+        Command::Serve {
+            config,
+            migrate_layout,
+            profile,
+        } => serve(config, migrate_layout, profile).await,
+        Command::Init {
+            config,
+            profile,
+            force,
+        } => init(config, &profile, force),
+        Command::EncryptDb { config } => encrypt_db(config).await,
+        Command::RotateDbKey { config, old, new } => rotate_db_key(config, old, new).await,
+        Command::Doctor { config, json } => {
+            let contract_path = load_runtime_config(&config)
+                .map(|runtime| layout::contract_path(&runtime))
+                .unwrap_or_else(|_| PathBuf::from(CONTRACT_PATH));
+            let report = doctor::run_checks(&config, &contract_path).await;
+            doctor::print_report(&report, json);
+            std::process::exit(doctor::exit_code(&report));
+        }
+        Command::ReplayInfo { file, json } => replay_info(file, json),
+        Command::CheckConfig { config, format } => {
+            let report = check_config::check_file(&config);
+            check_config::print_report(&report, format);
+            std::process::exit(if report.valid { 0 } else { 2 });
+        }
+        Command::ArchiveQuery { dir, job_id } => archive_query(dir, job_id),
+        Command::Bench {
+            config,
+            suite,
+            duration,
+            storage_path,
+            allow_live,
+            json,
+        } => {
+            let options = bench::BenchOptions {
+                suite,
+                duration: bench::parse_duration(&duration)?,
+                storage_path,
+                allow_live,
+            };
+            run_bench(config, options, json).await
+        }
+        Command::MigratePayloads {
+            config,
+            dry_run,
+            include_cached_events,
+            json,
+        } => migrate_payloads(config, dry_run, include_cached_events, json).await,
+        Command::ReplayJobs {
+            config,
+            db,
+            since,
+            against_handlers,
+            json,
+        } => replay_jobs(config, db, &since, against_handlers, json).await,
+        Command::Tail {
+            base_url,
+            token,
+            ca_cert,
+            events,
+            since,
+            json,
+        } => {
+            let options = tail::TailOptions {
+                base_url,
+                token,
+                ca_cert,
+                events,
+                since,
+            };
+            tail::run(options, json).await
+        }
     }
 }
 
-async fn serve(config_path: PathBuf) -> Result<()> {
-    let config_source = std::fs::read_to_string(&config_path)
+fn load_runtime_config(config_path: &Path) -> Result<RuntimeConfig> {
+    read_runtime_config(config_path).map(|(config, _)| config)
+}
+
+// The config with legacy key names read under their current ones, with a note per renamed key.
+fn read_runtime_config(config_path: &Path) -> Result<(RuntimeConfig, Vec<String>)> {
+    read_profiled_config(config_path, None).map(|(config, notes, _)| (config, notes))
+}
+
+// As `read_runtime_config`, with the profile preset merged in and where each value came from.
+// `profile` replaces the one the file names.
+fn read_profiled_config(
+    config_path: &Path,
+    profile: Option<&str>,
+) -> Result<(RuntimeConfig, Vec<String>, EffectiveConfig)> {
+    let config_source = std::fs::read_to_string(config_path)
         .with_context(|| format!("failed to read config file {}", config_path.display()))?;
-    let config: RuntimeConfig = toml::from_str(&config_source)
-        .with_context(|| format!("invalid config TOML at {}", config_path.display()))?;
+    let invalid = || format!("invalid config TOML at {}", config_path.display());
+    let mut document: toml::Value = toml::from_str(&config_source).with_context(invalid)?;
+    let notes = layout::rename_legacy_keys(&mut document).with_context(invalid)?;
+    let effective = EffectiveConfig::resolve(&serde_json::to_value(&document)?, profile)?;
+    // A preset fills in sections the file leaves out, so with one the merged config is parsed.
+    // Otherwise, without renames, the source itself is, so errors keep their line numbers.
+    let mut config: RuntimeConfig = if effective.profile.is_some() {
+        serde_json::from_value(effective.config.clone()).with_context(invalid)?
+    } else if notes.is_empty() {
+        toml::from_str(&config_source).with_context(invalid)?
+    } else {
+        document.try_into().with_context(invalid)?
+    };
+    config.profile = effective.profile.clone();
+    for note in &notes {
+        warn!(config = %config_path.display(), "{note}");
+    }
+    layout::anchor_paths(config_path, &mut config);
+    Ok((config, notes, effective))
+}
+
+// Writes a node.toml holding only the profile line and the secrets the preset needs.
+fn init(config_path: PathBuf, profile: &str, force: bool) -> Result<()> {
+    let profile = find_profile(profile)?;
+    if config_path.exists() && !force {
+        return Err(anyhow!(
+            "{} already exists; pass --force to replace it",
+            config_path.display()
+        ));
+    }
+    if let Some(dir) = config_path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create {}", dir.display()))?;
+    }
+    std::fs::write(&config_path, profile.init_document()?)
+        .with_context(|| format!("failed to write {}", config_path.display()))?;
+    println!("wrote {} for the {} profile", config_path.display(), profile.name);
+    Ok(())
+}
+
+// Brings files an older layout left behind to where `config` expects them.
+fn upgrade_layout(
+    config: &RuntimeConfig,
+    choice: Option<layout::LayoutChoice>,
+) -> Result<Vec<String>> {
+    let cwd = std::env::current_dir().context("failed to read working directory")?;
+    layout::upgrade(config, &cwd, choice)
+}
+
+async fn serve(
+    config_path: PathBuf,
+    migrate_layout: Option<layout::LayoutChoice>,
+    profile: Option<String>,
+) -> Result<()> {
+    let (config, mut layout_notes, effective) =
+        read_profiled_config(&config_path, profile.as_deref())?;
+    if let Some(profile) = &config.profile {
+        info!(profile, "node profile preset applied");
+    }
+    layout_notes.extend(upgrade_layout(&config, migrate_layout)?);
+    let contract_path = layout::contract_path(&config);
 
     let storage = RetasyncStorage::connect(&StorageConfig {
         sqlite_path: config.storage.sqlite_path.clone(),
+        encryption_key_path: config.storage.encryption_key_path.clone(),
+        read_pool_size: config.storage.read_pool_size,
     })
     .await?;
+    install_panic_hook(config.crash_reports.dir_for(storage.database_path()));
 
-    let contract_doc = std::fs::read_to_string("contracts/retasyncapi-v1.asyncapi.yaml")
-        .context("failed to load contracts/retasyncapi-v1.asyncapi.yaml")?;
-
-    let require_bearer = requires_token(&config.http.bind);
-    if require_bearer && config.http.auth_token.is_none() {
-        return Err(anyhow!(
-            "non-loopback bind {} requires http.auth_token",
-            config.http.bind
-        ));
-    }
+    let contract_doc = std::fs::read_to_string(&contract_path)
+        .with_context(|| format!("failed to load {}", contract_path.display()))?;
+    ContractRegistry::from_yaml(&contract_doc)
+        .with_context(|| format!("invalid contract {}", contract_path.display()))?;
 
+    let plans = listeners::plan_listeners(&config.http)?;
+    // Requests always carry their listener's requirement; this covers any that do not.
+    let require_bearer = plans[0].require_bearer;
     if !require_bearer {
         info!("loopback bind detected: bearer auth optional");
     } else {
         warn!("non-loopback bind detected: bearer auth enforced");
     }
 
-    let node_config = NodeConfig {
+    let tls_principals = plans
+        .iter()
+        .filter_map(|plan| plan.tls.as_ref())
+        .flat_map(|tls| tls.principals.clone())
+        .collect();
+    let node_config = node_config(&config, tls_principals);
+    validate_dispatch_config(&node_config).map_err(|detail| anyhow!(detail))?;
+    if !layout_notes.is_empty() {
+        storage
+            .note_node_config_revision(
+                &serde_json::to_string(&node_config)?,
+                layout::LAYOUT_MIGRATION_REASON,
+                &layout_notes.join("\n"),
+            )
+            .await?;
+    }
+
+    let (bridge, simulation) = build_bridge(&config)?;
+    let state = AppState::new(storage, bridge, node_config, contract_doc, require_bearer)
+        .with_contract_source(contract_path)
+        .with_effective_config(effective);
+    let state = match simulation {
+        Some(simulation) => state.with_simulation(simulation),
+        None => state,
+    };
+    migrations::register(&state.migrations);
+    // The workers run for the life of the process.
+    ControlPlaneRuntime::new(state.clone())
+        .health_sample_interval(std::time::Duration::from_secs(
+            config.health.sample_interval_secs,
+        ))
+        .start(std::future::pending())
+        .await?;
+    let app = if config.http.bootstraps() {
+        let ttl_secs = config.http.bootstrap_token_ttl_secs;
+        let token = ProvisioningToken::generate(ttl_secs, chrono::Utc::now())?;
+        warn!(
+            provisioning_token = token.as_str(),
+            expires_at = %token.expires_at().to_rfc3339(),
+            "bootstrap mode: POST /v1/bootstrap with this token to provision the node"
+        );
+        bootstrap_router(state, token, config_path)
+    } else {
+        build_router(state)
+    };
+
+    let shutdown = listeners::shutdown_signal()?;
+    let bound = listeners::bind_listeners(plans).await?;
+    listeners::serve_listeners(app, bound, shutdown).await
+}
+
+// The control plane's view of the config. `tls_principals` come from the TLS listeners, which
+// only `serve` sets up.
+fn node_config(config: &RuntimeConfig, tls_principals: BTreeMap<String, String>) -> NodeConfig {
+    NodeConfig {
         rpc_endpoint: config.rpc.endpoint.clone(),
         http_bind: config.http.bind.clone(),
         http_auth_token: config.http.auth_token.clone(),
         sqlite_path: config.storage.sqlite_path.clone(),
         acl_mode: config.acl.mode.clone(),
+        identity_validation: config.acl.identity_validation,
         prefer_link: config.transport.prefer_link,
+        compression_threshold_bytes: config
+            .transport
+            .compression_threshold_bytes
+            .unwrap_or(DEFAULT_COMPRESSION_THRESHOLD),
+        transfer_stall_after_secs: config
+            .transport
+            .transfer_stall_after_secs
+            .unwrap_or(DEFAULT_TRANSFER_STALL_AFTER_SECS),
+        result_stream_timeout_secs: config
+            .transport
+            .result_stream_timeout_secs
+            .unwrap_or(DEFAULT_RESULT_STREAM_TIMEOUT_SECS),
+        max_link_bytes: config
+            .transport
+            .max_link_bytes
+            .unwrap_or(DEFAULT_MAX_LINK_BYTES),
+        max_lxmf_bytes: config
+            .transport
+            .max_lxmf_bytes
+            .unwrap_or(DEFAULT_MAX_LXMF_BYTES),
+        api_tokens: config.http.api_tokens.clone(),
+        tls_principals,
+        status_page: config.http.status_page,
+        notifications: config.notifications.clone(),
+        inbound: config.inbound.clone(),
+        codec_limits: config.codec,
+        identity: config.identity.clone(),
+        operation_defaults: config.operation_defaults.clone(),
+        allow_sunset_operations: config.contract.allow_sunset_operations,
+        attachments: config.attachments.clone(),
+        retention: config.retention.clone(),
+        submissions: config.submissions.clone(),
+        delivery: config.delivery.clone(),
+        aggregates: config.aggregates.clone(),
+        liveness: config.liveness.clone(),
+        job_watchdog: config.job_watchdog.clone(),
+        transfer_dedup: config.transfer_dedup.clone(),
+        transfer_bundles: config.transfer_bundles.clone(),
+        quotas: config.quotas.clone(),
+        routing: config.routing.clone(),
+        dependencies: config.dependencies.clone(),
+        transforms: config.transforms.clone(),
+        event_feed: config.event_feed.clone(),
+        consistency: config.consistency.clone(),
+        clock: config.clock.clone(),
+        payload_migrations: config.payload_migrations.clone(),
+        files: config.files.clone(),
+        transfer_spool: config.transfer_spool.clone(),
+        submission_spool: config.submission_spool.clone(),
+        result_validation: config.result_validation.clone(),
+        dispatch_queue: config.dispatch_queue.clone(),
+        sneakernet: config.sneakernet.clone(),
+        crash_reports: config.crash_reports.clone(),
+        config_apply: config.config_apply.clone(),
+        fleet: config.fleet.clone(),
+        pagination: config.pagination.clone(),
+        webhooks: config.webhooks.clone(),
+        result_cache: config.result_cache.clone(),
+        event_coalescing: config.event_coalescing.clone(),
+        security: config.security.clone(),
+        receipts: config.receipts.clone(),
+    }
+}
+
+type BridgeSetup = (Arc<dyn RpcMeshBridge>, Option<Arc<SimulatedMeshBridge>>);
+
+// `bridge.layers` wraps the transport outermost first. A recording path set without a
+// `recording` layer still records, directly above the transport.
+fn build_bridge(config: &RuntimeConfig) -> Result<BridgeSetup> {
+    let (transport, simulation) = select_bridge(config)?;
+    let mut record_path = config.debug.record_bridge.as_deref();
+    let mut stack = BridgeStack::builder();
+    for layer in &config.bridge.layers {
+        stack = match layer.as_str() {
+            "logging" => stack.layer(LoggingLayer),
+            "metrics" => stack.layer(MetricsLayer::new()),
+            "recording" => {
+                let path = record_path
+                    .take()
+                    .context("bridge layer `recording` needs debug.record_bridge")?;
+                stack.layer(open_recording(path)?)
+            }
+            other => return Err(anyhow!("unknown bridge layer `{other}`")),
+        };
+    }
+    if let Some(path) = record_path {
+        stack = stack.layer(open_recording(path)?);
+    }
+    Ok((stack.build(transport), simulation))
+}
+
+fn open_recording(path: &str) -> Result<RecordingLayer> {
+    warn!(path, "debug.record_bridge is set: recording every bridge call");
+    RecordingLayer::open(Path::new(path))
+        .with_context(|| format!("failed to open bridge recording {path}"))
+}
+
+fn select_bridge(config: &RuntimeConfig) -> Result<BridgeSetup> {
+    if let Some(endpoint) = config.rpc.endpoint.strip_prefix("replay://") {
+        let (path, matching) = parse_replay_endpoint(endpoint)?;
+        warn!(
+            path,
+            matching = ?matching,
+            "rpc.endpoint uses replay://: serving recorded responses"
+        );
+        let replay = ReplayBridge::open(Path::new(path), matching)
+            .with_context(|| format!("failed to load bridge recording {path}"))?;
+        return Ok((Arc::new(replay), None));
+    }
+    if let Some(addr) = config.rpc.endpoint.strip_prefix("tcp://") {
+        let settings = config.rpc.pool_settings(config.codec);
+        info!(addr, pool_size = settings.pool_size, "using daemon RPC over TCP");
+        return Ok((Arc::new(TcpRpcMeshBridge::new(addr, settings)), None));
+    }
+
+    let in_memory = Arc::new(InMemoryRpcMeshBridge::new(config.transport.prefer_link, true));
+    if let Some(answers) = config.rpc.endpoint.strip_prefix("mock://") {
+        let answers = parse_mock_results(answers)?;
+        warn!(answers = ?answers, "rpc.endpoint uses mock://: commands get canned results");
+        in_memory.set_results(answers);
+        return Ok((in_memory, None));
+    }
+    let Some(profile_path) = config.rpc.endpoint.strip_prefix("sim://") else {
+        return Ok((in_memory, None));
+    };
+
+    let profile = if profile_path.is_empty() {
+        SimulationProfile::default()
+    } else {
+        SimulationProfile::from_file(Path::new(profile_path))?
+    };
+    warn!(profile = ?profile, "rpc.endpoint uses sim://: mesh conditions are simulated");
+    let simulation = Arc::new(SimulatedMeshBridge::new(in_memory, profile));
+    Ok((simulation.clone(), Some(simulation)))
+}
+
+fn parse_mock_results(answers: &str) -> Result<MockResults> {
+    match answers {
+        "" | "accepted" => Ok(MockResults::Accepted),
+        "echo" => Ok(MockResults::Echo),
+        "invalid" => Ok(MockResults::Invalid),
+        other => Err(anyhow!("unsupported mock results `{other}`; use accepted, echo or invalid")),
+    }
+}
+
+fn parse_replay_endpoint(endpoint: &str) -> Result<(&str, ReplayMatching)> {
+    match endpoint.split_once('?') {
+        None => Ok((endpoint, ReplayMatching::Strict)),
+        Some((path, "mode=strict")) => Ok((path, ReplayMatching::Strict)),
+        Some((path, "mode=lenient")) => Ok((path, ReplayMatching::Lenient)),
+        Some((_, query)) => Err(anyhow!("unsupported replay endpoint option `{query}`")),
+    }
+}
+
+fn replay_info(file: PathBuf, json: bool) -> Result<()> {
+    let records = read_recording(&file)
+        .with_context(|| format!("failed to read bridge recording {}", file.display()))?;
+    let summary = summarize(&records);
+    if json {
+        println!("{}", serde_json::to_string_pretty(&summary)?);
+        return Ok(());
+    }
+
+    println!("recording: {}", file.display());
+    println!(
+        "records: {} ({} errors, {:.1}% error rate)",
+        summary.records,
+        summary.errors,
+        summary.error_rate * 100.0
+    );
+    if let (Some(first), Some(last)) = (summary.first_at, summary.last_at) {
+        println!(
+            "duration: {} ms ({} .. {})",
+            summary.duration_ms,
+            first.to_rfc3339(),
+            last.to_rfc3339()
+        );
+    }
+    for (call, stats) in &summary.calls {
+        println!("  {call:<40} calls={:<6} errors={}", stats.calls, stats.errors);
+    }
+    Ok(())
+}
+
+// Streams the archive files record by record, so large archives are never held in memory.
+fn archive_query(dir: PathBuf, job_id: String) -> Result<()> {
+    let job = find_job(&dir, &job_id)
+        .with_context(|| format!("failed to search archives in {}", dir.display()))?;
+    let Some(job) = job else {
+        eprintln!("job {job_id} not found in {}", dir.display());
+        std::process::exit(1);
     };
+    let transfers = find_job_transfers(&dir, &job_id)
+        .with_context(|| format!("failed to search archives in {}", dir.display()))?;
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&serde_json::json!({ "job": job, "transfers": transfers }))?
+    );
+    Ok(())
+}
 
-    let bridge = Arc::new(InMemoryRpcMeshBridge::new(config.transport.prefer_link, true));
-    let state = AppState::new(storage, bridge, node_config, contract_doc, require_bearer);
-    let app = build_router(state);
+async fn encrypt_db(config_path: PathBuf) -> Result<()> {
+    let config = load_runtime_config(&config_path)?;
+    upgrade_layout(&config, None)?;
+    let key_path = config
+        .storage
+        .encryption_key_path
+        .as_deref()
+        .ok_or_else(|| {
+            anyhow!("storage.encryption_key_path must be set to encrypt the database")
+        })?;
 
-    let socket: SocketAddr = config
-        .http
-        .bind
-        .parse()
-        .with_context(|| format!("invalid socket address {}", config.http.bind))?;
+    let rows = RetasyncStorage::encrypt_database(&config.storage.sqlite_path, key_path).await?;
+    info!(
+        sqlite_path = %config.storage.sqlite_path,
+        rows,
+        "database encrypted in place"
+    );
+    Ok(())
+}
 
-    let listener = tokio::net::TcpListener::bind(socket)
-        .await
-        .with_context(|| format!("failed to bind {}", config.http.bind))?;
+// Runs the same migrations `serve` applies at startup, on demand; `--dry-run` only reports.
+async fn migrate_payloads(
+    config_path: PathBuf,
+    dry_run: bool,
+    include_cached_events: bool,
+    json: bool,
+) -> Result<()> {
+    let config = load_runtime_config(&config_path)?;
+    upgrade_layout(&config, None)?;
+    let contract_path = layout::contract_path(&config);
+    let contract_doc = std::fs::read_to_string(&contract_path)
+        .with_context(|| format!("failed to load {}", contract_path.display()))?;
+    let contract = ContractRegistry::from_yaml(&contract_doc)
+        .with_context(|| format!("invalid contract {}", contract_path.display()))?;
+    let contract_version = contract.version().ok_or_else(|| {
+        anyhow!("{} has no info.version to migrate towards", contract_path.display())
+    })?;
+    let storage = RetasyncStorage::connect(&StorageConfig {
+        sqlite_path: config.storage.sqlite_path.clone(),
+        encryption_key_path: config.storage.encryption_key_path.clone(),
+        read_pool_size: config.storage.read_pool_size,
+    })
+    .await?;
 
-    info!(bind = %config.http.bind, "retasyncd control-plane listening");
-    axum::serve(listener, app).await.context("axum server failed")
+    let registry = MigrationRegistry::default();
+    migrations::register(&registry);
+    let tables = stored_tables(include_cached_events || config.payload_migrations.cached_events);
+    let report =
+        migrate_stored_payloads(&storage, &registry, contract_version, &tables, dry_run).await?;
+    migrations::print_report(&report, json)?;
+    if !report.failed.is_empty() {
+        std::process::exit(1);
+    }
+    Ok(())
 }
 
-fn requires_token(bind: &str) -> bool {
-    match bind.parse::<SocketAddr>() {
-        Ok(addr) => !addr.ip().is_loopback(),
-        Err(_) => true,
+async fn replay_jobs(
+    config_path: PathBuf,
+    db: Option<PathBuf>,
+    since: &str,
+    against_handlers: bool,
+    json: bool,
+) -> Result<()> {
+    if !against_handlers {
+        bail!("replay-jobs replays against this build's handlers; pass --against-handlers");
+    }
+    let since = replay_jobs::parse_since(since, chrono::Utc::now())?;
+    let config = load_runtime_config(&config_path)?;
+    let contract_path = layout::contract_path(&config);
+    let contract_doc = std::fs::read_to_string(&contract_path)
+        .with_context(|| format!("failed to load {}", contract_path.display()))?;
+    ContractRegistry::from_yaml(&contract_doc)
+        .with_context(|| format!("invalid contract {}", contract_path.display()))?;
+    let storage = StorageConfig {
+        sqlite_path: db.map_or_else(
+            || config.storage.sqlite_path.clone(),
+            |db| db.to_string_lossy().into_owned(),
+        ),
+        encryption_key_path: config.storage.encryption_key_path.clone(),
+        read_pool_size: config.storage.read_pool_size,
+    };
+    // Read-only; the replay itself runs against a scratch database.
+    let records = RetasyncStorage::read_inbound_records(&storage, since).await?;
+    let allowlist = RetasyncStorage::read_allowlist(&storage, chrono::Utc::now()).await?;
+    let handlers = Arc::new(HandlerRegistry::default());
+    let node_config = node_config(&config, BTreeMap::new());
+    let report = replay_records(&records, &allowlist, node_config, contract_doc, handlers).await?;
+    replay_jobs::print_report(&report, since, json)?;
+    if report.changed + report.errored > 0 {
+        std::process::exit(1);
     }
+    Ok(())
+}
+
+async fn run_bench(config_path: PathBuf, options: bench::BenchOptions, json: bool) -> Result<()> {
+    let config = load_runtime_config(&config_path)?;
+    let contract_path = layout::contract_path(&config);
+    let target = bench::BenchTarget {
+        sqlite_path: config.storage.sqlite_path.clone(),
+        encryption_key_path: config.storage.encryption_key_path.clone(),
+        read_pool_size: config.storage.read_pool_size,
+        contract_doc: std::fs::read_to_string(&contract_path)
+            .with_context(|| format!("failed to load {}", contract_path.display()))?,
+    };
+    let report = bench::run_suites(&target, &options).await?;
+    bench::print_report(&report, json);
+    Ok(())
 }
+
+async fn rotate_db_key(config_path: PathBuf, old: PathBuf, new: PathBuf) -> Result<()> {
+    let config = load_runtime_config(&config_path)?;
+    upgrade_layout(&config, None)?;
+    let rows = RetasyncStorage::rotate_encryption_key(
+        &config.storage.sqlite_path,
+        &old.to_string_lossy(),
+        &new.to_string_lossy(),
+    )
+    .await?;
+    info!(
+        sqlite_path = %config.storage.sqlite_path,
+        rows,
+        "database re-encrypted; point storage.encryption_key_path at {}",
+        new.display()
+    );
+    Ok(())
+}
+
@@ -0,0 +1,52 @@
+﻿use retasync_control_plane::migrations::{MigrationRegistry, MigrationReport};
+
+// Payload migrations for the contract this binary ships with. Each contract release that
+// renames, adds or removes a payload field registers a `PayloadMigration` here, e.g.
+//
+//     registry.register(
+//         PayloadMigration::new("group-name-snake-case", MigrationTarget::Entity("group".into()), "1.0.0", "1.1.0")
+//             .rename("/groupName", "group_name")
+//             .set_default("/priority", json!("routine")),
+//     );
+//
+// `serve` and `migrate-payloads` both read from here, so the two always agree.
+pub(crate) fn register(_registry: &MigrationRegistry) {}
+
+pub(crate) fn print_report(report: &MigrationReport, json: bool) -> anyhow::Result<()> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(report)?);
+        return Ok(());
+    }
+
+    let verb = if report.dry_run {
+        "would migrate"
+    } else {
+        "migrated"
+    };
+    println!(
+        "contract {}: {verb} {} record(s), {} failed",
+        report.contract_version,
+        report.migrated.len(),
+        report.failed.len()
+    );
+    for record in &report.migrated {
+        println!(
+            "  {:<14} {:<40} {} -> {}  [{}]",
+            record.table.as_str(),
+            record.key,
+            record.from_version.as_deref().unwrap_or("unversioned"),
+            record.to_version,
+            record.migrations.join(", ")
+        );
+    }
+    for failure in &report.failed {
+        println!(
+            "  {:<14} {:<40} FAILED {}: {}",
+            failure.table.as_str(),
+            failure.key,
+            failure.migration,
+            failure.error
+        );
+    }
+    Ok(())
+}
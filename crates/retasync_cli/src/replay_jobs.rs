@@ -0,0 +1,86 @@
+﻿use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Duration, Utc};
+use retasync_control_plane::job_replay::{ReplayOutcome, ReplayReport};
+
+// `--since` is an RFC 3339 time or a span back from `now` in minutes, hours or days.
+pub(crate) fn parse_since(raw: &str, now: DateTime<Utc>) -> Result<DateTime<Utc>> {
+    if let Ok(at) = DateTime::parse_from_rfc3339(raw) {
+        return Ok(at.with_timezone(&Utc));
+    }
+    let split = raw.find(|c: char| !c.is_ascii_digit()).unwrap_or(raw.len());
+    let (amount, unit) = raw.split_at(split);
+    let amount: i64 = amount
+        .parse()
+        .map_err(|_| anyhow!("--since {raw:?} is neither an RFC 3339 time nor a span like 7d"))?;
+    let span = match unit {
+        "m" => Duration::minutes(amount),
+        "h" => Duration::hours(amount),
+        "d" => Duration::days(amount),
+        _ => bail!("--since {raw:?} has an unknown unit; use m, h or d"),
+    };
+    Ok(now - span)
+}
+
+pub(crate) fn print_report(report: &ReplayReport, since: DateTime<Utc>, json: bool) -> Result<()> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(report)?);
+        return Ok(());
+    }
+
+    println!(
+        "replayed {} command(s) received since {}: {} identical, {} changed, {} errored",
+        report.replayed,
+        since.to_rfc3339(),
+        report.identical,
+        report.changed,
+        report.errored
+    );
+    for (operation, tally) in &report.operations {
+        println!(
+            "  {:<32} {} identical, {} changed, {} errored",
+            operation, tally.identical, tally.changed, tally.errored
+        );
+    }
+    for command in &report.differences {
+        let outcome = match command.outcome {
+            ReplayOutcome::Identical => "IDENTICAL",
+            ReplayOutcome::Changed => "CHANGED",
+            ReplayOutcome::Errored => "ERRORED",
+        };
+        println!("{outcome} {} {}", command.operation, command.message_id);
+        for diff in &command.diffs {
+            let shown = |value: &Option<serde_json::Value>| {
+                value.as_ref().map_or_else(|| "(absent)".to_string(), ToString::to_string)
+            };
+            let pointer = if diff.pointer.is_empty() { "(result)" } else { &diff.pointer };
+            println!("  {pointer}: {} -> {}", shown(&diff.recorded), shown(&diff.replayed));
+        }
+        if let Some(error) = &command.error {
+            println!("  {error}");
+        }
+    }
+    if report.bridge_calls_refused > 0 {
+        println!(
+            "handlers tried {} bridge call(s); the sandbox refused them",
+            report.bridge_calls_refused
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_since;
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn since_takes_a_time_or_a_span_back_from_now() {
+        let now = Utc.with_ymd_and_hms(2026, 5, 8, 12, 0, 0).unwrap();
+        let week_ago = Utc.with_ymd_and_hms(2026, 5, 1, 12, 0, 0).unwrap();
+        assert_eq!(parse_since("7d", now).unwrap(), week_ago);
+        assert_eq!(parse_since("168h", now).unwrap(), week_ago);
+        assert_eq!(parse_since("2026-05-01T14:00:00+02:00", now).unwrap(), week_ago);
+        assert!(parse_since("7w", now).is_err());
+        assert!(parse_since("last week", now).is_err());
+    }
+}
@@ -0,0 +1,845 @@
+﻿use std::collections::HashSet;
+use std::fmt;
+use std::io::{IsTerminal, Write};
+use std::ops::ControlFlow;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+use http_body_util::{BodyExt, Empty};
+use hyper::body::{Bytes, Incoming};
+use hyper::client::conn::http1;
+use hyper::header::{ACCEPT, AUTHORIZATION, HOST};
+use hyper::{Request, Response, Uri};
+use hyper_util::rt::TokioIo;
+use retasync_control_plane::{event_type_matches, LogLine};
+use rustls::crypto::ring;
+use rustls::pki_types::ServerName;
+use rustls::{ClientConfig, RootCertStore};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+
+use crate::tls::read_certificates;
+
+const STREAM_PATH: &str = "/v1/logs/stream";
+const BACKFILL_PAGE: usize = 500;
+const RECONNECT_DELAY: Duration = Duration::from_millis(500);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+const SUMMARY_MAX_CHARS: usize = 160;
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct TailOptions {
+    pub base_url: String,
+    pub token: Option<String>,
+    pub ca_cert: Option<PathBuf>,
+    // Event type patterns; empty shows everything.
+    pub events: Vec<String>,
+    // RFC 3339 timestamp or a relative age such as `90s`, `15m`, `2h` or `1d`.
+    pub since: Option<String>,
+}
+
+// One line of output. Live events carry their stream id; backfilled ones do not.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct TailEvent {
+    pub id: Option<u64>,
+    pub event_type: String,
+    pub timestamp: DateTime<Utc>,
+    pub data: Value,
+}
+
+// The node refused the credentials; retrying would not help.
+#[derive(Debug)]
+struct AuthRejected(String);
+
+impl fmt::Display for AuthRejected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for AuthRejected {}
+
+// Streams until Ctrl-C, rendering each event to stdout.
+pub(crate) async fn run(options: TailOptions, raw_json: bool) -> Result<()> {
+    let color = !raw_json && std::io::stdout().is_terminal();
+    let print = |event: TailEvent| {
+        let line = if raw_json {
+            render_json(&event)
+        } else {
+            render(&event, color)
+        };
+        let mut stdout = std::io::stdout().lock();
+        match writeln!(stdout, "{line}").and_then(|_| stdout.flush()) {
+            Ok(()) => ControlFlow::Continue(()),
+            // A closed pipe, e.g. `| head`, ends the tail.
+            Err(_) => ControlFlow::Break(()),
+        }
+    };
+    tokio::select! {
+        tailed = tail(&options, None, print) => tailed,
+        _ = tokio::signal::ctrl_c() => Ok(()),
+    }
+}
+
+// Backfills from `since`, then follows the node's event stream, reconnecting with
+// `Last-Event-ID` whenever it drops. Returns once `sink` breaks or the node rejects the
+// credentials.
+pub(crate) async fn tail<F>(
+    options: &TailOptions,
+    resume_from: Option<u64>,
+    mut sink: F,
+) -> Result<()>
+where
+    F: FnMut(TailEvent) -> ControlFlow<()>,
+{
+    let client = Client::new(options)?;
+    if let Some(since) = &options.since {
+        for event in client
+            .backfill(parse_since(since, Utc::now())?, &options.events)
+            .await?
+        {
+            if sink(event).is_break() {
+                return Ok(());
+            }
+        }
+    }
+
+    let mut last_id = resume_from;
+    let mut delay = RECONNECT_DELAY;
+    loop {
+        let error = match client
+            .stream(&mut last_id, &options.events, &mut sink)
+            .await
+        {
+            Ok(ControlFlow::Break(())) => return Ok(()),
+            Ok(ControlFlow::Continue(())) => "stream closed by the node".to_string(),
+            Err(err) if err.is::<AuthRejected>() => return Err(err),
+            Err(err) => format!("{err:#}"),
+        };
+        let notice = TailEvent {
+            id: None,
+            event_type: "tail.reconnecting".to_string(),
+            timestamp: Utc::now(),
+            data: json!({
+                "error": error,
+                "retry_in_ms": delay.as_millis() as u64,
+                "last_event_id": last_id,
+            }),
+        };
+        if sink(notice).is_break() {
+            return Ok(());
+        }
+        tokio::time::sleep(delay).await;
+        delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+    }
+}
+
+pub(crate) fn matches_filters(filters: &[String], event_type: &str) -> bool {
+    filters.is_empty()
+        || filters
+            .iter()
+            .any(|pattern| event_type_matches(pattern, event_type))
+}
+
+pub(crate) fn parse_since(since: &str, now: DateTime<Utc>) -> Result<DateTime<Utc>> {
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(since) {
+        return Ok(timestamp.with_timezone(&Utc));
+    }
+    let split = since
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(since.len());
+    let (amount, unit) = since.split_at(split);
+    let amount: i64 = amount
+        .parse()
+        .map_err(|_| anyhow!("--since {since:?} is neither RFC 3339 nor an age like 15m"))?;
+    let age = match unit {
+        "s" => chrono::Duration::seconds(amount),
+        "m" => chrono::Duration::minutes(amount),
+        "h" => chrono::Duration::hours(amount),
+        "d" => chrono::Duration::days(amount),
+        _ => bail!("--since {since:?} has an unknown unit; use s, m, h or d"),
+    };
+    Ok(now - age)
+}
+
+pub(crate) fn render(event: &TailEvent, color: bool) -> String {
+    let timestamp = event.timestamp.to_rfc3339_opts(SecondsFormat::Millis, true);
+    let summary = summarize(&event.data);
+    if !color {
+        return format!("{timestamp} {:<28} {summary}", event.event_type);
+    }
+    let tint = if event.event_type.ends_with("failed") || event.event_type == "log.error" {
+        "\x1b[31m"
+    } else if event.event_type.starts_with("tail.") || event.event_type.ends_with("overflowed") {
+        "\x1b[33m"
+    } else {
+        "\x1b[36m"
+    };
+    format!(
+        "\x1b[2m{timestamp}\x1b[0m {tint}{:<28}\x1b[0m {summary}",
+        event.event_type
+    )
+}
+
+pub(crate) fn render_json(event: &TailEvent) -> String {
+    json!({
+        "id": event.id,
+        "event": event.event_type,
+        "timestamp": event.timestamp,
+        "data": event.data,
+    })
+    .to_string()
+}
+
+// `key=value` pairs for an object, with nested values kept as compact JSON.
+fn summarize(data: &Value) -> String {
+    let summary = match data {
+        Value::Object(fields) => fields
+            .iter()
+            .map(|(key, value)| match value {
+                Value::String(text) => format!("{key}={text}"),
+                other => format!("{key}={other}"),
+            })
+            .collect::<Vec<_>>()
+            .join(" "),
+        Value::String(text) => text.clone(),
+        other => other.to_string(),
+    };
+    if summary.chars().count() <= SUMMARY_MAX_CHARS {
+        return summary;
+    }
+    let mut truncated: String = summary.chars().take(SUMMARY_MAX_CHARS - 1).collect();
+    truncated.push('…');
+    truncated
+}
+
+// Accumulates `text/event-stream` bytes and yields each complete event.
+#[derive(Debug, Default)]
+pub(crate) struct SseParser {
+    pending: Vec<u8>,
+    id: Option<String>,
+    event: Option<String>,
+    data: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SseFrame {
+    pub id: Option<String>,
+    pub event: String,
+    pub data: String,
+}
+
+impl SseParser {
+    pub(crate) fn feed(&mut self, bytes: &[u8]) -> Vec<SseFrame> {
+        self.pending.extend_from_slice(bytes);
+        let mut frames = Vec::new();
+        while let Some(end) = self.pending.iter().position(|byte| *byte == b'\n') {
+            let line: Vec<u8> = self.pending.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\n', '\r']);
+            if line.is_empty() {
+                if let Some(frame) = self.dispatch() {
+                    frames.push(frame);
+                }
+                continue;
+            }
+            if line.starts_with(':') {
+                continue;
+            }
+            let (field, value) = line.split_once(':').unwrap_or((line, ""));
+            let value = value.strip_prefix(' ').unwrap_or(value).to_string();
+            match field {
+                "id" => self.id = Some(value),
+                "event" => self.event = Some(value),
+                "data" => self.data.push(value),
+                _ => {}
+            }
+        }
+        frames
+    }
+
+    fn dispatch(&mut self) -> Option<SseFrame> {
+        let event = self.event.take();
+        let id = self.id.take();
+        if self.data.is_empty() && event.is_none() {
+            return None;
+        }
+        Some(SseFrame {
+            id,
+            event: event.unwrap_or_else(|| "message".to_string()),
+            data: std::mem::take(&mut self.data).join("\n"),
+        })
+    }
+}
+
+trait Io: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Io for T {}
+
+// One `/v1/logs` page.
+#[derive(Debug, Deserialize)]
+struct LogPage {
+    items: Vec<LogLine>,
+}
+
+// The fields of a cached event envelope that the tail shows.
+#[derive(Debug, Deserialize)]
+struct CachedEvent {
+    event: String,
+    sent_at: DateTime<Utc>,
+    #[serde(default)]
+    source_identity: Value,
+    #[serde(default)]
+    payload: Value,
+}
+
+struct Client {
+    host: String,
+    port: u16,
+    authority: String,
+    prefix: String,
+    tls: Option<TlsConnector>,
+    token: Option<String>,
+}
+
+impl Client {
+    fn new(options: &TailOptions) -> Result<Self> {
+        let url = options.base_url.trim_end_matches('/');
+        let uri: Uri = url
+            .parse()
+            .with_context(|| format!("--base-url {url} is not a valid URL"))?;
+        let https = match uri.scheme_str() {
+            Some("https") => true,
+            Some("http") => false,
+            _ => bail!("--base-url {url} must start with http:// or https://"),
+        };
+        let authority = uri
+            .authority()
+            .ok_or_else(|| anyhow!("--base-url {url} has no host"))?;
+        let host = authority.host();
+        let port = authority.port_u16().unwrap_or(if https { 443 } else { 80 });
+        let tls = match (https, &options.ca_cert) {
+            (false, _) => None,
+            (true, None) => bail!("https:// base URLs need --ca-cert with the node's CA"),
+            (true, Some(ca_cert)) => {
+                let mut roots = RootCertStore::empty();
+                for certificate in read_certificates(ca_cert)? {
+                    roots.add(certificate).context("invalid CA certificate")?;
+                }
+                let config =
+                    ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+                        .with_safe_default_protocol_versions()
+                        .context("configure TLS client")?
+                        .with_root_certificates(roots)
+                        .with_no_client_auth();
+                Some(TlsConnector::from(Arc::new(config)))
+            }
+        };
+        Ok(Self {
+            host: host
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .to_string(),
+            port,
+            authority: authority.as_str().to_string(),
+            prefix: uri.path().trim_end_matches('/').to_string(),
+            tls,
+            token: options.token.clone(),
+        })
+    }
+
+    async fn connect(&self) -> Result<Box<dyn Io>> {
+        let tcp = TcpStream::connect((self.host.as_str(), self.port))
+            .await
+            .with_context(|| format!("connect to {}:{}", self.host, self.port))?;
+        let Some(tls) = &self.tls else {
+            return Ok(Box::new(tcp));
+        };
+        let server_name = ServerName::try_from(self.host.clone())
+            .with_context(|| format!("{} is not a valid TLS server name", self.host))?;
+        let stream = tls
+            .connect(server_name, tcp)
+            .await
+            .with_context(|| format!("TLS handshake with {}", self.host))?;
+        Ok(Box::new(stream))
+    }
+
+    // Sends a GET over a fresh connection and returns the response once its status is a success.
+    async fn get(
+        &self,
+        path: &str,
+        accept: &str,
+        last_event_id: Option<u64>,
+    ) -> Result<Response<Incoming>> {
+        let io = TokioIo::new(self.connect().await?);
+        let (mut sender, connection) = http1::handshake(io)
+            .await
+            .with_context(|| format!("HTTP handshake with {}", self.authority))?;
+        tokio::spawn(connection);
+        let mut request = Request::get(format!("{}{path}", self.prefix))
+            .header(HOST, &self.authority)
+            .header(ACCEPT, accept);
+        if let Some(token) = &self.token {
+            request = request.header(AUTHORIZATION, format!("Bearer {token}"));
+        }
+        if let Some(last_event_id) = last_event_id {
+            request = request.header("last-event-id", last_event_id);
+        }
+        let response = sender
+            .send_request(request.body(Empty::<Bytes>::new())?)
+            .await
+            .with_context(|| format!("GET {path}"))?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+        let body = match response.into_body().collect().await {
+            Ok(body) => body.to_bytes(),
+            Err(_) => Bytes::new(),
+        };
+        let detail = serde_json::from_slice::<Value>(&body)
+            .ok()
+            .and_then(|body| {
+                body.get("error")
+                    .and_then(Value::as_str)
+                    .map(str::to_string)
+            })
+            .unwrap_or_else(|| String::from_utf8_lossy(&body).trim().to_string());
+        let status = status.as_u16();
+        if matches!(status, 401 | 403) {
+            let hint = if self.token.is_some() {
+                "check --token"
+            } else {
+                "pass --token"
+            };
+            let message = format!("{path} refused with {status} {detail}; {hint}");
+            return Err(AuthRejected(message).into());
+        }
+        bail!("{path} answered {status} {detail}")
+    }
+
+    async fn get_json<T: DeserializeOwned>(&self, path: &str) -> Result<T> {
+        let response = self.get(path, "application/json", None).await?;
+        let body = response
+            .into_body()
+            .collect()
+            .await
+            .with_context(|| format!("read {path} response"))?
+            .to_bytes();
+        serde_json::from_slice(&body).with_context(|| format!("decode {path} response"))
+    }
+
+    // Log lines (as `log.<level>`) and cached mesh events since `since`, oldest first.
+    async fn backfill(&self, since: DateTime<Utc>, filters: &[String]) -> Result<Vec<TailEvent>> {
+        let mut events: Vec<TailEvent> = self
+            .backfill_logs(since)
+            .await?
+            .into_iter()
+            .map(|line| TailEvent {
+                id: None,
+                event_type: format!("log.{}", line.level.to_ascii_lowercase()),
+                timestamp: line.timestamp.as_datetime(),
+                data: json!({ "message": line.message }),
+            })
+            .collect();
+        for cached in self.backfill_cached_events(since).await? {
+            events.push(TailEvent {
+                id: None,
+                event_type: cached.event,
+                timestamp: cached.sent_at,
+                data: json!({
+                    "source_identity": cached.source_identity,
+                    "payload": cached.payload,
+                }),
+            });
+        }
+        events.retain(|event| {
+            event.timestamp >= since && matches_filters(filters, &event.event_type)
+        });
+        events.sort_by_key(|event| event.timestamp);
+        Ok(events)
+    }
+
+    // `/v1/logs` answers with the newest lines of a window, so pages walk back from the present
+    // with `until` until one comes back short.
+    async fn backfill_logs(&self, since: DateTime<Utc>) -> Result<Vec<LogLine>> {
+        let mut lines = Vec::new();
+        let mut until: Option<String> = None;
+        loop {
+            let mut path = format!(
+                "/v1/logs?since={}&limit={BACKFILL_PAGE}",
+                query_value(&since.to_rfc3339())
+            );
+            if let Some(until) = &until {
+                path.push_str(&format!("&until={}", query_value(until)));
+            }
+            let page: LogPage = self.get_json(&path).await?;
+            let full = page.items.len() == BACKFILL_PAGE;
+            until = page.items.first().map(|line| line.timestamp.to_string());
+            lines.splice(0..0, page.items);
+            if !full || until.is_none() {
+                return Ok(lines);
+            }
+        }
+    }
+
+    // Pages forward through the cache, moving `since` up to the last envelope seen. Envelopes
+    // sent at that instant come back on the next page too and are skipped.
+    async fn backfill_cached_events(&self, since: DateTime<Utc>) -> Result<Vec<CachedEvent>> {
+        let mut cached = Vec::new();
+        let mut seen = HashSet::new();
+        let mut since = since;
+        loop {
+            let path = format!(
+                "/v1/cache/events?since={}&limit={BACKFILL_PAGE}",
+                query_value(&since.to_rfc3339())
+            );
+            let page: Vec<Value> = self.get_json(&path).await?;
+            let full = page.len() == BACKFILL_PAGE;
+            let mut fresh = 0;
+            for raw in page {
+                if !seen.insert(raw.to_string()) {
+                    continue;
+                }
+                let envelope: CachedEvent = serde_json::from_value(raw)
+                    .context("decode cached event from /v1/cache/events")?;
+                since = since.max(envelope.sent_at);
+                cached.push(envelope);
+                fresh += 1;
+            }
+            // A full page with nothing new means more than a page shares one `sent_at`.
+            if !full || fresh == 0 {
+                return Ok(cached);
+            }
+        }
+    }
+
+    // Follows the stream once. `last_id` advances past every event seen, filtered or not, so a
+    // reconnect resumes after it.
+    async fn stream<F>(
+        &self,
+        last_id: &mut Option<u64>,
+        filters: &[String],
+        sink: &mut F,
+    ) -> Result<ControlFlow<()>>
+    where
+        F: FnMut(TailEvent) -> ControlFlow<()>,
+    {
+        let mut body = self
+            .get(STREAM_PATH, "text/event-stream", *last_id)
+            .await?
+            .into_body();
+        let mut parser = SseParser::default();
+        while let Some(frame) = body.frame().await {
+            let Ok(bytes) = frame.context("read event stream")?.into_data() else {
+                continue;
+            };
+            for frame in parser.feed(&bytes) {
+                let id = frame.id.as_deref().and_then(|id| id.parse::<u64>().ok());
+                if id.is_some() {
+                    *last_id = id;
+                }
+                // Gap markers have no id and always pass the filters.
+                if id.is_some() && !matches_filters(filters, &frame.event) {
+                    continue;
+                }
+                let event = TailEvent {
+                    id,
+                    event_type: frame.event,
+                    timestamp: Utc::now(),
+                    data: serde_json::from_str(&frame.data).unwrap_or(Value::String(frame.data)),
+                };
+                if sink(event).is_break() {
+                    return Ok(ControlFlow::Break(()));
+                }
+            }
+        }
+        Ok(ControlFlow::Continue(()))
+    }
+}
+
+// Timestamps are the only query values the tail sends; `+` would otherwise decode as a space.
+fn query_value(value: &str) -> String {
+    value.replace('+', "%2B")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_since, render, tail, SseParser, TailEvent, TailOptions, BACKFILL_PAGE};
+    use chrono::{TimeZone, Utc};
+    use retasync_control_plane::test_support::test_state;
+    use retasync_control_plane::{build_router, AppState};
+    use serde_json::json;
+    use std::net::SocketAddr;
+    use std::ops::ControlFlow;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    async fn serve() -> (SocketAddr, AppState) {
+        let state = test_state().await;
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = build_router(state.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (addr, state)
+    }
+
+    async fn request(addr: SocketAddr, method: &str, path: &str, body: &str) -> String {
+        let request = format!(
+            "{method} {path} HTTP/1.1\r\nHost: localhost\r\n\
+             Content-Type: application/json\r\nContent-Length: {}\r\n\
+             Connection: close\r\n\r\n{body}",
+            body.len()
+        );
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    const ALPHA: &str = "a1fa0000000000000000000000000001";
+    const BRAVO: &str = "b4a0000000000000000000000000000b";
+    const CHARLIE: &str = "c4a0000000000000000000000000000c";
+    const DELTA: &str = "de1a0000000000000000000000000004";
+
+    async fn add_allowlist(addr: SocketAddr, identity_hash: &str) {
+        let body = json!({ "identity_hash": identity_hash }).to_string();
+        let response = request(addr, "POST", "/v1/security/allowlist", &body).await;
+        assert!(response.starts_with("HTTP/1.1 201"), "{response}");
+    }
+
+    // Uploads a file and waits for the completion log line it leaves behind.
+    async fn upload_and_settle(addr: SocketAddr) {
+        let body = json!({
+            "destination_identity": "bb00000000000000000000000000000b",
+            "file_name": "tile.png",
+            "media_type": "image/png",
+            "payload_base64": "dGlsZQ==",
+            "dedup": false,
+        })
+        .to_string();
+        let response = request(addr, "POST", "/v1/jobs/transfers/upload", &body).await;
+        assert!(response.starts_with("HTTP/1.1 202"), "{response}");
+        for _ in 0..200 {
+            if request(addr, "GET", "/v1/logs", "")
+                .await
+                .contains("completed")
+            {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        panic!("upload never completed");
+    }
+
+    fn options(addr: SocketAddr, events: &[&str]) -> TailOptions {
+        TailOptions {
+            base_url: format!("http://{addr}"),
+            events: events.iter().map(|pattern| pattern.to_string()).collect(),
+            ..TailOptions::default()
+        }
+    }
+
+    // Tails until `count` events have arrived, running `during` once the stream is connected.
+    async fn collect<F>(
+        options: &TailOptions,
+        resume_from: Option<u64>,
+        count: usize,
+        during: F,
+    ) -> Vec<TailEvent>
+    where
+        F: std::future::Future<Output = ()>,
+    {
+        let mut events = Vec::new();
+        let tailing = tail(options, resume_from, |event| {
+            events.push(event);
+            if events.len() == count {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        });
+        let during = async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            during.await;
+        };
+        let (tailed, _) = tokio::time::timeout(Duration::from_secs(5), async {
+            tokio::join!(tailing, during)
+        })
+        .await
+        .expect("tail timed out");
+        tailed.unwrap();
+        events
+    }
+
+    #[tokio::test]
+    async fn live_events_are_filtered_and_resume_after_the_last_id() {
+        let (addr, _) = serve().await;
+        let filtered = options(addr, &["security.allowlist.*"]);
+        let seen = collect(&filtered, None, 2, async {
+            add_allowlist(addr, ALPHA).await;
+            add_allowlist(addr, BRAVO).await;
+        })
+        .await;
+        assert_eq!(seen[0].event_type, "security.allowlist.updated");
+        assert_eq!(seen[0].data["identity_hash"], ALPHA);
+        assert_eq!(seen[1].data["identity_hash"], BRAVO);
+        let last_id = seen[1].id;
+
+        // Emitted while nobody is connected, then replayed from Last-Event-ID.
+        add_allowlist(addr, CHARLIE).await;
+        add_allowlist(addr, DELTA).await;
+        let resumed = collect(&filtered, last_id, 2, async {}).await;
+        let identities: Vec<_> = resumed
+            .iter()
+            .map(|event| event.data["identity_hash"].clone())
+            .collect();
+        assert_eq!(identities, [json!(CHARLIE), json!(DELTA)]);
+        assert!(resumed[0].id > last_id);
+
+        let excluded = options(addr, &["transfer.*"]);
+        let nothing = tokio::time::timeout(
+            Duration::from_millis(300),
+            collect(&excluded, last_id, 1, async {}),
+        )
+        .await;
+        assert!(
+            nothing.is_err(),
+            "allowlist events passed a transfer.* filter"
+        );
+    }
+
+    #[tokio::test]
+    async fn since_backfills_logs_and_cached_events_in_order_before_going_live() {
+        let (addr, state) = serve().await;
+        let base = Utc::now() - chrono::Duration::minutes(10);
+        for (event_id, minutes) in [("late", 6), ("early", 2), ("stale", -30)] {
+            state
+                .storage
+                .cache_event(
+                    event_id,
+                    "eam.updated",
+                    "aa00000000000000000000000000000a",
+                    base + chrono::Duration::minutes(minutes),
+                    &json!({
+                        "event": "eam.updated",
+                        "sent_at": base + chrono::Duration::minutes(minutes),
+                        "source_identity": "aa00000000000000000000000000000a",
+                        "payload": { "uid": event_id },
+                    }),
+                )
+                .await
+                .unwrap();
+        }
+        upload_and_settle(addr).await;
+
+        let mut backfill = options(addr, &[]);
+        backfill.since = Some("15m".to_string());
+        let seen = collect(&backfill, None, 4, async {
+            add_allowlist(addr, BRAVO).await;
+        })
+        .await;
+        let types: Vec<&str> = seen.iter().map(|event| event.event_type.as_str()).collect();
+        assert_eq!(
+            types,
+            [
+                "eam.updated",
+                "eam.updated",
+                "log.info",
+                "security.allowlist.updated"
+            ]
+        );
+        assert_eq!(seen[0].data["payload"]["uid"], "early");
+        assert_eq!(seen[1].data["payload"]["uid"], "late");
+        assert!(seen[2].data["message"]
+            .as_str()
+            .unwrap()
+            .ends_with("completed"));
+        assert!(seen
+            .windows(2)
+            .all(|pair| pair[0].timestamp <= pair[1].timestamp));
+        assert!(seen[..3].iter().all(|event| event.id.is_none()));
+        assert_eq!(seen[3].data["identity_hash"], BRAVO);
+    }
+
+    #[tokio::test]
+    async fn backfill_pages_through_more_cached_events_than_one_request_returns() {
+        let (addr, state) = serve().await;
+        let base = Utc::now() - chrono::Duration::minutes(30);
+        let total = BACKFILL_PAGE + 3;
+        for index in 0..total {
+            // The last few share an instant that straddles the page boundary.
+            let sent_at = base + chrono::Duration::seconds(index.min(BACKFILL_PAGE - 2) as i64);
+            let uid = format!("e-{index:04}");
+            state
+                .storage
+                .cache_event(
+                    &uid,
+                    "eam.updated",
+                    "aa00000000000000000000000000000a",
+                    sent_at,
+                    &json!({
+                        "event": "eam.updated",
+                        "sent_at": sent_at,
+                        "source_identity": "aa00000000000000000000000000000a",
+                        "payload": { "uid": uid },
+                    }),
+                )
+                .await
+                .unwrap();
+        }
+
+        let mut backfill = options(addr, &["eam.*"]);
+        backfill.since = Some("1h".to_string());
+        let seen = collect(&backfill, None, total, async {}).await;
+        let uids: Vec<String> = seen
+            .iter()
+            .map(|event| event.data["payload"]["uid"].as_str().unwrap().to_string())
+            .collect();
+        let expected: Vec<String> = (0..total).map(|index| format!("e-{index:04}")).collect();
+        assert_eq!(uids, expected);
+    }
+
+    #[test]
+    fn frames_split_across_reads_parse_once_complete() {
+        let mut parser = SseParser::default();
+        assert!(parser
+            .feed(b": keep-alive\n\nid: 7\nevent: job.sta")
+            .is_empty());
+        let frames = parser.feed(b"tus.changed\ndata: {\"status\":\"success\"}\n\n");
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].id.as_deref(), Some("7"));
+        assert_eq!(frames[0].event, "job.status.changed");
+        assert_eq!(frames[0].data, r#"{"status":"success"}"#);
+
+        let now = Utc.with_ymd_and_hms(2026, 5, 1, 12, 0, 0).unwrap();
+        assert_eq!(
+            parse_since("90m", now).unwrap(),
+            now - chrono::Duration::minutes(90)
+        );
+        assert_eq!(
+            parse_since("2026-05-01T11:00:00Z", now).unwrap(),
+            now - chrono::Duration::hours(1)
+        );
+        assert!(parse_since("yesterday", now).is_err());
+
+        let event = TailEvent {
+            id: Some(7),
+            event_type: "job.status.changed".to_string(),
+            timestamp: now,
+            data: json!({ "job_id": "j-1", "status": "success" }),
+        };
+        assert_eq!(
+            render(&event, false),
+            "2026-05-01T12:00:00.000Z job.status.changed           job_id=j-1 status=success"
+        );
+    }
+}
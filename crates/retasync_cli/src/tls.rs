@@ -0,0 +1,481 @@
+﻿use std::collections::BTreeMap;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use axum::extract::connect_info::{ConnectInfo, Connected};
+use axum::extract::Request;
+use axum::middleware::Next;
+use axum::response::Response;
+use axum::serve::{IncomingStream, Listener};
+use retasync_control_plane::ClientPrincipal;
+use rustls::crypto::ring;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig};
+use serde::Deserialize;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use tracing::{info, warn};
+use x509_parser::prelude::{FromDer, GeneralName, X509Certificate};
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct TlsSection {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+    pub client_ca_path: Option<PathBuf>,
+    #[serde(default)]
+    pub principals: BTreeMap<String, String>,
+}
+
+// The server config currently handed to new connections; `reload` swaps it in place so renewed
+// certificates apply without dropping established connections.
+#[derive(Clone)]
+pub(crate) struct TlsCertificates {
+    settings: TlsSection,
+    current: Arc<RwLock<Arc<ServerConfig>>>,
+}
+
+impl TlsCertificates {
+    pub fn load(settings: TlsSection) -> Result<Self> {
+        let config = server_config(&settings)?;
+        Ok(Self {
+            settings,
+            current: Arc::new(RwLock::new(config)),
+        })
+    }
+
+    pub fn reload(&self) -> Result<()> {
+        let config = server_config(&self.settings)?;
+        *self.current.write().unwrap_or_else(|p| p.into_inner()) = config;
+        Ok(())
+    }
+
+    fn acceptor(&self) -> TlsAcceptor {
+        TlsAcceptor::from(self.current.read().unwrap_or_else(|p| p.into_inner()).clone())
+    }
+}
+
+fn server_config(settings: &TlsSection) -> Result<Arc<ServerConfig>> {
+    let provider = Arc::new(ring::default_provider());
+    let certs = read_certificates(&settings.cert_path)?;
+    let key = PrivateKeyDer::from_pem_file(&settings.key_path)
+        .with_context(|| format!("failed to read TLS key {}", settings.key_path.display()))?;
+
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+    let builder = match &settings.client_ca_path {
+        Some(ca_path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in read_certificates(ca_path)? {
+                roots
+                    .add(cert)
+                    .with_context(|| format!("invalid client CA in {}", ca_path.display()))?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                .build()
+                .with_context(|| format!("invalid client CA in {}", ca_path.display()))?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let config = builder.with_single_cert(certs, key).map_err(|err| {
+        anyhow!(
+            "TLS certificate {} and key {} do not match: {err}",
+            settings.cert_path.display(),
+            settings.key_path.display()
+        )
+    })?;
+    Ok(Arc::new(config))
+}
+
+pub(crate) fn read_certificates(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("failed to read certificates from {}", path.display()))?;
+    if certs.is_empty() {
+        bail!("no certificates in {}", path.display());
+    }
+    Ok(certs)
+}
+
+#[cfg(unix)]
+pub(crate) fn spawn_reload_on_sighup(certificates: TlsCertificates) -> Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangups = signal(SignalKind::hangup()).context("failed to listen for SIGHUP")?;
+    tokio::spawn(async move {
+        while hangups.recv().await.is_some() {
+            match certificates.reload() {
+                Ok(()) => info!("reloaded TLS certificates"),
+                Err(err) => warn!(
+                    error = format!("{err:#}"),
+                    "TLS reload failed; keeping the previous certificates"
+                ),
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn spawn_reload_on_sighup(_certificates: TlsCertificates) -> Result<()> {
+    Ok(())
+}
+
+// Accepts TCP connections and completes each TLS handshake on its own task, so one slow or
+// failing client never holds up the others.
+pub(crate) struct TlsListener {
+    local_addr: SocketAddr,
+    connections: mpsc::Receiver<(TlsStream<TcpStream>, SocketAddr)>,
+}
+
+impl TlsListener {
+    pub fn start(tcp: TcpListener, certificates: TlsCertificates) -> io::Result<Self> {
+        let local_addr = tcp.local_addr()?;
+        let (sender, connections) = mpsc::channel(64);
+        tokio::spawn(async move {
+            while !sender.is_closed() {
+                let (stream, remote) = match tcp.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(err) => {
+                        warn!(error = %err, "failed to accept connection");
+                        tokio::time::sleep(Duration::from_millis(50)).await;
+                        continue;
+                    }
+                };
+                let acceptor = certificates.acceptor();
+                let sender = sender.clone();
+                tokio::spawn(async move {
+                    match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                        Ok(Ok(stream)) => {
+                            let _ = sender.send((stream, remote)).await;
+                        }
+                        Ok(Err(err)) => warn!(%remote, error = %err, "TLS handshake failed"),
+                        Err(_) => warn!(%remote, "TLS handshake timed out"),
+                    }
+                });
+            }
+        });
+        Ok(Self {
+            local_addr,
+            connections,
+        })
+    }
+}
+
+impl Listener for TlsListener {
+    type Io = TlsStream<TcpStream>;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        match self.connections.recv().await {
+            Some(connection) => connection,
+            None => std::future::pending().await,
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        Ok(self.local_addr)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct TlsPeer {
+    pub principal: ClientPrincipal,
+    pub addr: SocketAddr,
+}
+
+impl Connected<IncomingStream<'_, TlsListener>> for TlsPeer {
+    fn connect_info(stream: IncomingStream<'_, TlsListener>) -> Self {
+        let (_, connection) = stream.io().get_ref();
+        let names = connection
+            .peer_certificates()
+            .and_then(|chain| chain.first())
+            .map(certificate_names)
+            .unwrap_or_default();
+        Self {
+            principal: ClientPrincipal { names },
+            addr: *stream.remote_addr(),
+        }
+    }
+}
+
+pub(crate) async fn attach_principal(
+    ConnectInfo(peer): ConnectInfo<TlsPeer>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    request.extensions_mut().insert(ConnectInfo(peer.addr));
+    if !peer.principal.names.is_empty() {
+        request.extensions_mut().insert(peer.principal);
+    }
+    next.run(request).await
+}
+
+fn certificate_names(der: &CertificateDer<'_>) -> Vec<String> {
+    let Ok((_, cert)) = X509Certificate::from_der(der) else {
+        return Vec::new();
+    };
+    let mut names = vec![cert.subject().to_string()];
+    names.extend(
+        cert.subject()
+            .iter_common_name()
+            .filter_map(|common_name| common_name.as_str().ok())
+            .map(str::to_string),
+    );
+    if let Ok(Some(alternative)) = cert.subject_alternative_name() {
+        for name in &alternative.value.general_names {
+            match name {
+                GeneralName::DNSName(name)
+                | GeneralName::RFC822Name(name)
+                | GeneralName::URI(name) => names.push(name.to_string()),
+                _ => {}
+            }
+        }
+    }
+    names.dedup();
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{attach_principal, TlsCertificates, TlsListener, TlsPeer, TlsSection};
+    use rcgen::{
+        BasicConstraints, Certificate, CertificateParams, DnType, ExtendedKeyUsagePurpose, IsCa,
+        KeyPair,
+    };
+    use retasync_control_plane::test_support::{
+        node_config, scratch_dir, scratch_sqlite, test_storage,
+    };
+    use retasync_control_plane::{build_router, AppState};
+    use retasync_mesh_bridge::InMemoryRpcMeshBridge;
+    use rustls::crypto::ring;
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+    use rustls::{ClientConfig, RootCertStore};
+    use serde_json::json;
+    use std::collections::BTreeMap;
+    use std::io::ErrorKind;
+    use std::net::SocketAddr;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_rustls::TlsConnector;
+
+    struct Pki {
+        dir: PathBuf,
+        ca: Certificate,
+        ca_key: KeyPair,
+    }
+
+    impl Pki {
+        fn new() -> Self {
+            let dir = scratch_dir();
+            let ca_key = KeyPair::generate().unwrap();
+            let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            params.distinguished_name.push(DnType::CommonName, "retasync test ca");
+            let ca = params.self_signed(&ca_key).unwrap();
+            std::fs::write(dir.join("ca.pem"), ca.pem()).unwrap();
+            Self { dir, ca, ca_key }
+        }
+
+        // Issues a leaf certificate and writes `<name>.pem` / `<name>.key`.
+        fn issue(
+            &self,
+            name: &str,
+            common_name: &str,
+            sans: &[&str],
+            usage: ExtendedKeyUsagePurpose,
+        ) {
+            let key = KeyPair::generate().unwrap();
+            let sans = sans.iter().map(|san| san.to_string()).collect::<Vec<_>>();
+            let mut params = CertificateParams::new(sans).unwrap();
+            params.distinguished_name.push(DnType::CommonName, common_name);
+            params.extended_key_usages = vec![usage];
+            let cert = params.signed_by(&key, &self.ca, &self.ca_key).unwrap();
+            std::fs::write(self.dir.join(format!("{name}.pem")), cert.pem()).unwrap();
+            std::fs::write(self.dir.join(format!("{name}.key")), key.serialize_pem()).unwrap();
+        }
+
+        fn settings(&self, mutual: bool) -> TlsSection {
+            TlsSection {
+                cert_path: self.dir.join("server.pem"),
+                key_path: self.dir.join("server.key"),
+                client_ca_path: mutual.then(|| self.dir.join("ca.pem")),
+                principals: BTreeMap::from([
+                    ("ops-console".to_string(), "admin".to_string()),
+                    ("tablet-07.field.example".to_string(), "field".to_string()),
+                ]),
+            }
+        }
+
+        fn connector(&self, client: Option<&str>) -> TlsConnector {
+            let mut roots = RootCertStore::empty();
+            roots
+                .add(CertificateDer::from_pem_file(self.dir.join("ca.pem")).unwrap())
+                .unwrap();
+            let builder = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_root_certificates(roots);
+            let config = match client {
+                Some(name) => builder
+                    .with_client_auth_cert(
+                        vec![CertificateDer::from_pem_file(self.dir.join(format!("{name}.pem")))
+                            .unwrap()],
+                        PrivateKeyDer::from_pem_file(self.dir.join(format!("{name}.key")))
+                            .unwrap(),
+                    )
+                    .unwrap(),
+                None => builder.with_no_client_auth(),
+            };
+            TlsConnector::from(Arc::new(config))
+        }
+    }
+
+    async fn serve(tls: TlsSection) -> (SocketAddr, TlsCertificates) {
+        let sqlite_path = scratch_sqlite();
+        let storage = test_storage(&sqlite_path).await;
+        let mut config = node_config(json!({
+            "http_bind": "0.0.0.0:8443",
+            "sqlite_path": sqlite_path
+        }));
+        config.tls_principals = tls.principals.clone();
+        let bridge = Arc::new(InMemoryRpcMeshBridge::new(true, true));
+        let state = AppState::new(storage, bridge, config, String::new(), true);
+
+        let certificates = TlsCertificates::load(tls).unwrap();
+        let tcp = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let listener = TlsListener::start(tcp, certificates.clone()).unwrap();
+        let addr = listener.local_addr;
+        let app = build_router(state).layer(axum::middleware::from_fn(attach_principal));
+        tokio::spawn(async move {
+            axum::serve(listener, app.into_make_service_with_connect_info::<TlsPeer>()).await
+        });
+        (addr, certificates)
+    }
+
+    async fn request(
+        addr: SocketAddr,
+        connector: &TlsConnector,
+        request: String,
+    ) -> std::io::Result<String> {
+        let tcp = TcpStream::connect(addr).await?;
+        let server_name = ServerName::try_from("localhost").unwrap();
+        let mut stream = connector.connect(server_name, tcp).await?;
+        stream.write_all(request.as_bytes()).await?;
+        let mut response = Vec::new();
+        // The server may close without a TLS close_notify once the response is written.
+        match stream.read_to_end(&mut response).await {
+            Ok(_) => {}
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof && !response.is_empty() => {}
+            Err(err) => return Err(err),
+        }
+        Ok(String::from_utf8_lossy(&response).into_owned())
+    }
+
+    const ALPHA: &str = "a1fa0000000000000000000000000001";
+    const BRAVO: &str = "b4a0000000000000000000000000000b";
+    const CHARLIE: &str = "c4a0000000000000000000000000000c";
+
+    fn add_allowlist(identity_hash: &str) -> String {
+        let body = json!({ "identity_hash": identity_hash }).to_string();
+        format!(
+            "POST /v1/security/allowlist HTTP/1.1\r\nHost: localhost\r\n\
+             Content-Type: application/json\r\nContent-Length: {}\r\n\
+             Connection: close\r\n\r\n{body}",
+            body.len()
+        )
+    }
+
+    fn server_pki() -> Pki {
+        let pki = Pki::new();
+        pki.issue("server", "localhost", &["localhost"], ExtendedKeyUsagePurpose::ServerAuth);
+        pki
+    }
+
+    #[tokio::test]
+    async fn client_certificates_map_to_roles() {
+        let pki = server_pki();
+        pki.issue("admin", "ops-console", &[], ExtendedKeyUsagePurpose::ClientAuth);
+        pki.issue(
+            "tablet",
+            "tablet-07",
+            &["tablet-07.field.example"],
+            ExtendedKeyUsagePurpose::ClientAuth,
+        );
+        pki.issue("stranger", "stranger", &[], ExtendedKeyUsagePurpose::ClientAuth);
+        let (addr, _) = serve(pki.settings(true)).await;
+
+        let live = "GET /health/live HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+        let response = request(addr, &pki.connector(Some("admin")), live.to_string())
+            .await
+            .unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+
+        let admin = request(addr, &pki.connector(Some("admin")), add_allowlist(ALPHA))
+            .await
+            .unwrap();
+        assert!(admin.starts_with("HTTP/1.1 201"), "{admin}");
+        assert!(admin.contains(r#""status":"active""#), "{admin}");
+
+        let field = request(addr, &pki.connector(Some("tablet")), add_allowlist(BRAVO))
+            .await
+            .unwrap();
+        assert!(field.starts_with("HTTP/1.1 201"), "{field}");
+        assert!(field.contains(r#""status":"pending""#), "{field}");
+
+        let unmapped = request(addr, &pki.connector(Some("stranger")), add_allowlist(CHARLIE))
+            .await
+            .unwrap();
+        assert!(unmapped.starts_with("HTTP/1.1 401"), "{unmapped}");
+    }
+
+    #[tokio::test]
+    async fn missing_client_certificate_is_rejected() {
+        let pki = server_pki();
+        let (addr, _) = serve(pki.settings(true)).await;
+
+        let live = "GET /health/live HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+        let response = request(addr, &pki.connector(None), live.to_string()).await;
+        assert!(
+            response.as_ref().map_or(true, |body| !body.starts_with("HTTP/1.1")),
+            "{response:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn mismatched_key_fails_and_reload_picks_up_renewed_certificates() {
+        let pki = server_pki();
+        let mut settings = pki.settings(false);
+        settings.key_path = pki.dir.join("other.key");
+        std::fs::write(&settings.key_path, KeyPair::generate().unwrap().serialize_pem()).unwrap();
+        let err = TlsCertificates::load(settings).err().expect("mismatch rejected");
+        assert!(err.to_string().contains("do not match"), "{err}");
+
+        let (addr, certificates) = serve(pki.settings(false)).await;
+        let before = std::fs::read_to_string(pki.dir.join("server.pem")).unwrap();
+        pki.issue("server", "localhost", &["localhost"], ExtendedKeyUsagePurpose::ServerAuth);
+        certificates.reload().unwrap();
+
+        let tcp = TcpStream::connect(addr).await.unwrap();
+        let server_name = ServerName::try_from("localhost").unwrap();
+        let stream = pki.connector(None).connect(server_name, tcp).await.unwrap();
+        let served = stream.get_ref().1.peer_certificates().unwrap()[0].clone();
+        let renewed = CertificateDer::from_pem_file(pki.dir.join("server.pem")).unwrap();
+        assert_eq!(served, renewed);
+        assert_ne!(before, std::fs::read_to_string(pki.dir.join("server.pem")).unwrap());
+    }
+}
@@ -1,12 +1,21 @@
-﻿use std::path::Path;
+﻿use std::collections::BTreeMap;
+use std::path::Path;
 
 use anyhow::{anyhow, Context, Result};
 use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::schemas::{declares, load_schemas, render_schemas};
+
+// The contract schema handlers receive a command's metadata block as, when it declares one.
+pub const ENVELOPE_META_SCHEMA: &str = "EnvelopeMeta";
 
 #[derive(Debug, Clone)]
 pub struct CodegenSpec {
     pub commands: Vec<String>,
     pub events: Vec<String>,
+    // Commands the contract marks `idempotent: false`, which the client never sends twice.
+    pub non_idempotent: Vec<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -27,6 +36,15 @@ struct OperationsExtension {
     commands: Vec<String>,
     #[serde(default)]
     events: Vec<String>,
+    #[serde(default, rename = "x-retasync-delivery")]
+    delivery: BTreeMap<String, DeliveryFlags>,
+}
+
+// The part of a delivery policy the client needs; the registry validates the rest.
+#[derive(Debug, Default, Deserialize)]
+struct DeliveryFlags {
+    #[serde(default)]
+    idempotent: Option<bool>,
 }
 
 pub fn generate_contracts(input_path: &Path, output_path: &Path) -> Result<()> {
@@ -49,11 +67,28 @@ pub fn generate_contracts(input_path: &Path, output_path: &Path) -> Result<()> {
 
 pub fn render_contracts_module(asyncapi_yaml: &str) -> Result<String> {
     let spec = load_spec(asyncapi_yaml)?;
-    Ok(render_spec(&spec))
+    let doc: serde_yaml::Value =
+        serde_yaml::from_str(asyncapi_yaml.trim_start_matches('\u{feff}'))
+            .context("failed parsing AsyncAPI YAML")?;
+    let schemas = load_schemas(&doc)?;
+    let mut rendered = render_spec(
+        &spec,
+        &contract_digest(asyncapi_yaml),
+        declares(&schemas, ENVELOPE_META_SCHEMA),
+    );
+    rendered.push_str(&render_schemas(&schemas)?);
+    Ok(rendered)
 }
 
-fn load_spec(source: &str) -> Result<CodegenSpec> {
-    let doc: AsyncApiDoc = serde_yaml::from_str(source).context("failed parsing AsyncAPI YAML")?;
+// SHA-256 of the contract text as stored, BOM included, with CRLF line endings read as LF so a
+// Windows checkout hashes the same as `sha256sum` on a Unix one.
+pub fn contract_digest(asyncapi_yaml: &str) -> String {
+    format!("{:x}", Sha256::digest(asyncapi_yaml.replace("\r\n", "\n")))
+}
+
+pub(crate) fn load_spec(source: &str) -> Result<CodegenSpec> {
+    let doc: AsyncApiDoc = serde_yaml::from_str(source.trim_start_matches('\u{feff}'))
+        .context("failed parsing AsyncAPI YAML")?;
 
     if doc.retasync.operations.commands.is_empty() {
         return Err(anyhow!(
@@ -61,15 +96,29 @@ fn load_spec(source: &str) -> Result<CodegenSpec> {
         ));
     }
 
+    let operations = doc.retasync.operations;
+    let non_idempotent = operations
+        .delivery
+        .iter()
+        .filter(|(_, flags)| flags.idempotent == Some(false))
+        .map(|(command, _)| command.clone())
+        .collect();
     Ok(CodegenSpec {
-        commands: doc.retasync.operations.commands,
-        events: doc.retasync.operations.events,
+        commands: operations.commands,
+        events: operations.events,
+        non_idempotent,
     })
 }
 
-fn render_spec(spec: &CodegenSpec) -> String {
+// The async traits and client are only compiled with the contract crate's `client` feature, so
+// a build without it keeps the payload types but pulls in no async dependencies.
+const CLIENT_GATE: &str = "#[cfg(feature = \"client\")]\n";
+
+fn render_spec(spec: &CodegenSpec, contract_digest: &str, with_meta: bool) -> String {
     let mut out = String::new();
-    out.push_str("// Generated by cargo xtask codegen. Do not edit manually.\n\n");
+    out.push_str("// Generated by cargo xtask codegen. Do not edit manually.\n");
+    out.push_str(&format!("// Source contract sha256: {contract_digest}\n\n"));
+    out.push_str(CLIENT_GATE);
     out.push_str("use async_trait::async_trait;\n");
     out.push_str("use serde::{Deserialize, Serialize};\n\n");
 
@@ -96,6 +145,7 @@ fn render_spec(spec: &CodegenSpec) -> String {
     }
     out.push_str("}\n\n");
 
+    out.push_str(CLIENT_GATE);
     out.push_str("#[async_trait]\n");
     out.push_str("pub trait CommandDispatch {\n");
     for command in &spec.commands {
@@ -105,6 +155,11 @@ fn render_spec(spec: &CodegenSpec) -> String {
         out.push_str(&fn_name);
         out.push_str("(&self, payload: ");
         out.push_str(&rust_ty);
+        if with_meta {
+            out.push_str(", meta: Option<schemas::");
+            out.push_str(ENVELOPE_META_SCHEMA);
+            out.push('>');
+        }
         out.push_str(") -> anyhow::Result<serde_json::Value>;\n");
     }
     out.push_str("}\n\n");
@@ -119,21 +174,36 @@ fn render_spec(spec: &CodegenSpec) -> String {
         out.push_str("}\n\n");
     }
 
+    out.push_str(CLIENT_GATE);
     out.push_str("#[async_trait]\n");
     out.push_str("pub trait MeshClientBackend {\n");
     out.push_str("    async fn send_command(&self, operation: &str, payload: serde_json::Value) -> anyhow::Result<serde_json::Value>;\n");
+    out.push_str("    // Waits out retry advice before `MeshClient` sends a command again.\n");
+    out.push_str("    async fn wait(&self, delay: std::time::Duration);\n");
     out.push_str("}\n\n");
 
+    out.push_str(CLIENT_GATE);
     out.push_str("pub struct MeshClient<B> {\n");
     out.push_str("    backend: B,\n");
+    out.push_str("    auto_retry: Option<crate::AutoRetry>,\n");
     out.push_str("}\n\n");
 
+    out.push_str(CLIENT_GATE);
     out.push_str("impl<B> MeshClient<B> {\n");
     out.push_str("    pub fn new(backend: B) -> Self {\n");
-    out.push_str("        Self { backend }\n");
+    out.push_str("        Self { backend, auto_retry: None }\n");
+    out.push_str("    }\n\n");
+    out.push_str("    // Sends idempotent commands again when refused with retry advice.\n");
+    out.push_str("    pub fn with_auto_retry(mut self, auto_retry: crate::AutoRetry) -> Self {\n");
+    out.push_str("        self.auto_retry = Some(auto_retry);\n");
+    out.push_str("        self\n");
+    out.push_str("    }\n\n");
+    out.push_str("    pub fn backend(&self) -> &B {\n");
+    out.push_str("        &self.backend\n");
     out.push_str("    }\n");
     out.push_str("}\n\n");
 
+    out.push_str(CLIENT_GATE);
     out.push_str("impl<B> MeshClient<B>\n");
     out.push_str("where\n");
     out.push_str("    B: MeshClientBackend + Send + Sync,\n");
@@ -146,17 +216,36 @@ fn render_spec(spec: &CodegenSpec) -> String {
         out.push_str("(&self, payload: ");
         out.push_str(&rust_ty);
         out.push_str(") -> anyhow::Result<serde_json::Value> {\n");
-        out.push_str("        self.backend.send_command(\"");
+        out.push_str("        self.send(\"");
         out.push_str(command);
-        out.push_str("\", payload.body).await\n");
+        out.push_str("\", ");
+        out.push_str(if spec.non_idempotent.contains(command) { "false" } else { "true" });
+        out.push_str(", payload.body).await\n");
         out.push_str("    }\n\n");
     }
+    out.push_str("    async fn send(&self, operation: &str, idempotent: bool, payload: serde_json::Value) -> anyhow::Result<serde_json::Value> {\n");
+    out.push_str("        let mut attempt = 1;\n");
+    out.push_str("        loop {\n");
+    out.push_str("            let sent = self.backend.send_command(operation, payload.clone());\n");
+    out.push_str("            let err = match sent.await {\n");
+    out.push_str("                Ok(result) => return Ok(result),\n");
+    out.push_str("                Err(err) => err,\n");
+    out.push_str("            };\n");
+    out.push_str("            let retry = self.auto_retry.filter(|_| idempotent);\n");
+    out.push_str("            let delay = retry.and_then(|retry| retry.delay(&err, attempt));\n");
+    out.push_str("            let Some(delay) = delay else {\n");
+    out.push_str("                return Err(err);\n");
+    out.push_str("            };\n");
+    out.push_str("            self.backend.wait(delay).await;\n");
+    out.push_str("            attempt += 1;\n");
+    out.push_str("        }\n");
+    out.push_str("    }\n");
     out.push_str("}\n");
 
     out
 }
 
-fn to_pascal_case(name: &str) -> String {
+pub(crate) fn to_pascal_case(name: &str) -> String {
     name
         .split(['.', '_', '-', '/'])
         .filter(|segment| !segment.is_empty())
@@ -187,7 +276,7 @@ fn to_snake_case(name: &str) -> String {
 
 #[cfg(test)]
 mod tests {
-    use super::render_contracts_module;
+    use super::{contract_digest, render_contracts_module};
 
     #[test]
     fn renders_commands_and_events() {
@@ -204,6 +293,139 @@ x-retasync:
         let rendered = render_contracts_module(source).expect("rendered");
         assert!(rendered.contains("EmergencyActionMessageCreate"));
         assert!(rendered.contains("EmergencyActionMessageCreated"));
-        assert!(rendered.contains("trait CommandDispatch"));
+        let gated = "#[cfg(feature = \"client\")]\n#[async_trait]\npub trait CommandDispatch";
+        assert!(rendered.contains(gated));
+        assert!(rendered.contains("payload: EmergencyActionMessageCreatePayload) ->"));
+    }
+
+    #[test]
+    fn commands_marked_non_idempotent_are_never_retried_by_the_client() {
+        let source = r#"
+x-retasync:
+  operations:
+    commands: [event.create, event.stream]
+    x-retasync-delivery:
+      event.stream: { idempotent: false, max_attempts: 1 }
+"#;
+
+        let rendered = render_contracts_module(source).expect("rendered");
+        assert!(rendered.contains("self.send(\"event.create\", true, payload.body).await"));
+        assert!(rendered.contains("self.send(\"event.stream\", false, payload.body).await"));
+    }
+
+    #[test]
+    fn header_names_the_digest_of_the_source_contract() {
+        assert_eq!(
+            contract_digest("abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        let source = "\u{feff}asyncapi: \"3.0.0\"
+x-retasync:
+  operations:
+    commands:
+      - event.create
+";
+        assert_eq!(
+            contract_digest(source),
+            contract_digest(&source.replace('\n', "\r\n"))
+        );
+
+        let rendered = render_contracts_module(source).expect("rendered");
+        let header = format!("// Source contract sha256: {}", contract_digest(source));
+        assert_eq!(rendered.lines().nth(1), Some(header.as_str()));
+    }
+
+    #[test]
+    fn handlers_receive_the_metadata_block_apart_from_the_payload() {
+        let source = r#"
+x-retasync:
+  operations:
+    commands: [beacon.put]
+components:
+  schemas:
+    EnvelopeMeta:
+      type: object
+      properties:
+        priority:
+          type: string
+        custom:
+          type: object
+          additionalProperties:
+            type: string
+      example:
+        priority: flash
+        custom:
+          shift: night
+"#;
+
+        let rendered = render_contracts_module(source).expect("rendered");
+        assert!(rendered.contains(
+            "async fn beacon_put(&self, payload: BeaconPutPayload, \
+             meta: Option<schemas::EnvelopeMeta>) -> anyhow::Result<serde_json::Value>;"
+        ));
+        assert!(rendered.contains(
+            "pub custom: Option<std::collections::BTreeMap<String, String>>,"
+        ));
+        assert!(rendered.contains(
+            "custom: Some([(\"shift\".to_string(), \"night\".to_string())]\
+             .into_iter().collect()),"
+        ));
+    }
+
+    #[test]
+    fn schema_defaults_and_examples_match_golden_output() {
+        let source = include_str!("../tests/fixtures/defaults.asyncapi.yaml");
+        let golden = include_str!("../tests/fixtures/defaults.rs.golden");
+
+        let rendered = render_contracts_module(source).expect("rendered");
+        assert_eq!(rendered, golden, "regenerate tests/fixtures/defaults.rs.golden");
+    }
+
+    #[test]
+    fn mistyped_defaults_and_examples_fail_codegen() {
+        let schema = |property: &str| {
+            format!(
+                r#"
+x-retasync:
+  operations:
+    commands: [beacon.put]
+components:
+  schemas:
+    Beacon:
+      type: object
+      properties:
+{property}
+"#
+            )
+        };
+
+        let cases = [
+            (
+                "        channel:
+          type: integer
+          default: green",
+                "schema Beacon property channel: default \"green\" is not an integer",
+            ),
+            (
+                "        mode:
+          type: string
+          enum: [burst]
+          example: loud",
+                "schema Beacon property mode: example \"loud\" is not one of [\"burst\"]",
+            ),
+            (
+                "        tags:
+          type: array
+          items:
+            type: boolean
+          default: [yes]",
+                "schema Beacon property tags: default [\"yes\"] is not an array whose items \
+                 are each a boolean",
+            ),
+        ];
+        for (property, expected) in cases {
+            let err = render_contracts_module(&schema(property)).expect_err("codegen fails");
+            assert_eq!(format!("{err:#}"), expected);
+        }
     }
 }
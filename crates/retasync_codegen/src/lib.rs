@@ -1,3 +1,6 @@
 ﻿mod generator;
+mod samples;
+mod schemas;
 
-pub use generator::{generate_contracts, render_contracts_module, CodegenSpec};
+pub use generator::{contract_digest, generate_contracts, render_contracts_module, CodegenSpec};
+pub use samples::{sample_envelopes, schema_violations, SampleEnvelope, SampleKind};
@@ -0,0 +1,517 @@
+﻿use anyhow::{anyhow, bail, Context, Result};
+use serde_json::{json, Map, Value as Json};
+use serde_yaml::{Mapping, Value};
+
+use crate::generator::load_spec;
+use crate::schemas::{first_example, SCHEMA_REF_PREFIX};
+
+// Fixed stand-ins for formats a schema gives no example for, so every run produces the same
+// bytes.
+const SAMPLE_UUID: &str = "01900000-0000-7000-8000-000000000000";
+const SAMPLE_DATE_TIME: &str = "2026-01-01T00:00:00Z";
+const SAMPLE_BYTES: &str = "c2FtcGxl";
+const MAX_SAMPLE_DEPTH: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleKind {
+    Command,
+    Event,
+}
+
+impl SampleKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SampleKind::Command => "command",
+            SampleKind::Event => "event",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SampleEnvelope {
+    pub kind: SampleKind,
+    pub operation: String,
+    // The `oneOf` member used for the payload; a free-form object when none is named after the
+    // operation.
+    pub payload_schema: Option<String>,
+    pub envelope: Json,
+}
+
+// One fully populated envelope per command and event, in contract order. Values come from the
+// same places as the generated `example()` functions (schema and property examples, then
+// defaults), and optional properties are filled in too.
+pub fn sample_envelopes(asyncapi_yaml: &str) -> Result<Vec<SampleEnvelope>> {
+    let spec = load_spec(asyncapi_yaml)?;
+    let doc = parse_contract(asyncapi_yaml)?;
+    let schemas = component_schemas(&doc)?;
+
+    let mut samples = Vec::new();
+    for operation in &spec.commands {
+        samples.push(sample_envelope(
+            schemas,
+            SampleKind::Command,
+            "MeshCommandEnvelope",
+            "operation",
+            operation,
+        )?);
+    }
+    for event in &spec.events {
+        samples.push(sample_envelope(
+            schemas,
+            SampleKind::Event,
+            "MeshEventEnvelope",
+            "event",
+            event,
+        )?);
+    }
+    Ok(samples)
+}
+
+// Where `value` breaks the named component schema, one `<pointer>: <problem>` line each; empty
+// when it conforms. Covers what the contract uses: `$ref`, `type`, `required`, `properties`,
+// `enum`, `const`, `oneOf`, `minimum` and the uuid, date-time and byte formats.
+pub fn schema_violations(asyncapi_yaml: &str, schema: &str, value: &Json) -> Result<Vec<String>> {
+    let doc = parse_contract(asyncapi_yaml)?;
+    let schemas = component_schemas(&doc)?;
+    let definition = schemas
+        .get(schema)
+        .ok_or_else(|| anyhow!("contract has no {schema} schema"))?;
+    let mut violations = Vec::new();
+    check(definition, value, schemas, "", &mut violations, 0)?;
+    Ok(violations)
+}
+
+fn parse_contract(asyncapi_yaml: &str) -> Result<Value> {
+    serde_yaml::from_str(asyncapi_yaml.trim_start_matches('\u{feff}'))
+        .context("failed parsing AsyncAPI YAML")
+}
+
+fn component_schemas(doc: &Value) -> Result<&Mapping> {
+    doc.get("components")
+        .and_then(|components| components.get("schemas"))
+        .and_then(Value::as_mapping)
+        .ok_or_else(|| anyhow!("contract has no components.schemas"))
+}
+
+fn sample_envelope(
+    schemas: &Mapping,
+    kind: SampleKind,
+    envelope_schema: &str,
+    name_property: &str,
+    operation: &str,
+) -> Result<SampleEnvelope> {
+    let schema = schemas
+        .get(envelope_schema)
+        .ok_or_else(|| anyhow!("contract has no {envelope_schema} schema"))?;
+    let mut envelope = sample(envelope_schema, schema, schemas, 0)?;
+    let payload_schema = payload_schema(schema, operation);
+    let payload = match &payload_schema {
+        Some(name) => sample(name, &schemas[name.as_str()], schemas, 0)?,
+        None => json!({}),
+    };
+    let Some(object) = envelope.as_object_mut() else {
+        bail!("schema {envelope_schema} is not an object");
+    };
+    object.insert(name_property.to_string(), json!(operation));
+    object.insert("payload".to_string(), payload);
+    Ok(SampleEnvelope {
+        kind,
+        operation: operation.to_string(),
+        payload_schema,
+        envelope,
+    })
+}
+
+// The payload `oneOf` member named after the operation's namespace, preferring one named after
+// the whole operation: `transfer.upload` takes `TransferUploadRequest` over `TransferCompletion`.
+fn payload_schema(envelope: &Value, operation: &str) -> Option<String> {
+    let members: Vec<&str> = envelope
+        .get("properties")
+        .and_then(|properties| properties.get("payload"))
+        .and_then(|payload| payload.get("oneOf"))
+        .and_then(Value::as_sequence)?
+        .iter()
+        .filter_map(|member| member.get("$ref").and_then(Value::as_str))
+        .filter_map(|reference| reference.strip_prefix(SCHEMA_REF_PREFIX))
+        .collect();
+    let namespace = operation.split('.').next().unwrap_or(operation);
+    let full = operation.replace('.', "_");
+    let in_namespace: Vec<&str> = members
+        .into_iter()
+        .filter(|name| {
+            let snake = to_snake(name);
+            snake == namespace || snake.starts_with(&format!("{namespace}_"))
+        })
+        .collect();
+    in_namespace
+        .iter()
+        .find(|name| to_snake(name).starts_with(&full))
+        .or(in_namespace.first())
+        .map(|name| name.to_string())
+}
+
+fn sample(name: &str, definition: &Value, schemas: &Mapping, depth: usize) -> Result<Json> {
+    if depth > MAX_SAMPLE_DEPTH {
+        bail!("schema {name} nests deeper than {MAX_SAMPLE_DEPTH} levels");
+    }
+    if let Some(reference) = definition.get("$ref").and_then(Value::as_str) {
+        let target = reference
+            .strip_prefix(SCHEMA_REF_PREFIX)
+            .ok_or_else(|| anyhow!("unsupported $ref {reference}"))?;
+        let schema = schemas
+            .get(target)
+            .ok_or_else(|| anyhow!("unresolved $ref {reference}"))?;
+        return sample(target, schema, schemas, depth + 1);
+    }
+
+    if let Some(properties) = definition.get("properties").and_then(Value::as_mapping) {
+        let example = first_example(definition);
+        let mut object = Map::new();
+        for (property, field) in properties {
+            let property = property
+                .as_str()
+                .ok_or_else(|| anyhow!("schema {name} has a non-string property name"))?;
+            let value = match example.as_ref().and_then(|example| example.get(property)) {
+                Some(value) => to_json(value)?,
+                None => sample(property, field, schemas, depth + 1)?,
+            };
+            object.insert(property.to_string(), value);
+        }
+        return Ok(Json::Object(object));
+    }
+
+    let given = first_example(definition)
+        .or_else(|| definition.get("default").cloned())
+        .or_else(|| definition.get("const").cloned())
+        .or_else(|| {
+            definition
+                .get("enum")
+                .and_then(Value::as_sequence)
+                .and_then(|members| members.first().cloned())
+        });
+    if let Some(value) = given {
+        return to_json(&value);
+    }
+    if let Some(first) = definition
+        .get("oneOf")
+        .and_then(Value::as_sequence)
+        .and_then(|members| members.first())
+    {
+        return sample(name, first, schemas, depth + 1);
+    }
+
+    Ok(match definition.get("type").and_then(Value::as_str) {
+        Some("string") => match definition.get("format").and_then(Value::as_str) {
+            Some("uuid") => json!(SAMPLE_UUID),
+            Some("date-time") => json!(SAMPLE_DATE_TIME),
+            Some("byte") => json!(SAMPLE_BYTES),
+            _ => json!(name),
+        },
+        Some("integer") => json!(definition
+            .get("minimum")
+            .and_then(Value::as_i64)
+            .unwrap_or(0)),
+        Some("number") => json!(0.0),
+        Some("boolean") => json!(false),
+        Some("array") => {
+            let items = definition
+                .get("items")
+                .ok_or_else(|| anyhow!("array {name} has no items schema"))?;
+            json!([sample(name, items, schemas, depth + 1)?])
+        }
+        _ => json!({}),
+    })
+}
+
+fn check(
+    definition: &Value,
+    value: &Json,
+    schemas: &Mapping,
+    pointer: &str,
+    violations: &mut Vec<String>,
+    depth: usize,
+) -> Result<()> {
+    if depth > MAX_SAMPLE_DEPTH {
+        bail!("schema at {pointer} nests deeper than {MAX_SAMPLE_DEPTH} levels");
+    }
+    let mut violation = |problem: String| {
+        let at = if pointer.is_empty() { "/" } else { pointer };
+        violations.push(format!("{at}: {problem}"));
+    };
+    if let Some(reference) = definition.get("$ref").and_then(Value::as_str) {
+        let schema = reference
+            .strip_prefix(SCHEMA_REF_PREFIX)
+            .and_then(|target| schemas.get(target))
+            .ok_or_else(|| anyhow!("unresolved $ref {reference}"))?;
+        return check(schema, value, schemas, pointer, violations, depth + 1);
+    }
+    if let Some(members) = definition.get("oneOf").and_then(Value::as_sequence) {
+        let mut matched = false;
+        for member in members {
+            let mut member_violations = Vec::new();
+            check(member, value, schemas, pointer, &mut member_violations, depth + 1)?;
+            matched |= member_violations.is_empty();
+        }
+        if !matched {
+            violation("matches no oneOf member".to_string());
+        }
+        return Ok(());
+    }
+    if let Some(expected) = definition.get("const") {
+        if &to_json(expected)? != value {
+            violation(format!("expected {}", to_json(expected)?));
+        }
+    }
+    if let Some(members) = definition.get("enum").and_then(Value::as_sequence) {
+        let members = members.iter().map(to_json).collect::<Result<Vec<_>>>()?;
+        if !members.contains(value) {
+            violation(format!("{value} is not one of {}", Json::Array(members)));
+        }
+    }
+
+    match definition.get("type").and_then(Value::as_str) {
+        Some("object") => {
+            let Some(object) = value.as_object() else {
+                violation("expected an object".to_string());
+                return Ok(());
+            };
+            for required in definition
+                .get("required")
+                .and_then(Value::as_sequence)
+                .into_iter()
+                .flatten()
+                .filter_map(Value::as_str)
+            {
+                if !object.contains_key(required) {
+                    violation(format!("missing required property `{required}`"));
+                }
+            }
+            if let Some(properties) = definition.get("properties").and_then(Value::as_mapping) {
+                for (property, field) in properties {
+                    let Some(property) = property.as_str() else {
+                        continue;
+                    };
+                    if let Some(item) = object.get(property) {
+                        let item_pointer = format!("{pointer}/{property}");
+                        check(field, item, schemas, &item_pointer, violations, depth + 1)?;
+                    }
+                }
+            }
+        }
+        Some("string") => {
+            let Some(text) = value.as_str() else {
+                violation("expected a string".to_string());
+                return Ok(());
+            };
+            let format = definition.get("format").and_then(Value::as_str);
+            if let Some(format) = format.filter(|format| !matches_format(format, text)) {
+                violation(format!("`{text}` is not a valid {format}"));
+            }
+        }
+        Some("integer") => match value.as_i64() {
+            Some(number) => {
+                let minimum = definition.get("minimum").and_then(Value::as_i64);
+                if let Some(minimum) = minimum.filter(|minimum| number < *minimum) {
+                    violation(format!("{number} is below the minimum {minimum}"));
+                }
+            }
+            None => violation("expected an integer".to_string()),
+        },
+        Some("number") if !value.is_number() => violation("expected a number".to_string()),
+        Some("boolean") if !value.is_boolean() => violation("expected a boolean".to_string()),
+        Some("array") => {
+            let Some(items) = value.as_array() else {
+                violation("expected an array".to_string());
+                return Ok(());
+            };
+            if let Some(schema) = definition.get("items") {
+                for (index, item) in items.iter().enumerate() {
+                    let item_pointer = format!("{pointer}/{index}");
+                    check(schema, item, schemas, &item_pointer, violations, depth + 1)?;
+                }
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+// Shape checks only: enough to catch a sample that would not survive a real parser.
+fn matches_format(format: &str, text: &str) -> bool {
+    match format {
+        "uuid" => {
+            let groups: Vec<&str> = text.split('-').collect();
+            groups.iter().map(|group| group.len()).eq([8, 4, 4, 4, 12])
+                && groups
+                    .iter()
+                    .all(|group| group.chars().all(|c| c.is_ascii_hexdigit()))
+        }
+        "date-time" => {
+            let bytes = text.as_bytes();
+            bytes.len() >= 20
+                && bytes[..4].iter().all(u8::is_ascii_digit)
+                && bytes[4] == b'-'
+                && bytes[10] == b'T'
+        }
+        "byte" => {
+            text.len().is_multiple_of(4)
+                && text
+                    .trim_end_matches('=')
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '/')
+        }
+        _ => true,
+    }
+}
+
+fn to_json(value: &Value) -> Result<Json> {
+    serde_json::to_value(value).context("schema example is not representable as JSON")
+}
+
+fn to_snake(pascal: &str) -> String {
+    let mut out = String::new();
+    for (index, c) in pascal.chars().enumerate() {
+        if c.is_ascii_uppercase() {
+            if index > 0 {
+                out.push('_');
+            }
+            out.push(c.to_ascii_lowercase());
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{sample_envelopes, schema_violations, SampleKind};
+
+    const CONTRACT: &str = "asyncapi: \"3.0.0\"
+info:
+  title: Fixture
+  version: \"1.0.0\"
+components:
+  schemas:
+    MeshCommandEnvelope:
+      type: object
+      properties:
+        message_id:
+          type: string
+          format: uuid
+        operation:
+          type: string
+        ttl_ms:
+          type: integer
+          minimum: 1
+        payload:
+          oneOf:
+            - $ref: '#/components/schemas/TransferCompletion'
+            - $ref: '#/components/schemas/TransferUploadRequest'
+            - type: object
+    MeshEventEnvelope:
+      type: object
+      properties:
+        event:
+          type: string
+        payload:
+          oneOf:
+            - $ref: '#/components/schemas/TransferCompletion'
+            - type: object
+    TransferUploadRequest:
+      type: object
+      example:
+        file_name: tile.png
+      properties:
+        file_name:
+          type: string
+        media_type:
+          type: string
+          default: image/png
+        sent_at:
+          type: string
+          format: date-time
+    TransferCompletion:
+      type: object
+      properties:
+        status:
+          type: string
+          enum: [success, failed]
+x-retasync:
+  operations:
+    commands:
+      - transfer.upload
+      - note.create
+    events:
+      - transfer.completed
+";
+
+    #[test]
+    fn payloads_follow_the_operation_and_fill_every_property() {
+        let samples = sample_envelopes(CONTRACT).unwrap();
+        let kinds: Vec<(SampleKind, &str)> = samples
+            .iter()
+            .map(|sample| (sample.kind, sample.operation.as_str()))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                (SampleKind::Command, "transfer.upload"),
+                (SampleKind::Command, "note.create"),
+                (SampleKind::Event, "transfer.completed"),
+            ]
+        );
+
+        let upload = &samples[0];
+        assert_eq!(
+            upload.payload_schema.as_deref(),
+            Some("TransferUploadRequest")
+        );
+        assert_eq!(
+            upload.envelope,
+            serde_json::json!({
+                "message_id": super::SAMPLE_UUID,
+                "operation": "transfer.upload",
+                "ttl_ms": 1,
+                "payload": {
+                    "file_name": "tile.png",
+                    "media_type": "image/png",
+                    "sent_at": super::SAMPLE_DATE_TIME,
+                },
+            })
+        );
+        assert_eq!(samples[1].payload_schema, None);
+        assert_eq!(samples[1].envelope["payload"], serde_json::json!({}));
+        assert_eq!(
+            samples[2].payload_schema.as_deref(),
+            Some("TransferCompletion")
+        );
+        assert_eq!(samples[2].envelope["payload"]["status"], "success");
+    }
+
+    #[test]
+    fn samples_conform_and_violations_name_the_field() {
+        for sample in sample_envelopes(CONTRACT).unwrap() {
+            let envelope = match sample.kind {
+                SampleKind::Command => "MeshCommandEnvelope",
+                SampleKind::Event => "MeshEventEnvelope",
+            };
+            let violations = schema_violations(CONTRACT, envelope, &sample.envelope).unwrap();
+            assert_eq!(violations, Vec::<String>::new(), "{}", sample.operation);
+        }
+
+        let broken = serde_json::json!({ "file_name": 7, "sent_at": "yesterday" });
+        assert_eq!(
+            schema_violations(CONTRACT, "TransferUploadRequest", &broken).unwrap(),
+            [
+                "/file_name: expected a string",
+                "/sent_at: `yesterday` is not a valid date-time",
+            ]
+        );
+        let completion = serde_json::json!({ "status": "lost" });
+        assert_eq!(
+            schema_violations(CONTRACT, "TransferCompletion", &completion).unwrap(),
+            [r#"/status: "lost" is not one of ["success","failed"]"#]
+        );
+    }
+}
@@ -0,0 +1,525 @@
+﻿use anyhow::{anyhow, bail, Result};
+use serde_yaml::{Mapping, Value};
+
+use crate::generator::to_pascal_case;
+
+pub(crate) const SCHEMA_REF_PREFIX: &str = "#/components/schemas/";
+const RESERVED_IDENTS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "dyn", "else", "enum", "extern", "false",
+    "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref",
+    "return", "static", "struct", "trait", "true", "type", "unsafe", "use", "where", "while",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+enum FieldType {
+    String,
+    Integer { unsigned: bool },
+    Number,
+    Boolean,
+    Array(Box<FieldType>),
+    // An object whose `additionalProperties` give the type of every value.
+    Map(Box<FieldType>),
+    Struct(String),
+    Json,
+}
+
+#[derive(Debug, Clone)]
+struct SchemaField {
+    property: String,
+    ident: String,
+    ty: FieldType,
+    required: bool,
+    default: Option<Value>,
+    example: Option<Value>,
+    // `enum` members, or the single `const` value.
+    allowed: Vec<Value>,
+    minimum: Option<i64>,
+}
+
+impl SchemaField {
+    fn optional(&self) -> bool {
+        !self.required && self.default.is_none()
+    }
+
+    fn rust_type(&self) -> String {
+        let ty = rust_type(&self.ty);
+        if self.optional() {
+            format!("Option<{ty}>")
+        } else {
+            ty
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct SchemaStruct {
+    name: String,
+    fields: Vec<SchemaField>,
+    example: Option<Value>,
+}
+
+impl SchemaStruct {
+    fn default_fn(&self, field: &SchemaField) -> String {
+        format!(
+            "default_{}_{}",
+            to_field_ident(&self.name),
+            field.ident.trim_start_matches("r#")
+        )
+    }
+}
+
+// Object schemas under components.schemas, plus the inline objects nested in them.
+pub(crate) fn load_schemas(doc: &Value) -> Result<Vec<SchemaStruct>> {
+    let Some(schemas) = doc
+        .get("components")
+        .and_then(|components| components.get("schemas"))
+        .and_then(Value::as_mapping)
+    else {
+        return Ok(Vec::new());
+    };
+
+    let mut structs = Vec::new();
+    for (name, schema) in schemas {
+        let name = name
+            .as_str()
+            .ok_or_else(|| anyhow!("components.schemas keys must be strings"))?;
+        if is_struct(schema) {
+            collect_struct(&to_pascal_case(name), schema, schemas, &mut structs)?;
+        }
+    }
+    Ok(structs)
+}
+
+pub(crate) fn declares(structs: &[SchemaStruct], name: &str) -> bool {
+    structs.iter().any(|schema| schema.name == name)
+}
+
+fn is_struct(schema: &Value) -> bool {
+    schema.get("properties").and_then(Value::as_mapping).is_some()
+}
+
+fn is_map(schema: &Value) -> bool {
+    schema
+        .get("additionalProperties")
+        .is_some_and(Value::is_mapping)
+}
+
+pub(crate) fn first_example(definition: &Value) -> Option<Value> {
+    definition
+        .get("example")
+        .or_else(|| definition.get("examples").and_then(|examples| examples.get(0)))
+        .cloned()
+}
+
+fn collect_struct(
+    name: &str,
+    schema: &Value,
+    schemas: &Mapping,
+    out: &mut Vec<SchemaStruct>,
+) -> Result<()> {
+    let required: Vec<&str> = schema
+        .get("required")
+        .and_then(Value::as_sequence)
+        .map(|items| items.iter().filter_map(Value::as_str).collect())
+        .unwrap_or_default();
+    let properties = schema
+        .get("properties")
+        .and_then(Value::as_mapping)
+        .ok_or_else(|| anyhow!("schema {name} has no properties"))?;
+
+    let mut fields = Vec::new();
+    for (property, definition) in properties {
+        let property = property
+            .as_str()
+            .ok_or_else(|| anyhow!("schema {name} has a non-string property name"))?;
+        let nested = format!("{name}{}", to_pascal_case(property));
+        let allowed = match definition.get("const") {
+            Some(value) => vec![value.clone()],
+            None => definition
+                .get("enum")
+                .and_then(Value::as_sequence)
+                .cloned()
+                .unwrap_or_default(),
+        };
+        fields.push(SchemaField {
+            property: property.to_string(),
+            ident: to_field_ident(property),
+            ty: field_type(&nested, definition, schemas, out)?,
+            required: required.contains(&property),
+            default: definition.get("default").cloned(),
+            example: first_example(definition),
+            allowed,
+            minimum: definition.get("minimum").and_then(Value::as_i64),
+        });
+    }
+
+    let example = first_example(schema);
+    if let Some(example) = &example {
+        let Some(example) = example.as_mapping() else {
+            bail!("schema {name}: example {} is not an object", display(example));
+        };
+        for key in example.keys() {
+            if !fields.iter().any(|field| Some(field.property.as_str()) == key.as_str()) {
+                bail!("schema {name}: example names unknown property {}", display(key));
+            }
+        }
+    }
+
+    out.push(SchemaStruct {
+        name: name.to_string(),
+        fields,
+        example,
+    });
+    Ok(())
+}
+
+fn field_type(
+    nested_name: &str,
+    definition: &Value,
+    schemas: &Mapping,
+    out: &mut Vec<SchemaStruct>,
+) -> Result<FieldType> {
+    if let Some(reference) = definition.get("$ref").and_then(Value::as_str) {
+        let target = reference
+            .strip_prefix(SCHEMA_REF_PREFIX)
+            .ok_or_else(|| anyhow!("unsupported $ref {reference}"))?;
+        let schema = schemas
+            .get(target)
+            .ok_or_else(|| anyhow!("unresolved $ref {reference}"))?;
+        if is_struct(schema) {
+            return Ok(FieldType::Struct(to_pascal_case(target)));
+        }
+        return field_type(nested_name, schema, schemas, out);
+    }
+
+    if is_struct(definition) {
+        collect_struct(nested_name, definition, schemas, out)?;
+        return Ok(FieldType::Struct(nested_name.to_string()));
+    }
+
+    Ok(match definition.get("type").and_then(Value::as_str) {
+        Some("string") => FieldType::String,
+        Some("integer") => FieldType::Integer {
+            unsigned: definition
+                .get("minimum")
+                .and_then(Value::as_i64)
+                .is_some_and(|minimum| minimum >= 0),
+        },
+        Some("number") => FieldType::Number,
+        Some("boolean") => FieldType::Boolean,
+        Some("array") => {
+            let items = definition
+                .get("items")
+                .ok_or_else(|| anyhow!("array {nested_name} has no items schema"))?;
+            let item = field_type(&format!("{nested_name}Item"), items, schemas, out)?;
+            FieldType::Array(Box::new(item))
+        }
+        Some("object") if is_map(definition) => {
+            let values = &definition["additionalProperties"];
+            let value = field_type(&format!("{nested_name}Value"), values, schemas, out)?;
+            FieldType::Map(Box::new(value))
+        }
+        // Free-form objects and oneOf unions stay untyped.
+        _ => FieldType::Json,
+    })
+}
+
+fn rust_type(ty: &FieldType) -> String {
+    match ty {
+        FieldType::String => "String".to_string(),
+        FieldType::Integer { unsigned: true } => "u64".to_string(),
+        FieldType::Integer { unsigned: false } => "i64".to_string(),
+        FieldType::Number => "f64".to_string(),
+        FieldType::Boolean => "bool".to_string(),
+        FieldType::Array(item) => format!("Vec<{}>", rust_type(item)),
+        FieldType::Map(value) => {
+            format!("std::collections::BTreeMap<String, {}>", rust_type(value))
+        }
+        FieldType::Struct(name) => name.clone(),
+        FieldType::Json => "serde_json::Value".to_string(),
+    }
+}
+
+pub(crate) fn render_schemas(structs: &[SchemaStruct]) -> Result<String> {
+    let mut out = String::new();
+    if structs.is_empty() {
+        return Ok(out);
+    }
+
+    out.push_str("\npub mod schemas {\n");
+    out.push_str("    use serde::{Deserialize, Serialize};\n");
+    for schema in structs {
+        render_struct(schema, structs, &mut out)?;
+    }
+    out.push_str("}\n");
+    Ok(out)
+}
+
+fn render_struct(schema: &SchemaStruct, structs: &[SchemaStruct], out: &mut String) -> Result<()> {
+    let all_optional = schema.fields.iter().all(SchemaField::optional);
+    let defaultable = schema
+        .fields
+        .iter()
+        .all(|field| field.optional() || field.default.is_some());
+
+    out.push('\n');
+    if all_optional {
+        out.push_str("    #[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]\n");
+    } else {
+        out.push_str("    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]\n");
+    }
+    out.push_str(&format!("    pub struct {} {{\n", schema.name));
+    for field in &schema.fields {
+        let mut attributes = Vec::new();
+        if field.ident.trim_start_matches("r#") != field.property {
+            attributes.push(format!("rename = \"{}\"", field.property));
+        }
+        if field.default.is_some() {
+            attributes.push(format!("default = \"{}\"", schema.default_fn(field)));
+        } else if field.optional() {
+            attributes.push("default, skip_serializing_if = \"Option::is_none\"".to_string());
+        }
+        if !attributes.is_empty() {
+            out.push_str(&format!("        #[serde({})]\n", attributes.join(", ")));
+        }
+        out.push_str(&format!("        pub {}: {},\n", field.ident, field.rust_type()));
+    }
+    out.push_str("    }\n");
+
+    for field in &schema.fields {
+        let Some(default) = &field.default else {
+            continue;
+        };
+        let value = field_literal(&schema.name, field, "default", default, structs)?;
+        out.push_str(&format!(
+            "\n    fn {}() -> {} {{\n        {value}\n    }}\n",
+            schema.default_fn(field),
+            field.rust_type()
+        ));
+    }
+
+    if defaultable && !all_optional {
+        out.push_str(&format!("\n    impl Default for {} {{\n", schema.name));
+        out.push_str("        fn default() -> Self {\n");
+        out.push_str("            Self {\n");
+        for field in &schema.fields {
+            let value = if field.default.is_some() {
+                format!("{}()", schema.default_fn(field))
+            } else {
+                "None".to_string()
+            };
+            out.push_str(&format!("                {}: {value},\n", field.ident));
+        }
+        out.push_str("            }\n");
+        out.push_str("        }\n");
+        out.push_str("    }\n");
+    }
+
+    out.push_str(&format!("\n    impl {} {{\n", schema.name));
+    out.push_str("        pub fn example() -> Self {\n");
+    out.push_str("            Self {\n");
+    for field in &schema.fields {
+        let example = schema
+            .example
+            .as_ref()
+            .and_then(|example| example.get(field.property.as_str()))
+            .or(field.example.as_ref());
+        let value = match example {
+            Some(example) => field_literal(&schema.name, field, "example", example, structs)?,
+            None if field.default.is_some() => format!("{}()", schema.default_fn(field)),
+            None if field.optional() => "None".to_string(),
+            None => placeholder(field),
+        };
+        out.push_str(&format!("                {}: {value},\n", field.ident));
+    }
+    out.push_str("            }\n");
+    out.push_str("        }\n");
+    out.push_str("    }\n");
+    Ok(())
+}
+
+// A stand-in for a required property the schema gives no example or default for.
+fn placeholder(field: &SchemaField) -> String {
+    match &field.ty {
+        FieldType::String => match field.allowed.first().and_then(Value::as_str) {
+            Some(value) => format!("{value:?}.to_string()"),
+            None => "String::new()".to_string(),
+        },
+        FieldType::Integer { .. } => field.minimum.unwrap_or(0).to_string(),
+        FieldType::Number => "0.0".to_string(),
+        FieldType::Boolean => "false".to_string(),
+        FieldType::Array(_) => "Vec::new()".to_string(),
+        FieldType::Map(_) => "std::collections::BTreeMap::new()".to_string(),
+        FieldType::Struct(name) => format!("{name}::example()"),
+        FieldType::Json => "serde_json::Value::Null".to_string(),
+    }
+}
+
+fn field_literal(
+    schema: &str,
+    field: &SchemaField,
+    kind: &str,
+    value: &Value,
+    structs: &[SchemaStruct],
+) -> Result<String> {
+    let fail = |expected: &str| {
+        anyhow!(
+            "schema {schema} property {}: {kind} {} is not {expected}",
+            field.property,
+            display(value)
+        )
+    };
+    if !field.allowed.is_empty() && !field.allowed.contains(value) {
+        let allowed: Vec<String> = field.allowed.iter().map(display).collect();
+        return Err(fail(&format!("one of [{}]", allowed.join(", "))));
+    }
+    let literal = literal(&field.ty, value, structs).map_err(|expected| match expected {
+        Expected::Type(expected) => fail(&expected),
+        Expected::Nested(err) => err.context(format!(
+            "schema {schema} property {}: invalid {kind}",
+            field.property
+        )),
+    })?;
+    Ok(if field.optional() {
+        format!("Some({literal})")
+    } else {
+        literal
+    })
+}
+
+enum Expected {
+    Type(String),
+    Nested(anyhow::Error),
+}
+
+fn literal(ty: &FieldType, value: &Value, structs: &[SchemaStruct]) -> Result<String, Expected> {
+    let expected = |description: &str| Expected::Type(description.to_string());
+    match ty {
+        FieldType::String => value
+            .as_str()
+            .map(|text| format!("{text:?}.to_string()"))
+            .ok_or_else(|| expected("a string")),
+        FieldType::Integer { unsigned: true } => value
+            .as_u64()
+            .map(|number| number.to_string())
+            .ok_or_else(|| expected("a non-negative integer")),
+        FieldType::Integer { unsigned: false } => value
+            .as_i64()
+            .map(|number| number.to_string())
+            .ok_or_else(|| expected("an integer")),
+        FieldType::Number => value
+            .as_f64()
+            .filter(|number| number.is_finite())
+            .map(|number| format!("{number:?}"))
+            .ok_or_else(|| expected("a number")),
+        FieldType::Boolean => value
+            .as_bool()
+            .map(|flag| flag.to_string())
+            .ok_or_else(|| expected("a boolean")),
+        FieldType::Array(item) => {
+            let items = value.as_sequence().ok_or_else(|| expected("an array"))?;
+            if items.is_empty() {
+                return Ok("Vec::new()".to_string());
+            }
+            let rendered = items
+                .iter()
+                .map(|entry| literal(item, entry, structs))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|err| match err {
+                    Expected::Type(inner) => {
+                        Expected::Type(format!("an array whose items are each {inner}"))
+                    }
+                    nested => nested,
+                })?;
+            Ok(format!("vec![{}]", rendered.join(", ")))
+        }
+        FieldType::Map(item) => {
+            let entries = value.as_mapping().ok_or_else(|| expected("an object"))?;
+            if entries.is_empty() {
+                return Ok("std::collections::BTreeMap::new()".to_string());
+            }
+            let mut rendered = Vec::with_capacity(entries.len());
+            for (key, entry) in entries {
+                let key = key.as_str().ok_or_else(|| expected("an object with string keys"))?;
+                let entry = literal(item, entry, structs).map_err(|err| match err {
+                    Expected::Type(inner) => {
+                        Expected::Type(format!("an object whose values are each {inner}"))
+                    }
+                    nested => nested,
+                })?;
+                rendered.push(format!("({key:?}.to_string(), {entry})"));
+            }
+            Ok(format!("[{}].into_iter().collect()", rendered.join(", ")))
+        }
+        FieldType::Struct(name) => {
+            let object = value.as_mapping().ok_or_else(|| expected("an object"))?;
+            let schema = structs
+                .iter()
+                .find(|schema| &schema.name == name)
+                .ok_or_else(|| Expected::Nested(anyhow!("unknown schema {name}")))?;
+            struct_literal(schema, object, structs).map_err(Expected::Nested)
+        }
+        FieldType::Json => serde_json::to_string(value)
+            .map(|json| format!("serde_json::json!({json})"))
+            .map_err(|err| Expected::Nested(err.into())),
+    }
+}
+
+fn struct_literal(
+    schema: &SchemaStruct,
+    object: &Mapping,
+    structs: &[SchemaStruct],
+) -> Result<String> {
+    for key in object.keys() {
+        if !schema
+            .fields
+            .iter()
+            .any(|field| Some(field.property.as_str()) == key.as_str())
+        {
+            bail!("schema {} has no property {}", schema.name, display(key));
+        }
+    }
+
+    let mut values = Vec::new();
+    for field in &schema.fields {
+        let value = match object.get(field.property.as_str()) {
+            Some(value) => field_literal(&schema.name, field, "value", value, structs)?,
+            None if field.default.is_some() => format!("{}()", schema.default_fn(field)),
+            None if field.optional() => "None".to_string(),
+            None => bail!(
+                "schema {} property {}: required value is missing",
+                schema.name,
+                field.property
+            ),
+        };
+        values.push(format!("{}: {value}", field.ident));
+    }
+    Ok(format!("{} {{ {} }}", schema.name, values.join(", ")))
+}
+
+fn display(value: &Value) -> String {
+    serde_json::to_string(value).unwrap_or_else(|_| format!("{value:?}"))
+}
+
+fn to_field_ident(property: &str) -> String {
+    let mut ident = String::new();
+    let mut previous: Option<char> = None;
+    for c in property.chars() {
+        match c {
+            '.' | '-' | '/' | ' ' => ident.push('_'),
+            c if c.is_ascii_uppercase() => {
+                if previous.is_some_and(|p| p.is_ascii_lowercase() || p.is_ascii_digit()) {
+                    ident.push('_');
+                }
+                ident.push(c.to_ascii_lowercase());
+            }
+            c => ident.push(c),
+        }
+        previous = Some(c);
+    }
+    if RESERVED_IDENTS.contains(&ident.as_str()) {
+        format!("r#{ident}")
+    } else {
+        ident
+    }
+}
@@ -0,0 +1,286 @@
+﻿use std::collections::BTreeSet;
+use std::fmt;
+
+use serde::de::{self, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::codec::{canonical_digest, CodecError};
+
+pub const BUNDLE_MEDIA_TYPE: &str = "application/vnd.retasync.bundle+msgpack";
+pub const BUNDLE_FORMAT_VERSION: u32 = 1;
+
+#[derive(Debug, Error)]
+pub enum BundleError {
+    #[error("bundle has no entries")]
+    Empty,
+    #[error("bundle entry name {0:?} appears more than once")]
+    DuplicateName(String),
+    #[error("bundle entry name {0:?} is empty or not a plain relative path")]
+    InvalidName(String),
+    #[error("unsupported bundle format version {0}")]
+    UnsupportedVersion(u32),
+    #[error("bundle manifest digest mismatch: declared {declared}, computed {computed}")]
+    DigestMismatch { declared: String, computed: String },
+    #[error("failed to encode bundle: {0}")]
+    Encode(#[source] rmp_serde::encode::Error),
+    #[error("failed to decode bundle: {0}")]
+    Decode(#[source] rmp_serde::decode::Error),
+    #[error(transparent)]
+    Codec(#[from] CodecError),
+}
+
+// One file inside a bundle. `sha256` is the hash the sender computed, so a member whose bytes
+// were damaged on the way fails `verify` without invalidating its siblings.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BundleEntry {
+    pub name: String,
+    pub media_type: String,
+    #[serde(serialize_with = "write_bytes", deserialize_with = "read_bytes")]
+    pub bytes: Vec<u8>,
+    pub sha256: String,
+}
+
+impl BundleEntry {
+    pub fn new(name: &str, media_type: &str, bytes: Vec<u8>) -> Self {
+        Self {
+            name: name.to_string(),
+            media_type: media_type.to_string(),
+            sha256: format!("{:x}", Sha256::digest(&bytes)),
+            bytes,
+        }
+    }
+
+    pub fn verify(&self) -> bool {
+        format!("{:x}", Sha256::digest(&self.bytes)) == self.sha256
+    }
+
+    fn manifest(&self) -> Value {
+        json!({
+            "name": self.name,
+            "media_type": self.media_type,
+            "size": self.bytes.len(),
+            "sha256": self.sha256,
+        })
+    }
+}
+
+// Several files sent as one transfer payload. Entries keep the order they were packed in, and
+// the struct encodes with fixed field order, so equal bundles encode to equal bytes. `digest`
+// covers the metadata and each entry's name, type, size and declared hash but not the bytes,
+// which each entry's own hash covers.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Bundle {
+    pub version: u32,
+    pub metadata: Map<String, Value>,
+    pub entries: Vec<BundleEntry>,
+    pub digest: String,
+}
+
+impl Bundle {
+    pub fn pack(
+        metadata: Map<String, Value>,
+        entries: Vec<BundleEntry>,
+    ) -> Result<Self, BundleError> {
+        if entries.is_empty() {
+            return Err(BundleError::Empty);
+        }
+        let mut names = BTreeSet::new();
+        for entry in &entries {
+            if !is_member_name(&entry.name) {
+                return Err(BundleError::InvalidName(entry.name.clone()));
+            }
+            if !names.insert(entry.name.as_str()) {
+                return Err(BundleError::DuplicateName(entry.name.clone()));
+            }
+        }
+        let digest = manifest_digest(BUNDLE_FORMAT_VERSION, &metadata, &entries)?;
+        Ok(Self {
+            version: BUNDLE_FORMAT_VERSION,
+            metadata,
+            entries,
+            digest,
+        })
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, BundleError> {
+        rmp_serde::to_vec_named(self).map_err(BundleError::Encode)
+    }
+
+    // Checks the container and its manifest digest. Member hashes are left to the caller so a
+    // damaged member can be reported on its own.
+    pub fn decode(bytes: &[u8]) -> Result<Self, BundleError> {
+        let bundle: Self = rmp_serde::from_slice(bytes).map_err(BundleError::Decode)?;
+        if bundle.version != BUNDLE_FORMAT_VERSION {
+            return Err(BundleError::UnsupportedVersion(bundle.version));
+        }
+        let computed = manifest_digest(bundle.version, &bundle.metadata, &bundle.entries)?;
+        if computed != bundle.digest {
+            return Err(BundleError::DigestMismatch {
+                declared: bundle.digest,
+                computed,
+            });
+        }
+        Ok(bundle)
+    }
+
+    pub fn total_bytes(&self) -> usize {
+        self.entries.iter().map(|entry| entry.bytes.len()).sum()
+    }
+}
+
+// Relative, `/`-separated, and without `.` or `..` segments, so a receiver can lay members out
+// under one directory.
+fn is_member_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('/')
+        && !name.contains('\\')
+        && name
+            .split('/')
+            .all(|segment| !segment.is_empty() && segment != "." && segment != "..")
+}
+
+fn manifest_digest(
+    version: u32,
+    metadata: &Map<String, Value>,
+    entries: &[BundleEntry],
+) -> Result<String, CodecError> {
+    let entries: Vec<Value> = entries.iter().map(BundleEntry::manifest).collect();
+    canonical_digest(&json!({
+        "version": version,
+        "metadata": metadata,
+        "entries": entries,
+    }))
+}
+
+fn write_bytes<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_bytes(bytes)
+}
+
+fn read_bytes<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    struct BytesVisitor;
+
+    impl<'de> Visitor<'de> for BytesVisitor {
+        type Value = Vec<u8>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a byte array")
+        }
+
+        fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<Vec<u8>, E> {
+            Ok(bytes.to_vec())
+        }
+
+        fn visit_byte_buf<E: de::Error>(self, bytes: Vec<u8>) -> Result<Vec<u8>, E> {
+            Ok(bytes)
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<u8>, A::Error> {
+            let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0).min(4096));
+            while let Some(byte) = seq.next_element()? {
+                bytes.push(byte);
+            }
+            Ok(bytes)
+        }
+    }
+
+    deserializer.deserialize_byte_buf(BytesVisitor)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Bundle, BundleEntry, BundleError};
+    use serde_json::{json, Map, Value};
+
+    fn metadata(value: Value) -> Map<String, Value> {
+        value.as_object().cloned().unwrap()
+    }
+
+    fn entries() -> Vec<BundleEntry> {
+        vec![
+            BundleEntry::new(
+                "manifest.json",
+                "application/json",
+                br#"{"photos":2}"#.to_vec(),
+            ),
+            BundleEntry::new("photos/a.jpg", "image/jpeg", vec![0xff, 0xd8, 0x00, 0x01]),
+            BundleEntry::new("photos/b.jpg", "image/jpeg", vec![0xff, 0xd8, 0x02]),
+        ]
+    }
+
+    #[test]
+    fn encoding_is_deterministic_and_keeps_entry_order() {
+        let first = Bundle::pack(
+            metadata(json!({ "mission": "ALPHA", "grid": 4 })),
+            entries(),
+        )
+        .unwrap()
+        .encode()
+        .unwrap();
+        let second = Bundle::pack(
+            metadata(json!({ "grid": 4, "mission": "ALPHA" })),
+            entries(),
+        )
+        .unwrap()
+        .encode()
+        .unwrap();
+        assert_eq!(first, second);
+
+        let decoded = Bundle::decode(&first).unwrap();
+        let names: Vec<_> = decoded
+            .entries
+            .iter()
+            .map(|entry| entry.name.as_str())
+            .collect();
+        assert_eq!(names, ["manifest.json", "photos/a.jpg", "photos/b.jpg"]);
+        assert!(decoded.entries.iter().all(BundleEntry::verify));
+        assert_eq!(decoded.total_bytes(), 12 + 4 + 3);
+
+        let mut swapped = entries();
+        swapped.swap(1, 2);
+        let reordered =
+            Bundle::pack(metadata(json!({ "mission": "ALPHA", "grid": 4 })), swapped).unwrap();
+        assert_ne!(reordered.encode().unwrap(), first);
+        assert_ne!(reordered.digest, decoded.digest);
+    }
+
+    #[test]
+    fn damaged_member_fails_alone_but_a_damaged_manifest_fails_the_bundle() {
+        let bundle = Bundle::pack(Map::new(), entries()).unwrap();
+        let mut damaged = bundle.clone();
+        damaged.entries[1].bytes[2] ^= 0xff;
+        let decoded = Bundle::decode(&damaged.encode().unwrap()).unwrap();
+        let verified: Vec<_> = decoded.entries.iter().map(BundleEntry::verify).collect();
+        assert_eq!(verified, [true, false, true]);
+
+        let mut renamed = bundle;
+        renamed.entries[0].name = "other.json".to_string();
+        assert!(matches!(
+            Bundle::decode(&renamed.encode().unwrap()),
+            Err(BundleError::DigestMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn names_must_be_unique_relative_paths() {
+        let mut duplicate = entries();
+        duplicate[2].name = "photos/a.jpg".to_string();
+        assert!(matches!(
+            Bundle::pack(Map::new(), duplicate),
+            Err(BundleError::DuplicateName(name)) if name == "photos/a.jpg"
+        ));
+        for name in ["", "/etc/passwd", "photos/../../x", "photos//a", "a\\b"] {
+            let entry = BundleEntry::new(name, "text/plain", Vec::new());
+            assert!(matches!(
+                Bundle::pack(Map::new(), vec![entry]),
+                Err(BundleError::InvalidName(_))
+            ));
+        }
+        assert!(matches!(
+            Bundle::pack(Map::new(), Vec::new()),
+            Err(BundleError::Empty)
+        ));
+    }
+}
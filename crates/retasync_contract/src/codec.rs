@@ -1,8 +1,126 @@
-﻿use rmp_serde::{decode::Error as DecodeError, encode::Error as EncodeError};
-use serde::{de::DeserializeOwned, Serialize};
+﻿use std::io::{Read, Write};
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use rmp_serde::{decode::Error as DecodeError, encode::Error as EncodeError};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
 use thiserror::Error;
 
+pub const CONTENT_TYPE_MSGPACK: &str = "application/msgpack";
+// A payload sealed for its destination; see `sealed`.
+pub const CONTENT_TYPE_SEALED: &str = "application/msgpack+sealed";
+pub const DEFAULT_COMPRESSION_THRESHOLD: usize = 4 * 1024;
+pub const DEFAULT_MAX_DECOMPRESSED_SIZE: usize = 16 * 1024 * 1024;
+
+// Limits applied to payloads from untrusted peers. Local encode paths stay unlimited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CodecLimits {
+    pub max_depth: usize,
+    pub max_bytes: usize,
+    pub max_map_entries: usize,
+    pub max_array_len: usize,
+    pub max_string_len: usize,
+}
+
+impl Default for CodecLimits {
+    fn default() -> Self {
+        Self {
+            max_depth: 64,
+            max_bytes: 4 * 1024 * 1024,
+            max_map_entries: 4096,
+            max_array_len: 65_536,
+            max_string_len: 1024 * 1024,
+        }
+    }
+}
+
+impl CodecLimits {
+    pub fn unlimited() -> Self {
+        Self {
+            max_depth: usize::MAX,
+            max_bytes: usize::MAX,
+            max_map_entries: usize::MAX,
+            max_array_len: usize::MAX,
+            max_string_len: usize::MAX,
+        }
+    }
+
+    // Walks an already-decoded value without recursion.
+    pub fn check_value(&self, value: &Value) -> Result<(), CodecError> {
+        let mut pending = vec![(value, 1usize)];
+        while let Some((value, depth)) = pending.pop() {
+            match value {
+                Value::Object(map) => {
+                    check_limit("depth", self.max_depth, depth)?;
+                    check_limit("map_entries", self.max_map_entries, map.len())?;
+                    for (key, item) in map {
+                        check_limit("string_len", self.max_string_len, key.len())?;
+                        pending.push((item, depth + 1));
+                    }
+                }
+                Value::Array(items) => {
+                    check_limit("depth", self.max_depth, depth)?;
+                    check_limit("array_len", self.max_array_len, items.len())?;
+                    pending.extend(items.iter().map(|item| (item, depth + 1)));
+                }
+                Value::String(text) => {
+                    check_limit("string_len", self.max_string_len, text.len())?;
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+fn check_limit(which: &'static str, limit: usize, actual: usize) -> Result<(), CodecError> {
+    if actual > limit {
+        Err(CodecError::LimitExceeded {
+            which,
+            limit,
+            actual,
+        })
+    } else {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Compression {
+    Zstd,
+    Gzip,
+}
+
+impl Compression {
+    pub const ALL: [Compression; 2] = [Compression::Zstd, Compression::Gzip];
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Compression::Zstd => "application/msgpack+zstd",
+            Compression::Gzip => "application/msgpack+gzip",
+        }
+    }
+
+    pub fn from_content_type(content_type: &str) -> Result<Option<Self>, CodecError> {
+        match content_type {
+            // Ciphertext does not compress, so a sealed payload never is.
+            CONTENT_TYPE_MSGPACK | CONTENT_TYPE_SEALED => Ok(None),
+            "application/msgpack+zstd" => Ok(Some(Compression::Zstd)),
+            "application/msgpack+gzip" => Ok(Some(Compression::Gzip)),
+            other => Err(CodecError::UnsupportedContentType(other.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncodedPayload {
+    pub content_type: String,
+    pub bytes: Vec<u8>,
+}
+
 #[derive(Debug, Error)]
 pub enum CodecError {
     #[error("failed to serialize payload to JSON value: {0}")]
@@ -13,6 +131,20 @@ pub enum CodecError {
     MessagePackDecode(#[source] DecodeError),
     #[error("failed to deserialize decoded payload to target type: {0}")]
     JsonDeserialize(#[source] serde_json::Error),
+    #[error("unsupported payload content type: {0}")]
+    UnsupportedContentType(String),
+    #[error("payload compression failed: {0}")]
+    Compression(#[source] std::io::Error),
+    #[error("decompressed payload exceeds {limit} bytes")]
+    DecompressedTooLarge { limit: usize },
+    #[error("compressed payload is not a base64 string")]
+    MalformedCompressed,
+    #[error("payload {which} limit exceeded: {actual} > {limit}")]
+    LimitExceeded {
+        which: &'static str,
+        limit: usize,
+        actual: usize,
+    },
 }
 
 pub fn encode_canonical<T: Serialize>(value: &T) -> Result<Vec<u8>, CodecError> {
@@ -21,37 +153,373 @@ pub fn encode_canonical<T: Serialize>(value: &T) -> Result<Vec<u8>, CodecError>
     rmp_serde::to_vec_named(&normalized).map_err(CodecError::MessagePackEncode)
 }
 
+// Hex SHA-256 of the canonical encoding, so equal values hash alike on every node.
+pub fn canonical_digest<T: Serialize>(value: &T) -> Result<String, CodecError> {
+    Ok(format!("{:x}", Sha256::digest(encode_canonical(value)?)))
+}
+
 pub fn decode_canonical<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, CodecError> {
     let decoded: Value = rmp_serde::from_slice(bytes).map_err(CodecError::MessagePackDecode)?;
     serde_json::from_value(decoded).map_err(CodecError::JsonDeserialize)
 }
 
+pub fn decode_canonical_with_limits<T: DeserializeOwned>(
+    bytes: &[u8],
+    limits: &CodecLimits,
+) -> Result<T, CodecError> {
+    check_limit("bytes", limits.max_bytes, bytes.len())?;
+    scan_msgpack(bytes, limits)?;
+    decode_canonical(bytes)
+}
+
+pub fn decode_canonical_compressed_with_limits<T: DeserializeOwned>(
+    bytes: &[u8],
+    content_type: &str,
+    limits: &CodecLimits,
+) -> Result<T, CodecError> {
+    let expanded = expand(bytes, content_type, limits.max_bytes)?;
+    decode_canonical_with_limits(&expanded, limits)
+}
+
+pub fn encode_canonical_compressed<T: Serialize>(
+    value: &T,
+    compression: Compression,
+    threshold: usize,
+) -> Result<EncodedPayload, CodecError> {
+    let canonical = encode_canonical(value)?;
+    if canonical.len() <= threshold {
+        return Ok(EncodedPayload {
+            content_type: CONTENT_TYPE_MSGPACK.to_string(),
+            bytes: canonical,
+        });
+    }
+
+    let bytes = match compression {
+        Compression::Zstd => {
+            zstd::encode_all(canonical.as_slice(), 0).map_err(CodecError::Compression)?
+        }
+        Compression::Gzip => {
+            let mut encoder =
+                flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder
+                .write_all(&canonical)
+                .map_err(CodecError::Compression)?;
+            encoder.finish().map_err(CodecError::Compression)?
+        }
+    };
+
+    Ok(EncodedPayload {
+        content_type: compression.content_type().to_string(),
+        bytes,
+    })
+}
+
+// A compressed envelope payload travels as the base64 of the compressed canonical bytes, the
+// same way a sealed one does.
+pub fn compress_payload(payload: &Value, compression: Compression) -> Result<Value, CodecError> {
+    let encoded = encode_canonical_compressed(payload, compression, 0)?;
+    Ok(Value::String(STANDARD.encode(encoded.bytes)))
+}
+
+// Undoes `compress_payload` for a payload from a peer. Expansion stops at `limits.max_bytes`
+// (never past `DEFAULT_MAX_DECOMPRESSED_SIZE`) and the result is held to the same limits as an
+// uncompressed payload. Plain and sealed payloads come back unchanged.
+pub fn expand_payload(
+    payload: Value,
+    content_type: &str,
+    limits: &CodecLimits,
+) -> Result<Value, CodecError> {
+    if Compression::from_content_type(content_type)?.is_none() {
+        return Ok(payload);
+    }
+    let bytes = payload
+        .as_str()
+        .and_then(|encoded| STANDARD.decode(encoded).ok())
+        .ok_or(CodecError::MalformedCompressed)?;
+    let max_decompressed = limits.max_bytes.min(DEFAULT_MAX_DECOMPRESSED_SIZE);
+    let expanded = expand(&bytes, content_type, max_decompressed)?;
+    decode_canonical_with_limits(&expanded, limits)
+}
+
+pub fn decode_canonical_compressed<T: DeserializeOwned>(
+    bytes: &[u8],
+    content_type: &str,
+    max_decompressed: usize,
+) -> Result<T, CodecError> {
+    decode_canonical(&expand(bytes, content_type, max_decompressed)?)
+}
+
+fn expand<'a>(
+    bytes: &'a [u8],
+    content_type: &str,
+    max_decompressed: usize,
+) -> Result<std::borrow::Cow<'a, [u8]>, CodecError> {
+    let Some(compression) = Compression::from_content_type(content_type)? else {
+        return Ok(std::borrow::Cow::Borrowed(bytes));
+    };
+
+    let reader: Box<dyn Read + '_> = match compression {
+        Compression::Zstd => Box::new(
+            zstd::stream::read::Decoder::new(bytes).map_err(CodecError::Compression)?,
+        ),
+        Compression::Gzip => Box::new(flate2::read::GzDecoder::new(bytes)),
+    };
+
+    let mut expanded = Vec::new();
+    reader
+        .take(max_decompressed as u64 + 1)
+        .read_to_end(&mut expanded)
+        .map_err(CodecError::Compression)?;
+    if expanded.len() > max_decompressed {
+        return Err(CodecError::DecompressedTooLarge {
+            limit: max_decompressed,
+        });
+    }
+
+    Ok(std::borrow::Cow::Owned(expanded))
+}
+
+// Walks msgpack headers before anything is allocated so hostile lengths and nesting are
+// rejected up front. Malformed input is left for the real decoder to report.
+fn scan_msgpack(bytes: &[u8], limits: &CodecLimits) -> Result<(), CodecError> {
+    let mut open: Vec<usize> = Vec::new();
+    let mut remaining = 1usize;
+    let mut offset = 0usize;
+
+    while remaining > 0 || !open.is_empty() {
+        if remaining == 0 {
+            remaining = open.pop().unwrap_or_default();
+            continue;
+        }
+        remaining -= 1;
+
+        let Some(&marker) = bytes.get(offset) else {
+            return Ok(());
+        };
+        offset += 1;
+        let length = |offset: &mut usize, width: usize| -> Option<usize> {
+            let raw = bytes.get(*offset..*offset + width)?;
+            *offset += width;
+            Some(raw.iter().fold(0usize, |acc, byte| (acc << 8) | *byte as usize))
+        };
+
+        let (skip, children) = match marker {
+            0x00..=0x7f | 0xc0 | 0xc2 | 0xc3 | 0xe0..=0xff => (0, None),
+            0x80..=0x8f => (0, Some(("map_entries", (marker & 0x0f) as usize))),
+            0x90..=0x9f => (0, Some(("array_len", (marker & 0x0f) as usize))),
+            0xa0..=0xbf => (string_len(limits, (marker & 0x1f) as usize)?, None),
+            0xc4 | 0xd9 => match length(&mut offset, 1) {
+                Some(len) => (string_len(limits, len)?, None),
+                None => return Ok(()),
+            },
+            0xc5 | 0xda => match length(&mut offset, 2) {
+                Some(len) => (string_len(limits, len)?, None),
+                None => return Ok(()),
+            },
+            0xc6 | 0xdb => match length(&mut offset, 4) {
+                Some(len) => (string_len(limits, len)?, None),
+                None => return Ok(()),
+            },
+            0xc7 => match length(&mut offset, 1) {
+                Some(len) => (string_len(limits, len)? + 1, None),
+                None => return Ok(()),
+            },
+            0xc8 => match length(&mut offset, 2) {
+                Some(len) => (string_len(limits, len)? + 1, None),
+                None => return Ok(()),
+            },
+            0xc9 => match length(&mut offset, 4) {
+                Some(len) => (string_len(limits, len)? + 1, None),
+                None => return Ok(()),
+            },
+            0xcc | 0xd0 => (1, None),
+            0xcd | 0xd1 => (2, None),
+            0xca | 0xce | 0xd2 => (4, None),
+            0xcb | 0xcf | 0xd3 => (8, None),
+            0xd4 => (2, None),
+            0xd5 => (3, None),
+            0xd6 => (5, None),
+            0xd7 => (9, None),
+            0xd8 => (17, None),
+            0xdc => match length(&mut offset, 2) {
+                Some(len) => (0, Some(("array_len", len))),
+                None => return Ok(()),
+            },
+            0xdd => match length(&mut offset, 4) {
+                Some(len) => (0, Some(("array_len", len))),
+                None => return Ok(()),
+            },
+            0xde => match length(&mut offset, 2) {
+                Some(len) => (0, Some(("map_entries", len))),
+                None => return Ok(()),
+            },
+            0xdf => match length(&mut offset, 4) {
+                Some(len) => (0, Some(("map_entries", len))),
+                None => return Ok(()),
+            },
+            0xc1 => return Ok(()),
+        };
+        offset = offset.saturating_add(skip);
+
+        if let Some((which, count)) = children {
+            let limit = match which {
+                "map_entries" => limits.max_map_entries,
+                _ => limits.max_array_len,
+            };
+            check_limit(which, limit, count)?;
+            check_limit("depth", limits.max_depth, open.len() + 1)?;
+            open.push(remaining);
+            remaining = if which == "map_entries" { count * 2 } else { count };
+        }
+    }
+    Ok(())
+}
+
+fn string_len(limits: &CodecLimits, len: usize) -> Result<usize, CodecError> {
+    check_limit("string_len", limits.max_string_len, len)?;
+    Ok(len)
+}
+
+// Iterative so that hostile nesting cannot overflow the stack while encoding.
 fn normalize_json(value: Value) -> Value {
-    match value {
-        Value::Object(obj) => {
-            let mut keys: Vec<String> = obj.keys().cloned().collect();
-            keys.sort();
-
-            let mut normalized = Map::new();
-            for key in keys {
-                if let Some(item) = obj.get(&key) {
-                    normalized.insert(key, normalize_json(item.clone()));
+    enum Frame {
+        Object {
+            pending: std::vec::IntoIter<(String, Value)>,
+            done: Map<String, Value>,
+            key: String,
+        },
+        Array {
+            pending: std::vec::IntoIter<Value>,
+            done: Vec<Value>,
+        },
+    }
+
+    let mut stack: Vec<Frame> = Vec::new();
+    let mut current = value;
+    loop {
+        let mut finished = match current {
+            Value::Object(obj) => {
+                let mut entries: Vec<(String, Value)> = obj.into_iter().collect();
+                entries.sort_by(|left, right| left.0.cmp(&right.0));
+                stack.push(Frame::Object {
+                    pending: entries.into_iter(),
+                    done: Map::new(),
+                    key: String::new(),
+                });
+                None
+            }
+            Value::Array(items) => {
+                stack.push(Frame::Array {
+                    done: Vec::with_capacity(items.len()),
+                    pending: items.into_iter(),
+                });
+                None
+            }
+            primitive => Some(primitive),
+        };
+
+        loop {
+            let Some(frame) = stack.last_mut() else {
+                return finished.unwrap_or(Value::Null);
+            };
+            if let Some(value) = finished.take() {
+                match frame {
+                    Frame::Object { done, key, .. } => {
+                        done.insert(std::mem::take(key), value);
+                    }
+                    Frame::Array { done, .. } => done.push(value),
+                }
+            }
+            let next = match frame {
+                Frame::Object { pending, key, .. } => pending.next().map(|(name, item)| {
+                    *key = name;
+                    item
+                }),
+                Frame::Array { pending, .. } => pending.next(),
+            };
+            match next {
+                Some(child) => {
+                    current = child;
+                    break;
+                }
+                None => {
+                    finished = match stack.pop() {
+                        Some(Frame::Object { done, .. }) => Some(Value::Object(done)),
+                        Some(Frame::Array { done, .. }) => Some(Value::Array(done)),
+                        None => None,
+                    };
                 }
             }
-            Value::Object(normalized)
-        }
-        Value::Array(items) => {
-            let normalized = items.into_iter().map(normalize_json).collect();
-            Value::Array(normalized)
         }
-        primitive => primitive,
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{decode_canonical, encode_canonical};
+    use super::{
+        canonical_digest, compress_payload, decode_canonical, decode_canonical_compressed,
+        decode_canonical_with_limits, encode_canonical, encode_canonical_compressed,
+        expand_payload, CodecError, CodecLimits, Compression, CONTENT_TYPE_MSGPACK,
+    };
+    use crate::{EnvelopeMeta, IdentityHash, MeshCommandEnvelope, MetaPriority};
     use serde::{Deserialize, Serialize};
+    use serde_json::{json, Value};
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+
+    struct CountingAllocator;
+
+    thread_local! {
+        static TRACKING: Cell<bool> = const { Cell::new(false) };
+        static LIVE: Cell<isize> = const { Cell::new(0) };
+        static PEAK: Cell<isize> = const { Cell::new(0) };
+    }
+
+    fn record(delta: isize) {
+        let _ = TRACKING.try_with(|tracking| {
+            if tracking.get() {
+                let live = LIVE.get() + delta;
+                LIVE.set(live);
+                PEAK.set(PEAK.get().max(live));
+            }
+        });
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let ptr = System.alloc(layout);
+            if !ptr.is_null() {
+                record(layout.size() as isize);
+            }
+            ptr
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout);
+            record(-(layout.size() as isize));
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    const PEAK_BUDGET: isize = 64 * 1024;
+
+    fn peak_allocation<R>(run: impl FnOnce() -> R) -> (R, isize) {
+        LIVE.set(0);
+        PEAK.set(0);
+        TRACKING.set(true);
+        let result = run();
+        TRACKING.set(false);
+        (result, PEAK.get())
+    }
+
+    fn assert_limit(result: Result<Value, CodecError>, expected: &str) {
+        match result {
+            Err(CodecError::LimitExceeded { which, .. }) => assert_eq!(which, expected),
+            other => panic!("expected {expected} limit, got {other:?}"),
+        }
+    }
 
     #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
     struct Nested {
@@ -80,4 +548,209 @@ mod tests {
 
         assert_eq!(sample, decoded);
     }
+
+    #[test]
+    fn canonical_digest_ignores_key_order() {
+        let sample = Sample {
+            zulu: "z".to_string(),
+            nested: Nested {
+                alpha: "a".to_string(),
+                beta: "b".to_string(),
+            },
+        };
+        let reordered = json!({ "nested": { "beta": "b", "alpha": "a" }, "zulu": "z" });
+        assert_eq!(canonical_digest(&sample).unwrap(), canonical_digest(&reordered).unwrap());
+    }
+
+    fn large_payload() -> Value {
+        json!({ "summary": "all teams green ".repeat(1024) })
+    }
+
+    #[test]
+    fn compressed_round_trip_for_each_scheme() {
+        let payload = large_payload();
+        for compression in [Compression::Zstd, Compression::Gzip] {
+            let encoded =
+                encode_canonical_compressed(&payload, compression, 4096).expect("encode");
+            assert_eq!(encoded.content_type, compression.content_type());
+            assert!(encoded.bytes.len() < encode_canonical(&payload).unwrap().len());
+
+            let decoded: Value =
+                decode_canonical_compressed(&encoded.bytes, &encoded.content_type, 1 << 20)
+                    .expect("decode");
+            assert_eq!(decoded, payload);
+        }
+    }
+
+    #[test]
+    fn compressed_envelope_payloads_expand_within_limits() {
+        let payload = large_payload();
+        let limits = CodecLimits::default();
+        for compression in [Compression::Zstd, Compression::Gzip] {
+            let compressed = compress_payload(&payload, compression).expect("compress");
+            assert!(
+                encode_canonical(&compressed).unwrap().len()
+                    < encode_canonical(&payload).unwrap().len()
+            );
+            let expanded =
+                expand_payload(compressed, compression.content_type(), &limits).expect("expand");
+            assert_eq!(expanded, payload);
+        }
+
+        let plain = json!({ "callsign": "ALPHA-1" });
+        assert_eq!(
+            expand_payload(plain.clone(), CONTENT_TYPE_MSGPACK, &limits).unwrap(),
+            plain
+        );
+        let err = expand_payload(plain, Compression::Zstd.content_type(), &limits)
+            .expect_err("not base64");
+        assert!(matches!(err, CodecError::MalformedCompressed));
+
+        let compressed = compress_payload(&payload, Compression::Zstd).unwrap();
+        let tight = CodecLimits {
+            max_bytes: 1024,
+            ..limits
+        };
+        let err = expand_payload(compressed, Compression::Zstd.content_type(), &tight)
+            .expect_err("bomb guard");
+        assert!(matches!(err, CodecError::DecompressedTooLarge { limit: 1024 }));
+    }
+
+    #[test]
+    fn small_payloads_stay_uncompressed() {
+        let payload = json!({ "callsign": "ALPHA-1" });
+        let encoded =
+            encode_canonical_compressed(&payload, Compression::Zstd, 4096).expect("encode");
+        assert_eq!(encoded.content_type, CONTENT_TYPE_MSGPACK);
+        assert_eq!(encoded.bytes, encode_canonical(&payload).unwrap());
+    }
+
+    #[test]
+    fn decompression_bomb_is_rejected() {
+        let payload = large_payload();
+        let encoded =
+            encode_canonical_compressed(&payload, Compression::Gzip, 0).expect("encode");
+        let err = decode_canonical_compressed::<Value>(&encoded.bytes, &encoded.content_type, 1024)
+            .expect_err("bomb guard");
+        assert!(matches!(err, CodecError::DecompressedTooLarge { limit: 1024 }));
+    }
+
+    #[test]
+    fn deeply_nested_arrays_fail_on_depth() {
+        let mut bytes = vec![0x91; 10_000];
+        bytes.push(0xc0);
+
+        let (result, peak) = peak_allocation(|| {
+            decode_canonical_with_limits::<Value>(&bytes, &CodecLimits::default())
+        });
+        assert!(matches!(
+            result,
+            Err(CodecError::LimitExceeded {
+                which: "depth",
+                limit: 64,
+                actual: 65,
+            })
+        ));
+        assert!(peak < PEAK_BUDGET, "peak allocation {peak}");
+    }
+
+    #[test]
+    fn huge_strings_fail_before_allocation() {
+        let len = 50 * 1024 * 1024;
+        let mut bytes = vec![0xdb];
+        bytes.extend_from_slice(&(len as u32).to_be_bytes());
+        bytes.resize(bytes.len() + len, b'a');
+
+        assert_limit(
+            decode_canonical_with_limits(&bytes, &CodecLimits::default()),
+            "bytes",
+        );
+        let limits = CodecLimits {
+            max_bytes: usize::MAX,
+            ..CodecLimits::default()
+        };
+        let (result, peak) = peak_allocation(|| decode_canonical_with_limits(&bytes, &limits));
+        assert_limit(result, "string_len");
+        assert!(peak < PEAK_BUDGET, "peak allocation {peak}");
+    }
+
+    #[test]
+    fn maps_with_a_million_keys_fail_on_entries() {
+        let entries: u32 = 1_000_000;
+        let mut bytes = vec![0xdf];
+        bytes.extend_from_slice(&entries.to_be_bytes());
+        for index in 0..entries {
+            let key = format!("k{index}");
+            bytes.push(0xa0 | key.len() as u8);
+            bytes.extend_from_slice(key.as_bytes());
+            bytes.push(0xc0);
+        }
+        let limits = CodecLimits {
+            max_bytes: usize::MAX,
+            ..CodecLimits::default()
+        };
+
+        let (result, peak) = peak_allocation(|| decode_canonical_with_limits(&bytes, &limits));
+        assert_limit(result, "map_entries");
+        assert!(peak < PEAK_BUDGET, "peak allocation {peak}");
+
+        let small = encode_canonical(&json!({ "b": [1, 2, { "c": "d" }], "a": null })).unwrap();
+        let decoded: Value = decode_canonical_with_limits(&small, &CodecLimits::default()).unwrap();
+        assert_eq!(decoded, json!({ "a": null, "b": [1, 2, { "c": "d" }] }));
+        let shallow = CodecLimits {
+            max_depth: 1,
+            ..CodecLimits::default()
+        };
+        assert!(matches!(
+            shallow.check_value(&decoded),
+            Err(CodecError::LimitExceeded { which: "depth", .. })
+        ));
+    }
+
+    fn command(meta: Option<EnvelopeMeta>) -> MeshCommandEnvelope<Value> {
+        MeshCommandEnvelope {
+            message_id: "01900000-0000-7000-8000-000000000000".to_string(),
+            operation: "event.create".to_string(),
+            sent_at: "2026-01-01T00:00:00Z".parse().unwrap(),
+            source_identity: IdentityHash::parse("00112233445566778899aabbccddeeff").unwrap(),
+            destination_identity: IdentityHash::parse("0f1e2d3c4b5a69788796a5b4c3d2e1f0")
+                .unwrap(),
+            content_type: CONTENT_TYPE_MSGPACK.to_string(),
+            payload: json!({ "uid": "evt-1" }),
+            ttl_ms: None,
+            transport_hint: None,
+            trace: Vec::new(),
+            trace_truncated: false,
+            meta,
+        }
+    }
+
+    #[test]
+    fn metadata_block_is_encoded_only_when_present() {
+        let bare = command(None);
+        let encoded = encode_canonical(&bare).unwrap();
+        let mut legacy = serde_json::to_value(&bare).unwrap();
+        assert!(legacy.get("meta").is_none());
+        assert_eq!(encoded, encode_canonical(&legacy).unwrap());
+        let decoded: MeshCommandEnvelope<Value> = decode_canonical(&encoded).unwrap();
+        assert_eq!(decoded.meta, None);
+
+        let meta = EnvelopeMeta {
+            priority: Some(MetaPriority::Flash),
+            custom: [("shift".to_string(), "night".to_string())].into(),
+            ..EnvelopeMeta::default()
+        };
+        let block = encode_canonical(&meta).unwrap();
+        let hex: String = block.iter().map(|byte| format!("{byte:02x}")).collect();
+        assert_eq!(
+            hex,
+            "82a6637573746f6d81a57368696674a56e69676874a87072696f72697479a5666c617368"
+        );
+        let with_meta = encode_canonical(&command(Some(meta.clone()))).unwrap();
+        assert_eq!(with_meta.len(), encoded.len() + "meta".len() + 1 + block.len());
+        legacy["meta"] = serde_json::to_value(&meta).unwrap();
+        assert_eq!(with_meta, encode_canonical(&legacy).unwrap());
+        let decoded: MeshCommandEnvelope<Value> = decode_canonical(&with_meta).unwrap();
+        assert_eq!(decoded.meta, Some(meta));
+    }
 }
@@ -1,12 +1,28 @@
-﻿use chrono::{DateTime, Utc};
+﻿use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
+use crate::identity::IdentityHash;
+
 pub type MessageId = String;
 pub type CorrelationId = String;
-pub type IdentityHash = String;
 pub type OperationName = String;
 pub type EventName = String;
 
+// An event whose payload is a `MeshResultEnvelope` that arrived after its command was handed to
+// store-and-forward, rather than in reply to the send.
+pub const DELAYED_RESULT_EVENT: &str = "result.delayed";
+
+// Limits the contract's `EnvelopeMeta` schema sets on the metadata block.
+pub const META_MAX_ID_LEN: usize = 128;
+pub const META_MAX_CUSTOM_ENTRIES: usize = 16;
+pub const META_MAX_CUSTOM_KEY_LEN: usize = 64;
+pub const META_MAX_CUSTOM_VALUE_LEN: usize = 256;
+pub const META_MAX_LABELS: usize = 16;
+pub const META_MAX_LABEL_KEY_LEN: usize = 64;
+pub const META_MAX_LABEL_VALUE_LEN: usize = 128;
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
 pub enum TransferHint {
@@ -14,6 +30,167 @@ pub enum TransferHint {
     Lxmf,
 }
 
+// One node's handling of a traced envelope. The origin only has `forwarded_at`; the node that
+// answers stamps `forwarded_at` when its result leaves.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct HopRecord {
+    pub identity: IdentityHash,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub received_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub forwarded_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transport: Option<TransferHint>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum MetaPriority {
+    Routine,
+    Priority,
+    Immediate,
+    Flash,
+}
+
+impl std::str::FromStr for MetaPriority {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "routine" => Ok(Self::Routine),
+            "priority" => Ok(Self::Priority),
+            "immediate" => Ok(Self::Immediate),
+            "flash" => Ok(Self::Flash),
+            other => Err(format!(
+                "priority {other:?} is not one of routine, priority, immediate, flash"
+            )),
+        }
+    }
+}
+
+// Operational metadata about a command, kept apart from its business payload so handlers can
+// rely on it without agreeing on payload keys.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct EnvelopeMeta {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<MetaPriority>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_version: Option<String>,
+    // Canonical digest of the result the submitter already holds; a handler that supports it
+    // answers `not_modified` rather than resending a result with the same digest.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub if_result_digest_differs: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub custom: BTreeMap<String, String>,
+    // Operator labels for the job; a node applies them to the records it creates in answer.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+}
+
+impl EnvelopeMeta {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    // Checks the block against the contract's limits, naming the first field that breaks one.
+    pub fn validate(&self) -> Result<(), String> {
+        let ids = [
+            ("trace_id", &self.trace_id),
+            ("client_id", &self.client_id),
+            ("client_version", &self.client_version),
+        ];
+        for (field, value) in ids {
+            if let Some(value) = value {
+                check_meta_text(field, value, META_MAX_ID_LEN)?;
+            }
+        }
+        if let Some(digest) = &self.if_result_digest_differs {
+            let well_formed = digest.len() == 64
+                && digest.chars().all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c));
+            if !well_formed {
+                return Err("if_result_digest_differs must be 64 lowercase hex characters".into());
+            }
+        }
+        if self.custom.len() > META_MAX_CUSTOM_ENTRIES {
+            return Err(format!(
+                "custom has {} entries, more than {META_MAX_CUSTOM_ENTRIES}",
+                self.custom.len()
+            ));
+        }
+        for (key, value) in &self.custom {
+            let well_formed = key
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-');
+            if !well_formed {
+                return Err(format!(
+                    "custom key {key:?} may only hold lowercase letters, digits, '_' and '-'"
+                ));
+            }
+            check_meta_text("custom key", key, META_MAX_CUSTOM_KEY_LEN)?;
+            check_meta_text(&format!("custom.{key}"), value, META_MAX_CUSTOM_VALUE_LEN)?;
+        }
+        validate_labels(&self.labels)
+    }
+
+    // Fields set in `other` win; custom entries are merged key by key.
+    pub fn merged(mut self, other: EnvelopeMeta) -> Self {
+        self.priority = other.priority.or(self.priority);
+        self.trace_id = other.trace_id.or(self.trace_id);
+        self.client_id = other.client_id.or(self.client_id);
+        self.client_version = other.client_version.or(self.client_version);
+        self.if_result_digest_differs =
+            other.if_result_digest_differs.or(self.if_result_digest_differs);
+        self.custom.extend(other.custom);
+        self.labels.extend(other.labels);
+        self
+    }
+}
+
+// Checks a label set against the contract's limits: keys are lowercase letters, digits, '_',
+// '-' and '.', starting with a letter or digit, and values are short single-line text.
+pub fn validate_labels(labels: &BTreeMap<String, String>) -> Result<(), String> {
+    if labels.len() > META_MAX_LABELS {
+        return Err(format!(
+            "labels has {} entries, more than {META_MAX_LABELS}",
+            labels.len()
+        ));
+    }
+    for (key, value) in labels {
+        let well_formed = key.starts_with(|c: char| c.is_ascii_lowercase() || c.is_ascii_digit())
+            && key.chars().all(|c| {
+                c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '_' | '-' | '.')
+            });
+        if !well_formed {
+            return Err(format!(
+                "label key {key:?} must start with a lowercase letter or digit and may only hold \
+                 lowercase letters, digits, '_', '-' and '.'"
+            ));
+        }
+        check_meta_text("label key", key, META_MAX_LABEL_KEY_LEN)?;
+        check_meta_text(&format!("labels.{key}"), value, META_MAX_LABEL_VALUE_LEN)?;
+    }
+    Ok(())
+}
+
+fn check_meta_text(field: &str, value: &str, max_len: usize) -> Result<(), String> {
+    if value.is_empty() {
+        return Err(format!("{field} must not be empty"));
+    }
+    if value.len() > max_len {
+        return Err(format!("{field} is longer than {max_len} bytes"));
+    }
+    if value.chars().any(char::is_control) {
+        return Err(format!("{field} holds control characters"));
+    }
+    Ok(())
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MeshCommandEnvelope<T>
 where
@@ -28,6 +205,14 @@ where
     pub payload: T,
     pub ttl_ms: Option<u64>,
     pub transport_hint: Option<TransferHint>,
+    // Empty unless the submitter asked for tracing, so untraced envelopes stay the same size.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trace: Vec<HopRecord>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub trace_truncated: bool,
+    // Absent from envelopes sent before the block existed, and whenever nothing was set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub meta: Option<EnvelopeMeta>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -45,6 +230,10 @@ where
     pub payload: T,
     pub ttl_ms: Option<u64>,
     pub transport_hint: Option<TransferHint>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trace: Vec<HopRecord>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub trace_truncated: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
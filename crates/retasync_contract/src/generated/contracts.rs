@@ -1,5 +1,7 @@
 ﻿// Generated by cargo xtask codegen. Do not edit manually.
+// Source contract sha256: 1153cc1c445e141a29ce38f07711ee47664a00a94f071d94ed978e3add29ec33
 
+#[cfg(feature = "client")]
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 
@@ -33,19 +35,20 @@ pub enum EventOperation {
     TransferFailed,
 }
 
+#[cfg(feature = "client")]
 #[async_trait]
 pub trait CommandDispatch {
-    async fn emergency_action_message_create(&self, payload: EmergencyActionMessageCreatePayload) -> anyhow::Result<serde_json::Value>;
-    async fn emergency_action_message_list(&self, payload: EmergencyActionMessageListPayload) -> anyhow::Result<serde_json::Value>;
-    async fn emergency_action_message_put(&self, payload: EmergencyActionMessagePutPayload) -> anyhow::Result<serde_json::Value>;
-    async fn emergency_action_message_retrieve(&self, payload: EmergencyActionMessageRetrievePayload) -> anyhow::Result<serde_json::Value>;
-    async fn emergency_action_message_delete(&self, payload: EmergencyActionMessageDeletePayload) -> anyhow::Result<serde_json::Value>;
-    async fn event_create(&self, payload: EventCreatePayload) -> anyhow::Result<serde_json::Value>;
-    async fn event_list(&self, payload: EventListPayload) -> anyhow::Result<serde_json::Value>;
-    async fn event_put(&self, payload: EventPutPayload) -> anyhow::Result<serde_json::Value>;
-    async fn event_retrieve(&self, payload: EventRetrievePayload) -> anyhow::Result<serde_json::Value>;
-    async fn event_delete(&self, payload: EventDeletePayload) -> anyhow::Result<serde_json::Value>;
-    async fn transfer_upload(&self, payload: TransferUploadPayload) -> anyhow::Result<serde_json::Value>;
+    async fn emergency_action_message_create(&self, payload: EmergencyActionMessageCreatePayload, meta: Option<schemas::EnvelopeMeta>) -> anyhow::Result<serde_json::Value>;
+    async fn emergency_action_message_list(&self, payload: EmergencyActionMessageListPayload, meta: Option<schemas::EnvelopeMeta>) -> anyhow::Result<serde_json::Value>;
+    async fn emergency_action_message_put(&self, payload: EmergencyActionMessagePutPayload, meta: Option<schemas::EnvelopeMeta>) -> anyhow::Result<serde_json::Value>;
+    async fn emergency_action_message_retrieve(&self, payload: EmergencyActionMessageRetrievePayload, meta: Option<schemas::EnvelopeMeta>) -> anyhow::Result<serde_json::Value>;
+    async fn emergency_action_message_delete(&self, payload: EmergencyActionMessageDeletePayload, meta: Option<schemas::EnvelopeMeta>) -> anyhow::Result<serde_json::Value>;
+    async fn event_create(&self, payload: EventCreatePayload, meta: Option<schemas::EnvelopeMeta>) -> anyhow::Result<serde_json::Value>;
+    async fn event_list(&self, payload: EventListPayload, meta: Option<schemas::EnvelopeMeta>) -> anyhow::Result<serde_json::Value>;
+    async fn event_put(&self, payload: EventPutPayload, meta: Option<schemas::EnvelopeMeta>) -> anyhow::Result<serde_json::Value>;
+    async fn event_retrieve(&self, payload: EventRetrievePayload, meta: Option<schemas::EnvelopeMeta>) -> anyhow::Result<serde_json::Value>;
+    async fn event_delete(&self, payload: EventDeletePayload, meta: Option<schemas::EnvelopeMeta>) -> anyhow::Result<serde_json::Value>;
+    async fn transfer_upload(&self, payload: TransferUploadPayload, meta: Option<schemas::EnvelopeMeta>) -> anyhow::Result<serde_json::Value>;
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -103,67 +106,444 @@ pub struct TransferUploadPayload {
     pub body: serde_json::Value,
 }
 
+#[cfg(feature = "client")]
 #[async_trait]
 pub trait MeshClientBackend {
     async fn send_command(&self, operation: &str, payload: serde_json::Value) -> anyhow::Result<serde_json::Value>;
+    // Waits out retry advice before `MeshClient` sends a command again.
+    async fn wait(&self, delay: std::time::Duration);
 }
 
+#[cfg(feature = "client")]
 pub struct MeshClient<B> {
     backend: B,
+    auto_retry: Option<crate::AutoRetry>,
 }
 
+#[cfg(feature = "client")]
 impl<B> MeshClient<B> {
     pub fn new(backend: B) -> Self {
-        Self { backend }
+        Self { backend, auto_retry: None }
+    }
+
+    // Sends idempotent commands again when refused with retry advice.
+    pub fn with_auto_retry(mut self, auto_retry: crate::AutoRetry) -> Self {
+        self.auto_retry = Some(auto_retry);
+        self
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.backend
     }
 }
 
+#[cfg(feature = "client")]
 impl<B> MeshClient<B>
 where
     B: MeshClientBackend + Send + Sync,
 {
     pub async fn call_emergency_action_message_create(&self, payload: EmergencyActionMessageCreatePayload) -> anyhow::Result<serde_json::Value> {
-        self.backend.send_command("emergency_action_message.create", payload.body).await
+        self.send("emergency_action_message.create", true, payload.body).await
     }
 
     pub async fn call_emergency_action_message_list(&self, payload: EmergencyActionMessageListPayload) -> anyhow::Result<serde_json::Value> {
-        self.backend.send_command("emergency_action_message.list", payload.body).await
+        self.send("emergency_action_message.list", true, payload.body).await
     }
 
     pub async fn call_emergency_action_message_put(&self, payload: EmergencyActionMessagePutPayload) -> anyhow::Result<serde_json::Value> {
-        self.backend.send_command("emergency_action_message.put", payload.body).await
+        self.send("emergency_action_message.put", true, payload.body).await
     }
 
     pub async fn call_emergency_action_message_retrieve(&self, payload: EmergencyActionMessageRetrievePayload) -> anyhow::Result<serde_json::Value> {
-        self.backend.send_command("emergency_action_message.retrieve", payload.body).await
+        self.send("emergency_action_message.retrieve", true, payload.body).await
     }
 
     pub async fn call_emergency_action_message_delete(&self, payload: EmergencyActionMessageDeletePayload) -> anyhow::Result<serde_json::Value> {
-        self.backend.send_command("emergency_action_message.delete", payload.body).await
+        self.send("emergency_action_message.delete", true, payload.body).await
     }
 
     pub async fn call_event_create(&self, payload: EventCreatePayload) -> anyhow::Result<serde_json::Value> {
-        self.backend.send_command("event.create", payload.body).await
+        self.send("event.create", true, payload.body).await
     }
 
     pub async fn call_event_list(&self, payload: EventListPayload) -> anyhow::Result<serde_json::Value> {
-        self.backend.send_command("event.list", payload.body).await
+        self.send("event.list", true, payload.body).await
     }
 
     pub async fn call_event_put(&self, payload: EventPutPayload) -> anyhow::Result<serde_json::Value> {
-        self.backend.send_command("event.put", payload.body).await
+        self.send("event.put", true, payload.body).await
     }
 
     pub async fn call_event_retrieve(&self, payload: EventRetrievePayload) -> anyhow::Result<serde_json::Value> {
-        self.backend.send_command("event.retrieve", payload.body).await
+        self.send("event.retrieve", true, payload.body).await
     }
 
     pub async fn call_event_delete(&self, payload: EventDeletePayload) -> anyhow::Result<serde_json::Value> {
-        self.backend.send_command("event.delete", payload.body).await
+        self.send("event.delete", true, payload.body).await
     }
 
     pub async fn call_transfer_upload(&self, payload: TransferUploadPayload) -> anyhow::Result<serde_json::Value> {
-        self.backend.send_command("transfer.upload", payload.body).await
+        self.send("transfer.upload", true, payload.body).await
+    }
+
+    async fn send(&self, operation: &str, idempotent: bool, payload: serde_json::Value) -> anyhow::Result<serde_json::Value> {
+        let mut attempt = 1;
+        loop {
+            let sent = self.backend.send_command(operation, payload.clone());
+            let err = match sent.await {
+                Ok(result) => return Ok(result),
+                Err(err) => err,
+            };
+            let retry = self.auto_retry.filter(|_| idempotent);
+            let delay = retry.and_then(|retry| retry.delay(&err, attempt));
+            let Some(delay) = delay else {
+                return Err(err);
+            };
+            self.backend.wait(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+pub mod schemas {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct MeshCommandEnvelope {
+        pub message_id: String,
+        pub operation: String,
+        pub sent_at: String,
+        pub source_identity: String,
+        pub destination_identity: String,
+        pub content_type: String,
+        pub payload: serde_json::Value,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub ttl_ms: Option<u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub transport_hint: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub trace: Option<Vec<HopRecord>>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub trace_truncated: Option<bool>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub meta: Option<EnvelopeMeta>,
+    }
+
+    impl MeshCommandEnvelope {
+        pub fn example() -> Self {
+            Self {
+                message_id: String::new(),
+                operation: String::new(),
+                sent_at: String::new(),
+                source_identity: "a1b2c3d4e5f60718293a4b5c6d7e8f90".to_string(),
+                destination_identity: "0f1e2d3c4b5a69788796a5b4c3d2e1f0".to_string(),
+                content_type: "application/msgpack".to_string(),
+                payload: serde_json::Value::Null,
+                ttl_ms: None,
+                transport_hint: None,
+                trace: None,
+                trace_truncated: None,
+                meta: None,
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct MeshResultEnvelope {
+        pub message_id: String,
+        pub correlation_id: String,
+        pub operation: String,
+        pub sent_at: String,
+        pub source_identity: String,
+        pub destination_identity: String,
+        pub content_type: String,
+        pub payload: serde_json::Value,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub ttl_ms: Option<u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub transport_hint: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub trace: Option<Vec<HopRecord>>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub trace_truncated: Option<bool>,
     }
 
+    impl MeshResultEnvelope {
+        pub fn example() -> Self {
+            Self {
+                message_id: String::new(),
+                correlation_id: String::new(),
+                operation: String::new(),
+                sent_at: String::new(),
+                source_identity: "a1b2c3d4e5f60718293a4b5c6d7e8f90".to_string(),
+                destination_identity: "0f1e2d3c4b5a69788796a5b4c3d2e1f0".to_string(),
+                content_type: "application/msgpack".to_string(),
+                payload: serde_json::Value::Null,
+                ttl_ms: None,
+                transport_hint: None,
+                trace: None,
+                trace_truncated: None,
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct MeshEventEnvelope {
+        pub message_id: String,
+        pub event: String,
+        pub sent_at: String,
+        pub source_identity: String,
+        pub destination_identity: String,
+        pub content_type: String,
+        pub payload: serde_json::Value,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub ttl_ms: Option<u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub transport_hint: Option<String>,
+    }
+
+    impl MeshEventEnvelope {
+        pub fn example() -> Self {
+            Self {
+                message_id: String::new(),
+                event: String::new(),
+                sent_at: String::new(),
+                source_identity: "a1b2c3d4e5f60718293a4b5c6d7e8f90".to_string(),
+                destination_identity: "0f1e2d3c4b5a69788796a5b4c3d2e1f0".to_string(),
+                content_type: "application/msgpack".to_string(),
+                payload: serde_json::Value::Null,
+                ttl_ms: None,
+                transport_hint: None,
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct MeshTransferEnvelope {
+        pub message_id: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub correlation_id: Option<String>,
+        pub operation: String,
+        pub sent_at: String,
+        pub source_identity: String,
+        pub destination_identity: String,
+        pub content_type: String,
+        pub direction: String,
+        pub payload: serde_json::Value,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub ttl_ms: Option<u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub transport_hint: Option<String>,
+    }
+
+    impl MeshTransferEnvelope {
+        pub fn example() -> Self {
+            Self {
+                message_id: String::new(),
+                correlation_id: None,
+                operation: String::new(),
+                sent_at: String::new(),
+                source_identity: "a1b2c3d4e5f60718293a4b5c6d7e8f90".to_string(),
+                destination_identity: "0f1e2d3c4b5a69788796a5b4c3d2e1f0".to_string(),
+                content_type: "application/msgpack".to_string(),
+                direction: "upload".to_string(),
+                payload: serde_json::Value::Null,
+                ttl_ms: None,
+                transport_hint: None,
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct HopRecord {
+        pub identity: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub received_at: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub forwarded_at: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub transport: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub note: Option<String>,
+    }
+
+    impl HopRecord {
+        pub fn example() -> Self {
+            Self {
+                identity: "a1b2c3d4e5f60718293a4b5c6d7e8f90".to_string(),
+                received_at: None,
+                forwarded_at: None,
+                transport: None,
+                note: None,
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+    pub struct EnvelopeMeta {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub priority: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub trace_id: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub client_id: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub client_version: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub if_result_digest_differs: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub custom: Option<std::collections::BTreeMap<String, String>>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub labels: Option<std::collections::BTreeMap<String, String>>,
+    }
+
+    impl EnvelopeMeta {
+        pub fn example() -> Self {
+            Self {
+                priority: Some("flash".to_string()),
+                trace_id: Some("4bf92f3577b34da6a3ce929d0e0e4736".to_string()),
+                client_id: Some("tak-bridge".to_string()),
+                client_version: Some("2.3.0".to_string()),
+                if_result_digest_differs: Some("9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08".to_string()),
+                custom: Some([("shift".to_string(), "night".to_string())].into_iter().collect()),
+                labels: Some([("exercise".to_string(), "redfish-day2".to_string())].into_iter().collect()),
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct EmergencyActionMessage {
+        pub callsign: String,
+        #[serde(rename = "groupName", default, skip_serializing_if = "Option::is_none")]
+        pub group_name: Option<String>,
+        #[serde(rename = "commsMethod", default, skip_serializing_if = "Option::is_none")]
+        pub comms_method: Option<String>,
+        #[serde(rename = "medicalStatus", default = "default_emergency_action_message_medical_status")]
+        pub medical_status: String,
+        #[serde(rename = "commsStatus", default, skip_serializing_if = "Option::is_none")]
+        pub comms_status: Option<String>,
+        #[serde(rename = "preparednessStatus", default, skip_serializing_if = "Option::is_none")]
+        pub preparedness_status: Option<String>,
+        #[serde(rename = "mobilityStatus", default, skip_serializing_if = "Option::is_none")]
+        pub mobility_status: Option<String>,
+        #[serde(rename = "securityCapability", default, skip_serializing_if = "Option::is_none")]
+        pub security_capability: Option<String>,
+        #[serde(rename = "personnelStatus", default, skip_serializing_if = "Option::is_none")]
+        pub personnel_status: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub summary: Option<String>,
+    }
+
+    fn default_emergency_action_message_medical_status() -> String {
+        "green".to_string()
+    }
+
+    impl EmergencyActionMessage {
+        pub fn example() -> Self {
+            Self {
+                callsign: "ALPHA-1".to_string(),
+                group_name: Some("North Team".to_string()),
+                comms_method: None,
+                medical_status: default_emergency_action_message_medical_status(),
+                comms_status: None,
+                preparedness_status: None,
+                mobility_status: None,
+                security_capability: None,
+                personnel_status: None,
+                summary: None,
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct Event {
+        pub uid: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub title: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub detail: Option<String>,
+        #[serde(rename = "eventType", default, skip_serializing_if = "Option::is_none")]
+        pub event_type: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub location: Option<String>,
+        #[serde(rename = "occurredAt", default, skip_serializing_if = "Option::is_none")]
+        pub occurred_at: Option<String>,
+    }
+
+    impl Event {
+        pub fn example() -> Self {
+            Self {
+                uid: String::new(),
+                title: None,
+                detail: None,
+                event_type: None,
+                location: None,
+                occurred_at: None,
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct TransferUploadRequest {
+        pub destination_identity: String,
+        pub file_name: String,
+        pub media_type: String,
+        pub payload_base64: String,
+    }
+
+    impl TransferUploadRequest {
+        pub fn example() -> Self {
+            Self {
+                destination_identity: "0f1e2d3c4b5a69788796a5b4c3d2e1f0".to_string(),
+                file_name: String::new(),
+                media_type: String::new(),
+                payload_base64: String::new(),
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct TransferProgress {
+        pub transfer_id: String,
+        pub status: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub bytes_sent: Option<u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub bytes_total: Option<u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub reason: Option<String>,
+    }
+
+    impl TransferProgress {
+        pub fn example() -> Self {
+            Self {
+                transfer_id: String::new(),
+                status: "queued".to_string(),
+                bytes_sent: None,
+                bytes_total: None,
+                reason: None,
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct TransferCompletion {
+        pub transfer_id: String,
+        pub status: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub checksum_sha256: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub reason: Option<String>,
+    }
+
+    impl TransferCompletion {
+        pub fn example() -> Self {
+            Self {
+                transfer_id: String::new(),
+                status: "success".to_string(),
+                checksum_sha256: None,
+                reason: None,
+            }
+        }
+    }
 }
@@ -0,0 +1,294 @@
+﻿use std::borrow::Borrow;
+use std::fmt;
+use std::ops::Deref;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use thiserror::Error;
+
+// Reticulum truncates identity hashes to 128 bits.
+pub const IDENTITY_HASH_BYTES: usize = 16;
+// What a node without a configured identity sends from, and the destination a command without
+// one is broadcast to. Envelopes may carry them in place of a hash.
+pub const LOCAL_NODE_IDENTITY: &str = "local-node";
+pub const MESH_IDENTITY: &str = "mesh";
+
+static LENIENT: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IdentityValidation {
+    #[default]
+    Strict,
+    // Deprecated: keeps identifiers that are not hashes as they were given.
+    Lenient,
+}
+
+// The mode envelope fields are deserialized with; a node sets it once at startup from its config.
+pub fn set_identity_validation(mode: IdentityValidation) {
+    LENIENT.store(mode == IdentityValidation::Lenient, Ordering::Relaxed);
+}
+
+pub fn identity_validation() -> IdentityValidation {
+    if LENIENT.load(Ordering::Relaxed) {
+        IdentityValidation::Lenient
+    } else {
+        IdentityValidation::Strict
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum IdentityHashError {
+    #[error("identity hash is empty")]
+    Empty,
+    #[error("identity hash has non-hex character {character:?} at position {position}")]
+    NonHex { character: char, position: usize },
+    #[error("identity hash is {actual} hex characters; expected {expected}")]
+    WrongLength { expected: usize, actual: usize },
+}
+
+impl IdentityHashError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            IdentityHashError::Empty => "empty",
+            IdentityHashError::NonHex { .. } => "non_hex",
+            IdentityHashError::WrongLength { .. } => "wrong_length",
+        }
+    }
+}
+
+// A destination or source identity: lowercase hex of the expected length once trimmed, one of
+// the reserved names, or under lenient validation whatever the caller gave.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct IdentityHash(String);
+
+impl IdentityHash {
+    pub fn parse(value: &str) -> Result<Self, IdentityHashError> {
+        Self::parse_with_len(value, IDENTITY_HASH_BYTES)
+    }
+
+    pub fn parse_with_len(value: &str, bytes: usize) -> Result<Self, IdentityHashError> {
+        let normalized = Self::normalize(value);
+        if normalized.is_empty() {
+            return Err(IdentityHashError::Empty);
+        }
+        if let Some((position, character)) = normalized
+            .chars()
+            .enumerate()
+            .find(|(_, character)| !character.is_ascii_hexdigit())
+        {
+            return Err(IdentityHashError::NonHex {
+                character,
+                position,
+            });
+        }
+        if normalized.len() != bytes * 2 {
+            return Err(IdentityHashError::WrongLength {
+                expected: bytes * 2,
+                actual: normalized.len(),
+            });
+        }
+        Ok(Self(normalized))
+    }
+
+    // What envelopes and clients may name: a hash or a reserved name. Lenient validation still
+    // normalizes anything that parses, so hashes match whatever their case; only values that
+    // are not hashes are kept verbatim.
+    pub fn parse_as(value: &str, mode: IdentityValidation) -> Result<Self, IdentityHashError> {
+        match mode {
+            IdentityValidation::Strict => match Self::reserved(value) {
+                Some(reserved) => Ok(reserved),
+                None => Self::parse(value),
+            },
+            IdentityValidation::Lenient => Ok(Self::lenient(value)),
+        }
+    }
+
+    // Also for values that were validated when they entered the node, such as configured
+    // defaults.
+    pub fn lenient(value: &str) -> Self {
+        Self::reserved(value)
+            .or_else(|| Self::parse(value).ok())
+            .unwrap_or_else(|| Self(value.to_string()))
+    }
+
+    pub fn is_reserved(&self) -> bool {
+        self.0 == LOCAL_NODE_IDENTITY || self.0 == MESH_IDENTITY
+    }
+
+    fn reserved(value: &str) -> Option<Self> {
+        let normalized = Self::normalize(value);
+        let reserved = normalized == LOCAL_NODE_IDENTITY || normalized == MESH_IDENTITY;
+        reserved.then_some(Self(normalized))
+    }
+
+    pub fn normalize(value: &str) -> String {
+        value.trim().to_ascii_lowercase()
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn into_string(self) -> String {
+        self.0
+    }
+}
+
+impl fmt::Display for IdentityHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl FromStr for IdentityHash {
+    type Err = IdentityHashError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Self::parse_as(value, identity_validation())
+    }
+}
+
+impl Deref for IdentityHash {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for IdentityHash {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Borrow<str> for IdentityHash {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<IdentityHash> for String {
+    fn from(identity: IdentityHash) -> Self {
+        identity.0
+    }
+}
+
+impl PartialEq<str> for IdentityHash {
+    fn eq(&self, other: &str) -> bool {
+        self.0 == other
+    }
+}
+
+impl PartialEq<&str> for IdentityHash {
+    fn eq(&self, other: &&str) -> bool {
+        self.0 == *other
+    }
+}
+
+impl PartialEq<String> for IdentityHash {
+    fn eq(&self, other: &String) -> bool {
+        &self.0 == other
+    }
+}
+
+impl PartialEq<IdentityHash> for String {
+    fn eq(&self, other: &IdentityHash) -> bool {
+        self == &other.0
+    }
+}
+
+impl PartialEq<IdentityHash> for &str {
+    fn eq(&self, other: &IdentityHash) -> bool {
+        *self == other.0
+    }
+}
+
+impl Serialize for IdentityHash {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for IdentityHash {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        Self::parse_as(&value, identity_validation()).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{IdentityHash, IdentityHashError, IdentityValidation};
+
+    #[test]
+    fn constructor_normalizes_or_names_the_defect() {
+        let hash = "a1b2c3d4e5f60718293a4b5c6d7e8f90";
+        let cases = [
+            (hash, Ok(hash)),
+            ("  A1B2C3D4E5F60718293A4B5C6D7E8F90\n", Ok(hash)),
+            (
+                "mesh",
+                Err(IdentityHashError::NonHex {
+                    character: 'm',
+                    position: 0,
+                }),
+            ),
+            ("", Err(IdentityHashError::Empty)),
+            ("   ", Err(IdentityHashError::Empty)),
+            (
+                "Bob's laptop",
+                Err(IdentityHashError::NonHex {
+                    character: 'o',
+                    position: 1,
+                }),
+            ),
+            (
+                "a1b2",
+                Err(IdentityHashError::WrongLength {
+                    expected: 32,
+                    actual: 4,
+                }),
+            ),
+            (
+                "a1b2c3d4e5f60718293a4b5c6d7e8f9012",
+                Err(IdentityHashError::WrongLength {
+                    expected: 32,
+                    actual: 34,
+                }),
+            ),
+        ];
+        for (input, expected) in cases {
+            let parsed = IdentityHash::parse(input);
+            assert_eq!(parsed.as_ref().map(IdentityHash::as_str), expected.as_ref().map(|s| *s));
+        }
+        assert_eq!(IdentityHash::parse("Bob's laptop").unwrap_err().code(), "non_hex");
+        assert_eq!(IdentityHash::parse_with_len("A1B2", 2).unwrap(), "a1b2");
+        let mesh = IdentityHash::parse_as(" MESH", IdentityValidation::Strict).unwrap();
+        assert!(mesh.is_reserved());
+        assert_eq!(mesh, "mesh");
+    }
+
+    #[test]
+    fn lenient_validation_keeps_non_hashes_verbatim() {
+        let kept = IdentityHash::parse_as("Bob's laptop", IdentityValidation::Lenient).unwrap();
+        assert_eq!(kept, "Bob's laptop");
+        let hash = IdentityHash::parse_as(" AB", IdentityValidation::Lenient).unwrap();
+        assert_eq!(hash, " AB");
+        let upper = "A1B2C3D4E5F60718293A4B5C6D7E8F90";
+        let normalized = IdentityHash::parse_as(upper, IdentityValidation::Lenient).unwrap();
+        assert_eq!(normalized, upper.to_ascii_lowercase());
+    }
+
+    #[test]
+    fn envelope_fields_are_validated_on_deserialize() {
+        let parsed: IdentityHash =
+            serde_json::from_str("\"A1B2C3D4E5F60718293A4B5C6D7E8F90\"").unwrap();
+        assert_eq!(parsed, "a1b2c3d4e5f60718293a4b5c6d7e8f90");
+        assert_eq!(serde_json::to_string(&parsed).unwrap(), format!("\"{parsed}\""));
+        let error = serde_json::from_str::<IdentityHash>("\"\"").unwrap_err();
+        assert!(error.to_string().contains("empty"));
+    }
+}
@@ -1,10 +1,51 @@
-﻿pub mod codec;
+﻿pub mod bundle;
+pub mod codec;
 pub mod envelope;
 pub mod generated;
+pub mod identity;
+pub mod partial;
+pub mod receipt;
+#[cfg(feature = "registry")]
+pub mod registry;
+pub mod retry;
+pub mod schema;
+pub mod sealed;
 
-pub use codec::{decode_canonical, encode_canonical, CodecError};
+pub use bundle::{Bundle, BundleEntry, BundleError, BUNDLE_FORMAT_VERSION, BUNDLE_MEDIA_TYPE};
+pub use codec::{
+    canonical_digest, compress_payload, decode_canonical, decode_canonical_compressed,
+    decode_canonical_compressed_with_limits, decode_canonical_with_limits, encode_canonical,
+    encode_canonical_compressed, expand_payload, CodecError, CodecLimits, Compression,
+    EncodedPayload, CONTENT_TYPE_MSGPACK, CONTENT_TYPE_SEALED, DEFAULT_COMPRESSION_THRESHOLD,
+    DEFAULT_MAX_DECOMPRESSED_SIZE,
+};
 pub use envelope::{
-    CorrelationId, EventName, IdentityHash, MessageId, MeshCommandEnvelope, MeshEventEnvelope,
-    MeshResultEnvelope, MeshTransferEnvelope, OperationName, TransferDirection, TransferHint,
+    validate_labels, CorrelationId, EnvelopeMeta, EventName, HopRecord, MessageId,
+    MeshCommandEnvelope, MeshEventEnvelope, MeshResultEnvelope, MeshTransferEnvelope,
+    MetaPriority, OperationName, TransferDirection, TransferHint, DELAYED_RESULT_EVENT,
+    META_MAX_CUSTOM_ENTRIES, META_MAX_CUSTOM_KEY_LEN, META_MAX_CUSTOM_VALUE_LEN, META_MAX_ID_LEN,
+    META_MAX_LABELS, META_MAX_LABEL_KEY_LEN, META_MAX_LABEL_VALUE_LEN,
 };
 pub use generated::contracts::*;
+pub use identity::{
+    identity_validation, set_identity_validation, IdentityHash, IdentityHashError,
+    IdentityValidation, IDENTITY_HASH_BYTES, LOCAL_NODE_IDENTITY, MESH_IDENTITY,
+};
+pub use partial::{PartialResult, PartialResultSequence, SequenceError, PARTIAL_RESULT_EVENT};
+pub use receipt::{
+    receipt_public_key, DeliveryReceipt, ReceiptError, DELIVERY_RECEIPT_EVENT,
+    RECEIPT_FORMAT_VERSION, RECEIPT_KEY_LEN, RECEIPT_SIGNING_CONTEXT,
+};
+#[cfg(feature = "registry")]
+pub use registry::{
+    ChannelStyle, ContractError, ContractRegistry, DeliveryPolicy, Deprecation, ALIASES_EXTENSION,
+    DELIVERY_EXTENSION, PAYLOADS_EXTENSION, RESULTS_EXTENSION, ROLES_EXTENSION, SUNSET_EXTENSION,
+};
+#[cfg(feature = "client")]
+pub use retry::AutoRetry;
+pub use retry::{RetryAdvice, RetryScope, Throttled, RETRY_ADVICE_FIELD};
+pub use schema::{PayloadSchema, SchemaViolation, SCHEMA_REF_PREFIX};
+pub use sealed::{
+    open_payload, seal_payload, seal_payload_with_nonce, sealed_sender_key, sealing_public_key,
+    SealError, SEALED_FORMAT_VERSION, SEALED_KEY_LEN,
+};
@@ -0,0 +1,145 @@
+﻿use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use thiserror::Error;
+
+use crate::envelope::CorrelationId;
+
+pub const PARTIAL_RESULT_EVENT: &str = "result.partial";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PartialResult {
+    pub correlation_id: CorrelationId,
+    pub sequence: u32,
+    #[serde(rename = "final", default)]
+    pub is_final: bool,
+    pub data: Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum SequenceError {
+    #[error("duplicate partial result sequence {0}")]
+    Duplicate(u32),
+    #[error("partial result sequence {sequence} arrived after final part {final_sequence}")]
+    BeyondFinal { sequence: u32, final_sequence: u32 },
+    #[error("partial result stream is missing parts {0:?}")]
+    Incomplete(Vec<u32>),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PartialResultSequence {
+    parts: BTreeMap<u32, Value>,
+    final_sequence: Option<u32>,
+}
+
+impl PartialResultSequence {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, part: PartialResult) -> Result<(), SequenceError> {
+        if self.parts.contains_key(&part.sequence) {
+            return Err(SequenceError::Duplicate(part.sequence));
+        }
+        if let Some(final_sequence) = self.final_sequence {
+            if part.sequence > final_sequence {
+                return Err(SequenceError::BeyondFinal {
+                    sequence: part.sequence,
+                    final_sequence,
+                });
+            }
+        }
+        if part.is_final {
+            self.final_sequence = Some(part.sequence);
+        }
+        self.parts.insert(part.sequence, part.data);
+        Ok(())
+    }
+
+    pub fn missing(&self) -> Vec<u32> {
+        let last = self
+            .final_sequence
+            .or_else(|| self.parts.keys().next_back().copied())
+            .unwrap_or(0);
+        (0..=last)
+            .filter(|sequence| !self.parts.contains_key(sequence))
+            .collect()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.final_sequence.is_some() && self.missing().is_empty()
+    }
+
+    // Pages that all carry an `items` array are concatenated; anything else is
+    // returned as the ordered list of raw parts.
+    pub fn assemble(&self) -> Result<Value, SequenceError> {
+        if !self.is_complete() {
+            let mut missing = self.missing();
+            if self.final_sequence.is_none() {
+                missing.push(self.parts.len() as u32);
+            }
+            return Err(SequenceError::Incomplete(missing));
+        }
+
+        let pages: Vec<&Value> = self.parts.values().collect();
+        let item_pages: Option<Vec<&Vec<Value>>> = pages
+            .iter()
+            .map(|page| page.get("items").and_then(Value::as_array))
+            .collect();
+        Ok(match item_pages {
+            Some(item_pages) => serde_json::json!({
+                "items": item_pages.into_iter().flatten().cloned().collect::<Vec<_>>()
+            }),
+            None => serde_json::json!({ "parts": pages }),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PartialResult, PartialResultSequence, SequenceError};
+    use serde_json::json;
+
+    fn part(sequence: u32, is_final: bool, items: &[u32]) -> PartialResult {
+        PartialResult {
+            correlation_id: "cmd-1".to_string(),
+            sequence,
+            is_final,
+            data: json!({ "items": items }),
+        }
+    }
+
+    #[test]
+    fn out_of_order_parts_assemble_in_sequence() {
+        let mut sequence = PartialResultSequence::new();
+        sequence.insert(part(2, true, &[5])).unwrap();
+        sequence.insert(part(0, false, &[1, 2])).unwrap();
+        assert_eq!(sequence.missing(), vec![1]);
+        assert!(matches!(
+            sequence.assemble(),
+            Err(SequenceError::Incomplete(missing)) if missing == vec![1]
+        ));
+
+        sequence.insert(part(1, false, &[3, 4])).unwrap();
+        assert_eq!(
+            sequence.assemble().unwrap(),
+            json!({ "items": [1, 2, 3, 4, 5] })
+        );
+    }
+
+    #[test]
+    fn duplicates_and_parts_past_final_are_rejected() {
+        let mut sequence = PartialResultSequence::new();
+        sequence.insert(part(0, false, &[1])).unwrap();
+        assert_eq!(
+            sequence.insert(part(0, false, &[1])),
+            Err(SequenceError::Duplicate(0))
+        );
+        sequence.insert(part(1, true, &[2])).unwrap();
+        assert!(matches!(
+            sequence.insert(part(2, false, &[3])),
+            Err(SequenceError::BeyondFinal { .. })
+        ));
+    }
+}
@@ -0,0 +1,592 @@
+﻿use std::collections::{BTreeMap, BTreeSet};
+
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use thiserror::Error;
+
+use crate::schema::PayloadSchema;
+
+pub const SUNSET_EXTENSION: &str = "x-retasync-sunset";
+pub const ALIASES_EXTENSION: &str = "x-retasync-aliases";
+pub const DELIVERY_EXTENSION: &str = "x-retasync-delivery";
+pub const RESULTS_EXTENSION: &str = "x-retasync-results";
+pub const PAYLOADS_EXTENSION: &str = "x-retasync-payloads";
+pub const ROLES_EXTENSION: &str = "x-retasync-roles";
+
+#[derive(Debug, Error)]
+pub enum ContractError {
+    #[error("invalid AsyncAPI YAML: {0}")]
+    Yaml(#[from] serde_yaml::Error),
+    #[error("operation {operation} has invalid {SUNSET_EXTENSION} date {value:?}")]
+    InvalidSunset { operation: String, value: String },
+    #[error("{ALIASES_EXTENSION} has a cycle: {0}")]
+    AliasCycle(String),
+    #[error("{ALIASES_EXTENSION} renames {0}, which is still declared as a command")]
+    AliasShadowsCommand(String),
+    #[error("{DELIVERY_EXTENSION} for {operation}: {reason}")]
+    InvalidDelivery { operation: String, reason: String },
+    #[error("x-retasync.channels names {0}, which is not declared")]
+    UnknownChannelOperation(String),
+    #[error("{RESULTS_EXTENSION} for {operation}: {reason}")]
+    InvalidResult { operation: String, reason: String },
+    #[error("{PAYLOADS_EXTENSION} for {operation}: components.schemas has no {schema}")]
+    InvalidPayload { operation: String, schema: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Deprecation {
+    pub operation: String,
+    pub sunset: Option<NaiveDate>,
+}
+
+impl Deprecation {
+    pub fn is_sunset(&self, now: DateTime<Utc>) -> bool {
+        self.sunset_at().is_some_and(|sunset| now >= sunset)
+    }
+
+    pub fn sunset_at(&self) -> Option<DateTime<Utc>> {
+        self.sunset
+            .and_then(|date| date.and_hms_opt(0, 0, 0))
+            .map(|at| at.and_utc())
+    }
+
+    // RFC 8594 wants an IMF-fixdate in the `Sunset` header.
+    pub fn sunset_http_date(&self) -> Option<String> {
+        self.sunset_at()
+            .map(|at| at.format("%a, %d %b %Y %H:%M:%S GMT").to_string())
+    }
+}
+
+// Delivery defaults the contract owner declares for one command; unset fields fall back to the
+// node's own `[delivery]` settings.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeliveryPolicy {
+    pub max_attempts: Option<u32>,
+    pub backoff_ms: Option<u64>,
+    pub timeout_ms: Option<u64>,
+    pub idempotent: Option<bool>,
+    // Whether an unreachable peer may be handed to LXMF store-and-forward.
+    #[serde(default)]
+    pub store_and_forward: Option<bool>,
+}
+
+impl DeliveryPolicy {
+    // Only an explicit `idempotent: false` forbids retries.
+    pub fn allows_retry(&self) -> bool {
+        self.idempotent != Some(false)
+    }
+}
+
+// How the contract lays commands, results and events out over channels.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ChannelStyle {
+    // `commands/{operation}`, `results/{operation}` and `events/{event}`.
+    #[default]
+    Generic,
+    // `<entity>/commands`, `<entity>/results` and `<entity>/events`.
+    PerEntity,
+}
+
+impl ChannelStyle {
+    pub fn command_address(self, operation: &str) -> String {
+        match self {
+            ChannelStyle::Generic => format!("commands/{operation}"),
+            ChannelStyle::PerEntity => format!("{}/commands", entity_of(operation)),
+        }
+    }
+
+    pub fn result_address(self, operation: &str) -> String {
+        match self {
+            ChannelStyle::Generic => format!("results/{operation}"),
+            ChannelStyle::PerEntity => format!("{}/results", entity_of(operation)),
+        }
+    }
+
+    pub fn event_address(self, event: &str) -> String {
+        match self {
+            ChannelStyle::Generic => format!("events/{event}"),
+            ChannelStyle::PerEntity => format!("{}/events", entity_of(event)),
+        }
+    }
+}
+
+fn entity_of(operation: &str) -> &str {
+    operation
+        .rsplit_once('.')
+        .map_or(operation, |(entity, _)| entity)
+}
+
+// Operations and deprecation metadata read from the `x-retasync` block of the contract.
+#[derive(Debug, Clone, Default)]
+pub struct ContractRegistry {
+    asyncapi: Option<String>,
+    version: Option<String>,
+    commands: BTreeSet<String>,
+    events: BTreeSet<String>,
+    deprecations: BTreeMap<String, Deprecation>,
+    aliases: BTreeMap<String, String>,
+    delivery: BTreeMap<String, DeliveryPolicy>,
+    channel_style: ChannelStyle,
+    command_channels: BTreeMap<String, String>,
+    event_channels: BTreeMap<String, String>,
+    schemas: Map<String, Value>,
+    // The component schema each command's result payload must match.
+    results: BTreeMap<String, String>,
+    // The component schema an inbound command's payload must match before a handler sees it.
+    payloads: BTreeMap<String, String>,
+    // Allowlist roles a sender needs for the node to answer the operation.
+    roles: BTreeMap<String, Vec<String>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ContractDoc {
+    asyncapi: Option<String>,
+    #[serde(default)]
+    info: InfoBlock,
+    #[serde(default)]
+    components: ComponentsBlock,
+    #[serde(rename = "x-retasync", default)]
+    retasync: RetasyncBlock,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ComponentsBlock {
+    #[serde(default)]
+    schemas: Map<String, Value>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct InfoBlock {
+    version: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct RetasyncBlock {
+    #[serde(default)]
+    operations: OperationsBlock,
+    #[serde(default)]
+    channel_style: ChannelStyle,
+    #[serde(default)]
+    channels: ChannelsBlock,
+}
+
+// Addresses the converter recorded per command and event; missing ones follow the style.
+#[derive(Debug, Default, Deserialize)]
+struct ChannelsBlock {
+    #[serde(default)]
+    commands: BTreeMap<String, String>,
+    #[serde(default)]
+    events: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct OperationsBlock {
+    #[serde(default)]
+    commands: Vec<String>,
+    #[serde(default)]
+    events: Vec<String>,
+    #[serde(default)]
+    deprecated: BTreeMap<String, DeprecationBlock>,
+    #[serde(rename = "x-retasync-aliases", default)]
+    aliases: BTreeMap<String, String>,
+    #[serde(rename = "x-retasync-delivery", default)]
+    delivery: BTreeMap<String, DeliveryPolicy>,
+    #[serde(rename = "x-retasync-results", default)]
+    results: BTreeMap<String, String>,
+    #[serde(rename = "x-retasync-payloads", default)]
+    payloads: BTreeMap<String, String>,
+    #[serde(rename = "x-retasync-roles", default)]
+    roles: BTreeMap<String, Vec<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DeprecationBlock {
+    #[serde(default = "default_deprecated")]
+    deprecated: bool,
+    #[serde(rename = "x-retasync-sunset")]
+    sunset: Option<String>,
+}
+
+fn default_deprecated() -> bool {
+    true
+}
+
+impl ContractRegistry {
+    pub fn from_yaml(contract_doc: &str) -> Result<Self, ContractError> {
+        let doc: ContractDoc = serde_yaml::from_str(contract_doc.trim_start_matches('\u{feff}'))?;
+        let operations = doc.retasync.operations;
+        let channels = doc.retasync.channels;
+
+        let mut deprecations = BTreeMap::new();
+        for (operation, block) in operations.deprecated {
+            if !block.deprecated {
+                continue;
+            }
+            let sunset = block
+                .sunset
+                .map(|value| {
+                    NaiveDate::parse_from_str(&value, "%Y-%m-%d").map_err(|_| {
+                        ContractError::InvalidSunset {
+                            operation: operation.clone(),
+                            value,
+                        }
+                    })
+                })
+                .transpose()?;
+            deprecations.insert(operation.clone(), Deprecation { operation, sunset });
+        }
+
+        let commands: BTreeSet<String> = operations.commands.into_iter().collect();
+        if let Some(alias) = operations.aliases.keys().find(|alias| commands.contains(*alias)) {
+            return Err(ContractError::AliasShadowsCommand(alias.clone()));
+        }
+        for (operation, policy) in &operations.delivery {
+            let reason = match (policy.max_attempts, policy.allows_retry()) {
+                (Some(0), _) => "max_attempts must be at least 1",
+                (Some(attempts), false) if attempts > 1 => {
+                    "a non-idempotent operation cannot be retried"
+                }
+                _ => continue,
+            };
+            return Err(ContractError::InvalidDelivery {
+                operation: operation.clone(),
+                reason: reason.to_string(),
+            });
+        }
+        let events: BTreeSet<String> = operations.events.into_iter().collect();
+        let undeclared = channels
+            .commands
+            .keys()
+            .find(|command| !commands.contains(*command))
+            .or_else(|| channels.events.keys().find(|event| !events.contains(*event)));
+        if let Some(operation) = undeclared {
+            return Err(ContractError::UnknownChannelOperation(operation.clone()));
+        }
+        let schemas = doc.components.schemas;
+        for (operation, schema) in &operations.results {
+            let reason = if !commands.contains(operation) {
+                "not a declared command".to_string()
+            } else if !schemas.contains_key(schema) {
+                format!("components.schemas has no {schema}")
+            } else {
+                continue;
+            };
+            return Err(ContractError::InvalidResult {
+                operation: operation.clone(),
+                reason,
+            });
+        }
+        // Built-in operations the node answers itself need not be declared commands.
+        if let Some((operation, schema)) = operations
+            .payloads
+            .iter()
+            .find(|(_, schema)| !schemas.contains_key(*schema))
+        {
+            return Err(ContractError::InvalidPayload {
+                operation: operation.clone(),
+                schema: schema.clone(),
+            });
+        }
+        Ok(Self {
+            asyncapi: doc.asyncapi,
+            version: doc.info.version,
+            commands,
+            events,
+            deprecations,
+            aliases: resolve_chains(&operations.aliases)?,
+            delivery: operations.delivery,
+            channel_style: doc.retasync.channel_style,
+            command_channels: channels.commands,
+            event_channels: channels.events,
+            schemas,
+            results: operations.results,
+            payloads: operations.payloads,
+            roles: operations.roles,
+        })
+    }
+
+    pub fn asyncapi_version(&self) -> Option<&str> {
+        self.asyncapi.as_deref()
+    }
+
+    pub fn version(&self) -> Option<&str> {
+        self.version.as_deref()
+    }
+
+    pub fn commands(&self) -> impl Iterator<Item = &str> {
+        self.commands.iter().map(String::as_str)
+    }
+
+    pub fn events(&self) -> impl Iterator<Item = &str> {
+        self.events.iter().map(String::as_str)
+    }
+
+    pub fn is_command(&self, operation: &str) -> bool {
+        self.commands.contains(operation)
+    }
+
+    pub fn deprecation(&self, operation: &str) -> Option<&Deprecation> {
+        self.deprecations.get(operation)
+    }
+
+    pub fn deprecations(&self) -> impl Iterator<Item = &Deprecation> {
+        self.deprecations.values()
+    }
+
+    // The current name for `operation` and whether `operation` was an old name for it.
+    pub fn resolve_alias<'a>(&'a self, operation: &'a str) -> (&'a str, bool) {
+        match self.aliases.get(operation) {
+            Some(canonical) => (canonical, true),
+            None => (operation, false),
+        }
+    }
+
+    pub fn delivery(&self, operation: &str) -> Option<&DeliveryPolicy> {
+        self.delivery.get(operation)
+    }
+
+    pub fn channel_style(&self) -> ChannelStyle {
+        self.channel_style
+    }
+
+    // The address `operation` is sent on: the one the contract records, else the style's.
+    pub fn command_channel(&self, operation: &str) -> String {
+        self.command_channels
+            .get(operation)
+            .cloned()
+            .unwrap_or_else(|| self.channel_style.command_address(operation))
+    }
+
+    pub fn result_channel(&self, operation: &str) -> String {
+        self.channel_style.result_address(operation)
+    }
+
+    pub fn event_channel(&self, event: &str) -> String {
+        self.event_channels
+            .get(event)
+            .cloned()
+            .unwrap_or_else(|| self.channel_style.event_address(event))
+    }
+
+    // The schema a result for `operation` has to match; none when the contract declares none.
+    pub fn result_schema(&self, operation: &str) -> Option<PayloadSchema<'_>> {
+        self.results
+            .get(operation)
+            .map(|name| PayloadSchema::new(name, &self.schemas))
+    }
+
+    // The schema an inbound `operation` payload has to match; none when the contract declares none.
+    pub fn payload_schema(&self, operation: &str) -> Option<PayloadSchema<'_>> {
+        self.payloads
+            .get(operation)
+            .map(|name| PayloadSchema::new(name, &self.schemas))
+    }
+
+    // The allowlist roles that may send `operation`; none when any allowlisted sender may.
+    pub fn required_roles(&self, operation: &str) -> Option<&[String]> {
+        self.roles.get(operation).map(Vec::as_slice)
+    }
+
+    pub fn aliases(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.aliases
+            .iter()
+            .map(|(alias, canonical)| (alias.as_str(), canonical.as_str()))
+    }
+}
+
+// Maps every alias straight to the end of its rename chain.
+fn resolve_chains(
+    renames: &BTreeMap<String, String>,
+) -> Result<BTreeMap<String, String>, ContractError> {
+    let mut resolved = BTreeMap::new();
+    for alias in renames.keys() {
+        let mut chain = vec![alias.as_str()];
+        let mut current = alias.as_str();
+        while let Some(next) = renames.get(current) {
+            let seen = chain.contains(&next.as_str());
+            chain.push(next);
+            if seen {
+                return Err(ContractError::AliasCycle(chain.join(" -> ")));
+            }
+            current = next;
+        }
+        resolved.insert(alias.clone(), current.to_string());
+    }
+    Ok(resolved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ChannelStyle, ContractError, ContractRegistry};
+    use chrono::{TimeZone, Utc};
+    use serde_json::json;
+
+    const DOC: &str = r#"
+asyncapi: 3.0.0
+info:
+  version: "1.2.0"
+x-retasync:
+  operations:
+    commands: [event.create, event.stream]
+    events: [event.created]
+    deprecated:
+      event.stream:
+        deprecated: true
+        x-retasync-sunset: "2026-09-01"
+      event.create:
+        deprecated: false
+"#;
+
+    #[test]
+    fn deprecations_carry_sunset_dates() {
+        let registry = ContractRegistry::from_yaml(DOC).unwrap();
+        assert_eq!(registry.version(), Some("1.2.0"));
+        assert!(registry.is_command("event.stream"));
+        assert!(registry.deprecation("event.create").is_none());
+
+        let deprecation = registry.deprecation("event.stream").unwrap();
+        assert_eq!(
+            deprecation.sunset_http_date().as_deref(),
+            Some("Tue, 01 Sep 2026 00:00:00 GMT")
+        );
+        assert!(!deprecation.is_sunset(Utc.with_ymd_and_hms(2026, 8, 31, 23, 59, 59).unwrap()));
+        assert!(deprecation.is_sunset(Utc.with_ymd_and_hms(2026, 9, 1, 0, 0, 0).unwrap()));
+
+        let invalid = DOC.replace("2026-09-01", "next week");
+        assert!(matches!(
+            ContractRegistry::from_yaml(&invalid),
+            Err(ContractError::InvalidSunset { .. })
+        ));
+    }
+
+    fn with_aliases(aliases: &str) -> String {
+        format!("{DOC}    x-retasync-aliases:\n{aliases}")
+    }
+
+    #[test]
+    fn alias_chains_resolve_to_the_current_name() {
+        let doc = with_aliases("      event.put: event.modify\n      event.modify: event.update\n");
+        let registry = ContractRegistry::from_yaml(&doc).unwrap();
+        assert_eq!(registry.resolve_alias("event.put"), ("event.update", true));
+        assert_eq!(registry.resolve_alias("event.modify"), ("event.update", true));
+        assert_eq!(registry.resolve_alias("event.create"), ("event.create", false));
+    }
+
+    #[test]
+    fn cyclic_or_shadowing_aliases_fail_to_load() {
+        let cyclic = with_aliases("      event.put: event.update\n      event.update: event.put\n");
+        let err = ContractRegistry::from_yaml(&cyclic).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "x-retasync-aliases has a cycle: event.put -> event.update -> event.put"
+        );
+
+        let shadowing = with_aliases("      event.create: event.update\n");
+        assert!(matches!(
+            ContractRegistry::from_yaml(&shadowing),
+            Err(ContractError::AliasShadowsCommand(alias)) if alias == "event.create"
+        ));
+    }
+
+    #[test]
+    fn delivery_policies_are_read_per_operation() {
+        let doc = format!(
+            "{DOC}    x-retasync-delivery:\n      event.create:\n        max_attempts: 5\n        \
+             backoff_ms: 200\n        store_and_forward: true\n      \
+             event.stream: {{ idempotent: false }}\n"
+        );
+        let registry = ContractRegistry::from_yaml(&doc).unwrap();
+        let create = registry.delivery("event.create").unwrap();
+        assert_eq!((create.max_attempts, create.backoff_ms), (Some(5), Some(200)));
+        assert!(create.allows_retry());
+        assert_eq!(create.store_and_forward, Some(true));
+        assert!(!registry.delivery("event.stream").unwrap().allows_retry());
+        assert!(registry.delivery("event.update").is_none());
+
+        let retried = doc.replace("idempotent: false", "idempotent: false, max_attempts: 2");
+        assert!(matches!(
+            ContractRegistry::from_yaml(&retried),
+            Err(ContractError::InvalidDelivery { operation, .. }) if operation == "event.stream"
+        ));
+    }
+
+    #[test]
+    fn result_schemas_are_read_per_command() {
+        let doc = format!(
+            "{DOC}    x-retasync-results:\n      event.create: EventCreateResult\n\
+             components:\n  schemas:\n    EventCreateResult:\n      type: object\n      \
+             required: [uid]\n"
+        );
+        let registry = ContractRegistry::from_yaml(&doc).unwrap();
+        let schema = registry.result_schema("event.create").unwrap();
+        assert_eq!(schema.name(), "EventCreateResult");
+        assert!(schema.validate(&json!({ "uid": "e-1" })).is_empty());
+        let violations = schema.validate(&json!({ "title": "no uid" }));
+        assert_eq!(violations[0].message, "missing required property `uid`");
+        assert!(registry.result_schema("event.stream").is_none());
+
+        let missing = doc.replace("    EventCreateResult:", "    EventResult:");
+        let err = ContractRegistry::from_yaml(&missing).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "x-retasync-results for event.create: components.schemas has no EventCreateResult"
+        );
+        let undeclared = doc.replace("event.create: EventCreate", "event.put: EventCreate");
+        assert!(matches!(
+            ContractRegistry::from_yaml(&undeclared),
+            Err(ContractError::InvalidResult { operation, .. }) if operation == "event.put"
+        ));
+    }
+
+    #[test]
+    fn payload_schemas_and_roles_are_read_per_operation() {
+        let doc = format!(
+            "{DOC}    x-retasync-payloads:\n      node.ping: PingPayload\n    \
+             x-retasync-roles:\n      event.create: [admin-peer]\n\
+             components:\n  schemas:\n    PingPayload:\n      type: object\n      \
+             additionalProperties: false\n"
+        );
+        let registry = ContractRegistry::from_yaml(&doc).unwrap();
+        let schema = registry.payload_schema("node.ping").unwrap();
+        assert!(schema.validate(&json!({})).is_empty());
+        assert!(!schema.validate(&json!({ "extra": 1 })).is_empty());
+        assert!(registry.payload_schema("event.create").is_none());
+        assert_eq!(registry.required_roles("event.create"), Some(&["admin-peer".to_string()][..]));
+        assert_eq!(registry.required_roles("event.stream"), None);
+
+        let missing = doc.replace("    PingPayload:", "    Ping:");
+        assert_eq!(
+            ContractRegistry::from_yaml(&missing).unwrap_err().to_string(),
+            "x-retasync-payloads for node.ping: components.schemas has no PingPayload"
+        );
+    }
+
+    #[test]
+    fn channel_addresses_follow_the_contract_style() {
+        let generic = ContractRegistry::from_yaml(DOC).unwrap();
+        assert_eq!(generic.channel_style(), ChannelStyle::Generic);
+        assert_eq!(generic.command_channel("event.create"), "commands/event.create");
+        assert_eq!(generic.event_channel("event.created"), "events/event.created");
+
+        let doc = DOC.replace(
+            "x-retasync:\n",
+            "x-retasync:\n  channel_style: per-entity\n  channels:\n    commands:\n      \
+             event.create: incidents/commands\n",
+        );
+        let per_entity = ContractRegistry::from_yaml(&doc).unwrap();
+        assert_eq!(per_entity.channel_style(), ChannelStyle::PerEntity);
+        assert_eq!(per_entity.command_channel("event.create"), "incidents/commands");
+        assert_eq!(per_entity.command_channel("event.stream"), "event/commands");
+        assert_eq!(per_entity.result_channel("event.stream"), "event/results");
+        assert_eq!(per_entity.event_channel("event.created"), "event/events");
+
+        let undeclared = doc.replace("event.create: incidents", "event.delete: incidents");
+        assert!(matches!(
+            ContractRegistry::from_yaml(&undeclared),
+            Err(ContractError::UnknownChannelOperation(operation)) if operation == "event.delete"
+        ));
+    }
+}
@@ -0,0 +1,225 @@
+﻿use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use thiserror::Error;
+
+// Where a node puts its advice in an error body, and in a job event it defers or fails.
+pub const RETRY_ADVICE_FIELD: &str = "retry";
+
+// What a refusal holds back: one client's budget, one destination's, one circuit, or the node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RetryScope {
+    Client,
+    Destination,
+    Circuit,
+    Node,
+}
+
+// When a throttled or temporarily refused request is worth sending again, read from the timer
+// that refused it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetryAdvice {
+    pub retry_after_ms: u64,
+    // The error code of the refusal, such as `rate_limited`.
+    pub reason: String,
+    pub scope: RetryScope,
+}
+
+impl RetryAdvice {
+    pub fn after(reason: &str, scope: RetryScope, delay: Duration) -> Self {
+        Self {
+            retry_after_ms: delay.as_millis().min(u128::from(u64::MAX)) as u64,
+            reason: reason.to_string(),
+            scope,
+        }
+    }
+
+    // Advice to come back at `at`; an instant already past advises retrying now.
+    pub fn until(reason: &str, scope: RetryScope, at: DateTime<Utc>, now: DateTime<Utc>) -> Self {
+        Self::after(reason, scope, (at - now).to_std().unwrap_or_default())
+    }
+
+    pub fn retry_after(&self) -> Duration {
+        Duration::from_millis(self.retry_after_ms)
+    }
+
+    // The `Retry-After` header value: whole seconds, rounded up.
+    pub fn retry_after_secs(&self) -> u64 {
+        self.retry_after_ms.div_ceil(1000)
+    }
+
+    // Reads the advice out of a `/v1` error body, or the `error` object of a `/v2` one.
+    pub fn from_body(body: &Value) -> Option<Self> {
+        let advice = body
+            .get(RETRY_ADVICE_FIELD)
+            .or_else(|| body.get("error")?.get(RETRY_ADVICE_FIELD))?;
+        serde_json::from_value(advice.clone()).ok()
+    }
+}
+
+// A request the node refused for now. Client backends return it, wrapped in their error, for
+// any answer that carries retry advice.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("{error} (HTTP {status}), retry in {}ms", advice.retry_after_ms)]
+pub struct Throttled {
+    pub status: u16,
+    pub error: String,
+    pub advice: RetryAdvice,
+}
+
+impl Throttled {
+    pub fn from_response(status: u16, body: &Value) -> Option<Self> {
+        let advice = RetryAdvice::from_body(body)?;
+        let error = match body.get("error") {
+            Some(Value::String(code)) => code.clone(),
+            Some(error) => error.get("code").and_then(Value::as_str)?.to_string(),
+            None => advice.reason.clone(),
+        };
+        Some(Self {
+            status,
+            error,
+            advice,
+        })
+    }
+
+    // The advice of a `Throttled` anywhere in an error's chain.
+    #[cfg(feature = "client")]
+    pub fn advice_of(err: &anyhow::Error) -> Option<&RetryAdvice> {
+        err.chain()
+            .find_map(|cause| cause.downcast_ref::<Throttled>())
+            .map(|throttled| &throttled.advice)
+    }
+}
+
+// Opt-in retries for `MeshClient`: an idempotent command refused with advice is sent again
+// once the advice has passed, up to `max_attempts` sends in all. Advice longer than
+// `max_delay` is handed back to the caller instead of waited out.
+#[cfg(feature = "client")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AutoRetry {
+    pub max_attempts: u32,
+    pub max_delay: Duration,
+}
+
+#[cfg(feature = "client")]
+impl Default for AutoRetry {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            max_delay: Duration::from_secs(60),
+        }
+    }
+}
+
+#[cfg(feature = "client")]
+impl AutoRetry {
+    // How long to wait before sending again after `attempt` sends failed with `err`, if at all.
+    pub fn delay(&self, err: &anyhow::Error, attempt: u32) -> Option<Duration> {
+        if attempt >= self.max_attempts {
+            return None;
+        }
+        let delay = Throttled::advice_of(err)?.retry_after();
+        (delay <= self.max_delay).then_some(delay)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn advice_rounds_the_header_up_and_reads_from_either_body() {
+        let delay = Duration::from_millis(1001);
+        let advice = RetryAdvice::after("rate_limited", RetryScope::Client, delay);
+        assert_eq!(advice.retry_after_secs(), 2);
+        let now_due = RetryAdvice::after("node_muted", RetryScope::Node, Duration::ZERO);
+        assert_eq!(now_due.retry_after_secs(), 0);
+        let now = Utc::now();
+        let at = now - chrono::Duration::seconds(5);
+        assert_eq!(RetryAdvice::until("node_muted", RetryScope::Node, at, now).retry_after_ms, 0);
+
+        let v1 = json!({ "error": "rate_limited", "retry": advice });
+        assert_eq!(RetryAdvice::from_body(&v1), Some(advice.clone()));
+        let v2 = json!({ "error": { "code": "rate_limited", "retry": advice } });
+        let throttled = Throttled::from_response(429, &v2).unwrap();
+        assert_eq!(throttled.error, "rate_limited");
+        assert_eq!(throttled.advice, advice);
+        assert!(Throttled::from_response(404, &json!({ "error": "job_not_found" })).is_none());
+    }
+
+    // Refuses the first `refusals` sends with the advice a rate-limited node answers with.
+    #[cfg(feature = "client")]
+    #[derive(Default)]
+    struct Refusing {
+        refusals: std::sync::Mutex<u32>,
+        sent: std::sync::Mutex<u32>,
+        waits: std::sync::Mutex<Vec<Duration>>,
+    }
+
+    #[cfg(feature = "client")]
+    #[async_trait::async_trait]
+    impl crate::MeshClientBackend for Refusing {
+        async fn send_command(&self, _operation: &str, payload: Value) -> anyhow::Result<Value> {
+            *self.sent.lock().unwrap() += 1;
+            let mut refusals = self.refusals.lock().unwrap();
+            if *refusals == 0 {
+                return Ok(payload);
+            }
+            *refusals -= 1;
+            let advice = RetryAdvice::after(
+                "rate_limited",
+                RetryScope::Client,
+                Duration::from_millis(1500),
+            );
+            let body = json!({ "error": "rate_limited", "retry": advice });
+            let throttled = Throttled::from_response(429, &body).unwrap();
+            Err(anyhow::Error::new(throttled).context("POST /v1/jobs/commands/event.create"))
+        }
+
+        async fn wait(&self, delay: Duration) {
+            self.waits.lock().unwrap().push(delay);
+        }
+    }
+
+    #[cfg(feature = "client")]
+    fn refusing(refusals: u32) -> Refusing {
+        Refusing {
+            refusals: std::sync::Mutex::new(refusals),
+            ..Refusing::default()
+        }
+    }
+
+    #[cfg(feature = "client")]
+    #[tokio::test]
+    async fn client_waits_out_the_advice_only_when_asked() {
+        use crate::{EventCreatePayload, MeshClient};
+        let payload = || EventCreatePayload { body: json!({ "uid": "evt-1" }) };
+
+        let client = MeshClient::new(refusing(1));
+        let err = client.call_event_create(payload()).await.unwrap_err();
+        let advice = Throttled::advice_of(&err).unwrap();
+        assert_eq!((advice.retry_after_ms, advice.scope), (1500, RetryScope::Client));
+
+        let client = MeshClient::new(refusing(2)).with_auto_retry(AutoRetry::default());
+        assert_eq!(client.call_event_create(payload()).await.unwrap(), json!({ "uid": "evt-1" }));
+        let backend = client.backend();
+        assert_eq!(*backend.sent.lock().unwrap(), 3);
+        assert_eq!(*backend.waits.lock().unwrap(), [Duration::from_millis(1500); 2]);
+
+        let client = MeshClient::new(refusing(5)).with_auto_retry(AutoRetry::default());
+        assert!(client.call_event_create(payload()).await.is_err());
+        assert_eq!(*client.backend().sent.lock().unwrap(), 3);
+
+        let impatient = AutoRetry {
+            max_attempts: 3,
+            max_delay: Duration::from_secs(1),
+        };
+        let client = MeshClient::new(refusing(1)).with_auto_retry(impatient);
+        assert!(client.call_event_create(payload()).await.is_err());
+        assert!(client.backend().waits.lock().unwrap().is_empty());
+    }
+}
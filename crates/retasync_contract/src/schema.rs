@@ -0,0 +1,329 @@
+﻿use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+pub const SCHEMA_REF_PREFIX: &str = "#/components/schemas/";
+
+// Deep enough for any contract we ship; a `$ref` loop stops here instead of overflowing.
+const MAX_SCHEMA_DEPTH: usize = 32;
+
+// One place a value breaks its schema. `pointer` is the JSON pointer of the offending value,
+// empty for the root.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SchemaViolation {
+    pub pointer: String,
+    pub message: String,
+}
+
+// A named component schema together with the schemas its `$ref`s point into.
+#[derive(Debug, Clone, Copy)]
+pub struct PayloadSchema<'a> {
+    name: &'a str,
+    schemas: &'a Map<String, Value>,
+}
+
+impl<'a> PayloadSchema<'a> {
+    pub fn new(name: &'a str, schemas: &'a Map<String, Value>) -> Self {
+        Self { name, schemas }
+    }
+
+    pub fn name(&self) -> &str {
+        self.name
+    }
+
+    // Every violation found, in document order; empty when `value` conforms. Covers `$ref`,
+    // `allOf`, `anyOf`, `oneOf`, `const`, `enum`, `type` (with `nullable`), `required`,
+    // `properties`, `additionalProperties`, `items` and the length, size and range bounds.
+    // `pattern` and `format` are not checked.
+    pub fn validate(&self, value: &Value) -> Vec<SchemaViolation> {
+        let mut violations = Vec::new();
+        let reference = Value::String(format!("{SCHEMA_REF_PREFIX}{}", self.name));
+        let root = Value::Object(Map::from_iter([("$ref".to_string(), reference)]));
+        self.check(&root, value, "", &mut violations, 0);
+        violations
+    }
+
+    fn check(
+        &self,
+        schema: &Value,
+        value: &Value,
+        pointer: &str,
+        violations: &mut Vec<SchemaViolation>,
+        depth: usize,
+    ) {
+        if depth > MAX_SCHEMA_DEPTH {
+            let message = format!("schema nests deeper than {MAX_SCHEMA_DEPTH} levels");
+            violations.push(violation(pointer, message));
+            return;
+        }
+        let Some(schema) = schema.as_object() else {
+            // `true` and `{}` accept anything, `false` nothing.
+            if schema == &Value::Bool(false) {
+                violations.push(violation(pointer, "no value is allowed here".to_string()));
+            }
+            return;
+        };
+        if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+            match reference
+                .strip_prefix(SCHEMA_REF_PREFIX)
+                .and_then(|name| self.schemas.get(name))
+            {
+                Some(target) => self.check(target, value, pointer, violations, depth + 1),
+                None => violations.push(violation(pointer, format!("unresolved $ref {reference}"))),
+            }
+            return;
+        }
+        if value.is_null() && schema.get("nullable") == Some(&Value::Bool(true)) {
+            return;
+        }
+
+        for member in schema.get("allOf").and_then(Value::as_array).into_iter().flatten() {
+            self.check(member, value, pointer, violations, depth + 1);
+        }
+        if let Some(members) = schema.get("anyOf").and_then(Value::as_array) {
+            if self.matching(members, value, pointer, depth) == 0 {
+                violations.push(violation(pointer, "matches no anyOf member".to_string()));
+            }
+        }
+        if let Some(members) = schema.get("oneOf").and_then(Value::as_array) {
+            match self.matching(members, value, pointer, depth) {
+                1 => {}
+                0 => violations.push(violation(pointer, "matches no oneOf member".to_string())),
+                count => violations.push(
+                    violation(pointer, format!("matches {count} oneOf members instead of one")),
+                ),
+            }
+        }
+        if let Some(expected) = schema.get("const").filter(|expected| *expected != value) {
+            violations.push(violation(pointer, format!("expected {expected}")));
+        }
+        if let Some(members) = schema.get("enum").and_then(Value::as_array) {
+            if !members.contains(value) {
+                let allowed = Value::Array(members.clone());
+                violations.push(violation(pointer, format!("{value} is not one of {allowed}")));
+            }
+        }
+
+        if let Some(expected) = schema.get("type") {
+            let allowed: Vec<&str> = match expected {
+                Value::String(name) => vec![name.as_str()],
+                Value::Array(names) => names.iter().filter_map(Value::as_str).collect(),
+                _ => Vec::new(),
+            };
+            if !allowed.is_empty() && !allowed.iter().any(|name| is_type(name, value)) {
+                let message = format!("expected {}, got {}", allowed.join(" or "), type_of(value));
+                violations.push(violation(pointer, message));
+                return;
+            }
+        }
+
+        match value {
+            Value::Object(object) => self.check_object(schema, object, pointer, violations, depth),
+            Value::Array(items) => {
+                if let Some(items_schema) = schema.get("items") {
+                    for (index, item) in items.iter().enumerate() {
+                        let item_pointer = format!("{pointer}/{index}");
+                        self.check(items_schema, item, &item_pointer, violations, depth + 1);
+                    }
+                }
+                let count = items.len() as u64;
+                if let Some(min) = bound(schema, "minItems").filter(|min| count < *min) {
+                    let message = format!("has {count} items, fewer than {min}");
+                    violations.push(violation(pointer, message));
+                }
+                if let Some(max) = bound(schema, "maxItems").filter(|max| count > *max) {
+                    let message = format!("has {count} items, more than {max}");
+                    violations.push(violation(pointer, message));
+                }
+            }
+            Value::String(text) => {
+                let length = text.chars().count() as u64;
+                if let Some(min) = bound(schema, "minLength").filter(|min| length < *min) {
+                    let message = format!("is shorter than {min} characters");
+                    violations.push(violation(pointer, message));
+                }
+                if let Some(max) = bound(schema, "maxLength").filter(|max| length > *max) {
+                    let message = format!("is longer than {max} characters");
+                    violations.push(violation(pointer, message));
+                }
+            }
+            Value::Number(number) => {
+                let number = number.as_f64().unwrap_or_default();
+                let minimum = schema.get("minimum").and_then(Value::as_f64);
+                if let Some(minimum) = minimum.filter(|minimum| number < *minimum) {
+                    violations.push(violation(pointer, format!("{number} is below {minimum}")));
+                }
+                let maximum = schema.get("maximum").and_then(Value::as_f64);
+                if let Some(maximum) = maximum.filter(|maximum| number > *maximum) {
+                    violations.push(violation(pointer, format!("{number} is above {maximum}")));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn check_object(
+        &self,
+        schema: &Map<String, Value>,
+        object: &Map<String, Value>,
+        pointer: &str,
+        violations: &mut Vec<SchemaViolation>,
+        depth: usize,
+    ) {
+        let required = schema.get("required").and_then(Value::as_array);
+        for name in required.into_iter().flatten().filter_map(Value::as_str) {
+            if !object.contains_key(name) {
+                let message = format!("missing required property `{name}`");
+                violations.push(violation(pointer, message));
+            }
+        }
+        let properties = schema.get("properties").and_then(Value::as_object);
+        let additional = schema.get("additionalProperties");
+        for (name, item) in object {
+            let item_pointer = format!("{pointer}/{}", escape_segment(name));
+            match (properties.and_then(|properties| properties.get(name)), additional) {
+                (Some(property), _) => {
+                    self.check(property, item, &item_pointer, violations, depth + 1)
+                }
+                (None, Some(Value::Bool(false))) => violations.push(
+                    violation(&item_pointer, format!("property `{name}` is not allowed")),
+                ),
+                (None, Some(extra)) => {
+                    self.check(extra, item, &item_pointer, violations, depth + 1)
+                }
+                (None, None) => {}
+            }
+        }
+    }
+
+    fn matching(&self, members: &[Value], value: &Value, pointer: &str, depth: usize) -> usize {
+        members
+            .iter()
+            .filter(|member| {
+                let mut found = Vec::new();
+                self.check(member, value, pointer, &mut found, depth + 1);
+                found.is_empty()
+            })
+            .count()
+    }
+
+}
+
+fn violation(pointer: &str, message: String) -> SchemaViolation {
+    SchemaViolation {
+        pointer: pointer.to_string(),
+        message,
+    }
+}
+
+fn is_type(name: &str, value: &Value) -> bool {
+    match name {
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "number" => value.is_number(),
+        "boolean" => value.is_boolean(),
+        "null" => value.is_null(),
+        _ => true,
+    }
+}
+
+fn type_of(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(number) if number.is_f64() => "number",
+        Value::Number(_) => "integer",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+fn bound(schema: &Map<String, Value>, keyword: &str) -> Option<u64> {
+    schema.get(keyword).and_then(Value::as_u64)
+}
+
+// RFC 6901: `~` and `/` inside a key become `~0` and `~1`.
+fn escape_segment(segment: &str) -> String {
+    segment.replace('~', "~0").replace('/', "~1")
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Map, Value};
+
+    use super::PayloadSchema;
+
+    fn schemas() -> Map<String, Value> {
+        let schemas = json!({
+            "Event": {
+                "type": "object",
+                "required": ["uid"],
+                "properties": {
+                    "uid": {"type": "string", "minLength": 1},
+                    "tags": {"type": "array", "items": {"type": "string"}, "maxItems": 2},
+                    "severity": {"type": "integer", "minimum": 0, "maximum": 5},
+                    "status": {"enum": ["open", "closed"]},
+                    "note": {"type": "string", "nullable": true}
+                },
+                "additionalProperties": false
+            },
+            "EventListResult": {"type": "array", "items": {"$ref": "#/components/schemas/Event"}},
+            "Loop": {"$ref": "#/components/schemas/Loop"}
+        });
+        schemas.as_object().unwrap().clone()
+    }
+
+    fn pointers(name: &str, value: Value) -> Vec<(String, String)> {
+        let schemas = schemas();
+        PayloadSchema::new(name, &schemas)
+            .validate(&value)
+            .into_iter()
+            .map(|violation| (violation.pointer, violation.message))
+            .collect()
+    }
+
+    #[test]
+    fn conforming_values_have_no_violations() {
+        let event = json!({"uid": "e-1", "tags": ["a"], "severity": 3, "note": null});
+        assert!(pointers("Event", event.clone()).is_empty());
+        assert!(pointers("EventListResult", json!([event])).is_empty());
+    }
+
+    #[test]
+    fn violations_point_at_the_offending_value() {
+        let listed = json!([
+            {"uid": "e-1"},
+            {"uid": 7, "tags": ["a", 2, "c"], "severity": 9, "status": "lost", "extra~/": 1},
+            {"tags": []}
+        ]);
+        assert_eq!(
+            pointers("EventListResult", listed),
+            [
+                ("/1/extra~0~1".to_string(), "property `extra~/` is not allowed".to_string()),
+                ("/1/severity".to_string(), "9 is above 5".to_string()),
+                ("/1/status".to_string(), r#""lost" is not one of ["open","closed"]"#.to_string()),
+                ("/1/tags/1".to_string(), "expected string, got integer".to_string()),
+                ("/1/tags".to_string(), "has 3 items, more than 2".to_string()),
+                ("/1/uid".to_string(), "expected string, got integer".to_string()),
+                ("/2".to_string(), "missing required property `uid`".to_string()),
+            ]
+        );
+        assert_eq!(
+            pointers("Event", json!("garbage")),
+            [(String::new(), "expected object, got string".to_string())]
+        );
+    }
+
+    #[test]
+    fn reference_loops_and_missing_schemas_are_reported() {
+        let looped = pointers("Loop", json!({}));
+        assert_eq!(looped.len(), 1);
+        assert!(looped[0].1.contains("deeper than"), "{looped:?}");
+        assert_eq!(
+            pointers("Missing", json!({})),
+            [(String::new(), "unresolved $ref #/components/schemas/Missing".to_string())]
+        );
+    }
+}
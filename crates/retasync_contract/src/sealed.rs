@@ -0,0 +1,248 @@
+﻿// Sealed payloads: a command payload encrypted for its destination, so relays and recordings
+// only ever see ciphertext. The envelope's `content_type` is `application/msgpack+sealed` and
+// its payload is one base64 (standard, padded) string of
+//
+//   version     1 byte    SEALED_FORMAT_VERSION
+//   sender key  32 bytes  the sender's X25519 public key
+//   nonce       24 bytes  XChaCha20-Poly1305 nonce
+//   ciphertext  rest      the payload's `encode_canonical` bytes, sealed, with the 16 byte tag
+//
+// The AEAD key is SHA-256(SEALED_KEY_CONTEXT || X25519(sender secret, recipient public) ||
+// sender key || recipient key) and the 57 header bytes are its associated data. The operation
+// is not bound, since an alias may rename it on the way.
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    XChaCha20Poly1305, XNonce,
+};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::codec::{decode_canonical_with_limits, encode_canonical, CodecError, CodecLimits};
+
+pub const SEALED_FORMAT_VERSION: u8 = 1;
+pub const SEALED_KEY_LEN: usize = 32;
+pub const SEALED_NONCE_LEN: usize = 24;
+pub const SEALED_HEADER_LEN: usize = 1 + SEALED_KEY_LEN + SEALED_NONCE_LEN;
+pub const SEALED_KEY_CONTEXT: &[u8] = b"retasync-sealed-v1";
+
+#[derive(Debug, Error)]
+pub enum SealError {
+    #[error("sealed payload is malformed: {0}")]
+    Malformed(String),
+    #[error("sealed payload format version {0} is not supported")]
+    UnsupportedVersion(u8),
+    #[error("payload was not sealed with the sender's registered key")]
+    SenderKeyMismatch,
+    #[error("sealed payload failed authentication")]
+    Authentication,
+    #[error(transparent)]
+    Codec(#[from] CodecError),
+}
+
+impl SealError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            SealError::Malformed(_) => "sealed_payload_malformed",
+            SealError::UnsupportedVersion(_) => "sealed_version_unsupported",
+            SealError::SenderKeyMismatch => "sealed_sender_key_mismatch",
+            SealError::Authentication => "sealed_authentication_failed",
+            SealError::Codec(_) => "sealed_payload_undecodable",
+        }
+    }
+}
+
+// The X25519 public key a peer registers for this secret.
+pub fn sealing_public_key(secret: &[u8; SEALED_KEY_LEN]) -> [u8; SEALED_KEY_LEN] {
+    PublicKey::from(&StaticSecret::from(*secret)).to_bytes()
+}
+
+pub fn seal_payload(
+    payload: &Value,
+    sender_secret: &[u8; SEALED_KEY_LEN],
+    recipient_key: &[u8; SEALED_KEY_LEN],
+) -> Result<Value, SealError> {
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    seal_payload_with_nonce(payload, sender_secret, recipient_key, nonce.into())
+}
+
+// `seal_payload` with a caller-chosen nonce, for test vectors. A nonce must never be reused
+// with the same pair of keys.
+pub fn seal_payload_with_nonce(
+    payload: &Value,
+    sender_secret: &[u8; SEALED_KEY_LEN],
+    recipient_key: &[u8; SEALED_KEY_LEN],
+    nonce: [u8; SEALED_NONCE_LEN],
+) -> Result<Value, SealError> {
+    let sender_key = sealing_public_key(sender_secret);
+    let mut sealed = Vec::with_capacity(SEALED_HEADER_LEN);
+    sealed.push(SEALED_FORMAT_VERSION);
+    sealed.extend_from_slice(&sender_key);
+    sealed.extend_from_slice(&nonce);
+
+    let cipher = cipher(sender_secret, recipient_key, &sender_key, recipient_key);
+    let plaintext = encode_canonical(payload)?;
+    let ciphertext = cipher
+        .encrypt(
+            XNonce::from_slice(&nonce),
+            Payload {
+                msg: &plaintext,
+                aad: &sealed,
+            },
+        )
+        .map_err(|_| SealError::Authentication)?;
+    sealed.extend_from_slice(&ciphertext);
+    Ok(Value::String(STANDARD.encode(sealed)))
+}
+
+// The sender key a sealed payload names, read without opening it.
+pub fn sealed_sender_key(payload: &Value) -> Result<[u8; SEALED_KEY_LEN], SealError> {
+    let sealed = sealed_bytes(payload)?;
+    Ok(sealed[1..1 + SEALED_KEY_LEN]
+        .try_into()
+        .expect("header length checked"))
+}
+
+// Opens a payload sealed for `recipient_secret`, refusing it unless the sender key in its header
+// is `expected_sender`. The plaintext is decoded within `limits`.
+pub fn open_payload(
+    payload: &Value,
+    recipient_secret: &[u8; SEALED_KEY_LEN],
+    expected_sender: &[u8; SEALED_KEY_LEN],
+    limits: &CodecLimits,
+) -> Result<Value, SealError> {
+    let sealed = sealed_bytes(payload)?;
+    let (header, ciphertext) = sealed.split_at(SEALED_HEADER_LEN);
+    if header[1..1 + SEALED_KEY_LEN] != expected_sender[..] {
+        return Err(SealError::SenderKeyMismatch);
+    }
+    let recipient_key = sealing_public_key(recipient_secret);
+    let cipher = cipher(recipient_secret, expected_sender, expected_sender, &recipient_key);
+    let plaintext = cipher
+        .decrypt(
+            XNonce::from_slice(&header[1 + SEALED_KEY_LEN..]),
+            Payload {
+                msg: ciphertext,
+                aad: header,
+            },
+        )
+        .map_err(|_| SealError::Authentication)?;
+    Ok(decode_canonical_with_limits(&plaintext, limits)?)
+}
+
+fn sealed_bytes(payload: &Value) -> Result<Vec<u8>, SealError> {
+    let encoded = payload
+        .as_str()
+        .ok_or_else(|| SealError::Malformed("payload is not a string".to_string()))?;
+    let sealed = STANDARD
+        .decode(encoded)
+        .map_err(|_| SealError::Malformed("payload is not base64".to_string()))?;
+    if sealed.len() < SEALED_HEADER_LEN {
+        return Err(SealError::Malformed("payload is shorter than its header".to_string()));
+    }
+    if sealed[0] != SEALED_FORMAT_VERSION {
+        return Err(SealError::UnsupportedVersion(sealed[0]));
+    }
+    Ok(sealed)
+}
+
+// Both sides derive the same cipher: each holds one secret and the other side's public key.
+fn cipher(
+    own_secret: &[u8; SEALED_KEY_LEN],
+    peer_key: &[u8; SEALED_KEY_LEN],
+    sender_key: &[u8; SEALED_KEY_LEN],
+    recipient_key: &[u8; SEALED_KEY_LEN],
+) -> XChaCha20Poly1305 {
+    let shared = StaticSecret::from(*own_secret).diffie_hellman(&PublicKey::from(*peer_key));
+    let key = Sha256::new()
+        .chain_update(SEALED_KEY_CONTEXT)
+        .chain_update(shared.as_bytes())
+        .chain_update(sender_key)
+        .chain_update(recipient_key)
+        .finalize();
+    XChaCha20Poly1305::new(&key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    // Test vector: fixed secrets and nonce, so other implementations can check their sealing
+    // byte for byte.
+    const SENDER_SECRET: [u8; 32] = [0x11; 32];
+    const RECIPIENT_SECRET: [u8; 32] = [0x22; 32];
+    const NONCE: [u8; 24] = [0x33; 24];
+    const SENDER_KEY: &str = "e06Qm75//kTEZaIgA31gjuNYl9Me+XLwf3SJLLD3PxM=";
+    const RECIPIENT_KEY: &str = "D6poTtKIZ7l/Smot7l34zpdOdrcBjj8iocTPJnhXDyA=";
+    const SEALED: &str = concat!(
+        "AXtOkJu+f/5ExGWiIAN9YI7jWJfTHvly8H90iSyw9z8TMzMzMzMzMzMzMzMzMzMzMzMzMzMzMzMz",
+        "gvb1BqfEsEN7lbqT1lxcsxCBt1AvYJgYOOCsAQThaKw0AGEDlaW3G4nX6g==",
+    );
+
+    fn plaintext() -> Value {
+        json!({ "uid": "eam-1", "priority": "urgent" })
+    }
+
+    #[test]
+    fn vector_seals_and_opens_byte_for_byte() {
+        let limits = CodecLimits::default();
+        let sender_key = sealing_public_key(&SENDER_SECRET);
+        let recipient_key = sealing_public_key(&RECIPIENT_SECRET);
+        assert_eq!(STANDARD.encode(sender_key), SENDER_KEY);
+        assert_eq!(STANDARD.encode(recipient_key), RECIPIENT_KEY);
+
+        let sealed =
+            seal_payload_with_nonce(&plaintext(), &SENDER_SECRET, &recipient_key, NONCE).unwrap();
+        assert_eq!(sealed, json!(SEALED));
+        assert_eq!(sealed_sender_key(&sealed).unwrap(), sender_key);
+        let opened = open_payload(&sealed, &RECIPIENT_SECRET, &sender_key, &limits).unwrap();
+        assert_eq!(opened, plaintext());
+    }
+
+    #[test]
+    fn tampering_or_the_wrong_keys_fail_to_open() {
+        let limits = CodecLimits::default();
+        let sender_key = sealing_public_key(&SENDER_SECRET);
+        let recipient_key = sealing_public_key(&RECIPIENT_SECRET);
+        let sealed = seal_payload(&plaintext(), &SENDER_SECRET, &recipient_key).unwrap();
+        let mut bytes = STANDARD.decode(sealed.as_str().unwrap()).unwrap();
+
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0x01;
+        let tampered = json!(STANDARD.encode(&bytes));
+        let err = open_payload(&tampered, &RECIPIENT_SECRET, &sender_key, &limits).unwrap_err();
+        assert!(matches!(err, SealError::Authentication), "{err}");
+
+        // A relay that swaps in its own key is refused before any decryption.
+        let impostor = sealing_public_key(&[0x44; 32]);
+        let err = open_payload(&sealed, &RECIPIENT_SECRET, &impostor, &limits).unwrap_err();
+        assert_eq!(err.code(), "sealed_sender_key_mismatch");
+
+        let err = open_payload(&sealed, &[0x55; 32], &sender_key, &limits).unwrap_err();
+        assert!(matches!(err, SealError::Authentication), "{err}");
+
+        bytes[0] = 9;
+        let reopened = json!(STANDARD.encode(&bytes));
+        let err = open_payload(&reopened, &RECIPIENT_SECRET, &sender_key, &limits);
+        assert!(matches!(err, Err(SealError::UnsupportedVersion(9))));
+        let plain = json!({ "uid": "eam-1" });
+        let err = open_payload(&plain, &RECIPIENT_SECRET, &sender_key, &limits);
+        assert_eq!(err.unwrap_err().code(), "sealed_payload_malformed");
+    }
+
+    #[test]
+    fn plaintext_is_decoded_within_the_limits() {
+        let sender_key = sealing_public_key(&SENDER_SECRET);
+        let recipient_key = sealing_public_key(&RECIPIENT_SECRET);
+        let sealed = seal_payload(&plaintext(), &SENDER_SECRET, &recipient_key).unwrap();
+        let limits = CodecLimits {
+            max_bytes: 4,
+            ..CodecLimits::default()
+        };
+        let err = open_payload(&sealed, &RECIPIENT_SECRET, &sender_key, &limits).unwrap_err();
+        assert_eq!(err.code(), "sealed_payload_undecodable");
+    }
+}
@@ -0,0 +1,261 @@
+﻿use std::collections::BTreeMap;
+
+use chrono::{DateTime, Duration, Utc};
+use retasync_storage::AggregateCount;
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_MAX_BUCKETS: u64 = 1000;
+pub const DEFAULT_BUCKET: &str = "1h";
+// Groups past the `top` cutoff are summed under this name, reserved like the `_`-prefixed
+// payload fields so a real group called `other` keeps its own series.
+pub const OTHER_GROUP: &str = "_other";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AggregateSettings {
+    // Most buckets a single aggregate query may span.
+    pub max_buckets: u64,
+}
+
+impl Default for AggregateSettings {
+    fn default() -> Self {
+        Self {
+            max_buckets: DEFAULT_MAX_BUCKETS,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct AggregateQuery {
+    pub group_by: Option<String>,
+    pub bucket: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub top: Option<usize>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum AggregateRejection {
+    InvalidBucket,
+    InvalidRange,
+    InvalidTop,
+    TooManyBuckets { buckets: i64, max_buckets: u64 },
+}
+
+// Half-open `[since, until)` window in epoch seconds, cut into UTC-aligned buckets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BucketRange {
+    pub bucket_secs: i64,
+    pub since: i64,
+    pub until: i64,
+}
+
+impl BucketRange {
+    pub fn first_bucket(&self) -> i64 {
+        self.since.div_euclid(self.bucket_secs) * self.bucket_secs
+    }
+
+    pub fn bucket_count(&self) -> i64 {
+        (self.until - 1).div_euclid(self.bucket_secs) - self.since.div_euclid(self.bucket_secs) + 1
+    }
+}
+
+impl AggregateQuery {
+    // Hourly buckets over the 24 hours before `now` unless the query says otherwise.
+    pub fn range(
+        &self,
+        max_buckets: u64,
+        now: DateTime<Utc>,
+    ) -> Result<BucketRange, AggregateRejection> {
+        let bucket = self.bucket.as_deref().unwrap_or(DEFAULT_BUCKET);
+        let bucket_secs = parse_bucket(bucket).ok_or(AggregateRejection::InvalidBucket)?;
+        if self.top == Some(0) {
+            return Err(AggregateRejection::InvalidTop);
+        }
+        // The default end is exclusive, so it sits just past `now` to keep the current second.
+        let until = self.until.unwrap_or(now + Duration::seconds(1));
+        let since = self.since.unwrap_or(until - Duration::hours(24));
+        let range = BucketRange {
+            bucket_secs,
+            since: since.timestamp(),
+            until: until.timestamp(),
+        };
+        if range.since >= range.until {
+            return Err(AggregateRejection::InvalidRange);
+        }
+        let buckets = range.bucket_count();
+        if buckets > i64::try_from(max_buckets).unwrap_or(i64::MAX) {
+            return Err(AggregateRejection::TooManyBuckets {
+                buckets,
+                max_buckets,
+            });
+        }
+        Ok(range)
+    }
+}
+
+// Accepts a positive count followed by `s`, `m`, `h`, or `d`, such as `15m` or `1h`.
+pub fn parse_bucket(raw: &str) -> Option<i64> {
+    let unit = raw.chars().last()?;
+    let scale = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 3600,
+        'd' => 86_400,
+        _ => return None,
+    };
+    let value = raw[..raw.len() - unit.len_utf8()].parse::<i64>().ok()?;
+    if value <= 0 {
+        return None;
+    }
+    value.checked_mul(scale)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AggregateSeries {
+    pub group_by: String,
+    pub bucket_seconds: i64,
+    pub since: DateTime<Utc>,
+    pub until: DateTime<Utc>,
+    pub buckets: Vec<DateTime<Utc>>,
+    // Largest first, then `_other` when the tail was collapsed.
+    pub groups: Vec<String>,
+    pub series: BTreeMap<String, Vec<i64>>,
+    pub total: i64,
+}
+
+pub fn build_series(
+    group_by: &str,
+    range: BucketRange,
+    top: Option<usize>,
+    rows: &[AggregateCount],
+) -> AggregateSeries {
+    let mut totals: BTreeMap<&str, i64> = BTreeMap::new();
+    for row in rows {
+        *totals.entry(row.group.as_str()).or_default() += row.count;
+    }
+    let mut ranked: Vec<(&str, i64)> = totals.into_iter().collect();
+    ranked.sort_by(|left, right| right.1.cmp(&left.1).then(left.0.cmp(right.0)));
+    let mut groups: Vec<String> = ranked.iter().map(|(group, _)| group.to_string()).collect();
+    if let Some(top) = top.filter(|top| groups.len() > *top) {
+        groups.truncate(top);
+        groups.push(OTHER_GROUP.to_string());
+    }
+
+    let width = usize::try_from(range.bucket_count()).unwrap_or(0);
+    let mut series: BTreeMap<String, Vec<i64>> = groups
+        .iter()
+        .map(|group| (group.clone(), vec![0; width]))
+        .collect();
+    let first = range.first_bucket();
+    for row in rows {
+        let Ok(slot) = usize::try_from((row.bucket_start - first) / range.bucket_secs) else {
+            continue;
+        };
+        let group = match series.contains_key(&row.group) {
+            true => row.group.as_str(),
+            false => OTHER_GROUP,
+        };
+        if let Some(count) = series.get_mut(group).and_then(|counts| counts.get_mut(slot)) {
+            *count += row.count;
+        }
+    }
+
+    AggregateSeries {
+        group_by: group_by.to_string(),
+        bucket_seconds: range.bucket_secs,
+        since: timestamp(range.since),
+        until: timestamp(range.until),
+        buckets: (0..range.bucket_count())
+            .map(|index| timestamp(first + index * range.bucket_secs))
+            .collect(),
+        groups,
+        series,
+        total: rows.iter().map(|row| row.count).sum(),
+    }
+}
+
+fn timestamp(secs: i64) -> DateTime<Utc> {
+    DateTime::from_timestamp(secs, 0).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{build_series, parse_bucket, AggregateQuery, AggregateRejection, BucketRange};
+    use chrono::{TimeZone, Utc};
+    use retasync_storage::AggregateCount;
+
+    fn count(bucket_start: i64, group: &str, count: i64) -> AggregateCount {
+        AggregateCount {
+            bucket_start,
+            group: group.to_string(),
+            count,
+        }
+    }
+
+    #[test]
+    fn buckets_parse_and_ranges_are_bounded() {
+        assert_eq!(parse_bucket("15m"), Some(900));
+        assert_eq!(parse_bucket("1d"), Some(86_400));
+        assert_eq!(parse_bucket("0h"), None);
+        assert_eq!(parse_bucket("1w"), None);
+        assert_eq!(parse_bucket("hé"), None);
+
+        let now = Utc.with_ymd_and_hms(2026, 3, 1, 12, 30, 0).unwrap();
+        let range = AggregateQuery::default().range(1000, now).unwrap();
+        assert_eq!(range.bucket_count(), 25);
+        assert_eq!(range.first_bucket(), now.timestamp() - 24 * 3600 - 1800);
+
+        let query = AggregateQuery {
+            bucket: Some("1m".to_string()),
+            ..AggregateQuery::default()
+        };
+        assert_eq!(
+            query.range(1000, now),
+            Err(AggregateRejection::TooManyBuckets {
+                buckets: 1441,
+                max_buckets: 1000
+            })
+        );
+        let query = AggregateQuery {
+            since: Some(now),
+            until: Some(now),
+            ..AggregateQuery::default()
+        };
+        assert_eq!(query.range(1000, now), Err(AggregateRejection::InvalidRange));
+    }
+
+    #[test]
+    fn the_tail_past_top_collapses_into_other() {
+        let range = BucketRange {
+            bucket_secs: 60,
+            since: 0,
+            until: 180,
+        };
+        let rows = [
+            count(0, "a", 1),
+            count(0, "b", 5),
+            count(60, "c", 2),
+            count(120, "a", 3),
+            count(120, "other", 1),
+        ];
+        let series = build_series("event", range, Some(2), &rows);
+        assert_eq!(series.groups, ["b", "a", "_other"]);
+        assert_eq!(series.series["a"], [1, 0, 3]);
+        assert_eq!(series.series["_other"], [0, 2, 1]);
+        assert_eq!(series.total, 12);
+        assert_eq!(series.buckets.len(), 3);
+
+        let series = build_series("event", range, None, &rows);
+        assert_eq!(series.groups, ["b", "a", "c", "other"]);
+
+        // A real `other` group ranked into the top stays apart from the collapsed tail.
+        let series = build_series("event", range, Some(3), &rows);
+        assert_eq!(series.groups, ["b", "a", "c", "_other"]);
+        let rows = [count(0, "other", 4), count(0, "a", 2), count(60, "c", 1)];
+        let series = build_series("event", range, Some(1), &rows);
+        assert_eq!(series.groups, ["other", "_other"]);
+        assert_eq!(series.series["other"], [4, 0, 0]);
+        assert_eq!(series.series["_other"], [2, 1, 0]);
+    }
+}
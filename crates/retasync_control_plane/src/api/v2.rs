@@ -0,0 +1,377 @@
+﻿use std::collections::BTreeMap;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::error::{RetryAdvice, RETRY_ADVICE_FIELD};
+
+// Every `/v2` success body is `{"data": ...}` and every failure body is `{"error": {...}}`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Envelope<T> {
+    pub data: T,
+}
+
+impl<T> Envelope<T> {
+    pub fn new(data: T) -> Self {
+        Self { data }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ErrorEnvelope {
+    pub error: ApiError,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ApiError {
+    pub code: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry: Option<RetryAdvice>,
+}
+
+impl ApiError {
+    // v1 errors are `{"error": code, ...}`; everything next to the code becomes the detail,
+    // except retry advice, which keeps its own field.
+    pub fn from_v1(body: Value) -> Self {
+        let Value::Object(mut fields) = body else {
+            return Self {
+                code: "internal_error".to_string(),
+                detail: Some(body),
+                retry: None,
+            };
+        };
+        let retry = fields
+            .remove(RETRY_ADVICE_FIELD)
+            .and_then(|advice| serde_json::from_value(advice).ok());
+        let code = match fields.remove("error") {
+            Some(Value::String(code)) => code,
+            Some(other) => other.to_string(),
+            None => "internal_error".to_string(),
+        };
+        let detail = match fields.len() {
+            0 => None,
+            1 if fields.contains_key("detail") => fields.remove("detail"),
+            _ => Some(Value::Object(fields)),
+        };
+        Self {
+            code,
+            detail,
+            retry,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<String>,
+    #[serde(default)]
+    pub prev_cursor: Option<String>,
+    // The sort order the cursors walk, as `column desc, ...`.
+    #[serde(default)]
+    pub order: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthStatus {
+    Live,
+    Ready,
+    Degraded,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Health {
+    pub status: HealthStatus,
+    pub timestamp: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JobAccepted {
+    pub job_id: String,
+    pub submitted_at: DateTime<Utc>,
+    pub status_url: String,
+    // Held in the node's submission spool until storage can take it.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub spooled: bool,
+}
+
+impl JobAccepted {
+    pub fn new(job_id: String, submitted_at: &str) -> Result<Self> {
+        Ok(Self {
+            submitted_at: timestamp(submitted_at)?,
+            status_url: format!("/v2/jobs/{job_id}"),
+            job_id,
+            spooled: false,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Job {
+    pub job_id: String,
+    pub operation: String,
+    pub requested_operation: Option<String>,
+    pub status: String,
+    // Left out when the request passed `omit_payload=true`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload: Option<Value>,
+    pub payload_bytes: Option<i64>,
+    pub payload_sha256: Option<String>,
+    pub dispatch: Option<Value>,
+    pub failure_reason: Option<String>,
+    pub submitted_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub attachments: Vec<Transfer>,
+    // How many bridge calls the job took and the last error among them.
+    #[serde(default)]
+    pub dispatch_attempts: retasync_storage::DispatchAttemptSummary,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+}
+
+impl Job {
+    pub const FIELDS: &'static [&'static str] = &[
+        "job_id",
+        "operation",
+        "requested_operation",
+        "status",
+        "payload",
+        "payload_bytes",
+        "payload_sha256",
+        "dispatch",
+        "failure_reason",
+        "submitted_at",
+        "updated_at",
+        "attachments",
+        "dispatch_attempts",
+        "labels",
+    ];
+
+    pub fn from_record(
+        record: retasync_storage::JobRecord,
+        attachments: Vec<Transfer>,
+    ) -> Result<Self> {
+        let payload = serde_json::from_str(&record.payload_json).context("decode job payload")?;
+        let job = Self::from_summary((&record).into(), attachments)?;
+        Ok(Self {
+            payload: Some(payload),
+            ..job
+        })
+    }
+
+    pub fn from_summary(
+        summary: retasync_storage::JobSummary,
+        attachments: Vec<Transfer>,
+    ) -> Result<Self> {
+        Ok(Self {
+            payload: None,
+            payload_bytes: summary.payload_bytes,
+            payload_sha256: summary.payload_sha256,
+            dispatch: summary
+                .dispatch_json
+                .as_deref()
+                .map(serde_json::from_str)
+                .transpose()
+                .context("decode job dispatch")?,
+            submitted_at: timestamp(&summary.submitted_at)?,
+            updated_at: timestamp(&summary.updated_at)?,
+            job_id: summary.job_id,
+            operation: summary.operation,
+            requested_operation: summary.requested_operation,
+            status: summary.status,
+            failure_reason: summary.failure_reason,
+            attachments,
+            dispatch_attempts: Default::default(),
+            labels: BTreeMap::new(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Transfer {
+    pub transfer_id: String,
+    pub job_id: Option<String>,
+    pub status: String,
+    pub metadata: Value,
+    pub failure_reason: Option<String>,
+    pub submitted_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub progress: Option<TransferProgress>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dedup: Option<TransferDedup>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+}
+
+impl Transfer {
+    pub const FIELDS: &'static [&'static str] = &[
+        "transfer_id",
+        "job_id",
+        "status",
+        "metadata",
+        "failure_reason",
+        "submitted_at",
+        "updated_at",
+        "progress",
+        "dedup",
+        "labels",
+    ];
+
+    pub fn from_record(
+        record: retasync_storage::TransferRecord,
+        progress: Option<retasync_transfer::TransferProgress>,
+        dedup: Option<retasync_storage::TransferDedup>,
+    ) -> Result<Self> {
+        Ok(Self {
+            metadata: serde_json::from_str(&record.metadata_json)
+                .context("decode transfer metadata")?,
+            submitted_at: timestamp(&record.submitted_at)?,
+            updated_at: timestamp(&record.updated_at)?,
+            progress: progress.map(TransferProgress::try_from).transpose()?,
+            dedup: dedup.map(TransferDedup::try_from).transpose()?,
+            transfer_id: record.transfer_id,
+            job_id: record.job_id,
+            status: record.status,
+            failure_reason: record.failure_reason,
+            labels: BTreeMap::new(),
+        })
+    }
+}
+
+// Present once the upload offered its content hash to the destination.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TransferDedup {
+    pub sha256: String,
+    pub outcome: String,
+    pub bytes_saved: u64,
+    pub remote_ack: Option<Value>,
+    pub decided_at: DateTime<Utc>,
+}
+
+impl TryFrom<retasync_storage::TransferDedup> for TransferDedup {
+    type Error = anyhow::Error;
+
+    fn try_from(dedup: retasync_storage::TransferDedup) -> Result<Self> {
+        Ok(Self {
+            decided_at: timestamp(&dedup.decided_at)?,
+            bytes_saved: dedup.bytes_saved.max(0) as u64,
+            sha256: dedup.sha256,
+            outcome: dedup.outcome,
+            remote_ack: dedup.remote_ack,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransferProgress {
+    pub bytes_total: u64,
+    pub bytes_sent: u64,
+    pub chunks_total: u64,
+    pub chunks_sent: u64,
+    pub last_chunk_at: Option<DateTime<Utc>>,
+    pub stalled: bool,
+}
+
+impl TryFrom<retasync_transfer::TransferProgress> for TransferProgress {
+    type Error = anyhow::Error;
+
+    fn try_from(progress: retasync_transfer::TransferProgress) -> Result<Self> {
+        Ok(Self {
+            bytes_total: progress.bytes_total,
+            bytes_sent: progress.bytes_sent,
+            chunks_total: progress.chunks_total,
+            chunks_sent: progress.chunks_sent,
+            last_chunk_at: progress.last_chunk_at.as_deref().map(timestamp).transpose()?,
+            stalled: progress.stalled,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AllowlistEntry {
+    pub identity_hash: String,
+    pub note: Option<String>,
+    pub role: String,
+    pub status: String,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub approved_at: Option<DateTime<Utc>>,
+    pub public_key: Option<String>,
+}
+
+impl TryFrom<retasync_storage::AllowlistEntry> for AllowlistEntry {
+    type Error = anyhow::Error;
+
+    fn try_from(entry: retasync_storage::AllowlistEntry) -> Result<Self> {
+        Ok(Self {
+            expires_at: entry.expires_at.as_deref().map(timestamp).transpose()?,
+            created_at: timestamp(&entry.created_at)?,
+            approved_at: entry.approved_at.as_deref().map(timestamp).transpose()?,
+            identity_hash: entry.identity_hash,
+            note: entry.note,
+            role: entry.role,
+            status: entry.status,
+            public_key: entry.public_key,
+        })
+    }
+}
+
+// Request counts per deprecated `/v1` route, keyed by `METHOD /v1/path/{param}`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApiUsage {
+    pub v1_requests: BTreeMap<String, u64>,
+}
+
+fn timestamp(value: &str) -> Result<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|parsed| parsed.with_timezone(&Utc))
+        .with_context(|| format!("parse timestamp {value}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn v1_error_bodies_become_code_and_detail() {
+        assert_eq!(
+            ApiError::from_v1(json!({"error": "job_not_found"})),
+            ApiError {
+                code: "job_not_found".to_string(),
+                detail: None,
+                retry: None,
+            }
+        );
+        assert_eq!(
+            ApiError::from_v1(json!({"error": "internal_error", "detail": "disk full"})),
+            ApiError {
+                code: "internal_error".to_string(),
+                detail: Some(json!("disk full")),
+                retry: None,
+            }
+        );
+        assert_eq!(
+            ApiError::from_v1(json!({"error": "payload_too_large", "limit": 10, "size": 12})),
+            ApiError {
+                code: "payload_too_large".to_string(),
+                detail: Some(json!({"limit": 10, "size": 12})),
+                retry: None,
+            }
+        );
+        let advice = json!({ "retry_after_ms": 1500, "reason": "rate_limited", "scope": "client" });
+        assert_eq!(
+            ApiError::from_v1(json!({"error": "rate_limited", "retry": advice})),
+            ApiError {
+                code: "rate_limited".to_string(),
+                detail: None,
+                retry: serde_json::from_value(advice).ok(),
+            }
+        );
+    }
+}
@@ -1,28 +1,235 @@
-﻿use std::sync::Arc;
+﻿use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::net::SocketAddr;
+use std::sync::Arc;
 
 use axum::{
-    extract::{Path, Query, State},
-    http::{header, HeaderMap, StatusCode},
-    response::{
-        sse::{Event as SseEvent, KeepAlive, Sse},
-        IntoResponse,
+    body::{Body, Bytes},
+    extract::{
+        rejection::{JsonRejection, QueryRejection},
+        ConnectInfo, MatchedPath, OriginalUri, Path, Query, Request, State,
     },
-    routing::{delete, get, post},
+    http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    middleware::{self, Next},
+    routing::{delete, get, post, put, MethodRouter},
     Json, Router,
 };
+#[cfg(feature = "sse")]
+use axum::response::sse::{Event as SseEvent, KeepAlive, Sse};
+#[cfg(feature = "status-page")]
+use axum::response::Html;
 use chrono::Utc;
+#[cfg(feature = "sse")]
 use futures::stream::StreamExt;
-use retasync_contract::MeshCommandEnvelope;
-use retasync_mesh_bridge::RpcMeshBridge;
-use retasync_storage::{JobRecord, RetasyncStorage};
-use retasync_transfer::TransferUploadRequest;
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use retasync_contract::{
+    canonical_digest, compress_payload, CodecError, CodecLimits, Compression, ContractRegistry,
+    Deprecation, EnvelopeMeta, HopRecord, IdentityHash, IdentityHashError, IdentityValidation,
+    MeshCommandEnvelope, MeshResultEnvelope, MeshTransferEnvelope, SchemaViolation,
+    TransferDirection, CONTENT_TYPE_MSGPACK, CONTENT_TYPE_SEALED, DEFAULT_COMPRESSION_THRESHOLD,
+    LOCAL_NODE_IDENTITY,
+};
+#[cfg(feature = "transfers")]
+use retasync_contract::BUNDLE_MEDIA_TYPE;
+#[cfg(any(feature = "entities", feature = "transfers"))]
+use retasync_contract::validate_labels;
+use retasync_mesh_bridge::{
+    BridgeError, CallMetrics, Compatibility, PeerCapabilities, PeerDirectory, RpcMeshBridge,
+    SimulatedMeshBridge, SimulationProfile, TransportSelection, TransportStatus,
+};
+use retasync_storage::{
+    CanonicalTimestamp, EventGrouping, IntegrityStats, JobGrouping, JobRecord, NotificationCursor,
+    NotificationRecord, PoolStats, QuotaClaim, RetasyncStorage, StorageError, StorageTx,
+    FEED_JOB_EVENT,
+};
+use retasync_storage::{
+    BundleMember, CachedSummary, CrashReport, DispatchAttemptSummary, FeatureFlagRecord,
+    FleetNode, JobTransformTrace, KeyValue, PageDirection, SortOrder, SubmissionSource,
+    TransferDedup, TransferRecord, CRASH_REPORT_ORDER, FLEET_NODE_ORDER, LABEL_SUBJECT_JOB,
+    LABEL_SUBJECT_TRANSFER,
+};
+#[cfg(feature = "transfers")]
+use retasync_storage::{Keyset, ReceivedFile, RECEIVED_FILE_ORDER, TRANSFER_ORDER};
+#[cfg(feature = "entities")]
+use retasync_storage::{
+    entity_label_subject, payload_digest, EntityRecord, EntitySummary, LABEL_SUBJECT_ENTITY,
+};
+use retasync_transfer::{TransferProgress, TransferUploadRequest, DEFAULT_CHUNK_SIZE};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncReadExt;
 use tokio::sync::{broadcast, RwLock};
-use tokio_stream::wrappers::BroadcastStream;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 use uuid::Uuid;
 
+use crate::aggregates::{
+    build_series, AggregateQuery, AggregateRejection, AggregateSettings, BucketRange,
+};
+use crate::api::v2::{
+    self, ApiError, ApiUsage, Envelope, ErrorEnvelope, Health, HealthStatus, JobAccepted, Page,
+};
+use crate::archive::{self, RetentionSettings};
+use crate::attribution::{
+    client_id, client_key, client_stats, ClientStatsQuery, ANONYMOUS_LOOPBACK,
+    DEFAULT_STATS_WINDOW, MAX_STATS_WINDOW_SECS,
+};
+use crate::attempts::{
+    AttemptLog, DispatchLatencies, LatencyHistogram, DISPATCH_ATTEMPTS_INCLUDE,
+};
+use crate::attachments::{
+    take_attachments, Attachment, AttachmentDispatch, AttachmentRejection, AttachmentSettings,
+    ATTACHMENTS_FIELD,
+};
+use crate::bundles::BundleSettings;
+#[cfg(feature = "transfers")]
+use crate::bundles::{
+    members, pack_request, BundleAssembly, BundleRejection, BundleUploadRequest,
+    PACKED_MEMBER_STATUS,
+};
+use crate::cancellation::{
+    chunks_open, close_chunks, is_cancelled, open_chunks, recall_job, recall_message, track_chunk,
+    OutstandingChunks, CANCELLED_STATUS,
+};
+#[cfg(feature = "transfers")]
+use crate::cancellation::{outstanding_chunk_count, recall_chunks};
+use crate::capabilities::{node_capabilities, Capabilities};
+use crate::circuit::{admit_job, circuit_views, refuse_job, Admission, CircuitBreakers};
+use crate::clock::{observe_result, ClockSettings};
+use crate::coalescing::{
+    coalescing_subject, CoalescingMetrics, EventCoalescingSettings, EventSubscribers,
+};
+#[cfg(feature = "sse")]
+use crate::coalescing::{push_updates, NOTIFICATION_SUBSCRIBER, SSE_SUBSCRIBER};
+use crate::config_apply::{
+    self, awaiting_confirmation, config_apply_status, ConfigApplyError, ConfigApplySettings,
+    PendingConfig,
+};
+use crate::config_schema::runtime_config_schema;
+use crate::consistency::{enforce_consistency, ConsistencySettings};
+use crate::contracts::{
+    diff_contracts, ContractStore, LoadedContract, CONTRACT_RELOADED_EVENT,
+};
+use crate::crash::CrashReportSettings;
+use crate::cursor::{CursorCodec, CursorError, CursorKeys, PaginationSettings};
+#[cfg(feature = "transfers")]
+use crate::cursor::PageCursors;
+#[cfg(feature = "transfers")]
+use crate::dedup::UploadDigests;
+use crate::dedup::{
+    offer_transfer, DedupMetrics, OfferAnswer, TransferDedupSettings, SKIPPED_DUPLICATE_STATUS,
+};
+use crate::delivery::{take_delivery, DeliveryRejection, DeliverySettings, EffectiveDelivery};
+use crate::dependencies::{
+    ancestor_graph, check_chain, is_terminal, release_if_ready, settle_dependents,
+    take_dependencies, DependencyRejection, DependencySettings, DEPENDS_ON_FIELD, WAITING_STATUS,
+};
+use crate::diagnostics::{
+    check_bridge, check_contract, check_identity_hashes, check_storage, CheckResult, CheckStatus,
+    BRIDGE_PROBE_TIMEOUT,
+};
+use crate::dispatch::{
+    local_identity, resolve_dispatch, validate_dispatch_config, Dispatch,
+    IdentitySettings, OperationDefaults,
+};
+use crate::dispatch_queue::{
+    dispatch_view, hold as hold_job, prioritize, release as release_job, required_reason,
+    set_order, DispatchQueue, DispatchQueueSettings, QueueChange, QueueRefusal,
+    DUPLICATE_JOB_ERROR, HELD_STATUS, JOB_NOT_HELD_ERROR, JOB_NOT_QUEUED_ERROR,
+    REASON_REQUIRED_ERROR,
+};
+use crate::dry_run::{DryRunReport, EnvelopePreview, COMMAND_STAGES};
+#[cfg(feature = "entities")]
+use crate::entity_sync::{sync_with_peer, version_conflict_result, SyncLimits};
+use crate::error::{
+    refusal, retry_after_header, JobProcessingError, RetryAdvice, RetryScope, STORAGE_RETRY_AFTER,
+};
+use crate::escalation::{
+    escalate_envelope, is_in_transit, mark_in_transit, record_escalation, EscalationRecord,
+    EscalationStep,
+};
+use crate::examples::{operation_catalog, operation_example, operation_suggestions};
+use crate::features::{
+    is_known, FeatureFlagUpdate, FeatureFlags, COMPRESSION_FLAG, FEATURE_CHANGED_EVENT,
+    KNOWN_FLAGS, TRANSFER_DEDUP_FLAG,
+};
+use crate::feed::{read_page, EventFeedSettings, FeedQuery};
+use crate::fleet::{FleetNodeView, FleetSettings};
+use crate::files::{
+    check_outbound, ContentInspector, FileSettings, NoopInspector, PolicyViolation,
+};
+#[cfg(feature = "transfers")]
+use crate::files::OUTBOUND_SAMPLE_BYTES;
+use crate::handlers::HandlerRegistry;
+use crate::handshake::{handshake, peer_handshake, HandshakeAttempts};
+use crate::health::{self, Availability};
+use crate::inbound::{InboundQueue, InboundSettings, QueueSummary};
+use crate::labels::{
+    label_filters, meta_labels, parse_labels, subject_type, take_labels, with_labels, Labels,
+    INVALID_LABELS_ERROR, INVALID_LABEL_FILTER_ERROR, JOB_LABELS_CHANGED_EVENT,
+};
+use crate::leases::{spawn_leased, JobWatchdogSettings};
+use crate::liveness::{check_destination, contact_window, record_contact, LivenessSettings};
+use crate::meta::{
+    check_payload, meta_from_headers, resolve_meta, MetaRejection, INVALID_META_ERROR,
+    RESERVED_KEY_ERROR,
+};
+use crate::migrations::{MigrationRegistry, PayloadMigrationSettings};
+use crate::mute::{
+    mark_muted, mute, mute_status, unmute, MuteRequest, MuteStatus, NodeMute, Traffic,
+    NODE_MUTED_ERROR,
+};
+use crate::notifier::{deliver, EventNotifier};
+use crate::profiles::EffectiveConfig;
+use crate::quotas::{
+    bucket_start, check_quotas, exceeded_quota, quota_claims, quota_report, window_start,
+    QuotaExceeded, QuotaSettings, DESTINATION_QUOTA, TOKEN_QUOTA,
+};
+use crate::rebuild::{
+    current_rebuild, parse_scopes, start_rebuild, RebuildQuery, RebuildRefusal, RebuildRun,
+    RebuildScope,
+};
+use crate::receipts::{receipt_view, ReceiptSettings, RECEIPT_NOT_FOUND_ERROR};
+use crate::result_cache::{
+    not_modified_digest, result_key, CachedResult, ResultCache, ResultCacheSettings,
+    ResultReuseMetrics, RESULT_SOURCE_CACHE, RESULT_SOURCE_MESH,
+};
+use crate::results::{is_streaming, mark_streaming, missing_sequences};
+use crate::result_validation::{screen_result, ResultValidationSettings, Screened};
+use crate::sealing::{
+    parse_sealing_key, seal_outgoing, Outgoing, SecuritySettings, SEALING_KEY_MISSING_ERROR,
+};
+use crate::sizing::{
+    check_envelope, envelope_size, transport_limit, Oversize, DEFAULT_MAX_LINK_BYTES,
+    DEFAULT_MAX_LXMF_BYTES,
+};
+use crate::shaping::{Shape, Shaped, UNKNOWN_FIELD_ERROR};
+use crate::sneakernet::{
+    export_bundle, import_bundle, signer, ExportFilter, SneakernetError, SneakernetSettings,
+    BUNDLE_ID_HEADER, SNEAKERNET_MEDIA_TYPE,
+};
+use crate::spool::{SpoolUsage, TransferContent, TransferSpool, TransferSpoolSettings};
+use crate::store::ControlPlaneStore;
+#[cfg(feature = "transfers")]
+use crate::spool::SpoolError;
+use crate::submission_spool::{
+    SpooledSubmission, SubmissionSpool, SubmissionSpoolError, SubmissionSpoolSettings,
+    SubmissionSpoolStatus, SPOOLED_STATUS,
+};
+use crate::submissions::{ClientBudgets, SubmissionSettings};
+use crate::trace::{timeline, transport_hint, RoutingSettings};
+use crate::transforms::{
+    TransformError, TransformRegistry, TransformSettings, TransformStage, TRANSFORM_TRACE_INCLUDE,
+};
+#[cfg(feature = "status-page")]
+use crate::ui::{
+    JobSummary, NodeSummary, TransferSummary, UiSnapshot, SNAPSHOT_JOBS, SNAPSHOT_LOG_LINES,
+    SNAPSHOT_TRANSFERS, STATUS_PAGE,
+};
+use crate::webhooks::WebhookSettings;
+#[cfg(feature = "webhooks")]
+use crate::webhooks::{new_subscription, WebhookRequest, WebhookView, WEBHOOK_NOT_FOUND_ERROR};
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NodeConfig {
     pub rpc_endpoint: String,
@@ -30,7 +237,193 @@ pub struct NodeConfig {
     pub http_auth_token: Option<String>,
     pub sqlite_path: String,
     pub acl_mode: String,
+    // How identity hashes from clients, config and the mesh are checked.
+    #[serde(default)]
+    pub identity_validation: IdentityValidation,
     pub prefer_link: bool,
+    #[serde(default = "default_compression_threshold")]
+    pub compression_threshold_bytes: usize,
+    #[serde(default = "default_transfer_stall_after_secs")]
+    pub transfer_stall_after_secs: u64,
+    #[serde(default = "default_result_stream_timeout_secs")]
+    pub result_stream_timeout_secs: u64,
+    #[serde(default = "default_max_link_bytes")]
+    pub max_link_bytes: usize,
+    #[serde(default = "default_max_lxmf_bytes")]
+    pub max_lxmf_bytes: usize,
+    #[serde(default)]
+    pub api_tokens: Vec<ApiToken>,
+    #[serde(default)]
+    pub tls_principals: BTreeMap<String, String>,
+    #[serde(default)]
+    pub status_page: bool,
+    #[serde(default)]
+    pub notifications: NotificationSettings,
+    #[serde(default)]
+    pub inbound: InboundSettings,
+    #[serde(default)]
+    pub codec_limits: CodecLimits,
+    #[serde(default)]
+    pub identity: IdentitySettings,
+    #[serde(default)]
+    pub operation_defaults: BTreeMap<String, OperationDefaults>,
+    #[serde(default)]
+    pub allow_sunset_operations: bool,
+    #[serde(default)]
+    pub attachments: AttachmentSettings,
+    #[serde(default)]
+    pub retention: RetentionSettings,
+    #[serde(default)]
+    pub submissions: SubmissionSettings,
+    #[serde(default)]
+    pub delivery: DeliverySettings,
+    #[serde(default)]
+    pub aggregates: AggregateSettings,
+    #[serde(default)]
+    pub liveness: LivenessSettings,
+    #[serde(default)]
+    pub job_watchdog: JobWatchdogSettings,
+    #[serde(default)]
+    pub transfer_dedup: TransferDedupSettings,
+    #[serde(default)]
+    pub transfer_bundles: BundleSettings,
+    #[serde(default)]
+    pub quotas: QuotaSettings,
+    #[serde(default)]
+    pub routing: RoutingSettings,
+    #[serde(default)]
+    pub dependencies: DependencySettings,
+    #[serde(default)]
+    pub transforms: Vec<TransformSettings>,
+    #[serde(default)]
+    pub event_feed: EventFeedSettings,
+    #[serde(default)]
+    pub consistency: ConsistencySettings,
+    #[serde(default)]
+    pub clock: ClockSettings,
+    #[serde(default)]
+    pub payload_migrations: PayloadMigrationSettings,
+    #[serde(default)]
+    pub files: FileSettings,
+    #[serde(default)]
+    pub transfer_spool: TransferSpoolSettings,
+    #[serde(default)]
+    pub submission_spool: SubmissionSpoolSettings,
+    #[serde(default)]
+    pub result_validation: ResultValidationSettings,
+    #[serde(default)]
+    pub dispatch_queue: DispatchQueueSettings,
+    #[serde(default)]
+    pub sneakernet: SneakernetSettings,
+    #[serde(default)]
+    pub crash_reports: CrashReportSettings,
+    #[serde(default)]
+    pub config_apply: ConfigApplySettings,
+    #[serde(default)]
+    pub fleet: FleetSettings,
+    #[serde(default)]
+    pub pagination: PaginationSettings,
+    #[serde(default)]
+    pub webhooks: WebhookSettings,
+    #[serde(default)]
+    pub result_cache: ResultCacheSettings,
+    #[serde(default)]
+    pub event_coalescing: EventCoalescingSettings,
+    #[serde(default)]
+    pub security: SecuritySettings,
+    #[serde(default)]
+    pub receipts: ReceiptSettings,
+}
+
+fn default_compression_threshold() -> usize {
+    DEFAULT_COMPRESSION_THRESHOLD
+}
+
+pub const DEFAULT_TRANSFER_STALL_AFTER_SECS: u64 = 120;
+
+fn default_transfer_stall_after_secs() -> u64 {
+    DEFAULT_TRANSFER_STALL_AFTER_SECS
+}
+
+pub const DEFAULT_RESULT_STREAM_TIMEOUT_SECS: u64 = 300;
+
+fn default_result_stream_timeout_secs() -> u64 {
+    DEFAULT_RESULT_STREAM_TIMEOUT_SECS
+}
+
+fn default_max_link_bytes() -> usize {
+    DEFAULT_MAX_LINK_BYTES
+}
+
+fn default_max_lxmf_bytes() -> usize {
+    DEFAULT_MAX_LXMF_BYTES
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ApiToken {
+    pub label: String,
+    pub token: String,
+}
+
+// Names read from a verified client certificate: the subject, its common name and any DNS,
+// email or URI subject alternative names. Inserted as a request extension by the TLS listener.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientPrincipal {
+    pub names: Vec<String>,
+}
+
+pub const CLIENT_PRINCIPAL_HEADER: &str = "x-retasync-client-principal";
+
+// The listener a request arrived on, inserted as a request extension by the server. A unix
+// socket listener may waive bearer auth for local callers while a TCP one enforces it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestListener {
+    pub name: String,
+    pub require_bearer: bool,
+}
+
+const LISTENER_HEADER: &str = "x-retasync-listener";
+const LISTENER_AUTH_HEADER: &str = "x-retasync-listener-auth";
+const REMOTE_ADDR_HEADER: &str = "x-retasync-remote-addr";
+pub const ADMIN_PRINCIPAL_ROLE: &str = "admin";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NotificationSettings {
+    pub event_types: Vec<String>,
+    pub retention_hours: i64,
+    pub max_unacked: i64,
+}
+
+impl Default for NotificationSettings {
+    fn default() -> Self {
+        Self {
+            event_types: vec![
+                "job.status.changed".to_string(),
+                "transfer.*".to_string(),
+                "security.*".to_string(),
+            ],
+            retention_hours: 72,
+            max_unacked: 1000,
+        }
+    }
+}
+
+impl NotificationSettings {
+    pub fn records(&self, event_type: &str) -> bool {
+        self.event_types
+            .iter()
+            .any(|pattern| event_type_matches(pattern, event_type))
+    }
+}
+
+// A trailing `*` matches any event type with that prefix; anything else matches exactly.
+pub fn event_type_matches(pattern: &str, event_type: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => event_type.starts_with(prefix),
+        None => pattern == event_type,
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -39,17 +432,105 @@ pub struct NodeStatus {
     pub ready: bool,
     pub daemon_connected: bool,
     pub timestamp: String,
+    #[serde(default)]
+    pub checks: Vec<CheckResult>,
+    #[serde(default)]
+    pub availability_last_hour: Option<Availability>,
+    #[serde(default)]
+    pub capabilities_digest: String,
+    #[serde(default)]
+    pub oversize_rejections: BTreeMap<String, u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transport: Option<TransportStatus>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bridge_calls: Option<BTreeMap<String, CallMetrics>>,
+    // Per operation, from the dispatch attempt log.
+    #[serde(default)]
+    pub dispatch_latency: BTreeMap<String, LatencyHistogram>,
+    // Per operation, from the built-in handlers answering inbound commands.
+    #[serde(default)]
+    pub handler_latency: BTreeMap<String, LatencyHistogram>,
+    #[serde(default)]
+    pub storage_integrity: IntegrityStats,
+    #[serde(default)]
+    pub storage_pools: PoolStats,
+    #[serde(default)]
+    pub transfer_dedup: DedupMetrics,
+    #[serde(default)]
+    pub result_reuse: ResultReuseMetrics,
+    // Pass back in `X-Retasync-Consistency` to read at least this node's current state.
+    #[serde(default)]
+    pub write_sequence: i64,
+    #[serde(default)]
+    pub mute: MuteStatus,
+    #[serde(default)]
+    pub transfer_spool: SpoolUsage,
+    #[serde(default)]
+    pub submission_spool: SubmissionSpoolStatus,
+    #[serde(default)]
+    pub event_coalescing: CoalescingMetrics,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SseUpdate {
+    #[serde(default)]
+    pub id: u64,
     pub event_type: String,
     pub data: Value,
+    // Set when push delivery may collapse this event with later ones for the same subject.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub coalesce_subject: Option<String>,
+}
+
+pub const EVENT_REPLAY_CAPACITY: usize = 256;
+
+// The most recent events with their stream ids, so a client reconnecting with `Last-Event-ID`
+// picks up where it dropped off.
+#[derive(Debug, Default)]
+pub struct RecentEvents {
+    last_id: u64,
+    events: VecDeque<SseUpdate>,
+}
+
+impl RecentEvents {
+    pub(crate) fn push(&mut self, event_type: &str, data: Value) -> SseUpdate {
+        self.last_id += 1;
+        let update = SseUpdate {
+            id: self.last_id,
+            event_type: event_type.to_string(),
+            coalesce_subject: coalescing_subject(event_type, &data),
+            data,
+        };
+        if self.events.len() == EVENT_REPLAY_CAPACITY {
+            self.events.pop_front();
+        }
+        self.events.push_back(update.clone());
+        update
+    }
+
+    // Events after `last_id`, whether some of them were already dropped, and the newest id
+    // issued so far. An id this buffer never issued predates a restart, so everything is replayed.
+    #[cfg(feature = "sse")]
+    fn since(&self, last_id: Option<u64>) -> (Vec<SseUpdate>, bool, u64) {
+        let Some(last_id) = last_id else {
+            return (Vec::new(), false, self.last_id);
+        };
+        let restarted = last_id > self.last_id;
+        let last_id = if restarted { 0 } else { last_id };
+        let oldest = self.events.front().map_or(self.last_id + 1, |oldest| oldest.id);
+        let events = self
+            .events
+            .iter()
+            .filter(|update| update.id > last_id)
+            .cloned()
+            .collect();
+        (events, restarted || oldest > last_id + 1, self.last_id)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LogLine {
-    pub timestamp: String,
+    pub timestamp: CanonicalTimestamp,
     pub level: String,
     pub message: String,
 }
@@ -57,15 +538,48 @@ pub struct LogLine {
 #[derive(Debug, Deserialize)]
 pub struct LogQuery {
     pub since: Option<String>,
+    pub until: Option<String>,
     pub level: Option<String>,
     pub contains: Option<String>,
     pub limit: Option<usize>,
 }
 
+const MAX_HEALTH_BUCKETS: i64 = 10_000;
+const JOB_EXPORT_CHUNK: i64 = 500;
+const CONTENT_TYPE_NDJSON: &str = "application/x-ndjson";
+pub(crate) const ALLOWLIST_ROLES: [&str; 3] = ["peer", "relay", "admin-peer"];
+const ALLOWLIST_STATUSES: [&str; 3] = ["active", "pending", "expired"];
+
 #[derive(Debug, Deserialize)]
 struct AddAllowlistRequest {
     identity_hash: String,
     note: Option<String>,
+    role: Option<String>,
+    status: Option<String>,
+    expires_at: Option<String>,
+    // Base64 X25519 key for sealed payloads; left as it was when omitted.
+    public_key: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct QuotaOverrideRequest {
+    max_bytes: u64,
+    note: Option<String>,
+    // `destination` (the default) or `token`; the same name can be both.
+    subject_kind: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AllowlistQuery {
+    status: Option<String>,
+    expiring_within_secs: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct HealthHistoryQuery {
+    window: Option<String>,
+    resolution: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -73,15 +587,295 @@ struct ListQuery {
     limit: Option<i64>,
 }
 
+#[derive(Debug, Deserialize)]
+struct SentSinceQuery {
+    since: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CursorQuery {
+    limit: Option<i64>,
+    cursor: Option<String>,
+}
+
+// Extracted alongside an endpoint's own query; see `response_shape`.
+#[derive(Debug, Default, Deserialize)]
+struct ShapeQuery {
+    // Comma-separated top-level fields to keep.
+    fields: Option<String>,
+    // Leave payload bodies out, keeping their size and digest.
+    omit_payload: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+struct NodeConfigUpdateQuery {
+    // Store the config for `POST /v1/node/config/apply` instead of applying it.
+    stage: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OperationExampleQuery {
+    // Wrap the payload in the envelope a submission of it would carry.
+    envelope: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ContractReloadQuery {
+    // Swap even when queued jobs name operations the new contract refuses.
+    force: Option<bool>,
+}
+
+#[cfg(feature = "transfers")]
+#[derive(Debug, Deserialize)]
+struct FileQuery {
+    // Quarantined files are only listed and served to admin callers.
+    include_quarantined: Option<bool>,
+    limit: Option<i64>,
+    cursor: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CommandSubmitQuery {
+    force: Option<bool>,
+    // Ping the destination with `node.ping` first and only send the command if it answers.
+    probe: Option<bool>,
+    // Report what every pre-dispatch stage would decide, without creating a job.
+    dry_run: Option<bool>,
+    // Digest of the result the caller already holds; carried in the envelope's metadata block.
+    if_result_digest_differs: Option<String>,
+}
+
+impl CommandSubmitQuery {
+    fn conditional_meta(&self) -> Option<EnvelopeMeta> {
+        let digest = self.if_result_digest_differs.clone()?;
+        Some(EnvelopeMeta {
+            if_result_digest_differs: Some(digest),
+            ..EnvelopeMeta::default()
+        })
+    }
+}
+
+#[cfg(feature = "transfers")]
+#[derive(Debug, Deserialize)]
+struct TransferListQuery {
+    limit: Option<i64>,
+    cursor: Option<String>,
+    stalled: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PageQuery {
+    limit: Option<i64>,
+    cursor: Option<String>,
+    #[cfg(feature = "transfers")]
+    stalled: Option<bool>,
+    status: Option<String>,
+    expiring_within_secs: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+struct TransferView {
+    #[serde(flatten)]
+    record: TransferRecord,
+    progress: Option<TransferProgress>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dedup: Option<TransferDedup>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    members: Vec<BundleMember>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source: Option<SubmissionSource>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    labels: Labels,
+}
+
+impl TransferView {
+    fn into_v2(self) -> anyhow::Result<v2::Transfer> {
+        let transfer = v2::Transfer::from_record(self.record, self.progress, self.dedup)?;
+        Ok(v2::Transfer {
+            labels: self.labels,
+            ..transfer
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct JobView {
+    #[serde(flatten)]
+    record: JobRecord,
+    attachments: Vec<TransferView>,
+    #[serde(flatten)]
+    extras: JobExtras,
+}
+
+// `JobView` for a shaped request, which always carries the payload's size and digest and the
+// payload itself only when it is selected.
+#[derive(Debug, Serialize)]
+struct JobSummaryView {
+    #[serde(flatten)]
+    summary: retasync_storage::JobSummary,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    payload_json: Option<String>,
+    attachments: Vec<TransferView>,
+    #[serde(flatten)]
+    extras: JobExtras,
+}
+
+// The submission source and whatever `?include=` asked for.
+#[derive(Debug, Default, Serialize)]
+struct JobExtras {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    transform_trace: Option<Vec<JobTransformTrace>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dispatch_attempts: Option<DispatchAttemptSummary>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source: Option<SubmissionSource>,
+    // Canonical digest of the result. When `result_unchanged`, the submitter already held it and
+    // no result was stored; `result_source` says whether the mesh or the local cache answered.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result_digest: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result_unchanged: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result_source: Option<String>,
+    // Set when the received result broke its result schema under the `flag` or `quarantine`
+    // policy, with the violations found.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result_nonconforming: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result_errors: Option<Vec<SchemaViolation>>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    labels: Labels,
+}
+
+#[cfg(feature = "entities")]
+#[derive(Debug, Serialize)]
+struct EntityView {
+    #[serde(flatten)]
+    summary: EntitySummary,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    record: Option<Value>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    labels: Labels,
+}
+
+#[cfg(feature = "entities")]
+#[derive(Debug, Serialize)]
+struct LabelledEntity {
+    #[serde(flatten)]
+    entity: EntityRecord,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    labels: Labels,
+}
+
+// The top-level fields `?fields=` may name on each shaped v1 endpoint.
+const JOB_FIELDS: &[&str] = &[
+    "job_id",
+    "operation",
+    "status",
+    "payload_json",
+    "submitted_at",
+    "updated_at",
+    "failure_reason",
+    "dispatch_json",
+    "requested_operation",
+    "payload_bytes",
+    "payload_sha256",
+    "attachments",
+    "transform_trace",
+    "dispatch_attempts",
+    "source",
+    "result_digest",
+    "result_unchanged",
+    "result_source",
+    "result_nonconforming",
+    "result_errors",
+    "labels",
+];
+#[cfg(feature = "transfers")]
+const TRANSFER_FIELDS: &[&str] = &[
+    "transfer_id",
+    "status",
+    "metadata_json",
+    "submitted_at",
+    "updated_at",
+    "failure_reason",
+    "job_id",
+    "progress",
+    "dedup",
+    "members",
+    "source",
+    "labels",
+];
+const CACHED_FIELDS: &[&str] = &["id", "name", "received_at", "payload_bytes", "payload_sha256"];
+#[cfg(feature = "entities")]
+const ENTITY_FIELDS: &[&str] = &[
+    "entity_type",
+    "entity_id",
+    "updated_at",
+    "record",
+    "version",
+    "deleted_at",
+    "record_bytes",
+    "record_sha256",
+    "labels",
+];
+
+#[derive(Debug, Default, Deserialize)]
+struct JobQuery {
+    // Comma-separated extras: `transform_trace` and `dispatch_attempts`.
+    include: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AckNotificationsRequest {
+    up_to_seq: i64,
+}
+
 #[derive(Clone)]
 pub struct AppState {
     pub storage: RetasyncStorage,
+    // Entity data; `storage` itself unless the host supplied another store.
+    pub store: Arc<dyn ControlPlaneStore>,
     pub bridge: Arc<dyn RpcMeshBridge>,
     pub node_config: Arc<RwLock<NodeConfig>>,
-    pub contract_doc: Arc<String>,
+    pub contract: Arc<ContractStore>,
+    pub deprecated_usage: Arc<std::sync::Mutex<BTreeMap<String, u64>>>,
+    pub v1_usage: Arc<std::sync::Mutex<BTreeMap<String, u64>>>,
+    pub oversize_rejections: Arc<std::sync::Mutex<BTreeMap<String, u64>>>,
+    pub dedup_metrics: Arc<std::sync::Mutex<DedupMetrics>>,
     pub sse_bus: broadcast::Sender<SseUpdate>,
+    pub recent_events: Arc<std::sync::Mutex<RecentEvents>>,
+    pub notification_bus: broadcast::Sender<NotificationRecord>,
     pub log_buffer: Arc<RwLock<Vec<LogLine>>>,
     pub require_bearer: bool,
+    pub simulation: Option<Arc<SimulatedMeshBridge>>,
+    pub peers: PeerDirectory,
+    pub handshakes: Arc<HandshakeAttempts>,
+    pub inbound: Arc<InboundQueue>,
+    pub submission_budget: Arc<std::sync::Mutex<ClientBudgets>>,
+    #[cfg(feature = "transfers")]
+    pub bundle_assembly: Arc<std::sync::Mutex<BundleAssembly>>,
+    #[cfg(feature = "transfers")]
+    pub upload_digests: Arc<std::sync::Mutex<UploadDigests>>,
+    pub transforms: Arc<TransformRegistry>,
+    pub migrations: Arc<MigrationRegistry>,
+    pub features: Arc<FeatureFlags>,
+    pub outstanding_chunks: Arc<std::sync::Mutex<OutstandingChunks>>,
+    pub mute: Arc<NodeMute>,
+    pub circuits: Arc<CircuitBreakers>,
+    pub dispatch_latencies: Arc<DispatchLatencies>,
+    pub content_inspector: Arc<dyn ContentInspector>,
+    pub transfer_spool: Arc<TransferSpool>,
+    pub submission_spool: Arc<SubmissionSpool>,
+    pub dispatch_queue: Arc<DispatchQueue>,
+    pub notifier: Arc<EventNotifier>,
+    pub pending_config: Arc<tokio::sync::Mutex<Option<PendingConfig>>>,
+    pub cursor_keys: Arc<CursorKeys>,
+    pub storage_rebuild: Arc<std::sync::Mutex<Option<RebuildRun>>>,
+    pub effective_config: Option<Arc<EffectiveConfig>>,
+    pub handlers: Arc<HandlerRegistry>,
+    pub result_cache: Arc<ResultCache>,
+    pub event_subscribers: Arc<EventSubscribers>,
 }
 
 impl AppState {
@@ -93,46 +887,478 @@ impl AppState {
         require_bearer: bool,
     ) -> Self {
         let (sse_bus, _) = broadcast::channel(256);
+        let (notification_bus, _) = broadcast::channel(256);
+        let inbound = Arc::new(InboundQueue::new(node_config.inbound.clone()));
+        let transforms = Arc::new(TransformRegistry::new(&node_config));
+        let features = Arc::new(FeatureFlags::new(&node_config));
+        let contract = LoadedContract::lenient(contract_doc);
+        let submission_spool = node_config
+            .submission_spool
+            .path_for(storage.database_path());
+        let storage = storage.with_contract_version(contract.registry.version());
         Self {
+            store: Arc::new(storage.clone()),
             storage,
             bridge,
             node_config: Arc::new(RwLock::new(node_config)),
-            contract_doc: Arc::new(contract_doc),
+            contract: Arc::new(ContractStore::new(contract)),
+            deprecated_usage: Arc::new(std::sync::Mutex::new(BTreeMap::new())),
+            v1_usage: Arc::new(std::sync::Mutex::new(BTreeMap::new())),
+            oversize_rejections: Arc::new(std::sync::Mutex::new(BTreeMap::new())),
+            dedup_metrics: Arc::new(std::sync::Mutex::new(DedupMetrics::default())),
             sse_bus,
+            recent_events: Arc::new(std::sync::Mutex::new(RecentEvents::default())),
+            notification_bus,
             log_buffer: Arc::new(RwLock::new(Vec::new())),
             require_bearer,
+            simulation: None,
+            peers: PeerDirectory::new(),
+            handshakes: Arc::new(HandshakeAttempts::default()),
+            inbound,
+            submission_budget: Arc::new(std::sync::Mutex::new(ClientBudgets::default())),
+            #[cfg(feature = "transfers")]
+            bundle_assembly: Arc::new(std::sync::Mutex::new(BundleAssembly::default())),
+            #[cfg(feature = "transfers")]
+            upload_digests: Arc::new(std::sync::Mutex::new(UploadDigests::default())),
+            transforms,
+            migrations: Arc::new(MigrationRegistry::default()),
+            features,
+            outstanding_chunks: Arc::new(std::sync::Mutex::new(BTreeMap::new())),
+            mute: Arc::new(NodeMute::default()),
+            circuits: Arc::new(CircuitBreakers::default()),
+            dispatch_latencies: Arc::new(DispatchLatencies::default()),
+            content_inspector: Arc::new(NoopInspector),
+            transfer_spool: Arc::new(TransferSpool::default()),
+            submission_spool: Arc::new(SubmissionSpool::new(submission_spool)),
+            dispatch_queue: Arc::new(DispatchQueue::default()),
+            notifier: Arc::new(EventNotifier::default()),
+            pending_config: Arc::new(tokio::sync::Mutex::new(None)),
+            cursor_keys: Arc::new(CursorKeys::default()),
+            storage_rebuild: Arc::new(std::sync::Mutex::new(None)),
+            effective_config: None,
+            handlers: Arc::new(HandlerRegistry::default()),
+            result_cache: Arc::new(ResultCache::default()),
+            event_subscribers: Arc::new(EventSubscribers::default()),
+        }
+    }
+
+    pub fn with_simulation(mut self, simulation: Arc<SimulatedMeshBridge>) -> Self {
+        self.simulation = Some(simulation);
+        self
+    }
+
+    // The contract file `POST /v1/admin/contracts/reload` re-reads.
+    pub fn with_contract_source(mut self, path: impl Into<std::path::PathBuf>) -> Self {
+        self.contract = Arc::new(self.contract.sourced_from(path));
+        self
+    }
+
+    pub fn with_content_inspector(mut self, inspector: Arc<dyn ContentInspector>) -> Self {
+        self.content_inspector = inspector;
+        self
+    }
+
+    pub fn with_store(mut self, store: Arc<dyn ControlPlaneStore>) -> Self {
+        self.store = store;
+        self
+    }
+
+    // The config as loaded at startup, before any `PUT /v1/node/config`.
+    pub fn with_effective_config(mut self, effective: EffectiveConfig) -> Self {
+        self.effective_config = Some(Arc::new(effective));
+        self
+    }
+}
+
+// One row per resource path; a row with only a `v2` handler has no `/v1` counterpart.
+struct ApiRoute {
+    path: &'static str,
+    v1: Option<MethodRouter<AppState>>,
+    v2: Option<MethodRouter<AppState>>,
+}
+
+impl ApiRoute {
+    fn v1(path: &'static str, v1: MethodRouter<AppState>) -> Self {
+        Self {
+            path,
+            v1: Some(v1),
+            v2: None,
+        }
+    }
+
+    fn both(path: &'static str, v1: MethodRouter<AppState>, v2: MethodRouter<AppState>) -> Self {
+        Self {
+            path,
+            v1: Some(v1),
+            v2: Some(v2),
+        }
+    }
+
+    fn v2(path: &'static str, v2: MethodRouter<AppState>) -> Self {
+        Self {
+            path,
+            v1: None,
+            v2: Some(v2),
         }
     }
 }
 
+fn api_routes() -> Vec<ApiRoute> {
+    vec![
+        ApiRoute::v2("/health/live", get(health_live_v2)),
+        ApiRoute::v2("/health/ready", get(health_ready_v2)),
+        ApiRoute::v1("/node/status", get(node_status)),
+        ApiRoute::v1("/node/capabilities", get(get_capabilities)),
+        ApiRoute::v1("/node/config", get(node_config).put(update_node_config)),
+        ApiRoute::v1(
+            "/node/config/apply",
+            get(get_config_apply).post(post_config_apply),
+        ),
+        ApiRoute::v1("/node/config/confirm", post(post_config_confirm)),
+        ApiRoute::v1("/node/config/schema", get(get_config_schema)),
+        ApiRoute::v1("/node/config/effective", get(get_effective_config)),
+        ApiRoute::v1("/node/features", get(get_features).put(put_features)),
+        ApiRoute::v1("/node/features/{name}", put(put_feature)),
+        ApiRoute::v1("/node/queue", get(node_queue)),
+        ApiRoute::v1("/node/queue/order", put(put_queue_order)),
+        ApiRoute::v1(
+            "/node/mute",
+            get(get_mute).post(post_mute).delete(delete_mute),
+        ),
+        #[cfg(feature = "status-page")]
+        ApiRoute::v1("/ui/snapshot", get(get_ui_snapshot)),
+        ApiRoute::v1("/node/health/history", get(node_health_history)),
+        ApiRoute::v1("/node/stats/clients", get(node_client_stats)),
+        ApiRoute::v2("/node/api-usage", get(get_api_usage)),
+        ApiRoute::v1("/contracts/asyncapi", get(get_contract)),
+        ApiRoute::v1("/contracts/deprecations", get(list_deprecations)),
+        ApiRoute::v1("/contracts/operations", get(list_operations)),
+        ApiRoute::v1(
+            "/contracts/operations/{operation}/example",
+            get(get_operation_example),
+        ),
+        ApiRoute::v1("/jobs/aggregate", get(aggregate_jobs)),
+        ApiRoute::v1("/jobs/export", get(export_jobs)),
+        ApiRoute::v1("/labels/keys", get(list_label_keys)),
+        ApiRoute::both("/jobs/{job_id}", get(get_job), get(get_job_v2)),
+        ApiRoute::v1("/jobs/{job_id}/result", get(get_job_result)),
+        ApiRoute::v1("/jobs/{job_id}/result/parts", get(get_job_result_parts)),
+        ApiRoute::v1("/jobs/{job_id}/trace", get(get_job_trace)),
+        ApiRoute::v1("/jobs/{job_id}/receipt", get(get_job_receipt)),
+        ApiRoute::v1("/jobs/{job_id}/attempts", get(get_job_attempts)),
+        ApiRoute::v1("/jobs/{job_id}/dependents", get(get_job_dependents)),
+        ApiRoute::v1("/jobs/{job_id}/labels", put(put_job_labels)),
+        ApiRoute::v1("/jobs/{job_id}/cancel", post(cancel_job)),
+        ApiRoute::v1("/jobs/{job_id}/prioritize", post(post_job_prioritize)),
+        ApiRoute::v1("/jobs/{job_id}/hold", post(post_job_hold)),
+        ApiRoute::v1("/jobs/{job_id}/release", post(post_job_release)),
+        ApiRoute::both(
+            "/jobs/commands/{operation}",
+            post(post_command_job),
+            post(post_command_job_v2),
+        ),
+        ApiRoute::v1("/jobs/commands:batch", post(post_command_batch)),
+        #[cfg(feature = "transfers")]
+        ApiRoute::v1("/jobs/transfers/upload", post(post_transfer_job)),
+        #[cfg(feature = "transfers")]
+        ApiRoute::v1("/jobs/transfers/bundle", post(post_bundle_transfer_job)),
+        #[cfg(feature = "transfers")]
+        ApiRoute::v1("/files", get(list_received_files)),
+        #[cfg(feature = "transfers")]
+        ApiRoute::v1("/files/{sha256}", get(download_received_file)),
+        #[cfg(feature = "transfers")]
+        ApiRoute::both("/transfers", get(list_transfers), get(list_transfers_v2)),
+        #[cfg(feature = "transfers")]
+        ApiRoute::both(
+            "/transfers/{transfer_id}",
+            get(get_transfer).delete(cancel_transfer),
+            get(get_transfer_v2),
+        ),
+        #[cfg(feature = "transfers")]
+        ApiRoute::v1("/transfers/{transfer_id}/receipt", get(get_transfer_receipt)),
+        ApiRoute::v1("/cache/events", get(get_cached_events)),
+        ApiRoute::v1("/cache/events/aggregate", get(aggregate_cached_events)),
+        ApiRoute::v1("/cache/messages", get(get_cached_messages)),
+        ApiRoute::v1("/logs", get(get_logs)),
+        #[cfg(feature = "sse")]
+        ApiRoute::v1("/logs/stream", get(stream_logs)),
+        ApiRoute::v1("/events/feed", get(get_event_feed)),
+        ApiRoute::v1("/events/subscribers", get(list_event_subscribers)),
+        ApiRoute::v1("/events/feed/head", get(get_event_feed_head)),
+        ApiRoute::v1("/notifications", get(list_notifications)),
+        ApiRoute::v1("/notifications/ack", post(ack_notifications)),
+        #[cfg(feature = "sse")]
+        ApiRoute::v1("/notifications/stream", get(stream_notifications)),
+        ApiRoute::both(
+            "/security/allowlist",
+            get(get_allowlist).post(add_allowlist),
+            get(get_allowlist_v2),
+        ),
+        ApiRoute::v1(
+            "/security/allowlist/{identity_hash}",
+            delete(delete_allowlist),
+        ),
+        ApiRoute::v1(
+            "/security/allowlist/{identity_hash}/approve",
+            post(approve_allowlist),
+        ),
+        ApiRoute::v1("/security/quotas", get(get_quotas)),
+        ApiRoute::v1("/security/quotas/{identity}", put(put_quota_override)),
+        ApiRoute::v1("/peers", get(list_peers)),
+        ApiRoute::v1("/peers/{identity_hash}", get(get_peer)),
+        #[cfg(feature = "entities")]
+        ApiRoute::v1("/entities/{entity_type}/sync", post(sync_entities)),
+        #[cfg(feature = "entities")]
+        ApiRoute::v1(
+            "/entities/{entity_type}/conflicts",
+            get(list_entity_conflicts),
+        ),
+        #[cfg(feature = "entities")]
+        ApiRoute::v1(
+            "/entities/{entity_type}/{entity_id}",
+            get(get_entity_record)
+                .put(put_entity_record)
+                .delete(delete_entity_record),
+        ),
+        ApiRoute::v1(
+            "/peers/{identity_hash}/capabilities",
+            put(update_peer_capabilities),
+        ),
+        ApiRoute::v1("/peers/{identity_hash}/handshake", post(handshake_peer)),
+        ApiRoute::v1("/peers/{identity_hash}/clock", get(get_peer_clock)),
+        ApiRoute::v1("/fleet/nodes", get(list_fleet_nodes)),
+        ApiRoute::v1("/fleet/nodes/{identity_hash}", get(get_fleet_node)),
+        #[cfg(feature = "webhooks")]
+        ApiRoute::v1("/webhooks", get(list_webhooks).post(create_webhook)),
+        #[cfg(feature = "webhooks")]
+        ApiRoute::v1(
+            "/webhooks/{subscription_id}",
+            get(get_webhook).delete(delete_webhook),
+        ),
+        #[cfg(feature = "webhooks")]
+        ApiRoute::v1(
+            "/webhooks/{subscription_id}/deliveries",
+            get(list_webhook_deliveries),
+        ),
+        ApiRoute::v1(
+            "/admin/simulation",
+            get(get_simulation).post(update_simulation),
+        ),
+        ApiRoute::v1("/admin/contracts/reload", post(reload_contract)),
+        ApiRoute::v1("/admin/archives", get(list_archives)),
+        ApiRoute::v1("/admin/archives/{name}", get(download_archive)),
+        ApiRoute::v1(
+            "/admin/storage/quarantine",
+            get(list_quarantine).delete(purge_quarantine),
+        ),
+        ApiRoute::v1(
+            "/admin/storage/rebuild",
+            get(get_storage_rebuild).post(post_storage_rebuild),
+        ),
+        ApiRoute::v1("/admin/bundles/key", get(get_bundle_signer)),
+        ApiRoute::v1("/admin/bundles/export", post(export_sneakernet_bundle)),
+        ApiRoute::v1("/admin/bundles/import", post(import_sneakernet_bundle)),
+        ApiRoute::v1("/admin/crashes", get(list_crash_reports)),
+        ApiRoute::v1("/admin/crashes/{crash_id}", get(get_crash_report)),
+    ]
+}
+
 pub fn build_router(state: AppState) -> Router {
-    Router::new()
+    let mut v1 = Router::new();
+    let mut v2 = Router::new();
+    let mut successors = BTreeSet::new();
+    for route in api_routes() {
+        if let Some(handler) = route.v1 {
+            v1 = v1.route(&format!("/v1{}", route.path), handler);
+        }
+        if let Some(handler) = route.v2 {
+            successors.insert(format!("/v1{}", route.path));
+            v2 = v2.route(&format!("/v2{}", route.path), handler);
+        }
+    }
+    let deprecation = V1Deprecation {
+        usage: state.v1_usage.clone(),
+        successors: Arc::new(successors),
+    };
+
+    let router = Router::new();
+    #[cfg(feature = "status-page")]
+    let router = router.route("/", get(status_page));
+    router
         .route("/health/live", get(health_live))
         .route("/health/ready", get(health_ready))
-        .route("/v1/node/status", get(node_status))
-        .route("/v1/node/config", get(node_config).put(update_node_config))
-        .route("/v1/contracts/asyncapi", get(get_contract))
-        .route("/v1/jobs/{job_id}", get(get_job))
-        .route("/v1/jobs/{job_id}/result", get(get_job_result))
-        .route("/v1/jobs/commands/{operation}", post(post_command_job))
-        .route("/v1/jobs/transfers/upload", post(post_transfer_job))
-        .route("/v1/transfers/{transfer_id}", get(get_transfer))
-        .route("/v1/cache/events", get(get_cached_events))
-        .route("/v1/cache/messages", get(get_cached_messages))
-        .route("/v1/logs", get(get_logs))
-        .route("/v1/logs/stream", get(stream_logs))
-        .route(
-            "/v1/security/allowlist",
-            get(get_allowlist).post(add_allowlist),
-        )
-        .route(
-            "/v1/security/allowlist/{identity_hash}",
-            delete(delete_allowlist),
-        )
+        .merge(v1.route_layer(middleware::from_fn_with_state(deprecation, deprecate_v1)))
+        .merge(v2)
+        .layer(middleware::from_fn_with_state(state.clone(), mark_muted))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            enforce_consistency,
+        ))
+        .layer(middleware::from_fn(attach_client_principal))
+        .layer(middleware::from_fn(retry_after_header))
         .with_state(state)
 }
 
-async fn health_live() -> impl IntoResponse {
+// The node's routes under `prefix` (such as `/mesh`), for a host application to merge into its
+// own router and wrap in its own layers. Background work is started separately through
+// `ControlPlaneRuntime`.
+pub fn embedded_router(state: AppState, prefix: &str) -> Router {
+    Router::new().nest(prefix, build_router(state))
+}
+
+#[derive(Clone)]
+struct V1Deprecation {
+    usage: Arc<std::sync::Mutex<BTreeMap<String, u64>>>,
+    successors: Arc<BTreeSet<String>>,
+}
+
+// /v1 keeps its response shapes; it only gains the headers and a per-route usage count.
+async fn deprecate_v1(
+    State(deprecation): State<V1Deprecation>,
+    request: Request,
+    next: Next,
+) -> Response {
+    // Under `embedded_router` the matched path carries the host's prefix as well.
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|matched| {
+            let matched = matched.as_str();
+            matched[matched.find("/v1/").unwrap_or(0)..].to_string()
+        })
+        .unwrap_or_default();
+    let path = request
+        .extensions()
+        .get::<OriginalUri>()
+        .map_or(request.uri().path(), |original| original.path());
+    let successor = deprecation
+        .successors
+        .contains(&route)
+        .then(|| path.replacen("/v1/", "/v2/", 1));
+    *deprecation
+        .usage
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .entry(format!("{} {route}", request.method()))
+        .or_default() += 1;
+
+    let mut response = next.run(request).await;
+    let headers = response.headers_mut();
+    headers.insert(HeaderName::from_static("deprecation"), HeaderValue::from_static("true"));
+    if let Some(successor) = successor {
+        let link = format!("<{successor}>; rel=\"successor-version\"");
+        if let (false, Ok(value)) =
+            (headers.contains_key(header::LINK), HeaderValue::from_str(&link))
+        {
+            headers.insert(header::LINK, value);
+        }
+    }
+    response
+}
+
+type V2Error = (StatusCode, Json<ErrorEnvelope>);
+
+fn v2_error((status, Json(body)): (StatusCode, Json<Value>)) -> V2Error {
+    (
+        status,
+        Json(ErrorEnvelope {
+            error: ApiError::from_v1(body),
+        }),
+    )
+}
+
+fn v2_rejection(code: &str, detail: String) -> V2Error {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(ErrorEnvelope {
+            error: ApiError {
+                code: code.to_string(),
+                detail: Some(Value::String(detail)),
+                retry: None,
+            },
+        }),
+    )
+}
+
+fn v2_internal(error: anyhow::Error) -> V2Error {
+    v2_error(internal_error(error))
+}
+
+fn cursor_error(error: CursorError) -> (StatusCode, Json<Value>) {
+    let status = if error.is_client_error() {
+        StatusCode::BAD_REQUEST
+    } else {
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+    (
+        status,
+        Json(json!({ "error": error.code(), "detail": error.to_string() })),
+    )
+}
+
+async fn cursor_codec(state: &AppState) -> Result<CursorCodec, (StatusCode, Json<Value>)> {
+    let settings = state.node_config.read().await.pagination.clone();
+    state.cursor_keys.codec(&settings).map_err(cursor_error)
+}
+
+fn response_shape(
+    query: &ShapeQuery,
+    known: &[&str],
+) -> Result<Shape, (StatusCode, Json<Value>)> {
+    Shape::parse(query.fields.as_deref(), query.omit_payload, known).map_err(|field| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": UNKNOWN_FIELD_ERROR, "field": field, "known": known })),
+        )
+    })
+}
+
+// A shaped response is a different representation, so it must not share the full one's ETag.
+fn shaped_etag(etag: String, shape: &Shape) -> String {
+    if shape.is_full() {
+        return etag;
+    }
+    compute_etag(format!("{etag}|{}", shape.etag_key()).as_bytes())
+}
+
+// List limits default to 100 and are kept to 1..=1000.
+fn page_limit(limit: Option<i64>) -> i64 {
+    limit.unwrap_or(100).clamp(1, 1000)
+}
+
+// Only the TLS listener may vouch for a client certificate, so a client-supplied principal
+// header is always dropped before the verified names are copied in. Listener and remote address
+// headers are likewise only ever set from the server's own extensions.
+async fn attach_client_principal(mut request: Request, next: Next) -> Response {
+    request.headers_mut().remove(CLIENT_PRINCIPAL_HEADER);
+    request.headers_mut().remove(LISTENER_HEADER);
+    request.headers_mut().remove(LISTENER_AUTH_HEADER);
+    request.headers_mut().remove(REMOTE_ADDR_HEADER);
+    if let Some(ConnectInfo(addr)) = request.extensions().get::<ConnectInfo<SocketAddr>>() {
+        if let Ok(value) = HeaderValue::from_str(&addr.ip().to_string()) {
+            request.headers_mut().insert(REMOTE_ADDR_HEADER, value);
+        }
+    }
+    if let Some(listener) = request.extensions().get::<RequestListener>().cloned() {
+        if let Ok(value) = HeaderValue::from_str(&listener.name) {
+            request.headers_mut().insert(LISTENER_HEADER, value);
+        }
+        let auth = if listener.require_bearer { "required" } else { "optional" };
+        request
+            .headers_mut()
+            .insert(LISTENER_AUTH_HEADER, HeaderValue::from_static(auth));
+    }
+    if let Some(principal) = request.extensions().get::<ClientPrincipal>().cloned() {
+        for name in &principal.names {
+            if let Ok(value) = HeaderValue::from_str(name) {
+                request.headers_mut().append(CLIENT_PRINCIPAL_HEADER, value);
+            }
+        }
+    }
+    next.run(request).await
+}
+
+pub(crate) async fn health_live() -> impl IntoResponse {
     Json(json!({
         "status": "live",
         "timestamp": Utc::now().to_rfc3339()
@@ -140,7 +1366,7 @@ async fn health_live() -> impl IntoResponse {
 }
 
 async fn health_ready(State(state): State<AppState>) -> impl IntoResponse {
-    let ready = state.bridge.query_receipt("readiness-probe").await.is_ok();
+    let ready = probe_ready(&state).await;
     let payload = Json(json!({
         "status": if ready { "ready" } else { "degraded" },
         "timestamp": Utc::now().to_rfc3339()
@@ -153,521 +1379,14776 @@ async fn health_ready(State(state): State<AppState>) -> impl IntoResponse {
     }
 }
 
-async fn node_status(State(state): State<AppState>) -> impl IntoResponse {
-    let ready = state.bridge.query_receipt("status-probe").await.is_ok();
-    Json(NodeStatus {
-        healthy: true,
-        ready,
-        daemon_connected: ready,
-        timestamp: Utc::now().to_rfc3339(),
-    })
+async fn probe_ready(state: &AppState) -> bool {
+    state.bridge.query_receipt("readiness-probe").await.is_ok()
 }
 
-async fn node_config(State(state): State<AppState>) -> impl IntoResponse {
-    let cfg = state.node_config.read().await.clone();
-    Json(cfg)
+async fn health_live_v2() -> Json<Envelope<Health>> {
+    Json(Envelope::new(Health {
+        status: HealthStatus::Live,
+        timestamp: Utc::now(),
+    }))
 }
 
-async fn update_node_config(
-    State(state): State<AppState>,
-    headers: HeaderMap,
-    Json(payload): Json<NodeConfig>,
-) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
-    authorize(&state, &headers, true).await?;
+async fn health_ready_v2(State(state): State<AppState>) -> Response {
+    let ready = probe_ready(&state).await;
+    let payload = Json(Envelope::new(Health {
+        status: if ready {
+            HealthStatus::Ready
+        } else {
+            HealthStatus::Degraded
+        },
+        timestamp: Utc::now(),
+    }));
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, payload).into_response()
+}
 
-    {
-        let mut guard = state.node_config.write().await;
-        *guard = payload.clone();
-    }
+async fn get_api_usage(State(state): State<AppState>) -> Json<Envelope<ApiUsage>> {
+    let v1_requests = state
+        .v1_usage
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .clone();
+    Json(Envelope::new(ApiUsage { v1_requests }))
+}
 
-    let serialized = serde_json::to_string(&payload).map_err(|e| internal_error(e.into()))?;
-    state
+async fn node_status(State(state): State<AppState>) -> impl IntoResponse {
+    Json(collect_node_status(&state).await)
+}
+
+pub(crate) async fn collect_node_status(state: &AppState) -> NodeStatus {
+    let checks = vec![
+        check_bridge(state.bridge.as_ref(), BRIDGE_PROBE_TIMEOUT).await,
+        check_storage(&state.storage).await,
+        check_contract(&state.contract.current().document),
+        check_identity_hashes(&state.storage).await,
+    ];
+    let ready = checks
+        .iter()
+        .all(|check| check.status != CheckStatus::Fail);
+    let daemon_connected = checks[0].status == CheckStatus::Pass;
+    let now = Utc::now();
+    let availability_last_hour = match state
         .storage
-        .append_node_config_revision(&serialized)
+        .list_health_samples(now - chrono::Duration::hours(1), now)
         .await
-        .map_err(internal_error)?;
-
-    write_log(&state, "info", "node config updated").await;
-    emit(
-        &state,
-        "node.config.updated",
-        json!({ "updated_at": Utc::now().to_rfc3339() }),
-    );
-
-    Ok((StatusCode::OK, Json(payload)))
+    {
+        Ok(samples) if !samples.is_empty() => Some(health::availability(&samples)),
+        Ok(_) => None,
+        Err(err) => {
+            error!(error = %err, "failed to load health samples");
+            None
+        }
+    };
+    let capabilities_digest = current_capabilities(state).await.digest();
+    let oversize_rejections = state
+        .oversize_rejections
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .clone();
+    let transfer_dedup = state
+        .dedup_metrics
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .clone();
+    let write_sequence = state.storage.write_sequence().await.unwrap_or_else(|err| {
+        error!(error = %err, "failed to read write sequence");
+        0
+    });
+    let transfer_spool = state
+        .transfer_spool
+        .usage(&state.node_config.read().await.transfer_spool);
+    let spool_max_bytes = state.node_config.read().await.submission_spool.max_bytes;
+    let submission_spool = state
+        .submission_spool
+        .status(spool_max_bytes, CanonicalTimestamp::from(now))
+        .await
+        .unwrap_or_else(|err| {
+            error!(error = %err, "failed to read the submission spool");
+            SubmissionSpoolStatus {
+                max_bytes: spool_max_bytes,
+                ..SubmissionSpoolStatus::default()
+            }
+        });
+    NodeStatus {
+        healthy: true,
+        ready,
+        daemon_connected,
+        timestamp: now.to_rfc3339(),
+        checks,
+        availability_last_hour,
+        capabilities_digest,
+        oversize_rejections,
+        transport: state.bridge.transport_status(),
+        bridge_calls: state.bridge.call_metrics(),
+        dispatch_latency: state.dispatch_latencies.snapshot(),
+        handler_latency: state.handlers.latencies(),
+        storage_integrity: state.storage.integrity_stats(),
+        storage_pools: state.storage.pool_stats(),
+        transfer_dedup,
+        result_reuse: state.result_cache.metrics(),
+        write_sequence,
+        mute: mute_status(state, now),
+        transfer_spool,
+        submission_spool,
+        event_coalescing: state.event_subscribers.metrics(),
+    }
 }
 
-async fn get_contract(State(state): State<AppState>) -> impl IntoResponse {
-    (
-        StatusCode::OK,
-        [(header::CONTENT_TYPE, "application/yaml")],
-        state.contract_doc.as_ref().clone(),
-    )
+pub(crate) async fn current_capabilities(state: &AppState) -> Capabilities {
+    let config = state.node_config.read().await;
+    node_capabilities(&config, &state.contract.current().registry, state.simulation.is_some())
 }
 
-async fn get_job(
-    State(state): State<AppState>,
-    Path(job_id): Path<String>,
-) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
-    let job = state.storage.get_job(&job_id).await.map_err(internal_error)?;
-    match job {
-        Some(record) => Ok((StatusCode::OK, Json(record))),
-        None => Err((
-            StatusCode::NOT_FOUND,
-            Json(json!({"error":"job_not_found"})),
-        )),
-    }
+async fn get_capabilities(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    let capabilities = current_capabilities(&state).await;
+    let etag = format!("\"{}\"", capabilities.digest());
+    respond_with_etag(&headers, etag, Json(capabilities))
 }
 
-async fn get_job_result(
+async fn node_health_history(
     State(state): State<AppState>,
-    Path(job_id): Path<String>,
+    Query(query): Query<HealthHistoryQuery>,
 ) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
-    let result = state
+    let window = health::parse_span(query.window.as_deref().unwrap_or("24h"))
+        .filter(|secs| *secs <= health::AGGREGATE_RETENTION_SECS)
+        .ok_or((
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error":"invalid_window"})),
+        ))?;
+    let resolution = health::parse_span(query.resolution.as_deref().unwrap_or("5m"))
+        .filter(|secs| *secs <= window && window / secs <= MAX_HEALTH_BUCKETS)
+        .ok_or((
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error":"invalid_resolution"})),
+        ))?;
+
+    let to = Utc::now();
+    let from = to - chrono::Duration::seconds(window);
+    let samples = state
         .storage
-        .get_job_result(&job_id)
+        .list_health_samples(from, to)
         .await
-        .map_err(internal_error)?;
-    match result {
-        Some(record) => Ok((StatusCode::OK, Json(record))),
-        None => Err((
-            StatusCode::NOT_FOUND,
-            Json(json!({"error":"job_result_not_found"})),
-        )),
-    }
+        .map_err(storage_error)?;
+    let history = health::summarize(&samples, from, to, resolution).map_err(internal_error)?;
+    Ok(Json(history))
 }
 
-async fn post_command_job(
+async fn node_client_stats(
     State(state): State<AppState>,
-    Path(operation): Path<String>,
     headers: HeaderMap,
-    Json(payload): Json<Value>,
+    Query(query): Query<ClientStatsQuery>,
 ) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
-    authorize(&state, &headers, true).await?;
-
-    let job = state
+    let window = health::parse_span(query.window.as_deref().unwrap_or(DEFAULT_STATS_WINDOW))
+        .filter(|secs| *secs > 0 && *secs <= MAX_STATS_WINDOW_SECS)
+        .ok_or((
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error":"invalid_window"})),
+        ))?;
+    let start = Utc::now() - chrono::Duration::seconds(window);
+    let since = CanonicalTimestamp::from(start).to_string();
+    let activity = state
         .storage
-        .create_job(&operation, payload.clone())
+        .list_client_activity(&since)
         .await
-        .map_err(internal_error)?;
-    let job_id = job.job_id.clone();
-    let submitted_at = job.submitted_at.clone();
+        .map_err(storage_error)?;
+    // Rejections are kept per hour, so they are counted from the start of the window's first hour.
+    let rejected_since = CanonicalTimestamp::from(bucket_start(start)).to_string();
+    let rejections = state
+        .storage
+        .list_client_rejections(&rejected_since)
+        .await
+        .map_err(storage_error)?;
+    let mut clients = client_stats(&activity, &rejections);
+    if !is_admin(&state, &headers).await {
+        for client in &mut clients {
+            client.remote_addrs.clear();
+        }
+    }
+    Ok(Json(json!({
+        "window_secs": window,
+        "since": since,
+        "clients": clients,
+    })))
+}
 
-    write_log(
-        &state,
-        "info",
-        &format!("job submitted for operation {}", operation),
-    )
-    .await;
+async fn node_config(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, Json<Value>)> {
+    let cfg = state.node_config.read().await.clone();
+    let etag = node_config_etag(&cfg).map_err(internal_error)?;
+    Ok(respond_with_etag(&headers, etag, Json(cfg)))
+}
 
-    emit(
-        &state,
-        "job.status.changed",
-        json!({
-            "job_id": job_id.clone(),
-            "status": "queued"
-        }),
-    );
+async fn update_node_config(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<NodeConfigUpdateQuery>,
+    Json(payload): Json<NodeConfig>,
+) -> Result<Response, (StatusCode, Json<Value>)> {
+    authorize(&state, &headers, true).await?;
+    if query.stage.unwrap_or(false) {
+        let staged_by = caller_label(&*state.node_config.read().await, &headers);
+        let status = config_apply::stage(&state, payload, staged_by, Utc::now())
+            .await
+            .map_err(config_apply_error)?;
+        return Ok((StatusCode::ACCEPTED, Json(status)).into_response());
+    }
+    if awaiting_confirmation(&state).await {
+        return Err(config_apply_error(ConfigApplyError::AwaitingConfirmation));
+    }
+    if let Err(detail) = validate_dispatch_config(&payload) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": "invalid_node_config", "detail": detail })),
+        ));
+    }
 
-    let state_for_task = state.clone();
-    let operation_for_task = operation.clone();
-    let job_id_for_task = job_id.clone();
-    let payload_for_task = payload.clone();
-    tokio::spawn(async move {
-        if let Err(err) = process_command_job(
-            state_for_task,
-            &job_id_for_task,
-            &operation_for_task,
-            payload_for_task,
-        )
-        .await
+    let etag = {
+        let mut guard = state.node_config.write().await;
+        let current_etag = node_config_etag(&guard).map_err(internal_error)?;
+        if headers.contains_key(header::IF_MATCH)
+            && !etag_matches(&headers, header::IF_MATCH, &current_etag, false)
         {
-            error!(job_id = %job_id_for_task, error = %err, "job processing failed");
+            return Ok((
+                StatusCode::PRECONDITION_FAILED,
+                [(header::ETAG, current_etag.clone())],
+                Json(json!({ "error": "config_etag_mismatch", "etag": current_etag })),
+            )
+                .into_response());
         }
-    });
-
-    Ok((
-        StatusCode::ACCEPTED,
-        Json(json!({
-            "job_id": job_id.clone(),
-            "submitted_at": submitted_at,
-            "status_url": format!("/v1/jobs/{}", job_id)
-        })),
-    ))
-}
+        configure_node(&state, &payload);
+        *guard = payload.clone();
+        node_config_etag(&payload).map_err(internal_error)?
+    };
 
-async fn process_command_job(
-    state: AppState,
-    job_id: &str,
-    operation: &str,
-    payload: Value,
-) -> anyhow::Result<()> {
+    let serialized = serde_json::to_string(&payload).map_err(|e| internal_error(e.into()))?;
     state
         .storage
-        .update_job_status(job_id, "running", None)
-        .await?;
+        .with_event(
+            "node.config.updated",
+            json!({ "updated_at": Utc::now().to_rfc3339() }),
+        )
+        .append_node_config_revision(&serialized)
+        .await
+        .map_err(storage_error)?;
 
-    emit(
-        &state,
-        "job.status.changed",
-        json!({
-            "job_id": job_id,
-            "status": "running"
-        }),
-    );
+    write_log(&state, "info", "node config updated").await;
+    publish(&state).await;
 
-    let envelope = MeshCommandEnvelope {
-        message_id: Uuid::now_v7().to_string(),
-        operation: operation.to_string(),
-        sent_at: Utc::now(),
-        source_identity: "local-node".to_string(),
-        destination_identity: payload
-            .get("destination_identity")
-            .and_then(Value::as_str)
-            .unwrap_or("mesh")
-            .to_string(),
-        content_type: "application/msgpack".to_string(),
-        payload,
-        ttl_ms: None,
-        transport_hint: None,
-    };
+    Ok((StatusCode::OK, [(header::ETAG, etag)], Json(payload)).into_response())
+}
 
-    match state.bridge.send_command(envelope).await {
-        Ok(result) => {
-            state
-                .storage
-                .insert_job_result(job_id, result.payload)
-                .await?;
-            state
-                .storage
-                .update_job_status(job_id, "success", None)
-                .await?;
-            emit(
-                &state,
-                "job.status.changed",
-                json!({ "job_id": job_id, "status": "success" }),
-            );
-            write_log(&state, "info", &format!("job {} completed", job_id)).await;
-        }
-        Err(error) => {
-            state
-                .storage
-                .update_job_status(job_id, "failed", Some(&error.to_string()))
-                .await?;
-            emit(
-                &state,
-                "job.status.changed",
-                json!({ "job_id": job_id, "status": "failed", "reason": error.to_string() }),
-            );
-            write_log(&state, "error", &format!("job {} failed", job_id)).await;
-        }
-    }
+// Swaps in `config` for everything after this call.
+pub(crate) async fn install_node_config(state: &AppState, config: NodeConfig) {
+    let mut guard = state.node_config.write().await;
+    configure_node(state, &config);
+    *guard = config;
+}
 
-    Ok(())
+fn configure_node(state: &AppState, config: &NodeConfig) {
+    state.inbound.configure(config.inbound.clone());
+    state.transforms.configure(config);
 }
 
-async fn post_transfer_job(
+async fn get_config_apply(State(state): State<AppState>) -> impl IntoResponse {
+    Json(config_apply_status(&state, Utc::now()).await)
+}
+
+async fn post_config_apply(
     State(state): State<AppState>,
     headers: HeaderMap,
-    Json(payload): Json<TransferUploadRequest>,
 ) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
     authorize(&state, &headers, true).await?;
-
-    let transfer = state
-        .storage
-        .create_transfer(json!({
-            "destination_identity": payload.destination_identity,
-            "file_name": payload.file_name,
-            "media_type": payload.media_type,
-            "payload_size": payload.payload_base64.len()
-        }))
+    let applied_by = caller_label(&*state.node_config.read().await, &headers);
+    let status = config_apply::apply(&state, applied_by, Utc::now())
         .await
-        .map_err(internal_error)?;
-    let transfer_id = transfer.transfer_id.clone();
-    let transfer_submitted_at = transfer.submitted_at.clone();
+        .map_err(config_apply_error)?;
+    Ok(Json(status))
+}
 
-    emit(
-        &state,
-        "transfer.progress",
-        json!({
-            "transfer_id": transfer_id.clone(),
-            "status": "queued"
-        }),
-    );
+async fn post_config_confirm(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    authorize(&state, &headers, true).await?;
+    let confirmed_by = caller_label(&*state.node_config.read().await, &headers);
+    let status = config_apply::confirm(&state, confirmed_by, Utc::now())
+        .await
+        .map_err(config_apply_error)?;
+    Ok(Json(status))
+}
 
-    let state_for_task = state.clone();
-    let transfer_id_for_task = transfer_id.clone();
-    tokio::spawn(async move {
-        if let Err(err) = process_transfer_job(state_for_task, &transfer_id_for_task).await {
-            error!(transfer_id = %transfer_id_for_task, error = %err, "transfer processing failed");
+fn config_apply_error(error: ConfigApplyError) -> (StatusCode, Json<Value>) {
+    let status = match &error {
+        ConfigApplyError::Invalid(_) => StatusCode::BAD_REQUEST,
+        ConfigApplyError::RestartRequired(fields) => {
+            return (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(json!({
+                    "error": error.code(),
+                    "detail": error.to_string(),
+                    "fields": fields,
+                })),
+            );
         }
-    });
-
-    Ok((
-        StatusCode::ACCEPTED,
-        Json(json!({
-            "job_id": transfer_id.clone(),
-            "transfer_id": transfer_id.clone(),
-            "submitted_at": transfer_submitted_at,
-            "status_url": format!("/v1/transfers/{}", transfer_id)
-        })),
-    ))
+        ConfigApplyError::AwaitingConfirmation
+        | ConfigApplyError::NothingStaged
+        | ConfigApplyError::NothingToConfirm => StatusCode::CONFLICT,
+        ConfigApplyError::Internal(_) => return internal_error(error.into()),
+    };
+    (
+        status,
+        Json(json!({ "error": error.code(), "detail": error.to_string() })),
+    )
 }
 
-async fn process_transfer_job(state: AppState, transfer_id: &str) -> anyhow::Result<()> {
-    state
-        .storage
-        .update_transfer_status(transfer_id, "running", None)
-        .await?;
-
-    emit(
-        &state,
-        "transfer.progress",
-        json!({ "transfer_id": transfer_id, "status": "running" }),
-    );
-
-    state
-        .storage
-        .update_transfer_status(transfer_id, "success", None)
-        .await?;
-    emit(
-        &state,
-        "transfer.completed",
-        json!({ "transfer_id": transfer_id, "status": "success" }),
-    );
-    write_log(&state, "info", &format!("transfer {} completed", transfer_id)).await;
-    Ok(())
+async fn get_config_schema() -> impl IntoResponse {
+    Json(runtime_config_schema())
 }
 
-async fn get_transfer(
+async fn get_effective_config(
     State(state): State<AppState>,
-    Path(transfer_id): Path<String>,
 ) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
-    let record = state
-        .storage
-        .get_transfer(&transfer_id)
-        .await
-        .map_err(internal_error)?;
-    match record {
-        Some(record) => Ok((StatusCode::OK, Json(record))),
-        None => Err((
+    let Some(effective) = state.effective_config.as_deref() else {
+        return Err((
             StatusCode::NOT_FOUND,
-            Json(json!({"error":"transfer_not_found"})),
-        )),
-    }
+            Json(json!({ "error": "effective_config_unavailable" })),
+        ));
+    };
+    Ok(Json(json!(effective)))
 }
 
-async fn get_cached_events(
+// Flags never stored, on a node whose flags were not loaded, show their seed with no author.
+async fn get_features(
     State(state): State<AppState>,
-    Query(query): Query<ListQuery>,
 ) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
-    let limit = query.limit.unwrap_or(100);
-    let events = state
+    let stored: BTreeMap<String, FeatureFlagRecord> = state
         .storage
-        .list_cached_events(limit)
+        .list_feature_flags()
         .await
-        .map_err(internal_error)?;
-    Ok((StatusCode::OK, Json(events)))
+        .map_err(storage_error)?
+        .into_iter()
+        .map(|record| (record.name.clone(), record))
+        .collect();
+    let cached = state.features.snapshot();
+    let features: Vec<Value> = KNOWN_FLAGS
+        .iter()
+        .map(|flag| match stored.get(flag.name) {
+            Some(record) => feature_view(flag.description, record),
+            None => json!({
+                "name": flag.name,
+                "description": flag.description,
+                "enabled": cached.get(flag.name).is_some_and(|state| state.enabled),
+                "variant": Value::Null,
+                "updated_at": Value::Null,
+                "updated_by": Value::Null,
+            }),
+        })
+        .collect();
+    Ok(Json(json!({ "features": features })))
 }
 
-async fn get_cached_messages(
+async fn put_features(
     State(state): State<AppState>,
-    Query(query): Query<ListQuery>,
+    headers: HeaderMap,
+    Json(updates): Json<BTreeMap<String, FeatureFlagUpdate>>,
 ) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
-    let limit = query.limit.unwrap_or(100);
-    let messages = state
-        .storage
-        .list_cached_messages(limit)
-        .await
-        .map_err(internal_error)?;
-    Ok((StatusCode::OK, Json(messages)))
+    update_features(&state, &headers, updates).await
 }
 
-async fn get_logs(
+async fn put_feature(
     State(state): State<AppState>,
-    Query(query): Query<LogQuery>,
-) -> impl IntoResponse {
-    let limit = query.limit.unwrap_or(200);
-    let level_filter = query.level.as_deref().map(str::to_ascii_lowercase);
-    let contains_filter = query.contains.as_deref().map(str::to_owned);
+    headers: HeaderMap,
+    Path(name): Path<String>,
+    Json(update): Json<FeatureFlagUpdate>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    update_features(&state, &headers, BTreeMap::from([(name, update)])).await
+}
 
-    let logs = state.log_buffer.read().await;
-    let mut items: Vec<LogLine> = logs
-        .iter()
-        .filter(|entry| {
-            if let Some(level) = &level_filter {
-                if entry.level.to_ascii_lowercase() != *level {
-                    return false;
-                }
-            }
-
-            if let Some(needle) = &contains_filter {
-                if !entry.message.contains(needle) {
-                    return false;
-                }
-            }
+// All updates are stored in one transaction, and only when every name is a known flag. Each
+// change is recorded as a config revision carrying the flag values next to the config.
+async fn update_features(
+    state: &AppState,
+    headers: &HeaderMap,
+    updates: BTreeMap<String, FeatureFlagUpdate>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    authorize_admin(state, headers).await?;
+    if updates.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error":"empty_feature_update"})),
+        ));
+    }
+    let unknown: Vec<&String> = updates.keys().filter(|name| !is_known(name)).collect();
+    if !unknown.is_empty() {
+        let known: Vec<&str> = KNOWN_FLAGS.iter().map(|flag| flag.name).collect();
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": "unknown_feature_flag", "names": unknown, "known": known })),
+        ));
+    }
 
-            if let Some(since) = &query.since {
-                if entry.timestamp < *since {
-                    return false;
+    let updated_by = caller_label(&*state.node_config.read().await, headers);
+    let records = state
+        .storage
+        .with_tx(move |tx| {
+            Box::pin(async move {
+                let mut records = Vec::with_capacity(updates.len());
+                for (name, update) in &updates {
+                    let record = tx
+                        .put_feature_flag(
+                            name,
+                            update.enabled,
+                            update.variant.as_ref(),
+                            &updated_by,
+                        )
+                        .await?;
+                    let changed = json!({
+                        "name": record.name,
+                        "enabled": record.enabled,
+                        "variant": record.variant,
+                        "updated_by": record.updated_by,
+                    });
+                    tx.append_feed_event(FEATURE_CHANGED_EVENT, &changed).await?;
+                    records.push(record);
                 }
-            }
-            true
+                Ok(records)
+            })
         })
-        .cloned()
-        .collect();
-
-    if items.len() > limit {
-        let start = items.len().saturating_sub(limit);
-        items = items.split_off(start);
+        .await
+        .map_err(storage_error)?;
+    for record in &records {
+        state.features.cache_record(record);
     }
 
-    Json(json!({ "items": items }))
+    let mut revision = serde_json::to_value(&*state.node_config.read().await)
+        .map_err(|e| internal_error(e.into()))?;
+    revision["features"] = json!(state.features.snapshot());
+    state
+        .storage
+        .append_node_config_revision(&revision.to_string())
+        .await
+        .map_err(storage_error)?;
+
+    let mut views = Vec::with_capacity(records.len());
+    for record in &records {
+        let message = format!(
+            "feature {} {} by {}",
+            record.name,
+            if record.enabled {
+                "enabled"
+            } else {
+                "disabled"
+            },
+            record.updated_by
+        );
+        write_log(state, "info", &message).await;
+        let description = KNOWN_FLAGS
+            .iter()
+            .find(|flag| flag.name == record.name)
+            .map_or("", |flag| flag.description);
+        views.push(feature_view(description, record));
+    }
+    publish(state).await;
+    Ok(Json(json!({ "features": views })))
+}
+fn feature_view(description: &str, record: &FeatureFlagRecord) -> Value {
+    json!({
+        "name": record.name,
+        "description": description,
+        "enabled": record.enabled,
+        "variant": record.variant,
+        "updated_at": record.updated_at,
+        "updated_by": record.updated_by,
+    })
 }
 
-async fn stream_logs(
+// Jobs waiting on dependencies are held in storage rather than the inbound queue, so they are
+// counted on their own.
+async fn node_queue(
     State(state): State<AppState>,
-) -> Sse<impl futures::Stream<Item = Result<SseEvent, std::convert::Infallible>>> {
-    let receiver = state.sse_bus.subscribe();
-    let stream = BroadcastStream::new(receiver).filter_map(|item| async move {
-        match item {
-            Ok(update) => {
-                let data = serde_json::to_string(&update.data).unwrap_or_else(|_| "{}".to_string());
-                Some(Ok(SseEvent::default().event(update.event_type).data(data)))
-            }
-            Err(_) => None,
-        }
-    });
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    Ok(Json(queue_summary(&state).await?))
+}
 
-    Sse::new(stream).keep_alive(KeepAlive::new().interval(std::time::Duration::from_secs(15)))
+async fn queue_summary(state: &AppState) -> Result<QueueSummary, (StatusCode, Json<Value>)> {
+    let waiting_jobs = state
+        .storage
+        .count_jobs_with_status(WAITING_STATUS)
+        .await
+        .map_err(storage_error)?;
+    Ok(QueueSummary {
+        inbound: state.inbound.snapshot(),
+        waiting_jobs,
+        mute: mute_status(state, Utc::now()),
+        circuits: circuit_views(state).await,
+        dispatch: dispatch_view(state).await.map_err(internal_error)?,
+    })
 }
 
-async fn get_allowlist(
+// Replaces the operator's dispatch order: the listed queued jobs go out next, in that order,
+// ahead of priority.
+async fn put_queue_order(
     State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(body): Json<Value>,
 ) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
-    let items = state.storage.list_allowlist().await.map_err(internal_error)?;
-    Ok((StatusCode::OK, Json(json!({ "identities": items }))))
+    authorize_admin(&state, &headers).await?;
+    let job_ids = body
+        .get("job_ids")
+        .cloned()
+        .map(serde_json::from_value::<Vec<String>>)
+        .and_then(Result::ok)
+        .ok_or_else(|| {
+            (
+                StatusCode::BAD_REQUEST,
+                Json(json!({
+                    "error": "invalid_queue_order",
+                    "detail": "job_ids must be an array of job ids",
+                })),
+            )
+        })?;
+    let change = queue_change(&state, &headers, &body).await;
+    let view = set_order(&state, job_ids, change)
+        .await
+        .map_err(internal_error)?
+        .map_err(queue_refusal)?;
+    Ok(Json(view))
 }
 
-async fn add_allowlist(
+async fn get_mute(State(state): State<AppState>) -> impl IntoResponse {
+    Json(mute_status(&state, Utc::now()))
+}
+
+// Muting holds dispatch only: submissions are still stored and answered, and health is unaffected.
+async fn post_mute(
     State(state): State<AppState>,
     headers: HeaderMap,
-    Json(payload): Json<AddAllowlistRequest>,
+    Json(request): Json<MuteRequest>,
 ) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
-    authorize(&state, &headers, true).await?;
-    state
-        .storage
-        .add_allowlist(&payload.identity_hash, payload.note.as_deref())
+    authorize_admin(&state, &headers).await?;
+    let now = Utc::now();
+    if request.until.is_some_and(|until| until <= now) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error":"invalid_mute_until","detail":"until must be in the future"})),
+        ));
+    }
+    let muted_by = caller_label(&*state.node_config.read().await, &headers);
+    let message = format!("node muted by {muted_by}");
+    let status = mute(&state, request, muted_by, now)
         .await
         .map_err(internal_error)?;
+    write_log(&state, "warn", &message).await;
+    Ok(Json(status))
+}
 
-    emit(
-        &state,
-        "security.allowlist.updated",
-        json!({ "identity_hash": payload.identity_hash }),
+async fn delete_mute(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    authorize_admin(&state, &headers).await?;
+    let muted_by = caller_label(&*state.node_config.read().await, &headers);
+    if unmute(&state, "unmuted").await.map_err(internal_error)? {
+        write_log(&state, "info", &format!("node unmuted by {muted_by}")).await;
+    }
+    Ok(Json(mute_status(&state, Utc::now())))
+}
+
+// Calls that only make sense answered right away are refused rather than held while muted.
+fn refuse_when_muted(state: &AppState) -> Result<(), (StatusCode, Json<Value>)> {
+    let window = state.mute.window();
+    let Some(window) = window.filter(|window| window.scope.covers(Traffic::Commands)) else {
+        return Ok(());
+    };
+    Err(refusal(
+        StatusCode::SERVICE_UNAVAILABLE,
+        json!({ "error": NODE_MUTED_ERROR }),
+        window.retry_advice(Utc::now()),
+    ))
+}
+
+// The page is read-only and fetches everything else through the API, so it needs no token
+// itself. Both routes answer 404 unless `[http] status_page` is on.
+#[cfg(feature = "status-page")]
+async fn status_page(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    if !state.node_config.read().await.status_page {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    let mut response = respond_with_etag(
+        &headers,
+        compute_etag(STATUS_PAGE.as_bytes()),
+        Html(STATUS_PAGE),
     );
-    Ok((StatusCode::CREATED, Json(json!({ "status": "ok" }))))
+    response
+        .headers_mut()
+        .insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+    response
 }
 
-async fn delete_allowlist(
+#[cfg(feature = "status-page")]
+async fn get_ui_snapshot(
+    State(state): State<AppState>,
+) -> Result<Response, (StatusCode, Json<Value>)> {
+    if !state.node_config.read().await.status_page {
+        return Err((StatusCode::NOT_FOUND, Json(json!({"error":"status_page_disabled"}))));
+    }
+    let status = collect_node_status(&state).await;
+    let jobs = state
+        .storage
+        .list_recent_jobs(SNAPSHOT_JOBS)
+        .await
+        .map_err(storage_error)?;
+    let cutoff = stall_cutoff(&state).await;
+    let records = state
+        .storage
+        .list_transfers(None, SNAPSHOT_TRANSFERS)
+        .await
+        .map_err(storage_error)?;
+    let mut transfers = Vec::with_capacity(records.len());
+    for record in records {
+        let progress = state
+            .storage
+            .get_transfer_progress(&record.transfer_id, &cutoff)
+            .await
+            .map_err(storage_error)?;
+        transfers.push(TransferSummary::new(record, progress));
+    }
+    let logs = {
+        let buffer = state.log_buffer.read().await;
+        buffer[buffer.len().saturating_sub(SNAPSHOT_LOG_LINES)..].to_vec()
+    };
+    let snapshot = UiSnapshot {
+        generated_at: Utc::now().to_rfc3339(),
+        node: NodeSummary {
+            ready: status.ready,
+            daemon_connected: status.daemon_connected,
+            checks: status.checks,
+            availability_last_hour: status.availability_last_hour,
+            capabilities_digest: status.capabilities_digest,
+        },
+        queue: queue_summary(&state).await?,
+        jobs: jobs.into_iter().map(JobSummary::from).collect(),
+        transfers,
+        logs,
+    };
+    Ok(([(header::CACHE_CONTROL, "no-store")], Json(snapshot)).into_response())
+}
+
+async fn get_contract(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    let contract = state.contract.current();
+    let etag = compute_etag(contract.document.as_bytes());
+    respond_with_etag(
+        &headers,
+        etag,
+        (
+            [(header::CONTENT_TYPE, "application/yaml")],
+            contract.document.clone(),
+        ),
+    )
+}
+
+// Re-reads the contract file and swaps it in for everything after this request. Queued jobs the
+// new contract would refuse block the swap unless `force=true`; schema changes only a rebuild
+// picks up are reported, not refused.
+async fn reload_contract(
     State(state): State<AppState>,
     headers: HeaderMap,
-    Path(identity_hash): Path<String>,
+    Query(query): Query<ContractReloadQuery>,
 ) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
-    authorize(&state, &headers, true).await?;
-    let deleted = state
+    authorize_admin(&state, &headers).await?;
+    let Some(source) = state.contract.source().map(std::path::Path::to_path_buf) else {
+        return Err((
+            StatusCode::CONFLICT,
+            Json(json!({"error":"contract_source_unconfigured"})),
+        ));
+    };
+    let document = tokio::fs::read_to_string(&source).await.map_err(|err| {
+        (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(json!({
+                "error": "contract_unreadable",
+                "source": source.display().to_string(),
+                "detail": err.to_string(),
+            })),
+        )
+    })?;
+    let registry = ContractRegistry::from_yaml(&document).map_err(|err| {
+        (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(json!({ "error": "invalid_contract", "detail": err.to_string() })),
+        )
+    })?;
+    let next = LoadedContract { document, registry };
+    let pending = state
         .storage
-        .delete_allowlist(&identity_hash)
+        .count_pending_jobs_by_operation()
         .await
-        .map_err(internal_error)?;
+        .map_err(storage_error)?;
+    let allow_sunset = state.node_config.read().await.allow_sunset_operations;
+    let diff = diff_contracts(&state.contract.current(), &next, &pending, allow_sunset, Utc::now());
+    let forced = query.force.unwrap_or(false);
+    if !diff.is_safe() && !forced {
+        return Err((
+            StatusCode::CONFLICT,
+            Json(json!({ "error": "contract_reload_refused", "diff": diff })),
+        ));
+    }
 
-    if deleted {
-        emit(
-            &state,
-            "security.allowlist.updated",
-            json!({ "identity_hash": identity_hash, "deleted": true }),
-        );
-        Ok((StatusCode::NO_CONTENT, Json(json!({}))))
-    } else {
-        Err((
+    let etag = compute_etag(next.document.as_bytes());
+    state.contract.replace(next);
+    state.storage.set_contract_version(diff.new_version.as_deref());
+    let mut revision = serde_json::to_value(&*state.node_config.read().await)
+        .map_err(|e| internal_error(e.into()))?;
+    revision["contract"] = json!({
+        "source": source.display().to_string(),
+        "version": diff.new_version,
+        "etag": etag,
+        "forced": forced,
+    });
+    let reloaded = json!({
+        "old_version": diff.old_version,
+        "new_version": diff.new_version,
+        "forced": forced,
+        "restart_required": !diff.restart_required.is_empty(),
+    });
+    state
+        .storage
+        .with_event(CONTRACT_RELOADED_EVENT, reloaded)
+        .append_node_config_revision(&revision.to_string())
+        .await
+        .map_err(storage_error)?;
+
+    let caller = caller_label(&*state.node_config.read().await, &headers);
+    let message = format!(
+        "contract reloaded by {caller}: {} -> {}",
+        diff.old_version.as_deref().unwrap_or("unversioned"),
+        diff.new_version.as_deref().unwrap_or("unversioned"),
+    );
+    write_log(&state, if forced { "warn" } else { "info" }, &message).await;
+    publish(&state).await;
+    Ok(Json(json!({
+        "reloaded": true,
+        "etag": etag,
+        "restart_required": !diff.restart_required.is_empty(),
+        "diff": diff,
+    })))
+}
+
+async fn list_deprecations(State(state): State<AppState>) -> impl IntoResponse {
+    let now = Utc::now();
+    let usage = state
+        .deprecated_usage
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .clone();
+    let operations: Vec<Value> = state
+        .contract
+        .current()
+        .registry
+        .deprecations()
+        .map(|deprecation| {
+            json!({
+                "operation": deprecation.operation,
+                "sunset": deprecation.sunset,
+                "sunset_passed": deprecation.is_sunset(now),
+                "used": usage.get(&deprecation.operation).copied().unwrap_or(0),
+            })
+        })
+        .collect();
+    Json(json!({ "operations": operations }))
+}
+
+async fn list_operations(
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    let operations =
+        operation_catalog(&state.contract.current(), Utc::now()).map_err(internal_error)?;
+    Ok(Json(json!({ "operations": operations })))
+}
+
+// A ready-to-submit payload built from the schema's examples and defaults; aliases answer with
+// the operation they resolve to.
+async fn get_operation_example(
+    State(state): State<AppState>,
+    Path(operation): Path<String>,
+    Query(query): Query<OperationExampleQuery>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    let contract = state.contract.current();
+    let source_identity = local_identity(&*state.node_config.read().await);
+    let Some(mut sample) =
+        operation_example(&contract, &operation, &source_identity).map_err(internal_error)?
+    else {
+        return Err((
             StatusCode::NOT_FOUND,
-            Json(json!({"error":"identity_not_found"})),
-        ))
+            Json(json!({
+                "error": "unknown_operation",
+                "operation": operation,
+                "suggestions": operation_suggestions(&contract.registry, &operation),
+            })),
+        ));
+    };
+    if query.envelope.unwrap_or(false) {
+        return Ok(Json(sample.envelope));
     }
+    Ok(Json(sample.envelope["payload"].take()))
 }
 
-async fn authorize(
+// `?fields=` and `?omit_payload=true` answer with a `JobSummaryView`; a request that leaves the
+// payload out never reads it from storage.
+async fn get_job(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(job_id): Path<String>,
+    Query(query): Query<JobQuery>,
+    Query(shape): Query<ShapeQuery>,
+) -> Result<Response, (StatusCode, Json<Value>)> {
+    let shape = response_shape(&shape, JOB_FIELDS)?;
+    if shape.is_full() {
+        let mut view = load_job(&state, &job_id).await?;
+        view.extras = job_extras(&state, &headers, &job_id, &query).await?;
+        return Ok((StatusCode::OK, Json(view)).into_response());
+    }
+    let (summary, payload_json) = if shape.wants_payload("payload_json") {
+        let record = load_job_record(&state, &job_id).await?;
+        ((&record).into(), Some(record.payload_json))
+    } else {
+        (load_job_summary(&state, &job_id).await?, None)
+    };
+    let attachments = if shape.includes("attachments") {
+        job_attachments(&state, &job_id).await?
+    } else {
+        Vec::new()
+    };
+    let extras = job_extras(&state, &headers, &job_id, &query).await?;
+    let view = JobSummaryView {
+        summary,
+        payload_json,
+        attachments,
+        extras,
+    };
+    Ok((StatusCode::OK, Json(shape.apply(view))).into_response())
+}
+
+async fn job_extras(
     state: &AppState,
     headers: &HeaderMap,
-    write_operation: bool,
-) -> Result<(), (StatusCode, Json<Value>)> {
-    if !write_operation || !state.require_bearer {
-        return Ok(());
+    job_id: &str,
+    query: &JobQuery,
+) -> Result<JobExtras, (StatusCode, Json<Value>)> {
+    let source = state
+        .storage
+        .get_job_source(job_id)
+        .await
+        .map_err(storage_error)?;
+    let digest = state
+        .storage
+        .get_job_result_digest(job_id)
+        .await
+        .map_err(storage_error)?;
+    let labels = state
+        .storage
+        .get_labels(LABEL_SUBJECT_JOB, job_id)
+        .await
+        .map_err(storage_error)?;
+    let mut extras = JobExtras {
+        source: visible_source(state, headers, source).await,
+        labels,
+        ..JobExtras::default()
+    };
+    if let Some(digest) = digest {
+        extras.result_digest = Some(digest.result_digest);
+        extras.result_unchanged = Some(digest.unchanged);
+        extras.result_source = Some(digest.source);
     }
+    let check = state
+        .storage
+        .get_job_result_check(job_id)
+        .await
+        .map_err(storage_error)?;
+    if let Some(check) = check {
+        let errors = serde_json::from_str(&check.errors_json)
+            .map_err(|err| internal_error(err.into()))?;
+        extras.result_nonconforming = Some(true);
+        extras.result_errors = Some(errors);
+    }
+    for include in query.include.iter().flat_map(|include| include.split(',')) {
+        match include.trim() {
+            "" => {}
+            TRANSFORM_TRACE_INCLUDE => {
+                let traces = state
+                    .storage
+                    .list_job_transforms(job_id)
+                    .await
+                    .map_err(storage_error)?;
+                extras.transform_trace = Some(traces);
+            }
+            DISPATCH_ATTEMPTS_INCLUDE => {
+                let summary = state
+                    .storage
+                    .dispatch_attempt_summary(job_id)
+                    .await
+                    .map_err(storage_error)?;
+                extras.dispatch_attempts = Some(summary);
+            }
+            other => {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    Json(json!({ "error": "unknown_include", "include": other })),
+                ))
+            }
+        }
+    }
+    Ok(extras)
+}
 
-    let configured = state.node_config.read().await.http_auth_token.clone();
-    let token = configured.ok_or_else(|| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!({"error":"auth_token_required_but_not_configured"})),
-        )
-    })?;
-
-    let provided = headers
-        .get(header::AUTHORIZATION)
-        .and_then(|value| value.to_str().ok())
-        .unwrap_or_default();
-
-    let expected = format!("Bearer {token}");
-    if provided == expected {
-        Ok(())
+async fn get_job_v2(
+    State(state): State<AppState>,
+    Path(job_id): Path<String>,
+    Query(shape): Query<ShapeQuery>,
+) -> Result<Json<Envelope<Shaped<v2::Job>>>, V2Error> {
+    let shape = response_shape(&shape, v2::Job::FIELDS).map_err(v2_error)?;
+    let attachments = if shape.includes("attachments") {
+        job_attachments(&state, &job_id)
+            .await
+            .map_err(v2_error)?
+            .into_iter()
+            .map(TransferView::into_v2)
+            .collect::<anyhow::Result<Vec<_>>>()
+            .map_err(v2_internal)?
     } else {
-        Err((
-            StatusCode::UNAUTHORIZED,
-            Json(json!({"error":"invalid_or_missing_bearer_token"})),
-        ))
+        Vec::new()
+    };
+    let job = if shape.wants_payload("payload") {
+        let record = load_job_record(&state, &job_id).await.map_err(v2_error)?;
+        v2::Job::from_record(record, attachments)
+    } else {
+        let summary = load_job_summary(&state, &job_id).await.map_err(v2_error)?;
+        v2::Job::from_summary(summary, attachments)
+    };
+    let mut job = job.map_err(v2_internal)?;
+    job.labels = state
+        .storage
+        .get_labels(LABEL_SUBJECT_JOB, &job_id)
+        .await
+        .map_err(|err| v2_error(storage_error(err)))?;
+    if shape.includes("dispatch_attempts") {
+        job.dispatch_attempts = state
+            .storage
+            .dispatch_attempt_summary(&job_id)
+            .await
+            .map_err(|err| v2_error(storage_error(err)))?;
     }
+    Ok(Json(Envelope::new(shape.apply(job))))
 }
 
-fn internal_error(error: anyhow::Error) -> (StatusCode, Json<Value>) {
-    error!(error = %error, "request failed");
+async fn load_job(state: &AppState, job_id: &str) -> Result<JobView, (StatusCode, Json<Value>)> {
+    let record = load_job_record(state, job_id).await?;
+    Ok(JobView {
+        record,
+        attachments: job_attachments(state, job_id).await?,
+        extras: JobExtras::default(),
+    })
+}
+
+fn job_not_found() -> (StatusCode, Json<Value>) {
     (
-        StatusCode::INTERNAL_SERVER_ERROR,
-        Json(json!({ "error": "internal_error", "detail": error.to_string() })),
+        StatusCode::NOT_FOUND,
+        Json(json!({"error":"job_not_found"})),
     )
 }
 
-fn emit(state: &AppState, event_type: &str, data: Value) {
-    let _ = state.sse_bus.send(SseUpdate {
-        event_type: event_type.to_string(),
-        data,
-    });
+async fn load_job_record(
+    state: &AppState,
+    job_id: &str,
+) -> Result<JobRecord, (StatusCode, Json<Value>)> {
+    let job = state.storage.get_job(job_id).await.map_err(storage_error)?;
+    match job {
+        Some(job) => Ok(job),
+        None => spooled_job(state, job_id).await?.ok_or_else(job_not_found),
+    }
 }
 
-async fn write_log(state: &AppState, level: &str, message: &str) {
-    info!(level = %level, message = %message, "control-plane log entry");
-    let mut buffer = state.log_buffer.write().await;
-    buffer.push(LogLine {
-        timestamp: Utc::now().to_rfc3339(),
-        level: level.to_string(),
-        message: message.to_string(),
-    });
+async fn load_job_summary(
+    state: &AppState,
+    job_id: &str,
+) -> Result<retasync_storage::JobSummary, (StatusCode, Json<Value>)> {
+    let job = state
+        .storage
+        .get_job_summary(job_id)
+        .await
+        .map_err(storage_error)?;
+    match job {
+        Some(job) => Ok(job),
+        None => {
+            let spooled = spooled_job(state, job_id).await?;
+            spooled.as_ref().map(Into::into).ok_or_else(job_not_found)
+        }
+    }
+}
 
-    if buffer.len() > 500 {
-        let keep_start = buffer.len() - 500;
-        let trimmed = buffer.split_off(keep_start);
-        *buffer = trimmed;
+// A job accepted into the submission spool that storage has not taken yet.
+async fn spooled_job(
+    state: &AppState,
+    job_id: &str,
+) -> Result<Option<JobRecord>, (StatusCode, Json<Value>)> {
+    let drain_interval = state.node_config.read().await.submission_spool.drain_interval();
+    let spooled = state
+        .submission_spool
+        .get(job_id)
+        .await
+        .map_err(|err| submission_spool_error(drain_interval, err))?;
+    Ok(spooled.as_ref().map(SpooledSubmission::job_record))
+}
+
+async fn job_attachments(
+    state: &AppState,
+    job_id: &str,
+) -> Result<Vec<TransferView>, (StatusCode, Json<Value>)> {
+    let transfers = state
+        .storage
+        .list_job_transfers(job_id)
+        .await
+        .map_err(storage_error)?;
+    let cutoff = stall_cutoff(state).await;
+    let mut attachments = Vec::with_capacity(transfers.len());
+    for transfer in transfers {
+        attachments.push(transfer_view(state, transfer, &cutoff).await?);
     }
+    Ok(attachments)
 }
 
-#[allow(dead_code)]
-fn _job_record_to_value(job: JobRecord) -> Value {
-    json!({
-        "job_id": job.job_id,
-        "operation": job.operation,
-        "status": job.status,
-        "submitted_at": job.submitted_at,
-        "updated_at": job.updated_at,
-        "failure_reason": job.failure_reason,
-    })
+async fn get_job_result(
+    State(state): State<AppState>,
+    Path(job_id): Path<String>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    let result = state
+        .storage
+        .get_job_result(&job_id)
+        .await
+        .map_err(storage_error)?;
+    if let Some(record) = result {
+        return Ok((StatusCode::OK, Json(record)).into_response());
+    }
+    // The submitter already holds an unchanged result, so there is none here to send.
+    let digest = state
+        .storage
+        .get_job_result_digest(&job_id)
+        .await
+        .map_err(storage_error)?;
+    match digest {
+        Some(digest) if digest.unchanged => {
+            Ok(not_modified(format!("\"{}\"", digest.result_digest)))
+        }
+        _ => Err((
+            StatusCode::NOT_FOUND,
+            Json(json!({"error":"job_result_not_found"})),
+        )),
+    }
+}
+
+// The delivery receipt the destination signed for a job's command, and whether it verified.
+async fn get_job_receipt(
+    State(state): State<AppState>,
+    Path(job_id): Path<String>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    if state.storage.get_job(&job_id).await.map_err(storage_error)?.is_none() {
+        return Err(job_not_found());
+    }
+    let receipt = state
+        .storage
+        .delivery_receipt_for_job(&job_id)
+        .await
+        .map_err(storage_error)?;
+    match receipt {
+        Some(record) => Ok(Json(receipt_view(record))),
+        None => Err((
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": RECEIPT_NOT_FOUND_ERROR })),
+        )),
+    }
+}
+
+#[cfg(feature = "transfers")]
+async fn get_transfer_receipt(
+    State(state): State<AppState>,
+    Path(transfer_id): Path<String>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    if state
+        .storage
+        .get_transfer(&transfer_id)
+        .await
+        .map_err(storage_error)?
+        .is_none()
+    {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(json!({"error":"transfer_not_found"})),
+        ));
+    }
+    let receipt = state
+        .storage
+        .delivery_receipt_for_transfer(&transfer_id)
+        .await
+        .map_err(storage_error)?;
+    match receipt {
+        Some(record) => Ok(Json(receipt_view(record))),
+        None => Err((
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": RECEIPT_NOT_FOUND_ERROR })),
+        )),
+    }
+}
+
+async fn get_job_result_parts(
+    State(state): State<AppState>,
+    Path(job_id): Path<String>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    let job = state.storage.get_job(&job_id).await.map_err(storage_error)?;
+    let Some(job) = job else {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(json!({"error":"job_not_found"})),
+        ));
+    };
+    let parts = state
+        .storage
+        .list_job_result_parts(&job_id)
+        .await
+        .map_err(storage_error)?;
+    Ok(Json(json!({
+        "job_id": job.job_id,
+        "status": job.status,
+        "missing": missing_sequences(&parts),
+        "parts": parts,
+    })))
+}
+
+async fn get_job_dependents(
+    State(state): State<AppState>,
+    Path(job_id): Path<String>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    if state.storage.get_job(&job_id).await.map_err(storage_error)?.is_none() {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(json!({"error":"job_not_found"})),
+        ));
+    }
+    let dependents = state
+        .storage
+        .list_job_dependents(&job_id)
+        .await
+        .map_err(storage_error)?;
+    Ok(Json(json!({ "job_id": job_id, "dependents": dependents })))
+}
+
+// Replaces a job's labels. Only the caller that submitted the job, or an admin, may relabel
+// it; the previous and new labels are kept as an audit event.
+async fn put_job_labels(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(job_id): Path<String>,
+    Json(body): Json<Value>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    authorize(&state, &headers, true).await?;
+    if state.storage.get_job(&job_id).await.map_err(storage_error)?.is_none() {
+        return Err(job_not_found());
+    }
+    if !is_admin(&state, &headers).await {
+        let caller = caller_label(&*state.node_config.read().await, &headers);
+        let submitter = state
+            .storage
+            .get_job_submitter(&job_id)
+            .await
+            .map_err(storage_error)?;
+        if submitter.as_deref() != Some(caller.as_str()) {
+            return Err((
+                StatusCode::FORBIDDEN,
+                Json(json!({"error":"not_job_submitter"})),
+            ));
+        }
+    }
+    let labels = match body {
+        Value::Object(mut body) => parse_labels(body.remove("labels").unwrap_or(Value::Null)),
+        _ => Err("body must be an object with a labels field".to_string()),
+    }
+    .map_err(label_rejection)?;
+    let changed_by = caller_label(&*state.node_config.read().await, &headers);
+    let (id, replacement) = (job_id.clone(), labels.clone());
+    state
+        .storage
+        .with_tx(move |tx| {
+            Box::pin(async move {
+                let previous = tx.replace_labels(LABEL_SUBJECT_JOB, &id, &replacement).await?;
+                tx.append_feed_event(
+                    JOB_LABELS_CHANGED_EVENT,
+                    &json!({
+                        "job_id": id,
+                        "previous": previous,
+                        "labels": replacement,
+                        "changed_by": changed_by,
+                    }),
+                )
+                .await?;
+                Ok(())
+            })
+        })
+        .await
+        .map_err(storage_error)?;
+    publish(&state).await;
+    Ok(Json(json!({ "job_id": job_id, "labels": labels })))
+}
+
+#[derive(Debug, Deserialize)]
+struct LabelKeysQuery {
+    subject_type: Option<String>,
+}
+
+// Every label key in use, with how many distinct values and subjects it has.
+async fn list_label_keys(
+    State(state): State<AppState>,
+    Query(query): Query<LabelKeysQuery>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    let subject = match query.subject_type.as_deref() {
+        Some(name) => Some(subject_type(name).ok_or_else(|| {
+            (
+                StatusCode::BAD_REQUEST,
+                Json(json!({ "error": "invalid_subject_type", "subject_type": name })),
+            )
+        })?),
+        None => None,
+    };
+    let keys = state.storage.label_keys(subject).await.map_err(storage_error)?;
+    Ok(Json(json!({ "keys": keys })))
+}
+
+// A job whose envelope already went out is also recalled from the mesh, which may or may not
+// still be able to stop delivery; the answer is kept on the job's dispatch.
+async fn cancel_job(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(job_id): Path<String>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    authorize(&state, &headers, true).await?;
+    if !state
+        .storage
+        .with_event(
+            FEED_JOB_EVENT,
+            json!({ "job_id": job_id, "status": CANCELLED_STATUS }),
+        )
+        .cancel_job(&job_id)
+        .await
+        .map_err(storage_error)?
+    {
+        let Some(job) = state
+            .storage
+            .get_job(&job_id)
+            .await
+            .map_err(storage_error)?
+        else {
+            return Err((
+                StatusCode::NOT_FOUND,
+                Json(json!({"error":"job_not_found"})),
+            ));
+        };
+        return Err((
+            StatusCode::CONFLICT,
+            Json(json!({ "error": "job_not_cancellable", "status": job.status })),
+        ));
+    }
+    state.dispatch_queue.forget(&job_id);
+    let recall = recall_job(&state, &job_id).await.map_err(internal_error)?;
+    publish(&state).await;
+    write_log(&state, "info", &format!("job {job_id} cancelled")).await;
+    settle_dependents(&state, &job_id).await;
+
+    let mut body = json!({ "job_id": job_id, "status": CANCELLED_STATUS });
+    if let Some(recall) = recall {
+        body["mesh_cancel_outcome"] = json!(recall.outcome);
+        if let Some(error) = recall.error {
+            body["mesh_cancel_error"] = json!(error);
+        }
+    }
+    Ok(Json(body))
+}
+
+// Sends a queued or deferred job next, ahead of everything else waiting. The caller and the
+// required `reason` are kept on the event.
+async fn post_job_prioritize(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(job_id): Path<String>,
+    Json(body): Json<Value>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    authorize_admin(&state, &headers).await?;
+    let change = queue_change(&state, &headers, &body).await;
+    if change.reason.is_none() {
+        return Err(reason_required());
+    }
+    let view = prioritize(&state, &job_id, change)
+        .await
+        .map_err(internal_error)?
+        .map_err(queue_refusal)?;
+    Ok(Json(view))
+}
+
+async fn post_job_hold(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(job_id): Path<String>,
+    Json(body): Json<Value>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    authorize_admin(&state, &headers).await?;
+    let change = queue_change(&state, &headers, &body).await;
+    if change.reason.is_none() {
+        return Err(reason_required());
+    }
+    let hold = hold_job(&state, &job_id, change)
+        .await
+        .map_err(internal_error)?
+        .map_err(queue_refusal)?;
+    Ok(Json(json!({ "job_id": job_id, "status": HELD_STATUS, "hold": hold })))
+}
+
+async fn post_job_release(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(job_id): Path<String>,
+    body: Option<Json<Value>>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    authorize_admin(&state, &headers).await?;
+    let body = body.map(|Json(body)| body).unwrap_or(Value::Null);
+    let change = queue_change(&state, &headers, &body).await;
+    let status = release_job(&state, &job_id, change)
+        .await
+        .map_err(internal_error)?
+        .map_err(queue_refusal)?;
+    Ok(Json(json!({ "job_id": job_id, "status": status })))
+}
+
+async fn queue_change(state: &AppState, headers: &HeaderMap, body: &Value) -> QueueChange {
+    QueueChange {
+        by: caller_label(&*state.node_config.read().await, headers),
+        reason: required_reason(body),
+    }
+}
+
+fn reason_required() -> (StatusCode, Json<Value>) {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(json!({
+            "error": REASON_REQUIRED_ERROR,
+            "detail": "reason must be a non-empty string",
+        })),
+    )
+}
+
+fn queue_refusal(refusal: QueueRefusal) -> (StatusCode, Json<Value>) {
+    match refusal {
+        QueueRefusal::NotFound(job_id) => (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": "job_not_found", "job_id": job_id })),
+        ),
+        QueueRefusal::NotQueued { job_id, status } => (
+            StatusCode::CONFLICT,
+            Json(json!({ "error": JOB_NOT_QUEUED_ERROR, "job_id": job_id, "status": status })),
+        ),
+        QueueRefusal::NotHeld { job_id, status } => (
+            StatusCode::CONFLICT,
+            Json(json!({ "error": JOB_NOT_HELD_ERROR, "job_id": job_id, "status": status })),
+        ),
+        QueueRefusal::Duplicate(job_id) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": DUPLICATE_JOB_ERROR, "job_id": job_id })),
+        ),
+    }
+}
+
+async fn get_job_trace(
+    State(state): State<AppState>,
+    Path(job_id): Path<String>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    if state.storage.get_job(&job_id).await.map_err(storage_error)?.is_none() {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(json!({"error":"job_not_found"})),
+        ));
+    }
+    let Some(trace) = state.storage.get_job_trace(&job_id).await.map_err(storage_error)? else {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(json!({"error":"job_not_traced"})),
+        ));
+    };
+    let hops: Vec<HopRecord> = serde_json::from_str(&trace.hops_json)
+        .map_err(|err| internal_error(err.into()))?;
+    let returned_at = trace
+        .returned_at
+        .as_deref()
+        .and_then(|at| chrono::DateTime::parse_from_rfc3339(at).ok())
+        .map(|at| at.with_timezone(&Utc));
+    Ok(Json(timeline(&job_id, hops, trace.truncated, returned_at)))
+}
+
+// Every bridge call made for the job, oldest first, with the summary its detail shows.
+async fn get_job_attempts(
+    State(state): State<AppState>,
+    Path(job_id): Path<String>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    if state.storage.get_job(&job_id).await.map_err(storage_error)?.is_none() {
+        return Err(job_not_found());
+    }
+    let attempts = state
+        .storage
+        .list_dispatch_attempts(&job_id)
+        .await
+        .map_err(storage_error)?;
+    let summary = state
+        .storage
+        .dispatch_attempt_summary(&job_id)
+        .await
+        .map_err(storage_error)?;
+    Ok(Json(json!({ "job_id": job_id, "attempts": attempts, "summary": summary })))
+}
+
+async fn post_command_job(
+    State(state): State<AppState>,
+    Path(operation): Path<String>,
+    Query(query): Query<CommandSubmitQuery>,
+    headers: HeaderMap,
+    Json(payload): Json<Value>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    if query.dry_run.unwrap_or(false) {
+        let report = dry_run_submission(&state, &operation, &query, &headers, payload).await?;
+        return Ok(Json(report).into_response());
+    }
+    let (deprecation_headers, job) =
+        submit_command(&state, operation, &query, &headers, payload).await?;
+    let mut accepted = json!({
+        "job_id": job.job_id.clone(),
+        "submitted_at": job.submitted_at,
+        "status_url": format!("/v1/jobs/{}", job.job_id)
+    });
+    if job.status == SPOOLED_STATUS {
+        accepted["spooled"] = json!(true);
+    }
+    Ok((StatusCode::ACCEPTED, deprecation_headers, Json(accepted)).into_response())
+}
+
+async fn post_command_job_v2(
+    State(state): State<AppState>,
+    Path(operation): Path<String>,
+    query: Result<Query<CommandSubmitQuery>, QueryRejection>,
+    headers: HeaderMap,
+    payload: Result<Json<Value>, JsonRejection>,
+) -> Result<impl IntoResponse, V2Error> {
+    let Query(query) = query.map_err(|err| v2_rejection("invalid_query", err.body_text()))?;
+    let Json(payload) = payload.map_err(|err| v2_rejection("invalid_body", err.body_text()))?;
+    if query.dry_run.unwrap_or(false) {
+        let report = dry_run_submission(&state, &operation, &query, &headers, payload)
+            .await
+            .map_err(v2_error)?;
+        return Ok(Json(Envelope::new(report)).into_response());
+    }
+    let (deprecation_headers, job) = submit_command(&state, operation, &query, &headers, payload)
+        .await
+        .map_err(v2_error)?;
+    let mut accepted = JobAccepted::new(job.job_id, &job.submitted_at).map_err(v2_internal)?;
+    accepted.spooled = job.status == SPOOLED_STATUS;
+    Ok((
+        StatusCode::ACCEPTED,
+        deprecation_headers,
+        Json(Envelope::new(accepted)),
+    )
+        .into_response())
+}
+
+async fn submit_command(
+    state: &AppState,
+    operation: String,
+    query: &CommandSubmitQuery,
+    headers: &HeaderMap,
+    mut payload: Value,
+) -> Result<(HeaderMap, JobRecord), (StatusCode, Json<Value>)> {
+    authorize(state, headers, true).await?;
+    let contract = state.contract.current();
+    let (canonical, aliased) = contract.registry.resolve_alias(&operation);
+    let requested_operation = aliased.then(|| operation.clone());
+    let operation = canonical.to_string();
+    let mut deprecation_headers = check_deprecation(state, &operation).await?;
+    if let Some(alias) = &requested_operation {
+        note_alias(state, alias, &operation, &mut deprecation_headers).await;
+    }
+    let labels = take_labels(&mut payload).map_err(label_rejection)?;
+    let meta = submission_meta(headers, &payload, with_labels(query.conditional_meta(), labels))?;
+    let attachment_settings = state.node_config.read().await.attachments.clone();
+    let attachments =
+        take_attachments(&mut payload, &attachment_settings).map_err(attachment_rejection)?;
+    verify_outbound(
+        state,
+        attachments
+            .iter()
+            .map(|file| (&*file.file_name, &*file.media_type, &*file.bytes)),
+    )
+    .await?;
+    let dependencies = take_dependencies(&mut payload).map_err(dependency_rejection)?;
+    check_held_attachments(&dependencies, &attachments)?;
+    let delivery = resolve_delivery(state, &operation, &mut payload).await?;
+
+    let mut dispatch = routed_dispatch(state, &operation, &payload).await?;
+    dispatch.delivery = delivery;
+    dispatch.meta = meta;
+    dispatch.liveness.probe = query.probe.unwrap_or(false);
+    check_peer_compatibility(state, &operation, &mut dispatch, query.force.unwrap_or(false))
+        .await?;
+    let attachment_bytes = attachments
+        .iter()
+        .map(|attachment| attachment.bytes.len() as u64)
+        .sum();
+    let (submitted_by, quota_claims) =
+        enforce_quotas(state, headers, &dispatch.destination_identity, attachment_bytes).await?;
+    check_dependencies(state, headers, &submitted_by, &dependencies).await?;
+    // Charged once the command is known to be valid, so a rejected one costs nothing.
+    if take_submissions(state, headers, 1).await == 0 {
+        return Err(rate_limited(rate_limit_advice(state, headers).await));
+    }
+    let dispatch_json = serde_json::to_value(&dispatch).map_err(|e| internal_error(e.into()))?;
+    let staged: Vec<(Value, TransferProgress)> = attachments
+        .iter()
+        .map(|attachment| {
+            let mut metadata = attachment.metadata(&dispatch.destination_identity);
+            metadata["submitted_by"] = json!(submitted_by);
+            (
+                metadata,
+                TransferProgress::new(attachment.bytes.len() as u64, DEFAULT_CHUNK_SIZE),
+            )
+        })
+        .collect();
+    let (operation_for_tx, payload_for_tx) = (operation.clone(), payload.clone());
+    let dependencies_for_tx = dependencies.clone();
+    let labels = meta_labels(dispatch.meta.as_ref());
+    let source = submission_source(&*state.node_config.read().await, headers);
+    // Attachment bytes are not stored, so a command carrying them cannot wait in the spool.
+    let spoolable =
+        attachments.is_empty() && state.node_config.read().await.submission_spool.enabled;
+    let spooled = spoolable.then(|| SpooledSubmission {
+        job_id: Uuid::now_v7().to_string(),
+        submitted_at: CanonicalTimestamp::now(),
+        operation: operation.clone(),
+        requested_operation: requested_operation.clone(),
+        payload: payload.clone(),
+        dispatch: dispatch_json.clone(),
+        submitted_by: Some(submitted_by.clone()),
+        source: source.clone(),
+        labels: labels.clone(),
+        depends_on: dependencies.clone(),
+        idempotency_key: None,
+    });
+    if let Some(spooled) = spooled.clone() {
+        // Nothing overtakes the submissions still waiting in the spool.
+        if spool_waiting(state).await {
+            let job = spool_submissions(state, vec![spooled]).await?.remove(0);
+            return Ok((deprecation_headers, job));
+        }
+    }
+    let stored = state
+        .storage
+        .with_tx(move |tx| {
+            Box::pin(async move {
+                let mut job = tx.create_job(&operation_for_tx, payload_for_tx).await?;
+                tx.set_job_dispatch(&job.job_id, &dispatch_json).await?;
+                tx.set_job_submitted_by(&job.job_id, &submitted_by).await?;
+                tx.set_job_source(&job.job_id, &source).await?;
+                tx.put_labels(LABEL_SUBJECT_JOB, &job.job_id, &labels).await?;
+                if let Some(requested) = &requested_operation {
+                    tx.set_job_requested_operation(&job.job_id, requested).await?;
+                }
+                if !dependencies_for_tx.is_empty() {
+                    let waiting = json!({
+                        "job_id": job.job_id,
+                        "status": WAITING_STATUS,
+                        "depends_on": dependencies_for_tx,
+                    });
+                    tx.stage_job_event(&job.job_id, &waiting).await?;
+                    tx.update_job_status(&job.job_id, WAITING_STATUS, None).await?;
+                    for depends_on in &dependencies_for_tx {
+                        tx.add_job_dependency(&job.job_id, depends_on).await?;
+                    }
+                    job.status = WAITING_STATUS.to_string();
+                }
+                let mut transfers = Vec::with_capacity(staged.len());
+                for (metadata, progress) in &staged {
+                    let transfer = tx
+                        .create_transfer_with_progress(Some(&job.job_id), metadata, progress)
+                        .await?;
+                    reserve_upload(tx, &transfer.transfer_id, &quota_claims, progress.bytes_total)
+                        .await?;
+                    tx.set_transfer_source(&transfer.transfer_id, &source).await?;
+                    tx.put_labels(LABEL_SUBJECT_TRANSFER, &transfer.transfer_id, &labels)
+                        .await?;
+                    tx.append_feed_event(
+                        "transfer.progress",
+                        &json!({
+                            "transfer_id": transfer.transfer_id,
+                            "job_id": job.job_id,
+                            "status": "queued",
+                        }),
+                    )
+                    .await?;
+                    transfers.push(transfer);
+                }
+                Ok((job, transfers))
+            })
+        })
+        .await;
+    let (job, transfers) = match (stored, spooled) {
+        (Ok(stored), _) => stored,
+        (Err(err), Some(spooled)) if err.is_transient() => {
+            warn!(error = %err, "storage unavailable; spooling the submission");
+            let job = spool_submissions(state, vec![spooled]).await?.remove(0);
+            return Ok((deprecation_headers, job));
+        }
+        (Err(err), _) => {
+            return Err(admission_error(state, &dispatch.destination_identity, err).await)
+        }
+    };
+    let job_id = job.job_id.clone();
+    publish(state).await;
+
+    let mut references = Vec::with_capacity(attachments.len());
+    let mut linked = Vec::with_capacity(attachments.len());
+    for (attachment, transfer) in attachments.into_iter().zip(transfers) {
+        let destination = &dispatch.destination_identity;
+        references.push(attachment.reference(&transfer.transfer_id));
+        linked.push(LinkedTransfer {
+            transfer_id: transfer.transfer_id,
+            request: attachment.upload_request(destination),
+            bytes: attachment.bytes,
+        });
+    }
+    if !references.is_empty() {
+        payload[ATTACHMENTS_FIELD] = Value::Array(references);
+    }
+
+    write_log(
+        state,
+        "info",
+        &format!("job submitted for operation {}", operation),
+    )
+    .await;
+
+    if !dependencies.is_empty() {
+        // A dependency may have finished before this job was recorded as waiting on it.
+        release_if_ready(state, &job_id).await.map_err(internal_error)?;
+        return Ok((deprecation_headers, job));
+    }
+
+    spawn_command_worker(state, job_id, operation, payload, dispatch, linked);
+
+    Ok((deprecation_headers, job))
+}
+
+// Whether earlier submissions still wait in the spool. A spool that cannot be read is logged
+// and passed over, so storage gets the submission.
+async fn spool_waiting(state: &AppState) -> bool {
+    match state.submission_spool.is_empty().await {
+        Ok(empty) => !empty,
+        Err(err) => {
+            error!(error = %err, "failed to read the submission spool");
+            false
+        }
+    }
+}
+
+// Appends submissions storage cannot take to the spool and answers for them with jobs in
+// `spooled` status, under the ids they keep once the drain stores them.
+async fn spool_submissions(
+    state: &AppState,
+    submissions: Vec<SpooledSubmission>,
+) -> Result<Vec<JobRecord>, (StatusCode, Json<Value>)> {
+    let (max_bytes, drain_interval) = {
+        let spool = &state.node_config.read().await.submission_spool;
+        (spool.max_bytes, spool.drain_interval())
+    };
+    state
+        .submission_spool
+        .append(&submissions, max_bytes)
+        .await
+        .map_err(|err| submission_spool_error(drain_interval, err))?;
+    for submission in &submissions {
+        write_log(
+            state,
+            "warn",
+            &format!(
+                "job {} for operation {} spooled until storage is available",
+                submission.job_id, submission.operation
+            ),
+        )
+        .await;
+    }
+    Ok(submissions.iter().map(SpooledSubmission::job_record).collect())
+}
+
+// Stores spooled submissions in the order they were accepted and starts them. The drain stops
+// at the first one storage still cannot take; one storage refuses for good is logged and
+// dropped so it cannot hold up the rest. Returns how many jobs it stored.
+pub(crate) async fn drain_submission_spool(state: &AppState) -> anyhow::Result<usize> {
+    let pending = state.submission_spool.pending().await?;
+    let mut stored = 0;
+    let mut released = None;
+    for (submission, end) in pending {
+        match store_spooled(state, &submission).await {
+            Ok(Some(job)) => {
+                stored += 1;
+                start_spooled(state, job, submission).await;
+            }
+            // Stored before, or a retry of a keyed submission storage already has.
+            Ok(None) => {}
+            Err(err) if err.is_transient() => break,
+            Err(err) => {
+                error!(error = %err, job_id = %submission.job_id, "dropped a spooled submission");
+                write_log(
+                    state,
+                    "error",
+                    &format!("spooled job {} could not be stored: {err}", submission.job_id),
+                )
+                .await;
+            }
+        }
+        released = Some(end);
+    }
+    if let Some(end) = released {
+        state.submission_spool.release(end).await?;
+    }
+    if stored > 0 {
+        publish(state).await;
+    }
+    Ok(stored)
+}
+
+async fn store_spooled(
+    state: &AppState,
+    submission: &SpooledSubmission,
+) -> Result<Option<JobRecord>, StorageError> {
+    let submission = submission.clone();
+    state
+        .storage
+        .with_tx(move |tx| {
+            Box::pin(async move {
+                if let Some(key) = &submission.idempotency_key {
+                    if tx.find_job_by_idempotency_key(key).await?.is_some() {
+                        return Ok(None);
+                    }
+                }
+                let created = tx
+                    .create_job_as(
+                        &submission.job_id,
+                        &submission.submitted_at,
+                        &submission.operation,
+                        submission.payload,
+                    )
+                    .await?;
+                let Some(mut job) = created else {
+                    return Ok(None);
+                };
+                tx.set_job_dispatch(&job.job_id, &submission.dispatch).await?;
+                if let Some(submitted_by) = &submission.submitted_by {
+                    tx.set_job_submitted_by(&job.job_id, submitted_by).await?;
+                }
+                tx.set_job_source(&job.job_id, &submission.source).await?;
+                tx.put_labels(LABEL_SUBJECT_JOB, &job.job_id, &submission.labels).await?;
+                if let Some(requested) = &submission.requested_operation {
+                    tx.set_job_requested_operation(&job.job_id, requested).await?;
+                }
+                if let Some(key) = &submission.idempotency_key {
+                    tx.set_job_idempotency_key(&job.job_id, key).await?;
+                }
+                if !submission.depends_on.is_empty() {
+                    let waiting = json!({
+                        "job_id": job.job_id,
+                        "status": WAITING_STATUS,
+                        "depends_on": submission.depends_on,
+                    });
+                    tx.stage_job_event(&job.job_id, &waiting).await?;
+                    tx.update_job_status(&job.job_id, WAITING_STATUS, None).await?;
+                    for depends_on in &submission.depends_on {
+                        tx.add_job_dependency(&job.job_id, depends_on).await?;
+                    }
+                    job.status = WAITING_STATUS.to_string();
+                }
+                Ok(Some(job))
+            })
+        })
+        .await
+}
+
+async fn start_spooled(state: &AppState, job: JobRecord, submission: SpooledSubmission) {
+    write_log(
+        state,
+        "info",
+        &format!("spooled job {} stored for operation {}", job.job_id, job.operation),
+    )
+    .await;
+    if !submission.depends_on.is_empty() {
+        if let Err(err) = release_if_ready(state, &job.job_id).await {
+            error!(error = %err, job_id = %job.job_id, "failed to release a spooled job");
+        }
+        return;
+    }
+    match serde_json::from_value::<Dispatch>(submission.dispatch) {
+        Ok(dispatch) => spawn_command_worker(
+            state,
+            job.job_id,
+            submission.operation,
+            submission.payload,
+            dispatch,
+            Vec::new(),
+        ),
+        Err(err) => error!(error = %err, job_id = %job.job_id, "spooled dispatch is unreadable"),
+    }
+}
+
+// Attachment bytes are not stored, so a command carrying them cannot be held back.
+fn check_held_attachments(
+    dependencies: &[String],
+    attachments: &[Attachment],
+) -> Result<(), (StatusCode, Json<Value>)> {
+    if !dependencies.is_empty() && !attachments.is_empty() {
+        return Err(dependency_rejection(DependencyRejection::Malformed(
+            format!("{DEPENDS_ON_FIELD} cannot be combined with {ATTACHMENTS_FIELD}"),
+        )));
+    }
+    Ok(())
+}
+
+// Runs the pre-dispatch stages of a single submission. Only authorization stops the run: every
+// other stage is reported, and nothing is stored, counted, logged, or sent.
+async fn dry_run_submission(
+    state: &AppState,
+    operation: &str,
+    query: &CommandSubmitQuery,
+    headers: &HeaderMap,
+    payload: Value,
+) -> Result<DryRunReport, (StatusCode, Json<Value>)> {
+    authorize(state, headers, true).await?;
+    let mut report = DryRunReport::default();
+    let available = available_submissions(state, headers).await;
+    report.check(
+        "rate_limit",
+        if available == Some(0) {
+            Err(rate_limited(rate_limit_advice(state, headers).await))
+        } else {
+            Ok(())
+        },
+    );
+    let force = query.force.unwrap_or(false);
+    let probe = query.probe.unwrap_or(false);
+    dry_run_command(
+        state,
+        &mut report,
+        headers,
+        operation,
+        payload,
+        query.conditional_meta(),
+        force,
+        probe,
+        false,
+    )
+    .await?;
+    Ok(report)
+}
+
+// The stages shared by single and batch dry runs, in the order a real submission meets them.
+// `reject_unknown` applies the batch endpoint's check that the contract declares the operation.
+#[allow(clippy::too_many_arguments)]
+async fn dry_run_command(
+    state: &AppState,
+    report: &mut DryRunReport,
+    headers: &HeaderMap,
+    requested: &str,
+    mut payload: Value,
+    submitted_meta: Option<EnvelopeMeta>,
+    force: bool,
+    probe: bool,
+    reject_unknown: bool,
+) -> Result<(), (StatusCode, Json<Value>)> {
+    let contract = state.contract.current();
+    let (canonical, aliased) = contract.registry.resolve_alias(requested);
+    let operation = canonical.to_string();
+    let declares_commands = contract.registry.commands().next().is_some();
+    let known = if reject_unknown && declares_commands && !contract.registry.is_command(&operation)
+    {
+        Err((
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": "unknown_operation", "operation": requested })),
+        ))
+    } else {
+        check_sunset(state, &operation).await
+    };
+    if let Some(deprecation) = report.check("operation", known) {
+        report.detail(
+            "operation",
+            json!({
+                "operation": operation,
+                "requested_operation": aliased.then_some(requested),
+                "deprecated": deprecation.is_some(),
+            }),
+        );
+    }
+
+    let meta = take_labels(&mut payload)
+        .map_err(label_rejection)
+        .and_then(|labels| submission_meta(headers, &payload, with_labels(submitted_meta, labels)));
+    let meta = report.check("meta", meta);
+    if let Some(Some(meta)) = &meta {
+        report.detail("meta", json!(meta));
+    }
+
+    let attachment_settings = state.node_config.read().await.attachments.clone();
+    let taken = take_attachments(&mut payload, &attachment_settings).map_err(attachment_rejection);
+    let taken = match taken {
+        Ok(attachments) => verify_outbound(
+            state,
+            attachments
+                .iter()
+                .map(|file| (&*file.file_name, &*file.media_type, &*file.bytes)),
+        )
+        .await
+        .map(|()| attachments),
+        Err(rejection) => Err(rejection),
+    };
+    let mut payload_valid = meta.is_some() && taken.is_ok();
+    let attachments = report.check("attachments", taken).unwrap_or_default();
+    let submitted_by = caller_label(&*state.node_config.read().await, headers);
+    let dependencies = match take_dependencies(&mut payload).map_err(dependency_rejection) {
+        Ok(dependencies) => {
+            let checked = match check_held_attachments(&dependencies, &attachments) {
+                Ok(()) => check_dependencies(state, headers, &submitted_by, &dependencies).await,
+                Err(rejection) => Err(rejection),
+            };
+            report.check("dependencies", checked).map(|()| dependencies)
+        }
+        Err(rejection) => report.check("dependencies", Err(rejection)),
+    };
+    if let Some(dependencies) = dependencies.filter(|dependencies| !dependencies.is_empty()) {
+        report.detail("dependencies", json!({ "depends_on": dependencies }));
+    }
+    let delivery = resolve_delivery(state, &operation, &mut payload).await;
+    payload_valid &= delivery.is_ok();
+    let delivery = report.check("delivery", delivery).unwrap_or_default();
+
+    let mut dispatch = match routed_dispatch(state, &operation, &payload).await {
+        Ok(dispatch) => dispatch,
+        Err(rejection) => {
+            report.check::<()>("routing", Err(rejection));
+            for stage in ["quota", "transforms", "envelope"] {
+                report.skip(stage);
+            }
+            return Ok(());
+        }
+    };
+    dispatch.delivery = delivery;
+    dispatch.meta = meta.flatten();
+    dispatch.liveness.probe = probe;
+    let compatibility = peer_compatibility(state, &dispatch.destination_identity, force);
+    if let Some(compatibility) = report.check("routing", compatibility) {
+        dispatch.peer_compatibility = compatibility;
+        let routed = serde_json::to_value(&dispatch).map_err(|e| internal_error(e.into()))?;
+        report.detail("routing", routed);
+    }
+
+    let attachment_bytes: u64 = attachments
+        .iter()
+        .map(|attachment| attachment.bytes.len() as u64)
+        .sum();
+    let exceeded = if attachment_bytes == 0 {
+        None
+    } else {
+        let client = client_key(&submission_source(&*state.node_config.read().await, headers))
+            .to_string();
+        let claims = quota_claims(state, &dispatch.destination_identity, &client)
+            .await
+            .map_err(internal_error)?;
+        check_quotas(state, &claims, attachment_bytes, Utc::now())
+            .await
+            .map_err(internal_error)?
+    };
+    let quota = exceeded.map_or(Ok(()), |exceeded| Err(quota_exceeded(exceeded)));
+    if report.check("quota", quota).is_some() {
+        report.detail(
+            "quota",
+            json!({ "submitted_by": submitted_by, "requested_bytes": attachment_bytes }),
+        );
+    }
+
+    if !payload_valid {
+        report.skip("transforms");
+        report.skip("envelope");
+        return Ok(());
+    }
+    // Transfers are created on submission; a nil id sizes the reference the same.
+    if !attachments.is_empty() {
+        let placeholder = Uuid::nil().to_string();
+        let references = attachments
+            .iter()
+            .map(|attachment| attachment.reference(&placeholder))
+            .collect();
+        payload[ATTACHMENTS_FIELD] = Value::Array(references);
+    }
+    let payload = match state
+        .transforms
+        .apply(TransformStage::Command, &operation, payload)
+    {
+        Ok(transformed) => {
+            report.pass(
+                "transforms",
+                Some(json!({ "applied": transformed.applied })),
+            );
+            transformed.payload
+        }
+        Err(failure) => {
+            let error = json!({ "error": failure.code(), "detail": failure.detail() });
+            report.fail("transforms", None, error);
+            report.skip("envelope");
+            return Ok(());
+        }
+    };
+
+    let config = state.node_config.read().await.clone();
+    let sealing = seal_outgoing(
+        state,
+        &config.security,
+        &operation,
+        &dispatch.destination_identity,
+        &payload,
+    )
+    .await
+    .map_err(internal_error)?;
+    let (payload, content_type) = match sealing {
+        Outgoing::Plain => command_payload(
+            state,
+            &dispatch.destination_identity,
+            payload,
+            config.compression_threshold_bytes,
+        )
+        .map_err(|e| internal_error(e.into()))?,
+        Outgoing::Sealed(sealed) => (sealed, CONTENT_TYPE_SEALED.to_string()),
+        Outgoing::NoKey => {
+            report.fail("envelope", None, json!({ "error": SEALING_KEY_MISSING_ERROR }));
+            return Ok(());
+        }
+    };
+    let planned = state
+        .bridge
+        .planned_transport(dispatch.transport_hint.clone());
+    let envelope = command_envelope(dispatch, &operation, payload, content_type, &planned);
+    let (size_bytes, _) = envelope_size(&envelope, &envelope.payload).map_err(internal_error)?;
+    let oversize = check_envelope(
+        &config,
+        planned.clone(),
+        &envelope,
+        &envelope.payload,
+        &envelope.content_type,
+        true,
+    )
+    .map_err(internal_error)?;
+    match oversize {
+        Some(oversize) => report.fail(
+            "envelope",
+            None,
+            json!({ "error": "envelope_too_large", "detail": oversize }),
+        ),
+        None => report.pass("envelope", None),
+    }
+    report.envelope = Some(EnvelopePreview {
+        limit_bytes: transport_limit(&config, &planned),
+        channel: contract.registry.command_channel(&envelope.operation),
+        operation: envelope.operation,
+        source_identity: envelope.source_identity,
+        destination_identity: envelope.destination_identity,
+        content_type: envelope.content_type,
+        ttl_ms: envelope.ttl_ms,
+        transport_hint: envelope.transport_hint,
+        transport: planned,
+        size_bytes,
+    });
+    Ok(())
+}
+// Dependencies must exist and, unless the caller is an admin, have been submitted by the
+// same caller; other jobs are reported as missing rather than revealed.
+async fn check_dependencies(
+    state: &AppState,
+    headers: &HeaderMap,
+    submitted_by: &str,
+    dependencies: &[String],
+) -> Result<(), (StatusCode, Json<Value>)> {
+    if dependencies.is_empty() {
+        return Ok(());
+    }
+    let admin = is_admin(state, headers).await;
+    for depends_on in dependencies {
+        let found = state.storage.get_job(depends_on).await.map_err(storage_error)?;
+        let visible = match found {
+            Some(_) if admin => true,
+            Some(_) => {
+                let submitter = state
+                    .storage
+                    .get_job_submitter(depends_on)
+                    .await
+                    .map_err(storage_error)?;
+                submitter.as_deref() == Some(submitted_by)
+            }
+            None => false,
+        };
+        if !visible {
+            return Err((
+                StatusCode::NOT_FOUND,
+                Json(json!({ "error": "dependency_not_found", "job_id": depends_on })),
+            ));
+        }
+    }
+    let max_depth = state.node_config.read().await.dependencies.max_chain_depth;
+    let graph = ancestor_graph(&state.storage, dependencies)
+        .await
+        .map_err(internal_error)?;
+    check_chain(&graph, dependencies, max_depth).map_err(dependency_rejection)?;
+    Ok(())
+}
+
+pub(crate) fn identity_rejection(error: IdentityHashError) -> (StatusCode, Json<Value>) {
+    (
+        StatusCode::UNPROCESSABLE_ENTITY,
+        Json(json!({
+            "error": "invalid_identity_hash",
+            "defect": error.code(),
+            "detail": error.to_string(),
+        })),
+    )
+}
+
+// Where a submitted command goes. The destination must be an identity hash and a requested
+// contact window must fit the signed seconds it is checked in.
+async fn routed_dispatch(
+    state: &AppState,
+    operation: &str,
+    payload: &Value,
+) -> Result<Dispatch, (StatusCode, Json<Value>)> {
+    let dispatch = resolve_dispatch(&*state.node_config.read().await, operation, payload)
+        .map_err(identity_rejection)?;
+    if let Some(window_s) = dispatch.liveness.require_recent_contact_s {
+        contact_window(window_s).map_err(|failure| {
+            (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(json!({ "error": failure.code(), "detail": failure.detail() })),
+            )
+        })?;
+    }
+    Ok(dispatch)
+}
+
+// Identity hashes named by clients, checked the way the node config asks.
+pub(crate) async fn client_identity(
+    state: &AppState,
+    value: &str,
+) -> Result<IdentityHash, (StatusCode, Json<Value>)> {
+    let mode = state.node_config.read().await.identity_validation;
+    IdentityHash::parse_as(value, mode).map_err(identity_rejection)
+}
+
+fn dependency_rejection(rejection: DependencyRejection) -> (StatusCode, Json<Value>) {
+    match rejection {
+        DependencyRejection::Malformed(detail) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": "invalid_dependencies", "detail": detail })),
+        ),
+        DependencyRejection::Cycle(cycle) => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(json!({ "error": "dependency_cycle", "cycle": cycle })),
+        ),
+        DependencyRejection::TooDeep { limit, depth } => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(json!({ "error": "dependency_chain_too_deep", "limit": limit, "depth": depth })),
+        ),
+    }
+}
+
+async fn check_peer_compatibility(
+    state: &AppState,
+    operation: &str,
+    dispatch: &mut Dispatch,
+    force: bool,
+) -> Result<(), (StatusCode, Json<Value>)> {
+    let destination = &dispatch.destination_identity;
+    if let Some(compatibility) = peer_compatibility(state, destination, force)? {
+        let message = format!("{operation} sent to {destination} with contract {compatibility:?}");
+        write_log(state, "warn", &message).await;
+        dispatch.peer_compatibility = Some(compatibility);
+    }
+    Ok(())
+}
+
+// Returns the compatibility to record when the peer's contract differs but the command may
+// still be sent.
+fn peer_compatibility(
+    state: &AppState,
+    destination: &str,
+    force: bool,
+) -> Result<Option<Compatibility>, (StatusCode, Json<Value>)> {
+    let Some(peer) = peer_handshake(state, destination) else {
+        return Ok(None);
+    };
+    match peer.compatibility {
+        Compatibility::Compatible => Ok(None),
+        Compatibility::Incompatible if !force => Err((
+            StatusCode::CONFLICT,
+            Json(json!({
+                "error": "peer_contract_incompatible",
+                "destination_identity": destination,
+                "peer": peer.hello,
+            })),
+        )),
+        compatibility => Ok(Some(compatibility)),
+    }
+}
+
+async fn available_submissions(state: &AppState, headers: &HeaderMap) -> Option<usize> {
+    let (rate_per_minute, source) = {
+        let config = state.node_config.read().await;
+        (
+            config.submissions.rate_per_minute,
+            submission_source(&config, headers),
+        )
+    };
+    state
+        .submission_budget
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .available(
+            client_key(&source),
+            rate_per_minute,
+            std::time::Instant::now(),
+        )
+}
+
+// How long until the caller's budget holds a whole submission again.
+async fn rate_limit_advice(state: &AppState, headers: &HeaderMap) -> RetryAdvice {
+    let (rate_per_minute, source) = {
+        let config = state.node_config.read().await;
+        (
+            config.submissions.rate_per_minute,
+            submission_source(&config, headers),
+        )
+    };
+    let refill_in = state
+        .submission_budget
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .refill_in(
+            client_key(&source),
+            rate_per_minute,
+            std::time::Instant::now(),
+        );
+    RetryAdvice::after("rate_limited", RetryScope::Client, refill_in)
+}
+
+// Charges the caller's own budget; whatever it could not grant is counted against the client.
+async fn take_submissions(state: &AppState, headers: &HeaderMap, requested: usize) -> usize {
+    let (rate_per_minute, source) = {
+        let config = state.node_config.read().await;
+        (
+            config.submissions.rate_per_minute,
+            submission_source(&config, headers),
+        )
+    };
+    let client = client_key(&source);
+    let granted = state
+        .submission_budget
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .take(
+            client,
+            rate_per_minute,
+            requested,
+            std::time::Instant::now(),
+        );
+    if granted < requested {
+        let bucket = CanonicalTimestamp::from(bucket_start(Utc::now())).to_string();
+        let rejected = (requested - granted) as i64;
+        if let Err(err) = state
+            .storage
+            .record_client_rejections(client, &bucket, rejected)
+            .await
+        {
+            warn!(error = %err, client, "failed to record rate limit rejections");
+        }
+    }
+    granted
+}
+
+// Returns the submitter's label and the quota subjects the upload is charged to. The transaction
+// that creates each transfer reserves its bytes against them with `reserve_upload`.
+async fn enforce_quotas(
+    state: &AppState,
+    headers: &HeaderMap,
+    destination: &str,
+    requested: u64,
+) -> Result<(String, Vec<QuotaClaim>), (StatusCode, Json<Value>)> {
+    let (submitted_by, source) = {
+        let config = state.node_config.read().await;
+        (
+            caller_label(&config, headers),
+            submission_source(&config, headers),
+        )
+    };
+    if requested == 0 {
+        return Ok((submitted_by, Vec::new()));
+    }
+    let claims = quota_claims(state, destination, client_key(&source))
+        .await
+        .map_err(internal_error)?;
+    match check_quotas(state, &claims, requested, Utc::now())
+        .await
+        .map_err(internal_error)?
+    {
+        Some(exceeded) => Err(refuse_upload(state, destination, exceeded).await),
+        None => Ok((submitted_by, claims)),
+    }
+}
+
+async fn refuse_upload(
+    state: &AppState,
+    destination: &str,
+    exceeded: QuotaExceeded,
+) -> (StatusCode, Json<Value>) {
+    let message = format!(
+        "upload of {} bytes to {destination} rejected: {} {} quota exceeded",
+        exceeded.requested_bytes, exceeded.subject_kind, exceeded.subject
+    );
+    write_log(state, "warn", &message).await;
+    quota_exceeded(exceeded)
+}
+
+// Holds a new transfer's bytes against its quota claims inside the transaction creating it, so
+// concurrent uploads cannot all pass the check before any of them is charged.
+async fn reserve_upload(
+    tx: &mut StorageTx,
+    transfer_id: &str,
+    claims: &[QuotaClaim],
+    bytes: u64,
+) -> Result<(), StorageError> {
+    if claims.is_empty() || bytes == 0 {
+        return Ok(());
+    }
+    let window = CanonicalTimestamp::from(window_start(Utc::now())).to_string();
+    tx.reserve_quota(transfer_id, claims, i64::try_from(bytes).unwrap_or(i64::MAX), &window)
+        .await
+}
+
+// A reservation refused inside the admitting transaction answers like the check before it.
+async fn admission_error(
+    state: &AppState,
+    destination: &str,
+    error: StorageError,
+) -> (StatusCode, Json<Value>) {
+    let StorageError::QuotaExceeded {
+        subject_kind,
+        subject,
+        limit_bytes,
+        used_bytes,
+        requested_bytes,
+    } = error
+    else {
+        return storage_error(error);
+    };
+    let claim = QuotaClaim {
+        subject_kind,
+        subject,
+        limit_bytes: Some(limit_bytes),
+    };
+    let (used, requested) = (used_bytes.max(0) as u64, requested_bytes.max(0) as u64);
+    match exceeded_quota(&state.storage, &claim, used, requested, Utc::now()).await {
+        Ok(exceeded) => refuse_upload(state, destination, exceeded).await,
+        Err(err) => internal_error(err),
+    }
+}
+
+fn quota_exceeded(exceeded: QuotaExceeded) -> (StatusCode, Json<Value>) {
+    let advice = exceeded.retry_advice(Utc::now());
+    refusal(
+        StatusCode::TOO_MANY_REQUESTS,
+        json!({"error":"quota_exceeded","detail":exceeded}),
+        advice,
+    )
+}
+
+fn rate_limited(advice: RetryAdvice) -> (StatusCode, Json<Value>) {
+    refusal(
+        StatusCode::TOO_MANY_REQUESTS,
+        json!({"error":"rate_limited"}),
+        advice,
+    )
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BatchEntry {
+    operation: String,
+    payload: Value,
+    ttl_ms: Option<u64>,
+    destination_identity: Option<String>,
+    idempotency_key: Option<String>,
+    meta: Option<EnvelopeMeta>,
+}
+
+struct PreparedCommand {
+    index: usize,
+    operation: String,
+    requested_operation: Option<String>,
+    deprecated: bool,
+    payload: Value,
+    dispatch: Dispatch,
+    idempotency_key: Option<String>,
+}
+
+enum BatchOutcome {
+    Accepted(Value),
+    Duplicate(String),
+    SameAs(usize),
+    Rejected(StatusCode, Value),
+}
+
+// Validates and stores every entry independently: the response lists one outcome per entry in
+// submission order, and the accepted jobs are created in a single transaction.
+async fn post_command_batch(
+    State(state): State<AppState>,
+    Query(query): Query<CommandSubmitQuery>,
+    headers: HeaderMap,
+    Json(entries): Json<Vec<Value>>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    authorize(&state, &headers, true).await?;
+    let (max_batch_size, drain_interval) = {
+        let config = state.node_config.read().await;
+        (
+            config.submissions.max_batch_size,
+            config.submission_spool.drain_interval(),
+        )
+    };
+    if entries.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, Json(json!({"error":"empty_batch"}))));
+    }
+    if entries.len() > max_batch_size {
+        return Err((
+            StatusCode::PAYLOAD_TOO_LARGE,
+            Json(json!({
+                "error": "batch_too_large",
+                "limit_entries": max_batch_size,
+                "entries": entries.len(),
+            })),
+        ));
+    }
+
+    let force = query.force.unwrap_or(false);
+    let probe = query.probe.unwrap_or(false);
+    if query.dry_run.unwrap_or(false) {
+        let results = dry_run_batch(&state, &headers, entries, force, probe).await?;
+        return Ok(Json(json!({ "dry_run": true, "results": results })).into_response());
+    }
+    let mut outcomes = Vec::with_capacity(entries.len());
+    let mut first_by_key = BTreeMap::new();
+    let mut prepared = Vec::new();
+    for (index, entry) in entries.into_iter().enumerate() {
+        let mut command = match prepare_batch_entry(&state, &headers, index, entry, force).await {
+            Ok(command) => command,
+            Err((status, Json(error))) => {
+                outcomes.push(BatchOutcome::Rejected(status, error));
+                continue;
+            }
+        };
+        command.dispatch.liveness.probe = probe;
+        if let Some(key) = &command.idempotency_key {
+            if let Some(first) = first_by_key.get(key) {
+                outcomes.push(BatchOutcome::SameAs(*first));
+                continue;
+            }
+            first_by_key.insert(key.clone(), index);
+            let existing = state
+                .storage
+                .find_job_by_idempotency_key(key)
+                .await
+                .map_err(storage_error)?;
+            if let Some(job_id) = existing {
+                outcomes.push(BatchOutcome::Duplicate(job_id));
+                continue;
+            }
+            // A retry of an entry accepted while storage was unavailable finds it spooled.
+            let spooled = state
+                .submission_spool
+                .find_key(key)
+                .await
+                .map_err(|err| submission_spool_error(drain_interval, err))?;
+            if let Some(entry) = spooled {
+                outcomes.push(BatchOutcome::Duplicate(entry.job_id));
+                continue;
+            }
+        }
+        // Replaced once the job is created, or with the rate limit's advice past the limit.
+        outcomes.push(BatchOutcome::Rejected(StatusCode::TOO_MANY_REQUESTS, Value::Null));
+        prepared.push(command);
+    }
+    let granted = take_submissions(&state, &headers, prepared.len()).await;
+    if granted < prepared.len() {
+        let (status, Json(error)) = rate_limited(rate_limit_advice(&state, &headers).await);
+        for command in &prepared[granted..] {
+            outcomes[command.index] = BatchOutcome::Rejected(status, error.clone());
+        }
+    }
+    prepared.truncate(granted);
+
+    let mut staged = Vec::with_capacity(prepared.len());
+    for command in &prepared {
+        let dispatch_json =
+            serde_json::to_value(&command.dispatch).map_err(|e| internal_error(e.into()))?;
+        staged.push((
+            command.operation.clone(),
+            command.payload.clone(),
+            dispatch_json,
+            command.requested_operation.clone(),
+            command.idempotency_key.clone(),
+            meta_labels(command.dispatch.meta.as_ref()),
+        ));
+    }
+    let source = submission_source(&*state.node_config.read().await, &headers);
+    let spooled = state.node_config.read().await.submission_spool.enabled.then(|| {
+        staged
+            .iter()
+            .map(|(operation, payload, dispatch_json, requested, key, labels)| {
+                SpooledSubmission {
+                    job_id: Uuid::now_v7().to_string(),
+                    submitted_at: CanonicalTimestamp::now(),
+                    operation: operation.clone(),
+                    requested_operation: requested.clone(),
+                    payload: payload.clone(),
+                    dispatch: dispatch_json.clone(),
+                    submitted_by: None,
+                    source: source.clone(),
+                    labels: labels.clone(),
+                    depends_on: Vec::new(),
+                    idempotency_key: key.clone(),
+                }
+            })
+            .collect::<Vec<_>>()
+    });
+    let stored = match &spooled {
+        Some(_) if !staged.is_empty() && spool_waiting(&state).await => None,
+        _ => Some(
+            state
+                .storage
+                .with_tx(move |tx| {
+                    Box::pin(async move {
+                        let mut jobs = Vec::with_capacity(staged.len());
+                        for (operation, payload, dispatch_json, requested, key, labels) in staged {
+                            // A concurrent submission may have taken the key since it was
+                            // checked; that entry answers with its job instead.
+                            if let Some(key) = &key {
+                                if let Some(job_id) = tx.find_job_by_idempotency_key(key).await? {
+                                    jobs.push(Err(job_id));
+                                    continue;
+                                }
+                            }
+                            let job = tx.create_job(&operation, payload).await?;
+                            tx.set_job_dispatch(&job.job_id, &dispatch_json).await?;
+                            tx.set_job_source(&job.job_id, &source).await?;
+                            tx.put_labels(LABEL_SUBJECT_JOB, &job.job_id, &labels).await?;
+                            if let Some(requested) = &requested {
+                                tx.set_job_requested_operation(&job.job_id, requested).await?;
+                            }
+                            if let Some(key) = &key {
+                                tx.set_job_idempotency_key(&job.job_id, key).await?;
+                            }
+                            jobs.push(Ok(job));
+                        }
+                        Ok(jobs)
+                    })
+                })
+                .await,
+        ),
+    };
+    let jobs = match (stored, spooled) {
+        (Some(Ok(jobs)), _) => jobs,
+        (Some(Err(err)), Some(spooled)) if err.is_transient() => {
+            warn!(error = %err, "storage unavailable; spooling the batch");
+            spool_submissions(&state, spooled).await?.into_iter().map(Ok).collect()
+        }
+        (Some(Err(err)), _) => return Err(storage_error(err)),
+        // Nothing overtakes the submissions still waiting in the spool.
+        (None, spooled) => spool_submissions(&state, spooled.unwrap_or_default())
+            .await?
+            .into_iter()
+            .map(Ok)
+            .collect(),
+    };
+    publish(&state).await;
+
+    let mut job_ids = Vec::with_capacity(jobs.len());
+    for (command, job) in prepared.into_iter().zip(jobs) {
+        let job = match job {
+            Ok(job) => job,
+            Err(existing) => {
+                outcomes[command.index] = BatchOutcome::Duplicate(existing);
+                continue;
+            }
+        };
+        let spooled = job.status == SPOOLED_STATUS;
+        if !spooled {
+            write_log(
+                &state,
+                "info",
+                &format!("job submitted for operation {} in batch", command.operation),
+            )
+            .await;
+            spawn_command_worker(
+                &state,
+                job.job_id.clone(),
+                command.operation.clone(),
+                command.payload,
+                command.dispatch,
+                Vec::new(),
+            );
+        }
+        let mut accepted = json!({
+            "job_id": job.job_id,
+            "submitted_at": job.submitted_at,
+            "status_url": format!("/v1/jobs/{}", job.job_id),
+        });
+        if spooled {
+            accepted["spooled"] = json!(true);
+        }
+        if command.deprecated {
+            accepted["deprecated"] = json!(true);
+        }
+        if let Some(requested) = command.requested_operation {
+            accepted["operation"] = json!(command.operation);
+            accepted["requested_operation"] = json!(requested);
+        }
+        job_ids.push(job.job_id);
+        outcomes[command.index] = BatchOutcome::Accepted(accepted);
+    }
+
+    let results: Vec<Value> = (0..outcomes.len())
+        .map(|index| batch_result(&outcomes, index))
+        .collect();
+    let accepted = job_ids.len();
+    let rejected = results
+        .iter()
+        .filter(|result| result["status"] == "rejected")
+        .count();
+    emit(
+        &state,
+        "jobs.batch.submitted",
+        json!({
+            "entries": results.len(),
+            "accepted": accepted,
+            "duplicates": results.len() - accepted - rejected,
+            "rejected": rejected,
+            "job_ids": job_ids,
+        }),
+    )
+    .await;
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({ "accepted": accepted, "rejected": rejected, "results": results })),
+    )
+        .into_response())
+}
+
+async fn prepare_batch_entry(
+    state: &AppState,
+    headers: &HeaderMap,
+    index: usize,
+    entry: Value,
+    force: bool,
+) -> Result<PreparedCommand, (StatusCode, Json<Value>)> {
+    let entry = parse_batch_entry(entry)?;
+    let mut payload = entry.payload;
+    let contract = state.contract.current();
+    let (canonical, aliased) = contract.registry.resolve_alias(&entry.operation);
+    let operation = canonical.to_string();
+    let declares_commands = contract.registry.commands().next().is_some();
+    if declares_commands && !contract.registry.is_command(&operation) {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": "unknown_operation", "operation": entry.operation })),
+        ));
+    }
+    let deprecated = !check_deprecation(state, &operation).await?.is_empty();
+    let requested_operation = aliased.then(|| entry.operation.clone());
+    if let Some(alias) = &requested_operation {
+        note_alias(state, alias, &operation, &mut HeaderMap::new()).await;
+    }
+
+    let labels = take_labels(&mut payload).map_err(label_rejection)?;
+    let meta = submission_meta(headers, &payload, with_labels(entry.meta, labels))?;
+    apply_batch_fields(&mut payload, entry.ttl_ms, entry.destination_identity)?;
+    let delivery = resolve_delivery(state, &operation, &mut payload).await?;
+    let mut dispatch = routed_dispatch(state, &operation, &payload).await?;
+    dispatch.delivery = delivery;
+    dispatch.meta = meta;
+    check_peer_compatibility(state, &operation, &mut dispatch, force).await?;
+    Ok(PreparedCommand {
+        index,
+        operation,
+        requested_operation,
+        deprecated,
+        payload,
+        dispatch,
+        idempotency_key: entry.idempotency_key,
+    })
+}
+
+fn invalid_batch_entry(detail: String) -> (StatusCode, Json<Value>) {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(json!({ "error": "invalid_batch_entry", "detail": detail })),
+    )
+}
+
+fn parse_batch_entry(entry: Value) -> Result<BatchEntry, (StatusCode, Json<Value>)> {
+    let entry: BatchEntry =
+        serde_json::from_value(entry).map_err(|err| invalid_batch_entry(err.to_string()))?;
+    if !entry.payload.is_object() {
+        return Err(invalid_batch_entry("payload must be an object".to_string()));
+    }
+    if entry.payload.get(ATTACHMENTS_FIELD).is_some() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error":"attachments_not_batched"})),
+        ));
+    }
+    if entry.payload.get(DEPENDS_ON_FIELD).is_some() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error":"dependencies_not_batched"})),
+        ));
+    }
+    if entry.idempotency_key.as_deref().is_some_and(str::is_empty) {
+        return Err(invalid_batch_entry(
+            "idempotency_key must not be empty".to_string(),
+        ));
+    }
+    Ok(entry)
+}
+
+// Folds the entry's own ttl and destination into its payload.
+fn apply_batch_fields(
+    payload: &mut Value,
+    ttl_ms: Option<u64>,
+    destination: Option<String>,
+) -> Result<(), (StatusCode, Json<Value>)> {
+    if let Some(ttl_ms) = ttl_ms {
+        payload["ttl_ms"] = json!(ttl_ms);
+    }
+    if let Some(destination) = destination {
+        let destination = IdentityHash::parse(&destination).map_err(|err| {
+            invalid_batch_entry(format!("{destination} is not an identity hash: {err}"))
+        })?;
+        payload["destination_identity"] = Value::String(destination.into_string());
+    }
+    Ok(())
+}
+
+// One report per entry. Entries the real batch would accept share the rate limit in order;
+// duplicates of an earlier entry or an existing job are reported but take no share.
+async fn dry_run_batch(
+    state: &AppState,
+    headers: &HeaderMap,
+    entries: Vec<Value>,
+    force: bool,
+    probe: bool,
+) -> Result<Vec<Value>, (StatusCode, Json<Value>)> {
+    let mut available = available_submissions(state, headers).await;
+    let drain_interval = state.node_config.read().await.submission_spool.drain_interval();
+    let mut first_by_key = BTreeMap::new();
+    let mut results = Vec::with_capacity(entries.len());
+    for (index, entry) in entries.into_iter().enumerate() {
+        let mut report = DryRunReport::default();
+        let parsed = parse_batch_entry(entry).and_then(|mut entry| {
+            apply_batch_fields(
+                &mut entry.payload,
+                entry.ttl_ms,
+                entry.destination_identity.take(),
+            )?;
+            Ok(entry)
+        });
+        let Some(entry) = report.check("entry", parsed) else {
+            for stage in COMMAND_STAGES
+                .into_iter()
+                .chain(["idempotency", "rate_limit"])
+            {
+                report.skip(stage);
+            }
+            results.push(batch_dry_run_result(index, report)?);
+            continue;
+        };
+        dry_run_command(
+            state,
+            &mut report,
+            headers,
+            &entry.operation,
+            entry.payload,
+            entry.meta,
+            force,
+            probe,
+            true,
+        )
+        .await?;
+
+        let mut duplicate = None;
+        if let Some(key) = &entry.idempotency_key {
+            if let Some(first) = first_by_key.get(key) {
+                duplicate = Some(json!({ "same_as_index": first }));
+            } else {
+                first_by_key.insert(key.clone(), index);
+                let mut existing = state
+                    .storage
+                    .find_job_by_idempotency_key(key)
+                    .await
+                    .map_err(storage_error)?;
+                if existing.is_none() {
+                    let spooled = state.submission_spool.find_key(key).await;
+                    existing = spooled
+                        .map_err(|err| submission_spool_error(drain_interval, err))?
+                        .map(|entry| entry.job_id);
+                }
+                duplicate = existing.map(|job_id| json!({ "duplicate_of": job_id }));
+            }
+        }
+        report.pass("idempotency", duplicate.clone());
+        if duplicate.is_some() || report.refused() {
+            report.skip("rate_limit");
+        } else if available == Some(0) {
+            let (status, Json(error)) = rate_limited(rate_limit_advice(state, headers).await);
+            report.fail("rate_limit", Some(status), error);
+        } else {
+            available = available.map(|left| left - 1);
+            report.pass("rate_limit", None);
+        }
+        results.push(batch_dry_run_result(index, report)?);
+    }
+    Ok(results)
+}
+
+fn batch_dry_run_result(
+    index: usize,
+    report: DryRunReport,
+) -> Result<Value, (StatusCode, Json<Value>)> {
+    let mut result = serde_json::to_value(report).map_err(|e| internal_error(e.into()))?;
+    result["index"] = json!(index);
+    Ok(result)
+}
+fn batch_result(outcomes: &[BatchOutcome], index: usize) -> Value {
+    match &outcomes[index] {
+        BatchOutcome::Accepted(accepted) => {
+            let mut result = json!({ "index": index, "status": "accepted" });
+            for (key, value) in accepted.as_object().into_iter().flatten() {
+                result[key] = value.clone();
+            }
+            result
+        }
+        BatchOutcome::Duplicate(job_id) => json!({
+            "index": index,
+            "status": "duplicate",
+            "job_id": job_id,
+            "status_url": format!("/v1/jobs/{job_id}"),
+        }),
+        BatchOutcome::SameAs(first) => {
+            let mut result = batch_result(outcomes, *first);
+            result["index"] = json!(index);
+            if result["status"] == "accepted" {
+                result["status"] = json!("duplicate");
+            }
+            result
+        }
+        BatchOutcome::Rejected(status, error) => json!({
+            "index": index,
+            "status": "rejected",
+            "http_status": status.as_u16(),
+            "error": error,
+        }),
+    }
+}
+
+async fn resolve_delivery(
+    state: &AppState,
+    operation: &str,
+    payload: &mut Value,
+) -> Result<EffectiveDelivery, (StatusCode, Json<Value>)> {
+    let settings = state.node_config.read().await.delivery.clone();
+    let contract = state.contract.current();
+    take_delivery(payload, &settings, contract.registry.delivery(operation)).map_err(|rejection| {
+        match rejection {
+            DeliveryRejection::Malformed(detail) => (
+                StatusCode::BAD_REQUEST,
+                Json(json!({ "error": "invalid_delivery", "detail": detail })),
+            ),
+            DeliveryRejection::RetryNotAllowed => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(json!({ "error": "retry_not_allowed_for_operation", "operation": operation })),
+            ),
+        }
+    })
+}
+
+// Refuses reserved payload keys, then builds the block from the headers and, for a batch entry,
+// its own `meta`.
+fn submission_meta(
+    headers: &HeaderMap,
+    payload: &Value,
+    submitted: Option<EnvelopeMeta>,
+) -> Result<Option<EnvelopeMeta>, (StatusCode, Json<Value>)> {
+    check_payload(payload).map_err(meta_rejection)?;
+    let from_headers = meta_from_headers(headers).map_err(meta_rejection)?;
+    resolve_meta(from_headers, submitted).map_err(meta_rejection)
+}
+
+fn meta_rejection(rejection: MetaRejection) -> (StatusCode, Json<Value>) {
+    match rejection {
+        MetaRejection::ReservedKey(key) => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(json!({ "error": RESERVED_KEY_ERROR, "key": key })),
+        ),
+        MetaRejection::Invalid(detail) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": INVALID_META_ERROR, "detail": detail })),
+        ),
+    }
+}
+
+fn label_rejection(detail: String) -> (StatusCode, Json<Value>) {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(json!({ "error": INVALID_LABELS_ERROR, "detail": detail })),
+    )
+}
+
+fn label_filter_rejection(detail: String) -> (StatusCode, Json<Value>) {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(json!({ "error": INVALID_LABEL_FILTER_ERROR, "detail": detail })),
+    )
+}
+
+fn attachment_rejection(rejection: AttachmentRejection) -> (StatusCode, Json<Value>) {
+    match rejection {
+        AttachmentRejection::Disabled => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error":"attachments_disabled"})),
+        ),
+        AttachmentRejection::Malformed(detail) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error":"invalid_attachments","detail":detail})),
+        ),
+        AttachmentRejection::AttachmentTooLarge {
+            file_name,
+            limit,
+            actual,
+        } => (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            Json(json!({
+                "error": "attachment_too_large",
+                "file_name": file_name,
+                "limit_bytes": limit,
+                "size_bytes": actual,
+            })),
+        ),
+        AttachmentRejection::JobTooLarge { limit, actual } => (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            Json(json!({
+                "error": "attachments_too_large",
+                "limit_bytes": limit,
+                "size_bytes": actual,
+            })),
+        ),
+    }
+}
+
+// Deprecated operations still run but are flagged; past their sunset they are refused
+// unless the node explicitly allows them.
+async fn check_deprecation(
+    state: &AppState,
+    operation: &str,
+) -> Result<HeaderMap, (StatusCode, Json<Value>)> {
+    let mut headers = HeaderMap::new();
+    let Some(deprecation) = check_sunset(state, operation).await? else {
+        return Ok(headers);
+    };
+
+    *state
+        .deprecated_usage
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .entry(operation.to_string())
+        .or_default() += 1;
+    let sunset = deprecation
+        .sunset
+        .map(|date| date.to_string())
+        .unwrap_or_else(|| "none".to_string());
+    write_log(
+        state,
+        "warn",
+        &format!("deprecated_operation_used operation={operation} sunset={sunset}"),
+    )
+    .await;
+
+    headers.insert(HeaderName::from_static("deprecation"), HeaderValue::from_static("true"));
+    if let Some(sunset) = deprecation.sunset_http_date() {
+        if let Ok(value) = HeaderValue::from_str(&sunset) {
+            headers.insert(HeaderName::from_static("sunset"), value);
+        }
+    }
+    Ok(headers)
+}
+
+// Returns the operation's deprecation, if any, without counting or logging its use.
+async fn check_sunset(
+    state: &AppState,
+    operation: &str,
+) -> Result<Option<Deprecation>, (StatusCode, Json<Value>)> {
+    let Some(deprecation) = state.contract.current().registry.deprecation(operation).cloned() else {
+        return Ok(None);
+    };
+    let allow_sunset = state.node_config.read().await.allow_sunset_operations;
+    if deprecation.is_sunset(Utc::now()) && !allow_sunset {
+        return Err((
+            StatusCode::GONE,
+            Json(json!({
+                "error": "operation_sunset",
+                "operation": operation,
+                "sunset": deprecation.sunset,
+            })),
+        ));
+    }
+    Ok(Some(deprecation))
+}
+
+// An aliased submission runs as the current operation; the headers name it for the client.
+async fn note_alias(state: &AppState, alias: &str, operation: &str, headers: &mut HeaderMap) {
+    write_log(
+        state,
+        "warn",
+        &format!("aliased_operation_used alias={alias} operation={operation}"),
+    )
+    .await;
+    headers.insert(HeaderName::from_static("deprecation"), HeaderValue::from_static("true"));
+    let successor = format!("</v1/jobs/commands/{operation}>; rel=\"successor-version\"");
+    if let Ok(value) = HeaderValue::from_str(&successor) {
+        headers.insert(header::LINK, value);
+    }
+}
+
+struct LinkedTransfer {
+    transfer_id: String,
+    request: TransferUploadRequest,
+    bytes: Vec<u8>,
+}
+
+// Workers run under a job lease so the watchdog can recover the job if they are lost, and wait
+// their turn in the dispatch queue before running.
+fn spawn_command_worker(
+    state: &AppState,
+    job_id: String,
+    operation: String,
+    payload: Value,
+    dispatch: Dispatch,
+    transfers: Vec<LinkedTransfer>,
+) {
+    let state_for_task = state.clone();
+    let job_id_for_task = job_id.clone();
+    let priority = dispatch.meta.as_ref().and_then(|meta| meta.priority);
+    spawn_leased(state.clone(), job_id, async move {
+        let limit = state_for_task.node_config.read().await.dispatch_queue.max_concurrent_jobs;
+        let _slot = state_for_task
+            .dispatch_queue
+            .enter(&job_id_for_task, priority, limit)
+            .await;
+        run_command_job(
+            state_for_task,
+            &job_id_for_task,
+            &operation,
+            payload,
+            dispatch,
+            transfers,
+        )
+        .await
+    });
+}
+
+pub(crate) fn redeliver_command_job(
+    state: &AppState,
+    job_id: String,
+    operation: String,
+    payload: Value,
+    dispatch: Dispatch,
+) {
+    spawn_command_worker(state, job_id, operation, payload, dispatch, Vec::new());
+}
+
+async fn run_command_job(
+    state: AppState,
+    job_id: &str,
+    operation: &str,
+    payload: Value,
+    dispatch: Dispatch,
+    transfers: Vec<LinkedTransfer>,
+) -> Result<(), JobProcessingError> {
+    let mode = state.node_config.read().await.attachments.dispatch;
+    match mode {
+        AttachmentDispatch::Concurrent => {
+            let (command, transfers) = tokio::join!(
+                process_command_job(state.clone(), job_id, operation, payload, dispatch),
+                send_job_transfers(&state, job_id, transfers),
+            );
+            command.and(transfers.map_err(JobProcessingError::from))
+        }
+        AttachmentDispatch::AfterCommand => {
+            process_command_job(state.clone(), job_id, operation, payload, dispatch).await?;
+            Ok(send_job_transfers(&state, job_id, transfers).await?)
+        }
+    }
+}
+
+async fn send_job_transfers(
+    state: &AppState,
+    job_id: &str,
+    transfers: Vec<LinkedTransfer>,
+) -> anyhow::Result<()> {
+    for transfer in transfers {
+        // A failed command fails the attachments that have not started yet.
+        let queued = state
+            .storage
+            .get_transfer(&transfer.transfer_id)
+            .await?
+            .is_some_and(|record| record.status == "queued");
+        if !queued {
+            continue;
+        }
+        let sent = process_transfer_job(
+            state.clone(),
+            &transfer.transfer_id,
+            transfer.request,
+            transfer.bytes.into(),
+        )
+        .await;
+        if let Err(err) = sent {
+            let reason = format!("attachment_dispatch_failed: {err:#}");
+            state
+                .storage
+                .with_event(
+                    "transfer.failed",
+                    json!({
+                        "transfer_id": transfer.transfer_id,
+                        "status": "failed",
+                        "reason": reason
+                    }),
+                )
+                .fail_job_transfers(job_id, &transfer.transfer_id, &reason)
+                .await?;
+            publish(state).await;
+            complete_job(state, job_id, None).await?;
+            return Err(err);
+        }
+        complete_job(state, job_id, None).await?;
+    }
+    Ok(())
+}
+
+pub(crate) fn is_settled_transfer(status: &str) -> bool {
+    matches!(
+        status,
+        "success" | "failed" | "cancelled" | SKIPPED_DUPLICATE_STATUS
+    )
+}
+
+// A job finishes once its result is in and every attachment transfer has settled; failed
+// transfers turn an otherwise successful command into a partial failure. `result` is the
+// command result when it has just arrived, stored together with the final status.
+pub(crate) async fn complete_job(
+    state: &AppState,
+    job_id: &str,
+    result: Option<Value>,
+) -> anyhow::Result<()> {
+    let transfers = state.storage.list_job_transfers(job_id).await?;
+    if transfers
+        .iter()
+        .any(|transfer| !is_settled_transfer(&transfer.status))
+    {
+        if let Some(result) = result {
+            state.storage.insert_job_result(job_id, result).await?;
+        }
+        return Ok(());
+    }
+    let Some(job) = state.storage.get_job(job_id).await? else {
+        return Ok(());
+    };
+    if is_terminal(&job.status) {
+        return Ok(());
+    }
+    // A job whose submitter already held the result succeeds without one.
+    let unchanged = result.is_none()
+        && state
+            .storage
+            .get_job_result_digest(job_id)
+            .await?
+            .is_some_and(|digest| digest.unchanged);
+    if result.is_none() && !unchanged && state.storage.get_job_result(job_id).await?.is_none() {
+        return Ok(());
+    }
+
+    let failed: Vec<Value> = transfers
+        .iter()
+        .filter(|transfer| transfer.status == "failed")
+        .map(|transfer| {
+            json!({ "transfer_id": transfer.transfer_id, "reason": transfer.failure_reason })
+        })
+        .collect();
+    if failed.is_empty() {
+        let mut event = json!({ "job_id": job_id, "status": "success" });
+        if unchanged {
+            event["result_unchanged"] = json!(true);
+        }
+        if !finish_job(state, job_id, result, "success", None, event).await? {
+            return Ok(());
+        }
+        write_log(state, "info", &format!("job {} completed", job_id)).await;
+        settle_dependents(state, job_id).await;
+        return Ok(());
+    }
+
+    let reason = format!(
+        "{} of {} attachment transfers failed",
+        failed.len(),
+        transfers.len()
+    );
+    let event = json!({
+        "job_id": job_id,
+        "status": "partial_failure",
+        "reason": reason,
+        "failed_attachments": failed,
+    });
+    if !finish_job(state, job_id, result, "partial_failure", Some(&reason), event).await? {
+        return Ok(());
+    }
+    write_log(state, "warn", &format!("job {} partially failed", job_id)).await;
+    settle_dependents(state, job_id).await;
+    Ok(())
+}
+
+async fn finish_job(
+    state: &AppState,
+    job_id: &str,
+    result: Option<Value>,
+    status: &str,
+    failure_reason: Option<&str>,
+    event: Value,
+) -> anyhow::Result<bool> {
+    // Conditional, so a concurrent cancellation or a second result cannot finish it twice.
+    let finished = state
+        .storage
+        .with_event(FEED_JOB_EVENT, event)
+        .finish_job(job_id, result, status, failure_reason)
+        .await?;
+    if finished {
+        publish(state).await;
+    }
+    Ok(finished)
+}
+
+async fn process_command_job(
+    state: AppState,
+    job_id: &str,
+    operation: &str,
+    payload: Value,
+    mut dispatch: Dispatch,
+) -> Result<(), JobProcessingError> {
+    state.mute.hold(Traffic::Commands).await;
+    if is_cancelled(&state, job_id).await? {
+        return Ok(());
+    }
+    state
+        .storage
+        .with_event(
+            FEED_JOB_EVENT,
+            json!({
+                "job_id": job_id,
+                "status": "running"
+            }),
+        )
+        .update_job_status(job_id, "running", None)
+        .await?;
+    publish(&state).await;
+
+    let cache_settings = state.node_config.read().await.result_cache.clone();
+    let cache_key = cache_settings
+        .caches(operation)
+        .then(|| result_key(operation, &dispatch.destination_identity, &payload))
+        .flatten();
+    let cached = cache_key
+        .as_ref()
+        .and_then(|key| state.result_cache.lookup(&cache_settings, key));
+    if let Some(cached) = cached {
+        answer_from_cache(&state, job_id, &dispatch, cached).await?;
+        return Ok(());
+    }
+
+    let destination = dispatch.destination_identity.clone();
+    let admission = admit_job(&state, job_id, &destination, operation).await;
+    if let Admission::Rejected { retry_at } = admission {
+        refuse_job(&state, job_id, retry_at).await?;
+        settle_dependents(&state, job_id).await;
+        return Ok(());
+    }
+
+    let config = state.node_config.read().await.clone();
+    let liveness = check_destination(&state, &mut dispatch, &config.liveness).await;
+    if dispatch.liveness.bypassed || dispatch.liveness.probe_outcome.is_some() {
+        state
+            .storage
+            .set_job_dispatch(job_id, &serde_json::to_value(&dispatch)?)
+            .await?;
+    }
+    if let Err(failure) = liveness {
+        state
+            .storage
+            .with_event(
+                FEED_JOB_EVENT,
+                json!({
+                    "job_id": job_id,
+                    "status": "failed",
+                    "reason": failure.code(),
+                    "detail": failure.detail(),
+                }),
+            )
+            .fail_job(job_id, &failure.reason())
+            .await?;
+        publish(&state).await;
+        settle_dependents(&state, job_id).await;
+        return Ok(());
+    }
+
+    let payload =
+        match transform_payload(&state, job_id, TransformStage::Command, operation, payload).await?
+        {
+            Ok(payload) => payload,
+            Err(failure) => {
+                fail_transformed_job(&state, job_id, &failure).await?;
+                return Ok(());
+            }
+        };
+
+    let sealing = seal_outgoing(
+        &state,
+        &config.security,
+        operation,
+        &dispatch.destination_identity,
+        &payload,
+    )
+    .await?;
+    let (payload, content_type) = match sealing {
+        Outgoing::Plain => command_payload(
+            &state,
+            &dispatch.destination_identity,
+            payload,
+            config.compression_threshold_bytes,
+        )?,
+        Outgoing::Sealed(sealed) => (sealed, CONTENT_TYPE_SEALED.to_string()),
+        Outgoing::NoKey => {
+            fail_unsealed(&state, job_id, &dispatch.destination_identity).await?;
+            return Ok(());
+        }
+    };
+
+    let planned = state.bridge.planned_transport(dispatch.transport_hint.clone());
+    let delivery = dispatch.delivery.clone();
+    let mut envelope = command_envelope(dispatch, operation, payload, content_type, &planned);
+    let oversize = check_envelope(
+        &config,
+        planned,
+        &envelope,
+        &envelope.payload,
+        &envelope.content_type,
+        true,
+    )?;
+    if let Some(oversize) = oversize {
+        fail_oversize(&state, job_id, operation, oversize).await?;
+        return Ok(());
+    }
+
+    state
+        .storage
+        .record_job_message(&envelope.message_id, job_id)
+        .await?;
+    let origin_trace = envelope.trace.clone();
+    if !origin_trace.is_empty() {
+        state
+            .storage
+            .save_job_trace(job_id, &serde_json::to_value(&origin_trace)?, false, None)
+            .await?;
+    }
+    // A job cancelled while it was being prepared is never handed to the bridge.
+    if is_cancelled(&state, job_id).await? {
+        return Ok(());
+    }
+
+    let mut attempts = AttemptLog::new(job_id, delivery.store_and_forward_ttl_ms.is_some());
+    let mut sent = send_with_delivery(&state, &mut attempts, envelope.clone(), &delivery).await;
+    if is_cancelled(&state, job_id).await? {
+        return Ok(());
+    }
+    let mut escalated = false;
+    if let (Err(BridgeError::PeerUnreachable(reason)), Some(ttl_ms)) =
+        (&sent, delivery.store_and_forward_ttl_ms)
+    {
+        escalated = true;
+        attempts.escalate();
+        let unreachable = EscalationRecord {
+            transport_hint: envelope.transport_hint.clone(),
+            ttl_ms: envelope.ttl_ms,
+            detail: Some(reason.clone()),
+            ..EscalationRecord::new(EscalationStep::Unreachable)
+        };
+        record_escalation(&state, job_id, unreachable).await?;
+        escalate_envelope(&mut envelope, ttl_ms);
+        let planned = state
+            .bridge
+            .planned_transport(envelope.transport_hint.clone());
+        let oversize = check_envelope(
+            &config,
+            planned,
+            &envelope,
+            &envelope.payload,
+            &envelope.content_type,
+            true,
+        )?;
+        if let Some(oversize) = oversize {
+            fail_oversize(&state, job_id, operation, oversize).await?;
+            return Ok(());
+        }
+        let escalation = EscalationRecord {
+            transport_hint: envelope.transport_hint.clone(),
+            ttl_ms: envelope.ttl_ms,
+            ..EscalationRecord::new(EscalationStep::Escalated)
+        };
+        record_escalation(&state, job_id, escalation).await?;
+        write_log(
+            &state,
+            "warn",
+            &format!("job {job_id} destination unreachable; escalated to LXMF store-and-forward"),
+        )
+        .await;
+        sent = send_with_delivery(&state, &mut attempts, envelope.clone(), &delivery).await;
+        if is_cancelled(&state, job_id).await? {
+            return Ok(());
+        }
+    }
+    if let (false, Ok(result)) = (origin_trace.is_empty(), &sent) {
+        // A peer without tracing answers with no trace; the origin hop still dates the round trip.
+        let (hops, truncated) = if result.trace.is_empty() {
+            (&origin_trace, false)
+        } else {
+            (&result.trace, result.trace_truncated)
+        };
+        let returned_at = Utc::now().to_rfc3339();
+        state
+            .storage
+            .save_job_trace(job_id, &serde_json::to_value(hops)?, truncated, Some(&returned_at))
+            .await?;
+    }
+    match sent {
+        Ok(result) if is_in_transit(&result.payload) => {
+            let ttl_ms = envelope
+                .ttl_ms
+                .unwrap_or(config.delivery.store_and_forward_ttl_ms);
+            mark_in_transit(&state, job_id, &envelope, ttl_ms).await?;
+        }
+        Ok(result) if is_streaming(&result.payload) => {
+            // Parts may already have completed the job by the time the ack lands.
+            if state.storage.get_job_result(job_id).await?.is_none() {
+                mark_streaming(&state, job_id).await?;
+            }
+        }
+        Ok(result) => {
+            if escalated {
+                record_escalation(
+                    &state,
+                    job_id,
+                    EscalationRecord::new(EscalationStep::Delivered),
+                )
+                .await?;
+            }
+            let conditional = envelope
+                .meta
+                .as_ref()
+                .is_some_and(|meta| meta.if_result_digest_differs.is_some());
+            let unchanged = not_modified_digest(&result.payload).filter(|_| conditional);
+            if let Some((digest, bytes)) = unchanged {
+                state.result_cache.note_not_modified(bytes);
+                state
+                    .storage
+                    .record_job_result_digest(job_id, digest, true, RESULT_SOURCE_MESH)
+                    .await?;
+                complete_job(&state, job_id, None).await?;
+                return Ok(());
+            }
+            match transform_payload(
+                &state,
+                job_id,
+                TransformStage::Result,
+                operation,
+                result.payload,
+            )
+            .await?
+            {
+                Ok(payload) => {
+                    let (payload, conforming) =
+                        match screen_result(&state, job_id, operation, payload).await? {
+                            Screened::Conforming(payload) => (payload, true),
+                            Screened::Nonconforming(payload) => (payload, false),
+                            Screened::Quarantined => return Ok(()),
+                        };
+                    let digest = canonical_digest(&payload)?;
+                    state
+                        .storage
+                        .record_job_result_digest(job_id, &digest, false, RESULT_SOURCE_MESH)
+                        .await?;
+                    // An error answer is not worth repeating to the next identical submission.
+                    let reusable = conforming && payload.get("error").is_none();
+                    if let Some(key) = cache_key.filter(|_| reusable) {
+                        state.result_cache.store(&cache_settings, key, &payload);
+                    }
+                    complete_job(&state, job_id, Some(payload)).await?
+                }
+                Err(failure) => fail_transformed_job(&state, job_id, &failure).await?,
+            }
+        }
+        Err(error) => {
+            state
+                .storage
+                .with_event(
+                    FEED_JOB_EVENT,
+                    json!({ "job_id": job_id, "status": "failed", "reason": error.to_string() }),
+                )
+                .fail_job(job_id, &error.to_string())
+                .await?;
+            publish(&state).await;
+            write_log(&state, "error", &format!("job {} failed", job_id)).await;
+            settle_dependents(&state, job_id).await;
+        }
+    }
+
+    Ok(())
+}
+
+// A fresh cached result settles the job with no mesh traffic at all. A submitter that already
+// holds it gets the same unchanged success a peer's `not_modified` would have produced.
+async fn answer_from_cache(
+    state: &AppState,
+    job_id: &str,
+    dispatch: &Dispatch,
+    cached: CachedResult,
+) -> anyhow::Result<()> {
+    state.result_cache.note_hit(cached.bytes);
+    let held = dispatch
+        .meta
+        .as_ref()
+        .and_then(|meta| meta.if_result_digest_differs.as_deref());
+    let unchanged = held == Some(cached.digest.as_str());
+    state
+        .storage
+        .record_job_result_digest(job_id, &cached.digest, unchanged, RESULT_SOURCE_CACHE)
+        .await?;
+    write_log(
+        state,
+        "info",
+        &format!("job {job_id} answered from the local result cache"),
+    )
+    .await;
+    complete_job(state, job_id, (!unchanged).then_some(cached.result)).await
+}
+
+// A sealed operation is never sent in the clear, so a destination without a key fails the job.
+async fn fail_unsealed(state: &AppState, job_id: &str, destination: &str) -> anyhow::Result<()> {
+    state
+        .storage
+        .with_event(
+            FEED_JOB_EVENT,
+            json!({
+                "job_id": job_id,
+                "status": "failed",
+                "reason": SEALING_KEY_MISSING_ERROR,
+                "destination_identity": destination,
+            }),
+        )
+        .fail_job(job_id, SEALING_KEY_MISSING_ERROR)
+        .await?;
+    publish(state).await;
+    settle_dependents(state, job_id).await;
+    Ok(())
+}
+
+async fn fail_oversize(
+    state: &AppState,
+    job_id: &str,
+    operation: &str,
+    oversize: Oversize,
+) -> anyhow::Result<()> {
+    let reason = oversize.reason();
+    record_oversize(state, operation, &reason).await;
+    state
+        .storage
+        .with_event(
+            FEED_JOB_EVENT,
+            json!({
+                "job_id": job_id,
+                "status": "failed",
+                "reason": "envelope_too_large",
+                "detail": oversize,
+            }),
+        )
+        .fail_job(job_id, &reason)
+        .await?;
+    publish(state).await;
+    settle_dependents(state, job_id).await;
+    Ok(())
+}
+
+// Compresses a plain command payload when the destination accepts a scheme and the payload is
+// over the threshold. The `compression` flag turns compression off for every peer, whatever
+// they advertise.
+fn command_payload(
+    state: &AppState,
+    destination: &str,
+    payload: Value,
+    threshold: usize,
+) -> Result<(Value, String), CodecError> {
+    if !state.features.is_enabled(COMPRESSION_FLAG) {
+        return Ok((payload, CONTENT_TYPE_MSGPACK.to_string()));
+    }
+    let content_type = state
+        .peers
+        .negotiate_content_type(destination, &payload, threshold)?;
+    match Compression::from_content_type(&content_type)? {
+        Some(compression) => Ok((compress_payload(&payload, compression)?, content_type)),
+        None => Ok((payload, content_type)),
+    }
+}
+
+fn command_envelope(
+    dispatch: Dispatch,
+    operation: &str,
+    payload: Value,
+    content_type: String,
+    planned: &TransportSelection,
+) -> MeshCommandEnvelope<Value> {
+    let sent_at = Utc::now();
+    let trace = if dispatch.tracing_enabled {
+        vec![HopRecord {
+            identity: dispatch.source_identity.clone(),
+            received_at: None,
+            forwarded_at: Some(sent_at),
+            transport: Some(transport_hint(planned)),
+            note: Some("origin".to_string()),
+        }]
+    } else {
+        Vec::new()
+    };
+    MeshCommandEnvelope {
+        message_id: Uuid::now_v7().to_string(),
+        operation: operation.to_string(),
+        sent_at,
+        source_identity: dispatch.source_identity,
+        destination_identity: dispatch.destination_identity,
+        content_type,
+        payload,
+        ttl_ms: dispatch.ttl_ms,
+        transport_hint: dispatch.transport_hint,
+        trace,
+        trace_truncated: false,
+        meta: dispatch.meta,
+    }
+}
+
+// Runs the registered transforms for `stage` and keeps the before and after payloads on the
+// job when any of them applied.
+pub(crate) async fn transform_payload(
+    state: &AppState,
+    job_id: &str,
+    stage: TransformStage,
+    operation: &str,
+    payload: Value,
+) -> anyhow::Result<Result<Value, TransformError>> {
+    let before = payload.clone();
+    let transformed = match state.transforms.apply(stage, operation, payload) {
+        Ok(transformed) => transformed,
+        Err(failure) => return Ok(Err(failure)),
+    };
+    if !transformed.applied.is_empty() {
+        state
+            .storage
+            .save_job_transform(
+                job_id,
+                stage.as_str(),
+                &transformed.applied,
+                &before,
+                &transformed.payload,
+            )
+            .await?;
+    }
+    Ok(Ok(transformed.payload))
+}
+
+pub(crate) async fn fail_transformed_job(
+    state: &AppState,
+    job_id: &str,
+    failure: &TransformError,
+) -> anyhow::Result<()> {
+    state
+        .storage
+        .with_event(
+            FEED_JOB_EVENT,
+            json!({
+                "job_id": job_id,
+                "status": "failed",
+                "reason": failure.code(),
+                "detail": failure.detail(),
+            }),
+        )
+        .fail_job(job_id, &failure.reason())
+        .await?;
+    publish(state).await;
+    write_log(
+        state,
+        "error",
+        &format!("job {job_id} failed: {}", failure.reason()),
+    )
+    .await;
+    settle_dependents(state, job_id).await;
+    Ok(())
+}
+
+// Resends the same envelope, so the peer can drop duplicates by message id, until an attempt
+// succeeds or the policy runs out. Payloads the bridge rejects are not retried. Every call is
+// written to the attempt log as soon as it returns.
+async fn send_with_delivery(
+    state: &AppState,
+    attempts: &mut AttemptLog,
+    envelope: MeshCommandEnvelope<Value>,
+    delivery: &EffectiveDelivery,
+) -> Result<MeshResultEnvelope<Value>, BridgeError> {
+    let job_id = attempts.job_id().to_string();
+    let planned = state.bridge.planned_transport(envelope.transport_hint.clone());
+    let mut attempt = 1;
+    loop {
+        let dispatched_at = Utc::now();
+        let sent = state.bridge.send_command(envelope.clone());
+        let outcome = match delivery.timeout_ms {
+            Some(timeout_ms) => {
+                tokio::time::timeout(std::time::Duration::from_millis(timeout_ms), sent)
+                    .await
+                    .unwrap_or_else(|_| {
+                        Err(BridgeError::SendFailed(format!(
+                            "attempt timed out after {timeout_ms}ms"
+                        )))
+                    })
+            }
+            None => sent.await,
+        };
+        let retrying = outcome
+            .as_ref()
+            .is_err_and(|error| attempt < delivery.max_attempts && retryable(error, delivery));
+        attempts
+            .record(state, &envelope, &planned, dispatched_at, &outcome, retrying)
+            .await;
+        match outcome {
+            Err(error) if retrying => {
+                let backoff = delivery.backoff_after(attempt);
+                write_log(
+                    state,
+                    "warn",
+                    &format!(
+                        "job {job_id} attempt {attempt}/{} failed: {error}; retrying in {}ms",
+                        delivery.max_attempts,
+                        backoff.as_millis()
+                    ),
+                )
+                .await;
+                tokio::time::sleep(backoff).await;
+                attempt += 1;
+            }
+            outcome => {
+                if let Ok(result) = &outcome {
+                    let received_at = Utc::now();
+                    record_contact(state, &envelope.destination_identity, received_at).await;
+                    observe_result(
+                        state,
+                        &result.source_identity,
+                        result.sent_at,
+                        dispatched_at,
+                        received_at,
+                    )
+                    .await;
+                }
+                return outcome;
+            }
+        }
+    }
+}
+
+// An unreachable peer is left to store-and-forward when the policy allows it, rather than
+// retried over the same transport.
+fn retryable(error: &BridgeError, delivery: &EffectiveDelivery) -> bool {
+    match error {
+        BridgeError::InvalidPayload(_) => false,
+        BridgeError::PeerUnreachable(_) => delivery.store_and_forward_ttl_ms.is_none(),
+        _ => true,
+    }
+}
+
+// `TransferUploadRequest` with the base64 borrowed from the request body rather than copied.
+#[cfg(feature = "transfers")]
+#[derive(Debug, Deserialize)]
+struct InlineUpload<'a> {
+    destination_identity: String,
+    file_name: String,
+    media_type: String,
+    #[serde(borrow)]
+    payload_base64: std::borrow::Cow<'a, str>,
+    dedup: Option<bool>,
+    #[serde(default)]
+    labels: Labels,
+}
+
+#[cfg(feature = "transfers")]
+async fn post_transfer_job(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    authorize(&state, &headers, true).await?;
+    let settings = state.node_config.read().await.transfer_spool.clone();
+    let too_large = || {
+        (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            Json(json!({"error":"upload_too_large","limit_bytes":settings.max_request_bytes})),
+        )
+    };
+    let declared = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    if declared.is_some_and(|declared| declared > settings.max_request_bytes) {
+        return Err(too_large());
+    }
+    let limit = usize::try_from(settings.max_request_bytes).unwrap_or(usize::MAX);
+    let raw = axum::body::to_bytes(body, limit)
+        .await
+        .map_err(|_| too_large())?;
+    let upload: InlineUpload<'_> = serde_json::from_slice(&raw).map_err(|err| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error":"invalid_upload_request","detail":err.to_string()})),
+        )
+    })?;
+    validate_labels(&upload.labels).map_err(label_rejection)?;
+    let destination = client_identity(&state, &upload.destination_identity).await?;
+    let dir = settings.dir_for(state.storage.database_path());
+    let content = state
+        .transfer_spool
+        .decode(&settings, &dir, &upload.payload_base64)
+        .await
+        .map_err(|err| match err {
+            SpoolError::InvalidBase64 => (
+                StatusCode::BAD_REQUEST,
+                Json(json!({"error":"invalid_payload_base64"})),
+            ),
+            SpoolError::Io(err) => internal_error(err.into()),
+        })?;
+    let payload_size = upload.payload_base64.len();
+    let labels = upload.labels;
+    let payload = TransferUploadRequest {
+        destination_identity: destination.into_string(),
+        file_name: upload.file_name,
+        media_type: upload.media_type,
+        payload_base64: String::new(),
+        dedup: upload.dedup.unwrap_or(true),
+    };
+    // The decoded content stands in for the request body from here on.
+    drop(raw);
+    let head = content
+        .head(OUTBOUND_SAMPLE_BYTES)
+        .await
+        .map_err(|err| internal_error(err.into()))?;
+    verify_outbound(&state, [(payload.file_name.as_str(), payload.media_type.as_str(), &head[..])])
+        .await?;
+    let (submitted_by, quota_claims) =
+        enforce_quotas(&state, &headers, &payload.destination_identity, content.len()).await?;
+
+    let metadata = json!({
+        "destination_identity": payload.destination_identity,
+        "file_name": payload.file_name,
+        "media_type": payload.media_type,
+        "payload_size": payload_size,
+        "submitted_by": submitted_by,
+    });
+    let progress = TransferProgress::new(content.len(), DEFAULT_CHUNK_SIZE);
+    let source = submission_source(&*state.node_config.read().await, &headers);
+    let stored = state
+        .storage
+        .with_tx(move |tx| {
+            Box::pin(async move {
+                let transfer = tx
+                    .create_transfer_with_progress(None, &metadata, &progress)
+                    .await?;
+                reserve_upload(tx, &transfer.transfer_id, &quota_claims, progress.bytes_total)
+                    .await?;
+                tx.set_transfer_source(&transfer.transfer_id, &source).await?;
+                tx.put_labels(LABEL_SUBJECT_TRANSFER, &transfer.transfer_id, &labels)
+                    .await?;
+                tx.append_feed_event(
+                    "transfer.progress",
+                    &json!({
+                        "transfer_id": transfer.transfer_id,
+                        "status": "queued"
+                    }),
+                )
+                .await?;
+                Ok(transfer)
+            })
+        })
+        .await;
+    let transfer = match stored {
+        Ok(transfer) => transfer,
+        Err(err) => return Err(admission_error(&state, &payload.destination_identity, err).await),
+    };
+    let transfer_id = transfer.transfer_id.clone();
+    let transfer_submitted_at = transfer.submitted_at.clone();
+    publish(&state).await;
+
+    let state_for_task = state.clone();
+    let transfer_id_for_task = transfer_id.clone();
+    tokio::spawn(async move {
+        if let Err(err) =
+            process_transfer_job(state_for_task, &transfer_id_for_task, payload, content).await
+        {
+            error!(transfer_id = %transfer_id_for_task, error = %err, "transfer processing failed");
+        }
+    });
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(json!({
+            "job_id": transfer_id.clone(),
+            "transfer_id": transfer_id.clone(),
+            "submitted_at": transfer_submitted_at,
+            "status_url": format!("/v1/transfers/{}", transfer_id)
+        })),
+    ))
+}
+
+#[cfg(feature = "transfers")]
+async fn post_bundle_transfer_job(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(mut payload): Json<BundleUploadRequest>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    authorize(&state, &headers, true).await?;
+    payload.destination_identity = client_identity(&state, &payload.destination_identity)
+        .await?
+        .into_string();
+    let settings = state.node_config.read().await.transfer_bundles.clone();
+    let (bundle, bytes) = pack_request(&payload, &settings).map_err(bundle_rejection)?;
+    verify_outbound(
+        &state,
+        bundle
+            .entries
+            .iter()
+            .map(|entry| (entry.name.as_str(), entry.media_type.as_str(), entry.bytes.as_slice())),
+    )
+    .await?;
+    let (submitted_by, quota_claims) =
+        enforce_quotas(&state, &headers, &payload.destination_identity, bytes.len() as u64)
+            .await?;
+
+    let metadata = json!({
+        "destination_identity": payload.destination_identity,
+        "file_name": payload.bundle_name,
+        "media_type": BUNDLE_MEDIA_TYPE,
+        "payload_size": bytes.len(),
+        "submitted_by": submitted_by,
+        "bundle_digest": bundle.digest,
+        "bundle_metadata": bundle.metadata,
+        "size": bundle.total_bytes(),
+    });
+    let progress = TransferProgress::new(bytes.len() as u64, DEFAULT_CHUNK_SIZE);
+    let packed = members(&bundle, |_| PACKED_MEMBER_STATUS);
+    let source = submission_source(&*state.node_config.read().await, &headers);
+    let stored = state
+        .storage
+        .with_tx(move |tx| {
+            Box::pin(async move {
+                let transfer = tx
+                    .create_transfer_with_progress(None, &metadata, &progress)
+                    .await?;
+                reserve_upload(tx, &transfer.transfer_id, &quota_claims, progress.bytes_total)
+                    .await?;
+                tx.set_transfer_source(&transfer.transfer_id, &source).await?;
+                tx.add_bundle_members(&transfer.transfer_id, &packed).await?;
+                tx.append_feed_event(
+                    "transfer.progress",
+                    &json!({ "transfer_id": transfer.transfer_id, "status": "queued" }),
+                )
+                .await?;
+                Ok(transfer)
+            })
+        })
+        .await;
+    let transfer = match stored {
+        Ok(transfer) => transfer,
+        Err(err) => return Err(admission_error(&state, &payload.destination_identity, err).await),
+    };
+    let transfer_id = transfer.transfer_id.clone();
+    publish(&state).await;
+
+    // Receivers record bundle members, never the bundle itself, so a dedup offer would not hit.
+    let request = TransferUploadRequest {
+        destination_identity: payload.destination_identity,
+        file_name: payload.bundle_name,
+        media_type: BUNDLE_MEDIA_TYPE.to_string(),
+        payload_base64: String::new(),
+        dedup: false,
+    };
+    let state_for_task = state.clone();
+    let transfer_id_for_task = transfer_id.clone();
+    tokio::spawn(async move {
+        if let Err(err) =
+            process_transfer_job(state_for_task, &transfer_id_for_task, request, bytes.into())
+                .await
+        {
+            error!(transfer_id = %transfer_id_for_task, error = %err, "bundle transfer failed");
+        }
+    });
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(json!({
+            "job_id": transfer_id.clone(),
+            "transfer_id": transfer_id.clone(),
+            "submitted_at": transfer.submitted_at,
+            "bundle_digest": bundle.digest,
+            "status_url": format!("/v1/transfers/{}", transfer_id)
+        })),
+    ))
+}
+
+// Refuses the first file whose content contradicts its declared media type, when
+// `[files] verify_outbound` is on.
+async fn verify_outbound<'a>(
+    state: &AppState,
+    files: impl IntoIterator<Item = (&'a str, &'a str, &'a [u8])>,
+) -> Result<(), (StatusCode, Json<Value>)> {
+    let settings = state.node_config.read().await.files.clone();
+    for (file_name, media_type, content) in files {
+        check_outbound(&settings, media_type, content)
+            .map_err(|violation| file_rejection(file_name, violation))?;
+    }
+    Ok(())
+}
+
+fn file_rejection(file_name: &str, violation: PolicyViolation) -> (StatusCode, Json<Value>) {
+    let mut body = violation.detail();
+    body["error"] = json!(violation.code());
+    body["file_name"] = json!(file_name);
+    if let Some(fields) = body.as_object_mut() {
+        fields.remove("reason");
+    }
+    (StatusCode::UNPROCESSABLE_ENTITY, Json(body))
+}
+
+#[cfg(feature = "transfers")]
+async fn include_quarantined(
+    state: &AppState,
+    headers: &HeaderMap,
+    query: &FileQuery,
+) -> Result<bool, (StatusCode, Json<Value>)> {
+    if query.include_quarantined.unwrap_or(false) {
+        authorize_admin(state, headers).await?;
+        return Ok(true);
+    }
+    authorize(state, headers, false).await?;
+    Ok(false)
+}
+
+#[cfg(feature = "transfers")]
+async fn list_received_files(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<FileQuery>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    let include_quarantined = include_quarantined(&state, &headers, &query).await?;
+    let codec = cursor_codec(&state).await?;
+    let now = Utc::now();
+    let page = codec
+        .keyset(
+            "received_files",
+            RECEIVED_FILE_ORDER,
+            query.cursor.as_deref(),
+            page_limit(query.limit),
+            now,
+        )
+        .map_err(cursor_error)?;
+    let mut files = state
+        .storage
+        .list_received_files_page(include_quarantined, &page)
+        .await
+        .map_err(storage_error)?;
+    let key_of = |file: &ReceivedFile| {
+        vec![
+            KeyValue::from(file.received_at.as_str()),
+            KeyValue::from(file.sha256.as_str()),
+            KeyValue::from(file.size_bytes),
+        ]
+    };
+    let cursors = codec
+        .finish("received_files", &page, &mut files, key_of, now)
+        .map_err(cursor_error)?;
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "files": files,
+            "next_cursor": cursors.next_cursor,
+            "prev_cursor": cursors.prev_cursor,
+            "order": RECEIVED_FILE_ORDER.describe(),
+        })),
+    ))
+}
+
+// Serves a received file's content under its declared media type. A quarantined file reads as
+// missing unless an admin asks for it.
+#[cfg(feature = "transfers")]
+async fn download_received_file(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(sha256): Path<String>,
+    Query(query): Query<FileQuery>,
+) -> Result<Response, (StatusCode, Json<Value>)> {
+    let include_quarantined = include_quarantined(&state, &headers, &query).await?;
+    let not_found = || {
+        (
+            StatusCode::NOT_FOUND,
+            Json(json!({"error":"file_not_found"})),
+        )
+    };
+    let (file, content) = state
+        .storage
+        .received_file_content(&sha256)
+        .await
+        .map_err(storage_error)?
+        .ok_or_else(not_found)?;
+    if file.quarantine_reason.is_some() && !include_quarantined {
+        return Err(not_found());
+    }
+    let Some(content) = content else {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(json!({"error":"file_content_unavailable"})),
+        ));
+    };
+    let media_type = file
+        .media_type
+        .as_deref()
+        .and_then(|media_type| HeaderValue::from_str(media_type).ok())
+        .unwrap_or_else(|| HeaderValue::from_static("application/octet-stream"));
+    let file_name = file
+        .file_name
+        .replace(|c: char| c.is_control() || c == '"' || c == '\\', "_");
+    Ok((
+        [
+            (header::CONTENT_TYPE, media_type),
+            (
+                header::CONTENT_DISPOSITION,
+                HeaderValue::from_str(&format!("attachment; filename=\"{file_name}\""))
+                    .map_err(|err| internal_error(err.into()))?,
+            ),
+        ],
+        content,
+    )
+        .into_response())
+}
+
+#[cfg(feature = "transfers")]
+fn bundle_rejection(rejection: BundleRejection) -> (StatusCode, Json<Value>) {
+    match rejection {
+        BundleRejection::Malformed(detail) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error":"invalid_bundle","detail":detail})),
+        ),
+        BundleRejection::TooManyFiles { limit, actual } => (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            Json(json!({"error":"bundle_too_many_files","limit":limit,"actual":actual})),
+        ),
+        BundleRejection::TooLarge { limit, actual } => (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            Json(json!({"error":"bundle_too_large","limit":limit,"actual":actual})),
+        ),
+    }
+}
+
+async fn process_transfer_job(
+    state: AppState,
+    transfer_id: &str,
+    request: TransferUploadRequest,
+    content: TransferContent,
+) -> anyhow::Result<()> {
+    // The entry is opened before the status check so a cancellation from here on finds it.
+    open_chunks(&state, transfer_id);
+    let cancelled = state
+        .storage
+        .get_transfer(transfer_id)
+        .await?
+        .is_some_and(|transfer| transfer.status == CANCELLED_STATUS);
+    if cancelled {
+        close_chunks(&state, transfer_id);
+        return Ok(());
+    }
+    state
+        .storage
+        .with_event(
+            "transfer.progress",
+            json!({ "transfer_id": transfer_id, "status": "running" }),
+        )
+        .update_transfer_status(transfer_id, "running", None)
+        .await?;
+    publish(&state).await;
+
+    let settings = state.node_config.read().await.transfer_dedup.clone();
+    if request.dedup && state.features.is_enabled(TRANSFER_DEDUP_FLAG) {
+        let offer = content.offer(&request.file_name).await?;
+        let answer = offer_transfer(
+            &state,
+            &request.destination_identity,
+            &offer,
+            settings.offer_timeout_ms,
+        )
+        .await;
+        let skipped = matches!(answer, OfferAnswer::Have(_));
+        let (remote_ack, fallback) = match &answer {
+            OfferAnswer::Have(ack) | OfferAnswer::Need(ack) => (Some(ack.clone()), None),
+            OfferAnswer::Unanswered(reason) => (None, Some(reason.clone())),
+        };
+        state
+            .storage
+            .record_transfer_dedup(&TransferDedup {
+                transfer_id: transfer_id.to_string(),
+                sha256: offer.sha256.clone(),
+                outcome: answer.outcome().to_string(),
+                bytes_saved: if skipped { offer.size as i64 } else { 0 },
+                remote_ack,
+                decided_at: Utc::now().to_rfc3339(),
+            })
+            .await?;
+        if let Some(reason) = fallback {
+            write_log(
+                &state,
+                "warn",
+                &format!("transfer {transfer_id} offer unanswered, sending in full: {reason}"),
+            )
+            .await;
+        }
+        if skipped {
+            close_chunks(&state, transfer_id);
+            state
+                .storage
+                .with_event(
+                    "transfer.completed",
+                    json!({
+                        "transfer_id": transfer_id,
+                        "status": SKIPPED_DUPLICATE_STATUS,
+                        "bytes_saved": offer.size,
+                    }),
+                )
+                .update_transfer_status(transfer_id, SKIPPED_DUPLICATE_STATUS, None)
+                .await?;
+            publish(&state).await;
+            write_log(
+                &state,
+                "info",
+                &format!("transfer {transfer_id} skipped, destination already holds it"),
+            )
+            .await;
+            return Ok(());
+        }
+    }
+
+    let chunks_total = content.len().div_ceil(DEFAULT_CHUNK_SIZE as u64);
+    let mut bytes_sent = 0;
+    let mut chunks = content.chunks(DEFAULT_CHUNK_SIZE).await?;
+    let mut chunk_index = 0;
+    loop {
+        let chunk = match chunks.next().await {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => break,
+            Err(err) => {
+                close_chunks(&state, transfer_id);
+                let reason = format!("spool_read_failed: {err}");
+                state
+                    .storage
+                    .with_event(
+                        "transfer.failed",
+                        json!({ "transfer_id": transfer_id, "status": "failed", "reason": reason }),
+                    )
+                    .update_transfer_status(transfer_id, "failed", Some(&reason))
+                    .await?;
+                publish(&state).await;
+                return Ok(());
+            }
+        };
+        state.mute.hold(Traffic::Transfers).await;
+        if !chunks_open(&state, transfer_id) {
+            write_log(
+                &state,
+                "info",
+                &format!(
+                    "transfer {transfer_id} stopped after {chunk_index} of {chunks_total} chunks"
+                ),
+            )
+            .await;
+            return Ok(());
+        }
+        let envelope = MeshTransferEnvelope {
+            message_id: Uuid::now_v7().to_string(),
+            correlation_id: Some(transfer_id.to_string()),
+            operation: "transfer.upload".to_string(),
+            sent_at: Utc::now(),
+            source_identity: IdentityHash::lenient(LOCAL_NODE_IDENTITY),
+            destination_identity: IdentityHash::lenient(&request.destination_identity),
+            content_type: "application/msgpack".to_string(),
+            direction: TransferDirection::Upload,
+            payload: json!({
+                "transfer_id": transfer_id,
+                "file_name": request.file_name,
+                "media_type": request.media_type,
+                "chunk_index": chunk_index,
+                "chunks_total": chunks_total,
+                "payload_base64": STANDARD.encode(&chunk),
+            }),
+            ttl_ms: None,
+            transport_hint: None,
+        };
+        let planned = state.bridge.planned_transport(None);
+        let config = state.node_config.read().await.clone();
+        let oversize = check_envelope(
+            &config,
+            planned,
+            &envelope,
+            &envelope.payload,
+            &envelope.content_type,
+            false,
+        )?;
+        if let Some(oversize) = oversize {
+            close_chunks(&state, transfer_id);
+            let reason = oversize.reason();
+            state
+                .storage
+                .with_event(
+                    "transfer.failed",
+                    json!({
+                        "transfer_id": transfer_id,
+                        "status": "failed",
+                        "reason": "envelope_too_large",
+                        "detail": oversize,
+                    }),
+                )
+                .update_transfer_status(transfer_id, "failed", Some(&reason))
+                .await?;
+            publish(&state).await;
+            record_oversize(&state, &envelope.operation, &reason).await;
+            return Ok(());
+        }
+
+        let message_id = envelope.message_id.clone();
+        if let Err(err) = state.bridge.start_transfer(envelope).await {
+            close_chunks(&state, transfer_id);
+            let reason = err.to_string();
+            state
+                .storage
+                .with_event(
+                    "transfer.failed",
+                    json!({ "transfer_id": transfer_id, "status": "failed", "reason": reason }),
+                )
+                .update_transfer_status(transfer_id, "failed", Some(&reason))
+                .await?;
+            publish(&state).await;
+            write_log(&state, "error", &format!("transfer {} failed", transfer_id)).await;
+            return Ok(());
+        }
+        if !track_chunk(&state, transfer_id, message_id.clone()) {
+            // Cancelled while this chunk was being sent, after the others were recalled.
+            recall_message(&state, &message_id).await;
+            return Ok(());
+        }
+
+        bytes_sent += chunk.len();
+        state
+            .storage
+            .with_event(
+                "transfer.progress",
+                json!({
+                    "transfer_id": transfer_id,
+                    "status": "running",
+                    "bytes_sent": bytes_sent,
+                    "bytes_total": content.len()
+                }),
+            )
+            .record_transfer_chunk(transfer_id, chunk.len() as u64)
+            .await?;
+        publish(&state).await;
+        chunk_index += 1;
+    }
+
+    if !close_chunks(&state, transfer_id) {
+        return Ok(());
+    }
+    // Marking it successful charges the bytes reserved at admission against the quotas.
+    state
+        .storage
+        .with_event(
+            "transfer.completed",
+            json!({ "transfer_id": transfer_id, "status": "success" }),
+        )
+        .update_transfer_status(transfer_id, "success", None)
+        .await?;
+    publish(&state).await;
+    write_log(&state, "info", &format!("transfer {} completed", transfer_id)).await;
+    Ok(())
+}
+
+async fn record_oversize(state: &AppState, operation: &str, reason: &str) {
+    *state
+        .oversize_rejections
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .entry(operation.to_string())
+        .or_default() += 1;
+    write_log(state, "warn", &format!("{operation} rejected before send: {reason}")).await;
+}
+
+// Newest first; cursors from `next_cursor` and `prev_cursor` walk `TRANSFER_ORDER`.
+#[cfg(feature = "transfers")]
+async fn list_transfers(
+    State(state): State<AppState>,
+    Query(query): Query<TransferListQuery>,
+    Query(shape): Query<ShapeQuery>,
+    Query(params): Query<Vec<(String, String)>>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    let shape = response_shape(&shape, TRANSFER_FIELDS)?;
+    let labels = label_filters(&params).map_err(label_filter_rejection)?;
+    let stalled = query.stalled.unwrap_or(false);
+    let limit = page_limit(query.limit);
+    let (items, cursors) =
+        page_transfers(&state, stalled, &labels, query.cursor.as_deref(), limit).await?;
+    let items: Vec<_> = items.into_iter().map(|item| shape.apply(item)).collect();
+    Ok(Json(json!({
+        "items": items,
+        "next_cursor": cursors.next_cursor,
+        "prev_cursor": cursors.prev_cursor,
+        "order": TRANSFER_ORDER.describe(),
+    })))
+}
+
+#[cfg(feature = "transfers")]
+async fn list_transfers_v2(
+    State(state): State<AppState>,
+    query: Result<Query<PageQuery>, QueryRejection>,
+    Query(shape): Query<ShapeQuery>,
+    Query(params): Query<Vec<(String, String)>>,
+) -> Result<Json<Envelope<Page<Shaped<v2::Transfer>>>>, V2Error> {
+    let Query(query) = query.map_err(|err| v2_rejection("invalid_query", err.body_text()))?;
+    let shape = response_shape(&shape, v2::Transfer::FIELDS).map_err(v2_error)?;
+    let labels = label_filters(&params)
+        .map_err(|detail| v2_rejection(INVALID_LABEL_FILTER_ERROR, detail))?;
+    let stalled = query.stalled.unwrap_or(false);
+    let limit = page_limit(query.limit);
+    let (views, cursors) = page_transfers(&state, stalled, &labels, query.cursor.as_deref(), limit)
+        .await
+        .map_err(v2_error)?;
+    let items = views
+        .into_iter()
+        .map(|view| view.into_v2().map(|transfer| shape.apply(transfer)))
+        .collect::<anyhow::Result<Vec<_>>>()
+        .map_err(v2_internal)?;
+    Ok(Json(Envelope::new(Page {
+        items,
+        next_cursor: cursors.next_cursor,
+        prev_cursor: cursors.prev_cursor,
+        order: TRANSFER_ORDER.describe(),
+    })))
+}
+
+#[cfg(feature = "transfers")]
+async fn page_transfers(
+    state: &AppState,
+    stalled: bool,
+    labels: &[(String, String)],
+    cursor: Option<&str>,
+    limit: i64,
+) -> Result<(Vec<TransferView>, PageCursors), (StatusCode, Json<Value>)> {
+    let codec = cursor_codec(state).await?;
+    let now = Utc::now();
+    let page = codec
+        .keyset("transfers", TRANSFER_ORDER, cursor, limit, now)
+        .map_err(cursor_error)?;
+    let mut views = load_transfers(state, stalled, labels, &page).await?;
+    let key_of = |view: &TransferView| {
+        vec![
+            KeyValue::from(view.record.submitted_at.as_str()),
+            KeyValue::from(view.record.transfer_id.as_str()),
+        ]
+    };
+    let cursors = codec
+        .finish("transfers", &page, &mut views, key_of, now)
+        .map_err(cursor_error)?;
+    Ok((views, cursors))
+}
+
+#[cfg(feature = "transfers")]
+async fn load_transfers(
+    state: &AppState,
+    stalled: bool,
+    labels: &[(String, String)],
+    page: &Keyset,
+) -> Result<Vec<TransferView>, (StatusCode, Json<Value>)> {
+    let cutoff = stall_cutoff(state).await;
+    let stalled_before = stalled.then_some(cutoff.as_str());
+    let records = state
+        .storage
+        .list_transfers_page(stalled_before, labels, page)
+        .await
+        .map_err(storage_error)?;
+
+    let mut items = Vec::with_capacity(records.len());
+    for record in records {
+        items.push(transfer_view(state, record, &cutoff).await?);
+    }
+    Ok(items)
+}
+
+#[cfg(feature = "transfers")]
+async fn get_transfer(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(transfer_id): Path<String>,
+    Query(shape): Query<ShapeQuery>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    let shape = response_shape(&shape, TRANSFER_FIELDS)?;
+    let mut view = load_transfer(&state, &transfer_id).await?;
+    let source = state
+        .storage
+        .get_transfer_source(&transfer_id)
+        .await
+        .map_err(storage_error)?;
+    view.source = visible_source(&state, &headers, source).await;
+    Ok((StatusCode::OK, Json(shape.apply(view))))
+}
+
+// Only admins see the address a submission came from.
+async fn visible_source(
+    state: &AppState,
+    headers: &HeaderMap,
+    source: Option<SubmissionSource>,
+) -> Option<SubmissionSource> {
+    let mut source = source?;
+    if !is_admin(state, headers).await {
+        source.remote_addr = None;
+    }
+    Some(source)
+}
+
+// Stops a transfer that is queued or still sending; each chunk already handed to the bridge is
+// recalled from the mesh.
+#[cfg(feature = "transfers")]
+async fn cancel_transfer(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(transfer_id): Path<String>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    authorize(&state, &headers, true).await?;
+    let event = json!({
+        "transfer_id": transfer_id,
+        "status": CANCELLED_STATUS,
+        "chunks_recalled": outstanding_chunk_count(&state, &transfer_id),
+    });
+    let id = transfer_id.clone();
+    let cancelled = state
+        .storage
+        .with_tx(move |tx| {
+            Box::pin(async move {
+                let cancelled = tx.cancel_transfer(&id).await?;
+                if cancelled {
+                    tx.append_feed_event("transfer.cancelled", &event).await?;
+                }
+                Ok(cancelled)
+            })
+        })
+        .await
+        .map_err(storage_error)?;
+    let Some(transfer) = state
+        .storage
+        .get_transfer(&transfer_id)
+        .await
+        .map_err(storage_error)?
+    else {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(json!({"error":"transfer_not_found"})),
+        ));
+    };
+    if !cancelled {
+        return Err((
+            StatusCode::CONFLICT,
+            Json(json!({ "error": "transfer_not_cancellable", "status": transfer.status })),
+        ));
+    }
+    let chunks = recall_chunks(&state, &transfer_id).await;
+    publish(&state).await;
+    write_log(&state, "info", &format!("transfer {transfer_id} cancelled")).await;
+    if let Some(job_id) = &transfer.job_id {
+        complete_job(&state, job_id, None)
+            .await
+            .map_err(internal_error)?;
+    }
+    Ok(Json(json!({
+        "transfer_id": transfer_id,
+        "status": CANCELLED_STATUS,
+        "chunks": chunks,
+    })))
+}
+
+#[cfg(feature = "transfers")]
+async fn get_transfer_v2(
+    State(state): State<AppState>,
+    Path(transfer_id): Path<String>,
+    Query(shape): Query<ShapeQuery>,
+) -> Result<Json<Envelope<Shaped<v2::Transfer>>>, V2Error> {
+    let shape = response_shape(&shape, v2::Transfer::FIELDS).map_err(v2_error)?;
+    let view = load_transfer(&state, &transfer_id).await.map_err(v2_error)?;
+    let transfer = view.into_v2().map_err(v2_internal)?;
+    Ok(Json(Envelope::new(shape.apply(transfer))))
+}
+
+#[cfg(feature = "transfers")]
+async fn load_transfer(
+    state: &AppState,
+    transfer_id: &str,
+) -> Result<TransferView, (StatusCode, Json<Value>)> {
+    let record = state
+        .storage
+        .get_transfer(transfer_id)
+        .await
+        .map_err(storage_error)?;
+    match record {
+        Some(record) => {
+            let cutoff = stall_cutoff(state).await;
+            transfer_view(state, record, &cutoff).await
+        }
+        None => Err((
+            StatusCode::NOT_FOUND,
+            Json(json!({"error":"transfer_not_found"})),
+        )),
+    }
+}
+
+async fn transfer_view(
+    state: &AppState,
+    record: TransferRecord,
+    stalled_before: &str,
+) -> Result<TransferView, (StatusCode, Json<Value>)> {
+    let progress = state
+        .storage
+        .get_transfer_progress(&record.transfer_id, stalled_before)
+        .await
+        .map_err(storage_error)?;
+    let dedup = state
+        .storage
+        .get_transfer_dedup(&record.transfer_id)
+        .await
+        .map_err(storage_error)?;
+    let members = state
+        .storage
+        .list_bundle_members(&record.transfer_id)
+        .await
+        .map_err(storage_error)?;
+    let labels = state
+        .storage
+        .get_labels(LABEL_SUBJECT_TRANSFER, &record.transfer_id)
+        .await
+        .map_err(storage_error)?;
+    Ok(TransferView {
+        record,
+        progress,
+        dedup,
+        members,
+        source: None,
+        labels,
+    })
+}
+
+pub(crate) async fn stall_cutoff(state: &AppState) -> String {
+    let stall_after = state.node_config.read().await.transfer_stall_after_secs;
+    (Utc::now() - chrono::Duration::seconds(stall_after as i64)).to_rfc3339()
+}
+
+// Shaped requests list `CachedSummary` items, which never carry the payload. `since` lists the
+// envelopes sent at or after it, oldest first, so callers can page forward through the cache.
+async fn get_cached_events(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<ListQuery>,
+    Query(sent): Query<SentSinceQuery>,
+    Query(shape): Query<ShapeQuery>,
+) -> Result<Response, (StatusCode, Json<Value>)> {
+    let shape = response_shape(&shape, CACHED_FIELDS)?;
+    let limit = query.limit.unwrap_or(100);
+    let since = query_timestamp("since", sent.since.as_deref())?;
+    if since.is_some() && !shape.is_full() {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(json!({ "error": "since_is_not_shaped" })),
+        ));
+    }
+    let (max_rowid, count) = state
+        .storage
+        .cached_events_fingerprint()
+        .await
+        .map_err(storage_error)?;
+    let since_key = since.as_ref().map(ToString::to_string).unwrap_or_default();
+    let etag =
+        compute_etag(format!("events:{max_rowid}:{count}:{limit}:{since_key}").as_bytes());
+    let etag = shaped_etag(etag, &shape);
+    if etag_matches(&headers, header::IF_NONE_MATCH, &etag, true) {
+        return Ok(not_modified(etag));
+    }
+
+    if !shape.is_full() {
+        let summaries = state
+            .storage
+            .list_cached_event_summaries(limit)
+            .await
+            .map_err(storage_error)?;
+        return Ok(shaped_list(etag, &shape, summaries));
+    }
+    let events = match since {
+        Some(since) => state
+            .storage
+            .list_cached_events_sent_since(since.as_datetime(), limit)
+            .await
+            .map_err(storage_error)?,
+        None => state
+            .storage
+            .list_cached_events(limit)
+            .await
+            .map_err(storage_error)?,
+    };
+    Ok((StatusCode::OK, [(header::ETAG, etag)], Json(events)).into_response())
+}
+
+fn shaped_list(etag: String, shape: &Shape, summaries: Vec<CachedSummary>) -> Response {
+    let items: Vec<_> = summaries.into_iter().map(|item| shape.apply(item)).collect();
+    (StatusCode::OK, [(header::ETAG, etag)], Json(items)).into_response()
+}
+
+async fn aggregate_cached_events(
+    State(state): State<AppState>,
+    Query(query): Query<AggregateQuery>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    let group_by = query.group_by.as_deref().unwrap_or("event");
+    let grouping = match group_by {
+        "event" => EventGrouping::Event,
+        "source" => EventGrouping::Source,
+        _ => return Err(invalid_group_by()),
+    };
+    let range = aggregate_range(&state, &query).await?;
+    let rows = state
+        .storage
+        .aggregate_cached_events(grouping, range.bucket_secs, range.since, range.until)
+        .await
+        .map_err(storage_error)?;
+    Ok(Json(build_series(group_by, range, query.top, &rows)))
+}
+
+async fn aggregate_jobs(
+    State(state): State<AppState>,
+    Query(query): Query<AggregateQuery>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    let group_by = query.group_by.as_deref().unwrap_or("status");
+    let grouping = match group_by {
+        "status" => JobGrouping::Status,
+        "operation" => JobGrouping::Operation,
+        _ => return Err(invalid_group_by()),
+    };
+    let range = aggregate_range(&state, &query).await?;
+    let rows = state
+        .storage
+        .aggregate_jobs(grouping, range.bucket_secs, range.since, range.until)
+        .await
+        .map_err(storage_error)?;
+    Ok(Json(build_series(group_by, range, query.top, &rows)))
+}
+
+// Every job as newline-delimited JSON, read a chunk at a time so the export never holds a read
+// transaction across the whole table while job updates go on. `label` parameters narrow it to
+// the jobs carrying all of them.
+async fn export_jobs(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(params): Query<Vec<(String, String)>>,
+) -> Result<Response, (StatusCode, Json<Value>)> {
+    authorize_admin(&state, &headers).await?;
+    let labels = Arc::new(label_filters(&params).map_err(label_filter_rejection)?);
+    let storage = state.storage.clone();
+    let chunks = futures::stream::unfold(Some(0), move |after| {
+        let (storage, labels) = (storage.clone(), labels.clone());
+        async move {
+            let chunk = match storage.export_jobs(after?, JOB_EXPORT_CHUNK, &labels).await {
+                Ok(chunk) => chunk,
+                Err(err) => {
+                    warn!(error = %format!("{err:#}"), "job export failed");
+                    return Some((Err(std::io::Error::other(err)), None));
+                }
+            };
+            let mut lines = Vec::new();
+            for job in &chunk.jobs {
+                if let Err(err) = serde_json::to_writer(&mut lines, job) {
+                    return Some((Err(err.into()), None));
+                }
+                lines.push(b'\n');
+            }
+            Some((Ok(Bytes::from(lines)), chunk.next_after))
+        }
+    });
+    Ok((
+        [(header::CONTENT_TYPE, HeaderValue::from_static(CONTENT_TYPE_NDJSON))],
+        Body::from_stream(chunks),
+    )
+        .into_response())
+}
+
+async fn aggregate_range(
+    state: &AppState,
+    query: &AggregateQuery,
+) -> Result<BucketRange, (StatusCode, Json<Value>)> {
+    let max_buckets = state.node_config.read().await.aggregates.max_buckets;
+    query.range(max_buckets, Utc::now()).map_err(|rejection| match rejection {
+        AggregateRejection::InvalidBucket => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error":"invalid_bucket"})),
+        ),
+        AggregateRejection::InvalidRange => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error":"invalid_range"})),
+        ),
+        AggregateRejection::InvalidTop => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error":"invalid_top"})),
+        ),
+        AggregateRejection::TooManyBuckets {
+            buckets,
+            max_buckets,
+        } => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(json!({
+                "error": "too_many_buckets",
+                "buckets": buckets,
+                "max_buckets": max_buckets,
+            })),
+        ),
+    })
+}
+
+fn invalid_group_by() -> (StatusCode, Json<Value>) {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(json!({"error":"invalid_group_by"})),
+    )
+}
+
+async fn get_cached_messages(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<ListQuery>,
+    Query(shape): Query<ShapeQuery>,
+) -> Result<Response, (StatusCode, Json<Value>)> {
+    let shape = response_shape(&shape, CACHED_FIELDS)?;
+    let limit = query.limit.unwrap_or(100);
+    let (max_rowid, count) = state
+        .storage
+        .cached_messages_fingerprint()
+        .await
+        .map_err(storage_error)?;
+    let etag = compute_etag(format!("messages:{max_rowid}:{count}:{limit}").as_bytes());
+    let etag = shaped_etag(etag, &shape);
+    if etag_matches(&headers, header::IF_NONE_MATCH, &etag, true) {
+        return Ok(not_modified(etag));
+    }
+
+    if !shape.is_full() {
+        let summaries = state
+            .storage
+            .list_cached_message_summaries(limit)
+            .await
+            .map_err(storage_error)?;
+        return Ok(shaped_list(etag, &shape, summaries));
+    }
+
+    let messages = state
+        .storage
+        .list_cached_messages(limit)
+        .await
+        .map_err(storage_error)?;
+    Ok((StatusCode::OK, [(header::ETAG, etag)], Json(messages)).into_response())
+}
+
+async fn get_logs(
+    State(state): State<AppState>,
+    Query(query): Query<LogQuery>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    let limit = query.limit.unwrap_or(200);
+    let since = query_timestamp("since", query.since.as_deref())?;
+    let until = query_timestamp("until", query.until.as_deref())?;
+    let level_filter = query.level.as_deref().map(str::to_ascii_lowercase);
+    let contains_filter = query.contains.as_deref().map(str::to_owned);
+
+    let logs = state.log_buffer.read().await;
+    let mut items: Vec<LogLine> = logs
+        .iter()
+        .filter(|entry| {
+            if let Some(level) = &level_filter {
+                if entry.level.to_ascii_lowercase() != *level {
+                    return false;
+                }
+            }
+
+            if let Some(needle) = &contains_filter {
+                if !entry.message.contains(needle) {
+                    return false;
+                }
+            }
+
+            since.is_none_or(|since| entry.timestamp >= since)
+                && until.is_none_or(|until| entry.timestamp < until)
+        })
+        .cloned()
+        .collect();
+
+    if items.len() > limit {
+        let start = items.len().saturating_sub(limit);
+        items = items.split_off(start);
+    }
+
+    Ok(Json(json!({ "items": items })))
+}
+
+// Parses a timestamp query parameter so that filters compare instants, not strings.
+fn query_timestamp(
+    name: &str,
+    raw: Option<&str>,
+) -> Result<Option<CanonicalTimestamp>, (StatusCode, Json<Value>)> {
+    raw.map(|raw| {
+        CanonicalTimestamp::parse(raw).map_err(|_| {
+            (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(json!({ "error": "invalid_timestamp", "parameter": name, "value": raw })),
+            )
+        })
+    })
+    .transpose()
+}
+
+#[cfg(feature = "sse")]
+async fn stream_logs(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Sse<impl futures::Stream<Item = Result<SseEvent, std::convert::Infallible>>> {
+    let last_event_id = headers
+        .get("last-event-id")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<u64>().ok());
+    // Subscribe before reading the replay buffer so nothing emitted in between is lost.
+    let receiver = state.sse_bus.subscribe();
+    let (replay, missed, replayed_up_to) = state
+        .recent_events
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .since(last_event_id);
+    let gap = missed.then(|| {
+        let data = json!({ "last_event_id": last_event_id });
+        SseEvent::default()
+            .event("events.overflowed")
+            .data(data.to_string())
+    });
+    let replay = gap
+        .into_iter()
+        .chain(replay.into_iter().map(|update| sse_update_event(&update)))
+        .map(Ok);
+    // Only live delivery is collapsed; a replay after reconnecting sends every buffered event.
+    let window_ms = state.node_config.read().await.event_coalescing.window_ms;
+    let handle = state.event_subscribers.register(SSE_SUBSCRIBER, window_ms);
+    let live = push_updates(
+        receiver,
+        replayed_up_to,
+        std::time::Duration::from_millis(window_ms),
+        handle,
+    )
+    .map(|update| Ok(sse_update_event(&update)));
+
+    Sse::new(futures::stream::iter(replay).chain(live))
+        .keep_alive(KeepAlive::new().interval(std::time::Duration::from_secs(15)))
+}
+
+// Push subscribers connected now, with the events delivered to and collapsed for each.
+async fn list_event_subscribers(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    authorize(&state, &headers, true).await?;
+    let window_ms = state.node_config.read().await.event_coalescing.window_ms;
+    Ok(Json(json!({
+        "window_ms": window_ms,
+        "subscribers": state.event_subscribers.list(),
+    })))
+}
+
+#[cfg(feature = "sse")]
+fn sse_update_event(update: &SseUpdate) -> SseEvent {
+    let data = serde_json::to_string(&update.data).unwrap_or_else(|_| "{}".to_string());
+    SseEvent::default()
+        .id(update.id.to_string())
+        .event(update.event_type.clone())
+        .data(data)
+}
+
+async fn list_notifications(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<ListQuery>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    let token_label = notification_consumer(&state, &headers).await?;
+    let limit = query.limit.unwrap_or(100).clamp(1, 1000);
+    let max_unacked = state.node_config.read().await.notifications.max_unacked;
+    let cursor = state
+        .storage
+        .cap_notification_cursor(&token_label, max_unacked)
+        .await
+        .map_err(storage_error)?;
+    let mut items: Vec<NotificationRecord> = overflow_marker(&cursor).into_iter().collect();
+    items.extend(
+        state
+            .storage
+            .list_notifications(cursor.acked_seq, limit)
+            .await
+            .map_err(storage_error)?,
+    );
+
+    Ok(Json(json!({
+        "token_label": token_label,
+        "acked_seq": cursor.acked_seq,
+        "items": items,
+    })))
+}
+
+// Every emitted event in sequence order, for consumers that mirror the node elsewhere. Needs a
+// token whenever writes do, like the notification inbox.
+async fn get_event_feed(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<FeedQuery>,
+) -> Result<Response, (StatusCode, Json<Value>)> {
+    authorize(&state, &headers, true).await?;
+    let settings = state.node_config.read().await.event_feed.clone();
+    match read_page(&state.storage, &settings, &query)
+        .await
+        .map_err(internal_error)?
+    {
+        Ok(page) => Ok(Json(page).into_response()),
+        Err(behind) => Ok((
+            StatusCode::GONE,
+            Json(json!({
+                "error": "feed_position_trimmed",
+                "after_seq": behind.after_seq,
+                "trimmed_through": behind.trimmed_through,
+            })),
+        )
+            .into_response()),
+    }
+}
+
+async fn get_event_feed_head(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    authorize(&state, &headers, true).await?;
+    let bounds = state.storage.feed_bounds().await.map_err(storage_error)?;
+    Ok(Json(bounds))
+}
+
+async fn ack_notifications(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<AckNotificationsRequest>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    let token_label = notification_consumer(&state, &headers).await?;
+    let cursor = state
+        .storage
+        .ack_notifications(&token_label, payload.up_to_seq)
+        .await
+        .map_err(storage_error)?;
+    Ok(Json(cursor))
+}
+
+#[cfg(feature = "sse")]
+async fn stream_notifications(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<
+    Sse<impl futures::Stream<Item = Result<SseEvent, std::convert::Infallible>>>,
+    (StatusCode, Json<Value>),
+> {
+    let token_label = notification_consumer(&state, &headers).await?;
+    // Subscribe before reading the backlog so nothing emitted in between is lost.
+    let receiver = state.notification_bus.subscribe();
+    let max_unacked = state.node_config.read().await.notifications.max_unacked;
+    let cursor = state
+        .storage
+        .cap_notification_cursor(&token_label, max_unacked)
+        .await
+        .map_err(storage_error)?;
+    let backlog = state
+        .storage
+        .list_notifications(cursor.acked_seq, max_unacked.max(1))
+        .await
+        .map_err(storage_error)?;
+
+    let replayed_up_to = backlog.last().map_or(cursor.acked_seq, |record| record.seq);
+    let replay = overflow_marker(&cursor)
+        .into_iter()
+        .chain(backlog)
+        .map(|record| Ok(notification_event(&record)));
+    // Live notifications collapse like the event stream; the backlog is sent whole.
+    let window_ms = state.node_config.read().await.event_coalescing.window_ms;
+    let handle = state
+        .event_subscribers
+        .register(NOTIFICATION_SUBSCRIBER, window_ms);
+    let live = push_updates(
+        receiver,
+        replayed_up_to.max(0) as u64,
+        std::time::Duration::from_millis(window_ms),
+        handle,
+    )
+    .map(|record| Ok(notification_event(&record)));
+
+    Ok(Sse::new(futures::stream::iter(replay).chain(live))
+        .keep_alive(KeepAlive::new().interval(std::time::Duration::from_secs(15))))
+}
+
+fn overflow_marker(cursor: &NotificationCursor) -> Option<NotificationRecord> {
+    (cursor.dropped > 0).then(|| NotificationRecord {
+        seq: cursor.acked_seq,
+        event_type: "notifications.overflowed".to_string(),
+        data: json!({ "dropped": cursor.dropped }),
+        created_at: cursor.updated_at.clone(),
+    })
+}
+
+#[cfg(feature = "sse")]
+fn notification_event(record: &NotificationRecord) -> SseEvent {
+    let data = serde_json::to_string(&record.data).unwrap_or_else(|_| "{}".to_string());
+    SseEvent::default()
+        .id(record.seq.to_string())
+        .event(record.event_type.clone())
+        .data(data)
+}
+
+async fn notification_consumer(
+    state: &AppState,
+    headers: &HeaderMap,
+) -> Result<String, (StatusCode, Json<Value>)> {
+    let config = state.node_config.read().await;
+    match token_label(&config, headers) {
+        Some(label) => Ok(label),
+        None if !bearer_required(state, headers) => Ok("local".to_string()),
+        None => Err((
+            StatusCode::UNAUTHORIZED,
+            Json(json!({"error":"invalid_or_missing_bearer_token"})),
+        )),
+    }
+}
+
+async fn get_allowlist(
+    State(state): State<AppState>,
+    Query(query): Query<AllowlistQuery>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, Json<Value>)> {
+    let entries = load_allowlist(&state, &query).await?;
+    let identities: Vec<&str> = entries
+        .iter()
+        .map(|entry| entry.identity_hash.as_str())
+        .collect();
+    let body = json!({ "identities": identities, "entries": entries });
+    let etag = compute_etag(body.to_string().as_bytes());
+    Ok(respond_with_etag(&headers, etag, Json(body)))
+}
+
+// Entries are few and ordered by identity hash, so the page is cut after loading.
+const ALLOWLIST_ORDER: SortOrder = SortOrder::ascending(&["identity_hash"]);
+
+async fn get_allowlist_v2(
+    State(state): State<AppState>,
+    query: Result<Query<PageQuery>, QueryRejection>,
+    headers: HeaderMap,
+) -> Result<Response, V2Error> {
+    let Query(query) = query.map_err(|err| v2_rejection("invalid_query", err.body_text()))?;
+    let codec = cursor_codec(&state).await.map_err(v2_error)?;
+    let now = Utc::now();
+    let page = codec
+        .keyset(
+            "allowlist",
+            ALLOWLIST_ORDER,
+            query.cursor.as_deref(),
+            page_limit(query.limit),
+            now,
+        )
+        .map_err(|err| v2_error(cursor_error(err)))?;
+    let filter = AllowlistQuery {
+        status: query.status,
+        expiring_within_secs: query.expiring_within_secs,
+    };
+    let mut entries = load_allowlist(&state, &filter).await.map_err(v2_error)?;
+    let after = match page.key.as_deref() {
+        Some([KeyValue::Text(after)]) => Some(after.as_str()),
+        _ => None,
+    };
+    let backward = page.direction == PageDirection::Backward;
+    entries.retain(|entry| {
+        let hash = entry.identity_hash.as_str();
+        after.is_none_or(|after| if backward { hash < after } else { hash > after })
+    });
+    if backward {
+        entries.reverse();
+    }
+    entries.truncate(page.limit as usize);
+    let key_of =
+        |entry: &retasync_storage::AllowlistEntry| vec![KeyValue::from(entry.identity_hash.as_str())];
+    let cursors = codec
+        .finish("allowlist", &page, &mut entries, key_of, now)
+        .map_err(|err| v2_error(cursor_error(err)))?;
+    let items = entries
+        .into_iter()
+        .map(v2::AllowlistEntry::try_from)
+        .collect::<anyhow::Result<Vec<_>>>()
+        .map_err(v2_internal)?;
+    let body = Envelope::new(Page {
+        items,
+        next_cursor: cursors.next_cursor,
+        prev_cursor: cursors.prev_cursor,
+        order: ALLOWLIST_ORDER.describe(),
+    });
+    let bytes = serde_json::to_vec(&body).map_err(|err| v2_internal(err.into()))?;
+    Ok(respond_with_etag(&headers, compute_etag(&bytes), Json(body)))
+}
+
+async fn load_allowlist(
+    state: &AppState,
+    query: &AllowlistQuery,
+) -> Result<Vec<retasync_storage::AllowlistEntry>, (StatusCode, Json<Value>)> {
+    if let Some(status) = query.status.as_deref() {
+        if !ALLOWLIST_STATUSES.contains(&status) {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(json!({"error":"invalid_status"})),
+            ));
+        }
+    }
+    let expiring_before = query
+        .expiring_within_secs
+        .map(|secs| Utc::now() + chrono::Duration::seconds(secs));
+    state
+        .storage
+        .list_allowlist_entries(query.status.as_deref(), expiring_before)
+        .await
+        .map_err(storage_error)
+}
+
+async fn add_allowlist(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<AddAllowlistRequest>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    authorize(&state, &headers, true).await?;
+    let identity_hash = client_identity(&state, &payload.identity_hash).await?;
+
+    let role = payload.role.as_deref().unwrap_or("peer");
+    if !ALLOWLIST_ROLES.contains(&role) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error":"invalid_role"})),
+        ));
+    }
+    let status = match payload.status.as_deref().unwrap_or("active") {
+        // Only the admin token may grant access directly; everyone else queues for approval.
+        "active" if is_admin(&state, &headers).await => "active",
+        "active" | "pending" => "pending",
+        _ => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(json!({"error":"invalid_status"})),
+            ))
+        }
+    };
+    let expires_at = match payload.expires_at.as_deref() {
+        Some(raw) => {
+            let parsed = chrono::DateTime::parse_from_rfc3339(raw)
+                .map(|parsed| parsed.with_timezone(&Utc))
+                .map_err(|_| {
+                    (
+                        StatusCode::BAD_REQUEST,
+                        Json(json!({"error":"invalid_expires_at"})),
+                    )
+                })?;
+            if parsed <= Utc::now() {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    Json(json!({"error":"expires_at_in_past"})),
+                ));
+            }
+            Some(parsed)
+        }
+        None => None,
+    };
+    let public_key = payload.public_key.as_deref().map(str::trim);
+    if public_key.is_some_and(|key| parse_sealing_key(key).is_err()) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error":"invalid_public_key"})),
+        ));
+    }
+
+    let event_type = if status == "pending" {
+        "security.allowlist.pending"
+    } else {
+        "security.allowlist.updated"
+    };
+    let mut event = json!({
+        "identity_hash": identity_hash,
+        "role": role,
+        "status": status,
+        "expires_at": expires_at.map(|at| CanonicalTimestamp::from(at).to_string()),
+    });
+    if let Some(public_key) = public_key {
+        event["public_key"] = json!(public_key);
+    }
+    let mut entry = state
+        .storage
+        .with_event(event_type, event)
+        .put_allowlist_entry(
+            &identity_hash,
+            payload.note.as_deref(),
+            role,
+            status,
+            expires_at,
+        )
+        .await
+        .map_err(storage_error)?;
+    if let Some(public_key) = public_key {
+        state
+            .storage
+            .set_allowlist_public_key(&identity_hash, Some(public_key))
+            .await
+            .map_err(storage_error)?;
+        entry.public_key = Some(public_key.to_string());
+    }
+    publish(&state).await;
+    Ok((
+        StatusCode::CREATED,
+        Json(json!({ "status": "ok", "entry": entry })),
+    ))
+}
+
+async fn get_quotas(
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    let report = quota_report(&state, Utc::now())
+        .await
+        .map_err(internal_error)?;
+    Ok(Json(report))
+}
+
+async fn put_quota_override(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(identity): Path<String>,
+    Json(payload): Json<QuotaOverrideRequest>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    authorize_admin(&state, &headers).await?;
+    let max_bytes = i64::try_from(payload.max_bytes).map_err(|_| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error":"invalid_max_bytes"})),
+        )
+    })?;
+    let subject_kind = payload.subject_kind.as_deref().unwrap_or(DESTINATION_QUOTA);
+    if ![DESTINATION_QUOTA, TOKEN_QUOTA].contains(&subject_kind) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error":"invalid_subject_kind","subject_kind":subject_kind})),
+        ));
+    }
+    let updated = json!({
+        "subject_kind": subject_kind,
+        "subject": identity,
+        "max_bytes": max_bytes,
+    });
+    let entry = state
+        .storage
+        .with_event("security.quota.updated", updated)
+        .put_quota_override(subject_kind, &identity, max_bytes, payload.note.as_deref())
+        .await
+        .map_err(storage_error)?;
+    publish(&state).await;
+    Ok(Json(json!({ "status": "ok", "override": entry })))
+}
+
+async fn approve_allowlist(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(identity_hash): Path<String>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    authorize_admin(&state, &headers).await?;
+    let identity_hash = client_identity(&state, &identity_hash).await?;
+    let Some(existing) = state
+        .storage
+        .get_allowlist_entry(&identity_hash)
+        .await
+        .map_err(storage_error)?
+    else {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(json!({"error":"identity_not_found"})),
+        ));
+    };
+
+    let event = json!({
+        "identity_hash": existing.identity_hash,
+        "role": existing.role,
+        "expires_at": existing.expires_at,
+    });
+    let hash = identity_hash.clone();
+    let approved = state
+        .storage
+        .with_tx(move |tx| {
+            Box::pin(async move {
+                let approved = tx.approve_allowlist(&hash).await?;
+                if approved {
+                    tx.append_feed_event("security.allowlist.approved", &event)
+                        .await?;
+                }
+                Ok(approved)
+            })
+        })
+        .await
+        .map_err(storage_error)?;
+    if !approved {
+        return Err((
+            StatusCode::CONFLICT,
+            Json(json!({"error":"allowlist_entry_not_pending"})),
+        ));
+    }
+    publish(&state).await;
+    let entry = state
+        .storage
+        .get_allowlist_entry(&identity_hash)
+        .await
+        .map_err(storage_error)?
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                Json(json!({"error":"identity_not_found"})),
+            )
+        })?;
+    Ok(Json(entry))
+}
+
+async fn delete_allowlist(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(identity_hash): Path<String>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    authorize(&state, &headers, true).await?;
+    let identity_hash = client_identity(&state, &identity_hash).await?;
+    let event = json!({ "identity_hash": identity_hash, "deleted": true });
+    let hash = identity_hash.clone();
+    let deleted = state
+        .storage
+        .with_tx(move |tx| {
+            Box::pin(async move {
+                let deleted = tx.delete_allowlist(&hash).await?;
+                if deleted {
+                    tx.append_feed_event("security.allowlist.updated", &event)
+                        .await?;
+                }
+                Ok(deleted)
+            })
+        })
+        .await
+        .map_err(storage_error)?;
+
+    if deleted {
+        publish(&state).await;
+        Ok((StatusCode::NO_CONTENT, Json(json!({}))))
+    } else {
+        Err((
+            StatusCode::NOT_FOUND,
+            Json(json!({"error":"identity_not_found"})),
+        ))
+    }
+}
+
+async fn list_peers(State(state): State<AppState>) -> impl IntoResponse {
+    Json(json!({
+        "peers": state.peers.list(),
+        "handshakes": state.peers.handshakes(),
+        "last_seen": state.peers.contacts(),
+        "clocks": state.peers.clock_estimates(),
+    }))
+}
+
+// Everything the node knows about one peer, including the circuits for commands sent to it.
+async fn get_peer(
+    State(state): State<AppState>,
+    Path(identity_hash): Path<String>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    let identity_hash = client_identity(&state, &identity_hash).await?;
+    let circuits: Vec<_> = circuit_views(&state)
+        .await
+        .into_iter()
+        .filter(|circuit| circuit.destination_identity == identity_hash)
+        .collect();
+    let capabilities = state.peers.capabilities(&identity_hash);
+    let handshake = state.peers.handshake(&identity_hash);
+    let last_seen = state.peers.last_seen(&identity_hash);
+    let clock = state.peers.clock(&identity_hash);
+    let unknown = capabilities.is_none()
+        && handshake.is_none()
+        && last_seen.is_none()
+        && clock.is_none()
+        && circuits.is_empty();
+    if unknown {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(json!({"error":"peer_not_found"})),
+        ));
+    }
+    Ok(Json(json!({
+        "identity_hash": identity_hash,
+        "capabilities": capabilities,
+        "handshake": handshake,
+        "last_seen": last_seen,
+        "clock": clock,
+        "circuits": circuits,
+    })))
+}
+
+// The smoothed estimate with the raw samples behind it, oldest first.
+async fn get_peer_clock(
+    State(state): State<AppState>,
+    Path(identity_hash): Path<String>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    let identity_hash = client_identity(&state, &identity_hash).await?;
+    let clock = state.peers.clock(&identity_hash).ok_or_else(|| {
+        (
+            StatusCode::NOT_FOUND,
+            Json(json!({"error":"no_clock_samples"})),
+        )
+    })?;
+    Ok(Json(
+        json!({ "identity_hash": identity_hash, "clock": clock }),
+    ))
+}
+
+// Every node that has reported to this collector, flagged stale once quiet for too long.
+async fn list_fleet_nodes(
+    State(state): State<AppState>,
+    Query(query): Query<CursorQuery>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    let stale_after_intervals = state.node_config.read().await.fleet.stale_after_intervals;
+    let codec = cursor_codec(&state).await?;
+    let now = Utc::now();
+    let page = codec
+        .keyset(
+            "fleet_nodes",
+            FLEET_NODE_ORDER,
+            query.cursor.as_deref(),
+            page_limit(query.limit),
+            now,
+        )
+        .map_err(cursor_error)?;
+    let mut nodes = state
+        .storage
+        .list_fleet_nodes_page(&page)
+        .await
+        .map_err(storage_error)?;
+    let key_of = |node: &FleetNode| vec![KeyValue::from(node.identity_hash.as_str())];
+    let cursors = codec
+        .finish("fleet_nodes", &page, &mut nodes, key_of, now)
+        .map_err(cursor_error)?;
+    let nodes: Vec<_> = nodes
+        .into_iter()
+        .map(|node| FleetNodeView::new(node, stale_after_intervals, now))
+        .collect();
+    Ok(Json(json!({
+        "nodes": nodes,
+        "next_cursor": cursors.next_cursor,
+        "prev_cursor": cursors.prev_cursor,
+        "order": FLEET_NODE_ORDER.describe(),
+    })))
+}
+
+async fn get_fleet_node(
+    State(state): State<AppState>,
+    Path(identity_hash): Path<String>,
+    Query(query): Query<ListQuery>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    let identity_hash = client_identity(&state, &identity_hash).await?;
+    let stale_after_intervals = state.node_config.read().await.fleet.stale_after_intervals;
+    let Some(node) = state
+        .storage
+        .get_fleet_node(&identity_hash)
+        .await
+        .map_err(storage_error)?
+    else {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(json!({"error":"fleet_node_not_found"})),
+        ));
+    };
+    let history: Vec<_> = state
+        .storage
+        .list_fleet_reports(&identity_hash, query.limit.unwrap_or(50))
+        .await
+        .map_err(storage_error)?
+        .into_iter()
+        .map(|report| {
+            json!({
+                "received_at": report.received_at,
+                "report": serde_json::from_str::<Value>(&report.report_json).unwrap_or(Value::Null),
+            })
+        })
+        .collect();
+    let node = FleetNodeView::new(node, stale_after_intervals, Utc::now());
+    Ok(Json(json!({ "node": node, "history": history })))
+}
+
+#[cfg(feature = "webhooks")]
+async fn list_webhooks(
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    let subscriptions = state
+        .storage
+        .list_webhook_subscriptions()
+        .await
+        .map_err(storage_error)?;
+    let mut webhooks = Vec::with_capacity(subscriptions.len());
+    for subscription in subscriptions {
+        webhooks.push(webhook_view(&state, subscription).await?);
+    }
+    Ok(Json(json!({ "webhooks": webhooks })))
+}
+
+// The secret, given or generated, is returned here and never again.
+#[cfg(feature = "webhooks")]
+async fn create_webhook(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<WebhookRequest>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    authorize_admin(&state, &headers).await?;
+    let source_identity = match request.source_identity.as_deref() {
+        Some(identity) => Some(client_identity(&state, identity).await?.to_string()),
+        None => None,
+    };
+    let settings = state.node_config.read().await.webhooks.clone();
+    let subscription = new_subscription(request, source_identity, &settings, Utc::now())
+        .map_err(internal_error)?
+        .map_err(|error| (StatusCode::BAD_REQUEST, Json(json!({ "error": error }))))?;
+    state
+        .storage
+        .create_webhook_subscription(&subscription)
+        .await
+        .map_err(storage_error)?;
+    let secret = subscription.secret.clone();
+    Ok((
+        StatusCode::CREATED,
+        Json(json!({ "webhook": WebhookView::new(subscription, 0), "secret": secret })),
+    ))
+}
+
+#[cfg(feature = "webhooks")]
+async fn get_webhook(
+    State(state): State<AppState>,
+    Path(subscription_id): Path<String>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    let subscription = load_webhook(&state, &subscription_id).await?;
+    Ok(Json(webhook_view(&state, subscription).await?))
+}
+
+// Drops the subscription with the deliveries still pending for it.
+#[cfg(feature = "webhooks")]
+async fn delete_webhook(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(subscription_id): Path<String>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    authorize_admin(&state, &headers).await?;
+    let deleted = state
+        .storage
+        .delete_webhook_subscription(&subscription_id)
+        .await
+        .map_err(storage_error)?;
+    if !deleted {
+        return Err(webhook_not_found());
+    }
+    Ok((StatusCode::NO_CONTENT, Json(json!({}))))
+}
+
+// The subscription's latest delivery attempts, newest first.
+#[cfg(feature = "webhooks")]
+async fn list_webhook_deliveries(
+    State(state): State<AppState>,
+    Path(subscription_id): Path<String>,
+    Query(query): Query<ListQuery>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    let subscription = load_webhook(&state, &subscription_id).await?;
+    let attempts = state
+        .storage
+        .list_webhook_attempts(&subscription_id, query.limit.unwrap_or(50))
+        .await
+        .map_err(storage_error)?;
+    Ok(Json(json!({
+        "webhook": webhook_view(&state, subscription).await?,
+        "attempts": attempts,
+    })))
+}
+
+#[cfg(feature = "webhooks")]
+async fn load_webhook(
+    state: &AppState,
+    subscription_id: &str,
+) -> Result<retasync_storage::WebhookSubscription, (StatusCode, Json<Value>)> {
+    state
+        .storage
+        .get_webhook_subscription(subscription_id)
+        .await
+        .map_err(storage_error)?
+        .ok_or_else(webhook_not_found)
+}
+
+#[cfg(feature = "webhooks")]
+async fn webhook_view(
+    state: &AppState,
+    subscription: retasync_storage::WebhookSubscription,
+) -> Result<WebhookView, (StatusCode, Json<Value>)> {
+    let pending = state
+        .storage
+        .count_pending_webhook_deliveries(&subscription.subscription_id)
+        .await
+        .map_err(storage_error)?;
+    Ok(WebhookView::new(subscription, pending))
+}
+
+#[cfg(feature = "webhooks")]
+fn webhook_not_found() -> (StatusCode, Json<Value>) {
+    (
+        StatusCode::NOT_FOUND,
+        Json(json!({ "error": WEBHOOK_NOT_FOUND_ERROR })),
+    )
+}
+
+async fn update_peer_capabilities(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(identity_hash): Path<String>,
+    Json(capabilities): Json<PeerCapabilities>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    authorize(&state, &headers, true).await?;
+    let identity_hash = client_identity(&state, &identity_hash).await?;
+    state
+        .peers
+        .set_capabilities(&identity_hash, capabilities.clone());
+    Ok((StatusCode::OK, Json(capabilities)))
+}
+
+async fn handshake_peer(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(identity_hash): Path<String>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    authorize(&state, &headers, true).await?;
+    refuse_when_muted(&state)?;
+    // A handshake needs a real peer, so reserved names are refused even in lenient mode.
+    let identity_hash = IdentityHash::parse(&identity_hash).map_err(identity_rejection)?;
+    let handshake = handshake(&state, &identity_hash).await.map_err(|err| {
+        (
+            StatusCode::BAD_GATEWAY,
+            Json(json!({"error":"handshake_failed","detail":err.to_string()})),
+        )
+    })?;
+    Ok((StatusCode::OK, Json(handshake)))
+}
+
+#[cfg(feature = "entities")]
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EntitySyncBody {
+    peer: String,
+    max_rounds: Option<usize>,
+    max_response_size: Option<usize>,
+    // Applied to the records either side stores from the other.
+    #[serde(default)]
+    labels: Labels,
+}
+
+#[cfg(feature = "entities")]
+async fn sync_entities(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(entity_type): Path<String>,
+    Json(body): Json<EntitySyncBody>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    authorize(&state, &headers, true).await?;
+    refuse_when_muted(&state)?;
+    IdentityHash::parse(&body.peer).map_err(identity_rejection)?;
+    validate_labels(&body.labels).map_err(label_rejection)?;
+    let defaults = SyncLimits::default();
+    let limits = SyncLimits {
+        max_rounds: body.max_rounds.unwrap_or(defaults.max_rounds).max(1),
+        max_response_size: body.max_response_size.unwrap_or(defaults.max_response_size),
+    };
+    let report = sync_with_peer(&state, &entity_type, &body.peer, limits, &body.labels)
+        .await
+        .map_err(|err| {
+            (
+                StatusCode::BAD_GATEWAY,
+                Json(json!({"error":"entity_sync_failed","detail":format!("{err:#}")})),
+            )
+        })?;
+
+    if report.conflicted > 0 {
+        let message = format!(
+            "{} {entity_type} conflicts with {} kept for review",
+            report.conflicted, body.peer
+        );
+        write_log(&state, "warn", &message).await;
+    }
+    emit(
+        &state,
+        "entity.sync.completed",
+        json!({
+            "entity_type": entity_type,
+            "peer_identity": body.peer,
+            "rounds": report.rounds,
+            "converged": report.converged,
+            "pulled": report.pulled,
+            "pushed": report.pushed,
+            "conflicted": report.conflicted,
+        }),
+    )
+    .await;
+    Ok((StatusCode::OK, Json(report)))
+}
+
+#[cfg(feature = "entities")]
+async fn list_entity_conflicts(
+    State(state): State<AppState>,
+    Path(entity_type): Path<String>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    let conflicts = state
+        .store
+        .list_sync_conflicts(&entity_type)
+        .await
+        .map_err(storage_error)?;
+    Ok(Json(json!({ "conflicts": conflicts })))
+}
+
+#[cfg(feature = "entities")]
+fn entity_etag(version: i64) -> String {
+    format!("\"{version}\"")
+}
+
+// The version a write was based on, from `If-Match: "<version>"`.
+#[cfg(feature = "entities")]
+fn if_match_version(headers: &HeaderMap) -> Result<Option<i64>, (StatusCode, Json<Value>)> {
+    let Some(value) = headers.get(header::IF_MATCH) else {
+        return Ok(None);
+    };
+    value
+        .to_str()
+        .ok()
+        .and_then(|value| {
+            value
+                .trim()
+                .strip_prefix('"')?
+                .strip_suffix('"')?
+                .parse()
+                .ok()
+        })
+        .filter(|version: &i64| *version > 0)
+        .map(Some)
+        .ok_or_else(|| {
+            (
+                StatusCode::BAD_REQUEST,
+                Json(json!({ "error": "invalid_if_match" })),
+            )
+        })
+}
+
+// A stale version answers 409 with the record the store holds, so the client can rebase.
+#[cfg(feature = "entities")]
+fn entity_write_error(error: StorageError) -> (StatusCode, Json<Value>) {
+    match version_conflict_result(&error) {
+        Some(mut body) => {
+            if let Some(body) = body.as_object_mut() {
+                body.remove("status");
+            }
+            (StatusCode::CONFLICT, Json(body))
+        }
+        None => storage_error(error),
+    }
+}
+
+#[cfg(feature = "entities")]
+fn entity_not_found() -> (StatusCode, Json<Value>) {
+    (
+        StatusCode::NOT_FOUND,
+        Json(json!({ "error": "entity_not_found" })),
+    )
+}
+
+// A shaped entity carries an ETag of its own, which `If-Match` does not accept; writes go by the
+// version instead.
+#[cfg(feature = "entities")]
+async fn get_entity_record(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path((entity_type, entity_id)): Path<(String, String)>,
+    Query(shape): Query<ShapeQuery>,
+) -> Result<Response, (StatusCode, Json<Value>)> {
+    let shape = response_shape(&shape, ENTITY_FIELDS)?;
+    if !shape.is_full() {
+        return get_shaped_entity(&state, &headers, &entity_type, &entity_id, &shape).await;
+    }
+    let entity = state
+        .store
+        .get_entity(&entity_type, &entity_id)
+        .await
+        .map_err(storage_error)?
+        .filter(|entity| entity.deleted_at.is_none())
+        .ok_or_else(entity_not_found)?;
+    let labels = entity_labels(&state, &entity_type, &entity_id).await?;
+    Ok(respond_with_etag(
+        &headers,
+        entity_etag(entity.version),
+        Json(LabelledEntity { entity, labels }),
+    ))
+}
+
+#[cfg(feature = "entities")]
+async fn get_shaped_entity(
+    state: &AppState,
+    headers: &HeaderMap,
+    entity_type: &str,
+    entity_id: &str,
+    shape: &Shape,
+) -> Result<Response, (StatusCode, Json<Value>)> {
+    let view = if shape.wants_payload("record") {
+        state
+            .store
+            .get_entity(entity_type, entity_id)
+            .await
+            .map_err(storage_error)?
+            .map(|entity| {
+                let (record_bytes, record_sha256) = payload_digest(&entity.record.to_string());
+                EntityView {
+                    summary: EntitySummary {
+                        entity_type: entity.entity_type,
+                        entity_id: entity.entity_id,
+                        updated_at: entity.updated_at,
+                        version: entity.version,
+                        deleted_at: entity.deleted_at,
+                        record_bytes: Some(record_bytes),
+                        record_sha256: Some(record_sha256),
+                    },
+                    record: Some(entity.record),
+                    labels: Labels::new(),
+                }
+            })
+    } else {
+        state
+            .store
+            .get_entity_summary(entity_type, entity_id)
+            .await
+            .map_err(storage_error)?
+            .map(|summary| EntityView {
+                summary,
+                record: None,
+                labels: Labels::new(),
+            })
+    };
+    let mut view = view
+        .filter(|view| view.summary.deleted_at.is_none())
+        .ok_or_else(entity_not_found)?;
+    view.labels = entity_labels(state, entity_type, entity_id).await?;
+    let etag = shaped_etag(entity_etag(view.summary.version), shape);
+    Ok(respond_with_etag(headers, etag, Json(shape.apply(view))))
+}
+
+#[cfg(feature = "entities")]
+async fn entity_labels(
+    state: &AppState,
+    entity_type: &str,
+    entity_id: &str,
+) -> Result<Labels, (StatusCode, Json<Value>)> {
+    state
+        .storage
+        .get_labels(LABEL_SUBJECT_ENTITY, &entity_label_subject(entity_type, entity_id))
+        .await
+        .map_err(storage_error)
+}
+
+// Without `If-Match` the write creates the entity; with it, it replaces that version.
+#[cfg(feature = "entities")]
+async fn put_entity_record(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path((entity_type, entity_id)): Path<(String, String)>,
+    Json(record): Json<Value>,
+) -> Result<Response, (StatusCode, Json<Value>)> {
+    authorize(&state, &headers, true).await?;
+    let expected = if_match_version(&headers)?;
+    let entity = state
+        .store
+        .update_entity(&entity_type, &entity_id, &record, expected.unwrap_or(0))
+        .await
+        .map_err(entity_write_error)?;
+    let status = if expected.is_some() {
+        StatusCode::OK
+    } else {
+        StatusCode::CREATED
+    };
+    Ok((
+        status,
+        [(header::ETAG, entity_etag(entity.version))],
+        Json(entity),
+    )
+        .into_response())
+}
+
+// Deletes leave a tombstone, so the removal replicates like any other edit.
+#[cfg(feature = "entities")]
+async fn delete_entity_record(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path((entity_type, entity_id)): Path<(String, String)>,
+) -> Result<Response, (StatusCode, Json<Value>)> {
+    authorize(&state, &headers, true).await?;
+    let Some(expected) = if_match_version(&headers)? else {
+        return Err((
+            StatusCode::PRECONDITION_REQUIRED,
+            Json(json!({ "error": "if_match_required" })),
+        ));
+    };
+    let entity = state
+        .store
+        .soft_delete_entity(&entity_type, &entity_id, expected)
+        .await
+        .map_err(entity_write_error)?;
+    Ok((
+        StatusCode::OK,
+        [(header::ETAG, entity_etag(entity.version))],
+        Json(entity),
+    )
+        .into_response())
+}
+
+async fn get_simulation(
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    let simulation = simulation_handle(&state)?;
+    Ok((StatusCode::OK, Json(simulation.profile())))
+}
+
+async fn update_simulation(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(profile): Json<SimulationProfile>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    authorize(&state, &headers, true).await?;
+    let simulation = simulation_handle(&state)?;
+    simulation.set_profile(profile.clone());
+
+    write_log(&state, "warn", "mesh simulation profile updated").await;
+    emit(
+        &state,
+        "node.simulation.updated",
+        serde_json::to_value(&profile).unwrap_or_default(),
+    )
+    .await;
+    Ok((StatusCode::OK, Json(profile)))
+}
+
+fn simulation_handle(
+    state: &AppState,
+) -> Result<Arc<SimulatedMeshBridge>, (StatusCode, Json<Value>)> {
+    state.simulation.clone().ok_or_else(|| {
+        (
+            StatusCode::NOT_FOUND,
+            Json(json!({"error":"simulation_not_enabled"})),
+        )
+    })
+}
+
+async fn list_archives(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    authorize_admin(&state, &headers).await?;
+    let dir = archive_dir(&state).await?;
+    let archives = {
+        let dir = dir.clone();
+        tokio::task::spawn_blocking(move || archive::list_archives(&dir))
+            .await
+            .map_err(|err| internal_error(err.into()))?
+            .map_err(internal_error)?
+    };
+    Ok((
+        StatusCode::OK,
+        Json(json!({ "archive_dir": dir, "archives": archives })),
+    ))
+}
+
+// Streams one archive file as stored: concatenated canonical msgpack records.
+async fn download_archive(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+) -> Result<Response, (StatusCode, Json<Value>)> {
+    authorize_admin(&state, &headers).await?;
+    let dir = archive_dir(&state).await?;
+    let Some(path) = archive::archive_path(&dir, &name) else {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error":"invalid_archive_name"})),
+        ));
+    };
+    let file = match tokio::fs::File::open(&path).await {
+        Ok(file) => file,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            return Err((
+                StatusCode::NOT_FOUND,
+                Json(json!({"error":"archive_not_found"})),
+            ));
+        }
+        Err(err) => return Err(internal_error(err.into())),
+    };
+
+    let chunks = futures::stream::unfold(file, |mut file| async move {
+        let mut chunk = vec![0; 64 * 1024];
+        match file.read(&mut chunk).await {
+            Ok(0) => None,
+            Ok(read) => {
+                chunk.truncate(read);
+                Some((Ok::<_, std::io::Error>(Bytes::from(chunk)), file))
+            }
+            Err(err) => Some((Err(err), file)),
+        }
+    });
+    Ok((
+        [
+            (header::CONTENT_TYPE, HeaderValue::from_static(CONTENT_TYPE_MSGPACK)),
+            (
+                header::CONTENT_DISPOSITION,
+                HeaderValue::from_str(&format!("attachment; filename=\"{name}\""))
+                    .map_err(|err| internal_error(err.into()))?,
+            ),
+        ],
+        Body::from_stream(chunks),
+    )
+        .into_response())
+}
+
+async fn list_quarantine(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<ListQuery>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    authorize_admin(&state, &headers).await?;
+    let rows = state
+        .storage
+        .list_quarantine(query.limit.unwrap_or(100))
+        .await
+        .map_err(storage_error)?;
+    let rows: Vec<Value> = rows
+        .into_iter()
+        .map(|row| {
+            json!({
+                "id": row.id,
+                "source_table": row.source_table,
+                "row_key": row.row_key,
+                "column_name": row.column_name,
+                "raw_base64": STANDARD.encode(&row.raw),
+                "reason": row.reason,
+                "quarantined_at": row.quarantined_at,
+            })
+        })
+        .collect();
+    Ok((StatusCode::OK, Json(json!({ "rows": rows }))))
+}
+
+async fn purge_quarantine(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    authorize_admin(&state, &headers).await?;
+    let purged = state
+        .storage
+        .purge_quarantine()
+        .await
+        .map_err(storage_error)?;
+    write_log(&state, "warn", &format!("storage_quarantine_purged rows={purged}")).await;
+    Ok((StatusCode::OK, Json(json!({ "purged": purged }))))
+}
+
+// Recomputes state derived from the primary tables after they were edited by hand. Refused
+// while workers hold leases or inbound work is queued, unless the node is fully muted.
+async fn post_storage_rebuild(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<RebuildQuery>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    authorize_admin(&state, &headers).await?;
+    let scopes = parse_scopes(query.scope.as_deref()).map_err(|scope| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "error": "unknown_rebuild_scope",
+                "scope": scope,
+                "known": RebuildScope::ALL.map(RebuildScope::as_str),
+            })),
+        )
+    })?;
+    let allow_renumber = query.allow_renumber.unwrap_or(false);
+    match start_rebuild(&state, scopes, allow_renumber)
+        .await
+        .map_err(internal_error)?
+    {
+        Ok(run) => Ok((StatusCode::ACCEPTED, Json(json!(run)))),
+        Err(RebuildRefusal::InProgress(rebuild_id)) => Err((
+            StatusCode::CONFLICT,
+            Json(json!({ "error": "rebuild_in_progress", "rebuild_id": rebuild_id })),
+        )),
+        Err(RebuildRefusal::WorkersActive {
+            leased_jobs,
+            inbound_depth,
+        }) => Err((
+            StatusCode::CONFLICT,
+            Json(json!({
+                "error": "storage_rebuild_refused",
+                "reason": "workers_active",
+                "leased_jobs": leased_jobs,
+                "inbound_depth": inbound_depth,
+            })),
+        )),
+    }
+}
+
+async fn get_storage_rebuild(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    authorize_admin(&state, &headers).await?;
+    let Some(run) = current_rebuild(&state) else {
+        return Err((StatusCode::NOT_FOUND, Json(json!({ "error": "no_rebuild" }))));
+    };
+    Ok((StatusCode::OK, Json(json!(run))))
+}
+
+async fn get_bundle_signer(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    authorize_admin(&state, &headers).await?;
+    Ok(Json(signer(&state).await.map_err(sneakernet_error)?))
+}
+
+async fn export_sneakernet_bundle(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(filter): Json<ExportFilter>,
+) -> Result<Response, (StatusCode, Json<Value>)> {
+    authorize_admin(&state, &headers).await?;
+    let exported = export_bundle(&state, &filter)
+        .await
+        .map_err(sneakernet_error)?;
+    let disposition = format!("attachment; filename=\"{}.retasync-bundle\"", exported.bundle_id);
+    Ok((
+        [
+            (header::CONTENT_TYPE, HeaderValue::from_static(SNEAKERNET_MEDIA_TYPE)),
+            (
+                header::CONTENT_DISPOSITION,
+                HeaderValue::from_str(&disposition).map_err(|err| internal_error(err.into()))?,
+            ),
+            (
+                HeaderName::from_static(BUNDLE_ID_HEADER),
+                HeaderValue::from_str(&exported.bundle_id)
+                    .map_err(|err| internal_error(err.into()))?,
+            ),
+        ],
+        Body::from(exported.bytes),
+    )
+        .into_response())
+}
+
+async fn import_sneakernet_bundle(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    authorize_admin(&state, &headers).await?;
+    let limit = state.node_config.read().await.sneakernet.max_bundle_bytes;
+    let raw = axum::body::to_bytes(body, usize::try_from(limit).unwrap_or(usize::MAX))
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                Json(json!({"error":"bundle_too_large","limit_bytes":limit})),
+            )
+        })?;
+    let report = import_bundle(&state, &raw)
+        .await
+        .map_err(sneakernet_error)?;
+    write_log(
+        &state,
+        "info",
+        &format!(
+            "bundle {} from {} imported: {} accepted, {} duplicate, {} rejected",
+            report.bundle_id,
+            report.source_identity,
+            report.accepted,
+            report.duplicate,
+            report.rejected
+        ),
+    )
+    .await;
+    Ok(Json(report))
+}
+
+fn sneakernet_error(error: SneakernetError) -> (StatusCode, Json<Value>) {
+    let status = match &error {
+        SneakernetError::TooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+        SneakernetError::Invalid(_) => StatusCode::BAD_REQUEST,
+        SneakernetError::UntrustedSigner(_) | SneakernetError::BadSignature => {
+            StatusCode::FORBIDDEN
+        }
+        SneakernetError::SigningKey { .. } | SneakernetError::Internal(_) => {
+            return internal_error(error.into());
+        }
+    };
+    (
+        status,
+        Json(json!({ "error": error.code(), "detail": error.to_string() })),
+    )
+}
+
+async fn list_crash_reports(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<CursorQuery>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    authorize_admin(&state, &headers).await?;
+    let codec = cursor_codec(&state).await?;
+    let now = Utc::now();
+    let limit = query.limit.unwrap_or(50).clamp(1, 1000);
+    let page = codec
+        .keyset("crash_reports", CRASH_REPORT_ORDER, query.cursor.as_deref(), limit, now)
+        .map_err(cursor_error)?;
+    let mut reports = state
+        .storage
+        .list_crash_reports_page(&page)
+        .await
+        .map_err(storage_error)?;
+    let key_of = |report: &CrashReport| {
+        vec![
+            KeyValue::from(report.occurred_at.as_str()),
+            KeyValue::from(report.crash_id.as_str()),
+        ]
+    };
+    let cursors = codec
+        .finish("crash_reports", &page, &mut reports, key_of, now)
+        .map_err(cursor_error)?;
+    Ok(Json(json!({
+        "crashes": reports,
+        "next_cursor": cursors.next_cursor,
+        "prev_cursor": cursors.prev_cursor,
+        "order": CRASH_REPORT_ORDER.describe(),
+    })))
+}
+
+async fn get_crash_report(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(crash_id): Path<String>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    authorize_admin(&state, &headers).await?;
+    match state
+        .storage
+        .get_crash_report(&crash_id)
+        .await
+        .map_err(storage_error)?
+    {
+        Some(report) => Ok(Json(report)),
+        None => Err((
+            StatusCode::NOT_FOUND,
+            Json(json!({"error":"crash_not_found"})),
+        )),
+    }
+}
+
+async fn archive_dir(state: &AppState) -> Result<std::path::PathBuf, (StatusCode, Json<Value>)> {
+    match state.node_config.read().await.retention.archive_dir.as_deref() {
+        Some(dir) => Ok(std::path::PathBuf::from(dir)),
+        None => Err((
+            StatusCode::NOT_FOUND,
+            Json(json!({"error":"archiving_disabled"})),
+        )),
+    }
+}
+
+async fn authorize(
+    state: &AppState,
+    headers: &HeaderMap,
+    write_operation: bool,
+) -> Result<(), (StatusCode, Json<Value>)> {
+    if !write_operation || !bearer_required(state, headers) {
+        return Ok(());
+    }
+
+    let config = state.node_config.read().await;
+    if config.http_auth_token.is_none()
+        && config.api_tokens.is_empty()
+        && config.tls_principals.is_empty()
+    {
+        return Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({"error":"auth_token_required_but_not_configured"})),
+        ));
+    }
+
+    if token_label(&config, headers).is_some() {
+        Ok(())
+    } else {
+        Err((
+            StatusCode::UNAUTHORIZED,
+            Json(json!({"error":"invalid_or_missing_bearer_token"})),
+        ))
+    }
+}
+
+async fn authorize_admin(
+    state: &AppState,
+    headers: &HeaderMap,
+) -> Result<(), (StatusCode, Json<Value>)> {
+    authorize(state, headers, true).await?;
+    if is_admin(state, headers).await {
+        Ok(())
+    } else {
+        Err((
+            StatusCode::FORBIDDEN,
+            Json(json!({"error":"admin_token_required"})),
+        ))
+    }
+}
+
+// The primary `http_auth_token` and client certificates mapped to the `admin` role are admin
+// credentials; labelled API tokens and other principals are not.
+async fn is_admin(state: &AppState, headers: &HeaderMap) -> bool {
+    if !bearer_required(state, headers) {
+        return true;
+    }
+    let config = state.node_config.read().await;
+    token_label(&config, headers).as_deref() == Some("default")
+}
+
+// The listener's own auth requirement wins; requests without one fall back to the node's.
+fn bearer_required(state: &AppState, headers: &HeaderMap) -> bool {
+    match headers
+        .get(LISTENER_AUTH_HEADER)
+        .and_then(|value| value.to_str().ok())
+    {
+        Some("required") => true,
+        Some("optional") => false,
+        _ => state.require_bearer,
+    }
+}
+
+// Who to attribute a request to: its token label, or the listener an unauthenticated caller
+// came in on.
+fn caller_label(config: &NodeConfig, headers: &HeaderMap) -> String {
+    token_label(config, headers).unwrap_or_else(|| {
+        match headers
+            .get(LISTENER_HEADER)
+            .and_then(|value| value.to_str().ok())
+        {
+            Some(name) => format!("listener:{name}"),
+            None => "local".to_string(),
+        }
+    })
+}
+
+// Who a new job or transfer is recorded as submitted by.
+fn submission_source(config: &NodeConfig, headers: &HeaderMap) -> SubmissionSource {
+    SubmissionSource {
+        token_label: token_label(config, headers).unwrap_or_else(|| ANONYMOUS_LOOPBACK.to_string()),
+        remote_addr: headers
+            .get(REMOTE_ADDR_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string),
+        client_id: client_id(headers),
+    }
+}
+
+fn token_label(config: &NodeConfig, headers: &HeaderMap) -> Option<String> {
+    let Some(provided) = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+    else {
+        return principal_label(config, headers);
+    };
+
+    if config.http_auth_token.as_deref() == Some(provided) {
+        return Some("default".to_string());
+    }
+    config
+        .api_tokens
+        .iter()
+        .find(|api_token| api_token.token == provided)
+        .map(|api_token| api_token.label.clone())
+}
+
+// A client certificate stands in for a token: `admin` maps to the primary token's label and any
+// other role becomes the label itself.
+fn principal_label(config: &NodeConfig, headers: &HeaderMap) -> Option<String> {
+    let role = headers
+        .get_all(CLIENT_PRINCIPAL_HEADER)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .find_map(|name| config.tls_principals.get(name))?;
+    if role == ADMIN_PRINCIPAL_ROLE {
+        Some("default".to_string())
+    } else {
+        Some(role.clone())
+    }
+}
+
+fn compute_etag(bytes: &[u8]) -> String {
+    let digest = Sha256::digest(bytes);
+    let hex: String = digest[..16].iter().map(|byte| format!("{byte:02x}")).collect();
+    format!("\"{hex}\"")
+}
+
+fn node_config_etag(config: &NodeConfig) -> anyhow::Result<String> {
+    let serialized = serde_json::to_vec(config)?;
+    Ok(compute_etag(&serialized))
+}
+
+fn etag_matches(headers: &HeaderMap, name: HeaderName, etag: &str, weak: bool) -> bool {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .any(|candidate| {
+            let candidate = if weak {
+                candidate.trim_start_matches("W/")
+            } else {
+                candidate
+            };
+            candidate == "*" || candidate == etag
+        })
+}
+
+fn not_modified(etag: String) -> Response {
+    (StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response()
+}
+
+fn respond_with_etag(headers: &HeaderMap, etag: String, body: impl IntoResponse) -> Response {
+    if etag_matches(headers, header::IF_NONE_MATCH, &etag, true) {
+        return not_modified(etag);
+    }
+    (StatusCode::OK, [(header::ETAG, etag)], body).into_response()
+}
+
+pub(crate) fn internal_error(error: anyhow::Error) -> (StatusCode, Json<Value>) {
+    error!(error = %error, "request failed");
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(json!({ "error": "internal_error", "detail": error.to_string() })),
+    )
+}
+
+// A busy or unreachable database is worth retrying, so it answers 503 rather than 500.
+pub(crate) fn storage_error(error: StorageError) -> (StatusCode, Json<Value>) {
+    let (status, code) = match &error {
+        StorageError::NotFound { .. } => (StatusCode::NOT_FOUND, "not_found"),
+        StorageError::Conflict { .. } => (StatusCode::CONFLICT, "conflict"),
+        StorageError::VersionConflict { .. } => (StatusCode::CONFLICT, "version_conflict"),
+        StorageError::QuotaExceeded { .. } => (StatusCode::TOO_MANY_REQUESTS, "quota_exceeded"),
+        StorageError::Busy { .. } | StorageError::Connection { .. } => {
+            (StatusCode::SERVICE_UNAVAILABLE, "storage_unavailable")
+        }
+        StorageError::Corrupt { .. } => (StatusCode::INTERNAL_SERVER_ERROR, "storage_corrupt"),
+        StorageError::Serialization { .. }
+        | StorageError::Database { .. }
+        | StorageError::Encryption(_) => (StatusCode::INTERNAL_SERVER_ERROR, "internal_error"),
+    };
+    if status.is_server_error() {
+        error!(error = %error, kind = error.kind(), "request failed");
+    }
+    let body = json!({ "error": code, "detail": error.to_string() });
+    if status == StatusCode::SERVICE_UNAVAILABLE {
+        let advice = RetryAdvice::after(code, RetryScope::Node, STORAGE_RETRY_AFTER);
+        return refusal(status, body, advice);
+    }
+    (status, Json(body))
+}
+
+// The spool only takes submissions storage could not, so a full or failing spool answers 503
+// like the storage behind it. A full spool frees room at the next drain.
+fn submission_spool_error(
+    drain_interval: std::time::Duration,
+    error: SubmissionSpoolError,
+) -> (StatusCode, Json<Value>) {
+    let (code, retry_after) = match &error {
+        SubmissionSpoolError::Full { .. } => ("submission_spool_full", drain_interval),
+        SubmissionSpoolError::Io(_) | SubmissionSpoolError::Codec(_) => {
+            error!(error = %error, "submission spool failed");
+            ("storage_unavailable", STORAGE_RETRY_AFTER)
+        }
+    };
+    refusal(
+        StatusCode::SERVICE_UNAVAILABLE,
+        json!({ "error": code, "detail": error.to_string() }),
+        RetryAdvice::after(code, RetryScope::Node, retry_after),
+    )
+}
+
+// Records an event that goes with no stored change, such as a config update or a batch summary,
+// and publishes it. State changes record theirs in the transaction making them (see
+// `RetasyncStorage::with_event`) and then call `publish`.
+pub(crate) async fn emit(state: &AppState, event_type: &str, data: Value) {
+    if let Err(err) = state.storage.append_feed_event(event_type, &data).await {
+        error!(error = %err, event_type, "failed to append event to feed");
+        deliver(state, event_type, data).await;
+        return;
+    }
+    publish(state).await;
+}
+
+// Pushes the feed rows committed so far to subscribers.
+pub(crate) async fn publish(state: &AppState) {
+    if let Err(err) = state.notifier.publish(state).await {
+        error!(error = %err, "failed to publish feed events");
+    }
+}
+
+pub(crate) async fn write_log(state: &AppState, level: &str, message: &str) {
+    info!(level = %level, message = %message, "control-plane log entry");
+    let mut buffer = state.log_buffer.write().await;
+    buffer.push(LogLine {
+        timestamp: CanonicalTimestamp::now(),
+        level: level.to_string(),
+        message: message.to_string(),
+    });
+
+    if buffer.len() > 500 {
+        let keep_start = buffer.len() - 500;
+        let trimmed = buffer.split_off(keep_start);
+        *buffer = trimmed;
+    }
+}
+
+#[allow(dead_code)]
+fn _job_record_to_value(job: JobRecord) -> Value {
+    json!({
+        "job_id": job.job_id,
+        "operation": job.operation,
+        "status": job.status,
+        "submitted_at": job.submitted_at,
+        "updated_at": job.updated_at,
+        "failure_reason": job.failure_reason,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        build_router, drain_submission_spool, embedded_router, emit, process_command_job, publish,
+        quota_exceeded, rate_limited, resolve_dispatch, storage_error, submission_spool_error,
+        ApiToken,
+        AppState, CanonicalTimestamp, ClientPrincipal, LogLine, NodeConfig, OperationDefaults,
+        RequestListener, StorageError, CLIENT_PRINCIPAL_HEADER, DEFAULT_MAX_LINK_BYTES,
+        DEFAULT_MAX_LXMF_BYTES,
+    };
+    #[cfg(feature = "transfers")]
+    use super::DEFAULT_CHUNK_SIZE;
+    use crate::circuit::{probe_deferred, CircuitPolicy, CIRCUIT_OPEN_REASON, DEFERRED_STATUS};
+    use crate::clock::{
+        observe_event, seen_message_horizon_secs, skew_allowance_secs, DEFAULT_EVENT_TRANSIT_MS,
+        PEER_CLOCK_DRIFT_EVENT,
+    };
+    #[cfg(feature = "sse")]
+    use crate::coalescing::{NOTIFICATION_SUBSCRIBER, SSE_SUBSCRIBER, WEBHOOK_SUBSCRIBER};
+    use crate::config_apply;
+    use crate::dispatch_queue::{JOB_PRIORITIZED_EVENT, QUEUE_ORDER_CHANGED_EVENT};
+    use crate::error::{RetryAdvice, RetryScope};
+    use crate::escalation::{check_transit_expiry, UNDELIVERED_TTL_EXPIRED};
+    use crate::features::{
+        FeatureFlags, CLOCK_SKEW_TOLERANCE_FLAG, COMPRESSION_FLAG, TRANSFER_DEDUP_FLAG,
+    };
+    #[cfg(feature = "entities")]
+    use crate::handlers::NOT_ALLOWLISTED_ERROR;
+    use crate::inbound::{route_command, spawn_inbound_worker};
+    use crate::labels::JOB_LABELS_CHANGED_EVENT;
+    #[cfg(feature = "transfers")]
+    use crate::labels::{INVALID_LABELS_ERROR, INVALID_LABEL_FILTER_ERROR};
+    #[cfg(feature = "entities")]
+    use crate::labels::{Labels, LABELS_FIELD};
+    use crate::mute::{
+        expire_mute, load as load_mute, Traffic, MUTED_HEADER, NODE_MUTE_CHANGED_EVENT,
+    };
+    use crate::quotas::QuotaExceeded;
+    #[cfg(feature = "entities")]
+    use crate::receipts::{RECEIPT_INVALID_EVENT, RECEIPT_NOT_FOUND_ERROR, RECEIPT_SIGNER_UNKNOWN};
+    use crate::test_support::{scratch_dir, scratch_sqlite, test_storage};
+    use crate::result_validation::{
+        ResultPolicy, INVALID_RESULT_REASON, RESULT_NONCONFORMING_EVENT,
+    };
+    use crate::results::ingest_events;
+    #[cfg(feature = "entities")]
+    use crate::results::apply_event;
+    use crate::runtime::ControlPlaneRuntime;
+    use crate::sealing::{
+        local_sealing_key, DECRYPT_FAILED_EVENT, SEALED_SENDER_UNKNOWN_ERROR,
+        SEALING_KEY_MISSING_ERROR, SEALING_KEY_REGISTERED_EVENT,
+    };
+    #[cfg(feature = "entities")]
+    use crate::shaping::UNKNOWN_FIELD_ERROR;
+    use crate::sneakernet::{BUNDLE_ID_HEADER, SNEAKERNET_MEDIA_TYPE};
+    #[cfg(feature = "entities")]
+    use crate::store::{ControlPlaneStore, InMemoryStore};
+    #[cfg(feature = "entities")]
+    use crate::sneakernet::signer;
+    use crate::submission_spool::{SubmissionSpoolError, SPOOLED_STATUS};
+    use crate::trace::RoutingSettings;
+    use axum::{
+        body::Body,
+        http::{header, Request, StatusCode},
+        Router,
+    };
+    use base64::{engine::general_purpose::STANDARD, Engine as _};
+    use retasync_codegen::schema_violations;
+    use retasync_contract::{
+        decode_canonical, IdentityHash, IdentityValidation, MeshCommandEnvelope,
+        MeshEventEnvelope, MeshResultEnvelope, MeshTransferEnvelope, TransferHint,
+        CONTENT_TYPE_MSGPACK, CONTENT_TYPE_SEALED, DELAYED_RESULT_EVENT, LOCAL_NODE_IDENTITY,
+    };
+    #[cfg(feature = "transfers")]
+    use retasync_contract::{Bundle, BundleEntry, TransferDirection, BUNDLE_MEDIA_TYPE};
+    #[cfg(feature = "entities")]
+    use retasync_contract::{
+        canonical_digest, encode_canonical, DELIVERY_RECEIPT_EVENT, SEALED_FORMAT_VERSION,
+    };
+    use retasync_mesh_bridge::{
+        read_recording, BridgeError, BridgeReceipt, BridgeRecord, CancelOutcome, ClockEstimate,
+        ClockSampleSource, InMemoryRpcMeshBridge, LoopbackMeshBridge, LossProfile, MockResults,
+        RecordedOutcome, RecordingBridge, ReplayBridge, ReplayMatching, RpcMeshBridge,
+        SimulatedMeshBridge, SimulationProfile,
+    };
+    #[cfg(feature = "sse")]
+    use futures::StreamExt;
+    use retasync_storage::{
+        HealthSample, JobRecord,
+        FEED_JOB_EVENT,
+    };
+    #[cfg(feature = "entities")]
+    use retasync_storage::{
+        entity_label_subject, payload_digest, EntityRecord, LABEL_SUBJECT_ENTITY,
+    };
+    use serde_json::{json, Value};
+    #[cfg(feature = "transfers")]
+    use sha2::{Digest, Sha256};
+    use std::num::NonZeroUsize;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tower::ServiceExt;
+    use uuid::Uuid;
+
+    fn test_node_config() -> NodeConfig {
+        NodeConfig {
+            rpc_endpoint: "tcp://127.0.0.1:31337".to_string(),
+            http_bind: "127.0.0.1:8080".to_string(),
+            http_auth_token: None,
+            sqlite_path: "test.sqlite".to_string(),
+            acl_mode: "allowlist".to_string(),
+            identity_validation: Default::default(),
+            prefer_link: true,
+            compression_threshold_bytes: 4096,
+            transfer_stall_after_secs: 120,
+            result_stream_timeout_secs: 300,
+            max_link_bytes: DEFAULT_MAX_LINK_BYTES,
+            max_lxmf_bytes: DEFAULT_MAX_LXMF_BYTES,
+            api_tokens: Vec::new(),
+            tls_principals: Default::default(),
+            status_page: false,
+            notifications: Default::default(),
+            inbound: Default::default(),
+            codec_limits: Default::default(),
+            identity: Default::default(),
+            operation_defaults: Default::default(),
+            allow_sunset_operations: false,
+            attachments: Default::default(),
+            retention: Default::default(),
+            submissions: Default::default(),
+            delivery: Default::default(),
+            aggregates: Default::default(),
+            liveness: Default::default(),
+            job_watchdog: Default::default(),
+            transfer_dedup: Default::default(),
+            transfer_bundles: Default::default(),
+            quotas: Default::default(),
+            routing: Default::default(),
+            dependencies: Default::default(),
+            transforms: Vec::new(),
+            event_feed: Default::default(),
+            consistency: Default::default(),
+            clock: Default::default(),
+            payload_migrations: Default::default(),
+            files: Default::default(),
+            transfer_spool: Default::default(),
+            submission_spool: Default::default(),
+            result_validation: Default::default(),
+            dispatch_queue: Default::default(),
+            sneakernet: Default::default(),
+            crash_reports: Default::default(),
+            config_apply: Default::default(),
+            fleet: Default::default(),
+            pagination: Default::default(),
+            webhooks: Default::default(),
+            result_cache: Default::default(),
+            event_coalescing: Default::default(),
+            security: Default::default(),
+            receipts: Default::default(),
+        }
+    }
+
+    async fn test_router() -> Router {
+        build_router(test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await)
+    }
+
+    async fn test_state(bridge: Arc<dyn RpcMeshBridge>) -> AppState {
+        AppState::new(
+            test_storage(&scratch_sqlite()).await,
+            bridge,
+            test_node_config(),
+            "asyncapi: 3.0.0\n".to_string(),
+            false,
+        )
+    }
+
+    async fn set_feature(state: &AppState, name: &str, enabled: bool) {
+        let record = state
+            .storage
+            .put_feature_flag(name, enabled, None, "test")
+            .await
+            .unwrap();
+        state.features.cache_record(&record);
+    }
+
+    async fn send(router: &Router, request: Request<Body>) -> axum::response::Response {
+        router.clone().oneshot(request).await.expect("response")
+    }
+
+    async fn json_body(response: axum::response::Response) -> serde_json::Value {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    fn etag_of(response: &axum::response::Response) -> String {
+        response
+            .headers()
+            .get(header::ETAG)
+            .and_then(|value| value.to_str().ok())
+            .expect("etag header")
+            .to_string()
+    }
+
+    #[tokio::test]
+    async fn repeat_get_with_etag_returns_not_modified() {
+        let router = test_router().await;
+        let first = send(
+            &router,
+            Request::get("/v1/contracts/asyncapi").body(Body::empty()).unwrap(),
+        )
+        .await;
+        assert_eq!(first.status(), StatusCode::OK);
+        let etag = etag_of(&first);
+
+        let second = send(
+            &router,
+            Request::get("/v1/contracts/asyncapi")
+                .header(header::IF_NONE_MATCH, &etag)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(second.status(), StatusCode::NOT_MODIFIED);
+        let body = axum::body::to_bytes(second.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(body.is_empty());
+    }
+
+    #[tokio::test]
+    async fn allowlist_etag_changes_after_mutation() {
+        let router = test_router().await;
+        let before = send(
+            &router,
+            Request::get("/v1/security/allowlist").body(Body::empty()).unwrap(),
+        )
+        .await;
+        let etag = etag_of(&before);
+
+        let added = send(
+            &router,
+            Request::post("/v1/security/allowlist")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(r#"{"identity_hash":"abc12300000000000000000000000000"}"#))
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(added.status(), StatusCode::CREATED);
+
+        let after = send(
+            &router,
+            Request::get("/v1/security/allowlist")
+                .header(header::IF_NONE_MATCH, &etag)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(after.status(), StatusCode::OK);
+        assert_ne!(etag_of(&after), etag);
+    }
+
+    #[tokio::test]
+    async fn stale_if_match_on_config_put_is_rejected() {
+        let router = test_router().await;
+        let current = send(
+            &router,
+            Request::get("/v1/node/config").body(Body::empty()).unwrap(),
+        )
+        .await;
+        let etag = etag_of(&current);
+
+        let mut first_edit = test_node_config();
+        first_edit.acl_mode = "denylist".to_string();
+        let accepted = send(
+            &router,
+            Request::put("/v1/node/config")
+                .header(header::CONTENT_TYPE, "application/json")
+                .header(header::IF_MATCH, &etag)
+                .body(Body::from(serde_json::to_vec(&first_edit).unwrap()))
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(accepted.status(), StatusCode::OK);
+        let new_etag = etag_of(&accepted);
+
+        let mut second_edit = test_node_config();
+        second_edit.prefer_link = false;
+        let rejected = send(
+            &router,
+            Request::put("/v1/node/config")
+                .header(header::CONTENT_TYPE, "application/json")
+                .header(header::IF_MATCH, &etag)
+                .body(Body::from(serde_json::to_vec(&second_edit).unwrap()))
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(rejected.status(), StatusCode::PRECONDITION_FAILED);
+        assert_eq!(etag_of(&rejected), new_etag);
+    }
+
+    #[tokio::test]
+    async fn effective_config_reports_where_each_value_came_from() {
+        let state = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        let (status, _) =
+            get_json(&build_router(state.clone()), "/v1/node/config/effective").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let file = json!({ "profile": "field_client", "retention": { "job_retention_hours": 2 } });
+        let effective = crate::profiles::EffectiveConfig::resolve(&file, None).unwrap();
+        let router = build_router(state.with_effective_config(effective));
+        let (status, body) = get_json(&router, "/v1/node/config/effective").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["profile"], "field_client");
+        assert_eq!(body["config"]["inbound"]["capacity"], 0);
+        assert_eq!(body["sources"]["/retention/job_retention_hours"], "file");
+        assert_eq!(body["sources"]["/retention/cache_retention_hours"], "preset");
+        assert_eq!(body["sources"]["/rpc/endpoint"], "default");
+    }
+
+    #[tokio::test]
+    async fn soak_under_loss_leaves_no_stuck_jobs() {
+        let simulation = Arc::new(SimulatedMeshBridge::new(
+            Arc::new(InMemoryRpcMeshBridge::new(true, true)),
+            SimulationProfile {
+                seed: 1613,
+                loss: LossProfile {
+                    send_command: 0.2,
+                    ..LossProfile::default()
+                },
+                ..SimulationProfile::default()
+            },
+        ));
+        let state = test_state(simulation.clone())
+            .await
+            .with_simulation(simulation);
+        let router = build_router(state.clone());
+
+        let mut job_ids = Vec::new();
+        for idx in 0..100 {
+            let response = send(
+                &router,
+                Request::post("/v1/jobs/commands/event.create")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(format!(r#"{{"uid":"evt-{idx}"}}"#)))
+                    .unwrap(),
+            )
+            .await;
+            assert_eq!(response.status(), StatusCode::ACCEPTED);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            job_ids.push(body["job_id"].as_str().unwrap().to_string());
+        }
+
+        // Generous deadline: a loaded test runner can take far longer than the bridge latency.
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(30);
+        let mut statuses = Vec::new();
+        loop {
+            statuses.clear();
+            for job_id in &job_ids {
+                let job = state.storage.get_job(job_id).await.unwrap().unwrap();
+                statuses.push(job.status);
+            }
+            if statuses.iter().all(|status| status == "success" || status == "failed")
+                || tokio::time::Instant::now() >= deadline
+            {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        let succeeded = statuses.iter().filter(|status| *status == "success").count();
+        let failed = statuses.iter().filter(|status| *status == "failed").count();
+        assert_eq!(succeeded + failed, 100, "stuck jobs: {statuses:?}");
+        assert!(failed > 0 && succeeded > failed);
+    }
+
+    #[tokio::test]
+    async fn notifications_persist_until_acked() {
+        let state = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        let router = build_router(state.clone());
+        emit(&state, "job.status.changed", json!({ "job_id": "a", "status": "queued" })).await;
+        emit(&state, "node.config.updated", json!({})).await;
+        emit(&state, "transfer.completed", json!({ "transfer_id": "t" })).await;
+
+        let listed = json_body(
+            send(
+                &router,
+                Request::get("/v1/notifications").body(Body::empty()).unwrap(),
+            )
+            .await,
+        )
+        .await;
+        let items = listed["items"].as_array().unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0]["event_type"], "job.status.changed");
+
+        let acked = send(
+            &router,
+            Request::post("/v1/notifications/ack")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(format!(r#"{{"up_to_seq":{}}}"#, items[0]["seq"])))
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(acked.status(), StatusCode::OK);
+
+        let remaining = json_body(
+            send(
+                &router,
+                Request::get("/v1/notifications").body(Body::empty()).unwrap(),
+            )
+            .await,
+        )
+        .await;
+        let items = remaining["items"].as_array().unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0]["event_type"], "transfer.completed");
+    }
+
+    #[cfg(feature = "sse")]
+    #[tokio::test]
+    async fn notification_stream_replays_then_tails() {
+        let state = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        let router = build_router(state.clone());
+        emit(&state, "job.status.changed", json!({ "job_id": "replayed" })).await;
+
+        let response = send(
+            &router,
+            Request::get("/v1/notifications/stream")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        let mut body = response.into_body().into_data_stream();
+        let mut next_frame = async || {
+            let frame = tokio::time::timeout(Duration::from_secs(2), body.next())
+                .await
+                .expect("frame before timeout")
+                .expect("open stream")
+                .expect("frame");
+            String::from_utf8(frame.to_vec()).unwrap()
+        };
+
+        assert!(next_frame().await.contains("replayed"));
+        emit(&state, "job.status.changed", json!({ "job_id": "live" })).await;
+        assert!(next_frame().await.contains("live"));
+    }
+
+    #[cfg(feature = "sse")]
+    #[tokio::test]
+    async fn notification_stream_collapses_live_statuses_per_job() {
+        let state = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        state.node_config.write().await.event_coalescing.window_ms = 300;
+        let router = build_router(state.clone());
+        let response = send(
+            &router,
+            Request::get("/v1/notifications/stream")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        let mut body = response.into_body().into_data_stream();
+
+        let status = |job_id: &str, status: &str| json!({ "job_id": job_id, "status": status });
+        let mut emitted = vec![status("job-a", "queued"), status("job-b", "queued")];
+        emitted.extend((0..5).map(|attempt| {
+            json!({ "job_id": "job-a", "status": "dispatched", "attempt": attempt })
+        }));
+        emitted.push(status("job-a", "success"));
+        for data in &emitted {
+            state.storage.append_feed_event("job.status.changed", data).await.unwrap();
+        }
+        super::publish(&state).await;
+
+        let mut delivered = Vec::new();
+        let mut text = String::new();
+        while delivered.len() < 3 {
+            let frame = tokio::time::timeout(Duration::from_secs(2), body.next())
+                .await
+                .expect("frame before timeout")
+                .expect("open stream")
+                .expect("frame");
+            text.push_str(std::str::from_utf8(&frame).unwrap());
+            while let Some((event, rest)) = text.split_once("\n\n") {
+                let data = event.lines().find_map(|line| line.strip_prefix("data: "));
+                delivered.push(serde_json::from_str::<Value>(data.unwrap()).unwrap());
+                text = rest.to_string();
+            }
+        }
+        assert_eq!(
+            delivered,
+            [
+                status("job-b", "queued"),
+                json!({ "job_id": "job-a", "status": "dispatched", "attempt": 4 }),
+                status("job-a", "success"),
+            ]
+        );
+
+        // The inbox keeps every notification.
+        let listed = json_body(
+            send(
+                &router,
+                Request::get("/v1/notifications").body(Body::empty()).unwrap(),
+            )
+            .await,
+        )
+        .await;
+        assert_eq!(listed["items"].as_array().unwrap().len(), emitted.len());
+        let (_, subscribers) = get_json(&router, "/v1/events/subscribers").await;
+        let subscriber = &subscribers["subscribers"][0];
+        assert_eq!(subscriber["kind"], NOTIFICATION_SUBSCRIBER);
+        assert_eq!(
+            (subscriber["delivered"].as_u64(), subscriber["collapsed"].as_u64()),
+            (Some(3), Some(5))
+        );
+    }
+
+    #[tokio::test]
+    async fn notification_backlog_cap_leaves_overflow_marker() {
+        let state = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        state.node_config.write().await.notifications.max_unacked = 2;
+        let router = build_router(state.clone());
+        let list = || Request::get("/v1/notifications").body(Body::empty()).unwrap();
+        send(&router, list()).await;
+
+        for idx in 0..5 {
+            emit(&state, "job.status.changed", json!({ "job_id": idx })).await;
+        }
+
+        let listed = json_body(send(&router, list()).await).await;
+        let items = listed["items"].as_array().unwrap();
+        assert_eq!(items.len(), 3);
+        assert_eq!(items[0]["event_type"], "notifications.overflowed");
+        assert_eq!(items[0]["data"]["dropped"], 3);
+        assert_eq!(items[1]["data"]["job_id"], 3);
+    }
+
+    // State changes reach subscribers from the feed rows their transactions wrote; the names
+    // and payloads are the ones clients already parse.
+    #[tokio::test]
+    async fn state_change_events_keep_their_names_and_payloads() {
+        let state = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        let router = build_router(state.clone());
+        let mut events = state.sse_bus.subscribe();
+
+        let job_id = unworked_job(&state, "evt-1").await;
+        let response = send(&router, cancel_request(&job_id)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let soon = (chrono::Utc::now() + chrono::Duration::minutes(30)).to_rfc3339();
+        let body = json!({ "identity_hash": PARTNER, "status": "pending", "expires_at": soon });
+        let added = send(&router, add_allowlist_request(body, "unused")).await;
+        let expires_at = json_body(added).await["entry"]["expires_at"].clone();
+        let approve = Request::post(format!("/v1/security/allowlist/{PARTNER}/approve"))
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(send(&router, approve).await.status(), StatusCode::OK);
+        let delete = Request::delete(format!("/v1/security/allowlist/{PARTNER}"))
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(send(&router, delete).await.status(), StatusCode::NO_CONTENT);
+
+        let mut pushed = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            pushed.push(json!([event.event_type, event.data]));
+        }
+        assert_eq!(
+            json!(pushed),
+            json!([
+                ["job.status.changed", { "job_id": job_id, "status": "queued" }],
+                ["job.status.changed", { "job_id": job_id, "status": "cancelled" }],
+                [
+                    "security.allowlist.pending",
+                    {
+                        "identity_hash": PARTNER,
+                        "role": "peer",
+                        "status": "pending",
+                        "expires_at": expires_at,
+                    }
+                ],
+                [
+                    "security.allowlist.approved",
+                    { "identity_hash": PARTNER, "role": "peer", "expires_at": expires_at }
+                ],
+                [
+                    "security.allowlist.updated",
+                    { "identity_hash": PARTNER, "deleted": true }
+                ],
+            ])
+        );
+        let feed = state.storage.list_feed_events(0, 100).await.unwrap();
+        let recorded: Vec<Value> = feed
+            .into_iter()
+            .map(|event| json!([event.event_type, event.data]))
+            .collect();
+        assert_eq!(recorded, pushed);
+    }
+
+    const PARTNER: &str = "ba57ba57ba57ba57ba57ba57ba57ba57";
+
+    fn add_allowlist_request(body: serde_json::Value, token: &str) -> Request<Body> {
+        Request::post("/v1/security/allowlist")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::AUTHORIZATION, format!("Bearer {token}"))
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn pending_allowlist_entries_need_admin_approval() {
+        let mut state = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        state.require_bearer = true;
+        {
+            let mut config = state.node_config.write().await;
+            config.http_auth_token = Some("admin-secret".to_string());
+            config.api_tokens = vec![ApiToken {
+                label: "field".to_string(),
+                token: "field-secret".to_string(),
+            }];
+        }
+        let mut events = state.sse_bus.subscribe();
+        let router = build_router(state.clone());
+
+        let added = send(
+            &router,
+            add_allowlist_request(
+                json!({ "identity_hash": PARTNER, "role": "relay" }),
+                "field-secret",
+            ),
+        )
+        .await;
+        assert_eq!(added.status(), StatusCode::CREATED);
+        assert_eq!(json_body(added).await["entry"]["status"], "pending");
+        assert_eq!(events.try_recv().unwrap().event_type, "security.allowlist.pending");
+        assert!(!state
+            .storage
+            .is_allowlisted(&PARTNER.parse().unwrap(), chrono::Utc::now())
+            .await
+            .unwrap());
+
+        let approve = |token: &str| {
+            Request::post(format!("/v1/security/allowlist/{PARTNER}/approve"))
+                .header(header::AUTHORIZATION, format!("Bearer {token}"))
+                .body(Body::empty())
+                .unwrap()
+        };
+        let forbidden = send(&router, approve("field-secret")).await;
+        assert_eq!(forbidden.status(), StatusCode::FORBIDDEN);
+
+        let approved = send(&router, approve("admin-secret")).await;
+        assert_eq!(approved.status(), StatusCode::OK);
+        let entry = json_body(approved).await;
+        assert_eq!(entry["status"], "active");
+        assert_eq!(entry["role"], "relay");
+        assert!(entry["approved_at"].is_string());
+        assert_eq!(events.try_recv().unwrap().event_type, "security.allowlist.approved");
+        assert!(state
+            .storage
+            .is_allowlisted(&PARTNER.parse().unwrap(), chrono::Utc::now())
+            .await
+            .unwrap());
+
+        let again = send(&router, approve("admin-secret")).await;
+        assert_eq!(again.status(), StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn allowlist_filters_by_status_and_expiry_window() {
+        let state = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        let router = build_router(state.clone());
+        let soon = (chrono::Utc::now() + chrono::Duration::minutes(30)).to_rfc3339();
+        let later = (chrono::Utc::now() + chrono::Duration::days(30)).to_rfc3339();
+        const LONG: &str = "10000000000000000000000000000001";
+        const PERMANENT: &str = "20000000000000000000000000000002";
+        const QUEUED: &str = "30000000000000000000000000000003";
+        const SHORT: &str = "40000000000000000000000000000004";
+        for body in [
+            json!({ "identity_hash": PERMANENT }),
+            json!({ "identity_hash": SHORT, "expires_at": soon }),
+            json!({ "identity_hash": LONG, "expires_at": later }),
+            json!({ "identity_hash": QUEUED, "status": "pending", "expires_at": soon }),
+        ] {
+            let added = send(&router, add_allowlist_request(body, "unused")).await;
+            assert_eq!(added.status(), StatusCode::CREATED);
+        }
+
+        let identities = |uri: &'static str| {
+            let router = router.clone();
+            async move {
+                let response =
+                    send(&router, Request::get(uri).body(Body::empty()).unwrap()).await;
+                assert_eq!(response.status(), StatusCode::OK);
+                json_body(response).await["identities"].clone()
+            }
+        };
+        assert_eq!(
+            identities("/v1/security/allowlist?status=active").await,
+            json!([LONG, PERMANENT, SHORT])
+        );
+        assert_eq!(
+            identities("/v1/security/allowlist?expiring_within_secs=3600").await,
+            json!([QUEUED, SHORT])
+        );
+        assert_eq!(
+            identities("/v1/security/allowlist?status=active&expiring_within_secs=3600").await,
+            json!([SHORT])
+        );
+
+        let invalid = send(
+            &router,
+            Request::get("/v1/security/allowlist?status=bogus")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(invalid.status(), StatusCode::BAD_REQUEST);
+
+        let expired_at = chrono::Utc::now() + chrono::Duration::hours(2);
+        assert_eq!(state.storage.expire_allowlist(expired_at).await.unwrap().len(), 2);
+        assert_eq!(
+            identities("/v1/security/allowlist?status=expired").await,
+            json!([QUEUED, SHORT])
+        );
+    }
+
+    #[tokio::test]
+    async fn invalid_identity_hashes_are_refused_with_their_defect() {
+        let state = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        let router = build_router(state.clone());
+        let refused = |response: axum::response::Response, defect: &'static str| async move {
+            assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+            let body = json_body(response).await;
+            assert_eq!(body["error"], "invalid_identity_hash");
+            assert_eq!(body["defect"], defect);
+        };
+
+        for (identity, defect) in [
+            ("  ", "empty"),
+            ("Bob's laptop", "non_hex"),
+            ("a1b2c3", "wrong_length"),
+        ] {
+            let body = json!({ "identity_hash": identity });
+            refused(send(&router, add_allowlist_request(body, "unused")).await, defect).await;
+        }
+        let payload = json!({ "uid": "evt-1", "destination_identity": "a1b2-c3d4" });
+        refused(send(&router, command_request("event.create", payload)).await, "non_hex").await;
+        let handshake = Request::post("/v1/peers/local-node/handshake")
+            .body(Body::empty())
+            .unwrap();
+        refused(send(&router, handshake).await, "non_hex").await;
+        assert!(state.storage.list_allowlist().await.unwrap().is_empty());
+
+        // The escape hatch stores whatever a deployment names its peers.
+        state.node_config.write().await.identity_validation = IdentityValidation::Lenient;
+        let body = json!({ "identity_hash": "Bob's laptop" });
+        let added = send(&router, add_allowlist_request(body, "unused")).await;
+        assert_eq!(added.status(), StatusCode::CREATED);
+        assert_eq!(state.storage.list_allowlist().await.unwrap(), ["Bob's laptop"]);
+    }
+
+    #[tokio::test]
+    async fn allowlist_matches_identities_regardless_of_case() {
+        let state = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        let router = build_router(state.clone());
+        let shouted = PARTNER.to_uppercase();
+        let body = json!({ "identity_hash": format!(" {shouted} ") });
+        let added = send(&router, add_allowlist_request(body, "unused")).await;
+        assert_eq!(added.status(), StatusCode::CREATED);
+        assert_eq!(json_body(added).await["entry"]["identity_hash"], PARTNER);
+
+        let again = json!({ "identity_hash": PARTNER, "note": "same peer" });
+        let added = send(&router, add_allowlist_request(again, "unused")).await;
+        assert_eq!(added.status(), StatusCode::CREATED);
+        assert_eq!(state.storage.list_allowlist().await.unwrap(), [PARTNER]);
+        let lookup: IdentityHash = shouted.parse().unwrap();
+        assert!(state
+            .storage
+            .is_allowlisted(&lookup, chrono::Utc::now())
+            .await
+            .unwrap());
+
+        let delete = Request::delete(format!("/v1/security/allowlist/{shouted}"))
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(send(&router, delete).await.status(), StatusCode::NO_CONTENT);
+        assert!(state.storage.list_allowlist().await.unwrap().is_empty());
+    }
+
+    // Submits jobs one at a time so the bridge sees a deterministic call order.
+    async fn run_scripted_jobs(bridge: Arc<dyn RpcMeshBridge>) -> Vec<(String, String, String)> {
+        let state = test_state(bridge).await;
+        let router = build_router(state.clone());
+        let mut outcomes = Vec::new();
+        for (idx, operation) in ["event.create", "event.update", "event.create", "event.delete"]
+            .iter()
+            .cycle()
+            .take(12)
+            .enumerate()
+        {
+            let response = send(
+                &router,
+                Request::post(format!("/v1/jobs/commands/{operation}"))
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(format!(r#"{{"uid":"evt-{idx}"}}"#)))
+                    .unwrap(),
+            )
+            .await;
+            let job_id = json_body(response).await["job_id"]
+                .as_str()
+                .unwrap()
+                .to_string();
+            let mut job = state.storage.get_job(&job_id).await.unwrap().unwrap();
+            for _ in 0..200 {
+                if job.status == "success" || job.status == "failed" {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(5)).await;
+                job = state.storage.get_job(&job_id).await.unwrap().unwrap();
+            }
+            let result = state
+                .storage
+                .get_job_result(&job_id)
+                .await
+                .unwrap()
+                .map(|result| result.result_json)
+                .unwrap_or_default();
+            outcomes.push((job.operation, job.status, result));
+        }
+        outcomes
+    }
+
+    #[tokio::test]
+    async fn replayed_recording_reproduces_job_records() {
+        let path = scratch_dir().join("bridge.rec");
+        let lossy = SimulatedMeshBridge::new(
+            Arc::new(InMemoryRpcMeshBridge::new(true, true)),
+            SimulationProfile {
+                seed: 1622,
+                loss: LossProfile {
+                    send_command: 0.3,
+                    ..LossProfile::default()
+                },
+                ..SimulationProfile::default()
+            },
+        );
+        let recorder = Arc::new(RecordingBridge::start(Arc::new(lossy), &path).unwrap());
+        let original = run_scripted_jobs(recorder.clone()).await;
+        recorder.flush().await;
+        assert!(original.iter().any(|(_, status, _)| status == "failed"));
+        assert!(original.iter().any(|(_, status, _)| status == "success"));
+
+        let replay = Arc::new(ReplayBridge::new(
+            read_recording(&path).unwrap(),
+            ReplayMatching::Strict,
+        ));
+        let replayed = run_scripted_jobs(replay.clone()).await;
+        assert_eq!(replayed, original);
+        assert_eq!(replay.remaining(), 0);
+    }
+
+    #[tokio::test]
+    async fn health_history_and_status_report_recent_availability() {
+        let state = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        let router = build_router(state.clone());
+        let now = chrono::Utc::now();
+        for (minutes_ago, bridge_ok) in [(50, true), (40, false), (30, false), (20, true)] {
+            let at = now - chrono::Duration::minutes(minutes_ago);
+            state
+                .storage
+                .insert_health_sample(&HealthSample::raw(at, (bridge_ok, 12), (true, 1), 0))
+                .await
+                .unwrap();
+        }
+
+        let history = send(
+            &router,
+            Request::get("/v1/node/health/history?window=1h&resolution=10m")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(history.status(), StatusCode::OK);
+        let history = json_body(history).await;
+        assert_eq!(history["availability"]["bridge_pct"], json!(50.0));
+        assert_eq!(history["outages"].as_array().unwrap().len(), 1);
+        assert_eq!(history["outages"][0]["duration_secs"], json!(1200));
+
+        let status = send(
+            &router,
+            Request::get("/v1/node/status").body(Body::empty()).unwrap(),
+        )
+        .await;
+        let status = json_body(status).await;
+        assert_eq!(status["availability_last_hour"]["storage_pct"], json!(100.0));
+
+        let invalid = send(
+            &router,
+            Request::get("/v1/node/health/history?window=1h&resolution=2h")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(invalid.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn operation_defaults_are_validated_and_recorded_on_jobs() {
+        let router = test_router().await;
+        let mut invalid = test_node_config();
+        invalid.operation_defaults.insert(
+            "telemetry.*".to_string(),
+            OperationDefaults {
+                destination_identity: Some("hub".to_string()),
+                ..OperationDefaults::default()
+            },
+        );
+        let put_config = |config: NodeConfig| {
+            Request::put("/v1/node/config")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(serde_json::to_vec(&config).unwrap()))
+                .unwrap()
+        };
+        let rejected = send(&router, put_config(invalid)).await;
+        assert_eq!(rejected.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(json_body(rejected).await["error"], "invalid_node_config");
+
+        let mut valid = test_node_config();
+        valid.operation_defaults.insert(
+            "telemetry.*".to_string(),
+            OperationDefaults {
+                destination_identity: Some("0123456789abcdef0123456789abcdef".to_string()),
+                ttl_ms: Some(30_000),
+                ..OperationDefaults::default()
+            },
+        );
+        assert_eq!(send(&router, put_config(valid)).await.status(), StatusCode::OK);
+
+        let submitted = send(
+            &router,
+            Request::post("/v1/jobs/commands/telemetry.report")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(r#"{"ttl_ms":1000}"#))
+                .unwrap(),
+        )
+        .await;
+        let job_id = json_body(submitted).await["job_id"].as_str().unwrap().to_string();
+        let job = send(
+            &router,
+            Request::get(format!("/v1/jobs/{job_id}")).body(Body::empty()).unwrap(),
+        )
+        .await;
+        let dispatch: serde_json::Value =
+            serde_json::from_str(json_body(job).await["dispatch_json"].as_str().unwrap()).unwrap();
+        assert_eq!(dispatch["destination_identity"], "0123456789abcdef0123456789abcdef");
+        assert_eq!(dispatch["ttl_ms"], 1000);
+        assert_eq!(dispatch["source_identity"], "local-node");
+        assert_eq!(dispatch["matched_pattern"], "telemetry.*");
+        assert_eq!(dispatch["defaulted"], json!(["destination_identity"]));
+    }
+
+    async fn contract_router() -> Router {
+        let base = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        build_router(AppState::new(
+            base.storage,
+            Arc::new(InMemoryRpcMeshBridge::new(true, true)),
+            test_node_config(),
+            CONTRACT.to_string(),
+            false,
+        ))
+    }
+
+    const CONTRACT: &str = include_str!("../../../contracts/retasyncapi-v1.asyncapi.yaml");
+
+    #[tokio::test]
+    async fn every_operation_example_validates_against_its_schema() {
+        let router = contract_router().await;
+        let listed = send(
+            &router,
+            Request::get("/v1/contracts/operations").body(Body::empty()).unwrap(),
+        )
+        .await;
+        assert_eq!(listed.status(), StatusCode::OK);
+        let operations = json_body(listed).await["operations"].as_array().unwrap().clone();
+        let registry = super::ContractRegistry::from_yaml(CONTRACT).unwrap();
+        assert_eq!(
+            operations.len(),
+            registry.commands().count() + registry.events().count()
+        );
+
+        for listed in operations {
+            let operation = listed["operation"].as_str().unwrap();
+            let (envelope_schema, channel) = if listed["kind"] == "command" {
+                ("MeshCommandEnvelope", registry.command_channel(operation))
+            } else {
+                ("MeshEventEnvelope", registry.event_channel(operation))
+            };
+            assert_eq!(listed["channel"], channel);
+            assert_eq!(listed["typed_example"], listed["payload_schema"].is_string());
+
+            let path = format!("/v1/contracts/operations/{operation}/example");
+            let payload = send(&router, Request::get(&path).body(Body::empty()).unwrap()).await;
+            assert_eq!(payload.status(), StatusCode::OK, "{operation}");
+            let payload = json_body(payload).await;
+            if let Some(schema) = listed["payload_schema"].as_str() {
+                let violations = schema_violations(CONTRACT, schema, &payload).unwrap();
+                assert!(violations.is_empty(), "{operation}: {violations:?}");
+            }
+
+            let envelope = send(
+                &router,
+                Request::get(format!("{path}?envelope=true")).body(Body::empty()).unwrap(),
+            )
+            .await;
+            let envelope = json_body(envelope).await;
+            assert_eq!(envelope["payload"], payload);
+            assert_eq!(envelope["source_identity"], "local-node");
+            let violations = schema_violations(CONTRACT, envelope_schema, &envelope).unwrap();
+            assert!(violations.is_empty(), "{operation}: {violations:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn unknown_operation_examples_suggest_near_matches() {
+        let router = contract_router().await;
+        let missing = send(
+            &router,
+            Request::get("/v1/contracts/operations/emergency_action_message.pt/example")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+        let body = json_body(missing).await;
+        assert_eq!(body["error"], "unknown_operation");
+        assert_eq!(body["suggestions"][0], "emergency_action_message.put");
+
+        let unrelated = send(
+            &router,
+            Request::get("/v1/contracts/operations/zzz/example").body(Body::empty()).unwrap(),
+        )
+        .await;
+        assert_eq!(json_body(unrelated).await["suggestions"], json!([]));
+    }
+
+    async fn deprecation_router(sunset: &str, allow_sunset_operations: bool) -> Router {
+        let base = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        let mut config = test_node_config();
+        config.allow_sunset_operations = allow_sunset_operations;
+        let contract = format!(
+            "asyncapi: 3.0.0\nx-retasync:\n  operations:\n    commands: [event.stream]\n    \
+             deprecated:\n      event.stream:\n        x-retasync-sunset: \"{sunset}\"\n"
+        );
+        build_router(AppState::new(
+            base.storage,
+            Arc::new(InMemoryRpcMeshBridge::new(true, true)),
+            config,
+            contract,
+            false,
+        ))
+    }
+
+    fn stream_request() -> Request<Body> {
+        Request::post("/v1/jobs/commands/event.stream")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from("{}"))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn deprecated_operations_warn_before_sunset() {
+        let router = deprecation_router("2999-01-01", false).await;
+        let accepted = send(&router, stream_request()).await;
+        assert_eq!(accepted.status(), StatusCode::ACCEPTED);
+        assert_eq!(accepted.headers()["deprecation"], "true");
+        assert_eq!(accepted.headers()["sunset"], "Tue, 01 Jan 2999 00:00:00 GMT");
+
+        let plain = send(
+            &router,
+            Request::post("/v2/jobs/commands/event.create")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from("{}"))
+                .unwrap(),
+        )
+        .await;
+        assert!(plain.headers().get("deprecation").is_none());
+
+        let logs = send(&router, Request::get("/v1/logs").body(Body::empty()).unwrap()).await;
+        assert!(json_body(logs).await.to_string().contains("deprecated_operation_used"));
+        let usage = send(
+            &router,
+            Request::get("/v1/contracts/deprecations").body(Body::empty()).unwrap(),
+        )
+        .await;
+        let usage = json_body(usage).await;
+        assert_eq!(usage["operations"][0]["operation"], "event.stream");
+        assert_eq!(usage["operations"][0]["used"], 1);
+        assert_eq!(usage["operations"][0]["sunset_passed"], false);
+    }
+
+    #[tokio::test]
+    async fn log_filters_compare_instants_across_offsets() {
+        let state = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        state.log_buffer.write().await.extend(
+            ["2026-03-01T08:00:00.000Z", "2026-03-01T09:00:00.000Z", "2026-03-01T10:00:00.000Z"]
+                .into_iter()
+                .enumerate()
+                .map(|(index, at)| LogLine {
+                    timestamp: CanonicalTimestamp::parse(at).unwrap(),
+                    level: "info".to_string(),
+                    message: format!("line {index}"),
+                }),
+        );
+        let router = build_router(state);
+
+        // 10:30+02:00 is 08:30Z and 11:00+01:00 is 10:00Z, though both sort after 09:00Z as text.
+        let (status, body) = get_json(
+            &router,
+            "/v1/logs?since=2026-03-01T10:30:00%2B02:00&until=2026-03-01T11:00:00%2B01:00",
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let messages: Vec<_> = body["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item["message"].as_str().unwrap())
+            .collect();
+        assert_eq!(messages, ["line 1"]);
+        assert_eq!(body["items"][0]["timestamp"], "2026-03-01T09:00:00.000Z");
+
+        let (status, body) = get_json(&router, "/v1/logs?until=last-tuesday").await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["error"], "invalid_timestamp");
+        assert_eq!(body["parameter"], "until");
+    }
+
+    #[tokio::test]
+    async fn sunset_operations_are_gone_unless_allowed() {
+        let router = deprecation_router("2000-01-01", false).await;
+        let rejected = send(&router, stream_request()).await;
+        assert_eq!(rejected.status(), StatusCode::GONE);
+        assert_eq!(json_body(rejected).await["error"], "operation_sunset");
+
+        let router = deprecation_router("2000-01-01", true).await;
+        let accepted = send(&router, stream_request()).await;
+        assert_eq!(accepted.status(), StatusCode::ACCEPTED);
+        assert_eq!(accepted.headers()["deprecation"], "true");
+    }
+    #[cfg(feature = "transfers")]
+    fn attachment(file_name: &str, content: &[u8]) -> serde_json::Value {
+        json!({
+            "file_name": file_name,
+            "media_type": "image/png",
+            "content_base64": STANDARD.encode(content),
+        })
+    }
+
+    #[cfg(feature = "transfers")]
+    fn attachment_request(attachments: serde_json::Value) -> Request<Body> {
+        Request::post("/v1/jobs/commands/event.create")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(
+                json!({ "uid": "evt-1", "_attachments": attachments }).to_string(),
+            ))
+            .unwrap()
+    }
+
+    async fn settled_job(router: &Router, response: axum::response::Response) -> serde_json::Value {
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        let job_id = json_body(response).await["job_id"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        for _ in 0..200 {
+            let job = json_body(
+                send(
+                    router,
+                    Request::get(format!("/v1/jobs/{job_id}"))
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await,
+            )
+            .await;
+            if matches!(job["status"].as_str(), Some("success" | "partial_failure" | "failed")) {
+                return job;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        panic!("job {job_id} never settled");
+    }
+
+    #[cfg(feature = "transfers")]
+    async fn recorded_attachment_run(
+        attachments: serde_json::Value,
+    ) -> (serde_json::Value, Vec<BridgeRecord>) {
+        let path = scratch_dir().join("bridge.rec");
+        let recorder = Arc::new(
+            RecordingBridge::start(Arc::new(InMemoryRpcMeshBridge::new(true, true)), &path)
+                .unwrap(),
+        );
+        let router = build_router(test_state(recorder.clone()).await);
+        let job = settled_job(&router, send(&router, attachment_request(attachments)).await).await;
+        recorder.flush().await;
+        (job, read_recording(&path).unwrap())
+    }
+
+    #[cfg(feature = "transfers")]
+    #[tokio::test]
+    async fn command_attachments_are_sent_as_linked_transfers() {
+        let content = b"map tile bytes";
+        let (job, records) =
+            recorded_attachment_run(json!([attachment("tile.png", content)])).await;
+        assert_eq!(job["status"], "success");
+        let attachments = job["attachments"].as_array().unwrap();
+        assert_eq!(attachments.len(), 1);
+        assert_eq!(attachments[0]["status"], "success");
+        assert_eq!(attachments[0]["job_id"], job["job_id"]);
+        assert_eq!(attachments[0]["progress"]["bytes_sent"], content.len());
+
+        let methods: Vec<&str> = records.iter().map(|record| record.method.as_str()).collect();
+        assert_eq!(methods, ["send_command", "start_transfer"]);
+        let command: MeshCommandEnvelope<serde_json::Value> =
+            decode_canonical(&records[0].request).unwrap();
+        assert_eq!(
+            command.payload["_attachments"],
+            json!([{
+                "transfer_id": attachments[0]["transfer_id"],
+                "file_name": "tile.png",
+                "sha256": format!("{:x}", Sha256::digest(content)),
+                "size": content.len(),
+            }])
+        );
+        assert_eq!(command.payload["uid"], "evt-1");
+    }
+
+    #[cfg(feature = "transfers")]
+    #[tokio::test]
+    async fn failed_attachment_transfer_leaves_job_partially_failed() {
+        let attachments = json!([attachment("a.png", b"first"), attachment("b.png", b"second")]);
+        let (_, mut records) = recorded_attachment_run(attachments.clone()).await;
+        records[2].outcome = RecordedOutcome::Err {
+            kind: "send_failed".to_string(),
+            message: "link dropped".to_string(),
+        };
+        let replay = Arc::new(ReplayBridge::new(records, ReplayMatching::Strict));
+        let router = build_router(test_state(replay).await);
+
+        let job = settled_job(&router, send(&router, attachment_request(attachments)).await).await;
+        assert_eq!(job["status"], "partial_failure");
+        assert_eq!(job["failure_reason"], "1 of 2 attachment transfers failed");
+        let statuses: Vec<&str> = job["attachments"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|transfer| transfer["status"].as_str().unwrap())
+            .collect();
+        assert_eq!(statuses, ["success", "failed"]);
+        assert_eq!(
+            job["attachments"][1]["failure_reason"],
+            "bridge send failure: link dropped"
+        );
+    }
+
+    fn command_request(operation: &str, payload: serde_json::Value) -> Request<Body> {
+        Request::post(format!("/v1/jobs/commands/{operation}"))
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(payload.to_string()))
+            .unwrap()
+    }
+
+    fn delivery_of(job: &serde_json::Value) -> (serde_json::Value, serde_json::Value) {
+        let dispatch: serde_json::Value =
+            serde_json::from_str(job["dispatch_json"].as_str().unwrap()).unwrap();
+        let delivery = &dispatch["delivery"];
+        (delivery["source"].clone(), delivery["max_attempts"].clone())
+    }
+
+    async fn settled_command(
+        router: &Router,
+        operation: &str,
+        payload: serde_json::Value,
+    ) -> serde_json::Value {
+        settled_job(router, send(router, command_request(operation, payload)).await).await
+    }
+
+    // (outcome, error variant, escalated) of each attempt in a job's log, oldest first.
+    async fn attempt_log(router: &Router, job_id: &Value) -> Vec<(String, Value, bool)> {
+        let job_id = job_id.as_str().unwrap();
+        let (status, body) = get_json(router, &format!("/v1/jobs/{job_id}/attempts")).await;
+        assert_eq!(status, StatusCode::OK);
+        let attempts = body["attempts"].as_array().unwrap();
+        for (number, attempt) in attempts.iter().enumerate() {
+            assert_eq!(attempt["job_id"], job_id);
+            assert_eq!(attempt["attempt"], number + 1);
+        }
+        attempts
+            .iter()
+            .map(|attempt| {
+                (
+                    attempt["outcome"].as_str().unwrap().to_string(),
+                    attempt["error_variant"].clone(),
+                    attempt["escalated"].as_bool().unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn contract_delivery_policies_drive_retries() {
+        let path = scratch_dir().join("bridge.rec");
+        let recorder = Arc::new(
+            RecordingBridge::start(Arc::new(InMemoryRpcMeshBridge::new(true, true)), &path)
+                .unwrap(),
+        );
+        let router = build_router(test_state(recorder.clone()).await);
+        settled_command(&router, "event.create", json!({})).await;
+        recorder.flush().await;
+        let delivered = read_recording(&path).unwrap().remove(0);
+        let mut dropped = delivered.clone();
+        dropped.outcome = RecordedOutcome::Err {
+            kind: "send_failed".to_string(),
+            message: "link dropped".to_string(),
+        };
+
+        let replay = Arc::new(ReplayBridge::new(vec![dropped, delivered], ReplayMatching::Lenient));
+        let base = test_state(replay.clone()).await;
+        let contract = "asyncapi: 3.0.0\nx-retasync:\n  operations:\n    \
+                        commands: [event.create, event.delete, event.update]\n    \
+                        x-retasync-delivery:\n      \
+                        event.create: { max_attempts: 3, backoff_ms: 1 }\n      \
+                        event.delete: { idempotent: false }\n";
+        let mut config = test_node_config();
+        config.delivery.max_attempts = 2;
+        config.delivery.backoff_ms = 1;
+        let router = build_router(AppState::new(
+            base.storage,
+            replay.clone(),
+            config,
+            contract.to_string(),
+            false,
+        ));
+
+        let job = settled_command(&router, "event.create", json!({})).await;
+        assert_eq!(job["status"], "success");
+        assert_eq!(replay.remaining(), 0);
+        assert_eq!(delivery_of(&job), (json!("contract"), json!(3)));
+        assert_eq!(
+            attempt_log(&router, &job["job_id"]).await,
+            [
+                ("retried".to_string(), json!("send_failed"), false),
+                ("delivered".to_string(), Value::Null, false),
+            ]
+        );
+        let job_id = job["job_id"].as_str().unwrap();
+        let (_, detail) =
+            get_json(&router, &format!("/v1/jobs/{job_id}?include=dispatch_attempts")).await;
+        let summary = &detail["dispatch_attempts"];
+        assert_eq!(summary["attempts"], 2);
+        assert_eq!(summary["last_error"]["attempt"], 1);
+        assert_eq!(summary["last_error"]["variant"], "send_failed");
+        assert_eq!(summary["last_error"]["detail"], "link dropped");
+        let (_, v2) = get_json(&router, &format!("/v2/jobs/{job_id}")).await;
+        assert_eq!(&v2["data"]["dispatch_attempts"], summary);
+        let (_, status) = get_json(&router, "/v1/node/status").await;
+        let latency = &status["dispatch_latency"]["event.create"];
+        assert_eq!((&latency["attempts"], &latency["errors"]), (&json!(2), &json!(1)));
+
+        let refused = send(
+            &router,
+            command_request("event.delete", json!({ "_delivery": { "max_attempts": 2 } })),
+        )
+        .await;
+        assert_eq!(refused.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(json_body(refused).await["error"], "retry_not_allowed_for_operation");
+
+        let fallback = settled_command(&router, "event.update", json!({})).await;
+        assert_eq!(delivery_of(&fallback), (json!("config"), json!(2)));
+
+        let overridden = json!({ "uid": "evt-9", "_delivery": { "max_attempts": 4 } });
+        let overridden = settled_command(&router, "event.update", overridden).await;
+        assert_eq!(delivery_of(&overridden), (json!("client"), json!(4)));
+        let payload: serde_json::Value =
+            serde_json::from_str(overridden["payload_json"].as_str().unwrap()).unwrap();
+        assert_eq!(payload, json!({ "uid": "evt-9" }));
+    }
+
+    #[cfg(feature = "transfers")]
+    #[tokio::test]
+    async fn oversized_attachments_are_rejected_at_submission() {
+        let state = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        {
+            let mut config = state.node_config.write().await;
+            config.attachments.max_attachment_bytes = 8;
+            config.attachments.max_job_bytes = 12;
+        }
+        let router = build_router(state.clone());
+
+        let too_large = send(
+            &router,
+            attachment_request(json!([attachment("big.png", b"123456789")])),
+        )
+        .await;
+        assert_eq!(too_large.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let body = json_body(too_large).await;
+        assert_eq!(body["error"], "attachment_too_large");
+        assert_eq!(body["file_name"], "big.png");
+
+        let pair = json!([attachment("a.png", b"1234567"), attachment("b.png", b"1234567")]);
+        let job_too_large = send(&router, attachment_request(pair)).await;
+        assert_eq!(job_too_large.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(json_body(job_too_large).await["error"], "attachments_too_large");
+
+        let malformed = send(&router, attachment_request(json!({ "file_name": "x" }))).await;
+        assert_eq!(malformed.status(), StatusCode::BAD_REQUEST);
+        assert!(state.storage.list_transfers(None, 10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn oversized_envelopes_fail_before_reaching_the_bridge() {
+        let replay = Arc::new(ReplayBridge::new(Vec::new(), ReplayMatching::Strict));
+        let state = test_state(replay).await;
+        state.node_config.write().await.max_lxmf_bytes = 512;
+        let router = build_router(state);
+
+        let request = Request::post("/v1/jobs/commands/event.create")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(
+                json!({ "uid": "evt-1", "notes": "x".repeat(1024) }).to_string(),
+            ))
+            .unwrap();
+        let job = settled_job(&router, send(&router, request).await).await;
+        assert_eq!(job["status"], "failed");
+        let reason = job["failure_reason"].as_str().unwrap();
+        assert!(reason.starts_with("envelope_too_large: "), "{reason}");
+        assert!(reason.contains("lxmf limit of 512 bytes"), "{reason}");
+        assert!(reason.ends_with("mitigation: compression"), "{reason}");
+
+        let status = json_body(
+            send(
+                &router,
+                Request::get("/v1/node/status").body(Body::empty()).unwrap(),
+            )
+            .await,
+        )
+        .await;
+        assert_eq!(status["oversize_rejections"]["event.create"], 1);
+    }
+
+    #[cfg(feature = "transfers")]
+    #[tokio::test]
+    async fn capabilities_follow_runtime_config() {
+        let router = test_router().await;
+        let get_capabilities = |etag: Option<&str>| {
+            let mut request = Request::get("/v1/node/capabilities");
+            if let Some(etag) = etag {
+                request = request.header(header::IF_NONE_MATCH, etag);
+            }
+            request.body(Body::empty()).unwrap()
+        };
+
+        let before = send(&router, get_capabilities(None)).await;
+        let etag = etag_of(&before);
+        let body = json_body(before).await;
+        assert_eq!(body["api_version"], "v1");
+        assert_eq!(body["contracts"][0]["asyncapi"], "3.0.0");
+        assert!(body["content_types"]
+            .as_array()
+            .unwrap()
+            .contains(&json!("application/msgpack+zstd")));
+        assert_eq!(body["features"]["commands.attachments"]["max_job_bytes"], 4 * 1024 * 1024);
+        assert_eq!(body["features"]["operations.deprecation"]["sunset_override"], false);
+        assert!(body["features"].get("admin.simulation").is_none());
+        assert_eq!(
+            send(&router, get_capabilities(Some(&etag))).await.status(),
+            StatusCode::NOT_MODIFIED
+        );
+
+        let mut config = test_node_config();
+        config.attachments.enabled = false;
+        config.allow_sunset_operations = true;
+        let updated = send(
+            &router,
+            Request::put("/v1/node/config")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(serde_json::to_vec(&config).unwrap()))
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(updated.status(), StatusCode::OK);
+
+        let after = send(&router, get_capabilities(Some(&etag))).await;
+        assert_eq!(after.status(), StatusCode::OK);
+        let new_etag = etag_of(&after);
+        assert_ne!(new_etag, etag);
+        let body = json_body(after).await;
+        assert!(body["features"].get("commands.attachments").is_none());
+        assert!(body["limits"]["max_attachment_bytes"].is_null());
+        assert_eq!(body["features"]["operations.deprecation"]["sunset_override"], true);
+
+        let status = send(
+            &router,
+            Request::get("/v1/node/status").body(Body::empty()).unwrap(),
+        )
+        .await;
+        let digest = json_body(status).await["capabilities_digest"].clone();
+        assert_eq!(format!("\"{}\"", digest.as_str().unwrap()), new_etag);
+    }
+
+    #[tokio::test]
+    async fn aliased_submissions_run_as_the_current_operation() {
+        let (local, remote) = LoopbackMeshBridge::pair();
+        let base = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        let contract = "asyncapi: 3.0.0\nx-retasync:\n  operations:\n    \
+                        commands: [event.update]\n    \
+                        x-retasync-aliases:\n      event.put: event.update\n";
+        let router = build_router(AppState::new(
+            base.storage,
+            Arc::new(local),
+            test_node_config(),
+            contract.to_string(),
+            false,
+        ));
+
+        let accepted = send(
+            &router,
+            Request::post("/v1/jobs/commands/event.put")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(r#"{"uid":"evt-1"}"#))
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(accepted.status(), StatusCode::ACCEPTED);
+        assert_eq!(accepted.headers()["deprecation"], "true");
+        assert_eq!(
+            accepted.headers()[header::LINK],
+            "</v1/jobs/commands/event.update>; rel=\"successor-version\""
+        );
+        let job_id = json_body(accepted).await["job_id"].as_str().unwrap().to_string();
+
+        let mut sent = Vec::new();
+        for _ in 0..100 {
+            sent = remote.poll_commands(10).await.unwrap();
+            if !sent.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(sent[0].operation, "event.update");
+
+        let job = send(
+            &router,
+            Request::get(format!("/v1/jobs/{job_id}")).body(Body::empty()).unwrap(),
+        )
+        .await;
+        let job = json_body(job).await;
+        assert_eq!(job["operation"], "event.update");
+        assert_eq!(job["requested_operation"], "event.put");
+    }
+
+    async fn batch_router(config: NodeConfig) -> (AppState, Router) {
+        let base = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        let contract = "asyncapi: 3.0.0\nx-retasync:\n  operations:\n    \
+                        commands: [event.create, event.update]\n";
+        let state = AppState::new(
+            base.storage,
+            Arc::new(InMemoryRpcMeshBridge::new(true, true)),
+            config,
+            contract.to_string(),
+            false,
+        );
+        (state.clone(), build_router(state))
+    }
+
+    fn contract_file(version: &str, commands: &str, required: &str) -> String {
+        format!(
+            "asyncapi: 3.0.0\ninfo:\n  version: \"{version}\"\n\
+             x-retasync:\n  operations:\n    commands: [{commands}]\n\
+             components:\n  schemas:\n    Event:\n      type: object\n      required: [{required}]\n"
+        )
+    }
+
+    #[tokio::test]
+    async fn contract_reloads_swap_what_the_node_serves_and_accepts() {
+        let path = scratch_dir().join("contract.yaml");
+        std::fs::write(&path, contract_file("1.0.0", "event.create, event.update", "uid")).unwrap();
+        let base = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        let state = AppState::new(
+            base.storage,
+            Arc::new(InMemoryRpcMeshBridge::new(true, true)),
+            test_node_config(),
+            std::fs::read_to_string(&path).unwrap(),
+            false,
+        )
+        .with_contract_source(&path);
+        let mut events = state.sse_bus.subscribe();
+        let router = build_router(state.clone());
+        let reload = |query: &str| {
+            Request::post(format!("/v1/admin/contracts/reload{query}"))
+                .body(Body::empty())
+                .unwrap()
+        };
+        let delete = json!([{ "operation": "event.delete", "payload": { "uid": "evt-1" } }]);
+        let refused = json_body(submit_batch(&router, delete.clone()).await).await;
+        assert_eq!(refused["results"][0]["error"]["error"], "unknown_operation");
+
+        let v2 = contract_file("2.0.0", "event.create, event.update, event.delete", "uid, kind");
+        std::fs::write(&path, &v2).unwrap();
+        let reloaded = send(&router, reload("")).await;
+        assert_eq!(reloaded.status(), StatusCode::OK);
+        let reloaded = json_body(reloaded).await;
+        assert_eq!(reloaded["diff"]["added_commands"], json!(["event.delete"]));
+        assert_eq!(reloaded["restart_required"], true);
+        assert_eq!(
+            reloaded["diff"]["restart_required"],
+            json!([{ "schema": "Event", "field": "kind", "change": "required_field_added" }])
+        );
+        let served = send(
+            &router,
+            Request::get("/v1/contracts/asyncapi").body(Body::empty()).unwrap(),
+        )
+        .await;
+        let served = axum::body::to_bytes(served.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(std::str::from_utf8(&served).unwrap(), v2);
+        let accepted = json_body(submit_batch(&router, delete).await).await;
+        assert_eq!(accepted["accepted"], 1);
+        assert_eq!(state.storage.contract_version().as_deref(), Some("2.0.0"));
+        let event = loop {
+            let event = events.try_recv().unwrap();
+            if event.event_type == crate::contracts::CONTRACT_RELOADED_EVENT {
+                break event;
+            }
+        };
+        assert_eq!(
+            (event.data["old_version"].clone(), event.data["new_version"].clone()),
+            (json!("1.0.0"), json!("2.0.0"))
+        );
+
+        // A queued job for an operation the next contract drops blocks the swap.
+        state
+            .storage
+            .create_job("event.update", json!({ "uid": "evt-2" }))
+            .await
+            .unwrap();
+        std::fs::write(&path, contract_file("3.0.0", "event.create, event.delete", "uid, kind"))
+            .unwrap();
+        let blocked = send(&router, reload("")).await;
+        assert_eq!(blocked.status(), StatusCode::CONFLICT);
+        let blocked = json_body(blocked).await;
+        assert_eq!(blocked["error"], "contract_reload_refused");
+        assert_eq!(
+            blocked["diff"]["stranded_jobs"],
+            json!([{ "operation": "event.update", "pending_jobs": 1, "reason": "undeclared" }])
+        );
+        assert_eq!(blocked["diff"]["removed_commands"], json!(["event.update"]));
+        assert_eq!(state.contract.current().registry.version(), Some("2.0.0"));
+
+        let forced = send(&router, reload("?force=true")).await;
+        assert_eq!(forced.status(), StatusCode::OK);
+        assert_eq!(json_body(forced).await["restart_required"], false);
+        assert_eq!(state.contract.current().registry.version(), Some("3.0.0"));
+        let update = json!([{ "operation": "event.update", "payload": { "uid": "evt-3" } }]);
+        let refused = json_body(submit_batch(&router, update).await).await;
+        assert_eq!(refused["results"][0]["error"]["error"], "unknown_operation");
+        let _ = std::fs::remove_file(&path);
+    }
+
+    async fn submit_batch(router: &Router, entries: serde_json::Value) -> axum::response::Response {
+        send(
+            router,
+            Request::post("/v1/jobs/commands:batch")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(entries.to_string()))
+                .unwrap(),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn submissions_are_attributed_and_rate_limited_per_client() {
+        let mut config = test_node_config();
+        config.submissions.rate_per_minute = 2;
+        config.http_auth_token = Some("admin-secret".to_string());
+        config.api_tokens = vec![ApiToken {
+            label: "ops".to_string(),
+            token: "ops-secret".to_string(),
+        }];
+        let (mut state, _) = batch_router(config).await;
+        state.require_bearer = true;
+        let router = build_router(state.clone());
+        let submit = |bearer: Option<&str>, client_id: Option<&str>, addr: [u8; 4], count| {
+            let entries: Vec<_> = (0..count)
+                .map(|n| json!({ "operation": "event.create", "payload": { "uid": n } }))
+                .collect();
+            let mut request = Request::post("/v1/jobs/commands:batch")
+                .header(header::CONTENT_TYPE, "application/json");
+            if let Some(client_id) = client_id {
+                request = request.header(crate::attribution::CLIENT_ID_HEADER, client_id);
+            }
+            match bearer {
+                Some(token) => {
+                    request = request.header(header::AUTHORIZATION, format!("Bearer {token}"));
+                }
+                None => {
+                    request = request.extension(RequestListener {
+                        name: "local".to_string(),
+                        require_bearer: false,
+                    });
+                }
+            }
+            request
+                .extension(axum::extract::ConnectInfo(std::net::SocketAddr::from((
+                    addr, 40_000,
+                ))))
+                .body(Body::from(json!(entries).to_string()))
+                .unwrap()
+        };
+        let job_ids = |body: serde_json::Value| -> Vec<String> {
+            body["results"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .filter_map(|result| result["job_id"].as_str().map(str::to_string))
+                .collect()
+        };
+
+        let token = submit(Some("ops-secret"), None, [10, 0, 0, 7], 1);
+        let token = job_ids(json_body(send(&router, token).await).await);
+        // The client id gets a budget of its own even though it shares the token.
+        let header = submit(Some("ops-secret"), Some("tak-bridge"), [10, 0, 0, 9], 3);
+        let header = job_ids(json_body(send(&router, header).await).await);
+        assert_eq!(header.len(), 2);
+        let loopback = submit(None, None, [127, 0, 0, 1], 1);
+        let loopback = job_ids(json_body(send(&router, loopback).await).await);
+        let again = submit(Some("ops-secret"), None, [10, 0, 0, 8], 2);
+        assert_eq!(
+            job_ids(json_body(send(&router, again).await).await).len(),
+            1
+        );
+
+        let source = |job_id: &str, token: &str| {
+            Request::get(format!("/v1/jobs/{job_id}"))
+                .header(header::AUTHORIZATION, format!("Bearer {token}"))
+                .body(Body::empty())
+                .unwrap()
+        };
+        let seen = json_body(send(&router, source(&token[0], "ops-secret")).await).await;
+        assert_eq!(seen["source"], json!({ "token_label": "ops" }));
+        let seen = json_body(send(&router, source(&header[0], "admin-secret")).await).await;
+        assert_eq!(
+            seen["source"],
+            json!({ "token_label": "ops", "remote_addr": "10.0.0.9", "client_id": "tak-bridge" })
+        );
+        let seen = json_body(send(&router, source(&loopback[0], "admin-secret")).await).await;
+        assert_eq!(
+            seen["source"],
+            json!({ "token_label": "anonymous-loopback", "remote_addr": "127.0.0.1" })
+        );
+
+        let stats = Request::get("/v1/node/stats/clients?window=1h")
+            .header(header::AUTHORIZATION, "Bearer admin-secret")
+            .body(Body::empty())
+            .unwrap();
+        let stats = json_body(send(&router, stats).await).await;
+        let clients: Vec<_> = stats["clients"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|client| {
+                let client = |field: &str| client[field].clone();
+                (
+                    client("client"),
+                    client("submissions"),
+                    client("rate_limited"),
+                )
+            })
+            .collect();
+        assert_eq!(
+            clients,
+            [
+                (json!("127.0.0.1"), json!(1), json!(0)),
+                (json!("ops"), json!(2), json!(1)),
+                (json!("tak-bridge"), json!(2), json!(1)),
+            ]
+        );
+        assert_eq!(
+            stats["clients"][1]["remote_addrs"],
+            json!(["10.0.0.7", "10.0.0.8"])
+        );
+        let (status, _) = get_json(&router, "/v1/node/stats/clients?window=90d").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn batch_submissions_report_each_entry_in_order() {
+        let mut config = test_node_config();
+        config.submissions.max_batch_size = 3;
+        let (state, router) = batch_router(config).await;
+        let mut events = state.sse_bus.subscribe();
+
+        let valid = submit_batch(
+            &router,
+            json!([
+                { "operation": "event.create", "payload": { "uid": "evt-1" } },
+                { "operation": "event.update", "payload": { "uid": "evt-2" }, "ttl_ms": 5000 },
+            ]),
+        )
+        .await;
+        assert_eq!(valid.status(), StatusCode::OK);
+        let valid = json_body(valid).await;
+        assert_eq!(valid["accepted"], 2);
+        let job_id = valid["results"][1]["job_id"].as_str().unwrap();
+        let job = state.storage.get_job(job_id).await.unwrap().unwrap();
+        assert_eq!(job.operation, "event.update");
+        assert!(job.dispatch_json.unwrap().contains("\"ttl_ms\":5000"));
+        let mut summaries = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            if event.event_type == "jobs.batch.submitted" {
+                summaries.push(event.data);
+            }
+        }
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0]["accepted"], 2);
+
+        let mixed = submit_batch(
+            &router,
+            json!([
+                { "operation": "event.delete", "payload": {} },
+                { "operation": "event.create", "payload": { "uid": "evt-3" } },
+                { "operation": "event.create", "payload": "evt-4" },
+            ]),
+        )
+        .await;
+        let mixed = json_body(mixed).await;
+        let statuses: Vec<_> = mixed["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|result| (result["index"].clone(), result["status"].clone()))
+            .collect();
+        assert_eq!(
+            statuses,
+            [
+                (json!(0), json!("rejected")),
+                (json!(1), json!("accepted")),
+                (json!(2), json!("rejected")),
+            ]
+        );
+        assert_eq!(mixed["results"][0]["error"]["error"], "unknown_operation");
+        assert_eq!(mixed["results"][2]["error"]["error"], "invalid_batch_entry");
+        assert_eq!((mixed["accepted"].clone(), mixed["rejected"].clone()), (json!(1), json!(2)));
+
+        let entry = json!({ "operation": "event.create", "payload": {} });
+        let oversize = submit_batch(&router, json!([entry, entry, entry, entry])).await;
+        assert_eq!(oversize.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(json_body(oversize).await["error"], "batch_too_large");
+        let jobs: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM jobs")
+            .fetch_one(state.storage.pool())
+            .await
+            .unwrap();
+        assert_eq!(jobs, 3);
+    }
+
+    #[tokio::test]
+    async fn batch_idempotency_keys_and_rate_limit_apply_per_entry() {
+        let mut config = test_node_config();
+        config.submissions.rate_per_minute = 4;
+        let (_state, router) = batch_router(config).await;
+        let keyed = |key: &str| {
+            json!({ "operation": "event.create", "payload": {}, "idempotency_key": key })
+        };
+
+        let first = submit_batch(&router, json!([keyed("a"), keyed("a"), keyed("b")])).await;
+        let first = json_body(first).await;
+        let results = &first["results"];
+        assert_eq!(results[0]["status"], "accepted");
+        assert_eq!(results[1]["status"], "duplicate");
+        assert_eq!(results[1]["job_id"], results[0]["job_id"]);
+        assert_eq!(results[2]["status"], "accepted");
+        assert_ne!(results[2]["job_id"], results[0]["job_id"]);
+
+        let again = json!([keyed("b"), keyed("c"), keyed("d"), keyed("e")]);
+        let again = json_body(submit_batch(&router, again).await).await;
+        let results = &again["results"];
+        assert_eq!(results[0]["status"], "duplicate");
+        assert_eq!(results[0]["job_id"], first["results"][2]["job_id"]);
+        assert_eq!(results[1]["status"], "accepted");
+        assert_eq!(results[2]["status"], "accepted");
+        assert_eq!(results[3]["status"], "rejected");
+        assert_eq!(results[3]["error"]["error"], "rate_limited");
+        // Four a minute: the next entry fits once a quarter of a minute has refilled.
+        let advice = RetryAdvice::from_body(&results[3]["error"]).unwrap();
+        assert_eq!(advice.scope, RetryScope::Client);
+        assert!((14_000..=15_000).contains(&advice.retry_after_ms), "{advice:?}");
+
+        let retried = json_body(submit_batch(&router, json!([keyed("e")])).await).await;
+        assert_eq!(retried["results"][0]["error"]["error"], "rate_limited");
+    }
+
+    #[tokio::test]
+    async fn rate_limited_submissions_say_when_the_budget_refills() {
+        let mut config = test_node_config();
+        config.submissions.rate_per_minute = 2;
+        let (_state, router) = batch_router(config).await;
+        for uid in ["evt-1", "evt-2"] {
+            assert_eq!(submit_event(&router, uid).await.status(), StatusCode::ACCEPTED);
+        }
+
+        let refused = submit_event(&router, "evt-3").await;
+        assert_eq!(refused.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(header_of(&refused, header::RETRY_AFTER), Some("30"));
+        let advice = RetryAdvice::from_body(&json_body(refused).await).unwrap();
+        assert_eq!((advice.reason.as_str(), advice.scope), ("rate_limited", RetryScope::Client));
+        assert!((29_000..=30_000).contains(&advice.retry_after_ms), "{advice:?}");
+
+        let refused = send(
+            &router,
+            Request::post("/v2/jobs/commands/event.create")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(json!({ "uid": "evt-4" }).to_string()))
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(refused.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(header_of(&refused, header::RETRY_AFTER), Some("30"));
+        let body = json_body(refused).await;
+        assert_eq!(body["error"]["code"], "rate_limited");
+        assert_eq!(body["error"]["retry"]["scope"], "client");
+        assert!(body["error"].get("detail").is_none());
+    }
+
+    // Every answer that turns a client away for now says when to come back.
+    #[test]
+    fn throttling_and_unavailable_errors_all_carry_retry_advice() {
+        let now = chrono::Utc::now();
+        let exceeded = |reset_at: Option<chrono::DateTime<chrono::Utc>>| QuotaExceeded {
+            subject_kind: crate::quotas::DESTINATION_QUOTA.to_string(),
+            subject: PEER.to_string(),
+            limit_bytes: 100,
+            used_bytes: 100,
+            requested_bytes: 1,
+            reset_at: reset_at.map(|at| CanonicalTimestamp::from(at).to_string()),
+        };
+        let client = RetryAdvice::after("rate_limited", RetryScope::Client, Duration::ZERO);
+        let drain = Duration::from_millis(2500);
+        let spool_full = SubmissionSpoolError::Full {
+            bytes: 10,
+            max_bytes: 10,
+        };
+        let spool_io = SubmissionSpoolError::Io(std::io::Error::other("disk gone"));
+        let errors = [
+            rate_limited(client),
+            quota_exceeded(exceeded(Some(now + chrono::Duration::minutes(5)))),
+            quota_exceeded(exceeded(None)),
+            storage_error(StorageError::from_sqlx("query", sqlx::Error::PoolTimedOut)),
+            storage_error(StorageError::from_sqlx("query", sqlx::Error::PoolClosed)),
+            submission_spool_error(drain, spool_full),
+            submission_spool_error(drain, spool_io),
+        ];
+        for (status, body) in errors {
+            assert!(
+                matches!(
+                    status,
+                    StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE
+                ),
+                "{}",
+                body.0
+            );
+            let advice = RetryAdvice::from_body(&body.0).unwrap_or_else(|| panic!("{}", body.0));
+            assert_eq!(advice.reason, body.0["error"]);
+        }
+
+        let advice_of = |(_, body): (StatusCode, axum::Json<Value>)| {
+            RetryAdvice::from_body(&body.0).unwrap()
+        };
+        let advice = advice_of(quota_exceeded(exceeded(Some(now + chrono::Duration::minutes(5)))));
+        assert_eq!(advice.scope, RetryScope::Destination);
+        assert!((299_000..=300_000).contains(&advice.retry_after_ms), "{advice:?}");
+        // Larger than the whole budget: wait out a full window.
+        let advice = advice_of(quota_exceeded(exceeded(None)));
+        assert_eq!(advice.retry_after_ms, 86_400_000);
+        let spool_full = SubmissionSpoolError::Full {
+            bytes: 10,
+            max_bytes: 10,
+        };
+        let advice = advice_of(submission_spool_error(drain, spool_full));
+        assert_eq!((advice.retry_after_ms, advice.scope), (2500, RetryScope::Node));
+    }
+
+    // Takes the database's write lock from a second connection, so the node's writes fail as
+    // busy until the returned connection commits.
+    async fn lock_storage(state: &AppState) -> sqlx::SqliteConnection {
+        use sqlx::Connection;
+        sqlx::query("PRAGMA busy_timeout = 50")
+            .execute(state.storage.pool())
+            .await
+            .unwrap();
+        let url = format!("sqlite://{}", state.storage.database_path().display());
+        let mut lock = sqlx::SqliteConnection::connect(&url).await.unwrap();
+        sqlx::query("BEGIN EXCLUSIVE").execute(&mut lock).await.unwrap();
+        lock
+    }
+
+    async fn unlock_storage(mut lock: sqlx::SqliteConnection) {
+        sqlx::query("COMMIT").execute(&mut lock).await.unwrap();
+    }
+
+    async fn submit_event(router: &Router, uid: &str) -> axum::response::Response {
+        send(
+            router,
+            Request::post("/v1/jobs/commands/event.create")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(json!({ "uid": uid }).to_string()))
+                .unwrap(),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn rejected_submissions_do_not_spend_the_rate_limit() {
+        let mut config = test_node_config();
+        config.submissions.rate_per_minute = 1;
+        let (_, router) = batch_router(config).await;
+        let invalid = send(
+            &router,
+            Request::post("/v1/jobs/commands/event.create")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(json!({ "uid": "evt-0", "_labels": 7 }).to_string()))
+                .unwrap(),
+        )
+        .await;
+        assert!(invalid.status().is_client_error());
+        assert_ne!(invalid.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        assert_eq!(submit_event(&router, "evt-1").await.status(), StatusCode::ACCEPTED);
+        let limited = submit_event(&router, "evt-2").await;
+        assert_eq!(limited.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    async fn stored_job_ids(state: &AppState) -> Vec<String> {
+        sqlx::query_scalar("SELECT job_id FROM jobs ORDER BY rowid")
+            .fetch_all(state.storage.pool())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn submissions_spool_while_storage_is_busy_and_drain_in_order() {
+        let (state, router) = batch_router(test_node_config()).await;
+        let lock = lock_storage(&state).await;
+
+        let mut spooled = Vec::new();
+        for uid in ["evt-1", "evt-2", "evt-3"] {
+            let response = submit_event(&router, uid).await;
+            assert_eq!(response.status(), StatusCode::ACCEPTED);
+            let body = json_body(response).await;
+            assert_eq!(body["spooled"], true, "{body}");
+            spooled.push(body["job_id"].as_str().unwrap().to_string());
+        }
+        let (status, job) = get_json(&router, &format!("/v1/jobs/{}", spooled[1])).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(job["status"], SPOOLED_STATUS);
+        let (_, node) = get_json(&router, "/v1/node/status").await;
+        assert_eq!(node["submission_spool"]["depth"], 3);
+        assert!(node["submission_spool"]["oldest_age_secs"].is_i64(), "{node}");
+
+        // Still locked: nothing is stored and nothing leaves the spool.
+        assert_eq!(drain_submission_spool(&state).await.unwrap(), 0);
+        assert!(!state.submission_spool.is_empty().await.unwrap());
+
+        unlock_storage(lock).await;
+        assert_eq!(drain_submission_spool(&state).await.unwrap(), 3);
+        assert!(state.submission_spool.is_empty().await.unwrap());
+        assert_eq!(stored_job_ids(&state).await, spooled);
+        let stored = state.storage.get_job(&spooled[1]).await.unwrap().unwrap();
+        assert_eq!(serde_json::from_str::<Value>(&stored.payload_json).unwrap()["uid"], "evt-2");
+        let (_, node) = get_json(&router, "/v1/node/status").await;
+        assert_eq!(node["submission_spool"]["depth"], 0);
+        assert!(node["submission_spool"].get("oldest_age_secs").is_none());
+
+        // With the spool empty again, submissions go straight to storage.
+        let body = json_body(submit_event(&router, "evt-4").await).await;
+        assert!(body.get("spooled").is_none(), "{body}");
+        assert_eq!(stored_job_ids(&state).await.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn idempotency_keys_hold_across_a_storage_outage() {
+        let (state, router) = batch_router(test_node_config()).await;
+        let keyed = |key: &str| {
+            json!({ "operation": "event.create", "payload": {}, "idempotency_key": key })
+        };
+        let before = json_body(submit_batch(&router, json!([keyed("a")])).await).await;
+        let lock = lock_storage(&state).await;
+
+        let first = json_body(submit_batch(&router, json!([keyed("a"), keyed("b")])).await).await;
+        assert_eq!(first["results"][0]["status"], "duplicate");
+        assert_eq!(first["results"][0]["job_id"], before["results"][0]["job_id"]);
+        assert_eq!(first["results"][1]["status"], "accepted");
+        assert_eq!(first["results"][1]["spooled"], true, "{first}");
+        // The client did not hear back and retried while storage was still down.
+        let retried = json_body(submit_batch(&router, json!([keyed("b"), keyed("c")])).await).await;
+        assert_eq!(retried["results"][0]["status"], "duplicate");
+        assert_eq!(retried["results"][0]["job_id"], first["results"][1]["job_id"]);
+        assert_eq!(retried["results"][1]["spooled"], true);
+
+        unlock_storage(lock).await;
+        assert_eq!(drain_submission_spool(&state).await.unwrap(), 2);
+        let stored = stored_job_ids(&state).await;
+        assert_eq!(
+            stored,
+            [
+                &before["results"][0]["job_id"],
+                &first["results"][1]["job_id"],
+                &retried["results"][1]["job_id"],
+            ]
+            .map(|id| id.as_str().unwrap().to_string())
+        );
+        let b = state.storage.find_job_by_idempotency_key("b").await.unwrap();
+        assert_eq!(b.as_deref(), first["results"][1]["job_id"].as_str());
+        let again = json_body(submit_batch(&router, json!([keyed("c")])).await).await;
+        assert_eq!(again["results"][0]["status"], "duplicate");
+    }
+
+    #[tokio::test]
+    async fn a_full_or_disabled_spool_answers_503() {
+        let (state, router) = batch_router(test_node_config()).await;
+        let lock = lock_storage(&state).await;
+        assert_eq!(submit_event(&router, "evt-1").await.status(), StatusCode::ACCEPTED);
+        let (_, node) = get_json(&router, "/v1/node/status").await;
+        let used = node["submission_spool"]["bytes"].as_u64().unwrap();
+
+        state.node_config.write().await.submission_spool.max_bytes = used;
+        let full = submit_event(&router, "evt-2").await;
+        assert_eq!(full.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(header_of(&full, header::RETRY_AFTER), Some("1"));
+        let full = json_body(full).await;
+        assert_eq!(full["error"], "submission_spool_full");
+        // Room frees up at the next drain.
+        assert_eq!(full["retry"]["retry_after_ms"], 1000);
+        let status = state.submission_spool.status(used, CanonicalTimestamp::now()).await;
+        assert_eq!(status.unwrap().depth, 1);
+
+        state.node_config.write().await.submission_spool.enabled = false;
+        drain_submission_spool(&state).await.unwrap();
+        let refused = submit_event(&router, "evt-3").await;
+        assert_eq!(refused.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let refused = json_body(refused).await;
+        assert_eq!(refused["error"], "storage_unavailable");
+        assert_eq!(refused["retry"]["retry_after_ms"], 1000);
+
+        unlock_storage(lock).await;
+        assert_eq!(drain_submission_spool(&state).await.unwrap(), 1);
+        assert_eq!(stored_job_ids(&state).await.len(), 1);
+    }
+
+    const PEER: &str = "bb00000000000000000000000000000b";
+
+    async fn contract_node(bridge: LoopbackMeshBridge, version: &str) -> AppState {
+        let base = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        AppState::new(
+            base.storage,
+            Arc::new(bridge),
+            test_node_config(),
+            format!("asyncapi: 3.0.0\ninfo:\n  version: \"{version}\"\n"),
+            false,
+        )
+    }
+
+    // A node on contract 1.2.0 linked to a peer on `peer_version` that answers `node.hello`.
+    async fn handshake_pair(peer_version: &str) -> (Router, tokio::task::JoinHandle<()>) {
+        let (local, remote) = LoopbackMeshBridge::pair();
+        let peer = contract_node(remote, peer_version).await;
+        let worker = spawn_inbound_worker(peer, Duration::from_millis(5));
+        (build_router(contract_node(local, "1.2.0").await), worker)
+    }
+
+    fn peer_command(force: bool) -> Request<Body> {
+        let query = if force { "?force=true" } else { "" };
+        Request::post(format!("/v1/jobs/commands/event.create{query}"))
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(
+                json!({ "uid": "evt-1", "destination_identity": PEER }).to_string(),
+            ))
+            .unwrap()
+    }
+
+    async fn dispatch_of(router: &Router, response: axum::response::Response) -> serde_json::Value {
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        let job_id = json_body(response).await["job_id"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        let job = send(
+            router,
+            Request::get(format!("/v1/jobs/{job_id}")).body(Body::empty()).unwrap(),
+        )
+        .await;
+        serde_json::from_str(json_body(job).await["dispatch_json"].as_str().unwrap()).unwrap()
+    }
+
+    // Submits a first command, which goes out unassessed while the handshake runs behind it,
+    // and waits for the verdict.
+    async fn await_verdict(router: &Router) -> serde_json::Value {
+        let first = send(router, peer_command(false)).await;
+        assert!(dispatch_of(router, first)
+            .await
+            .get("peer_compatibility")
+            .is_none());
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(30);
+        loop {
+            let peers = json_body(
+                send(router, Request::get("/v1/peers").body(Body::empty()).unwrap()).await,
+            )
+            .await;
+            let verdict = &peers["handshakes"][PEER]["compatibility"];
+            if !verdict.is_null() {
+                return verdict.clone();
+            }
+            assert!(tokio::time::Instant::now() < deadline, "no handshake verdict");
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn first_dispatch_to_a_peer_handshakes_and_gates_on_the_verdict() {
+        let (router, worker) = handshake_pair("1.2.0").await;
+        assert_eq!(await_verdict(&router).await, "compatible");
+        let compatible = send(&router, peer_command(false)).await;
+        assert!(dispatch_of(&router, compatible)
+            .await
+            .get("peer_compatibility")
+            .is_none());
+        worker.abort();
+
+        let (router, worker) = handshake_pair("1.5.0").await;
+        assert_eq!(await_verdict(&router).await, "degraded");
+        let degraded = send(&router, peer_command(false)).await;
+        assert_eq!(dispatch_of(&router, degraded).await["peer_compatibility"], "degraded");
+        worker.abort();
+
+        let (router, worker) = handshake_pair("2.0.0").await;
+        assert_eq!(await_verdict(&router).await, "incompatible");
+        let rejected = send(&router, peer_command(false)).await;
+        assert_eq!(rejected.status(), StatusCode::CONFLICT);
+        let body = json_body(rejected).await;
+        assert_eq!(body["error"], "peer_contract_incompatible");
+        assert_eq!(body["peer"]["contracts"][0]["version"], "2.0.0");
+
+        let forced = send(&router, peer_command(true)).await;
+        assert_eq!(dispatch_of(&router, forced).await["peer_compatibility"], "incompatible");
+
+        let rehandshake = send(
+            &router,
+            Request::post(format!("/v1/peers/{PEER}/handshake"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(rehandshake.status(), StatusCode::OK);
+        assert_eq!(json_body(rehandshake).await["compatibility"], "incompatible");
+        worker.abort();
+    }
+
+    #[tokio::test]
+    async fn submission_metadata_reaches_the_peer_beside_the_payload() {
+        let (local, remote) = LoopbackMeshBridge::pair();
+        let peer = contract_node(remote, "1.2.0").await;
+        let mut received = peer.sse_bus.subscribe();
+        let worker = spawn_inbound_worker(peer.clone(), Duration::from_millis(5));
+        let router = build_router(contract_node(local, "1.2.0").await);
+
+        let submitted = Request::post("/v1/jobs/commands/event.create")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(crate::meta::PRIORITY_HEADER, "flash")
+            .header(crate::meta::TRACE_ID_HEADER, "trace-7")
+            .header(crate::attribution::CLIENT_ID_HEADER, "tak-bridge")
+            .header(crate::meta::CLIENT_VERSION_HEADER, "2.3.0")
+            .header("x-retasync-meta-shift", "night")
+            .body(Body::from(
+                json!({ "uid": "evt-1", "destination_identity": PEER }).to_string(),
+            ))
+            .unwrap();
+        let expected = json!({
+            "priority": "flash",
+            "trace_id": "trace-7",
+            "client_id": "tak-bridge",
+            "client_version": "2.3.0",
+            "custom": { "shift": "night" },
+        });
+        let dispatch = dispatch_of(&router, send(&router, submitted).await).await;
+        assert_eq!(dispatch["meta"], expected);
+
+        let event = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let event = received.recv().await.unwrap();
+                if event.event_type == "inbound.command.received" {
+                    return event;
+                }
+            }
+        })
+        .await
+        .expect("peer received the command");
+        assert_eq!(event.data["meta"], expected);
+        let cached = peer.storage.list_cached_messages(10).await.unwrap();
+        let envelope = cached
+            .iter()
+            .find(|envelope| envelope["operation"] == "event.create")
+            .expect("cached command");
+        assert_eq!(envelope["meta"], expected);
+        assert_eq!(
+            envelope["payload"],
+            json!({ "uid": "evt-1", "destination_identity": PEER })
+        );
+        worker.abort();
+    }
+
+    #[tokio::test]
+    async fn reserved_payload_keys_and_malformed_metadata_are_refused() {
+        let (_state, router) = batch_router(test_node_config()).await;
+        for key in crate::meta::RESERVED_PAYLOAD_KEYS {
+            let payload = json!({ "uid": "evt-1", key: "flash" });
+            let response = send(&router, command_request("event.create", payload)).await;
+            assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+            assert_eq!(
+                json_body(response).await,
+                json!({ "error": "reserved_key_in_payload", "key": key })
+            );
+        }
+        let unknown_priority = Request::post("/v1/jobs/commands/event.create")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(crate::meta::PRIORITY_HEADER, "urgent")
+            .body(Body::from(r#"{"uid":"evt-1"}"#))
+            .unwrap();
+        let response = send(&router, unknown_priority).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(json_body(response).await["error"], "invalid_meta");
+
+        let entries = json!([
+            { "operation": "event.create", "payload": { "uid": "evt-1", "_meta": {} } },
+            {
+                "operation": "event.create",
+                "payload": { "uid": "evt-2" },
+                "meta": { "priority": "immediate", "custom": { "Shift": "night" } },
+            },
+            {
+                "operation": "event.create",
+                "payload": { "uid": "evt-3" },
+                "meta": { "priority": "immediate", "trace_id": "trace-9" },
+            },
+        ]);
+        let results = json_body(submit_batch(&router, entries.clone()).await).await["results"].take();
+        assert_eq!(results[0]["http_status"], 422);
+        assert_eq!(results[0]["error"]["error"], "reserved_key_in_payload");
+        assert_eq!(results[1]["http_status"], 400);
+        assert_eq!(results[1]["error"]["error"], "invalid_meta");
+        assert_eq!(results[2]["status"], "accepted");
+        let job = get_json(&router, &format!("/v1/jobs/{}", results[2]["job_id"].as_str().unwrap()))
+            .await
+            .1;
+        let dispatch: serde_json::Value =
+            serde_json::from_str(job["dispatch_json"].as_str().unwrap()).unwrap();
+        assert_eq!(
+            dispatch["meta"],
+            json!({ "priority": "immediate", "trace_id": "trace-9" })
+        );
+
+        let report = dry_run(&router, "/v1/jobs/commands:batch?dry_run=true", entries).await;
+        let meta = |index: usize| stage(&report["results"][index], "meta").clone();
+        assert_eq!(meta(0)["error"]["error"], "reserved_key_in_payload");
+        assert_eq!(meta(1)["error"]["error"], "invalid_meta");
+        assert_eq!(meta(2)["detail"]["trace_id"], "trace-9");
+    }
+
+    const ORIGIN: &str = "aa00000000000000000000000000000a";
+    const RELAY: &str = "cc00000000000000000000000000000c";
+
+    // A relay's two links: commands and results for the upstream node travel on one, and the
+    // commands it relays go out on the other.
+    struct RelayLinks {
+        upstream: LoopbackMeshBridge,
+        downstream: LoopbackMeshBridge,
+    }
+
+    #[async_trait::async_trait]
+    impl RpcMeshBridge for RelayLinks {
+        async fn send_command(
+            &self,
+            envelope: MeshCommandEnvelope<Value>,
+        ) -> Result<MeshResultEnvelope<Value>, BridgeError> {
+            self.downstream.send_command(envelope).await
+        }
+
+        async fn publish_event(
+            &self,
+            envelope: MeshEventEnvelope<Value>,
+        ) -> Result<BridgeReceipt, BridgeError> {
+            self.upstream.publish_event(envelope).await
+        }
+
+        async fn start_transfer(
+            &self,
+            envelope: MeshTransferEnvelope<Value>,
+        ) -> Result<BridgeReceipt, BridgeError> {
+            self.upstream.start_transfer(envelope).await
+        }
+
+        async fn query_receipt(
+            &self,
+            message_id: &str,
+        ) -> Result<Option<BridgeReceipt>, BridgeError> {
+            self.upstream.query_receipt(message_id).await
+        }
+
+        async fn poll_events(
+            &self,
+            limit: usize,
+        ) -> Result<Vec<MeshEventEnvelope<Value>>, BridgeError> {
+            self.upstream.poll_events(limit).await
+        }
+
+        async fn poll_commands(
+            &self,
+            limit: usize,
+        ) -> Result<Vec<MeshCommandEnvelope<Value>>, BridgeError> {
+            self.upstream.poll_commands(limit).await
+        }
+
+        async fn send_result(
+            &self,
+            envelope: MeshResultEnvelope<Value>,
+        ) -> Result<BridgeReceipt, BridgeError> {
+            self.upstream.send_result(envelope).await
+        }
+
+        async fn set_inbound_backpressure(&self, enabled: bool) -> Result<(), BridgeError> {
+            self.upstream.set_inbound_backpressure(enabled).await
+        }
+    }
+
+    async fn traced_node(
+        bridge: Arc<dyn RpcMeshBridge>,
+        identity: &str,
+        routing: RoutingSettings,
+    ) -> AppState {
+        let base = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        let mut config = test_node_config();
+        config.identity.source_identity = Some(identity.to_string());
+        config.routing = routing;
+        AppState::new(
+            base.storage,
+            bridge,
+            config,
+            "asyncapi: 3.0.0\ninfo:\n  version: \"1.2.0\"\n".to_string(),
+            false,
+        )
+    }
+
+    // Pings PEER from ORIGIN through RELAY with tracing on and returns the job's trace.
+    async fn relayed_trace(peer_max_hops: usize) -> serde_json::Value {
+        let (origin_link, relay_up) = LoopbackMeshBridge::pair();
+        let (relay_down, peer_link) = LoopbackMeshBridge::pair();
+        let relay = traced_node(
+            Arc::new(RelayLinks {
+                upstream: relay_up,
+                downstream: relay_down,
+            }),
+            RELAY,
+            RoutingSettings {
+                relay_destinations: vec![PEER.to_string()],
+                ..RoutingSettings::default()
+            },
+        )
+        .await;
+        let peer = traced_node(
+            Arc::new(peer_link),
+            PEER,
+            RoutingSettings {
+                max_trace_hops: peer_max_hops,
+                ..RoutingSettings::default()
+            },
+        )
+        .await;
+        let workers = [
+            spawn_inbound_worker(relay, Duration::from_millis(5)),
+            spawn_inbound_worker(peer, Duration::from_millis(5)),
+        ];
+        let origin = traced_node(Arc::new(origin_link), ORIGIN, RoutingSettings::default()).await;
+        let router = build_router(origin);
+
+        let accepted = send(
+            &router,
+            Request::post("/v1/jobs/commands/node.ping")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(
+                    json!({ "destination_identity": PEER, "tracing_enabled": true }).to_string(),
+                ))
+                .unwrap(),
+        )
+        .await;
+        let job = settled_job(&router, accepted).await;
+        assert_eq!(job["status"], "success");
+        let (status, trace) =
+            get_json(&router, &format!("/v1/jobs/{}/trace", job["job_id"].as_str().unwrap()))
+                .await;
+        for worker in workers {
+            worker.abort();
+        }
+        assert_eq!(status, StatusCode::OK);
+        trace
+    }
+
+    fn leg_kinds(trace: &serde_json::Value) -> Vec<&str> {
+        trace["legs"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|leg| leg["kind"].as_str().unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn relayed_commands_carry_a_hop_trace_back_to_the_origin() {
+        let trace = relayed_trace(16).await;
+        let hops: Vec<(&str, &str)> = trace["hops"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|hop| (hop["identity"].as_str().unwrap(), hop["note"].as_str().unwrap()))
+            .collect();
+        assert_eq!(hops, [(ORIGIN, "origin"), (RELAY, "relayed"), (PEER, "answered")]);
+        assert_eq!(
+            leg_kinds(&trace),
+            ["transit", "processing", "transit", "processing", "return"]
+        );
+        assert_eq!(trace["truncated"], false);
+        // One clock throughout, so no leg runs backwards and the legs add up to the round trip
+        // less a millisecond of rounding per leg.
+        assert_eq!(trace["clock_skew"], false);
+        let total: i64 = trace["legs"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|leg| leg["latency_ms"].as_i64().unwrap())
+            .sum();
+        let round_trip = trace["round_trip_ms"].as_i64().unwrap();
+        assert!(total <= round_trip && round_trip - total < 5, "{trace}");
+
+        // The far end keeps at most two hops: the origin and itself.
+        let capped = relayed_trace(2).await;
+        let identities: Vec<&str> = capped["hops"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|hop| hop["identity"].as_str().unwrap())
+            .collect();
+        assert_eq!(identities, [ORIGIN, PEER]);
+        assert_eq!(capped["truncated"], true);
+        assert_eq!(leg_kinds(&capped), ["gap", "processing", "return"]);
+    }
+
+    #[tokio::test]
+    async fn archives_are_listed_and_streamed() {
+        let state = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        let router = build_router(state.clone());
+        let get = |uri: &str| Request::builder().uri(uri).body(Body::empty()).unwrap();
+
+        let disabled = send(&router, get("/v1/admin/archives")).await;
+        assert_eq!(disabled.status(), StatusCode::NOT_FOUND);
+
+        let dir = scratch_dir().join("archives");
+        let settings = crate::archive::RetentionSettings {
+            job_retention_hours: 0,
+            archive_dir: Some(dir.to_string_lossy().into_owned()),
+            ..Default::default()
+        };
+        state.node_config.write().await.retention = settings.clone();
+        let job = state
+            .storage
+            .create_job("event.create", json!({ "uid": "evt-1" }))
+            .await
+            .unwrap();
+        let later = chrono::Utc::now() + chrono::Duration::seconds(1);
+        crate::archive::apply_retention(&state.storage, &settings, later)
+            .await
+            .unwrap();
+
+        let listed = json_body(send(&router, get("/v1/admin/archives")).await).await;
+        let archive = &listed["archives"][0];
+        assert_eq!(archive["table"], "jobs");
+        let name = archive["name"].as_str().unwrap().to_string();
+
+        let streamed = send(&router, get(&format!("/v1/admin/archives/{name}"))).await;
+        assert_eq!(streamed.status(), StatusCode::OK);
+        assert_eq!(streamed.headers()[header::CONTENT_TYPE], "application/msgpack");
+        let bytes = axum::body::to_bytes(streamed.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(bytes.len() as u64, archive["size_bytes"].as_u64().unwrap());
+        let record: serde_json::Value = decode_canonical(&bytes).unwrap();
+        assert_eq!(record["job_id"], job.job_id);
+
+        let invalid = send(&router, get("/v1/admin/archives/node.toml")).await;
+        assert_eq!(invalid.status(), StatusCode::BAD_REQUEST);
+        let missing = send(&router, get("/v1/admin/archives/jobs-2001-01-01.msgpack")).await;
+        assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn quarantined_rows_are_listed_and_purged() {
+        let state = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        let router = build_router(state.clone());
+        let mut events = state.sse_bus.subscribe();
+        sqlx::query(
+            "INSERT INTO cached_messages(message_id, operation, payload_json, received_at) VALUES ('msg-bad', 'chat.send', '{', '2026-01-01T00:00:00Z')",
+        )
+        .execute(state.storage.pool())
+        .await
+        .unwrap();
+
+        let cached = json_body(
+            send(&router, Request::get("/v1/cache/messages").body(Body::empty()).unwrap()).await,
+        )
+        .await;
+        assert_eq!(cached, json!([]));
+        let report = crate::watchdog::run_integrity_check(&state).await.unwrap();
+        assert_eq!(report.quarantined, 1);
+        assert_eq!(events.try_recv().unwrap().event_type, "storage.integrity.completed");
+
+        let listed = json_body(
+            send(
+                &router,
+                Request::get("/v1/admin/storage/quarantine").body(Body::empty()).unwrap(),
+            )
+            .await,
+        )
+        .await;
+        assert_eq!(listed["rows"][0]["row_key"], "msg-bad");
+        assert_eq!(listed["rows"][0]["raw_base64"], STANDARD.encode("{"));
+
+        let purged = json_body(
+            send(
+                &router,
+                Request::delete("/v1/admin/storage/quarantine").body(Body::empty()).unwrap(),
+            )
+            .await,
+        )
+        .await;
+        assert_eq!(purged["purged"], 1);
+        let status = json_body(
+            send(&router, Request::get("/v1/node/status").body(Body::empty()).unwrap()).await,
+        )
+        .await;
+        assert_eq!(status["storage_integrity"]["skipped_rows"], 1);
+        assert_eq!(status["storage_integrity"]["quarantined_rows"], 1);
+    }
+
+    // A node whose contract gives `event.create` a result schema, answered by the mock bridge.
+    async fn result_schema_node(answers: MockResults, policy: ResultPolicy) -> AppState {
+        let bridge = InMemoryRpcMeshBridge::new(true, true);
+        bridge.set_results(answers);
+        let state = test_state(Arc::new(bridge)).await;
+        state.contract.replace(crate::contracts::LoadedContract::lenient(
+            "asyncapi: 3.0.0\n\
+             x-retasync:\n  operations:\n    commands: [event.create]\n    \
+             x-retasync-results:\n      event.create: EventCreateResult\n\
+             components:\n  schemas:\n    EventCreateResult:\n      type: object\n      \
+             required: [uid]\n      properties:\n        uid: {type: string}\n"
+                .to_string(),
+        ));
+        state.node_config.write().await.result_validation.policy = policy;
+        state
+    }
+
+    #[tokio::test]
+    async fn nonconforming_results_are_accepted_by_default() {
+        let state = result_schema_node(MockResults::Invalid, ResultPolicy::default()).await;
+        let router = build_router(state.clone());
+        let mut events = state.sse_bus.subscribe();
+
+        let job = settled_job(&router, submit_event(&router, "evt-1").await).await;
+        assert_eq!(job["status"], "success");
+        assert!(job.get("result_nonconforming").is_none(), "{job}");
+        assert!(job.get("result_errors").is_none(), "{job}");
+        let stored = json_body(job_result(&router, &job).await).await;
+        assert_eq!(stored["result_json"], r#"{"uid":5}"#);
+        while let Ok(event) = events.try_recv() {
+            assert_ne!(event.event_type, RESULT_NONCONFORMING_EVENT);
+        }
+    }
+
+    #[tokio::test]
+    async fn flagged_results_are_stored_with_their_violations() {
+        let state = result_schema_node(MockResults::Invalid, ResultPolicy::Flag).await;
+        let router = build_router(state.clone());
+        let mut events = state.sse_bus.subscribe();
+
+        let job = settled_job(&router, submit_event(&router, "evt-1").await).await;
+        assert_eq!(job["status"], "success");
+        assert_eq!(job["result_nonconforming"], true);
+        assert_eq!(
+            job["result_errors"],
+            json!([{ "pointer": "/uid", "message": "expected string, got integer" }])
+        );
+        let stored = json_body(job_result(&router, &job).await).await;
+        assert_eq!(stored["result_json"], r#"{"uid":5}"#);
+        let flagged = std::iter::from_fn(|| events.try_recv().ok())
+            .find(|event| event.event_type == RESULT_NONCONFORMING_EVENT)
+            .expect("nonconforming event");
+        assert_eq!(flagged.data["job_id"], job["job_id"]);
+        assert_eq!(flagged.data["schema"], "EventCreateResult");
+        assert_eq!(flagged.data["errors"], job["result_errors"]);
+
+        // A conforming answer carries no marker.
+        let echoed = result_schema_node(MockResults::Echo, ResultPolicy::Flag).await;
+        let router = build_router(echoed);
+        let job = settled_job(&router, submit_event(&router, "evt-2").await).await;
+        assert_eq!(job["status"], "success");
+        assert!(job.get("result_nonconforming").is_none(), "{job}");
+    }
+
+    #[tokio::test]
+    async fn quarantined_results_fail_the_job_and_keep_the_raw_result() {
+        let state = result_schema_node(MockResults::Invalid, ResultPolicy::Quarantine).await;
+        let router = build_router(state.clone());
+
+        let job = settled_job(&router, submit_event(&router, "evt-1").await).await;
+        assert_eq!(job["status"], "failed");
+        let reason = job["failure_reason"].as_str().unwrap();
+        assert!(reason.starts_with(INVALID_RESULT_REASON), "{reason}");
+        assert!(reason.contains("/uid expected string, got integer"), "{reason}");
+        assert_eq!(job["result_nonconforming"], true);
+        assert_eq!(job["result_errors"][0]["pointer"], "/uid");
+        assert_eq!(job_result(&router, &job).await.status(), StatusCode::NOT_FOUND);
+
+        let listed = json_body(
+            send(
+                &router,
+                Request::get("/v1/admin/storage/quarantine").body(Body::empty()).unwrap(),
+            )
+            .await,
+        )
+        .await;
+        assert_eq!(listed["rows"][0]["source_table"], "job_results");
+        assert_eq!(listed["rows"][0]["row_key"], job["job_id"]);
+        assert_eq!(listed["rows"][0]["raw_base64"], STANDARD.encode(r#"{"uid":5}"#));
+    }
+
+    #[tokio::test]
+    async fn storage_rebuild_waits_for_idle_workers_unless_muted() {
+        let state = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        let router = build_router(state.clone());
+        let rebuild = |query: &str| {
+            Request::post(format!("/v1/admin/storage/rebuild{query}"))
+                .body(Body::empty())
+                .unwrap()
+        };
+        let job = state.storage.create_job("event.create", json!({})).await.unwrap();
+        state
+            .storage
+            .acquire_job_lease(
+                &job.job_id,
+                "worker-1",
+                chrono::Utc::now() + chrono::Duration::minutes(5),
+            )
+            .await
+            .unwrap();
+
+        let (status, _) = get_json(&router, "/v1/admin/storage/rebuild").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let unknown = send(&router, rebuild("?scope=feed,counters")).await;
+        assert_eq!(unknown.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(json_body(unknown).await["scope"], "counters");
+        let refused = send(&router, rebuild("")).await;
+        assert_eq!(refused.status(), StatusCode::CONFLICT);
+        let refused = json_body(refused).await;
+        assert_eq!(refused["error"], "storage_rebuild_refused");
+        assert_eq!(refused["leased_jobs"], 1);
+
+        // Muting only commands still leaves inbound work and events flowing.
+        let response = send(&router, mute_request(json!({ "scope": "commands" }))).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(send(&router, rebuild("")).await.status(), StatusCode::CONFLICT);
+        unmute_node(&router).await;
+
+        let mut events = state.sse_bus.subscribe();
+        let response = send(&router, mute_request(json!({}))).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let accepted = send(&router, rebuild("?scope=feed,orphans")).await;
+        assert_eq!(accepted.status(), StatusCode::ACCEPTED);
+        let accepted = json_body(accepted).await;
+        assert_eq!(accepted["scopes"], json!(["feed", "orphans"]));
+        let mut run = accepted;
+        for _ in 0..400 {
+            if run["status"] != "running" {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+            run = get_json(&router, "/v1/admin/storage/rebuild").await.1;
+        }
+        assert_eq!(run["status"], "completed");
+        assert_eq!(run["found"], 0);
+        assert_eq!(run["reports"].as_array().unwrap().len(), 2);
+        let mut seen = Vec::new();
+        while let Ok(update) = events.try_recv() {
+            seen.push(update.event_type);
+        }
+        assert!(seen.ends_with(&[
+            "storage.rebuild.progress".to_string(),
+            "storage.rebuild.progress".to_string(),
+            "storage.rebuild.completed".to_string(),
+        ]));
+    }
+
+    #[tokio::test]
+    async fn client_principals_only_count_when_the_listener_attached_them() {
+        let mut state = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        state.require_bearer = true;
+        state
+            .node_config
+            .write()
+            .await
+            .tls_principals
+            .insert("ops-console".to_string(), "admin".to_string());
+        let router = build_router(state);
+        let add = |principal: Option<ClientPrincipal>| {
+            let mut request = Request::builder()
+                .method("POST")
+                .uri("/v1/security/allowlist")
+                .header(header::CONTENT_TYPE, "application/json")
+                .header(CLIENT_PRINCIPAL_HEADER, "ops-console")
+                .body(Body::from(json!({ "identity_hash": PEER }).to_string()))
+                .unwrap();
+            if let Some(principal) = principal {
+                request.extensions_mut().insert(principal);
+            }
+            request
+        };
+
+        let forged = send(&router, add(None)).await;
+        assert_eq!(forged.status(), StatusCode::UNAUTHORIZED);
+
+        let verified = ClientPrincipal {
+            names: vec!["CN=ops-console".to_string(), "ops-console".to_string()],
+        };
+        let attached = send(&router, add(Some(verified))).await;
+        assert_eq!(attached.status(), StatusCode::CREATED);
+        assert_eq!(json_body(attached).await["entry"]["status"], "active");
+    }
+
+    #[tokio::test]
+    async fn listeners_decide_whether_writes_need_a_bearer_token() {
+        let mut state = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        state.require_bearer = true;
+        state.node_config.write().await.http_auth_token = Some("secret".to_string());
+        let router = build_router(state);
+        let add = |identity_hash: &str, listener: Option<(&str, bool)>| {
+            let mut request = Request::builder()
+                .method("POST")
+                .uri("/v1/security/allowlist")
+                .header(header::CONTENT_TYPE, "application/json")
+                .header("x-retasync-listener-auth", "optional")
+                .body(Body::from(json!({ "identity_hash": identity_hash }).to_string()))
+                .unwrap();
+            if let Some((name, require_bearer)) = listener {
+                request.extensions_mut().insert(RequestListener {
+                    name: name.to_string(),
+                    require_bearer,
+                });
+            }
+            request
+        };
+
+        const ALPHA: &str = "a1fa0000000000000000000000000001";
+        const BRAVO: &str = "b4a0000000000000000000000000000b";
+        const CHARLIE: &str = "c4a0000000000000000000000000000c";
+        let forged = send(&router, add(ALPHA, None)).await;
+        assert_eq!(forged.status(), StatusCode::UNAUTHORIZED);
+        let tcp = send(&router, add(BRAVO, Some(("public", true)))).await;
+        assert_eq!(tcp.status(), StatusCode::UNAUTHORIZED);
+        let unix = send(&router, add(CHARLIE, Some(("local", false)))).await;
+        assert_eq!(unix.status(), StatusCode::CREATED);
+        assert_eq!(json_body(unix).await["entry"]["status"], "active");
+    }
+
+    #[tokio::test]
+    async fn aggregates_bucket_cached_events_and_jobs_in_utc() {
+        let state = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        let router = build_router(state.clone());
+        let seeded = [
+            ("evt-early", "event.created", "peer-a", "2026-03-01T09:59:59Z"),
+            ("evt-1", "event.created", "peer-a", "2026-03-01T10:00:00Z"),
+            ("evt-2", "event.created", "peer-a", "2026-03-01T10:59:59Z"),
+            ("evt-3", "event.updated", "peer-b", "2026-03-01T11:00:00Z"),
+            ("evt-4", "event.deleted", "peer-c", "2026-03-01T11:30:00Z"),
+            ("evt-5", "event.created", "peer-b", "2026-03-01T12:15:00Z"),
+        ];
+        for (event_id, event, source, sent_at) in seeded {
+            let sent_at = sent_at.parse().unwrap();
+            state
+                .storage
+                .cache_event(event_id, event, source, sent_at, &json!({}))
+                .await
+                .unwrap();
+        }
+        let aggregate = |query: &str| {
+            let uri = format!("/v1/cache/events/aggregate?{query}");
+            let router = router.clone();
+            async move { send(&router, Request::get(uri).body(Body::empty()).unwrap()).await }
+        };
+        let window = "since=2026-03-01T10:00:00Z&until=2026-03-01T13:00:00Z";
+
+        let by_event = json_body(aggregate(window).await).await;
+        assert_eq!(
+            by_event["buckets"],
+            json!(["2026-03-01T10:00:00Z", "2026-03-01T11:00:00Z", "2026-03-01T12:00:00Z"])
+        );
+        assert_eq!(by_event["groups"][0], "event.created");
+        assert_eq!(by_event["series"]["event.created"], json!([2, 0, 1]));
+        assert_eq!(by_event["series"]["event.updated"], json!([0, 1, 0]));
+        assert_eq!(by_event["total"], 5);
+
+        let top = json_body(aggregate(&format!("{window}&top=1")).await).await;
+        assert_eq!(top["groups"], json!(["event.created", "_other"]));
+        assert_eq!(top["series"]["_other"], json!([0, 2, 0]));
+
+        let offset = "since=2026-03-01T12:00:00%2B02:00&until=2026-03-01T15:00:00%2B02:00";
+        let shifted = json_body(aggregate(&format!("{offset}&group_by=source")).await).await;
+        assert_eq!(shifted["buckets"][0], "2026-03-01T10:00:00Z");
+        assert_eq!(shifted["series"]["peer-a"], json!([2, 0, 0]));
+        assert_eq!(shifted["series"]["peer-b"], json!([0, 1, 1]));
+
+        let too_fine = aggregate(&format!("{window}&bucket=1s")).await;
+        assert_eq!(too_fine.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(json_body(too_fine).await["error"], "too_many_buckets");
+        let unknown = aggregate("group_by=operation").await;
+        assert_eq!(unknown.status(), StatusCode::BAD_REQUEST);
+
+        let failed = state.storage.create_job("event.create", json!({})).await.unwrap();
+        state.storage.fail_job(&failed.job_id, "boom").await.unwrap();
+        state.storage.create_job("event.create", json!({})).await.unwrap();
+        let jobs = json_body(
+            send(
+                &router,
+                Request::get("/v1/jobs/aggregate?bucket=1d").body(Body::empty()).unwrap(),
+            )
+            .await,
+        )
+        .await;
+        assert_eq!(jobs["group_by"], "status");
+        assert_eq!(jobs["total"], 2);
+        let sum = |group: &str| jobs["series"][group].as_array().unwrap().iter().fold(0, |sum, n| {
+            sum + n.as_i64().unwrap()
+        });
+        assert_eq!((sum("queued"), sum("failed")), (1, 1));
+    }
+
+    fn probed_command(destination: &str) -> Request<Body> {
+        Request::post("/v1/jobs/commands/event.create?probe=true")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(json!({ "destination_identity": destination }).to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn liveness_checks_run_before_dispatch() {
+        const STALE: &str = "ee00000000000000000000000000000e";
+        const UNKNOWN: &str = "ff00000000000000000000000000000f";
+        let path = scratch_dir().join("bridge.rec");
+        let simulation = Arc::new(SimulatedMeshBridge::new(
+            Arc::new(InMemoryRpcMeshBridge::new(true, true)),
+            SimulationProfile::default(),
+        ));
+        let recorder = Arc::new(RecordingBridge::start(simulation.clone(), &path).unwrap());
+        let state = test_state(recorder.clone()).await;
+        let router = build_router(state.clone());
+        let an_hour_ago = chrono::Utc::now() - chrono::Duration::hours(1);
+        state.peers.record_contact(&STALE.parse().unwrap(), an_hour_ago);
+
+        let stale = settled_command(
+            &router,
+            "event.create",
+            json!({ "destination_identity": STALE, "require_recent_contact_s": 60 }),
+        )
+        .await;
+        assert_eq!(stale["status"], "failed");
+        assert_eq!(
+            stale["failure_reason"],
+            format!(
+                "destination_not_recently_seen: last seen {} (window 60s)",
+                an_hour_ago.to_rfc3339()
+            )
+        );
+
+        let probed = settled_job(&router, send(&router, probed_command(STALE)).await).await;
+        assert_eq!(probed["status"], "success");
+        let dispatch: serde_json::Value =
+            serde_json::from_str(probed["dispatch_json"].as_str().unwrap()).unwrap();
+        assert_eq!(dispatch["liveness"]["probe_outcome"]["ok"], true);
+        assert!(state.peers.last_seen(STALE).unwrap() > an_hour_ago);
+        let stored = state.storage.list_peer_last_seen().await.unwrap();
+        assert!(stored.iter().any(|(identity, _)| identity == STALE));
+
+        let oversized = send(
+            &router,
+            Request::post("/v1/jobs/commands/event.create")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(
+                    json!({ "destination_identity": STALE, "require_recent_contact_s": u64::MAX })
+                        .to_string(),
+                ))
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(oversized.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(json_body(oversized).await["error"], "invalid_require_recent_contact_s");
+
+        simulation.set_profile(SimulationProfile {
+            loss: LossProfile {
+                send_command: 1.0,
+                ..LossProfile::default()
+            },
+            ..SimulationProfile::default()
+        });
+        let unreachable = settled_job(&router, send(&router, probed_command(STALE)).await).await;
+        assert_eq!(unreachable["status"], "failed");
+        let reason = unreachable["failure_reason"].as_str().unwrap();
+        assert!(reason.starts_with("destination_probe_failed: "), "{reason}");
+        let dispatch: serde_json::Value =
+            serde_json::from_str(unreachable["dispatch_json"].as_str().unwrap()).unwrap();
+        assert_eq!(dispatch["liveness"]["probe_outcome"]["ok"], false);
+
+        simulation.set_profile(SimulationProfile::default());
+        state.node_config.write().await.liveness.allow_unknown_peers = true;
+        let bypassed = settled_command(
+            &router,
+            "event.create",
+            json!({ "destination_identity": UNKNOWN, "require_recent_contact_s": 60 }),
+        )
+        .await;
+        assert_eq!(bypassed["status"], "success");
+        let dispatch: serde_json::Value =
+            serde_json::from_str(bypassed["dispatch_json"].as_str().unwrap()).unwrap();
+        assert_eq!(dispatch["liveness"]["bypassed"], true);
+
+        recorder.flush().await;
+        let sent: Vec<String> = read_recording(&path)
+            .unwrap()
+            .iter()
+            .filter(|record| record.method == "send_command")
+            .map(|record| {
+                let command: MeshCommandEnvelope<serde_json::Value> =
+                    decode_canonical(&record.request).unwrap();
+                command.operation
+            })
+            .filter(|operation| operation != "node.hello")
+            .collect();
+        assert_eq!(sent, ["node.ping", "event.create", "node.ping", "event.create"]);
+    }
+
+    #[cfg(feature = "entities")]
+    fn sync_request(peer: &str) -> Request<Body> {
+        Request::post("/v1/entities/eam/sync")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(
+                json!({ "peer": peer, "max_response_size": 600 }).to_string(),
+            ))
+            .unwrap()
+    }
+
+    #[cfg(feature = "entities")]
+    #[tokio::test]
+    async fn entity_sync_converges_divergent_stores_over_a_mesh_link() {
+        let (local, remote) = LoopbackMeshBridge::pair();
+        let peer = contract_node(remote, "1.2.0").await;
+        let worker = spawn_inbound_worker(peer.clone(), Duration::from_millis(5));
+        let node = contract_node(local, "1.2.0").await;
+        let router = build_router(node.clone());
+        let base = chrono::Utc::now() - chrono::Duration::hours(1);
+        let eam = |idx: usize, minutes: i64, status: &str| EntityRecord {
+            entity_type: "eam".to_string(),
+            entity_id: format!("{idx:x}-eam"),
+            updated_at: base + chrono::Duration::minutes(minutes),
+            record: json!({ "status": status, "unit": format!("team-{idx}") }),
+            // Every record after the first write has been edited once.
+            version: 1 + i64::from(minutes > 0),
+            deleted_at: None,
+        };
+        for idx in 0..48 {
+            node.storage.put_entity(&eam(idx, 0, "green")).await.unwrap();
+            peer.storage.put_entity(&eam(idx, 0, "green")).await.unwrap();
+        }
+        for idx in 100..104 {
+            node.storage.put_entity(&eam(idx, 5, "yellow")).await.unwrap();
+        }
+        for idx in 200..204 {
+            peer.storage.put_entity(&eam(idx, 5, "yellow")).await.unwrap();
+        }
+        node.storage.put_entity(&eam(7, 20, "red")).await.unwrap();
+        peer.storage.put_entity(&eam(7, 30, "amber")).await.unwrap();
+
+        let refused = send(&router, sync_request(PEER)).await;
+        assert_eq!(refused.status(), StatusCode::BAD_GATEWAY);
+        let error = json_body(refused).await["detail"].as_str().unwrap().to_string();
+        assert!(error.contains("requester_not_allowlisted"), "{error}");
+        let requester = crate::dispatch::local_identity(&*node.node_config.read().await);
+        peer.storage.add_allowlist(&requester, None).await.unwrap();
+
+        let response = send(&router, sync_request(PEER)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let report = json_body(response).await;
+        assert_eq!(report["converged"], true);
+        let rounds = report["rounds"].as_u64().unwrap();
+        assert!((3..=6).contains(&rounds), "{report}");
+        assert_eq!((report["pulled"].as_u64(), report["pushed"].as_u64()), (Some(5), Some(4)));
+        assert_eq!(report["conflicted"], 1);
+        let ours = node.storage.list_entities("eam", "").await.unwrap();
+        assert_eq!(ours, peer.storage.list_entities("eam", "").await.unwrap());
+        assert_eq!(ours.len(), 56);
+
+        let conflicts = json_body(
+            send(
+                &router,
+                Request::get("/v1/entities/eam/conflicts")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await,
+        )
+        .await;
+        let conflict = &conflicts["conflicts"][0];
+        assert_eq!(conflicts["conflicts"].as_array().unwrap().len(), 1);
+        assert_eq!(
+            (conflict["entity_id"].as_str(), conflict["kept"].as_str()),
+            (Some("7-eam"), Some("remote"))
+        );
+        assert_eq!(conflict["local"]["record"]["status"], "red");
+
+        // Once the stores have converged, a change made on one side is an update, not a conflict.
+        let edited = peer
+            .storage
+            .update_entity("eam", "9-eam", &json!({ "status": "black" }), 1)
+            .await
+            .unwrap();
+        let report = json_body(send(&router, sync_request(PEER)).await).await;
+        assert_eq!((report["pulled"].as_u64(), report["conflicted"].as_u64()), (Some(1), Some(0)));
+        let pulled = node.storage.get_entity("eam", "9-eam").await.unwrap().unwrap();
+        assert_eq!((&pulled.record, pulled.version), (&edited.record, 2));
+        assert_eq!(Some(pulled), peer.storage.get_entity("eam", "9-eam").await.unwrap());
+        worker.abort();
+    }
+
+    #[cfg(feature = "entities")]
+    #[tokio::test]
+    async fn entity_sync_holds_pushed_records_to_the_clock_skew_allowance() {
+        let (local, remote) = LoopbackMeshBridge::pair();
+        let peer = contract_node(remote, "1.2.0").await;
+        let worker = spawn_inbound_worker(peer.clone(), Duration::from_millis(5));
+        let node = contract_node(local, "1.2.0").await;
+        let router = build_router(node.clone());
+        let requester = crate::dispatch::local_identity(&*node.node_config.read().await);
+        peer.storage.add_allowlist(&requester, None).await.unwrap();
+        let skew = peer.node_config.read().await.clock.max_skew_tolerance_secs;
+        let far_future = chrono::Utc::now() + chrono::Duration::days(30);
+        node.storage
+            .put_entity(&EntityRecord {
+                entity_type: "eam".to_string(),
+                entity_id: "1-eam".to_string(),
+                updated_at: far_future,
+                record: json!({ "status": "red" }),
+                version: 1,
+                deleted_at: None,
+            })
+            .await
+            .unwrap();
+
+        // The peer keeps its held-back copy, so the two never hash alike.
+        let report = json_body(send(&router, sync_request(PEER)).await).await;
+        assert_eq!(report["converged"], false);
+        worker.abort();
+        let stored = peer.storage.get_entity("eam", "1-eam").await.unwrap().unwrap();
+        assert_eq!(stored.record, json!({ "status": "red" }));
+        let latest = chrono::Utc::now() + chrono::Duration::seconds(skew as i64);
+        assert!(stored.updated_at <= latest, "{}", stored.updated_at);
+    }
+
+    #[cfg(feature = "entities")]
+    fn entity_request(method: &str, if_match: Option<i64>, body: Option<Value>) -> Request<Body> {
+        let mut request = Request::builder()
+            .method(method)
+            .uri("/v1/entities/eam/4-eam")
+            .header(header::CONTENT_TYPE, "application/json");
+        if let Some(version) = if_match {
+            request = request.header(header::IF_MATCH, format!("\"{version}\""));
+        }
+        request
+            .body(body.map_or_else(Body::empty, |body| Body::from(body.to_string())))
+            .unwrap()
+    }
+
+    #[cfg(feature = "entities")]
+    #[tokio::test]
+    async fn entity_writes_name_their_version_and_stale_ones_get_409() {
+        let state = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        let router = build_router(state.clone());
+
+        let created = send(
+            &router,
+            entity_request("PUT", None, Some(json!({ "status": "green" }))),
+        )
+        .await;
+        assert_eq!(created.status(), StatusCode::CREATED);
+        assert_eq!(created.headers()[header::ETAG], "\"1\"");
+        let updated = entity_request("PUT", Some(1), Some(json!({ "status": "amber" })));
+        let updated = send(&router, updated).await;
+        assert_eq!(updated.status(), StatusCode::OK);
+        assert_eq!(updated.headers()[header::ETAG], "\"2\"");
+
+        // A second writer still holding version 1 is told where the entity stands now.
+        let stale = send(
+            &router,
+            entity_request("PUT", Some(1), Some(json!({ "status": "red" }))),
+        )
+        .await;
+        assert_eq!(stale.status(), StatusCode::CONFLICT);
+        let conflict = json_body(stale).await;
+        assert_eq!(conflict["error"], "version_conflict");
+        assert_eq!(
+            (
+                conflict["expected_version"].as_i64(),
+                conflict["current_version"].as_i64()
+            ),
+            (Some(1), Some(2))
+        );
+        assert_eq!(conflict["current"]["record"]["status"], "amber");
+
+        let unguarded = send(&router, entity_request("DELETE", None, None)).await;
+        assert_eq!(unguarded.status(), StatusCode::PRECONDITION_REQUIRED);
+        let malformed = Request::delete("/v1/entities/eam/4-eam")
+            .header(header::IF_MATCH, "W/\"2\"")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(
+            send(&router, malformed).await.status(),
+            StatusCode::BAD_REQUEST
+        );
+        let deleted = send(&router, entity_request("DELETE", Some(2), None)).await;
+        assert_eq!(deleted.status(), StatusCode::OK);
+        assert_eq!(deleted.headers()[header::ETAG], "\"3\"");
+        let (status, _) = get_json(&router, "/v1/entities/eam/4-eam").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let tombstone = state
+            .storage
+            .get_entity("eam", "4-eam")
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(tombstone.deleted_at.is_some());
+
+        // The store-level error maps to the same envelope an entity handler answers a peer with.
+        let error = state
+            .storage
+            .update_entity("eam", "4-eam", &json!({}), 2)
+            .await
+            .unwrap_err();
+        let result = crate::entity_sync::version_conflict_result(&error).unwrap();
+        assert_eq!(
+            (
+                result["status"].as_str(),
+                result["current_version"].as_i64()
+            ),
+            (Some("error"), Some(3))
+        );
+    }
+
+    #[cfg(feature = "transfers")]
+    fn upload_request(content: &[u8], dedup: bool) -> Request<Body> {
+        Request::post("/v1/jobs/transfers/upload")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(
+                json!({
+                    "destination_identity": PEER,
+                    "file_name": "tile.png",
+                    "media_type": "image/png",
+                    "payload_base64": STANDARD.encode(content),
+                    "dedup": dedup,
+                })
+                .to_string(),
+            ))
+            .unwrap()
+    }
+
+    #[cfg(feature = "transfers")]
+    async fn settled_transfer(router: &Router, content: &[u8], dedup: bool) -> serde_json::Value {
+        let accepted = json_body(send(router, upload_request(content, dedup)).await).await;
+        let transfer_id = accepted["transfer_id"].as_str().unwrap().to_string();
+        for _ in 0..400 {
+            let (_, transfer) = get_json(router, &format!("/v1/transfers/{transfer_id}")).await;
+            if !matches!(transfer["status"].as_str(), Some("queued" | "running")) {
+                return transfer;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        panic!("transfer {transfer_id} never settled");
+    }
+
+    #[cfg(feature = "transfers")]
+    #[tokio::test]
+    async fn uploads_past_the_memory_threshold_are_spooled_and_sent_whole() {
+        let (local, remote) = LoopbackMeshBridge::pair();
+        let node = contract_node(local, "1.2.0").await;
+        node.node_config.write().await.transfer_spool = crate::spool::TransferSpoolSettings {
+            max_request_bytes: 64 * 1024,
+            memory_threshold_bytes: 40_000,
+            ..Default::default()
+        };
+        let spool_dir = crate::spool::TransferSpoolSettings::default()
+            .dir_for(node.storage.database_path());
+        let router = build_router(node);
+        let content: Vec<u8> = (0..40_001u32).map(|byte| byte as u8).collect();
+
+        let sent = settled_transfer(&router, &content, false).await;
+        assert_eq!(sent["status"], "success");
+        assert_eq!(sent["progress"]["chunks_sent"], 2);
+        let mut delivered = Vec::new();
+        for chunk in remote.poll_transfers(10).await.unwrap() {
+            let encoded = chunk.payload["payload_base64"].as_str().unwrap();
+            delivered.extend(STANDARD.decode(encoded).unwrap());
+        }
+        assert_eq!(delivered, content);
+        let mut spool = json!(null);
+        for _ in 0..200 {
+            spool = get_json(&router, "/v1/node/status").await.1["transfer_spool"].take();
+            if spool["spooled_files"] == 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        assert_eq!(
+            (spool["spooled_uploads"].as_u64(), spool["memory_uploads"].as_u64()),
+            (Some(1), Some(0))
+        );
+        assert_eq!(
+            (spool["spooled_files"].as_u64(), spool["memory_bytes"].as_u64()),
+            (Some(0), Some(0))
+        );
+        assert_eq!(std::fs::read_dir(&spool_dir).unwrap().count(), 0);
+
+        // Past the request limit the body is refused, whether or not its length is declared.
+        let declared = Request::post("/v1/jobs/transfers/upload")
+            .header(header::CONTENT_LENGTH, 64 * 1024 + 1)
+            .body(Body::from("{}"))
+            .unwrap();
+        let response = send(&router, declared).await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(json_body(response).await["error"], "upload_too_large");
+        let undeclared = send(&router, upload_request(&[0; 50_000], false)).await;
+        assert_eq!(undeclared.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[cfg(feature = "transfers")]
+    #[tokio::test]
+    async fn uploads_skip_files_the_destination_already_holds() {
+        let (local, remote) = LoopbackMeshBridge::pair();
+        let peer = contract_node(remote, "1.2.0").await;
+        let node = contract_node(local, "1.2.0").await;
+        set_feature(&peer, TRANSFER_DEDUP_FLAG, true).await;
+        set_feature(&node, TRANSFER_DEDUP_FLAG, true).await;
+        let worker = spawn_inbound_worker(peer.clone(), Duration::from_millis(5));
+        let router = build_router(node);
+        let content = b"tile bytes the peer has not seen yet";
+        let sha256 = format!("{:x}", Sha256::digest(content));
+
+        let novel = settled_transfer(&router, content, true).await;
+        assert_eq!(novel["status"], "success");
+        assert_eq!(novel["progress"]["bytes_sent"], content.len());
+        assert_eq!(
+            (novel["dedup"]["outcome"].as_str(), novel["dedup"]["bytes_saved"].as_u64()),
+            (Some("sent"), Some(0))
+        );
+        assert_eq!(novel["dedup"]["remote_ack"]["status"], "need");
+        let mut held = None;
+        for _ in 0..200 {
+            held = peer
+                .storage
+                .find_received_file(&sha256, content.len() as i64)
+                .await
+                .unwrap();
+            if held.is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        assert_eq!(held.map(|file| file.file_name).as_deref(), Some("tile.png"));
+
+        let duplicate = settled_transfer(&router, content, true).await;
+        assert_eq!(duplicate["status"], "skipped_duplicate");
+        assert_eq!(duplicate["progress"]["bytes_sent"], 0);
+        assert_eq!(duplicate["dedup"]["bytes_saved"], content.len());
+        assert_eq!(duplicate["dedup"]["remote_ack"]["status"], "have");
+        assert_eq!(duplicate["dedup"]["sha256"], sha256);
+
+        let forced = settled_transfer(&router, content, false).await;
+        assert_eq!(forced["status"], "success");
+        assert!(forced.get("dedup").is_none());
+
+        let (_, status) = get_json(&router, "/v1/node/status").await;
+        assert_eq!(
+            status["transfer_dedup"],
+            json!({ "offers": 2, "hits": 1, "fallbacks": 0, "bytes_avoided": content.len() })
+        );
+        worker.abort();
+    }
+
+    #[cfg(feature = "transfers")]
+    fn bundle_request(files: &[(&str, &[u8])]) -> Request<Body> {
+        let files: Vec<_> = files
+            .iter()
+            .map(|(name, content)| {
+                json!({
+                    "name": name,
+                    "media_type": "image/jpeg",
+                    "content_base64": STANDARD.encode(content),
+                })
+            })
+            .collect();
+        Request::post("/v1/jobs/transfers/bundle")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(
+                json!({
+                    "destination_identity": PEER,
+                    "bundle_name": "patrol-photos",
+                    "metadata": { "mission": "ALPHA" },
+                    "files": files,
+                })
+                .to_string(),
+            ))
+            .unwrap()
+    }
+
+    // The peer's transfer row for a bundle, found through a member it recorded as received.
+    #[cfg(feature = "transfers")]
+    async fn received_bundle(peer: &AppState, member: &[u8]) -> Value {
+        let sha256 = format!("{:x}", Sha256::digest(member));
+        for _ in 0..400 {
+            let held = peer
+                .storage
+                .find_received_file(&sha256, member.len() as i64)
+                .await
+                .unwrap();
+            if let Some(bundle_id) = held.and_then(|file| file.bundle_id) {
+                let router = build_router(peer.clone());
+                return get_json(&router, &format!("/v1/transfers/{bundle_id}")).await.1;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        panic!("peer never received the bundle");
+    }
+
+    #[cfg(feature = "transfers")]
+    #[tokio::test]
+    async fn bundles_unpack_into_received_files_on_the_peer() {
+        let (local, remote) = LoopbackMeshBridge::pair();
+        let peer = contract_node(remote, "1.2.0").await;
+        let worker = spawn_inbound_worker(peer.clone(), Duration::from_millis(5));
+        let router = build_router(contract_node(local, "1.2.0").await);
+        let large = vec![7u8; retasync_transfer::DEFAULT_CHUNK_SIZE + 100];
+        let files: [(&str, &[u8]); 3] = [
+            ("manifest.json", br#"{"photos":2}"#),
+            ("photos/a.jpg", &large),
+            ("photos/b.jpg", b"second photo"),
+        ];
+
+        let accepted = json_body(send(&router, bundle_request(&files)).await).await;
+        let transfer_id = accepted["transfer_id"].as_str().unwrap().to_string();
+        let mut sent = Value::Null;
+        for _ in 0..400 {
+            sent = get_json(&router, &format!("/v1/transfers/{transfer_id}")).await.1;
+            if !matches!(sent["status"].as_str(), Some("queued" | "running")) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        assert_eq!(sent["status"], "success");
+        assert_eq!(sent["progress"]["chunks_sent"], 2);
+        let names: Vec<_> = sent["members"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|member| (member["name"].clone(), member["status"].clone()))
+            .collect();
+        assert_eq!(
+            names,
+            [
+                (json!("manifest.json"), json!("packed")),
+                (json!("photos/a.jpg"), json!("packed")),
+                (json!("photos/b.jpg"), json!("packed")),
+            ]
+        );
+        assert_eq!(sent["members"][1]["sha256"], format!("{:x}", Sha256::digest(&large)));
+
+        let received = received_bundle(&peer, b"second photo").await;
+        assert_eq!(received["status"], "success");
+        let metadata: Value =
+            serde_json::from_str(received["metadata_json"].as_str().unwrap()).unwrap();
+        assert_eq!(metadata["bundle_digest"], accepted["bundle_digest"]);
+        assert_eq!(metadata["bundle_metadata"]["mission"], "ALPHA");
+        let mut expected = sent["members"].clone();
+        for member in expected.as_array_mut().unwrap() {
+            member["status"] = json!("received");
+        }
+        assert_eq!(received["members"], expected);
+        for (_, content) in files {
+            let sha256 = format!("{:x}", Sha256::digest(content));
+            let file = peer
+                .storage
+                .find_received_file(&sha256, content.len() as i64)
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(file.bundle_id.as_deref(), received["transfer_id"].as_str());
+        }
+        worker.abort();
+    }
+
+    // Sends an already packed bundle as `transfer.upload` chunks, last chunk first.
+    #[cfg(feature = "transfers")]
+    async fn send_bundle(local: &LoopbackMeshBridge, bundle: &Bundle) {
+        let bytes = bundle.encode().unwrap();
+        let chunks: Vec<_> = bytes.chunks(bytes.len() / 2 + 1).collect();
+        for (chunk_index, chunk) in chunks.iter().enumerate().rev() {
+            local
+                .start_transfer(MeshTransferEnvelope {
+                    message_id: Uuid::now_v7().to_string(),
+                    correlation_id: Some("remote-bundle".to_string()),
+                    operation: "transfer.upload".to_string(),
+                    sent_at: chrono::Utc::now(),
+                    source_identity: "local-node".parse().unwrap(),
+                    destination_identity: PEER.parse().unwrap(),
+                    content_type: "application/msgpack".to_string(),
+                    direction: TransferDirection::Upload,
+                    payload: json!({
+                        "transfer_id": "remote-bundle",
+                        "file_name": "photos",
+                        "media_type": BUNDLE_MEDIA_TYPE,
+                        "chunk_index": chunk_index,
+                        "chunks_total": chunks.len(),
+                        "payload_base64": STANDARD.encode(chunk),
+                    }),
+                    ttl_ms: None,
+                    transport_hint: None,
+                })
+                .await
+                .unwrap();
+        }
+    }
+
+    #[cfg(feature = "transfers")]
+    #[tokio::test]
+    async fn a_damaged_member_leaves_the_bundle_partial() {
+        let (local, remote) = LoopbackMeshBridge::pair();
+        let peer = contract_node(remote, "1.2.0").await;
+        let worker = spawn_inbound_worker(peer.clone(), Duration::from_millis(5));
+        let mut bundle = Bundle::pack(
+            serde_json::Map::new(),
+            vec![
+                BundleEntry::new("a.jpg", "image/jpeg", b"first intact".to_vec()),
+                BundleEntry::new("b.jpg", "image/jpeg", b"damaged in transit".to_vec()),
+                BundleEntry::new("c.jpg", "image/jpeg", b"second intact".to_vec()),
+            ],
+        )
+        .unwrap();
+        bundle.entries[1].bytes[0] ^= 0xff;
+        send_bundle(&local, &bundle).await;
+
+        let received = received_bundle(&peer, b"first intact").await;
+        assert_eq!(received["status"], "partial");
+        assert_eq!(received["failure_reason"], "1 member(s) failed their hash");
+        let statuses: Vec<_> = received["members"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|member| member["status"].as_str().unwrap())
+            .collect();
+        assert_eq!(statuses, ["received", "failed", "received"]);
+        let damaged = &bundle.entries[1];
+        assert!(peer
+            .storage
+            .find_received_file(&damaged.sha256, damaged.bytes.len() as i64)
+            .await
+            .unwrap()
+            .is_none());
+        let intact = format!("{:x}", Sha256::digest(b"second intact"));
+        assert!(peer.storage.find_received_file(&intact, 13).await.unwrap().is_some());
+        worker.abort();
+    }
+
+    // Refuses anything named like an executable, and remembers what it was shown.
+    #[cfg(feature = "transfers")]
+    #[derive(Default)]
+    struct ExecutableScanner {
+        seen: std::sync::Mutex<Vec<(String, Option<&'static str>, String)>>,
+    }
+
+    #[cfg(feature = "transfers")]
+    impl crate::files::ContentInspector for ExecutableScanner {
+        fn inspect(&self, file: &crate::files::InspectedFile<'_>) -> Result<(), String> {
+            self.seen.lock().unwrap().push((
+                file.name.to_string(),
+                file.detected_media_type,
+                file.source_identity.to_string(),
+            ));
+            if file.name.ends_with(".exe") {
+                return Err("executable".to_string());
+            }
+            Ok(())
+        }
+    }
+
+    #[cfg(feature = "transfers")]
+    #[tokio::test]
+    async fn policy_violations_quarantine_members_out_of_sight() {
+        const PNG: &[u8] = b"\x89PNG\r\n\x1a\nIHDR";
+        let (local, remote) = LoopbackMeshBridge::pair();
+        let scanner = Arc::new(ExecutableScanner::default());
+        let peer = contract_node(remote, "1.2.0")
+            .await
+            .with_content_inspector(scanner.clone());
+        peer.node_config.write().await.files.verify_magic = true;
+        let mut events = peer.sse_bus.subscribe();
+        let worker = spawn_inbound_worker(peer.clone(), Duration::from_millis(5));
+        let bundle = Bundle::pack(
+            serde_json::Map::new(),
+            vec![
+                BundleEntry::new("map.png", "image/png", PNG.to_vec()),
+                BundleEntry::new("report.pdf", "application/pdf", b"plain words".to_vec()),
+                BundleEntry::new("tool.exe", "application/octet-stream", b"MZ".to_vec()),
+            ],
+        )
+        .unwrap();
+        send_bundle(&local, &bundle).await;
+
+        let received = received_bundle(&peer, PNG).await;
+        assert_eq!(received["status"], "success");
+        let statuses: Vec<_> = received["members"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|member| member["status"].as_str().unwrap())
+            .collect();
+        assert_eq!(statuses, ["received", "quarantined", "quarantined"]);
+        // The mismatched PDF never reaches the inspector.
+        assert_eq!(
+            *scanner.seen.lock().unwrap(),
+            [
+                ("map.png".to_string(), Some("image/png"), "local-node".to_string()),
+                ("tool.exe".to_string(), Some("text/plain"), "local-node".to_string()),
+            ]
+        );
+        let mut quarantined = Vec::new();
+        while quarantined.len() < 2 {
+            let event = tokio::time::timeout(Duration::from_secs(2), events.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            if event.event_type == crate::files::FILE_QUARANTINED_EVENT {
+                quarantined.push((event.data["file_name"].clone(), event.data["reason"].clone()));
+            }
+        }
+        assert_eq!(
+            quarantined,
+            [
+                (json!("report.pdf"), json!("media_type_mismatch")),
+                (json!("tool.exe"), json!("rejected_by_inspector")),
+            ]
+        );
+        worker.abort();
+
+        let mut guarded = peer.clone();
+        guarded.require_bearer = true;
+        {
+            let mut config = guarded.node_config.write().await;
+            config.http_auth_token = Some("admin-secret".to_string());
+            config.api_tokens = vec![ApiToken {
+                label: "field".to_string(),
+                token: "field-secret".to_string(),
+            }];
+        }
+        let router = build_router(guarded);
+        let get = |uri: &str, token: &str| {
+            Request::get(uri)
+                .header(header::AUTHORIZATION, format!("Bearer {token}"))
+                .body(Body::empty())
+                .unwrap()
+        };
+        let names = |listing: Value| -> Vec<String> {
+            let mut names: Vec<String> = listing["files"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|file| file["file_name"].as_str().unwrap().to_string())
+                .collect();
+            names.sort();
+            names
+        };
+
+        let listed = send(&router, get("/v1/files", "field-secret")).await;
+        assert_eq!(names(json_body(listed).await), ["map.png"]);
+        let forbidden = send(&router, get("/v1/files?include_quarantined=true", "field-secret")).await;
+        assert_eq!(forbidden.status(), StatusCode::FORBIDDEN);
+        let listed = send(&router, get("/v1/files?include_quarantined=true", "admin-secret")).await;
+        let listing = json_body(listed).await;
+        assert_eq!(names(listing.clone()), ["map.png", "report.pdf", "tool.exe"]);
+        let report = listing["files"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|file| file["file_name"] == "report.pdf")
+            .unwrap();
+        assert_eq!(
+            report["quarantine_reason"],
+            "media_type_mismatch: declared application/pdf, content is text/plain"
+        );
+
+        let report_sha = &bundle.entries[1].sha256;
+        let hidden = send(&router, get(&format!("/v1/files/{report_sha}"), "admin-secret")).await;
+        assert_eq!(hidden.status(), StatusCode::NOT_FOUND);
+        assert_eq!(json_body(hidden).await["error"], "file_not_found");
+        let uri = format!("/v1/files/{report_sha}?include_quarantined=true");
+        let forbidden = send(&router, get(&uri, "field-secret")).await;
+        assert_eq!(forbidden.status(), StatusCode::FORBIDDEN);
+        let served = send(&router, get(&uri, "admin-secret")).await;
+        assert_eq!(served.status(), StatusCode::OK);
+        assert_eq!(served.headers()[header::CONTENT_TYPE], "application/pdf");
+        let body = axum::body::to_bytes(served.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"plain words");
+
+        let map_sha = &bundle.entries[0].sha256;
+        let served = send(&router, get(&format!("/v1/files/{map_sha}"), "field-secret")).await;
+        assert_eq!(served.status(), StatusCode::OK);
+        assert_eq!(served.headers()[header::CONTENT_TYPE], "image/png");
+        assert_eq!(
+            served.headers()[header::CONTENT_DISPOSITION],
+            "attachment; filename=\"map.png\""
+        );
+    }
+
+    #[cfg(feature = "transfers")]
+    #[tokio::test]
+    async fn mislabelled_uploads_are_refused_when_outbound_checks_are_on() {
+        let node = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        node.node_config.write().await.files.verify_outbound = true;
+        let router = build_router(node);
+
+        let response = send(&router, bundle_request(&[("a.jpg", b"not a jpeg")])).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(
+            json_body(response).await,
+            json!({
+                "error": "media_type_mismatch",
+                "file_name": "a.jpg",
+                "declared": "image/jpeg",
+                "detected": "text/plain",
+            })
+        );
+        let jpeg = b"\xff\xd8\xff\xe0 JFIF";
+        let response = send(&router, bundle_request(&[("a.jpg", jpeg)])).await;
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+    }
+
+    #[cfg(feature = "transfers")]
+    #[tokio::test]
+    async fn bundle_limits_apply_to_the_whole_bundle() {
+        let node = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        {
+            let mut config = node.node_config.write().await;
+            config.transfer_bundles.max_bundle_bytes = 512;
+            config.transfer_bundles.max_files = 2;
+        }
+        let router = build_router(node);
+
+        let large = [("a", &[1; 200][..]), ("b", &[2; 200])];
+        let response = send(&router, bundle_request(&large)).await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(json_body(response).await["error"], "bundle_too_large");
+
+        let three = [("a", &b"1"[..]), ("b", b"2"), ("c", b"3")];
+        let response = send(&router, bundle_request(&three)).await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(json_body(response).await["error"], "bundle_too_many_files");
+
+        let response = send(&router, bundle_request(&[("a", b"1"), ("a", b"2")])).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(json_body(response).await["error"], "invalid_bundle");
+
+        let response = send(&router, bundle_request(&[("a", &[1; 200])])).await;
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+    }
+
+    #[cfg(feature = "transfers")]
+    #[tokio::test]
+    async fn unanswered_offers_fall_back_to_a_full_send() {
+        let (local, _remote) = LoopbackMeshBridge::pair();
+        let node = contract_node(local, "1.2.0").await;
+        set_feature(&node, TRANSFER_DEDUP_FLAG, true).await;
+        node.node_config.write().await.transfer_dedup.offer_timeout_ms = 50;
+        let router = build_router(node);
+        let content = b"nobody is answering offers";
+
+        let transfer = settled_transfer(&router, content, true).await;
+        assert_eq!(transfer["status"], "success");
+        assert_eq!(transfer["progress"]["bytes_sent"], content.len());
+        assert_eq!(transfer["dedup"]["outcome"], "offer_unanswered");
+        assert!(transfer["dedup"]["remote_ack"].is_null());
+        let (_, status) = get_json(&router, "/v1/node/status").await;
+        assert_eq!(status["transfer_dedup"]["fallbacks"], 1);
+    }
+
+    #[cfg(feature = "transfers")]
+    async fn quota_node(max_bytes_per_destination: u64) -> (AppState, Router) {
+        let state = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        state.node_config.write().await.quotas.max_bytes_per_destination =
+            Some(max_bytes_per_destination);
+        let router = build_router(state.clone());
+        (state, router)
+    }
+
+    #[cfg(feature = "transfers")]
+    fn quota_of<'a>(report: &'a serde_json::Value, kind: &str) -> &'a serde_json::Value {
+        report["subjects"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|subject| subject["subject_kind"] == kind)
+            .expect("tracked subject")
+    }
+
+    #[cfg(feature = "transfers")]
+    #[tokio::test]
+    async fn uploads_past_the_destination_quota_are_rejected_until_overridden() {
+        let (state, router) = quota_node(100).await;
+
+        assert_eq!(settled_transfer(&router, &[1; 60], false).await["status"], "success");
+        let first_charge = state
+            .storage
+            .list_quota_usage("2000-01-01T00:00:00Z")
+            .await
+            .unwrap()
+            .remove(0);
+        // Landing exactly on the limit is still allowed.
+        assert_eq!(settled_transfer(&router, &[2; 40], false).await["status"], "success");
+        let (_, report) = get_json(&router, "/v1/security/quotas").await;
+        let destination = quota_of(&report, "destination");
+        assert_eq!(destination["subject"], PEER);
+        assert_eq!(
+            (destination["used_bytes"].as_u64(), destination["remaining_bytes"].as_u64()),
+            (Some(100), Some(0))
+        );
+        let token = quota_of(&report, "token");
+        assert_eq!(
+            (token["subject"].as_str(), token["used_bytes"].as_u64()),
+            (Some(crate::attribution::ANONYMOUS_LOOPBACK), Some(100))
+        );
+        assert_eq!(token["limit_bytes"], serde_json::Value::Null);
+
+        let rejected = send(&router, upload_request(&[3; 1], false)).await;
+        assert_eq!(rejected.status(), StatusCode::TOO_MANY_REQUESTS);
+        let retry_after: u64 = header_of(&rejected, header::RETRY_AFTER).unwrap().parse().unwrap();
+        let body = json_body(rejected).await;
+        assert_eq!(body["error"], "quota_exceeded");
+        assert_eq!(
+            (body["detail"]["used_bytes"].as_u64(), body["detail"]["limit_bytes"].as_u64()),
+            (Some(100), Some(100))
+        );
+        // The first upload's bucket frees up a day after the end of its hour.
+        let reset_at = CanonicalTimestamp::parse(&first_charge.used_at).unwrap().as_datetime()
+            + chrono::Duration::hours(25);
+        assert_eq!(
+            body["detail"]["reset_at"],
+            CanonicalTimestamp::from(reset_at).to_string()
+        );
+        // The advice runs out when the oldest usage ages out of the window.
+        let advice = RetryAdvice::from_body(&body).unwrap();
+        assert_eq!(advice.scope, RetryScope::Destination);
+        let expected = (reset_at - chrono::Utc::now()).num_milliseconds() as u64;
+        assert!(advice.retry_after_ms.abs_diff(expected) < 2000, "{advice:?}");
+        assert_eq!(retry_after, advice.retry_after_secs());
+
+        // An override for a token that happens to share the name leaves the destination alone.
+        let token_only = send(
+            &router,
+            Request::put(format!("/v1/security/quotas/{PEER}"))
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(
+                    json!({ "max_bytes": 1000, "subject_kind": "token" }).to_string(),
+                ))
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(token_only.status(), StatusCode::OK);
+        let rejected = send(&router, upload_request(&[3; 1], false)).await;
+        assert_eq!(rejected.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        let raised = send(
+            &router,
+            Request::put(format!("/v1/security/quotas/{PEER}"))
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(json!({ "max_bytes": 150, "note": "bulk sync" }).to_string()))
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(raised.status(), StatusCode::OK);
+        assert_eq!(settled_transfer(&router, &[4; 50], false).await["status"], "success");
+        let (_, report) = get_json(&router, "/v1/security/quotas").await;
+        let destination = quota_of(&report, "destination");
+        assert_eq!(destination["overridden"], true);
+        assert_eq!(
+            (destination["limit_bytes"].as_u64(), destination["used_bytes"].as_u64()),
+            (Some(150), Some(150))
+        );
+        let overrides: Vec<_> = report["overrides"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|entry| (entry["subject_kind"].clone(), entry["note"].clone()))
+            .collect();
+        assert_eq!(
+            overrides,
+            [(json!("destination"), json!("bulk sync")), (json!("token"), json!(null))]
+        );
+        let rejected = send(&router, upload_request(&[5; 1], false)).await;
+        assert_eq!(rejected.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[cfg(feature = "transfers")]
+    #[tokio::test]
+    async fn concurrent_uploads_cannot_share_the_same_budget() {
+        let (state, router) = quota_node(100).await;
+
+        // Each fits on its own; admission reserves the bytes, so only one of them is let in.
+        let (first, second) = tokio::join!(
+            send(&router, upload_request(&[1; 60], false)),
+            send(&router, upload_request(&[2; 60], false)),
+        );
+        let mut statuses = [first.status(), second.status()];
+        statuses.sort();
+        assert_eq!(statuses, [StatusCode::ACCEPTED, StatusCode::TOO_MANY_REQUESTS]);
+        for _ in 0..400 {
+            if state.storage.list_quota_reservations().await.unwrap().is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        let (_, report) = get_json(&router, "/v1/security/quotas").await;
+        let destination = quota_of(&report, "destination");
+        assert_eq!(
+            (destination["used_bytes"].as_u64(), destination["reserved_bytes"].as_u64()),
+            (Some(60), Some(0))
+        );
+    }
+
+    #[cfg(feature = "transfers")]
+    #[tokio::test]
+    async fn usage_that_rolls_out_of_the_window_frees_budget() {
+        let (state, router) = quota_node(100).await;
+        let now = chrono::Utc::now();
+        let stale = now - chrono::Duration::hours(25);
+        let client = Some(crate::attribution::ANONYMOUS_LOOPBACK);
+        crate::quotas::record_usage(&state.storage, PEER, client, 100, stale)
+            .await
+            .unwrap();
+
+        assert_eq!(settled_transfer(&router, &[1; 80], false).await["status"], "success");
+        let (_, report) = get_json(&router, "/v1/security/quotas").await;
+        assert_eq!(quota_of(&report, "destination")["used_bytes"], 80);
+
+        crate::archive::apply_retention(&state.storage, &Default::default(), now)
+            .await
+            .unwrap();
+        let since = (now - chrono::Duration::days(7)).to_rfc3339();
+        let remaining = state
+            .storage
+            .list_subject_quota_usage("destination", PEER, &since)
+            .await
+            .unwrap();
+        assert_eq!(remaining.iter().map(|bucket| bucket.bytes).collect::<Vec<_>>(), [80]);
+    }
+
+    // Replaces every scalar with its JSON type name and keeps one array element, so the
+    // snapshot pins field names and types without the per-run ids and timestamps.
+    #[cfg(feature = "transfers")]
+    fn shape(value: &serde_json::Value) -> serde_json::Value {
+        use serde_json::Value;
+        match value {
+            Value::Null => json!("null"),
+            Value::Bool(_) => json!("bool"),
+            Value::Number(_) => json!("number"),
+            Value::String(_) => json!("string"),
+            Value::Array(items) => Value::Array(items.iter().take(1).map(shape).collect()),
+            Value::Object(fields) => Value::Object(
+                fields
+                    .iter()
+                    .map(|(key, value)| (key.clone(), shape(value)))
+                    .collect(),
+            ),
+        }
+    }
+
+    fn header_of(response: &axum::response::Response, name: header::HeaderName) -> Option<&str> {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+    }
+
+    async fn get_json(router: &Router, uri: &str) -> (StatusCode, serde_json::Value) {
+        let response = send(router, Request::get(uri).body(Body::empty()).unwrap()).await;
+        (response.status(), json_body(response).await)
+    }
+
+    async fn add_allowlisted(router: &Router, identity_hash: &str) {
+        let added = send(
+            router,
+            Request::post("/v1/security/allowlist")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(json!({ "identity_hash": identity_hash }).to_string()))
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(added.status(), StatusCode::CREATED);
+    }
+
+    #[cfg(feature = "transfers")]
+    #[tokio::test]
+    async fn v1_response_shapes_are_pinned_and_marked_deprecated() {
+        let router = test_router().await;
+        let live = send(&router, Request::get("/health/live").body(Body::empty()).unwrap()).await;
+        assert_eq!(header_of(&live, header::HeaderName::from_static("deprecation")), None);
+        assert_eq!(
+            shape(&json_body(live).await),
+            json!({ "status": "string", "timestamp": "string" })
+        );
+
+        let submitted = send(
+            &router,
+            attachment_request(json!([attachment("tile.png", b"tile")])),
+        )
+        .await;
+        assert_eq!(submitted.status(), StatusCode::ACCEPTED);
+        assert_eq!(
+            header_of(&submitted, header::HeaderName::from_static("deprecation")),
+            Some("true")
+        );
+        let accepted = json_body(submitted).await;
+        assert_eq!(
+            shape(&accepted),
+            json!({ "job_id": "string", "submitted_at": "string", "status_url": "string" })
+        );
+        let job_id = accepted["job_id"].as_str().unwrap().to_string();
+        assert_eq!(accepted["status_url"], format!("/v1/jobs/{job_id}"));
+
+        for _ in 0..200 {
+            let (_, job) = get_json(&router, &format!("/v1/jobs/{job_id}")).await;
+            if job["status"] == "success" {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        let job_response = send(
+            &router,
+            Request::get(format!("/v1/jobs/{job_id}"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(
+            header_of(&job_response, header::LINK),
+            Some(format!("</v2/jobs/{job_id}>; rel=\"successor-version\"").as_str())
+        );
+        let transfer = json!({
+            "transfer_id": "string",
+            "job_id": "string",
+            "status": "string",
+            "metadata_json": "string",
+            "submitted_at": "string",
+            "updated_at": "string",
+            "failure_reason": "null",
+            "progress": {
+                "bytes_total": "number",
+                "bytes_sent": "number",
+                "chunks_total": "number",
+                "chunks_sent": "number",
+                "last_chunk_at": "string",
+                "stalled": "bool"
+            }
+        });
+        assert_eq!(
+            shape(&json_body(job_response).await),
+            json!({
+                "job_id": "string",
+                "operation": "string",
+                "status": "string",
+                "payload_json": "string",
+                "dispatch_json": "string",
+                "requested_operation": "null",
+                "failure_reason": "null",
+                "submitted_at": "string",
+                "updated_at": "string",
+                "source": { "token_label": "string" },
+                "result_digest": "string",
+                "result_unchanged": "bool",
+                "result_source": "string",
+                "attachments": [transfer.clone()]
+            })
+        );
+        let (_, transfers) = get_json(&router, "/v1/transfers").await;
+        assert_eq!(
+            shape(&transfers),
+            json!({
+                "items": [transfer],
+                "next_cursor": "null",
+                "prev_cursor": "null",
+                "order": "string"
+            })
+        );
+
+        add_allowlisted(&router, "abc12300000000000000000000000000").await;
+        let (_, allowlist) = get_json(&router, "/v1/security/allowlist").await;
+        assert_eq!(
+            shape(&allowlist),
+            json!({
+                "identities": ["string"],
+                "entries": [{
+                    "identity_hash": "string",
+                    "note": "null",
+                    "role": "string",
+                    "status": "string",
+                    "expires_at": "null",
+                    "created_at": "string",
+                    "approved_at": "null"
+                }]
+            })
+        );
+        let (status, missing) = get_json(&router, "/v1/jobs/missing").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(missing, json!({ "error": "job_not_found" }));
+
+        let (_, usage) = get_json(&router, "/v2/node/api-usage").await;
+        let usage = &usage["data"]["v1_requests"];
+        assert_eq!(usage["POST /v1/jobs/commands/{operation}"], 1);
+        assert_eq!(usage["GET /v1/security/allowlist"], 1);
+        assert_eq!(usage["GET /v1/jobs/{job_id}"].as_u64().map(|count| count >= 2), Some(true));
+    }
+
+    #[cfg(feature = "transfers")]
+    #[tokio::test]
+    async fn v2_responses_match_their_typed_schemas() {
+        use crate::api::v2::{
+            AllowlistEntry, ApiUsage, Envelope, ErrorEnvelope, Health, HealthStatus, Job,
+            JobAccepted, Page, Transfer,
+        };
+
+        // Decoding into the typed struct and re-encoding must give back the exact body,
+        // which rules out missing, extra or differently formatted fields.
+        fn typed<T: serde::de::DeserializeOwned + serde::Serialize>(body: &serde_json::Value) -> T {
+            let decoded: T = serde_json::from_value(body.clone()).expect("typed v2 body");
+            assert_eq!(&serde_json::to_value(&decoded).unwrap(), body);
+            decoded
+        }
+
+        let router = test_router().await;
+        let (status, live) = get_json(&router, "/v2/health/live").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(typed::<Envelope<Health>>(&live).data.status, HealthStatus::Live);
+        let (_, ready) = get_json(&router, "/v2/health/ready").await;
+        assert_eq!(typed::<Envelope<Health>>(&ready).data.status, HealthStatus::Ready);
+
+        let submitted = send(
+            &router,
+            Request::post("/v2/jobs/commands/event.create")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(
+                    json!({
+                        "uid": "evt-1",
+                        "_attachments": [
+                            attachment("a.png", b"a"),
+                            attachment("b.png", b"b"),
+                            attachment("c.png", b"c")
+                        ]
+                    })
+                    .to_string(),
+                ))
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(submitted.status(), StatusCode::ACCEPTED);
+        assert_eq!(header_of(&submitted, header::HeaderName::from_static("deprecation")), None);
+        let accepted = typed::<Envelope<JobAccepted>>(&json_body(submitted).await).data;
+        assert_eq!(accepted.status_url, format!("/v2/jobs/{}", accepted.job_id));
+
+        let mut job = None;
+        for _ in 0..200 {
+            let (_, body) = get_json(&router, &accepted.status_url).await;
+            let current = typed::<Envelope<Job>>(&body).data;
+            if current.status == "success" {
+                job = Some(current);
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        let job = job.expect("job settled");
+        assert_eq!(job.submitted_at, accepted.submitted_at);
+        assert_eq!(job.payload.as_ref().unwrap()["uid"], "evt-1");
+        assert_eq!(job.attachments.len(), 3);
+
+        let (_, first) = get_json(&router, "/v2/transfers?limit=2").await;
+        let first = typed::<Envelope<Page<Transfer>>>(&first).data;
+        assert_eq!(first.items.len(), 2);
+        let cursor = first.next_cursor.expect("second page");
+        let (_, second) =
+            get_json(&router, &format!("/v2/transfers?limit=2&cursor={cursor}")).await;
+        let second = typed::<Envelope<Page<Transfer>>>(&second).data;
+        assert_eq!(second.items.len(), 1);
+        assert_eq!(second.next_cursor, None);
+        assert!(second.prev_cursor.is_some());
+        assert_eq!(second.order, "submitted_at desc, transfer_id desc");
+        let mut seen: Vec<String> = first
+            .items
+            .iter()
+            .chain(&second.items)
+            .map(|transfer| transfer.transfer_id.clone())
+            .collect();
+        seen.sort();
+        let mut expected: Vec<String> = job
+            .attachments
+            .iter()
+            .map(|transfer| transfer.transfer_id.clone())
+            .collect();
+        expected.sort();
+        assert_eq!(seen, expected);
+        let (_, single) = get_json(&router, &format!("/v2/transfers/{}", seen[0])).await;
+        assert_eq!(typed::<Envelope<Transfer>>(&single).data.transfer_id, seen[0]);
+
+        const AA01: &str = "aa010000000000000000000000000000";
+        const AA02: &str = "aa020000000000000000000000000000";
+        add_allowlisted(&router, AA01).await;
+        add_allowlisted(&router, AA02).await;
+        let (_, page) = get_json(&router, "/v2/security/allowlist?limit=1").await;
+        let page = typed::<Envelope<Page<AllowlistEntry>>>(&page).data;
+        assert_eq!(page.items[0].identity_hash, AA01);
+        let cursor = page.next_cursor.expect("second page");
+        let (_, page) =
+            get_json(&router, &format!("/v2/security/allowlist?limit=1&cursor={cursor}")).await;
+        let page = typed::<Envelope<Page<AllowlistEntry>>>(&page).data;
+        assert_eq!(page.items[0].identity_hash, AA02);
+        assert_eq!(page.next_cursor, None);
+
+        let (status, missing) = get_json(&router, "/v2/jobs/missing").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let missing = typed::<ErrorEnvelope>(&missing).error;
+        assert_eq!((missing.code.as_str(), missing.detail), ("job_not_found", None));
+        let (status, bad) = get_json(&router, "/v2/transfers?cursor=%21%21").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(typed::<ErrorEnvelope>(&bad).error.code, "invalid_cursor");
+
+        let (_, usage) = get_json(&router, "/v2/node/api-usage").await;
+        assert_eq!(
+            typed::<Envelope<ApiUsage>>(&usage).data.v1_requests.keys().collect::<Vec<_>>(),
+            ["POST /v1/security/allowlist"]
+        );
+    }
+
+    async fn unworked_job(state: &AppState, uid: &str) -> String {
+        let job = state
+            .storage
+            .create_job("event.create", json!({ "uid": uid }))
+            .await
+            .unwrap();
+        job.job_id
+    }
+
+    async fn submit_waiting(router: &Router, uid: &str, depends_on: &[&str]) -> String {
+        let payload = json!({ "uid": uid, "depends_on": depends_on });
+        let response = send(router, command_request("event.create", payload)).await;
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        json_body(response).await["job_id"].as_str().unwrap().to_string()
+    }
+
+    async fn finished_status(state: &AppState, job_id: &str) -> String {
+        for _ in 0..200 {
+            let job = state.storage.get_job(job_id).await.unwrap().unwrap();
+            if matches!(job.status.as_str(), "success" | "partial_failure" | "failed") {
+                return job.status;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        panic!("job {job_id} never finished");
+    }
+
+    async fn get_json_at(router: &Router, uri: &str) -> serde_json::Value {
+        json_body(send(router, Request::get(uri).body(Body::empty()).unwrap()).await).await
+    }
+
+    #[tokio::test]
+    async fn dependency_chains_run_in_order() {
+        let path = scratch_dir().join("bridge.rec");
+        let recorder = Arc::new(
+            RecordingBridge::start(Arc::new(InMemoryRpcMeshBridge::new(true, true)), &path)
+                .unwrap(),
+        );
+        let state = test_state(recorder.clone()).await;
+        let router = build_router(state.clone());
+        let first = unworked_job(&state, "evt-1").await;
+        let second = submit_waiting(&router, "evt-2", &[&first]).await;
+        let third = submit_waiting(&router, "evt-3", &[&second]).await;
+
+        assert_eq!(get_json_at(&router, "/v1/node/queue").await["waiting_jobs"], 2);
+        let dependents = get_json_at(&router, &format!("/v1/jobs/{first}/dependents")).await;
+        assert_eq!(
+            dependents["dependents"],
+            json!([{ "job_id": second, "status": "waiting" }])
+        );
+
+        super::complete_job(&state, &first, Some(json!({ "status": "accepted" })))
+            .await
+            .unwrap();
+        assert_eq!(finished_status(&state, &third).await, "success");
+        assert_eq!(finished_status(&state, &second).await, "success");
+        recorder.flush().await;
+        let sent: Vec<serde_json::Value> = read_recording(&path)
+            .unwrap()
+            .iter()
+            .map(|record| {
+                let command: MeshCommandEnvelope<serde_json::Value> =
+                    decode_canonical(&record.request).unwrap();
+                command.payload
+            })
+            .filter(|payload| payload.get("uid").is_some())
+            .collect();
+        assert_eq!(sent, [json!({ "uid": "evt-2" }), json!({ "uid": "evt-3" })]);
+        assert_eq!(get_json_at(&router, "/v1/node/queue").await["waiting_jobs"], 0);
+    }
+
+    #[tokio::test]
+    async fn failed_dependencies_fail_the_whole_chain() {
+        let state = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        let router = build_router(state.clone());
+        let first = unworked_job(&state, "evt-1").await;
+        let second = submit_waiting(&router, "evt-2", &[&first]).await;
+        let third = submit_waiting(&router, "evt-3", &[&second]).await;
+
+        state.storage.fail_job(&first, "link_down").await.unwrap();
+        crate::dependencies::settle_dependents(&state, &first).await;
+        for (job_id, culprit) in [(&second, &first), (&third, &second)] {
+            let job = state.storage.get_job(job_id).await.unwrap().unwrap();
+            assert_eq!(job.status, "failed");
+            assert_eq!(job.failure_reason, Some(format!("dependency_failed: {culprit}")));
+        }
+
+        // A job that names an already failed dependency fails straight away.
+        let late = submit_waiting(&router, "evt-4", &[&first]).await;
+        let job = state.storage.get_job(&late).await.unwrap().unwrap();
+        assert_eq!(job.status, "failed");
+    }
+
+    #[tokio::test]
+    async fn dependency_cycles_and_deep_chains_are_refused() {
+        let state = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        let router = build_router(state.clone());
+        let first = unworked_job(&state, "evt-1").await;
+        let second = unworked_job(&state, "evt-2").await;
+        state.storage.add_job_dependency(&first, &second).await.unwrap();
+        state.storage.add_job_dependency(&second, &first).await.unwrap();
+
+        let payload = json!({ "uid": "evt-3", "depends_on": [first] });
+        let response = send(&router, command_request("event.create", payload)).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body = json_body(response).await;
+        assert_eq!(body["error"], "dependency_cycle");
+        assert_eq!(body["cycle"], json!([first, second]));
+
+        let payload = json!({ "uid": "evt-3", "depends_on": ["missing"] });
+        let response = send(&router, command_request("event.create", payload)).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(json_body(response).await["error"], "dependency_not_found");
+
+        state.node_config.write().await.dependencies.max_chain_depth = 1;
+        let root = unworked_job(&state, "evt-4").await;
+        let waiting = submit_waiting(&router, "evt-5", &[&root]).await;
+        let payload = json!({ "uid": "evt-6", "depends_on": [waiting] });
+        let response = send(&router, command_request("event.create", payload)).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(json_body(response).await["error"], "dependency_chain_too_deep");
+    }
+
+    #[tokio::test]
+    async fn diamond_dependencies_release_once_both_parents_succeed() {
+        let state = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        let router = build_router(state.clone());
+        let left = unworked_job(&state, "evt-1").await;
+        let right = unworked_job(&state, "evt-2").await;
+        let child = submit_waiting(&router, "evt-3", &[&left, &right]).await;
+        let mut events = state.sse_bus.subscribe();
+
+        super::complete_job(&state, &left, Some(json!({ "status": "accepted" })))
+            .await
+            .unwrap();
+        let job = state.storage.get_job(&child).await.unwrap().unwrap();
+        assert_eq!(job.status, "waiting");
+
+        super::complete_job(&state, &right, Some(json!({ "status": "accepted" })))
+            .await
+            .unwrap();
+        assert_eq!(finished_status(&state, &child).await, "success");
+        // The status is stored before its event goes out, so wait for the event itself.
+        let mut released = Vec::new();
+        while released.last() != Some(&json!("success")) {
+            let event = tokio::time::timeout(Duration::from_secs(5), events.recv())
+                .await
+                .expect("child status events")
+                .unwrap();
+            if event.event_type == "job.status.changed" && event.data["job_id"] == child {
+                released.push(event.data["status"].clone());
+            }
+        }
+        assert_eq!(released, ["queued", "running", "success"]);
+    }
+
+    #[cfg(feature = "status-page")]
+    #[tokio::test]
+    async fn status_page_is_served_only_when_enabled() {
+        let state = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        let router = build_router(state.clone());
+        let page = || Request::get("/").body(Body::empty()).unwrap();
+        assert_eq!(send(&router, page()).await.status(), StatusCode::NOT_FOUND);
+        let (status, _) = get_json(&router, "/v1/ui/snapshot").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        state.node_config.write().await.status_page = true;
+        let response = send(&router, page()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let headers = response.headers().clone();
+        assert!(headers[header::CONTENT_TYPE].to_str().unwrap().starts_with("text/html"));
+        assert_eq!(headers[header::CACHE_CONTROL], "no-cache");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let html = String::from_utf8(body.to_vec()).unwrap();
+        assert!(html.contains("/v1/ui/snapshot") && !html.contains("https://"));
+
+        let revalidate = Request::get("/")
+            .header(header::IF_NONE_MATCH, headers[header::ETAG].clone())
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(send(&router, revalidate).await.status(), StatusCode::NOT_MODIFIED);
+    }
+
+    #[cfg(feature = "status-page")]
+    #[tokio::test]
+    async fn ui_snapshot_combines_status_queue_jobs_transfers_and_logs() {
+        let state = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        state.node_config.write().await.status_page = true;
+        let router = build_router(state.clone());
+        let job = unworked_job(&state, "evt-1").await;
+        let transfer = state
+            .storage
+            .create_transfer(json!({ "file_name": "tile.png" }))
+            .await
+            .unwrap();
+        for index in 0..60 {
+            super::write_log(&state, "info", &format!("line {index}")).await;
+        }
+
+        let request = Request::get("/v1/ui/snapshot").body(Body::empty()).unwrap();
+        let response = send(&router, request).await;
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "no-store");
+        let snapshot = json_body(response).await;
+        let mut keys: Vec<_> = snapshot.as_object().unwrap().keys().cloned().collect();
+        keys.sort();
+        assert_eq!(keys, ["generated_at", "jobs", "logs", "node", "queue", "transfers"]);
+        assert!(snapshot["node"]["ready"].is_boolean());
+        assert_eq!(snapshot["node"]["checks"].as_array().unwrap().len(), 4);
+        assert_eq!(
+            (snapshot["queue"]["depth"].as_u64(), snapshot["queue"]["waiting_jobs"].as_u64()),
+            (Some(0), Some(0))
+        );
+        assert_eq!(
+            snapshot["jobs"],
+            json!([{
+                "job_id": job,
+                "operation": "event.create",
+                "status": "queued",
+                "submitted_at": snapshot["jobs"][0]["submitted_at"],
+                "updated_at": snapshot["jobs"][0]["updated_at"],
+                "failure_reason": null,
+            }])
+        );
+        assert_eq!(snapshot["transfers"][0]["transfer_id"], transfer.transfer_id);
+        assert_eq!(snapshot["transfers"][0]["file_name"], "tile.png");
+        let logs = snapshot["logs"].as_array().unwrap();
+        assert_eq!(logs.len(), super::SNAPSHOT_LOG_LINES);
+        assert_eq!(logs.last().unwrap()["message"], "line 59");
+    }
+
+    fn transforms(settings: serde_json::Value) -> NodeConfig {
+        let mut config = test_node_config();
+        config.identity.source_identity = Some("aa".repeat(16));
+        config.transforms = serde_json::from_value(settings).unwrap();
+        config
+    }
+
+    fn sent_payloads(path: &std::path::Path) -> Vec<serde_json::Value> {
+        read_recording(path)
+            .unwrap()
+            .iter()
+            .map(|record| {
+                let command: MeshCommandEnvelope<serde_json::Value> =
+                    decode_canonical(&record.request).unwrap();
+                command.payload
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn transforms_rewrite_commands_in_order_and_expose_the_trace() {
+        let path = scratch_dir().join("bridge.rec");
+        let recorder = Arc::new(
+            RecordingBridge::start(Arc::new(InMemoryRpcMeshBridge::new(true, true)), &path)
+                .unwrap(),
+        );
+        let state = test_state(recorder.clone()).await;
+        // The rename only finds `/step` because the inject before it already ran.
+        state.transforms.configure(&transforms(json!([
+            {
+                "name": "stamp",
+                "kind": "inject",
+                "operations": ["event.*"],
+                "fields": {
+                    "/meta/origin": "{node_identity}",
+                    "/meta/op": "{operation}",
+                    "/step": 1
+                }
+            },
+            { "name": "strip", "kind": "remove", "pointers": ["/client_only", "/meta/missing"] },
+            {
+                "name": "legacy",
+                "kind": "rename",
+                "renames": { "/cs": "callsign", "/step": "steps" }
+            }
+        ])));
+        let router = build_router(state.clone());
+
+        let job = settled_command(
+            &router,
+            "event.create",
+            json!({ "uid": "evt-1", "cs": "ALPHA", "client_only": true }),
+        )
+        .await;
+        assert_eq!(job["status"], "success");
+        assert!(job.get("transform_trace").is_none());
+        let job_id = job["job_id"].as_str().unwrap();
+
+        recorder.flush().await;
+        let expected = json!({
+            "uid": "evt-1",
+            "callsign": "ALPHA",
+            "steps": 1,
+            "meta": { "origin": "aa".repeat(16), "op": "event.create" }
+        });
+        assert_eq!(sent_payloads(&path), vec![expected.clone()]);
+
+        let (status, job) = get_json(
+            &router,
+            &format!("/v1/jobs/{job_id}?include=transform_trace"),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let trace = job["transform_trace"].as_array().unwrap();
+        assert_eq!(trace.len(), 1);
+        assert_eq!(trace[0]["stage"], "command");
+        assert_eq!(trace[0]["applied"], json!(["stamp", "strip", "legacy"]));
+        assert_eq!(trace[0]["before"]["client_only"], true);
+        assert_eq!(trace[0]["after"], expected);
+
+        let (status, body) = get_json(&router, &format!("/v1/jobs/{job_id}?include=bogus")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"], "unknown_include");
+    }
+
+    #[tokio::test]
+    async fn transforms_are_scoped_to_operations_and_failures_fail_the_job() {
+        let state = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        state.transforms.configure(&transforms(json!([
+            { "kind": "remove", "operations": ["telemetry.*"], "pointers": ["/uid"] },
+            {
+                "name": "clash",
+                "kind": "rename",
+                "operations": ["event.update"],
+                "renames": { "/a": "b" }
+            }
+        ])));
+        struct Panics;
+        impl crate::transforms::PayloadTransform for Panics {
+            fn transform(
+                &self,
+                _operation: &str,
+                _payload: serde_json::Value,
+            ) -> Result<serde_json::Value, super::TransformError> {
+                panic!("bad plugin")
+            }
+        }
+        state.transforms.register(
+            "plugin",
+            super::TransformStage::Command,
+            &["event.delete"],
+            Arc::new(Panics),
+        );
+        let router = build_router(state.clone());
+
+        let job = settled_command(&router, "event.create", json!({ "uid": "evt-1" })).await;
+        assert_eq!(job["status"], "success");
+        let job_id = job["job_id"].as_str().unwrap();
+        let (_, job) = get_json(
+            &router,
+            &format!("/v1/jobs/{job_id}?include=transform_trace"),
+        )
+        .await;
+        assert_eq!(job["transform_trace"], json!([]));
+
+        let job = settled_command(&router, "event.update", json!({ "a": 1, "b": 2 })).await;
+        assert_eq!(job["status"], "failed");
+        assert!(job["failure_reason"]
+            .as_str()
+            .unwrap()
+            .starts_with("payload_transform_failed: clash: /a: renaming to \"b\""));
+
+        let job = settled_command(&router, "event.delete", json!({ "uid": "evt-1" })).await;
+        assert_eq!(job["status"], "failed");
+        assert_eq!(
+            job["failure_reason"],
+            "payload_transform_failed: plugin: transform panicked"
+        );
+    }
+
+    #[tokio::test]
+    async fn result_transforms_rename_remote_fields() {
+        let state = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        state.transforms.configure(&transforms(json!([
+            {
+                "name": "local_names",
+                "kind": "rename",
+                "stage": "result",
+                "renames": { "/status": "state" }
+            }
+        ])));
+        let router = build_router(state.clone());
+
+        let job = settled_command(&router, "event.create", json!({ "uid": "evt-1" })).await;
+        assert_eq!(job["status"], "success");
+        let job_id = job["job_id"].as_str().unwrap();
+        let result = state.storage.get_job_result(job_id).await.unwrap().unwrap();
+        let result: serde_json::Value = serde_json::from_str(&result.result_json).unwrap();
+        assert_eq!(result["state"], "accepted");
+        assert!(result.get("status").is_none());
+
+        let (_, job) = get_json(
+            &router,
+            &format!("/v1/jobs/{job_id}?include=transform_trace"),
+        )
+        .await;
+        assert_eq!(job["transform_trace"][0]["stage"], "result");
+        assert_eq!(job["transform_trace"][0]["before"]["status"], "accepted");
+    }
+
+    #[tokio::test]
+    async fn event_feed_pages_in_order_and_reports_trimmed_positions() {
+        let state = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        let router = build_router(state.clone());
+        let job = settled_command(&router, "event.create", json!({ "uid": "evt-1" })).await;
+        emit(&state, "node.config.updated", json!({ "n": 1 })).await;
+
+        let (status, page) = get_json(&router, "/v1/events/feed?limit=2").await;
+        assert_eq!(status, StatusCode::OK);
+        let events = page["events"].as_array().unwrap();
+        assert_eq!(
+            (events[0]["seq"].clone(), events[1]["seq"].clone()),
+            (json!(1), json!(2))
+        );
+        assert_eq!(events[0]["data"]["job_id"], job["job_id"]);
+        assert_eq!(events[0]["data"]["status"], "queued");
+        assert_eq!(page["next_after_seq"], 2);
+
+        let (_, rest) = get_json(&router, "/v1/events/feed?after_seq=2").await;
+        let statuses: Vec<&str> = rest["events"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|event| event["event_type"].as_str().unwrap())
+            .collect();
+        assert_eq!(statuses.last(), Some(&"node.config.updated"));
+        let (_, head) = get_json(&router, "/v1/events/feed/head").await;
+        assert_eq!(head["head_seq"], rest["next_after_seq"]);
+        assert_eq!(head["trimmed_through"], 0);
+
+        let trimmed = crate::feed::trim_event_feed(
+            &state.storage,
+            &crate::feed::EventFeedSettings {
+                retention_hours: 0,
+                ..Default::default()
+            },
+            chrono::Utc::now() + chrono::Duration::hours(1),
+        )
+        .await
+        .unwrap();
+        assert!(trimmed > 2);
+        let (_, head) = get_json(&router, "/v1/events/feed/head").await;
+        assert_eq!(head["trimmed_through"], head["head_seq"]);
+
+        let (status, body) = get_json(&router, "/v1/events/feed?after_seq=2").await;
+        assert_eq!(status, StatusCode::GONE);
+        assert_eq!(body["error"], "feed_position_trimmed");
+        assert_eq!(body["trimmed_through"], head["trimmed_through"]);
+        let (status, page) = get_json(&router, "/v1/events/feed").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(page["events"], json!([]));
+        assert_eq!(page["next_after_seq"], head["trimmed_through"]);
+    }
+
+    async fn dry_run(router: &Router, path: &str, body: serde_json::Value) -> serde_json::Value {
+        let response = send(
+            router,
+            Request::post(path)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        json_body(response).await
+    }
+
+    fn stage<'a>(report: &'a serde_json::Value, name: &str) -> &'a serde_json::Value {
+        report["stages"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|stage| stage["stage"] == name)
+            .expect("reported stage")
+    }
+
+    #[cfg(feature = "transfers")]
+    #[tokio::test]
+    async fn dry_run_reports_every_stage_and_the_envelope() {
+        let (_state, router) = quota_node(1_000).await;
+        let report = dry_run(
+            &router,
+            "/v1/jobs/commands/event.create?dry_run=true",
+            json!({ "uid": "evt-1", "destination_identity": PEER, "_attachments": [attachment("a.png", &[1; 10])] }),
+        )
+        .await;
+
+        assert_eq!(
+            (report["dry_run"].as_bool(), report["passed"].as_bool()),
+            (Some(true), Some(true))
+        );
+        let stages: Vec<_> = report["stages"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|stage| {
+                (
+                    stage["stage"].as_str().unwrap(),
+                    stage["outcome"].as_str().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            stages,
+            [
+                "rate_limit",
+                "operation",
+                "meta",
+                "attachments",
+                "dependencies",
+                "delivery",
+                "routing",
+                "quota",
+                "transforms",
+                "envelope"
+            ]
+            .map(|name| (name, "passed"))
+        );
+        assert_eq!(
+            stage(&report, "routing")["detail"]["destination_identity"],
+            PEER
+        );
+        assert_eq!(stage(&report, "quota")["detail"]["requested_bytes"], 10);
+        let envelope = &report["envelope"];
+        assert_eq!(envelope["operation"], "event.create");
+        assert_eq!(envelope["channel"], "commands/event.create");
+        assert_eq!(envelope["destination_identity"], PEER);
+        let size = envelope["size_bytes"].as_u64().unwrap();
+        assert!(size > 0 && size <= envelope["limit_bytes"].as_u64().unwrap());
+    }
+
+    #[cfg(feature = "transfers")]
+    #[tokio::test]
+    async fn dry_run_reports_validation_and_quota_failures_together() {
+        let (_state, router) = quota_node(100).await;
+        let report = dry_run(
+            &router,
+            "/v1/jobs/commands/event.create?dry_run=true",
+            json!({
+                "uid": "evt-1",
+                "destination_identity": PEER,
+                "depends_on": "not-a-list",
+                "_attachments": [attachment("big.png", &[1; 200])],
+            }),
+        )
+        .await;
+
+        assert_eq!(report["passed"], false);
+        let dependencies = stage(&report, "dependencies");
+        assert_eq!(dependencies["outcome"], "failed");
+        assert_eq!(dependencies["http_status"], 400);
+        assert_eq!(dependencies["error"]["error"], "invalid_dependencies");
+        let quota = stage(&report, "quota");
+        assert_eq!(quota["outcome"], "failed");
+        assert_eq!(quota["http_status"], 429);
+        assert_eq!(quota["error"]["error"], "quota_exceeded");
+        assert_eq!(quota["error"]["detail"]["requested_bytes"], 200);
+        assert_eq!(stage(&report, "routing")["outcome"], "passed");
+    }
+
+    #[cfg(feature = "transfers")]
+    #[tokio::test]
+    async fn dry_runs_write_nothing_and_emit_nothing() {
+        let (state, router) = quota_node(1_000).await;
+        state.node_config.write().await.submissions.rate_per_minute = 1;
+        let mut events = state.sse_bus.subscribe();
+        let command = json!({ "uid": "evt-1", "destination_identity": PEER, "_attachments": [attachment("a.png", &[1; 10])] });
+        for _ in 0..3 {
+            let report = dry_run(
+                &router,
+                "/v1/jobs/commands/event.create?dry_run=true",
+                command.clone(),
+            )
+            .await;
+            assert_eq!(report["passed"], true);
+        }
+        let entry = json!({ "operation": "event.create", "payload": { "uid": "evt-2" } });
+        let batch = dry_run(
+            &router,
+            "/v1/jobs/commands:batch?dry_run=true",
+            json!([entry, entry, { "operation": "event.create" }]),
+        )
+        .await;
+        let results = batch["results"].as_array().unwrap();
+        assert_eq!(stage(&results[0], "rate_limit")["outcome"], "passed");
+        assert_eq!(
+            stage(&results[1], "rate_limit")["error"]["error"],
+            "rate_limited"
+        );
+        assert_eq!(
+            stage(&results[2], "entry")["error"]["error"],
+            "invalid_batch_entry"
+        );
+        assert_eq!(stage(&results[2], "envelope")["outcome"], "skipped");
+
+        assert!(state.storage.list_recent_jobs(10).await.unwrap().is_empty());
+        assert!(state
+            .storage
+            .list_transfers(None, 10)
+            .await
+            .unwrap()
+            .is_empty());
+        let since = CanonicalTimestamp::from(chrono::DateTime::UNIX_EPOCH).to_string();
+        assert!(state
+            .storage
+            .list_quota_usage(&since)
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(state
+            .storage
+            .list_feed_events(0, 10)
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(events.try_recv().is_err());
+        // The budget was only inspected, so the one real submission still goes through.
+        let accepted = send(
+            &router,
+            attachment_request(json!([attachment("a.png", &[1; 10])])),
+        )
+        .await;
+        assert_eq!(accepted.status(), StatusCode::ACCEPTED);
+    }
+
+    async fn put_json(router: &Router, path: &str, body: Value) -> (StatusCode, Value) {
+        let response = send(
+            router,
+            Request::put(path)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap(),
+        )
+        .await;
+        let status = response.status();
+        (status, json_body(response).await)
+    }
+
+    #[cfg(feature = "transfers")]
+    fn inbound_command() -> MeshCommandEnvelope<Value> {
+        MeshCommandEnvelope {
+            message_id: Uuid::now_v7().to_string(),
+            operation: "event.create".to_string(),
+            sent_at: chrono::Utc::now(),
+            source_identity: PEER.parse().unwrap(),
+            destination_identity: "local-node".parse().unwrap(),
+            content_type: "application/msgpack".to_string(),
+            payload: json!({ "uid": "evt-1" }),
+            ttl_ms: None,
+            transport_hint: None,
+            trace: Vec::new(),
+            trace_truncated: false,
+            meta: None,
+        }
+    }
+
+    #[cfg(feature = "transfers")]
+    #[tokio::test]
+    async fn feature_flips_take_effect_without_restart() {
+        let bridge = Arc::new(InMemoryRpcMeshBridge::new(true, true));
+        let state = test_state(bridge.clone()).await;
+        state
+            .node_config
+            .write()
+            .await
+            .transfer_dedup
+            .offer_timeout_ms = 20;
+        set_feature(&state, TRANSFER_DEDUP_FLAG, true).await;
+        state.peers.set_capabilities(
+            &PEER.parse().unwrap(),
+            serde_json::from_value(json!({ "compression": ["zstd"] })).unwrap(),
+        );
+        let router = build_router(state.clone());
+        let worker = spawn_inbound_worker(state.clone(), Duration::from_millis(5));
+        let mut events = state.sse_bus.subscribe();
+
+        let command =
+            json!({ "uid": "evt-1", "destination_identity": PEER, "notes": "x".repeat(8192) });
+        let path = "/v1/jobs/commands/event.create?dry_run=true";
+        let compressed = dry_run(&router, path, command.clone()).await;
+        assert_eq!(
+            compressed["envelope"]["content_type"],
+            "application/msgpack+zstd"
+        );
+        assert!(compressed["envelope"]["size_bytes"].as_u64().unwrap() < 1024);
+        let offered = settled_transfer(&router, b"tile", true).await;
+        assert_eq!(offered["dedup"]["outcome"], "offer_unanswered");
+        bridge.inject_command(inbound_command());
+        for _ in 0..200 {
+            if !state
+                .storage
+                .list_cached_messages(10)
+                .await
+                .unwrap()
+                .is_empty()
+            {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        assert_eq!(
+            state.storage.list_cached_messages(10).await.unwrap().len(),
+            1
+        );
+
+        let (status, changed) = put_json(
+            &router,
+            "/v1/node/features",
+            json!({
+                "compression": { "enabled": false },
+                "inbound_commands": { "enabled": false },
+                "transfer_dedup": { "enabled": false, "variant": { "reason": "field trial" } },
+            }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(changed["features"].as_array().unwrap().len(), 3);
+        assert_eq!(changed["features"][2]["updated_by"], "local");
+        let changed_events: Vec<_> = std::iter::from_fn(|| events.try_recv().ok())
+            .filter(|event| event.event_type == "node.feature.changed")
+            .map(|event| event.data["name"].clone())
+            .collect();
+        assert_eq!(
+            changed_events,
+            [
+                json!("compression"),
+                json!("inbound_commands"),
+                json!("transfer_dedup")
+            ]
+        );
+
+        let plain = dry_run(&router, path, command).await;
+        assert_eq!(plain["envelope"]["content_type"], "application/msgpack");
+        let direct = settled_transfer(&router, b"tile", true).await;
+        assert_eq!(direct["status"], "success");
+        assert!(direct.get("dedup").is_none());
+        bridge.inject_command(inbound_command());
+        let mut refused = None;
+        for _ in 0..200 {
+            refused = bridge.sent_results().pop();
+            if refused.is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        assert_eq!(
+            refused.unwrap().payload["error"],
+            "inbound_commands_disabled"
+        );
+        assert_eq!(
+            state.storage.list_cached_messages(10).await.unwrap().len(),
+            1
+        );
+        assert_eq!(
+            state.features.variant::<Value>(TRANSFER_DEDUP_FLAG),
+            Some(json!({ "reason": "field trial" }))
+        );
+        worker.abort();
+    }
+
+    #[tokio::test]
+    async fn feature_flags_are_seeded_from_config_only_when_absent() {
+        let state = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        let mut config = state.node_config.read().await.clone();
+        config.transfer_dedup.enabled = false;
+        state.features.load(&state.storage, &config).await.unwrap();
+        let stored = state.storage.list_feature_flags().await.unwrap();
+        let seeded: Vec<_> = stored
+            .iter()
+            .map(|flag| (flag.name.as_str(), flag.enabled, flag.updated_by.as_str()))
+            .collect();
+        assert_eq!(
+            seeded,
+            [
+                ("clock_skew_tolerance", false, "config"),
+                ("compression", true, "config"),
+                ("inbound_commands", true, "config"),
+                ("transfer_dedup", false, "config"),
+            ]
+        );
+        assert!(!state.features.is_enabled(TRANSFER_DEDUP_FLAG));
+
+        let router = build_router(state.clone());
+        let (status, _) = put_json(
+            &router,
+            "/v1/node/features/transfer_dedup",
+            json!({ "enabled": true }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        // A restart with the same config keeps the stored value rather than reseeding it.
+        let restarted = FeatureFlags::new(&config);
+        restarted.load(&state.storage, &config).await.unwrap();
+        assert!(restarted.is_enabled(TRANSFER_DEDUP_FLAG));
+        let (_, listed) = get_json(&router, "/v1/node/features").await;
+        assert_eq!(listed["features"][2]["enabled"], true);
+        assert_eq!(listed["features"][2]["updated_by"], "local");
+        let revisions: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM node_config_revisions WHERE config_json LIKE '%\"features\"%'",
+        )
+        .fetch_one(state.storage.pool())
+        .await
+        .unwrap();
+        assert_eq!(revisions, 1);
+    }
+
+    #[tokio::test]
+    async fn unknown_feature_flags_are_rejected() {
+        let state = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        let router = build_router(state.clone());
+        let (status, body) = put_json(
+            &router,
+            "/v1/node/features",
+            json!({ "compresion": { "enabled": false }, "compression": { "enabled": false } }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"], "unknown_feature_flag");
+        assert_eq!(body["names"], json!(["compresion"]));
+        assert!(state.features.is_enabled(COMPRESSION_FLAG));
+        assert!(state.storage.list_feature_flags().await.unwrap().is_empty());
+
+        let (status, body) = put_json(
+            &router,
+            "/v1/node/features/webhooks",
+            json!({ "enabled": true }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["names"], json!(["webhooks"]));
+    }
+
+    // Has no path to any peer but takes LXMF envelopes for store-and-forward; their results
+    // come back later as `result.delayed` events.
+    struct OfflinePeerBridge {
+        mesh: InMemoryRpcMeshBridge,
+        sent: std::sync::Mutex<Vec<MeshCommandEnvelope<Value>>>,
+    }
+
+    #[async_trait::async_trait]
+    impl RpcMeshBridge for OfflinePeerBridge {
+        async fn send_command(
+            &self,
+            envelope: MeshCommandEnvelope<Value>,
+        ) -> Result<MeshResultEnvelope<Value>, BridgeError> {
+            self.sent.lock().unwrap().push(envelope.clone());
+            if envelope.transport_hint != Some(TransferHint::Lxmf) {
+                return Err(BridgeError::PeerUnreachable(format!(
+                    "peer_unreachable: no path to {}",
+                    envelope.destination_identity
+                )));
+            }
+            let mut ack = self.mesh.send_command(envelope).await?;
+            ack.payload = json!({ "in_transit": true });
+            Ok(ack)
+        }
+
+        async fn publish_event(
+            &self,
+            envelope: MeshEventEnvelope<Value>,
+        ) -> Result<BridgeReceipt, BridgeError> {
+            self.mesh.publish_event(envelope).await
+        }
+
+        async fn start_transfer(
+            &self,
+            envelope: MeshTransferEnvelope<Value>,
+        ) -> Result<BridgeReceipt, BridgeError> {
+            self.mesh.start_transfer(envelope).await
+        }
+
+        async fn query_receipt(
+            &self,
+            message_id: &str,
+        ) -> Result<Option<BridgeReceipt>, BridgeError> {
+            self.mesh.query_receipt(message_id).await
+        }
+
+        async fn poll_events(
+            &self,
+            limit: usize,
+        ) -> Result<Vec<MeshEventEnvelope<Value>>, BridgeError> {
+            self.mesh.poll_events(limit).await
+        }
+
+        async fn poll_commands(
+            &self,
+            limit: usize,
+        ) -> Result<Vec<MeshCommandEnvelope<Value>>, BridgeError> {
+            self.mesh.poll_commands(limit).await
+        }
+
+        async fn send_result(
+            &self,
+            envelope: MeshResultEnvelope<Value>,
+        ) -> Result<BridgeReceipt, BridgeError> {
+            self.mesh.send_result(envelope).await
+        }
+
+        async fn set_inbound_backpressure(&self, enabled: bool) -> Result<(), BridgeError> {
+            self.mesh.set_inbound_backpressure(enabled).await
+        }
+    }
+
+    // The commands sent for jobs, leaving out the handshake that goes first to a new peer.
+    fn sent_commands(bridge: &OfflinePeerBridge) -> Vec<MeshCommandEnvelope<Value>> {
+        let sent = bridge.sent.lock().unwrap();
+        sent.iter()
+            .filter(|envelope| envelope.operation == "event.create")
+            .cloned()
+            .collect()
+    }
+
+    async fn offline_peer_node(ttl_ms: u64) -> (AppState, Arc<OfflinePeerBridge>) {
+        let bridge = Arc::new(OfflinePeerBridge {
+            mesh: InMemoryRpcMeshBridge::new(true, true),
+            sent: Default::default(),
+        });
+        let state = test_state(bridge.clone()).await;
+        {
+            let mut config = state.node_config.write().await;
+            config.delivery.store_and_forward = true;
+            config.delivery.store_and_forward_ttl_ms = ttl_ms;
+        }
+        (state, bridge)
+    }
+
+    async fn in_transit_job(state: &AppState, operation: &str, payload: Value) -> String {
+        let router = build_router(state.clone());
+        let response = send(&router, command_request(operation, payload)).await;
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        let job_id = json_body(response).await["job_id"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        // The status flips before the escalation step is recorded; wait for both.
+        for _ in 0..200 {
+            let job = state.storage.get_job(&job_id).await.unwrap().unwrap();
+            let dispatch: Value = job
+                .dispatch_json
+                .as_deref()
+                .map_or(Value::Null, |raw| serde_json::from_str(raw).unwrap());
+            let recorded = dispatch["escalations"]
+                .as_array()
+                .is_some_and(|steps| steps.iter().any(|step| step["step"] == "in_transit"));
+            if job.status == "in_transit" && recorded {
+                return job_id;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        panic!("job {job_id} never went in transit");
+    }
+
+    fn escalation_steps(job: &serde_json::Value) -> Vec<serde_json::Value> {
+        let dispatch: serde_json::Value =
+            serde_json::from_str(job["dispatch_json"].as_str().unwrap()).unwrap();
+        dispatch["escalations"].as_array().unwrap().clone()
+    }
+
+    #[tokio::test]
+    async fn unreachable_peer_is_escalated_and_completed_by_a_late_result() {
+        let (state, bridge) = offline_peer_node(60_000).await;
+        let router = build_router(state.clone());
+        let payload = json!({ "uid": "evt-1", "destination_identity": PEER, "ttl_ms": 1000 });
+        let job_id = in_transit_job(&state, "event.create", payload).await;
+
+        let sent = sent_commands(&bridge);
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[0].message_id, sent[1].message_id);
+        assert_eq!(sent[0].ttl_ms, Some(1000));
+        assert_eq!(sent[1].transport_hint, Some(TransferHint::Lxmf));
+        assert_eq!(sent[1].ttl_ms, Some(60_000));
+
+        let result = MeshResultEnvelope {
+            message_id: Uuid::now_v7().to_string(),
+            correlation_id: sent[1].message_id.clone(),
+            operation: "event.create".to_string(),
+            sent_at: chrono::Utc::now(),
+            source_identity: PEER.parse().unwrap(),
+            destination_identity: sent[1].source_identity.clone(),
+            content_type: CONTENT_TYPE_MSGPACK.to_string(),
+            payload: json!({ "uid": "evt-1", "stored": true }),
+            ttl_ms: None,
+            transport_hint: Some(TransferHint::Lxmf),
+            trace: Vec::new(),
+            trace_truncated: false,
+        };
+        bridge.mesh.inject_event(MeshEventEnvelope {
+            message_id: Uuid::now_v7().to_string(),
+            event: DELAYED_RESULT_EVENT.to_string(),
+            sent_at: chrono::Utc::now(),
+            source_identity: PEER.parse().unwrap(),
+            destination_identity: sent[1].source_identity.clone(),
+            content_type: CONTENT_TYPE_MSGPACK.to_string(),
+            payload: serde_json::to_value(&result).unwrap(),
+            ttl_ms: None,
+            transport_hint: Some(TransferHint::Lxmf),
+        });
+        ingest_events(&state).await.unwrap();
+
+        let (status, job) = get_json(&router, &format!("/v1/jobs/{job_id}")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(job["status"], "success");
+        let steps = escalation_steps(&job);
+        let names: Vec<&str> = steps
+            .iter()
+            .map(|step| step["step"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            ["unreachable", "escalated", "in_transit", "delivered"]
+        );
+        assert!(steps[0]["detail"].as_str().unwrap().contains("no path"));
+        assert_eq!(steps[1]["transport_hint"], "lxmf");
+        assert_eq!(steps[1]["ttl_ms"], 60_000);
+        assert!(steps[2]["expires_at"].is_string());
+        assert_eq!(
+            attempt_log(&router, &json!(job_id)).await,
+            [
+                ("escalated".to_string(), json!("peer_unreachable"), false),
+                ("delivered".to_string(), Value::Null, true),
+            ]
+        );
+        let (_, log) = get_json(&router, &format!("/v1/jobs/{job_id}/attempts")).await;
+        let attempts = log["attempts"].as_array().unwrap();
+        assert_eq!(attempts[0]["message_id"], attempts[1]["message_id"]);
+        assert!(attempts[0]["error_detail"].as_str().unwrap().contains("no path"));
+        assert_eq!(attempts[0]["transport_used"], Value::Null);
+        assert_eq!(attempts[1]["transport_planned"], "lxmf");
+        assert!(attempts[0]["finished_at"].as_str() <= attempts[1]["started_at"].as_str());
+        assert_eq!(log["summary"]["last_error"]["variant"], "peer_unreachable");
+        let stored = state
+            .storage
+            .get_job_result(&job_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(stored.result_json.contains("stored"));
+    }
+
+    #[tokio::test]
+    async fn escalated_job_fails_when_the_ttl_runs_out() {
+        let (state, bridge) = offline_peer_node(50).await;
+        let payload = json!({ "uid": "evt-1", "destination_identity": PEER });
+        let job_id = in_transit_job(&state, "event.create", payload).await;
+        assert_eq!(sent_commands(&bridge)[1].ttl_ms, Some(50));
+
+        tokio::time::sleep(Duration::from_millis(80)).await;
+        assert_eq!(check_transit_expiry(&state).await.unwrap(), 1);
+        let job = state.storage.get_job(&job_id).await.unwrap().unwrap();
+        assert_eq!(job.status, "failed");
+        assert_eq!(job.failure_reason.as_deref(), Some(UNDELIVERED_TTL_EXPIRED));
+        let job = serde_json::to_value(&job).unwrap();
+        let last = escalation_steps(&job).pop().unwrap();
+        assert_eq!(last["step"], "expired");
+
+        // Without store-and-forward an unreachable peer fails the job on the spot.
+        state.node_config.write().await.delivery.store_and_forward = false;
+        let router = build_router(state.clone());
+        let payload = json!({ "uid": "evt-2", "destination_identity": PEER });
+        let job = settled_command(&router, "event.create", payload).await;
+        assert_eq!(job["status"], "failed");
+        assert!(job["failure_reason"]
+            .as_str()
+            .unwrap()
+            .starts_with("peer unreachable"));
+        assert_eq!(
+            attempt_log(&router, &job["job_id"]).await,
+            [("failed".to_string(), json!("peer_unreachable"), false)]
+        );
+    }
+
+    const DOWN_PEER: &str = "dd00000000000000000000000000000d";
+
+    // Fails every command sent to the destinations in `down` and counts the job commands.
+    struct ScriptedBridge {
+        mesh: InMemoryRpcMeshBridge,
+        down: std::sync::Mutex<Vec<String>>,
+        sends: std::sync::Mutex<Vec<(String, String)>>,
+    }
+
+    impl ScriptedBridge {
+        fn sends_to(&self, destination: &str, operation: &str) -> usize {
+            let sends = self.sends.lock().unwrap();
+            sends
+                .iter()
+                .filter(|sent| sent.0 == destination && sent.1 == operation)
+                .count()
+        }
+
+        fn recover(&self, destination: &str) {
+            self.down.lock().unwrap().retain(|down| down != destination);
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl RpcMeshBridge for ScriptedBridge {
+        async fn send_command(
+            &self,
+            envelope: MeshCommandEnvelope<Value>,
+        ) -> Result<MeshResultEnvelope<Value>, BridgeError> {
+            let destination = envelope.destination_identity.to_string();
+            self.sends
+                .lock()
+                .unwrap()
+                .push((destination.clone(), envelope.operation.clone()));
+            if self.down.lock().unwrap().contains(&destination) {
+                return Err(BridgeError::SendFailed(format!("link to {destination} failed")));
+            }
+            self.mesh.send_command(envelope).await
+        }
+
+        async fn publish_event(
+            &self,
+            envelope: MeshEventEnvelope<Value>,
+        ) -> Result<BridgeReceipt, BridgeError> {
+            self.mesh.publish_event(envelope).await
+        }
+
+        async fn start_transfer(
+            &self,
+            envelope: MeshTransferEnvelope<Value>,
+        ) -> Result<BridgeReceipt, BridgeError> {
+            self.mesh.start_transfer(envelope).await
+        }
+
+        async fn query_receipt(
+            &self,
+            message_id: &str,
+        ) -> Result<Option<BridgeReceipt>, BridgeError> {
+            self.mesh.query_receipt(message_id).await
+        }
+
+        async fn poll_events(
+            &self,
+            limit: usize,
+        ) -> Result<Vec<MeshEventEnvelope<Value>>, BridgeError> {
+            self.mesh.poll_events(limit).await
+        }
+
+        async fn poll_commands(
+            &self,
+            limit: usize,
+        ) -> Result<Vec<MeshCommandEnvelope<Value>>, BridgeError> {
+            self.mesh.poll_commands(limit).await
+        }
+
+        async fn send_result(
+            &self,
+            envelope: MeshResultEnvelope<Value>,
+        ) -> Result<BridgeReceipt, BridgeError> {
+            self.mesh.send_result(envelope).await
+        }
+
+        async fn set_inbound_backpressure(&self, enabled: bool) -> Result<(), BridgeError> {
+            self.mesh.set_inbound_backpressure(enabled).await
+        }
+    }
+
+    async fn scripted_node(
+        failure_threshold: u32,
+        cooldown_secs: u64,
+        policy: CircuitPolicy,
+    ) -> (AppState, Arc<ScriptedBridge>) {
+        let bridge = Arc::new(ScriptedBridge {
+            mesh: InMemoryRpcMeshBridge::new(true, true),
+            down: std::sync::Mutex::new(vec![DOWN_PEER.to_string()]),
+            sends: Default::default(),
+        });
+        let state = test_state(bridge.clone()).await;
+        {
+            let mut config = state.node_config.write().await;
+            config.delivery.circuit_breaker.failure_threshold = failure_threshold;
+            config.delivery.circuit_breaker.cooldown_secs = cooldown_secs;
+            config.delivery.circuit_breaker.policy = policy;
+        }
+        (state, bridge)
+    }
+
+    async fn job_reaches(state: &AppState, job_id: &str, status: &str) -> JobRecord {
+        for _ in 0..200 {
+            let job = state.storage.get_job(job_id).await.unwrap().unwrap();
+            if job.status == status {
+                return job;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        panic!("job {job_id} never reached {status}");
+    }
+
+    #[tokio::test]
+    async fn circuit_opens_after_repeated_failures_and_fails_fast() {
+        let (state, bridge) = scripted_node(2, 3600, CircuitPolicy::FailFast).await;
+        let router = build_router(state.clone());
+        let down = |uid: &str| json!({ "uid": uid, "destination_identity": DOWN_PEER });
+        for uid in ["evt-1", "evt-2"] {
+            let job = settled_command(&router, "event.create", down(uid)).await;
+            assert!(job["failure_reason"].as_str().unwrap().contains("link to"));
+            assert_eq!(
+                attempt_log(&router, &job["job_id"]).await,
+                [("failed".to_string(), json!("send_failed"), false)]
+            );
+        }
+        assert_eq!(bridge.sends_to(DOWN_PEER, "event.create"), 2);
+
+        let job = settled_command(&router, "event.create", down("evt-3")).await;
+        assert_eq!(job["status"], "failed");
+        assert_eq!(job["failure_reason"], CIRCUIT_OPEN_REASON);
+        assert_eq!(bridge.sends_to(DOWN_PEER, "event.create"), 2);
+        // Refused jobs never reach the bridge, so they log nothing.
+        assert_eq!(attempt_log(&router, &job["job_id"]).await, []);
+        let (_, status) = get_json(&router, "/v1/node/status").await;
+        let latency = &status["dispatch_latency"]["event.create"];
+        assert_eq!((&latency["attempts"], &latency["errors"]), (&json!(2), &json!(2)));
+
+        let (_, queue) = get_json(&router, "/v1/node/queue").await;
+        let circuits = queue["circuits"].as_array().unwrap();
+        assert_eq!(circuits.len(), 1);
+        assert_eq!(circuits[0]["destination_identity"], DOWN_PEER);
+        assert_eq!(circuits[0]["state"], "open");
+        assert_eq!(circuits[0]["consecutive_failures"], 2);
+        assert!(circuits[0]["retry_at"].is_string());
+        let (status, peer) = get_json(&router, &format!("/v1/peers/{DOWN_PEER}")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(peer["circuits"][0]["operation"], "event.create");
+        let (status, _) = get_json(&router, &format!("/v1/peers/{PEER}")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn circuits_are_independent_per_destination_and_operation() {
+        let (state, bridge) = scripted_node(1, 3600, CircuitPolicy::FailFast).await;
+        let router = build_router(state.clone());
+        let payload = json!({ "uid": "evt-1", "destination_identity": DOWN_PEER });
+        settled_command(&router, "event.create", payload).await;
+        let payload = json!({ "uid": "evt-1", "destination_identity": DOWN_PEER });
+        let job = settled_command(&router, "event.create", payload).await;
+        assert_eq!(job["failure_reason"], CIRCUIT_OPEN_REASON);
+
+        // Another operation to the same peer still goes out and trips its own circuit.
+        let payload = json!({ "uid": "evt-1", "destination_identity": DOWN_PEER });
+        let job = settled_command(&router, "event.update", payload).await;
+        assert_ne!(job["failure_reason"], CIRCUIT_OPEN_REASON);
+        assert_eq!(bridge.sends_to(DOWN_PEER, "event.update"), 1);
+
+        // The same operation to a healthy peer is unaffected.
+        let payload = json!({ "uid": "evt-1", "destination_identity": PEER });
+        let job = settled_command(&router, "event.create", payload).await;
+        assert_eq!(job["status"], "success");
+        let circuits = state.circuits.views(&Default::default());
+        let keys: Vec<_> = circuits
+            .iter()
+            .map(|circuit| (circuit.operation.as_str(), circuit.state))
+            .collect();
+        assert_eq!(
+            keys,
+            [
+                ("event.create", crate::circuit::CircuitState::Open),
+                ("event.update", crate::circuit::CircuitState::Open),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn half_open_probe_success_closes_the_circuit_and_releases_deferred_jobs() {
+        let (state, bridge) = scripted_node(1, 3600, CircuitPolicy::Defer).await;
+        let router = build_router(state.clone());
+        let mut events = state.sse_bus.subscribe();
+        let payload = json!({ "uid": "evt-1", "destination_identity": DOWN_PEER });
+        settled_command(&router, "event.create", payload).await;
+
+        let mut deferred = Vec::new();
+        for uid in ["evt-2", "evt-3"] {
+            let payload = json!({ "uid": uid, "destination_identity": DOWN_PEER });
+            let response = send(&router, command_request("event.create", payload)).await;
+            let job_id = json_body(response).await["job_id"].as_str().unwrap().to_string();
+            let job = job_reaches(&state, &job_id, DEFERRED_STATUS).await;
+            assert_eq!(job.failure_reason.as_deref(), Some(CIRCUIT_OPEN_REASON));
+            deferred.push(job_id);
+        }
+        assert_eq!(bridge.sends_to(DOWN_PEER, "event.create"), 1);
+        // Nothing is released while the cool-down runs.
+        assert_eq!(probe_deferred(&state, chrono::Utc::now()).await.unwrap(), 0);
+
+        bridge.recover(DOWN_PEER);
+        state.node_config.write().await.delivery.circuit_breaker.cooldown_secs = 0;
+        assert_eq!(probe_deferred(&state, chrono::Utc::now()).await.unwrap(), 1);
+        for job_id in &deferred {
+            job_reaches(&state, job_id, "success").await;
+        }
+        assert_eq!(bridge.sends_to(DOWN_PEER, "event.create"), 3);
+        let (_, queue) = get_json(&router, "/v1/node/queue").await;
+        assert_eq!(queue["circuits"], json!([]));
+
+        let mut states = Vec::new();
+        let mut deferrals = Vec::new();
+        while let Ok(update) = events.try_recv() {
+            if update.event_type == crate::circuit::CIRCUIT_CHANGED_EVENT {
+                states.push(update.data["state"].as_str().unwrap().to_string());
+            }
+            if update.event_type == FEED_JOB_EVENT && update.data["status"] == DEFERRED_STATUS {
+                deferrals.push(RetryAdvice::from_body(&update.data).unwrap());
+            }
+        }
+        assert_eq!(states, ["open", "half_open", "closed"]);
+        // Subscribers were told the cool-down's end with each deferral.
+        assert_eq!(deferrals.len(), 2);
+        for advice in deferrals {
+            assert_eq!(advice.scope, RetryScope::Circuit);
+            assert!((3_590_000..=3_600_000).contains(&advice.retry_after_ms), "{advice:?}");
+        }
+    }
+
+    async fn export_bundle_file(router: &Router, filter: Value) -> (String, Vec<u8>) {
+        let response = send(
+            router,
+            Request::post("/v1/admin/bundles/export")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(filter.to_string()))
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            header_of(&response, header::CONTENT_TYPE),
+            Some(SNEAKERNET_MEDIA_TYPE)
+        );
+        let bundle_id = header_of(&response, header::HeaderName::from_static(BUNDLE_ID_HEADER))
+            .unwrap()
+            .to_string();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (bundle_id, bytes.to_vec())
+    }
+
+    async fn import_bundle_file(router: &Router, bytes: Vec<u8>) -> (StatusCode, Value) {
+        let response = send(
+            router,
+            Request::post("/v1/admin/bundles/import")
+                .header(header::CONTENT_TYPE, SNEAKERNET_MEDIA_TYPE)
+                .body(Body::from(bytes))
+                .unwrap(),
+        )
+        .await;
+        let status = response.status();
+        (status, json_body(response).await)
+    }
+
+    // Has `state` trust bundles signed with the key `signer` reported.
+    async fn trust_signer(state: &AppState, router: &Router, signer: &Value) {
+        let identity = signer["identity"].as_str().unwrap();
+        state.node_config.write().await.sneakernet.trusted_signers.insert(
+            identity.to_string(),
+            signer["public_key"].as_str().unwrap().to_string(),
+        );
+        add_allowlisted(router, identity).await;
+    }
+
+    #[tokio::test]
+    async fn bundles_carry_a_job_between_disconnected_nodes() {
+        const ORIGIN: &str = "aa00000000000000000000000000000a";
+        let (origin, _bridge) = offline_peer_node(60_000).await;
+        origin.node_config.write().await.identity.source_identity = Some(ORIGIN.to_string());
+        let far_bridge = Arc::new(InMemoryRpcMeshBridge::new(true, true));
+        let far = test_state(far_bridge.clone()).await;
+        far.node_config.write().await.identity.source_identity = Some(PEER.to_string());
+        let origin_router = build_router(origin.clone());
+        let far_router = build_router(far.clone());
+        let (_, origin_key) = get_json(&origin_router, "/v1/admin/bundles/key").await;
+        let (_, far_key) = get_json(&far_router, "/v1/admin/bundles/key").await;
+        assert_eq!(origin_key["identity"], ORIGIN);
+
+        let payload = json!({ "destination_identity": PEER, "ttl_ms": 1000 });
+        let job_id = in_transit_job(&origin, "node.ping", payload).await;
+        let filter = json!({ "destination_identities": [PEER] });
+        let (bundle_id, outbound) = export_bundle_file(&origin_router, filter.clone()).await;
+
+        // Until the far node trusts the origin's key it takes nothing from the file.
+        let (status, refused) = import_bundle_file(&far_router, outbound.clone()).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(refused["error"], "bundle_signer_not_trusted");
+        trust_signer(&far, &far_router, &origin_key).await;
+        trust_signer(&origin, &origin_router, &far_key).await;
+
+        let (status, report) = import_bundle_file(&far_router, outbound.clone()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(report["bundle_id"], bundle_id.as_str());
+        assert_eq!(report["source_identity"], ORIGIN);
+        assert_eq!(report["accepted"], 1);
+        assert_eq!(report["items"][0]["kind"], "command");
+        let (_, again) = import_bundle_file(&far_router, outbound).await;
+        assert_eq!((again["accepted"].clone(), again["duplicate"].clone()), (json!(0), json!(1)));
+        // The command left with the first bundle, so a second export carries nothing.
+        let (_, empty) = export_bundle_file(&origin_router, filter).await;
+        let (_, report) = import_bundle_file(&far_router, empty).await;
+        assert_eq!(report["items"], json!([]));
+
+        // The answer waits in the far node's outbox rather than going to its mesh.
+        let (_, inbound) = export_bundle_file(&far_router, json!({})).await;
+        assert!(far_bridge.sent_results().is_empty());
+        let mut forged: Value = decode_canonical(&inbound).unwrap();
+        forged["bundle"]["results"][0]["payload"]["status"] = json!("forged");
+        let forged = retasync_contract::encode_canonical(&forged).unwrap();
+        let (status, refused) = import_bundle_file(&origin_router, forged).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(refused["error"], "bundle_signature_invalid");
+        assert_eq!(
+            origin.storage.get_job(&job_id).await.unwrap().unwrap().status,
+            "in_transit"
+        );
+
+        let (status, report) = import_bundle_file(&origin_router, inbound).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(report["accepted"], 1);
+        assert_eq!(report["items"][0]["kind"], "result");
+        let (_, job) = get_json(&origin_router, &format!("/v1/jobs/{job_id}")).await;
+        assert_eq!(job["status"], "success");
+        let steps = escalation_steps(&job);
+        let names: Vec<&str> = steps
+            .iter()
+            .map(|step| step["step"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            ["unreachable", "escalated", "in_transit", "exported", "delivered"]
+        );
+        assert_eq!(steps[3]["detail"], bundle_id.as_str());
+        let stored = origin.storage.get_job_result(&job_id).await.unwrap().unwrap();
+        assert!(stored.result_json.contains("ok"));
+    }
+
+    // Holds command sends open like a daemon waiting on a slow peer, answers cancellation
+    // with a preset outcome, and lets a transfer chunk through only for each gate permit.
+    struct HeldBridge {
+        mesh: InMemoryRpcMeshBridge,
+        cancel_outcome: Option<CancelOutcome>,
+        chunk_gate: tokio::sync::Semaphore,
+        chunks_offered: std::sync::atomic::AtomicUsize,
+        sent: std::sync::Mutex<Vec<String>>,
+        recalled: std::sync::Mutex<Vec<String>>,
+    }
+
+    impl HeldBridge {
+        fn new(cancel_outcome: Option<CancelOutcome>, chunk_permits: usize) -> Arc<Self> {
+            Arc::new(Self {
+                mesh: InMemoryRpcMeshBridge::new(true, true),
+                cancel_outcome,
+                chunk_gate: tokio::sync::Semaphore::new(chunk_permits),
+                chunks_offered: Default::default(),
+                sent: Default::default(),
+                recalled: Default::default(),
+            })
+        }
+
+        fn sent(&self) -> Vec<String> {
+            self.sent.lock().unwrap().clone()
+        }
+
+        fn recalled(&self) -> Vec<String> {
+            self.recalled.lock().unwrap().clone()
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl RpcMeshBridge for HeldBridge {
+        async fn send_command(
+            &self,
+            envelope: MeshCommandEnvelope<Value>,
+        ) -> Result<MeshResultEnvelope<Value>, BridgeError> {
+            if envelope.operation != "event.create" {
+                return self.mesh.send_command(envelope).await;
+            }
+            self.sent.lock().unwrap().push(envelope.message_id);
+            std::future::pending().await
+        }
+
+        async fn publish_event(
+            &self,
+            envelope: MeshEventEnvelope<Value>,
+        ) -> Result<BridgeReceipt, BridgeError> {
+            self.mesh.publish_event(envelope).await
+        }
+
+        async fn start_transfer(
+            &self,
+            envelope: MeshTransferEnvelope<Value>,
+        ) -> Result<BridgeReceipt, BridgeError> {
+            self.chunks_offered
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            self.chunk_gate.acquire().await.unwrap().forget();
+            self.sent.lock().unwrap().push(envelope.message_id.clone());
+            self.mesh.start_transfer(envelope).await
+        }
+
+        async fn query_receipt(
+            &self,
+            message_id: &str,
+        ) -> Result<Option<BridgeReceipt>, BridgeError> {
+            self.mesh.query_receipt(message_id).await
+        }
+
+        async fn poll_events(
+            &self,
+            limit: usize,
+        ) -> Result<Vec<MeshEventEnvelope<Value>>, BridgeError> {
+            self.mesh.poll_events(limit).await
+        }
+
+        async fn poll_commands(
+            &self,
+            limit: usize,
+        ) -> Result<Vec<MeshCommandEnvelope<Value>>, BridgeError> {
+            self.mesh.poll_commands(limit).await
+        }
+
+        async fn send_result(
+            &self,
+            envelope: MeshResultEnvelope<Value>,
+        ) -> Result<BridgeReceipt, BridgeError> {
+            self.mesh.send_result(envelope).await
+        }
+
+        async fn set_inbound_backpressure(&self, enabled: bool) -> Result<(), BridgeError> {
+            self.mesh.set_inbound_backpressure(enabled).await
+        }
+
+        async fn cancel_message(&self, message_id: &str) -> Result<CancelOutcome, BridgeError> {
+            self.recalled.lock().unwrap().push(message_id.to_string());
+            match self.cancel_outcome {
+                Some(outcome) => Ok(outcome),
+                None => self.mesh.cancel_message(message_id).await,
+            }
+        }
+    }
+
+    async fn wait_for_sends(bridge: &HeldBridge, count: usize) {
+        for _ in 0..400 {
+            if bridge.sent().len() >= count {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        panic!("bridge never saw {count} sends");
+    }
+
+    fn cancel_request(job_id: &str) -> Request<Body> {
+        Request::post(format!("/v1/jobs/{job_id}/cancel"))
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn job_cancelled_before_dispatch_never_reaches_the_bridge() {
+        let bridge = HeldBridge::new(Some(CancelOutcome::Cancelled), 0);
+        let state = test_state(bridge.clone()).await;
+        let router = build_router(state.clone());
+        let job_id = unworked_job(&state, "evt-1").await;
+
+        let response = send(&router, cancel_request(&job_id)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = json_body(response).await;
+        assert_eq!(body["status"], "cancelled");
+        assert!(body.get("mesh_cancel_outcome").is_none());
+
+        let payload = json!({ "uid": "evt-1" });
+        let dispatch = resolve_dispatch(&test_node_config(), "event.create", &payload).unwrap();
+        process_command_job(state.clone(), &job_id, "event.create", payload, dispatch)
+            .await
+            .unwrap();
+        assert!(bridge.sent().is_empty());
+        assert!(bridge.recalled().is_empty());
+        let job = state.storage.get_job(&job_id).await.unwrap().unwrap();
+        assert_eq!(job.status, "cancelled");
+
+        let response = send(&router, cancel_request(&job_id)).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert_eq!(json_body(response).await["status"], "cancelled");
+        let response = send(&router, cancel_request("no-such-job")).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn dispatched_job_records_each_mesh_cancel_outcome() {
+        for (outcome, name) in [
+            (CancelOutcome::Cancelled, "cancelled"),
+            (CancelOutcome::AlreadyDelivered, "already_delivered"),
+            (CancelOutcome::NotFound, "not_found"),
+            (CancelOutcome::Unsupported, "unsupported"),
+        ] {
+            let bridge = HeldBridge::new(Some(outcome), 0);
+            let state = test_state(bridge.clone()).await;
+            let router = build_router(state.clone());
+            let response = send(
+                &router,
+                command_request("event.create", json!({ "uid": name })),
+            )
+            .await;
+            let job_id = json_body(response).await["job_id"]
+                .as_str()
+                .unwrap()
+                .to_string();
+            wait_for_sends(&bridge, 1).await;
+
+            let response = send(&router, cancel_request(&job_id)).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(json_body(response).await["mesh_cancel_outcome"], name);
+            assert_eq!(bridge.recalled(), bridge.sent());
+
+            let (_, job) = get_json(&router, &format!("/v1/jobs/{job_id}")).await;
+            assert_eq!(job["status"], "cancelled");
+            let dispatch: Value =
+                serde_json::from_str(job["dispatch_json"].as_str().unwrap()).unwrap();
+            assert_eq!(dispatch["mesh_cancel_outcome"], name);
+        }
+    }
+
+    #[cfg(feature = "transfers")]
+    #[tokio::test]
+    async fn cancelled_transfer_stops_sending_chunks() {
+        let bridge = HeldBridge::new(None, 2);
+        let state = test_state(bridge.clone()).await;
+        let router = build_router(state.clone());
+        let content = vec![7u8; DEFAULT_CHUNK_SIZE * 4 + 1];
+        let accepted = json_body(send(&router, upload_request(&content, false)).await).await;
+        let transfer_id = accepted["transfer_id"].as_str().unwrap().to_string();
+        wait_for_sends(&bridge, 2).await;
+        for _ in 0..400 {
+            if bridge
+                .chunks_offered
+                .load(std::sync::atomic::Ordering::SeqCst)
+                == 3
+            {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+
+        let response = send(
+            &router,
+            Request::delete(format!("/v1/transfers/{transfer_id}"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = json_body(response).await;
+        assert_eq!(body["status"], "cancelled");
+        let outcomes: Vec<&str> = body["chunks"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|chunk| chunk["outcome"].as_str().unwrap())
+            .collect();
+        assert_eq!(outcomes, ["cancelled", "cancelled"]);
+
+        // The chunk already waiting on the bridge goes out and is recalled; nothing follows it.
+        bridge.chunk_gate.add_permits(8);
+        wait_for_sends(&bridge, 3).await;
+        for _ in 0..400 {
+            if bridge.recalled().len() == 3 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(bridge.sent().len(), 3);
+        assert_eq!(bridge.recalled(), bridge.sent());
+        assert!(bridge.mesh.in_flight().is_empty());
+        let (_, transfer) = get_json(&router, &format!("/v1/transfers/{transfer_id}")).await;
+        assert_eq!(transfer["status"], "cancelled");
+
+        let response = send(
+            &router,
+            Request::delete(format!("/v1/transfers/{transfer_id}"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+    }
+
+    fn consistency_of(response: &axum::response::Response) -> i64 {
+        response
+            .headers()
+            .get(crate::consistency::CONSISTENCY_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok())
+            .expect("consistency header")
+    }
+
+    fn read_at(uri: &str, seq: i64) -> Request<Body> {
+        Request::get(uri)
+            .header(crate::consistency::CONSISTENCY_HEADER, seq.to_string())
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn mutations_return_the_write_sequence_they_reached() {
+        let state = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        let router = build_router(state.clone());
+        let before = state.storage.write_sequence().await.unwrap();
+
+        let response = send(&router, command_request("event.create", json!({}))).await;
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        let seq = consistency_of(&response);
+        assert!(seq > before);
+        let job_id = json_body(response).await["job_id"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        let read = send(&router, read_at(&format!("/v1/jobs/{job_id}"), seq)).await;
+        assert_eq!(read.status(), StatusCode::OK);
+        assert!(consistency_of(&read) >= seq);
+        let (status, _) = get_json(&router, &format!("/v1/jobs/{job_id}?min_seq={seq}")).await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, node) = get_json(&router, "/v1/node/status").await;
+        assert!(node["write_sequence"].as_i64().unwrap() >= seq);
+    }
+
+    #[tokio::test]
+    async fn reads_ahead_of_the_node_time_out_with_503() {
+        let state = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        state.node_config.write().await.consistency.max_wait_ms = 100;
+        let router = build_router(state.clone());
+        let current = state.storage.write_sequence().await.unwrap();
+
+        let started = std::time::Instant::now();
+        let response = send(&router, read_at("/v1/transfers", current + 50)).await;
+        assert!(started.elapsed() >= Duration::from_millis(100));
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(consistency_of(&response), current);
+        assert_eq!(header_of(&response, header::RETRY_AFTER), Some("1"));
+        let body = json_body(response).await;
+        assert_eq!(body["error"], "consistency_unavailable");
+        assert_eq!(body["retry"]["retry_after_ms"], 1000);
+        assert_eq!(body["current_seq"], current);
+        assert_eq!(body["requested_seq"], current + 50);
+
+        let (status, body) = get_json(&router, "/v1/transfers?min_seq=soon").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"], "invalid_consistency_token");
+    }
+
+    #[cfg(feature = "transfers")]
+    #[tokio::test]
+    async fn satisfied_reads_answer_without_waiting() {
+        let state = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        state.node_config.write().await.consistency.max_wait_ms = 10_000;
+        let router = build_router(state.clone());
+        state
+            .storage
+            .create_job("event.create", json!({}))
+            .await
+            .unwrap();
+        let current = state.storage.write_sequence().await.unwrap();
+
+        let started = std::time::Instant::now();
+        let response = send(&router, read_at("/v1/transfers", current)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(consistency_of(&response), current);
+    }
+
+    fn mute_request(body: serde_json::Value) -> Request<Body> {
+        Request::post("/v1/node/mute")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    async fn unmute_node(router: &Router) -> serde_json::Value {
+        let response = send(
+            router,
+            Request::delete("/v1/node/mute")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        json_body(response).await
+    }
+
+    async fn held_by_mute(router: &Router, traffic: &str, count: u64) -> serde_json::Value {
+        for _ in 0..400 {
+            let (_, queue) = get_json(router, "/v1/node/queue").await;
+            if queue["mute"]["held"][traffic] == count {
+                return queue["mute"].clone();
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        panic!("{traffic} never reached {count} held");
+    }
+
+    #[tokio::test]
+    async fn muted_refusals_advise_waiting_out_the_window() {
+        let state = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        let router = build_router(state.clone());
+        let handshake = || {
+            Request::post(format!("/v1/peers/{PEER}/handshake"))
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let until = chrono::Utc::now() + chrono::Duration::seconds(90);
+        let muted = send(&router, mute_request(json!({ "scope": "commands", "until": until })));
+        assert_eq!(muted.await.status(), StatusCode::OK);
+        let refused = send(&router, handshake()).await;
+        assert_eq!(refused.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let retry_after: u64 = header_of(&refused, header::RETRY_AFTER).unwrap().parse().unwrap();
+        let advice = RetryAdvice::from_body(&json_body(refused).await).unwrap();
+        assert_eq!((advice.reason.as_str(), advice.scope), ("node_muted", RetryScope::Node));
+        assert!((89_000..=90_000).contains(&advice.retry_after_ms), "{advice:?}");
+        assert_eq!(retry_after, 90);
+
+        // Without an end the mute is checked on at a steady pace.
+        unmute_node(&router).await;
+        let muted = send(&router, mute_request(json!({ "scope": "commands" }))).await;
+        assert_eq!(muted.status(), StatusCode::OK);
+        let refused = send(&router, handshake()).await;
+        assert_eq!(header_of(&refused, header::RETRY_AFTER), Some("30"));
+        assert_eq!(json_body(refused).await["retry"]["retry_after_ms"], 30_000);
+    }
+
+    #[tokio::test]
+    async fn muted_node_accepts_submissions_but_holds_dispatch() {
+        let path = scratch_dir().join("bridge.rec");
+        let recorder = Arc::new(
+            RecordingBridge::start(Arc::new(InMemoryRpcMeshBridge::new(true, true)), &path)
+                .unwrap(),
+        );
+        let state = test_state(recorder.clone()).await;
+        let router = build_router(state.clone());
+        let mut events = state.sse_bus.subscribe();
+
+        let response = send(&router, mute_request(json!({}))).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let muted = json_body(response).await;
+        assert_eq!(muted["muted"], true);
+        assert_eq!(muted["scope"], "all");
+        assert_eq!(muted["muted_by"], "local");
+        let changed = events.recv().await.unwrap();
+        assert_eq!(changed.event_type, NODE_MUTE_CHANGED_EVENT);
+        assert_eq!(changed.data["muted"], true);
+
+        let mut held = Vec::new();
+        for uid in ["first", "second"] {
+            let response = send(
+                &router,
+                command_request("event.create", json!({ "uid": uid })),
+            )
+            .await;
+            assert_eq!(response.status(), StatusCode::ACCEPTED);
+            assert_eq!(response.headers()[MUTED_HEADER], "all");
+            held.push(
+                json_body(response).await["job_id"]
+                    .as_str()
+                    .unwrap()
+                    .to_string(),
+            );
+        }
+        held_by_mute(&router, "commands", 2).await;
+        let (_, node) = get_json(&router, "/v1/node/status").await;
+        assert_eq!(node["mute"]["held"]["commands"], 2);
+        let ready = send(
+            &router,
+            Request::get("/health/ready").body(Body::empty()).unwrap(),
+        )
+        .await;
+        assert_eq!(ready.status(), StatusCode::OK);
+        for job_id in &held {
+            let job = state.storage.get_job(job_id).await.unwrap().unwrap();
+            assert_eq!(job.status, "queued");
+        }
+        recorder.flush().await;
+        let commands_sent = |records: Vec<BridgeRecord>| -> Vec<String> {
+            records
+                .iter()
+                .filter(|record| record.method == "send_command")
+                .map(|record| {
+                    let command: MeshCommandEnvelope<serde_json::Value> =
+                        decode_canonical(&record.request).unwrap();
+                    command.payload["uid"].as_str().unwrap().to_string()
+                })
+                .collect()
+        };
+        assert!(commands_sent(read_recording(&path).unwrap()).is_empty());
+
+        assert_eq!(unmute_node(&router).await["muted"], false);
+        for job_id in &held {
+            assert_eq!(finished_status(&state, job_id).await, "success");
+        }
+        recorder.flush().await;
+        let mut sent = commands_sent(read_recording(&path).unwrap());
+        sent.sort();
+        assert_eq!(sent, ["first", "second"]);
+        let changed = loop {
+            let event = events.recv().await.unwrap();
+            if event.event_type == NODE_MUTE_CHANGED_EVENT {
+                break event;
+            }
+        };
+        assert_eq!(changed.data["muted"], false);
+        assert_eq!(changed.data["reason"], "unmuted");
+    }
+
+    #[cfg(feature = "transfers")]
+    #[tokio::test]
+    async fn scoped_mute_lets_other_traffic_through() {
+        let state = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        let router = build_router(state.clone());
+        let response = send(&router, mute_request(json!({ "scope": "transfers" }))).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let accepted = json_body(send(&router, upload_request(b"tile bytes", false)).await).await;
+        let transfer_id = accepted["transfer_id"].as_str().unwrap().to_string();
+        let job = settled_command(&router, "event.create", json!({})).await;
+        assert_eq!(job["status"], "success");
+        let mute = held_by_mute(&router, "transfers", 1).await;
+        assert_eq!(mute["held"]["commands"], 0);
+        let (_, transfer) = get_json(&router, &format!("/v1/transfers/{transfer_id}")).await;
+        assert_ne!(transfer["status"], "success");
+
+        unmute_node(&router).await;
+        for _ in 0..400 {
+            let (_, transfer) = get_json(&router, &format!("/v1/transfers/{transfer_id}")).await;
+            if transfer["status"] == "success" {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        panic!("transfer {transfer_id} never resumed");
+    }
+
+    #[tokio::test]
+    async fn timed_mute_lifts_once_its_window_passes() {
+        let state = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        let router = build_router(state.clone());
+        let now = chrono::Utc::now();
+
+        let response = send(
+            &router,
+            mute_request(json!({ "until": now - chrono::Duration::minutes(1) })),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(json_body(response).await["error"], "invalid_mute_until");
+
+        let response = send(
+            &router,
+            mute_request(json!({ "scope": "commands", "until": now + chrono::Duration::hours(1) })),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let muted = json_body(response).await;
+        assert!(muted["remaining_secs"].as_i64().unwrap() > 3500);
+        let response = send(&router, command_request("event.create", json!({}))).await;
+        let job_id = json_body(response).await["job_id"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        held_by_mute(&router, "commands", 1).await;
+
+        assert!(!expire_mute(&state, now + chrono::Duration::minutes(30))
+            .await
+            .unwrap());
+        assert!(expire_mute(&state, now + chrono::Duration::hours(2))
+            .await
+            .unwrap());
+        assert_eq!(finished_status(&state, &job_id).await, "success");
+        let (_, mute) = get_json(&router, "/v1/node/mute").await;
+        assert_eq!(mute["muted"], false);
+    }
+
+    #[tokio::test]
+    async fn mute_survives_a_restart_until_it_expires() {
+        let state = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        let router = build_router(state.clone());
+        let now = chrono::Utc::now();
+        let until = now + chrono::Duration::hours(1);
+        let response = send(
+            &router,
+            mute_request(json!({ "scope": "transfers", "until": until })),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let restarted = || {
+            AppState::new(
+                state.storage.clone(),
+                Arc::new(InMemoryRpcMeshBridge::new(true, true)),
+                test_node_config(),
+                "asyncapi: 3.0.0\n".to_string(),
+                false,
+            )
+        };
+        let resumed = restarted();
+        load_mute(&resumed, now).await.unwrap();
+        let (_, mute) = get_json(&build_router(resumed.clone()), "/v1/node/mute").await;
+        assert_eq!(mute["muted"], true);
+        assert_eq!(mute["scope"], "transfers");
+        assert!(resumed.mute.blocks(Traffic::Transfers));
+        assert!(!resumed.mute.blocks(Traffic::Commands));
+
+        let late = restarted();
+        load_mute(&late, until + chrono::Duration::seconds(1))
+            .await
+            .unwrap();
+        assert!(late.mute.window().is_none());
+        assert!(state.storage.node_mute().await.unwrap().is_none());
+    }
+
+    // A node that dispatches one command at a time, with the first one parked on a commands mute
+    // so the rest queue up behind it. Sent commands are recorded in order.
+    async fn single_lane_node(
+        sqlite_path: &str,
+    ) -> (AppState, Router, Arc<RecordingBridge>, std::path::PathBuf, String) {
+        let path = scratch_dir().join("bridge.rec");
+        let recorder = Arc::new(
+            RecordingBridge::start(Arc::new(InMemoryRpcMeshBridge::new(true, true)), &path)
+                .unwrap(),
+        );
+        let storage = test_storage(sqlite_path).await;
+        let mut config = test_node_config();
+        config.dispatch_queue.max_concurrent_jobs = NonZeroUsize::new(1);
+        let state = AppState::new(
+            storage,
+            recorder.clone(),
+            config,
+            "asyncapi: 3.0.0\n".to_string(),
+            false,
+        );
+        let router = build_router(state.clone());
+        let response = send(&router, mute_request(json!({ "scope": "commands" }))).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let first = queue_command(&router, "first", None).await;
+        (state, router, recorder, path, first)
+    }
+
+    // Submits `event.create` for `uid` and waits until its worker has a place in the lineup.
+    async fn queue_command(router: &Router, uid: &str, priority: Option<&str>) -> String {
+        let mut request = Request::post("/v1/jobs/commands/event.create")
+            .header(header::CONTENT_TYPE, "application/json");
+        if let Some(priority) = priority {
+            request = request.header(crate::meta::PRIORITY_HEADER, priority);
+        }
+        let body = Body::from(json!({ "uid": uid }).to_string());
+        let response = send(router, request.body(body).unwrap()).await;
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        let job_id = json_body(response).await["job_id"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        for _ in 0..400 {
+            let (_, queue) = get_json(router, "/v1/node/queue").await;
+            let dispatch = &queue["dispatch"];
+            let listed = |list: &Value| list.as_array().unwrap().contains(&json!(job_id));
+            if listed(&dispatch["running"]) || listed(&dispatch["next"]) {
+                return job_id;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        panic!("job {job_id} never joined the dispatch queue");
+    }
+
+    fn queue_change_request(uri: &str, body: Value) -> Request<Body> {
+        Request::post(uri)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    fn body_with_reason() -> Value {
+        json!({ "reason": "incident response" })
+    }
+
+    fn queue_order_request(body: Value) -> Request<Body> {
+        Request::put("/v1/node/queue/order")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    async fn sent_uids(recorder: &RecordingBridge, path: &std::path::Path) -> Vec<String> {
+        recorder.flush().await;
+        read_recording(path)
+            .unwrap()
+            .iter()
+            .filter(|record| record.method == "send_command")
+            .map(|record| {
+                let command: MeshCommandEnvelope<Value> =
+                    decode_canonical(&record.request).unwrap();
+                command.payload["uid"].as_str().unwrap().to_string()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn prioritized_job_jumps_the_line() {
+        let (state, router, recorder, path, first) = single_lane_node(&scratch_sqlite()).await;
+        let mut queued = Vec::new();
+        for uid in ["second", "third", "fourth"] {
+            queued.push(queue_command(&router, uid, None).await);
+        }
+        let last = &queued[2];
+
+        let uri = format!("/v1/jobs/{last}/prioritize");
+        let response = send(&router, queue_change_request(&uri, json!({ "reason": " " }))).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(json_body(response).await["error"], "reason_required");
+        let body = json!({ "reason": "casualty report" });
+        let response = send(&router, queue_change_request(&uri, body)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let view = json_body(response).await;
+        assert_eq!(view["running"], json!([first]));
+        assert_eq!(view["next"], json!([last, queued[0], queued[1]]));
+        let prioritized = feed_events(&state, JOB_PRIORITIZED_EVENT).await;
+        assert_eq!(prioritized[0]["job_id"], json!(last));
+        assert_eq!(prioritized[0]["prioritized_by"], "local");
+        assert_eq!(prioritized[0]["reason"], "casualty report");
+
+        unmute_node(&router).await;
+        for job_id in queued.iter().chain([&first]) {
+            assert_eq!(finished_status(&state, job_id).await, "success");
+        }
+        assert_eq!(
+            sent_uids(&recorder, &path).await,
+            ["first", "fourth", "second", "third"]
+        );
+        let uri = format!("/v1/jobs/{first}/prioritize");
+        let response = send(&router, queue_change_request(&uri, body_with_reason())).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let refused = json_body(response).await;
+        assert_eq!(refused["error"], "job_not_queued");
+        assert_eq!(refused["status"], "success");
+    }
+
+    #[tokio::test]
+    async fn held_jobs_are_skipped_until_released() {
+        let (state, router, recorder, path, first) = single_lane_node(&scratch_sqlite()).await;
+        let held = queue_command(&router, "held", None).await;
+        let after = queue_command(&router, "after", None).await;
+
+        let uri = format!("/v1/jobs/{held}/hold");
+        let response = send(&router, queue_change_request(&uri, body_with_reason())).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let hold = json_body(response).await;
+        assert_eq!(hold["status"], "held");
+        assert_eq!(hold["hold"]["held_by"], "local");
+        assert_eq!(hold["hold"]["previous_status"], "queued");
+        let (_, queue) = get_json(&router, "/v1/node/queue").await;
+        assert_eq!(queue["dispatch"]["next"], json!([after]));
+        assert_eq!(queue["dispatch"]["held"][0]["job_id"], json!(held));
+        let uri = format!("/v1/jobs/{after}/release");
+        let response = send(&router, queue_change_request(&uri, json!({}))).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert_eq!(json_body(response).await["error"], "job_not_held");
+
+        unmute_node(&router).await;
+        assert_eq!(finished_status(&state, &first).await, "success");
+        assert_eq!(finished_status(&state, &after).await, "success");
+        let job = state.storage.get_job(&held).await.unwrap().unwrap();
+        assert_eq!(job.status, "held");
+        assert_eq!(sent_uids(&recorder, &path).await, ["first", "after"]);
+
+        let uri = format!("/v1/jobs/{held}/release");
+        let response = send(&router, queue_change_request(&uri, json!({}))).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(json_body(response).await["status"], "queued");
+        assert_eq!(finished_status(&state, &held).await, "success");
+        assert_eq!(sent_uids(&recorder, &path).await, ["first", "after", "held"]);
+        let statuses: Vec<Value> = feed_events(&state, "job.status.changed")
+            .await
+            .into_iter()
+            .filter(|event| event["job_id"] == json!(held))
+            .map(|event| event["status"].clone())
+            .collect();
+        assert!(statuses.contains(&json!("held")));
+        let (_, queue) = get_json(&router, "/v1/node/queue").await;
+        assert_eq!(queue["dispatch"]["held"], json!([]));
+    }
+
+    #[tokio::test]
+    async fn explicit_order_goes_first_then_priority_and_age_resume() {
+        let (state, router, recorder, path, first) = single_lane_node(&scratch_sqlite()).await;
+        let mut jobs = vec![first];
+        for (uid, priority) in [("b", None), ("c", None), ("d", None), ("e", Some("flash"))] {
+            jobs.push(queue_command(&router, uid, priority).await);
+        }
+        let (b, c, d) = (&jobs[1], &jobs[2], &jobs[3]);
+
+        let response = send(&router, queue_order_request(json!({ "job_ids": [d, d] }))).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(json_body(response).await["error"], "duplicate_job_id");
+        let response = send(&router, queue_order_request(json!({ "job_ids": ["missing"] }))).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let uri = format!("/v1/jobs/{b}/hold");
+        send(&router, queue_change_request(&uri, body_with_reason())).await;
+        let response = send(&router, queue_order_request(json!({ "job_ids": [b] }))).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert_eq!(json_body(response).await["status"], "held");
+        let uri = format!("/v1/jobs/{b}/release");
+        send(&router, queue_change_request(&uri, json!({}))).await;
+
+        let body = json!({ "job_ids": [d, c], "reason": "relay the casualty list first" });
+        let response = send(&router, queue_order_request(body)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let view = json_body(response).await;
+        assert_eq!(view["order"], json!([d, c]));
+        assert_eq!(view["next"], json!([d, c, jobs[4], b]));
+        let changed = feed_events(&state, QUEUE_ORDER_CHANGED_EVENT).await;
+        assert_eq!(changed[0]["order"], json!([d, c]));
+        assert_eq!(changed[0]["reason"], "relay the casualty list first");
+
+        unmute_node(&router).await;
+        for job_id in &jobs {
+            assert_eq!(finished_status(&state, job_id).await, "success");
+        }
+        assert_eq!(sent_uids(&recorder, &path).await, ["first", "d", "c", "e", "b"]);
+        let (_, queue) = get_json(&router, "/v1/node/queue").await;
+        assert_eq!(queue["dispatch"]["order"], json!([]));
+    }
+
+    #[tokio::test]
+    async fn ordered_jobs_that_finish_leave_the_queue_view() {
+        let (state, router, recorder, path, first) = single_lane_node(&scratch_sqlite()).await;
+        let mut jobs = Vec::new();
+        for uid in ["b", "c", "d"] {
+            jobs.push(queue_command(&router, uid, None).await);
+        }
+        let (b, c, d) = (&jobs[0], &jobs[1], &jobs[2]);
+        let body = json!({ "job_ids": [d, c], "reason": "relay the casualty list first" });
+        let response = send(&router, queue_order_request(body)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let uri = format!("/v1/jobs/{d}/cancel");
+        let response = send(&router, Request::post(uri).body(Body::empty()).unwrap()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        // A path that never calls the queue itself: the job changes status in storage alone.
+        assert!(state.storage.cancel_job(c).await.unwrap());
+        publish(&state).await;
+        let (_, queue) = get_json(&router, "/v1/node/queue").await;
+        assert_eq!(queue["dispatch"]["order"], json!([]));
+        assert_eq!(queue["dispatch"]["next"], json!([b]));
+        assert_eq!(queue["dispatch"]["running"], json!([first]));
+
+        unmute_node(&router).await;
+        assert_eq!(finished_status(&state, b).await, "success");
+        assert_eq!(sent_uids(&recorder, &path).await, ["first", "b"]);
+    }
+
+    #[tokio::test]
+    async fn holds_survive_a_storage_reopen() {
+        let sqlite_path = scratch_sqlite();
+        let (_state, router, _recorder, _path, _first) = single_lane_node(&sqlite_path).await;
+        let held = queue_command(&router, "held", None).await;
+        let uri = format!("/v1/jobs/{held}/hold");
+        let response = send(&router, queue_change_request(&uri, body_with_reason())).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let storage = test_storage(&sqlite_path).await;
+        let reopened = AppState::new(
+            storage,
+            Arc::new(InMemoryRpcMeshBridge::new(true, true)),
+            test_node_config(),
+            "asyncapi: 3.0.0\n".to_string(),
+            false,
+        );
+        let router = build_router(reopened.clone());
+        let (_, queue) = get_json(&router, "/v1/node/queue").await;
+        let hold = &queue["dispatch"]["held"][0];
+        assert_eq!(hold["job_id"], json!(held));
+        assert_eq!(hold["reason"], "incident response");
+        let job = reopened.storage.get_job(&held).await.unwrap().unwrap();
+        assert_eq!(job.status, "held");
+
+        let uri = format!("/v1/jobs/{held}/release");
+        let response = send(&router, queue_change_request(&uri, json!({}))).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(finished_status(&reopened, &held).await, "success");
+    }
+
+    fn stage_config(config: &NodeConfig) -> Request<Body> {
+        Request::put("/v1/node/config?stage=true")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(serde_json::to_vec(config).unwrap()))
+            .unwrap()
+    }
+
+    fn config_step(step: &str) -> Request<Body> {
+        Request::post(format!("/v1/node/config/{step}"))
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    async fn staged_and_applied(router: &Router, acl_mode: &str) -> serde_json::Value {
+        let mut candidate = test_node_config();
+        candidate.acl_mode = acl_mode.to_string();
+        let staged = send(router, stage_config(&candidate)).await;
+        assert_eq!(staged.status(), StatusCode::ACCEPTED);
+        assert_eq!(json_body(staged).await["phase"], "staged");
+        let applied = send(router, config_step("apply")).await;
+        assert_eq!(applied.status(), StatusCode::OK);
+        json_body(applied).await
+    }
+
+    async fn revision_reasons(state: &AppState) -> Vec<Option<String>> {
+        let mut revisions = state.storage.list_node_config_revisions(10).await.unwrap();
+        revisions.reverse();
+        revisions.into_iter().map(|revision| revision.reason).collect()
+    }
+
+    #[tokio::test]
+    async fn confirmed_config_apply_stays_in_place() {
+        let state = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        let router = build_router(state.clone());
+        let mut events = state.sse_bus.subscribe();
+
+        let applied = staged_and_applied(&router, "denylist").await;
+        assert_eq!(applied["phase"], "applied");
+        assert_eq!(applied["applied_by"], "local");
+        assert_eq!(applied["remaining_secs"], 120);
+        assert_eq!(state.node_config.read().await.acl_mode, "denylist");
+        let mut put = test_node_config();
+        put.acl_mode = "open".to_string();
+        let refused = send(
+            &router,
+            Request::put("/v1/node/config")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(serde_json::to_vec(&put).unwrap()))
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(refused.status(), StatusCode::CONFLICT);
+        assert_eq!(json_body(refused).await["error"], "config_apply_pending");
+
+        let confirmed = send(&router, config_step("confirm")).await;
+        assert_eq!(confirmed.status(), StatusCode::OK);
+        assert_eq!(json_body(confirmed).await["phase"], serde_json::Value::Null);
+        let later = chrono::Utc::now() + chrono::Duration::hours(1);
+        assert!(!config_apply::expire_config_apply(&state, later).await.unwrap());
+        assert_eq!(state.node_config.read().await.acl_mode, "denylist");
+        assert!(state.storage.node_config_apply().await.unwrap().is_none());
+        assert_eq!(revision_reasons(&state).await, [Some("staged_apply".to_string())]);
+
+        let mut seen = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            seen.push(event.event_type);
+        }
+        assert_eq!(
+            seen,
+            [
+                config_apply::CONFIG_STAGED_EVENT,
+                config_apply::CONFIG_APPLIED_EVENT,
+                config_apply::CONFIG_CONFIRMED_EVENT,
+            ]
+        );
+        let again = send(&router, config_step("confirm")).await;
+        assert_eq!(again.status(), StatusCode::CONFLICT);
+        assert_eq!(json_body(again).await["error"], "no_config_apply_pending");
+    }
+
+    #[tokio::test]
+    async fn unconfirmed_config_apply_reverts_when_the_timer_runs_out() {
+        let state = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        let router = build_router(state.clone());
+        let applied = staged_and_applied(&router, "denylist").await;
+        let confirm_by: chrono::DateTime<chrono::Utc> =
+            serde_json::from_value(applied["confirm_by"].clone()).unwrap();
+        let mut events = state.sse_bus.subscribe();
+
+        let early = confirm_by - chrono::Duration::seconds(1);
+        assert!(!config_apply::expire_config_apply(&state, early).await.unwrap());
+        assert_eq!(state.node_config.read().await.acl_mode, "denylist");
+        assert!(config_apply::expire_config_apply(&state, confirm_by).await.unwrap());
+
+        assert_eq!(state.node_config.read().await.acl_mode, "allowlist");
+        let reverted = events.recv().await.unwrap();
+        assert_eq!(reverted.event_type, config_apply::CONFIG_REVERTED_EVENT);
+        assert_eq!(reverted.data["reason"], "unconfirmed_apply");
+        assert_eq!(
+            revision_reasons(&state).await,
+            [Some("staged_apply".to_string()), Some("unconfirmed_apply".to_string())]
+        );
+        let revisions = state.storage.list_node_config_revisions(1).await.unwrap();
+        let restored: NodeConfig = serde_json::from_str(&revisions[0].config_json).unwrap();
+        assert_eq!(restored.acl_mode, "allowlist");
+        let (_, status) = get_json(&router, "/v1/node/config/apply").await;
+        assert_eq!(status["phase"], serde_json::Value::Null);
+        let late = send(&router, config_step("confirm")).await;
+        assert_eq!(late.status(), StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn restart_during_the_confirmation_window_reverts_at_startup() {
+        let state = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        let router = build_router(state.clone());
+        staged_and_applied(&router, "denylist").await;
+
+        // The crashed run had the candidate installed; the restart comes up with it too.
+        let mut running = test_node_config();
+        running.acl_mode = "denylist".to_string();
+        let restarted = AppState::new(
+            state.storage.clone(),
+            Arc::new(InMemoryRpcMeshBridge::new(true, true)),
+            running,
+            "asyncapi: 3.0.0\n".to_string(),
+            false,
+        );
+        config_apply::load(&restarted).await.unwrap();
+        assert_eq!(restarted.node_config.read().await.acl_mode, "allowlist");
+        assert!(restarted.pending_config.lock().await.is_none());
+        assert!(state.storage.node_config_apply().await.unwrap().is_none());
+        assert_eq!(
+            revision_reasons(&state).await.last().unwrap().as_deref(),
+            Some("unconfirmed_apply")
+        );
+    }
+
+    #[tokio::test]
+    async fn staged_config_failures_leave_the_active_config_alone() {
+        let state = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        let router = build_router(state.clone());
+        let before = serde_json::to_value(&*state.node_config.read().await).unwrap();
+
+        let mut invalid = test_node_config();
+        invalid.acl_mode = "denylist".to_string();
+        invalid.operation_defaults.insert(
+            "telemetry.*".to_string(),
+            OperationDefaults {
+                destination_identity: Some("hub".to_string()),
+                ..OperationDefaults::default()
+            },
+        );
+        let rejected = send(&router, stage_config(&invalid)).await;
+        assert_eq!(rejected.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(json_body(rejected).await["error"], "invalid_node_config");
+
+        let mut rebind = test_node_config();
+        rebind.http_bind = "0.0.0.0:9090".to_string();
+        let rejected = send(&router, stage_config(&rebind)).await;
+        assert_eq!(rejected.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body = json_body(rejected).await;
+        assert_eq!(body["error"], "restart_required");
+        assert_eq!(body["fields"], json!(["/http_bind"]));
+
+        let nothing = send(&router, config_step("apply")).await;
+        assert_eq!(nothing.status(), StatusCode::CONFLICT);
+        assert_eq!(json_body(nothing).await["error"], "no_staged_config");
+        assert_eq!(serde_json::to_value(&*state.node_config.read().await).unwrap(), before);
+        assert!(state.storage.node_config_apply().await.unwrap().is_none());
+        assert!(revision_reasons(&state).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn embedded_router_nests_under_the_host_prefix() {
+        async fn host_auth(
+            request: Request<Body>,
+            next: axum::middleware::Next,
+        ) -> axum::response::Response {
+            if request.headers().get("x-host-key").is_none() {
+                return axum::response::IntoResponse::into_response(StatusCode::UNAUTHORIZED);
+            }
+            next.run(request).await
+        }
+        let state = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        let job_id = unworked_job(&state, "nested").await;
+        let host = Router::new()
+            .route("/", axum::routing::get(|| async { "host" }))
+            .merge(embedded_router(state, "/mesh"))
+            .layer(axum::middleware::from_fn(host_auth));
+        let get = |uri: &str| {
+            Request::get(uri)
+                .header("x-host-key", "1")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        assert_eq!(send(&host, get("/")).await.status(), StatusCode::OK);
+        let response = send(&host, get("/mesh/v1/node/status")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(json_body(response).await["healthy"], true);
+        assert_eq!(
+            send(&host, get("/v1/node/status")).await.status(),
+            StatusCode::NOT_FOUND
+        );
+        let unauthenticated = Request::get("/mesh/v1/node/status")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(
+            send(&host, unauthenticated).await.status(),
+            StatusCode::UNAUTHORIZED
+        );
+
+        let response = send(&host, get(&format!("/mesh/v1/jobs/{job_id}"))).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::LINK],
+            format!("</mesh/v2/jobs/{job_id}>; rel=\"successor-version\"").as_str()
+        );
+        let response = send(&host, get(&format!("/mesh/v2/jobs/{job_id}"))).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[cfg(feature = "entities")]
+    #[tokio::test]
+    async fn embedded_router_serves_entities_from_the_host_store() {
+        let store = Arc::new(InMemoryStore::new());
+        let state = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true)))
+            .await
+            .with_store(store.clone());
+        let host = Router::new().merge(embedded_router(state.clone(), "/mesh"));
+        let put = |if_match: Option<i64>, status: &str| {
+            let mut request = Request::put("/mesh/v1/entities/eam/4-eam")
+                .header(header::CONTENT_TYPE, "application/json");
+            if let Some(version) = if_match {
+                request = request.header(header::IF_MATCH, format!("\"{version}\""));
+            }
+            request
+                .body(Body::from(json!({ "status": status }).to_string()))
+                .unwrap()
+        };
+
+        assert_eq!(send(&host, put(None, "green")).await.status(), StatusCode::CREATED);
+        assert_eq!(send(&host, put(Some(1), "amber")).await.status(), StatusCode::OK);
+        let stale = send(&host, put(Some(1), "red")).await;
+        assert_eq!(stale.status(), StatusCode::CONFLICT);
+        assert_eq!(json_body(stale).await["current"]["record"]["status"], "amber");
+        let (status, entity) = get_json(&host, "/mesh/v1/entities/eam/4-eam").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(entity["version"], 2);
+        assert_eq!(entity["record"]["status"], "amber");
+
+        // Only the host's store holds the entity; the node's database never saw it.
+        let stored = store.get_entity("eam", "4-eam").await.unwrap().unwrap();
+        assert_eq!(stored.record, json!({ "status": "amber" }));
+        assert!(state.storage.get_entity("eam", "4-eam").await.unwrap().is_none());
+        let unprefixed = Request::get("/v1/entities/eam/4-eam").body(Body::empty()).unwrap();
+        assert_eq!(send(&host, unprefixed).await.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn runtime_restores_state_and_stops_on_shutdown() {
+        let state = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        state
+            .storage
+            .set_node_mute(Some(&json!({
+                "scope": "events",
+                "since": chrono::Utc::now(),
+                "muted_by": "test",
+            })))
+            .await
+            .unwrap();
+        // Spool files from an upload the last run never finished, and one still being written.
+        let spool_dir = crate::spool::TransferSpoolSettings::default()
+            .dir_for(state.storage.database_path());
+        std::fs::create_dir_all(&spool_dir).unwrap();
+        let two_hours_ago = std::time::SystemTime::now() - Duration::from_secs(7200);
+        for name in ["orphan.spool", "notes.txt"] {
+            let file = std::fs::File::create(spool_dir.join(name)).unwrap();
+            file.set_modified(two_hours_ago).unwrap();
+        }
+        std::fs::File::create(spool_dir.join("fresh.spool")).unwrap();
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let runtime = ControlPlaneRuntime::new(state.clone())
+            .start(async move {
+                let _ = stopped.await;
+            })
+            .await
+            .unwrap();
+        assert!(state.mute.blocks(Traffic::Events));
+        let mut left: Vec<_> = std::fs::read_dir(&spool_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        left.sort();
+        assert_eq!(left, ["fresh.spool", "notes.txt"]);
+
+        stop.send(()).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), runtime)
+            .await
+            .expect("runtime stops")
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn fleet_nodes_are_listed_with_their_staleness() {
+        let state = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        let now = chrono::Utc::now();
+        let report = |n: u64| json!({ "identity": PARTNER, "interval_secs": 60, "n": n });
+        for (n, received_at) in [(1, now - chrono::Duration::seconds(600)), (2, now)] {
+            state
+                .storage
+                .record_fleet_report(&PARTNER.parse().unwrap(), &report(n), 60, received_at, 20)
+                .await
+                .unwrap();
+        }
+        let quiet = "0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a".parse().unwrap();
+        let long_ago = now - chrono::Duration::seconds(181);
+        state
+            .storage
+            .record_fleet_report(&quiet, &report(3), 60, long_ago, 20)
+            .await
+            .unwrap();
+        let router = build_router(state);
+
+        let (status, listed) = get_json(&router, "/v1/fleet/nodes").await;
+        assert_eq!(status, StatusCode::OK);
+        let nodes = listed["nodes"].as_array().unwrap();
+        assert_eq!(nodes.len(), 2);
+        assert_eq!(nodes[0]["identity_hash"], "0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a");
+        assert_eq!(nodes[0]["stale"], true);
+        assert_eq!(nodes[1]["identity_hash"], PARTNER);
+        assert_eq!(nodes[1]["stale"], false);
+
+        let uri = format!("/v1/fleet/nodes/{}", PARTNER.to_uppercase());
+        let (status, node) = get_json(&router, &uri).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(node["node"]["report"]["n"], 2);
+        let history: Vec<_> = node["history"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|entry| entry["report"]["n"].clone())
+            .collect();
+        assert_eq!(history, [json!(2), json!(1)]);
+        let unknown = "/v1/fleet/nodes/fe0000000000000000000000000000fe";
+        let (status, missing) = get_json(&router, unknown).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(missing["error"], "fleet_node_not_found");
+    }
+
+    #[cfg(feature = "webhooks")]
+    #[tokio::test]
+    async fn webhooks_are_managed_and_report_their_deliveries() {
+        let state = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        let router = build_router(state.clone());
+        let create = |body: Value| {
+            Request::post("/v1/webhooks")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+        for (body, error) in [
+            (json!({ "url": "https://tak.example/hook" }), "invalid_webhook_url"),
+            (json!({ "url": "http://tak.example/", "events": ["eam*"] }), "invalid_event_filter"),
+        ] {
+            let refused = send(&router, create(body)).await;
+            assert_eq!(refused.status(), StatusCode::BAD_REQUEST);
+            assert_eq!(json_body(refused).await["error"], error);
+        }
+        let body = json!({
+            "url": "http://127.0.0.1:9/hook",
+            "events": ["emergency_action_message.*"],
+            "source_identity": PARTNER.to_uppercase(),
+            "max_retries": 0,
+        });
+        let created = send(&router, create(body)).await;
+        assert_eq!(created.status(), StatusCode::CREATED);
+        let created = json_body(created).await;
+        assert!(created["secret"].as_str().is_some_and(|secret| secret.len() >= 32));
+        let webhook = &created["webhook"];
+        assert_eq!(webhook["source_identity"], PARTNER);
+        assert_eq!(webhook["timeout_ms"], 5000);
+        let id = webhook["subscription_id"].as_str().unwrap().to_string();
+
+        let (status, listed) = get_json(&router, "/v1/webhooks").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(listed["webhooks"][0]["subscription_id"], id.as_str());
+        assert!(listed["webhooks"][0].get("secret").is_none());
+
+        let event = MeshEventEnvelope {
+            message_id: Uuid::now_v7().to_string(),
+            event: "emergency_action_message.create".to_string(),
+            sent_at: chrono::Utc::now(),
+            source_identity: PARTNER.parse().unwrap(),
+            destination_identity: "local-node".parse().unwrap(),
+            content_type: "application/msgpack".to_string(),
+            payload: json!({ "uid": "eam-1" }),
+            ttl_ms: None,
+            transport_hint: None,
+        };
+        crate::results::apply_event(&state, event).await.unwrap();
+        let (_, pending) = get_json(&router, &format!("/v1/webhooks/{id}")).await;
+        assert_eq!(pending["pending_deliveries"], 1);
+        // Nothing listens on the discard port, and the one attempt is the last.
+        crate::webhooks::deliver_due(&state, chrono::Utc::now()).await.unwrap();
+        let uri = format!("/v1/webhooks/{id}/deliveries");
+        let (status, deliveries) = get_json(&router, &uri).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(deliveries["webhook"]["pending_deliveries"], 0);
+        assert_eq!(deliveries["webhook"]["consecutive_failures"], 1);
+        let attempts = deliveries["attempts"].as_array().unwrap();
+        assert_eq!(attempts.len(), 1);
+        assert_eq!(attempts[0]["outcome"], "failed");
+        assert_eq!(attempts[0]["event_name"], "emergency_action_message.create");
+
+        let uri = format!("/v1/webhooks/{id}");
+        let deleted = send(&router, Request::delete(&uri).body(Body::empty()).unwrap()).await;
+        assert_eq!(deleted.status(), StatusCode::NO_CONTENT);
+        let (status, missing) = get_json(&router, &uri).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(missing["error"], "webhook_not_found");
+    }
+
+    #[tokio::test]
+    async fn crash_report_pages_hold_steady_under_inserts_and_shared_timestamps() {
+        let state = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        let crash = |crash_id: &str, occurred_at: &str| retasync_storage::CrashReport {
+            crash_id: crash_id.to_string(),
+            kind: "task".to_string(),
+            occurred_at: occurred_at.to_string(),
+            message: "boom".to_string(),
+            thread: None,
+            location: None,
+            backtrace: None,
+            version: "test".to_string(),
+            uptime_secs: None,
+            job_id: None,
+            recorded_at: "2026-03-01T08:00:00.000Z".to_string(),
+        };
+        const SHARED: &str = "2026-03-01T08:00:00.000Z";
+        for crash_id in ["c1", "c2", "c3", "c4", "c5"] {
+            state.storage.record_crash_report(&crash(crash_id, SHARED)).await.unwrap();
+        }
+        let router = build_router(state.clone());
+        let ids = |page: &Value| -> Vec<String> {
+            page["crashes"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|report| report["crash_id"].as_str().unwrap().to_string())
+                .collect()
+        };
+        let page_at = |cursor: &Value| {
+            format!("/v1/admin/crashes?limit=2&cursor={}", cursor.as_str().unwrap())
+        };
+
+        let (status, first) = get_json(&router, "/v1/admin/crashes?limit=2").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(ids(&first), ["c5", "c4"]);
+        assert_eq!(first["order"], "occurred_at desc, crash_id desc");
+        assert_eq!(first["prev_cursor"], Value::Null);
+
+        // A newer crash lands ahead of the pages already read, and one sharing the timestamp
+        // lands behind them; neither shifts the page that follows.
+        state
+            .storage
+            .record_crash_report(&crash("c9", "2026-03-01T09:00:00.000Z"))
+            .await
+            .unwrap();
+        state.storage.record_crash_report(&crash("c0", SHARED)).await.unwrap();
+        let (_, second) = get_json(&router, &page_at(&first["next_cursor"])).await;
+        assert_eq!(ids(&second), ["c3", "c2"]);
+        let (_, third) = get_json(&router, &page_at(&second["next_cursor"])).await;
+        assert_eq!(ids(&third), ["c1", "c0"]);
+        assert_eq!(third["next_cursor"], Value::Null);
+
+        let (_, back) = get_json(&router, &page_at(&second["prev_cursor"])).await;
+        assert_eq!(ids(&back), ["c5", "c4"]);
+        let (_, head) = get_json(&router, &page_at(&back["prev_cursor"])).await;
+        assert_eq!(ids(&head), ["c9"]);
+        assert_eq!(head["prev_cursor"], Value::Null);
+
+        let cursor = first["next_cursor"].as_str().unwrap();
+        let mut tampered = cursor.to_string().into_bytes();
+        tampered[4] = if tampered[4] == b'A' { b'B' } else { b'A' };
+        let tampered = String::from_utf8(tampered).unwrap();
+        for uri in [
+            format!("/v1/admin/crashes?cursor={tampered}"),
+            format!("/v1/fleet/nodes?cursor={cursor}"),
+            "/v1/admin/crashes?cursor=%21%21".to_string(),
+        ] {
+            let (status, refused) = get_json(&router, &uri).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{uri}");
+            assert_eq!(refused["error"], "invalid_cursor");
+        }
+    }
+
+    #[tokio::test]
+    async fn runtime_loads_crash_reports_for_the_admin_api() {
+        let state = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        let dir = state
+            .node_config
+            .read()
+            .await
+            .crash_reports
+            .dir_for(state.storage.database_path());
+        let marker = crate::crash::CrashMarker::new("out of memory".to_string(), None, None);
+        let crash_id = marker.crash_id.clone();
+        crate::crash::write_crash_marker(&dir, marker).unwrap();
+        let runtime = ControlPlaneRuntime::new(state.clone())
+            .start(async {})
+            .await
+            .unwrap();
+        runtime.await.unwrap();
+
+        let router = build_router(state);
+        let (status, listed) = get_json(&router, "/v1/admin/crashes").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(listed["crashes"][0]["crash_id"], crash_id.as_str());
+        assert_eq!(listed["crashes"][0]["kind"], "process");
+        let (status, report) = get_json(&router, &format!("/v1/admin/crashes/{crash_id}")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(report["message"], "out of memory");
+        let (status, missing) = get_json(&router, "/v1/admin/crashes/nope").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(missing["error"], "crash_not_found");
+    }
+
+    #[tokio::test]
+    async fn clock_drift_is_reported_once_per_excursion() {
+        let state = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        let router = build_router(state.clone());
+        let mut events = state.sse_bus.subscribe();
+        let start = chrono::Utc::now();
+        let transit = chrono::Duration::milliseconds(DEFAULT_EVENT_TRANSIT_MS as i64);
+        // Each step is one event from PEER, stamped `offset_ms` off our clock.
+        let mut tick = 0;
+        let mut script = |offsets: Vec<i64>| {
+            let steps: Vec<_> = offsets
+                .into_iter()
+                .map(|offset_ms| {
+                    tick += 1;
+                    let received_at = start + chrono::Duration::seconds(tick);
+                    let sent_at = received_at - transit + chrono::Duration::milliseconds(offset_ms);
+                    (sent_at, received_at)
+                })
+                .collect();
+            steps
+        };
+        let drift_events = |events: &mut tokio::sync::broadcast::Receiver<super::SseUpdate>| {
+            std::iter::from_fn(|| events.try_recv().ok())
+                .filter(|event| event.event_type == PEER_CLOCK_DRIFT_EVENT)
+                .count()
+        };
+
+        // 8s ahead, with one sample an hour out that the median filter drops.
+        let mut ahead = vec![8_000; 20];
+        ahead[6] = 3_600_000;
+        for (sent_at, received_at) in script(ahead) {
+            observe_event(&state, PEER, sent_at, received_at).await;
+        }
+        assert_eq!(drift_events(&mut events), 1);
+        let (_, peers) = get_json(&router, "/v1/peers").await;
+        let clock = &peers["clocks"][PEER];
+        assert_eq!(clock["drifting"], true);
+        assert!((clock["offset_ms"].as_i64().unwrap() - 8_000).abs() < 500);
+
+        // Back in sync, then 9s behind: a second excursion, reported once more.
+        for (sent_at, received_at) in script([vec![0; 30], vec![-9_000; 30]].concat()) {
+            observe_event(&state, PEER, sent_at, received_at).await;
+        }
+        assert_eq!(drift_events(&mut events), 1);
+        let (status, body) = get_json(&router, &format!("/v1/peers/{PEER}/clock")).await;
+        assert_eq!(status, StatusCode::OK);
+        let clock = &body["clock"];
+        assert!((clock["offset_ms"].as_i64().unwrap() + 9_000).abs() < 500);
+        assert_eq!(clock["samples"], 80);
+        assert_eq!(clock["recent"].as_array().unwrap().len(), 32);
+        assert_eq!(clock["recent"][31]["source"], "event");
+        let unheard = "0000000000000000000000000000dead";
+        let (status, _) = get_json(&router, &format!("/v1/peers/{unheard}/clock")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn tolerated_skew_widens_the_age_limit_for_lagging_peers() {
+        let state = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        let router = build_router(state.clone());
+        let job = settled_command(&router, "event.create", json!({})).await;
+        let dispatch: serde_json::Value =
+            serde_json::from_str(job["dispatch_json"].as_str().unwrap()).unwrap();
+        let destination = dispatch["destination_identity"].as_str().unwrap();
+        let clock = state
+            .peers
+            .clock(destination)
+            .expect("sampled from the result");
+        assert_eq!(clock.recent[0].source, ClockSampleSource::Result);
+        assert!(clock.estimate.offset_ms.abs() < 1_000);
+
+        let now = chrono::Utc::now();
+        for _ in 0..10 {
+            observe_event(&state, PEER, now - chrono::Duration::seconds(42), now).await;
+        }
+        let lagging = state.peers.clock(PEER).unwrap().estimate;
+        assert!(lagging.drifting);
+        assert_eq!(skew_allowance_secs(&state, Some(&lagging)).await, 0);
+        let max_age_secs = state.node_config.read().await.inbound.max_envelope_age_secs;
+        assert_eq!(seen_message_horizon_secs(&state).await, max_age_secs);
+
+        set_feature(&state, CLOCK_SKEW_TOLERANCE_FLAG, true).await;
+        assert_eq!(skew_allowance_secs(&state, Some(&lagging)).await, 40);
+        state
+            .node_config
+            .write()
+            .await
+            .clock
+            .max_skew_tolerance_secs = 30;
+        assert_eq!(skew_allowance_secs(&state, Some(&lagging)).await, 30);
+        assert_eq!(seen_message_horizon_secs(&state).await, max_age_secs + 30);
+        let ahead = ClockEstimate {
+            offset_ms: 60_000,
+            ..lagging
+        };
+        assert_eq!(skew_allowance_secs(&state, Some(&ahead)).await, 0);
+    }
+
+    #[test]
+    fn storage_errors_map_to_http_status_by_variant() {
+        let cases = [
+            (StorageError::not_found("job j-1"), StatusCode::NOT_FOUND, "not_found"),
+            (
+                StorageError::from_sqlx("query", sqlx::Error::PoolTimedOut),
+                StatusCode::SERVICE_UNAVAILABLE,
+                "storage_unavailable",
+            ),
+            (
+                StorageError::corrupt("jobs", "truncated ciphertext"),
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "storage_corrupt",
+            ),
+            (
+                StorageError::Encryption("wrong key".to_string()),
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "internal_error",
+            ),
+        ];
+        for (err, status, code) in cases {
+            let (actual, body) = storage_error(err);
+            assert_eq!(actual, status);
+            assert_eq!(body.0["error"], code);
+        }
+    }
+
+    #[cfg(feature = "entities")]
+    #[tokio::test]
+    async fn shaped_responses_leave_payloads_unread_and_carry_their_own_etags() {
+        let state = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        let bulky = json!({ "uid": "evt-1", "detail": "x".repeat(4096) });
+        let job = state.storage.create_job("event.create", bulky.clone()).await.unwrap();
+        for n in 0..5 {
+            let event_id = format!("evt-{n}");
+            let sent_at = chrono::Utc::now();
+            state
+                .storage
+                .cache_event(&event_id, "event.create", "peer", sent_at, &bulky)
+                .await
+                .unwrap();
+        }
+        state.storage.update_entity("note", "n1", &bulky, 0).await.unwrap();
+        let router = build_router(state.clone());
+        let (bytes, sha256) = payload_digest(&bulky.to_string());
+        let fetch = |uri: String| {
+            let router = router.clone();
+            async move {
+                let response = send(&router, Request::get(uri).body(Body::empty()).unwrap()).await;
+                let status = response.status();
+                let etag = header_of(&response, header::ETAG).map(str::to_string);
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                (status, etag, body.len(), serde_json::from_slice::<Value>(&body).unwrap())
+            }
+        };
+
+        let job_uri = format!("/v1/jobs/{}", job.job_id);
+        let (_, _, full_len, full) = fetch(job_uri.clone()).await;
+        assert!(full.get("payload_sha256").is_none());
+        let (_, full_etag, events_len, _) = fetch("/v1/cache/events".into()).await;
+        let reads = state.storage.payload_reads();
+        let (status, _, _, shaped) =
+            fetch(format!("{job_uri}?fields=job_id,status,payload_bytes&omit_payload=true")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            shaped,
+            json!({ "job_id": job.job_id, "status": full["status"], "payload_bytes": bytes })
+        );
+        let (_, _, _, v2) = fetch(format!("/v2/jobs/{}?omit_payload=true", job.job_id)).await;
+        assert!(v2["data"].get("payload").is_none());
+        assert_eq!(v2["data"]["payload_sha256"], sha256);
+        assert_eq!(v2["data"]["job_id"], job.job_id);
+
+        let (status, shaped_etag, shaped_len, events) =
+            fetch("/v1/cache/events?omit_payload=true".into()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_ne!(shaped_etag, full_etag);
+        assert!(shaped_len * 10 < events_len, "{shaped_len} of {events_len} bytes");
+        assert_eq!(events[0]["payload_sha256"], sha256);
+        assert_eq!(events.as_array().unwrap().len(), 5);
+
+        let (_, entity_etag, _, entity) =
+            fetch("/v1/entities/note/n1?fields=version,record_bytes".into()).await;
+        assert_eq!(entity, json!({ "version": 1, "record_bytes": bytes }));
+        assert_ne!(entity_etag.as_deref(), Some("\"1\""));
+        // Nothing shaped so far selected a payload, so none was read.
+        assert_eq!(state.storage.payload_reads(), reads);
+
+        let (_, _, with_payload_len, _) =
+            fetch(format!("{job_uri}?fields=job_id,payload_json")).await;
+        assert!(with_payload_len < full_len);
+        assert_eq!(state.storage.payload_reads(), reads + 1);
+
+        let (status, _, _, unknown) = fetch(format!("{job_uri}?fields=job_id,payload")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(unknown["error"], UNKNOWN_FIELD_ERROR);
+        assert_eq!(unknown["field"], "payload");
+        let (status, _, _, unknown) =
+            fetch(format!("/v2/jobs/{}?fields=payload_json", job.job_id)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(unknown["error"]["code"], UNKNOWN_FIELD_ERROR);
+    }
+
+    // An `event.list` poll of the peer, from a client holding the result with `held` when set.
+    fn list_poll(held: Option<&str>) -> Request<Body> {
+        let query = held
+            .map(|digest| format!("?if_result_digest_differs={digest}"))
+            .unwrap_or_default();
+        Request::post(format!("/v1/jobs/commands/event.list{query}"))
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(json!({ "destination_identity": PEER }).to_string()))
+            .unwrap()
+    }
+
+    #[cfg(feature = "entities")]
+    async fn store_event(node: &AppState, entity_id: &str, title: &str) {
+        let event = EntityRecord {
+            entity_type: "event".to_string(),
+            entity_id: entity_id.to_string(),
+            updated_at: chrono::Utc::now(),
+            record: json!({ "title": title }),
+            version: 1,
+            deleted_at: None,
+        };
+        node.storage.put_entity(&event).await.unwrap();
+    }
+
+    // Lets the peer answer lists for the node under test.
+    #[cfg(feature = "entities")]
+    async fn allowlist_requester(peer: &AppState) {
+        let requester = IdentityHash::lenient(LOCAL_NODE_IDENTITY);
+        peer.storage.add_allowlist(&requester, None).await.unwrap();
+    }
+
+    async fn job_result(router: &Router, job: &Value) -> axum::response::Response {
+        let job_id = job["job_id"].as_str().unwrap();
+        let uri = format!("/v1/jobs/{job_id}/result");
+        send(router, Request::get(uri).body(Body::empty()).unwrap()).await
+    }
+
+    #[cfg(feature = "entities")]
+    #[tokio::test]
+    async fn list_polls_holding_the_current_result_come_back_not_modified() {
+        let (local, remote) = LoopbackMeshBridge::pair();
+        let peer = contract_node(remote, "1.2.0").await;
+        allowlist_requester(&peer).await;
+        store_event(&peer, "evt-1", "Flood on Main St").await;
+        let worker = spawn_inbound_worker(peer.clone(), Duration::from_millis(5));
+        let router = build_router(contract_node(local, "1.2.0").await);
+
+        let first = settled_job(&router, send(&router, list_poll(None)).await).await;
+        assert_eq!(first["status"], "success");
+        assert_eq!(first["result_unchanged"], false);
+        assert_eq!(first["result_source"], "mesh");
+        let digest = first["result_digest"].as_str().unwrap().to_string();
+        let stored = json_body(job_result(&router, &first).await).await;
+        let result: Value = serde_json::from_str(stored["result_json"].as_str().unwrap()).unwrap();
+        assert_eq!(result["records"][0]["entity_id"], "evt-1");
+        assert_eq!(canonical_digest(&result).unwrap(), digest);
+
+        let unchanged = settled_job(&router, send(&router, list_poll(Some(&digest))).await).await;
+        assert_eq!(unchanged["status"], "success");
+        assert_eq!(unchanged["result_unchanged"], true);
+        assert_eq!(unchanged["result_digest"], digest.as_str());
+        let response = job_result(&router, &unchanged).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(
+            response.headers()[header::ETAG].to_str().unwrap(),
+            format!("\"{digest}\"")
+        );
+        let (_, status) = get_json(&router, "/v1/node/status").await;
+        assert_eq!(status["result_reuse"]["not_modified"], 1);
+        let saved = status["result_reuse"]["bytes_saved"].as_u64().unwrap();
+        assert_eq!(saved, encode_canonical(&result).unwrap().len() as u64);
+
+        // Once the peer's data moves on, the same digest gets the new result in full.
+        store_event(&peer, "evt-2", "Power out on 5th").await;
+        let changed = settled_job(&router, send(&router, list_poll(Some(&digest))).await).await;
+        assert_eq!(changed["status"], "success");
+        assert_eq!(changed["result_unchanged"], false);
+        assert_ne!(changed["result_digest"], digest.as_str());
+        let stored = json_body(job_result(&router, &changed).await).await;
+        let result: Value = serde_json::from_str(stored["result_json"].as_str().unwrap()).unwrap();
+        assert_eq!(result["records"].as_array().unwrap().len(), 2);
+        worker.abort();
+    }
+
+    #[cfg(feature = "entities")]
+    #[tokio::test]
+    async fn list_polls_from_a_peer_off_the_allowlist_get_no_records() {
+        let (local, remote) = LoopbackMeshBridge::pair();
+        let peer = contract_node(remote, "1.2.0").await;
+        store_event(&peer, "evt-1", "Flood on Main St").await;
+        let worker = spawn_inbound_worker(peer.clone(), Duration::from_millis(5));
+        let router = build_router(contract_node(local, "1.2.0").await);
+
+        let refused = settled_job(&router, send(&router, list_poll(None)).await).await;
+        let stored = json_body(job_result(&router, &refused).await).await;
+        let result: Value = serde_json::from_str(stored["result_json"].as_str().unwrap()).unwrap();
+        assert_eq!(result, json!({ "status": "error", "error": NOT_ALLOWLISTED_ERROR }));
+        worker.abort();
+    }
+
+    #[cfg(feature = "entities")]
+    #[tokio::test]
+    async fn fresh_results_answer_identical_polls_locally_until_they_expire() {
+        let (local, remote) = LoopbackMeshBridge::pair();
+        let peer = contract_node(remote, "1.2.0").await;
+        allowlist_requester(&peer).await;
+        store_event(&peer, "evt-1", "Flood on Main St").await;
+        let worker = spawn_inbound_worker(peer.clone(), Duration::from_millis(5));
+        let node = contract_node(local, "1.2.0").await;
+        node.node_config.write().await.result_cache.ttl_ms = 400;
+        let router = build_router(node.clone());
+
+        let first = settled_job(&router, send(&router, list_poll(None)).await).await;
+        assert_eq!(first["result_source"], "mesh");
+        let digest = first["result_digest"].as_str().unwrap().to_string();
+
+        // With the peer gone, only the cache can answer.
+        worker.abort();
+        let cached = settled_job(&router, send(&router, list_poll(None)).await).await;
+        assert_eq!(cached["status"], "success");
+        assert_eq!(cached["result_source"], "cache");
+        assert_eq!(cached["result_unchanged"], false);
+        assert_eq!(
+            json_body(job_result(&router, &cached).await).await["result_json"],
+            json_body(job_result(&router, &first).await).await["result_json"]
+        );
+        let held = settled_job(&router, send(&router, list_poll(Some(&digest))).await).await;
+        assert_eq!(held["result_source"], "cache");
+        assert_eq!(held["result_unchanged"], true);
+        let (_, status) = get_json(&router, "/v1/node/status").await;
+        assert_eq!(status["result_reuse"]["cache_hits"], 2);
+        assert_eq!(status["result_reuse"]["not_modified"], 0);
+
+        tokio::time::sleep(Duration::from_millis(450)).await;
+        store_event(&peer, "evt-2", "Power out on 5th").await;
+        let worker = spawn_inbound_worker(peer.clone(), Duration::from_millis(5));
+        let expired = settled_job(&router, send(&router, list_poll(None)).await).await;
+        assert_eq!(expired["result_source"], "mesh");
+        assert_ne!(expired["result_digest"], digest.as_str());
+        worker.abort();
+    }
+
+    async fn register_sealing_key(node: &AppState, identity: &str, key: Option<&str>) {
+        let hash = IdentityHash::lenient(identity);
+        node.storage.add_allowlist(&hash, None).await.unwrap();
+        node.storage.set_allowlist_public_key(&hash, key).await.unwrap();
+    }
+
+    // Runs the `node.hello` exchange with a worker briefly answering for the peer.
+    async fn handshake_with(router: &Router, peer: &AppState) {
+        let worker = spawn_inbound_worker(peer.clone(), Duration::from_millis(5));
+        let handshake = send(
+            router,
+            Request::post(format!("/v1/peers/{PEER}/handshake"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(handshake.status(), StatusCode::OK);
+        worker.abort();
+    }
+
+    // Linked nodes holding each other's sealing key, the first sealing `operations`. Once the
+    // handshake is done the peer has no worker, so tests read its commands with `next_command`.
+    async fn sealing_pair(operations: &[&str]) -> (AppState, AppState) {
+        let (local, remote) = LoopbackMeshBridge::pair();
+        let node = contract_node(local, "1.2.0").await;
+        let peer = contract_node(remote, "1.2.0").await;
+        node.node_config.write().await.security.encrypt_operations =
+            operations.iter().map(|op| op.to_string()).collect();
+        let peer_key = local_sealing_key(&peer).await.unwrap();
+        let node_key = local_sealing_key(&node).await.unwrap();
+        register_sealing_key(&node, PEER, Some(&peer_key)).await;
+        register_sealing_key(&peer, LOCAL_NODE_IDENTITY, Some(&node_key)).await;
+        handshake_with(&build_router(node.clone()), &peer).await;
+        (node, peer)
+    }
+
+    async fn next_command(peer: &AppState) -> MeshCommandEnvelope<Value> {
+        for _ in 0..200 {
+            if let Some(command) = peer.bridge.poll_commands(1).await.unwrap().pop() {
+                return command;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        panic!("no command reached the peer");
+    }
+
+    async fn feed_events(node: &AppState, event_type: &str) -> Vec<Value> {
+        let (_, page) = get_json(&build_router(node.clone()), "/v1/events/feed").await;
+        page["events"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|event| event["event_type"] == event_type)
+            .map(|event| event["data"].clone())
+            .collect()
+    }
+
+    #[cfg(feature = "entities")]
+    #[tokio::test]
+    async fn listed_operations_travel_sealed_and_open_at_the_destination() {
+        let (node, peer) = sealing_pair(&["event.list"]).await;
+        store_event(&peer, "evt-1", "Flood on Main St").await;
+        let router = build_router(node.clone());
+
+        let submitted = send(&router, list_poll(None)).await;
+        let command = next_command(&peer).await;
+        assert_eq!(command.content_type, CONTENT_TYPE_SEALED);
+        let sealed = STANDARD.decode(command.payload.as_str().unwrap()).unwrap();
+        assert_eq!(sealed[0], SEALED_FORMAT_VERSION);
+        route_command(&peer, command, chrono::Utc::now()).await.unwrap();
+        let job = settled_job(&router, submitted).await;
+        assert_eq!(job["status"], "success");
+        let stored = json_body(job_result(&router, &job).await).await;
+        let result: Value = serde_json::from_str(stored["result_json"].as_str().unwrap()).unwrap();
+        assert_eq!(result["records"][0]["entity_id"], "evt-1");
+
+        // Operations that are not listed keep going out in the clear.
+        let _create = send(&router, peer_command(false)).await;
+        let command = next_command(&peer).await;
+        assert_eq!(command.operation, "event.create");
+        assert_eq!(command.content_type, CONTENT_TYPE_MSGPACK);
+        assert_eq!(command.payload["uid"], "evt-1");
+
+        let report = dry_run(
+            &router,
+            "/v1/jobs/commands/event.list?dry_run=true",
+            json!({ "destination_identity": PEER }),
+        )
+        .await;
+        assert_eq!(report["envelope"]["content_type"], CONTENT_TYPE_SEALED);
+    }
+
+    #[tokio::test]
+    async fn sealed_commands_that_do_not_open_are_refused_and_reported() {
+        let (node, peer) = sealing_pair(&["event.list"]).await;
+        let router = build_router(node.clone());
+
+        let submitted = send(&router, list_poll(None)).await;
+        let mut command = next_command(&peer).await;
+        let mut sealed = STANDARD.decode(command.payload.as_str().unwrap()).unwrap();
+        *sealed.last_mut().unwrap() ^= 0x01;
+        command.payload = json!(STANDARD.encode(&sealed));
+        let message_id = command.message_id.clone();
+        route_command(&peer, command, chrono::Utc::now()).await.unwrap();
+        let job = settled_job(&router, submitted).await;
+        let stored = json_body(job_result(&router, &job).await).await;
+        let result: Value = serde_json::from_str(stored["result_json"].as_str().unwrap()).unwrap();
+        assert_eq!(result["error"], "sealed_authentication_failed");
+
+        // A sender whose key the peer does not hold is refused the same way.
+        let hash = IdentityHash::lenient(LOCAL_NODE_IDENTITY);
+        peer.storage.set_allowlist_public_key(&hash, None).await.unwrap();
+        let _submitted = send(&router, list_poll(None)).await;
+        let command = next_command(&peer).await;
+        route_command(&peer, command, chrono::Utc::now()).await.unwrap();
+
+        let refused = feed_events(&peer, DECRYPT_FAILED_EVENT).await;
+        assert_eq!(refused.len(), 2);
+        assert_eq!(refused[0]["message_id"], message_id.as_str());
+        assert_eq!(refused[0]["operation"], "event.list");
+        assert_eq!(refused[0]["reason"], "sealed_authentication_failed");
+        assert_eq!(refused[1]["reason"], SEALED_SENDER_UNKNOWN_ERROR);
+        assert_eq!(refused[1]["source_identity"], LOCAL_NODE_IDENTITY);
+    }
+
+    #[tokio::test]
+    async fn missing_keys_fail_the_job_until_the_handshake_registers_one() {
+        let (local, remote) = LoopbackMeshBridge::pair();
+        let node = contract_node(local, "1.2.0").await;
+        let peer = contract_node(remote, "1.2.0").await;
+        node.node_config.write().await.security.encrypt_operations = vec!["event.*".to_string()];
+        register_sealing_key(&node, PEER, None).await;
+        let router = build_router(node.clone());
+        handshake_with(&router, &peer).await;
+
+        let unsealed = settled_job(&router, send(&router, peer_command(false)).await).await;
+        assert_eq!(unsealed["status"], "failed");
+        assert_eq!(unsealed["failure_reason"], SEALING_KEY_MISSING_ERROR);
+        assert!(peer.bridge.poll_commands(10).await.unwrap().is_empty());
+
+        // With exchange_keys on, re-running the handshake fills in the peer's offered key.
+        for state in [&node, &peer] {
+            state.node_config.write().await.security.exchange_keys = true;
+        }
+        handshake_with(&router, &peer).await;
+        let entry = node
+            .storage
+            .get_allowlist_entry(&IdentityHash::lenient(PEER))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(entry.public_key, Some(local_sealing_key(&peer).await.unwrap()));
+        let registered = feed_events(&node, SEALING_KEY_REGISTERED_EVENT).await;
+        assert_eq!(registered[0]["identity_hash"], PEER);
+
+        let _sealed = send(&router, peer_command(false)).await;
+        let command = next_command(&peer).await;
+        assert_eq!(command.content_type, CONTENT_TYPE_SEALED);
+    }
+
+    #[cfg(feature = "transfers")]
+    fn labelled_command(labels: Value, attachments: Value) -> Request<Body> {
+        Request::post("/v1/jobs/commands/event.create")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(
+                json!({ "uid": "evt-1", "_labels": labels, "_attachments": attachments })
+                    .to_string(),
+            ))
+            .unwrap()
+    }
+
+    #[cfg(feature = "transfers")]
+    #[tokio::test]
+    async fn labels_follow_submissions_and_narrow_transfer_lists_and_exports() {
+        let state = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        let router = build_router(state.clone());
+        let day2 = json!({ "exercise": "redfish-day2", "casualty": "7" });
+        let first = labelled_command(day2.clone(), json!([attachment("a.png", &[1; 10])]));
+        let first = settled_job(&router, send(&router, first).await).await;
+        assert_eq!(first["labels"], day2);
+        let second = labelled_command(
+            json!({ "exercise": "redfish-day3" }),
+            json!([attachment("b.png", &[2; 10])]),
+        );
+        let second = settled_job(&router, send(&router, second).await).await;
+        let upload = Request::post("/v1/jobs/transfers/upload")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(
+                json!({
+                    "destination_identity": PEER,
+                    "file_name": "tile.png",
+                    "media_type": "image/png",
+                    "payload_base64": STANDARD.encode([3; 10]),
+                    "labels": { "exercise": "redfish-day2" },
+                })
+                .to_string(),
+            ))
+            .unwrap();
+        assert_eq!(send(&router, upload).await.status(), StatusCode::ACCEPTED);
+
+        let transfer_ids = |page: &Value, items: &str| -> Vec<String> {
+            page[items]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|item| item["transfer_id"].as_str().unwrap().to_string())
+                .collect()
+        };
+        let (_, day2_transfers) =
+            get_json(&router, "/v1/transfers?label=exercise:redfish-day2").await;
+        assert_eq!(transfer_ids(&day2_transfers, "items").len(), 2);
+        let (_, casualty) =
+            get_json(&router, "/v1/transfers?label=exercise:redfish-day2&label=casualty:7").await;
+        assert_eq!(
+            transfer_ids(&casualty, "items"),
+            [first["attachments"][0]["transfer_id"].as_str().unwrap()]
+        );
+        assert_eq!(casualty["items"][0]["labels"], day2);
+        let (_, v2) = get_json(&router, "/v2/transfers?label=exercise:redfish-day3").await;
+        assert_eq!(
+            v2["data"]["items"][0]["labels"],
+            json!({ "exercise": "redfish-day3" })
+        );
+        let (status, rejected) = get_json(&router, "/v1/transfers?label=exercise").await;
+        assert_eq!(
+            (status, rejected["error"].as_str()),
+            (StatusCode::BAD_REQUEST, Some(INVALID_LABEL_FILTER_ERROR))
+        );
+
+        let export = send(
+            &router,
+            Request::get("/v1/jobs/export?label=exercise:redfish-day3")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        let body = axum::body::to_bytes(export.into_body(), usize::MAX).await.unwrap();
+        let lines: Vec<Value> = body
+            .split(|byte| *byte == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0]["job_id"], second["job_id"]);
+        assert_eq!(lines[0]["labels"], json!({ "exercise": "redfish-day3" }));
+
+        let (_, keys) = get_json(&router, "/v1/labels/keys").await;
+        assert_eq!(
+            keys["keys"],
+            json!([
+                { "key": "casualty", "values": 1, "subjects": 2 },
+                { "key": "exercise", "values": 2, "subjects": 5 },
+            ])
+        );
+        let (_, jobs) = get_json(&router, "/v1/labels/keys?subject_type=job").await;
+        assert_eq!(jobs["keys"][1], json!({ "key": "exercise", "values": 2, "subjects": 2 }));
+        let (status, _) = get_json(&router, "/v1/labels/keys?subject_type=peer").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let oversized = labelled_command(json!({ "Exercise": "x" }), json!([]));
+        let rejected = send(&router, oversized).await;
+        assert_eq!(rejected.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(json_body(rejected).await["error"], INVALID_LABELS_ERROR);
+    }
+
+    #[tokio::test]
+    async fn only_the_submitter_or_an_admin_relabels_a_job_and_the_change_is_audited() {
+        let mut config = test_node_config();
+        config.http_auth_token = Some("admin-secret".to_string());
+        config.api_tokens = ["ops", "intel"]
+            .map(|label| ApiToken {
+                label: label.to_string(),
+                token: format!("{label}-secret"),
+            })
+            .to_vec();
+        let (mut state, _) = batch_router(config).await;
+        state.require_bearer = true;
+        let router = build_router(state.clone());
+        let as_caller = |token: &str, request: axum::http::request::Builder, body: Value| {
+            request
+                .header(header::AUTHORIZATION, format!("Bearer {token}"))
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+        let submitted = as_caller(
+            "ops-secret",
+            Request::post("/v1/jobs/commands/event.create"),
+            json!({ "uid": "evt-1", "_labels": { "exercise": "redfish-day2" } }),
+        );
+        let submitted = json_body(send(&router, submitted).await).await;
+        let job_id = submitted["job_id"].as_str().unwrap();
+        let relabel = |token: &str, labels: Value| {
+            as_caller(
+                token,
+                Request::put(format!("/v1/jobs/{job_id}/labels")),
+                json!({ "labels": labels }),
+            )
+        };
+
+        let refused = send(&router, relabel("intel-secret", json!({ "casualty": "7" }))).await;
+        assert_eq!(refused.status(), StatusCode::FORBIDDEN);
+        assert_eq!(json_body(refused).await["error"], "not_job_submitter");
+        let invalid = send(&router, relabel("ops-secret", json!({ "casualty": 7 }))).await;
+        assert_eq!(invalid.status(), StatusCode::BAD_REQUEST);
+
+        let own = send(&router, relabel("ops-secret", json!({ "casualty": "7" }))).await;
+        assert_eq!(own.status(), StatusCode::OK);
+        assert_eq!(json_body(own).await["labels"], json!({ "casualty": "7" }));
+        let admin = send(&router, relabel("admin-secret", json!({}))).await;
+        assert_eq!(admin.status(), StatusCode::OK);
+        let missing = Request::put("/v1/jobs/missing/labels")
+            .header(header::AUTHORIZATION, "Bearer admin-secret")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(json!({ "labels": {} }).to_string()))
+            .unwrap();
+        assert_eq!(send(&router, missing).await.status(), StatusCode::NOT_FOUND);
+
+        state.require_bearer = false;
+        let changes = feed_events(&state, JOB_LABELS_CHANGED_EVENT).await;
+        assert_eq!(
+            changes,
+            [
+                json!({
+                    "job_id": job_id,
+                    "previous": { "exercise": "redfish-day2" },
+                    "labels": { "casualty": "7" },
+                    "changed_by": "ops",
+                }),
+                json!({
+                    "job_id": job_id,
+                    "previous": { "casualty": "7" },
+                    "labels": {},
+                    "changed_by": "default",
+                }),
+            ]
+        );
+    }
+
+    #[cfg(feature = "entities")]
+    #[tokio::test]
+    async fn labels_travel_to_the_peer_and_mark_the_entities_a_sync_stores() {
+        let (local, remote) = LoopbackMeshBridge::pair();
+        let node = contract_node(local, "1.2.0").await;
+        let peer = contract_node(remote, "1.2.0").await;
+        let router = build_router(node.clone());
+        handshake_with(&router, &peer).await;
+
+        let command = Request::post("/v1/jobs/commands/event.create")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(
+                json!({
+                    "uid": "evt-1",
+                    "destination_identity": PEER,
+                    "_labels": { "exercise": "redfish-day2" },
+                })
+                .to_string(),
+            ))
+            .unwrap();
+        let _submitted = send(&router, command).await;
+        let envelope = next_command(&peer).await;
+        assert_eq!(
+            envelope.meta.unwrap().labels,
+            Labels::from([("exercise".to_string(), "redfish-day2".to_string())])
+        );
+        assert!(envelope.payload.get(LABELS_FIELD).is_none());
+
+        let eam = |id: &str| EntityRecord {
+            entity_type: "eam".to_string(),
+            entity_id: id.to_string(),
+            updated_at: chrono::Utc::now(),
+            record: json!({ "status": "green" }),
+            version: 1,
+            deleted_at: None,
+        };
+        node.storage.put_entity(&eam("1-eam")).await.unwrap();
+        peer.storage.put_entity(&eam("2-eam")).await.unwrap();
+        let requester = crate::dispatch::local_identity(&*node.node_config.read().await);
+        peer.storage.add_allowlist(&requester, None).await.unwrap();
+        let worker = spawn_inbound_worker(peer.clone(), Duration::from_millis(5));
+        let sync = Request::post("/v1/entities/eam/sync")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(
+                json!({ "peer": PEER, "labels": { "casualty": "7" } }).to_string(),
+            ))
+            .unwrap();
+        assert_eq!(send(&router, sync).await.status(), StatusCode::OK);
+        worker.abort();
+
+        let (_, pulled) = get_json(&router, "/v1/entities/eam/2-eam").await;
+        assert_eq!(pulled["labels"], json!({ "casualty": "7" }));
+        let (_, own) = get_json(&router, "/v1/entities/eam/1-eam").await;
+        assert!(own.get("labels").is_none());
+        let pushed = peer
+            .storage
+            .get_labels(LABEL_SUBJECT_ENTITY, &entity_label_subject("eam", "1-eam"))
+            .await
+            .unwrap();
+        assert_eq!(pushed["casualty"], "7");
+    }
+
+    #[cfg(feature = "sse")]
+    #[tokio::test]
+    async fn live_subscribers_get_the_latest_status_per_job_while_the_feed_keeps_every_event() {
+        let state = test_state(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        state.node_config.write().await.event_coalescing.window_ms = 300;
+        let router = build_router(state.clone());
+        let response = send(
+            &router,
+            Request::get("/v1/logs/stream").body(Body::empty()).unwrap(),
+        )
+        .await;
+        let mut body = response.into_body().into_data_stream();
+
+        let status = |job_id: &str, status: &str| json!({ "job_id": job_id, "status": status });
+        let mut emitted = vec![status("job-a", "queued"), status("job-b", "queued")];
+        emitted.extend((0..20).map(|attempt| {
+            json!({ "job_id": "job-a", "status": "dispatched", "attempt": attempt })
+        }));
+        emitted.extend([status("job-b", "dispatched"), status("job-a", "success")]);
+        for data in &emitted {
+            state.storage.append_feed_event("job.status.changed", data).await.unwrap();
+        }
+        let replay = json!({ "source_identity": PEER, "message_id": "m-1" });
+        for _ in 0..2 {
+            state
+                .storage
+                .append_feed_event("security.replay_detected", &replay)
+                .await
+                .unwrap();
+        }
+        super::publish(&state).await;
+
+        let mut delivered = Vec::new();
+        let mut text = String::new();
+        while delivered.len() < 5 {
+            let frame = tokio::time::timeout(Duration::from_secs(2), body.next())
+                .await
+                .expect("frame before timeout")
+                .expect("open stream")
+                .expect("frame");
+            text.push_str(std::str::from_utf8(&frame).unwrap());
+            while let Some((event, rest)) = text.split_once("\n\n") {
+                let data = event.lines().find_map(|line| line.strip_prefix("data: "));
+                delivered.push(serde_json::from_str::<Value>(data.unwrap()).unwrap());
+                text = rest.to_string();
+            }
+        }
+        let statuses: Vec<_> = delivered
+            .iter()
+            .map(|data| (data["job_id"].as_str(), data["status"].as_str()))
+            .collect();
+        assert_eq!(
+            statuses,
+            [
+                (Some("job-a"), Some("dispatched")),
+                (Some("job-b"), Some("dispatched")),
+                (Some("job-a"), Some("success")),
+                (None, None),
+                (None, None),
+            ]
+        );
+        assert_eq!(delivered[0]["attempt"], 19);
+        assert_eq!(delivered[3], replay);
+        assert!(tokio::time::timeout(Duration::from_millis(400), body.next()).await.is_err());
+
+        let feed = feed_events(&state, "job.status.changed").await;
+        assert_eq!(feed, emitted);
+        let (_, subscribers) = get_json(&router, "/v1/events/subscribers").await;
+        assert_eq!(subscribers["window_ms"], 300);
+        let subscriber = &subscribers["subscribers"][0];
+        assert_eq!(subscriber["kind"], SSE_SUBSCRIBER);
+        assert_eq!(
+            (subscriber["delivered"].as_u64(), subscriber["collapsed"].as_u64()),
+            (Some(5), Some(21))
+        );
+        let (_, status) = get_json(&router, "/v1/node/status").await;
+        assert_eq!(status["event_coalescing"], json!({ "subscribers": 1, "collapsed": 21 }));
+        let (_, capabilities) = get_json(&router, "/v1/node/capabilities").await;
+        let coalescing = &capabilities["features"]["events.coalescing"];
+        assert_eq!(coalescing["window_ms"], 300);
+        assert_eq!(coalescing["subscribers"][2], WEBHOOK_SUBSCRIBER);
+        assert_eq!(coalescing["pulled_feeds"], "never coalesced");
+
+        drop(body);
+        let (_, subscribers) = get_json(&router, "/v1/events/subscribers").await;
+        assert_eq!(subscribers["subscribers"], json!([]));
+    }
+
+    // Linked nodes where the peer, known as PEER, signs delivery receipts. The node trusts the
+    // peer's signing key only when `trusted` is set.
+    #[cfg(feature = "entities")]
+    async fn receipt_pair(trusted: bool) -> (AppState, AppState) {
+        let (local, remote) = LoopbackMeshBridge::pair();
+        let node = contract_node(local, "1.2.0").await;
+        let peer = contract_node(remote, "1.2.0").await;
+        peer.node_config.write().await.identity.source_identity = Some(PEER.to_string());
+        allowlist_requester(&peer).await;
+        if trusted {
+            let key = signer(&peer).await.unwrap();
+            node.node_config.write().await.sneakernet.trusted_signers.insert(
+                PEER.to_string(),
+                key["public_key"].as_str().unwrap().to_string(),
+            );
+        }
+        handshake_with(&build_router(node.clone()), &peer).await;
+        peer.node_config.write().await.receipts.enabled = true;
+        (node, peer)
+    }
+
+    // An `event.list` poll the peer answers; returns the settled job and its command's id.
+    #[cfg(feature = "entities")]
+    async fn answered_poll(router: &Router, peer: &AppState) -> (Value, String) {
+        let submitted = send(router, list_poll(None)).await;
+        let command = next_command(peer).await;
+        let message_id = command.message_id.clone();
+        route_command(peer, command, chrono::Utc::now()).await.unwrap();
+        (settled_job(router, submitted).await, message_id)
+    }
+
+    #[cfg(feature = "entities")]
+    async fn job_receipt(router: &Router, job: &Value) -> (StatusCode, Value) {
+        let job_id = job["job_id"].as_str().unwrap();
+        get_json(router, &format!("/v1/jobs/{job_id}/receipt")).await
+    }
+
+    #[cfg(feature = "entities")]
+    #[tokio::test]
+    async fn receipts_from_a_trusted_peer_verify_and_attach_to_the_job() {
+        let (node, peer) = receipt_pair(true).await;
+        store_event(&peer, "evt-1", "Flood on Main St").await;
+        let router = build_router(node.clone());
+        let (job, message_id) = answered_poll(&router, &peer).await;
+        assert_eq!(job["status"], "success");
+        let (status, missing) = job_receipt(&router, &job).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(missing["error"], RECEIPT_NOT_FOUND_ERROR);
+
+        ingest_events(&node).await.unwrap();
+        let (status, receipt) = job_receipt(&router, &job).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(receipt["verification"], "verified");
+        assert_eq!(receipt["reason"], Value::Null);
+        assert_eq!(receipt["receipt"]["message_id"], message_id.as_str());
+        assert_eq!(receipt["receipt"]["receiver_identity"], PEER);
+        let stored = json_body(job_result(&router, &job).await).await;
+        let result: Value = serde_json::from_str(stored["result_json"].as_str().unwrap()).unwrap();
+        assert_eq!(receipt["receipt"]["result_digest"], canonical_digest(&result).unwrap());
+        assert!(feed_events(&node, RECEIPT_INVALID_EVENT).await.is_empty());
+    }
+
+    #[cfg(feature = "entities")]
+    #[tokio::test]
+    async fn tampered_receipts_are_stored_flagged_until_a_valid_one_arrives() {
+        let (node, peer) = receipt_pair(true).await;
+        let router = build_router(node.clone());
+        let (job, message_id) = answered_poll(&router, &peer).await;
+        let genuine = node
+            .bridge
+            .poll_events(10)
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|event| event.event == DELIVERY_RECEIPT_EVENT)
+            .unwrap();
+        let mut tampered = genuine.clone();
+        tampered.payload["result_digest"] = json!("0".repeat(64));
+        apply_event(&node, tampered).await.unwrap();
+
+        let (_, receipt) = job_receipt(&router, &job).await;
+        assert_eq!(receipt["verification"], "unverified");
+        assert_eq!(receipt["reason"], "receipt_signature_invalid");
+        let flagged = feed_events(&node, RECEIPT_INVALID_EVENT).await;
+        assert_eq!(flagged.len(), 1);
+        assert_eq!(flagged[0]["message_id"], message_id.as_str());
+        assert_eq!(flagged[0]["job_id"], job["job_id"]);
+        assert_eq!(flagged[0]["reason"], "receipt_signature_invalid");
+
+        // The genuine receipt replaces the flagged one, and a forgery cannot displace it.
+        apply_event(&node, genuine.clone()).await.unwrap();
+        let mut forged = genuine;
+        forged.payload["received_at"] = json!("2020-01-01T00:00:00.000000Z");
+        apply_event(&node, forged).await.unwrap();
+        let (_, receipt) = job_receipt(&router, &job).await;
+        assert_eq!(receipt["verification"], "verified");
+        assert_eq!(feed_events(&node, RECEIPT_INVALID_EVENT).await.len(), 2);
+    }
+
+    #[cfg(feature = "entities")]
+    #[tokio::test]
+    async fn receipts_from_a_peer_without_a_registered_key_are_kept_unverified() {
+        let (node, peer) = receipt_pair(false).await;
+        let router = build_router(node.clone());
+        let (job, _) = answered_poll(&router, &peer).await;
+        ingest_events(&node).await.unwrap();
+
+        let (status, receipt) = job_receipt(&router, &job).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(receipt["verification"], "unverified");
+        assert_eq!(receipt["reason"], RECEIPT_SIGNER_UNKNOWN);
+        assert_eq!(receipt["receipt"]["receiver_identity"], PEER);
+        let flagged = feed_events(&node, RECEIPT_INVALID_EVENT).await;
+        assert_eq!(flagged.len(), 1);
+        assert_eq!(flagged[0]["reason"], RECEIPT_SIGNER_UNKNOWN);
+        assert_eq!(flagged[0]["receiver_identity"], PEER);
+    }
 }
@@ -0,0 +1,562 @@
+﻿use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context};
+use chrono::{DateTime, Duration, NaiveDate, NaiveTime, Utc};
+use retasync_contract::encode_canonical;
+use retasync_storage::{JobRecord, JobResultPart, RetasyncStorage, TransferRecord};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::info;
+
+use crate::quotas::window_start;
+
+pub const ARCHIVE_EXTENSION: &str = "msgpack";
+const ARCHIVE_BATCH_SIZE: i64 = 500;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetentionSettings {
+    pub job_retention_hours: u64,
+    pub cache_retention_hours: u64,
+    pub transfer_retention_days: u64,
+    pub archive_dir: Option<String>,
+}
+
+impl Default for RetentionSettings {
+    fn default() -> Self {
+        Self {
+            job_retention_hours: 720,
+            cache_retention_hours: 72,
+            transfer_retention_days: 30,
+            archive_dir: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArchiveTable {
+    Jobs,
+    Transfers,
+}
+
+impl ArchiveTable {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ArchiveTable::Jobs => "jobs",
+            ArchiveTable::Transfers => "transfers",
+        }
+    }
+
+    fn parse(table: &str) -> Option<Self> {
+        match table {
+            "jobs" => Some(ArchiveTable::Jobs),
+            "transfers" => Some(ArchiveTable::Transfers),
+            _ => None,
+        }
+    }
+}
+
+// A purged job together with its result, as written to `jobs-YYYY-MM-DD.msgpack`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchivedJob {
+    pub job_id: String,
+    pub operation: String,
+    #[serde(default)]
+    pub requested_operation: Option<String>,
+    pub status: String,
+    pub payload: Value,
+    pub submitted_at: String,
+    pub updated_at: String,
+    pub failure_reason: Option<String>,
+    pub dispatch: Option<Value>,
+    pub result: Option<Value>,
+    pub completed_at: Option<String>,
+    #[serde(default)]
+    pub result_parts: Vec<JobResultPart>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ArchivedTransfer {
+    pub transfer_id: String,
+    pub status: String,
+    pub metadata: Value,
+    pub submitted_at: String,
+    pub updated_at: String,
+    pub failure_reason: Option<String>,
+    pub job_id: Option<String>,
+}
+
+impl TryFrom<TransferRecord> for ArchivedTransfer {
+    type Error = anyhow::Error;
+
+    fn try_from(record: TransferRecord) -> anyhow::Result<Self> {
+        Ok(Self {
+            metadata: serde_json::from_str(&record.metadata_json)
+                .with_context(|| format!("parse metadata of transfer {}", record.transfer_id))?,
+            transfer_id: record.transfer_id,
+            status: record.status,
+            submitted_at: record.submitted_at,
+            updated_at: record.updated_at,
+            failure_reason: record.failure_reason,
+            job_id: record.job_id,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArchiveFile {
+    pub name: String,
+    pub table: ArchiveTable,
+    pub from: String,
+    pub to: String,
+    pub size_bytes: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RetentionSummary {
+    pub archived_jobs: usize,
+    pub archived_transfers: usize,
+}
+
+pub fn archive_name(table: ArchiveTable, day: NaiveDate) -> String {
+    format!("{}-{}.{ARCHIVE_EXTENSION}", table.as_str(), day.format("%Y-%m-%d"))
+}
+
+pub fn parse_archive_name(name: &str) -> Option<(ArchiveTable, NaiveDate)> {
+    let stem = name.strip_suffix(&format!(".{ARCHIVE_EXTENSION}"))?;
+    let (table, day) = stem.split_once('-')?;
+    let table = ArchiveTable::parse(table)?;
+    let day = NaiveDate::parse_from_str(day, "%Y-%m-%d").ok()?;
+    (archive_name(table, day) == name).then_some((table, day))
+}
+
+// Archives what has outlived its retention, then purges it. Without an `archive_dir` expired
+// rows are deleted outright. Rows are only deleted once the file holding them has been synced
+// and read back, so a failed write leaves them for the next run.
+pub async fn apply_retention(
+    storage: &RetasyncStorage,
+    settings: &RetentionSettings,
+    now: DateTime<Utc>,
+) -> anyhow::Result<RetentionSummary> {
+    let mut summary = RetentionSummary::default();
+    storage
+        .purge_quota_usage(&window_start(now).to_rfc3339())
+        .await?;
+    let rejections_through = now - Duration::hours(settings.job_retention_hours as i64);
+    storage
+        .purge_client_rejections(&rejections_through.to_rfc3339())
+        .await?;
+    let Some(archive_dir) = settings.archive_dir.as_deref() else {
+        storage
+            .purge_expired(
+                settings.job_retention_hours as i64,
+                settings.cache_retention_hours as i64,
+                settings.transfer_retention_days as i64,
+            )
+            .await?;
+        return Ok(summary);
+    };
+    let dir = PathBuf::from(archive_dir);
+    tokio::fs::create_dir_all(&dir)
+        .await
+        .with_context(|| format!("create archive dir {}", dir.display()))?;
+
+    // Transfers go first: a job is only purged once none of its transfers remain.
+    let cutoff = (now - Duration::days(settings.transfer_retention_days as i64)).to_rfc3339();
+    loop {
+        let batch = storage
+            .list_expired_transfers(&cutoff, ARCHIVE_BATCH_SIZE)
+            .await?;
+        let last_batch = batch.len() < ARCHIVE_BATCH_SIZE as usize;
+        let records = batch
+            .into_iter()
+            .map(ArchivedTransfer::try_from)
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        for (day, group) in by_day(records, |record| &record.updated_at)? {
+            write_archive(&dir, ArchiveTable::Transfers, day, &group).await?;
+            let ids: Vec<String> = group.into_iter().map(|record| record.transfer_id).collect();
+            storage.purge_transfers(&ids).await?;
+            summary.archived_transfers += ids.len();
+        }
+        if last_batch {
+            break;
+        }
+    }
+
+    let cutoff = (now - Duration::hours(settings.job_retention_hours as i64)).to_rfc3339();
+    loop {
+        let batch = storage.list_expired_jobs(&cutoff, ARCHIVE_BATCH_SIZE).await?;
+        let last_batch = batch.len() < ARCHIVE_BATCH_SIZE as usize;
+        let mut records = Vec::with_capacity(batch.len());
+        for record in batch {
+            records.push(archived_job(storage, record).await?);
+        }
+        for (day, group) in by_day(records, |record| &record.updated_at)? {
+            write_archive(&dir, ArchiveTable::Jobs, day, &group).await?;
+            let ids: Vec<String> = group.into_iter().map(|record| record.job_id).collect();
+            storage.purge_jobs(&ids).await?;
+            summary.archived_jobs += ids.len();
+        }
+        if last_batch {
+            break;
+        }
+    }
+
+    storage
+        .purge_expired_caches(settings.cache_retention_hours as i64)
+        .await?;
+    if summary != RetentionSummary::default() {
+        info!(
+            jobs = summary.archived_jobs,
+            transfers = summary.archived_transfers,
+            archive_dir,
+            "archived expired rows"
+        );
+    }
+    Ok(summary)
+}
+
+async fn archived_job(storage: &RetasyncStorage, record: JobRecord) -> anyhow::Result<ArchivedJob> {
+    let result = storage.get_job_result(&record.job_id).await?;
+    let result_parts = storage.list_job_result_parts(&record.job_id).await?;
+    let parse = |json: &str| {
+        serde_json::from_str::<Value>(json)
+            .with_context(|| format!("parse archived job {}", record.job_id))
+    };
+    Ok(ArchivedJob {
+        payload: parse(&record.payload_json)?,
+        dispatch: record.dispatch_json.as_deref().map(parse).transpose()?,
+        result: result
+            .as_ref()
+            .map(|result| parse(&result.result_json))
+            .transpose()?,
+        completed_at: result.map(|result| result.completed_at),
+        result_parts,
+        job_id: record.job_id,
+        operation: record.operation,
+        requested_operation: record.requested_operation,
+        status: record.status,
+        submitted_at: record.submitted_at,
+        updated_at: record.updated_at,
+        failure_reason: record.failure_reason,
+    })
+}
+
+fn by_day<T>(
+    records: Vec<T>,
+    updated_at: impl Fn(&T) -> &str,
+) -> anyhow::Result<BTreeMap<NaiveDate, Vec<T>>> {
+    let mut days: BTreeMap<NaiveDate, Vec<T>> = BTreeMap::new();
+    for record in records {
+        let day = DateTime::parse_from_rfc3339(updated_at(&record))
+            .with_context(|| format!("parse archive timestamp {}", updated_at(&record)))?
+            .with_timezone(&Utc)
+            .date_naive();
+        days.entry(day).or_default().push(record);
+    }
+    Ok(days)
+}
+
+async fn write_archive<T: Serialize>(
+    dir: &Path,
+    table: ArchiveTable,
+    day: NaiveDate,
+    records: &[T],
+) -> anyhow::Result<()> {
+    let encoded = records
+        .iter()
+        .map(encode_canonical)
+        .collect::<Result<Vec<_>, _>>()?;
+    let path = dir.join(archive_name(table, day));
+    tokio::task::spawn_blocking(move || append_verified(&path, &encoded)).await?
+}
+
+// Appends to the day's file, syncs it and reads the new tail back. On any mismatch the tail is
+// cut off again so the file is left as it was.
+fn append_verified(path: &Path, records: &[Vec<u8>]) -> anyhow::Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("open archive {}", path.display()))?;
+    let start = file.metadata()?.len();
+    let written = write_and_verify(&mut file, path, start, records);
+    if written.is_err() {
+        let _ = file.set_len(start).and_then(|_| file.sync_all());
+    }
+    written
+}
+
+fn write_and_verify(
+    file: &mut File,
+    path: &Path,
+    start: u64,
+    records: &[Vec<u8>],
+) -> anyhow::Result<()> {
+    let bytes = records.concat();
+    file.write_all(&bytes)
+        .with_context(|| format!("write archive {}", path.display()))?;
+    file.sync_all()
+        .with_context(|| format!("sync archive {}", path.display()))?;
+    if let Some(dir) = path.parent() {
+        sync_dir(dir).with_context(|| format!("sync archive dir {}", dir.display()))?;
+    }
+
+    let mut reread = File::open(path)?;
+    reread.seek(SeekFrom::Start(start))?;
+    let mut tail = Vec::with_capacity(bytes.len());
+    reread.read_to_end(&mut tail)?;
+    if tail != bytes {
+        bail!("archive {} did not read back as written", path.display());
+    }
+    let mut count = 0;
+    for record in ArchiveRecords::new(tail.as_slice()) {
+        record?;
+        count += 1;
+    }
+    if count != records.len() {
+        bail!(
+            "archive {} holds {count} new records, expected {}",
+            path.display(),
+            records.len()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> std::io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+// Decodes an archive one record at a time.
+pub struct ArchiveRecords<R> {
+    reader: BufReader<R>,
+}
+
+impl<R: Read> ArchiveRecords<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader: BufReader::new(reader),
+        }
+    }
+}
+
+impl ArchiveRecords<File> {
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let file = File::open(path).with_context(|| format!("open archive {}", path.display()))?;
+        Ok(Self::new(file))
+    }
+}
+
+impl<R: Read> Iterator for ArchiveRecords<R> {
+    type Item = anyhow::Result<Value>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.reader.fill_buf() {
+            Ok([]) => None,
+            Ok(_) => Some(
+                rmp_serde::from_read::<_, Value>(&mut self.reader)
+                    .context("decode archive record"),
+            ),
+            Err(err) => Some(Err(err.into())),
+        }
+    }
+}
+
+pub fn list_archives(dir: &Path) -> anyhow::Result<Vec<ArchiveFile>> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir).with_context(|| format!("read {}", dir.display()))? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let Some((table, day)) = parse_archive_name(&name) else {
+            continue;
+        };
+        let from = day.and_time(NaiveTime::MIN).and_utc();
+        files.push(ArchiveFile {
+            name,
+            table,
+            from: from.to_rfc3339(),
+            to: (from + Duration::days(1)).to_rfc3339(),
+            size_bytes: entry.metadata()?.len(),
+        });
+    }
+    files.sort_by(|left, right| left.name.cmp(&right.name));
+    Ok(files)
+}
+
+// Resolves a name from `list_archives` inside `dir`; anything else is rejected.
+pub fn archive_path(dir: &Path, name: &str) -> Option<PathBuf> {
+    parse_archive_name(name).map(|_| dir.join(name))
+}
+
+pub fn find_job(dir: &Path, job_id: &str) -> anyhow::Result<Option<ArchivedJob>> {
+    let mut found = None;
+    scan(dir, ArchiveTable::Jobs, |job: ArchivedJob| {
+        if job.job_id == job_id {
+            found = Some(job);
+        }
+        found.is_none()
+    })?;
+    Ok(found)
+}
+
+pub fn find_job_transfers(dir: &Path, job_id: &str) -> anyhow::Result<Vec<ArchivedTransfer>> {
+    let mut transfers = Vec::new();
+    scan(dir, ArchiveTable::Transfers, |transfer: ArchivedTransfer| {
+        if transfer.job_id.as_deref() == Some(job_id) {
+            transfers.push(transfer);
+        }
+        true
+    })?;
+    Ok(transfers)
+}
+
+// Streams every record of `table`, oldest file first, until `visit` returns false.
+fn scan<T: DeserializeOwned>(
+    dir: &Path,
+    table: ArchiveTable,
+    mut visit: impl FnMut(T) -> bool,
+) -> anyhow::Result<()> {
+    for file in list_archives(dir)? {
+        if file.table != table {
+            continue;
+        }
+        let path = dir.join(&file.name);
+        for record in ArchiveRecords::open(&path)? {
+            let record = serde_json::from_value(record?)
+                .with_context(|| format!("unexpected record in {}", file.name))?;
+            if !visit(record) {
+                return Ok(());
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        apply_retention, find_job, find_job_transfers, list_archives, parse_archive_name,
+        ArchiveRecords, ArchiveTable, RetentionSettings,
+    };
+    use crate::test_support::{scratch_dir, scratch_sqlite, test_storage};
+    use chrono::{Duration, NaiveDate, Utc};
+    use retasync_storage::RetasyncStorage;
+    use retasync_transfer::TransferProgress;
+    use serde_json::json;
+    use std::path::{Path, PathBuf};
+
+    async fn storage() -> RetasyncStorage {
+        test_storage(&scratch_sqlite()).await
+    }
+
+    fn settings(archive_dir: &Path) -> RetentionSettings {
+        RetentionSettings {
+            job_retention_hours: 1,
+            transfer_retention_days: 1,
+            archive_dir: Some(archive_dir.to_string_lossy().into_owned()),
+            ..RetentionSettings::default()
+        }
+    }
+
+    fn archive_dir() -> PathBuf {
+        scratch_dir().join("archives")
+    }
+
+    #[test]
+    fn only_well_formed_names_resolve() {
+        let day = NaiveDate::from_ymd_opt(2026, 3, 9).unwrap();
+        assert_eq!(
+            parse_archive_name("jobs-2026-03-09.msgpack"),
+            Some((ArchiveTable::Jobs, day))
+        );
+        assert_eq!(parse_archive_name("jobs-2026-3-9.msgpack"), None);
+        assert_eq!(parse_archive_name("../jobs-2026-03-09.msgpack"), None);
+        assert_eq!(parse_archive_name("cached_events-2026-03-09.msgpack"), None);
+    }
+
+    #[tokio::test]
+    async fn archive_then_purge_keeps_every_record() {
+        let storage = storage().await;
+        let mut jobs = Vec::new();
+        for index in 0..3 {
+            let job = storage
+                .create_job("event.create", json!({ "uid": format!("evt-{index}") }))
+                .await
+                .unwrap();
+            storage
+                .finish_job(
+                    &job.job_id,
+                    Some(json!({ "status": "accepted" })),
+                    "succeeded",
+                    None,
+                )
+                .await
+                .unwrap();
+            jobs.push(job.job_id);
+        }
+        let transfer = storage
+            .create_transfer_with_progress(
+                Some(&jobs[0]),
+                json!({ "file_name": "map.png" }),
+                TransferProgress::new(16, 1),
+            )
+            .await
+            .unwrap();
+
+        let dir = archive_dir();
+        let later = Utc::now() + Duration::days(2);
+        let summary = apply_retention(&storage, &settings(&dir), later).await.unwrap();
+        assert_eq!((summary.archived_jobs, summary.archived_transfers), (3, 1));
+        for job_id in &jobs {
+            assert!(storage.get_job(job_id).await.unwrap().is_none());
+        }
+        assert!(storage.get_transfer(&transfer.transfer_id).await.unwrap().is_none());
+
+        let files = list_archives(&dir).unwrap();
+        let tables: Vec<ArchiveTable> = files.iter().map(|file| file.table).collect();
+        assert_eq!(tables, [ArchiveTable::Jobs, ArchiveTable::Transfers]);
+        let archived = ArchiveRecords::open(&dir.join(&files[0].name))
+            .unwrap()
+            .count();
+        assert_eq!(archived, 3);
+
+        let job = find_job(&dir, &jobs[1]).unwrap().expect("archived job");
+        assert_eq!(job.payload, json!({ "uid": "evt-1" }));
+        assert_eq!(job.result, Some(json!({ "status": "accepted" })));
+        assert_eq!(job.status, "succeeded");
+        let transfers = find_job_transfers(&dir, &jobs[0]).unwrap();
+        assert_eq!(transfers[0].metadata, json!({ "file_name": "map.png" }));
+        assert!(find_job(&dir, "missing").unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn failed_archive_write_keeps_rows() {
+        let storage = storage().await;
+        let job = storage
+            .create_job("event.create", json!({ "uid": "evt-1" }))
+            .await
+            .unwrap();
+
+        // A regular file where the archive directory should be makes every write fail.
+        let blocked = archive_dir();
+        std::fs::write(&blocked, b"not a directory").unwrap();
+        let later = Utc::now() + Duration::days(2);
+        assert!(apply_retention(&storage, &settings(&blocked), later).await.is_err());
+        assert!(storage.get_job(&job.job_id).await.unwrap().is_some());
+    }
+}
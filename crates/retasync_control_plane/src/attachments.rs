@@ -0,0 +1,159 @@
+﻿use base64::{engine::general_purpose::STANDARD, Engine as _};
+use retasync_transfer::TransferUploadRequest;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+pub const ATTACHMENTS_FIELD: &str = "_attachments";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AttachmentDispatch {
+    #[default]
+    AfterCommand,
+    Concurrent,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AttachmentSettings {
+    pub enabled: bool,
+    pub max_attachment_bytes: usize,
+    pub max_job_bytes: usize,
+    pub dispatch: AttachmentDispatch,
+}
+
+impl Default for AttachmentSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_attachment_bytes: 1024 * 1024,
+            max_job_bytes: 4 * 1024 * 1024,
+            dispatch: AttachmentDispatch::AfterCommand,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct InlineAttachment {
+    file_name: String,
+    media_type: String,
+    content_base64: String,
+    #[serde(default)]
+    dedup: Option<bool>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Attachment {
+    pub file_name: String,
+    pub media_type: String,
+    pub content_base64: String,
+    pub bytes: Vec<u8>,
+    pub sha256: String,
+    pub dedup: bool,
+}
+
+impl Attachment {
+    pub fn metadata(&self, destination_identity: &str) -> Value {
+        json!({
+            "destination_identity": destination_identity,
+            "file_name": self.file_name,
+            "media_type": self.media_type,
+            "size": self.bytes.len(),
+            "sha256": self.sha256,
+        })
+    }
+
+    // What the peer sees in place of the inline content.
+    pub fn reference(&self, transfer_id: &str) -> Value {
+        json!({
+            "transfer_id": transfer_id,
+            "file_name": self.file_name,
+            "sha256": self.sha256,
+            "size": self.bytes.len(),
+        })
+    }
+
+    pub fn upload_request(&self, destination_identity: &str) -> TransferUploadRequest {
+        TransferUploadRequest {
+            destination_identity: destination_identity.to_string(),
+            file_name: self.file_name.clone(),
+            media_type: self.media_type.clone(),
+            payload_base64: self.content_base64.clone(),
+            dedup: self.dedup,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AttachmentRejection {
+    Disabled,
+    Malformed(String),
+    AttachmentTooLarge {
+        file_name: String,
+        limit: usize,
+        actual: usize,
+    },
+    JobTooLarge {
+        limit: usize,
+        actual: usize,
+    },
+}
+
+// Removes `_attachments` from a command payload, enforcing the configured size limits.
+pub fn take_attachments(
+    payload: &mut Value,
+    settings: &AttachmentSettings,
+) -> Result<Vec<Attachment>, AttachmentRejection> {
+    let Some(raw) = payload
+        .as_object_mut()
+        .and_then(|object| object.remove(ATTACHMENTS_FIELD))
+    else {
+        return Ok(Vec::new());
+    };
+    // Attachments travel as transfers, which a build without them cannot send.
+    if !settings.enabled || !cfg!(feature = "transfers") {
+        return Err(AttachmentRejection::Disabled);
+    }
+    let Value::Array(entries) = raw else {
+        return Err(AttachmentRejection::Malformed(format!(
+            "{ATTACHMENTS_FIELD} must be an array"
+        )));
+    };
+
+    let mut attachments = Vec::with_capacity(entries.len());
+    let mut total = 0;
+    for (index, entry) in entries.into_iter().enumerate() {
+        let inline: InlineAttachment = serde_json::from_value(entry).map_err(|err| {
+            AttachmentRejection::Malformed(format!("{ATTACHMENTS_FIELD}[{index}]: {err}"))
+        })?;
+        let bytes = STANDARD.decode(&inline.content_base64).map_err(|_| {
+            AttachmentRejection::Malformed(format!(
+                "{ATTACHMENTS_FIELD}[{index}].content_base64 is not valid base64"
+            ))
+        })?;
+        if bytes.len() > settings.max_attachment_bytes {
+            return Err(AttachmentRejection::AttachmentTooLarge {
+                file_name: inline.file_name,
+                limit: settings.max_attachment_bytes,
+                actual: bytes.len(),
+            });
+        }
+        total += bytes.len();
+        if total > settings.max_job_bytes {
+            return Err(AttachmentRejection::JobTooLarge {
+                limit: settings.max_job_bytes,
+                actual: total,
+            });
+        }
+        attachments.push(Attachment {
+            file_name: inline.file_name,
+            media_type: inline.media_type,
+            content_base64: inline.content_base64,
+            sha256: format!("{:x}", Sha256::digest(&bytes)),
+            bytes,
+            dedup: inline.dedup.unwrap_or(true),
+        });
+    }
+    Ok(attachments)
+}
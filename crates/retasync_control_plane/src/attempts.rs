@@ -0,0 +1,226 @@
+﻿use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use retasync_contract::{MeshCommandEnvelope, MeshResultEnvelope, TransferHint};
+use retasync_mesh_bridge::{BridgeError, TransportSelection};
+use retasync_storage::{CanonicalTimestamp, DispatchAttempt};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::app::write_log;
+use crate::circuit::{counts_against_circuit, record_dispatch};
+use crate::trace::transport_hint;
+use crate::AppState;
+
+pub const ATTEMPT_DELIVERED: &str = "delivered";
+pub const ATTEMPT_RETRIED: &str = "retried";
+pub const ATTEMPT_ESCALATED: &str = "escalated";
+pub const ATTEMPT_FAILED: &str = "failed";
+// `?include=` on a job's detail that adds its attempt summary.
+pub const DISPATCH_ATTEMPTS_INCLUDE: &str = "dispatch_attempts";
+// Upper bounds of the latency buckets; a last, unbounded bucket takes the rest.
+pub const LATENCY_BUCKETS_MS: [u64; 9] = [10, 25, 50, 100, 250, 500, 1000, 2500, 5000];
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LatencyHistogram {
+    pub attempts: u64,
+    pub errors: u64,
+    pub total_ms: u64,
+    pub max_ms: u64,
+    // One count per bound in `LATENCY_BUCKETS_MS`, then the overflow.
+    pub buckets: Vec<u64>,
+}
+
+impl LatencyHistogram {
+    pub(crate) fn observe(&mut self, elapsed_ms: u64, failed: bool) {
+        if self.buckets.is_empty() {
+            self.buckets = vec![0; LATENCY_BUCKETS_MS.len() + 1];
+        }
+        let bucket = LATENCY_BUCKETS_MS
+            .iter()
+            .position(|bound| elapsed_ms <= *bound)
+            .unwrap_or(LATENCY_BUCKETS_MS.len());
+        self.buckets[bucket] += 1;
+        self.attempts += 1;
+        self.errors += u64::from(failed);
+        self.total_ms += elapsed_ms;
+        self.max_ms = self.max_ms.max(elapsed_ms);
+    }
+}
+
+// Per-operation dispatch latency, counted only from attempts that made it into the log.
+#[derive(Debug, Default)]
+pub struct DispatchLatencies {
+    operations: Mutex<BTreeMap<String, LatencyHistogram>>,
+}
+
+impl DispatchLatencies {
+    pub fn snapshot(&self) -> BTreeMap<String, LatencyHistogram> {
+        self.lock().clone()
+    }
+
+    fn observe(&self, attempt: &DispatchAttempt) {
+        let elapsed_ms = match (
+            CanonicalTimestamp::parse(&attempt.started_at),
+            CanonicalTimestamp::parse(&attempt.finished_at),
+        ) {
+            (Ok(started), Ok(finished)) => {
+                let elapsed = finished.as_datetime() - started.as_datetime();
+                elapsed.num_milliseconds().max(0) as u64
+            }
+            _ => 0,
+        };
+        self.lock()
+            .entry(attempt.operation.clone())
+            .or_default()
+            .observe(elapsed_ms, attempt.error_variant.is_some());
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BTreeMap<String, LatencyHistogram>> {
+        self.operations
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+// Numbers the bridge calls made for one job message and writes each one to the log.
+#[derive(Debug)]
+pub struct AttemptLog {
+    job_id: String,
+    attempts: i64,
+    // Set once the message has been handed over to store-and-forward.
+    escalated: bool,
+    // Whether an unreachable peer is escalated next rather than failing the job.
+    escalates: bool,
+}
+
+impl AttemptLog {
+    pub fn new(job_id: &str, escalates: bool) -> Self {
+        Self {
+            job_id: job_id.to_string(),
+            attempts: 0,
+            escalated: false,
+            escalates,
+        }
+    }
+
+    pub fn job_id(&self) -> &str {
+        &self.job_id
+    }
+
+    pub fn escalate(&mut self) {
+        self.escalated = true;
+        self.escalates = false;
+    }
+
+    // What follows an attempt, decided before it is written so the row never changes.
+    fn outcome(
+        &self,
+        sent: &Result<MeshResultEnvelope<Value>, BridgeError>,
+        retrying: bool,
+    ) -> &'static str {
+        match sent {
+            Ok(_) => ATTEMPT_DELIVERED,
+            Err(_) if retrying => ATTEMPT_RETRIED,
+            Err(BridgeError::PeerUnreachable(_)) if self.escalates => ATTEMPT_ESCALATED,
+            Err(_) => ATTEMPT_FAILED,
+        }
+    }
+
+    // The circuit breaker and the latency histograms only learn of an attempt once its row is
+    // written, so neither can disagree with the log.
+    pub async fn record(
+        &mut self,
+        state: &AppState,
+        envelope: &MeshCommandEnvelope<Value>,
+        planned: &TransportSelection,
+        started_at: DateTime<Utc>,
+        sent: &Result<MeshResultEnvelope<Value>, BridgeError>,
+        retrying: bool,
+    ) {
+        self.attempts += 1;
+        let (error_variant, error_detail) = match sent {
+            Ok(_) => (None, None),
+            Err(error) => {
+                let (variant, detail) = error_parts(error);
+                (Some(variant.to_string()), (!detail.is_empty()).then_some(detail))
+            }
+        };
+        let transport_used = match sent {
+            Ok(result) => Some(transport_name(
+                result
+                    .transport_hint
+                    .as_ref()
+                    .unwrap_or(&transport_hint(planned)),
+            )),
+            Err(_) => None,
+        };
+        let attempt = DispatchAttempt {
+            message_id: envelope.message_id.clone(),
+            job_id: self.job_id.clone(),
+            destination_identity: envelope.destination_identity.to_string(),
+            operation: envelope.operation.clone(),
+            attempt: self.attempts,
+            transport_planned: transport_name(&transport_hint(planned)).to_string(),
+            transport_used: transport_used.map(str::to_string),
+            started_at: CanonicalTimestamp::from(started_at).to_string(),
+            finished_at: CanonicalTimestamp::now().to_string(),
+            outcome: self.outcome(sent, retrying).to_string(),
+            error_variant,
+            error_detail,
+            escalated: self.escalated,
+        };
+        if let Err(err) = state.storage.record_dispatch_attempt(&attempt).await {
+            let (number, job_id) = (attempt.attempt, &attempt.job_id);
+            let line = format!("recording attempt {number} of job {job_id} failed: {err:#}");
+            write_log(state, "error", &line).await;
+            return;
+        }
+        state.dispatch_latencies.observe(&attempt);
+        let counted = match attempt.outcome.as_str() {
+            ATTEMPT_DELIVERED => Some(true),
+            ATTEMPT_FAILED => attempt
+                .error_variant
+                .as_deref()
+                .filter(|variant| counts_against_circuit(variant))
+                .map(|_| false),
+            _ => None,
+        };
+        if let Some(ok) = counted {
+            record_dispatch(state, &attempt.destination_identity, &attempt.operation, ok).await;
+        }
+    }
+}
+
+pub fn error_parts(error: &BridgeError) -> (&'static str, String) {
+    match error {
+        BridgeError::DaemonUnavailable => ("daemon_unavailable", String::new()),
+        BridgeError::SendFailed(message) => ("send_failed", message.clone()),
+        BridgeError::InvalidPayload(message) => ("invalid_payload", message.clone()),
+        BridgeError::PeerUnreachable(message) => ("peer_unreachable", message.clone()),
+    }
+}
+
+fn transport_name(hint: &TransferHint) -> &'static str {
+    match hint {
+        TransferHint::Link => "link",
+        TransferHint::Lxmf => "lxmf",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn latencies_fall_into_the_first_bucket_that_holds_them() {
+        let mut histogram = LatencyHistogram::default();
+        for (elapsed_ms, failed) in [(0, false), (10, false), (11, true), (9000, true)] {
+            histogram.observe(elapsed_ms, failed);
+        }
+        assert_eq!(histogram.buckets, [2, 1, 0, 0, 0, 0, 0, 0, 0, 1]);
+        assert_eq!((histogram.attempts, histogram.errors), (4, 2));
+        assert_eq!((histogram.total_ms, histogram.max_ms), (9021, 9000));
+    }
+}
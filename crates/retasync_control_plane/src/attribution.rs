@@ -0,0 +1,172 @@
+﻿use std::collections::{BTreeMap, BTreeSet};
+
+use axum::http::HeaderMap;
+use retasync_storage::{ClientActivity, SubmissionSource};
+use serde::{Deserialize, Serialize};
+
+// The token label recorded for callers a listener let in without one.
+pub const ANONYMOUS_LOOPBACK: &str = "anonymous-loopback";
+pub const CLIENT_ID_HEADER: &str = "x-client-id";
+pub const MAX_CLIENT_ID_LEN: usize = 128;
+pub const DEFAULT_STATS_WINDOW: &str = "24h";
+pub const MAX_STATS_WINDOW_SECS: i64 = 31 * 24 * 3600;
+
+// The id a client gave itself. Blank, oversized or non-printable ids are ignored.
+pub fn client_id(headers: &HeaderMap) -> Option<String> {
+    let id = headers.get(CLIENT_ID_HEADER)?.to_str().ok()?.trim();
+    let printable = id.chars().all(|c| c.is_ascii_graphic() || c == ' ');
+    (!id.is_empty() && id.len() <= MAX_CLIENT_ID_LEN && printable).then(|| id.to_string())
+}
+
+// What the rate limit and the token quota charge a submission to: the client's own id, then
+// its token label, then its address. Anonymous callers with no address share one key.
+pub fn client_key(source: &SubmissionSource) -> &str {
+    if let Some(client_id) = &source.client_id {
+        return client_id;
+    }
+    if source.token_label != ANONYMOUS_LOOPBACK {
+        return &source.token_label;
+    }
+    source.remote_addr.as_deref().unwrap_or(ANONYMOUS_LOOPBACK)
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClientStatsQuery {
+    // A span such as `90m` or `7d`; 24 hours when absent.
+    pub window: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ClientStats {
+    pub client: String,
+    // Every token and address the client submitted under during the window. Addresses are
+    // only shown to admins.
+    pub token_labels: BTreeSet<String>,
+    #[serde(skip_serializing_if = "BTreeSet::is_empty")]
+    pub remote_addrs: BTreeSet<String>,
+    pub submissions: i64,
+    pub failures: i64,
+    pub bytes_transferred: i64,
+    pub rate_limited: i64,
+}
+
+// Folds activity recorded per source and rate limit rejections recorded per key into one row
+// per client key.
+pub fn client_stats(activity: &[ClientActivity], rejections: &[(String, i64)]) -> Vec<ClientStats> {
+    let mut clients: BTreeMap<&str, ClientStats> = BTreeMap::new();
+    for entry in activity {
+        let key = client_key(&entry.source);
+        let stats = clients.entry(key).or_insert_with(|| ClientStats {
+            client: key.to_string(),
+            ..Default::default()
+        });
+        stats.token_labels.insert(entry.source.token_label.clone());
+        stats.remote_addrs.extend(entry.source.remote_addr.clone());
+        stats.submissions += entry.submissions;
+        stats.failures += entry.failures;
+        stats.bytes_transferred += entry.bytes;
+    }
+    for (key, rejected) in rejections {
+        let stats = clients.entry(key.as_str()).or_insert_with(|| ClientStats {
+            client: key.clone(),
+            ..Default::default()
+        });
+        stats.rate_limited += rejected;
+    }
+    clients.into_values().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(
+        token_label: &str,
+        remote_addr: Option<&str>,
+        client_id: Option<&str>,
+    ) -> SubmissionSource {
+        SubmissionSource {
+            token_label: token_label.to_string(),
+            remote_addr: remote_addr.map(str::to_string),
+            client_id: client_id.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn client_id_wins_then_token_label_then_address() {
+        assert_eq!(
+            client_key(&source("ops", Some("10.0.0.7"), Some("tak-bridge"))),
+            "tak-bridge"
+        );
+        assert_eq!(client_key(&source("ops", Some("10.0.0.7"), None)), "ops");
+        assert_eq!(
+            client_key(&source(ANONYMOUS_LOOPBACK, Some("127.0.0.1"), None)),
+            "127.0.0.1"
+        );
+        assert_eq!(
+            client_key(&source(ANONYMOUS_LOOPBACK, None, None)),
+            ANONYMOUS_LOOPBACK
+        );
+
+        let mut headers = HeaderMap::new();
+        assert_eq!(client_id(&headers), None);
+        headers.insert(CLIENT_ID_HEADER, " tak-bridge ".parse().unwrap());
+        assert_eq!(client_id(&headers).as_deref(), Some("tak-bridge"));
+        headers.insert(
+            CLIENT_ID_HEADER,
+            "x".repeat(MAX_CLIENT_ID_LEN + 1).parse().unwrap(),
+        );
+        assert_eq!(client_id(&headers), None);
+    }
+
+    #[test]
+    fn activity_folds_into_one_row_per_client() {
+        let activity = |source, submissions, failures, bytes| ClientActivity {
+            source,
+            submissions,
+            failures,
+            bytes,
+        };
+        let activity = [
+            activity(
+                source("ops", Some("10.0.0.7"), Some("tak-bridge")),
+                4,
+                1,
+                2048,
+            ),
+            activity(
+                source("ops", Some("10.0.0.9"), Some("tak-bridge")),
+                2,
+                0,
+                512,
+            ),
+            activity(source("ops", Some("10.0.0.7"), None), 3, 3, 0),
+            activity(
+                source(ANONYMOUS_LOOPBACK, Some("127.0.0.1"), None),
+                1,
+                0,
+                100,
+            ),
+        ];
+        let rejections = [("tak-bridge".to_string(), 5), ("intel-feed".to_string(), 2)];
+        let stats = client_stats(&activity, &rejections);
+
+        let clients: Vec<&str> = stats.iter().map(|stats| stats.client.as_str()).collect();
+        assert_eq!(clients, ["127.0.0.1", "intel-feed", "ops", "tak-bridge"]);
+        let bridge = &stats[3];
+        assert_eq!(
+            (
+                bridge.submissions,
+                bridge.failures,
+                bridge.bytes_transferred,
+                bridge.rate_limited
+            ),
+            (6, 1, 2560, 5)
+        );
+        assert_eq!(bridge.remote_addrs.len(), 2);
+        assert_eq!((stats[2].submissions, stats[2].failures), (3, 3));
+        assert_eq!((stats[1].submissions, stats[1].rate_limited), (0, 2));
+        assert!(stats[1].token_labels.is_empty());
+    }
+}
@@ -0,0 +1,429 @@
+﻿use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use anyhow::Context;
+use axum::extract::{Request, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use chrono::{DateTime, Duration, Utc};
+use retasync_contract::IdentityHash;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::Mutex;
+use toml_edit::{table, value, Array, DocumentMut, InlineTable};
+use tower::ServiceExt;
+
+use crate::app::{
+    health_live, internal_error, publish, storage_error, write_log, ALLOWLIST_ROLES,
+};
+use crate::dispatch::{validate_dispatch_config, IdentitySettings};
+use crate::error::{refusal_response, RetryAdvice, RetryScope, BOOTSTRAP_RETRY_AFTER};
+use crate::{build_router, ApiToken, AppState, NodeConfig};
+
+pub const BOOTSTRAP_REQUIRED_ERROR: &str = "bootstrap_required";
+pub const BOOTSTRAP_COMPLETED_EVENT: &str = "node.bootstrap.completed";
+pub const DEFAULT_BOOTSTRAP_TOKEN_TTL_SECS: u64 = 900;
+const TOKEN_BYTES: usize = 32;
+// Labels resolve to notification cursors; "default" already names the admin token.
+const ADMIN_TOKEN_LABEL: &str = "default";
+
+pub struct ProvisioningToken {
+    token: String,
+    expires_at: DateTime<Utc>,
+}
+
+impl ProvisioningToken {
+    pub fn generate(ttl_secs: u64, now: DateTime<Utc>) -> anyhow::Result<Self> {
+        let mut bytes = [0u8; TOKEN_BYTES];
+        getrandom::getrandom(&mut bytes)
+            .map_err(|err| anyhow::anyhow!("generate provisioning token: {err}"))?;
+        let ttl = i64::try_from(ttl_secs)
+            .ok()
+            .and_then(Duration::try_seconds)
+            .context("bootstrap_token_ttl_secs is out of range")?;
+        Ok(Self {
+            token: URL_SAFE_NO_PAD.encode(bytes),
+            expires_at: now + ttl,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.token
+    }
+
+    pub fn expires_at(&self) -> DateTime<Utc> {
+        self.expires_at
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BootstrapRequest {
+    pub admin_token: String,
+    #[serde(default)]
+    pub api_tokens: Vec<ApiToken>,
+    pub identity: Option<IdentitySettings>,
+    pub acl_mode: Option<String>,
+    #[serde(default)]
+    pub allowlist: Vec<AllowlistSeed>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AllowlistSeed {
+    pub identity_hash: String,
+    pub note: Option<String>,
+    pub role: Option<String>,
+}
+
+enum Phase {
+    Pending(ProvisioningToken),
+    Complete,
+}
+
+#[derive(Clone)]
+struct Bootstrapper {
+    state: AppState,
+    config_path: PathBuf,
+    phase: Arc<Mutex<Phase>>,
+    current: Arc<RwLock<Router>>,
+    full: Router,
+}
+
+// Until `POST /v1/bootstrap` succeeds only `/health/live` answers; everything else gets 503
+// `bootstrap_required`. The full router is then swapped in for every later request, and the
+// bootstrap route stays behind it only to refuse repeats.
+pub fn bootstrap_router(state: AppState, token: ProvisioningToken, config_path: PathBuf) -> Router {
+    let pending = Router::new()
+        .route("/health/live", get(health_live))
+        .fallback(bootstrap_required);
+    let bootstrapper = Bootstrapper {
+        full: build_router(state.clone()),
+        state,
+        config_path,
+        phase: Arc::new(Mutex::new(Phase::Pending(token))),
+        current: Arc::new(RwLock::new(pending)),
+    };
+    Router::new()
+        .route("/v1/bootstrap", post(post_bootstrap))
+        .fallback(route_current)
+        .with_state(bootstrapper)
+}
+
+async fn bootstrap_required() -> Response {
+    refusal_response(
+        StatusCode::SERVICE_UNAVAILABLE,
+        json!({ "error": BOOTSTRAP_REQUIRED_ERROR }),
+        RetryAdvice::after(BOOTSTRAP_REQUIRED_ERROR, RetryScope::Node, BOOTSTRAP_RETRY_AFTER),
+    )
+}
+
+async fn route_current(State(bootstrapper): State<Bootstrapper>, request: Request) -> Response {
+    let router = bootstrapper
+        .current
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .clone();
+    router
+        .oneshot(request)
+        .await
+        .unwrap_or_else(|never| match never {})
+}
+
+fn rejected(status: StatusCode, error: &str) -> (StatusCode, Json<Value>) {
+    (status, Json(json!({ "error": error })))
+}
+
+// The phase lock is held throughout, so of two concurrent attempts one bootstraps the node
+// and the other sees 409. A failed attempt leaves the token usable until it expires.
+async fn post_bootstrap(
+    State(bootstrapper): State<Bootstrapper>,
+    headers: HeaderMap,
+    Json(payload): Json<Value>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    let mut phase = bootstrapper.phase.lock().await;
+    let Phase::Pending(token) = &*phase else {
+        return Err(rejected(StatusCode::CONFLICT, "bootstrap_complete"));
+    };
+    let provided = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if provided != Some(token.as_str()) {
+        return Err(rejected(StatusCode::UNAUTHORIZED, "invalid_bootstrap_token"));
+    }
+    if Utc::now() >= token.expires_at() {
+        return Err(rejected(StatusCode::UNAUTHORIZED, "bootstrap_token_expired"));
+    }
+
+    let request: BootstrapRequest = serde_json::from_value(payload).map_err(|err| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": "invalid_bootstrap_request", "detail": err.to_string() })),
+        )
+    })?;
+    let state = &bootstrapper.state;
+    let config = bootstrapped_config(state.node_config.read().await.clone(), &request)
+        .map_err(|detail| {
+            (
+                StatusCode::BAD_REQUEST,
+                Json(json!({ "error": "invalid_bootstrap_request", "detail": detail })),
+            )
+        })?;
+
+    for seed in &request.allowlist {
+        state
+            .storage
+            .put_allowlist_entry(
+                &IdentityHash::lenient(&seed.identity_hash),
+                seed.note.as_deref(),
+                seed.role.as_deref().unwrap_or("peer"),
+                "active",
+                None,
+            )
+            .await
+            .map_err(storage_error)?;
+    }
+    write_back(&bootstrapper.config_path, &config).map_err(internal_error)?;
+    let serialized = serde_json::to_string(&config).map_err(|err| internal_error(err.into()))?;
+    let completed = json!({
+        "api_tokens": request.api_tokens.len(),
+        "allowlist_seeded": request.allowlist.len(),
+    });
+    state
+        .storage
+        .with_event(BOOTSTRAP_COMPLETED_EVENT, completed)
+        .append_node_config_revision(&serialized)
+        .await
+        .map_err(storage_error)?;
+
+    *state.node_config.write().await = config;
+    *phase = Phase::Complete;
+    *bootstrapper
+        .current
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner()) = bootstrapper.full.clone();
+    drop(phase);
+
+    write_log(state, "info", "node bootstrapped").await;
+    publish(state).await;
+    Ok(Json(json!({
+        "status": "bootstrapped",
+        "allowlist_seeded": request.allowlist.len(),
+    })))
+}
+
+fn bootstrapped_config(
+    mut config: NodeConfig,
+    request: &BootstrapRequest,
+) -> Result<NodeConfig, String> {
+    if request.admin_token.trim().is_empty() {
+        return Err("admin_token must not be empty".to_string());
+    }
+    let mut labels = BTreeSet::new();
+    for token in &request.api_tokens {
+        if token.label.is_empty() || token.token.is_empty() {
+            return Err("api_tokens need a label and a token".to_string());
+        }
+        if token.label == ADMIN_TOKEN_LABEL || !labels.insert(token.label.as_str()) {
+            return Err(format!("api token label {} is already taken", token.label));
+        }
+        if token.token == request.admin_token {
+            return Err(format!("api token {} repeats admin_token", token.label));
+        }
+    }
+    for seed in &request.allowlist {
+        if let Err(err) = IdentityHash::parse(&seed.identity_hash) {
+            let identity = &seed.identity_hash;
+            return Err(format!("allowlist entry {identity} is not an identity hash: {err}"));
+        }
+        if let Some(role) = seed.role.as_deref().filter(|role| !ALLOWLIST_ROLES.contains(role)) {
+            return Err(format!("allowlist role {role} is not one of {ALLOWLIST_ROLES:?}"));
+        }
+    }
+
+    config.http_auth_token = Some(request.admin_token.clone());
+    config.api_tokens = request.api_tokens.clone();
+    if let Some(identity) = &request.identity {
+        config.identity = identity.clone();
+    }
+    if let Some(acl_mode) = &request.acl_mode {
+        if acl_mode.trim().is_empty() {
+            return Err("acl_mode must not be empty".to_string());
+        }
+        config.acl_mode = acl_mode.clone();
+    }
+    validate_dispatch_config(&config)?;
+    Ok(config)
+}
+
+// Edits only the bootstrapped keys, so comments and everything else in the file survive. The
+// file is replaced by rename, so a crash leaves either the old or the new version.
+fn write_back(path: &Path, config: &NodeConfig) -> anyhow::Result<()> {
+    let source = std::fs::read_to_string(path)
+        .with_context(|| format!("read config file {}", path.display()))?;
+    let (bom, body) = match source.strip_prefix('\u{feff}') {
+        Some(body) => ("\u{feff}", body),
+        None => ("", source.as_str()),
+    };
+    let mut doc: DocumentMut = body
+        .parse()
+        .with_context(|| format!("parse config file {}", path.display()))?;
+
+    if let Some(token) = &config.http_auth_token {
+        doc["http"]["auth_token"] = value(token.as_str());
+    }
+    if !config.api_tokens.is_empty() {
+        let mut tokens = Array::new();
+        for token in &config.api_tokens {
+            let mut entry = InlineTable::new();
+            entry.insert("label", token.label.as_str().into());
+            entry.insert("token", token.token.as_str().into());
+            tokens.push(entry);
+        }
+        doc["http"]["api_tokens"] = value(tokens);
+    }
+    if doc["http"].get("bootstrap").is_some() {
+        doc["http"]["bootstrap"] = value(false);
+    }
+    doc["acl"]["mode"] = value(config.acl_mode.as_str());
+    let identity = [
+        ("source_identity", &config.identity.source_identity),
+        ("default_destination", &config.identity.default_destination),
+    ];
+    for (key, identity) in identity {
+        if let Some(identity) = identity {
+            if doc.get("identity").is_none() {
+                doc["identity"] = table();
+            }
+            doc["identity"][key] = value(identity.as_str());
+        }
+    }
+
+    let mut staged = path.as_os_str().to_owned();
+    staged.push(".bootstrap");
+    let staged = PathBuf::from(staged);
+    std::fs::write(&staged, format!("{bom}{doc}"))
+        .with_context(|| format!("write {}", staged.display()))?;
+    std::fs::rename(&staged, path)
+        .with_context(|| format!("replace config file {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bootstrap_router, ProvisioningToken, BOOTSTRAP_REQUIRED_ERROR};
+    use crate::test_support::scratch_dir;
+    use crate::AppState;
+    use axum::body::{to_bytes, Body};
+    use axum::http::{header, Request, StatusCode};
+    use axum::Router;
+    use chrono::{Duration, Utc};
+
+    use serde_json::{json, Value};
+    use std::path::PathBuf;
+
+    use tower::ServiceExt;
+
+    const PEER: &str = "aa00000000000000000000000000000a";
+    const CONFIG: &str = "\u{feff}[http]\nbind = \"127.0.0.1:8080\"\n# Filled in by bootstrap.\n\
+                          bootstrap = true\n\n[acl]\nmode = \"allowlist\"\n";
+
+    async fn test_state() -> (AppState, PathBuf) {
+        let state = crate::test_support::test_state().await;
+        let config_path = scratch_dir().join("retasyncd.toml");
+        std::fs::write(&config_path, CONFIG).unwrap();
+        (state, config_path)
+    }
+
+    async fn call(router: &Router, request: Request<Body>) -> (StatusCode, Value) {
+        let response = router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+    }
+
+    fn bootstrap(token: &str, body: &Value) -> Request<Body> {
+        Request::post("/v1/bootstrap")
+            .header(header::AUTHORIZATION, format!("Bearer {token}"))
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    fn get(uri: &str) -> Request<Body> {
+        Request::get(uri).body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn bootstrap_provisions_the_node_once() {
+        let (state, config_path) = test_state().await;
+        let token = ProvisioningToken::generate(60, Utc::now()).unwrap();
+        let secret = token.as_str().to_string();
+        let router = bootstrap_router(state.clone(), token, config_path.clone());
+
+        let (status, body) = call(&router, get("/v1/node/config")).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["error"], BOOTSTRAP_REQUIRED_ERROR);
+        assert_eq!(body["retry"]["retry_after_ms"], 5000);
+        let pending = router.clone().oneshot(get("/v1/jobs")).await.unwrap();
+        assert_eq!(pending.headers()[header::RETRY_AFTER], "5");
+        assert_eq!(call(&router, get("/health/live")).await.0, StatusCode::OK);
+
+        let request = json!({
+            "admin_token": "admin-secret",
+            "api_tokens": [{ "label": "dashboard", "token": "dashboard-secret" }],
+            "identity": { "source_identity": "bb00000000000000000000000000000b" },
+            "acl_mode": "open",
+            "allowlist": [{ "identity_hash": PEER, "note": "first peer" }],
+        });
+        let (status, body) = call(&router, bootstrap("wrong", &request)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["error"], "invalid_bootstrap_token");
+        let (status, body) = call(&router, bootstrap(&secret, &request)).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+
+        let (status, config) = call(&router, get("/v1/node/config")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(config["http_auth_token"], "admin-secret");
+        assert_eq!(config["acl_mode"], "open");
+        assert!(state.storage.is_allowlisted(&PEER.parse().unwrap(), Utc::now()).await.unwrap());
+        let revisions: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM node_config_revisions")
+            .fetch_one(state.storage.pool())
+            .await
+            .unwrap();
+        assert_eq!(revisions, 1);
+
+        let written = std::fs::read_to_string(&config_path).unwrap();
+        assert!(written.starts_with("\u{feff}[http]"));
+        assert!(written.contains("# Filled in by bootstrap.\nbootstrap = false"));
+        assert!(written.contains("auth_token = \"admin-secret\""));
+        assert!(written.ends_with(
+            "[identity]\nsource_identity = \"bb00000000000000000000000000000b\"\n"
+        ));
+
+        let (status, body) = call(&router, bootstrap(&secret, &request)).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["error"], "bootstrap_complete");
+    }
+
+    #[tokio::test]
+    async fn expired_tokens_leave_the_node_unprovisioned() {
+        let (state, config_path) = test_state().await;
+        let token = ProvisioningToken::generate(60, Utc::now() - Duration::minutes(2)).unwrap();
+        let secret = token.as_str().to_string();
+        let router = bootstrap_router(state.clone(), token, config_path.clone());
+
+        let request = json!({ "admin_token": "admin-secret" });
+        let (status, body) = call(&router, bootstrap(&secret, &request)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["error"], "bootstrap_token_expired");
+        let (status, _) = call(&router, get("/v1/jobs/aggregate")).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert!(state.node_config.read().await.http_auth_token.is_none());
+        assert_eq!(std::fs::read_to_string(&config_path).unwrap(), CONFIG);
+    }
+}
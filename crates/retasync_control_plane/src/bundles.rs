@@ -0,0 +1,572 @@
+﻿#[cfg(feature = "transfers")]
+use std::collections::{BTreeMap, HashMap};
+#[cfg(feature = "transfers")]
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "transfers")]
+use anyhow::Context;
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+#[cfg(feature = "transfers")]
+use chrono::Utc;
+use retasync_contract::{Bundle, BundleEntry};
+#[cfg(feature = "transfers")]
+use retasync_contract::{IdentityHash, MeshTransferEnvelope, BUNDLE_MEDIA_TYPE};
+use retasync_storage::BundleMember;
+#[cfg(feature = "transfers")]
+use retasync_storage::ReceivedFile;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+#[cfg(feature = "transfers")]
+use serde_json::json;
+#[cfg(feature = "transfers")]
+use tracing::warn;
+
+#[cfg(feature = "transfers")]
+use crate::app::publish;
+#[cfg(feature = "transfers")]
+use crate::features::TRANSFER_DEDUP_FLAG;
+#[cfg(feature = "transfers")]
+use crate::files::{check_inbound, inspected, FILE_QUARANTINED_EVENT};
+#[cfg(feature = "transfers")]
+use crate::receipts::send_receipt;
+#[cfg(feature = "transfers")]
+use crate::AppState;
+
+pub const PARTIAL_STATUS: &str = "partial";
+pub const BUNDLE_RECEIVED_EVENT: &str = "transfer.bundle_received";
+pub const PACKED_MEMBER_STATUS: &str = "packed";
+pub const RECEIVED_MEMBER_STATUS: &str = "received";
+pub const FAILED_MEMBER_STATUS: &str = "failed";
+pub const QUARANTINED_MEMBER_STATUS: &str = "quarantined";
+// A bundle whose next chunk has not arrived in this long is given up on.
+#[cfg(feature = "transfers")]
+const ASSEMBLY_IDLE_TIMEOUT: Duration = Duration::from_secs(600);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BundleSettings {
+    // Limits the encoded bundle, on both the sending and the receiving side.
+    pub max_bundle_bytes: usize,
+    pub max_files: usize,
+}
+
+impl Default for BundleSettings {
+    fn default() -> Self {
+        Self {
+            max_bundle_bytes: 16 * 1024 * 1024,
+            max_files: 256,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BundleFile {
+    pub name: String,
+    pub media_type: String,
+    pub content_base64: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BundleUploadRequest {
+    pub destination_identity: String,
+    pub bundle_name: String,
+    #[serde(default)]
+    pub metadata: Map<String, Value>,
+    pub files: Vec<BundleFile>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BundleRejection {
+    Malformed(String),
+    TooManyFiles { limit: usize, actual: usize },
+    TooLarge { limit: usize, actual: usize },
+}
+
+// Decodes and packs the request's files; the size limit applies to the encoded bundle.
+pub fn pack_request(
+    request: &BundleUploadRequest,
+    settings: &BundleSettings,
+) -> Result<(Bundle, Vec<u8>), BundleRejection> {
+    if request.files.len() > settings.max_files {
+        return Err(BundleRejection::TooManyFiles {
+            limit: settings.max_files,
+            actual: request.files.len(),
+        });
+    }
+    let mut entries = Vec::with_capacity(request.files.len());
+    for (index, file) in request.files.iter().enumerate() {
+        let bytes = STANDARD.decode(&file.content_base64).map_err(|_| {
+            BundleRejection::Malformed(format!("files[{index}].content_base64 is not valid base64"))
+        })?;
+        entries.push(BundleEntry::new(&file.name, &file.media_type, bytes));
+    }
+    let bundle = Bundle::pack(request.metadata.clone(), entries)
+        .map_err(|err| BundleRejection::Malformed(err.to_string()))?;
+    let encoded = bundle
+        .encode()
+        .map_err(|err| BundleRejection::Malformed(err.to_string()))?;
+    if encoded.len() > settings.max_bundle_bytes {
+        return Err(BundleRejection::TooLarge {
+            limit: settings.max_bundle_bytes,
+            actual: encoded.len(),
+        });
+    }
+    Ok((bundle, encoded))
+}
+
+pub fn members<F>(bundle: &Bundle, status: F) -> Vec<BundleMember>
+where
+    F: Fn(&BundleEntry) -> &'static str,
+{
+    bundle
+        .entries
+        .iter()
+        .enumerate()
+        .map(|(position, entry)| BundleMember {
+            position: position as i64,
+            name: entry.name.clone(),
+            media_type: entry.media_type.clone(),
+            size_bytes: entry.bytes.len() as i64,
+            sha256: entry.sha256.clone(),
+            status: status(entry).to_string(),
+        })
+        .collect()
+}
+
+// The fields of a `transfer.upload` chunk that reassembly needs.
+#[cfg(feature = "transfers")]
+#[derive(Debug, Deserialize)]
+struct TransferChunk {
+    transfer_id: String,
+    file_name: String,
+    media_type: String,
+    chunk_index: usize,
+    chunks_total: usize,
+    payload_base64: String,
+}
+
+#[cfg(feature = "transfers")]
+#[derive(Debug)]
+struct PartialBundle {
+    chunks_total: usize,
+    chunks: BTreeMap<usize, Vec<u8>>,
+    bytes: usize,
+    touched: Instant,
+}
+
+// Bundle chunks received so far, keyed by sender and the sender's transfer id.
+#[cfg(feature = "transfers")]
+#[derive(Debug, Default)]
+pub struct BundleAssembly {
+    partial: HashMap<(String, String), PartialBundle>,
+}
+
+#[cfg(feature = "transfers")]
+impl BundleAssembly {
+    // Returns the whole payload once the last missing chunk arrives. An error drops the bundle.
+    fn add(
+        &mut self,
+        source_identity: &str,
+        chunk: TransferChunk,
+        bytes: Vec<u8>,
+        max_bytes: usize,
+        now: Instant,
+    ) -> Result<Option<Vec<u8>>, String> {
+        self.partial
+            .retain(|_, partial| now.duration_since(partial.touched) < ASSEMBLY_IDLE_TIMEOUT);
+        let key = (source_identity.to_string(), chunk.transfer_id);
+        let partial = self
+            .partial
+            .entry(key.clone())
+            .or_insert_with(|| PartialBundle {
+                chunks_total: chunk.chunks_total,
+                chunks: BTreeMap::new(),
+                bytes: 0,
+                touched: now,
+            });
+        if chunk.chunks_total != partial.chunks_total || chunk.chunk_index >= partial.chunks_total {
+            self.partial.remove(&key);
+            return Err(format!(
+                "chunk {} of {} does not fit the bundle",
+                chunk.chunk_index, chunk.chunks_total
+            ));
+        }
+        partial.touched = now;
+        partial.bytes += bytes.len();
+        if let Some(previous) = partial.chunks.insert(chunk.chunk_index, bytes) {
+            partial.bytes -= previous.len();
+        }
+        if partial.bytes > max_bytes {
+            let actual = partial.bytes;
+            self.partial.remove(&key);
+            return Err(format!(
+                "bundle exceeds {max_bytes} bytes (at least {actual})"
+            ));
+        }
+        if partial.chunks.len() < partial.chunks_total {
+            return Ok(None);
+        }
+        let partial = self.partial.remove(&key).expect("assembled bundle present");
+        Ok(Some(partial.chunks.into_values().flatten().collect()))
+    }
+}
+
+// Handles one inbound transfer chunk. Only bundle chunks are reassembled here; single-file
+// uploads are at most hashed, by `digest_upload`.
+#[cfg(feature = "transfers")]
+pub async fn receive_transfer(
+    state: &AppState,
+    envelope: MeshTransferEnvelope<Value>,
+) -> anyhow::Result<()> {
+    let chunk: TransferChunk =
+        serde_json::from_value(envelope.payload).context("decode transfer chunk")?;
+    if chunk.media_type != BUNDLE_MEDIA_TYPE {
+        return digest_upload(state, &envelope.source_identity, chunk).await;
+    }
+    let source = envelope.source_identity;
+    let remote_transfer_id = chunk.transfer_id.clone();
+    let max_bytes = state
+        .node_config
+        .read()
+        .await
+        .transfer_bundles
+        .max_bundle_bytes;
+    let added = match STANDARD.decode(&chunk.payload_base64) {
+        Ok(bytes) => {
+            let mut assembly = state
+                .bundle_assembly
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            let file_name = chunk.file_name.clone();
+            assembly
+                .add(&source, chunk, bytes, max_bytes, Instant::now())
+                .map(|payload| payload.map(|payload| (file_name, payload)))
+        }
+        Err(_) => Err("chunk payload is not valid base64".to_string()),
+    };
+    match added {
+        Ok(None) => Ok(()),
+        Ok(Some((file_name, payload))) => {
+            unpack(state, &source, &remote_transfer_id, &file_name, &payload).await
+        }
+        Err(reason) => {
+            let metadata = received_metadata(&source, &remote_transfer_id, None, None);
+            record(
+                state,
+                metadata,
+                "failed",
+                Some(&reason),
+                Vec::new(),
+                Vec::new(),
+                Vec::new(),
+            )
+            .await
+        }
+    }
+}
+
+// With transfer dedup on, a single-file upload received in full is recorded under the hash of
+// the bytes that arrived, so a later offer of the same content is answered `have`.
+#[cfg(feature = "transfers")]
+async fn digest_upload(
+    state: &AppState,
+    source: &IdentityHash,
+    chunk: TransferChunk,
+) -> anyhow::Result<()> {
+    if !state.features.is_enabled(TRANSFER_DEDUP_FLAG) {
+        return Ok(());
+    }
+    let bytes = STANDARD
+        .decode(&chunk.payload_base64)
+        .context("upload chunk payload is not valid base64")?;
+    let digested = state
+        .upload_digests
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .add(
+            source,
+            &chunk.transfer_id,
+            chunk.chunk_index,
+            chunk.chunks_total,
+            bytes,
+            Instant::now(),
+        );
+    let (sha256, size) = match digested {
+        Ok(Some(digest)) => digest,
+        Ok(None) => return Ok(()),
+        Err(reason) => {
+            warn!(
+                source_identity = %source,
+                transfer_id = %chunk.transfer_id,
+                reason,
+                "inbound upload not hashed"
+            );
+            return Ok(());
+        }
+    };
+    state
+        .storage
+        .record_received_file(&ReceivedFile {
+            sha256,
+            size_bytes: size as i64,
+            file_name: chunk.file_name,
+            source_identity: source.to_string(),
+            received_at: Utc::now().to_rfc3339(),
+            bundle_id: None,
+            media_type: Some(chunk.media_type),
+            quarantine_reason: None,
+        })
+        .await?;
+    Ok(())
+}
+
+// Records each member on its own: one failing its hash fails only that member and leaves the
+// bundle `partial`. Members refused by the `[files]` policy are kept, but quarantined.
+#[cfg(feature = "transfers")]
+async fn unpack(
+    state: &AppState,
+    source: &str,
+    remote_transfer_id: &str,
+    file_name: &str,
+    payload: &[u8],
+) -> anyhow::Result<()> {
+    let bundle = match Bundle::decode(payload) {
+        Ok(bundle) => bundle,
+        Err(err) => {
+            let metadata = received_metadata(source, remote_transfer_id, Some(file_name), None);
+            let reason = err.to_string();
+            return record(
+                state,
+                metadata,
+                "failed",
+                Some(&reason),
+                Vec::new(),
+                Vec::new(),
+                Vec::new(),
+            )
+            .await;
+        }
+    };
+    let received_at = Utc::now().to_rfc3339();
+    let settings = state.node_config.read().await.files.clone();
+    let mut files = Vec::new();
+    let mut quarantined = HashMap::new();
+    for entry in bundle.entries.iter().filter(|entry| entry.verify()) {
+        let file = inspected(
+            &entry.name,
+            &entry.media_type,
+            &entry.sha256,
+            source,
+            &entry.bytes,
+        );
+        let violation = check_inbound(&settings, state.content_inspector.as_ref(), &file).err();
+        let received = ReceivedFile {
+            sha256: entry.sha256.clone(),
+            size_bytes: entry.bytes.len() as i64,
+            file_name: entry.name.clone(),
+            source_identity: source.to_string(),
+            received_at: received_at.clone(),
+            bundle_id: None,
+            media_type: Some(entry.media_type.clone()),
+            quarantine_reason: violation.as_ref().map(|violation| violation.reason()),
+        };
+        if let Some(violation) = violation {
+            quarantined.insert(entry.sha256.clone(), violation);
+        }
+        files.push((received, entry.bytes.clone()));
+    }
+    let members = members(&bundle, |entry| {
+        if !entry.verify() {
+            FAILED_MEMBER_STATUS
+        } else if quarantined.contains_key(&entry.sha256) {
+            QUARANTINED_MEMBER_STATUS
+        } else {
+            RECEIVED_MEMBER_STATUS
+        }
+    });
+    let (status, reason) = match bundle.entries.len() - files.len() {
+        0 => ("success", None),
+        failed if failed == bundle.entries.len() => {
+            ("failed", Some("every member failed its hash".to_string()))
+        }
+        failed => (
+            PARTIAL_STATUS,
+            Some(format!("{failed} member(s) failed their hash")),
+        ),
+    };
+    let metadata = received_metadata(source, remote_transfer_id, Some(file_name), Some(&bundle));
+    let mut quarantine_events = Vec::new();
+    for (file, violation) in bundle
+        .entries
+        .iter()
+        .filter_map(|entry| Some((entry, quarantined.get(&entry.sha256)?)))
+    {
+        warn!(
+            sha256 = %file.sha256,
+            file_name = %file.name,
+            source_identity = %source,
+            reason = %violation.reason(),
+            "received file quarantined"
+        );
+        quarantine_events.push(json!({
+            "remote_transfer_id": remote_transfer_id,
+            "sha256": file.sha256,
+            "file_name": file.name,
+            "media_type": file.media_type,
+            "source_identity": source,
+            "reason": violation.code(),
+            "detail": violation.detail(),
+        }));
+    }
+    let reason = reason.as_deref();
+    record(state, metadata, status, reason, members, files, quarantine_events).await
+}
+
+#[cfg(feature = "transfers")]
+fn received_metadata(
+    source: &str,
+    remote_transfer_id: &str,
+    file_name: Option<&str>,
+    bundle: Option<&Bundle>,
+) -> Value {
+    let mut metadata = json!({
+        "direction": "inbound",
+        "source_identity": source,
+        "remote_transfer_id": remote_transfer_id,
+        "file_name": file_name,
+        "media_type": BUNDLE_MEDIA_TYPE,
+    });
+    if let Some(bundle) = bundle {
+        metadata["bundle_digest"] = json!(bundle.digest);
+        metadata["bundle_metadata"] = Value::Object(bundle.metadata.clone());
+        metadata["size"] = json!(bundle.total_bytes());
+    }
+    metadata
+}
+
+#[cfg(feature = "transfers")]
+async fn record(
+    state: &AppState,
+    metadata: Value,
+    status: &str,
+    reason: Option<&str>,
+    members: Vec<BundleMember>,
+    files: Vec<(ReceivedFile, Vec<u8>)>,
+    quarantine_events: Vec<Value>,
+) -> anyhow::Result<()> {
+    let quarantined = files
+        .iter()
+        .filter(|(file, _)| file.quarantine_reason.is_some())
+        .count();
+    let (received, failed) = (files.len() - quarantined, members.len() - files.len());
+    let mut received_event = json!({
+        "remote_transfer_id": metadata["remote_transfer_id"],
+        "source_identity": metadata["source_identity"],
+        "status": status,
+        "members_received": received,
+        "members_failed": failed,
+        "members_quarantined": quarantined,
+    });
+    let (stored, status_owned) = (metadata.clone(), status.to_string());
+    let reason_owned = reason.map(str::to_string);
+    // The bundle and its events commit together; the event names the transfer it created.
+    let transfer = state
+        .storage
+        .with_tx(move |tx| {
+            Box::pin(async move {
+                let transfer = tx
+                    .record_received_bundle(
+                        &stored,
+                        &status_owned,
+                        reason_owned.as_deref(),
+                        &members,
+                        files,
+                    )
+                    .await?;
+                received_event["transfer_id"] = json!(transfer.transfer_id);
+                tx.append_feed_event(BUNDLE_RECEIVED_EVENT, &received_event).await?;
+                for event in &quarantine_events {
+                    tx.append_feed_event(FILE_QUARANTINED_EVENT, event).await?;
+                }
+                Ok(transfer)
+            })
+        })
+        .await?;
+    if status != "success" {
+        warn!(
+            transfer_id = %transfer.transfer_id,
+            remote_transfer_id = %metadata["remote_transfer_id"],
+            reason = reason.unwrap_or_default(),
+            "inbound bundle {status}"
+        );
+    }
+    publish(state).await;
+    // The sender learns the outcome under its own transfer id.
+    if let (Some(source), Some(remote_transfer_id)) = (
+        metadata["source_identity"].as_str(),
+        metadata["remote_transfer_id"].as_str(),
+    ) {
+        let result = json!({ "status": status, "bundle_digest": metadata["bundle_digest"] });
+        let source = IdentityHash::lenient(source);
+        send_receipt(state, remote_transfer_id, &source, Utc::now(), &result).await;
+    }
+    Ok(())
+}
+
+#[cfg(all(test, feature = "transfers"))]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{BundleAssembly, TransferChunk};
+
+    fn chunk(index: usize, total: usize) -> TransferChunk {
+        TransferChunk {
+            transfer_id: "t1".to_string(),
+            file_name: "photos.bundle".to_string(),
+            media_type: super::BUNDLE_MEDIA_TYPE.to_string(),
+            chunk_index: index,
+            chunks_total: total,
+            payload_base64: String::new(),
+        }
+    }
+
+    #[test]
+    fn chunks_reassemble_in_index_order_and_respect_the_limit() {
+        let mut assembly = BundleAssembly::default();
+        let now = Instant::now();
+        assert_eq!(
+            assembly.add("peer", chunk(1, 3), b"cd".to_vec(), 64, now),
+            Ok(None)
+        );
+        assert_eq!(
+            assembly.add("peer", chunk(0, 3), b"ab".to_vec(), 64, now),
+            Ok(None)
+        );
+        assert_eq!(
+            assembly.add("peer", chunk(2, 3), b"e".to_vec(), 64, now),
+            Ok(Some(b"abcde".to_vec()))
+        );
+
+        assert_eq!(
+            assembly.add("peer", chunk(0, 2), b"abc".to_vec(), 4, now),
+            Ok(None)
+        );
+        assert!(assembly
+            .add("peer", chunk(1, 2), b"de".to_vec(), 4, now)
+            .is_err());
+        assert!(assembly.partial.is_empty());
+
+        assert_eq!(
+            assembly.add("peer", chunk(0, 2), b"ab".to_vec(), 64, now),
+            Ok(None)
+        );
+        let later = now + Duration::from_secs(601);
+        assert_eq!(
+            assembly.add("other", chunk(0, 2), b"ab".to_vec(), 64, later),
+            Ok(None)
+        );
+        assert_eq!(assembly.partial.len(), 1);
+    }
+}
@@ -0,0 +1,122 @@
+﻿use std::collections::BTreeMap;
+
+use retasync_mesh_bridge::CancelOutcome;
+use serde::Serialize;
+use tracing::warn;
+
+use crate::dispatch::Dispatch;
+use crate::AppState;
+
+pub const CANCELLED_STATUS: &str = "cancelled";
+
+// Message ids of the chunks each running transfer has handed to the bridge. A transfer's entry
+// exists while its worker runs; cancelling takes it, which tells the worker to stop.
+pub type OutstandingChunks = BTreeMap<String, Vec<String>>;
+
+// What the mesh said about one envelope it was asked to drop.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MeshRecall {
+    pub message_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub outcome: Option<CancelOutcome>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+pub(crate) async fn is_cancelled(state: &AppState, job_id: &str) -> anyhow::Result<bool> {
+    let job = state.storage.get_job(job_id).await?;
+    Ok(job.is_some_and(|job| job.status == CANCELLED_STATUS))
+}
+
+pub(crate) async fn recall_message(state: &AppState, message_id: &str) -> MeshRecall {
+    let (outcome, error) = match state.bridge.cancel_message(message_id).await {
+        Ok(outcome) => (Some(outcome), None),
+        Err(err) => {
+            warn!(message_id = %message_id, error = %err, "mesh cancellation failed");
+            (None, Some(err.to_string()))
+        }
+    };
+    MeshRecall {
+        message_id: message_id.to_string(),
+        outcome,
+        error,
+    }
+}
+
+// Asks the mesh to drop every envelope sent for a cancelled job. The newest send is the one
+// still travelling, so its outcome is the one kept on the job's dispatch.
+pub(crate) async fn recall_job(
+    state: &AppState,
+    job_id: &str,
+) -> anyhow::Result<Option<MeshRecall>> {
+    let mut message_ids = state.storage.list_job_messages(job_id).await?;
+    message_ids.dedup();
+    let mut newest = None;
+    for message_id in &message_ids {
+        newest = Some(recall_message(state, message_id).await);
+    }
+    let Some(outcome) = newest.as_ref().and_then(|recall| recall.outcome) else {
+        return Ok(newest);
+    };
+    if let Some(dispatch_json) = state
+        .storage
+        .get_job(job_id)
+        .await?
+        .and_then(|job| job.dispatch_json)
+    {
+        let mut dispatch: Dispatch = serde_json::from_str(&dispatch_json)?;
+        dispatch.mesh_cancel_outcome = Some(outcome);
+        state
+            .storage
+            .set_job_dispatch(job_id, &serde_json::to_value(&dispatch)?)
+            .await?;
+    }
+    Ok(newest)
+}
+
+pub(crate) fn open_chunks(state: &AppState, transfer_id: &str) {
+    lock(state).insert(transfer_id.to_string(), Vec::new());
+}
+
+// False once the transfer was cancelled; the caller then recalls the chunk itself.
+pub(crate) fn track_chunk(state: &AppState, transfer_id: &str, message_id: String) -> bool {
+    match lock(state).get_mut(transfer_id) {
+        Some(chunks) => {
+            chunks.push(message_id);
+            true
+        }
+        None => false,
+    }
+}
+
+pub(crate) fn chunks_open(state: &AppState, transfer_id: &str) -> bool {
+    lock(state).contains_key(transfer_id)
+}
+
+// How many chunks a cancellation taking the entry now would recall.
+#[cfg(feature = "transfers")]
+pub(crate) fn outstanding_chunk_count(state: &AppState, transfer_id: &str) -> usize {
+    lock(state).get(transfer_id).map_or(0, Vec::len)
+}
+
+// False when a cancellation took the entry first.
+pub(crate) fn close_chunks(state: &AppState, transfer_id: &str) -> bool {
+    lock(state).remove(transfer_id).is_some()
+}
+
+#[cfg(feature = "transfers")]
+pub(crate) async fn recall_chunks(state: &AppState, transfer_id: &str) -> Vec<MeshRecall> {
+    let chunks = lock(state).remove(transfer_id).unwrap_or_default();
+    let mut recalls = Vec::with_capacity(chunks.len());
+    for message_id in &chunks {
+        recalls.push(recall_message(state, message_id).await);
+    }
+    recalls
+}
+
+fn lock(state: &AppState) -> std::sync::MutexGuard<'_, OutstandingChunks> {
+    state
+        .outstanding_chunks
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
@@ -0,0 +1,177 @@
+﻿use std::collections::BTreeMap;
+
+use retasync_contract::{
+    ChannelStyle, CodecLimits, Compression, ContractRegistry, CONTENT_TYPE_MSGPACK,
+    DELIVERY_RECEIPT_EVENT, RECEIPT_FORMAT_VERSION,
+};
+use retasync_transfer::DEFAULT_CHUNK_SIZE;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use crate::coalescing::{
+    COALESCED_EVENTS, NOTIFICATION_SUBSCRIBER, SSE_SUBSCRIBER, WEBHOOK_SUBSCRIBER,
+};
+use crate::health::{AGGREGATE_RESOLUTION_SECS, AGGREGATE_RETENTION_SECS, RAW_RETENTION_SECS};
+use crate::NodeConfig;
+
+pub const API_VERSION: &str = "v1";
+
+// What this node supports right now, built from the running config rather than declared.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Capabilities {
+    pub api_version: String,
+    pub contracts: Vec<ContractVersion>,
+    pub content_types: Vec<String>,
+    pub features: BTreeMap<String, Value>,
+    pub rate_limits: RateLimits,
+    pub limits: PayloadLimits,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContractVersion {
+    pub asyncapi: Option<String>,
+    pub version: Option<String>,
+    // Older nodes don't send it and only know the generic layout.
+    #[serde(default)]
+    pub channel_style: ChannelStyle,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RateLimits {
+    pub inbound_per_minute: u32,
+    pub inbound_queue_capacity: usize,
+    pub source_overrides: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PayloadLimits {
+    pub codec: CodecLimits,
+    pub transfer_chunk_bytes: usize,
+    pub max_link_bytes: usize,
+    pub max_lxmf_bytes: usize,
+    pub max_attachment_bytes: Option<usize>,
+    pub max_job_attachment_bytes: Option<usize>,
+}
+
+impl Capabilities {
+    pub fn digest(&self) -> String {
+        let serialized = serde_json::to_vec(self).unwrap_or_default();
+        format!("{:x}", Sha256::digest(serialized))
+    }
+}
+
+pub fn node_capabilities(
+    config: &NodeConfig,
+    contract: &ContractRegistry,
+    simulation: bool,
+) -> Capabilities {
+    let attachments = (config.attachments.enabled && cfg!(feature = "transfers"))
+        .then_some(&config.attachments);
+
+    let mut features = BTreeMap::new();
+    features.insert(
+        "compression".to_string(),
+        json!({
+            "encodings": Compression::ALL,
+            "threshold_bytes": config.compression_threshold_bytes,
+        }),
+    );
+    features.insert(
+        "results.streaming".to_string(),
+        json!({ "idle_timeout_secs": config.result_stream_timeout_secs }),
+    );
+    #[cfg(feature = "transfers")]
+    features.insert(
+        "transfers.upload".to_string(),
+        json!({
+            "chunk_bytes": DEFAULT_CHUNK_SIZE,
+            "stall_after_secs": config.transfer_stall_after_secs,
+        }),
+    );
+    if let Some(attachments) = attachments {
+        features.insert(
+            "commands.attachments".to_string(),
+            json!({
+                "max_attachment_bytes": attachments.max_attachment_bytes,
+                "max_job_bytes": attachments.max_job_bytes,
+                "dispatch": attachments.dispatch,
+            }),
+        );
+    }
+    features.insert(
+        "operations.deprecation".to_string(),
+        json!({
+            "deprecated": contract.deprecations().count(),
+            "sunset_override": config.allow_sunset_operations,
+        }),
+    );
+    features.insert(
+        "notifications".to_string(),
+        json!({
+            "event_types": config.notifications.event_types,
+            "retention_hours": config.notifications.retention_hours,
+        }),
+    );
+    // Only push delivery collapses events; the feed and the notification inbox keep every one.
+    features.insert(
+        "events.coalescing".to_string(),
+        json!({
+            "window_ms": config.event_coalescing.window_ms,
+            "event_types": COALESCED_EVENTS.iter().map(|(event, _)| event).collect::<Vec<_>>(),
+            "never_coalesced": "terminal statuses and every other event type",
+            "subscribers": [SSE_SUBSCRIBER, NOTIFICATION_SUBSCRIBER, WEBHOOK_SUBSCRIBER],
+            "pulled_feeds": "never coalesced",
+        }),
+    );
+    features.insert(
+        "receipts.delivery".to_string(),
+        json!({
+            "sends": config.receipts.enabled,
+            "event": DELIVERY_RECEIPT_EVENT,
+            "format_version": RECEIPT_FORMAT_VERSION,
+        }),
+    );
+    features.insert(
+        "health.history".to_string(),
+        json!({
+            "raw_retention_secs": RAW_RETENTION_SECS,
+            "aggregate_resolution_secs": AGGREGATE_RESOLUTION_SECS,
+            "aggregate_retention_secs": AGGREGATE_RETENTION_SECS,
+        }),
+    );
+    if simulation {
+        features.insert("admin.simulation".to_string(), json!({ "enabled": true }));
+    }
+
+    let mut content_types = vec![CONTENT_TYPE_MSGPACK.to_string()];
+    content_types.extend(
+        Compression::ALL
+            .iter()
+            .map(|compression| compression.content_type().to_string()),
+    );
+
+    Capabilities {
+        api_version: API_VERSION.to_string(),
+        contracts: vec![ContractVersion {
+            asyncapi: contract.asyncapi_version().map(str::to_string),
+            version: contract.version().map(str::to_string),
+            channel_style: contract.channel_style(),
+        }],
+        content_types,
+        features,
+        rate_limits: RateLimits {
+            inbound_per_minute: config.inbound.rate_limit_per_minute,
+            inbound_queue_capacity: config.inbound.capacity,
+            source_overrides: config.inbound.source_rate_limits.len(),
+        },
+        limits: PayloadLimits {
+            codec: config.codec_limits,
+            transfer_chunk_bytes: DEFAULT_CHUNK_SIZE,
+            max_link_bytes: config.max_link_bytes,
+            max_lxmf_bytes: config.max_lxmf_bytes,
+            max_attachment_bytes: attachments.map(|settings| settings.max_attachment_bytes),
+            max_job_attachment_bytes: attachments.map(|settings| settings.max_job_bytes),
+        },
+    }
+}
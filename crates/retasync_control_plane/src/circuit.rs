@@ -0,0 +1,488 @@
+﻿use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use retasync_storage::FEED_JOB_EVENT;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::task::JoinHandle;
+use tracing::error;
+
+use crate::app::{emit, publish, redeliver_command_job, write_log};
+use crate::dispatch::Dispatch;
+use crate::error::{RetryAdvice, RetryScope, RETRY_ADVICE_FIELD};
+use crate::AppState;
+
+pub const CIRCUIT_CHANGED_EVENT: &str = "delivery.circuit.changed";
+pub const CIRCUIT_OPEN_REASON: &str = "circuit_open";
+pub const DEFERRED_STATUS: &str = "deferred";
+const DEFERRED_SCAN_LIMIT: i64 = 1000;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitPolicy {
+    // Fail jobs for an open circuit at once with `circuit_open`.
+    #[default]
+    FailFast,
+    // Park them as `deferred` until the circuit lets traffic through again.
+    Defer,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CircuitBreakerSettings {
+    // Consecutive dispatch failures that open a circuit; 0 turns the breaker off.
+    pub failure_threshold: u32,
+    // How long a circuit stays open before one probe job is let through.
+    pub cooldown_secs: u64,
+    pub policy: CircuitPolicy,
+}
+
+impl Default for CircuitBreakerSettings {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            cooldown_secs: 30,
+            policy: CircuitPolicy::FailFast,
+        }
+    }
+}
+
+impl CircuitBreakerSettings {
+    fn cooldown(&self) -> chrono::Duration {
+        chrono::Duration::seconds(self.cooldown_secs.min(i64::MAX as u64) as i64)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+impl CircuitState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CircuitState::Closed => "closed",
+            CircuitState::Open => "open",
+            CircuitState::HalfOpen => "half_open",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CircuitView {
+    pub destination_identity: String,
+    pub operation: String,
+    pub state: CircuitState,
+    pub consecutive_failures: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub opened_at: Option<DateTime<Utc>>,
+    // When the next probe may go out; unset while closed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub probe_job_id: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Admission {
+    Allowed,
+    // The one job let through a half-open circuit; its outcome closes or re-opens it.
+    Probe,
+    Rejected { retry_at: DateTime<Utc> },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transition {
+    pub destination_identity: String,
+    pub operation: String,
+    pub from: CircuitState,
+    pub to: CircuitState,
+    pub consecutive_failures: u32,
+}
+
+#[derive(Debug, Clone)]
+struct Breaker {
+    state: CircuitState,
+    consecutive_failures: u32,
+    opened_at: Option<DateTime<Utc>>,
+    // A probe that has not reported back within a cool-down is presumed lost, so another job
+    // may take its place.
+    probe: Option<(String, DateTime<Utc>)>,
+}
+
+impl Default for Breaker {
+    fn default() -> Self {
+        Self {
+            state: CircuitState::Closed,
+            consecutive_failures: 0,
+            opened_at: None,
+            probe: None,
+        }
+    }
+}
+
+impl Breaker {
+    fn retry_at(&self, cooldown: chrono::Duration) -> Option<DateTime<Utc>> {
+        match (self.state, &self.probe) {
+            (CircuitState::Closed, _) => None,
+            (CircuitState::HalfOpen, Some((_, started))) => Some(*started + cooldown),
+            _ => self.opened_at.map(|opened_at| opened_at + cooldown),
+        }
+    }
+}
+
+type CircuitKey = (String, String);
+
+// Breakers per (destination, operation), kept in memory only: a restart starts every circuit
+// closed, so the first failures after it open them again.
+#[derive(Debug, Default)]
+pub struct CircuitBreakers {
+    breakers: Mutex<BTreeMap<CircuitKey, Breaker>>,
+}
+
+impl CircuitBreakers {
+    pub fn admit(
+        &self,
+        destination: &str,
+        operation: &str,
+        job_id: &str,
+        settings: &CircuitBreakerSettings,
+        now: DateTime<Utc>,
+    ) -> (Admission, Option<Transition>) {
+        if settings.failure_threshold == 0 {
+            return (Admission::Allowed, None);
+        }
+        let mut breakers = self.lock();
+        let Some(breaker) = breakers.get_mut(&key(destination, operation)) else {
+            return (Admission::Allowed, None);
+        };
+        let cooldown = settings.cooldown();
+        match breaker.retry_at(cooldown) {
+            None => (Admission::Allowed, None),
+            Some(retry_at) if now < retry_at => (Admission::Rejected { retry_at }, None),
+            Some(_) => {
+                let from = breaker.state;
+                breaker.state = CircuitState::HalfOpen;
+                breaker.probe = Some((job_id.to_string(), now));
+                let transition = (from != CircuitState::HalfOpen).then(|| Transition {
+                    destination_identity: destination.to_string(),
+                    operation: operation.to_string(),
+                    from,
+                    to: CircuitState::HalfOpen,
+                    consecutive_failures: breaker.consecutive_failures,
+                });
+                (Admission::Probe, transition)
+            }
+        }
+    }
+
+    // A success closes the circuit whatever state it is in; a failure of the probe, or the
+    // threshold-th in a row, opens it.
+    pub fn record(
+        &self,
+        destination: &str,
+        operation: &str,
+        succeeded: bool,
+        settings: &CircuitBreakerSettings,
+        now: DateTime<Utc>,
+    ) -> Option<Transition> {
+        if settings.failure_threshold == 0 {
+            return None;
+        }
+        let mut breakers = self.lock();
+        let key = key(destination, operation);
+        let transition = |from, to, consecutive_failures| Transition {
+            destination_identity: destination.to_string(),
+            operation: operation.to_string(),
+            from,
+            to,
+            consecutive_failures,
+        };
+        if succeeded {
+            let breaker = breakers.remove(&key)?;
+            return (breaker.state != CircuitState::Closed).then(|| {
+                transition(breaker.state, CircuitState::Closed, breaker.consecutive_failures)
+            });
+        }
+        let breaker = breakers.entry(key).or_default();
+        breaker.consecutive_failures = breaker.consecutive_failures.saturating_add(1);
+        let opens = match breaker.state {
+            CircuitState::Closed => breaker.consecutive_failures >= settings.failure_threshold,
+            CircuitState::HalfOpen => true,
+            CircuitState::Open => false,
+        };
+        if !opens {
+            return None;
+        }
+        let from = breaker.state;
+        breaker.state = CircuitState::Open;
+        breaker.opened_at = Some(now);
+        breaker.probe = None;
+        Some(transition(from, CircuitState::Open, breaker.consecutive_failures))
+    }
+
+    // Open circuits whose cool-down has passed, so a deferred job can go out as the probe.
+    pub fn due_for_probe(
+        &self,
+        settings: &CircuitBreakerSettings,
+        now: DateTime<Utc>,
+    ) -> Vec<(String, String)> {
+        let cooldown = settings.cooldown();
+        self.lock()
+            .iter()
+            .filter(|(_, breaker)| breaker.state != CircuitState::Closed)
+            .filter(|(_, breaker)| breaker.retry_at(cooldown).is_some_and(|at| at <= now))
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+
+    pub fn views(&self, settings: &CircuitBreakerSettings) -> Vec<CircuitView> {
+        let cooldown = settings.cooldown();
+        self.lock()
+            .iter()
+            .map(|((destination, operation), breaker)| CircuitView {
+                destination_identity: destination.clone(),
+                operation: operation.clone(),
+                state: breaker.state,
+                consecutive_failures: breaker.consecutive_failures,
+                opened_at: breaker.opened_at,
+                retry_at: breaker.retry_at(cooldown),
+                probe_job_id: breaker.probe.as_ref().map(|(job_id, _)| job_id.clone()),
+            })
+            .collect()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BTreeMap<CircuitKey, Breaker>> {
+        self.breakers
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn key(destination: &str, operation: &str) -> CircuitKey {
+    (destination.to_string(), operation.to_string())
+}
+
+pub async fn circuit_views(state: &AppState) -> Vec<CircuitView> {
+    let settings = state.node_config.read().await.delivery.circuit_breaker.clone();
+    state.circuits.views(&settings)
+}
+
+pub async fn admit_job(
+    state: &AppState,
+    job_id: &str,
+    destination: &str,
+    operation: &str,
+) -> Admission {
+    let settings = state.node_config.read().await.delivery.circuit_breaker.clone();
+    let (admission, transition) =
+        state.circuits.admit(destination, operation, job_id, &settings, Utc::now());
+    if let Some(transition) = transition {
+        announce(state, &transition).await;
+    }
+    admission
+}
+
+// Only failures that point at the path to the peer count; a payload the bridge rejects says
+// nothing about the destination, and a missing daemon fails every destination alike.
+// Takes the error variant an attempt was logged with.
+pub fn counts_against_circuit(variant: &str) -> bool {
+    matches!(variant, "send_failed" | "peer_unreachable")
+}
+
+// Fed by the attempt log with the attempt that ended a dispatch, once store-and-forward
+// escalation has had its turn, so only a job that could not be handed over at all counts
+// against the circuit.
+pub async fn record_dispatch(state: &AppState, destination: &str, operation: &str, ok: bool) {
+    let settings = state.node_config.read().await.delivery.circuit_breaker.clone();
+    let Some(transition) = state.circuits.record(destination, operation, ok, &settings, Utc::now())
+    else {
+        return;
+    };
+    announce(state, &transition).await;
+    if transition.to == CircuitState::Closed {
+        if let Err(err) = release_deferred(state, Some((destination, operation)), None).await {
+            write_log(state, "error", &format!("releasing deferred jobs failed: {err:#}")).await;
+        }
+    }
+}
+
+// Fails the job straight away or parks it, as the policy says. The job has not touched the
+// bridge, so it keeps its whole retry budget for later.
+pub async fn refuse_job(
+    state: &AppState,
+    job_id: &str,
+    retry_at: DateTime<Utc>,
+) -> anyhow::Result<()> {
+    let policy = state.node_config.read().await.delivery.circuit_breaker.policy;
+    let (status, message) = match policy {
+        CircuitPolicy::FailFast => ("failed", format!("job {job_id} failed: circuit open")),
+        CircuitPolicy::Defer => (DEFERRED_STATUS, format!("job {job_id} deferred: circuit open")),
+    };
+    // Subscribers learn when the breaker's cool-down ends without polling the job.
+    let advice = RetryAdvice::until(CIRCUIT_OPEN_REASON, RetryScope::Circuit, retry_at, Utc::now());
+    let storage = state.storage.with_event(
+        FEED_JOB_EVENT,
+        json!({
+            "job_id": job_id,
+            "status": status,
+            "reason": CIRCUIT_OPEN_REASON,
+            "detail": { "retry_at": retry_at },
+            RETRY_ADVICE_FIELD: advice,
+        }),
+    );
+    match policy {
+        CircuitPolicy::FailFast => {
+            storage.fail_job(job_id, CIRCUIT_OPEN_REASON).await?;
+        }
+        CircuitPolicy::Defer => {
+            storage
+                .update_job_status(job_id, DEFERRED_STATUS, Some(CIRCUIT_OPEN_REASON))
+                .await?;
+        }
+    }
+    publish(state).await;
+    write_log(state, "warn", &message).await;
+    Ok(())
+}
+
+// Requeues deferred jobs, oldest first: all of them, or those for one circuit, up to `limit`.
+pub async fn release_deferred(
+    state: &AppState,
+    circuit: Option<(&str, &str)>,
+    limit: Option<usize>,
+) -> anyhow::Result<usize> {
+    let jobs = state
+        .storage
+        .list_jobs_with_status(DEFERRED_STATUS, DEFERRED_SCAN_LIMIT)
+        .await?;
+    let mut released = 0;
+    for job in jobs {
+        if limit.is_some_and(|limit| released >= limit) {
+            break;
+        }
+        let Some(dispatch) = job.dispatch_json.as_deref() else {
+            continue;
+        };
+        let dispatch: Dispatch = serde_json::from_str(dispatch)?;
+        let matches = circuit.is_none_or(|(destination, operation)| {
+            dispatch.destination_identity == destination && job.operation == operation
+        });
+        if !matches {
+            continue;
+        }
+        let requeued = json!({ "job_id": job.job_id, "status": "queued", "released": true });
+        if !state
+            .storage
+            .with_event(FEED_JOB_EVENT, requeued)
+            .requeue_deferred_job(&job.job_id)
+            .await?
+        {
+            continue;
+        }
+        publish(state).await;
+        let payload: Value = serde_json::from_str(&job.payload_json)?;
+        redeliver_command_job(state, job.job_id, job.operation, payload, dispatch);
+        released += 1;
+    }
+    Ok(released)
+}
+
+// Sends one deferred job through each circuit whose cool-down has passed.
+pub async fn probe_deferred(state: &AppState, now: DateTime<Utc>) -> anyhow::Result<usize> {
+    let settings = state.node_config.read().await.delivery.circuit_breaker.clone();
+    let mut released = 0;
+    for (destination, operation) in state.circuits.due_for_probe(&settings, now) {
+        released += release_deferred(state, Some((&destination, &operation)), Some(1)).await?;
+    }
+    Ok(released)
+}
+
+pub fn spawn_circuit_probes(state: AppState, period: Duration) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(period);
+        loop {
+            ticker.tick().await;
+            if let Err(err) = probe_deferred(&state, Utc::now()).await {
+                error!(error = %err, "circuit probe release failed");
+            }
+        }
+    })
+}
+
+async fn announce(state: &AppState, transition: &Transition) {
+    let message = format!(
+        "circuit {} {} {} -> {} after {} consecutive failures",
+        transition.destination_identity,
+        transition.operation,
+        transition.from.as_str(),
+        transition.to.as_str(),
+        transition.consecutive_failures,
+    );
+    let level = if transition.to == CircuitState::Closed { "info" } else { "warn" };
+    write_log(state, level, &message).await;
+    emit(
+        state,
+        CIRCUIT_CHANGED_EVENT,
+        json!({
+            "destination_identity": transition.destination_identity,
+            "operation": transition.operation,
+            "from": transition.from,
+            "state": transition.to,
+            "consecutive_failures": transition.consecutive_failures,
+        }),
+    )
+    .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> CircuitBreakerSettings {
+        CircuitBreakerSettings {
+            failure_threshold: 2,
+            cooldown_secs: 30,
+            policy: CircuitPolicy::FailFast,
+        }
+    }
+
+    #[test]
+    fn half_open_circuit_lets_one_probe_through() {
+        let (breakers, settings, now) = (CircuitBreakers::default(), settings(), Utc::now());
+        assert!(breakers.record("hub", "event.create", false, &settings, now).is_none());
+        let opened = breakers.record("hub", "event.create", false, &settings, now).unwrap();
+        assert_eq!((opened.from, opened.to), (CircuitState::Closed, CircuitState::Open));
+
+        let retry_at = now + chrono::Duration::seconds(30);
+        let early = breakers.admit("hub", "event.create", "a", &settings, now);
+        assert_eq!(early, (Admission::Rejected { retry_at }, None));
+        let (probe, half_open) = breakers.admit("hub", "event.create", "b", &settings, retry_at);
+        assert_eq!(probe, Admission::Probe);
+        assert_eq!(half_open.unwrap().to, CircuitState::HalfOpen);
+        let (behind, _) = breakers.admit("hub", "event.create", "c", &settings, retry_at);
+        assert!(matches!(behind, Admission::Rejected { .. }));
+        // A probe that never reports back gives way after another cool-down.
+        let later = retry_at + chrono::Duration::seconds(30);
+        let (replacement, none) = breakers.admit("hub", "event.create", "d", &settings, later);
+        assert_eq!((replacement, none), (Admission::Probe, None));
+
+        let reopened = breakers.record("hub", "event.create", false, &settings, later).unwrap();
+        assert_eq!(reopened.from, CircuitState::HalfOpen);
+        assert_eq!(breakers.due_for_probe(&settings, later), []);
+        let due = later + chrono::Duration::seconds(30);
+        assert_eq!(
+            breakers.due_for_probe(&settings, due),
+            [("hub".to_string(), "event.create".to_string())]
+        );
+        breakers.admit("hub", "event.create", "e", &settings, due);
+        let closed = breakers.record("hub", "event.create", true, &settings, due).unwrap();
+        assert_eq!((closed.from, closed.to), (CircuitState::HalfOpen, CircuitState::Closed));
+        assert!(breakers.views(&settings).is_empty());
+    }
+}
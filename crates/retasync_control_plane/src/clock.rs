@@ -0,0 +1,164 @@
+﻿use chrono::{DateTime, Utc};
+use retasync_contract::IdentityHash;
+use retasync_mesh_bridge::{ClockEstimate, ClockSample, ClockSampleSource};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::{info, warn};
+
+use crate::app::emit;
+use crate::features::CLOCK_SKEW_TOLERANCE_FLAG;
+use crate::AppState;
+
+pub const PEER_CLOCK_DRIFT_EVENT: &str = "peer.clock_drift";
+pub const DEFAULT_DRIFT_THRESHOLD_MS: u64 = 5_000;
+pub const DEFAULT_EVENT_TRANSIT_MS: u64 = 2_000;
+pub const DEFAULT_MAX_SKEW_TOLERANCE_SECS: u64 = 300;
+// An estimate from fewer samples is shown but neither reported nor trusted.
+pub const MIN_CLOCK_SAMPLES: u64 = 5;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClockSettings {
+    // How far a peer's estimated offset may stray before `peer.clock_drift` is emitted.
+    pub drift_threshold_ms: u64,
+    // Transit assumed for envelopes with no round trip to measure, such as mesh events.
+    pub event_transit_ms: u64,
+    // Most a drifting peer's envelope age allowance is widened by under the
+    // `clock_skew_tolerance` flag.
+    pub max_skew_tolerance_secs: u64,
+}
+
+impl Default for ClockSettings {
+    fn default() -> Self {
+        Self {
+            drift_threshold_ms: DEFAULT_DRIFT_THRESHOLD_MS,
+            event_transit_ms: DEFAULT_EVENT_TRANSIT_MS,
+            max_skew_tolerance_secs: DEFAULT_MAX_SKEW_TOLERANCE_SECS,
+        }
+    }
+}
+
+// A result to one of our commands: the peer stamped it about half a round trip before it got
+// back to us.
+pub async fn observe_result(
+    state: &AppState,
+    peer: &str,
+    sent_at: DateTime<Utc>,
+    dispatched_at: DateTime<Utc>,
+    received_at: DateTime<Utc>,
+) {
+    let transit_ms = ((received_at - dispatched_at).num_milliseconds() / 2).max(0);
+    observe(
+        state,
+        peer,
+        sent_at,
+        received_at,
+        transit_ms,
+        ClockSampleSource::Result,
+    )
+    .await;
+}
+
+pub async fn observe_event(
+    state: &AppState,
+    peer: &str,
+    sent_at: DateTime<Utc>,
+    received_at: DateTime<Utc>,
+) {
+    let transit_ms = state.node_config.read().await.clock.event_transit_ms;
+    let transit_ms = i64::try_from(transit_ms).unwrap_or(i64::MAX);
+    observe(
+        state,
+        peer,
+        sent_at,
+        received_at,
+        transit_ms,
+        ClockSampleSource::Event,
+    )
+    .await;
+}
+
+async fn observe(
+    state: &AppState,
+    peer: &str,
+    sent_at: DateTime<Utc>,
+    received_at: DateTime<Utc>,
+    transit_ms: i64,
+    source: ClockSampleSource,
+) {
+    let offset_ms = (sent_at - received_at)
+        .num_milliseconds()
+        .saturating_add(transit_ms);
+    let estimate = state.peers.record_clock_sample(
+        &IdentityHash::lenient(peer),
+        ClockSample {
+            observed_at: received_at,
+            offset_ms,
+            transit_ms,
+            source,
+        },
+    );
+    if estimate.samples < MIN_CLOCK_SAMPLES {
+        return;
+    }
+    let threshold_ms = state.node_config.read().await.clock.drift_threshold_ms;
+    let threshold_ms = i64::try_from(threshold_ms).unwrap_or(i64::MAX);
+    let drift = estimate.offset_ms.saturating_abs();
+    // Cleared only once well back inside the threshold, so an estimate hovering at the line
+    // is reported once.
+    if drift > threshold_ms && state.peers.mark_clock_drift(peer, true) {
+        warn!(
+            peer,
+            offset_ms = estimate.offset_ms,
+            threshold_ms,
+            "peer clock drifting"
+        );
+        emit(
+            state,
+            PEER_CLOCK_DRIFT_EVENT,
+            json!({
+                "identity_hash": peer,
+                "offset_ms": estimate.offset_ms,
+                "spread_ms": estimate.spread_ms,
+                "samples": estimate.samples,
+                "threshold_ms": threshold_ms,
+            }),
+        )
+        .await;
+    } else if drift <= threshold_ms / 4 * 3 && state.peers.mark_clock_drift(peer, false) {
+        info!(
+            peer,
+            offset_ms = estimate.offset_ms,
+            "peer clock back within threshold"
+        );
+    }
+}
+
+// Extra envelope age accepted from `peer`: how far its clock runs behind ours, when it is known
+// to drift and the `clock_skew_tolerance` flag is on, capped by `max_skew_tolerance_secs`.
+pub async fn skew_allowance_secs(state: &AppState, estimate: Option<&ClockEstimate>) -> u64 {
+    let Some(estimate) = estimate else {
+        return 0;
+    };
+    if !estimate.drifting
+        || estimate.offset_ms >= 0
+        || !state.features.is_enabled(CLOCK_SKEW_TOLERANCE_FLAG)
+    {
+        return 0;
+    }
+    let behind_secs = estimate.offset_ms.unsigned_abs().div_ceil(1000);
+    behind_secs.min(state.node_config.read().await.clock.max_skew_tolerance_secs)
+}
+
+// How long seen message ids must be kept: while skew tolerance is on, a drifting peer's envelope
+// can be accepted for up to `max_skew_tolerance_secs` past the usual age limit.
+pub async fn seen_message_horizon_secs(state: &AppState) -> u64 {
+    let config = state.node_config.read().await;
+    match config.inbound.max_envelope_age_secs {
+        0 => 0,
+        limit if state.features.is_enabled(CLOCK_SKEW_TOLERANCE_FLAG) => {
+            limit.saturating_add(config.clock.max_skew_tolerance_secs)
+        }
+        limit => limit,
+    }
+}
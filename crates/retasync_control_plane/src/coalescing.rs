@@ -0,0 +1,413 @@
+﻿use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::{Stream, StreamExt};
+use retasync_storage::{CanonicalTimestamp, NotificationRecord, WebhookDelivery, FEED_JOB_EVENT};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::app::SseUpdate;
+use crate::dependencies::is_terminal;
+
+pub const TRANSFER_PROGRESS_EVENT: &str = "transfer.progress";
+// Kinds of push subscriber, as `GET /v1/events/subscribers` lists them.
+pub const SSE_SUBSCRIBER: &str = "sse";
+pub const NOTIFICATION_SUBSCRIBER: &str = "notifications";
+pub const WEBHOOK_SUBSCRIBER: &str = "webhook";
+
+// Event types push delivery may collapse to the latest per subject, with the field naming the
+// subject. Every other type, security events included, is always delivered, and so is any of
+// these reporting a terminal status.
+pub const COALESCED_EVENTS: &[(&str, &str)] = &[
+    (FEED_JOB_EVENT, "job_id"),
+    (TRANSFER_PROGRESS_EVENT, "transfer_id"),
+];
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EventCoalescingSettings {
+    // How long a push subscriber holds events so that those for one subject collapse to the
+    // latest; 0 delivers every event as it is published. The event feed is never collapsed.
+    pub window_ms: u64,
+}
+
+// The subject an event may be collapsed under, or None when it must always be delivered.
+pub fn coalescing_subject(event_type: &str, data: &Value) -> Option<String> {
+    let (_, field) = COALESCED_EVENTS
+        .iter()
+        .find(|(coalesced, _)| *coalesced == event_type)?;
+    if data.get("status").and_then(Value::as_str).is_some_and(is_terminal) {
+        return None;
+    }
+    let subject = data.get(*field).and_then(Value::as_str)?;
+    Some(format!("{event_type}/{subject}"))
+}
+
+// What a window needs of the events it holds.
+pub trait Coalesce {
+    fn coalesce_subject(&self) -> Option<String>;
+}
+
+impl Coalesce for SseUpdate {
+    fn coalesce_subject(&self) -> Option<String> {
+        self.coalesce_subject.clone()
+    }
+}
+
+impl Coalesce for NotificationRecord {
+    fn coalesce_subject(&self) -> Option<String> {
+        coalescing_subject(&self.event_type, &self.data)
+    }
+}
+
+// A webhook body carries the event's data under `payload`.
+impl Coalesce for WebhookDelivery {
+    fn coalesce_subject(&self) -> Option<String> {
+        let body: Value = serde_json::from_str(&self.body_json).ok()?;
+        coalescing_subject(&self.event_name, body.get("payload")?)
+    }
+}
+
+// An event a push stream delivers, in the order of its `seq`.
+pub trait PushEvent: Coalesce + Clone + Send + 'static {
+    fn seq(&self) -> u64;
+}
+
+impl PushEvent for SseUpdate {
+    fn seq(&self) -> u64 {
+        self.id
+    }
+}
+
+impl PushEvent for NotificationRecord {
+    fn seq(&self) -> u64 {
+        self.seq.max(0) as u64
+    }
+}
+
+// Events held for one window. An event with a subject replaces the one held for that subject
+// and moves to the end, so each subject's states still arrive in the order they happened.
+#[derive(Debug)]
+pub struct CoalescingWindow<T = SseUpdate> {
+    held: Vec<(Option<String>, T)>,
+    collapsed: Vec<T>,
+}
+
+impl<T> Default for CoalescingWindow<T> {
+    fn default() -> Self {
+        Self {
+            held: Vec::new(),
+            collapsed: Vec::new(),
+        }
+    }
+}
+
+impl<T: Coalesce> CoalescingWindow<T> {
+    pub fn push(&mut self, event: T) {
+        let subject = event.coalesce_subject();
+        if subject.is_some() {
+            let superseded = self.held.iter().position(|(held, _)| *held == subject);
+            if let Some(index) = superseded {
+                self.collapsed.push(self.held.remove(index).1);
+            }
+        }
+        self.held.push((subject, event));
+    }
+
+    // The events to deliver, in order, and how many were collapsed away.
+    pub fn take(&mut self) -> (Vec<T>, u64) {
+        let (delivered, collapsed) = self.drain();
+        (delivered, collapsed.len() as u64)
+    }
+
+    // The events to deliver, in order, and the ones collapsed away.
+    pub fn drain(&mut self) -> (Vec<T>, Vec<T>) {
+        let held = std::mem::take(&mut self.held);
+        (
+            held.into_iter().map(|(_, event)| event).collect(),
+            std::mem::take(&mut self.collapsed),
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SubscriberView {
+    pub subscriber_id: u64,
+    pub kind: String,
+    pub connected_at: String,
+    pub window_ms: u64,
+    pub delivered: u64,
+    pub collapsed: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CoalescingMetrics {
+    pub subscribers: usize,
+    // Events collapsed for every push subscriber since the node started, gone ones included.
+    pub collapsed: u64,
+}
+
+#[derive(Debug, Default)]
+struct SubscriberCounts {
+    delivered: AtomicU64,
+    collapsed: AtomicU64,
+}
+
+impl SubscriberCounts {
+    fn note(&self, delivered: usize, collapsed: u64) {
+        self.delivered
+            .fetch_add(delivered as u64, Ordering::Relaxed);
+        self.collapsed.fetch_add(collapsed, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug)]
+struct Subscriber {
+    kind: &'static str,
+    connected_at: String,
+    window_ms: u64,
+    counts: Arc<SubscriberCounts>,
+}
+
+// Push subscribers connected right now, with what was delivered to and collapsed for each.
+#[derive(Debug, Default)]
+pub struct EventSubscribers {
+    next_id: AtomicU64,
+    live: Mutex<BTreeMap<u64, Subscriber>>,
+    collapsed: AtomicU64,
+    // Subscriber ids of the enabled webhook subscriptions, by subscription id.
+    webhooks: Mutex<BTreeMap<String, u64>>,
+}
+
+impl EventSubscribers {
+    pub fn register(self: &Arc<Self>, kind: &'static str, window_ms: u64) -> SubscriberHandle {
+        let (subscriber_id, counts) = self.add(kind, window_ms);
+        SubscriberHandle {
+            subscriber_id,
+            registry: self.clone(),
+            counts,
+        }
+    }
+
+    fn add(&self, kind: &'static str, window_ms: u64) -> (u64, Arc<SubscriberCounts>) {
+        let subscriber_id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let counts = Arc::new(SubscriberCounts::default());
+        let subscriber = Subscriber {
+            kind,
+            connected_at: CanonicalTimestamp::now().to_string(),
+            window_ms,
+            counts: counts.clone(),
+        };
+        self.live
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .insert(subscriber_id, subscriber);
+        (subscriber_id, counts)
+    }
+
+    fn remove(&self, subscriber_id: u64) {
+        self.live
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .remove(&subscriber_id);
+    }
+
+    // Lists exactly the webhook subscriptions in `enabled`, which outlive any connection, each
+    // under the window it is delivered with.
+    pub fn sync_webhooks(&self, enabled: &[String], window_ms: u64) {
+        let mut webhooks = self
+            .webhooks
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let gone: Vec<String> = webhooks
+            .keys()
+            .filter(|subscription_id| !enabled.contains(subscription_id))
+            .cloned()
+            .collect();
+        for subscription_id in gone {
+            if let Some(subscriber_id) = webhooks.remove(&subscription_id) {
+                self.remove(subscriber_id);
+            }
+        }
+        for subscription_id in enabled {
+            if !webhooks.contains_key(subscription_id) {
+                let (subscriber_id, _) = self.add(WEBHOOK_SUBSCRIBER, window_ms);
+                webhooks.insert(subscription_id.clone(), subscriber_id);
+            }
+        }
+        let mut live = self
+            .live
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        for subscriber_id in webhooks.values() {
+            if let Some(subscriber) = live.get_mut(subscriber_id) {
+                subscriber.window_ms = window_ms;
+            }
+        }
+    }
+
+    // Counts what one pass delivered to and collapsed for a webhook subscription.
+    pub fn note_webhook(&self, subscription_id: &str, delivered: usize, collapsed: u64) {
+        let subscriber_id = self
+            .webhooks
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .get(subscription_id)
+            .copied();
+        let counts = subscriber_id.and_then(|subscriber_id| {
+            self.live
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .get(&subscriber_id)
+                .map(|subscriber| subscriber.counts.clone())
+        });
+        if let Some(counts) = counts {
+            counts.note(delivered, collapsed);
+        }
+        self.collapsed.fetch_add(collapsed, Ordering::Relaxed);
+    }
+
+    pub fn list(&self) -> Vec<SubscriberView> {
+        self.live
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .iter()
+            .map(|(subscriber_id, subscriber)| SubscriberView {
+                subscriber_id: *subscriber_id,
+                kind: subscriber.kind.to_string(),
+                connected_at: subscriber.connected_at.clone(),
+                window_ms: subscriber.window_ms,
+                delivered: subscriber.counts.delivered.load(Ordering::Relaxed),
+                collapsed: subscriber.counts.collapsed.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+
+    pub fn metrics(&self) -> CoalescingMetrics {
+        CoalescingMetrics {
+            subscribers: self
+                .live
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .len(),
+            collapsed: self.collapsed.load(Ordering::Relaxed),
+        }
+    }
+}
+
+// Keeps a subscriber listed until its stream is dropped.
+#[derive(Debug)]
+pub struct SubscriberHandle {
+    subscriber_id: u64,
+    registry: Arc<EventSubscribers>,
+    counts: Arc<SubscriberCounts>,
+}
+
+impl SubscriberHandle {
+    fn note(&self, delivered: usize, collapsed: u64) {
+        self.counts.note(delivered, collapsed);
+        self.registry.collapsed.fetch_add(collapsed, Ordering::Relaxed);
+    }
+}
+
+impl Drop for SubscriberHandle {
+    fn drop(&mut self) {
+        self.registry.remove(self.subscriber_id);
+    }
+}
+
+// The live updates after `after` for one push subscriber. With a window, the first update
+// opens it and everything published before it closes is collapsed and delivered together.
+pub fn push_updates<T: PushEvent>(
+    receiver: broadcast::Receiver<T>,
+    after: u64,
+    window: Duration,
+    handle: SubscriberHandle,
+) -> impl Stream<Item = T> {
+    futures::stream::unfold((receiver, handle), move |(mut receiver, handle)| async move {
+        let mut held = CoalescingWindow::default();
+        held.push(next_update(&mut receiver, after).await?);
+        if !window.is_zero() {
+            let closes = tokio::time::Instant::now() + window;
+            while let Ok(Some(update)) =
+                tokio::time::timeout_at(closes, next_update(&mut receiver, after)).await
+            {
+                held.push(update);
+            }
+        }
+        let (updates, collapsed) = held.take();
+        handle.note(updates.len(), collapsed);
+        Some((updates, (receiver, handle)))
+    })
+    .flat_map(futures::stream::iter)
+}
+
+// A subscriber that falls behind the bus skips what it missed, as it always has.
+async fn next_update<T: PushEvent>(
+    receiver: &mut broadcast::Receiver<T>,
+    after: u64,
+) -> Option<T> {
+    loop {
+        match receiver.recv().await {
+            Ok(update) if update.seq() > after => return Some(update),
+            Ok(_) | Err(RecvError::Lagged(_)) => continue,
+            Err(RecvError::Closed) => return None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::RecentEvents;
+    use serde_json::json;
+
+    fn job_status(recent: &mut RecentEvents, job_id: &str, status: &str) -> SseUpdate {
+        recent.push(FEED_JOB_EVENT, json!({ "job_id": job_id, "status": status }))
+    }
+
+    #[test]
+    fn terminal_and_unlisted_events_are_never_collapsed() {
+        let job = |status: &str| json!({ "job_id": "j-1", "status": status });
+        assert_eq!(
+            coalescing_subject(FEED_JOB_EVENT, &job("dispatched")).as_deref(),
+            Some("job.status.changed/j-1")
+        );
+        for status in ["success", "partial_failure", "failed", "cancelled"] {
+            assert_eq!(coalescing_subject(FEED_JOB_EVENT, &job(status)), None);
+        }
+        assert_eq!(coalescing_subject("security.replay_detected", &job("running")), None);
+        assert_eq!(coalescing_subject(FEED_JOB_EVENT, &json!({ "status": "running" })), None);
+
+        let mut recent = RecentEvents::default();
+        let mut window = CoalescingWindow::default();
+        for (job_id, status) in [("j-1", "queued"), ("j-2", "queued"), ("j-1", "dispatched")] {
+            window.push(job_status(&mut recent, job_id, status));
+        }
+        window.push(recent.push(
+            TRANSFER_PROGRESS_EVENT,
+            json!({ "transfer_id": "j-1", "status": "running" }),
+        ));
+        window.push(job_status(&mut recent, "j-1", "success"));
+        let (updates, collapsed) = window.take();
+        let delivered: Vec<_> = updates
+            .iter()
+            .map(|update| (update.event_type.as_str(), update.data["status"].as_str().unwrap()))
+            .collect();
+        assert_eq!(
+            delivered,
+            [
+                (FEED_JOB_EVENT, "queued"),
+                (FEED_JOB_EVENT, "dispatched"),
+                (TRANSFER_PROGRESS_EVENT, "running"),
+                (FEED_JOB_EVENT, "success"),
+            ]
+        );
+        assert_eq!(updates[0].data["job_id"], "j-2");
+        assert_eq!(collapsed, 1);
+        assert!(window.take().0.is_empty());
+    }
+}
@@ -0,0 +1,379 @@
+﻿use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use thiserror::Error;
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+use crate::app::{install_node_config, publish, write_log};
+use crate::dispatch::validate_dispatch_config;
+use crate::{AppState, NodeConfig};
+
+pub const CONFIG_STAGED_EVENT: &str = "node.config.staged";
+pub const CONFIG_APPLIED_EVENT: &str = "node.config.applied";
+pub const CONFIG_CONFIRMED_EVENT: &str = "node.config.confirmed";
+pub const CONFIG_REVERTED_EVENT: &str = "node.config.reverted";
+pub const STAGED_APPLY_REASON: &str = "staged_apply";
+pub const UNCONFIRMED_APPLY_REASON: &str = "unconfirmed_apply";
+pub const DEFAULT_CONFIRM_TIMEOUT_SECS: u64 = 120;
+
+// NodeConfig fields only read at startup. A change to one would not take effect until a
+// restart, and a revert could not undo it, so the staged flow refuses them.
+const RESTART_REQUIRED_FIELDS: [&str; 12] = [
+    "/rpc_endpoint",
+    "/http_bind",
+    "/sqlite_path",
+    "/prefer_link",
+    "/job_watchdog/lease_interval_ms",
+    "/transfer_dedup/enabled",
+    "/transfer_spool/orphan_grace_secs",
+    "/submission_spool/path",
+    "/submission_spool/drain_interval_ms",
+    "/crash_reports/dir",
+    "/crash_reports/max_files",
+    "/payload_migrations/on_startup",
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigApplySettings {
+    // How long an applied config waits for `POST /v1/node/config/confirm` before it reverts.
+    pub confirm_timeout_secs: u64,
+}
+
+impl Default for ConfigApplySettings {
+    fn default() -> Self {
+        Self {
+            confirm_timeout_secs: DEFAULT_CONFIRM_TIMEOUT_SECS,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApplyPhase {
+    Staged,
+    // Installed and waiting for confirmation; reverts to `previous` at `confirm_by`.
+    Applied,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingConfig {
+    pub phase: ApplyPhase,
+    pub candidate: NodeConfig,
+    pub staged_at: DateTime<Utc>,
+    pub staged_by: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous: Option<NodeConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub applied_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub applied_by: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub confirm_by: Option<DateTime<Utc>>,
+}
+
+impl PendingConfig {
+    fn awaits_confirmation(&self) -> bool {
+        self.phase == ApplyPhase::Applied
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ConfigApplyStatus {
+    pub phase: Option<ApplyPhase>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub staged_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub staged_by: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub applied_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub applied_by: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub confirm_by: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remaining_secs: Option<i64>,
+}
+
+#[derive(Debug, Error)]
+pub enum ConfigApplyError {
+    #[error("{0}")]
+    Invalid(String),
+    #[error("changing {} takes a restart", .0.join(", "))]
+    RestartRequired(Vec<String>),
+    #[error("an applied config is waiting for confirmation")]
+    AwaitingConfirmation,
+    #[error("no config is staged")]
+    NothingStaged,
+    #[error("no applied config is waiting for confirmation")]
+    NothingToConfirm,
+    #[error(transparent)]
+    Internal(#[from] anyhow::Error),
+}
+
+impl ConfigApplyError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ConfigApplyError::Invalid(_) => "invalid_node_config",
+            ConfigApplyError::RestartRequired(_) => "restart_required",
+            ConfigApplyError::AwaitingConfirmation => "config_apply_pending",
+            ConfigApplyError::NothingStaged => "no_staged_config",
+            ConfigApplyError::NothingToConfirm => "no_config_apply_pending",
+            ConfigApplyError::Internal(_) => "internal_error",
+        }
+    }
+}
+
+impl From<retasync_storage::StorageError> for ConfigApplyError {
+    fn from(err: retasync_storage::StorageError) -> Self {
+        ConfigApplyError::Internal(err.into())
+    }
+}
+
+// JSON pointers of the restart-only fields `candidate` changes.
+pub fn restart_required_changes(current: &NodeConfig, candidate: &NodeConfig) -> Vec<String> {
+    let (Ok(current), Ok(candidate)) = (to_value(current), to_value(candidate)) else {
+        return Vec::new();
+    };
+    RESTART_REQUIRED_FIELDS
+        .iter()
+        .filter(|pointer| current.pointer(pointer) != candidate.pointer(pointer))
+        .map(|pointer| pointer.to_string())
+        .collect()
+}
+
+pub async fn config_apply_status(state: &AppState, now: DateTime<Utc>) -> ConfigApplyStatus {
+    status(state.pending_config.lock().await.as_ref(), now)
+}
+
+// A plain `PUT /v1/node/config` would be lost to the revert, so it waits for the confirmation.
+pub async fn awaiting_confirmation(state: &AppState) -> bool {
+    state
+        .pending_config
+        .lock()
+        .await
+        .as_ref()
+        .is_some_and(PendingConfig::awaits_confirmation)
+}
+
+// Validates and stores `candidate` without touching the active config; a later stage replaces
+// an earlier one.
+pub async fn stage(
+    state: &AppState,
+    candidate: NodeConfig,
+    staged_by: String,
+    now: DateTime<Utc>,
+) -> Result<ConfigApplyStatus, ConfigApplyError> {
+    let mut pending = state.pending_config.lock().await;
+    if pending.as_ref().is_some_and(PendingConfig::awaits_confirmation) {
+        return Err(ConfigApplyError::AwaitingConfirmation);
+    }
+    validate_dispatch_config(&candidate).map_err(ConfigApplyError::Invalid)?;
+    let changes = restart_required_changes(&*state.node_config.read().await, &candidate);
+    if !changes.is_empty() {
+        return Err(ConfigApplyError::RestartRequired(changes));
+    }
+    let staged = PendingConfig {
+        phase: ApplyPhase::Staged,
+        candidate,
+        staged_at: now,
+        staged_by,
+        previous: None,
+        applied_at: None,
+        applied_by: None,
+        confirm_by: None,
+    };
+    state
+        .storage
+        .with_event(
+            CONFIG_STAGED_EVENT,
+            json!({ "staged_by": staged.staged_by, "staged_at": staged.staged_at }),
+        )
+        .set_node_config_apply(Some(&to_value(&staged)?))
+        .await?;
+    let message = format!("node config staged by {}", staged.staged_by);
+    write_log(state, "info", &message).await;
+    publish(state).await;
+    *pending = Some(staged);
+    Ok(status(pending.as_ref(), now))
+}
+
+// Installs the staged config and starts the confirmation timer. The stored state is written
+// with the revision before the config is installed, so a crash from here on reverts at startup.
+pub async fn apply(
+    state: &AppState,
+    applied_by: String,
+    now: DateTime<Utc>,
+) -> Result<ConfigApplyStatus, ConfigApplyError> {
+    let mut pending = state.pending_config.lock().await;
+    let mut applied = match pending.as_ref() {
+        None => return Err(ConfigApplyError::NothingStaged),
+        Some(pending) if pending.awaits_confirmation() => {
+            return Err(ConfigApplyError::AwaitingConfirmation)
+        }
+        Some(pending) => pending.clone(),
+    };
+    let current = state.node_config.read().await.clone();
+    let changes = restart_required_changes(&current, &applied.candidate);
+    if !changes.is_empty() {
+        return Err(ConfigApplyError::RestartRequired(changes));
+    }
+    let timeout = chrono::Duration::seconds(current.config_apply.confirm_timeout_secs as i64);
+    applied.phase = ApplyPhase::Applied;
+    applied.previous = Some(current);
+    applied.applied_at = Some(now);
+    applied.applied_by = Some(applied_by);
+    applied.confirm_by = Some(now + timeout);
+    let applied_by = applied.applied_by.as_deref().unwrap_or_default();
+    state
+        .storage
+        .with_event(
+            CONFIG_APPLIED_EVENT,
+            json!({ "applied_by": applied_by, "confirm_by": applied.confirm_by }),
+        )
+        .record_node_config_apply(
+            &to_value(&applied.candidate)?.to_string(),
+            STAGED_APPLY_REASON,
+            Some(&to_value(&applied)?),
+        )
+        .await?;
+    install_node_config(state, applied.candidate.clone()).await;
+
+    let message = format!(
+        "node config applied by {applied_by}; reverts at {} unless confirmed",
+        applied.confirm_by.map(|at| at.to_rfc3339()).unwrap_or_default(),
+    );
+    write_log(state, "warn", &message).await;
+    publish(state).await;
+    *pending = Some(applied);
+    Ok(status(pending.as_ref(), now))
+}
+
+// Keeps the applied config. A confirmation that arrives after the deadline finds the revert
+// already done, even when the expiry task has not run yet.
+pub async fn confirm(
+    state: &AppState,
+    confirmed_by: String,
+    now: DateTime<Utc>,
+) -> Result<ConfigApplyStatus, ConfigApplyError> {
+    let mut pending = state.pending_config.lock().await;
+    let Some(applied) = pending.take_if(|pending| pending.awaits_confirmation()) else {
+        return Err(ConfigApplyError::NothingToConfirm);
+    };
+    if applied.confirm_by.is_some_and(|confirm_by| confirm_by <= now) {
+        revert(state, applied).await?;
+        return Err(ConfigApplyError::NothingToConfirm);
+    }
+    let confirmed = json!({ "confirmed_by": confirmed_by, "applied_by": applied.applied_by });
+    let cleared = state
+        .storage
+        .with_event(CONFIG_CONFIRMED_EVENT, confirmed)
+        .set_node_config_apply(None)
+        .await;
+    if let Err(err) = cleared {
+        *pending = Some(applied);
+        return Err(err.into());
+    }
+    write_log(state, "info", &format!("node config confirmed by {confirmed_by}")).await;
+    publish(state).await;
+    Ok(status(None, now))
+}
+
+pub async fn expire_config_apply(state: &AppState, now: DateTime<Utc>) -> anyhow::Result<bool> {
+    let mut pending = state.pending_config.lock().await;
+    let Some(applied) = pending.take_if(|pending| {
+        pending.awaits_confirmation()
+            && pending.confirm_by.is_some_and(|confirm_by| confirm_by <= now)
+    }) else {
+        return Ok(false);
+    };
+    if let Err(err) = revert(state, applied.clone()).await {
+        *pending = Some(applied);
+        return Err(err);
+    }
+    Ok(true)
+}
+
+// Restores the stored staged config at startup. An apply the previous run never saw confirmed
+// is reverted straight away: the window it was given ended with that run.
+pub async fn load(state: &AppState) -> anyhow::Result<()> {
+    let Some(stored) = state.storage.node_config_apply().await? else {
+        return Ok(());
+    };
+    let stored: PendingConfig = match serde_json::from_value(stored) {
+        Ok(stored) => stored,
+        Err(err) => {
+            warn!(error = %err, "ignoring unreadable stored config apply");
+            return Ok(state.storage.set_node_config_apply(None).await?);
+        }
+    };
+    if stored.awaits_confirmation() {
+        warn!("node restarted during an unconfirmed config apply; reverting");
+        return revert(state, stored).await;
+    }
+    info!(staged_by = stored.staged_by, "restored staged node config");
+    *state.pending_config.lock().await = Some(stored);
+    Ok(())
+}
+
+pub fn spawn_config_apply_expiry(state: AppState, period: Duration) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(period);
+        loop {
+            ticker.tick().await;
+            if let Err(err) = expire_config_apply(&state, Utc::now()).await {
+                error!(error = %err, "config apply expiry check failed");
+            }
+        }
+    })
+}
+
+async fn revert(state: &AppState, applied: PendingConfig) -> anyhow::Result<()> {
+    let previous = applied
+        .previous
+        .ok_or_else(|| anyhow::anyhow!("applied config has no previous config to revert to"))?;
+    let applied_by = applied.applied_by.unwrap_or_default();
+    let reverted = json!({
+        "reason": UNCONFIRMED_APPLY_REASON,
+        "applied_by": applied_by,
+        "confirm_by": applied.confirm_by,
+    });
+    state
+        .storage
+        .with_event(CONFIG_REVERTED_EVENT, reverted)
+        .record_node_config_apply(
+            &serde_json::to_string(&previous)?,
+            UNCONFIRMED_APPLY_REASON,
+            None,
+        )
+        .await?;
+    install_node_config(state, previous).await;
+    let message = format!("node config applied by {applied_by} was not confirmed; reverted");
+    write_log(state, "warn", &message).await;
+    publish(state).await;
+    Ok(())
+}
+
+fn status(pending: Option<&PendingConfig>, now: DateTime<Utc>) -> ConfigApplyStatus {
+    let Some(pending) = pending else {
+        return ConfigApplyStatus::default();
+    };
+    ConfigApplyStatus {
+        phase: Some(pending.phase),
+        staged_at: Some(pending.staged_at),
+        staged_by: Some(pending.staged_by.clone()),
+        applied_at: pending.applied_at,
+        applied_by: pending.applied_by.clone(),
+        confirm_by: pending.confirm_by,
+        remaining_secs: pending
+            .confirm_by
+            .map(|confirm_by| (confirm_by - now).num_seconds().max(0)),
+    }
+}
+
+fn to_value<T: Serialize>(value: &T) -> anyhow::Result<Value> {
+    Ok(serde_json::to_value(value)?)
+}
@@ -0,0 +1,1208 @@
+﻿use std::net::SocketAddr;
+
+use retasync_contract::{CodecLimits, DEFAULT_COMPRESSION_THRESHOLD};
+use retasync_mesh_bridge::{
+    BRIDGE_LAYER_NAMES, DEFAULT_PING_INTERVAL_SECS, DEFAULT_POOL_SIZE, DEFAULT_REQUEST_TIMEOUT_SECS,
+};
+use retasync_storage::DEFAULT_READ_POOL_SIZE;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use crate::aggregates::AggregateSettings;
+use crate::archive::RetentionSettings;
+use crate::attachments::AttachmentSettings;
+use crate::bundles::BundleSettings;
+use crate::bootstrap::DEFAULT_BOOTSTRAP_TOKEN_TTL_SECS;
+use crate::clock::ClockSettings;
+use crate::config_apply::ConfigApplySettings;
+use crate::consistency::ConsistencySettings;
+use crate::crash::CrashReportSettings;
+use crate::dedup::TransferDedupSettings;
+use crate::delivery::DeliverySettings;
+use crate::dependencies::DependencySettings;
+use crate::dispatch::{is_identity_hash, is_operation_pattern};
+use crate::feed::EventFeedSettings;
+use crate::files::{is_media_type_pattern, FileSettings};
+use crate::cursor::PaginationSettings;
+use crate::fleet::FleetSettings;
+use crate::inbound::InboundSettings;
+use crate::leases::JobWatchdogSettings;
+use crate::liveness::LivenessSettings;
+use crate::migrations::PayloadMigrationSettings;
+use crate::profiles::profile_names;
+use crate::result_cache::ResultCacheSettings;
+use crate::sealing::SecuritySettings;
+use crate::sizing::{DEFAULT_MAX_LINK_BYTES, DEFAULT_MAX_LXMF_BYTES};
+use crate::sneakernet::SneakernetSettings;
+use crate::spool::TransferSpoolSettings;
+use crate::submission_spool::SubmissionSpoolSettings;
+use crate::submissions::SubmissionSettings;
+use crate::webhooks::WebhookSettings;
+use crate::trace::RoutingSettings;
+use crate::{
+    NotificationSettings, DEFAULT_RESULT_STREAM_TIMEOUT_SECS, DEFAULT_TRANSFER_STALL_AFTER_SECS,
+};
+
+// Marks fields that `PUT /v1/node/config` applies without a restart.
+pub const HOT_RELOAD_KEYWORD: &str = "x-hot-reload";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FieldError {
+    pub pointer: String,
+    pub message: String,
+}
+
+// JSON Schema (2020-12 subset) for node.toml. Every field carries its type, its default where
+// it has one, and whether it is hot-reloadable.
+pub fn runtime_config_schema() -> Value {
+    let codec = CodecLimits::default();
+    let notifications = NotificationSettings::default();
+    let inbound = InboundSettings::default();
+    let attachments = AttachmentSettings::default();
+    let retention = RetentionSettings::default();
+    let submissions = SubmissionSettings::default();
+    let delivery = DeliverySettings::default();
+    let aggregates = AggregateSettings::default();
+    let liveness = LivenessSettings::default();
+    let job_watchdog = JobWatchdogSettings::default();
+    let transfer_dedup = TransferDedupSettings::default();
+    let transfer_bundles = BundleSettings::default();
+    let routing = RoutingSettings::default();
+    let dependencies = DependencySettings::default();
+    let event_feed = EventFeedSettings::default();
+    let consistency = ConsistencySettings::default();
+    let clock = ClockSettings::default();
+    let payload_migrations = PayloadMigrationSettings::default();
+    let files = FileSettings::default();
+    let transfer_spool = TransferSpoolSettings::default();
+    let submission_spool = SubmissionSpoolSettings::default();
+    let sneakernet = SneakernetSettings::default();
+    let crash_reports = CrashReportSettings::default();
+    let config_apply = ConfigApplySettings::default();
+    let fleet = FleetSettings::default();
+    let pagination = PaginationSettings::default();
+    let webhooks = WebhookSettings::default();
+    let result_cache = ResultCacheSettings::default();
+    let security = SecuritySettings::default();
+    let tls = section(
+        "HTTPS listener; a client CA turns on mutual TLS",
+        &["cert_path", "key_path"],
+        vec![
+            ("cert_path", string(None, false)),
+            ("key_path", string(None, false)),
+            ("client_ca_path", string(None, false)),
+            (
+                "principals",
+                field(
+                    json!({
+                        "type": "object",
+                        "description": "Client certificate subject or SAN mapped to a role",
+                        "additionalProperties": { "type": "string" },
+                    }),
+                    None,
+                    true,
+                ),
+            ),
+        ],
+    );
+
+    let mut schema = section(
+        "retasyncd node.toml",
+        &["rpc", "http", "storage", "acl", "transport"],
+        vec![
+            ("profile", one_of(&profile_names(), None, false)),
+            (
+                "rpc",
+                section(
+                    "Daemon RPC endpoint: tcp://, sim:// or replay://",
+                    &["endpoint"],
+                    vec![
+                        ("endpoint", string(Some("tcp://127.0.0.1:31337"), false)),
+                        ("pool_size", integer(Some(DEFAULT_POOL_SIZE as u64), false)),
+                        (
+                            "request_timeout_secs",
+                            integer(Some(DEFAULT_REQUEST_TIMEOUT_SECS), false),
+                        ),
+                        ("ping_interval_secs", integer(Some(DEFAULT_PING_INTERVAL_SECS), false)),
+                    ],
+                ),
+            ),
+            (
+                "http",
+                section(
+                    "HTTP listener and bearer tokens",
+                    &["bind"],
+                    vec![
+                        (
+                            "bind",
+                            with_format(string(Some("127.0.0.1:8080"), false), "socket-address"),
+                        ),
+                        ("auth_token", string(None, true)),
+                        (
+                            "api_tokens",
+                            field(
+                                json!({
+                                    "type": "array",
+                                    "items": section(
+                                        "Labelled API token",
+                                        &["label", "token"],
+                                        vec![
+                                            ("label", string(None, true)),
+                                            ("token", string(None, true)),
+                                        ],
+                                    ),
+                                }),
+                                Some(json!([])),
+                                true,
+                            ),
+                        ),
+                        ("tls", tls.clone()),
+                        (
+                            "listeners",
+                            field(
+                                json!({
+                                    "type": "array",
+                                    "items": section(
+                                        "Extra listener: a TCP address or unix:/path socket",
+                                        &["bind"],
+                                        vec![
+                                            ("bind", string(None, false)),
+                                            ("name", string(None, false)),
+                                            ("auth", one_of(&["bearer", "none"], None, false)),
+                                            ("tls", tls),
+                                            ("socket_mode", string(Some("0660"), false)),
+                                            ("socket_owner", string(None, false)),
+                                            ("socket_group", string(None, false)),
+                                        ],
+                                    ),
+                                }),
+                                Some(json!([])),
+                                false,
+                            ),
+                        ),
+                        ("bootstrap", boolean(Some(false), false)),
+                        (
+                            "bootstrap_token_ttl_secs",
+                            integer(Some(DEFAULT_BOOTSTRAP_TOKEN_TTL_SECS), false),
+                        ),
+                        ("status_page", boolean(Some(false), true)),
+                    ],
+                ),
+            ),
+            (
+                "storage",
+                section(
+                    "SQLite database and optional encryption key",
+                    &["sqlite_path"],
+                    vec![
+                        ("sqlite_path", string(Some("retasync.sqlite"), false)),
+                        ("encryption_key_path", string(None, false)),
+                        (
+                            "read_pool_size",
+                            integer(Some(u64::from(DEFAULT_READ_POOL_SIZE)), false),
+                        ),
+                    ],
+                ),
+            ),
+            (
+                "acl",
+                section(
+                    "Access control",
+                    &["mode"],
+                    vec![
+                        ("mode", string(Some("allowlist"), true)),
+                        (
+                            "identity_validation",
+                            one_of(&["strict", "lenient"], Some("strict"), false),
+                        ),
+                    ],
+                ),
+            ),
+            (
+                "transport",
+                section(
+                    "Transport selection, compression and envelope limits",
+                    &["prefer_link"],
+                    vec![
+                        ("prefer_link", boolean(Some(true), false)),
+                        (
+                            "compression_threshold_bytes",
+                            integer(Some(DEFAULT_COMPRESSION_THRESHOLD as u64), true),
+                        ),
+                        (
+                            "transfer_stall_after_secs",
+                            integer(Some(DEFAULT_TRANSFER_STALL_AFTER_SECS), true),
+                        ),
+                        (
+                            "result_stream_timeout_secs",
+                            integer(Some(DEFAULT_RESULT_STREAM_TIMEOUT_SECS), true),
+                        ),
+                        ("max_link_bytes", integer(Some(DEFAULT_MAX_LINK_BYTES as u64), true)),
+                        ("max_lxmf_bytes", integer(Some(DEFAULT_MAX_LXMF_BYTES as u64), true)),
+                    ],
+                ),
+            ),
+            (
+                "notifications",
+                section(
+                    "Durable notification log",
+                    &[],
+                    vec![
+                        (
+                            "event_types",
+                            field(
+                                json!({ "type": "array", "items": { "type": "string" } }),
+                                Some(json!(notifications.event_types)),
+                                true,
+                            ),
+                        ),
+                        (
+                            "retention_hours",
+                            field(
+                                json!({ "type": "integer" }),
+                                Some(json!(notifications.retention_hours)),
+                                true,
+                            ),
+                        ),
+                        (
+                            "max_unacked",
+                            field(
+                                json!({ "type": "integer" }),
+                                Some(json!(notifications.max_unacked)),
+                                true,
+                            ),
+                        ),
+                    ],
+                ),
+            ),
+            (
+                "inbound",
+                section(
+                    "Inbound command queue and rate limits",
+                    &[],
+                    vec![
+                        ("capacity", integer(Some(inbound.capacity as u64), true)),
+                        (
+                            "rate_limit_per_minute",
+                            integer(Some(u64::from(inbound.rate_limit_per_minute)), true),
+                        ),
+                        (
+                            "source_rate_limits",
+                            field(
+                                json!({
+                                    "type": "object",
+                                    "additionalProperties": { "type": "integer", "minimum": 0 },
+                                }),
+                                Some(json!({})),
+                                true,
+                            ),
+                        ),
+                        (
+                            "max_envelope_age_secs",
+                            integer(Some(inbound.max_envelope_age_secs), true),
+                        ),
+                        ("record_inbound", boolean(Some(inbound.record_inbound), true)),
+                        (
+                            "max_concurrent_per_operation",
+                            integer(Some(inbound.max_concurrent_per_operation as u64), true),
+                        ),
+                    ],
+                ),
+            ),
+            (
+                "codec",
+                section(
+                    "Limits on payloads from peers",
+                    &[],
+                    vec![
+                        ("max_depth", integer(Some(codec.max_depth as u64), true)),
+                        ("max_bytes", integer(Some(codec.max_bytes as u64), true)),
+                        ("max_map_entries", integer(Some(codec.max_map_entries as u64), true)),
+                        ("max_array_len", integer(Some(codec.max_array_len as u64), true)),
+                        ("max_string_len", integer(Some(codec.max_string_len as u64), true)),
+                    ],
+                ),
+            ),
+            (
+                "identity",
+                section(
+                    "Node identity used as the command source",
+                    &[],
+                    vec![
+                        ("source_identity", identity_hash(true)),
+                        ("default_destination", identity_hash(true)),
+                    ],
+                ),
+            ),
+            (
+                "operation_defaults",
+                field(
+                    json!({
+                        "type": "object",
+                        "description": "Per-operation dispatch defaults keyed by operation pattern",
+                        "propertyNames": { "format": "operation-pattern" },
+                        "additionalProperties": section(
+                            "Dispatch defaults for matching operations",
+                            &[],
+                            vec![
+                                ("destination_identity", identity_hash(true)),
+                                ("ttl_ms", integer(None, true)),
+                                ("transport_hint", one_of(&["link", "lxmf"], None, true)),
+                                ("require_recent_contact_s", integer(None, true)),
+                            ],
+                        ),
+                    }),
+                    Some(json!({})),
+                    true,
+                ),
+            ),
+            (
+                "contract",
+                section(
+                    "Contract policy",
+                    &[],
+                    vec![
+                        ("allow_sunset_operations", boolean(Some(false), true)),
+                        ("contracts_dir", string(None, false)),
+                    ],
+                ),
+            ),
+            (
+                "attachments",
+                section(
+                    "Inline command attachments",
+                    &[],
+                    vec![
+                        ("enabled", boolean(Some(attachments.enabled), true)),
+                        (
+                            "max_attachment_bytes",
+                            integer(Some(attachments.max_attachment_bytes as u64), true),
+                        ),
+                        ("max_job_bytes", integer(Some(attachments.max_job_bytes as u64), true)),
+                        (
+                            "dispatch",
+                            one_of(&["after_command", "concurrent"], Some("after_command"), true),
+                        ),
+                    ],
+                ),
+            ),
+            (
+                "health",
+                section(
+                    "Health sampler",
+                    &[],
+                    vec![("sample_interval_secs", integer(Some(30), false))],
+                ),
+            ),
+            (
+                "retention",
+                section(
+                    "Retention and archival of old jobs and transfers",
+                    &[],
+                    vec![
+                        (
+                            "job_retention_hours",
+                            integer(Some(retention.job_retention_hours), true),
+                        ),
+                        (
+                            "cache_retention_hours",
+                            integer(Some(retention.cache_retention_hours), true),
+                        ),
+                        (
+                            "transfer_retention_days",
+                            integer(Some(retention.transfer_retention_days), true),
+                        ),
+                        ("archive_dir", string(None, true)),
+                    ],
+                ),
+            ),
+            (
+                "submissions",
+                section(
+                    "Command submission limits",
+                    &[],
+                    vec![
+                        (
+                            "max_batch_size",
+                            integer(Some(submissions.max_batch_size as u64), true),
+                        ),
+                        (
+                            "rate_per_minute",
+                            integer(Some(u64::from(submissions.rate_per_minute)), true),
+                        ),
+                    ],
+                ),
+            ),
+            (
+                "delivery",
+                section(
+                    "Command delivery defaults for operations the contract leaves unset",
+                    &[],
+                    vec![
+                        (
+                            "max_attempts",
+                            integer(Some(u64::from(delivery.max_attempts)), true),
+                        ),
+                        ("backoff_ms", integer(Some(delivery.backoff_ms), true)),
+                        ("timeout_ms", integer(Some(delivery.timeout_ms), true)),
+                        (
+                            "store_and_forward",
+                            boolean(Some(delivery.store_and_forward), true),
+                        ),
+                        (
+                            "store_and_forward_ttl_ms",
+                            integer(Some(delivery.store_and_forward_ttl_ms), true),
+                        ),
+                        (
+                            "client_max_attempts",
+                            integer(Some(u64::from(delivery.client_max_attempts)), true),
+                        ),
+                        (
+                            "client_max_backoff_ms",
+                            integer(Some(delivery.client_max_backoff_ms), true),
+                        ),
+                        (
+                            "circuit_breaker",
+                            section(
+                                "Per destination and operation breaker for failing dispatch",
+                                &[],
+                                vec![
+                                    (
+                                        "failure_threshold",
+                                        integer(
+                                            Some(u64::from(
+                                                delivery.circuit_breaker.failure_threshold,
+                                            )),
+                                            true,
+                                        ),
+                                    ),
+                                    (
+                                        "cooldown_secs",
+                                        integer(Some(delivery.circuit_breaker.cooldown_secs), true),
+                                    ),
+                                    (
+                                        "policy",
+                                        one_of(&["fail_fast", "defer"], Some("fail_fast"), true),
+                                    ),
+                                ],
+                            ),
+                        ),
+                    ],
+                ),
+            ),
+            (
+                "aggregates",
+                section(
+                    "Dashboard aggregate queries",
+                    &[],
+                    vec![("max_buckets", integer(Some(aggregates.max_buckets), true))],
+                ),
+            ),
+            (
+                "liveness",
+                section(
+                    "Destination checks requested by require_recent_contact_s and ?probe=true",
+                    &[],
+                    vec![
+                        (
+                            "allow_unknown_peers",
+                            boolean(Some(liveness.allow_unknown_peers), true),
+                        ),
+                        ("probe_timeout_ms", integer(Some(liveness.probe_timeout_ms), true)),
+                    ],
+                ),
+            ),
+            (
+                "job_watchdog",
+                section(
+                    "Job leases renewed by workers; expired leases are requeued or failed",
+                    &[],
+                    vec![
+                        ("lease_interval_ms", integer(Some(job_watchdog.lease_interval_ms), false)),
+                        ("lease_grace_ms", integer(Some(job_watchdog.lease_grace_ms), true)),
+                    ],
+                ),
+            ),
+            (
+                "transfer_dedup",
+                section(
+                    "Content-hash offers that skip uploads the destination already holds",
+                    &[],
+                    vec![
+                        // Seeds the `transfer_dedup` feature flag; later changes go through it.
+                        ("enabled", boolean(Some(transfer_dedup.enabled), false)),
+                        (
+                            "offer_timeout_ms",
+                            integer(Some(transfer_dedup.offer_timeout_ms), true),
+                        ),
+                    ],
+                ),
+            ),
+            (
+                "files",
+                section(
+                    "Policy for files received from peers; refused files are quarantined",
+                    &[],
+                    vec![
+                        (
+                            "allowed_media_types",
+                            field(
+                                json!({
+                                    "type": "array",
+                                    "items": { "type": "string", "format": "media-type-pattern" },
+                                }),
+                                Some(json!(files.allowed_media_types)),
+                                true,
+                            ),
+                        ),
+                        (
+                            "max_bytes_per_media_type",
+                            field(
+                                json!({
+                                    "type": "object",
+                                    "description": "Media type pattern to its largest size",
+                                    "propertyNames": { "format": "media-type-pattern" },
+                                    "additionalProperties": { "type": "integer", "minimum": 0 },
+                                }),
+                                None,
+                                true,
+                            ),
+                        ),
+                        ("verify_magic", boolean(Some(files.verify_magic), true)),
+                        ("verify_outbound", boolean(Some(files.verify_outbound), true)),
+                    ],
+                ),
+            ),
+            (
+                "transfer_spool",
+                section(
+                    "Memory limits for base64 uploads; larger uploads are decoded to spool files",
+                    &[],
+                    vec![
+                        (
+                            "max_request_bytes",
+                            integer(Some(transfer_spool.max_request_bytes), true),
+                        ),
+                        (
+                            "memory_threshold_bytes",
+                            integer(Some(transfer_spool.memory_threshold_bytes), true),
+                        ),
+                        (
+                            "max_memory_bytes",
+                            integer(Some(transfer_spool.max_memory_bytes), true),
+                        ),
+                        ("dir", string(None, true)),
+                        (
+                            "orphan_grace_secs",
+                            integer(Some(transfer_spool.orphan_grace_secs), false),
+                        ),
+                    ],
+                ),
+            ),
+            (
+                "submission_spool",
+                section(
+                    "Append-only file holding submissions while storage is unavailable",
+                    &[],
+                    vec![
+                        ("enabled", boolean(Some(submission_spool.enabled), true)),
+                        ("path", string(None, false)),
+                        ("max_bytes", integer(Some(submission_spool.max_bytes), true)),
+                        (
+                            "drain_interval_ms",
+                            integer(Some(submission_spool.drain_interval_ms), false),
+                        ),
+                    ],
+                ),
+            ),
+            (
+                "dispatch_queue",
+                section(
+                    "Command jobs dispatched at once; later jobs wait their turn",
+                    &[],
+                    vec![(
+                        "max_concurrent_jobs",
+                        field(json!({ "type": "integer", "minimum": 1 }), None, true),
+                    )],
+                ),
+            ),
+            (
+                "result_validation",
+                section(
+                    "What happens to results that break their operation's result schema",
+                    &[],
+                    vec![(
+                        "policy",
+                        one_of(&["accept", "flag", "quarantine"], Some("accept"), true),
+                    )],
+                ),
+            ),
+            (
+                "security",
+                section(
+                    "End-to-end sealing of outgoing command payloads for their destination",
+                    &[],
+                    vec![
+                        (
+                            "encrypt_operations",
+                            field(
+                                json!({
+                                    "type": "array",
+                                    "description": "Operations to seal; a trailing * is a prefix",
+                                    "items": { "type": "string" },
+                                }),
+                                Some(json!(security.encrypt_operations)),
+                                true,
+                            ),
+                        ),
+                        ("identity_key_path", string(None, true)),
+                        ("exchange_keys", boolean(Some(security.exchange_keys), true)),
+                    ],
+                ),
+            ),
+            (
+                "sneakernet",
+                section(
+                    "Signed bundles that carry envelopes between disconnected meshes by hand",
+                    &[],
+                    vec![
+                        ("signing_key_path", string(None, true)),
+                        (
+                            "trusted_signers",
+                            field(
+                                json!({
+                                    "type": "object",
+                                    "description": "Identity to base64 Ed25519 public key",
+                                    "additionalProperties": { "type": "string" },
+                                }),
+                                None,
+                                true,
+                            ),
+                        ),
+                        ("max_bundle_bytes", integer(Some(sneakernet.max_bundle_bytes), true)),
+                        ("max_events", integer(Some(sneakernet.max_events as u64), true)),
+                    ],
+                ),
+            ),
+            (
+                "receipts",
+                section(
+                    "Signed delivery receipts sent back for answered commands and received bundles",
+                    &[],
+                    vec![("enabled", boolean(Some(false), true))],
+                ),
+            ),
+            (
+                "crash_reports",
+                section(
+                    "Crash files written by the panic hook and loaded as reports at the next start",
+                    &[],
+                    vec![
+                        ("dir", string(None, false)),
+                        ("max_files", integer(Some(crash_reports.max_files as u64), false)),
+                        ("max_rows", integer(Some(crash_reports.max_rows as u64), true)),
+                    ],
+                ),
+            ),
+            (
+                "config_apply",
+                section(
+                    "Staged config changes that revert unless confirmed in time",
+                    &[],
+                    vec![(
+                        "confirm_timeout_secs",
+                        integer(Some(config_apply.confirm_timeout_secs), true),
+                    )],
+                ),
+            ),
+            (
+                "fleet",
+                section(
+                    "Periodic status reports to a collector node, and collecting them from others",
+                    &[],
+                    vec![
+                        ("collector", identity_hash(true)),
+                        (
+                            "report_interval_secs",
+                            integer(Some(fleet.report_interval_secs), true),
+                        ),
+                        ("collect", boolean(Some(fleet.collect), true)),
+                        (
+                            "max_report_bytes",
+                            integer(Some(fleet.max_report_bytes as u64), true),
+                        ),
+                        ("history", integer(Some(fleet.history as u64), true)),
+                        (
+                            "stale_after_intervals",
+                            integer(Some(u64::from(fleet.stale_after_intervals)), true),
+                        ),
+                    ],
+                ),
+            ),
+            (
+                "pagination",
+                section(
+                    "Signing and expiry of the cursors list endpoints hand out",
+                    &[],
+                    vec![
+                        ("secret_path", string(None, true)),
+                        (
+                            "max_cursor_age_secs",
+                            integer(Some(pagination.max_cursor_age_secs), true),
+                        ),
+                    ],
+                ),
+            ),
+            (
+                "webhooks",
+                section(
+                    "Delivery defaults and auto-disabling of mesh event webhook subscriptions",
+                    &[],
+                    vec![
+                        ("timeout_ms", integer(Some(webhooks.timeout_ms), true)),
+                        (
+                            "max_retries",
+                            integer(Some(u64::from(webhooks.max_retries)), true),
+                        ),
+                        ("backoff_ms", integer(Some(webhooks.backoff_ms), true)),
+                        (
+                            "disable_after_failures",
+                            integer(Some(u64::from(webhooks.disable_after_failures)), true),
+                        ),
+                        ("history", integer(Some(webhooks.history as u64), true)),
+                    ],
+                ),
+            ),
+            (
+                "result_cache",
+                section(
+                    "Local answers to repeated identical list commands; a TTL of 0 turns it off",
+                    &[],
+                    vec![
+                        ("ttl_ms", integer(Some(result_cache.ttl_ms), true)),
+                        ("max_entries", integer(Some(result_cache.max_entries as u64), true)),
+                        (
+                            "max_result_bytes",
+                            integer(Some(result_cache.max_result_bytes as u64), true),
+                        ),
+                        (
+                            "operations",
+                            field(
+                                json!({ "type": "array", "items": { "type": "string" } }),
+                                Some(json!(result_cache.operations)),
+                                true,
+                            ),
+                        ),
+                    ],
+                ),
+            ),
+            (
+                "transfer_bundles",
+                section(
+                    "Multi-file bundle transfers; the byte limit applies to the encoded bundle",
+                    &[],
+                    vec![
+                        (
+                            "max_bundle_bytes",
+                            integer(Some(transfer_bundles.max_bundle_bytes as u64), true),
+                        ),
+                        ("max_files", integer(Some(transfer_bundles.max_files as u64), true)),
+                    ],
+                ),
+            ),
+            (
+                "quotas",
+                section(
+                    "Upload bytes allowed per destination and per token in a rolling 24h window",
+                    &[],
+                    vec![
+                        ("max_bytes_per_destination", integer(None, true)),
+                        ("max_bytes_per_token", integer(None, true)),
+                    ],
+                ),
+            ),
+            (
+                "routing",
+                section(
+                    "Relaying commands addressed to other nodes, and hop traces",
+                    &[],
+                    vec![
+                        (
+                            "relay_destinations",
+                            field(
+                                json!({ "type": "array", "items": { "type": "string" } }),
+                                Some(json!(routing.relay_destinations)),
+                                true,
+                            ),
+                        ),
+                        (
+                            "max_trace_hops",
+                            integer(Some(routing.max_trace_hops as u64), true),
+                        ),
+                    ],
+                ),
+            ),
+            (
+                "dependencies",
+                section(
+                    "Commands that wait for other jobs to succeed before they are sent",
+                    &[],
+                    vec![(
+                        "max_chain_depth",
+                        integer(Some(dependencies.max_chain_depth as u64), true),
+                    )],
+                ),
+            ),
+            (
+                "event_feed",
+                section(
+                    "Sequence-numbered history of every emitted event",
+                    &[],
+                    vec![
+                        ("retention_hours", integer(Some(event_feed.retention_hours), true)),
+                        ("max_page", integer(Some(event_feed.max_page as u64), true)),
+                    ],
+                ),
+            ),
+            (
+                "event_coalescing",
+                section(
+                    "Per-subject collapsing of live status events; the feed is never collapsed",
+                    &[],
+                    vec![("window_ms", integer(Some(0), true))],
+                ),
+            ),
+            (
+                "consistency",
+                section(
+                    "Read-your-writes tokens carried in X-Retasync-Consistency",
+                    &[],
+                    vec![("max_wait_ms", integer(Some(consistency.max_wait_ms), true))],
+                ),
+            ),
+            (
+                "clock",
+                section(
+                    "Peer clock offsets estimated from envelope timestamps",
+                    &[],
+                    vec![
+                        ("drift_threshold_ms", integer(Some(clock.drift_threshold_ms), true)),
+                        ("event_transit_ms", integer(Some(clock.event_transit_ms), true)),
+                        (
+                            "max_skew_tolerance_secs",
+                            integer(Some(clock.max_skew_tolerance_secs), true),
+                        ),
+                    ],
+                ),
+            ),
+            (
+                "payload_migrations",
+                section(
+                    "Migrations that carry stored payloads to the current contract version",
+                    &[],
+                    vec![
+                        (
+                            "on_startup",
+                            boolean(Some(payload_migrations.on_startup), false),
+                        ),
+                        (
+                            "cached_events",
+                            boolean(Some(payload_migrations.cached_events), false),
+                        ),
+                    ],
+                ),
+            ),
+            (
+                "transforms",
+                field(
+                    json!({
+                        "type": "array",
+                        "description": "Payload transforms, applied in order",
+                        "items": section(
+                            "Inject, remove or rename payload fields by JSON pointer",
+                            &["kind"],
+                            vec![
+                                ("name", string(None, true)),
+                                ("kind", one_of(&["inject", "remove", "rename"], None, true)),
+                                ("stage", one_of(&["command", "result"], Some("command"), true)),
+                                (
+                                    "operations",
+                                    field(
+                                        json!({
+                                            "type": "array",
+                                            "items": {
+                                                "type": "string",
+                                                "format": "operation-pattern",
+                                            },
+                                        }),
+                                        Some(json!(["*"])),
+                                        true,
+                                    ),
+                                ),
+                                ("fields", field(json!({ "type": "object" }), None, true)),
+                                (
+                                    "pointers",
+                                    field(
+                                        json!({ "type": "array", "items": { "type": "string" } }),
+                                        None,
+                                        true,
+                                    ),
+                                ),
+                                (
+                                    "renames",
+                                    field(
+                                        json!({
+                                            "type": "object",
+                                            "additionalProperties": { "type": "string" },
+                                        }),
+                                        None,
+                                        true,
+                                    ),
+                                ),
+                            ],
+                        ),
+                    }),
+                    Some(json!([])),
+                    true,
+                ),
+            ),
+            (
+                "bridge",
+                section(
+                    "Middleware wrapped around the bridge transport, outermost first",
+                    &[],
+                    vec![(
+                        "layers",
+                        field(
+                            json!({
+                                "type": "array",
+                                "items": { "type": "string", "enum": BRIDGE_LAYER_NAMES },
+                            }),
+                            Some(json!(["logging"])),
+                            false,
+                        ),
+                    )],
+                ),
+            ),
+            (
+                "debug",
+                section(
+                    "Debugging aids",
+                    &[],
+                    vec![("record_bridge", string(None, false))],
+                ),
+            ),
+        ],
+    );
+    schema["$schema"] = json!("https://json-schema.org/draft/2020-12/schema");
+    schema
+}
+
+// Checks a parsed config document against the schema, reporting each problem at its JSON
+// pointer.
+pub fn validate_config_value(schema: &Value, value: &Value) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+    check(schema, value, "", &mut errors);
+    errors
+}
+
+// A config document holding every schema default.
+pub fn schema_defaults(schema: &Value) -> Option<Value> {
+    if let Some(default) = schema.get("default") {
+        return Some(default.clone());
+    }
+    let properties = schema.get("properties")?.as_object()?;
+    let object: Map<String, Value> = properties
+        .iter()
+        .filter_map(|(key, property)| Some((key.clone(), schema_defaults(property)?)))
+        .collect();
+    // Optional sections whose required fields have no default are left out entirely.
+    let required = schema["required"].as_array().is_some_and(|required| !required.is_empty());
+    if object.is_empty() && required {
+        return None;
+    }
+    Some(Value::Object(object))
+}
+
+fn check(schema: &Value, value: &Value, pointer: &str, errors: &mut Vec<FieldError>) {
+    match schema["type"].as_str() {
+        Some("object") => {
+            let Some(object) = value.as_object() else {
+                return push(errors, pointer, "expected a table".to_string());
+            };
+            for required in schema["required"].as_array().into_iter().flatten() {
+                let key = required.as_str().unwrap_or_default();
+                if !object.contains_key(key) {
+                    push(errors, &child(pointer, key), format!("missing field `{key}`"));
+                }
+            }
+            for (key, item) in object {
+                let item_pointer = child(pointer, key);
+                if let Some(format) = schema["propertyNames"]["format"].as_str() {
+                    if let Some(message) = format_error(format, key) {
+                        push(errors, &item_pointer, format!("key {message}"));
+                    }
+                }
+                match (schema["properties"].get(key), &schema["additionalProperties"]) {
+                    (Some(property), _) => check(property, item, &item_pointer, errors),
+                    (None, Value::Bool(false)) => {
+                        push(errors, &item_pointer, format!("unknown field `{key}`"))
+                    }
+                    (None, additional) if additional.is_object() => {
+                        check(additional, item, &item_pointer, errors)
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Some("array") => {
+            let Some(items) = value.as_array() else {
+                return push(errors, pointer, "expected an array".to_string());
+            };
+            for (index, item) in items.iter().enumerate() {
+                check(&schema["items"], item, &child(pointer, &index.to_string()), errors);
+            }
+        }
+        Some("string") => {
+            let Some(text) = value.as_str() else {
+                return push(errors, pointer, "expected a string".to_string());
+            };
+            if let Some(allowed) = schema["enum"].as_array() {
+                if !allowed.iter().any(|option| option == value) {
+                    let message = format!("expected one of {}", Value::Array(allowed.clone()));
+                    return push(errors, pointer, message);
+                }
+            }
+            if let Some(message) = schema["format"].as_str().and_then(|f| format_error(f, text)) {
+                push(errors, pointer, message);
+            }
+        }
+        Some("integer") => {
+            let minimum = schema["minimum"].as_i64();
+            match value.as_i64() {
+                Some(number) if minimum.is_some_and(|minimum| number < minimum) => {
+                    let message = format!("must be at least {}", minimum.unwrap_or_default());
+                    push(errors, pointer, message)
+                }
+                Some(_) => {}
+                None if value.is_u64() => {}
+                None => push(errors, pointer, "expected an integer".to_string()),
+            }
+        }
+        Some("boolean") if !value.is_boolean() => {
+            push(errors, pointer, "expected a boolean".to_string())
+        }
+        _ => {}
+    }
+}
+
+fn push(errors: &mut Vec<FieldError>, pointer: &str, message: String) {
+    errors.push(FieldError {
+        pointer: pointer.to_string(),
+        message,
+    });
+}
+
+fn format_error(format: &str, text: &str) -> Option<String> {
+    let valid = match format {
+        "socket-address" => text.parse::<SocketAddr>().is_ok(),
+        "identity-hash" => is_identity_hash(text),
+        "operation-pattern" => is_operation_pattern(text),
+        "media-type-pattern" => is_media_type_pattern(text),
+        _ => true,
+    };
+    (!valid).then(|| format!("{text:?} is not a valid {format}"))
+}
+
+fn child(pointer: &str, key: &str) -> String {
+    format!("{pointer}/{}", key.replace('~', "~0").replace('/', "~1"))
+}
+
+fn section(description: &str, required: &[&str], properties: Vec<(&str, Value)>) -> Value {
+    let properties: Map<String, Value> = properties
+        .into_iter()
+        .map(|(key, schema)| (key.to_string(), schema))
+        .collect();
+    json!({
+        "type": "object",
+        "description": description,
+        "additionalProperties": false,
+        "required": required,
+        "properties": properties,
+    })
+}
+
+fn field(mut schema: Value, default: Option<Value>, hot_reload: bool) -> Value {
+    if let Some(default) = default {
+        schema["default"] = default;
+    }
+    schema[HOT_RELOAD_KEYWORD] = json!(hot_reload);
+    schema
+}
+
+fn string(default: Option<&str>, hot_reload: bool) -> Value {
+    field(json!({ "type": "string" }), default.map(|default| json!(default)), hot_reload)
+}
+
+fn integer(default: Option<u64>, hot_reload: bool) -> Value {
+    field(
+        json!({ "type": "integer", "minimum": 0 }),
+        default.map(|default| json!(default)),
+        hot_reload,
+    )
+}
+
+fn boolean(default: Option<bool>, hot_reload: bool) -> Value {
+    field(json!({ "type": "boolean" }), default.map(|default| json!(default)), hot_reload)
+}
+
+fn one_of(options: &[&str], default: Option<&str>, hot_reload: bool) -> Value {
+    field(
+        json!({ "type": "string", "enum": options }),
+        default.map(|default| json!(default)),
+        hot_reload,
+    )
+}
+
+fn identity_hash(hot_reload: bool) -> Value {
+    with_format(string(None, hot_reload), "identity-hash")
+}
+
+fn with_format(mut schema: Value, format: &str) -> Value {
+    schema["format"] = json!(format);
+    schema
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{runtime_config_schema, schema_defaults, validate_config_value, FieldError};
+    use serde_json::json;
+
+    #[test]
+    fn errors_point_at_the_offending_field() {
+        let schema = runtime_config_schema();
+        let mut config = schema_defaults(&schema).unwrap();
+        assert_eq!(validate_config_value(&schema, &config), Vec::new());
+
+        config["transport"]["max_lxmf_bytes"] = json!("64k");
+        config["identity"]["source_identity"] = json!("local");
+        config["operation_defaults"]["telemetry.*"] = json!({ "transport_hint": "radio" });
+        config["operation_defaults"]["*.bad"] = json!({});
+        config["http"].as_object_mut().unwrap().remove("bind");
+
+        let pointers: Vec<String> = validate_config_value(&schema, &config)
+            .into_iter()
+            .map(|FieldError { pointer, .. }| pointer)
+            .collect();
+        assert_eq!(
+            pointers,
+            [
+                "/http/bind",
+                "/identity/source_identity",
+                "/operation_defaults/*.bad",
+                "/operation_defaults/telemetry.*/transport_hint",
+                "/transport/max_lxmf_bytes",
+            ]
+        );
+    }
+}
@@ -0,0 +1,147 @@
+﻿use std::time::Duration;
+
+use axum::{
+    extract::{Query, Request, State},
+    http::{HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use retasync_storage::RetasyncStorage;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::time::Instant;
+use tracing::error;
+
+use crate::error::{refusal_response, RetryAdvice, RetryScope, CONSISTENCY_RETRY_AFTER};
+use crate::AppState;
+
+pub const CONSISTENCY_HEADER: &str = "x-retasync-consistency";
+pub const DEFAULT_CONSISTENCY_MAX_WAIT_MS: u64 = 2000;
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConsistencySettings {
+    // Longest a read waits to catch up to a client's sequence before answering `503`.
+    pub max_wait_ms: u64,
+}
+
+impl Default for ConsistencySettings {
+    fn default() -> Self {
+        Self {
+            max_wait_ms: DEFAULT_CONSISTENCY_MAX_WAIT_MS,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct MinSeqQuery {
+    min_seq: Option<i64>,
+}
+
+// The header wins over `?min_seq=`; a token that doesn't parse is refused rather than ignored,
+// since ignoring it would hand back exactly the stale read the client asked to avoid.
+fn requested_sequence(request: &Request) -> Result<Option<i64>, String> {
+    if let Some(value) = request.headers().get(CONSISTENCY_HEADER) {
+        return value
+            .to_str()
+            .ok()
+            .and_then(|raw| raw.trim().parse().ok())
+            .map(Some)
+            .ok_or_else(|| format!("{CONSISTENCY_HEADER} must be an integer write sequence"));
+    }
+    Query::<MinSeqQuery>::try_from_uri(request.uri())
+        .map(|Query(query)| query.min_seq)
+        .map_err(|_| "min_seq must be an integer write sequence".to_string())
+}
+
+// Polls until the node's applied sequence reaches `requested` or `max_wait` passes, and returns
+// the last sequence seen either way.
+pub async fn wait_for_sequence(
+    storage: &RetasyncStorage,
+    requested: i64,
+    max_wait: Duration,
+) -> anyhow::Result<i64> {
+    let deadline = Instant::now() + max_wait;
+    loop {
+        let applied = storage.write_sequence().await?;
+        let now = Instant::now();
+        if applied >= requested || now >= deadline {
+            return Ok(applied);
+        }
+        tokio::time::sleep(POLL_INTERVAL.min(deadline - now)).await;
+    }
+}
+
+// Mutations answer with the write sequence they left behind; reads carrying a sequence are held
+// until the node has applied it.
+pub async fn enforce_consistency(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if !matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    ) {
+        let mut response = next.run(request).await;
+        match state.storage.write_sequence().await {
+            Ok(seq) => stamp(&mut response, seq),
+            Err(err) => error!(error = %err, "failed to read write sequence"),
+        }
+        return response;
+    }
+
+    let requested = match requested_sequence(&request) {
+        Ok(Some(requested)) => requested,
+        Ok(None) => return next.run(request).await,
+        Err(detail) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({ "error": "invalid_consistency_token", "detail": detail })),
+            )
+                .into_response();
+        }
+    };
+    let max_wait = Duration::from_millis(state.node_config.read().await.consistency.max_wait_ms);
+    let applied = match wait_for_sequence(&state.storage, requested, max_wait).await {
+        Ok(applied) => applied,
+        Err(err) => {
+            error!(error = %err, "failed to read write sequence");
+            return consistency_unavailable(requested, None);
+        }
+    };
+    if applied < requested {
+        return consistency_unavailable(requested, Some(applied));
+    }
+    let mut response = next.run(request).await;
+    stamp(&mut response, applied);
+    response
+}
+
+fn stamp(response: &mut Response, seq: i64) {
+    response
+        .headers_mut()
+        .insert(CONSISTENCY_HEADER, HeaderValue::from(seq));
+}
+
+fn consistency_unavailable(requested: i64, current: Option<i64>) -> Response {
+    let mut response = refusal_response(
+        StatusCode::SERVICE_UNAVAILABLE,
+        json!({
+            "error": "consistency_unavailable",
+            "requested_seq": requested,
+            "current_seq": current,
+        }),
+        RetryAdvice::after(
+            "consistency_unavailable",
+            RetryScope::Node,
+            CONSISTENCY_RETRY_AFTER,
+        ),
+    );
+    if let Some(current) = current {
+        stamp(&mut response, current);
+    }
+    response
+}
@@ -0,0 +1,315 @@
+﻿use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use chrono::{DateTime, Utc};
+use retasync_contract::ContractRegistry;
+use serde::Serialize;
+use serde_yaml::Value as YamlValue;
+use tracing::warn;
+
+pub const CONTRACT_RELOADED_EVENT: &str = "node.contract.reloaded";
+
+// A contract document and the registry read from it; a reload replaces both at once.
+#[derive(Debug)]
+pub struct LoadedContract {
+    pub document: String,
+    pub registry: ContractRegistry,
+}
+
+impl LoadedContract {
+    // An unparseable document is still served, with an empty registry behind it.
+    pub fn lenient(document: String) -> Self {
+        let registry = ContractRegistry::from_yaml(&document).unwrap_or_else(|err| {
+            warn!(error = %err, "contract registry unavailable");
+            ContractRegistry::default()
+        });
+        Self { document, registry }
+    }
+}
+
+// The contract the node serves and validates submissions against. Readers take a snapshot with
+// `current`, so a request sees one contract from start to finish even across a reload.
+#[derive(Debug)]
+pub struct ContractStore {
+    current: RwLock<Arc<LoadedContract>>,
+    // The file `POST /v1/admin/contracts/reload` re-reads; a store without one cannot reload.
+    source: Option<PathBuf>,
+}
+
+impl ContractStore {
+    pub fn new(contract: LoadedContract) -> Self {
+        Self {
+            current: RwLock::new(Arc::new(contract)),
+            source: None,
+        }
+    }
+
+    // A store serving the same contract that reloads from `path`.
+    pub fn sourced_from(&self, path: impl Into<PathBuf>) -> Self {
+        Self {
+            current: RwLock::new(self.current()),
+            source: Some(path.into()),
+        }
+    }
+
+    pub fn source(&self) -> Option<&Path> {
+        self.source.as_deref()
+    }
+
+    pub fn current(&self) -> Arc<LoadedContract> {
+        self.current
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    // Swaps `next` in and hands back the contract it replaced.
+    pub fn replace(&self, next: LoadedContract) -> Arc<LoadedContract> {
+        let mut current = self
+            .current
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        std::mem::replace(&mut *current, Arc::new(next))
+    }
+}
+
+// Queued or waiting jobs whose operation the new contract would refuse.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StrandedOperation {
+    pub operation: String,
+    pub pending_jobs: i64,
+    // `undeclared` or `sunset`.
+    pub reason: &'static str,
+}
+
+// A field the generated types would only learn about from a rebuild.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SchemaChange {
+    pub schema: String,
+    pub field: String,
+    pub change: &'static str,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ContractDiff {
+    pub old_version: Option<String>,
+    pub new_version: Option<String>,
+    pub added_commands: Vec<String>,
+    pub removed_commands: Vec<String>,
+    pub added_events: Vec<String>,
+    pub removed_events: Vec<String>,
+    pub stranded_jobs: Vec<StrandedOperation>,
+    // Runtime validation only covers operations, aliases, deprecations and delivery policy;
+    // these need the node rebuilt from the new contract and restarted.
+    pub restart_required: Vec<SchemaChange>,
+}
+
+impl ContractDiff {
+    pub fn is_safe(&self) -> bool {
+        self.stranded_jobs.is_empty()
+    }
+}
+
+// What replacing `current` with `next` changes, given the pending jobs per operation.
+pub fn diff_contracts(
+    current: &LoadedContract,
+    next: &LoadedContract,
+    pending: &BTreeMap<String, i64>,
+    allow_sunset: bool,
+    now: DateTime<Utc>,
+) -> ContractDiff {
+    let (old, new) = (&current.registry, &next.registry);
+    let declares_commands = new.commands().next().is_some();
+    let stranded_jobs = pending
+        .iter()
+        .filter_map(|(operation, &pending_jobs)| {
+            let (canonical, aliased) = new.resolve_alias(operation);
+            let reason = if declares_commands && !aliased && !new.is_command(canonical) {
+                "undeclared"
+            } else if !allow_sunset
+                && new
+                    .deprecation(canonical)
+                    .is_some_and(|deprecation| deprecation.is_sunset(now))
+            {
+                "sunset"
+            } else {
+                return None;
+            };
+            Some(StrandedOperation {
+                operation: operation.clone(),
+                pending_jobs,
+                reason,
+            })
+        })
+        .collect();
+    let (added_commands, removed_commands) = changed(old.commands(), new.commands());
+    let (added_events, removed_events) = changed(old.events(), new.events());
+    ContractDiff {
+        old_version: old.version().map(str::to_string),
+        new_version: new.version().map(str::to_string),
+        added_commands,
+        removed_commands,
+        added_events,
+        removed_events,
+        stranded_jobs,
+        restart_required: new_required_fields(&current.document, &next.document),
+    }
+}
+
+fn changed<'a>(
+    old: impl Iterator<Item = &'a str>,
+    new: impl Iterator<Item = &'a str>,
+) -> (Vec<String>, Vec<String>) {
+    let (old, new): (BTreeSet<_>, BTreeSet<_>) = (old.collect(), new.collect());
+    (
+        new.difference(&old).map(|name| name.to_string()).collect(),
+        old.difference(&new).map(|name| name.to_string()).collect(),
+    )
+}
+
+// Fields that became required on a schema the old contract already had. A schema that is new
+// altogether has no generated type to disagree with yet.
+fn new_required_fields(old: &str, new: &str) -> Vec<SchemaChange> {
+    let (old, new) = (required_fields(old), required_fields(new));
+    let mut changes = Vec::new();
+    for (schema, required) in &new {
+        let Some(before) = old.get(schema) else {
+            continue;
+        };
+        for field in required.difference(before) {
+            changes.push(SchemaChange {
+                schema: schema.clone(),
+                field: field.clone(),
+                change: "required_field_added",
+            });
+        }
+    }
+    changes
+}
+
+fn required_fields(document: &str) -> BTreeMap<String, BTreeSet<String>> {
+    let doc: YamlValue =
+        serde_yaml::from_str(document.trim_start_matches('\u{feff}')).unwrap_or_default();
+    let Some(schemas) = doc
+        .get("components")
+        .and_then(|components| components.get("schemas"))
+        .and_then(YamlValue::as_mapping)
+    else {
+        return BTreeMap::new();
+    };
+    schemas
+        .iter()
+        .filter_map(|(name, schema)| {
+            let required = schema
+                .get("required")
+                .and_then(YamlValue::as_sequence)
+                .into_iter()
+                .flatten()
+                .filter_map(YamlValue::as_str)
+                .map(str::to_string)
+                .collect();
+            Some((name.as_str()?.to_string(), required))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contract(version: &str, retasync: &str, schemas: &str) -> LoadedContract {
+        LoadedContract::lenient(format!(
+            "asyncapi: 3.0.0\ninfo:\n  version: \"{version}\"\n\
+             x-retasync:\n  operations:\n{retasync}\
+             components:\n  schemas:\n{schemas}"
+        ))
+    }
+
+    const PING: &str = "    Ping:\n      type: object\n      required: [id]\n";
+
+    #[test]
+    fn pending_jobs_for_vanished_operations_are_stranded() {
+        let current = contract(
+            "1.0.0",
+            "    commands: [mission.create, mission.delete, node.ping]\n    events: [mission.created]\n",
+            PING,
+        );
+        let next = contract(
+            "1.1.0",
+            "    commands: [mission.open, node.ping, mission.close]\n    events: [mission.opened]\n    \
+             x-retasync-aliases:\n      mission.create: mission.open\n    \
+             deprecated:\n      node.ping:\n        x-retasync-sunset: \"2020-01-01\"\n",
+            PING,
+        );
+        let pending = BTreeMap::from([
+            ("mission.create".to_string(), 2),
+            ("mission.delete".to_string(), 1),
+            ("node.ping".to_string(), 4),
+        ]);
+
+        let diff = diff_contracts(&current, &next, &pending, false, Utc::now());
+        assert_eq!(diff.old_version.as_deref(), Some("1.0.0"));
+        assert_eq!(diff.new_version.as_deref(), Some("1.1.0"));
+        assert_eq!(diff.added_commands, ["mission.close", "mission.open"]);
+        assert_eq!(diff.removed_commands, ["mission.create", "mission.delete"]);
+        assert_eq!(diff.added_events, ["mission.opened"]);
+        assert_eq!(diff.removed_events, ["mission.created"]);
+        // The renamed operation still resolves through its alias.
+        assert_eq!(
+            diff.stranded_jobs,
+            [
+                StrandedOperation {
+                    operation: "mission.delete".to_string(),
+                    pending_jobs: 1,
+                    reason: "undeclared",
+                },
+                StrandedOperation {
+                    operation: "node.ping".to_string(),
+                    pending_jobs: 4,
+                    reason: "sunset",
+                },
+            ]
+        );
+        assert!(!diff.is_safe());
+
+        let diff = diff_contracts(&current, &next, &pending, true, Utc::now());
+        assert_eq!(diff.stranded_jobs.len(), 1);
+        assert!(diff_contracts(&current, &next, &BTreeMap::new(), false, Utc::now()).is_safe());
+    }
+
+    #[test]
+    fn new_required_fields_on_existing_schemas_need_a_restart() {
+        let current = contract("1.0.0", "    commands: [node.ping]\n", PING);
+        let next = contract(
+            "1.1.0",
+            "    commands: [node.ping]\n",
+            "    Ping:\n      type: object\n      required: [id, sent_at]\n\
+             \x20   Pong:\n      type: object\n      required: [id]\n",
+        );
+        let diff = diff_contracts(&current, &next, &BTreeMap::new(), false, Utc::now());
+        assert_eq!(
+            diff.restart_required,
+            [SchemaChange {
+                schema: "Ping".to_string(),
+                field: "sent_at".to_string(),
+                change: "required_field_added",
+            }]
+        );
+        assert!(diff.is_safe());
+    }
+
+    #[test]
+    fn replace_swaps_document_and_registry_together() {
+        let store = ContractStore::new(contract("1.0.0", "    commands: [node.ping]\n", PING))
+            .sourced_from("contracts/node.yaml");
+        let before = store.current();
+        let previous = store.replace(contract("2.0.0", "    commands: [node.pong]\n", PING));
+        assert_eq!(previous.registry.version(), Some("1.0.0"));
+        assert!(before.registry.is_command("node.ping"));
+        let after = store.current();
+        assert_eq!(after.registry.version(), Some("2.0.0"));
+        assert!(after.document.contains("node.pong"));
+        assert_eq!(store.source(), Some(Path::new("contracts/node.yaml")));
+    }
+}
@@ -0,0 +1,395 @@
+﻿use std::any::Any;
+use std::backtrace::{Backtrace, BacktraceStatus};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::thread::ThreadId;
+use std::time::Instant;
+
+use retasync_storage::{CanonicalTimestamp, CrashReport};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::app::{publish, write_log};
+use crate::AppState;
+
+pub const CRASH_DETECTED_EVENT: &str = "node.crash_detected";
+// Report kinds: a panic that took the process down, and one a worker wrapper caught.
+pub const PROCESS_CRASH: &str = "process";
+pub const TASK_PANIC: &str = "task";
+const CRASH_DIR_EXTENSION: &str = "crashes";
+const MARKER_EXTENSION: &str = "json";
+
+// The `[crash_reports]` section: where the panic hook leaves its files and how many of them,
+// and of the stored reports, are kept.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CrashReportSettings {
+    // Defaults to `<database>.crashes` beside the database file.
+    pub dir: Option<String>,
+    // Crash files kept after they are loaded, newest first.
+    pub max_files: usize,
+    // Stored reports kept, process crashes and worker panics together.
+    pub max_rows: i64,
+}
+
+impl Default for CrashReportSettings {
+    fn default() -> Self {
+        Self {
+            dir: None,
+            max_files: 20,
+            max_rows: 200,
+        }
+    }
+}
+
+impl CrashReportSettings {
+    pub fn dir_for(&self, database_path: &Path) -> PathBuf {
+        match &self.dir {
+            Some(dir) => PathBuf::from(dir),
+            None => {
+                let mut dir = database_path.as_os_str().to_owned();
+                dir.push(".");
+                dir.push(CRASH_DIR_EXTENSION);
+                PathBuf::from(dir)
+            }
+        }
+    }
+}
+
+// What the panic hook writes, one file per panic, named after `crash_id`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CrashMarker {
+    pub crash_id: String,
+    pub occurred_at: String,
+    pub message: String,
+    pub thread: Option<String>,
+    pub location: Option<String>,
+    pub backtrace: Option<String>,
+    pub version: String,
+    pub uptime_secs: Option<i64>,
+}
+
+impl CrashMarker {
+    pub fn new(message: String, location: Option<String>, backtrace: Option<String>) -> Self {
+        Self {
+            crash_id: Uuid::now_v7().to_string(),
+            occurred_at: CanonicalTimestamp::now().to_string(),
+            message,
+            thread: std::thread::current().name().map(str::to_string),
+            location,
+            backtrace,
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            uptime_secs: PROCESS_START
+                .get()
+                .map(|started| i64::try_from(started.elapsed().as_secs()).unwrap_or(i64::MAX)),
+        }
+    }
+
+    fn into_report(self, kind: &str, job_id: Option<String>) -> CrashReport {
+        CrashReport {
+            crash_id: self.crash_id,
+            kind: kind.to_string(),
+            occurred_at: self.occurred_at,
+            message: self.message,
+            thread: self.thread,
+            location: self.location,
+            backtrace: self.backtrace,
+            version: self.version,
+            uptime_secs: self.uptime_secs,
+            job_id,
+            recorded_at: CanonicalTimestamp::now().to_string(),
+        }
+    }
+}
+
+static PROCESS_START: OnceLock<Instant> = OnceLock::new();
+
+// Files the hook wrote in this process. The hook cannot tell whether the panic will be caught;
+// whoever catches it takes the file back, so only the panics that end the process leave one.
+static WRITTEN: Mutex<Vec<Written>> = Mutex::new(Vec::new());
+
+struct Written {
+    thread: ThreadId,
+    path: PathBuf,
+    marker: CrashMarker,
+}
+
+fn written() -> std::sync::MutexGuard<'static, Vec<Written>> {
+    WRITTEN.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+// Has every panic from here on write a crash file to `dir` before the previous hook runs.
+// Uptime is counted from this call.
+pub fn install_panic_hook(dir: PathBuf) {
+    PROCESS_START.get_or_init(Instant::now);
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let backtrace = Backtrace::capture();
+        let backtrace =
+            (backtrace.status() == BacktraceStatus::Captured).then(|| backtrace.to_string());
+        let marker = CrashMarker::new(
+            panic_message(info.payload()),
+            info.location().map(ToString::to_string),
+            backtrace,
+        );
+        if let Err(err) = write_crash_marker(&dir, marker) {
+            eprintln!("failed to write crash report to {}: {err}", dir.display());
+        }
+        previous(info);
+    }));
+}
+
+pub fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "panic with a non-string payload".to_string()
+    }
+}
+
+// The hook's work, without the panic: writes `marker` to `<dir>/<crash_id>.json` in one rename
+// so a crash mid-write never leaves half a file behind.
+pub fn write_crash_marker(dir: &Path, marker: CrashMarker) -> io::Result<PathBuf> {
+    std::fs::create_dir_all(dir)?;
+    let path = dir.join(format!("{}.{MARKER_EXTENSION}", marker.crash_id));
+    let partial = path.with_extension("partial");
+    std::fs::write(&partial, serde_json::to_vec_pretty(&marker)?)?;
+    std::fs::rename(&partial, &path)?;
+    written().push(Written {
+        thread: std::thread::current().id(),
+        path: path.clone(),
+        marker,
+    });
+    Ok(path)
+}
+
+// For `catch_unwind` callers: the panic just caught on this thread did not crash anything.
+pub fn forget_caught_panic() {
+    let current = std::thread::current().id();
+    let mut written = written();
+    if let Some(index) = written.iter().rposition(|entry| entry.thread == current) {
+        let entry = written.remove(index);
+        let _ = std::fs::remove_file(entry.path);
+    }
+}
+
+// Takes back the newest file written for a panic with `message`, caught on whichever thread.
+fn take_marker(message: &str) -> Option<CrashMarker> {
+    let mut written = written();
+    let index = written
+        .iter()
+        .rposition(|entry| entry.marker.message == message)?;
+    let entry = written.remove(index);
+    let _ = std::fs::remove_file(&entry.path);
+    Some(entry.marker)
+}
+
+// Records a worker panic the process survived as a `task` report. The hook's file for it, when
+// there is one, supplies the thread, location and backtrace and is removed.
+pub async fn record_task_panic(
+    state: &AppState,
+    job_id: &str,
+    message: String,
+) -> anyhow::Result<CrashReport> {
+    let marker = take_marker(&message).unwrap_or_else(|| CrashMarker::new(message, None, None));
+    let report = marker.into_report(TASK_PANIC, Some(job_id.to_string()));
+    state.storage.record_crash_report(&report).await?;
+    Ok(report)
+}
+
+// Loads the crash files a previous run left into `crash_reports`, reports each one not loaded
+// before with `node.crash_detected`, and applies the file and row limits. Returns the newest
+// crash found, the one that ended the previous run.
+pub async fn ingest_crash_reports(state: &AppState) -> anyhow::Result<Option<CrashReport>> {
+    let settings = state.node_config.read().await.crash_reports.clone();
+    let dir = settings.dir_for(state.storage.database_path());
+    let files = marker_files(&dir).await?;
+    let mut latest: Option<CrashReport> = None;
+    for path in &files {
+        let marker = match read_marker(path).await {
+            Ok(marker) => marker,
+            Err(err) => {
+                warn!(path = %path.display(), error = %err, "unreadable crash report skipped");
+                continue;
+            }
+        };
+        let report = marker.into_report(PROCESS_CRASH, None);
+        let detected = json!({
+            "crash_id": report.crash_id,
+            "occurred_at": report.occurred_at,
+            "message": report.message,
+            "thread": report.thread,
+            "location": report.location,
+            "version": report.version,
+            "uptime_secs": report.uptime_secs,
+        });
+        let recorded = state
+            .storage
+            .with_event(CRASH_DETECTED_EVENT, detected)
+            .record_crash_report(&report)
+            .await?;
+        if !recorded {
+            continue;
+        }
+        report_crash(state, &report).await;
+        latest = Some(report);
+    }
+
+    let excess = files.len().saturating_sub(settings.max_files);
+    for path in &files[..excess] {
+        if let Err(err) = tokio::fs::remove_file(path).await {
+            warn!(path = %path.display(), error = %err, "crash report file not removed");
+        }
+    }
+    trim_crash_reports(state).await?;
+    Ok(latest)
+}
+
+pub async fn trim_crash_reports(state: &AppState) -> anyhow::Result<u64> {
+    let max_rows = state.node_config.read().await.crash_reports.max_rows;
+    Ok(state.storage.trim_crash_reports(max_rows).await?)
+}
+
+// Oldest first: crash ids are v7 UUIDs, so the file names sort by time.
+async fn marker_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut entries = match tokio::fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+    let mut files = Vec::new();
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if path.extension().is_some_and(|ext| ext == MARKER_EXTENSION) {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+async fn read_marker(path: &Path) -> anyhow::Result<CrashMarker> {
+    let raw = tokio::fs::read(path).await?;
+    Ok(serde_json::from_slice(&raw)?)
+}
+
+async fn report_crash(state: &AppState, report: &CrashReport) {
+    let uptime = report
+        .uptime_secs
+        .map(|secs| format!(" after {secs}s"))
+        .unwrap_or_default();
+    let summary = format!(
+        "previous run crashed at {}{uptime}: {} (crash {})",
+        report.occurred_at, report.message, report.crash_id
+    );
+    warn!(
+        crash_id = %report.crash_id,
+        thread = report.thread.as_deref().unwrap_or("unnamed"),
+        location = report.location.as_deref().unwrap_or("unknown"),
+        "{summary}"
+    );
+    write_log(state, "warn", &summary).await;
+    publish(state).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        ingest_crash_reports, write_crash_marker, CrashMarker, CRASH_DETECTED_EVENT,
+        PROCESS_CRASH,
+    };
+    use crate::test_support::test_state;
+    use crate::AppState;
+
+    use std::path::{Path, PathBuf};
+
+    async fn crash_dir(state: &AppState) -> PathBuf {
+        let settings = state.node_config.read().await.crash_reports.clone();
+        settings.dir_for(state.storage.database_path())
+    }
+
+    fn files_in(dir: &Path) -> usize {
+        std::fs::read_dir(dir).map(|entries| entries.count()).unwrap_or(0)
+    }
+
+    #[tokio::test]
+    async fn crash_file_is_loaded_and_reported_once_at_startup() {
+        let state = test_state().await;
+        let dir = crash_dir(&state).await;
+        let marker = CrashMarker::new(
+            "index out of bounds".to_string(),
+            Some("src/app.rs:10:5".to_string()),
+            Some("0: retasyncd::main".to_string()),
+        );
+        let crash_id = marker.crash_id.clone();
+        write_crash_marker(&dir, marker).unwrap();
+        let mut events = state.sse_bus.subscribe();
+
+        let crash = ingest_crash_reports(&state).await.unwrap().expect("crash");
+        assert_eq!(crash.crash_id, crash_id);
+        assert_eq!(crash.kind, PROCESS_CRASH);
+        assert_eq!(crash.version, env!("CARGO_PKG_VERSION"));
+        let event = events.try_recv().expect("crash event");
+        assert_eq!(event.event_type, CRASH_DETECTED_EVENT);
+        assert_eq!(event.data["crash_id"], crash_id.as_str());
+        assert_eq!(event.data["message"], "index out of bounds");
+        let stored = state.storage.get_crash_report(&crash_id).await.unwrap();
+        assert_eq!(stored.unwrap().backtrace.as_deref(), Some("0: retasyncd::main"));
+        let logs = state.log_buffer.read().await;
+        assert!(logs.iter().any(|line| line.message.contains(&crash_id)));
+        drop(logs);
+
+        // The file stays until retention removes it, but a second start does not report it again.
+        assert_eq!(files_in(&dir), 1);
+        assert_eq!(ingest_crash_reports(&state).await.unwrap(), None);
+        assert!(events.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn retention_trims_crash_files_and_rows() {
+        let state = test_state().await;
+        {
+            let mut config = state.node_config.write().await;
+            config.crash_reports.max_files = 2;
+            config.crash_reports.max_rows = 3;
+        }
+        let dir = crash_dir(&state).await;
+        let mut ids = Vec::new();
+        for n in 0..4 {
+            let marker = CrashMarker::new(format!("crash {n}"), None, None);
+            ids.push(marker.crash_id.clone());
+            write_crash_marker(&dir, marker).unwrap();
+        }
+        std::fs::write(dir.join("notes.txt"), "not a crash").unwrap();
+
+        let latest = ingest_crash_reports(&state).await.unwrap().expect("crash");
+        assert_eq!(latest.crash_id, ids[3]);
+        let mut left: Vec<String> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        left.sort();
+        assert_eq!(
+            left,
+            [
+                format!("{}.json", ids[2]),
+                format!("{}.json", ids[3]),
+                "notes.txt".to_string()
+            ]
+        );
+        let kept: Vec<String> = state
+            .storage
+            .list_crash_reports(10)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|report| report.crash_id)
+            .collect();
+        assert_eq!(kept, [ids[3].clone(), ids[2].clone(), ids[1].clone()]);
+    }
+}
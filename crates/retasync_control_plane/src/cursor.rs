@@ -0,0 +1,321 @@
+﻿use std::collections::BTreeMap;
+use std::path::Path;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use retasync_contract::{decode_canonical, encode_canonical};
+use retasync_storage::{KeyValue, Keyset, PageDirection, SortOrder};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use thiserror::Error;
+
+use crate::sneakernet::load_secret;
+
+pub const INVALID_CURSOR_ERROR: &str = "invalid_cursor";
+const TAG_BYTES: usize = 32;
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PaginationSettings {
+    // File holding the secret cursors are signed with, created on first use. Unset signs with a
+    // secret drawn at boot, so cursors handed out before a restart are refused after it.
+    pub secret_path: Option<String>,
+    // Cursors older than this are refused; 0 never expires them.
+    pub max_cursor_age_secs: u64,
+}
+
+impl Default for PaginationSettings {
+    fn default() -> Self {
+        Self {
+            secret_path: None,
+            max_cursor_age_secs: 86_400,
+        }
+    }
+}
+
+// Where a page of a listing starts. Clients only ever see it encoded, signed for the listing
+// (`scope`) it came from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Cursor<T> {
+    pub key: T,
+    pub direction: PageDirection,
+    // Unix seconds.
+    pub issued_at: i64,
+}
+
+#[derive(Debug, Error)]
+pub enum CursorError {
+    #[error("cursor is malformed: {0}")]
+    Malformed(String),
+    #[error("cursor was not issued by this node for this listing")]
+    Tampered,
+    #[error("cursor expired {age_secs}s after it was issued")]
+    Expired { age_secs: i64 },
+    #[error("cursor secret {path}: {detail}")]
+    Secret { path: String, detail: String },
+    #[error(transparent)]
+    Internal(#[from] anyhow::Error),
+}
+
+impl CursorError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            CursorError::Malformed(_) | CursorError::Tampered | CursorError::Expired { .. } => {
+                INVALID_CURSOR_ERROR
+            }
+            CursorError::Secret { .. } => "cursor_secret_unavailable",
+            CursorError::Internal(_) => "internal_error",
+        }
+    }
+
+    // Whether the client sent a bad cursor rather than the node failing to handle it.
+    pub fn is_client_error(&self) -> bool {
+        self.code() == INVALID_CURSOR_ERROR
+    }
+}
+
+// The cursor before and after a page; either is absent at that end of the listing.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PageCursors {
+    pub next_cursor: Option<String>,
+    pub prev_cursor: Option<String>,
+}
+
+// Secrets cursors are signed with: the per-boot one under `None`, persisted ones by path.
+#[derive(Debug, Default)]
+pub struct CursorKeys {
+    secrets: std::sync::Mutex<BTreeMap<Option<String>, [u8; 32]>>,
+}
+
+impl CursorKeys {
+    pub fn codec(&self, settings: &PaginationSettings) -> Result<CursorCodec, CursorError> {
+        let mut secrets = self
+            .secrets
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let secret = match secrets.get(&settings.secret_path) {
+            Some(secret) => *secret,
+            None => {
+                let secret_error = |detail: String| CursorError::Secret {
+                    path: settings.secret_path.clone().unwrap_or_else(|| "boot".into()),
+                    detail,
+                };
+                let secret = match &settings.secret_path {
+                    Some(path) => load_secret(Path::new(path)).map_err(secret_error)?,
+                    None => {
+                        let mut secret = [0u8; 32];
+                        getrandom::getrandom(&mut secret)
+                            .map_err(|err| secret_error(err.to_string()))?;
+                        secret
+                    }
+                };
+                secrets.insert(settings.secret_path.clone(), secret);
+                secret
+            }
+        };
+        Ok(CursorCodec {
+            secret,
+            max_age_secs: settings.max_cursor_age_secs,
+        })
+    }
+}
+
+// Cursors are URL-safe base64 of the canonical encoding followed by an HMAC-SHA256 tag over the
+// listing's scope and that encoding, so a client can neither forge one nor reuse one elsewhere.
+#[derive(Clone)]
+pub struct CursorCodec {
+    secret: [u8; 32],
+    max_age_secs: u64,
+}
+
+impl CursorCodec {
+    fn tag(&self, scope: &str, payload: &[u8]) -> HmacSha256 {
+        let mut mac =
+            HmacSha256::new_from_slice(&self.secret).expect("HMAC accepts keys of any length");
+        mac.update(scope.as_bytes());
+        mac.update(&[0]);
+        mac.update(payload);
+        mac
+    }
+
+    pub fn encode<T: Serialize>(
+        &self,
+        scope: &str,
+        cursor: &Cursor<T>,
+    ) -> Result<String, CursorError> {
+        let mut bytes = encode_canonical(cursor).map_err(anyhow::Error::from)?;
+        let tag = self.tag(scope, &bytes).finalize().into_bytes();
+        bytes.extend_from_slice(&tag);
+        Ok(URL_SAFE_NO_PAD.encode(bytes))
+    }
+
+    pub fn decode<T: DeserializeOwned>(
+        &self,
+        scope: &str,
+        token: &str,
+        now: DateTime<Utc>,
+    ) -> Result<Cursor<T>, CursorError> {
+        let bytes = URL_SAFE_NO_PAD
+            .decode(token)
+            .map_err(|_| CursorError::Malformed("not base64url".into()))?;
+        if bytes.len() <= TAG_BYTES {
+            return Err(CursorError::Malformed("too short".into()));
+        }
+        let (payload, tag) = bytes.split_at(bytes.len() - TAG_BYTES);
+        self.tag(scope, payload)
+            .verify_slice(tag)
+            .map_err(|_| CursorError::Tampered)?;
+        let cursor: Cursor<T> =
+            decode_canonical(payload).map_err(|err| CursorError::Malformed(err.to_string()))?;
+        let age_secs = now.timestamp() - cursor.issued_at;
+        if self.max_age_secs > 0 && age_secs > self.max_age_secs as i64 {
+            return Err(CursorError::Expired { age_secs });
+        }
+        Ok(cursor)
+    }
+
+    // Where a list request picks up: the head of the listing, or just past its cursor's key. One
+    // row over the limit is fetched to tell whether another page follows.
+    pub fn keyset(
+        &self,
+        scope: &str,
+        order: SortOrder,
+        cursor: Option<&str>,
+        limit: i64,
+        now: DateTime<Utc>,
+    ) -> Result<Keyset, CursorError> {
+        let Some(token) = cursor else {
+            return Ok(Keyset::first(limit + 1));
+        };
+        let cursor: Cursor<Vec<KeyValue>> = self.decode(scope, token, now)?;
+        if cursor.key.len() != order.columns.len() {
+            return Err(CursorError::Malformed(format!(
+                "expected a key of {}",
+                order.describe()
+            )));
+        }
+        Ok(Keyset {
+            key: Some(cursor.key),
+            direction: cursor.direction,
+            limit: limit + 1,
+        })
+    }
+
+    // Cuts the rows fetched for `page` down to the limit, in listing order, and signs cursors
+    // for the pages on either side.
+    pub fn finish<I>(
+        &self,
+        scope: &str,
+        page: &Keyset,
+        rows: &mut Vec<I>,
+        key_of: impl Fn(&I) -> Vec<KeyValue>,
+        now: DateTime<Utc>,
+    ) -> Result<PageCursors, CursorError> {
+        let more = rows.len() as i64 >= page.limit;
+        rows.truncate((page.limit - 1).max(0) as usize);
+        let backward = page.direction == PageDirection::Backward;
+        if backward {
+            rows.reverse();
+        }
+        let cursor = |row: Option<&I>, direction| match row {
+            Some(row) => self
+                .encode(
+                    scope,
+                    &Cursor {
+                        key: key_of(row),
+                        direction,
+                        issued_at: now.timestamp(),
+                    },
+                )
+                .map(Some),
+            None => Ok(None),
+        };
+        // The page a cursor came from always lies behind it.
+        let has_next = if backward { true } else { more };
+        let has_prev = if backward { more } else { page.key.is_some() };
+        Ok(PageCursors {
+            next_cursor: cursor(rows.last().filter(|_| has_next), PageDirection::Forward)?,
+            prev_cursor: cursor(rows.first().filter(|_| has_prev), PageDirection::Backward)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::scratch_dir;
+
+    fn cursor(issued_at: i64) -> Cursor<Vec<KeyValue>> {
+        Cursor {
+            key: vec!["2026-01-01T00:00:00.000000Z".into(), "transfer-1".into()],
+            direction: PageDirection::Backward,
+            issued_at,
+        }
+    }
+
+    #[test]
+    fn tampered_foreign_and_expired_cursors_are_refused() {
+        let now = Utc::now();
+        let codec = CursorKeys::default()
+            .codec(&PaginationSettings::default())
+            .unwrap();
+        let token = codec.encode("transfers", &cursor(now.timestamp())).unwrap();
+        assert_eq!(
+            codec
+                .decode::<Vec<KeyValue>>("transfers", &token, now)
+                .unwrap(),
+            cursor(now.timestamp())
+        );
+
+        let mut bytes = URL_SAFE_NO_PAD.decode(&token).unwrap();
+        bytes[3] ^= 1;
+        let tampered = URL_SAFE_NO_PAD.encode(bytes);
+        for (scope, token) in [("transfers", tampered.as_str()), ("files", token.as_str())] {
+            let err = codec.decode::<Vec<KeyValue>>(scope, token, now).unwrap_err();
+            assert!(matches!(err, CursorError::Tampered), "{err}");
+        }
+        let err = codec
+            .decode::<Vec<KeyValue>>("transfers", "!!", now)
+            .unwrap_err();
+        assert_eq!(err.code(), INVALID_CURSOR_ERROR);
+
+        let stale = codec
+            .encode("transfers", &cursor(now.timestamp() - 86_401))
+            .unwrap();
+        let err = codec
+            .decode::<Vec<KeyValue>>("transfers", &stale, now)
+            .unwrap_err();
+        assert!(matches!(err, CursorError::Expired { .. }), "{err}");
+    }
+
+    #[test]
+    fn persisted_secret_keeps_cursors_valid_across_restarts() {
+        let path = scratch_dir().join("cursor.key");
+        let settings = PaginationSettings {
+            secret_path: Some(path.display().to_string()),
+            ..PaginationSettings::default()
+        };
+        let now = Utc::now();
+        let token = CursorKeys::default()
+            .codec(&settings)
+            .unwrap()
+            .encode("transfers", &cursor(now.timestamp()))
+            .unwrap();
+        // A fresh set of keys stands in for the restarted daemon.
+        let restarted = CursorKeys::default().codec(&settings).unwrap();
+        assert!(restarted
+            .decode::<Vec<KeyValue>>("transfers", &token, now)
+            .is_ok());
+        let per_boot = CursorKeys::default()
+            .codec(&PaginationSettings::default())
+            .unwrap();
+        assert!(per_boot
+            .decode::<Vec<KeyValue>>("transfers", &token, now)
+            .is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+}
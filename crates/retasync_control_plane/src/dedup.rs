@@ -0,0 +1,273 @@
+﻿use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context};
+use chrono::Utc;
+use retasync_contract::{MeshCommandEnvelope, CONTENT_TYPE_MSGPACK};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::dispatch::resolve_dispatch;
+use crate::liveness::record_contact;
+use crate::AppState;
+
+pub const TRANSFER_OFFER_OPERATION: &str = "transfer.offer";
+pub const SKIPPED_DUPLICATE_STATUS: &str = "skipped_duplicate";
+pub const DEFAULT_OFFER_TIMEOUT_MS: u64 = 2000;
+// Out-of-order chunks an upload digest holds before it gives up on the upload.
+const MAX_BUFFERED_BYTES: usize = 4 * 1024 * 1024;
+// An upload whose next chunk has not arrived in this long is given up on.
+const DIGEST_IDLE_TIMEOUT: Duration = Duration::from_secs(600);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TransferDedupSettings {
+    pub enabled: bool,
+    // How long an upload waits for the destination's answer before sending the file in full.
+    pub offer_timeout_ms: u64,
+}
+
+impl Default for TransferDedupSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            offer_timeout_ms: DEFAULT_OFFER_TIMEOUT_MS,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DedupMetrics {
+    pub offers: u64,
+    pub hits: u64,
+    pub fallbacks: u64,
+    pub bytes_avoided: u64,
+}
+
+// The content an upload is about to send, named by hash.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransferOffer {
+    pub sha256: String,
+    pub size: u64,
+    pub name: String,
+}
+
+impl TransferOffer {
+    pub fn for_bytes(name: &str, bytes: &[u8]) -> Self {
+        Self {
+            sha256: format!("{:x}", Sha256::digest(bytes)),
+            size: bytes.len() as u64,
+            name: name.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OfferAnswer {
+    Have(Value),
+    Need(Value),
+    // No usable answer in time; the upload goes ahead in full.
+    Unanswered(String),
+}
+
+impl OfferAnswer {
+    pub fn outcome(&self) -> &'static str {
+        match self {
+            OfferAnswer::Have(_) => SKIPPED_DUPLICATE_STATUS,
+            OfferAnswer::Need(_) => "sent",
+            OfferAnswer::Unanswered(_) => "offer_unanswered",
+        }
+    }
+}
+
+async fn exchange(
+    state: &AppState,
+    destination: &str,
+    operation: &str,
+    offer: &TransferOffer,
+    timeout_ms: u64,
+) -> anyhow::Result<Value> {
+    let dispatch = resolve_dispatch(
+        &*state.node_config.read().await,
+        operation,
+        &json!({ "destination_identity": destination }),
+    )?;
+    let dispatch_destination = dispatch.destination_identity;
+    let envelope = MeshCommandEnvelope {
+        message_id: Uuid::now_v7().to_string(),
+        operation: operation.to_string(),
+        sent_at: Utc::now(),
+        source_identity: dispatch.source_identity,
+        destination_identity: dispatch_destination.clone(),
+        content_type: CONTENT_TYPE_MSGPACK.to_string(),
+        payload: serde_json::to_value(offer)?,
+        ttl_ms: Some(timeout_ms),
+        transport_hint: dispatch.transport_hint,
+        trace: Vec::new(),
+        trace_truncated: false,
+        meta: None,
+    };
+    let sent = state.bridge.send_command(envelope);
+    let result = tokio::time::timeout(Duration::from_millis(timeout_ms), sent)
+        .await
+        .map_err(|_| anyhow!("no answer to {operation} within {timeout_ms}ms"))??;
+    record_contact(state, &dispatch_destination, Utc::now()).await;
+    Ok(result.payload)
+}
+
+pub async fn offer_transfer(
+    state: &AppState,
+    destination: &str,
+    offer: &TransferOffer,
+    timeout_ms: u64,
+) -> OfferAnswer {
+    let answer =
+        match exchange(state, destination, TRANSFER_OFFER_OPERATION, offer, timeout_ms).await {
+            Ok(payload) => match payload.get("status").and_then(Value::as_str) {
+                Some("have") => OfferAnswer::Have(payload),
+                Some("need") => OfferAnswer::Need(payload),
+                _ => OfferAnswer::Unanswered(format!("unexpected answer {payload}")),
+            },
+            Err(err) => OfferAnswer::Unanswered(format!("{err:#}")),
+        };
+    let mut metrics = state
+        .dedup_metrics
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    metrics.offers += 1;
+    match answer {
+        OfferAnswer::Have(_) => {
+            metrics.hits += 1;
+            metrics.bytes_avoided += offer.size;
+        }
+        OfferAnswer::Need(_) => {}
+        OfferAnswer::Unanswered(_) => metrics.fallbacks += 1,
+    }
+    answer
+}
+
+// Built-in answer to an inbound `transfer.offer`.
+pub async fn answer_offer(state: &AppState, payload: Value) -> anyhow::Result<Value> {
+    let offer: TransferOffer =
+        serde_json::from_value(payload).context("decode transfer.offer payload")?;
+    let held = state
+        .storage
+        .find_received_file(&offer.sha256, offer.size as i64)
+        .await?;
+    Ok(match held {
+        Some(file) => json!({
+            "status": "have",
+            "sha256": offer.sha256,
+            "received_at": file.received_at,
+        }),
+        None => json!({ "status": "need", "sha256": offer.sha256 }),
+    })
+}
+
+struct PartialDigest {
+    chunks_total: usize,
+    next_index: usize,
+    hasher: Sha256,
+    size: u64,
+    buffered: BTreeMap<usize, Vec<u8>>,
+    buffered_bytes: usize,
+    touched: Instant,
+}
+
+// Hashes of the single-file uploads being received, keyed by sender and the sender's transfer
+// id. Chunks are hashed in order as they arrive, so only the ones ahead of a gap are held.
+#[derive(Default)]
+pub struct UploadDigests {
+    partial: HashMap<(String, String), PartialDigest>,
+}
+
+impl UploadDigests {
+    // Returns the upload's SHA-256 and size once its last chunk is hashed. An error drops it.
+    pub fn add(
+        &mut self,
+        source_identity: &str,
+        transfer_id: &str,
+        chunk_index: usize,
+        chunks_total: usize,
+        bytes: Vec<u8>,
+        now: Instant,
+    ) -> Result<Option<(String, u64)>, String> {
+        self.partial
+            .retain(|_, partial| now.duration_since(partial.touched) < DIGEST_IDLE_TIMEOUT);
+        let key = (source_identity.to_string(), transfer_id.to_string());
+        let partial = self
+            .partial
+            .entry(key.clone())
+            .or_insert_with(|| PartialDigest {
+                chunks_total,
+                next_index: 0,
+                hasher: Sha256::new(),
+                size: 0,
+                buffered: BTreeMap::new(),
+                buffered_bytes: 0,
+                touched: now,
+            });
+        if chunks_total != partial.chunks_total || chunk_index >= partial.chunks_total {
+            self.partial.remove(&key);
+            return Err(format!("chunk {chunk_index} of {chunks_total} does not fit the upload"));
+        }
+        partial.touched = now;
+        if chunk_index >= partial.next_index {
+            partial.buffered_bytes += bytes.len();
+            if let Some(previous) = partial.buffered.insert(chunk_index, bytes) {
+                partial.buffered_bytes -= previous.len();
+            }
+        }
+        while let Some(bytes) = partial.buffered.remove(&partial.next_index) {
+            partial.buffered_bytes -= bytes.len();
+            partial.hasher.update(&bytes);
+            partial.size += bytes.len() as u64;
+            partial.next_index += 1;
+        }
+        if partial.buffered_bytes > MAX_BUFFERED_BYTES {
+            self.partial.remove(&key);
+            return Err(format!("more than {MAX_BUFFERED_BYTES} bytes arrived out of order"));
+        }
+        if partial.next_index < partial.chunks_total {
+            return Ok(None);
+        }
+        let partial = self.partial.remove(&key).expect("digested upload present");
+        Ok(Some((format!("{:x}", partial.hasher.finalize()), partial.size)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{OfferAnswer, TransferOffer, UploadDigests};
+    use serde_json::json;
+    use std::time::Instant;
+
+    #[test]
+    fn offers_name_content_by_hash_and_size() {
+        let offer = TransferOffer::for_bytes("report.txt", b"abc");
+        assert_eq!(
+            offer.sha256,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!((offer.size, offer.name.as_str()), (3, "report.txt"));
+        assert_eq!(OfferAnswer::Have(json!({})).outcome(), "skipped_duplicate");
+        assert_eq!(OfferAnswer::Unanswered(String::new()).outcome(), "offer_unanswered");
+    }
+
+    #[test]
+    fn uploads_are_hashed_from_the_chunks_that_arrived() {
+        let mut digests = UploadDigests::default();
+        let now = Instant::now();
+        let mut add = |index, bytes: &[u8]| digests.add("peer", "t1", index, 3, bytes.to_vec(), now);
+        assert_eq!(add(2, b"c"), Ok(None));
+        assert_eq!(add(0, b"a"), Ok(None));
+        let (sha256, size) = add(1, b"b").unwrap().unwrap();
+        assert_eq!((sha256, size), (TransferOffer::for_bytes("", b"abc").sha256, 3));
+        assert!(digests.partial.is_empty());
+
+        assert!(digests.add("peer", "t2", 4, 3, b"x".to_vec(), now).is_err());
+        assert!(digests.partial.is_empty());
+    }
+}
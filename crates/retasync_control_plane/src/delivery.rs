@@ -0,0 +1,264 @@
+﻿use std::time::Duration;
+
+use retasync_contract::DeliveryPolicy;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::circuit::CircuitBreakerSettings;
+
+// Payload field a client sets to override delivery; it is removed before the command is sent.
+pub const DELIVERY_FIELD: &str = "_delivery";
+pub const DEFAULT_STORE_AND_FORWARD_TTL_MS: u64 = 86_400_000;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeliverySettings {
+    pub max_attempts: u32,
+    pub backoff_ms: u64,
+    // 0 leaves each attempt unbounded.
+    pub timeout_ms: u64,
+    // Hand commands for unreachable peers to LXMF store-and-forward instead of failing them.
+    pub store_and_forward: bool,
+    // The TTL an escalated envelope is given, and how long its job waits in `in_transit`.
+    pub store_and_forward_ttl_ms: u64,
+    // The most a client's `_delivery` may ask for; larger requests are lowered to these.
+    pub client_max_attempts: u32,
+    pub client_max_backoff_ms: u64,
+    pub circuit_breaker: CircuitBreakerSettings,
+}
+
+impl Default for DeliverySettings {
+    fn default() -> Self {
+        Self {
+            max_attempts: 1,
+            backoff_ms: 1000,
+            timeout_ms: 0,
+            store_and_forward: false,
+            store_and_forward_ttl_ms: DEFAULT_STORE_AND_FORWARD_TTL_MS,
+            client_max_attempts: 10,
+            client_max_backoff_ms: 60_000,
+            circuit_breaker: CircuitBreakerSettings::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliverySource {
+    #[default]
+    Config,
+    Contract,
+    Client,
+}
+
+// The policy a job is delivered with, recorded on its dispatch.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EffectiveDelivery {
+    pub max_attempts: u32,
+    pub backoff_ms: u64,
+    pub timeout_ms: Option<u64>,
+    pub idempotent: bool,
+    pub source: DeliverySource,
+    // Set to the escalated TTL when an unreachable peer may be reached by store-and-forward.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub store_and_forward_ttl_ms: Option<u64>,
+}
+
+impl Default for EffectiveDelivery {
+    fn default() -> Self {
+        Self {
+            max_attempts: 1,
+            backoff_ms: 0,
+            timeout_ms: None,
+            idempotent: true,
+            source: DeliverySource::Config,
+            store_and_forward_ttl_ms: None,
+        }
+    }
+}
+
+impl EffectiveDelivery {
+    // The wait doubles after every failed attempt, starting at `backoff_ms`.
+    pub fn backoff_after(&self, attempt: u32) -> Duration {
+        let factor = 1u64 << attempt.saturating_sub(1).min(16);
+        Duration::from_millis(self.backoff_ms.saturating_mul(factor))
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RequestedDelivery {
+    max_attempts: Option<u32>,
+    backoff_ms: Option<u64>,
+    timeout_ms: Option<u64>,
+    store_and_forward: Option<bool>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum DeliveryRejection {
+    Malformed(String),
+    RetryNotAllowed,
+}
+
+// Contract defaults win over node settings and a client override wins over both, except that
+// an operation the contract marks non-idempotent is never retried.
+pub fn take_delivery(
+    payload: &mut Value,
+    settings: &DeliverySettings,
+    contract: Option<&DeliveryPolicy>,
+) -> Result<EffectiveDelivery, DeliveryRejection> {
+    let requested = match payload.as_object_mut().and_then(|map| map.remove(DELIVERY_FIELD)) {
+        Some(raw) => Some(
+            serde_json::from_value::<RequestedDelivery>(raw)
+                .map_err(|err| DeliveryRejection::Malformed(err.to_string()))?,
+        ),
+        None => None,
+    };
+
+    let idempotent = contract.is_none_or(DeliveryPolicy::allows_retry);
+    let configured_timeout = (settings.timeout_ms > 0).then_some(settings.timeout_ms);
+    let store_and_forward = contract
+        .and_then(|policy| policy.store_and_forward)
+        .unwrap_or(settings.store_and_forward);
+    let escalation_ttl = |allowed: bool| allowed.then_some(settings.store_and_forward_ttl_ms);
+    let mut delivery = EffectiveDelivery {
+        max_attempts: contract
+            .and_then(|policy| policy.max_attempts)
+            .unwrap_or(settings.max_attempts)
+            .max(1),
+        backoff_ms: contract
+            .and_then(|policy| policy.backoff_ms)
+            .unwrap_or(settings.backoff_ms),
+        timeout_ms: contract
+            .and_then(|policy| policy.timeout_ms)
+            .or(configured_timeout),
+        idempotent,
+        source: match contract {
+            Some(_) => DeliverySource::Contract,
+            None => DeliverySource::Config,
+        },
+        store_and_forward_ttl_ms: escalation_ttl(store_and_forward),
+    };
+    if !idempotent {
+        delivery.max_attempts = 1;
+    }
+
+    let Some(requested) = requested else {
+        return Ok(delivery);
+    };
+    match requested.max_attempts {
+        Some(0) => {
+            return Err(DeliveryRejection::Malformed(
+                "max_attempts must be at least 1".to_string(),
+            ))
+        }
+        Some(attempts) if attempts > 1 && !idempotent => {
+            return Err(DeliveryRejection::RetryNotAllowed)
+        }
+        Some(attempts) => delivery.max_attempts = attempts.min(settings.client_max_attempts.max(1)),
+        None => {}
+    }
+    if let Some(backoff_ms) = requested.backoff_ms {
+        delivery.backoff_ms = backoff_ms.min(settings.client_max_backoff_ms);
+    }
+    if let Some(timeout_ms) = requested.timeout_ms {
+        delivery.timeout_ms = (timeout_ms > 0).then_some(timeout_ms);
+    }
+    if let Some(store_and_forward) = requested.store_and_forward {
+        delivery.store_and_forward_ttl_ms = escalation_ttl(store_and_forward);
+    }
+    delivery.source = DeliverySource::Client;
+    Ok(delivery)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        take_delivery, DeliveryRejection, DeliverySettings, DeliverySource, DELIVERY_FIELD,
+    };
+    use retasync_contract::DeliveryPolicy;
+    use serde_json::json;
+    use std::time::Duration;
+
+    const AGGRESSIVE: DeliveryPolicy = DeliveryPolicy {
+        max_attempts: Some(5),
+        backoff_ms: Some(250),
+        timeout_ms: None,
+        idempotent: Some(true),
+        store_and_forward: Some(true),
+    };
+    const NO_RETRY: DeliveryPolicy = DeliveryPolicy {
+        max_attempts: None,
+        backoff_ms: None,
+        timeout_ms: None,
+        idempotent: Some(false),
+        store_and_forward: None,
+    };
+
+    #[test]
+    fn contract_defaults_apply_and_missing_extension_falls_back_to_config() {
+        let settings = DeliverySettings {
+            max_attempts: 3,
+            backoff_ms: 1000,
+            timeout_ms: 8000,
+            ..DeliverySettings::default()
+        };
+        let mut payload = json!({ "uid": "eam-1" });
+        let delivery = take_delivery(&mut payload, &settings, Some(&AGGRESSIVE)).unwrap();
+        assert_eq!((delivery.max_attempts, delivery.backoff_ms), (5, 250));
+        assert_eq!(delivery.timeout_ms, Some(8000));
+        assert_eq!(delivery.source, DeliverySource::Contract);
+        assert_eq!(delivery.backoff_after(3), Duration::from_millis(1000));
+        assert_eq!(delivery.store_and_forward_ttl_ms, Some(86_400_000));
+
+        let delivery = take_delivery(&mut payload, &settings, None).unwrap();
+        assert_eq!((delivery.max_attempts, delivery.source), (3, DeliverySource::Config));
+        let delivery = take_delivery(&mut payload, &settings, Some(&NO_RETRY)).unwrap();
+        assert_eq!((delivery.max_attempts, delivery.idempotent), (1, false));
+        assert_eq!(delivery.store_and_forward_ttl_ms, None);
+    }
+
+    #[test]
+    fn client_overrides_are_honored_only_for_idempotent_operations() {
+        let settings = DeliverySettings::default();
+        let mut payload = json!({ "uid": "eam-1", DELIVERY_FIELD: { "max_attempts": 2 } });
+        let delivery = take_delivery(&mut payload, &settings, Some(&AGGRESSIVE)).unwrap();
+        assert_eq!((delivery.max_attempts, delivery.backoff_ms), (2, 250));
+        assert_eq!(delivery.source, DeliverySource::Client);
+        assert_eq!(payload, json!({ "uid": "eam-1" }));
+
+        let mut payload = json!({ DELIVERY_FIELD: { "max_attempts": 3 } });
+        assert_eq!(
+            take_delivery(&mut payload, &settings, Some(&NO_RETRY)),
+            Err(DeliveryRejection::RetryNotAllowed)
+        );
+        let mut payload = json!({ DELIVERY_FIELD: { "timeout_ms": 500 } });
+        let delivery = take_delivery(&mut payload, &settings, Some(&NO_RETRY)).unwrap();
+        assert_eq!((delivery.max_attempts, delivery.timeout_ms), (1, Some(500)));
+        let mut payload = json!({ DELIVERY_FIELD: { "store_and_forward": false } });
+        let delivery = take_delivery(&mut payload, &settings, Some(&AGGRESSIVE)).unwrap();
+        assert_eq!(delivery.store_and_forward_ttl_ms, None);
+        let mut payload = json!({ DELIVERY_FIELD: { "retries": 3 } });
+        assert!(matches!(
+            take_delivery(&mut payload, &settings, None),
+            Err(DeliveryRejection::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn client_overrides_are_clamped_to_the_configured_maxima() {
+        let settings = DeliverySettings {
+            client_max_attempts: 4,
+            client_max_backoff_ms: 2000,
+            ..DeliverySettings::default()
+        };
+        let requested = json!({ "max_attempts": u32::MAX, "backoff_ms": u64::MAX });
+        let mut payload = json!({ DELIVERY_FIELD: requested });
+        let delivery = take_delivery(&mut payload, &settings, Some(&AGGRESSIVE)).unwrap();
+        assert_eq!((delivery.max_attempts, delivery.backoff_ms), (4, 2000));
+
+        let mut payload = json!({ DELIVERY_FIELD: { "max_attempts": 3, "backoff_ms": 100 } });
+        let delivery = take_delivery(&mut payload, &settings, None).unwrap();
+        assert_eq!((delivery.max_attempts, delivery.backoff_ms), (3, 100));
+    }
+}
@@ -0,0 +1,288 @@
+﻿use std::collections::HashMap;
+
+use retasync_storage::{RetasyncStorage, FEED_JOB_EVENT};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::app::{publish, redeliver_command_job, write_log};
+use crate::dispatch::Dispatch;
+use crate::AppState;
+
+// Payload field listing the jobs a command waits for; it is removed before the command is sent.
+pub const DEPENDS_ON_FIELD: &str = "depends_on";
+pub const WAITING_STATUS: &str = "waiting";
+pub const DEPENDENCY_FAILED_REASON: &str = "dependency_failed";
+pub const DEFAULT_MAX_CHAIN_DEPTH: usize = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DependencySettings {
+    // Most jobs a submission may wait on one behind another; 0 turns dependencies off.
+    pub max_chain_depth: usize,
+}
+
+impl Default for DependencySettings {
+    fn default() -> Self {
+        Self {
+            max_chain_depth: DEFAULT_MAX_CHAIN_DEPTH,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum DependencyRejection {
+    Malformed(String),
+    // The jobs on the loop, in the order they wait on each other.
+    Cycle(Vec<String>),
+    TooDeep { limit: usize, depth: usize },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Readiness {
+    Waiting,
+    Released,
+    Failed,
+}
+
+pub fn is_terminal(status: &str) -> bool {
+    matches!(
+        status,
+        "success" | "partial_failure" | "failed" | "cancelled"
+    )
+}
+
+// Removes `depends_on` from a command payload; an id listed twice is waited on once.
+pub fn take_dependencies(payload: &mut Value) -> Result<Vec<String>, DependencyRejection> {
+    let Some(raw) = payload
+        .as_object_mut()
+        .and_then(|object| object.remove(DEPENDS_ON_FIELD))
+    else {
+        return Ok(Vec::new());
+    };
+    let ids: Vec<String> = serde_json::from_value(raw).map_err(|_| {
+        DependencyRejection::Malformed(format!("{DEPENDS_ON_FIELD} must be an array of job ids"))
+    })?;
+    let mut unique = Vec::with_capacity(ids.len());
+    for id in ids {
+        if id.is_empty() {
+            return Err(DependencyRejection::Malformed(format!(
+                "{DEPENDS_ON_FIELD} must not contain empty job ids"
+            )));
+        }
+        if !unique.contains(&id) {
+            unique.push(id);
+        }
+    }
+    Ok(unique)
+}
+
+// Each job above `roots` in the stored graph, with the jobs it waits on.
+pub async fn ancestor_graph(
+    storage: &RetasyncStorage,
+    roots: &[String],
+) -> anyhow::Result<HashMap<String, Vec<String>>> {
+    let mut graph = HashMap::new();
+    let mut pending = roots.to_vec();
+    while let Some(job_id) = pending.pop() {
+        if graph.contains_key(&job_id) {
+            continue;
+        }
+        let parents: Vec<String> = storage
+            .list_job_dependencies(&job_id)
+            .await?
+            .into_iter()
+            .map(|parent| parent.job_id)
+            .collect();
+        pending.extend(parents.iter().cloned());
+        graph.insert(job_id, parents);
+    }
+    Ok(graph)
+}
+
+// A new job cannot close a loop itself, since nothing can wait on it yet, but the graph it
+// joins is checked anyway: a job on a loop would never be released.
+pub fn check_chain(
+    graph: &HashMap<String, Vec<String>>,
+    roots: &[String],
+    max_depth: usize,
+) -> Result<usize, DependencyRejection> {
+    let mut depths = HashMap::new();
+    let mut path = Vec::new();
+    let mut depth = 0;
+    for root in roots {
+        depth = depth.max(depth_of(root, graph, &mut depths, &mut path)?);
+    }
+    if depth > max_depth {
+        return Err(DependencyRejection::TooDeep {
+            limit: max_depth,
+            depth,
+        });
+    }
+    Ok(depth)
+}
+
+// How many jobs the longest chain from `job_id` upwards holds, `job_id` included.
+fn depth_of<'a>(
+    job_id: &'a str,
+    graph: &'a HashMap<String, Vec<String>>,
+    depths: &mut HashMap<&'a str, usize>,
+    path: &mut Vec<&'a str>,
+) -> Result<usize, DependencyRejection> {
+    if let Some(depth) = depths.get(job_id) {
+        return Ok(*depth);
+    }
+    if let Some(start) = path.iter().position(|on_path| *on_path == job_id) {
+        let cycle = path[start..].iter().map(|job| job.to_string()).collect();
+        return Err(DependencyRejection::Cycle(cycle));
+    }
+    path.push(job_id);
+    let mut depth = 1;
+    for parent in graph.get(job_id).into_iter().flatten() {
+        depth = depth.max(1 + depth_of(parent, graph, depths, path)?);
+    }
+    path.pop();
+    depths.insert(job_id, depth);
+    Ok(depth)
+}
+
+// Releases a waiting job once every job it waits on has succeeded, or fails it as soon as one
+// has finished any other way. Only the caller that moves the job out of `waiting` acts on it.
+pub async fn release_if_ready(state: &AppState, job_id: &str) -> anyhow::Result<Readiness> {
+    let parents = state.storage.list_job_dependencies(job_id).await?;
+    if let Some(culprit) = parents
+        .iter()
+        .find(|parent| is_terminal(&parent.status) && parent.status != "success")
+    {
+        let reason = format!("{DEPENDENCY_FAILED_REASON}: {}", culprit.job_id);
+        let failed = state
+            .storage
+            .with_event(
+                FEED_JOB_EVENT,
+                json!({
+                    "job_id": job_id,
+                    "status": "failed",
+                    "reason": DEPENDENCY_FAILED_REASON,
+                    "detail": { "culprit": culprit.job_id, "culprit_status": culprit.status },
+                }),
+            )
+            .settle_waiting_job(job_id, "failed", Some(&reason))
+            .await?;
+        if !failed {
+            return Ok(Readiness::Waiting);
+        }
+        publish(state).await;
+        write_log(
+            state,
+            "warn",
+            &format!("job {job_id} failed: dependency {} {}", culprit.job_id, culprit.status),
+        )
+        .await;
+        return Ok(Readiness::Failed);
+    }
+    if parents.iter().any(|parent| parent.status != "success") {
+        return Ok(Readiness::Waiting);
+    }
+
+    let released = json!({ "job_id": job_id, "status": "queued", "released": true });
+    if !state
+        .storage
+        .with_event(FEED_JOB_EVENT, released)
+        .settle_waiting_job(job_id, "queued", None)
+        .await?
+    {
+        return Ok(Readiness::Waiting);
+    }
+    publish(state).await;
+    let Some(job) = state.storage.get_job(job_id).await? else {
+        return Ok(Readiness::Waiting);
+    };
+    let payload: Value = serde_json::from_str(&job.payload_json)?;
+    let dispatch: Dispatch = match job.dispatch_json.as_deref() {
+        Some(dispatch) => serde_json::from_str(dispatch)?,
+        None => anyhow::bail!("waiting job {job_id} has no dispatch"),
+    };
+    write_log(state, "info", &format!("job {job_id} released by its dependencies")).await;
+    redeliver_command_job(state, job.job_id, job.operation, payload, dispatch);
+    Ok(Readiness::Released)
+}
+
+// Runs once a job has reached a terminal status. A dependent failed along the way settles its
+// own dependents in turn, so a failure travels down the whole chain.
+pub async fn settle_dependents(state: &AppState, job_id: &str) {
+    let mut settled = vec![job_id.to_string()];
+    while let Some(parent) = settled.pop() {
+        let dependents = match state.storage.list_job_dependents(&parent).await {
+            Ok(dependents) => dependents,
+            Err(err) => {
+                let message = format!("listing dependents of job {parent} failed: {err:#}");
+                write_log(state, "error", &message).await;
+                continue;
+            }
+        };
+        for dependent in dependents {
+            if dependent.status != WAITING_STATUS {
+                continue;
+            }
+            match release_if_ready(state, &dependent.job_id).await {
+                Ok(Readiness::Failed) => settled.push(dependent.job_id),
+                Ok(_) => {}
+                Err(err) => {
+                    let message = format!("releasing job {} failed: {err:#}", dependent.job_id);
+                    write_log(state, "error", &message).await;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_chain, take_dependencies, DependencyRejection};
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    fn graph(edges: &[(&str, &[&str])]) -> HashMap<String, Vec<String>> {
+        edges
+            .iter()
+            .map(|(job, parents)| {
+                let parents = parents.iter().map(|parent| parent.to_string()).collect();
+                (job.to_string(), parents)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn chains_are_measured_along_their_longest_path() {
+        // d waits on c and on a directly; c waits on b, which waits on a.
+        let graph = graph(&[("a", &[]), ("b", &["a"]), ("c", &["b"]), ("d", &["c", "a"])]);
+        let roots = vec!["d".to_string(), "a".to_string()];
+        assert_eq!(check_chain(&graph, &roots, 4), Ok(4));
+        assert_eq!(
+            check_chain(&graph, &roots, 3),
+            Err(DependencyRejection::TooDeep { limit: 3, depth: 4 })
+        );
+        assert_eq!(
+            check_chain(&graph, &roots, 0),
+            Err(DependencyRejection::TooDeep { limit: 0, depth: 4 })
+        );
+    }
+
+    #[test]
+    fn stored_loops_are_reported() {
+        let graph = graph(&[("a", &["c"]), ("b", &["a"]), ("c", &["b"])]);
+        let Err(DependencyRejection::Cycle(cycle)) = check_chain(&graph, &["b".to_string()], 8)
+        else {
+            panic!("loop not detected");
+        };
+        assert_eq!(cycle, ["b", "a", "c"]);
+
+        let mut payload = json!({ "uid": "evt-1", "depends_on": ["a", "b", "a"] });
+        assert_eq!(take_dependencies(&mut payload).unwrap(), ["a", "b"]);
+        assert_eq!(payload, json!({ "uid": "evt-1" }));
+        let mut payload = json!({ "depends_on": "a" });
+        assert!(matches!(
+            take_dependencies(&mut payload),
+            Err(DependencyRejection::Malformed(_))
+        ));
+    }
+}
@@ -0,0 +1,231 @@
+﻿use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, Utc};
+use retasync_contract::ContractRegistry;
+use retasync_mesh_bridge::RpcMeshBridge;
+use retasync_storage::RetasyncStorage;
+use serde::{Deserialize, Serialize};
+
+pub const BRIDGE_PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+const CLOCK_SKEW_TOLERANCE: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CheckResult {
+    pub name: String,
+    pub status: CheckStatus,
+    pub detail: String,
+}
+
+impl CheckResult {
+    pub fn pass(name: &str, detail: impl Into<String>) -> Self {
+        Self::new(name, CheckStatus::Pass, detail)
+    }
+
+    pub fn warn(name: &str, detail: impl Into<String>) -> Self {
+        Self::new(name, CheckStatus::Warn, detail)
+    }
+
+    pub fn fail(name: &str, detail: impl Into<String>) -> Self {
+        Self::new(name, CheckStatus::Fail, detail)
+    }
+
+    fn new(name: &str, status: CheckStatus, detail: impl Into<String>) -> Self {
+        Self {
+            name: name.to_string(),
+            status,
+            detail: detail.into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DiagnosticsReport {
+    pub checks: Vec<CheckResult>,
+}
+
+impl DiagnosticsReport {
+    pub fn push(&mut self, check: CheckResult) {
+        self.checks.push(check);
+    }
+
+    pub fn worst(&self) -> CheckStatus {
+        self.checks
+            .iter()
+            .map(|check| check.status)
+            .max()
+            .unwrap_or(CheckStatus::Pass)
+    }
+
+    pub fn check(&self, name: &str) -> Option<&CheckResult> {
+        self.checks.iter().find(|check| check.name == name)
+    }
+}
+
+pub fn check_contract(contract_doc: &str) -> CheckResult {
+    let source = contract_doc.trim_start_matches('\u{feff}');
+    let doc: serde_yaml::Value = match serde_yaml::from_str(source) {
+        Ok(doc) => doc,
+        Err(err) => return CheckResult::fail("contract", format!("invalid AsyncAPI YAML: {err}")),
+    };
+
+    let commands = doc
+        .get("x-retasync")
+        .and_then(|ext| ext.get("operations"))
+        .and_then(|ops| ops.get("commands"))
+        .and_then(serde_yaml::Value::as_sequence)
+        .map(Vec::len)
+        .unwrap_or(0);
+    if commands == 0 {
+        return CheckResult::fail(
+            "contract",
+            "x-retasync.operations.commands must include at least one operation",
+        );
+    }
+
+    if let Err(err) = ContractRegistry::from_yaml(source) {
+        return CheckResult::fail("contract", err.to_string());
+    }
+    CheckResult::pass("contract", format!("{commands} command operations declared"))
+}
+
+pub async fn check_storage(storage: &RetasyncStorage) -> CheckResult {
+    match storage.ping().await {
+        Ok(()) => CheckResult::pass("storage", "sqlite responds"),
+        Err(err) => CheckResult::fail("storage", format!("sqlite query failed: {err}")),
+    }
+}
+
+// Stored identities the normalization migration left alone because they are not hashes.
+pub async fn check_identity_hashes(storage: &RetasyncStorage) -> CheckResult {
+    match storage.identity_hash_issues().await {
+        Ok(issues) if issues.is_empty() => {
+            CheckResult::pass("identity_hashes", "every stored identity is a hash")
+        }
+        Ok(issues) => {
+            let listed: Vec<_> = issues
+                .iter()
+                .map(|issue| {
+                    format!("{} {:?} ({})", issue.table_name, issue.identity_hash, issue.defect)
+                })
+                .collect();
+            CheckResult::warn(
+                "identity_hashes",
+                format!("stored identities are not hashes: {}", listed.join(", ")),
+            )
+        }
+        Err(err) => CheckResult::fail("identity_hashes", format!("query failed: {err}")),
+    }
+}
+
+pub async fn check_bridge(bridge: &dyn RpcMeshBridge, timeout: Duration) -> CheckResult {
+    match tokio::time::timeout(timeout, bridge.query_receipt("doctor-probe")).await {
+        Ok(Ok(_)) => CheckResult::pass("bridge", "daemon RPC answered receipt probe"),
+        Ok(Err(err)) => CheckResult::fail("bridge", format!("daemon RPC probe failed: {err}")),
+        Err(_) => CheckResult::fail(
+            "bridge",
+            format!("daemon RPC probe timed out after {}ms", timeout.as_millis()),
+        ),
+    }
+}
+
+pub fn check_disk_space(path: &Path, min_free_bytes: u64) -> CheckResult {
+    let probe = existing_ancestor(path);
+    match fs2::available_space(&probe) {
+        Ok(free) if free >= min_free_bytes => CheckResult::pass(
+            "disk_space",
+            format!("{} MiB free at {}", free / (1024 * 1024), probe.display()),
+        ),
+        Ok(free) => CheckResult::warn(
+            "disk_space",
+            format!(
+                "only {} MiB free at {} (threshold {} MiB)",
+                free / (1024 * 1024),
+                probe.display(),
+                min_free_bytes / (1024 * 1024)
+            ),
+        ),
+        Err(err) => CheckResult::warn(
+            "disk_space",
+            format!("unable to query free space at {}: {err}", probe.display()),
+        ),
+    }
+}
+
+pub fn check_clock(reference_files: &[&Path]) -> CheckResult {
+    let now = SystemTime::now();
+    let newest = reference_files
+        .iter()
+        .filter_map(|path| std::fs::metadata(path).and_then(|meta| meta.modified()).ok())
+        .max();
+
+    match newest {
+        Some(mtime) if mtime > now + CLOCK_SKEW_TOLERANCE => {
+            let mtime: DateTime<Utc> = mtime.into();
+            CheckResult::fail(
+                "clock",
+                format!(
+                    "system clock is behind file modification time {}",
+                    mtime.to_rfc3339()
+                ),
+            )
+        }
+        Some(_) => CheckResult::pass("clock", "system clock is not behind local file times"),
+        None => CheckResult::warn("clock", "no reference files available for clock check"),
+    }
+}
+
+fn existing_ancestor(path: &Path) -> std::path::PathBuf {
+    let absolute = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        std::env::current_dir()
+            .map(|cwd| cwd.join(path))
+            .unwrap_or_else(|_| path.to_path_buf())
+    };
+    absolute
+        .ancestors()
+        .find(|candidate| candidate.exists())
+        .map(Path::to_path_buf)
+        .unwrap_or(absolute)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_bridge, check_contract, CheckStatus};
+    use retasync_mesh_bridge::{
+        InMemoryRpcMeshBridge, LossProfile, SimulatedMeshBridge, SimulationProfile,
+    };
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn contract_without_commands_fails() {
+        let check = check_contract("\u{feff}asyncapi: 3.0.0\n");
+        assert_eq!(check.status, CheckStatus::Fail);
+    }
+
+    #[tokio::test]
+    async fn unreachable_bridge_fails() {
+        let bridge = SimulatedMeshBridge::new(
+            Arc::new(InMemoryRpcMeshBridge::new(true, true)),
+            SimulationProfile {
+                loss: LossProfile {
+                    query_receipt: 1.0,
+                    ..LossProfile::default()
+                },
+                ..SimulationProfile::default()
+            },
+        );
+        let check = check_bridge(&bridge, Duration::from_millis(100)).await;
+        assert_eq!(check.status, CheckStatus::Fail);
+    }
+}
@@ -0,0 +1,349 @@
+﻿use std::collections::BTreeMap;
+
+use retasync_contract::{
+    EnvelopeMeta, IdentityHash, IdentityHashError, TransferHint, LOCAL_NODE_IDENTITY,
+    MESH_IDENTITY,
+};
+use retasync_mesh_bridge::{CancelOutcome, Compatibility};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::delivery::EffectiveDelivery;
+use crate::escalation::EscalationRecord;
+use crate::files::validate_file_settings;
+use crate::liveness::{contact_window, LivenessCheck, REQUIRE_RECENT_CONTACT_FIELD};
+use crate::sneakernet::validate_sneakernet_settings;
+use crate::trace::TRACING_ENABLED_FIELD;
+use crate::transforms::validate_transforms;
+use crate::NodeConfig;
+
+pub const FALLBACK_SOURCE_IDENTITY: &str = LOCAL_NODE_IDENTITY;
+pub const FALLBACK_DESTINATION_IDENTITY: &str = MESH_IDENTITY;
+
+// `source_identity` stands in for the node keypair until identities are derived from it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IdentitySettings {
+    pub source_identity: Option<String>,
+    pub default_destination: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OperationDefaults {
+    pub destination_identity: Option<String>,
+    pub ttl_ms: Option<u64>,
+    pub transport_hint: Option<TransferHint>,
+    pub require_recent_contact_s: Option<u64>,
+}
+
+// The values a command was actually sent with, and which of them came from config.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Dispatch {
+    pub source_identity: IdentityHash,
+    pub destination_identity: IdentityHash,
+    pub ttl_ms: Option<u64>,
+    pub transport_hint: Option<TransferHint>,
+    pub matched_pattern: Option<String>,
+    pub defaulted: Vec<String>,
+    // Set when the peer's last handshake was not fully compatible.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peer_compatibility: Option<Compatibility>,
+    #[serde(default)]
+    pub delivery: EffectiveDelivery,
+    #[serde(default, skip_serializing_if = "LivenessCheck::is_unset")]
+    pub liveness: LivenessCheck,
+    // Asks every node on the path to add a hop record to the envelope.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub tracing_enabled: bool,
+    // The metadata block the envelope carries, from the submission's headers and fields.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub meta: Option<EnvelopeMeta>,
+    // Store-and-forward escalation steps, oldest first; empty unless the peer was unreachable.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub escalations: Vec<EscalationRecord>,
+    // What the mesh said when the job was cancelled after its envelope went out.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mesh_cancel_outcome: Option<CancelOutcome>,
+}
+
+pub fn local_identity(config: &NodeConfig) -> IdentityHash {
+    let configured = config.identity.source_identity.as_deref();
+    IdentityHash::lenient(configured.unwrap_or(FALLBACK_SOURCE_IDENTITY))
+}
+
+// Fails only when the payload names a destination that is not an identity hash.
+pub fn resolve_dispatch(
+    config: &NodeConfig,
+    operation: &str,
+    payload: &Value,
+) -> Result<Dispatch, IdentityHashError> {
+    let matched = best_match(&config.operation_defaults, operation);
+    let defaults = matched.map(|(_, defaults)| defaults);
+    let mut defaulted = Vec::new();
+
+    let requested_destination = payload
+        .get("destination_identity")
+        .and_then(Value::as_str)
+        .map(|destination| IdentityHash::parse_as(destination, config.identity_validation))
+        .transpose()?;
+    let destination_identity = match requested_destination {
+        Some(destination) => destination,
+        None => {
+            defaulted.push("destination_identity".to_string());
+            let configured = defaults
+                .and_then(|defaults| defaults.destination_identity.as_deref())
+                .or(config.identity.default_destination.as_deref());
+            IdentityHash::lenient(configured.unwrap_or(FALLBACK_DESTINATION_IDENTITY))
+        }
+    };
+
+    let ttl_ms = payload.get("ttl_ms").and_then(Value::as_u64).or_else(|| {
+        let ttl_ms = defaults.and_then(|defaults| defaults.ttl_ms);
+        if ttl_ms.is_some() {
+            defaulted.push("ttl_ms".to_string());
+        }
+        ttl_ms
+    });
+    let requested_hint = payload
+        .get("transport_hint")
+        .cloned()
+        .and_then(|hint| serde_json::from_value::<TransferHint>(hint).ok());
+    let transport_hint = requested_hint.or_else(|| {
+        let hint = defaults.and_then(|defaults| defaults.transport_hint.clone());
+        if hint.is_some() {
+            defaulted.push("transport_hint".to_string());
+        }
+        hint
+    });
+    let require_recent_contact_s = payload
+        .get(REQUIRE_RECENT_CONTACT_FIELD)
+        .and_then(Value::as_u64)
+        .or_else(|| {
+            let window = defaults.and_then(|defaults| defaults.require_recent_contact_s);
+            if window.is_some() {
+                defaulted.push(REQUIRE_RECENT_CONTACT_FIELD.to_string());
+            }
+            window
+        });
+
+    Ok(Dispatch {
+        source_identity: local_identity(config),
+        destination_identity,
+        ttl_ms,
+        transport_hint,
+        matched_pattern: matched.map(|(pattern, _)| pattern.clone()),
+        defaulted,
+        peer_compatibility: None,
+        delivery: EffectiveDelivery::default(),
+        liveness: LivenessCheck {
+            require_recent_contact_s,
+            ..LivenessCheck::default()
+        },
+        tracing_enabled: payload
+            .get(TRACING_ENABLED_FIELD)
+            .and_then(Value::as_bool)
+            .unwrap_or(false),
+        meta: None,
+        escalations: Vec::new(),
+        mesh_cancel_outcome: None,
+    })
+}
+
+pub fn validate_dispatch_config(config: &NodeConfig) -> Result<(), String> {
+    let identities = [
+        ("identity.source_identity", &config.identity.source_identity),
+        ("identity.default_destination", &config.identity.default_destination),
+        ("fleet.collector", &config.fleet.collector),
+    ];
+    for (field, identity) in identities {
+        if let Some(identity) = identity {
+            if let Err(err) = IdentityHash::parse(identity) {
+                return Err(format!("{field} {identity} is not an identity hash: {err}"));
+            }
+        }
+    }
+    for (pattern, defaults) in &config.operation_defaults {
+        if !is_operation_pattern(pattern) {
+            return Err(format!("operation_defaults pattern {pattern:?} is malformed"));
+        }
+        if let Some(destination) = &defaults.destination_identity {
+            if let Err(err) = IdentityHash::parse(destination) {
+                return Err(format!(
+                    "operation_defaults.{pattern}.destination_identity {destination} is not an \
+                     identity hash: {err}"
+                ));
+            }
+        }
+        if let Some(window_s) = defaults.require_recent_contact_s {
+            if contact_window(window_s).is_err() {
+                return Err(format!(
+                    "operation_defaults.{pattern}.require_recent_contact_s {window_s} is too large"
+                ));
+            }
+        }
+    }
+    validate_file_settings(&config.files)?;
+    validate_sneakernet_settings(&config.sneakernet)?;
+    validate_transforms(&config.transforms)
+}
+
+// A real peer rather than a reserved name, in any case and with surrounding whitespace.
+pub fn is_identity_hash(value: &str) -> bool {
+    IdentityHash::parse(value).is_ok()
+}
+
+// `event.create` matches exactly, `event.*` matches anything under `event.`, `*` matches all.
+pub fn is_operation_pattern(pattern: &str) -> bool {
+    let segments: Vec<&str> = pattern.split('.').collect();
+    let last = segments.len() - 1;
+    segments.iter().enumerate().all(|(index, segment)| {
+        (index == last && *segment == "*")
+            || (!segment.is_empty()
+                && segment
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-'))
+    })
+}
+
+// Exact patterns win, then the longest wildcard prefix, then the bare `*`.
+fn best_match<'a>(
+    defaults: &'a BTreeMap<String, OperationDefaults>,
+    operation: &str,
+) -> Option<(&'a String, &'a OperationDefaults)> {
+    if let Some(exact) = defaults.get_key_value(operation) {
+        return Some(exact);
+    }
+    defaults
+        .iter()
+        .filter_map(|(pattern, defaults)| {
+            let prefix = pattern.strip_suffix('*')?;
+            let matches = prefix.is_empty() || operation.starts_with(prefix);
+            matches.then_some((prefix.len(), (pattern, defaults)))
+        })
+        .max_by_key(|(specificity, _)| *specificity)
+        .map(|(_, matched)| matched)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        is_operation_pattern, resolve_dispatch, validate_dispatch_config, IdentitySettings,
+        OperationDefaults,
+    };
+    use crate::test_support::node_config;
+    use crate::NodeConfig;
+    use retasync_contract::TransferHint;
+    use serde_json::json;
+
+    const HUB: &str = "aa00000000000000000000000000000a";
+    const LEAD: &str = "bb00000000000000000000000000000b";
+    const FALLBACK: &str = "cc00000000000000000000000000000c";
+
+    fn config() -> NodeConfig {
+        let mut config = node_config(json!({}));
+        config.identity = IdentitySettings {
+            source_identity: Some("dd00000000000000000000000000000d".to_string()),
+            default_destination: Some(FALLBACK.to_string()),
+        };
+        config.operation_defaults.insert(
+            "telemetry.*".to_string(),
+            OperationDefaults {
+                destination_identity: Some(HUB.to_string()),
+                ttl_ms: Some(60_000),
+                transport_hint: Some(TransferHint::Lxmf),
+                require_recent_contact_s: None,
+            },
+        );
+        config.operation_defaults.insert(
+            "eam.broadcast".to_string(),
+            OperationDefaults {
+                destination_identity: Some(LEAD.to_string()),
+                ..OperationDefaults::default()
+            },
+        );
+        config.operation_defaults.insert(
+            "eam.*".to_string(),
+            OperationDefaults {
+                destination_identity: Some(HUB.to_string()),
+                ttl_ms: Some(5_000),
+                ..OperationDefaults::default()
+            },
+        );
+        config
+    }
+
+    #[test]
+    fn request_values_beat_operation_defaults_which_beat_global_defaults() {
+        let config = config();
+
+        let explicit = resolve_dispatch(
+            &config,
+            "telemetry.report",
+            &json!({ "destination_identity": LEAD, "ttl_ms": 10, "transport_hint": "link" }),
+        )
+        .unwrap();
+        assert_eq!(explicit.destination_identity, LEAD);
+        assert_eq!(explicit.ttl_ms, Some(10));
+        assert_eq!(explicit.transport_hint, Some(TransferHint::Link));
+        assert!(explicit.defaulted.is_empty());
+
+        let payload = json!({ "ttl_ms": 10 });
+        let defaulted = resolve_dispatch(&config, "telemetry.report", &payload).unwrap();
+        assert_eq!(defaulted.destination_identity, HUB);
+        assert_eq!(defaulted.ttl_ms, Some(10));
+        assert_eq!(defaulted.transport_hint, Some(TransferHint::Lxmf));
+        assert_eq!(defaulted.matched_pattern.as_deref(), Some("telemetry.*"));
+        assert_eq!(defaulted.defaulted, ["destination_identity", "transport_hint"]);
+
+        let global = resolve_dispatch(&config, "event.create", &json!({})).unwrap();
+        assert_eq!(global.destination_identity, FALLBACK);
+        assert_eq!(global.ttl_ms, None);
+        assert_eq!(global.matched_pattern, None);
+        assert_eq!(global.source_identity, "dd00000000000000000000000000000d");
+    }
+
+    #[test]
+    fn exact_patterns_beat_wildcards() {
+        let mut config = config();
+        config
+            .operation_defaults
+            .insert("*".to_string(), OperationDefaults::default());
+
+        let exact = resolve_dispatch(&config, "eam.broadcast", &json!({})).unwrap();
+        assert_eq!(exact.destination_identity, LEAD);
+        assert_eq!(exact.ttl_ms, None);
+        assert_eq!(exact.matched_pattern.as_deref(), Some("eam.broadcast"));
+
+        let wildcard = resolve_dispatch(&config, "eam.status", &json!({})).unwrap();
+        assert_eq!(wildcard.destination_identity, HUB);
+        assert_eq!(wildcard.matched_pattern.as_deref(), Some("eam.*"));
+
+        let catch_all = resolve_dispatch(&config, "event.create", &json!({})).unwrap();
+        assert_eq!(catch_all.matched_pattern.as_deref(), Some("*"));
+        assert_eq!(catch_all.destination_identity, FALLBACK);
+    }
+
+    #[test]
+    fn validation_rejects_bad_patterns_and_identities() {
+        assert!(validate_dispatch_config(&config()).is_ok());
+        for pattern in ["event.*", "*", "event.create", "eam.team-lead"] {
+            assert!(is_operation_pattern(pattern), "{pattern}");
+        }
+        for pattern in ["", "event.", "*.create", "event.*.x", "event create", "ev*"] {
+            assert!(!is_operation_pattern(pattern), "{pattern}");
+        }
+
+        let mut config = config();
+        config.operation_defaults.insert(
+            "ops.*".to_string(),
+            OperationDefaults {
+                destination_identity: Some("hub".to_string()),
+                ..OperationDefaults::default()
+            },
+        );
+        assert!(validate_dispatch_config(&config)
+            .unwrap_err()
+            .contains("ops.*"));
+    }
+}
@@ -0,0 +1,477 @@
+﻿use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+
+use retasync_contract::MetaPriority;
+use retasync_storage::{JobHold, JobRecord, FEED_JOB_EVENT};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::watch;
+
+use crate::app::{emit, publish, redeliver_command_job, write_log};
+use crate::circuit::DEFERRED_STATUS;
+use crate::dependencies::is_terminal;
+use crate::dispatch::Dispatch;
+use crate::AppState;
+
+pub const HELD_STATUS: &str = "held";
+pub const JOB_PRIORITIZED_EVENT: &str = "job.queue.prioritized";
+pub const QUEUE_ORDER_CHANGED_EVENT: &str = "node.queue.order_changed";
+pub const JOB_NOT_QUEUED_ERROR: &str = "job_not_queued";
+pub const JOB_NOT_HELD_ERROR: &str = "job_not_held";
+pub const REASON_REQUIRED_ERROR: &str = "reason_required";
+pub const DUPLICATE_JOB_ERROR: &str = "duplicate_job_id";
+
+// The `[dispatch_queue]` section.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DispatchQueueSettings {
+    // Command jobs dispatched at once; unset sends every job as soon as it is queued.
+    pub max_concurrent_jobs: Option<NonZeroUsize>,
+}
+
+#[derive(Debug)]
+struct Waiter {
+    priority: MetaPriority,
+    arrived: u64,
+}
+
+#[derive(Debug, Default)]
+struct Lineup {
+    limit: Option<NonZeroUsize>,
+    running: BTreeSet<String>,
+    arrivals: u64,
+    waiting: BTreeMap<String, Waiter>,
+    held: BTreeSet<String>,
+    // The operator's order, ahead of priority; each entry is consumed when its job goes out.
+    order: VecDeque<String>,
+    // Waiting jobs that finished some other way; their workers leave the line without a slot.
+    finished: BTreeSet<String>,
+}
+
+impl Lineup {
+    fn ready(&self, job_id: &str) -> bool {
+        self.waiting.contains_key(job_id)
+            && !self.held.contains(job_id)
+            && !self.finished.contains(job_id)
+    }
+
+    // Waiting jobs in the order they go out: the operator's order, then priority, then arrival.
+    fn ordered(&self) -> Vec<&str> {
+        let mut ordered: Vec<&str> = self
+            .order
+            .iter()
+            .map(String::as_str)
+            .filter(|job_id| self.ready(job_id))
+            .collect();
+        let mut rest: Vec<(&String, &Waiter)> = self
+            .waiting
+            .iter()
+            .filter(|(job_id, _)| self.ready(job_id) && !self.order.contains(job_id))
+            .collect();
+        rest.sort_by_key(|(_, waiter)| (Reverse(waiter.priority), waiter.arrived));
+        ordered.extend(rest.into_iter().map(|(job_id, _)| job_id.as_str()));
+        ordered
+    }
+
+    fn next_up(&self) -> Option<&str> {
+        if self.limit.is_some_and(|limit| self.running.len() >= limit.get()) {
+            return None;
+        }
+        self.ordered().first().copied()
+    }
+}
+
+// Command jobs waiting for a dispatch slot. Every waiter watches the same lineup and goes out
+// when it is first in line and a slot is free.
+#[derive(Debug)]
+pub struct DispatchQueue {
+    gate: watch::Sender<Lineup>,
+}
+
+impl Default for DispatchQueue {
+    fn default() -> Self {
+        Self {
+            gate: watch::Sender::new(Lineup::default()),
+        }
+    }
+}
+
+// A place in the lineup, then a dispatch slot once `running`. Dropping it gives either back,
+// so a worker that dies while waiting does not hold up the jobs behind it.
+pub struct QueueSlot {
+    queue: Arc<DispatchQueue>,
+    job_id: String,
+    running: bool,
+}
+
+impl Drop for QueueSlot {
+    fn drop(&mut self) {
+        let (job_id, running) = (&self.job_id, self.running);
+        self.queue.gate.send_modify(|lineup| {
+            if running {
+                lineup.running.remove(job_id);
+            } else {
+                lineup.waiting.remove(job_id);
+                lineup.finished.remove(job_id);
+            }
+        });
+    }
+}
+
+impl DispatchQueue {
+    // Waits until `job_id` is first in line and fewer than `limit` jobs are out.
+    pub async fn enter(
+        self: &Arc<Self>,
+        job_id: &str,
+        priority: Option<MetaPriority>,
+        limit: Option<NonZeroUsize>,
+    ) -> QueueSlot {
+        self.gate.send_modify(|lineup| {
+            lineup.limit = limit;
+            let waiter = Waiter {
+                priority: priority.unwrap_or(MetaPriority::Routine),
+                arrived: lineup.arrivals,
+            };
+            lineup.arrivals += 1;
+            lineup.waiting.insert(job_id.to_string(), waiter);
+        });
+        let mut slot = QueueSlot {
+            queue: Arc::clone(self),
+            job_id: job_id.to_string(),
+            running: false,
+        };
+        let mut gate = self.gate.subscribe();
+        loop {
+            // The sender lives as long as the state, so this only fails during shutdown.
+            let called = gate.wait_for(|lineup| {
+                lineup.next_up() == Some(job_id) || lineup.finished.contains(job_id)
+            });
+            if called.await.is_err() {
+                return slot;
+            }
+            let mut finished = false;
+            // A hold may land between the wait and the claim.
+            let claimed = self.gate.send_if_modified(|lineup| {
+                if lineup.finished.remove(job_id) {
+                    lineup.waiting.remove(job_id);
+                    finished = true;
+                    return true;
+                }
+                if lineup.next_up() != Some(job_id) {
+                    return false;
+                }
+                lineup.waiting.remove(job_id);
+                lineup.order.retain(|ordered| ordered != job_id);
+                lineup.running.insert(job_id.to_string());
+                true
+            });
+            if finished {
+                return slot;
+            }
+            if claimed {
+                slot.running = true;
+                return slot;
+            }
+        }
+    }
+
+    pub fn is_waiting(&self, job_id: &str) -> bool {
+        self.gate.borrow().waiting.contains_key(job_id)
+    }
+
+    // Keeps a waiting job in line without letting it out; false when it has gone out already.
+    fn hold(&self, job_id: &str) -> bool {
+        let mut held = false;
+        self.gate.send_if_modified(|lineup| {
+            held = !lineup.running.contains(job_id);
+            held && lineup.held.insert(job_id.to_string())
+        });
+        held
+    }
+
+    fn release(&self, job_id: &str) {
+        self.gate.send_if_modified(|lineup| lineup.held.remove(job_id));
+    }
+
+    // A job that has gone out already has no line left to jump.
+    fn prioritize(&self, job_id: &str) {
+        self.gate.send_if_modified(|lineup| {
+            if lineup.running.contains(job_id) {
+                return false;
+            }
+            lineup.order.retain(|ordered| ordered != job_id);
+            lineup.order.push_front(job_id.to_string());
+            true
+        });
+    }
+
+    fn set_order(&self, order: Vec<String>) {
+        self.gate.send_modify(|lineup| {
+            let order = order.into_iter().filter(|job_id| !lineup.running.contains(job_id));
+            lineup.order = order.collect();
+        });
+    }
+
+    // Drops a job that will never be dispatched, such as a cancelled one, from the operator's
+    // order and the holds. A worker still waiting for it leaves the line at once, without a
+    // slot, and finds the job finished.
+    pub fn forget(&self, job_id: &str) {
+        self.gate.send_if_modified(|lineup| {
+            let before = lineup.order.len();
+            lineup.order.retain(|ordered| ordered != job_id);
+            let finished =
+                lineup.waiting.contains_key(job_id) && lineup.finished.insert(job_id.to_string());
+            lineup.held.remove(job_id) || lineup.order.len() != before || finished
+        });
+    }
+}
+
+// Every published feed event passes through here, so a job finished by any path, such as a
+// cancellation, an expiry or a failed dependency, leaves the lineup.
+pub fn forget_finished(state: &AppState, event_type: &str, data: &Value) {
+    if event_type != FEED_JOB_EVENT {
+        return;
+    }
+    let status = data.get("status").and_then(Value::as_str).unwrap_or_default();
+    if let Some(job_id) = data.get("job_id").and_then(Value::as_str) {
+        if is_terminal(status) {
+            state.dispatch_queue.forget(job_id);
+        }
+    }
+}
+
+// The `dispatch` block of `GET /v1/node/queue`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DispatchQueueView {
+    pub max_concurrent_jobs: Option<NonZeroUsize>,
+    pub running: Vec<String>,
+    // Jobs waiting for a slot, in the order they will go out.
+    pub next: Vec<String>,
+    // What is left of the operator's order, including jobs not waiting yet.
+    pub order: Vec<String>,
+    pub held: Vec<JobHold>,
+}
+
+pub async fn dispatch_view(state: &AppState) -> anyhow::Result<DispatchQueueView> {
+    let max_concurrent_jobs = state.node_config.read().await.dispatch_queue.max_concurrent_jobs;
+    let held = state.storage.list_job_holds().await?;
+    let lineup = state.dispatch_queue.gate.borrow();
+    Ok(DispatchQueueView {
+        max_concurrent_jobs,
+        running: lineup.running.iter().cloned().collect(),
+        next: lineup.ordered().into_iter().map(str::to_string).collect(),
+        order: lineup.order.iter().cloned().collect(),
+        held,
+    })
+}
+
+// Why a queue change was refused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueueRefusal {
+    NotFound(String),
+    NotQueued { job_id: String, status: String },
+    NotHeld { job_id: String, status: String },
+    Duplicate(String),
+}
+
+// Who changed the queue and why; kept on the event every change emits.
+#[derive(Debug, Clone)]
+pub struct QueueChange {
+    pub by: String,
+    pub reason: Option<String>,
+}
+
+// Reads the required `reason` of a queue change body.
+pub fn required_reason(body: &Value) -> Option<String> {
+    body.get("reason")
+        .and_then(Value::as_str)
+        .map(str::trim)
+        .filter(|reason| !reason.is_empty())
+        .map(str::to_string)
+}
+
+// Puts a queued or deferred job at the head of the line. A deferred job is requeued first,
+// ahead of its circuit's probe.
+pub async fn prioritize(
+    state: &AppState,
+    job_id: &str,
+    change: QueueChange,
+) -> anyhow::Result<Result<DispatchQueueView, QueueRefusal>> {
+    let Some(job) = state.storage.get_job(job_id).await? else {
+        return Ok(Err(QueueRefusal::NotFound(job_id.to_string())));
+    };
+    let previous_status = job.status.clone();
+    match job.status.as_str() {
+        "queued" => state.dispatch_queue.prioritize(job_id),
+        DEFERRED_STATUS => {
+            state.dispatch_queue.prioritize(job_id);
+            let requeued = json!({ "job_id": job_id, "status": "queued", "released": true });
+            if !state
+                .storage
+                .with_event(FEED_JOB_EVENT, requeued)
+                .requeue_deferred_job(job_id)
+                .await?
+            {
+                state.dispatch_queue.forget(job_id);
+                return Ok(Err(not_queued(state, job_id).await?));
+            }
+            publish(state).await;
+            redeliver(state, job)?;
+        }
+        _ => return Ok(Err(not_queued(state, job_id).await?)),
+    }
+    emit(
+        state,
+        JOB_PRIORITIZED_EVENT,
+        json!({
+            "job_id": job_id,
+            "previous_status": previous_status,
+            "prioritized_by": change.by,
+            "reason": change.reason,
+        }),
+    )
+    .await;
+    let message = format!("job {job_id} moved to the head of the queue by {}", change.by);
+    write_log(state, "warn", &message).await;
+    Ok(Ok(dispatch_view(state).await?))
+}
+
+// Parks a queued or deferred job in `held`, where no worker dispatches it until it is
+// released. The hold is stored, so it outlasts a restart.
+pub async fn hold(
+    state: &AppState,
+    job_id: &str,
+    change: QueueChange,
+) -> anyhow::Result<Result<JobHold, QueueRefusal>> {
+    if state.storage.get_job(job_id).await?.is_none() {
+        return Ok(Err(QueueRefusal::NotFound(job_id.to_string())));
+    }
+    if !state.dispatch_queue.hold(job_id) {
+        return Ok(Err(not_queued(state, job_id).await?));
+    }
+    let reason = change.reason.unwrap_or_default();
+    let held = state
+        .storage
+        .with_event(
+            FEED_JOB_EVENT,
+            json!({
+                "job_id": job_id,
+                "status": HELD_STATUS,
+                "held_by": change.by,
+                "reason": reason,
+            }),
+        )
+        .hold_job(job_id, &change.by, &reason)
+        .await
+        .inspect_err(|_| state.dispatch_queue.release(job_id))?;
+    if held.is_none() {
+        state.dispatch_queue.release(job_id);
+        return Ok(Err(not_queued(state, job_id).await?));
+    }
+    publish(state).await;
+    write_log(state, "warn", &format!("job {job_id} held by {}: {reason}", change.by)).await;
+    let hold = find_hold(state, job_id).await?;
+    Ok(Ok(hold.ok_or_else(|| anyhow::anyhow!("hold on job {job_id} vanished"))?))
+}
+
+// Puts a held job back where it was held from. A queued job whose worker did not survive a
+// restart gets a new one.
+pub async fn release(
+    state: &AppState,
+    job_id: &str,
+    change: QueueChange,
+) -> anyhow::Result<Result<String, QueueRefusal>> {
+    let Some(job) = state.storage.get_job(job_id).await? else {
+        return Ok(Err(QueueRefusal::NotFound(job_id.to_string())));
+    };
+    let not_held = |job: JobRecord| QueueRefusal::NotHeld {
+        job_id: job.job_id,
+        status: job.status,
+    };
+    let Some(hold) = find_hold(state, job_id).await? else {
+        return Ok(Err(not_held(job)));
+    };
+    let released = state
+        .storage
+        .with_event(
+            FEED_JOB_EVENT,
+            json!({
+                "job_id": job_id,
+                "status": hold.previous_status,
+                "released": true,
+                "released_by": change.by,
+                "reason": change.reason,
+            }),
+        )
+        .release_job_hold(job_id)
+        .await?;
+    let Some(status) = released else {
+        return Ok(Err(not_held(job)));
+    };
+    // Looked at before the release, since a waiting worker may go out as soon as it lands.
+    let waiting = state.dispatch_queue.is_waiting(job_id);
+    state.dispatch_queue.release(job_id);
+    publish(state).await;
+    if status == "queued" && !waiting {
+        redeliver(state, job)?;
+    }
+    write_log(state, "info", &format!("job {job_id} released by {}", change.by)).await;
+    Ok(Ok(status))
+}
+
+// Sends the listed queued jobs next, in that order, ahead of priority. Each entry is consumed
+// as its job goes out; an empty list clears the order.
+pub async fn set_order(
+    state: &AppState,
+    job_ids: Vec<String>,
+    change: QueueChange,
+) -> anyhow::Result<Result<DispatchQueueView, QueueRefusal>> {
+    let mut seen = BTreeSet::new();
+    for job_id in &job_ids {
+        if !seen.insert(job_id.as_str()) {
+            return Ok(Err(QueueRefusal::Duplicate(job_id.clone())));
+        }
+        let Some(job) = state.storage.get_job(job_id).await? else {
+            return Ok(Err(QueueRefusal::NotFound(job_id.clone())));
+        };
+        if job.status != "queued" {
+            let job_id = job_id.clone();
+            return Ok(Err(QueueRefusal::NotQueued { job_id, status: job.status }));
+        }
+    }
+    state.dispatch_queue.set_order(job_ids.clone());
+    emit(
+        state,
+        QUEUE_ORDER_CHANGED_EVENT,
+        json!({ "order": job_ids, "changed_by": change.by, "reason": change.reason }),
+    )
+    .await;
+    let message = format!("dispatch order set by {}: {}", change.by, job_ids.join(", "));
+    write_log(state, "warn", &message).await;
+    Ok(Ok(dispatch_view(state).await?))
+}
+
+async fn not_queued(state: &AppState, job_id: &str) -> anyhow::Result<QueueRefusal> {
+    let job_id = job_id.to_string();
+    Ok(match state.storage.get_job(&job_id).await? {
+        Some(job) => QueueRefusal::NotQueued { job_id, status: job.status },
+        None => QueueRefusal::NotFound(job_id),
+    })
+}
+
+async fn find_hold(state: &AppState, job_id: &str) -> anyhow::Result<Option<JobHold>> {
+    let holds = state.storage.list_job_holds().await?;
+    Ok(holds.into_iter().find(|hold| hold.job_id == job_id))
+}
+
+fn redeliver(state: &AppState, job: JobRecord) -> anyhow::Result<()> {
+    let Some(dispatch) = job.dispatch_json.as_deref() else {
+        return Ok(());
+    };
+    let dispatch: Dispatch = serde_json::from_str(dispatch)?;
+    let payload: Value = serde_json::from_str(&job.payload_json)?;
+    redeliver_command_job(state, job.job_id, job.operation, payload, dispatch);
+    Ok(())
+}
@@ -0,0 +1,148 @@
+﻿use axum::http::StatusCode;
+use retasync_contract::{IdentityHash, TransferHint};
+use retasync_mesh_bridge::TransportSelection;
+use serde::Serialize;
+use serde_json::Value;
+
+// Stages after `rate_limit` for a single submission, and after `entry` for a batch entry.
+pub const COMMAND_STAGES: [&str; 9] = [
+    "operation",
+    "meta",
+    "attachments",
+    "dependencies",
+    "delivery",
+    "routing",
+    "quota",
+    "transforms",
+    "envelope",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StageOutcome {
+    Passed,
+    Failed,
+    // An earlier stage failed and this one had nothing to work on.
+    Skipped,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StageReport {
+    pub stage: &'static str,
+    pub outcome: StageOutcome,
+    // Set when the real submission would be refused with this status; stages that fail
+    // after acceptance, such as an oversize envelope, fail the job instead.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub http_status: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<Value>,
+}
+
+// The envelope the command would be sent in, minus its payload and message id.
+#[derive(Debug, Clone, Serialize)]
+pub struct EnvelopePreview {
+    pub operation: String,
+    // The contract's channel address for the operation.
+    pub channel: String,
+    pub source_identity: IdentityHash,
+    pub destination_identity: IdentityHash,
+    pub content_type: String,
+    pub ttl_ms: Option<u64>,
+    pub transport_hint: Option<TransferHint>,
+    pub transport: TransportSelection,
+    pub size_bytes: usize,
+    pub limit_bytes: usize,
+}
+
+// Every stage is reported, failed or not, so a client can show all problems at once.
+#[derive(Debug, Clone, Serialize)]
+pub struct DryRunReport {
+    pub dry_run: bool,
+    pub passed: bool,
+    pub stages: Vec<StageReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub envelope: Option<EnvelopePreview>,
+}
+
+impl Default for DryRunReport {
+    fn default() -> Self {
+        Self {
+            dry_run: true,
+            passed: true,
+            stages: Vec::new(),
+            envelope: None,
+        }
+    }
+}
+
+impl DryRunReport {
+    pub fn pass(&mut self, stage: &'static str, detail: Option<Value>) {
+        self.stages.push(StageReport {
+            stage,
+            outcome: StageOutcome::Passed,
+            http_status: None,
+            error: None,
+            detail,
+        });
+    }
+
+    pub fn fail(&mut self, stage: &'static str, status: Option<StatusCode>, error: Value) {
+        self.passed = false;
+        self.stages.push(StageReport {
+            stage,
+            outcome: StageOutcome::Failed,
+            http_status: status.map(|status| status.as_u16()),
+            error: Some(error),
+            detail: None,
+        });
+    }
+
+    pub fn skip(&mut self, stage: &'static str) {
+        self.stages.push(StageReport {
+            stage,
+            outcome: StageOutcome::Skipped,
+            http_status: None,
+            error: None,
+            detail: None,
+        });
+    }
+
+    // Records a stage that refuses the submission the way its handler would.
+    pub fn check<T>(
+        &mut self,
+        stage: &'static str,
+        result: Result<T, (StatusCode, axum::Json<Value>)>,
+    ) -> Option<T> {
+        match result {
+            Ok(value) => {
+                self.pass(stage, None);
+                Some(value)
+            }
+            Err((status, axum::Json(error))) => {
+                self.fail(stage, Some(status), error);
+                None
+            }
+        }
+    }
+
+    // The most recent report of `stage`, for adding what it resolved.
+    pub fn detail(&mut self, stage: &'static str, detail: Value) {
+        if let Some(report) = self
+            .stages
+            .iter_mut()
+            .rev()
+            .find(|report| report.stage == stage)
+        {
+            report.detail = Some(detail);
+        }
+    }
+
+    // Whether a stage would have the submission refused outright rather than fail the job.
+    pub fn refused(&self) -> bool {
+        self.stages
+            .iter()
+            .any(|report| report.http_status.is_some())
+    }
+}
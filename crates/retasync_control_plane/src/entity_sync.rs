@@ -0,0 +1,453 @@
+﻿use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context};
+use chrono::{DateTime, Utc};
+use retasync_contract::{
+    canonical_digest, encode_canonical, EnvelopeMeta, MeshCommandEnvelope, CONTENT_TYPE_MSGPACK,
+};
+use retasync_storage::{EntityRecord, StorageError, SyncConflict};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::dispatch::resolve_dispatch;
+use crate::labels::{label_entity, Labels};
+use crate::AppState;
+
+pub const ENTITY_SYNC_REQUEST_OPERATION: &str = "entity.sync_request";
+pub const ENTITY_SYNC_RESPONSE_OPERATION: &str = "entity.sync_response";
+pub const SYNC_ROUND_TIMEOUT: Duration = Duration::from_secs(10);
+pub const DEFAULT_MAX_ROUNDS: usize = 16;
+pub const DEFAULT_MAX_RESPONSE_SIZE: usize = 32 * 1024;
+pub const SYNC_NOT_ALLOWLISTED_ERROR: &str = "requester_not_allowlisted";
+
+// One round of the exchange. Buckets are entity ids cut to `depth` characters, and only ids
+// under one of the `scope` prefixes take part. `records` are the requester's winners from the
+// previous round, which the responder stores before comparing.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EntitySyncRequest {
+    pub entity_type: String,
+    pub depth: usize,
+    pub scope: Vec<String>,
+    pub digests: BTreeMap<String, String>,
+    #[serde(default)]
+    pub records: Vec<EntityRecord>,
+    pub max_response_size: usize,
+}
+
+// Differing buckets either come back in full (`complete`) or, when they hold more than the
+// response has room for, are named in `subdivide` for the next, deeper round.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EntitySyncResponse {
+    pub entity_type: String,
+    pub complete: Vec<String>,
+    pub subdivide: Vec<String>,
+    pub records: Vec<EntityRecord>,
+    pub applied: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyncLimits {
+    pub max_rounds: usize,
+    pub max_response_size: usize,
+}
+
+impl Default for SyncLimits {
+    fn default() -> Self {
+        Self {
+            max_rounds: DEFAULT_MAX_ROUNDS,
+            max_response_size: DEFAULT_MAX_RESPONSE_SIZE,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SyncReport {
+    pub entity_type: String,
+    pub peer_identity: String,
+    pub rounds: usize,
+    pub converged: bool,
+    pub pulled: usize,
+    pub pushed: usize,
+    pub conflicted: usize,
+    pub conflicts: Vec<SyncConflict>,
+}
+
+pub fn bucket_key(entity_id: &str, depth: usize) -> String {
+    entity_id.chars().take(depth).collect()
+}
+
+// Groups records that arrive sorted by id, so every bucket stays sorted.
+pub fn buckets(records: &[EntityRecord], depth: usize) -> BTreeMap<String, Vec<&EntityRecord>> {
+    let mut grouped: BTreeMap<String, Vec<&EntityRecord>> = BTreeMap::new();
+    for record in records {
+        grouped
+            .entry(bucket_key(&record.entity_id, depth))
+            .or_default()
+            .push(record);
+    }
+    grouped
+}
+
+// Canonical digest over the bucket's (id, version, updated_at, record digest) tuples.
+pub fn bucket_digest(records: &[&EntityRecord]) -> String {
+    let versions: Vec<(&str, i64, String, String)> = records
+        .iter()
+        .map(|record| {
+            let updated_at = record.updated_at.to_rfc3339();
+            (record.entity_id.as_str(), record.version, updated_at, record_digest(record))
+        })
+        .collect();
+    canonical_digest(&versions).unwrap_or_default()
+}
+
+fn record_digest(record: &EntityRecord) -> String {
+    canonical_digest(&record.record).unwrap_or_default()
+}
+
+// The record with more edits behind it wins. Between two edits of the same version the last
+// writer wins, and a tie goes to the larger record digest so both nodes pick the same side.
+pub fn supersedes(candidate: &EntityRecord, current: &EntityRecord) -> bool {
+    let order = candidate
+        .version
+        .cmp(&current.version)
+        .then(candidate.updated_at.cmp(&current.updated_at));
+    match order {
+        Ordering::Equal => record_digest(candidate) > record_digest(current),
+        order => order == Ordering::Greater,
+    }
+}
+
+// What an entity put or delete answers when the write named a stale version: the version the
+// store is at and the record there, so the writer can rebase and try again.
+pub fn version_conflict_result(error: &StorageError) -> Option<Value> {
+    let StorageError::VersionConflict { expected, current } = error else {
+        return None;
+    };
+    Some(json!({
+        "status": "error",
+        "error": "version_conflict",
+        "expected_version": expected,
+        "current_version": current.version,
+        "current": current,
+    }))
+}
+
+// The latest `updated_at` a record from a peer is taken at: now plus the clock skew allowance.
+// Anything stamped later is pulled back to it, so a record from the far future cannot win every
+// later last-writer-wins comparison. Such a record keeps differing from the sender's copy, and
+// syncs with that sender stop converging until its clock is fixed.
+async fn latest_accepted(state: &AppState) -> anyhow::Result<DateTime<Utc>> {
+    let skew_secs = state.node_config.read().await.clock.max_skew_tolerance_secs;
+    Ok(Utc::now() + chrono::Duration::seconds(i64::try_from(skew_secs)?))
+}
+
+fn held_back(mut record: EntityRecord, latest: DateTime<Utc>) -> EntityRecord {
+    record.updated_at = record.updated_at.min(latest);
+    record
+}
+
+async fn load_scope(
+    state: &AppState,
+    entity_type: &str,
+    scope: &[String],
+) -> anyhow::Result<Vec<EntityRecord>> {
+    let mut records = Vec::new();
+    for prefix in scope {
+        records.extend(state.store.list_entities(entity_type, prefix).await?);
+    }
+    Ok(records)
+}
+
+// Built-in handler for an inbound `entity.sync_request` from an allowlisted requester. Records
+// the requester pushes are held back to `latest_accepted` and given the `labels` its metadata
+// block carries.
+pub async fn answer_sync_request(
+    state: &AppState,
+    envelope: &MeshCommandEnvelope<Value>,
+    labels: &Labels,
+) -> anyhow::Result<Value> {
+    if !state.storage.is_allowlisted(&envelope.source_identity, Utc::now()).await? {
+        return Ok(json!({ "status": "error", "error": SYNC_NOT_ALLOWLISTED_ERROR }));
+    }
+    let request: EntitySyncRequest = serde_json::from_value(envelope.payload.clone())
+        .context("decode entity.sync_request payload")?;
+    if request.depth == 0 {
+        bail!("entity.sync_request depth must be at least 1");
+    }
+
+    let latest = latest_accepted(state).await?;
+    let mut applied = 0;
+    for pushed in &request.records {
+        if pushed.entity_type != request.entity_type {
+            bail!("pushed {} entity in a {} sync", pushed.entity_type, request.entity_type);
+        }
+        let pushed = held_back(pushed.clone(), latest);
+        let current = state
+            .store
+            .get_entity(&pushed.entity_type, &pushed.entity_id)
+            .await?;
+        if current.is_none_or(|current| supersedes(&pushed, &current)) {
+            state.store.put_entity(&pushed).await?;
+            label_entity(state, &pushed, labels).await?;
+            applied += 1;
+        }
+    }
+
+    let local = load_scope(state, &request.entity_type, &request.scope).await?;
+    let ours = buckets(&local, request.depth);
+    let keys: BTreeSet<&String> = ours.keys().chain(request.digests.keys()).collect();
+    let mut budget = request.max_response_size;
+    let mut response = EntitySyncResponse {
+        entity_type: request.entity_type.clone(),
+        complete: Vec::new(),
+        subdivide: Vec::new(),
+        records: Vec::new(),
+        applied,
+    };
+    for key in keys {
+        let bucket = ours.get(key).map(Vec::as_slice).unwrap_or_default();
+        if request.digests.get(key) == Some(&bucket_digest(bucket)) {
+            continue;
+        }
+        let size = bucket
+            .iter()
+            .map(|record| encode_canonical(record).map_or(0, |bytes| bytes.len()))
+            .sum::<usize>();
+        if size > budget && bucket.len() > 1 {
+            response.subdivide.push(key.clone());
+            continue;
+        }
+        budget = budget.saturating_sub(size);
+        response.complete.push(key.clone());
+        response.records.extend(bucket.iter().map(|record| (*record).clone()));
+    }
+    Ok(serde_json::to_value(response)?)
+}
+
+async fn exchange(
+    state: &AppState,
+    peer_identity: &str,
+    request: &EntitySyncRequest,
+    labels: &Labels,
+) -> anyhow::Result<EntitySyncResponse> {
+    let dispatch = resolve_dispatch(
+        &*state.node_config.read().await,
+        ENTITY_SYNC_REQUEST_OPERATION,
+        &json!({ "destination_identity": peer_identity }),
+    )?;
+    let envelope = MeshCommandEnvelope {
+        message_id: Uuid::now_v7().to_string(),
+        operation: ENTITY_SYNC_REQUEST_OPERATION.to_string(),
+        sent_at: Utc::now(),
+        source_identity: dispatch.source_identity,
+        destination_identity: dispatch.destination_identity,
+        content_type: CONTENT_TYPE_MSGPACK.to_string(),
+        payload: serde_json::to_value(request)?,
+        ttl_ms: dispatch
+            .ttl_ms
+            .or(Some(SYNC_ROUND_TIMEOUT.as_millis() as u64)),
+        transport_hint: dispatch.transport_hint,
+        trace: Vec::new(),
+        trace_truncated: false,
+        meta: (!labels.is_empty()).then(|| EnvelopeMeta {
+            labels: labels.clone(),
+            ..EnvelopeMeta::default()
+        }),
+    };
+    let result = tokio::time::timeout(SYNC_ROUND_TIMEOUT, state.bridge.send_command(envelope))
+        .await
+        .map_err(|_| anyhow!("{ENTITY_SYNC_REQUEST_OPERATION} to {peer_identity} timed out"))??;
+    if let Some(error) = result.payload.get("error").and_then(Value::as_str) {
+        bail!("{peer_identity} refused {ENTITY_SYNC_REQUEST_OPERATION}: {error}");
+    }
+    serde_json::from_value(result.payload).with_context(|| {
+        format!("{peer_identity} sent a malformed {ENTITY_SYNC_RESPONSE_OPERATION}")
+    })
+}
+
+// Reconciles one entity type with a peer. Each round compares bucket digests under the current
+// scope, merges the buckets that came back in full, and descends into the ones that did not.
+// Once nothing is left to descend into, a root round pushes the remaining winners and confirms
+// that both sides now hash alike. Records either side stores from the other are given `labels`.
+pub async fn sync_with_peer(
+    state: &AppState,
+    entity_type: &str,
+    peer_identity: &str,
+    limits: SyncLimits,
+    labels: &Labels,
+) -> anyhow::Result<SyncReport> {
+    let started_at = Utc::now();
+    // A record at a higher version is an ordinary update. Two different records at the same
+    // version are edits made from the same base, and a conflict when both sides wrote theirs
+    // since the last converged sync.
+    let since = state
+        .store
+        .last_entity_sync(entity_type, peer_identity)
+        .await?;
+    let mut report = SyncReport {
+        entity_type: entity_type.to_string(),
+        peer_identity: peer_identity.to_string(),
+        rounds: 0,
+        converged: false,
+        pulled: 0,
+        pushed: 0,
+        conflicted: 0,
+        conflicts: Vec::new(),
+    };
+    let mut depth = 1;
+    let mut scope = vec![String::new()];
+    let mut push = Vec::new();
+    while report.rounds < limits.max_rounds {
+        report.rounds += 1;
+        let local = load_scope(state, entity_type, &scope).await?;
+        let ours = buckets(&local, depth);
+        let request = EntitySyncRequest {
+            entity_type: entity_type.to_string(),
+            depth,
+            scope: scope.clone(),
+            digests: ours
+                .iter()
+                .map(|(key, bucket)| (key.clone(), bucket_digest(bucket)))
+                .collect(),
+            records: std::mem::take(&mut push),
+            max_response_size: limits.max_response_size,
+        };
+        let response = exchange(state, peer_identity, &request, labels).await?;
+        report.pushed += response.applied;
+
+        let latest = latest_accepted(state).await?;
+        let pulled: Vec<EntityRecord> = response
+            .records
+            .into_iter()
+            .map(|record| held_back(record, latest))
+            .collect();
+        let theirs = buckets(&pulled, depth);
+        for key in &response.complete {
+            let mine = by_id(ours.get(key));
+            let remote = by_id(theirs.get(key));
+            let ids: BTreeSet<&str> = mine.keys().chain(remote.keys()).copied().collect();
+            for id in ids {
+                match (mine.get(id), remote.get(id)) {
+                    (Some(local), None) => push.push((*local).clone()),
+                    (None, Some(remote)) => {
+                        state.store.put_entity(remote).await?;
+                        label_entity(state, remote, labels).await?;
+                        report.pulled += 1;
+                    }
+                    (Some(local), Some(remote)) if local != remote => {
+                        let remote_wins = supersedes(remote, local);
+                        let concurrent = local.version == remote.version
+                            && since.is_none_or(|since| {
+                                local.updated_at > since && remote.updated_at > since
+                            });
+                        if concurrent {
+                            let kept = if remote_wins { "remote" } else { "local" };
+                            let conflict = state
+                                .store
+                                .record_sync_conflict(peer_identity, local, remote, kept)
+                                .await?;
+                            report.conflicts.push(conflict);
+                            report.conflicted += 1;
+                        }
+                        if remote_wins {
+                            state.store.put_entity(remote).await?;
+                            label_entity(state, remote, labels).await?;
+                            report.pulled += 1;
+                        } else {
+                            push.push((*local).clone());
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        if depth == 1 && response.complete.is_empty() && response.subdivide.is_empty() {
+            report.converged = true;
+            break;
+        }
+        if response.subdivide.is_empty() {
+            depth = 1;
+            scope = vec![String::new()];
+        } else {
+            depth += 1;
+            scope = response.subdivide;
+        }
+    }
+
+    if report.converged {
+        state
+            .store
+            .set_last_entity_sync(entity_type, peer_identity, started_at)
+            .await?;
+    }
+    Ok(report)
+}
+
+fn by_id<'a>(bucket: Option<&Vec<&'a EntityRecord>>) -> BTreeMap<&'a str, &'a EntityRecord> {
+    bucket
+        .into_iter()
+        .flatten()
+        .map(|record| (record.entity_id.as_str(), *record))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bucket_digest, bucket_key, buckets, supersedes};
+    use chrono::{Duration, TimeZone, Utc};
+    use retasync_storage::EntityRecord;
+    use serde_json::json;
+
+    fn entity(id: &str, minute: i64, status: &str) -> EntityRecord {
+        EntityRecord {
+            entity_type: "eam".to_string(),
+            entity_id: id.to_string(),
+            updated_at: Utc.with_ymd_and_hms(2026, 5, 1, 8, 0, 0).unwrap()
+                + Duration::minutes(minute),
+            record: json!({ "status": status, "team": "alpha" }),
+            version: 1,
+            deleted_at: None,
+        }
+    }
+
+    #[test]
+    fn buckets_cut_ids_by_prefix_and_digest_versions() {
+        let records = [entity("a1", 0, "green"), entity("a2", 0, "green"), entity("b", 0, "red")];
+        let grouped = buckets(&records, 2);
+        assert_eq!(grouped.keys().collect::<Vec<_>>(), ["a1", "a2", "b"]);
+        assert_eq!(bucket_key("abc", 1), "a");
+        assert_eq!(buckets(&records, 1)["a"].len(), 2);
+
+        let same = [entity("a1", 0, "green"), entity("a2", 0, "green")];
+        let root = buckets(&records, 1);
+        assert_eq!(bucket_digest(&root["a"]), bucket_digest(&same.iter().collect::<Vec<_>>()));
+        let edited = [entity("a1", 0, "green"), entity("a2", 0, "amber")];
+        assert_ne!(bucket_digest(&root["a"]), bucket_digest(&edited.iter().collect::<Vec<_>>()));
+        assert_ne!(bucket_digest(&root["a"]), bucket_digest(&[]));
+    }
+
+    #[test]
+    fn later_writes_win_and_ties_break_the_same_way_on_both_sides() {
+        let older = entity("a1", 0, "green");
+        let newer = entity("a1", 5, "red");
+        assert!(supersedes(&newer, &older));
+        assert!(!supersedes(&older, &newer));
+
+        let left = entity("a1", 5, "amber");
+        assert_ne!(supersedes(&left, &newer), supersedes(&newer, &left));
+        assert!(!supersedes(&newer, &newer));
+
+        // A record edited twice beats one edited once, whatever the clocks said.
+        let rebased = EntityRecord {
+            version: 2,
+            ..entity("a1", 1, "amber")
+        };
+        assert!(supersedes(&rebased, &newer));
+        assert!(!supersedes(&newer, &rebased));
+        assert_ne!(bucket_digest(&[&rebased]), bucket_digest(&[&entity("a1", 1, "amber")]));
+    }
+}
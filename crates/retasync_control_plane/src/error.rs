@@ -0,0 +1,204 @@
+﻿use std::time::Duration;
+
+use axum::body::Body;
+use axum::extract::Request;
+use axum::http::{header, HeaderValue, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use retasync_contract::CodecError;
+use retasync_mesh_bridge::BridgeError;
+use retasync_storage::StorageError;
+use serde_json::Value;
+use thiserror::Error;
+
+pub use retasync_contract::{RetryAdvice, RetryScope, RETRY_ADVICE_FIELD};
+
+// Refusals with no timer of their own behind them advise these waits.
+pub const STORAGE_RETRY_AFTER: Duration = Duration::from_secs(1);
+pub const CONSISTENCY_RETRY_AFTER: Duration = Duration::from_secs(1);
+pub const BOOTSTRAP_RETRY_AFTER: Duration = Duration::from_secs(5);
+// A mute with no end is lifted by an operator, so clients check back at this pace.
+pub const INDEFINITE_MUTE_RETRY_AFTER: Duration = Duration::from_secs(30);
+
+// A throttling or temporarily-unavailable answer: `body` gains the advice under
+// `RETRY_ADVICE_FIELD`, and `retry_after_header` turns it into `Retry-After` on the way out.
+pub fn refusal(
+    status: StatusCode,
+    mut body: Value,
+    advice: RetryAdvice,
+) -> (StatusCode, Json<Value>) {
+    if let Some(fields) = body.as_object_mut() {
+        fields.insert(
+            RETRY_ADVICE_FIELD.to_string(),
+            serde_json::to_value(advice).unwrap_or_default(),
+        );
+    }
+    (status, Json(body))
+}
+
+// `refusal` with the header already set, for answers built outside `build_router`'s layers.
+pub fn refusal_response(status: StatusCode, body: Value, advice: RetryAdvice) -> Response {
+    let secs = advice.retry_after_secs();
+    let mut response = refusal(status, body, advice).into_response();
+    response
+        .headers_mut()
+        .insert(header::RETRY_AFTER, HeaderValue::from(secs));
+    response
+}
+
+// Copies the advice of a `429` or `503` body into `Retry-After`, in whole seconds rounded up,
+// unless the handler set the header itself.
+pub async fn retry_after_header(request: Request, next: Next) -> Response {
+    let response = next.run(request).await;
+    let refused = matches!(
+        response.status(),
+        StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE
+    );
+    if !refused || response.headers().contains_key(header::RETRY_AFTER) {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    let raw = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(raw) => raw,
+        Err(_) => return parts.status.into_response(),
+    };
+    let advice = serde_json::from_slice::<Value>(&raw)
+        .ok()
+        .and_then(|body| RetryAdvice::from_body(&body));
+    if let Some(advice) = advice {
+        parts
+            .headers
+            .insert(header::RETRY_AFTER, HeaderValue::from(advice.retry_after_secs()));
+    }
+    Response::from_parts(parts, Body::from(raw))
+}
+
+// Why a job worker stopped. The variants carry enough to decide between trying the job again
+// and failing it for good without reading the message.
+#[derive(Debug, Error)]
+pub enum JobProcessingError {
+    #[error(transparent)]
+    Storage(#[from] StorageError),
+    #[error(transparent)]
+    Bridge(#[from] BridgeError),
+    // The job's payload or stored state can never be processed as it stands.
+    #[error("{0}")]
+    Validation(String),
+    #[error(transparent)]
+    Internal(anyhow::Error),
+}
+
+impl JobProcessingError {
+    // A busy database or a flaky link may clear up; a rejected payload or corrupt row will not.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            JobProcessingError::Storage(err) => err.is_transient(),
+            JobProcessingError::Bridge(BridgeError::InvalidPayload(_)) => false,
+            JobProcessingError::Bridge(_) => true,
+            JobProcessingError::Validation(_) | JobProcessingError::Internal(_) => false,
+        }
+    }
+
+    // The failure reason a job is left with when this error ends it.
+    pub fn code(&self) -> String {
+        match self {
+            JobProcessingError::Storage(err) => format!("storage_{}", err.kind()),
+            JobProcessingError::Bridge(err) => match err {
+                BridgeError::DaemonUnavailable => "bridge_unavailable",
+                BridgeError::SendFailed(_) => "bridge_send_failed",
+                BridgeError::InvalidPayload(_) => "bridge_invalid_payload",
+                BridgeError::PeerUnreachable(_) => "peer_unreachable",
+            }
+            .to_string(),
+            JobProcessingError::Validation(_) => "validation_failed".to_string(),
+            JobProcessingError::Internal(_) => "internal_error".to_string(),
+        }
+    }
+}
+
+// Helpers that still return `anyhow` hand their typed cause back rather than hiding it.
+impl From<anyhow::Error> for JobProcessingError {
+    fn from(err: anyhow::Error) -> Self {
+        let err = match err.downcast::<StorageError>() {
+            Ok(storage) => return JobProcessingError::Storage(storage),
+            Err(err) => err,
+        };
+        match err.downcast::<BridgeError>() {
+            Ok(bridge) => JobProcessingError::Bridge(bridge),
+            Err(err) => JobProcessingError::Internal(err),
+        }
+    }
+}
+
+impl From<serde_json::Error> for JobProcessingError {
+    fn from(err: serde_json::Error) -> Self {
+        JobProcessingError::Validation(err.to_string())
+    }
+}
+
+impl From<CodecError> for JobProcessingError {
+    fn from(err: CodecError) -> Self {
+        JobProcessingError::Validation(err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::JobProcessingError;
+    use retasync_mesh_bridge::BridgeError;
+    use retasync_storage::StorageError;
+
+    fn storage(err: StorageError) -> JobProcessingError {
+        JobProcessingError::Storage(err)
+    }
+
+    #[test]
+    fn retry_policy_follows_the_variant() {
+        let transient = [
+            storage(StorageError::from_sqlx("query", sqlx::Error::PoolTimedOut)),
+            storage(StorageError::from_sqlx("query", sqlx::Error::PoolClosed)),
+            JobProcessingError::Bridge(BridgeError::DaemonUnavailable),
+            JobProcessingError::Bridge(BridgeError::SendFailed("timeout".to_string())),
+            JobProcessingError::Bridge(BridgeError::PeerUnreachable("no path".to_string())),
+        ];
+        for err in &transient {
+            assert!(err.is_retryable(), "{err:?}");
+        }
+
+        let terminal = [
+            storage(StorageError::not_found("job j-1")),
+            storage(StorageError::from_sqlx("query", sqlx::Error::RowNotFound)),
+            storage(StorageError::serialization(
+                "parse payload",
+                serde_json::from_str::<serde_json::Value>("{").unwrap_err(),
+            )),
+            storage(StorageError::corrupt("jobs", "truncated ciphertext")),
+            storage(StorageError::Encryption("wrong key".to_string())),
+            JobProcessingError::Bridge(BridgeError::InvalidPayload("bad".to_string())),
+            JobProcessingError::Validation("missing uid".to_string()),
+            JobProcessingError::Internal(anyhow::anyhow!("unexpected")),
+        ];
+        for err in &terminal {
+            assert!(!err.is_retryable(), "{err:?}");
+        }
+    }
+
+    #[test]
+    fn anyhow_errors_keep_their_typed_cause() {
+        let err = JobProcessingError::from(anyhow::Error::new(StorageError::from_sqlx(
+            "query",
+            sqlx::Error::PoolTimedOut,
+        )));
+        assert!(matches!(err, JobProcessingError::Storage(StorageError::Connection { .. })));
+        assert_eq!(err.code(), "storage_connection");
+
+        let err = JobProcessingError::from(
+            anyhow::Error::new(BridgeError::DaemonUnavailable).context("send command"),
+        );
+        assert!(matches!(err, JobProcessingError::Bridge(BridgeError::DaemonUnavailable)));
+
+        let err = JobProcessingError::from(anyhow::anyhow!("something else"));
+        assert_eq!(err.code(), "internal_error");
+    }
+}
@@ -0,0 +1,183 @@
+﻿use chrono::{DateTime, Utc};
+use retasync_contract::{MeshCommandEnvelope, TransferHint};
+use retasync_storage::FEED_JOB_EVENT;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::warn;
+
+use crate::app::{publish, write_log};
+use crate::dependencies::settle_dependents;
+use crate::dispatch::Dispatch;
+use crate::sneakernet::OUTBOX_COMMAND;
+use crate::AppState;
+
+pub const UNDELIVERED_TTL_EXPIRED: &str = "undelivered_ttl_expired";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EscalationStep {
+    // The direct send found no path to the destination.
+    Unreachable,
+    // The envelope was re-sent over LXMF with the store-and-forward TTL.
+    Escalated,
+    // The mesh took the envelope for store-and-forward and no result has come back yet.
+    InTransit,
+    Delivered,
+    Expired,
+    // A courier bundle carries the envelope; `detail` is the bundle id.
+    Exported,
+}
+
+// One step of a job's store-and-forward escalation, kept on its dispatch.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EscalationRecord {
+    pub step: EscalationStep,
+    pub at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transport_hint: Option<TransferHint>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl EscalationRecord {
+    pub fn new(step: EscalationStep) -> Self {
+        Self {
+            step,
+            at: Utc::now(),
+            transport_hint: None,
+            ttl_ms: None,
+            expires_at: None,
+            detail: None,
+        }
+    }
+}
+
+// A bridge acknowledges a store-and-forward hand-off with `"in_transit": true` instead of a
+// result.
+pub(crate) fn is_in_transit(payload: &Value) -> bool {
+    payload
+        .get("in_transit")
+        .and_then(Value::as_bool)
+        .unwrap_or(false)
+}
+
+// Re-plans an envelope for LXMF store-and-forward. The message id is kept, so a peer that did
+// get the first send drops the second as a duplicate.
+pub fn escalate_envelope(envelope: &mut MeshCommandEnvelope<Value>, ttl_ms: u64) {
+    envelope.transport_hint = Some(TransferHint::Lxmf);
+    envelope.ttl_ms = Some(envelope.ttl_ms.unwrap_or(0).max(ttl_ms));
+    if let Some(origin) = envelope.trace.first_mut() {
+        origin.transport = Some(TransferHint::Lxmf);
+    }
+}
+
+// Appends to the history stored on the job rather than a caller's copy, since steps are
+// recorded by the worker, the result ingest and the expiry check alike.
+pub async fn record_escalation(
+    state: &AppState,
+    job_id: &str,
+    record: EscalationRecord,
+) -> anyhow::Result<()> {
+    let Some(job) = state.storage.get_job(job_id).await? else {
+        return Ok(());
+    };
+    let Some(dispatch_json) = job.dispatch_json else {
+        return Ok(());
+    };
+    let mut dispatch: Dispatch = serde_json::from_str(&dispatch_json)?;
+    dispatch.escalations.push(record);
+    state
+        .storage
+        .set_job_dispatch(job_id, &serde_json::to_value(&dispatch)?)
+        .await?;
+    Ok(())
+}
+
+// The envelope is queued in the outbox too, so a courier can carry it if the mesh never does.
+pub(crate) async fn mark_in_transit(
+    state: &AppState,
+    job_id: &str,
+    envelope: &MeshCommandEnvelope<Value>,
+    ttl_ms: u64,
+) -> anyhow::Result<()> {
+    let expires_at = Utc::now() + chrono::Duration::milliseconds(ttl_ms as i64);
+    let id = job_id.to_string();
+    let (message_id, operation) = (envelope.message_id.clone(), envelope.operation.clone());
+    let destination = envelope.destination_identity.clone();
+    let queued = serde_json::to_value(envelope)?;
+    if !state
+        .storage
+        .with_event(
+            FEED_JOB_EVENT,
+            json!({ "job_id": job_id, "status": "in_transit", "expires_at": expires_at }),
+        )
+        .with_tx(move |tx| {
+            Box::pin(async move {
+                if !tx.mark_job_in_transit(&id, expires_at).await? {
+                    return Ok(false);
+                }
+                tx.queue_outbox(
+                    OUTBOX_COMMAND,
+                    &message_id,
+                    Some(&id),
+                    &operation,
+                    &destination,
+                    &queued,
+                )
+                .await?;
+                Ok(true)
+            })
+        })
+        .await?
+    {
+        return Ok(());
+    }
+    record_escalation(
+        state,
+        job_id,
+        EscalationRecord {
+            transport_hint: Some(TransferHint::Lxmf),
+            ttl_ms: Some(ttl_ms),
+            expires_at: Some(expires_at),
+            ..EscalationRecord::new(EscalationStep::InTransit)
+        },
+    )
+    .await?;
+    publish(state).await;
+    Ok(())
+}
+
+// Fails jobs whose store-and-forward TTL ran out before any result came back.
+pub async fn check_transit_expiry(state: &AppState) -> anyhow::Result<usize> {
+    let expired = state.storage.list_expired_in_transit(Utc::now()).await?;
+    for job_id in &expired {
+        state
+            .storage
+            .with_event(
+                FEED_JOB_EVENT,
+                json!({ "job_id": job_id, "status": "failed", "reason": UNDELIVERED_TTL_EXPIRED }),
+            )
+            .fail_job(job_id, UNDELIVERED_TTL_EXPIRED)
+            .await?;
+        record_escalation(
+            state,
+            job_id,
+            EscalationRecord::new(EscalationStep::Expired),
+        )
+        .await?;
+        warn!(job_id = %job_id, "store-and-forward TTL expired without a result");
+        publish(state).await;
+        write_log(
+            state,
+            "error",
+            &format!("job {job_id} failed: {UNDELIVERED_TTL_EXPIRED}"),
+        )
+        .await;
+        settle_dependents(state, job_id).await;
+    }
+    Ok(expired.len())
+}
@@ -0,0 +1,145 @@
+﻿use anyhow::Result;
+use chrono::{DateTime, NaiveDate, Utc};
+use retasync_codegen::{sample_envelopes, SampleEnvelope};
+use retasync_contract::ContractRegistry;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::contracts::LoadedContract;
+
+const MAX_SUGGESTIONS: usize = 3;
+const MAX_SUGGESTION_DISTANCE: usize = 3;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OperationSummary {
+    pub operation: String,
+    pub kind: &'static str,
+    pub channel: String,
+    pub payload_schema: Option<String>,
+    pub deprecated: bool,
+    pub sunset: Option<NaiveDate>,
+    pub sunset_passed: bool,
+    // False when the payload falls back to a free-form object because no schema is named after
+    // the operation.
+    pub typed_example: bool,
+}
+
+// Every command and event of the loaded contract, in contract order.
+pub fn operation_catalog(
+    contract: &LoadedContract,
+    now: DateTime<Utc>,
+) -> Result<Vec<OperationSummary>> {
+    let registry = &contract.registry;
+    Ok(sample_envelopes(&contract.document)?
+        .into_iter()
+        .map(|sample| {
+            let deprecation = registry.deprecation(&sample.operation);
+            OperationSummary {
+                channel: if registry.is_command(&sample.operation) {
+                    registry.command_channel(&sample.operation)
+                } else {
+                    registry.event_channel(&sample.operation)
+                },
+                kind: sample.kind.as_str(),
+                deprecated: deprecation.is_some(),
+                sunset: deprecation.and_then(|deprecation| deprecation.sunset),
+                sunset_passed: deprecation.is_some_and(|deprecation| deprecation.is_sunset(now)),
+                typed_example: sample.payload_schema.is_some(),
+                payload_schema: sample.payload_schema,
+                operation: sample.operation,
+            }
+        })
+        .collect())
+}
+
+// The sample for `operation`, looked up by its canonical name when it is an alias. The envelope
+// carries this node's identity so it can be submitted as-is.
+pub fn operation_example(
+    contract: &LoadedContract,
+    operation: &str,
+    source_identity: &str,
+) -> Result<Option<SampleEnvelope>> {
+    let (canonical, _) = contract.registry.resolve_alias(operation);
+    let Some(mut sample) = sample_envelopes(&contract.document)?
+        .into_iter()
+        .find(|sample| sample.operation == canonical)
+    else {
+        return Ok(None);
+    };
+    sample.envelope["source_identity"] = Value::String(source_identity.to_string());
+    Ok(Some(sample))
+}
+
+// Declared operations close to an unknown name: ones it is a prefix of, then ones within a few
+// edits. An alias that matches suggests the operation it resolves to.
+pub fn operation_suggestions(registry: &ContractRegistry, operation: &str) -> Vec<String> {
+    let declared = registry
+        .commands()
+        .chain(registry.events())
+        .map(|name| (name, name));
+    let mut candidates: Vec<(usize, &str)> = declared
+        .chain(registry.aliases())
+        .filter_map(|(name, canonical)| {
+            if !operation.is_empty() && name.starts_with(operation) {
+                return Some((0, canonical));
+            }
+            let distance = levenshtein(name, operation);
+            (distance <= MAX_SUGGESTION_DISTANCE).then_some((distance, canonical))
+        })
+        .collect();
+    candidates.sort();
+    let mut suggestions: Vec<String> = Vec::new();
+    for (_, canonical) in candidates {
+        if !suggestions.iter().any(|suggestion| suggestion == canonical) {
+            suggestions.push(canonical.to_string());
+        }
+    }
+    suggestions.truncate(MAX_SUGGESTIONS);
+    suggestions
+}
+
+fn levenshtein(left: &str, right: &str) -> usize {
+    let right: Vec<char> = right.chars().collect();
+    let mut previous: Vec<usize> = (0..=right.len()).collect();
+    for (i, left_char) in left.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, right_char) in right.iter().enumerate() {
+            let substitution = previous[j] + usize::from(left_char != *right_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[right.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn suggestions_resolve_aliases_and_rank_by_distance() {
+        let registry = ContractRegistry::from_yaml(
+            r#"
+asyncapi: 3.0.0
+x-retasync:
+  operations:
+    commands: [emergency_action_message.put, emergency_action_message.get]
+    events: [ping.sent]
+    x-retasync-aliases:
+      eam.put: emergency_action_message.put
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            operation_suggestions(&registry, "emergency_action_message.pt"),
+            ["emergency_action_message.put", "emergency_action_message.get"]
+        );
+        assert_eq!(operation_suggestions(&registry, "eam.pu"), ["emergency_action_message.put"]);
+        assert_eq!(
+            operation_suggestions(&registry, "emergency"),
+            ["emergency_action_message.get", "emergency_action_message.put"]
+        );
+        assert!(operation_suggestions(&registry, "transfer.upload").is_empty());
+    }
+}
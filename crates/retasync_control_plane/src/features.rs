@@ -0,0 +1,183 @@
+﻿use std::collections::BTreeMap;
+use std::sync::RwLock;
+
+use retasync_storage::{FeatureFlagRecord, RetasyncStorage};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::NodeConfig;
+
+pub const COMPRESSION_FLAG: &str = "compression";
+pub const INBOUND_COMMANDS_FLAG: &str = "inbound_commands";
+pub const TRANSFER_DEDUP_FLAG: &str = "transfer_dedup";
+pub const CLOCK_SKEW_TOLERANCE_FLAG: &str = "clock_skew_tolerance";
+pub const FEATURE_CHANGED_EVENT: &str = "node.feature.changed";
+// Who a flag seeded on first boot is attributed to.
+pub const CONFIG_SEED: &str = "config";
+
+pub struct KnownFlag {
+    pub name: &'static str,
+    pub description: &'static str,
+    // The value a node starts with before the flag is first stored.
+    pub seed: fn(&NodeConfig) -> bool,
+}
+
+// Only these names can be stored, so a mistyped flag is refused instead of doing nothing.
+pub const KNOWN_FLAGS: [KnownFlag; 4] = [
+    KnownFlag {
+        name: COMPRESSION_FLAG,
+        description: "compress outgoing command payloads for peers that advertise it",
+        seed: |_| true,
+    },
+    KnownFlag {
+        name: INBOUND_COMMANDS_FLAG,
+        description: "accept contract commands from the mesh; built-in operations always run",
+        seed: |_| true,
+    },
+    KnownFlag {
+        name: TRANSFER_DEDUP_FLAG,
+        description: "offer uploads by hash so peers that have the file skip the transfer",
+        seed: |config| config.transfer_dedup.enabled,
+    },
+    KnownFlag {
+        name: CLOCK_SKEW_TOLERANCE_FLAG,
+        description: "accept older envelopes from peers whose clocks are known to run behind",
+        seed: |_| false,
+    },
+];
+
+pub fn is_known(name: &str) -> bool {
+    KNOWN_FLAGS.iter().any(|flag| flag.name == name)
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FeatureFlagUpdate {
+    pub enabled: bool,
+    #[serde(default)]
+    pub variant: Option<Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FeatureFlagState {
+    pub enabled: bool,
+    pub variant: Option<Value>,
+}
+
+// Flag values for the decision points, read without touching storage. Until `load` runs the
+// cache holds the seeds from config; writes through the admin API refresh it.
+pub struct FeatureFlags {
+    cache: RwLock<BTreeMap<String, FeatureFlagState>>,
+}
+
+impl FeatureFlags {
+    pub fn new(config: &NodeConfig) -> Self {
+        let seeds = KNOWN_FLAGS
+            .iter()
+            .map(|flag| {
+                let state = FeatureFlagState {
+                    enabled: (flag.seed)(config),
+                    variant: None,
+                };
+                (flag.name.to_string(), state)
+            })
+            .collect();
+        Self {
+            cache: RwLock::new(seeds),
+        }
+    }
+
+    // Stores the config seed of every flag that has no row yet, then caches what is stored.
+    pub async fn load(&self, storage: &RetasyncStorage, config: &NodeConfig) -> anyhow::Result<()> {
+        let seeds: Vec<(&str, bool)> = KNOWN_FLAGS
+            .iter()
+            .map(|flag| (flag.name, (flag.seed)(config)))
+            .collect();
+        storage.seed_feature_flags(&seeds, CONFIG_SEED).await?;
+        for record in storage.list_feature_flags().await? {
+            self.cache_record(&record);
+        }
+        Ok(())
+    }
+
+    pub fn cache_record(&self, record: &FeatureFlagRecord) {
+        // Rows left by a flag that has since been retired are ignored.
+        if !is_known(&record.name) {
+            return;
+        }
+        let state = FeatureFlagState {
+            enabled: record.enabled,
+            variant: record.variant.clone(),
+        };
+        self.cache
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .insert(record.name.clone(), state);
+    }
+
+    pub fn is_enabled(&self, name: &str) -> bool {
+        self.get(name).is_some_and(|state| state.enabled)
+    }
+
+    // The flag's variant as `T`, or `None` when it has none or it does not fit `T`.
+    pub fn variant<T: DeserializeOwned>(&self, name: &str) -> Option<T> {
+        let variant = self.get(name)?.variant?;
+        serde_json::from_value(variant).ok()
+    }
+
+    pub fn get(&self, name: &str) -> Option<FeatureFlagState> {
+        self.cache
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .get(name)
+            .cloned()
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<String, FeatureFlagState> {
+        self.cache
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FeatureFlags, COMPRESSION_FLAG, TRANSFER_DEDUP_FLAG};
+    use crate::test_support::node_config;
+    use retasync_storage::FeatureFlagRecord;
+    use serde_json::json;
+
+    fn config() -> crate::NodeConfig {
+        node_config(json!({ "prefer_link": false, "transfer_dedup": { "enabled": false } }))
+    }
+
+    #[test]
+    fn seeds_come_from_config_and_records_replace_them() {
+        let flags = FeatureFlags::new(&config());
+        assert!(flags.is_enabled(COMPRESSION_FLAG));
+        assert!(!flags.is_enabled(TRANSFER_DEDUP_FLAG));
+        assert!(!flags.is_enabled("no_such_flag"));
+
+        let record = |name: &str, variant| FeatureFlagRecord {
+            name: name.to_string(),
+            enabled: true,
+            variant,
+            updated_at: "2026-01-01T00:00:00.000000Z".to_string(),
+            updated_by: "local".to_string(),
+        };
+        flags.cache_record(&record(
+            TRANSFER_DEDUP_FLAG,
+            Some(json!({ "timeout_ms": 50 })),
+        ));
+        flags.cache_record(&record("retired_flag", None));
+        assert!(flags.is_enabled(TRANSFER_DEDUP_FLAG));
+        assert_eq!(
+            flags.variant::<serde_json::Value>(TRANSFER_DEDUP_FLAG),
+            Some(json!({ "timeout_ms": 50 }))
+        );
+        assert_eq!(flags.variant::<u64>(TRANSFER_DEDUP_FLAG), None);
+        assert!(flags.get("retired_flag").is_none());
+    }
+}
@@ -0,0 +1,87 @@
+﻿use chrono::{DateTime, Utc};
+use retasync_storage::{FeedBounds, FeedEvent, RetasyncStorage};
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_FEED_RETENTION_HOURS: u64 = 168;
+pub const DEFAULT_FEED_MAX_PAGE: i64 = 1000;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EventFeedSettings {
+    // Events older than this are trimmed; consumers further behind get `410` and must resync.
+    pub retention_hours: u64,
+    pub max_page: i64,
+}
+
+impl Default for EventFeedSettings {
+    fn default() -> Self {
+        Self {
+            retention_hours: DEFAULT_FEED_RETENTION_HOURS,
+            max_page: DEFAULT_FEED_MAX_PAGE,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FeedQuery {
+    pub after_seq: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FeedPage {
+    pub events: Vec<FeedEvent>,
+    // Pass back as `after_seq` for the next page.
+    pub next_after_seq: i64,
+    #[serde(flatten)]
+    pub bounds: FeedBounds,
+}
+
+// `after_seq` fell behind the trim point: the events right after it are gone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FellBehind {
+    pub after_seq: i64,
+    pub trimmed_through: i64,
+}
+
+// Bounds are read first, so a page never claims a head older than the events it holds.
+pub async fn read_page(
+    storage: &RetasyncStorage,
+    settings: &EventFeedSettings,
+    query: &FeedQuery,
+) -> anyhow::Result<Result<FeedPage, FellBehind>> {
+    let bounds = storage.feed_bounds().await?;
+    let after_seq = match query.after_seq {
+        Some(after_seq) if after_seq < bounds.trimmed_through => {
+            return Ok(Err(FellBehind {
+                after_seq,
+                trimmed_through: bounds.trimmed_through,
+            }))
+        }
+        Some(after_seq) => after_seq,
+        None => bounds.trimmed_through,
+    };
+    let limit = query
+        .limit
+        .unwrap_or(100)
+        .clamp(1, settings.max_page.max(1));
+    let events = storage.list_feed_events(after_seq, limit).await?;
+    let next_after_seq = events.last().map_or(after_seq, |event| event.seq);
+    Ok(Ok(FeedPage {
+        events,
+        next_after_seq,
+        bounds: FeedBounds {
+            head_seq: bounds.head_seq.max(next_after_seq),
+            ..bounds
+        },
+    }))
+}
+
+pub async fn trim_event_feed(
+    storage: &RetasyncStorage,
+    settings: &EventFeedSettings,
+    now: DateTime<Utc>,
+) -> anyhow::Result<u64> {
+    let cutoff = now - chrono::Duration::hours(settings.retention_hours as i64);
+    Ok(storage.trim_event_feed(cutoff).await?)
+}
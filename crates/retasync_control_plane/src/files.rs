@@ -0,0 +1,436 @@
+﻿use std::collections::BTreeMap;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::crash::forget_caught_panic;
+use crate::magic;
+
+pub const FILE_QUARANTINED_EVENT: &str = "files.quarantined";
+
+// The `[files]` policy for content arriving from peers.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FileSettings {
+    // `image/png`, `image/*` for every subtype, or `*/*` for anything.
+    pub allowed_media_types: Vec<String>,
+    // Media type pattern to its largest accepted size; the most specific matching pattern wins.
+    pub max_bytes_per_media_type: BTreeMap<String, u64>,
+    // Checks PNG, JPEG, PDF, ZIP and plain text content against its declared media type.
+    pub verify_magic: bool,
+    // Runs the same check on uploads, bundles and attachments before they are sent.
+    pub verify_outbound: bool,
+}
+
+impl Default for FileSettings {
+    fn default() -> Self {
+        Self {
+            allowed_media_types: vec!["*/*".to_string()],
+            max_bytes_per_media_type: BTreeMap::new(),
+            verify_magic: false,
+            verify_outbound: false,
+        }
+    }
+}
+
+pub fn validate_file_settings(settings: &FileSettings) -> Result<(), String> {
+    let patterns = settings
+        .allowed_media_types
+        .iter()
+        .chain(settings.max_bytes_per_media_type.keys());
+    for pattern in patterns {
+        if !is_media_type_pattern(pattern) {
+            return Err(format!("files: media type pattern {pattern:?} is malformed"));
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn is_media_type_pattern(pattern: &str) -> bool {
+    let token = |part: &str| {
+        !part.is_empty()
+            && part
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "!#$&-^_.+".contains(c))
+    };
+    match pattern.split_once('/') {
+        Some(("*", "*")) => true,
+        Some((kind, "*")) => token(kind),
+        Some((kind, subtype)) => token(kind) && token(subtype),
+        None => false,
+    }
+}
+
+pub fn media_type_matches(pattern: &str, media_type: &str) -> bool {
+    let (pattern, media_type) = (magic::essence(pattern), magic::essence(media_type));
+    match pattern.split_once('/') {
+        Some(("*", "*")) => true,
+        Some((kind, "*")) => media_type
+            .split_once('/')
+            .is_some_and(|(actual, _)| actual == kind),
+        _ => pattern == media_type,
+    }
+}
+
+// Exact patterns are more specific than `type/*`, which is more specific than `*/*`.
+fn size_limit(settings: &FileSettings, media_type: &str) -> Option<u64> {
+    settings
+        .max_bytes_per_media_type
+        .iter()
+        .filter(|(pattern, _)| media_type_matches(pattern, media_type))
+        .max_by_key(|(pattern, _)| match pattern.split_once('/') {
+            Some(("*", _)) => 0,
+            Some((_, "*")) => 1,
+            _ => 2,
+        })
+        .map(|(_, limit)| *limit)
+}
+
+// What an inspector is shown of a received file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InspectedFile<'a> {
+    pub name: &'a str,
+    pub media_type: &'a str,
+    // What the content's signature says it is, when it is a type the node recognises.
+    pub detected_media_type: Option<&'static str>,
+    pub size_bytes: u64,
+    pub sha256: &'a str,
+    pub source_identity: &'a str,
+    pub content: &'a [u8],
+}
+
+// Hook for a deployment's own scanner, run on every received file that passed the configured
+// policy. `Err` quarantines the file with the given reason.
+pub trait ContentInspector: Send + Sync {
+    fn inspect(&self, file: &InspectedFile<'_>) -> Result<(), String>;
+}
+
+pub struct NoopInspector;
+
+impl ContentInspector for NoopInspector {
+    fn inspect(&self, _file: &InspectedFile<'_>) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum PolicyViolation {
+    MediaTypeNotAllowed {
+        media_type: String,
+    },
+    TooLargeForMediaType {
+        media_type: String,
+        limit_bytes: u64,
+        size_bytes: u64,
+    },
+    MediaTypeMismatch {
+        declared: String,
+        detected: Option<String>,
+    },
+    RejectedByInspector {
+        detail: String,
+    },
+}
+
+impl PolicyViolation {
+    pub fn code(&self) -> &'static str {
+        match self {
+            PolicyViolation::MediaTypeNotAllowed { .. } => "media_type_not_allowed",
+            PolicyViolation::TooLargeForMediaType { .. } => "too_large_for_media_type",
+            PolicyViolation::MediaTypeMismatch { .. } => "media_type_mismatch",
+            PolicyViolation::RejectedByInspector { .. } => "rejected_by_inspector",
+        }
+    }
+
+    // The reason kept on a quarantined file.
+    pub fn reason(&self) -> String {
+        match self {
+            PolicyViolation::MediaTypeNotAllowed { media_type } => {
+                format!("{}: {media_type}", self.code())
+            }
+            PolicyViolation::TooLargeForMediaType {
+                media_type,
+                limit_bytes,
+                size_bytes,
+            } => format!(
+                "{}: {size_bytes} bytes of {media_type}, limit {limit_bytes}",
+                self.code()
+            ),
+            PolicyViolation::MediaTypeMismatch { declared, detected } => format!(
+                "{}: declared {declared}, content is {}",
+                self.code(),
+                detected.as_deref().unwrap_or("unrecognised")
+            ),
+            PolicyViolation::RejectedByInspector { detail } => {
+                format!("{}: {detail}", self.code())
+            }
+        }
+    }
+
+    pub fn detail(&self) -> Value {
+        serde_json::to_value(self).unwrap_or_else(|_| json!({ "reason": self.code() }))
+    }
+}
+
+// `None` when the declared type is one the node cannot verify, or the content agrees with it.
+pub fn media_type_mismatch(media_type: &str, content: &[u8]) -> Option<PolicyViolation> {
+    if !magic::verifiable(media_type) {
+        return None;
+    }
+    let detected = magic::detect(content);
+    (detected != Some(magic::essence(media_type).as_str())).then(|| {
+        PolicyViolation::MediaTypeMismatch {
+            declared: media_type.to_string(),
+            detected: detected.map(str::to_string),
+        }
+    })
+}
+
+// Checked in order: allowed type, size for the type, signature, then the inspector. A panicking
+// inspector quarantines the file rather than taking the receiver down.
+pub fn check_inbound(
+    settings: &FileSettings,
+    inspector: &dyn ContentInspector,
+    file: &InspectedFile<'_>,
+) -> Result<(), PolicyViolation> {
+    if !settings
+        .allowed_media_types
+        .iter()
+        .any(|pattern| media_type_matches(pattern, file.media_type))
+    {
+        return Err(PolicyViolation::MediaTypeNotAllowed {
+            media_type: file.media_type.to_string(),
+        });
+    }
+    if let Some(limit_bytes) = size_limit(settings, file.media_type) {
+        if file.size_bytes > limit_bytes {
+            return Err(PolicyViolation::TooLargeForMediaType {
+                media_type: file.media_type.to_string(),
+                limit_bytes,
+                size_bytes: file.size_bytes,
+            });
+        }
+    }
+    if settings.verify_magic {
+        if let Some(violation) = media_type_mismatch(file.media_type, file.content) {
+            return Err(violation);
+        }
+    }
+    catch_unwind(AssertUnwindSafe(|| inspector.inspect(file)))
+        .unwrap_or_else(|_| {
+            forget_caught_panic();
+            Err("inspector panicked".to_string())
+        })
+        .map_err(|detail| PolicyViolation::RejectedByInspector { detail })
+}
+
+pub fn inspected<'a>(
+    name: &'a str,
+    media_type: &'a str,
+    sha256: &'a str,
+    source_identity: &'a str,
+    content: &'a [u8],
+) -> InspectedFile<'a> {
+    InspectedFile {
+        name,
+        media_type,
+        detected_media_type: magic::detect(content),
+        size_bytes: content.len() as u64,
+        sha256,
+        source_identity,
+        content,
+    }
+}
+
+// Outbound content is only checked for a mislabelled type, and only when configured to.
+// How much of the content `check_outbound` reads. One byte past the text sample, so a character
+// cut off at the sample's end is still told apart from content that ends mid-character.
+pub const OUTBOUND_SAMPLE_BYTES: usize = magic::TEXT_SAMPLE_BYTES + 1;
+
+pub fn check_outbound(
+    settings: &FileSettings,
+    media_type: &str,
+    content: &[u8],
+) -> Result<(), PolicyViolation> {
+    if !settings.verify_outbound {
+        return Ok(());
+    }
+    media_type_mismatch(media_type, content).map_or(Ok(()), Err)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+
+    fn file<'a>(media_type: &'a str, content: &'a [u8]) -> InspectedFile<'a> {
+        inspected("upload", media_type, "abc123", "peer-a", content)
+    }
+
+    fn settings(value: Value) -> FileSettings {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn media_types_outside_the_allow_list_are_refused() {
+        let settings = settings(json!({ "allowed_media_types": ["image/*", "application/pdf"] }));
+        let check = |media_type| check_inbound(&settings, &NoopInspector, &file(media_type, PNG));
+        assert!(check("image/png").is_ok());
+        assert!(check("IMAGE/JPEG; q=1").is_ok());
+        assert!(check("application/pdf").is_ok());
+        assert_eq!(
+            check("application/x-msdownload").unwrap_err(),
+            PolicyViolation::MediaTypeNotAllowed {
+                media_type: "application/x-msdownload".to_string()
+            }
+        );
+        assert!(validate_file_settings(&settings).is_ok());
+        assert!(validate_file_settings(&FileSettings {
+            allowed_media_types: vec!["image".to_string()],
+            ..FileSettings::default()
+        })
+        .unwrap_err()
+        .contains("malformed"));
+    }
+
+    #[test]
+    fn the_most_specific_size_limit_applies() {
+        let settings = settings(json!({
+            "max_bytes_per_media_type": { "*/*": 4, "image/*": 8, "image/png": 32 }
+        }));
+        let check = |media_type, content| {
+            check_inbound(&settings, &NoopInspector, &file(media_type, content))
+        };
+        assert!(check("image/png", PNG).is_ok());
+        assert!(check("image/jpeg", b"12345678").is_ok());
+        assert_eq!(
+            check("image/jpeg", b"123456789").unwrap_err(),
+            PolicyViolation::TooLargeForMediaType {
+                media_type: "image/jpeg".to_string(),
+                limit_bytes: 8,
+                size_bytes: 9,
+            }
+        );
+        assert_eq!(check("text/plain", b"12345").unwrap_err().code(), "too_large_for_media_type");
+    }
+
+    #[test]
+    fn content_must_match_its_declared_signature_when_verified() {
+        let mismatch = file("application/pdf", PNG);
+        assert!(check_inbound(&FileSettings::default(), &NoopInspector, &mismatch).is_ok());
+
+        let settings = FileSettings {
+            verify_magic: true,
+            ..FileSettings::default()
+        };
+        let violation = check_inbound(&settings, &NoopInspector, &mismatch).unwrap_err();
+        assert_eq!(
+            violation.reason(),
+            "media_type_mismatch: declared application/pdf, content is image/png"
+        );
+        assert!(check_inbound(&settings, &NoopInspector, &file("image/png", PNG)).is_ok());
+        assert!(check_inbound(&settings, &NoopInspector, &file("text/plain", b"notes")).is_ok());
+        // Types the node cannot recognise are taken at their word.
+        assert!(check_inbound(&settings, &NoopInspector, &file("image/gif", b"x")).is_ok());
+        assert_eq!(
+            check_inbound(&settings, &NoopInspector, &file("text/plain", PNG))
+                .unwrap_err()
+                .detail(),
+            json!({
+                "reason": "media_type_mismatch",
+                "declared": "text/plain",
+                "detected": "image/png",
+            })
+        );
+
+        assert!(check_outbound(&settings, "application/pdf", PNG).is_ok());
+        let outbound = FileSettings {
+            verify_outbound: true,
+            ..FileSettings::default()
+        };
+        assert!(check_outbound(&outbound, "application/pdf", PNG).is_err());
+        assert!(check_outbound(&outbound, "image/png", PNG).is_ok());
+    }
+
+    // Name, declared type, detected type, size, digest and sender of each inspected file.
+    type Seen = (String, String, Option<&'static str>, u64, String, String);
+
+    #[derive(Default)]
+    struct Recording {
+        seen: Mutex<Vec<Seen>>,
+    }
+
+    impl ContentInspector for Recording {
+        fn inspect(&self, file: &InspectedFile<'_>) -> Result<(), String> {
+            self.seen.lock().unwrap().push((
+                file.name.to_string(),
+                file.media_type.to_string(),
+                file.detected_media_type,
+                file.size_bytes,
+                file.sha256.to_string(),
+                file.source_identity.to_string(),
+            ));
+            if file.name.ends_with(".exe") {
+                return Err("signature Win.Test matched".to_string());
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn the_inspector_sees_each_file_that_passed_the_policy() {
+        let inspector = Recording::default();
+        let settings = settings(json!({ "allowed_media_types": ["image/*"] }));
+        let photo = inspected("photo.png", "image/png", "sha-photo", "peer-a", PNG);
+        assert!(check_inbound(&settings, &inspector, &photo).is_ok());
+        let tool = inspected("tool.exe", "image/png", "sha-tool", "peer-b", b"MZ");
+        assert_eq!(
+            check_inbound(&settings, &inspector, &tool).unwrap_err(),
+            PolicyViolation::RejectedByInspector {
+                detail: "signature Win.Test matched".to_string()
+            }
+        );
+        let refused = inspected("notes.txt", "text/plain", "sha-notes", "peer-a", b"hi");
+        assert!(check_inbound(&settings, &inspector, &refused).is_err());
+
+        assert_eq!(
+            *inspector.seen.lock().unwrap(),
+            [
+                (
+                    "photo.png".to_string(),
+                    "image/png".to_string(),
+                    Some("image/png"),
+                    PNG.len() as u64,
+                    "sha-photo".to_string(),
+                    "peer-a".to_string(),
+                ),
+                (
+                    "tool.exe".to_string(),
+                    "image/png".to_string(),
+                    Some("text/plain"),
+                    2,
+                    "sha-tool".to_string(),
+                    "peer-b".to_string(),
+                ),
+            ]
+        );
+
+        struct Panics;
+        impl ContentInspector for Panics {
+            fn inspect(&self, _file: &InspectedFile<'_>) -> Result<(), String> {
+                panic!("scanner crashed")
+            }
+        }
+        assert_eq!(
+            check_inbound(&FileSettings::default(), &Panics, &photo)
+                .unwrap_err()
+                .reason(),
+            "rejected_by_inspector: inspector panicked"
+        );
+    }
+}
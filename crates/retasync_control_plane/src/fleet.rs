@@ -0,0 +1,458 @@
+﻿use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use retasync_contract::{encode_canonical, IdentityHash, MeshCommandEnvelope, CONTENT_TYPE_MSGPACK};
+use retasync_storage::FleetNode;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::task::JoinHandle;
+use tracing::{debug, error, warn};
+use uuid::Uuid;
+
+use crate::app::{collect_node_status, current_capabilities, publish};
+use crate::diagnostics::CheckStatus;
+use crate::dispatch::local_identity;
+use crate::mute::Traffic;
+use crate::AppState;
+
+pub const NODE_STATUS_REPORT_OPERATION: &str = "node.status_report";
+pub const FLEET_NODE_STALE_EVENT: &str = "fleet.node.stale";
+pub const FLEET_COLLECTOR_DISABLED_ERROR: &str = "fleet_collector_disabled";
+pub const REPORT_NOT_ALLOWLISTED_ERROR: &str = "reporter_not_allowlisted";
+pub const REPORT_TOO_LARGE_ERROR: &str = "status_report_too_large";
+pub const INVALID_REPORT_ERROR: &str = "invalid_status_report";
+const REPORT_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FleetSettings {
+    // Identity hash of the node this one reports its status to; unset sends no reports.
+    pub collector: Option<String>,
+    pub report_interval_secs: u64,
+    // Accepts reports from allowlisted nodes and serves them under `/v1/fleet/nodes`.
+    pub collect: bool,
+    // Encoded report payloads above this are refused by the collector and never sent.
+    pub max_report_bytes: usize,
+    // Reports kept per node besides its latest.
+    pub history: usize,
+    // A node is flagged stale once it has been quiet for this many of its own intervals.
+    pub stale_after_intervals: u32,
+}
+
+impl Default for FleetSettings {
+    fn default() -> Self {
+        Self {
+            collector: None,
+            report_interval_secs: 60,
+            collect: false,
+            max_report_bytes: 4096,
+            history: 20,
+            stale_after_intervals: 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueueDepths {
+    pub queued: i64,
+    pub running: i64,
+    pub in_transit: i64,
+    pub deferred: i64,
+    pub inbound: usize,
+}
+
+// The node's readiness and the checks that did not pass.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HealthSummary {
+    pub ready: bool,
+    pub status: CheckStatus,
+    #[serde(default)]
+    pub failing: Vec<String>,
+}
+
+// Payload of `node.status_report`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StatusReport {
+    pub identity: IdentityHash,
+    pub version: String,
+    pub reported_at: DateTime<Utc>,
+    pub interval_secs: u64,
+    pub queue_depths: QueueDepths,
+    pub health: HealthSummary,
+    pub capabilities_digest: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReportOutcome {
+    NotConfigured,
+    Accepted,
+    // The collector was unreachable or refused the report. Nothing is kept to resend: the next
+    // report supersedes it.
+    Skipped(String),
+}
+
+// A node as the collector last heard from it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FleetNodeView {
+    pub identity_hash: String,
+    pub received_at: String,
+    pub interval_secs: i64,
+    pub stale: bool,
+    pub stale_since: Option<String>,
+    pub report: Value,
+}
+
+impl FleetNodeView {
+    pub fn new(node: FleetNode, stale_after_intervals: u32, now: DateTime<Utc>) -> Self {
+        let stale = node.stale_since.is_some() || overdue(&node, stale_after_intervals, now);
+        Self {
+            report: serde_json::from_str(&node.report_json).unwrap_or(Value::Null),
+            identity_hash: node.identity_hash,
+            received_at: node.received_at,
+            interval_secs: node.interval_secs,
+            stale,
+            stale_since: node.stale_since,
+        }
+    }
+}
+
+fn overdue(node: &FleetNode, stale_after_intervals: u32, now: DateTime<Utc>) -> bool {
+    let Ok(received_at) = DateTime::parse_from_rfc3339(&node.received_at) else {
+        return false;
+    };
+    let silence = now.signed_duration_since(received_at.with_timezone(&Utc));
+    silence.num_seconds() > node.interval_secs.max(1) * i64::from(stale_after_intervals.max(1))
+}
+
+pub async fn status_report(state: &AppState) -> anyhow::Result<StatusReport> {
+    let (identity, interval_secs) = {
+        let config = state.node_config.read().await;
+        (local_identity(&config), config.fleet.report_interval_secs)
+    };
+    let status = collect_node_status(state).await;
+    let count = |status: &'static str| state.storage.count_jobs_with_status(status);
+    let queue_depths = QueueDepths {
+        queued: count("queued").await?,
+        running: count("running").await?,
+        in_transit: count("in_transit").await?,
+        deferred: count("deferred").await?,
+        inbound: state.inbound.snapshot().depth,
+    };
+    let health = HealthSummary {
+        ready: status.ready,
+        status: status
+            .checks
+            .iter()
+            .map(|check| check.status)
+            .max()
+            .unwrap_or(CheckStatus::Pass),
+        failing: status
+            .checks
+            .iter()
+            .filter(|check| check.status != CheckStatus::Pass)
+            .map(|check| check.name.clone())
+            .collect(),
+    };
+    Ok(StatusReport {
+        identity,
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        reported_at: Utc::now(),
+        interval_secs,
+        queue_depths,
+        health,
+        capabilities_digest: current_capabilities(state).await.digest(),
+    })
+}
+
+// Sends one `node.status_report` to the configured collector. A muted, unreachable or refusing
+// collector skips the report rather than queueing it.
+pub async fn report_status(state: &AppState) -> anyhow::Result<ReportOutcome> {
+    let settings = state.node_config.read().await.fleet.clone();
+    let Some(collector) = settings.collector.as_deref() else {
+        return Ok(ReportOutcome::NotConfigured);
+    };
+    if state.mute.blocks(Traffic::Commands) {
+        return Ok(ReportOutcome::Skipped("commands are muted".to_string()));
+    }
+    let report = status_report(state).await?;
+    let payload = serde_json::to_value(&report)?;
+    let size_bytes = encode_canonical(&payload)?.len();
+    if size_bytes > settings.max_report_bytes {
+        return Ok(ReportOutcome::Skipped(format!(
+            "report is {size_bytes} bytes, over the {} byte budget",
+            settings.max_report_bytes
+        )));
+    }
+    let timeout = REPORT_TIMEOUT.min(Duration::from_secs(settings.report_interval_secs.max(1)));
+    let envelope = MeshCommandEnvelope {
+        message_id: Uuid::now_v7().to_string(),
+        operation: NODE_STATUS_REPORT_OPERATION.to_string(),
+        sent_at: report.reported_at,
+        source_identity: report.identity.clone(),
+        destination_identity: IdentityHash::lenient(collector),
+        content_type: CONTENT_TYPE_MSGPACK.to_string(),
+        payload,
+        ttl_ms: Some(timeout.as_millis() as u64),
+        transport_hint: None,
+        trace: Vec::new(),
+        trace_truncated: false,
+        meta: None,
+    };
+    let answer = match tokio::time::timeout(timeout, state.bridge.send_command(envelope)).await {
+        Ok(Ok(result)) => result.payload,
+        Ok(Err(err)) => return Ok(ReportOutcome::Skipped(err.to_string())),
+        Err(_) => {
+            return Ok(ReportOutcome::Skipped(format!(
+                "no answer within {}ms",
+                timeout.as_millis()
+            )))
+        }
+    };
+    if answer["status"] == "ok" {
+        return Ok(ReportOutcome::Accepted);
+    }
+    let error = answer["error"].as_str().unwrap_or("unexpected answer");
+    Ok(ReportOutcome::Skipped(error.to_string()))
+}
+
+// Built-in handler for an inbound `node.status_report`: stores it as the sender's latest when
+// this node collects, the sender is allowlisted and the report fits the size budget.
+pub async fn answer_status_report(
+    state: &AppState,
+    envelope: &MeshCommandEnvelope<Value>,
+) -> anyhow::Result<Value> {
+    let settings = state.node_config.read().await.fleet.clone();
+    if !settings.collect {
+        return Ok(json!({ "status": "error", "error": FLEET_COLLECTOR_DISABLED_ERROR }));
+    }
+    let reporter = &envelope.source_identity;
+    if !state.storage.is_allowlisted(reporter, Utc::now()).await? {
+        return Ok(json!({ "status": "error", "error": REPORT_NOT_ALLOWLISTED_ERROR }));
+    }
+    let size_bytes = encode_canonical(&envelope.payload)?.len();
+    if size_bytes > settings.max_report_bytes {
+        warn!(
+            source_identity = %reporter,
+            size_bytes,
+            limit_bytes = settings.max_report_bytes,
+            "fleet status report refused as too large"
+        );
+        return Ok(json!({
+            "status": "error",
+            "error": REPORT_TOO_LARGE_ERROR,
+            "size_bytes": size_bytes,
+            "limit_bytes": settings.max_report_bytes,
+        }));
+    }
+    let report: StatusReport = match serde_json::from_value(envelope.payload.clone()) {
+        Ok(report) => report,
+        Err(err) => {
+            let detail = err.to_string();
+            return Ok(json!({ "status": "error", "error": INVALID_REPORT_ERROR, "detail": detail }));
+        }
+    };
+    state
+        .storage
+        .record_fleet_report(
+            reporter,
+            &envelope.payload,
+            report.interval_secs as i64,
+            Utc::now(),
+            settings.history as i64,
+        )
+        .await?;
+    Ok(json!({ "status": "ok" }))
+}
+
+// Flags every node quiet for longer than `stale_after_intervals` of its reporting intervals,
+// once per silence, and returns their identities.
+pub async fn flag_stale_nodes(state: &AppState, now: DateTime<Utc>) -> anyhow::Result<Vec<String>> {
+    let stale_after_intervals = state.node_config.read().await.fleet.stale_after_intervals;
+    let mut flagged = Vec::new();
+    for node in state.storage.list_fleet_nodes().await? {
+        if node.stale_since.is_some() || !overdue(&node, stale_after_intervals, now) {
+            continue;
+        }
+        let stale = json!({
+            "identity_hash": node.identity_hash,
+            "last_report_at": node.received_at,
+            "interval_secs": node.interval_secs,
+        });
+        let marked = state
+            .storage
+            .with_event(FLEET_NODE_STALE_EVENT, stale)
+            .mark_fleet_node_stale(&node.identity_hash, &node.received_at, now)
+            .await?;
+        if !marked {
+            continue;
+        }
+        warn!(
+            identity_hash = %node.identity_hash,
+            last_report_at = %node.received_at,
+            "fleet node went stale"
+        );
+        publish(state).await;
+        flagged.push(node.identity_hash);
+    }
+    Ok(flagged)
+}
+
+// Sends a report whenever the configured interval has passed and, on a collector, flags the
+// nodes that went quiet. Settings are read every tick, so both follow a config change.
+pub fn spawn_fleet_reporter(state: AppState, period: Duration) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(period);
+        let mut last_report: Option<Instant> = None;
+        loop {
+            ticker.tick().await;
+            let settings = state.node_config.read().await.fleet.clone();
+            if settings.collect {
+                if let Err(err) = flag_stale_nodes(&state, Utc::now()).await {
+                    error!(error = %err, "fleet staleness check failed");
+                }
+            }
+            let interval = Duration::from_secs(settings.report_interval_secs.max(1));
+            let due = last_report.is_none_or(|sent| sent.elapsed() >= interval);
+            if settings.collector.is_none() || !due {
+                continue;
+            }
+            last_report = Some(Instant::now());
+            match report_status(&state).await {
+                Ok(ReportOutcome::Skipped(reason)) => {
+                    debug!(reason, "fleet status report skipped")
+                }
+                Ok(_) => {}
+                Err(err) => error!(error = %err, "fleet status report failed"),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        flag_stale_nodes, report_status, ReportOutcome, FLEET_NODE_STALE_EVENT,
+        REPORT_NOT_ALLOWLISTED_ERROR, REPORT_TOO_LARGE_ERROR,
+    };
+    use crate::inbound::spawn_inbound_worker;
+    use crate::test_support::node_state;
+    use crate::AppState;
+    use chrono::{Duration as ChronoDuration, Utc};
+    use retasync_contract::IdentityHash;
+    use retasync_mesh_bridge::{LoopbackMeshBridge, RpcMeshBridge};
+
+    use serde_json::{json, Value};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    const COLLECTOR: &str = "c0c0c0c0c0c0c0c0c0c0c0c0c0c0c0c0";
+    const ALPHA: &str = "a1a1a1a1a1a1a1a1a1a1a1a1a1a1a1a1";
+    const BRAVO: &str = "b2b2b2b2b2b2b2b2b2b2b2b2b2b2b2b2";
+
+    async fn node(bridge: Arc<dyn RpcMeshBridge>, identity: &str, fleet: Value) -> AppState {
+        let overrides = json!({ "identity": { "source_identity": identity }, "fleet": fleet });
+        node_state(bridge, overrides).await
+    }
+
+    async fn reporter(bridge: &LoopbackMeshBridge, identity: &str) -> AppState {
+        let fleet = json!({ "collector": COLLECTOR, "report_interval_secs": 30 });
+        node(Arc::new(bridge.clone()), identity, fleet).await
+    }
+
+    async fn collector(bridge: LoopbackMeshBridge, fleet: Value) -> AppState {
+        let state = node(Arc::new(bridge), COLLECTOR, fleet).await;
+        state.storage.add_allowlist(&hash(ALPHA), None).await.unwrap();
+        state
+    }
+
+    fn hash(value: &str) -> IdentityHash {
+        IdentityHash::parse(value).unwrap()
+    }
+
+    #[tokio::test]
+    async fn collector_keeps_the_latest_report_of_each_allowlisted_node() {
+        let (link, collector_link) = LoopbackMeshBridge::pair();
+        let collector = collector(collector_link, json!({ "collect": true, "history": 2 })).await;
+        let worker = spawn_inbound_worker(collector.clone(), Duration::from_millis(5));
+        let alpha = reporter(&link, ALPHA).await;
+        let bravo = reporter(&link, BRAVO).await;
+
+        assert_eq!(
+            report_status(&bravo).await.unwrap(),
+            ReportOutcome::Skipped(REPORT_NOT_ALLOWLISTED_ERROR.to_string())
+        );
+        collector.storage.add_allowlist(&hash(BRAVO), None).await.unwrap();
+        for reporter in [&alpha, &bravo, &alpha, &alpha] {
+            assert_eq!(report_status(reporter).await.unwrap(), ReportOutcome::Accepted);
+        }
+        worker.abort();
+
+        let nodes = collector.storage.list_fleet_nodes().await.unwrap();
+        let identities: Vec<_> = nodes.iter().map(|node| node.identity_hash.as_str()).collect();
+        assert_eq!(identities, [ALPHA, BRAVO]);
+        assert_eq!(nodes[0].interval_secs, 30);
+        let report: Value = serde_json::from_str(&nodes[0].report_json).unwrap();
+        assert_eq!(report["identity"], ALPHA);
+        assert_eq!(report["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(report["queue_depths"]["queued"], 0);
+        // The bare test contract declares no commands.
+        assert_eq!(report["health"]["failing"], json!(["contract"]));
+        assert!(!report["capabilities_digest"].as_str().unwrap().is_empty());
+        let history = collector.storage.list_fleet_reports(&hash(ALPHA), 10).await.unwrap();
+        assert_eq!(history.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn quiet_nodes_go_stale_once_per_silence() {
+        let (_, collector_link) = LoopbackMeshBridge::pair();
+        let collector = collector(collector_link, json!({ "collect": true })).await;
+        let mut events = collector.sse_bus.subscribe();
+        let received_at = Utc::now();
+        let report = json!({ "interval_secs": 30 });
+        let storage = &collector.storage;
+        storage.record_fleet_report(&hash(ALPHA), &report, 30, received_at, 5).await.unwrap();
+
+        let within = received_at + ChronoDuration::seconds(90);
+        assert!(flag_stale_nodes(&collector, within).await.unwrap().is_empty());
+        let beyond = received_at + ChronoDuration::seconds(91);
+        assert_eq!(flag_stale_nodes(&collector, beyond).await.unwrap(), [ALPHA]);
+        let event = events.try_recv().expect("stale event");
+        assert_eq!(event.event_type, FLEET_NODE_STALE_EVENT);
+        assert_eq!(event.data["identity_hash"], ALPHA);
+        let later = beyond + ChronoDuration::seconds(600);
+        assert!(flag_stale_nodes(&collector, later).await.unwrap().is_empty());
+        assert!(events.try_recv().is_err());
+
+        // A new report clears the flag, so the next silence is reported again.
+        storage.record_fleet_report(&hash(ALPHA), &report, 30, later, 5).await.unwrap();
+        assert!(storage.list_fleet_nodes().await.unwrap()[0].stale_since.is_none());
+        let quiet_again = later + ChronoDuration::seconds(91);
+        assert_eq!(flag_stale_nodes(&collector, quiet_again).await.unwrap(), [ALPHA]);
+    }
+
+    #[tokio::test]
+    async fn reports_over_the_size_budget_or_without_a_collector_are_skipped() {
+        let (link, collector_link) = LoopbackMeshBridge::pair();
+        let fleet = json!({ "collect": true, "max_report_bytes": 64 });
+        let collector = collector(collector_link, fleet).await;
+        let worker = spawn_inbound_worker(collector.clone(), Duration::from_millis(5));
+        let alpha = reporter(&link, ALPHA).await;
+
+        assert_eq!(
+            report_status(&alpha).await.unwrap(),
+            ReportOutcome::Skipped(REPORT_TOO_LARGE_ERROR.to_string())
+        );
+        alpha.node_config.write().await.fleet.max_report_bytes = 64;
+        let outcome = report_status(&alpha).await.unwrap();
+        assert!(matches!(outcome, ReportOutcome::Skipped(reason) if reason.contains("budget")));
+        assert!(collector.storage.list_fleet_nodes().await.unwrap().is_empty());
+        worker.abort();
+
+        // Nobody answers: the report is dropped rather than kept for a later attempt.
+        let (link, _silent) = LoopbackMeshBridge::pair();
+        let alpha = reporter(&link.with_reply_timeout(Duration::from_millis(50)), ALPHA).await;
+        let outcome = report_status(&alpha).await.unwrap();
+        assert!(matches!(outcome, ReportOutcome::Skipped(_)));
+        assert!(alpha.storage.list_pending_outbox(None).await.unwrap().is_empty());
+    }
+}
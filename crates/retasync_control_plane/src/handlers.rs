@@ -0,0 +1,808 @@
+﻿use std::collections::BTreeMap;
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Instant;
+
+use chrono::Utc;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use retasync_contract::MeshCommandEnvelope;
+use serde_json::{json, Value};
+use tokio::sync::Semaphore;
+use tracing::error;
+
+use crate::attempts::LatencyHistogram;
+use crate::crash::{forget_caught_panic, panic_message};
+#[cfg(feature = "transfers")]
+use crate::dedup::{answer_offer, TRANSFER_OFFER_OPERATION};
+#[cfg(feature = "entities")]
+use crate::entity_sync::{
+    answer_sync_request, ENTITY_SYNC_REQUEST_OPERATION, ENTITY_SYNC_RESPONSE_OPERATION,
+};
+use crate::fleet::{answer_status_report, NODE_STATUS_REPORT_OPERATION};
+use crate::handshake::{answer_hello, NODE_HELLO_OPERATION};
+#[cfg(feature = "entities")]
+use crate::labels::meta_labels;
+use crate::liveness::{answer_ping, NODE_PING_OPERATION};
+#[cfg(feature = "entities")]
+use crate::result_cache::{
+    answer_entity_list, EMERGENCY_ACTION_MESSAGE_LIST_OPERATION, EVENT_LIST_OPERATION,
+};
+use crate::AppState;
+
+// The built-in layers, outermost first. A handler may name any of them in `skip_layers`.
+pub const TIMING_LAYER: &str = "timing";
+pub const PANIC_LAYER: &str = "panic";
+pub const AUTHORIZATION_LAYER: &str = "authorization";
+pub const VALIDATION_LAYER: &str = "validation";
+pub const CONCURRENCY_LAYER: &str = "concurrency";
+pub const NOT_ALLOWLISTED_ERROR: &str = "not_allowlisted";
+pub const ROLE_NOT_PERMITTED_ERROR: &str = "role_not_permitted";
+pub const INVALID_PAYLOAD_ERROR: &str = "invalid_payload";
+pub const HANDLER_BUSY_ERROR: &str = "handler_busy";
+pub const HANDLER_PANICKED_ERROR: &str = "handler_panicked";
+pub const HANDLER_FAILED_ERROR: &str = "handler_failed";
+
+// Answers one inbound command. An error is logged, and the sender only gets `handler_failed`.
+pub type AnswerFn = for<'a> fn(
+    &'a AppState,
+    &'a MeshCommandEnvelope<Value>,
+) -> BoxFuture<'a, anyhow::Result<Value>>;
+
+#[derive(Clone, Copy)]
+pub struct InboundHandler {
+    pub answer: AnswerFn,
+    // The operation the result is sent under, when not the command's own.
+    pub reply_operation: Option<&'static str>,
+    // Built-in layers this handler is answered without.
+    pub skip_layers: &'static [&'static str],
+}
+
+impl InboundHandler {
+    pub fn new(answer: AnswerFn) -> Self {
+        Self {
+            answer,
+            reply_operation: None,
+            skip_layers: &[],
+        }
+    }
+
+    pub fn skipping(mut self, layers: &'static [&'static str]) -> Self {
+        self.skip_layers = layers;
+        self
+    }
+}
+
+// One step every handled command passes through. It answers in the handler's place or hands
+// the command on to `next`.
+pub trait HandlerLayer: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn call<'a>(
+        &'a self,
+        state: &'a AppState,
+        envelope: &'a MeshCommandEnvelope<Value>,
+        next: Next<'a>,
+    ) -> BoxFuture<'a, anyhow::Result<Value>>;
+}
+
+// The layers still to run for one command, then its handler.
+pub struct Next<'a> {
+    layers: &'a [Arc<dyn HandlerLayer>],
+    handler: InboundHandler,
+}
+
+impl<'a> Next<'a> {
+    pub fn run(
+        self,
+        state: &'a AppState,
+        envelope: &'a MeshCommandEnvelope<Value>,
+    ) -> BoxFuture<'a, anyhow::Result<Value>> {
+        let Some((layer, layers)) = self.layers.split_first() else {
+            return (self.handler.answer)(state, envelope);
+        };
+        let next = Next {
+            layers,
+            handler: self.handler,
+        };
+        if self.handler.skip_layers.contains(&layer.name()) {
+            return next.run(state, envelope);
+        }
+        layer.call(state, envelope, next)
+    }
+}
+
+// The commands this node answers itself, by operation. They are answered whether or not the
+// `inbound_commands` flag is on; any other command goes to the client as an event.
+pub struct HandlerRegistry {
+    handlers: RwLock<BTreeMap<String, InboundHandler>>,
+    layers: RwLock<Vec<Arc<dyn HandlerLayer>>>,
+    latencies: Arc<HandlerLatencies>,
+}
+
+impl Default for HandlerRegistry {
+    fn default() -> Self {
+        let latencies = Arc::new(HandlerLatencies::default());
+        let layers: Vec<Arc<dyn HandlerLayer>> = vec![
+            Arc::new(TimingLayer {
+                latencies: latencies.clone(),
+            }),
+            Arc::new(PanicLayer),
+            Arc::new(AuthorizationLayer),
+            Arc::new(ValidationLayer),
+            Arc::new(ConcurrencyLayer::default()),
+        ];
+        let registry = Self {
+            handlers: RwLock::new(BTreeMap::new()),
+            layers: RwLock::new(layers),
+            latencies,
+        };
+        // Any peer may probe liveness or say hello, which comes before it is allowlisted.
+        registry.register(
+            NODE_PING_OPERATION,
+            InboundHandler::new(ping).skipping(&[AUTHORIZATION_LAYER]),
+        );
+        #[cfg(feature = "entities")]
+        registry.register(
+            ENTITY_SYNC_REQUEST_OPERATION,
+            InboundHandler {
+                answer: sync_request,
+                reply_operation: Some(ENTITY_SYNC_RESPONSE_OPERATION),
+                // Refuses an unknown requester itself, as `requester_not_allowlisted`.
+                skip_layers: &[AUTHORIZATION_LAYER],
+            },
+        );
+        // Offers only say which chunks a transfer still needs; the chunks are screened apart.
+        #[cfg(feature = "transfers")]
+        registry.register(
+            TRANSFER_OFFER_OPERATION,
+            InboundHandler::new(offer).skipping(&[AUTHORIZATION_LAYER]),
+        );
+        // Refuses an unknown reporter itself, once it knows this node collects reports.
+        registry.register(
+            NODE_STATUS_REPORT_OPERATION,
+            InboundHandler::new(status_report).skipping(&[AUTHORIZATION_LAYER]),
+        );
+        registry.register(
+            NODE_HELLO_OPERATION,
+            InboundHandler::new(hello).skipping(&[AUTHORIZATION_LAYER]),
+        );
+        // Entity writes have no handler here; they are cached for the application to apply.
+        #[cfg(feature = "entities")]
+        registry.register(EVENT_LIST_OPERATION, InboundHandler::new(entity_list));
+        #[cfg(feature = "entities")]
+        registry.register(
+            EMERGENCY_ACTION_MESSAGE_LIST_OPERATION,
+            InboundHandler::new(entity_list),
+        );
+        registry
+    }
+}
+
+impl HandlerRegistry {
+    // Replaces any handler the operation already has.
+    pub fn register(&self, operation: &str, handler: InboundHandler) {
+        self.handlers
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .insert(operation.to_string(), handler);
+    }
+
+    pub fn get(&self, operation: &str) -> Option<InboundHandler> {
+        self.handlers
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .get(operation)
+            .copied()
+    }
+
+    pub fn operations(&self) -> Vec<String> {
+        self.handlers
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .keys()
+            .cloned()
+            .collect()
+    }
+
+    // Adds a layer inside the built-in ones, so it runs after them and before the handler.
+    pub fn push_layer(&self, layer: Arc<dyn HandlerLayer>) {
+        self.layers
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .push(layer);
+    }
+
+    // Answers `envelope` with `handler` through every layer the handler does not skip.
+    pub async fn answer(
+        &self,
+        state: &AppState,
+        handler: InboundHandler,
+        envelope: &MeshCommandEnvelope<Value>,
+    ) -> anyhow::Result<Value> {
+        let layers = self
+            .layers
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone();
+        Next {
+            layers: &layers,
+            handler,
+        }
+        .run(state, envelope)
+        .await
+    }
+
+    pub fn latencies(&self) -> BTreeMap<String, LatencyHistogram> {
+        self.latencies.snapshot()
+    }
+}
+
+pub(crate) fn refusal(error: &str) -> Value {
+    json!({ "status": "error", "error": error })
+}
+
+// Per-operation handler latency. A handler error or an error result counts as failed.
+#[derive(Debug, Default)]
+pub struct HandlerLatencies {
+    operations: Mutex<BTreeMap<String, LatencyHistogram>>,
+}
+
+impl HandlerLatencies {
+    pub fn snapshot(&self) -> BTreeMap<String, LatencyHistogram> {
+        self.operations
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    fn observe(&self, operation: &str, started: Instant, answer: &anyhow::Result<Value>) {
+        let failed = match answer {
+            Ok(answer) => answer.get("status").and_then(Value::as_str) == Some("error"),
+            Err(_) => true,
+        };
+        let elapsed_ms = started.elapsed().as_millis() as u64;
+        self.operations
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .entry(operation.to_string())
+            .or_default()
+            .observe(elapsed_ms, failed);
+    }
+}
+
+struct TimingLayer {
+    latencies: Arc<HandlerLatencies>,
+}
+
+impl HandlerLayer for TimingLayer {
+    fn name(&self) -> &'static str {
+        TIMING_LAYER
+    }
+
+    fn call<'a>(
+        &'a self,
+        state: &'a AppState,
+        envelope: &'a MeshCommandEnvelope<Value>,
+        next: Next<'a>,
+    ) -> BoxFuture<'a, anyhow::Result<Value>> {
+        Box::pin(async move {
+            let started = Instant::now();
+            let answer = next.run(state, envelope).await;
+            self.latencies
+                .observe(&envelope.operation, started, &answer);
+            answer
+        })
+    }
+}
+
+// A handler that panics answers `handler_panicked`; the inbound worker goes on to the next
+// command.
+struct PanicLayer;
+
+impl HandlerLayer for PanicLayer {
+    fn name(&self) -> &'static str {
+        PANIC_LAYER
+    }
+
+    fn call<'a>(
+        &'a self,
+        state: &'a AppState,
+        envelope: &'a MeshCommandEnvelope<Value>,
+        next: Next<'a>,
+    ) -> BoxFuture<'a, anyhow::Result<Value>> {
+        Box::pin(async move {
+            match AssertUnwindSafe(next.run(state, envelope))
+                .catch_unwind()
+                .await
+            {
+                Ok(answer) => answer,
+                Err(payload) => {
+                    forget_caught_panic();
+                    error!(
+                        operation = %envelope.operation,
+                        message_id = %envelope.message_id,
+                        panic = %panic_message(payload.as_ref()),
+                        "inbound handler panicked"
+                    );
+                    Ok(refusal(HANDLER_PANICKED_ERROR))
+                }
+            }
+        })
+    }
+}
+
+// Answers only allowlisted senders, and of those only the roles the contract's
+// `x-retasync-roles` names for the operation, when it names any.
+struct AuthorizationLayer;
+
+impl HandlerLayer for AuthorizationLayer {
+    fn name(&self) -> &'static str {
+        AUTHORIZATION_LAYER
+    }
+
+    fn call<'a>(
+        &'a self,
+        state: &'a AppState,
+        envelope: &'a MeshCommandEnvelope<Value>,
+        next: Next<'a>,
+    ) -> BoxFuture<'a, anyhow::Result<Value>> {
+        Box::pin(async move {
+            let source = &envelope.source_identity;
+            if !state.storage.is_allowlisted(source, Utc::now()).await? {
+                return Ok(refusal(NOT_ALLOWLISTED_ERROR));
+            }
+            let roles = state
+                .contract
+                .current()
+                .registry
+                .required_roles(&envelope.operation)
+                .map(<[String]>::to_vec);
+            if let Some(roles) = roles {
+                let entry = state.storage.get_allowlist_entry(source).await?;
+                if !entry.is_some_and(|entry| roles.contains(&entry.role)) {
+                    return Ok(refusal(ROLE_NOT_PERMITTED_ERROR));
+                }
+            }
+            next.run(state, envelope).await
+        })
+    }
+}
+
+// Holds the payload to the contract's `x-retasync-payloads` schema for the operation, if any.
+struct ValidationLayer;
+
+impl HandlerLayer for ValidationLayer {
+    fn name(&self) -> &'static str {
+        VALIDATION_LAYER
+    }
+
+    fn call<'a>(
+        &'a self,
+        state: &'a AppState,
+        envelope: &'a MeshCommandEnvelope<Value>,
+        next: Next<'a>,
+    ) -> BoxFuture<'a, anyhow::Result<Value>> {
+        Box::pin(async move {
+            let violations = state
+                .contract
+                .current()
+                .registry
+                .payload_schema(&envelope.operation)
+                .map(|schema| schema.validate(&envelope.payload))
+                .unwrap_or_default();
+            if !violations.is_empty() {
+                let mut answer = refusal(INVALID_PAYLOAD_ERROR);
+                answer["violations"] = json!(violations);
+                return Ok(answer);
+            }
+            next.run(state, envelope).await
+        })
+    }
+}
+
+// Caps the commands each operation's handler answers at once at
+// `[inbound] max_concurrent_per_operation`, refusing the rest as `handler_busy`.
+#[derive(Default)]
+struct ConcurrencyLayer {
+    // The limit each semaphore was made with, so a changed limit gets a new one.
+    permits: Mutex<BTreeMap<String, (usize, Arc<Semaphore>)>>,
+}
+
+impl ConcurrencyLayer {
+    fn semaphore(&self, operation: &str, limit: usize) -> Arc<Semaphore> {
+        let mut permits = self
+            .permits
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        match permits.get(operation) {
+            Some((made_with, semaphore)) if *made_with == limit => semaphore.clone(),
+            _ => {
+                let semaphore = Arc::new(Semaphore::new(limit));
+                permits.insert(operation.to_string(), (limit, semaphore.clone()));
+                semaphore
+            }
+        }
+    }
+}
+
+impl HandlerLayer for ConcurrencyLayer {
+    fn name(&self) -> &'static str {
+        CONCURRENCY_LAYER
+    }
+
+    fn call<'a>(
+        &'a self,
+        state: &'a AppState,
+        envelope: &'a MeshCommandEnvelope<Value>,
+        next: Next<'a>,
+    ) -> BoxFuture<'a, anyhow::Result<Value>> {
+        Box::pin(async move {
+            let limit = state
+                .node_config
+                .read()
+                .await
+                .inbound
+                .max_concurrent_per_operation;
+            if limit == 0 {
+                return next.run(state, envelope).await;
+            }
+            let semaphore = self.semaphore(&envelope.operation, limit);
+            let Ok(_permit) = semaphore.try_acquire_owned() else {
+                return Ok(refusal(HANDLER_BUSY_ERROR));
+            };
+            next.run(state, envelope).await
+        })
+    }
+}
+
+fn ping<'a>(
+    _state: &'a AppState,
+    _envelope: &'a MeshCommandEnvelope<Value>,
+) -> BoxFuture<'a, anyhow::Result<Value>> {
+    Box::pin(async { Ok(answer_ping()) })
+}
+
+#[cfg(feature = "entities")]
+fn sync_request<'a>(
+    state: &'a AppState,
+    envelope: &'a MeshCommandEnvelope<Value>,
+) -> BoxFuture<'a, anyhow::Result<Value>> {
+    Box::pin(async move {
+        let labels = meta_labels(envelope.meta.as_ref());
+        answer_sync_request(state, envelope, &labels).await
+    })
+}
+
+#[cfg(feature = "transfers")]
+fn offer<'a>(
+    state: &'a AppState,
+    envelope: &'a MeshCommandEnvelope<Value>,
+) -> BoxFuture<'a, anyhow::Result<Value>> {
+    Box::pin(answer_offer(state, envelope.payload.clone()))
+}
+
+fn status_report<'a>(
+    state: &'a AppState,
+    envelope: &'a MeshCommandEnvelope<Value>,
+) -> BoxFuture<'a, anyhow::Result<Value>> {
+    Box::pin(answer_status_report(state, envelope))
+}
+
+fn hello<'a>(
+    state: &'a AppState,
+    envelope: &'a MeshCommandEnvelope<Value>,
+) -> BoxFuture<'a, anyhow::Result<Value>> {
+    Box::pin(answer_hello(state, envelope))
+}
+
+#[cfg(feature = "entities")]
+fn entity_list<'a>(
+    state: &'a AppState,
+    envelope: &'a MeshCommandEnvelope<Value>,
+) -> BoxFuture<'a, anyhow::Result<Value>> {
+    Box::pin(answer_entity_list(state, envelope))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        InboundHandler, AUTHORIZATION_LAYER, CONCURRENCY_LAYER, HANDLER_BUSY_ERROR,
+        HANDLER_FAILED_ERROR, HANDLER_PANICKED_ERROR, INVALID_PAYLOAD_ERROR, NOT_ALLOWLISTED_ERROR,
+        ROLE_NOT_PERMITTED_ERROR,
+    };
+    use crate::contracts::LoadedContract;
+    use crate::dispatch::local_identity;
+    use crate::inbound::{route_command, spawn_inbound_worker};
+    use crate::liveness::NODE_PING_OPERATION;
+    use crate::test_support::node_state;
+    use crate::AppState;
+    use futures::future::BoxFuture;
+    use retasync_contract::MeshCommandEnvelope;
+    use retasync_mesh_bridge::InMemoryRpcMeshBridge;
+    use serde_json::{json, Value};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    const PEER: &str = "bb00000000000000000000000000000b";
+    const OTHER_PEER: &str = "cc00000000000000000000000000000c";
+    const PROBE: &str = "probe.run";
+
+    async fn node() -> (AppState, Arc<InMemoryRpcMeshBridge>) {
+        let bridge = Arc::new(InMemoryRpcMeshBridge::new(true, true));
+        (node_state(bridge.clone(), json!({})).await, bridge)
+    }
+
+    fn with_contract(state: &AppState, operations: &str) {
+        let document = format!(
+            "asyncapi: 3.0.0\nx-retasync:\n  operations:\n{operations}components:\n  schemas:\n    \
+             ProbePayload:\n      type: object\n      required: [uid]\n"
+        );
+        state.contract.replace(LoadedContract::lenient(document));
+    }
+
+    async fn command(
+        state: &AppState,
+        operation: &str,
+        payload: Value,
+    ) -> MeshCommandEnvelope<Value> {
+        MeshCommandEnvelope {
+            message_id: Uuid::now_v7().to_string(),
+            operation: operation.to_string(),
+            sent_at: chrono::Utc::now(),
+            source_identity: PEER.parse().unwrap(),
+            destination_identity: local_identity(&*state.node_config.read().await),
+            content_type: "application/msgpack".to_string(),
+            payload,
+            ttl_ms: None,
+            transport_hint: None,
+            trace: Vec::new(),
+            trace_truncated: false,
+            meta: None,
+        }
+    }
+
+    // Routes `payload` under `operation` and returns the answer sent back for it.
+    async fn answer(
+        state: &AppState,
+        bridge: &InMemoryRpcMeshBridge,
+        operation: &str,
+        payload: Value,
+    ) -> Value {
+        let envelope = command(state, operation, payload).await;
+        let message_id = envelope.message_id.clone();
+        route_command(state, envelope, chrono::Utc::now())
+            .await
+            .unwrap();
+        answer_to(bridge, &message_id)
+    }
+
+    fn answer_to(bridge: &InMemoryRpcMeshBridge, message_id: &str) -> Value {
+        bridge
+            .sent_results()
+            .into_iter()
+            .find(|result| result.correlation_id == message_id)
+            .map(|result| result.payload)
+            .unwrap_or(Value::Null)
+    }
+
+    static AUTHORIZED_RUNS: AtomicUsize = AtomicUsize::new(0);
+
+    fn count_authorized<'a>(
+        _state: &'a AppState,
+        _envelope: &'a MeshCommandEnvelope<Value>,
+    ) -> BoxFuture<'a, anyhow::Result<Value>> {
+        AUTHORIZED_RUNS.fetch_add(1, Ordering::SeqCst);
+        Box::pin(async { Ok(json!({ "status": "ok" })) })
+    }
+
+    #[tokio::test]
+    async fn unauthorized_senders_are_refused_without_the_handler_running() {
+        let (state, bridge) = node().await;
+        state
+            .handlers
+            .register(PROBE, InboundHandler::new(count_authorized));
+        with_contract(
+            &state,
+            "    commands: [probe.run]\n    x-retasync-roles:\n      probe.run: [relay]\n",
+        );
+
+        let refused = answer(&state, &bridge, PROBE, json!({})).await;
+        assert_eq!(
+            refused,
+            json!({ "status": "error", "error": NOT_ALLOWLISTED_ERROR })
+        );
+        state
+            .storage
+            .add_allowlist(&PEER.parse().unwrap(), None)
+            .await
+            .unwrap();
+        let refused = answer(&state, &bridge, PROBE, json!({})).await;
+        assert_eq!(
+            refused,
+            json!({ "status": "error", "error": ROLE_NOT_PERMITTED_ERROR })
+        );
+        assert_eq!(AUTHORIZED_RUNS.load(Ordering::SeqCst), 0);
+
+        with_contract(&state, "    commands: [probe.run]\n");
+        assert_eq!(
+            answer(&state, &bridge, PROBE, json!({})).await["status"],
+            "ok"
+        );
+        assert_eq!(AUTHORIZED_RUNS.load(Ordering::SeqCst), 1);
+        // Any peer may still ping.
+        with_contract(&state, "    x-retasync-roles:\n      node.ping: [relay]\n");
+        assert_eq!(
+            answer(&state, &bridge, NODE_PING_OPERATION, json!({})).await["status"],
+            "ok"
+        );
+    }
+
+    static VALIDATED_RUNS: AtomicUsize = AtomicUsize::new(0);
+
+    fn count_validated<'a>(
+        _state: &'a AppState,
+        _envelope: &'a MeshCommandEnvelope<Value>,
+    ) -> BoxFuture<'a, anyhow::Result<Value>> {
+        VALIDATED_RUNS.fetch_add(1, Ordering::SeqCst);
+        Box::pin(async { Ok(json!({ "status": "ok" })) })
+    }
+
+    #[tokio::test]
+    async fn payloads_breaking_the_contract_schema_never_reach_the_handler() {
+        let (state, bridge) = node().await;
+        let handler = InboundHandler::new(count_validated).skipping(&[AUTHORIZATION_LAYER]);
+        state.handlers.register(PROBE, handler);
+        with_contract(
+            &state,
+            "    x-retasync-payloads:\n      probe.run: ProbePayload\n",
+        );
+
+        let refused = answer(&state, &bridge, PROBE, json!({ "title": "no uid" })).await;
+        assert_eq!(refused["error"], INVALID_PAYLOAD_ERROR);
+        assert_eq!(
+            refused["violations"][0]["message"],
+            "missing required property `uid`"
+        );
+        assert_eq!(VALIDATED_RUNS.load(Ordering::SeqCst), 0);
+
+        let answered = answer(&state, &bridge, PROBE, json!({ "uid": "e-1" })).await;
+        assert_eq!(answered["status"], "ok");
+        assert_eq!(VALIDATED_RUNS.load(Ordering::SeqCst), 1);
+    }
+
+    fn panics<'a>(
+        _state: &'a AppState,
+        _envelope: &'a MeshCommandEnvelope<Value>,
+    ) -> BoxFuture<'a, anyhow::Result<Value>> {
+        Box::pin(async { panic!("probe handler gave up") })
+    }
+
+    #[tokio::test]
+    async fn a_panicking_handler_answers_with_an_error_and_the_worker_goes_on() {
+        let (state, bridge) = node().await;
+        let handler = InboundHandler::new(panics).skipping(&[AUTHORIZATION_LAYER]);
+        state.handlers.register(PROBE, handler);
+        let panicking = command(&state, PROBE, json!({})).await;
+        let ping = command(&state, NODE_PING_OPERATION, json!({})).await;
+        let ids = [panicking.message_id.clone(), ping.message_id.clone()];
+        bridge.inject_command(panicking);
+        bridge.inject_command(ping);
+
+        let worker = spawn_inbound_worker(state.clone(), Duration::from_millis(5));
+        for _ in 0..200 {
+            if bridge.sent_results().len() >= 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        worker.abort();
+        assert_eq!(
+            answer_to(&bridge, &ids[0]),
+            json!({ "status": "error", "error": HANDLER_PANICKED_ERROR })
+        );
+        assert_eq!(answer_to(&bridge, &ids[1])["status"], "ok");
+        let latencies = state.handlers.latencies();
+        assert_eq!((latencies[PROBE].attempts, latencies[PROBE].errors), (1, 1));
+        assert_eq!(latencies[NODE_PING_OPERATION].errors, 0);
+    }
+
+    fn failing<'a>(
+        _state: &'a AppState,
+        _envelope: &'a MeshCommandEnvelope<Value>,
+    ) -> BoxFuture<'a, anyhow::Result<Value>> {
+        Box::pin(async { Err(anyhow::anyhow!("database at /var/lib/node.db is locked")) })
+    }
+
+    #[tokio::test]
+    async fn a_failing_handler_answers_a_fixed_error_without_its_details() {
+        let (state, bridge) = node().await;
+        let handler = InboundHandler::new(failing).skipping(&[AUTHORIZATION_LAYER]);
+        state.handlers.register(PROBE, handler);
+
+        assert_eq!(
+            answer(&state, &bridge, PROBE, json!({})).await,
+            json!({ "status": "error", "error": HANDLER_FAILED_ERROR })
+        );
+    }
+
+    fn slow<'a>(
+        _state: &'a AppState,
+        _envelope: &'a MeshCommandEnvelope<Value>,
+    ) -> BoxFuture<'a, anyhow::Result<Value>> {
+        Box::pin(async {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            Ok(json!({ "status": "ok" }))
+        })
+    }
+
+    // Starts one slow command, then answers a second while the first still runs.
+    async fn overlapping(state: &AppState, bridge: &InMemoryRpcMeshBridge) -> Value {
+        let first = command(state, PROBE, json!({})).await;
+        let running = tokio::spawn({
+            let state = state.clone();
+            async move { route_command(&state, first, chrono::Utc::now()).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let second = answer(state, bridge, PROBE, json!({})).await;
+        running.await.unwrap().unwrap();
+        second
+    }
+
+    #[tokio::test]
+    async fn concurrent_commands_past_the_limit_are_refused_unless_the_handler_opts_out() {
+        let (state, bridge) = node().await;
+        state
+            .node_config
+            .write()
+            .await
+            .inbound
+            .max_concurrent_per_operation = 1;
+        let handler = InboundHandler::new(slow).skipping(&[AUTHORIZATION_LAYER]);
+        state.handlers.register(PROBE, handler);
+        let refused = overlapping(&state, &bridge).await;
+        assert_eq!(
+            refused,
+            json!({ "status": "error", "error": HANDLER_BUSY_ERROR })
+        );
+
+        let unlimited = handler.skipping(&[AUTHORIZATION_LAYER, CONCURRENCY_LAYER]);
+        state.handlers.register(PROBE, unlimited);
+        assert_eq!(overlapping(&state, &bridge).await["status"], "ok");
+    }
+
+    #[tokio::test]
+    async fn the_inbound_worker_answers_senders_at_once_so_the_limit_applies() {
+        let (state, bridge) = node().await;
+        state
+            .node_config
+            .write()
+            .await
+            .inbound
+            .max_concurrent_per_operation = 1;
+        let handler = InboundHandler::new(slow).skipping(&[AUTHORIZATION_LAYER]);
+        state.handlers.register(PROBE, handler);
+        let first = command(&state, PROBE, json!({})).await;
+        let mut second = command(&state, PROBE, json!({})).await;
+        second.source_identity = OTHER_PEER.parse().unwrap();
+        let ids = [first.message_id.clone(), second.message_id.clone()];
+        bridge.inject_command(first);
+        bridge.inject_command(second);
+
+        let worker = spawn_inbound_worker(state.clone(), Duration::from_millis(5));
+        for _ in 0..200 {
+            if bridge.sent_results().len() >= 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        worker.abort();
+        let mut answers = ids.map(|id| answer_to(&bridge, &id));
+        answers.sort_by_key(|answer| answer["status"].to_string());
+        assert_eq!(answers[1]["status"], "ok");
+        let busy = &answers[0];
+        assert_eq!(busy["error"], HANDLER_BUSY_ERROR);
+    }
+}
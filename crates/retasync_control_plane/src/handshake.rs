@@ -0,0 +1,318 @@
+﻿use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context};
+use chrono::Utc;
+use retasync_contract::{IdentityHash, MeshCommandEnvelope, CONTENT_TYPE_MSGPACK};
+use retasync_mesh_bridge::{Compatibility, PeerHandshake};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::app::{current_capabilities, emit};
+use crate::capabilities::{Capabilities, ContractVersion};
+use crate::dispatch::{is_identity_hash, local_identity};
+use crate::liveness::record_contact;
+use crate::sealing::{local_sealing_key, register_offered_key};
+use crate::AppState;
+
+pub const NODE_HELLO_OPERATION: &str = "node.hello";
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+// How long a peer whose handshake failed goes unassessed before dispatching to it tries again.
+pub const HANDSHAKE_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NodeHello {
+    pub contracts: Vec<ContractVersion>,
+    pub capabilities_digest: String,
+    pub content_types: Vec<String>,
+    // Our base64 X25519 key for sealed payloads, offered when `security.exchange_keys` is on.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sealing_key: Option<String>,
+}
+
+impl NodeHello {
+    pub fn from_capabilities(capabilities: &Capabilities) -> Self {
+        Self {
+            contracts: capabilities.contracts.clone(),
+            capabilities_digest: capabilities.digest(),
+            content_types: capabilities.content_types.clone(),
+            sealing_key: None,
+        }
+    }
+}
+
+// Identical contract sets are compatible. Sets that share a contract at the same major versions
+// are degraded; anything else, or no common content type, is incompatible.
+pub fn assess(local: &NodeHello, remote: &NodeHello) -> Compatibility {
+    let shared_content_type = remote
+        .content_types
+        .iter()
+        .any(|content_type| local.content_types.contains(content_type));
+    if !shared_content_type {
+        return Compatibility::Incompatible;
+    }
+
+    let same_set = local.contracts.len() == remote.contracts.len()
+        && local
+            .contracts
+            .iter()
+            .all(|contract| remote.contracts.contains(contract));
+    if same_set {
+        return Compatibility::Compatible;
+    }
+
+    let overlapping = local.contracts.iter().any(|ours| {
+        remote.contracts.iter().any(|theirs| {
+            major(&ours.asyncapi) == major(&theirs.asyncapi)
+                && major(&ours.version) == major(&theirs.version)
+        })
+    });
+    if overlapping {
+        Compatibility::Degraded
+    } else {
+        Compatibility::Incompatible
+    }
+}
+
+fn major(version: &Option<String>) -> Option<&str> {
+    version.as_deref().and_then(|version| version.split('.').next())
+}
+
+pub async fn local_hello(state: &AppState) -> NodeHello {
+    let mut hello = NodeHello::from_capabilities(&current_capabilities(state).await);
+    if state.node_config.read().await.security.exchange_keys {
+        match local_sealing_key(state).await {
+            Ok(key) => hello.sealing_key = Some(key),
+            Err(err) => warn!(error = %err, "sealing key not offered"),
+        }
+    }
+    hello
+}
+
+// Sends `node.hello` to a peer and caches the verdict in the peer directory.
+pub async fn handshake(
+    state: &AppState,
+    destination_identity: &str,
+) -> anyhow::Result<PeerHandshake> {
+    let local = local_hello(state).await;
+    let source_identity = local_identity(&*state.node_config.read().await);
+    let envelope = MeshCommandEnvelope {
+        message_id: Uuid::now_v7().to_string(),
+        operation: NODE_HELLO_OPERATION.to_string(),
+        sent_at: Utc::now(),
+        source_identity,
+        destination_identity: IdentityHash::lenient(destination_identity),
+        content_type: CONTENT_TYPE_MSGPACK.to_string(),
+        payload: serde_json::to_value(&local)?,
+        ttl_ms: Some(HANDSHAKE_TIMEOUT.as_millis() as u64),
+        transport_hint: None,
+        trace: Vec::new(),
+        trace_truncated: false,
+        meta: None,
+    };
+
+    let result = tokio::time::timeout(HANDSHAKE_TIMEOUT, state.bridge.send_command(envelope))
+        .await
+        .map_err(|_| anyhow!("{NODE_HELLO_OPERATION} to {destination_identity} timed out"))??;
+    let remote: NodeHello = serde_json::from_value(result.payload.clone()).with_context(|| {
+        format!("{destination_identity} did not answer {NODE_HELLO_OPERATION}")
+    })?;
+
+    let handshake = record(state, destination_identity, &local, &remote, result.payload).await;
+    Ok(handshake)
+}
+
+enum Attempt {
+    InFlight,
+    Failed(Instant),
+}
+
+// Background handshakes by peer: one at a time, and none for HANDSHAKE_RETRY_AFTER once one
+// has failed.
+#[derive(Default)]
+pub struct HandshakeAttempts {
+    attempts: Mutex<BTreeMap<String, Attempt>>,
+}
+
+impl HandshakeAttempts {
+    // Claims a handshake to `identity_hash` unless one is running or failed recently.
+    fn start(&self, identity_hash: &str, now: Instant) -> bool {
+        let mut attempts = self
+            .attempts
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        attempts.retain(|_, attempt| match attempt {
+            Attempt::InFlight => true,
+            Attempt::Failed(at) => now.duration_since(*at) < HANDSHAKE_RETRY_AFTER,
+        });
+        if attempts.contains_key(identity_hash) {
+            return false;
+        }
+        attempts.insert(identity_hash.to_string(), Attempt::InFlight);
+        true
+    }
+
+    fn finish(&self, identity_hash: &str, succeeded: bool, now: Instant) {
+        let mut attempts = self
+            .attempts
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if succeeded {
+            attempts.remove(identity_hash);
+        } else {
+            attempts.insert(identity_hash.to_string(), Attempt::Failed(now));
+        }
+    }
+}
+
+// The cached verdict for a destination. A peer without one is handshaken in the background
+// and left unassessed until it answers, so a submission never waits on `node.hello`; a peer
+// that cannot be reached or does not speak it is not asked again for HANDSHAKE_RETRY_AFTER.
+pub fn peer_handshake(state: &AppState, destination_identity: &str) -> Option<PeerHandshake> {
+    if !is_identity_hash(destination_identity) {
+        return None;
+    }
+    if let Some(cached) = state.peers.handshake(destination_identity) {
+        return Some(cached);
+    }
+    if state.handshakes.start(destination_identity, Instant::now()) {
+        let state = state.clone();
+        let destination_identity = destination_identity.to_string();
+        tokio::spawn(async move {
+            let outcome = handshake(&state, &destination_identity).await;
+            if let Err(err) = &outcome {
+                warn!(destination_identity, error = %err, "peer handshake failed");
+            }
+            state
+                .handshakes
+                .finish(&destination_identity, outcome.is_ok(), Instant::now());
+        });
+    }
+    None
+}
+
+// Built-in handler for an inbound `node.hello`: records the sender's verdict and returns ours.
+pub async fn answer_hello(
+    state: &AppState,
+    envelope: &MeshCommandEnvelope<Value>,
+) -> anyhow::Result<Value> {
+    let local = local_hello(state).await;
+    match serde_json::from_value::<NodeHello>(envelope.payload.clone()) {
+        Ok(remote) => {
+            record(
+                state,
+                &envelope.source_identity,
+                &local,
+                &remote,
+                envelope.payload.clone(),
+            )
+            .await;
+        }
+        Err(err) => warn!(
+            source_identity = %envelope.source_identity,
+            error = %err,
+            "malformed node.hello"
+        ),
+    }
+    Ok(serde_json::to_value(&local)?)
+}
+
+async fn record(
+    state: &AppState,
+    identity_hash: &str,
+    local: &NodeHello,
+    remote: &NodeHello,
+    hello: Value,
+) -> PeerHandshake {
+    let handshake = PeerHandshake {
+        compatibility: assess(local, remote),
+        hello,
+        checked_at: Utc::now().to_rfc3339(),
+    };
+    let peer = IdentityHash::lenient(identity_hash);
+    state.peers.record_handshake(&peer, handshake.clone());
+    record_contact(state, &peer, Utc::now()).await;
+    if let (Some(_), Some(offered)) = (&local.sealing_key, &remote.sealing_key) {
+        register_offered_key(state, identity_hash, offered).await;
+    }
+    if handshake.compatibility != Compatibility::Compatible {
+        warn!(
+            identity_hash,
+            compatibility = ?handshake.compatibility,
+            "peer contract differs from ours"
+        );
+    }
+    emit(
+        state,
+        "peer.handshake.completed",
+        json!({
+            "identity_hash": identity_hash,
+            "compatibility": handshake.compatibility,
+        }),
+    )
+    .await;
+    handshake
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{assess, HandshakeAttempts, NodeHello, HANDSHAKE_RETRY_AFTER};
+    use std::time::{Duration, Instant};
+    use crate::capabilities::ContractVersion;
+    use retasync_contract::ChannelStyle;
+    use retasync_mesh_bridge::Compatibility;
+
+    fn hello(versions: &[&str], content_types: &[&str]) -> NodeHello {
+        NodeHello {
+            contracts: versions
+                .iter()
+                .map(|version| ContractVersion {
+                    asyncapi: Some("3.0.0".to_string()),
+                    version: Some(version.to_string()),
+                    channel_style: ChannelStyle::Generic,
+                })
+                .collect(),
+            capabilities_digest: "digest".to_string(),
+            content_types: content_types.iter().map(|ct| ct.to_string()).collect(),
+            sealing_key: None,
+        }
+    }
+
+    #[test]
+    fn verdicts_follow_contract_overlap() {
+        let msgpack = ["application/msgpack"];
+        let local = hello(&["1.2.0"], &msgpack);
+
+        assert_eq!(assess(&local, &hello(&["1.2.0"], &msgpack)), Compatibility::Compatible);
+        assert_eq!(assess(&local, &hello(&["1.4.1"], &msgpack)), Compatibility::Degraded);
+        assert_eq!(
+            assess(&local, &hello(&["1.2.0", "2.0.0"], &msgpack)),
+            Compatibility::Degraded
+        );
+        assert_eq!(assess(&local, &hello(&["2.0.0"], &msgpack)), Compatibility::Incompatible);
+        assert_eq!(
+            assess(&local, &hello(&["1.2.0"], &["application/cbor"])),
+            Compatibility::Incompatible
+        );
+    }
+
+    #[test]
+    fn failed_handshakes_wait_before_trying_again() {
+        let attempts = HandshakeAttempts::default();
+        let start = Instant::now();
+        assert!(attempts.start("peer", start));
+        assert!(!attempts.start("peer", start), "one handshake at a time");
+        attempts.finish("peer", false, start);
+
+        let soon = start + Duration::from_secs(1);
+        assert!(!attempts.start("peer", soon));
+        assert!(attempts.start("other", soon));
+        let later = start + HANDSHAKE_RETRY_AFTER;
+        assert!(attempts.start("peer", later));
+        attempts.finish("peer", true, later);
+        assert!(attempts.start("peer", later));
+    }
+}
@@ -0,0 +1,392 @@
+﻿use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use retasync_storage::{HealthSample, RetasyncStorage};
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+use tracing::error;
+
+use crate::diagnostics::{check_bridge, check_storage, CheckStatus, BRIDGE_PROBE_TIMEOUT};
+use crate::AppState;
+
+pub const RAW_RETENTION_SECS: i64 = 24 * 3600;
+pub const AGGREGATE_RETENTION_SECS: i64 = 30 * 24 * 3600;
+pub const AGGREGATE_RESOLUTION_SECS: i64 = 300;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Availability {
+    pub bridge_pct: Option<f64>,
+    pub storage_pct: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Percentiles {
+    pub p50: Option<i64>,
+    pub p95: Option<i64>,
+    pub p99: Option<i64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LatencyStats {
+    pub bridge: Percentiles,
+    pub storage: Percentiles,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Outage {
+    pub component: String,
+    pub started_at: String,
+    pub ended_at: Option<String>,
+    pub duration_secs: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HealthBucket {
+    pub start: String,
+    pub samples: i64,
+    pub bridge_availability_pct: Option<f64>,
+    pub storage_availability_pct: Option<f64>,
+    pub bridge_latency_ms: Option<i64>,
+    pub storage_latency_ms: Option<i64>,
+    pub queued_jobs: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HealthHistory {
+    pub from: String,
+    pub to: String,
+    pub resolution_secs: i64,
+    pub samples: i64,
+    pub availability: Availability,
+    pub latency_ms: LatencyStats,
+    pub outages: Vec<Outage>,
+    pub buckets: Vec<HealthBucket>,
+}
+
+pub fn spawn_health_sampler(state: AppState, period: Duration) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(period);
+        loop {
+            ticker.tick().await;
+            if let Err(err) = sample_health(&state).await {
+                error!(error = %err, "health sample failed");
+            }
+        }
+    })
+}
+
+pub async fn sample_health(state: &AppState) -> anyhow::Result<HealthSample> {
+    let started = Instant::now();
+    let bridge = check_bridge(state.bridge.as_ref(), BRIDGE_PROBE_TIMEOUT).await;
+    let bridge_latency_ms = started.elapsed().as_millis() as i64;
+    let started = Instant::now();
+    let storage = check_storage(&state.storage).await;
+    let storage_latency_ms = started.elapsed().as_millis() as i64;
+    let queued_jobs = state.storage.count_jobs_with_status("queued").await.unwrap_or(0);
+
+    let sample = HealthSample::raw(
+        Utc::now(),
+        (bridge.status == CheckStatus::Pass, bridge_latency_ms),
+        (storage.status == CheckStatus::Pass, storage_latency_ms),
+        queued_jobs,
+    );
+    state.storage.insert_health_sample(&sample).await?;
+    Ok(sample)
+}
+
+// Raw samples older than a day are folded into 5-minute rows; the cutoff is aligned to a
+// bucket boundary so a bucket is never split between raw and aggregated storage.
+pub async fn compact_health_history(
+    storage: &RetasyncStorage,
+    now: DateTime<Utc>,
+) -> anyhow::Result<u64> {
+    let raw_before = align(now - chrono::Duration::seconds(RAW_RETENTION_SECS));
+    let keep_after = now - chrono::Duration::seconds(AGGREGATE_RETENTION_SECS);
+    let raw: Vec<HealthSample> = storage
+        .list_health_samples(keep_after, raw_before)
+        .await?
+        .into_iter()
+        .filter(|sample| sample.resolution_secs == 0)
+        .collect();
+    let aggregates = downsample(&raw, AGGREGATE_RESOLUTION_SECS)?;
+    Ok(storage
+        .compact_health_samples(raw_before, &aggregates, keep_after)
+        .await?)
+}
+
+pub fn downsample(
+    samples: &[HealthSample],
+    resolution_secs: i64,
+) -> anyhow::Result<Vec<HealthSample>> {
+    let mut buckets: Vec<(i64, Vec<&HealthSample>)> = Vec::new();
+    for sample in samples {
+        let start = bucket_start(sample.sampled_at()?, resolution_secs);
+        match buckets.last_mut() {
+            Some((current, members)) if *current == start => members.push(sample),
+            _ => buckets.push((start, vec![sample])),
+        }
+    }
+
+    buckets
+        .into_iter()
+        .map(|(start, members)| {
+            let at = DateTime::from_timestamp(start, 0).unwrap_or_default();
+            let mut aggregate = HealthSample::aggregate(at, resolution_secs);
+            aggregate.sample_count = members.iter().map(|row| row.sample_count).sum();
+            aggregate.bridge_ok = members.iter().map(|row| row.bridge_ok).sum();
+            aggregate.storage_ok = members.iter().map(|row| row.storage_ok).sum();
+            aggregate.bridge_latency_ms =
+                mean_ok_latency(&members, |row| (row.bridge_ok, row.bridge_latency_ms))
+                    .unwrap_or(0);
+            aggregate.storage_latency_ms =
+                mean_ok_latency(&members, |row| (row.storage_ok, row.storage_latency_ms))
+                    .unwrap_or(0);
+            aggregate.queued_jobs = members.iter().map(|row| row.queued_jobs).max().unwrap_or(0);
+            Ok(aggregate)
+        })
+        .collect()
+}
+
+pub fn summarize(
+    samples: &[HealthSample],
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    resolution_secs: i64,
+) -> anyhow::Result<HealthHistory> {
+    let buckets = downsample(samples, resolution_secs)?
+        .into_iter()
+        .map(|row| HealthBucket {
+            start: row.sampled_at.clone(),
+            samples: row.sample_count,
+            bridge_availability_pct: percent(row.bridge_ok, row.sample_count),
+            storage_availability_pct: percent(row.storage_ok, row.sample_count),
+            bridge_latency_ms: (row.bridge_ok > 0).then_some(row.bridge_latency_ms),
+            storage_latency_ms: (row.storage_ok > 0).then_some(row.storage_latency_ms),
+            queued_jobs: row.queued_jobs,
+        })
+        .collect();
+
+    Ok(HealthHistory {
+        from: from.to_rfc3339(),
+        to: to.to_rfc3339(),
+        resolution_secs,
+        samples: samples.iter().map(|row| row.sample_count).sum(),
+        availability: availability(samples),
+        latency_ms: LatencyStats {
+            bridge: percentiles(samples, |row| (row.bridge_ok, row.bridge_latency_ms)),
+            storage: percentiles(samples, |row| (row.storage_ok, row.storage_latency_ms)),
+        },
+        outages: [
+            outages(samples, "bridge", to, |row| row.bridge_ok)?,
+            outages(samples, "storage", to, |row| row.storage_ok)?,
+        ]
+        .concat(),
+        buckets,
+    })
+}
+
+pub fn availability(samples: &[HealthSample]) -> Availability {
+    let total = samples.iter().map(|row| row.sample_count).sum();
+    Availability {
+        bridge_pct: percent(samples.iter().map(|row| row.bridge_ok).sum(), total),
+        storage_pct: percent(samples.iter().map(|row| row.storage_ok).sum(), total),
+    }
+}
+
+// Accepts `90s`, `5m`, `24h` or `30d`.
+pub fn parse_span(raw: &str) -> Option<i64> {
+    let raw = raw.trim();
+    let split = raw.len().checked_sub(1)?;
+    let value: i64 = raw.get(..split)?.parse().ok()?;
+    let unit = match raw.get(split..)? {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86_400,
+        _ => return None,
+    };
+    value.checked_mul(unit).filter(|secs| *secs > 0)
+}
+
+// A downsampled row only counts as down when none of the samples it covers were ok.
+fn outages(
+    samples: &[HealthSample],
+    component: &str,
+    to: DateTime<Utc>,
+    ok: impl Fn(&HealthSample) -> i64,
+) -> anyhow::Result<Vec<Outage>> {
+    let mut found = Vec::new();
+    let mut open: Option<DateTime<Utc>> = None;
+    for sample in samples {
+        let at = sample.sampled_at()?;
+        match (ok(sample) > 0, open) {
+            (false, None) => open = Some(at),
+            (true, Some(started)) => {
+                found.push(outage(component, started, Some(at)));
+                open = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(started) = open {
+        let mut last = outage(component, started, None);
+        last.duration_secs = (to - started).num_seconds().max(0);
+        found.push(last);
+    }
+    Ok(found)
+}
+
+fn outage(component: &str, started: DateTime<Utc>, ended: Option<DateTime<Utc>>) -> Outage {
+    Outage {
+        component: component.to_string(),
+        started_at: started.to_rfc3339(),
+        ended_at: ended.map(|at| at.to_rfc3339()),
+        duration_secs: ended.map_or(0, |at| (at - started).num_seconds()),
+    }
+}
+
+// Nearest-rank over the latency of ok probes; failed probes only measure the timeout.
+fn percentiles(
+    samples: &[HealthSample],
+    probe: impl Fn(&HealthSample) -> (i64, i64),
+) -> Percentiles {
+    let mut latencies: Vec<i64> = samples
+        .iter()
+        .map(&probe)
+        .filter(|(ok, _)| *ok > 0)
+        .map(|(_, latency)| latency)
+        .collect();
+    latencies.sort_unstable();
+    let rank = |pct: usize| {
+        let index = (pct * latencies.len()).div_ceil(100).saturating_sub(1);
+        latencies.get(index).copied()
+    };
+    Percentiles {
+        p50: rank(50),
+        p95: rank(95),
+        p99: rank(99),
+    }
+}
+
+fn mean_ok_latency(
+    members: &[&HealthSample],
+    probe: impl Fn(&HealthSample) -> (i64, i64),
+) -> Option<i64> {
+    let (ok, weighted) = members
+        .iter()
+        .map(|row| probe(row))
+        .fold((0, 0), |(ok, weighted), (row_ok, latency)| {
+            (ok + row_ok, weighted + row_ok * latency)
+        });
+    (ok > 0).then(|| weighted / ok)
+}
+
+fn percent(ok: i64, total: i64) -> Option<f64> {
+    (total > 0).then(|| ok as f64 * 100.0 / total as f64)
+}
+
+fn bucket_start(at: DateTime<Utc>, resolution_secs: i64) -> i64 {
+    at.timestamp().div_euclid(resolution_secs) * resolution_secs
+}
+
+fn align(at: DateTime<Utc>) -> DateTime<Utc> {
+    DateTime::from_timestamp(bucket_start(at, AGGREGATE_RESOLUTION_SECS), 0).unwrap_or(at)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compact_health_history, parse_span, summarize, AGGREGATE_RESOLUTION_SECS};
+    use crate::test_support::{scratch_sqlite, test_storage};
+    use chrono::{DateTime, Duration, TimeZone, Utc};
+    use retasync_storage::HealthSample;
+
+    fn start() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 3, 1, 12, 0, 0).unwrap()
+    }
+
+    // One sample every 10s; the bridge is down for the listed sample indices.
+    fn flapping(count: i64, down: &[std::ops::Range<i64>]) -> Vec<HealthSample> {
+        (0..count)
+            .map(|index| {
+                let bridge_ok = !down.iter().any(|range| range.contains(&index));
+                HealthSample::raw(
+                    start() + Duration::seconds(index * 10),
+                    (bridge_ok, if bridge_ok { 10 + index } else { 3000 }),
+                    (true, 2),
+                    index % 4,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn flapping_bridge_yields_availability_and_outage_intervals() {
+        let samples = flapping(60, &[10..15, 30..33, 58..60]);
+        let to = start() + Duration::seconds(600);
+        let history = summarize(&samples, start(), to, 300).unwrap();
+
+        assert_eq!(history.samples, 60);
+        assert_eq!(history.availability.bridge_pct, Some(50.0 * 100.0 / 60.0));
+        assert_eq!(history.availability.storage_pct, Some(100.0));
+
+        let bridge: Vec<_> = history
+            .outages
+            .iter()
+            .filter(|outage| outage.component == "bridge")
+            .map(|outage| {
+                let ended = outage.ended_at.is_some();
+                (outage.started_at.clone(), outage.duration_secs, ended)
+            })
+            .collect();
+        let at = |secs: i64| (start() + Duration::seconds(secs)).to_rfc3339();
+        assert_eq!(
+            bridge,
+            vec![(at(100), 50, true), (at(300), 30, true), (at(580), 20, false)]
+        );
+        assert_eq!(history.outages.len(), 3);
+
+        assert_eq!(history.latency_ms.bridge.p50, Some(10 + 29));
+        assert_eq!(history.latency_ms.bridge.p99, Some(10 + 57));
+        assert_eq!(history.buckets.len(), 2);
+        assert_eq!(history.buckets[0].samples, 30);
+        assert_eq!(history.buckets[0].bridge_availability_pct, Some(25.0 * 100.0 / 30.0));
+        assert_eq!(history.buckets[1].queued_jobs, 3);
+    }
+
+    #[test]
+    fn spans_parse_with_units() {
+        assert_eq!(parse_span("24h"), Some(86_400));
+        assert_eq!(parse_span("5m"), Some(300));
+        assert_eq!(parse_span("0m"), None);
+        assert_eq!(parse_span("5w"), None);
+        assert_eq!(parse_span(""), None);
+    }
+
+    #[tokio::test]
+    async fn compaction_downsamples_old_samples_without_changing_availability() {
+        let storage = test_storage(&scratch_sqlite()).await;
+        let samples = flapping(60, &[10..15, 30..33, 58..60]);
+        for sample in &samples {
+            storage.insert_health_sample(sample).await.unwrap();
+        }
+        let window = (start(), start() + Duration::days(40));
+
+        let now = start() + Duration::hours(24) + Duration::seconds(450);
+        assert_eq!(compact_health_history(&storage, now).await.unwrap(), 30);
+        let stored = storage.list_health_samples(window.0, window.1).await.unwrap();
+        assert_eq!(stored.len(), 31);
+        assert_eq!(stored[0].resolution_secs, AGGREGATE_RESOLUTION_SECS);
+        assert_eq!((stored[0].sample_count, stored[0].bridge_ok), (30, 25));
+        let history = summarize(&stored, window.0, now, 300).unwrap();
+        assert_eq!(history.availability.bridge_pct, Some(50.0 * 100.0 / 60.0));
+        assert_eq!(history.outages.len(), 2);
+
+        let later = start() + Duration::days(31);
+        compact_health_history(&storage, later).await.unwrap();
+        assert!(storage
+            .list_health_samples(window.0, window.1)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
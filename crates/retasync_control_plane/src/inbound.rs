@@ -0,0 +1,943 @@
+﻿use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use retasync_contract::{
+    expand_payload, CodecError, CodecLimits, Compression, HopRecord, MeshCommandEnvelope,
+    MeshResultEnvelope, CONTENT_TYPE_MSGPACK,
+};
+use retasync_mesh_bridge::{BridgeError, RpcMeshBridge};
+use retasync_storage::{InboundRecord, PayloadTable};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::task::{JoinHandle, JoinSet};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::app::{emit, publish};
+#[cfg(feature = "transfers")]
+use crate::bundles::receive_transfer;
+use crate::circuit::CircuitView;
+use crate::clock::skew_allowance_secs;
+use crate::dispatch::local_identity;
+use crate::dispatch_queue::DispatchQueueView;
+use crate::error::{RetryAdvice, RetryScope, RETRY_ADVICE_FIELD};
+use crate::features::INBOUND_COMMANDS_FLAG;
+use crate::handlers::{refusal, HANDLER_FAILED_ERROR};
+use crate::liveness::record_contact;
+use crate::migrations::migrate_inbound;
+use crate::mute::{MuteStatus, Traffic};
+use crate::receipts::send_receipt;
+use crate::replay::{
+    screen_envelope, Verdict, DEFAULT_MAX_ENVELOPE_AGE_SECS, DUPLICATE_MESSAGE_ERROR,
+    MESSAGE_EXPIRED_ERROR, REPLAY_DETECTED_EVENT,
+};
+use crate::sealing::{is_sealed, open_command};
+use crate::trace::{push_hop, transport_hint, COMMAND_FORWARDED_EVENT, FORWARD_FAILED_ERROR};
+use crate::AppState;
+
+pub const INBOUND_COMMANDS_DISABLED_ERROR: &str = "inbound_commands_disabled";
+pub const DEFAULT_MAX_CONCURRENT_PER_OPERATION: usize = 4;
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+// Senders whose commands the worker answers at once; each sender's are answered in order.
+const MAX_CONCURRENT_SOURCES: usize = 16;
+#[cfg(feature = "transfers")]
+const TRANSFER_POLL_LIMIT: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InboundSettings {
+    pub capacity: usize,
+    pub rate_limit_per_minute: u32,
+    pub source_rate_limits: BTreeMap<String, u32>,
+    // Commands sent longer ago than this are refused; 0 accepts any age and keeps every
+    // message id seen, since none of them can be ruled out as a replay.
+    pub max_envelope_age_secs: u64,
+    // Keeps every command a built-in handler answers, with the answer, for replaying later.
+    pub record_inbound: bool,
+    // Commands one operation's handler answers at once; more are refused as `handler_busy`.
+    // 0 sets no limit.
+    pub max_concurrent_per_operation: usize,
+}
+
+impl Default for InboundSettings {
+    fn default() -> Self {
+        Self {
+            capacity: 256,
+            rate_limit_per_minute: 120,
+            source_rate_limits: BTreeMap::new(),
+            max_envelope_age_secs: DEFAULT_MAX_ENVELOPE_AGE_SECS,
+            record_inbound: false,
+            max_concurrent_per_operation: DEFAULT_MAX_CONCURRENT_PER_OPERATION,
+        }
+    }
+}
+
+impl InboundSettings {
+    fn limit_for(&self, source: &str) -> u32 {
+        self.source_rate_limits
+            .get(source)
+            .copied()
+            .unwrap_or(self.rate_limit_per_minute)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Admission {
+    Queued,
+    RateLimited { retry_after: Duration },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueueSnapshot {
+    pub depth: usize,
+    pub capacity: usize,
+    pub backpressure: bool,
+    pub sources: BTreeMap<String, usize>,
+    pub rate_limited: BTreeMap<String, u64>,
+}
+
+// What `GET /v1/node/queue` reports: the inbound queue and everything else holding work back.
+#[derive(Debug, Serialize)]
+pub struct QueueSummary {
+    #[serde(flatten)]
+    pub inbound: QueueSnapshot,
+    pub waiting_jobs: i64,
+    pub mute: MuteStatus,
+    pub circuits: Vec<CircuitView>,
+    pub dispatch: DispatchQueueView,
+}
+
+#[derive(Debug, Default)]
+struct QueueState {
+    settings: InboundSettings,
+    queues: HashMap<String, VecDeque<MeshCommandEnvelope<Value>>>,
+    rotation: VecDeque<String>,
+    depth: usize,
+    windows: HashMap<String, (Instant, u32)>,
+    windows_swept_at: Option<Instant>,
+    rate_limited: BTreeMap<String, u64>,
+    backpressure: bool,
+}
+
+#[derive(Debug)]
+pub struct InboundQueue {
+    state: Mutex<QueueState>,
+}
+
+impl InboundQueue {
+    pub fn new(settings: InboundSettings) -> Self {
+        Self {
+            state: Mutex::new(QueueState {
+                settings,
+                ..QueueState::default()
+            }),
+        }
+    }
+
+    pub fn configure(&self, settings: InboundSettings) {
+        self.lock_state().settings = settings;
+    }
+
+    pub fn admit(&self, envelope: MeshCommandEnvelope<Value>) -> Admission {
+        let mut state = self.lock_state();
+        let source = envelope.source_identity.to_string();
+        let limit = state.settings.limit_for(&source);
+
+        if limit > 0 {
+            let now = Instant::now();
+            evict_idle_windows(&mut state, now);
+            let window = state.windows.entry(source.clone()).or_insert((now, 0));
+            if now.duration_since(window.0) >= RATE_WINDOW {
+                *window = (now, 0);
+            }
+            if window.1 >= limit {
+                let retry_after = RATE_WINDOW.saturating_sub(now.duration_since(window.0));
+                *state.rate_limited.entry(source).or_default() += 1;
+                return Admission::RateLimited { retry_after };
+            }
+            window.1 += 1;
+        }
+
+        let queue = state.queues.entry(source.clone()).or_default();
+        queue.push_back(envelope);
+        if queue.len() == 1 {
+            state.rotation.push_back(source);
+        }
+        state.depth += 1;
+        Admission::Queued
+    }
+
+    // Round-robin across sources so one chatty peer cannot starve the others.
+    pub fn pop(&self) -> Option<MeshCommandEnvelope<Value>> {
+        let mut state = self.lock_state();
+        let source = state.rotation.pop_front()?;
+        let queue = state.queues.get_mut(&source)?;
+        let envelope = queue.pop_front();
+        if queue.is_empty() {
+            state.queues.remove(&source);
+        } else {
+            state.rotation.push_back(source);
+        }
+        if envelope.is_some() {
+            state.depth -= 1;
+        }
+        envelope
+    }
+
+    pub fn snapshot(&self) -> QueueSnapshot {
+        let state = self.lock_state();
+        QueueSnapshot {
+            depth: state.depth,
+            capacity: state.settings.capacity,
+            backpressure: state.backpressure,
+            sources: state
+                .queues
+                .iter()
+                .map(|(source, queue)| (source.clone(), queue.len()))
+                .collect(),
+            rate_limited: state.rate_limited.clone(),
+        }
+    }
+
+    fn free_slots(&self) -> usize {
+        let state = self.lock_state();
+        state.settings.capacity.saturating_sub(state.depth)
+    }
+
+    fn backpressure_transition(&self) -> Option<bool> {
+        let mut state = self.lock_state();
+        let saturated = state.depth >= state.settings.capacity;
+        if saturated == state.backpressure {
+            return None;
+        }
+        state.backpressure = saturated;
+        Some(saturated)
+    }
+
+    fn lock_state(&self) -> std::sync::MutexGuard<'_, QueueState> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+// A window older than `RATE_WINDOW` counts for nothing, so once a window has passed the
+// windows of sources that went quiet are dropped rather than kept forever.
+fn evict_idle_windows(state: &mut QueueState, now: Instant) {
+    if state
+        .windows_swept_at
+        .is_some_and(|swept_at| now.duration_since(swept_at) < RATE_WINDOW)
+    {
+        return;
+    }
+    state.windows_swept_at = Some(now);
+    state
+        .windows
+        .retain(|_, (started, _)| now.duration_since(*started) < RATE_WINDOW);
+}
+
+pub async fn pump(
+    queue: &InboundQueue,
+    bridge: &dyn RpcMeshBridge,
+    limits: &CodecLimits,
+) -> Result<usize, BridgeError> {
+    sync_backpressure(queue, bridge).await?;
+    let free = queue.free_slots();
+    if free == 0 {
+        return Ok(0);
+    }
+
+    let mut queued = 0;
+    for mut envelope in bridge.poll_commands(free).await? {
+        if let Err(err) = expand_command(&mut envelope, limits) {
+            warn!(
+                source_identity = %envelope.source_identity,
+                operation = %envelope.operation,
+                error = %err,
+                "inbound command rejected"
+            );
+            let refusal = json!({ "status": "error", "error": "payload_limit_exceeded" });
+            refuse(bridge, reply(&envelope, refusal)).await;
+            continue;
+        }
+        match queue.admit(envelope.clone()) {
+            Admission::Queued => queued += 1,
+            Admission::RateLimited { retry_after } => {
+                warn!(
+                    source_identity = %envelope.source_identity,
+                    operation = %envelope.operation,
+                    "inbound command rate limited"
+                );
+                refuse(bridge, rate_limited_result(&envelope, retry_after)).await;
+            }
+        }
+    }
+
+    sync_backpressure(queue, bridge).await?;
+    Ok(queued)
+}
+
+// A refusal that cannot be delivered is logged; the commands drained with it still go on.
+async fn refuse(bridge: &dyn RpcMeshBridge, result: MeshResultEnvelope<Value>) {
+    let correlation_id = result.correlation_id.clone();
+    if let Err(err) = bridge.send_result(result).await {
+        warn!(
+            correlation_id = %correlation_id,
+            error = %err,
+            "inbound refusal not delivered"
+        );
+    }
+}
+
+// Decompresses a compressed payload in place, then holds it to the inbound limits.
+fn expand_command(
+    envelope: &mut MeshCommandEnvelope<Value>,
+    limits: &CodecLimits,
+) -> Result<(), CodecError> {
+    if let Ok(Some(_)) = Compression::from_content_type(&envelope.content_type) {
+        let payload = std::mem::take(&mut envelope.payload);
+        envelope.payload = expand_payload(payload, &envelope.content_type, limits)?;
+        envelope.content_type = CONTENT_TYPE_MSGPACK.to_string();
+        return Ok(());
+    }
+    limits.check_value(&envelope.payload)
+}
+
+pub fn spawn_inbound_worker(state: AppState, period: Duration) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(period);
+        loop {
+            ticker.tick().await;
+            let limits = state.node_config.read().await.codec_limits;
+            if let Err(err) = pump(&state.inbound, state.bridge.as_ref(), &limits).await {
+                error!(error = %err, "inbound command poll failed");
+            }
+            // Muted answers wait in the inbound queue, where backpressure still applies.
+            let mut by_source: BTreeMap<String, Vec<MeshCommandEnvelope<Value>>> = BTreeMap::new();
+            while !state.mute.blocks(Traffic::Events) {
+                let Some(envelope) = state.inbound.pop() else {
+                    break;
+                };
+                let source = envelope.source_identity.to_string();
+                by_source.entry(source).or_default().push(envelope);
+            }
+            let mut answering = JoinSet::new();
+            for commands in by_source.into_values() {
+                settle(&mut answering, MAX_CONCURRENT_SOURCES).await;
+                let state = state.clone();
+                answering.spawn(async move {
+                    for envelope in commands {
+                        if let Err(err) = handle_command(&state, envelope).await {
+                            error!(error = %err, "inbound command handling failed");
+                        }
+                    }
+                });
+            }
+            settle(&mut answering, 1).await;
+            // A build without transfers leaves inbound chunks with the bridge.
+            #[cfg(feature = "transfers")]
+            match state.bridge.poll_transfers(TRANSFER_POLL_LIMIT).await {
+                Ok(transfers) => {
+                    for envelope in transfers {
+                        if let Err(err) = receive_transfer(&state, envelope).await {
+                            error!(error = %err, "inbound transfer chunk handling failed");
+                        }
+                    }
+                }
+                Err(err) => error!(error = %err, "inbound transfer poll failed"),
+            }
+        }
+    })
+}
+
+// Waits on the senders being answered until fewer than `limit` remain.
+async fn settle(answering: &mut JoinSet<()>, limit: usize) {
+    while answering.len() >= limit {
+        match answering.join_next().await {
+            Some(Err(err)) => error!(error = %err, "inbound command task failed"),
+            Some(Ok(())) => {}
+            None => break,
+        }
+    }
+}
+
+async fn handle_command(
+    state: &AppState,
+    mut envelope: MeshCommandEnvelope<Value>,
+) -> anyhow::Result<()> {
+    let received_at = Utc::now();
+    canonical_operation(state, &mut envelope);
+    let max_age_secs = state.node_config.read().await.inbound.max_envelope_age_secs;
+    // A peer whose clock is known to run behind may be allowed its lag on top of the age limit.
+    let clock = state
+        .peers
+        .clock(&envelope.source_identity)
+        .map(|clock| clock.estimate);
+    let allowance_secs = skew_allowance_secs(state, clock.as_ref()).await;
+    let max_age_secs = match max_age_secs {
+        0 => 0,
+        limit => limit.saturating_add(allowance_secs),
+    };
+    // Screened before any handler runs, built-in operations included.
+    match screen_envelope(&state.storage, &envelope, max_age_secs, received_at).await? {
+        Verdict::Fresh => {}
+        Verdict::Expired => {
+            warn!(
+                source_identity = %envelope.source_identity,
+                operation = %envelope.operation,
+                sent_at = %envelope.sent_at,
+                peer_clock_offset_ms = ?clock.as_ref().map(|clock| clock.offset_ms),
+                "inbound command expired"
+            );
+            let error = json!({
+                "status": "error",
+                "error": MESSAGE_EXPIRED_ERROR,
+                "sent_at": envelope.sent_at,
+                "max_age_secs": max_age_secs,
+                "peer_clock_offset_ms": clock.as_ref().map(|clock| clock.offset_ms),
+            });
+            state.bridge.send_result(reply(&envelope, error)).await?;
+            return Ok(());
+        }
+        Verdict::Replayed(first) => {
+            warn!(
+                source_identity = %envelope.source_identity,
+                operation = %envelope.operation,
+                message_id = %envelope.message_id,
+                "inbound command replay refused"
+            );
+            emit(
+                state,
+                REPLAY_DETECTED_EVENT,
+                json!({
+                    "message_id": envelope.message_id,
+                    "operation": envelope.operation,
+                    "source_identity": envelope.source_identity,
+                    "sent_at": envelope.sent_at,
+                    "first_received_at": first.received_at,
+                }),
+            )
+            .await;
+            let error = json!({
+                "status": "error",
+                "error": DUPLICATE_MESSAGE_ERROR,
+                "first_received_at": first.received_at,
+            });
+            state.bridge.send_result(reply(&envelope, error)).await?;
+            return Ok(());
+        }
+    }
+    record_contact(state, &envelope.source_identity, Utc::now()).await;
+    route_command(state, envelope, received_at).await
+}
+
+// Renames a command sent under an alias the contract still accepts.
+pub(crate) fn canonical_operation(state: &AppState, envelope: &mut MeshCommandEnvelope<Value>) {
+    let contract = state.contract.current();
+    let (canonical, aliased) = contract.registry.resolve_alias(&envelope.operation);
+    if aliased {
+        envelope.operation = canonical.to_string();
+    }
+}
+
+// Relays, answers or caches a command that passed screening. Commands a courier bundle carried
+// come in here directly, screened by the import instead.
+pub(crate) async fn route_command(
+    state: &AppState,
+    mut envelope: MeshCommandEnvelope<Value>,
+    received_at: DateTime<Utc>,
+) -> anyhow::Result<()> {
+    let (routing, local) = {
+        let config = state.node_config.read().await;
+        (config.routing.clone(), local_identity(&config))
+    };
+    if !envelope.trace.is_empty() {
+        let hop = HopRecord {
+            identity: local.clone(),
+            received_at: Some(received_at),
+            forwarded_at: None,
+            transport: None,
+            note: None,
+        };
+        push_hop(
+            &mut envelope.trace,
+            &mut envelope.trace_truncated,
+            hop,
+            routing.max_trace_hops,
+        );
+    }
+    if envelope.destination_identity != local && routing.relays(&envelope.destination_identity) {
+        forward_command(state, envelope);
+        return Ok(());
+    }
+    if is_sealed(&envelope) {
+        if let Err(reason) = open_command(state, &mut envelope).await? {
+            let error = json!({ "status": "error", "error": reason });
+            state.bridge.send_result(reply(&envelope, error)).await?;
+            return Ok(());
+        }
+    }
+    if let Some(handler) = state.handlers.get(&envelope.operation) {
+        let answer = state
+            .handlers
+            .answer(state, handler, &envelope)
+            .await
+            .unwrap_or_else(|err| {
+                error!(
+                    operation = %envelope.operation,
+                    error = format!("{err:#}"),
+                    "inbound handler failed"
+                );
+                refusal(HANDLER_FAILED_ERROR)
+            });
+        let mut result = reply(&envelope, answer);
+        if let Some(operation) = handler.reply_operation {
+            result.operation = operation.to_string();
+        }
+        if state.node_config.read().await.inbound.record_inbound {
+            record_answer(state, &envelope, &result, received_at).await;
+        }
+        let answer = result.payload.clone();
+        state.bridge.send_result(result).await?;
+        send_receipt(
+            state,
+            &envelope.message_id,
+            &envelope.source_identity,
+            received_at,
+            &answer,
+        )
+        .await;
+        return Ok(());
+    }
+    if !state.features.is_enabled(INBOUND_COMMANDS_FLAG) {
+        let error = json!({ "status": "error", "error": INBOUND_COMMANDS_DISABLED_ERROR });
+        state.bridge.send_result(reply(&envelope, error)).await?;
+        return Ok(());
+    }
+
+    envelope.payload = migrate_inbound(
+        state,
+        &envelope.source_identity,
+        PayloadTable::Jobs,
+        &envelope.operation,
+        envelope.payload,
+    );
+    // Handlers get the metadata block beside the payload, never merged into it.
+    let mut received = json!({
+        "message_id": envelope.message_id,
+        "operation": envelope.operation,
+        "source_identity": envelope.source_identity,
+    });
+    if let Some(meta) = &envelope.meta {
+        received["meta"] = json!(meta);
+    }
+    state
+        .storage
+        .with_event("inbound.command.received", received)
+        .cache_message(
+            &envelope.message_id,
+            &envelope.operation,
+            &serde_json::to_value(&envelope)?,
+        )
+        .await?;
+    publish(state).await;
+    Ok(())
+}
+
+// Keeps a command and its answer for `retasyncd replay-jobs`. A failure only costs the record.
+async fn record_answer(
+    state: &AppState,
+    envelope: &MeshCommandEnvelope<Value>,
+    result: &MeshResultEnvelope<Value>,
+    received_at: DateTime<Utc>,
+) {
+    let record = serde_json::to_string(envelope).and_then(|envelope_json| {
+        Ok(InboundRecord {
+            message_id: envelope.message_id.clone(),
+            operation: envelope.operation.clone(),
+            source_identity: envelope.source_identity.to_string(),
+            envelope_json,
+            result_json: serde_json::to_string(&result.payload)?,
+            received_at: received_at.to_rfc3339(),
+            answered_at: result.sent_at.to_rfc3339(),
+        })
+    });
+    let recorded = match record {
+        Ok(record) => state.storage.record_inbound(&record).await,
+        Err(err) => Err(err.into()),
+    };
+    if let Err(err) = recorded {
+        warn!(
+            message_id = %envelope.message_id,
+            operation = %envelope.operation,
+            error = %err,
+            "inbound command not recorded"
+        );
+    }
+}
+
+// Relays a command addressed to another node and passes its result back the way it came. Each
+// relay waits on its own task so a slow next hop does not hold up the inbound queue.
+fn forward_command(state: &AppState, mut envelope: MeshCommandEnvelope<Value>) {
+    let state = state.clone();
+    tokio::spawn(async move {
+        state.mute.hold(Traffic::Commands).await;
+        let transport = state.bridge.planned_transport(envelope.transport_hint.clone());
+        if let Some(hop) = envelope.trace.last_mut() {
+            hop.forwarded_at = Some(Utc::now());
+            hop.transport = Some(transport_hint(&transport));
+            hop.note = Some("relayed".to_string());
+        }
+        emit(
+            &state,
+            COMMAND_FORWARDED_EVENT,
+            json!({
+                "message_id": envelope.message_id,
+                "operation": envelope.operation,
+                "source_identity": envelope.source_identity,
+                "destination_identity": envelope.destination_identity,
+            }),
+        )
+        .await;
+        let result = match state.bridge.send_command(envelope.clone()).await {
+            Ok(result) => result,
+            Err(err) => {
+                warn!(
+                    destination_identity = %envelope.destination_identity,
+                    operation = %envelope.operation,
+                    error = %err,
+                    "inbound command relay failed"
+                );
+                let error = json!({
+                    "status": "error",
+                    "error": FORWARD_FAILED_ERROR,
+                    "detail": err.to_string(),
+                });
+                reply(&envelope, error)
+            }
+        };
+        if let Err(err) = state.bridge.send_result(result).await {
+            error!(error = %err, "relayed command result could not be returned");
+        }
+    });
+}
+
+async fn sync_backpressure(
+    queue: &InboundQueue,
+    bridge: &dyn RpcMeshBridge,
+) -> Result<(), BridgeError> {
+    if let Some(enabled) = queue.backpressure_transition() {
+        info!(enabled, "inbound backpressure changed");
+        bridge.set_inbound_backpressure(enabled).await?;
+    }
+    Ok(())
+}
+
+fn rate_limited_result(
+    envelope: &MeshCommandEnvelope<Value>,
+    retry_after: Duration,
+) -> MeshResultEnvelope<Value> {
+    reply(
+        envelope,
+        json!({
+            "status": "error",
+            "error": "rate_limited",
+            "retry_after_ms": retry_after.as_millis() as u64,
+            RETRY_ADVICE_FIELD: RetryAdvice::after("rate_limited", RetryScope::Client, retry_after),
+        }),
+    )
+}
+
+// The result carries the command's trace back, with this node's hop closed off by the time the
+// answer left.
+fn reply(envelope: &MeshCommandEnvelope<Value>, payload: Value) -> MeshResultEnvelope<Value> {
+    let sent_at = Utc::now();
+    let mut trace = envelope.trace.clone();
+    if let Some(hop) = trace.last_mut().filter(|hop| hop.forwarded_at.is_none()) {
+        hop.forwarded_at = Some(sent_at);
+        hop.note.get_or_insert_with(|| "answered".to_string());
+    }
+    MeshResultEnvelope {
+        message_id: Uuid::now_v7().to_string(),
+        correlation_id: envelope.message_id.clone(),
+        operation: envelope.operation.clone(),
+        sent_at,
+        source_identity: envelope.destination_identity.clone(),
+        destination_identity: envelope.source_identity.clone(),
+        content_type: "application/msgpack".to_string(),
+        payload,
+        ttl_ms: envelope.ttl_ms,
+        transport_hint: envelope.transport_hint.clone(),
+        trace,
+        trace_truncated: envelope.trace_truncated,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{pump, InboundQueue, InboundSettings, RATE_WINDOW};
+    use crate::error::{RetryAdvice, RetryScope};
+    use chrono::Utc;
+    use retasync_contract::{
+        compress_payload, CodecLimits, Compression, MeshCommandEnvelope, MeshEventEnvelope,
+        MeshResultEnvelope, MeshTransferEnvelope, CONTENT_TYPE_MSGPACK,
+    };
+    use async_trait::async_trait;
+    use retasync_mesh_bridge::{BridgeError, BridgeReceipt, InMemoryRpcMeshBridge, RpcMeshBridge};
+    use serde_json::{json, Value};
+    use std::collections::BTreeMap;
+    use std::time::Instant;
+    use uuid::Uuid;
+
+    const CHATTY: &str = "c4a77c4a77c4a77c4a77c4a77c4a77c4";
+    const QUIET: &str = "9e1e79e1e79e1e79e1e79e1e79e1e79e";
+    const NOISY: &str = "0a150a150a150a150a150a150a150a15";
+    const PEER_A: &str = "a1b2c3d4e5f60718293a4b5c6d7e8f90";
+
+    fn command(source: &str) -> MeshCommandEnvelope<Value> {
+        MeshCommandEnvelope {
+            message_id: Uuid::now_v7().to_string(),
+            operation: "event.create".to_string(),
+            sent_at: Utc::now(),
+            source_identity: source.parse().unwrap(),
+            destination_identity: "local-node".parse().unwrap(),
+            content_type: "application/msgpack".to_string(),
+            payload: json!({ "uid": "evt" }),
+            ttl_ms: None,
+            transport_hint: None,
+            trace: Vec::new(),
+            trace_truncated: false,
+            meta: None,
+        }
+    }
+
+    fn unlimited(capacity: usize) -> InboundSettings {
+        InboundSettings {
+            capacity,
+            rate_limit_per_minute: 0,
+            source_rate_limits: BTreeMap::new(),
+            ..InboundSettings::default()
+        }
+    }
+
+    #[test]
+    fn sources_are_served_round_robin() {
+        let queue = InboundQueue::new(unlimited(16));
+        for _ in 0..6 {
+            queue.admit(command(CHATTY));
+        }
+        for _ in 0..2 {
+            queue.admit(command(QUIET));
+        }
+
+        let order: Vec<String> = std::iter::from_fn(|| queue.pop())
+            .map(|envelope| envelope.source_identity.into_string())
+            .collect();
+        assert_eq!(
+            order,
+            [CHATTY, QUIET, CHATTY, QUIET, CHATTY, CHATTY, CHATTY, CHATTY]
+        );
+    }
+
+    #[tokio::test]
+    async fn backpressure_engages_at_capacity_and_releases_below() {
+        let bridge = InMemoryRpcMeshBridge::new(true, true);
+        for _ in 0..5 {
+            bridge.inject_command(command(PEER_A));
+        }
+        let queue = InboundQueue::new(unlimited(3));
+        let limits = CodecLimits::default();
+
+        assert_eq!(pump(&queue, &bridge, &limits).await.unwrap(), 3);
+        assert!(bridge.inbound_backpressure());
+        assert!(queue.snapshot().backpressure);
+        assert_eq!(bridge.pending_inbound(), 2);
+
+        queue.pop().expect("queued command");
+        assert_eq!(pump(&queue, &bridge, &limits).await.unwrap(), 1);
+        assert_eq!(bridge.pending_inbound(), 1);
+        assert!(bridge.inbound_backpressure());
+
+        queue.pop();
+        queue.pop();
+        assert_eq!(pump(&queue, &bridge, &limits).await.unwrap(), 1);
+        assert!(!bridge.inbound_backpressure());
+        assert_eq!(queue.snapshot().depth, 2);
+    }
+
+    #[tokio::test]
+    async fn rate_limited_commands_get_error_results() {
+        let bridge = InMemoryRpcMeshBridge::new(true, true);
+        let flood: Vec<_> = (0..3).map(|_| command(NOISY)).collect();
+        for envelope in &flood {
+            bridge.inject_command(envelope.clone());
+        }
+        let queue = InboundQueue::new(InboundSettings {
+            capacity: 16,
+            rate_limit_per_minute: 100,
+            source_rate_limits: BTreeMap::from([(NOISY.to_string(), 2)]),
+            ..InboundSettings::default()
+        });
+
+        assert_eq!(pump(&queue, &bridge, &CodecLimits::default()).await.unwrap(), 2);
+        let results = bridge.sent_results();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].correlation_id, flood[2].message_id);
+        assert_eq!(results[0].destination_identity, NOISY);
+        assert_eq!(results[0].payload["error"], "rate_limited");
+        // The advice is what is left of the source's window.
+        let advice = RetryAdvice::from_body(&results[0].payload).unwrap();
+        assert_eq!(advice.scope, RetryScope::Client);
+        assert_eq!(json!(advice.retry_after_ms), results[0].payload["retry_after_ms"]);
+        assert!((59_000..=60_000).contains(&advice.retry_after_ms), "{advice:?}");
+        assert_eq!(queue.snapshot().rate_limited[NOISY], 1);
+    }
+
+    #[tokio::test]
+    async fn oversized_payloads_are_rejected_before_queueing() {
+        let bridge = InMemoryRpcMeshBridge::new(true, true);
+        let mut nested = command(PEER_A);
+        nested.payload = (0..8).fold(json!("leaf"), |inner, _| json!([inner]));
+        bridge.inject_command(nested.clone());
+        bridge.inject_command(command(PEER_A));
+        let queue = InboundQueue::new(unlimited(16));
+        let limits = CodecLimits {
+            max_depth: 4,
+            ..CodecLimits::default()
+        };
+
+        assert_eq!(pump(&queue, &bridge, &limits).await.unwrap(), 1);
+        let results = bridge.sent_results();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].correlation_id, nested.message_id);
+        assert_eq!(results[0].payload["error"], "payload_limit_exceeded");
+    }
+
+    #[tokio::test]
+    async fn compressed_payloads_are_expanded_within_limits() {
+        let bridge = InMemoryRpcMeshBridge::new(true, true);
+        let payload = json!({ "uid": "evt", "notes": "all teams green ".repeat(512) });
+        let mut compressed = command(PEER_A);
+        compressed.content_type = Compression::Zstd.content_type().to_string();
+        compressed.payload = compress_payload(&payload, Compression::Zstd).unwrap();
+        let mut bomb = compressed.clone();
+        bomb.message_id = Uuid::now_v7().to_string();
+        bridge.inject_command(compressed);
+        bridge.inject_command(bomb.clone());
+        let queue = InboundQueue::new(unlimited(16));
+        let limits = CodecLimits::default();
+
+        assert_eq!(pump(&queue, &bridge, &limits).await.unwrap(), 2);
+        let expanded = queue.pop().expect("queued command");
+        assert_eq!(expanded.content_type, CONTENT_TYPE_MSGPACK);
+        assert_eq!(expanded.payload, payload);
+
+        bridge.inject_command(bomb.clone());
+        let tight = CodecLimits {
+            max_bytes: 1024,
+            ..limits
+        };
+        assert_eq!(pump(&queue, &bridge, &tight).await.unwrap(), 0);
+        let results = bridge.sent_results();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].correlation_id, bomb.message_id);
+        assert_eq!(results[0].payload["error"], "payload_limit_exceeded");
+    }
+
+    // Delivers nothing back to peers, like a daemon that drops result sends.
+    struct ResultsLost(InMemoryRpcMeshBridge);
+
+    #[async_trait]
+    impl RpcMeshBridge for ResultsLost {
+        async fn send_command(
+            &self,
+            envelope: MeshCommandEnvelope<Value>,
+        ) -> Result<MeshResultEnvelope<Value>, BridgeError> {
+            self.0.send_command(envelope).await
+        }
+
+        async fn publish_event(
+            &self,
+            envelope: MeshEventEnvelope<Value>,
+        ) -> Result<BridgeReceipt, BridgeError> {
+            self.0.publish_event(envelope).await
+        }
+
+        async fn start_transfer(
+            &self,
+            envelope: MeshTransferEnvelope<Value>,
+        ) -> Result<BridgeReceipt, BridgeError> {
+            self.0.start_transfer(envelope).await
+        }
+
+        async fn query_receipt(
+            &self,
+            message_id: &str,
+        ) -> Result<Option<BridgeReceipt>, BridgeError> {
+            self.0.query_receipt(message_id).await
+        }
+
+        async fn poll_events(
+            &self,
+            limit: usize,
+        ) -> Result<Vec<MeshEventEnvelope<Value>>, BridgeError> {
+            self.0.poll_events(limit).await
+        }
+
+        async fn poll_commands(
+            &self,
+            limit: usize,
+        ) -> Result<Vec<MeshCommandEnvelope<Value>>, BridgeError> {
+            self.0.poll_commands(limit).await
+        }
+
+        async fn send_result(
+            &self,
+            _envelope: MeshResultEnvelope<Value>,
+        ) -> Result<BridgeReceipt, BridgeError> {
+            Err(BridgeError::DaemonUnavailable)
+        }
+
+        async fn set_inbound_backpressure(&self, enabled: bool) -> Result<(), BridgeError> {
+            self.0.set_inbound_backpressure(enabled).await
+        }
+    }
+
+    #[tokio::test]
+    async fn undelivered_refusals_do_not_drop_the_rest_of_the_batch() {
+        let inner = InMemoryRpcMeshBridge::new(true, true);
+        let mut nested = command(PEER_A);
+        nested.payload = (0..8).fold(json!("leaf"), |inner, _| json!([inner]));
+        inner.inject_command(nested);
+        inner.inject_command(command(PEER_A));
+        let bridge = ResultsLost(inner);
+        let queue = InboundQueue::new(unlimited(16));
+        let limits = CodecLimits {
+            max_depth: 4,
+            ..CodecLimits::default()
+        };
+
+        assert_eq!(pump(&queue, &bridge, &limits).await.unwrap(), 1);
+        assert_eq!(queue.pop().unwrap().payload, json!({ "uid": "evt" }));
+    }
+
+    #[test]
+    fn idle_rate_windows_are_evicted() {
+        let queue = InboundQueue::new(InboundSettings {
+            capacity: 16,
+            ..InboundSettings::default()
+        });
+        let long_ago = Instant::now()
+            .checked_sub(RATE_WINDOW * 2)
+            .expect("monotonic clock far enough along");
+        queue
+            .lock_state()
+            .windows
+            .insert(QUIET.to_string(), (long_ago, 3));
+
+        queue.admit(command(CHATTY));
+        let state = queue.lock_state();
+        assert!(!state.windows.contains_key(QUIET));
+        assert_eq!(state.windows[CHATTY].1, 1);
+    }
+}
@@ -0,0 +1,483 @@
+﻿use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use anyhow::Context;
+use async_trait::async_trait;
+use retasync_contract::{
+    IdentityHash, MeshCommandEnvelope, MeshEventEnvelope, MeshResultEnvelope, MeshTransferEnvelope,
+};
+use retasync_mesh_bridge::{BridgeError, BridgeReceipt, RpcMeshBridge};
+use retasync_storage::{InboundRecord, RetasyncStorage, StorageConfig, DEFAULT_READ_POOL_SIZE};
+use serde::Serialize;
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::handlers::{refusal, HandlerRegistry, HANDLER_FAILED_ERROR};
+use crate::profiles::child;
+use crate::{AppState, NodeConfig};
+
+const SANDBOX_BRIDGE_ERROR: &str = "the replay sandbox makes no bridge calls";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplayOutcome {
+    Identical,
+    Changed,
+    // The replay could not produce an answer to compare: the record did not decode, no handler
+    // answers the operation any more, or the handler failed where it once answered.
+    Errored,
+}
+
+// One value that differs, by JSON pointer into the answer. `None` is a field one side lacks.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FieldDiff {
+    pub pointer: String,
+    pub recorded: Option<Value>,
+    pub replayed: Option<Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ReplayedCommand {
+    pub message_id: String,
+    pub operation: String,
+    pub outcome: ReplayOutcome,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub diffs: Vec<FieldDiff>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct OperationTally {
+    pub identical: usize,
+    pub changed: usize,
+    pub errored: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ReplayReport {
+    pub replayed: usize,
+    pub identical: usize,
+    pub changed: usize,
+    pub errored: usize,
+    pub operations: BTreeMap<String, OperationTally>,
+    // Every command whose replay did not match its record, in the order they were received.
+    pub differences: Vec<ReplayedCommand>,
+    // Bridge calls handlers attempted during the replay; the sandbox refused each one.
+    pub bridge_calls_refused: u64,
+}
+
+impl ReplayReport {
+    fn record(&mut self, command: ReplayedCommand) {
+        self.replayed += 1;
+        let tally = self.operations.entry(command.operation.clone()).or_default();
+        match command.outcome {
+            ReplayOutcome::Identical => {
+                self.identical += 1;
+                tally.identical += 1;
+                return;
+            }
+            ReplayOutcome::Changed => {
+                self.changed += 1;
+                tally.changed += 1;
+            }
+            ReplayOutcome::Errored => {
+                self.errored += 1;
+                tally.errored += 1;
+            }
+        }
+        self.differences.push(command);
+    }
+}
+
+// Runs recorded inbound commands through `handlers` again, oldest first, and compares each
+// answer with the one recorded. The handlers run against a sandbox: a scratch database that
+// starts empty apart from the recording node's `allowlist` and is deleted afterwards, and a
+// bridge that refuses every call. So an answer that depends on any other state not written by
+// the recorded commands shows up as changed.
+pub async fn replay_records(
+    records: &[InboundRecord],
+    allowlist: &[String],
+    node_config: NodeConfig,
+    contract_doc: String,
+    handlers: Arc<HandlerRegistry>,
+) -> anyhow::Result<ReplayReport> {
+    let sandbox = Sandbox::open(node_config, contract_doc, handlers.clone()).await?;
+    for identity_hash in allowlist {
+        let identity_hash = IdentityHash::lenient(identity_hash);
+        sandbox.state.storage.add_allowlist(&identity_hash, None).await?;
+    }
+    let mut report = ReplayReport::default();
+    for record in records {
+        let command = replay_one(&sandbox.state, &handlers, record).await;
+        report.record(command);
+    }
+    report.bridge_calls_refused = sandbox.bridge.refused.load(Ordering::SeqCst);
+    sandbox.state.storage.close().await;
+    Ok(report)
+}
+
+async fn replay_one(
+    state: &AppState,
+    handlers: &HandlerRegistry,
+    record: &InboundRecord,
+) -> ReplayedCommand {
+    let mut command = ReplayedCommand {
+        message_id: record.message_id.clone(),
+        operation: record.operation.clone(),
+        outcome: ReplayOutcome::Errored,
+        diffs: Vec::new(),
+        error: None,
+    };
+    let decoded = serde_json::from_str::<MeshCommandEnvelope<Value>>(&record.envelope_json)
+        .context("decode recorded envelope")
+        .and_then(|envelope| {
+            let recorded = serde_json::from_str::<Value>(&record.result_json)
+                .context("decode recorded result")?;
+            Ok((envelope, recorded))
+        });
+    let (envelope, recorded) = match decoded {
+        Ok(decoded) => decoded,
+        Err(err) => {
+            command.error = Some(format!("{err:#}"));
+            return command;
+        }
+    };
+    let Some(handler) = handlers.get(&record.operation) else {
+        command.error = Some(format!("no handler answers {}", record.operation));
+        return command;
+    };
+    match (handler.answer)(state, &envelope).await {
+        Ok(replayed) => {
+            diff_values("", &recorded, &replayed, &mut command.diffs);
+            command.outcome = if command.diffs.is_empty() {
+                ReplayOutcome::Identical
+            } else {
+                ReplayOutcome::Changed
+            };
+        }
+        // A command that failed the same way when it was answered replays as identical.
+        Err(_) if refusal(HANDLER_FAILED_ERROR) == recorded => {
+            command.outcome = ReplayOutcome::Identical;
+        }
+        Err(err) => command.error = Some(format!("{err:#}")),
+    }
+    command
+}
+
+// Objects compare key by key and arrays item by item, so a diff names the innermost value
+// that changed.
+fn diff_values(pointer: &str, recorded: &Value, replayed: &Value, diffs: &mut Vec<FieldDiff>) {
+    match (recorded, replayed) {
+        (Value::Object(left), Value::Object(right)) => {
+            let keys: BTreeSet<&String> = left.keys().chain(right.keys()).collect();
+            for key in keys {
+                diff_slots(&child(pointer, key), left.get(key), right.get(key), diffs);
+            }
+        }
+        (Value::Array(left), Value::Array(right)) => {
+            for index in 0..left.len().max(right.len()) {
+                let pointer = format!("{pointer}/{index}");
+                diff_slots(&pointer, left.get(index), right.get(index), diffs);
+            }
+        }
+        _ if recorded != replayed => diffs.push(FieldDiff {
+            pointer: pointer.to_string(),
+            recorded: Some(recorded.clone()),
+            replayed: Some(replayed.clone()),
+        }),
+        _ => {}
+    }
+}
+
+fn diff_slots(
+    pointer: &str,
+    recorded: Option<&Value>,
+    replayed: Option<&Value>,
+    diffs: &mut Vec<FieldDiff>,
+) {
+    match (recorded, replayed) {
+        (Some(recorded), Some(replayed)) => diff_values(pointer, recorded, replayed, diffs),
+        (recorded, replayed) => diffs.push(FieldDiff {
+            pointer: pointer.to_string(),
+            recorded: recorded.cloned(),
+            replayed: replayed.cloned(),
+        }),
+    }
+}
+
+struct Sandbox {
+    state: AppState,
+    bridge: Arc<SandboxBridge>,
+    path: PathBuf,
+}
+
+impl Sandbox {
+    async fn open(
+        mut node_config: NodeConfig,
+        contract_doc: String,
+        handlers: Arc<HandlerRegistry>,
+    ) -> anyhow::Result<Self> {
+        let path = std::env::temp_dir().join(format!("retasync-replay-{}.sqlite", Uuid::now_v7()));
+        let sqlite_path = path.to_string_lossy().into_owned();
+        let storage = RetasyncStorage::connect(&StorageConfig {
+            sqlite_path: sqlite_path.clone(),
+            encryption_key_path: None,
+            read_pool_size: DEFAULT_READ_POOL_SIZE,
+        })
+        .await
+        .with_context(|| format!("failed to open replay sandbox {}", path.display()))?;
+        node_config.sqlite_path = sqlite_path;
+        let bridge = Arc::new(SandboxBridge::default());
+        let mut state = AppState::new(storage, bridge.clone(), node_config, contract_doc, false);
+        state.handlers = handlers;
+        Ok(Self {
+            state,
+            bridge,
+            path,
+        })
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        for suffix in ["", "-journal", "-wal", "-shm"] {
+            let mut path = self.path.clone().into_os_string();
+            path.push(suffix);
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+// Refuses every call and counts them, so a replay can neither reach the mesh nor go quiet
+// about a handler that tried to.
+#[derive(Debug, Default)]
+struct SandboxBridge {
+    refused: AtomicU64,
+}
+
+impl SandboxBridge {
+    fn refuse<T>(&self) -> Result<T, BridgeError> {
+        self.refused.fetch_add(1, Ordering::SeqCst);
+        Err(BridgeError::SendFailed(SANDBOX_BRIDGE_ERROR.to_string()))
+    }
+}
+
+#[async_trait]
+impl RpcMeshBridge for SandboxBridge {
+    async fn send_command(
+        &self,
+        _envelope: MeshCommandEnvelope<Value>,
+    ) -> Result<MeshResultEnvelope<Value>, BridgeError> {
+        self.refuse()
+    }
+
+    async fn publish_event(
+        &self,
+        _envelope: MeshEventEnvelope<Value>,
+    ) -> Result<BridgeReceipt, BridgeError> {
+        self.refuse()
+    }
+
+    async fn start_transfer(
+        &self,
+        _envelope: MeshTransferEnvelope<Value>,
+    ) -> Result<BridgeReceipt, BridgeError> {
+        self.refuse()
+    }
+
+    async fn query_receipt(&self, _message_id: &str) -> Result<Option<BridgeReceipt>, BridgeError> {
+        self.refuse()
+    }
+
+    async fn poll_events(&self, _limit: usize) -> Result<Vec<MeshEventEnvelope<Value>>, BridgeError> {
+        self.refuse()
+    }
+
+    async fn poll_commands(
+        &self,
+        _limit: usize,
+    ) -> Result<Vec<MeshCommandEnvelope<Value>>, BridgeError> {
+        self.refuse()
+    }
+
+    async fn send_result(
+        &self,
+        _envelope: MeshResultEnvelope<Value>,
+    ) -> Result<BridgeReceipt, BridgeError> {
+        self.refuse()
+    }
+
+    async fn set_inbound_backpressure(&self, _enabled: bool) -> Result<(), BridgeError> {
+        self.refuse()
+    }
+}
+
+#[cfg(all(test, feature = "entities"))]
+mod tests {
+    use super::{diff_values, replay_records, FieldDiff, ReplayOutcome};
+    use crate::dispatch::local_identity;
+    use crate::entity_sync::{answer_sync_request, ENTITY_SYNC_REQUEST_OPERATION};
+    use crate::handlers::{HandlerRegistry, HANDLER_FAILED_ERROR};
+    use crate::inbound::route_command;
+    use crate::labels::meta_labels;
+    use crate::test_support::test_state;
+    use crate::AppState;
+    use chrono::{Duration, TimeZone, Utc};
+    use futures::future::BoxFuture;
+    use retasync_contract::MeshCommandEnvelope;
+
+    use retasync_storage::{
+        EntityRecord, RetasyncStorage, StorageConfig, DEFAULT_READ_POOL_SIZE,
+    };
+    use serde_json::{json, Value};
+    use std::sync::Arc;
+    use uuid::Uuid;
+
+    const REQUESTER: &str = "0123456789abcdef0123456789abcdef";
+
+    fn entity(id: &str, minute: i64, version: i64, status: &str, deleted: bool) -> EntityRecord {
+        let updated_at =
+            Utc.with_ymd_and_hms(2026, 5, 1, 8, 0, 0).unwrap() + Duration::minutes(minute);
+        EntityRecord {
+            entity_type: "eam".to_string(),
+            entity_id: id.to_string(),
+            updated_at,
+            record: json!({ "status": status }),
+            version,
+            deleted_at: deleted.then_some(updated_at),
+        }
+    }
+
+    async fn push(state: &AppState, records: &[EntityRecord], depth: usize) {
+        let config = state.node_config.read().await.clone();
+        let envelope = MeshCommandEnvelope {
+            message_id: Uuid::now_v7().to_string(),
+            operation: ENTITY_SYNC_REQUEST_OPERATION.to_string(),
+            sent_at: Utc::now(),
+            source_identity: REQUESTER.parse().unwrap(),
+            destination_identity: local_identity(&config),
+            content_type: "application/msgpack".to_string(),
+            payload: json!({
+                "entity_type": "eam",
+                "depth": depth,
+                "scope": [""],
+                "digests": {},
+                "records": records,
+                "max_response_size": 65536,
+            }),
+            ttl_ms: None,
+            transport_hint: None,
+            trace: Vec::new(),
+            trace_truncated: false,
+            meta: None,
+        };
+        route_command(state, envelope, Utc::now()).await.unwrap();
+    }
+
+    fn counts_twice<'a>(
+        state: &'a AppState,
+        envelope: &'a MeshCommandEnvelope<Value>,
+    ) -> BoxFuture<'a, anyhow::Result<Value>> {
+        Box::pin(async move {
+            let labels = meta_labels(envelope.meta.as_ref());
+            let mut answer = answer_sync_request(state, envelope, &labels).await?;
+            answer["applied"] = json!(answer["applied"].as_u64().unwrap_or_default() * 2);
+            Ok(answer)
+        })
+    }
+
+    async fn data_version(storage: &RetasyncStorage) -> i64 {
+        sqlx::query_scalar("PRAGMA data_version")
+            .fetch_one(storage.pool())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn replays_recorded_entity_syncs_without_touching_the_node() {
+        let state = test_state().await;
+        state.node_config.write().await.inbound.record_inbound = true;
+        state.storage.add_allowlist(&REQUESTER.parse().unwrap(), None).await.unwrap();
+        let since = Utc::now() - Duration::minutes(1);
+        push(&state, &[entity("a1", 0, 1, "green", false)], 1).await;
+        push(&state, &[entity("a2", 0, 1, "green", false)], 1).await;
+        push(&state, &[entity("a1", 5, 2, "red", false)], 1).await;
+        push(&state, &[entity("a2", 6, 2, "green", true)], 1).await;
+        // Stale, so nothing is applied; then one the handler refuses.
+        push(&state, &[entity("a1", 1, 1, "amber", false)], 1).await;
+        push(&state, &[], 0).await;
+
+        let config = StorageConfig {
+            sqlite_path: state.node_config.read().await.sqlite_path.clone(),
+            encryption_key_path: None,
+            read_pool_size: DEFAULT_READ_POOL_SIZE,
+        };
+        let records = RetasyncStorage::read_inbound_records(&config, since).await.unwrap();
+        assert_eq!(records.len(), 6);
+        assert!(records[5].result_json.contains(HANDLER_FAILED_ERROR));
+        let allowlist = RetasyncStorage::read_allowlist(&config, Utc::now()).await.unwrap();
+        assert_eq!(allowlist, [REQUESTER]);
+        let node_config = state.node_config.read().await.clone();
+        let entities = state.storage.list_entities("eam", "").await.unwrap();
+        let before = data_version(&state.storage).await;
+
+        let unchanged = replay_records(
+            &records,
+            &allowlist,
+            node_config.clone(),
+            "asyncapi: 3.0.0\n".to_string(),
+            Arc::new(HandlerRegistry::default()),
+        )
+        .await
+        .unwrap();
+        assert_eq!((unchanged.identical, unchanged.changed, unchanged.errored), (6, 0, 0));
+        assert!(unchanged.differences.is_empty());
+        assert_eq!(unchanged.operations[ENTITY_SYNC_REQUEST_OPERATION].identical, 6);
+
+        let altered = HandlerRegistry::default();
+        let mut handler = altered.get(ENTITY_SYNC_REQUEST_OPERATION).unwrap();
+        handler.answer = counts_twice;
+        altered.register(ENTITY_SYNC_REQUEST_OPERATION, handler);
+        let report = replay_records(
+            &records,
+            &allowlist,
+            node_config,
+            "asyncapi: 3.0.0\n".to_string(),
+            Arc::new(altered),
+        )
+        .await
+        .unwrap();
+        assert_eq!((report.identical, report.changed, report.errored), (2, 4, 0));
+        let changed = &report.differences[0];
+        assert_eq!(changed.message_id, records[0].message_id);
+        assert_eq!(changed.outcome, ReplayOutcome::Changed);
+        assert_eq!(
+            changed.diffs,
+            vec![FieldDiff {
+                pointer: "/applied".to_string(),
+                recorded: Some(json!(1)),
+                replayed: Some(json!(2)),
+            }]
+        );
+        assert_eq!(report.bridge_calls_refused, 0);
+
+        // Neither replay wrote to the node's database.
+        assert_eq!(data_version(&state.storage).await, before);
+        assert_eq!(state.storage.list_entities("eam", "").await.unwrap(), entities);
+    }
+
+    #[test]
+    fn diffs_name_the_innermost_changed_value() {
+        let recorded = json!({ "records": [{ "id": "a", "n": 1 }], "gone": true, "same": "x" });
+        let replayed = json!({ "records": [{ "id": "a", "n": 2 }, "b"], "same": "x" });
+        let mut diffs = Vec::new();
+        diff_values("", &recorded, &replayed, &mut diffs);
+        let pointers: Vec<&str> = diffs.iter().map(|diff| diff.pointer.as_str()).collect();
+        assert_eq!(pointers, ["/gone", "/records/0/n", "/records/1"]);
+        assert_eq!(diffs[0].replayed, None);
+        assert_eq!(diffs[2].recorded, None);
+    }
+}
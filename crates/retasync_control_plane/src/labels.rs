@@ -0,0 +1,166 @@
+﻿use std::collections::BTreeMap;
+
+use retasync_contract::{validate_labels, EnvelopeMeta, META_MAX_LABELS};
+use retasync_storage::{
+    entity_label_subject, EntityRecord, LABEL_SUBJECT_ENTITY, LABEL_SUBJECT_JOB,
+    LABEL_SUBJECT_TRANSFER,
+};
+use serde_json::Value;
+
+use crate::AppState;
+
+// Payload field a command's labels are submitted in; they travel in the metadata block instead.
+pub const LABELS_FIELD: &str = "_labels";
+// Each `label=key:value` query parameter is a label a listed subject must carry.
+pub const LABEL_QUERY_PARAM: &str = "label";
+pub const INVALID_LABELS_ERROR: &str = "invalid_labels";
+pub const INVALID_LABEL_FILTER_ERROR: &str = "invalid_label_filter";
+pub const JOB_LABELS_CHANGED_EVENT: &str = "job.labels.changed";
+
+pub type Labels = BTreeMap<String, String>;
+
+// A `{key: value}` object checked against the contract's label limits.
+pub fn parse_labels(raw: Value) -> Result<Labels, String> {
+    let labels: Labels = serde_json::from_value(raw)
+        .map_err(|_| "labels must be an object of string values".to_string())?;
+    validate_labels(&labels)?;
+    Ok(labels)
+}
+
+// Removes `_labels` from a command payload.
+pub fn take_labels(payload: &mut Value) -> Result<Labels, String> {
+    match payload
+        .as_object_mut()
+        .and_then(|object| object.remove(LABELS_FIELD))
+    {
+        Some(raw) => parse_labels(raw),
+        None => Ok(Labels::new()),
+    }
+}
+
+// The submitted block with `labels` added; labels the block already names are overridden.
+pub fn with_labels(meta: Option<EnvelopeMeta>, labels: Labels) -> Option<EnvelopeMeta> {
+    if labels.is_empty() {
+        return meta;
+    }
+    let mut meta = meta.unwrap_or_default();
+    meta.labels.extend(labels);
+    Some(meta)
+}
+
+pub fn meta_labels(meta: Option<&EnvelopeMeta>) -> Labels {
+    meta.map(|meta| meta.labels.clone()).unwrap_or_default()
+}
+
+// The `label` parameters of a query string as (key, value) pairs, in the order given.
+pub fn label_filters(query: &[(String, String)]) -> Result<Vec<(String, String)>, String> {
+    let filters = query
+        .iter()
+        .filter(|(name, _)| name == LABEL_QUERY_PARAM)
+        .map(|(_, filter)| {
+            let (key, value) = filter
+                .split_once(':')
+                .ok_or_else(|| format!("label filter {filter:?} is not key:value"))?;
+            validate_labels(&Labels::from([(key.to_string(), value.to_string())]))?;
+            Ok((key.to_string(), value.to_string()))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    if filters.len() > META_MAX_LABELS {
+        return Err(format!("more than {META_MAX_LABELS} label filters"));
+    }
+    Ok(filters)
+}
+
+// The `subject_type` a label listing may be narrowed to.
+pub fn subject_type(name: &str) -> Option<&'static str> {
+    [LABEL_SUBJECT_JOB, LABEL_SUBJECT_TRANSFER, LABEL_SUBJECT_ENTITY]
+        .into_iter()
+        .find(|subject| *subject == name)
+}
+
+// Applies a command's labels to an entity the node stored because of it.
+pub async fn label_entity(
+    state: &AppState,
+    record: &EntityRecord,
+    labels: &Labels,
+) -> anyhow::Result<()> {
+    if labels.is_empty() {
+        return Ok(());
+    }
+    let subject = entity_label_subject(&record.entity_type, &record.entity_id);
+    state
+        .storage
+        .put_labels(LABEL_SUBJECT_ENTITY, &subject, labels)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use retasync_contract::{META_MAX_LABEL_KEY_LEN, META_MAX_LABEL_VALUE_LEN};
+    use serde_json::json;
+
+    #[test]
+    fn labels_are_held_to_the_contract_limits() {
+        let many = |count: usize| -> Value {
+            (0..count)
+                .map(|n| (format!("k{n}"), json!("v")))
+                .collect::<serde_json::Map<_, _>>()
+                .into()
+        };
+        assert_eq!(parse_labels(many(META_MAX_LABELS)).unwrap().len(), META_MAX_LABELS);
+        assert_eq!(
+            parse_labels(many(META_MAX_LABELS + 1)),
+            Err(format!("labels has 17 entries, more than {META_MAX_LABELS}"))
+        );
+
+        let key = "k".repeat(META_MAX_LABEL_KEY_LEN);
+        let value = "v".repeat(META_MAX_LABEL_VALUE_LEN);
+        assert!(parse_labels(json!({ key.clone(): value.clone() })).is_ok());
+        assert_eq!(
+            parse_labels(json!({ format!("{key}k"): "v" })),
+            Err(format!("label key is longer than {META_MAX_LABEL_KEY_LEN} bytes"))
+        );
+        assert_eq!(
+            parse_labels(json!({ "exercise": format!("{value}v") })),
+            Err(format!("labels.exercise is longer than {META_MAX_LABEL_VALUE_LEN} bytes"))
+        );
+        for bad in ["Exercise", "-exercise", "exercise day", "exercise:day", ""] {
+            assert!(parse_labels(json!({ bad: "v" })).is_err(), "{bad:?} was accepted");
+        }
+        assert!(parse_labels(json!({ "casualty.id_7-b": "7" })).is_ok());
+        assert!(parse_labels(json!({ "exercise": "" })).is_err());
+        assert!(parse_labels(json!({ "exercise": "day\n2" })).is_err());
+        assert!(parse_labels(json!({ "exercise": 2 })).is_err());
+
+        let mut payload = json!({ "uid": "evt-1", LABELS_FIELD: { "casualty": "7" } });
+        assert_eq!(take_labels(&mut payload).unwrap()["casualty"], "7");
+        assert_eq!(payload, json!({ "uid": "evt-1" }));
+        assert!(take_labels(&mut payload).unwrap().is_empty());
+    }
+
+    #[test]
+    fn filters_split_on_the_first_colon_and_keep_their_order() {
+        let query = |pairs: &[(&str, &str)]| -> Vec<(String, String)> {
+            pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+        };
+        let filters = label_filters(&query(&[
+            ("label", "exercise:redfish-day2"),
+            ("limit", "10"),
+            ("label", "grid:38T:LP"),
+        ]))
+        .unwrap();
+        assert_eq!(
+            filters,
+            query(&[("exercise", "redfish-day2"), ("grid", "38T:LP")])
+        );
+        assert!(label_filters(&query(&[("label", "exercise")])).is_err());
+        assert!(label_filters(&query(&[("label", "Exercise:x")])).is_err());
+        assert!(label_filters(&query(&[("label", "exercise:")])).is_err());
+        let many: Vec<_> = (0..=META_MAX_LABELS)
+            .map(|n| ("label".to_string(), format!("k{n}:v")))
+            .collect();
+        assert!(label_filters(&many).is_err());
+    }
+}
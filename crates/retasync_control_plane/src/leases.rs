@@ -0,0 +1,325 @@
+﻿use std::future::Future;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use retasync_storage::{JobLease, FEED_JOB_EVENT};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::task::{AbortHandle, JoinError, JoinHandle};
+use tracing::{error, warn};
+use uuid::Uuid;
+
+use crate::app::{is_settled_transfer, publish, redeliver_command_job, write_log};
+use crate::crash::{panic_message, record_task_panic};
+use crate::dependencies::settle_dependents;
+use crate::dispatch::Dispatch;
+use crate::error::JobProcessingError;
+use crate::AppState;
+
+pub const WORKER_LOST_REASON: &str = "worker_lost";
+pub const DEFAULT_LEASE_INTERVAL_MS: u64 = 5000;
+pub const DEFAULT_LEASE_GRACE_MS: u64 = 15000;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct JobWatchdogSettings {
+    // How often workers renew their leases and the watchdog looks for expired ones.
+    pub lease_interval_ms: u64,
+    // How long a lease outlives its last renewal before the job counts as lost.
+    pub lease_grace_ms: u64,
+}
+
+impl Default for JobWatchdogSettings {
+    fn default() -> Self {
+        Self {
+            lease_interval_ms: DEFAULT_LEASE_INTERVAL_MS,
+            lease_grace_ms: DEFAULT_LEASE_GRACE_MS,
+        }
+    }
+}
+
+impl JobWatchdogSettings {
+    pub fn lease_interval(&self) -> Duration {
+        Duration::from_millis(self.lease_interval_ms.max(1))
+    }
+
+    pub fn lease_expiry(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        let lease_ms = self.lease_interval_ms.saturating_add(self.lease_grace_ms);
+        now + chrono::Duration::milliseconds(i64::try_from(lease_ms).unwrap_or(i64::MAX))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Recovery {
+    Requeued,
+    Failed,
+    // The job had already finished; only the lease was left behind.
+    Settled,
+}
+
+struct AbortOnDrop(AbortHandle);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+// Runs `work` for `job_id` under a renewed lease. If this task dies the work dies with it and
+// the lease expires; if the work panics the lease is released and the job recovered at once.
+// Once the lease is lost the watchdog redelivers the job, so the work is stopped then too.
+pub fn spawn_leased<F>(state: AppState, job_id: String, work: F) -> JoinHandle<()>
+where
+    F: Future<Output = Result<(), JobProcessingError>> + Send + 'static,
+{
+    tokio::spawn(async move {
+        let settings = state.node_config.read().await.job_watchdog.clone();
+        let worker_id = Uuid::now_v7().to_string();
+        let lease = match state
+            .storage
+            .acquire_job_lease(&job_id, &worker_id, settings.lease_expiry(Utc::now()))
+            .await
+        {
+            Ok(lease) => Some(lease),
+            Err(err) => {
+                error!(job_id = %job_id, error = %err, "job lease unavailable; running unleased");
+                None
+            }
+        };
+
+        let mut worker = tokio::spawn(work);
+        let _abort = AbortOnDrop(worker.abort_handle());
+        let interval = settings.lease_interval();
+        let first_renewal = tokio::time::Instant::now() + interval;
+        let mut renewal = tokio::time::interval_at(first_renewal, interval);
+        let mut held = lease.is_some();
+        let outcome = loop {
+            tokio::select! {
+                outcome = &mut worker => break outcome,
+                _ = renewal.tick(), if held => {
+                    let expires_at = settings.lease_expiry(Utc::now());
+                    match state.storage.renew_job_lease(&job_id, &worker_id, expires_at).await {
+                        Ok(true) => {}
+                        Ok(false) => {
+                            warn!(job_id = %job_id, "job lease lost to the watchdog; stopping it");
+                            worker.abort();
+                            held = false;
+                        }
+                        Err(err) => {
+                            error!(job_id = %job_id, error = %err, "job lease renewal failed")
+                        }
+                    }
+                }
+            }
+        };
+        let Some(lease) = lease else {
+            if let Ok(Err(err)) = outcome {
+                error!(job_id = %job_id, error = %err, "job processing failed");
+            }
+            return;
+        };
+        if let Err(err) = finish_lease(&state, &lease, outcome).await {
+            error!(job_id = %job_id, error = %err, "job lease release failed");
+        }
+    })
+}
+
+async fn finish_lease(
+    state: &AppState,
+    lease: &JobLease,
+    outcome: Result<Result<(), JobProcessingError>, JoinError>,
+) -> anyhow::Result<()> {
+    match outcome {
+        Ok(Ok(())) => {
+            state.storage.release_job_lease(lease, "done", None).await?;
+        }
+        Ok(Err(err)) => {
+            error!(job_id = %lease.job_id, error = %err, "job processing failed");
+            let diagnostic = err.to_string();
+            if state
+                .storage
+                .release_job_lease(lease, "error", Some(&diagnostic))
+                .await?
+            {
+                let failure = Failure {
+                    cause: &diagnostic,
+                    reason: &err.code(),
+                    retry: err.is_retryable(),
+                    crash_id: None,
+                };
+                recover_job(state, lease, failure).await?;
+            }
+        }
+        Err(join_error) => {
+            let cause = if join_error.is_panic() {
+                let message = panic_message(&*join_error.into_panic());
+                match record_task_panic(state, &lease.job_id, message).await {
+                    Ok(report) => warn!(
+                        job_id = %lease.job_id,
+                        crash_id = %report.crash_id,
+                        "job worker panicked"
+                    ),
+                    Err(err) => {
+                        error!(job_id = %lease.job_id, error = %err, "worker panic not recorded")
+                    }
+                }
+                "worker panicked"
+            } else {
+                "worker cancelled"
+            };
+            warn!(job_id = %lease.job_id, cause, "job worker lost");
+            // Released false means the watchdog already claimed the lease and recovers it.
+            if state.storage.release_job_lease(lease, "lost", Some(cause)).await? {
+                recover_lost_job(state, lease, cause).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Requeues a job whose worker is gone, or fails it with `worker_lost` once its delivery
+// policy has no attempts left. Jobs with unsettled attachments are failed too: the
+// attachment bytes only ever lived in the lost worker.
+pub async fn recover_lost_job(
+    state: &AppState,
+    lease: &JobLease,
+    cause: &str,
+) -> anyhow::Result<Recovery> {
+    let failure = Failure {
+        cause,
+        reason: WORKER_LOST_REASON,
+        retry: true,
+        crash_id: None,
+    };
+    recover_job(state, lease, failure).await
+}
+
+// As `recover_lost_job`, for a lease the previous run left behind. A job failed for want of
+// attempts names the crash that ended that run in its reason, when one was recorded.
+pub async fn recover_orphaned_job(
+    state: &AppState,
+    lease: &JobLease,
+    cause: &str,
+    crash_id: Option<&str>,
+) -> anyhow::Result<Recovery> {
+    let failure = Failure {
+        cause,
+        reason: WORKER_LOST_REASON,
+        retry: true,
+        crash_id,
+    };
+    recover_job(state, lease, failure).await
+}
+
+// How a worker ended: what happened, the reason a failed job is left with, whether the job
+// may run again at all, and the crash that took the worker down, if any.
+struct Failure<'a> {
+    cause: &'a str,
+    reason: &'a str,
+    retry: bool,
+    crash_id: Option<&'a str>,
+}
+
+// A worker that returned a terminal error fails its job straight away; anything else is
+// requeued while the delivery policy has attempts left.
+async fn recover_job(
+    state: &AppState,
+    lease: &JobLease,
+    failure: Failure<'_>,
+) -> anyhow::Result<Recovery> {
+    let Failure {
+        cause,
+        reason,
+        retry,
+        crash_id,
+    } = failure;
+    let job_id = lease.job_id.as_str();
+    let Some(job) = state.storage.get_job(job_id).await? else {
+        return Ok(Recovery::Settled);
+    };
+    if !matches!(job.status.as_str(), "queued" | "running") {
+        return Ok(Recovery::Settled);
+    }
+    let dispatch: Option<Dispatch> = job
+        .dispatch_json
+        .as_deref()
+        .map(serde_json::from_str)
+        .transpose()?;
+    let max_attempts = dispatch
+        .as_ref()
+        .map(|dispatch| dispatch.delivery.max_attempts)
+        .unwrap_or(1);
+    let stranded = state
+        .storage
+        .list_job_transfers(job_id)
+        .await?
+        .iter()
+        .any(|transfer| !is_settled_transfer(&transfer.status));
+
+    match dispatch {
+        Some(dispatch) if retry && lease.attempt < i64::from(max_attempts) && !stranded => {
+            let payload: Value = serde_json::from_str(&job.payload_json)?;
+            state
+                .storage
+                .with_event(
+                    FEED_JOB_EVENT,
+                    json!({
+                        "job_id": job_id,
+                        "status": "queued",
+                        "reason": reason,
+                        "attempt": lease.attempt,
+                    }),
+                )
+                .update_job_status(job_id, "queued", None)
+                .await?;
+            publish(state).await;
+            write_log(
+                state,
+                "warn",
+                &format!(
+                    "job {job_id} requeued after {cause} (attempt {}/{max_attempts})",
+                    lease.attempt
+                ),
+            )
+            .await;
+            redeliver_command_job(state, job.job_id, job.operation, payload, dispatch);
+            Ok(Recovery::Requeued)
+        }
+        _ => {
+            let mut detail = json!({ "cause": cause, "attempt": lease.attempt });
+            let failure_reason = match crash_id {
+                Some(crash_id) => {
+                    detail["crash_id"] = json!(crash_id);
+                    format!("{reason}: crash {crash_id}")
+                }
+                None => reason.to_string(),
+            };
+            state
+                .storage
+                .with_event(
+                    FEED_JOB_EVENT,
+                    json!({
+                        "job_id": job_id,
+                        "status": "failed",
+                        "reason": reason,
+                        "detail": detail,
+                    }),
+                )
+                .fail_job(job_id, &failure_reason)
+                .await?;
+            publish(state).await;
+            write_log(
+                state,
+                "warn",
+                &format!(
+                    "job {job_id} failed with {reason} after {cause} \
+                     (attempt {}/{max_attempts})",
+                    lease.attempt
+                ),
+            )
+            .await;
+            settle_dependents(state, job_id).await;
+            Ok(Recovery::Failed)
+        }
+    }
+}
@@ -1,3 +1,81 @@
-﻿mod app;
+﻿pub mod aggregates;
+pub mod api;
+mod app;
+pub mod archive;
+pub mod attachments;
+pub mod attempts;
+pub mod attribution;
+pub mod bootstrap;
+pub mod bundles;
+pub mod cancellation;
+pub mod capabilities;
+pub mod circuit;
+pub mod clock;
+pub mod coalescing;
+pub mod config_apply;
+pub mod config_schema;
+pub mod consistency;
+pub mod contracts;
+pub mod crash;
+pub mod cursor;
+pub mod dedup;
+pub mod delivery;
+pub mod dependencies;
+pub mod diagnostics;
+pub mod dispatch;
+pub mod dispatch_queue;
+pub mod dry_run;
+#[cfg(feature = "entities")]
+pub mod entity_sync;
+pub mod error;
+pub mod escalation;
+pub mod examples;
+pub mod features;
+pub mod feed;
+pub mod files;
+pub mod fleet;
+pub mod handlers;
+pub mod handshake;
+pub mod health;
+pub mod inbound;
+pub mod job_replay;
+pub mod labels;
+pub mod leases;
+pub mod liveness;
+mod magic;
+pub mod meta;
+pub mod migrations;
+pub mod mute;
+pub mod notifier;
+pub mod profiles;
+pub mod quotas;
+pub mod rebuild;
+pub mod receipts;
+pub mod replay;
+pub mod result_cache;
+pub mod result_validation;
+pub mod results;
+pub mod runtime;
+pub mod sealing;
+pub mod sizing;
+pub mod shaping;
+pub mod sneakernet;
+pub mod spool;
+pub mod store;
+pub mod submissions;
+pub mod submission_spool;
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support;
+pub mod trace;
+pub mod transforms;
+#[cfg(feature = "status-page")]
+pub mod ui;
+pub mod watchdog;
+pub mod webhooks;
 
-pub use app::{build_router, AppState, LogQuery, NodeConfig, NodeStatus};
+pub use app::{
+    build_router, embedded_router, event_type_matches, ApiToken, AppState, ClientPrincipal,
+    LogLine, LogQuery, NodeConfig, NodeStatus, NotificationSettings, RequestListener,
+    ADMIN_PRINCIPAL_ROLE, CLIENT_PRINCIPAL_HEADER, DEFAULT_RESULT_STREAM_TIMEOUT_SECS,
+    DEFAULT_TRANSFER_STALL_AFTER_SECS,
+};
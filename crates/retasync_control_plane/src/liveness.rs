@@ -0,0 +1,217 @@
+﻿use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use retasync_contract::{IdentityHash, MeshCommandEnvelope, CONTENT_TYPE_MSGPACK};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::dispatch::Dispatch;
+use crate::AppState;
+
+pub const NODE_PING_OPERATION: &str = "node.ping";
+pub const REQUIRE_RECENT_CONTACT_FIELD: &str = "require_recent_contact_s";
+pub const DEFAULT_PROBE_TIMEOUT_MS: u64 = 3000;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LivenessSettings {
+    // Destinations absent from the peer directory skip both checks instead of failing them.
+    pub allow_unknown_peers: bool,
+    pub probe_timeout_ms: u64,
+}
+
+impl Default for LivenessSettings {
+    fn default() -> Self {
+        Self {
+            allow_unknown_peers: false,
+            probe_timeout_ms: DEFAULT_PROBE_TIMEOUT_MS,
+        }
+    }
+}
+
+// What a submission asked to have checked before dispatch, and what the probe found.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LivenessCheck {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub require_recent_contact_s: Option<u64>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub probe: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub bypassed: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub probe_outcome: Option<ProbeOutcome>,
+}
+
+impl LivenessCheck {
+    pub fn is_unset(&self) -> bool {
+        self.require_recent_contact_s.is_none() && !self.probe
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProbeOutcome {
+    pub ok: bool,
+    pub latency_ms: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub probed_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LivenessFailure {
+    NotRecentlySeen {
+        last_seen: Option<DateTime<Utc>>,
+        window_s: u64,
+    },
+    ProbeFailed(String),
+    WindowTooLarge(u64),
+}
+
+impl LivenessFailure {
+    pub fn code(&self) -> &'static str {
+        match self {
+            LivenessFailure::NotRecentlySeen { .. } => "destination_not_recently_seen",
+            LivenessFailure::ProbeFailed(_) => "destination_probe_failed",
+            LivenessFailure::WindowTooLarge(_) => "invalid_require_recent_contact_s",
+        }
+    }
+
+    pub fn reason(&self) -> String {
+        match self {
+            LivenessFailure::NotRecentlySeen {
+                last_seen: Some(last_seen),
+                window_s,
+            } => format!(
+                "{}: last seen {} (window {window_s}s)",
+                self.code(),
+                last_seen.to_rfc3339()
+            ),
+            LivenessFailure::NotRecentlySeen {
+                last_seen: None,
+                window_s,
+            } => format!("{}: never seen (window {window_s}s)", self.code()),
+            LivenessFailure::ProbeFailed(error) => format!("{}: {error}", self.code()),
+            LivenessFailure::WindowTooLarge(window_s) => {
+                format!("{}: {window_s}s does not fit a signed duration", self.code())
+            }
+        }
+    }
+
+    pub fn detail(&self) -> Value {
+        match self {
+            LivenessFailure::NotRecentlySeen {
+                last_seen,
+                window_s,
+            } => json!({ "last_seen": last_seen, "require_recent_contact_s": window_s }),
+            LivenessFailure::ProbeFailed(error) => json!({ "error": error }),
+            LivenessFailure::WindowTooLarge(window_s) => {
+                json!({ "require_recent_contact_s": window_s, "max": i64::MAX })
+            }
+        }
+    }
+}
+
+// The window in the signed seconds a clock difference is measured in.
+pub fn contact_window(window_s: u64) -> Result<i64, LivenessFailure> {
+    i64::try_from(window_s).map_err(|_| LivenessFailure::WindowTooLarge(window_s))
+}
+
+// Notes that a peer was heard from, in the directory and in storage, so liveness checks made
+// after a restart still know it.
+pub async fn record_contact(state: &AppState, identity_hash: &IdentityHash, at: DateTime<Utc>) {
+    state.peers.record_contact(identity_hash, at);
+    if let Err(err) = state.storage.record_peer_contact(identity_hash, at).await {
+        warn!(%identity_hash, error = %err, "peer contact not stored");
+    }
+}
+
+// Seeds the peer directory with the contacts recorded by earlier runs.
+pub async fn load_contacts(state: &AppState) -> anyhow::Result<usize> {
+    let contacts = state.storage.list_peer_last_seen().await?;
+    for (identity_hash, last_seen) in &contacts {
+        let identity_hash = IdentityHash::lenient(identity_hash);
+        state.peers.record_contact(&identity_hash, last_seen.as_datetime());
+    }
+    Ok(contacts.len())
+}
+
+// Runs before the command is handed to the bridge, so a failure costs no delivery attempt.
+pub async fn check_destination(
+    state: &AppState,
+    dispatch: &mut Dispatch,
+    settings: &LivenessSettings,
+) -> Result<(), LivenessFailure> {
+    if dispatch.liveness.is_unset() {
+        return Ok(());
+    }
+    let destination = dispatch.destination_identity.clone();
+    if settings.allow_unknown_peers && !state.peers.is_known(&destination) {
+        dispatch.liveness.bypassed = true;
+        return Ok(());
+    }
+
+    if let Some(window_s) = dispatch.liveness.require_recent_contact_s {
+        let window = contact_window(window_s)?;
+        let last_seen = state.peers.last_seen(&destination);
+        let fresh = last_seen.is_some_and(|seen| {
+            Utc::now().signed_duration_since(seen).num_seconds() <= window
+        });
+        if !fresh {
+            return Err(LivenessFailure::NotRecentlySeen {
+                last_seen,
+                window_s,
+            });
+        }
+    }
+    if dispatch.liveness.probe {
+        let outcome = probe(state, dispatch, settings.probe_timeout_ms).await;
+        let error = outcome.error.clone();
+        dispatch.liveness.probe_outcome = Some(outcome);
+        if let Some(error) = error {
+            return Err(LivenessFailure::ProbeFailed(error));
+        }
+    }
+    Ok(())
+}
+
+// Any answer counts as alive, even one reporting an error.
+async fn probe(state: &AppState, dispatch: &Dispatch, timeout_ms: u64) -> ProbeOutcome {
+    let envelope = MeshCommandEnvelope {
+        message_id: Uuid::now_v7().to_string(),
+        operation: NODE_PING_OPERATION.to_string(),
+        sent_at: Utc::now(),
+        source_identity: dispatch.source_identity.clone(),
+        destination_identity: dispatch.destination_identity.clone(),
+        content_type: CONTENT_TYPE_MSGPACK.to_string(),
+        payload: json!({}),
+        ttl_ms: Some(timeout_ms),
+        transport_hint: dispatch.transport_hint.clone(),
+        trace: Vec::new(),
+        trace_truncated: false,
+        meta: None,
+    };
+    let probed_at = Utc::now();
+    let started = Instant::now();
+    let sent = state.bridge.send_command(envelope);
+    let error = match tokio::time::timeout(Duration::from_millis(timeout_ms), sent).await {
+        Ok(Ok(_)) => {
+            record_contact(state, &dispatch.destination_identity, Utc::now()).await;
+            None
+        }
+        Ok(Err(err)) => Some(err.to_string()),
+        Err(_) => Some(format!("no answer within {timeout_ms}ms")),
+    };
+    ProbeOutcome {
+        ok: error.is_none(),
+        latency_ms: started.elapsed().as_millis() as u64,
+        error,
+        probed_at,
+    }
+}
+
+// Built-in answer to an inbound `node.ping`.
+pub fn answer_ping() -> Value {
+    json!({ "status": "ok" })
+}
@@ -0,0 +1,81 @@
+﻿// Content sniffing for the few media types field devices commonly exchange. Only types listed
+// here can be verified; anything else is taken at its declared word.
+
+const SIGNATURES: [(&str, &[u8]); 4] = [
+    ("image/png", b"\x89PNG\r\n\x1a\n"),
+    ("image/jpeg", b"\xff\xd8\xff"),
+    ("application/pdf", b"%PDF-"),
+    ("application/zip", b"PK\x03\x04"),
+];
+pub(crate) const TEXT_PLAIN: &str = "text/plain";
+// Text detection looks no further than this into the content.
+pub(crate) const TEXT_SAMPLE_BYTES: usize = 8192;
+
+// `image/PNG; charset=x` -> `image/png`.
+pub(crate) fn essence(media_type: &str) -> String {
+    media_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase()
+}
+
+pub(crate) fn detect(content: &[u8]) -> Option<&'static str> {
+    SIGNATURES
+        .iter()
+        .find(|(_, signature)| content.starts_with(signature))
+        .map(|(media_type, _)| *media_type)
+        .or_else(|| looks_like_text(content).then_some(TEXT_PLAIN))
+}
+
+// Whether `detect` can confirm or refute a declared media type.
+pub(crate) fn verifiable(media_type: &str) -> bool {
+    let essence = essence(media_type);
+    essence == TEXT_PLAIN
+        || SIGNATURES
+            .iter()
+            .any(|(known, _)| *known == essence)
+}
+
+// UTF-8 without NUL bytes; a multi-byte character cut off by the sample's end still counts.
+fn looks_like_text(content: &[u8]) -> bool {
+    let sample = &content[..content.len().min(TEXT_SAMPLE_BYTES)];
+    if sample.contains(&0) {
+        return false;
+    }
+    match std::str::from_utf8(sample) {
+        Ok(_) => true,
+        Err(err) => err.error_len().is_none() && sample.len() < content.len(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{detect, essence, verifiable};
+
+    #[test]
+    fn signatures_and_text_are_detected() {
+        assert_eq!(detect(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"), Some("image/png"));
+        assert_eq!(detect(b"\xff\xd8\xff\xe0\0\x10JFIF"), Some("image/jpeg"));
+        assert_eq!(detect(b"%PDF-1.7\n%\xe2\xe3"), Some("application/pdf"));
+        assert_eq!(detect(b"PK\x03\x04\x14\0\0\0"), Some("application/zip"));
+        assert_eq!(detect("situation report: all clear ✓".as_bytes()), Some("text/plain"));
+        assert_eq!(detect(b"bin\0ary"), None);
+        assert_eq!(detect(b"\xc3\x28 invalid utf-8"), None);
+
+        // A character split by the sampling window is not held against the content.
+        let mut long = vec![b'a'; 8191];
+        long.extend_from_slice("é and more".as_bytes());
+        assert_eq!(detect(&long), Some("text/plain"));
+    }
+
+    #[test]
+    fn only_known_types_are_verifiable() {
+        assert_eq!(essence(" Image/PNG ; q=1"), "image/png");
+        assert!(verifiable("image/png"));
+        assert!(verifiable("text/plain; charset=utf-8"));
+        assert!(!verifiable("image/gif"));
+        assert!(!verifiable("application/octet-stream"));
+    }
+}
@@ -0,0 +1,155 @@
+﻿use axum::http::HeaderMap;
+use retasync_contract::{EnvelopeMeta, MetaPriority};
+use serde_json::Value;
+
+use crate::attribution::client_id;
+
+pub const PRIORITY_HEADER: &str = "x-retasync-priority";
+pub const TRACE_ID_HEADER: &str = "x-retasync-trace-id";
+pub const CLIENT_VERSION_HEADER: &str = "x-client-version";
+// Each `x-retasync-meta-<key>` header becomes one custom entry under `<key>`.
+pub const META_HEADER_PREFIX: &str = "x-retasync-meta-";
+// Payload keys clients used to smuggle the same metadata in before the block existed.
+pub const RESERVED_PAYLOAD_KEYS: [&str; 5] =
+    ["_meta", "_priority", "_trace_id", "_client_id", "_client_version"];
+pub const RESERVED_KEY_ERROR: &str = "reserved_key_in_payload";
+pub const INVALID_META_ERROR: &str = "invalid_meta";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MetaRejection {
+    ReservedKey(String),
+    Invalid(String),
+}
+
+pub fn check_payload(payload: &Value) -> Result<(), MetaRejection> {
+    let Some(fields) = payload.as_object() else {
+        return Ok(());
+    };
+    match RESERVED_PAYLOAD_KEYS.into_iter().find(|key| fields.contains_key(*key)) {
+        Some(key) => Err(MetaRejection::ReservedKey(key.to_string())),
+        None => Ok(()),
+    }
+}
+
+// The block the submission's headers describe. `x-client-id` is read the way attribution reads
+// it, so a job's client is the same in its source and its envelope.
+pub fn meta_from_headers(headers: &HeaderMap) -> Result<EnvelopeMeta, MetaRejection> {
+    let text = |name: &str| {
+        headers
+            .get(name)
+            .map(|value| header_text(name, value))
+            .transpose()
+    };
+    let priority = text(PRIORITY_HEADER)?
+        .map(|priority| priority.parse::<MetaPriority>())
+        .transpose()
+        .map_err(MetaRejection::Invalid)?;
+    let mut meta = EnvelopeMeta {
+        priority,
+        trace_id: text(TRACE_ID_HEADER)?,
+        client_id: client_id(headers),
+        client_version: text(CLIENT_VERSION_HEADER)?,
+        ..EnvelopeMeta::default()
+    };
+    for (name, value) in headers {
+        if let Some(key) = name.as_str().strip_prefix(META_HEADER_PREFIX) {
+            meta.custom.insert(key.to_string(), header_text(name.as_str(), value)?);
+        }
+    }
+    Ok(meta)
+}
+
+// Fields a batch entry sets win over the request's headers. Nothing is sent when neither set
+// anything.
+pub fn resolve_meta(
+    headers: EnvelopeMeta,
+    submitted: Option<EnvelopeMeta>,
+) -> Result<Option<EnvelopeMeta>, MetaRejection> {
+    let meta = match submitted {
+        Some(submitted) => headers.merged(submitted),
+        None => headers,
+    };
+    meta.validate().map_err(MetaRejection::Invalid)?;
+    Ok((!meta.is_empty()).then_some(meta))
+}
+
+fn header_text(name: &str, value: &axum::http::HeaderValue) -> Result<String, MetaRejection> {
+    value
+        .to_str()
+        .map(|text| text.trim().to_string())
+        .map_err(|_| MetaRejection::Invalid(format!("{name} is not visible ASCII")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+    use serde_json::json;
+
+    #[test]
+    fn headers_fill_the_block_and_entries_override_them() {
+        let mut headers = HeaderMap::new();
+        headers.insert(PRIORITY_HEADER, HeaderValue::from_static("flash"));
+        headers.insert(TRACE_ID_HEADER, HeaderValue::from_static(" trace-7 "));
+        headers.insert("x-client-id", HeaderValue::from_static("tak-bridge"));
+        headers.insert(CLIENT_VERSION_HEADER, HeaderValue::from_static("2.3.0"));
+        headers.insert("x-retasync-meta-shift", HeaderValue::from_static("night"));
+        headers.insert("x-retasync-meta-unit", HeaderValue::from_static("medic-4"));
+
+        let meta = resolve_meta(meta_from_headers(&headers).unwrap(), None)
+            .unwrap()
+            .unwrap();
+        assert_eq!(meta.priority, Some(MetaPriority::Flash));
+        assert_eq!(meta.trace_id.as_deref(), Some("trace-7"));
+        assert_eq!(meta.client_id.as_deref(), Some("tak-bridge"));
+        assert_eq!(meta.client_version.as_deref(), Some("2.3.0"));
+        assert_eq!(
+            serde_json::to_value(&meta.custom).unwrap(),
+            json!({ "shift": "night", "unit": "medic-4" })
+        );
+
+        let entry: EnvelopeMeta =
+            serde_json::from_value(json!({ "priority": "routine", "custom": { "shift": "day" } }))
+                .unwrap();
+        let merged = resolve_meta(meta.clone(), Some(entry)).unwrap().unwrap();
+        assert_eq!(merged.priority, Some(MetaPriority::Routine));
+        assert_eq!(merged.custom["shift"], "day");
+        assert_eq!(merged.custom["unit"], "medic-4");
+        assert_eq!(merged.trace_id, meta.trace_id);
+
+        let none = meta_from_headers(&HeaderMap::new()).unwrap();
+        assert_eq!(resolve_meta(none, None), Ok(None));
+    }
+
+    #[test]
+    fn bad_headers_and_reserved_payload_keys_are_refused() {
+        let mut headers = HeaderMap::new();
+        headers.insert(PRIORITY_HEADER, HeaderValue::from_static("urgent"));
+        assert!(matches!(meta_from_headers(&headers), Err(MetaRejection::Invalid(_))));
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-retasync-meta-shift", HeaderValue::from_static(""));
+        let meta = meta_from_headers(&headers).unwrap();
+        assert_eq!(
+            resolve_meta(meta, None),
+            Err(MetaRejection::Invalid("custom.shift must not be empty".to_string()))
+        );
+
+        let entry = EnvelopeMeta {
+            if_result_digest_differs: Some("ABC".to_string()),
+            ..EnvelopeMeta::default()
+        };
+        assert!(matches!(
+            resolve_meta(EnvelopeMeta::default(), Some(entry)),
+            Err(MetaRejection::Invalid(_))
+        ));
+
+        assert_eq!(check_payload(&json!({ "uid": "evt-1", "meta": {} })), Ok(()));
+        for key in RESERVED_PAYLOAD_KEYS {
+            assert_eq!(
+                check_payload(&json!({ "uid": "evt-1", key: "x" })),
+                Err(MetaRejection::ReservedKey(key.to_string()))
+            );
+        }
+    }
+}
@@ -0,0 +1,862 @@
+﻿use std::sync::RwLock;
+
+use retasync_storage::{PayloadTable, RetasyncStorage, StorageError};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tracing::{info, warn};
+
+use crate::app::event_type_matches;
+use crate::capabilities::ContractVersion;
+use crate::transforms::{parent_of, split_pointer, TransformError};
+use crate::AppState;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PayloadMigrationSettings {
+    // Migrate queued jobs and stored entities before the workers start.
+    pub on_startup: bool,
+    // Cached events are history as peers sent it; rewriting them is opt-in.
+    pub cached_events: bool,
+}
+
+impl Default for PayloadMigrationSettings {
+    fn default() -> Self {
+        Self {
+            on_startup: true,
+            cached_events: false,
+        }
+    }
+}
+
+// What a migration rewrites: command payloads by operation pattern, entity records by type, or
+// event payloads by event pattern.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MigrationTarget {
+    Operation(String),
+    Entity(String),
+    Event(String),
+}
+
+impl MigrationTarget {
+    fn matches(&self, table: PayloadTable, kind: &str) -> bool {
+        match self {
+            MigrationTarget::Operation(pattern) => {
+                table == PayloadTable::Jobs && event_type_matches(pattern, kind)
+            }
+            MigrationTarget::Entity(entity_type) => {
+                table == PayloadTable::Entities && entity_type == kind
+            }
+            MigrationTarget::Event(pattern) => {
+                table == PayloadTable::CachedEvents && event_type_matches(pattern, kind)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum MigrationStep {
+    Rename { pointer: String, to: String },
+    Default { pointer: String, value: Value },
+    Drop { pointer: String },
+}
+
+// One contract change, declared in Rust: carries payloads written under `from_version` to the
+// shape `to_version` expects. Every step is a no-op on a payload that already has the new
+// shape, so a record whose version is unknown can safely be run through the whole chain.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PayloadMigration {
+    name: String,
+    target: MigrationTarget,
+    from_version: String,
+    to_version: String,
+    steps: Vec<MigrationStep>,
+}
+
+impl PayloadMigration {
+    pub fn new(name: &str, target: MigrationTarget, from_version: &str, to_version: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            target,
+            from_version: from_version.to_string(),
+            to_version: to_version.to_string(),
+            steps: Vec::new(),
+        }
+    }
+
+    // Moves the field at `pointer` to `to` under the same parent.
+    pub fn rename(mut self, pointer: &str, to: &str) -> Self {
+        self.steps.push(MigrationStep::Rename {
+            pointer: pointer.to_string(),
+            to: to.to_string(),
+        });
+        self
+    }
+
+    // Sets a newly required field where it is absent, creating missing parent objects.
+    pub fn set_default(mut self, pointer: &str, value: Value) -> Self {
+        self.steps.push(MigrationStep::Default {
+            pointer: pointer.to_string(),
+            value,
+        });
+        self
+    }
+
+    pub fn drop_field(mut self, pointer: &str) -> Self {
+        self.steps.push(MigrationStep::Drop {
+            pointer: pointer.to_string(),
+        });
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn apply(&self, mut payload: Value) -> Result<Value, TransformError> {
+        let fail = |message: String| TransformError {
+            transform: self.name.clone(),
+            message,
+        };
+        for step in &self.steps {
+            match step {
+                MigrationStep::Rename { pointer, to } => {
+                    let Some((Value::Object(object), key)) = parent_of(&mut payload, pointer)
+                    else {
+                        continue;
+                    };
+                    if key == *to || !object.contains_key(&key) {
+                        continue;
+                    }
+                    if object.contains_key(to) {
+                        return Err(fail(format!(
+                            "{pointer}: renaming to {to:?} would overwrite an existing field"
+                        )));
+                    }
+                    let value = object.remove(&key).expect("checked key");
+                    object.insert(to.clone(), value);
+                }
+                MigrationStep::Default { pointer, value } => {
+                    let (parents, key) = split_pointer(pointer)
+                        .ok_or_else(|| fail(format!("{pointer:?} is not a JSON pointer")))?;
+                    let mut target = &mut payload;
+                    for parent in parents {
+                        let Value::Object(object) = target else {
+                            return Err(fail(format!("{pointer}: parent is not an object")));
+                        };
+                        target = object
+                            .entry(parent)
+                            .or_insert_with(|| Value::Object(Map::new()));
+                    }
+                    let Value::Object(object) = target else {
+                        return Err(fail(format!("{pointer}: parent is not an object")));
+                    };
+                    object.entry(key).or_insert_with(|| value.clone());
+                }
+                MigrationStep::Drop { pointer } => {
+                    if let Some((Value::Object(object), key)) = parent_of(&mut payload, pointer) {
+                        object.remove(&key);
+                    }
+                }
+            }
+        }
+        Ok(payload)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Migrated {
+    // Names of the migrations that ran, in order; ones already recorded for the record are
+    // skipped.
+    pub applied: Vec<String>,
+    pub to_version: String,
+    pub payload: Value,
+}
+
+#[derive(Default)]
+pub struct MigrationRegistry {
+    migrations: RwLock<Vec<PayloadMigration>>,
+}
+
+impl MigrationRegistry {
+    pub fn register(&self, migration: PayloadMigration) {
+        self.migrations
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .push(migration);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.read().is_empty()
+    }
+
+    // The chain of migrations from `version` towards `current`. A record with no version
+    // starts at the oldest version any migration for its kind knows about.
+    pub fn plan(
+        &self,
+        table: PayloadTable,
+        kind: &str,
+        version: Option<&str>,
+        current: &str,
+    ) -> Vec<PayloadMigration> {
+        let candidates: Vec<PayloadMigration> = self
+            .read()
+            .iter()
+            .filter(|migration| migration.target.matches(table, kind))
+            .cloned()
+            .collect();
+        let start = match version {
+            Some(version) => version.to_string(),
+            None => match candidates.iter().find(|migration| {
+                !candidates
+                    .iter()
+                    .any(|other| other.to_version == migration.from_version)
+            }) {
+                Some(root) => root.from_version.clone(),
+                None => return Vec::new(),
+            },
+        };
+
+        let mut plan = Vec::new();
+        let mut at = start;
+        // Bounded by the candidate count, so a cycle in the declared versions ends the walk.
+        while at != current && plan.len() < candidates.len() {
+            let Some(next) = candidates
+                .iter()
+                .find(|migration| migration.from_version == at)
+            else {
+                break;
+            };
+            at = next.to_version.clone();
+            plan.push(next.clone());
+        }
+        plan
+    }
+
+    // `None` when no migration covers the payload.
+    pub fn migrate(
+        &self,
+        table: PayloadTable,
+        kind: &str,
+        version: Option<&str>,
+        current: &str,
+        already_applied: &[String],
+        mut payload: Value,
+    ) -> Result<Option<Migrated>, TransformError> {
+        let plan = self.plan(table, kind, version, current);
+        let Some(last) = plan.last() else {
+            return Ok(None);
+        };
+        let to_version = last.to_version.clone();
+        let mut applied = Vec::new();
+        for migration in &plan {
+            if already_applied.contains(&migration.name) {
+                continue;
+            }
+            payload = migration.apply(payload)?;
+            applied.push(migration.name.clone());
+        }
+        Ok(Some(Migrated {
+            applied,
+            to_version,
+            payload,
+        }))
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, Vec<PayloadMigration>> {
+        self.migrations
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MigratedRecord {
+    pub table: PayloadTable,
+    pub key: String,
+    pub kind: String,
+    pub from_version: Option<String>,
+    pub to_version: String,
+    pub migrations: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FailedRecord {
+    pub table: PayloadTable,
+    pub key: String,
+    pub kind: String,
+    pub migration: String,
+    pub error: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MigrationReport {
+    pub contract_version: String,
+    pub dry_run: bool,
+    pub migrated: Vec<MigratedRecord>,
+    pub failed: Vec<FailedRecord>,
+}
+
+pub fn stored_tables(cached_events: bool) -> Vec<PayloadTable> {
+    let mut tables = vec![PayloadTable::Jobs, PayloadTable::Entities];
+    if cached_events {
+        tables.push(PayloadTable::CachedEvents);
+    }
+    tables
+}
+
+// Runs the registered migrations over every record in `tables` written under another contract
+// version. A record a migration fails on is reported and left as it was; with `dry_run` nothing
+// is written.
+pub async fn migrate_stored_payloads(
+    storage: &RetasyncStorage,
+    registry: &MigrationRegistry,
+    contract_version: &str,
+    tables: &[PayloadTable],
+    dry_run: bool,
+) -> Result<MigrationReport, StorageError> {
+    let mut report = MigrationReport {
+        contract_version: contract_version.to_string(),
+        dry_run,
+        migrated: Vec::new(),
+        failed: Vec::new(),
+    };
+    for &table in tables {
+        for mut record in storage
+            .list_versioned_payloads(table, contract_version)
+            .await?
+        {
+            let applied = storage
+                .applied_payload_migrations(table, &record.key)
+                .await?;
+            // A cached event is stored as its whole envelope; only the payload follows the
+            // contract.
+            let payload = match table {
+                PayloadTable::CachedEvents => record.payload["payload"].take(),
+                PayloadTable::Jobs | PayloadTable::Entities => record.payload.take(),
+            };
+            let outcome = registry.migrate(
+                table,
+                &record.kind,
+                record.contract_version.as_deref(),
+                contract_version,
+                &applied,
+                payload,
+            );
+            let migrated = match outcome {
+                Ok(Some(migrated)) => migrated,
+                Ok(None) => continue,
+                Err(err) => {
+                    report.failed.push(FailedRecord {
+                        table,
+                        key: record.key,
+                        kind: record.kind,
+                        migration: err.transform,
+                        error: err.message,
+                    });
+                    continue;
+                }
+            };
+            match table {
+                PayloadTable::CachedEvents => record.payload["payload"] = migrated.payload,
+                PayloadTable::Jobs | PayloadTable::Entities => record.payload = migrated.payload,
+            }
+            if !dry_run {
+                storage
+                    .save_migrated_payload(&record, &migrated.applied, &migrated.to_version)
+                    .await?;
+            }
+            if !migrated.applied.is_empty() {
+                report.migrated.push(MigratedRecord {
+                    table,
+                    key: record.key,
+                    kind: record.kind,
+                    from_version: record.contract_version,
+                    to_version: migrated.to_version,
+                    migrations: migrated.applied,
+                });
+            }
+        }
+    }
+    Ok(report)
+}
+
+// The startup hook: brings queued jobs and entities (and cached events, when configured) up to
+// the node's contract before any worker reads them.
+pub async fn migrate_on_startup(state: &AppState) -> Result<(), StorageError> {
+    let settings = state.node_config.read().await.payload_migrations.clone();
+    let contract = state.contract.current();
+    let Some(contract_version) = contract.registry.version() else {
+        return Ok(());
+    };
+    if !settings.on_startup || state.migrations.is_empty() {
+        return Ok(());
+    }
+    let report = migrate_stored_payloads(
+        &state.storage,
+        &state.migrations,
+        contract_version,
+        &stored_tables(settings.cached_events),
+        false,
+    )
+    .await?;
+    for failure in &report.failed {
+        warn!(
+            table = failure.table.as_str(),
+            key = %failure.key,
+            migration = %failure.migration,
+            error = %failure.error,
+            "payload migration failed"
+        );
+    }
+    if !report.migrated.is_empty() {
+        info!(
+            contract_version,
+            migrated = report.migrated.len(),
+            "stored payloads migrated"
+        );
+    }
+    Ok(())
+}
+
+// The contract version a peer announced in its last `node.hello`, if it has sent one.
+pub fn peer_contract_version(state: &AppState, identity_hash: &str) -> Option<String> {
+    let handshake = state.peers.handshake(identity_hash)?;
+    let contracts: Vec<ContractVersion> =
+        serde_json::from_value(handshake.hello.get("contracts")?.clone()).ok()?;
+    contracts.into_iter().find_map(|contract| contract.version)
+}
+
+// Brings a payload from a peer still on another contract version up to ours when a migration
+// chain covers it. Payloads from peers of unknown version, or that a migration fails on, pass
+// through unchanged.
+pub fn migrate_inbound(
+    state: &AppState,
+    source_identity: &str,
+    table: PayloadTable,
+    kind: &str,
+    payload: Value,
+) -> Value {
+    let contract = state.contract.current();
+    let Some(current) = contract.registry.version() else {
+        return payload;
+    };
+    let Some(peer_version) = peer_contract_version(state, source_identity) else {
+        return payload;
+    };
+    if peer_version == current || state.migrations.is_empty() {
+        return payload;
+    }
+    match state.migrations.migrate(
+        table,
+        kind,
+        Some(&peer_version),
+        current,
+        &[],
+        payload.clone(),
+    ) {
+        Ok(Some(migrated)) => migrated.payload,
+        Ok(None) => payload,
+        Err(err) => {
+            warn!(
+                source_identity,
+                kind,
+                peer_version = %peer_version,
+                error = %err.reason(),
+                "inbound payload migration failed"
+            );
+            payload
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::results::ingest_events;
+    use crate::test_support::{node_config, scratch_sqlite, test_storage};
+
+    use chrono::Utc;
+    use retasync_contract::MeshEventEnvelope;
+    use retasync_mesh_bridge::{Compatibility, InMemoryRpcMeshBridge, PeerHandshake};
+    use retasync_storage::EntityRecord;
+    use serde_json::json;
+    use std::sync::Arc;
+    use uuid::Uuid;
+
+    const PEER: &str = "bb00000000000000000000000000000b";
+
+    // Storage that stamps nothing, as a node did before versions were recorded, and a node on
+    // contract 1.1.0 over the same database.
+    async fn upgraded_node(bridge: Arc<InMemoryRpcMeshBridge>) -> (RetasyncStorage, AppState) {
+        let sqlite_path = scratch_sqlite();
+        let storage = test_storage(&sqlite_path).await;
+        let mut node_config = node_config(json!({}));
+        node_config.sqlite_path = sqlite_path;
+        let state = AppState::new(
+            storage.clone(),
+            bridge,
+            node_config,
+            "asyncapi: 3.0.0\ninfo:\n  version: \"1.1.0\"\n".to_string(),
+            false,
+        );
+        state.migrations.register(
+            PayloadMigration::new(
+                "group-name-snake-case",
+                MigrationTarget::Operation("group.*".to_string()),
+                "1.0.0",
+                "1.1.0",
+            )
+            .rename("/groupName", "group_name")
+            .set_default("/priority", json!("routine")),
+        );
+        state.migrations.register(
+            PayloadMigration::new(
+                "group-entity-snake-case",
+                MigrationTarget::Entity("group".to_string()),
+                "1.0.0",
+                "1.1.0",
+            )
+            .rename("/groupName", "group_name")
+            .set_default("/meta/priority", json!("routine"))
+            .drop_field("/legacy"),
+        );
+        (storage, state)
+    }
+
+    async fn seed_entity(storage: &RetasyncStorage, entity_id: &str, record: Value) {
+        storage
+            .put_entity(&EntityRecord {
+                entity_type: "group".to_string(),
+                entity_id: entity_id.to_string(),
+                updated_at: Utc::now(),
+                record,
+                version: 1,
+                deleted_at: None,
+            })
+            .await
+            .unwrap();
+    }
+
+    async fn job_payload(state: &AppState, job_id: &str) -> Value {
+        let job = state.storage.get_job(job_id).await.unwrap().unwrap();
+        serde_json::from_str(&job.payload_json).unwrap()
+    }
+
+    async fn entity_record(state: &AppState, entity_id: &str) -> Value {
+        state
+            .storage
+            .get_entity("group", entity_id)
+            .await
+            .unwrap()
+            .unwrap()
+            .record
+    }
+
+    #[test]
+    fn chains_run_from_the_oldest_version_when_none_is_recorded() {
+        let registry = MigrationRegistry::default();
+        let target = || MigrationTarget::Event("group.*".to_string());
+        registry.register(
+            PayloadMigration::new("second", target(), "1.1.0", "1.2.0").drop_field("/legacy"),
+        );
+        registry.register(
+            PayloadMigration::new("first", target(), "1.0.0", "1.1.0").rename("/cs", "callsign"),
+        );
+
+        let names = |plan: Vec<PayloadMigration>| -> Vec<String> {
+            plan.iter()
+                .map(|migration| migration.name().to_string())
+                .collect()
+        };
+        let table = PayloadTable::CachedEvents;
+        assert_eq!(
+            names(registry.plan(table, "group.updated", None, "1.2.0")),
+            ["first", "second"]
+        );
+        assert_eq!(
+            names(registry.plan(table, "group.updated", Some("1.1.0"), "1.2.0")),
+            ["second"]
+        );
+        assert!(registry
+            .plan(table, "event.updated", None, "1.2.0")
+            .is_empty());
+        assert!(registry
+            .plan(PayloadTable::Jobs, "group.updated", None, "1.2.0")
+            .is_empty());
+
+        let migrated = registry
+            .migrate(
+                table,
+                "group.updated",
+                None,
+                "1.2.0",
+                &[],
+                json!({ "cs": "A1", "legacy": 1 }),
+            )
+            .unwrap()
+            .unwrap();
+        assert_eq!(migrated.payload, json!({ "callsign": "A1" }));
+        assert_eq!(migrated.to_version, "1.2.0");
+
+        let clash = registry
+            .migrate(
+                table,
+                "group.updated",
+                None,
+                "1.2.0",
+                &[],
+                json!({ "cs": "A1", "callsign": "B2" }),
+            )
+            .unwrap_err();
+        assert_eq!(clash.transform, "first");
+    }
+
+    #[tokio::test]
+    async fn rename_and_default_apply_to_old_jobs_and_entities_once() {
+        let (storage, state) =
+            upgraded_node(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        let unversioned = storage
+            .create_job("group.create", json!({ "groupName": "alpha" }))
+            .await
+            .unwrap();
+        let old = storage
+            .clone()
+            .with_contract_version(Some("1.0.0"))
+            .create_job(
+                "group.create",
+                json!({ "groupName": "bravo", "priority": "flash" }),
+            )
+            .await
+            .unwrap();
+        let dispatched = storage
+            .create_job("group.create", json!({ "groupName": "charlie" }))
+            .await
+            .unwrap();
+        storage
+            .update_job_status(&dispatched.job_id, "success", None)
+            .await
+            .unwrap();
+        let current = state
+            .storage
+            .create_job("group.create", json!({ "group_name": "delta" }))
+            .await
+            .unwrap();
+        seed_entity(
+            &storage,
+            "g-1",
+            json!({ "groupName": "alpha", "legacy": true }),
+        )
+        .await;
+
+        migrate_on_startup(&state).await.unwrap();
+
+        assert_eq!(
+            job_payload(&state, &unversioned.job_id).await,
+            json!({ "group_name": "alpha", "priority": "routine" })
+        );
+        assert_eq!(
+            job_payload(&state, &old.job_id).await,
+            json!({ "group_name": "bravo", "priority": "flash" })
+        );
+        // Finished jobs keep the payload they ran with, and new ones already have the new shape.
+        assert_eq!(
+            job_payload(&state, &dispatched.job_id).await,
+            json!({ "groupName": "charlie" })
+        );
+        assert_eq!(
+            job_payload(&state, &current.job_id).await,
+            json!({ "group_name": "delta" })
+        );
+        assert_eq!(
+            entity_record(&state, "g-1").await,
+            json!({ "group_name": "alpha", "meta": { "priority": "routine" } })
+        );
+        assert_eq!(
+            state
+                .storage
+                .applied_payload_migrations(PayloadTable::Jobs, &unversioned.job_id)
+                .await
+                .unwrap(),
+            ["group-name-snake-case"]
+        );
+
+        // A second run finds nothing left to do.
+        let rerun = migrate_stored_payloads(
+            &state.storage,
+            &state.migrations,
+            "1.1.0",
+            &stored_tables(true),
+            false,
+        )
+        .await
+        .unwrap();
+        assert!(rerun.migrated.is_empty() && rerun.failed.is_empty());
+        assert_eq!(
+            job_payload(&state, &unversioned.job_id).await,
+            json!({ "group_name": "alpha", "priority": "routine" })
+        );
+
+        // A record that somehow reads as old again is restamped without re-running what it
+        // already went through.
+        seed_entity(
+            &storage,
+            "g-1",
+            json!({ "groupName": "alpha-2", "legacy": true }),
+        )
+        .await;
+        let report = migrate_stored_payloads(
+            &state.storage,
+            &state.migrations,
+            "1.1.0",
+            &stored_tables(false),
+            false,
+        )
+        .await
+        .unwrap();
+        assert!(report.migrated.is_empty());
+        assert_eq!(
+            entity_record(&state, "g-1").await,
+            json!({ "groupName": "alpha-2", "legacy": true })
+        );
+        assert!(state
+            .storage
+            .list_versioned_payloads(PayloadTable::Entities, "1.1.0")
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn dry_run_reports_without_writing() {
+        let (storage, state) =
+            upgraded_node(Arc::new(InMemoryRpcMeshBridge::new(true, true))).await;
+        let job = storage
+            .create_job("group.create", json!({ "groupName": "alpha" }))
+            .await
+            .unwrap();
+        seed_entity(
+            &storage,
+            "g-1",
+            json!({ "groupName": "alpha", "priority": 3 }),
+        )
+        .await;
+        seed_entity(
+            &storage,
+            "g-2",
+            json!({ "groupName": "bravo", "group_name": "bravo" }),
+        )
+        .await;
+
+        let tables = stored_tables(false);
+        let report =
+            migrate_stored_payloads(&state.storage, &state.migrations, "1.1.0", &tables, true)
+                .await
+                .unwrap();
+        assert!(report.dry_run);
+        assert_eq!(
+            serde_json::to_value(&report.migrated).unwrap(),
+            json!([
+                {
+                    "table": "jobs",
+                    "key": job.job_id,
+                    "kind": "group.create",
+                    "from_version": null,
+                    "to_version": "1.1.0",
+                    "migrations": ["group-name-snake-case"],
+                },
+                {
+                    "table": "entities",
+                    "key": "group/g-1",
+                    "kind": "group",
+                    "from_version": null,
+                    "to_version": "1.1.0",
+                    "migrations": ["group-entity-snake-case"],
+                },
+            ])
+        );
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.failed[0].key, "group/g-2");
+        assert_eq!(report.failed[0].migration, "group-entity-snake-case");
+
+        assert_eq!(
+            job_payload(&state, &job.job_id).await,
+            json!({ "groupName": "alpha" })
+        );
+        assert!(state
+            .storage
+            .applied_payload_migrations(PayloadTable::Jobs, &job.job_id)
+            .await
+            .unwrap()
+            .is_empty());
+
+        let applied =
+            migrate_stored_payloads(&state.storage, &state.migrations, "1.1.0", &tables, false)
+                .await
+                .unwrap();
+        assert_eq!(applied.migrated, report.migrated);
+        assert_eq!(
+            entity_record(&state, "g-2").await,
+            json!({ "groupName": "bravo", "group_name": "bravo" })
+        );
+    }
+
+    #[tokio::test]
+    async fn events_from_a_peer_on_the_old_contract_are_migrated_on_ingest() {
+        let bridge = Arc::new(InMemoryRpcMeshBridge::new(true, true));
+        let (_, state) = upgraded_node(bridge.clone()).await;
+        state.migrations.register(
+            PayloadMigration::new(
+                "group-event-snake-case",
+                MigrationTarget::Event("group.*".to_string()),
+                "1.0.0",
+                "1.1.0",
+            )
+            .rename("/groupName", "group_name"),
+        );
+        let event = |source: &str| MeshEventEnvelope {
+            message_id: Uuid::now_v7().to_string(),
+            event: "group.updated".to_string(),
+            sent_at: Utc::now(),
+            source_identity: source.parse().unwrap(),
+            destination_identity: "local-node".parse().unwrap(),
+            content_type: "application/msgpack".to_string(),
+            payload: json!({ "groupName": "alpha" }),
+            ttl_ms: None,
+            transport_hint: None,
+        };
+
+        // Without a handshake the peer's version is unknown and the payload is kept as sent.
+        bridge.inject_event(event(PEER));
+        ingest_events(&state).await.unwrap();
+        state.peers.record_handshake(
+            &PEER.parse().unwrap(),
+            PeerHandshake {
+                compatibility: Compatibility::Degraded,
+                hello: json!({
+                    "contracts": [{ "asyncapi": "3.0.0", "version": "1.0.0" }],
+                    "capabilities_digest": "digest",
+                    "content_types": ["application/msgpack"],
+                }),
+                checked_at: Utc::now().to_rfc3339(),
+            },
+        );
+        assert_eq!(
+            peer_contract_version(&state, PEER).as_deref(),
+            Some("1.0.0")
+        );
+        bridge.inject_event(event(PEER));
+        ingest_events(&state).await.unwrap();
+
+        let payloads: Vec<Value> = state
+            .storage
+            .list_cached_events(10)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|envelope| envelope["payload"].clone())
+            .collect();
+        assert_eq!(payloads.len(), 2);
+        assert!(payloads.contains(&json!({ "group_name": "alpha" })));
+        assert!(payloads.contains(&json!({ "groupName": "alpha" })));
+    }
+}
@@ -0,0 +1,370 @@
+﻿use std::time::Duration;
+
+use axum::{
+    extract::{Request, State},
+    http::{HeaderValue, Method},
+    middleware::Next,
+    response::Response,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+use crate::app::publish;
+use crate::error::{RetryAdvice, RetryScope, INDEFINITE_MUTE_RETRY_AFTER};
+use crate::AppState;
+
+pub const NODE_MUTE_CHANGED_EVENT: &str = "node.mute.changed";
+pub const MUTED_HEADER: &str = "x-retasync-muted";
+pub const NODE_MUTED_ERROR: &str = "node_muted";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MuteScope {
+    #[default]
+    All,
+    Commands,
+    Transfers,
+    // Answers to inbound commands: the node publishes no mesh events of its own.
+    Events,
+}
+
+impl MuteScope {
+    pub fn covers(self, traffic: Traffic) -> bool {
+        match self {
+            MuteScope::All => true,
+            MuteScope::Commands => traffic == Traffic::Commands,
+            MuteScope::Transfers => traffic == Traffic::Transfers,
+            MuteScope::Events => traffic == Traffic::Events,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            MuteScope::All => "all",
+            MuteScope::Commands => "commands",
+            MuteScope::Transfers => "transfers",
+            MuteScope::Events => "events",
+        }
+    }
+}
+
+// What a bridge call is about to send.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Traffic {
+    Commands,
+    Transfers,
+    Events,
+}
+
+impl Traffic {
+    fn index(self) -> usize {
+        match self {
+            Traffic::Commands => 0,
+            Traffic::Transfers => 1,
+            Traffic::Events => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MuteWindow {
+    pub scope: MuteScope,
+    pub since: DateTime<Utc>,
+    // Lifted by the expiry task once this passes; `None` holds until `DELETE /v1/node/mute`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub until: Option<DateTime<Utc>>,
+    pub muted_by: String,
+}
+
+impl MuteWindow {
+    // Come back when the window ends; a window with no end is checked on at a steady pace.
+    pub fn retry_advice(&self, now: DateTime<Utc>) -> RetryAdvice {
+        match self.until {
+            Some(until) => RetryAdvice::until(NODE_MUTED_ERROR, RetryScope::Node, until, now),
+            None => RetryAdvice::after(
+                NODE_MUTED_ERROR,
+                RetryScope::Node,
+                INDEFINITE_MUTE_RETRY_AFTER,
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MuteRequest {
+    #[serde(default)]
+    pub scope: MuteScope,
+    #[serde(default)]
+    pub until: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HeldCounts {
+    pub commands: u64,
+    pub transfers: u64,
+    pub events: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MuteStatus {
+    pub muted: bool,
+    #[serde(flatten, default, skip_serializing_if = "Option::is_none")]
+    pub window: Option<MuteWindow>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remaining_secs: Option<i64>,
+    pub held: HeldCounts,
+}
+
+// Tickets are handed out per kind of traffic while it is muted, so held work goes out in the
+// order it was held once the mute lifts.
+#[derive(Debug, Default)]
+struct Gate {
+    window: Option<MuteWindow>,
+    issued: [u64; 3],
+    served: [u64; 3],
+}
+
+impl Gate {
+    fn blocks(&self, traffic: Traffic) -> bool {
+        self.window
+            .as_ref()
+            .is_some_and(|window| window.scope.covers(traffic))
+    }
+
+    fn queued(&self, traffic: Traffic) -> u64 {
+        self.issued[traffic.index()] - self.served[traffic.index()]
+    }
+}
+
+pub struct NodeMute {
+    gate: watch::Sender<Gate>,
+}
+
+impl Default for NodeMute {
+    fn default() -> Self {
+        Self {
+            gate: watch::Sender::new(Gate::default()),
+        }
+    }
+}
+
+impl NodeMute {
+    pub fn window(&self) -> Option<MuteWindow> {
+        self.gate.borrow().window.clone()
+    }
+
+    pub fn blocks(&self, traffic: Traffic) -> bool {
+        self.gate.borrow().blocks(traffic)
+    }
+
+    fn set(&self, window: Option<MuteWindow>) {
+        self.gate.send_modify(|gate| gate.window = window);
+    }
+
+    // Returns at once unless `traffic` is muted or earlier held work of the same kind has not
+    // gone out yet; otherwise waits for the mute to lift and for its turn.
+    pub async fn hold(&self, traffic: Traffic) {
+        let mut ticket = None;
+        self.gate.send_if_modified(|gate| {
+            if !gate.blocks(traffic) && gate.queued(traffic) == 0 {
+                return false;
+            }
+            ticket = Some(gate.issued[traffic.index()]);
+            gate.issued[traffic.index()] += 1;
+            true
+        });
+        let Some(ticket) = ticket else {
+            return;
+        };
+        let mut gate = self.gate.subscribe();
+        // The sender lives as long as the state, so this only fails during shutdown.
+        let _ = gate
+            .wait_for(|gate| !gate.blocks(traffic) && gate.served[traffic.index()] == ticket)
+            .await;
+        self.gate
+            .send_modify(|gate| gate.served[traffic.index()] += 1);
+    }
+
+    pub fn held(&self) -> HeldCounts {
+        let gate = self.gate.borrow();
+        HeldCounts {
+            commands: gate.queued(Traffic::Commands),
+            transfers: gate.queued(Traffic::Transfers),
+            events: gate.queued(Traffic::Events),
+        }
+    }
+}
+
+// Inbound commands wait in the inbound queue rather than the gate while answers are muted.
+pub fn mute_status(state: &AppState, now: DateTime<Utc>) -> MuteStatus {
+    let window = state.mute.window();
+    let mut held = state.mute.held();
+    if state.mute.blocks(Traffic::Events) {
+        held.events += state.inbound.snapshot().depth as u64;
+    }
+    MuteStatus {
+        muted: window.is_some(),
+        remaining_secs: window
+            .as_ref()
+            .and_then(|window| window.until)
+            .map(|until| (until - now).num_seconds().max(0)),
+        window,
+        held,
+    }
+}
+
+// Restores the stored mute at startup; one whose window already passed is cleared instead.
+pub async fn load(state: &AppState, now: DateTime<Utc>) -> anyhow::Result<()> {
+    let Some(stored) = state.storage.node_mute().await? else {
+        return Ok(());
+    };
+    let window: MuteWindow = match serde_json::from_value(stored) {
+        Ok(window) => window,
+        Err(err) => {
+            warn!(error = %err, "ignoring unreadable stored mute");
+            return Ok(state.storage.set_node_mute(None).await?);
+        }
+    };
+    if window.until.is_some_and(|until| until <= now) {
+        info!("stored mute expired while the node was down");
+        return Ok(state.storage.set_node_mute(None).await?);
+    }
+    warn!(scope = window.scope.as_str(), "node starts muted");
+    state.mute.set(Some(window));
+    Ok(())
+}
+
+pub async fn mute(
+    state: &AppState,
+    request: MuteRequest,
+    muted_by: String,
+    now: DateTime<Utc>,
+) -> anyhow::Result<MuteStatus> {
+    let window = MuteWindow {
+        scope: request.scope,
+        since: now,
+        until: request.until,
+        muted_by,
+    };
+    let muted = json!({
+        "muted": true,
+        "scope": window.scope,
+        "until": window.until,
+        "muted_by": window.muted_by,
+    });
+    state
+        .storage
+        .with_event(NODE_MUTE_CHANGED_EVENT, muted)
+        .set_node_mute(Some(&serde_json::to_value(&window)?))
+        .await?;
+    state.mute.set(Some(window.clone()));
+    warn!(scope = window.scope.as_str(), until = ?window.until, "node muted");
+    publish(state).await;
+    Ok(mute_status(state, now))
+}
+
+// Returns false when the node was not muted.
+pub async fn unmute(state: &AppState, reason: &str) -> anyhow::Result<bool> {
+    let Some(window) = state.mute.window() else {
+        return Ok(false);
+    };
+    let unmuted = json!({ "muted": false, "scope": window.scope, "reason": reason });
+    state
+        .storage
+        .with_event(NODE_MUTE_CHANGED_EVENT, unmuted)
+        .set_node_mute(None)
+        .await?;
+    state.mute.set(None);
+    info!(reason, "node unmuted; held dispatch resumes");
+    publish(state).await;
+    Ok(true)
+}
+
+pub async fn expire_mute(state: &AppState, now: DateTime<Utc>) -> anyhow::Result<bool> {
+    let expired = state
+        .mute
+        .window()
+        .and_then(|window| window.until)
+        .is_some_and(|until| until <= now);
+    if !expired {
+        return Ok(false);
+    }
+    unmute(state, "expired").await
+}
+
+pub fn spawn_mute_expiry(state: AppState, period: Duration) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(period);
+        loop {
+            ticker.tick().await;
+            if let Err(err) = expire_mute(&state, Utc::now()).await {
+                error!(error = %err, "mute expiry check failed");
+            }
+        }
+    })
+}
+
+// Submissions accepted while muted say so, since nothing they queue goes out until it lifts.
+pub async fn mark_muted(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let submission = request.method() == Method::POST && request.uri().path().contains("/jobs/");
+    let mut response = next.run(request).await;
+    if !submission || !response.status().is_success() {
+        return response;
+    }
+    if let Some(window) = state.mute.window() {
+        response.headers_mut().insert(
+            MUTED_HEADER,
+            HeaderValue::from_static(window.scope.as_str()),
+        );
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    fn window(scope: MuteScope) -> MuteWindow {
+        MuteWindow {
+            scope,
+            since: Utc::now(),
+            until: None,
+            muted_by: "test".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn held_traffic_is_released_in_the_order_it_was_held() {
+        let mute = Arc::new(NodeMute::default());
+        mute.set(Some(window(MuteScope::Commands)));
+        let released = Arc::new(Mutex::new(Vec::new()));
+        let mut workers = Vec::new();
+        for n in 0..3u64 {
+            let (gate, released) = (mute.clone(), released.clone());
+            workers.push(tokio::spawn(async move {
+                gate.hold(Traffic::Commands).await;
+                released.lock().unwrap().push(n);
+            }));
+            while mute.held().commands <= n {
+                tokio::task::yield_now().await;
+            }
+        }
+        // Other traffic is not held by a scoped mute.
+        mute.hold(Traffic::Transfers).await;
+        assert_eq!(mute.held().commands, 3);
+
+        mute.set(None);
+        for worker in workers {
+            worker.await.unwrap();
+        }
+        assert_eq!(*released.lock().unwrap(), [0, 1, 2]);
+        assert_eq!(mute.held(), HeldCounts::default());
+    }
+}
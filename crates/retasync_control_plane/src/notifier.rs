@@ -0,0 +1,169 @@
+﻿use std::future::Future;
+use std::time::Duration;
+
+use serde_json::Value;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use tracing::error;
+
+use crate::dispatch_queue::forget_finished;
+use crate::AppState;
+
+// Feed rows read per pass.
+const PUBLISH_BATCH: i64 = 256;
+
+// The one place events leave the event feed for the notification inbox and SSE subscribers.
+// A state change records its event in the feed inside the transaction making it, so an event
+// exists exactly when its change committed; passes run one at a time and push rows in feed
+// order, so every committed row is pushed once however many writers commit together.
+#[derive(Debug, Default)]
+pub struct EventNotifier {
+    // The last row pushed; loaded from storage on the first pass.
+    published_through: Mutex<Option<i64>>,
+}
+
+impl EventNotifier {
+    // Pushes every row committed since the last pass and returns how many there were. The
+    // publish point is stored after each batch, so rows a crash left unpushed are pushed after
+    // the restart: subscribers see an event at least once, the feed holds it exactly once.
+    pub async fn publish(&self, state: &AppState) -> anyhow::Result<usize> {
+        let mut published_through = self.published_through.lock().await;
+        let mut after = match *published_through {
+            Some(seq) => seq,
+            None => state.storage.feed_published_through().await?,
+        };
+        let mut published = 0;
+        loop {
+            let events = state.storage.list_feed_events(after, PUBLISH_BATCH).await?;
+            let Some(last) = events.last().map(|event| event.seq) else {
+                break;
+            };
+            let batch = events.len();
+            for event in events {
+                forget_finished(state, &event.event_type, &event.data);
+                deliver(state, &event.event_type, event.data).await;
+            }
+            state.storage.set_feed_published_through(last).await?;
+            after = last;
+            *published_through = Some(after);
+            published += batch;
+            if (batch as i64) < PUBLISH_BATCH {
+                break;
+            }
+        }
+        *published_through = Some(after);
+        Ok(published)
+    }
+
+    // Runs a storage repair that moves the publish point between passes, and reloads the point
+    // from storage on the next one.
+    pub async fn repair<T>(
+        &self,
+        change: impl Future<Output = anyhow::Result<T>>,
+    ) -> anyhow::Result<T> {
+        let mut published_through = self.published_through.lock().await;
+        let result = change.await;
+        *published_through = None;
+        result
+    }
+}
+
+// Catches rows no writer published itself, such as those left by a crash or written by a
+// storage path that changes job statuses on its own.
+pub fn spawn_event_notifier(state: AppState, period: Duration) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(period);
+        loop {
+            ticker.tick().await;
+            if let Err(err) = state.notifier.publish(&state).await {
+                error!(error = %err, "event publish failed");
+            }
+        }
+    })
+}
+
+pub(crate) async fn deliver(state: &AppState, event_type: &str, data: Value) {
+    // The inbox is trimmed by the retention task; readers cap their own backlog.
+    let records = state.node_config.read().await.notifications.records(event_type);
+    if records {
+        match state.storage.append_notification(event_type, &data).await {
+            Ok(record) => {
+                let _ = state.notification_bus.send(record);
+            }
+            Err(err) => error!(error = %err, event_type, "failed to persist notification"),
+        }
+    }
+
+    // Ids are assigned and sent under the lock so live subscribers see them in order.
+    let mut recent = state
+        .recent_events
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let _ = state.sse_bus.send(recent.push(event_type, data));
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::test_state;
+    use retasync_storage::StorageError;
+    use retasync_transfer::TransferProgress;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn rolled_back_change_pushes_nothing_and_a_commit_pushes_once() {
+        let state = test_state().await;
+        let transfer = state
+            .storage
+            .create_transfer_with_progress(
+                None,
+                json!({ "file_name": "map.png" }),
+                TransferProgress::new(100, 50),
+            )
+            .await
+            .unwrap();
+        let transfer_id = transfer.transfer_id.clone();
+        let mut events = state.sse_bus.subscribe();
+        let failed = json!({ "transfer_id": transfer_id, "status": "failed", "reason": "x" });
+
+        let id = transfer_id.clone();
+        let injected = state
+            .storage
+            .with_event("transfer.failed", failed.clone())
+            .with_tx(move |tx| {
+                Box::pin(async move {
+                    tx.update_transfer_status(&id, "failed", Some("x")).await?;
+                    Err::<(), _>(StorageError::not_found("injected fault"))
+                })
+            })
+            .await;
+        assert!(injected.is_err());
+        assert_eq!(state.notifier.publish(&state).await.unwrap(), 0);
+        assert!(events.try_recv().is_err());
+        assert!(state.storage.list_feed_events(0, 10).await.unwrap().is_empty());
+        let transfer = state.storage.get_transfer(&transfer_id).await.unwrap().unwrap();
+        assert_eq!(transfer.status, "queued");
+
+        state
+            .storage
+            .with_event("transfer.failed", failed.clone())
+            .update_transfer_status(&transfer_id, "failed", Some("x"))
+            .await
+            .unwrap();
+        let publishers: Vec<_> = (0..4)
+            .map(|_| {
+                let state = state.clone();
+                tokio::spawn(async move { state.notifier.publish(&state).await.unwrap() })
+            })
+            .collect();
+        let mut published = 0;
+        for publisher in publishers {
+            published += publisher.await.unwrap();
+        }
+        assert_eq!(published, 1);
+        let event = events.try_recv().unwrap();
+        assert_eq!((event.event_type.as_str(), &event.data), ("transfer.failed", &failed));
+        assert!(events.try_recv().is_err());
+        assert_eq!(state.storage.list_feed_events(0, 10).await.unwrap().len(), 1);
+        assert_eq!(state.storage.feed_published_through().await.unwrap(), 1);
+    }
+}
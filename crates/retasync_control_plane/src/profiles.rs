@@ -0,0 +1,298 @@
+﻿use std::collections::BTreeMap;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use thiserror::Error;
+
+use crate::config_schema::{runtime_config_schema, schema_defaults};
+
+const SECRET_BYTES: usize = 32;
+
+// Config defaults for one deployment role. `preset` is node.toml text: keys a node's own file
+// sets override it, and it overrides the schema defaults.
+#[derive(Debug)]
+pub struct NodeProfile {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub preset: &'static str,
+    // Keys, as JSON pointers, the preset cannot start without; `retasyncd init` generates them.
+    pub secrets: &'static [&'static str],
+}
+
+pub const NODE_PROFILES: &[NodeProfile] = &[
+    NodeProfile {
+        name: "gateway",
+        description: "Serves inbound commands to remote clients, with a long-lived cache",
+        preset: r#"
+[http]
+bind = "0.0.0.0:8080"
+
+[inbound]
+capacity = 1024
+rate_limit_per_minute = 600
+
+[retention]
+cache_retention_hours = 336
+
+[event_feed]
+retention_hours = 336
+
+[webhooks]
+max_retries = 8
+history = 500
+"#,
+        secrets: &["/http/auth_token"],
+    },
+    NodeProfile {
+        name: "field_client",
+        description: "Sends commands only, keeps little history and compresses early",
+        preset: r#"
+[inbound]
+capacity = 0
+
+[transport]
+compression_threshold_bytes = 256
+
+[retention]
+job_retention_hours = 24
+cache_retention_hours = 6
+transfer_retention_days = 1
+
+[event_feed]
+retention_hours = 24
+"#,
+        secrets: &[],
+    },
+    NodeProfile {
+        name: "relay",
+        description: "Relays commands addressed to other nodes and keeps almost nothing",
+        preset: r#"
+[routing]
+relay_destinations = ["*"]
+
+[retention]
+job_retention_hours = 24
+cache_retention_hours = 1
+
+[event_feed]
+retention_hours = 24
+"#,
+        secrets: &[],
+    },
+];
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("unknown profile `{name}`; available profiles: {}", profile_names().join(", "))]
+pub struct UnknownProfile {
+    pub name: String,
+}
+
+pub fn profile_names() -> Vec<&'static str> {
+    NODE_PROFILES.iter().map(|profile| profile.name).collect()
+}
+
+pub fn find_profile(name: &str) -> Result<&'static NodeProfile, UnknownProfile> {
+    NODE_PROFILES
+        .iter()
+        .find(|profile| profile.name == name)
+        .ok_or_else(|| UnknownProfile {
+            name: name.to_string(),
+        })
+}
+
+impl NodeProfile {
+    pub fn preset_value(&self) -> anyhow::Result<Value> {
+        toml::from_str(self.preset)
+            .map_err(|err| anyhow::anyhow!("profile `{}` preset: {}", self.name, err.message()))
+    }
+
+    // The node.toml `retasyncd init` writes: the profile line and a fresh value per secret.
+    pub fn init_document(&self) -> anyhow::Result<String> {
+        let mut document = toml::Table::new();
+        document.insert("profile".to_string(), toml::Value::from(self.name));
+        for pointer in self.secrets {
+            let mut keys: Vec<&str> = pointer.trim_start_matches('/').split('/').collect();
+            let leaf = keys.pop().unwrap_or_default();
+            let mut table = &mut document;
+            for key in keys {
+                table = table
+                    .entry(key)
+                    .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+                    .as_table_mut()
+                    .ok_or_else(|| anyhow::anyhow!("secret {pointer} is not under a table"))?;
+            }
+            table.insert(leaf.to_string(), toml::Value::from(generate_secret()?));
+        }
+        Ok(toml::to_string(&document)?)
+    }
+}
+
+pub fn generate_secret() -> anyhow::Result<String> {
+    let mut bytes = [0u8; SECRET_BYTES];
+    getrandom::getrandom(&mut bytes).map_err(|err| anyhow::anyhow!("generate secret: {err}"))?;
+    Ok(URL_SAFE_NO_PAD.encode(bytes))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConfigSource {
+    Default,
+    Preset,
+    File,
+}
+
+// The config a node runs with: schema defaults, then its profile's preset, then its own file.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EffectiveConfig {
+    pub profile: Option<String>,
+    pub config: Value,
+    // The layer each value came from, by JSON pointer. Arrays count as one value.
+    pub sources: BTreeMap<String, ConfigSource>,
+}
+
+impl EffectiveConfig {
+    // `file` is the node's config as written, legacy keys already renamed; `profile` overrides
+    // the profile it names.
+    pub fn resolve(file: &Value, profile: Option<&str>) -> anyhow::Result<Self> {
+        let mut file = file.clone();
+        let named = match file.as_object_mut().and_then(|file| file.remove("profile")) {
+            None => None,
+            Some(Value::String(name)) => Some(name),
+            Some(other) => anyhow::bail!("profile must be a string, not {other}"),
+        };
+        let profile = profile.map(str::to_string).or(named);
+
+        let mut config = Value::Object(Map::new());
+        let mut sources = BTreeMap::new();
+        let defaults = schema_defaults(&runtime_config_schema()).unwrap_or_default();
+        overlay(&mut config, &defaults, ConfigSource::Default, "", &mut sources);
+        if let Some(name) = &profile {
+            let preset = find_profile(name)?.preset_value()?;
+            overlay(&mut config, &preset, ConfigSource::Preset, "", &mut sources);
+        }
+        overlay(&mut config, &file, ConfigSource::File, "", &mut sources);
+        Ok(Self {
+            profile,
+            config,
+            sources,
+        })
+    }
+}
+
+// Tables merge key by key; anything else in `layer` replaces what was there.
+fn overlay(
+    target: &mut Value,
+    layer: &Value,
+    source: ConfigSource,
+    pointer: &str,
+    sources: &mut BTreeMap<String, ConfigSource>,
+) {
+    if let (Some(target), Some(layer)) = (target.as_object_mut(), layer.as_object()) {
+        if !layer.is_empty() {
+            sources.remove(pointer);
+        }
+        for (key, value) in layer {
+            let slot = target.entry(key.clone()).or_insert(Value::Null);
+            overlay(slot, value, source, &child(pointer, key), sources);
+        }
+        return;
+    }
+    let nested = format!("{pointer}/");
+    sources.retain(|key, _| !key.starts_with(&nested));
+    *target = layer.clone();
+    mark(target, source, pointer, sources);
+}
+
+fn mark(
+    value: &Value,
+    source: ConfigSource,
+    pointer: &str,
+    sources: &mut BTreeMap<String, ConfigSource>,
+) {
+    match value.as_object() {
+        Some(object) if !object.is_empty() => {
+            for (key, value) in object {
+                mark(value, source, &child(pointer, key), sources);
+            }
+        }
+        _ => {
+            sources.insert(pointer.to_string(), source);
+        }
+    }
+}
+
+pub(crate) fn child(pointer: &str, key: &str) -> String {
+    format!("{pointer}/{}", key.replace('~', "~0").replace('/', "~1"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find_profile, ConfigSource, EffectiveConfig, NODE_PROFILES};
+    use crate::config_schema::{runtime_config_schema, validate_config_value};
+    use serde_json::json;
+
+    #[test]
+    fn file_beats_preset_beats_default() {
+        let file = json!({
+            "profile": "gateway",
+            "http": { "bind": "10.0.0.2:8080", "auth_token": "secret" },
+            "inbound": { "rate_limit_per_minute": 60 },
+            "retention": {},
+            "routing": { "relay_destinations": ["*"] },
+        });
+        let effective = EffectiveConfig::resolve(&file, None).unwrap();
+        assert_eq!(effective.profile.as_deref(), Some("gateway"));
+        let config = &effective.config;
+        assert_eq!(config["http"]["bind"], "10.0.0.2:8080");
+        assert_eq!(config["inbound"]["rate_limit_per_minute"], 60);
+        assert_eq!(config["inbound"]["capacity"], 1024);
+        assert_eq!(config["retention"]["cache_retention_hours"], 336);
+        assert_eq!(config["retention"]["job_retention_hours"], 720);
+        assert_eq!(config["storage"]["sqlite_path"], "retasync.sqlite");
+        assert!(config.get("profile").is_none());
+
+        let source = |pointer: &str| effective.sources.get(pointer).copied();
+        assert_eq!(source("/http/bind"), Some(ConfigSource::File));
+        assert_eq!(source("/http/auth_token"), Some(ConfigSource::File));
+        assert_eq!(source("/inbound/rate_limit_per_minute"), Some(ConfigSource::File));
+        assert_eq!(source("/inbound/capacity"), Some(ConfigSource::Preset));
+        assert_eq!(source("/retention/cache_retention_hours"), Some(ConfigSource::Preset));
+        assert_eq!(source("/retention/job_retention_hours"), Some(ConfigSource::Default));
+        assert_eq!(source("/storage/sqlite_path"), Some(ConfigSource::Default));
+        // An array is replaced whole, so it is reported once rather than per item.
+        assert_eq!(source("/routing/relay_destinations"), Some(ConfigSource::File));
+        assert_eq!(source("/routing/relay_destinations/0"), None);
+        // An empty table from the defaults stays a value until a layer fills it in.
+        assert_eq!(source("/operation_defaults"), Some(ConfigSource::Default));
+        let config = &effective.config;
+        assert!(effective.sources.keys().all(|pointer| config.pointer(pointer).is_some()));
+
+        // A profile given on the command line replaces the one the file names.
+        let effective = EffectiveConfig::resolve(&file, Some("relay")).unwrap();
+        assert_eq!(effective.profile.as_deref(), Some("relay"));
+        assert_eq!(effective.config["inbound"]["capacity"], 256);
+        assert_eq!(effective.config["retention"]["cache_retention_hours"], 1);
+    }
+
+    #[test]
+    fn unknown_profiles_list_the_known_ones() {
+        let err = EffectiveConfig::resolve(&json!({ "profile": "gatway" }), None).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "unknown profile `gatway`; available profiles: gateway, field_client, relay"
+        );
+        assert!(find_profile("relay").is_ok());
+    }
+
+    #[test]
+    fn every_preset_merges_into_a_valid_config() {
+        let schema = runtime_config_schema();
+        for profile in NODE_PROFILES {
+            let effective = EffectiveConfig::resolve(&json!({}), Some(profile.name)).unwrap();
+            assert_eq!(validate_config_value(&schema, &effective.config), Vec::new());
+            let preset = profile.preset_value().unwrap();
+            assert!(!preset.as_object().unwrap().is_empty(), "{}", profile.name);
+        }
+    }
+}
@@ -0,0 +1,344 @@
+﻿use std::collections::BTreeMap;
+
+use chrono::{DateTime, Duration, DurationRound, Utc};
+use retasync_storage::{
+    CanonicalTimestamp, QuotaClaim, QuotaOverride, QuotaUsage, RetasyncStorage,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{RetryAdvice, RetryScope};
+use crate::AppState;
+
+pub const QUOTA_WINDOW_HOURS: i64 = 24;
+pub const DESTINATION_QUOTA: &str = "destination";
+pub const TOKEN_QUOTA: &str = "token";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QuotaSettings {
+    // Bytes per rolling window; unset means unlimited.
+    pub max_bytes_per_destination: Option<u64>,
+    pub max_bytes_per_token: Option<u64>,
+}
+
+impl QuotaSettings {
+    fn limit(&self, subject_kind: &str) -> Option<u64> {
+        match subject_kind {
+            DESTINATION_QUOTA => self.max_bytes_per_destination,
+            _ => self.max_bytes_per_token,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct QuotaExceeded {
+    pub subject_kind: String,
+    pub subject: String,
+    pub limit_bytes: u64,
+    pub used_bytes: u64,
+    pub requested_bytes: u64,
+    // When enough hourly buckets have aged out of the window to admit the request; absent when
+    // the request is larger than the whole budget.
+    pub reset_at: Option<String>,
+}
+
+impl QuotaExceeded {
+    // Come back at `reset_at`; a request larger than the whole budget is told to wait out a
+    // full window, by which time the limit may have been raised.
+    pub fn retry_advice(&self, now: DateTime<Utc>) -> RetryAdvice {
+        let scope = match self.subject_kind.as_str() {
+            DESTINATION_QUOTA => RetryScope::Destination,
+            _ => RetryScope::Client,
+        };
+        let reset_at = self
+            .reset_at
+            .as_deref()
+            .and_then(|at| CanonicalTimestamp::parse(at).ok())
+            .map_or(now + Duration::hours(QUOTA_WINDOW_HOURS), |at| at.as_datetime());
+        RetryAdvice::until("quota_exceeded", scope, reset_at, now)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct QuotaStatus {
+    pub subject_kind: String,
+    pub subject: String,
+    pub used_bytes: u64,
+    // Held for transfers still in flight.
+    pub reserved_bytes: u64,
+    pub limit_bytes: Option<u64>,
+    pub remaining_bytes: Option<u64>,
+    pub overridden: bool,
+    // When the oldest bucket still in the window ages out.
+    pub reset_at: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct QuotaReport {
+    pub window_hours: i64,
+    pub max_bytes_per_destination: Option<u64>,
+    pub max_bytes_per_token: Option<u64>,
+    pub subjects: Vec<QuotaStatus>,
+    pub overrides: Vec<QuotaOverride>,
+}
+
+pub fn bucket_start(at: DateTime<Utc>) -> DateTime<Utc> {
+    at.duration_trunc(Duration::hours(1)).unwrap_or(at)
+}
+
+// Usage is kept in hourly buckets, and a bucket counts while any of its hour is inside the
+// window: that is, every bucket starting after this instant.
+pub fn window_start(now: DateTime<Utc>) -> DateTime<Utc> {
+    now - Duration::hours(QUOTA_WINDOW_HOURS + 1)
+}
+
+fn charged_bytes(charge: &QuotaUsage) -> u64 {
+    charge.bytes.max(0) as u64
+}
+
+// A full window after the end of the bucket's hour.
+fn usage_expiry(charge: &QuotaUsage) -> Option<String> {
+    let bucket = CanonicalTimestamp::parse(&charge.used_at).ok()?.as_datetime();
+    Some(CanonicalTimestamp::from(bucket + Duration::hours(QUOTA_WINDOW_HOURS + 1)).to_string())
+}
+
+// An override for the subject replaces the configured limit for its kind.
+async fn limit_for(
+    storage: &RetasyncStorage,
+    settings: &QuotaSettings,
+    subject_kind: &str,
+    subject: &str,
+) -> anyhow::Result<(Option<u64>, bool)> {
+    Ok(match storage.get_quota_override(subject_kind, subject).await? {
+        Some(entry) => (Some(entry.max_bytes.max(0) as u64), true),
+        None => (settings.limit(subject_kind), false),
+    })
+}
+
+// `used` includes reserved bytes, which only leave the window when their transfer ends.
+fn reset_at(charges: &[QuotaUsage], used: u64, requested: u64, limit: u64) -> Option<String> {
+    if requested > limit {
+        return None;
+    }
+    let mut remaining = used;
+    for charge in charges {
+        remaining = remaining.saturating_sub(charged_bytes(charge));
+        if remaining + requested <= limit {
+            return usage_expiry(charge);
+        }
+    }
+    None
+}
+
+// The destination and the submitting client an upload is charged to, with their limits.
+pub async fn quota_claims(
+    state: &AppState,
+    destination: &str,
+    token: &str,
+) -> anyhow::Result<Vec<QuotaClaim>> {
+    let settings = state.node_config.read().await.quotas.clone();
+    let mut claims = Vec::with_capacity(2);
+    for (subject_kind, subject) in [(DESTINATION_QUOTA, destination), (TOKEN_QUOTA, token)] {
+        let (limit, _) = limit_for(&state.storage, &settings, subject_kind, subject).await?;
+        claims.push(QuotaClaim {
+            subject_kind: subject_kind.to_string(),
+            subject: subject.to_string(),
+            limit_bytes: limit.map(|limit| i64::try_from(limit).unwrap_or(i64::MAX)),
+        });
+    }
+    Ok(claims)
+}
+
+// Describes a subject whose budget `requested` more bytes would overrun.
+pub async fn exceeded_quota(
+    storage: &RetasyncStorage,
+    claim: &QuotaClaim,
+    used: u64,
+    requested: u64,
+    now: DateTime<Utc>,
+) -> anyhow::Result<QuotaExceeded> {
+    let limit = claim.limit_bytes.unwrap_or(i64::MAX).max(0) as u64;
+    let since = CanonicalTimestamp::from(window_start(now)).to_string();
+    let charges = storage
+        .list_subject_quota_usage(&claim.subject_kind, &claim.subject, &since)
+        .await?;
+    Ok(QuotaExceeded {
+        subject_kind: claim.subject_kind.clone(),
+        subject: claim.subject.clone(),
+        limit_bytes: limit,
+        used_bytes: used,
+        requested_bytes: requested,
+        reset_at: reset_at(&charges, used, requested, limit),
+    })
+}
+
+// The first of `claims` that `requested` more bytes would overrun, counting what is already
+// reserved. Admission reserves the bytes again inside the transaction that creates the
+// transfer, so this only spares a doomed request the rest of the admission checks.
+pub async fn check_quotas(
+    state: &AppState,
+    claims: &[QuotaClaim],
+    requested: u64,
+    now: DateTime<Utc>,
+) -> anyhow::Result<Option<QuotaExceeded>> {
+    let since = CanonicalTimestamp::from(window_start(now)).to_string();
+    for claim in claims {
+        let Some(limit) = claim.limit_bytes else {
+            continue;
+        };
+        let charges = state
+            .storage
+            .list_subject_quota_usage(&claim.subject_kind, &claim.subject, &since)
+            .await?;
+        let reserved = state
+            .storage
+            .reserved_quota_bytes(&claim.subject_kind, &claim.subject)
+            .await?;
+        let used = charges.iter().map(charged_bytes).sum::<u64>() + reserved.max(0) as u64;
+        if used + requested > limit.max(0) as u64 {
+            return Ok(Some(exceeded_quota(&state.storage, claim, used, requested, now).await?));
+        }
+    }
+    Ok(None)
+}
+
+pub async fn record_usage(
+    storage: &RetasyncStorage,
+    destination: &str,
+    token: Option<&str>,
+    bytes: u64,
+    at: DateTime<Utc>,
+) -> anyhow::Result<()> {
+    let bucket = CanonicalTimestamp::from(bucket_start(at)).to_string();
+    storage
+        .record_quota_usage(DESTINATION_QUOTA, destination, &bucket, bytes as i64)
+        .await?;
+    if let Some(token) = token {
+        storage
+            .record_quota_usage(TOKEN_QUOTA, token, &bucket, bytes as i64)
+            .await?;
+    }
+    Ok(())
+}
+
+pub async fn quota_report(state: &AppState, now: DateTime<Utc>) -> anyhow::Result<QuotaReport> {
+    let settings = state.node_config.read().await.quotas.clone();
+    let usage = state
+        .storage
+        .list_quota_usage(&window_start(now).to_rfc3339())
+        .await?;
+    let reservations = state.storage.list_quota_reservations().await?;
+    let overrides = state.storage.list_quota_overrides().await?;
+
+    let mut grouped: BTreeMap<(String, String), (Vec<QuotaUsage>, u64)> = BTreeMap::new();
+    for charge in usage {
+        grouped
+            .entry((charge.subject_kind.clone(), charge.subject.clone()))
+            .or_default()
+            .0
+            .push(charge);
+    }
+    for reservation in reservations {
+        grouped
+            .entry((reservation.subject_kind, reservation.subject))
+            .or_default()
+            .1 += reservation.bytes.max(0) as u64;
+    }
+    let subjects = grouped
+        .into_iter()
+        .map(|((subject_kind, subject), (charges, reserved_bytes))| {
+            let used_bytes = charges.iter().map(charged_bytes).sum();
+            let overridden = overrides
+                .iter()
+                .find(|entry| entry.subject_kind == subject_kind && entry.subject == subject);
+            let limit_bytes = match overridden {
+                Some(entry) => Some(entry.max_bytes.max(0) as u64),
+                None => settings.limit(&subject_kind),
+            };
+            QuotaStatus {
+                remaining_bytes: limit_bytes
+                    .map(|limit| limit.saturating_sub(used_bytes + reserved_bytes)),
+                reset_at: charges.first().and_then(usage_expiry),
+                overridden: overridden.is_some(),
+                subject_kind,
+                subject,
+                used_bytes,
+                reserved_bytes,
+                limit_bytes,
+            }
+        })
+        .collect();
+
+    Ok(QuotaReport {
+        window_hours: QUOTA_WINDOW_HOURS,
+        max_bytes_per_destination: settings.max_bytes_per_destination,
+        max_bytes_per_token: settings.max_bytes_per_token,
+        subjects,
+        overrides,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{record_usage, reset_at, window_start, DESTINATION_QUOTA};
+    use retasync_storage::{QuotaUsage, RetasyncStorage};
+    use crate::test_support::{scratch_sqlite, test_storage};
+    use chrono::{DateTime, Utc};
+
+    fn at(raw: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(raw).unwrap().with_timezone(&Utc)
+    }
+
+    #[tokio::test]
+    async fn usage_accrues_into_hourly_buckets_across_the_hour_boundary() {
+        let storage = test_storage(&scratch_sqlite()).await;
+
+        for (time, bytes) in [
+            ("2026-03-01T10:05:00Z", 100),
+            ("2026-03-01T10:59:59Z", 50),
+            ("2026-03-01T11:00:00Z", 25),
+            ("2026-03-01T11:30:00Z", 5),
+        ] {
+            record_usage(&storage, "dest", Some("ops"), bytes, at(time)).await.unwrap();
+        }
+
+        // The 10:00 bucket holds usage from 10:59, so it still counts at 10:30 the next day.
+        let since = window_start(at("2026-03-02T10:30:00Z")).to_rfc3339();
+        let buckets = storage
+            .list_subject_quota_usage(DESTINATION_QUOTA, "dest", &since)
+            .await
+            .unwrap();
+        let hourly: Vec<(String, i64)> = buckets
+            .iter()
+            .map(|bucket| (bucket.used_at.clone(), bucket.bytes))
+            .collect();
+        assert_eq!(
+            hourly,
+            [
+                ("2026-03-01T10:00:00.000Z".to_string(), 150),
+                ("2026-03-01T11:00:00.000Z".to_string(), 30)
+            ]
+        );
+        // Once its whole hour has left the window, the bucket goes with it.
+        let later = window_start(at("2026-03-02T11:00:00Z")).to_rfc3339();
+        let buckets = storage
+            .list_subject_quota_usage("token", "ops", &later)
+            .await
+            .unwrap();
+        assert_eq!(buckets.iter().map(|bucket| bucket.bytes).collect::<Vec<_>>(), [30]);
+
+        // Admitting 150 more under 200 needs the 10:00 bucket gone: 11:00 the next day.
+        assert_eq!(
+            reset_at(&buckets_for(&storage).await, 180, 150, 200).as_deref(),
+            Some("2026-03-02T11:00:00.000Z")
+        );
+        assert_eq!(reset_at(&buckets_for(&storage).await, 180, 300, 200), None);
+    }
+
+    async fn buckets_for(storage: &RetasyncStorage) -> Vec<QuotaUsage> {
+        storage
+            .list_subject_quota_usage("token", "ops", "2026-03-01T00:00:00Z")
+            .await
+            .unwrap()
+    }
+}
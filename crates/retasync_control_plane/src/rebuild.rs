@@ -0,0 +1,646 @@
+﻿use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use retasync_contract::IdentityHash;
+use retasync_storage::{CanonicalTimestamp, QuotaUsage};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::{error, warn};
+use uuid::Uuid;
+
+use crate::app::{emit, write_log};
+use crate::attribution::client_key;
+use crate::mute::MuteScope;
+use crate::quotas::{bucket_start, window_start, DESTINATION_QUOTA, TOKEN_QUOTA};
+use crate::AppState;
+
+pub const STORAGE_REBUILD_PROGRESS_EVENT: &str = "storage.rebuild.progress";
+pub const STORAGE_REBUILD_COMPLETED_EVENT: &str = "storage.rebuild.completed";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RebuildScope {
+    // Event feed head, gaps and publish point, and notification cursors.
+    Feed,
+    // Quota usage buckets still inside the window, from completed transfers.
+    Quotas,
+    // Peer last-seen times, from stored inbound and answered traffic.
+    Peers,
+    // Rows kept per job or transfer whose job or transfer is gone.
+    Orphans,
+    Indexes,
+}
+
+impl RebuildScope {
+    pub const ALL: [RebuildScope; 5] = [
+        RebuildScope::Feed,
+        RebuildScope::Quotas,
+        RebuildScope::Peers,
+        RebuildScope::Orphans,
+        RebuildScope::Indexes,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RebuildScope::Feed => "feed",
+            RebuildScope::Quotas => "quotas",
+            RebuildScope::Peers => "peers",
+            RebuildScope::Orphans => "orphans",
+            RebuildScope::Indexes => "indexes",
+        }
+    }
+}
+
+// `all` or absent selects every scope; otherwise a comma-separated list, run in `ALL` order.
+// An unknown name is returned as the error.
+pub fn parse_scopes(raw: Option<&str>) -> Result<Vec<RebuildScope>, String> {
+    let raw = raw.map(str::trim).unwrap_or("all");
+    if raw == "all" {
+        return Ok(RebuildScope::ALL.to_vec());
+    }
+    let mut scopes = Vec::new();
+    for name in raw.split(',').map(str::trim) {
+        let scope = RebuildScope::ALL
+            .into_iter()
+            .find(|scope| scope.as_str() == name)
+            .ok_or_else(|| name.to_string())?;
+        scopes.push(scope);
+    }
+    scopes.sort();
+    scopes.dedup();
+    Ok(scopes)
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct RebuildQuery {
+    pub scope: Option<String>,
+    // Closing feed gaps renumbers events consumers may already hold, so it is never implied.
+    pub allow_renumber: Option<bool>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Discrepancy {
+    pub kind: String,
+    pub detail: Value,
+    pub fixed: bool,
+}
+
+impl Discrepancy {
+    fn new(kind: &str, detail: Value, fixed: bool) -> Self {
+        Self {
+            kind: kind.to_string(),
+            detail,
+            fixed,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ScopeReport {
+    pub scope: RebuildScope,
+    pub discrepancies: Vec<Discrepancy>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RebuildStatus {
+    Running,
+    Completed,
+    Failed,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RebuildRun {
+    pub rebuild_id: String,
+    pub status: RebuildStatus,
+    pub scopes: Vec<RebuildScope>,
+    pub allow_renumber: bool,
+    pub started_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<DateTime<Utc>>,
+    pub reports: Vec<ScopeReport>,
+    pub found: usize,
+    pub fixed: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RebuildRefusal {
+    InProgress(String),
+    // Jobs still hold leases or inbound commands are queued, and the node is not muted.
+    WorkersActive { leased_jobs: i64, inbound_depth: usize },
+}
+
+// Starts a rebuild in the background and returns its handle; `GET` on the endpoint reports it
+// until the next one starts.
+pub async fn start_rebuild(
+    state: &AppState,
+    scopes: Vec<RebuildScope>,
+    allow_renumber: bool,
+) -> anyhow::Result<Result<RebuildRun, RebuildRefusal>> {
+    let muted = state
+        .mute
+        .window()
+        .is_some_and(|window| window.scope == MuteScope::All);
+    if !muted {
+        let leased_jobs = state.storage.count_live_job_leases(Utc::now()).await?;
+        let inbound_depth = state.inbound.snapshot().depth;
+        if leased_jobs > 0 || inbound_depth > 0 {
+            return Ok(Err(RebuildRefusal::WorkersActive {
+                leased_jobs,
+                inbound_depth,
+            }));
+        }
+    }
+    let run = {
+        let mut current = state
+            .storage_rebuild
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(running) = current
+            .as_ref()
+            .filter(|run| run.status == RebuildStatus::Running)
+        {
+            return Ok(Err(RebuildRefusal::InProgress(running.rebuild_id.clone())));
+        }
+        let run = RebuildRun {
+            rebuild_id: Uuid::now_v7().to_string(),
+            status: RebuildStatus::Running,
+            scopes,
+            allow_renumber,
+            started_at: Utc::now(),
+            finished_at: None,
+            reports: Vec::new(),
+            found: 0,
+            fixed: 0,
+            error: None,
+        };
+        *current = Some(run.clone());
+        run
+    };
+    let background = state.clone();
+    let started = run.clone();
+    tokio::spawn(async move { run_rebuild(&background, started).await });
+    Ok(Ok(run))
+}
+
+pub fn current_rebuild(state: &AppState) -> Option<RebuildRun> {
+    state
+        .storage_rebuild
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .clone()
+}
+
+async fn run_rebuild(state: &AppState, mut run: RebuildRun) {
+    for scope in run.scopes.clone() {
+        match rebuild_scope(state, scope, run.allow_renumber, Utc::now()).await {
+            Ok(report) => {
+                let found = report.discrepancies.len();
+                let fixed = report.discrepancies.iter().filter(|found| found.fixed).count();
+                run.found += found;
+                run.fixed += fixed;
+                run.reports.push(report);
+                update(state, &run);
+                emit(
+                    state,
+                    STORAGE_REBUILD_PROGRESS_EVENT,
+                    json!({
+                        "rebuild_id": run.rebuild_id,
+                        "scope": scope,
+                        "found": found,
+                        "fixed": fixed,
+                        "completed_scopes": run.reports.len(),
+                        "total_scopes": run.scopes.len(),
+                    }),
+                )
+                .await;
+            }
+            Err(err) => {
+                error!(error = %err, scope = scope.as_str(), "storage rebuild failed");
+                run.status = RebuildStatus::Failed;
+                run.error = Some(format!("{}: {err:#}", scope.as_str()));
+                break;
+            }
+        }
+    }
+    if run.status == RebuildStatus::Running {
+        run.status = RebuildStatus::Completed;
+    }
+    run.finished_at = Some(Utc::now());
+    let level = if run.found > run.fixed { "warn" } else { "info" };
+    write_log(
+        state,
+        level,
+        &format!(
+            "storage.rebuild.completed rebuild_id={} found={} fixed={}",
+            run.rebuild_id, run.found, run.fixed
+        ),
+    )
+    .await;
+    // Publish the final status only once the completion event is out, so a caller that sees
+    // the run finished also finds its event in the feed.
+    emit(state, STORAGE_REBUILD_COMPLETED_EVENT, json!(run)).await;
+    update(state, &run);
+}
+
+fn update(state: &AppState, run: &RebuildRun) {
+    *state
+        .storage_rebuild
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(run.clone());
+}
+
+pub async fn rebuild_scope(
+    state: &AppState,
+    scope: RebuildScope,
+    allow_renumber: bool,
+    now: DateTime<Utc>,
+) -> anyhow::Result<ScopeReport> {
+    let discrepancies = match scope {
+        RebuildScope::Feed => rebuild_feed(state, allow_renumber).await?,
+        RebuildScope::Quotas => rebuild_quotas(state, now).await?,
+        RebuildScope::Peers => rebuild_peers(state).await?,
+        RebuildScope::Orphans => rebuild_orphans(state).await?,
+        RebuildScope::Indexes => rebuild_indexes(state).await?,
+    };
+    for found in &discrepancies {
+        warn!(
+            scope = scope.as_str(),
+            kind = %found.kind,
+            fixed = found.fixed,
+            "storage discrepancy"
+        );
+    }
+    Ok(ScopeReport {
+        scope,
+        discrepancies,
+    })
+}
+
+// A head behind the last row, a publish point past the head and cursors past the notifications
+// are moved back in line. Gaps are only closed when renumbering is allowed.
+async fn rebuild_feed(state: &AppState, allow_renumber: bool) -> anyhow::Result<Vec<Discrepancy>> {
+    let mut found = Vec::new();
+    let feed = state.storage.check_event_feed().await?;
+    if feed.head_seq < feed.max_seq {
+        state.storage.raise_event_feed_head(feed.max_seq).await?;
+        found.push(Discrepancy::new(
+            "feed_head_behind",
+            json!({ "head_seq": feed.head_seq, "max_seq": feed.max_seq }),
+            true,
+        ));
+    }
+    let head_seq = feed.head_seq.max(feed.max_seq);
+    if feed.published_through > head_seq {
+        let storage = &state.storage;
+        state
+            .notifier
+            .repair(async { Ok(storage.set_feed_published_through(head_seq).await?) })
+            .await?;
+        found.push(Discrepancy::new(
+            "feed_published_ahead",
+            json!({ "published_through": feed.published_through, "head_seq": head_seq }),
+            true,
+        ));
+    }
+    if !feed.gaps.is_empty() && allow_renumber {
+        let storage = &state.storage;
+        state
+            .notifier
+            .repair(async { Ok(storage.renumber_event_feed().await?) })
+            .await?;
+    }
+    for (from_seq, to_seq) in &feed.gaps {
+        found.push(Discrepancy::new(
+            "feed_gap",
+            json!({ "from_seq": from_seq, "to_seq": to_seq }),
+            allow_renumber,
+        ));
+    }
+    for cursor in state.storage.list_cursors_ahead().await? {
+        state
+            .storage
+            .rewind_notification_cursor(&cursor.token_label, cursor.head_seq)
+            .await?;
+        found.push(Discrepancy::new(
+            "notification_cursor_ahead",
+            json!(cursor),
+            true,
+        ));
+    }
+    Ok(found)
+}
+
+// Usage is recomputed from the transfers that succeeded in the window, charged as of the moment
+// they did, the way marking a transfer successful charges its reservation.
+async fn rebuild_quotas(state: &AppState, now: DateTime<Utc>) -> anyhow::Result<Vec<Discrepancy>> {
+    let after = CanonicalTimestamp::from(window_start(now)).to_string();
+    let mut expected: BTreeMap<(String, String, String), i64> = BTreeMap::new();
+    for completed in state.storage.list_completed_transfers(&after).await? {
+        let metadata: Value = serde_json::from_str(&completed.transfer.metadata_json)?;
+        let Some(destination) = metadata.get("destination_identity").and_then(Value::as_str)
+        else {
+            continue;
+        };
+        let completed_at = CanonicalTimestamp::parse(&completed.transfer.updated_at)?;
+        let used_at = CanonicalTimestamp::from(bucket_start(completed_at.into())).to_string();
+        let token = match &completed.source {
+            Some(source) => Some(client_key(source).to_string()),
+            None => metadata
+                .get("submitted_by")
+                .and_then(Value::as_str)
+                .map(str::to_string),
+        };
+        let subjects = [(DESTINATION_QUOTA, Some(destination.to_string())), (TOKEN_QUOTA, token)];
+        for (subject_kind, subject) in subjects {
+            if let Some(subject) = subject {
+                *expected
+                    .entry((subject_kind.to_string(), subject, used_at.clone()))
+                    .or_default() += completed.bytes;
+            }
+        }
+    }
+
+    let mut recorded: BTreeMap<(String, String, String), i64> = BTreeMap::new();
+    for charge in state.storage.list_quota_usage(&after).await? {
+        recorded.insert((charge.subject_kind, charge.subject, charge.used_at), charge.bytes);
+    }
+    let mut keys: Vec<_> = expected.keys().chain(recorded.keys()).cloned().collect();
+    keys.sort();
+    keys.dedup();
+    let found: Vec<_> = keys
+        .into_iter()
+        .filter_map(|key| {
+            let recorded_bytes = recorded.get(&key).copied().unwrap_or(0);
+            let expected_bytes = expected.get(&key).copied().unwrap_or(0);
+            let (subject_kind, subject, used_at) = key;
+            (recorded_bytes != expected_bytes).then(|| {
+                Discrepancy::new(
+                    "quota_usage_mismatch",
+                    json!({
+                        "subject_kind": subject_kind,
+                        "subject": subject,
+                        "used_at": used_at,
+                        "recorded_bytes": recorded_bytes,
+                        "expected_bytes": expected_bytes,
+                    }),
+                    true,
+                )
+            })
+        })
+        .collect();
+    if !found.is_empty() {
+        let usage: Vec<_> = expected
+            .into_iter()
+            .map(|((subject_kind, subject, used_at), bytes)| QuotaUsage {
+                subject_kind,
+                subject,
+                used_at,
+                bytes,
+            })
+            .collect();
+        state.storage.replace_quota_usage(&after, &usage).await?;
+    }
+    Ok(found)
+}
+
+// Last-seen times only move forward, so a peer is raised to the latest contact on record.
+async fn rebuild_peers(state: &AppState) -> anyhow::Result<Vec<Discrepancy>> {
+    let mut found = Vec::new();
+    for (identity_hash, seen_at) in state.storage.list_peer_contacts().await? {
+        let seen_at = CanonicalTimestamp::parse(&seen_at)?.as_datetime();
+        let last_seen = state.peers.last_seen(&identity_hash);
+        if last_seen.is_some_and(|last_seen| last_seen >= seen_at) {
+            continue;
+        }
+        state
+            .peers
+            .record_contact(&IdentityHash::lenient(&identity_hash), seen_at);
+        found.push(Discrepancy::new(
+            "peer_last_seen_behind",
+            json!({
+                "identity_hash": identity_hash,
+                "last_seen": last_seen,
+                "stored_contact": seen_at,
+            }),
+            true,
+        ));
+    }
+    Ok(found)
+}
+
+async fn rebuild_orphans(state: &AppState) -> anyhow::Result<Vec<Discrepancy>> {
+    Ok(state
+        .storage
+        .purge_orphaned_rows()
+        .await?
+        .into_iter()
+        .map(|orphaned| Discrepancy::new("orphaned_rows", json!(orphaned), true))
+        .collect())
+}
+
+async fn rebuild_indexes(state: &AppState) -> anyhow::Result<Vec<Discrepancy>> {
+    let rebuilt = state.storage.rebuild_indexes().await?;
+    Ok(rebuilt
+        .problems_before
+        .iter()
+        .map(|problem| {
+            let fixed = !rebuilt.problems_after.contains(problem);
+            Discrepancy::new("integrity_problem", json!({ "problem": problem }), fixed)
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_scopes, rebuild_scope, RebuildScope};
+    use crate::quotas::{bucket_start, record_usage, DESTINATION_QUOTA};
+    use crate::test_support::test_state;
+    use crate::AppState;
+    use chrono::{Duration, Utc};
+    use retasync_contract::IdentityHash;
+
+    use retasync_storage::CanonicalTimestamp;
+    use serde_json::{json, Value};
+    use sqlx::Acquire;
+
+    const PEER: &str = "0123456789abcdef0123456789abcdef";
+    const DESTINATION: &str = "fedcba9876543210fedcba9876543210";
+
+    async fn execute(state: &AppState, sql: &str) {
+        sqlx::query(sql).execute(state.storage.pool()).await.expect(sql);
+    }
+
+    // Runs every scope and lists what each one found as (kind, fixed).
+    async fn findings(state: &AppState, allow_renumber: bool) -> Vec<(String, bool)> {
+        let mut found = Vec::new();
+        for scope in RebuildScope::ALL {
+            let report = rebuild_scope(state, scope, allow_renumber, Utc::now()).await.unwrap();
+            found.extend(
+                report
+                    .discrepancies
+                    .into_iter()
+                    .map(|discrepancy| (discrepancy.kind, discrepancy.fixed)),
+            );
+        }
+        found
+    }
+
+    #[test]
+    fn scopes_parse_from_all_or_a_list() {
+        assert_eq!(parse_scopes(None).unwrap(), RebuildScope::ALL);
+        assert_eq!(
+            parse_scopes(Some("peers, feed,peers")).unwrap(),
+            [RebuildScope::Feed, RebuildScope::Peers]
+        );
+        assert_eq!(parse_scopes(Some("feed,counters")), Err("counters".to_string()));
+    }
+
+    #[tokio::test]
+    async fn rebuild_reports_exactly_the_injected_discrepancies() {
+        let state = test_state().await;
+        let completed_at = Utc::now() - Duration::hours(2);
+        let completed = CanonicalTimestamp::from(completed_at).to_string();
+
+        // A consistent base: feed events all published, one notification acked, a
+        // completed transfer charged to its destination and submitter, a peer last seen when
+        // its message arrived, and a job with a message.
+        execute(
+            &state,
+            &format!("INSERT INTO notifications(event_type, payload_json, created_at) VALUES ('job.updated', '{{}}', '{completed}')"),
+        )
+        .await;
+        execute(
+            &state,
+            &format!("INSERT INTO notification_cursors(token_label, acked_seq, updated_at) VALUES ('ops', 1, '{completed}')"),
+        )
+        .await;
+        execute(
+            &state,
+            &format!(
+                "INSERT INTO transfers(transfer_id, status, metadata_json, submitted_at, updated_at) VALUES ('t-1', 'success', '{{\"destination_identity\":\"{DESTINATION}\",\"submitted_by\":\"ops\"}}', '{completed}', '{completed}')"
+            ),
+        )
+        .await;
+        execute(
+            &state,
+            "INSERT INTO transfer_progress(transfer_id, bytes_total, bytes_sent, chunks_total, chunks_sent) VALUES ('t-1', 4096, 4096, 4, 4)",
+        )
+        .await;
+        record_usage(&state.storage, DESTINATION, Some("ops"), 4096, completed_at).await.unwrap();
+        execute(
+            &state,
+            &format!("INSERT INTO seen_messages(source_identity, message_id, sent_at, received_at) VALUES ('{PEER}', 'm-1', '{completed}', '{completed}')"),
+        )
+        .await;
+        state.peers.record_contact(&IdentityHash::lenient(PEER), completed_at);
+        execute(
+            &state,
+            &format!(
+                "INSERT INTO jobs(job_id, operation, status, payload_json, submitted_at, updated_at) VALUES ('j-1', 'ping', 'success', '{{}}', '{completed}', '{completed}')"
+            ),
+        )
+        .await;
+        execute(
+            &state,
+            &format!("INSERT INTO job_messages(message_id, job_id, sent_at) VALUES ('m-1', 'j-1', '{completed}')"),
+        )
+        .await;
+        // Inserting the job wrote its own feed event; these follow it.
+        for _ in 0..3 {
+            execute(
+                &state,
+                &format!(
+                    "INSERT INTO event_feed(event_type, payload_json, emitted_at) VALUES ('job.updated', '{{}}', '{completed}')"
+                ),
+            )
+            .await;
+        }
+        let head = state.storage.check_event_feed().await.unwrap().max_seq;
+        state.storage.set_feed_published_through(head).await.unwrap();
+        assert_eq!(findings(&state, false).await, []);
+
+        // The surgery: a feed row deleted and the head and publish point hand-edited, a cursor
+        // pushed past the last notification, a quota bucket overwritten, a newer message from
+        // the peer, and the job deleted with its message left behind.
+        execute(&state, "DELETE FROM event_feed WHERE seq = 2").await;
+        execute(&state, "UPDATE sqlite_sequence SET seq = 1 WHERE name = 'event_feed'").await;
+        state.storage.set_feed_published_through(9).await.unwrap();
+        execute(&state, "UPDATE notification_cursors SET acked_seq = 7").await;
+        execute(
+            &state,
+            &format!("UPDATE quota_usage SET bytes = 1 WHERE subject_kind = '{DESTINATION_QUOTA}'"),
+        )
+        .await;
+        let later = CanonicalTimestamp::from(completed_at + Duration::minutes(5)).to_string();
+        execute(
+            &state,
+            &format!("INSERT INTO seen_messages(source_identity, message_id, sent_at, received_at) VALUES ('{PEER}', 'm-2', '{later}', '{later}')"),
+        )
+        .await;
+        let mut conn = state.storage.pool().acquire().await.unwrap();
+        sqlx::query("PRAGMA foreign_keys = OFF").execute(&mut *conn).await.unwrap();
+        let mut tx = conn.begin().await.unwrap();
+        sqlx::query("DELETE FROM jobs WHERE job_id = 'j-1'").execute(&mut *tx).await.unwrap();
+        tx.commit().await.unwrap();
+        sqlx::query("PRAGMA foreign_keys = ON").execute(&mut *conn).await.unwrap();
+        drop(conn);
+
+        let feed = rebuild_scope(&state, RebuildScope::Feed, false, Utc::now()).await.unwrap();
+        let details: Vec<(String, Value)> = feed
+            .discrepancies
+            .iter()
+            .map(|discrepancy| (discrepancy.kind.clone(), discrepancy.detail.clone()))
+            .collect();
+        assert_eq!(
+            details,
+            [
+                ("feed_head_behind".to_string(), json!({ "head_seq": 1, "max_seq": head })),
+                (
+                    "feed_published_ahead".to_string(),
+                    json!({ "published_through": 9, "head_seq": head })
+                ),
+                ("feed_gap".to_string(), json!({ "from_seq": 2, "to_seq": 2 })),
+                (
+                    "notification_cursor_ahead".to_string(),
+                    json!({ "token_label": "ops", "acked_seq": 7, "head_seq": 1 })
+                ),
+            ]
+        );
+        let quotas = rebuild_scope(&state, RebuildScope::Quotas, false, Utc::now()).await.unwrap();
+        assert_eq!(
+            quotas.discrepancies[0].detail,
+            json!({
+                "subject_kind": DESTINATION_QUOTA,
+                "subject": DESTINATION,
+                "used_at": CanonicalTimestamp::from(bucket_start(completed_at)).to_string(),
+                "recorded_bytes": 1,
+                "expected_bytes": 4096,
+            })
+        );
+        assert_eq!(
+            findings(&state, false).await,
+            [
+                ("feed_gap".to_string(), false),
+                ("peer_last_seen_behind".to_string(), true),
+                ("orphaned_rows".to_string(), true),
+            ]
+        );
+        assert_eq!(
+            state.peers.last_seen(PEER),
+            Some(CanonicalTimestamp::parse(&later).unwrap().as_datetime())
+        );
+
+        // Only renumbering closes the gap; after that nothing is left to fix.
+        assert_eq!(findings(&state, true).await, [("feed_gap".to_string(), true)]);
+        assert_eq!(findings(&state, false).await, []);
+        let feed = state.storage.check_event_feed().await.unwrap();
+        let renumbered = head - 1;
+        assert_eq!(
+            (feed.head_seq, feed.max_seq, feed.published_through),
+            (renumbered, renumbered, renumbered)
+        );
+    }
+}
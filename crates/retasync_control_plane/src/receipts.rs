@@ -0,0 +1,199 @@
+﻿use anyhow::{anyhow, Context};
+use chrono::{DateTime, Utc};
+use retasync_contract::{
+    canonical_digest, DeliveryReceipt, IdentityHash, MeshEventEnvelope, CONTENT_TYPE_MSGPACK,
+    DELIVERY_RECEIPT_EVENT,
+};
+use retasync_storage::{CanonicalTimestamp, DeliveryReceiptRecord};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::app::publish;
+use crate::dispatch::local_identity;
+use crate::sneakernet::load_secret;
+use crate::AppState;
+
+pub const RECEIPT_INVALID_EVENT: &str = "security.receipt_invalid";
+pub const RECEIPT_NOT_FOUND_ERROR: &str = "receipt_not_found";
+// Why a stored receipt is unverified when its signature could not be checked at all.
+pub const RECEIPT_SIGNER_UNKNOWN: &str = "receipt_signer_unknown";
+pub const RECEIPT_RECEIVER_MISMATCH: &str = "receipt_receiver_mismatch";
+
+// The `[receipts]` section. Receipts that arrive are verified and stored whether or not this
+// node sends its own.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReceiptSettings {
+    // Sign a receipt for each command a handler answers and each bundle received, and send it
+    // back to the sender. Receipts are signed with the `[sneakernet]` signing key.
+    pub enabled: bool,
+}
+
+// Signs a receipt for `message_id` and sends it to `destination`. A receipt is a courtesy to
+// the sender, so a failure is logged and costs only the receipt.
+pub(crate) async fn send_receipt(
+    state: &AppState,
+    message_id: &str,
+    destination: &IdentityHash,
+    received_at: DateTime<Utc>,
+    result: &Value,
+) {
+    let (enabled, local, key_path) = {
+        let config = state.node_config.read().await;
+        let key_path = config.sneakernet.key_path_for(state.storage.database_path());
+        (config.receipts.enabled, local_identity(&config), key_path)
+    };
+    if !enabled {
+        return;
+    }
+    let signed = load_secret(&key_path)
+        .map_err(|detail| anyhow!("signing key {}: {detail}", key_path.display()))
+        .and_then(|seed| {
+            let digest = canonical_digest(result)?;
+            let received_at = CanonicalTimestamp::from(received_at).to_string();
+            Ok(DeliveryReceipt::sign(
+                message_id,
+                local.as_str(),
+                &received_at,
+                &digest,
+                &seed,
+            )?)
+        });
+    let sent = match signed.and_then(|receipt| Ok(serde_json::to_value(receipt)?)) {
+        Ok(payload) => {
+            let envelope = MeshEventEnvelope {
+                message_id: Uuid::now_v7().to_string(),
+                event: DELIVERY_RECEIPT_EVENT.to_string(),
+                sent_at: Utc::now(),
+                source_identity: local,
+                destination_identity: destination.clone(),
+                content_type: CONTENT_TYPE_MSGPACK.to_string(),
+                payload,
+                ttl_ms: None,
+                transport_hint: None,
+            };
+            state.bridge.publish_event(envelope).await.map(|_| ()).map_err(Into::into)
+        }
+        Err(err) => Err(err),
+    };
+    if let Err(err) = sent {
+        warn!(message_id, destination = %destination, error = %err, "delivery receipt not sent");
+    }
+}
+
+// Verifies a receipt against the signing key trusted for the node the command or transfer went
+// to, and stores it beside the job or transfer. A receipt that cannot be verified is kept, but
+// flagged and reported as `security.receipt_invalid`.
+pub(crate) async fn ingest_receipt(
+    state: &AppState,
+    envelope: MeshEventEnvelope<Value>,
+) -> anyhow::Result<()> {
+    let receipt: DeliveryReceipt =
+        serde_json::from_value(envelope.payload).context("decode delivery receipt payload")?;
+    let (job_id, transfer_id, destination) =
+        match state.storage.job_for_message(&receipt.message_id).await? {
+            Some(job_id) => {
+                let destination = match state.storage.get_job(&job_id).await? {
+                    Some(job) => job
+                        .dispatch_json
+                        .and_then(|dispatch| serde_json::from_str::<Value>(&dispatch).ok())
+                        .and_then(|dispatch| destination_of(&dispatch)),
+                    None => None,
+                };
+                (Some(job_id), None, destination)
+            }
+            None => match state.storage.get_transfer(&receipt.message_id).await? {
+                Some(transfer) => {
+                    let destination = serde_json::from_str::<Value>(&transfer.metadata_json)
+                        .ok()
+                        .and_then(|metadata| destination_of(&metadata));
+                    (None, Some(transfer.transfer_id), destination)
+                }
+                None => {
+                    warn!(
+                        message_id = %receipt.message_id,
+                        source_identity = %envelope.source_identity,
+                        "delivery receipt for unknown command or transfer"
+                    );
+                    return Ok(());
+                }
+            },
+        };
+    // The node the command was sent to must be the one that signed for it.
+    let destination = destination.unwrap_or(envelope.source_identity);
+    let signer = IdentityHash::lenient(&receipt.receiver_identity);
+    let verdict = if signer != destination {
+        Err(RECEIPT_RECEIVER_MISMATCH)
+    } else {
+        let key = state
+            .node_config
+            .read()
+            .await
+            .sneakernet
+            .trusted_key(destination.as_str())
+            .map(|key| key.to_bytes());
+        match key {
+            Some(key) => receipt.verify(&key).map_err(|err| err.code()),
+            None => Err(RECEIPT_SIGNER_UNKNOWN),
+        }
+    };
+    let reason = verdict.err();
+    let record = DeliveryReceiptRecord {
+        message_id: receipt.message_id.clone(),
+        job_id: job_id.clone(),
+        transfer_id: transfer_id.clone(),
+        receiver_identity: receipt.receiver_identity.clone(),
+        receipt_json: serde_json::to_string(&receipt)?,
+        verified: reason.is_none(),
+        reason: reason.map(str::to_string),
+        recorded_at: CanonicalTimestamp::now().to_string(),
+    };
+    let Some(reason) = reason else {
+        state.storage.record_delivery_receipt(&record).await?;
+        return Ok(());
+    };
+    let invalid = json!({
+        "message_id": receipt.message_id,
+        "job_id": job_id,
+        "transfer_id": transfer_id,
+        "receiver_identity": receipt.receiver_identity,
+        "destination_identity": destination,
+        "reason": reason,
+    });
+    state
+        .storage
+        .with_event(RECEIPT_INVALID_EVENT, invalid)
+        .record_delivery_receipt(&record)
+        .await?;
+    warn!(
+        message_id = %receipt.message_id,
+        receiver_identity = %receipt.receiver_identity,
+        reason,
+        "delivery receipt not verified"
+    );
+    publish(state).await;
+    Ok(())
+}
+
+fn destination_of(value: &Value) -> Option<IdentityHash> {
+    value
+        .get("destination_identity")
+        .and_then(Value::as_str)
+        .map(IdentityHash::lenient)
+}
+
+// A stored receipt as `GET /v1/jobs/{id}/receipt` shows it.
+pub fn receipt_view(record: DeliveryReceiptRecord) -> Value {
+    let receipt = serde_json::from_str::<Value>(&record.receipt_json).unwrap_or(Value::Null);
+    json!({
+        "message_id": record.message_id,
+        "job_id": record.job_id,
+        "transfer_id": record.transfer_id,
+        "receipt": receipt,
+        "verification": if record.verified { "verified" } else { "unverified" },
+        "reason": record.reason,
+        "recorded_at": record.recorded_at,
+    })
+}
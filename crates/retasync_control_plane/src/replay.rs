@@ -0,0 +1,195 @@
+﻿use chrono::{DateTime, Duration, Utc};
+use retasync_contract::MeshCommandEnvelope;
+use retasync_storage::{CanonicalTimestamp, RetasyncStorage, SeenMessage};
+use serde_json::Value;
+
+pub const DUPLICATE_MESSAGE_ERROR: &str = "duplicate_message";
+pub const MESSAGE_EXPIRED_ERROR: &str = "message_expired";
+pub const REPLAY_DETECTED_EVENT: &str = "security.replay_detected";
+pub const DEFAULT_MAX_ENVELOPE_AGE_SECS: u64 = 86_400;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    Fresh,
+    Expired,
+    Replayed(SeenMessage),
+}
+
+// The oldest `sent_at` still accepted; `None` when `max_age_secs` is 0 and age goes unchecked.
+pub fn oldest_accepted(max_age_secs: u64, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    if max_age_secs == 0 {
+        return None;
+    }
+    let max_age = Duration::try_seconds(i64::try_from(max_age_secs).ok()?)?;
+    now.checked_sub_signed(max_age)
+}
+
+// Age is checked before the seen-store, so an envelope old enough for its row to have been
+// pruned is refused as expired instead of being taken for a new one.
+pub async fn screen_envelope(
+    storage: &RetasyncStorage,
+    envelope: &MeshCommandEnvelope<Value>,
+    max_age_secs: u64,
+    now: DateTime<Utc>,
+) -> anyhow::Result<Verdict> {
+    if oldest_accepted(max_age_secs, now).is_some_and(|oldest| envelope.sent_at < oldest) {
+        return Ok(Verdict::Expired);
+    }
+    let first = storage
+        .record_seen_message(
+            &envelope.source_identity,
+            &envelope.message_id,
+            &CanonicalTimestamp::from(envelope.sent_at).to_string(),
+            &CanonicalTimestamp::from(now).to_string(),
+        )
+        .await?;
+    Ok(match first {
+        Some(first) => Verdict::Replayed(first),
+        None => Verdict::Fresh,
+    })
+}
+
+// Only rows for envelopes that would now be refused as expired are dropped. With no age limit
+// nothing is, since any of them could still be replayed.
+pub async fn prune_seen_messages(
+    storage: &RetasyncStorage,
+    max_age_secs: u64,
+    now: DateTime<Utc>,
+) -> anyhow::Result<u64> {
+    match oldest_accepted(max_age_secs, now) {
+        Some(oldest) => Ok(storage
+            .purge_seen_messages(&CanonicalTimestamp::from(oldest).to_string())
+            .await?),
+        None => Ok(0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{prune_seen_messages, screen_envelope, Verdict};
+    use crate::test_support::{scratch_sqlite, test_storage};
+    use chrono::{DateTime, Duration, Utc};
+    use retasync_contract::MeshCommandEnvelope;
+    use retasync_storage::CanonicalTimestamp;
+    use serde_json::{json, Value};
+    use uuid::Uuid;
+
+    const HOUR: u64 = 3600;
+    const PEER_A: &str = "a1b2c3d4e5f60718293a4b5c6d7e8f90";
+    const PEER_B: &str = "b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0";
+
+    fn delete_command(sent_at: DateTime<Utc>) -> MeshCommandEnvelope<Value> {
+        MeshCommandEnvelope {
+            message_id: Uuid::now_v7().to_string(),
+            operation: "emergency_action_message.delete".to_string(),
+            sent_at,
+            source_identity: PEER_A.parse().unwrap(),
+            destination_identity: "local-node".parse().unwrap(),
+            content_type: "application/msgpack".to_string(),
+            payload: json!({ "callsign": "ALPHA-1" }),
+            ttl_ms: None,
+            transport_hint: None,
+            trace: Vec::new(),
+            trace_truncated: false,
+            meta: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn replays_are_refused_after_a_restart() {
+        let path = scratch_sqlite();
+        let now = Utc::now();
+        let envelope = delete_command(now);
+        let storage = test_storage(&path).await;
+        assert_eq!(
+            screen_envelope(&storage, &envelope, HOUR, now).await.unwrap(),
+            Verdict::Fresh
+        );
+        drop(storage);
+
+        let restarted = test_storage(&path).await;
+        let later = now + Duration::minutes(5);
+        let Verdict::Replayed(first) =
+            screen_envelope(&restarted, &envelope, HOUR, later).await.unwrap()
+        else {
+            panic!("replay accepted after restart");
+        };
+        assert_eq!(first.received_at, CanonicalTimestamp::from(now).to_string());
+        // The same message id from another source is a different message.
+        let mut other = envelope.clone();
+        other.source_identity = PEER_B.parse().unwrap();
+        assert_eq!(
+            screen_envelope(&restarted, &other, HOUR, later).await.unwrap(),
+            Verdict::Fresh
+        );
+    }
+
+    #[tokio::test]
+    async fn pruned_envelopes_are_too_old_to_be_accepted() {
+        let storage = test_storage(&scratch_sqlite()).await;
+        let now = Utc::now();
+        let envelope = delete_command(now - Duration::minutes(50));
+        assert_eq!(
+            screen_envelope(&storage, &envelope, HOUR, now).await.unwrap(),
+            Verdict::Fresh
+        );
+
+        // Still inside the window: the row survives pruning and the replay is caught.
+        assert_eq!(prune_seen_messages(&storage, HOUR, now).await.unwrap(), 0);
+        let soon = now + Duration::minutes(5);
+        assert!(matches!(
+            screen_envelope(&storage, &envelope, HOUR, soon).await.unwrap(),
+            Verdict::Replayed(_)
+        ));
+
+        // Once the envelope has aged out its row goes, and age alone refuses the replay.
+        let later = now + Duration::minutes(15);
+        assert_eq!(prune_seen_messages(&storage, HOUR, later).await.unwrap(), 1);
+        assert_eq!(
+            screen_envelope(&storage, &envelope, HOUR, later).await.unwrap(),
+            Verdict::Expired
+        );
+        // Without an age limit nothing may be pruned.
+        assert_eq!(prune_seen_messages(&storage, 0, later).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn an_envelope_sent_at_the_cutoff_survives_pruning() {
+        let storage = test_storage(&scratch_sqlite()).await;
+        let now = Utc::now();
+        let envelope = delete_command(now - Duration::seconds(HOUR as i64));
+        assert_eq!(
+            screen_envelope(&storage, &envelope, HOUR, now).await.unwrap(),
+            Verdict::Fresh
+        );
+
+        assert_eq!(prune_seen_messages(&storage, HOUR, now).await.unwrap(), 0);
+        assert!(matches!(
+            screen_envelope(&storage, &envelope, HOUR, now).await.unwrap(),
+            Verdict::Replayed(_)
+        ));
+        let next = now + Duration::milliseconds(1);
+        assert_eq!(prune_seen_messages(&storage, HOUR, next).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn concurrent_duplicates_admit_exactly_one() {
+        let path = scratch_sqlite();
+        let first = test_storage(&path).await;
+        let second = test_storage(&path).await;
+        let now = Utc::now();
+        let envelope = delete_command(now);
+
+        let mut verdicts = Vec::new();
+        for _ in 0..4 {
+            let (a, b) = tokio::join!(
+                screen_envelope(&first, &envelope, HOUR, now),
+                screen_envelope(&second, &envelope, HOUR, now),
+            );
+            verdicts.push(a.unwrap());
+            verdicts.push(b.unwrap());
+        }
+        let fresh = verdicts.iter().filter(|verdict| **verdict == Verdict::Fresh).count();
+        assert_eq!(fresh, 1, "{verdicts:?}");
+    }
+}
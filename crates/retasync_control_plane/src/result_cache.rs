@@ -0,0 +1,251 @@
+﻿use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use retasync_contract::{canonical_digest, encode_canonical, MeshCommandEnvelope};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+#[cfg(feature = "entities")]
+use crate::AppState;
+
+pub const EVENT_LIST_OPERATION: &str = "event.list";
+pub const EMERGENCY_ACTION_MESSAGE_LIST_OPERATION: &str = "emergency_action_message.list";
+// What a handler answers in place of a result whose digest the sender already holds.
+pub const NOT_MODIFIED_STATUS: &str = "not_modified";
+// Which side answered a job, as its result digest records it.
+pub const RESULT_SOURCE_MESH: &str = "mesh";
+pub const RESULT_SOURCE_CACHE: &str = "cache";
+pub const DEFAULT_RESULT_CACHE_MAX_ENTRIES: usize = 256;
+pub const DEFAULT_RESULT_CACHE_MAX_RESULT_BYTES: usize = 16 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ResultCacheSettings {
+    // How long a result answers identical submissions without going to the mesh; 0 turns the
+    // cache off.
+    pub ttl_ms: u64,
+    pub max_entries: usize,
+    // Results larger than this, as canonical msgpack, are never cached and always go to the mesh.
+    pub max_result_bytes: usize,
+    // Only these operations are cached, so a create or delete is never answered locally.
+    pub operations: Vec<String>,
+}
+
+impl Default for ResultCacheSettings {
+    fn default() -> Self {
+        Self {
+            ttl_ms: 0,
+            max_entries: DEFAULT_RESULT_CACHE_MAX_ENTRIES,
+            max_result_bytes: DEFAULT_RESULT_CACHE_MAX_RESULT_BYTES,
+            operations: vec![
+                EVENT_LIST_OPERATION.to_string(),
+                EMERGENCY_ACTION_MESSAGE_LIST_OPERATION.to_string(),
+            ],
+        }
+    }
+}
+
+impl ResultCacheSettings {
+    pub fn caches(&self, operation: &str) -> bool {
+        self.ttl_ms > 0 && self.max_entries > 0 && self.operations.iter().any(|op| op == operation)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResultReuseMetrics {
+    // Submissions answered from the local cache.
+    pub cache_hits: u64,
+    // Results a peer did not resend because the submitter already held them.
+    pub not_modified: u64,
+    // Result bytes that did not cross the mesh because of either.
+    pub bytes_saved: u64,
+}
+
+// (operation, destination identity, canonical digest of the payload).
+pub type ResultKey = (String, String, String);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CachedResult {
+    pub result: Value,
+    pub digest: String,
+    pub bytes: u64,
+    stored_at: Instant,
+}
+
+// Recent results of the cached operations, answered again while fresh. Entries are kept in
+// memory only; a restart starts empty.
+#[derive(Default)]
+pub struct ResultCache {
+    entries: Mutex<BTreeMap<ResultKey, CachedResult>>,
+    metrics: Mutex<ResultReuseMetrics>,
+}
+
+pub fn result_key(operation: &str, destination: &str, payload: &Value) -> Option<ResultKey> {
+    let digest = canonical_digest(payload).ok()?;
+    Some((operation.to_string(), destination.to_string(), digest))
+}
+
+impl ResultCache {
+    pub fn lookup(&self, settings: &ResultCacheSettings, key: &ResultKey) -> Option<CachedResult> {
+        let mut entries = self.entries.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let entry = entries.get(key)?;
+        if entry.stored_at.elapsed() < Duration::from_millis(settings.ttl_ms) {
+            return Some(entry.clone());
+        }
+        entries.remove(key);
+        None
+    }
+
+    // Keeps `result` unless it is over the size limit, evicting the oldest entry when full.
+    pub fn store(&self, settings: &ResultCacheSettings, key: ResultKey, result: &Value) {
+        let Ok(encoded) = encode_canonical(result) else {
+            return;
+        };
+        if encoded.len() > settings.max_result_bytes {
+            return;
+        }
+        let Ok(digest) = canonical_digest(result) else {
+            return;
+        };
+        let mut entries = self.entries.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        entries.remove(&key);
+        while entries.len() >= settings.max_entries.max(1) {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.stored_at)
+                .map(|(key, _)| key.clone());
+            match oldest {
+                Some(oldest) => entries.remove(&oldest),
+                None => break,
+            };
+        }
+        entries.insert(
+            key,
+            CachedResult {
+                result: result.clone(),
+                digest,
+                bytes: encoded.len() as u64,
+                stored_at: Instant::now(),
+            },
+        );
+    }
+
+    pub fn note_hit(&self, bytes: u64) {
+        let mut metrics = self.metrics.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        metrics.cache_hits += 1;
+        metrics.bytes_saved += bytes;
+    }
+
+    pub fn note_not_modified(&self, bytes: u64) {
+        let mut metrics = self.metrics.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        metrics.not_modified += 1;
+        metrics.bytes_saved += bytes;
+    }
+
+    pub fn metrics(&self) -> ResultReuseMetrics {
+        self.metrics
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+}
+
+// The digest the submitter asked to compare against, if any.
+pub fn held_digest(envelope: &MeshCommandEnvelope<Value>) -> Option<&str> {
+    envelope
+        .meta
+        .as_ref()
+        .and_then(|meta| meta.if_result_digest_differs.as_deref())
+}
+
+// Swaps `result` for a `not_modified` answer when the sender already holds one with its digest.
+pub fn answer_conditionally(
+    envelope: &MeshCommandEnvelope<Value>,
+    result: Value,
+) -> anyhow::Result<Value> {
+    let Some(held) = held_digest(envelope) else {
+        return Ok(result);
+    };
+    let digest = canonical_digest(&result)?;
+    if digest != held {
+        return Ok(result);
+    }
+    Ok(json!({
+        "status": NOT_MODIFIED_STATUS,
+        "result_digest": digest,
+        "result_bytes": encode_canonical(&result)?.len(),
+    }))
+}
+
+// The digest and size of the result a `not_modified` answer stands for.
+pub fn not_modified_digest(payload: &Value) -> Option<(&str, u64)> {
+    if payload.get("status").and_then(Value::as_str) != Some(NOT_MODIFIED_STATUS) {
+        return None;
+    }
+    let digest = payload.get("result_digest").and_then(Value::as_str)?;
+    let bytes = payload.get("result_bytes").and_then(Value::as_u64).unwrap_or(0);
+    Some((digest, bytes))
+}
+
+// Built-in handler for `<entity_type>.list`: the type's live records in id order, narrowed to
+// ids starting with the payload's `prefix` when it has one. Unknown requesters are refused
+// before it runs, by the registry's authorization layer.
+#[cfg(feature = "entities")]
+pub async fn answer_entity_list(
+    state: &AppState,
+    envelope: &MeshCommandEnvelope<Value>,
+) -> anyhow::Result<Value> {
+    let entity_type = envelope
+        .operation
+        .strip_suffix(".list")
+        .ok_or_else(|| anyhow::anyhow!("{} is not a list operation", envelope.operation))?;
+    let prefix = envelope
+        .payload
+        .get("prefix")
+        .and_then(Value::as_str)
+        .unwrap_or_default();
+    let mut records = state.store.list_entities(entity_type, prefix).await?;
+    records.retain(|record| record.deleted_at.is_none());
+    let result = json!({ "entity_type": entity_type, "records": records });
+    answer_conditionally(envelope, result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn oversized_results_are_not_kept_and_the_oldest_entry_makes_room() {
+        let settings = ResultCacheSettings {
+            ttl_ms: 60_000,
+            max_entries: 2,
+            max_result_bytes: 64,
+            ..ResultCacheSettings::default()
+        };
+        let cache = ResultCache::default();
+        let key = |n: u64| result_key(EVENT_LIST_OPERATION, "peer", &json!({ "n": n })).unwrap();
+
+        cache.store(&settings, key(0), &json!({ "records": "x".repeat(100) }));
+        assert_eq!(cache.lookup(&settings, &key(0)), None);
+
+        for n in 1..=3 {
+            cache.store(&settings, key(n), &json!({ "records": [n] }));
+            std::thread::sleep(Duration::from_millis(2));
+        }
+        assert_eq!(cache.lookup(&settings, &key(1)), None);
+        let hit = cache.lookup(&settings, &key(3)).unwrap();
+        assert_eq!(hit.result, json!({ "records": [3] }));
+        assert_eq!(hit.digest, canonical_digest(&json!({ "records": [3] })).unwrap());
+        assert!(cache.lookup(&settings, &key(2)).is_some());
+
+        let expired = ResultCacheSettings {
+            ttl_ms: 1,
+            ..settings
+        };
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(cache.lookup(&expired, &key(3)), None);
+        assert!(!expired.caches("event.create"));
+        assert!(!ResultCacheSettings::default().caches(EVENT_LIST_OPERATION));
+    }
+}
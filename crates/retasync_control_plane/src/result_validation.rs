@@ -0,0 +1,138 @@
+﻿use retasync_contract::SchemaViolation;
+use retasync_storage::FEED_JOB_EVENT;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::warn;
+
+use crate::app::{publish, write_log};
+use crate::dependencies::settle_dependents;
+use crate::AppState;
+
+pub const INVALID_RESULT_REASON: &str = "invalid_result";
+pub const RESULT_NONCONFORMING_EVENT: &str = "job.result.nonconforming";
+
+// What happens to a result that breaks its operation's result schema. Results for operations
+// without one are never checked.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResultPolicy {
+    // Log the violations and complete the job as usual.
+    #[default]
+    Accept,
+    // Complete the job, mark the result nonconforming and warn subscribers.
+    Flag,
+    // Fail the job with `invalid_result` and keep the raw result in quarantine.
+    Quarantine,
+}
+
+impl ResultPolicy {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Accept => "accept",
+            Self::Flag => "flag",
+            Self::Quarantine => "quarantine",
+        }
+    }
+}
+
+// The `[result_validation]` section.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ResultValidationSettings {
+    pub policy: ResultPolicy,
+}
+
+// A received result after it was checked against the contract.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Screened {
+    Conforming(Value),
+    // Kept under `accept` or `flag`; not worth caching for the next identical submission.
+    Nonconforming(Value),
+    // The job has already failed; there is nothing left to complete.
+    Quarantined,
+}
+
+// Checks a transformed result against the result schema the contract declares for `operation`
+// and applies the configured policy to one that does not conform.
+pub(crate) async fn screen_result(
+    state: &AppState,
+    job_id: &str,
+    operation: &str,
+    result: Value,
+) -> anyhow::Result<Screened> {
+    let contract = state.contract.current();
+    let Some(schema) = contract.registry.result_schema(operation) else {
+        return Ok(Screened::Conforming(result));
+    };
+    let violations = schema.validate(&result);
+    if violations.is_empty() {
+        return Ok(Screened::Conforming(result));
+    }
+    let schema_name = schema.name().to_string();
+    drop(contract);
+
+    let policy = state.node_config.read().await.result_validation.policy;
+    let summary = summarize(&schema_name, &violations);
+    let errors = serde_json::to_value(&violations)?;
+    match policy {
+        ResultPolicy::Accept => {
+            warn!(job_id, operation, %summary, "nonconforming result accepted");
+            write_log(state, "warn", &format!("job {job_id} result accepted: {summary}")).await;
+            Ok(Screened::Nonconforming(result))
+        }
+        ResultPolicy::Flag => {
+            let flagged = json!({
+                "job_id": job_id,
+                "operation": operation,
+                "schema": schema_name,
+                "errors": errors,
+            });
+            state
+                .storage
+                .with_event(RESULT_NONCONFORMING_EVENT, flagged)
+                .record_job_result_check(job_id, &schema_name, policy.as_str(), &errors)
+                .await?;
+            publish(state).await;
+            write_log(state, "warn", &format!("job {job_id} result flagged: {summary}")).await;
+            Ok(Screened::Nonconforming(result))
+        }
+        ResultPolicy::Quarantine => {
+            let raw = serde_json::to_vec(&result)?;
+            state.storage.quarantine_job_result(job_id, &raw, &summary).await?;
+            state
+                .storage
+                .record_job_result_check(job_id, &schema_name, policy.as_str(), &errors)
+                .await?;
+            state
+                .storage
+                .with_event(
+                    FEED_JOB_EVENT,
+                    json!({
+                        "job_id": job_id,
+                        "status": "failed",
+                        "reason": INVALID_RESULT_REASON,
+                        "schema": schema_name,
+                        "errors": errors,
+                    }),
+                )
+                .fail_job(job_id, &summary)
+                .await?;
+            publish(state).await;
+            write_log(state, "error", &format!("job {job_id} failed: {summary}")).await;
+            settle_dependents(state, job_id).await;
+            Ok(Screened::Quarantined)
+        }
+    }
+}
+
+// `invalid_result against EventCreateResult: /uid expected string, got integer; ...`
+fn summarize(schema_name: &str, violations: &[SchemaViolation]) -> String {
+    let details: Vec<String> = violations
+        .iter()
+        .map(|violation| match violation.pointer.as_str() {
+            "" => violation.message.clone(),
+            pointer => format!("{pointer} {}", violation.message),
+        })
+        .collect();
+    format!("{INVALID_RESULT_REASON} against {schema_name}: {}", details.join("; "))
+}
@@ -0,0 +1,421 @@
+﻿use std::time::Duration;
+
+use anyhow::Context;
+use chrono::Utc;
+use retasync_contract::{
+    MeshEventEnvelope, MeshResultEnvelope, PartialResult, PartialResultSequence,
+    DELAYED_RESULT_EVENT, DELIVERY_RECEIPT_EVENT, PARTIAL_RESULT_EVENT,
+};
+use retasync_storage::{JobResultPart, PayloadTable, FEED_JOB_EVENT};
+use serde_json::{json, Value};
+use tokio::task::JoinHandle;
+use tracing::{error, warn};
+
+use crate::app::{complete_job, fail_transformed_job, publish, transform_payload};
+use crate::clock::observe_event;
+use crate::dependencies::{is_terminal, settle_dependents};
+use crate::escalation::{
+    check_transit_expiry, record_escalation, EscalationRecord, EscalationStep,
+};
+use crate::liveness::record_contact;
+use crate::migrations::migrate_inbound;
+use crate::receipts::ingest_receipt;
+use crate::result_validation::{screen_result, Screened};
+use crate::transforms::TransformStage;
+#[cfg(feature = "webhooks")]
+use crate::webhooks;
+use crate::AppState;
+
+const EVENT_BATCH: usize = 64;
+
+pub fn spawn_result_ingest(state: AppState, period: Duration) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(period);
+        loop {
+            ticker.tick().await;
+            if let Err(err) = ingest_events(&state).await {
+                error!(error = %err, "mesh event poll failed");
+            }
+            if let Err(err) = check_stream_timeouts(&state).await {
+                error!(error = %err, "result stream timeout check failed");
+            }
+            if let Err(err) = check_transit_expiry(&state).await {
+                error!(error = %err, "store-and-forward expiry check failed");
+            }
+        }
+    })
+}
+
+pub async fn ingest_events(state: &AppState) -> anyhow::Result<usize> {
+    let events = state.bridge.poll_events(EVENT_BATCH).await?;
+    let count = events.len();
+    let limits = state.node_config.read().await.codec_limits;
+    for envelope in events {
+        let message_id = envelope.message_id.clone();
+        if let Err(err) = limits.check_value(&envelope.payload) {
+            warn!(message_id = %message_id, error = %err, "mesh event rejected");
+            continue;
+        }
+        if let Err(err) = ingest_event(state, envelope).await {
+            warn!(message_id = %message_id, error = %err, "mesh event dropped");
+        }
+    }
+    Ok(count)
+}
+
+async fn ingest_event(state: &AppState, envelope: MeshEventEnvelope<Value>) -> anyhow::Result<()> {
+    let received_at = Utc::now();
+    record_contact(state, &envelope.source_identity, received_at).await;
+    observe_event(
+        state,
+        &envelope.source_identity,
+        envelope.sent_at,
+        received_at,
+    )
+    .await;
+    apply_event(state, envelope).await
+}
+
+// Delayed and partial results go to their jobs, anything else to the event cache and the
+// webhook subscriptions it matches. Events a courier bundle carried come in here directly,
+// since their timing says nothing of the peer.
+pub(crate) async fn apply_event(
+    state: &AppState,
+    mut envelope: MeshEventEnvelope<Value>,
+) -> anyhow::Result<()> {
+    if envelope.event == DELAYED_RESULT_EVENT {
+        let result: MeshResultEnvelope<Value> =
+            serde_json::from_value(envelope.payload).context("decode delayed result payload")?;
+        return ingest_delayed_result(state, result).await;
+    }
+    if envelope.event == DELIVERY_RECEIPT_EVENT {
+        return ingest_receipt(state, envelope).await;
+    }
+    if envelope.event != PARTIAL_RESULT_EVENT {
+        envelope.payload = migrate_inbound(
+            state,
+            &envelope.source_identity,
+            PayloadTable::CachedEvents,
+            &envelope.event,
+            envelope.payload,
+        );
+        #[cfg(feature = "webhooks")]
+        webhooks::cache_event(state, &envelope).await?;
+        #[cfg(not(feature = "webhooks"))]
+        state
+            .storage
+            .cache_event(
+                &envelope.message_id,
+                &envelope.event,
+                envelope.source_identity.as_str(),
+                envelope.sent_at,
+                &serde_json::to_value(&envelope)?,
+            )
+            .await?;
+        return Ok(());
+    }
+    let part: PartialResult =
+        serde_json::from_value(envelope.payload).context("decode partial result payload")?;
+    ingest_partial_result(state, part).await
+}
+
+pub async fn ingest_partial_result(state: &AppState, part: PartialResult) -> anyhow::Result<()> {
+    let Some(job_id) = state.storage.job_for_message(&part.correlation_id).await? else {
+        warn!(correlation_id = %part.correlation_id, "partial result for unknown command");
+        return Ok(());
+    };
+    let Some(job) = state.storage.get_job(&job_id).await? else {
+        return Ok(());
+    };
+    if is_terminal(&job.status) {
+        warn!(job_id = %job_id, status = %job.status, "partial result for finished job ignored");
+        return Ok(());
+    }
+
+    let (sequence_no, is_final) = (part.sequence, part.is_final);
+    let mut sequence = sequence_of(&state.storage.list_job_result_parts(&job_id).await?);
+    let data = part.data.clone();
+    if let Err(err) = sequence.insert(part) {
+        warn!(job_id = %job_id, error = %err, "partial result rejected");
+        return Ok(());
+    }
+    let received = json!({ "job_id": job_id, "sequence": sequence_no, "final": is_final });
+    if !state
+        .storage
+        .with_event("job.result.partial", received)
+        .insert_job_result_part(&job_id, sequence_no, is_final, &data)
+        .await?
+    {
+        return Ok(());
+    }
+
+    if job.status != "streaming" {
+        mark_streaming(state, &job_id).await?;
+    }
+    publish(state).await;
+
+    if sequence.is_complete() {
+        let assembled = sequence.assemble()?;
+        match transform_payload(
+            state,
+            &job_id,
+            TransformStage::Result,
+            &job.operation,
+            assembled,
+        )
+        .await?
+        {
+            Ok(result) => match screen_result(state, &job_id, &job.operation, result).await? {
+                Screened::Conforming(result) | Screened::Nonconforming(result) => {
+                    complete_job(state, &job_id, Some(result)).await?
+                }
+                Screened::Quarantined => {}
+            },
+            Err(failure) => fail_transformed_job(state, &job_id, &failure).await?,
+        }
+    }
+    Ok(())
+}
+
+// A result that store-and-forward carried back after the send itself returned.
+pub async fn ingest_delayed_result(
+    state: &AppState,
+    result: MeshResultEnvelope<Value>,
+) -> anyhow::Result<()> {
+    let Some(job_id) = state
+        .storage
+        .job_for_message(&result.correlation_id)
+        .await?
+    else {
+        warn!(correlation_id = %result.correlation_id, "delayed result for unknown command");
+        return Ok(());
+    };
+    let Some(job) = state.storage.get_job(&job_id).await? else {
+        return Ok(());
+    };
+    if is_terminal(&job.status) {
+        warn!(job_id = %job_id, status = %job.status, "delayed result for finished job ignored");
+        return Ok(());
+    }
+    if job.status == "in_transit" {
+        record_escalation(
+            state,
+            &job_id,
+            EscalationRecord::new(EscalationStep::Delivered),
+        )
+        .await?;
+    }
+    match transform_payload(
+        state,
+        &job_id,
+        TransformStage::Result,
+        &job.operation,
+        result.payload,
+    )
+    .await?
+    {
+        Ok(payload) => match screen_result(state, &job_id, &job.operation, payload).await? {
+            Screened::Conforming(payload) | Screened::Nonconforming(payload) => {
+                complete_job(state, &job_id, Some(payload)).await
+            }
+            Screened::Quarantined => Ok(()),
+        },
+        Err(failure) => fail_transformed_job(state, &job_id, &failure).await,
+    }
+}
+pub async fn check_stream_timeouts(state: &AppState) -> anyhow::Result<usize> {
+    let timeout = state.node_config.read().await.result_stream_timeout_secs;
+    let cutoff = (Utc::now() - chrono::Duration::seconds(timeout as i64)).to_rfc3339();
+    let stalled = state.storage.list_stalled_streams(&cutoff).await?;
+    for job_id in &stalled {
+        let parts = state.storage.list_job_result_parts(job_id).await?;
+        state
+            .storage
+            .with_event(
+                FEED_JOB_EVENT,
+                json!({
+                    "job_id": job_id,
+                    "status": "failed",
+                    "reason": "incomplete_stream",
+                    "missing": missing_sequences(&parts),
+                }),
+            )
+            .fail_job(job_id, "incomplete_stream")
+            .await?;
+        warn!(job_id = %job_id, received = parts.len(), "result stream timed out");
+        publish(state).await;
+        settle_dependents(state, job_id).await;
+    }
+    Ok(stalled.len())
+}
+
+pub(crate) fn is_streaming(payload: &Value) -> bool {
+    payload
+        .get("streaming")
+        .and_then(Value::as_bool)
+        .unwrap_or(false)
+}
+
+pub(crate) async fn mark_streaming(state: &AppState, job_id: &str) -> anyhow::Result<()> {
+    state
+        .storage
+        .with_event(
+            FEED_JOB_EVENT,
+            json!({ "job_id": job_id, "status": "streaming" }),
+        )
+        .update_job_status(job_id, "streaming", None)
+        .await?;
+    publish(state).await;
+    Ok(())
+}
+
+pub(crate) fn missing_sequences(parts: &[JobResultPart]) -> Vec<u32> {
+    sequence_of(parts).missing()
+}
+
+fn sequence_of(parts: &[JobResultPart]) -> PartialResultSequence {
+    let mut sequence = PartialResultSequence::new();
+    for part in parts {
+        // Stored parts were validated against the sequence when they arrived.
+        let _ = sequence.insert(PartialResult {
+            correlation_id: String::new(),
+            sequence: part.sequence,
+            is_final: part.is_final,
+            data: part.data.clone(),
+        });
+    }
+    sequence
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_stream_timeouts, ingest_events};
+    use crate::test_support::node_state;
+    use crate::AppState;
+    use chrono::Utc;
+    use retasync_contract::{MeshEventEnvelope, PARTIAL_RESULT_EVENT};
+    use retasync_mesh_bridge::InMemoryRpcMeshBridge;
+
+    use serde_json::{json, Value};
+    use std::sync::Arc;
+    use uuid::Uuid;
+
+    async fn streaming_job(state: &AppState) -> (String, String) {
+        let job = state
+            .storage
+            .create_job("event.list", json!({}))
+            .await
+            .unwrap();
+        let message_id = Uuid::now_v7().to_string();
+        state
+            .storage
+            .record_job_message(&message_id, &job.job_id)
+            .await
+            .unwrap();
+        (job.job_id, message_id)
+    }
+
+    fn partial(
+        correlation_id: &str,
+        sequence: u32,
+        is_final: bool,
+        items: Value,
+    ) -> MeshEventEnvelope<Value> {
+        MeshEventEnvelope {
+            message_id: Uuid::now_v7().to_string(),
+            event: PARTIAL_RESULT_EVENT.to_string(),
+            sent_at: Utc::now(),
+            source_identity: "a1b2c3d4e5f60718293a4b5c6d7e8f90".parse().unwrap(),
+            destination_identity: "local-node".parse().unwrap(),
+            content_type: "application/msgpack".to_string(),
+            payload: json!({
+                "correlation_id": correlation_id,
+                "sequence": sequence,
+                "final": is_final,
+                "data": { "items": items },
+            }),
+            ttl_ms: None,
+            transport_hint: None,
+        }
+    }
+
+    async fn job_status(state: &AppState, job_id: &str) -> (String, Option<String>) {
+        let job = state.storage.get_job(job_id).await.unwrap().unwrap();
+        (job.status, job.failure_reason)
+    }
+
+    async fn assembled(state: &AppState, job_id: &str) -> Value {
+        let result = state.storage.get_job_result(job_id).await.unwrap().unwrap();
+        serde_json::from_str(&result.result_json).unwrap()
+    }
+
+    #[tokio::test]
+    async fn in_order_parts_assemble_into_result() {
+        let bridge = Arc::new(InMemoryRpcMeshBridge::new(true, true));
+        let state = node_state(bridge.clone(), json!({})).await;
+        let mut events = state.sse_bus.subscribe();
+        let (job_id, message_id) = streaming_job(&state).await;
+
+        bridge.inject_event(partial(&message_id, 0, false, json!(["a", "b"])));
+        bridge.inject_event(partial(&message_id, 1, false, json!(["c"])));
+        assert_eq!(ingest_events(&state).await.unwrap(), 2);
+        assert_eq!(job_status(&state, &job_id).await.0, "streaming");
+
+        bridge.inject_event(partial(&message_id, 2, true, json!(["d"])));
+        ingest_events(&state).await.unwrap();
+        assert_eq!(job_status(&state, &job_id).await.0, "success");
+        assert_eq!(
+            assembled(&state, &job_id).await,
+            json!({ "items": ["a", "b", "c", "d"] })
+        );
+
+        let partial_events = std::iter::from_fn(|| events.try_recv().ok())
+            .filter(|event| event.event_type == "job.result.partial")
+            .count();
+        assert_eq!(partial_events, 3);
+    }
+
+    #[tokio::test]
+    async fn out_of_order_and_duplicate_parts_assemble_once() {
+        let bridge = Arc::new(InMemoryRpcMeshBridge::new(true, true));
+        let state = node_state(bridge.clone(), json!({})).await;
+        let (job_id, message_id) = streaming_job(&state).await;
+
+        bridge.inject_event(partial(&message_id, 2, true, json!([5])));
+        bridge.inject_event(partial(&message_id, 0, false, json!([1, 2])));
+        bridge.inject_event(partial(&message_id, 0, false, json!([1, 2])));
+        ingest_events(&state).await.unwrap();
+        assert_eq!(job_status(&state, &job_id).await.0, "streaming");
+        assert_eq!(state.storage.list_job_result_parts(&job_id).await.unwrap().len(), 2);
+
+        bridge.inject_event(partial(&message_id, 1, false, json!([3, 4])));
+        ingest_events(&state).await.unwrap();
+        assert_eq!(job_status(&state, &job_id).await.0, "success");
+        assert_eq!(assembled(&state, &job_id).await, json!({ "items": [1, 2, 3, 4, 5] }));
+    }
+
+    #[tokio::test]
+    async fn missing_part_times_out_as_incomplete_stream() {
+        let bridge = Arc::new(InMemoryRpcMeshBridge::new(true, true));
+        let state = node_state(bridge.clone(), json!({})).await;
+        let (job_id, message_id) = streaming_job(&state).await;
+
+        bridge.inject_event(partial(&message_id, 0, false, json!([1])));
+        bridge.inject_event(partial(&message_id, 2, true, json!([3])));
+        ingest_events(&state).await.unwrap();
+        assert_eq!(check_stream_timeouts(&state).await.unwrap(), 0);
+
+        state.node_config.write().await.result_stream_timeout_secs = 0;
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        assert_eq!(check_stream_timeouts(&state).await.unwrap(), 1);
+        assert_eq!(
+            job_status(&state, &job_id).await,
+            ("failed".to_string(), Some("incomplete_stream".to_string()))
+        );
+        assert!(state.storage.get_job_result(&job_id).await.unwrap().is_none());
+
+        bridge.inject_event(partial(&message_id, 1, false, json!([2])));
+        ingest_events(&state).await.unwrap();
+        assert_eq!(job_status(&state, &job_id).await.0, "failed");
+    }
+}
@@ -0,0 +1,143 @@
+﻿use std::future::Future;
+use std::time::{Duration, SystemTime};
+
+use chrono::Utc;
+use retasync_contract::{set_identity_validation, IdentityValidation};
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use crate::circuit::{release_deferred, spawn_circuit_probes};
+use crate::config_apply::{self, spawn_config_apply_expiry};
+use crate::crash::ingest_crash_reports;
+use crate::fleet::spawn_fleet_reporter;
+use crate::health::spawn_health_sampler;
+use crate::inbound::spawn_inbound_worker;
+use crate::liveness::load_contacts;
+use crate::migrations::migrate_on_startup;
+use crate::mute::{self, spawn_mute_expiry};
+use crate::notifier::spawn_event_notifier;
+use crate::results::spawn_result_ingest;
+use crate::spool::sweep_orphans;
+use crate::submission_spool::spawn_submission_spool_drain;
+use crate::watchdog::{
+    recover_orphaned_jobs, spawn_allowlist_expiry, spawn_integrity_check, spawn_job_watchdog,
+    spawn_retention,
+};
+#[cfg(feature = "transfers")]
+use crate::watchdog::spawn_transfer_watchdog;
+#[cfg(feature = "webhooks")]
+use crate::webhooks::spawn_webhook_dispatcher;
+use crate::app::write_log;
+use crate::AppState;
+
+pub const DEFAULT_HEALTH_SAMPLE_INTERVAL: Duration = Duration::from_secs(30);
+
+// The background work a node needs besides its router: mesh pollers, watchdogs, expiry and
+// retention. retasyncd starts it next to its listeners; an embedding host starts it next to its
+// own server and decides when it stops.
+pub struct ControlPlaneRuntime {
+    state: AppState,
+    health_sample_interval: Duration,
+}
+
+impl ControlPlaneRuntime {
+    pub fn new(state: AppState) -> Self {
+        Self {
+            state,
+            health_sample_interval: DEFAULT_HEALTH_SAMPLE_INTERVAL,
+        }
+    }
+
+    pub fn health_sample_interval(mut self, interval: Duration) -> Self {
+        self.health_sample_interval = interval.max(Duration::from_secs(1));
+        self
+    }
+
+    // Applies the configured identity validation, restores stored feature flags, mute state, peer
+    // contacts and staged config (reverting an unconfirmed apply), migrates stored payloads to
+    // the current contract, loads the crash reports and recovers the jobs a previous run left
+    // behind, releases the jobs it deferred behind open circuits (every circuit starts closed)
+    // and clears its upload spool files, then spawns every worker. Once `shutdown` resolves the
+    // workers are aborted; the returned handle finishes when they have stopped.
+    pub async fn start<F>(self, shutdown: F) -> anyhow::Result<JoinHandle<()>>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let state = self.state;
+        let validation = state.node_config.read().await.identity_validation;
+        set_identity_validation(validation);
+        if validation == IdentityValidation::Lenient {
+            let message = "identity_validation = \"lenient\" is deprecated; \
+                           non-hash identities will be refused in a future release";
+            warn!("{message}");
+            write_log(&state, "warn", message).await;
+        }
+        state
+            .features
+            .load(&state.storage, &*state.node_config.read().await)
+            .await?;
+        mute::load(&state, Utc::now()).await?;
+        load_contacts(&state).await?;
+        config_apply::load(&state).await?;
+        migrate_on_startup(&state).await?;
+        let crash = ingest_crash_reports(&state).await?;
+        let crash_id = crash.as_ref().map(|crash| crash.crash_id.as_str());
+        let requeued = recover_orphaned_jobs(&state, crash_id).await?;
+        if requeued > 0 {
+            info!(requeued, "requeued jobs orphaned by the previous run");
+        }
+        let unrepaired = state.storage.identity_hash_issues().await?;
+        if !unrepaired.is_empty() {
+            warn!(
+                count = unrepaired.len(),
+                "stored identities are not identity hashes; see the identity_hashes status check"
+            );
+        }
+        let released = release_deferred(&state, None, None).await?;
+        if released > 0 {
+            info!(released, "released jobs deferred behind open circuits");
+        }
+        let spool = state.node_config.read().await.transfer_spool.clone();
+        let spool_dir = spool.dir_for(state.storage.database_path());
+        let grace = Duration::from_secs(spool.orphan_grace_secs);
+        match sweep_orphans(&spool_dir, grace, SystemTime::now()).await {
+            Ok(0) => {}
+            Ok(removed) => {
+                info!(removed, dir = %spool_dir.display(), "removed orphaned spool files")
+            }
+            Err(err) => warn!(error = %err, dir = %spool_dir.display(), "spool sweep failed"),
+        }
+        let lease_interval = state.node_config.read().await.job_watchdog.lease_interval();
+        let drain_interval = state.node_config.read().await.submission_spool.drain_interval();
+
+        let workers = vec![
+            #[cfg(feature = "transfers")]
+            spawn_transfer_watchdog(state.clone(), Duration::from_secs(15)),
+            spawn_allowlist_expiry(state.clone(), Duration::from_secs(30)),
+            spawn_job_watchdog(state.clone(), lease_interval),
+            spawn_mute_expiry(state.clone(), Duration::from_secs(1)),
+            spawn_config_apply_expiry(state.clone(), Duration::from_secs(1)),
+            spawn_circuit_probes(state.clone(), Duration::from_secs(1)),
+            spawn_event_notifier(state.clone(), Duration::from_secs(1)),
+            spawn_inbound_worker(state.clone(), Duration::from_millis(250)),
+            spawn_submission_spool_drain(state.clone(), drain_interval),
+            spawn_result_ingest(state.clone(), Duration::from_secs(1)),
+            spawn_health_sampler(state.clone(), self.health_sample_interval),
+            spawn_fleet_reporter(state.clone(), Duration::from_secs(1)),
+            #[cfg(feature = "webhooks")]
+            spawn_webhook_dispatcher(state.clone(), Duration::from_millis(500)),
+            spawn_retention(state.clone(), Duration::from_secs(300)),
+            spawn_integrity_check(state, Duration::from_secs(600)),
+        ];
+        Ok(tokio::spawn(async move {
+            shutdown.await;
+            info!("control plane runtime stopping");
+            for worker in &workers {
+                worker.abort();
+            }
+            for worker in workers {
+                let _ = worker.await;
+            }
+        }))
+    }
+}
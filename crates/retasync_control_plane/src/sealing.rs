@@ -0,0 +1,202 @@
+﻿use std::path::{Path, PathBuf};
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use retasync_contract::{
+    open_payload, seal_payload, sealing_public_key, IdentityHash, MeshCommandEnvelope,
+    CONTENT_TYPE_MSGPACK, CONTENT_TYPE_SEALED, SEALED_KEY_LEN,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::warn;
+
+use crate::app::{emit, event_type_matches, publish};
+use crate::sneakernet::load_secret;
+use crate::AppState;
+
+pub const DECRYPT_FAILED_EVENT: &str = "security.decrypt_failed";
+pub const SEALING_KEY_REGISTERED_EVENT: &str = "security.sealing_key.registered";
+// A listed operation to a destination with no registered key is failed rather than sent plain.
+pub const SEALING_KEY_MISSING_ERROR: &str = "sealing_key_missing";
+pub const SEALED_SENDER_UNKNOWN_ERROR: &str = "sealed_sender_unknown";
+const KEY_EXTENSION: &str = "identity.key";
+
+// The `[security]` section: which outgoing command payloads are sealed end to end.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SecuritySettings {
+    // Operations sealed for their destination; a trailing `*` matches a prefix.
+    pub encrypt_operations: Vec<String>,
+    // X25519 secret, raw or base64. Defaults to `<database>.identity.key` beside the database
+    // file, generated the first time it is needed.
+    pub identity_key_path: Option<String>,
+    // Offer our public key in `node.hello` and register the one an allowlisted peer offers when
+    // its entry has none yet.
+    pub exchange_keys: bool,
+}
+
+impl SecuritySettings {
+    pub fn seals(&self, operation: &str) -> bool {
+        self.encrypt_operations
+            .iter()
+            .any(|pattern| event_type_matches(pattern, operation))
+    }
+
+    pub fn key_path_for(&self, database_path: &Path) -> PathBuf {
+        match &self.identity_key_path {
+            Some(path) => PathBuf::from(path),
+            None => {
+                let mut path = database_path.as_os_str().to_owned();
+                path.push(".");
+                path.push(KEY_EXTENSION);
+                PathBuf::from(path)
+            }
+        }
+    }
+}
+
+// What an outgoing command payload becomes.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outgoing {
+    Plain,
+    Sealed(Value),
+    // The operation is sealed but the destination has no registered key.
+    NoKey,
+}
+
+pub fn parse_sealing_key(encoded: &str) -> Result<[u8; SEALED_KEY_LEN], String> {
+    let bytes = STANDARD
+        .decode(encoded.trim())
+        .map_err(|_| "public key is not base64".to_string())?;
+    bytes
+        .try_into()
+        .map_err(|_| format!("public key must be {SEALED_KEY_LEN} bytes"))
+}
+
+async fn identity_secret(state: &AppState) -> anyhow::Result<[u8; SEALED_KEY_LEN]> {
+    let path = {
+        let config = state.node_config.read().await;
+        config.security.key_path_for(state.storage.database_path())
+    };
+    load_secret(&path)
+        .map_err(|detail| anyhow::anyhow!("identity key {}: {detail}", path.display()))
+}
+
+// This node's base64 X25519 public key, for peers to register.
+pub async fn local_sealing_key(state: &AppState) -> anyhow::Result<String> {
+    Ok(STANDARD.encode(sealing_public_key(&identity_secret(state).await?)))
+}
+
+async fn registered_key(
+    state: &AppState,
+    identity: &str,
+) -> anyhow::Result<Option<[u8; SEALED_KEY_LEN]>> {
+    let Some(entry) = state
+        .storage
+        .get_allowlist_entry(&IdentityHash::lenient(identity))
+        .await?
+    else {
+        return Ok(None);
+    };
+    // Keys are checked when registered, so one that no longer parses counts as none.
+    Ok(entry
+        .public_key
+        .and_then(|key| parse_sealing_key(&key).ok()))
+}
+
+pub async fn seal_outgoing(
+    state: &AppState,
+    settings: &SecuritySettings,
+    operation: &str,
+    destination: &str,
+    payload: &Value,
+) -> anyhow::Result<Outgoing> {
+    if !settings.seals(operation) {
+        return Ok(Outgoing::Plain);
+    }
+    let Some(recipient) = registered_key(state, destination).await? else {
+        return Ok(Outgoing::NoKey);
+    };
+    let secret = identity_secret(state).await?;
+    Ok(Outgoing::Sealed(seal_payload(payload, &secret, &recipient)?))
+}
+
+// Opens a sealed command in place. A sender with no registered key, or a payload that fails to
+// open, is reported as `security.decrypt_failed` and comes back as the error to answer with.
+pub async fn open_command(
+    state: &AppState,
+    envelope: &mut MeshCommandEnvelope<Value>,
+) -> anyhow::Result<Result<(), &'static str>> {
+    let opened = match registered_key(state, &envelope.source_identity).await? {
+        None => Err(SEALED_SENDER_UNKNOWN_ERROR),
+        Some(sender) => {
+            let secret = identity_secret(state).await?;
+            let limits = state.node_config.read().await.codec_limits;
+            open_payload(&envelope.payload, &secret, &sender, &limits).map_err(|err| err.code())
+        }
+    };
+    match opened {
+        Ok(payload) => {
+            envelope.payload = payload;
+            envelope.content_type = CONTENT_TYPE_MSGPACK.to_string();
+            Ok(Ok(()))
+        }
+        Err(reason) => {
+            warn!(
+                source_identity = %envelope.source_identity,
+                operation = %envelope.operation,
+                reason,
+                "sealed command refused"
+            );
+            emit(
+                state,
+                DECRYPT_FAILED_EVENT,
+                json!({
+                    "message_id": envelope.message_id,
+                    "operation": envelope.operation,
+                    "source_identity": envelope.source_identity,
+                    "reason": reason,
+                }),
+            )
+            .await;
+            Ok(Err(reason))
+        }
+    }
+}
+
+pub fn is_sealed(envelope: &MeshCommandEnvelope<Value>) -> bool {
+    envelope.content_type == CONTENT_TYPE_SEALED
+}
+
+// Registers the key a peer offered in its `node.hello`. Only allowlisted peers without a key are
+// filled in; a different key offered later is left for an admin to review.
+pub async fn register_offered_key(state: &AppState, identity: &str, offered: &str) {
+    if parse_sealing_key(offered).is_err() {
+        warn!(identity, "peer offered a malformed sealing key");
+        return;
+    }
+    let hash = IdentityHash::lenient(identity);
+    let entry = match state.storage.get_allowlist_entry(&hash).await {
+        Ok(Some(entry)) => entry,
+        Ok(None) => return,
+        Err(err) => {
+            warn!(identity, error = %err, "sealing key lookup failed");
+            return;
+        }
+    };
+    match entry.public_key.as_deref() {
+        Some(registered) if registered == offered.trim() => {}
+        Some(_) => warn!(identity, "peer offered a sealing key other than its registered one"),
+        None => match state
+            .storage
+            .with_event(
+                SEALING_KEY_REGISTERED_EVENT,
+                json!({ "identity_hash": identity, "source": "handshake" }),
+            )
+            .set_allowlist_public_key(&hash, Some(offered.trim()))
+            .await
+        {
+            Ok(_) => publish(state).await,
+            Err(err) => warn!(identity, error = %err, "sealing key not registered"),
+        },
+    }
+}
@@ -0,0 +1,390 @@
+﻿use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use serde::ser::{SerializeMap, SerializeStruct, Serializer};
+use serde::Serialize;
+
+pub const UNKNOWN_FIELD_ERROR: &str = "unknown_field";
+
+// What a client asked to see of a response: `?fields=` keeps only the named top-level fields and
+// `?omit_payload=true` leaves payload bodies out, keeping their sizes and digests.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Shape {
+    fields: Option<Arc<BTreeSet<String>>>,
+    omit_payload: bool,
+}
+
+impl Shape {
+    // Refuses a field that is not in `known` by returning it.
+    pub fn parse(
+        fields: Option<&str>,
+        omit_payload: Option<bool>,
+        known: &[&str],
+    ) -> Result<Self, String> {
+        let fields = match fields {
+            Some(fields) => {
+                let mut selected = BTreeSet::new();
+                for field in fields.split(',').map(str::trim).filter(|field| !field.is_empty()) {
+                    if !known.contains(&field) {
+                        return Err(field.to_string());
+                    }
+                    selected.insert(field.to_string());
+                }
+                (!selected.is_empty()).then(|| Arc::new(selected))
+            }
+            None => None,
+        };
+        Ok(Self {
+            fields,
+            omit_payload: omit_payload.unwrap_or(false),
+        })
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.fields.is_none() && !self.omit_payload
+    }
+
+    pub fn includes(&self, field: &str) -> bool {
+        self.fields
+            .as_ref()
+            .is_none_or(|fields| fields.contains(field))
+    }
+
+    // Whether the payload column has to be read at all.
+    pub fn wants_payload(&self, field: &str) -> bool {
+        !self.omit_payload && self.includes(field)
+    }
+
+    // Names the shape in the ETag of a shaped response, so it never matches the full one's.
+    pub fn etag_key(&self) -> String {
+        let fields = self
+            .fields
+            .as_ref()
+            .map(|fields| fields.iter().cloned().collect::<Vec<_>>().join(","))
+            .unwrap_or_default();
+        format!("fields={fields};omit_payload={}", self.omit_payload)
+    }
+
+    pub fn apply<T>(&self, value: T) -> Shaped<T> {
+        Shaped {
+            value,
+            fields: self.fields.clone(),
+        }
+    }
+}
+
+// A value that serializes with only its shape's top-level fields; nested values stay whole.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Shaped<T> {
+    value: T,
+    fields: Option<Arc<BTreeSet<String>>>,
+}
+
+impl<T> Shaped<T> {
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+impl<T: Serialize> Serialize for Shaped<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match &self.fields {
+            None => self.value.serialize(serializer),
+            Some(fields) => self.value.serialize(FieldFilter {
+                inner: serializer,
+                fields,
+            }),
+        }
+    }
+}
+
+// Passes everything through but the fields of the outermost struct or map, which it drops
+// unless selected. Structs with flattened members serialize as maps, hence both.
+struct FieldFilter<'a, S> {
+    inner: S,
+    fields: &'a BTreeSet<String>,
+}
+
+struct FilteredStruct<'a, S> {
+    inner: S,
+    fields: &'a BTreeSet<String>,
+}
+
+struct FilteredMap<'a, S> {
+    inner: S,
+    fields: &'a BTreeSet<String>,
+    keep_value: bool,
+}
+
+impl<S: SerializeStruct> SerializeStruct for FilteredStruct<'_, S> {
+    type Ok = S::Ok;
+    type Error = S::Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), S::Error> {
+        if self.fields.contains(key) {
+            self.inner.serialize_field(key, value)
+        } else {
+            self.inner.skip_field(key)
+        }
+    }
+
+    fn end(self) -> Result<S::Ok, S::Error> {
+        self.inner.end()
+    }
+}
+
+impl<S: SerializeMap> SerializeMap for FilteredMap<'_, S> {
+    type Ok = S::Ok;
+    type Error = S::Error;
+
+    fn serialize_key<T: ?Sized + Serialize>(&mut self, key: &T) -> Result<(), S::Error> {
+        self.keep_value = match serde_json::to_value(key) {
+            Ok(serde_json::Value::String(name)) => self.fields.contains(&name),
+            _ => true,
+        };
+        if self.keep_value {
+            self.inner.serialize_key(key)?;
+        }
+        Ok(())
+    }
+
+    fn serialize_value<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), S::Error> {
+        if self.keep_value {
+            self.inner.serialize_value(value)?;
+        }
+        Ok(())
+    }
+
+    fn end(self) -> Result<S::Ok, S::Error> {
+        self.inner.end()
+    }
+}
+
+impl<'a, S: Serializer> Serializer for FieldFilter<'a, S> {
+    type Ok = S::Ok;
+    type Error = S::Error;
+    type SerializeSeq = S::SerializeSeq;
+    type SerializeTuple = S::SerializeTuple;
+    type SerializeTupleStruct = S::SerializeTupleStruct;
+    type SerializeTupleVariant = S::SerializeTupleVariant;
+    type SerializeMap = FilteredMap<'a, S::SerializeMap>;
+    type SerializeStruct = FilteredStruct<'a, S::SerializeStruct>;
+    type SerializeStructVariant = S::SerializeStructVariant;
+
+    fn serialize_struct(
+        self,
+        name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStruct, S::Error> {
+        Ok(FilteredStruct {
+            inner: self.inner.serialize_struct(name, len)?,
+            fields: self.fields,
+        })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, S::Error> {
+        Ok(FilteredMap {
+            inner: self.inner.serialize_map(None)?,
+            fields: self.fields,
+            keep_value: false,
+        })
+    }
+
+    fn serialize_bool(self, v: bool) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_bool(v)
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_i8(v)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_i16(v)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_i32(v)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_i64(v)
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_i128(v)
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_u8(v)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_u16(v)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_u32(v)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_u64(v)
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_u128(v)
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_f32(v)
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_f64(v)
+    }
+
+    fn serialize_char(self, v: char) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_char(v)
+    }
+
+    fn serialize_str(self, v: &str) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_str(v)
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_bytes(v)
+    }
+
+    fn serialize_none(self) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_none()
+    }
+
+    // The selection applies to what is inside, as it would without the wrapper.
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<S::Ok, S::Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_unit()
+    }
+
+    fn serialize_unit_struct(self, name: &'static str) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_unit_struct(name)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+    ) -> Result<S::Ok, S::Error> {
+        self.inner
+            .serialize_unit_variant(name, variant_index, variant)
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<S::Ok, S::Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<S::Ok, S::Error> {
+        self.inner
+            .serialize_newtype_variant(name, variant_index, variant, value)
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<S::SerializeSeq, S::Error> {
+        self.inner.serialize_seq(len)
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<S::SerializeTuple, S::Error> {
+        self.inner.serialize_tuple(len)
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        name: &'static str,
+        len: usize,
+    ) -> Result<S::SerializeTupleStruct, S::Error> {
+        self.inner.serialize_tuple_struct(name, len)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<S::SerializeTupleVariant, S::Error> {
+        self.inner
+            .serialize_tuple_variant(name, variant_index, variant, len)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<S::SerializeStructVariant, S::Error> {
+        self.inner
+            .serialize_struct_variant(name, variant_index, variant, len)
+    }
+
+    fn is_human_readable(&self) -> bool {
+        self.inner.is_human_readable()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[derive(Serialize)]
+    struct Record {
+        id: &'static str,
+        body: &'static str,
+    }
+
+    #[derive(Serialize)]
+    struct View {
+        #[serde(flatten)]
+        record: Record,
+        size: u64,
+    }
+
+    #[test]
+    fn fields_select_top_level_members_of_plain_and_flattened_structs() {
+        let known = ["id", "body", "size"];
+        let shape = Shape::parse(Some("id, size"), None, &known).unwrap();
+        let record = Record { id: "a", body: "long" };
+        assert_eq!(
+            serde_json::to_value(shape.apply(&record)).unwrap(),
+            json!({ "id": "a" })
+        );
+        let view = View { record, size: 4 };
+        assert_eq!(
+            serde_json::to_value(shape.apply(Some(&view))).unwrap(),
+            json!({ "id": "a", "size": 4 })
+        );
+
+        assert_eq!(Shape::parse(Some("id,nope"), None, &known), Err("nope".to_string()));
+        let full = Shape::parse(Some(""), Some(false), &known).unwrap();
+        assert!(full.is_full());
+        let omitted = Shape::parse(None, Some(true), &known).unwrap();
+        assert!(!omitted.wants_payload("body") && omitted.includes("body"));
+        assert_ne!(omitted.etag_key(), shape.etag_key());
+    }
+}
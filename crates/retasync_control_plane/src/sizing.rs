@@ -0,0 +1,250 @@
+﻿use retasync_contract::{compress_payload, encode_canonical, Compression};
+use retasync_mesh_bridge::TransportSelection;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::NodeConfig;
+
+pub const DEFAULT_MAX_LINK_BYTES: usize = 1024 * 1024;
+pub const DEFAULT_MAX_LXMF_BYTES: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Mitigation {
+    Compression,
+    TransferSplitting,
+}
+
+impl Mitigation {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Mitigation::Compression => "compression",
+            Mitigation::TransferSplitting => "transfer_splitting",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Oversize {
+    pub transport: TransportSelection,
+    pub size_bytes: usize,
+    pub limit_bytes: usize,
+    pub mitigation: Option<Mitigation>,
+}
+
+impl Oversize {
+    pub fn reason(&self) -> String {
+        let transport = match self.transport {
+            TransportSelection::Link => "link",
+            TransportSelection::Lxmf => "lxmf",
+        };
+        let mut reason = format!(
+            "envelope_too_large: {} bytes exceeds the {transport} limit of {} bytes",
+            self.size_bytes, self.limit_bytes
+        );
+        if let Some(mitigation) = self.mitigation {
+            reason.push_str("; mitigation: ");
+            reason.push_str(mitigation.as_str());
+        }
+        reason
+    }
+}
+
+pub fn transport_limit(config: &NodeConfig, transport: &TransportSelection) -> usize {
+    match transport {
+        TransportSelection::Link => config.max_link_bytes,
+        TransportSelection::Lxmf => config.max_lxmf_bytes,
+    }
+}
+
+// Sizes the envelope as it will go on the wire: canonical msgpack, with a payload already
+// compressed when the negotiated content type says so. `splittable` payloads can shed bulk
+// into attachments or transfers; transfer chunks cannot.
+pub fn check_envelope<T: Serialize>(
+    config: &NodeConfig,
+    transport: TransportSelection,
+    envelope: &T,
+    payload: &Value,
+    content_type: &str,
+    splittable: bool,
+) -> anyhow::Result<Option<Oversize>> {
+    let (size_bytes, payload_bytes) = envelope_size(envelope, payload)?;
+    let limit_bytes = transport_limit(config, &transport);
+    if size_bytes <= limit_bytes {
+        return Ok(None);
+    }
+
+    // A payload that is already compressed has nothing more to gain from it.
+    let compressed = match Compression::from_content_type(content_type)? {
+        Some(_) => payload_bytes,
+        None => encode_canonical(&compress_payload(payload, Compression::Zstd)?)?.len(),
+    };
+    let mitigation = if payload_bytes > compressed
+        && size_bytes - payload_bytes + compressed <= limit_bytes
+    {
+        Some(Mitigation::Compression)
+    } else if splittable {
+        Some(Mitigation::TransferSplitting)
+    } else {
+        None
+    };
+    Ok(Some(Oversize {
+        transport,
+        size_bytes,
+        limit_bytes,
+        mitigation,
+    }))
+}
+
+// Wire size of the envelope and of the payload inside it.
+pub fn envelope_size<T: Serialize>(
+    envelope: &T,
+    payload: &Value,
+) -> anyhow::Result<(usize, usize)> {
+    Ok((encode_canonical(envelope)?.len(), encode_canonical(payload)?.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_envelope, Mitigation};
+    use crate::test_support::node_config;
+    use crate::NodeConfig;
+    use chrono::Utc;
+    use retasync_contract::{MeshCommandEnvelope, TransferHint, CONTENT_TYPE_MSGPACK};
+    use retasync_mesh_bridge::{InMemoryRpcMeshBridge, RpcMeshBridge, TransportSelection};
+    use serde_json::{json, Value};
+    use sha2::{Digest, Sha256};
+
+    fn config() -> NodeConfig {
+        node_config(json!({ "prefer_link": false }))
+    }
+
+    fn envelope(payload: Value) -> MeshCommandEnvelope<Value> {
+        MeshCommandEnvelope {
+            message_id: "msg-1".to_string(),
+            operation: "event.create".to_string(),
+            sent_at: Utc::now(),
+            source_identity: "local-node".parse().unwrap(),
+            destination_identity: "mesh".parse().unwrap(),
+            content_type: CONTENT_TYPE_MSGPACK.to_string(),
+            payload,
+            ttl_ms: None,
+            transport_hint: None,
+            trace: Vec::new(),
+            trace_truncated: false,
+            meta: None,
+        }
+    }
+
+    // Hex of a hash chain, which zstd cannot shrink below the limits used here.
+    fn noise(len: usize) -> String {
+        let mut digest = Sha256::digest(b"seed");
+        let mut out = String::new();
+        while out.len() < len {
+            out.push_str(&format!("{digest:x}"));
+            digest = Sha256::digest(digest);
+        }
+        out.truncate(len);
+        out
+    }
+
+    fn size_of(envelope: &MeshCommandEnvelope<Value>) -> usize {
+        let mut config = config();
+        config.max_lxmf_bytes = 0;
+        check_envelope(
+            &config,
+            TransportSelection::Lxmf,
+            envelope,
+            &envelope.payload,
+            &envelope.content_type,
+            true,
+        )
+        .unwrap()
+        .unwrap()
+        .size_bytes
+    }
+
+    #[test]
+    fn limits_apply_per_transport_at_the_boundary() {
+        let envelope = envelope(json!({ "uid": "evt-1", "notes": noise(2048) }));
+        let size = size_of(&envelope);
+
+        for transport in [TransportSelection::Link, TransportSelection::Lxmf] {
+            let check = |limit: usize| {
+                let mut config = config();
+                match transport {
+                    TransportSelection::Link => config.max_link_bytes = limit,
+                    TransportSelection::Lxmf => config.max_lxmf_bytes = limit,
+                }
+                check_envelope(
+                    &config,
+                    transport.clone(),
+                    &envelope,
+                    &envelope.payload,
+                    &envelope.content_type,
+                    true,
+                )
+                .unwrap()
+            };
+            assert_eq!(check(size), None, "{transport:?}");
+            let over = check(size - 1).expect("rejected one byte over");
+            assert_eq!(over.transport, transport);
+            assert_eq!((over.size_bytes, over.limit_bytes), (size, size - 1));
+        }
+    }
+
+    #[test]
+    fn mitigation_depends_on_what_would_fit() {
+        let mut config = config();
+        config.max_lxmf_bytes = 512;
+        let check = |payload: Value, splittable: bool| {
+            let envelope = envelope(payload);
+            check_envelope(
+                &config,
+                TransportSelection::Lxmf,
+                &envelope,
+                &envelope.payload,
+                &envelope.content_type,
+                splittable,
+            )
+            .unwrap()
+            .unwrap()
+        };
+
+        let repetitive = check(json!({ "notes": "x".repeat(4096) }), true);
+        assert_eq!(repetitive.mitigation, Some(Mitigation::Compression));
+        assert!(repetitive.reason().ends_with("mitigation: compression"));
+
+        let dense = check(json!({ "notes": noise(4096) }), true);
+        assert_eq!(dense.mitigation, Some(Mitigation::TransferSplitting));
+        assert_eq!(check(json!({ "notes": noise(4096) }), false).mitigation, None);
+    }
+
+    #[test]
+    fn the_planned_transport_selects_the_limit() {
+        let mut config = config();
+        let envelope = envelope(json!({ "notes": noise(2048) }));
+        let size = size_of(&envelope);
+        config.max_lxmf_bytes = size - 1;
+        config.max_link_bytes = size;
+
+        let bridge = InMemoryRpcMeshBridge::new(false, true);
+        let check = |hint: Option<TransferHint>| {
+            check_envelope(
+                &config,
+                bridge.planned_transport(hint),
+                &envelope,
+                &envelope.payload,
+                &envelope.content_type,
+                true,
+            )
+            .unwrap()
+        };
+        assert_eq!(check(Some(TransferHint::Link)), None);
+        assert_eq!(check(None).unwrap().transport, TransportSelection::Lxmf);
+        assert_eq!(
+            check(Some(TransferHint::Lxmf)).unwrap().limit_bytes,
+            size - 1
+        );
+    }
+}
@@ -0,0 +1,693 @@
+﻿use std::collections::BTreeMap;
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use chrono::{DateTime, Utc};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use retasync_contract::{
+    decode_canonical_with_limits, encode_canonical, CodecLimits, IdentityHash,
+    MeshCommandEnvelope, MeshEventEnvelope, MeshResultEnvelope, MeshTransferEnvelope,
+    TransferHint,
+};
+use retasync_mesh_bridge::{
+    BridgeError, BridgeReceipt, CallMetrics, CancelOutcome, RpcMeshBridge, TransportSelection,
+    TransportStatus,
+};
+use retasync_storage::{CanonicalTimestamp, RetasyncStorage};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::app::{emit, event_type_matches, publish};
+use crate::dispatch::local_identity;
+use crate::escalation::{record_escalation, EscalationRecord, EscalationStep};
+use crate::inbound::{canonical_operation, route_command};
+use crate::results::{apply_event, ingest_delayed_result};
+use crate::AppState;
+
+pub const SNEAKERNET_MEDIA_TYPE: &str = "application/vnd.retasync.sneakernet+msgpack";
+pub const SNEAKERNET_FORMAT_VERSION: u32 = 1;
+pub const BUNDLE_ID_HEADER: &str = "x-bundle-id";
+pub const OUTBOX_COMMAND: &str = "command";
+pub const OUTBOX_RESULT: &str = "result";
+pub const BUNDLE_EXPORTED_EVENT: &str = "sneakernet.bundle.exported";
+pub const BUNDLE_IMPORTED_EVENT: &str = "sneakernet.bundle.imported";
+const KEY_EXTENSION: &str = "sneakernet.key";
+// Levels an item payload sits below the bundle root: signed bundle, bundle, item list, item.
+const BUNDLE_NESTING: usize = 4;
+
+// The `[sneakernet]` section: how this node signs the bundles it exports and whose bundles it
+// imports.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SneakernetSettings {
+    // Ed25519 seed, raw or base64. Defaults to `<database>.sneakernet.key` beside the database
+    // file, generated the first time a bundle is exported.
+    pub signing_key_path: Option<String>,
+    // Identity to base64 Ed25519 public key. A bundle is imported only when its signer is listed
+    // here and is on the allowlist.
+    pub trusted_signers: BTreeMap<String, String>,
+    // Largest bundle exported or accepted for import.
+    pub max_bundle_bytes: u64,
+    // Cached events one export carries at most.
+    pub max_events: usize,
+}
+
+impl Default for SneakernetSettings {
+    fn default() -> Self {
+        Self {
+            signing_key_path: None,
+            trusted_signers: BTreeMap::new(),
+            max_bundle_bytes: 16 * 1024 * 1024,
+            max_events: 1000,
+        }
+    }
+}
+
+impl SneakernetSettings {
+    pub fn key_path_for(&self, database_path: &Path) -> PathBuf {
+        match &self.signing_key_path {
+            Some(path) => PathBuf::from(path),
+            None => {
+                let mut path = database_path.as_os_str().to_owned();
+                path.push(".");
+                path.push(KEY_EXTENSION);
+                PathBuf::from(path)
+            }
+        }
+    }
+
+    pub(crate) fn trusted_key(&self, identity: &str) -> Option<VerifyingKey> {
+        parse_public_key(self.trusted_signers.get(identity)?).ok()
+    }
+}
+
+pub fn validate_sneakernet_settings(settings: &SneakernetSettings) -> Result<(), String> {
+    for (identity, key) in &settings.trusted_signers {
+        parse_public_key(key)
+            .map_err(|detail| format!("sneakernet.trusted_signers.{identity}: {detail}"))?;
+    }
+    Ok(())
+}
+
+fn parse_public_key(encoded: &str) -> Result<VerifyingKey, String> {
+    let bytes = STANDARD
+        .decode(encoded.trim())
+        .map_err(|_| "public key is not base64".to_string())?;
+    let bytes: [u8; 32] = bytes
+        .try_into()
+        .map_err(|_| "public key must be 32 bytes".to_string())?;
+    VerifyingKey::from_bytes(&bytes).map_err(|_| "not an Ed25519 public key".to_string())
+}
+
+#[derive(Debug, Error)]
+pub enum SneakernetError {
+    #[error("bundle is {size} bytes, over the {limit} byte limit")]
+    TooLarge { size: u64, limit: u64 },
+    #[error("invalid bundle: {0}")]
+    Invalid(String),
+    #[error("bundle signer {0} is not trusted")]
+    UntrustedSigner(String),
+    #[error("bundle signature does not verify")]
+    BadSignature,
+    #[error("signing key {path}: {detail}")]
+    SigningKey { path: String, detail: String },
+    #[error(transparent)]
+    Internal(#[from] anyhow::Error),
+}
+
+impl SneakernetError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            SneakernetError::TooLarge { .. } => "bundle_too_large",
+            SneakernetError::Invalid(_) => "invalid_bundle",
+            SneakernetError::UntrustedSigner(_) => "bundle_signer_not_trusted",
+            SneakernetError::BadSignature => "bundle_signature_invalid",
+            SneakernetError::SigningKey { .. } => "signing_key_unavailable",
+            SneakernetError::Internal(_) => "internal_error",
+        }
+    }
+}
+
+impl From<retasync_storage::StorageError> for SneakernetError {
+    fn from(err: retasync_storage::StorageError) -> Self {
+        SneakernetError::Internal(err.into())
+    }
+}
+
+// Reads the node's signing key, generating and storing one the first time.
+pub fn load_signing_key(path: &Path) -> Result<SigningKey, SneakernetError> {
+    let seed = load_secret(path).map_err(|detail| SneakernetError::SigningKey {
+        path: path.display().to_string(),
+        detail,
+    })?;
+    Ok(SigningKey::from_bytes(&seed))
+}
+
+// Reads a 32 byte secret stored raw or as base64, generating and storing one the first time.
+pub(crate) fn load_secret(path: &Path) -> Result<[u8; 32], String> {
+    match std::fs::read(path) {
+        Ok(raw) => {
+            let seed = match raw.len() {
+                32 => raw,
+                _ => {
+                    let text = String::from_utf8(raw).map_err(|_| "not base64".to_string())?;
+                    STANDARD
+                        .decode(text.trim())
+                        .map_err(|_| "not base64".to_string())?
+                }
+            };
+            seed.try_into()
+                .map_err(|_| "seed must be 32 bytes".to_string())
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            let mut seed = [0u8; 32];
+            getrandom::getrandom(&mut seed).map_err(|err| err.to_string())?;
+            let mut options = std::fs::OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+            let mut file = match options.open(path) {
+                Ok(file) => file,
+                // Another caller created it first.
+                Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
+                    return load_secret(path);
+                }
+                Err(err) => return Err(err.to_string()),
+            };
+            file.write_all(STANDARD.encode(seed).as_bytes())
+                .and_then(|()| file.sync_all())
+                .map_err(|err| err.to_string())?;
+            Ok(seed)
+        }
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+// The identity and public key a receiving node lists under `trusted_signers`.
+pub async fn signer(state: &AppState) -> Result<Value, SneakernetError> {
+    let (settings, identity) = {
+        let config = state.node_config.read().await;
+        (config.sneakernet.clone(), local_identity(&config))
+    };
+    let key = load_signing_key(&settings.key_path_for(state.storage.database_path()))?;
+    Ok(json!({
+        "identity": identity,
+        "public_key": STANDARD.encode(key.verifying_key().as_bytes()),
+    }))
+}
+
+// What an export takes; every field narrows it, and an empty list selects everything.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExportFilter {
+    pub destination_identities: Vec<String>,
+    // Operation patterns such as `event.*`, matched against event names for cached events.
+    pub operations: Vec<String>,
+    pub since: Option<DateTime<Utc>>,
+    // Cached events ride along only when asked for.
+    pub include_events: bool,
+}
+
+impl ExportFilter {
+    fn selects(&self, destination_identity: Option<&str>, operation: &str) -> bool {
+        let destination = match destination_identity {
+            Some(destination) => {
+                self.destination_identities.is_empty()
+                    || self.destination_identities.iter().any(|d| d == destination)
+            }
+            None => true,
+        };
+        destination
+            && (self.operations.is_empty()
+                || self
+                    .operations
+                    .iter()
+                    .any(|pattern| event_type_matches(pattern, operation)))
+    }
+}
+
+// Envelopes carried between meshes by hand. The file holds the bundle beside an Ed25519
+// signature over its canonical encoding, made with the key of `source_identity`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SneakernetBundle {
+    pub version: u32,
+    pub bundle_id: String,
+    pub source_identity: String,
+    pub created_at: DateTime<Utc>,
+    pub commands: Vec<MeshCommandEnvelope<Value>>,
+    pub results: Vec<MeshResultEnvelope<Value>>,
+    pub events: Vec<MeshEventEnvelope<Value>>,
+}
+
+// The bundle is kept as a value so the signature is checked over exactly what was signed.
+#[derive(Debug, Serialize, Deserialize)]
+struct SignedBundle {
+    bundle: Value,
+    signature: String,
+}
+
+fn sign(bundle: &SneakernetBundle, key: &SigningKey) -> anyhow::Result<Vec<u8>> {
+    let bundle = serde_json::to_value(bundle)?;
+    let signature = key.sign(&encode_canonical(&bundle)?);
+    Ok(encode_canonical(&SignedBundle {
+        bundle,
+        signature: STANDARD.encode(signature.to_bytes()),
+    })?)
+}
+
+#[derive(Debug, Clone)]
+pub struct ExportedBundle {
+    pub bundle_id: String,
+    pub bytes: Vec<u8>,
+}
+
+// Packs the pending outbox entries the filter selects, and cached events if asked, into a
+// signed bundle. The entries are marked exported with the bundle id rather than sent: the mesh
+// may still deliver a command too, and the far side drops whichever copy arrives second.
+pub async fn export_bundle(
+    state: &AppState,
+    filter: &ExportFilter,
+) -> Result<ExportedBundle, SneakernetError> {
+    let (settings, source_identity) = {
+        let config = state.node_config.read().await;
+        (config.sneakernet.clone(), local_identity(&config).into_string())
+    };
+    let key = load_signing_key(&settings.key_path_for(state.storage.database_path()))?;
+    let entries: Vec<_> = state
+        .storage
+        .list_pending_outbox(filter.since)
+        .await?
+        .into_iter()
+        .filter(|entry| filter.selects(Some(&entry.destination_identity), &entry.operation))
+        .collect();
+
+    let mut bundle = SneakernetBundle {
+        version: SNEAKERNET_FORMAT_VERSION,
+        bundle_id: Uuid::now_v7().to_string(),
+        source_identity,
+        created_at: Utc::now(),
+        commands: Vec::new(),
+        results: Vec::new(),
+        events: Vec::new(),
+    };
+    for entry in &entries {
+        let envelope = entry.envelope.clone();
+        match entry.kind.as_str() {
+            OUTBOX_COMMAND => bundle.commands.push(
+                serde_json::from_value(envelope).map_err(anyhow::Error::from)?,
+            ),
+            _ => bundle.results.push(
+                serde_json::from_value(envelope).map_err(anyhow::Error::from)?,
+            ),
+        }
+    }
+    if filter.include_events {
+        let cached = state
+            .storage
+            .list_cached_events_since(filter.since, settings.max_events as i64)
+            .await?;
+        for event in cached {
+            let event: MeshEventEnvelope<Value> =
+                serde_json::from_value(event).map_err(anyhow::Error::from)?;
+            if filter.selects(None, &event.event) {
+                bundle.events.push(event);
+            }
+        }
+    }
+    let bytes = sign(&bundle, &key)?;
+    if bytes.len() as u64 > settings.max_bundle_bytes {
+        return Err(SneakernetError::TooLarge {
+            size: bytes.len() as u64,
+            limit: settings.max_bundle_bytes,
+        });
+    }
+
+    let message_ids: Vec<String> = entries.iter().map(|entry| entry.message_id.clone()).collect();
+    let event = json!({
+        "bundle_id": bundle.bundle_id,
+        "commands": bundle.commands.len(),
+        "results": bundle.results.len(),
+        "events": bundle.events.len(),
+        "destination_identities": filter.destination_identities,
+    });
+    let bundle_id = bundle.bundle_id.clone();
+    let exported = state
+        .storage
+        .with_tx(move |tx| {
+            Box::pin(async move {
+                let exported = tx.mark_outbox_exported(&message_ids, &bundle_id).await?;
+                tx.append_feed_event(BUNDLE_EXPORTED_EVENT, &event).await?;
+                Ok(exported)
+            })
+        })
+        .await?;
+    for entry in entries.iter().filter(|entry| exported.contains(&entry.message_id)) {
+        if let Some(job_id) = &entry.job_id {
+            let step = EscalationRecord {
+                transport_hint: Some(TransferHint::Lxmf),
+                detail: Some(bundle.bundle_id.clone()),
+                ..EscalationRecord::new(EscalationStep::Exported)
+            };
+            record_escalation(state, job_id, step).await?;
+        }
+    }
+    publish(state).await;
+    Ok(ExportedBundle {
+        bundle_id: bundle.bundle_id,
+        bytes,
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportOutcome {
+    Accepted,
+    Duplicate,
+    Rejected,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ImportItem {
+    pub kind: &'static str,
+    pub message_id: String,
+    pub outcome: ImportOutcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ImportReport {
+    pub bundle_id: String,
+    pub source_identity: String,
+    pub accepted: usize,
+    pub duplicate: usize,
+    pub rejected: usize,
+    pub items: Vec<ImportItem>,
+}
+
+impl ImportReport {
+    fn record(&mut self, kind: &'static str, message_id: &str, outcome: ImportOutcome) {
+        self.record_with(kind, message_id, outcome, None);
+    }
+
+    fn record_with(
+        &mut self,
+        kind: &'static str,
+        message_id: &str,
+        outcome: ImportOutcome,
+        reason: Option<String>,
+    ) {
+        match outcome {
+            ImportOutcome::Accepted => self.accepted += 1,
+            ImportOutcome::Duplicate => self.duplicate += 1,
+            ImportOutcome::Rejected => self.rejected += 1,
+        }
+        self.items.push(ImportItem {
+            kind,
+            message_id: message_id.to_string(),
+            outcome,
+            reason,
+        });
+    }
+}
+
+// Feeds a bundle's envelopes through inbound processing as if the mesh had delivered them:
+// commands to their handlers, whose answers queue in the outbox for the trip back, results to
+// the jobs that sent the commands, and events to the cache. The whole bundle is refused unless
+// its signer is trusted and its signature holds; after that each envelope stands alone.
+pub async fn import_bundle(
+    state: &AppState,
+    bytes: &[u8],
+) -> Result<ImportReport, SneakernetError> {
+    let (settings, limits) = {
+        let config = state.node_config.read().await;
+        (config.sneakernet.clone(), config.codec_limits)
+    };
+    if bytes.len() as u64 > settings.max_bundle_bytes {
+        return Err(SneakernetError::TooLarge {
+            size: bytes.len() as u64,
+            limit: settings.max_bundle_bytes,
+        });
+    }
+    // The raw bundle is held to the payload limits, widened for the bundle around them, before
+    // anything is decoded; each item is then checked against the limits themselves.
+    let bundle_limits = CodecLimits {
+        max_bytes: bytes.len(),
+        max_depth: limits.max_depth.saturating_add(BUNDLE_NESTING),
+        ..limits
+    };
+    let signed: SignedBundle = decode_canonical_with_limits(bytes, &bundle_limits)
+        .map_err(|err| SneakernetError::Invalid(err.to_string()))?;
+    let signer = signed
+        .bundle
+        .get("source_identity")
+        .and_then(Value::as_str)
+        .ok_or_else(|| SneakernetError::Invalid("bundle names no source_identity".into()))?
+        .to_string();
+    let Some(key) = settings.trusted_key(&signer) else {
+        return Err(SneakernetError::UntrustedSigner(signer));
+    };
+    let allowlisted = state
+        .storage
+        .is_allowlisted(&IdentityHash::lenient(&signer), Utc::now())
+        .await?;
+    if !allowlisted {
+        return Err(SneakernetError::UntrustedSigner(signer));
+    }
+    let signature = STANDARD
+        .decode(&signed.signature)
+        .ok()
+        .and_then(|bytes| Signature::from_slice(&bytes).ok())
+        .ok_or(SneakernetError::BadSignature)?;
+    let signed_bytes = encode_canonical(&signed.bundle).map_err(anyhow::Error::from)?;
+    key.verify(&signed_bytes, &signature)
+        .map_err(|_| SneakernetError::BadSignature)?;
+    let bundle: SneakernetBundle = serde_json::from_value(signed.bundle)
+        .map_err(|err| SneakernetError::Invalid(err.to_string()))?;
+    if bundle.version != SNEAKERNET_FORMAT_VERSION {
+        return Err(SneakernetError::Invalid(format!(
+            "unsupported bundle format version {}",
+            bundle.version
+        )));
+    }
+
+    let mut report = ImportReport {
+        bundle_id: bundle.bundle_id.clone(),
+        source_identity: signer,
+        accepted: 0,
+        duplicate: 0,
+        rejected: 0,
+        items: Vec::new(),
+    };
+    let courier = AppState {
+        bridge: Arc::new(CourierBridge {
+            inner: state.bridge.clone(),
+            storage: state.storage.clone(),
+        }),
+        ..state.clone()
+    };
+    let received_at = Utc::now();
+
+    for mut envelope in bundle.commands {
+        let id = envelope.message_id.clone();
+        if let Err(err) = limits.check_value(&envelope.payload) {
+            report.record_with("command", &id, ImportOutcome::Rejected, Some(err.to_string()));
+            continue;
+        }
+        if !first_receipt(state, &envelope.source_identity, &id, envelope.sent_at).await? {
+            report.record("command", &id, ImportOutcome::Duplicate);
+            continue;
+        }
+        canonical_operation(state, &mut envelope);
+        match route_command(&courier, envelope, received_at).await {
+            Ok(()) => report.record("command", &id, ImportOutcome::Accepted),
+            Err(err) => {
+                let reason = format!("{err:#}");
+                report.record_with("command", &id, ImportOutcome::Rejected, Some(reason))
+            }
+        }
+    }
+    for result in bundle.results {
+        let id = result.message_id.clone();
+        if let Err(err) = limits.check_value(&result.payload) {
+            report.record_with("result", &id, ImportOutcome::Rejected, Some(err.to_string()));
+            continue;
+        }
+        if state
+            .storage
+            .job_for_message(&result.correlation_id)
+            .await?
+            .is_none()
+        {
+            let reason = format!("no job sent command {}", result.correlation_id);
+            report.record_with("result", &id, ImportOutcome::Rejected, Some(reason));
+            continue;
+        }
+        if !first_receipt(state, &result.source_identity, &id, result.sent_at).await? {
+            report.record("result", &id, ImportOutcome::Duplicate);
+            continue;
+        }
+        match ingest_delayed_result(state, result).await {
+            Ok(()) => report.record("result", &id, ImportOutcome::Accepted),
+            Err(err) => {
+                let reason = format!("{err:#}");
+                report.record_with("result", &id, ImportOutcome::Rejected, Some(reason))
+            }
+        }
+    }
+    for event in bundle.events {
+        let id = event.message_id.clone();
+        if let Err(err) = limits.check_value(&event.payload) {
+            report.record_with("event", &id, ImportOutcome::Rejected, Some(err.to_string()));
+            continue;
+        }
+        if !first_receipt(state, &event.source_identity, &id, event.sent_at).await? {
+            report.record("event", &id, ImportOutcome::Duplicate);
+            continue;
+        }
+        match apply_event(state, event).await {
+            Ok(()) => report.record("event", &id, ImportOutcome::Accepted),
+            Err(err) => {
+                let reason = format!("{err:#}");
+                report.record_with("event", &id, ImportOutcome::Rejected, Some(reason))
+            }
+        }
+    }
+
+    emit(
+        state,
+        BUNDLE_IMPORTED_EVENT,
+        json!({
+            "bundle_id": report.bundle_id,
+            "source_identity": report.source_identity,
+            "accepted": report.accepted,
+            "duplicate": report.duplicate,
+            "rejected": report.rejected,
+        }),
+    )
+    .await;
+    Ok(report)
+}
+
+// True the first time this node sees the message, whether it came over the mesh or in a bundle.
+async fn first_receipt(
+    state: &AppState,
+    source_identity: &str,
+    message_id: &str,
+    sent_at: DateTime<Utc>,
+) -> anyhow::Result<bool> {
+    let first = state
+        .storage
+        .record_seen_message(
+            source_identity,
+            message_id,
+            &CanonicalTimestamp::from(sent_at).to_string(),
+            &CanonicalTimestamp::now().to_string(),
+        )
+        .await?;
+    Ok(first.is_none())
+}
+
+// Stands in for the mesh bridge while bundled commands are handled: their answers are queued
+// in the outbox for the next export instead of being sent. Everything else passes through.
+struct CourierBridge {
+    inner: Arc<dyn RpcMeshBridge>,
+    storage: RetasyncStorage,
+}
+
+#[async_trait]
+impl RpcMeshBridge for CourierBridge {
+    async fn send_command(
+        &self,
+        envelope: MeshCommandEnvelope<Value>,
+    ) -> Result<MeshResultEnvelope<Value>, BridgeError> {
+        self.inner.send_command(envelope).await
+    }
+
+    async fn publish_event(
+        &self,
+        envelope: MeshEventEnvelope<Value>,
+    ) -> Result<BridgeReceipt, BridgeError> {
+        self.inner.publish_event(envelope).await
+    }
+
+    async fn start_transfer(
+        &self,
+        envelope: MeshTransferEnvelope<Value>,
+    ) -> Result<BridgeReceipt, BridgeError> {
+        self.inner.start_transfer(envelope).await
+    }
+
+    async fn query_receipt(&self, message_id: &str) -> Result<Option<BridgeReceipt>, BridgeError> {
+        self.inner.query_receipt(message_id).await
+    }
+
+    async fn poll_events(
+        &self,
+        limit: usize,
+    ) -> Result<Vec<MeshEventEnvelope<Value>>, BridgeError> {
+        self.inner.poll_events(limit).await
+    }
+
+    async fn poll_commands(
+        &self,
+        limit: usize,
+    ) -> Result<Vec<MeshCommandEnvelope<Value>>, BridgeError> {
+        self.inner.poll_commands(limit).await
+    }
+
+    async fn send_result(
+        &self,
+        envelope: MeshResultEnvelope<Value>,
+    ) -> Result<BridgeReceipt, BridgeError> {
+        let queued = serde_json::to_value(&envelope)
+            .map_err(|err| BridgeError::SendFailed(err.to_string()))?;
+        self.storage
+            .queue_outbox(
+                OUTBOX_RESULT,
+                &envelope.message_id,
+                &envelope.operation,
+                &envelope.destination_identity,
+                &queued,
+            )
+            .await
+            .map_err(|err| BridgeError::SendFailed(format!("queue result for export: {err}")))?;
+        Ok(BridgeReceipt {
+            message_id: envelope.message_id,
+            accepted_at: Utc::now().to_rfc3339(),
+            transport: TransportSelection::Lxmf,
+        })
+    }
+
+    async fn set_inbound_backpressure(&self, enabled: bool) -> Result<(), BridgeError> {
+        self.inner.set_inbound_backpressure(enabled).await
+    }
+
+    async fn poll_transfers(
+        &self,
+        limit: usize,
+    ) -> Result<Vec<MeshTransferEnvelope<Value>>, BridgeError> {
+        self.inner.poll_transfers(limit).await
+    }
+
+    fn planned_transport(&self, hint: Option<TransferHint>) -> TransportSelection {
+        self.inner.planned_transport(hint)
+    }
+
+    async fn cancel_message(&self, message_id: &str) -> Result<CancelOutcome, BridgeError> {
+        self.inner.cancel_message(message_id).await
+    }
+
+    fn transport_status(&self) -> Option<TransportStatus> {
+        self.inner.transport_status()
+    }
+
+    fn call_metrics(&self) -> Option<BTreeMap<String, CallMetrics>> {
+        self.inner.call_metrics()
+    }
+}
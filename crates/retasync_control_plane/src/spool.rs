@@ -0,0 +1,408 @@
+﻿use std::borrow::Cow;
+use std::ffi::OsStr;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::dedup::TransferOffer;
+
+const SPOOL_EXTENSION: &str = "spool";
+// Base64 characters decoded per spool write. A multiple of 4, so each piece decodes alone.
+const DECODE_PIECE_CHARS: usize = 64 * 1024;
+
+// The `[transfer_spool]` limits on what a base64 upload may hold in memory.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TransferSpoolSettings {
+    // Largest upload request body. A larger Content-Length is refused before any of it is read.
+    pub max_request_bytes: u64,
+    // Decoded uploads up to this size stay in memory, larger ones are written to a spool file.
+    pub memory_threshold_bytes: u64,
+    // What all in-memory uploads may hold together; an upload that would exceed it spools.
+    pub max_memory_bytes: u64,
+    // Defaults to `<database>.spool` beside the database file.
+    pub dir: Option<String>,
+    // Spool files at least this old are removed at startup.
+    pub orphan_grace_secs: u64,
+}
+
+impl Default for TransferSpoolSettings {
+    fn default() -> Self {
+        Self {
+            max_request_bytes: 64 * 1024 * 1024,
+            memory_threshold_bytes: 1024 * 1024,
+            max_memory_bytes: 8 * 1024 * 1024,
+            dir: None,
+            orphan_grace_secs: 3600,
+        }
+    }
+}
+
+impl TransferSpoolSettings {
+    pub fn dir_for(&self, database_path: &Path) -> PathBuf {
+        match &self.dir {
+            Some(dir) => PathBuf::from(dir),
+            None => {
+                let mut dir = database_path.as_os_str().to_owned();
+                dir.push(".");
+                dir.push(SPOOL_EXTENSION);
+                PathBuf::from(dir)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum SpoolError {
+    #[error("payload is not valid base64")]
+    InvalidBase64,
+    #[error("spool file: {0}")]
+    Io(#[from] io::Error),
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpoolUsage {
+    pub memory_bytes: u64,
+    pub max_memory_bytes: u64,
+    pub spooled_files: u64,
+    pub spooled_bytes: u64,
+    // Uploads decoded since startup, by where their content was kept.
+    pub memory_uploads: u64,
+    pub spooled_uploads: u64,
+}
+
+// What the node's uploads hold in memory and in the spool right now.
+#[derive(Debug, Default)]
+pub struct TransferSpool {
+    memory_bytes: AtomicU64,
+    spooled_files: AtomicU64,
+    spooled_bytes: AtomicU64,
+    memory_uploads: AtomicU64,
+    spooled_uploads: AtomicU64,
+}
+
+impl TransferSpool {
+    pub fn usage(&self, settings: &TransferSpoolSettings) -> SpoolUsage {
+        SpoolUsage {
+            memory_bytes: self.memory_bytes.load(Ordering::Relaxed),
+            max_memory_bytes: settings.max_memory_bytes,
+            spooled_files: self.spooled_files.load(Ordering::Relaxed),
+            spooled_bytes: self.spooled_bytes.load(Ordering::Relaxed),
+            memory_uploads: self.memory_uploads.load(Ordering::Relaxed),
+            spooled_uploads: self.spooled_uploads.load(Ordering::Relaxed),
+        }
+    }
+
+    // Decodes an upload into memory when it is under the threshold and the memory ceiling has
+    // room for it, and into a spool file in `dir` otherwise.
+    pub async fn decode(
+        self: &Arc<Self>,
+        settings: &TransferSpoolSettings,
+        dir: &Path,
+        payload_base64: &str,
+    ) -> Result<TransferContent, SpoolError> {
+        let len = decoded_len(payload_base64);
+        if len <= settings.memory_threshold_bytes {
+            if let Some(reservation) = self.reserve(len, settings.max_memory_bytes) {
+                let bytes = STANDARD
+                    .decode(payload_base64)
+                    .map_err(|_| SpoolError::InvalidBase64)?;
+                self.memory_uploads.fetch_add(1, Ordering::Relaxed);
+                return Ok(TransferContent::Memory(bytes, Some(reservation)));
+            }
+        }
+        let spooled = self.spool(dir, payload_base64).await?;
+        self.spooled_uploads.fetch_add(1, Ordering::Relaxed);
+        Ok(TransferContent::Spooled(spooled))
+    }
+
+    fn reserve(self: &Arc<Self>, bytes: u64, ceiling: u64) -> Option<MemoryReservation> {
+        self.memory_bytes
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |held| {
+                held.checked_add(bytes).filter(|total| *total <= ceiling)
+            })
+            .ok()?;
+        Some(MemoryReservation {
+            spool: self.clone(),
+            bytes,
+        })
+    }
+
+    async fn spool(
+        self: &Arc<Self>,
+        dir: &Path,
+        payload_base64: &str,
+    ) -> Result<SpoolFile, SpoolError> {
+        tokio::fs::create_dir_all(dir).await?;
+        let path = dir.join(format!("{}.{SPOOL_EXTENSION}", Uuid::now_v7()));
+        let mut file = File::create(&path).await?;
+        // From here on the guard removes the file whichever way this returns.
+        let mut spooled = SpoolFile {
+            spool: self.clone(),
+            path,
+            len: 0,
+        };
+        self.spooled_files.fetch_add(1, Ordering::Relaxed);
+        let mut decoded = vec![0; DECODE_PIECE_CHARS / 4 * 3];
+        for piece in payload_base64.as_bytes().chunks(DECODE_PIECE_CHARS) {
+            let written = STANDARD
+                .decode_slice(piece, &mut decoded)
+                .map_err(|_| SpoolError::InvalidBase64)?;
+            file.write_all(&decoded[..written]).await?;
+            spooled.len += written as u64;
+            self.spooled_bytes
+                .fetch_add(written as u64, Ordering::Relaxed);
+        }
+        file.flush().await?;
+        Ok(spooled)
+    }
+}
+
+// The exact decoded size of well-formed padded base64.
+fn decoded_len(payload_base64: &str) -> u64 {
+    let padding = payload_base64
+        .bytes()
+        .rev()
+        .take(2)
+        .filter(|byte| *byte == b'=')
+        .count();
+    ((payload_base64.len() / 4 * 3).saturating_sub(padding)) as u64
+}
+
+#[derive(Debug)]
+pub struct MemoryReservation {
+    spool: Arc<TransferSpool>,
+    bytes: u64,
+}
+
+impl Drop for MemoryReservation {
+    fn drop(&mut self) {
+        self.spool
+            .memory_bytes
+            .fetch_sub(self.bytes, Ordering::AcqRel);
+    }
+}
+
+// A spool file, removed as soon as the content it holds is dropped.
+#[derive(Debug)]
+pub struct SpoolFile {
+    spool: Arc<TransferSpool>,
+    path: PathBuf,
+    len: u64,
+}
+
+impl Drop for SpoolFile {
+    fn drop(&mut self) {
+        if let Err(err) = std::fs::remove_file(&self.path) {
+            if err.kind() != io::ErrorKind::NotFound {
+                warn!(path = %self.path.display(), error = %err, "spool file not removed");
+            }
+        }
+        self.spool.spooled_files.fetch_sub(1, Ordering::Relaxed);
+        self.spool
+            .spooled_bytes
+            .fetch_sub(self.len, Ordering::Relaxed);
+    }
+}
+
+// Content on its way to the chunking pipeline, wherever it is kept.
+#[derive(Debug)]
+pub enum TransferContent {
+    // Counted against the memory ceiling only when it came through `TransferSpool::decode`.
+    Memory(Vec<u8>, Option<MemoryReservation>),
+    Spooled(SpoolFile),
+}
+
+impl From<Vec<u8>> for TransferContent {
+    fn from(bytes: Vec<u8>) -> Self {
+        TransferContent::Memory(bytes, None)
+    }
+}
+
+impl TransferContent {
+    pub fn len(&self) -> u64 {
+        match self {
+            TransferContent::Memory(bytes, _) => bytes.len() as u64,
+            TransferContent::Spooled(spooled) => spooled.len,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_spooled(&self) -> bool {
+        matches!(self, TransferContent::Spooled(_))
+    }
+
+    // Up to the first `max` bytes, enough to sniff the content type.
+    pub async fn head(&self, max: usize) -> io::Result<Cow<'_, [u8]>> {
+        match self {
+            TransferContent::Memory(bytes, _) => Ok(Cow::Borrowed(&bytes[..bytes.len().min(max)])),
+            TransferContent::Spooled(_) => {
+                let mut chunks = self.chunks(max).await?;
+                Ok(Cow::Owned(chunks.next().await?.unwrap_or_default()))
+            }
+        }
+    }
+
+    pub async fn offer(&self, name: &str) -> io::Result<TransferOffer> {
+        let mut hasher = Sha256::new();
+        let mut chunks = self.chunks(DECODE_PIECE_CHARS).await?;
+        while let Some(chunk) = chunks.next().await? {
+            hasher.update(&chunk);
+        }
+        Ok(TransferOffer {
+            sha256: format!("{:x}", hasher.finalize()),
+            size: self.len(),
+            name: name.to_string(),
+        })
+    }
+
+    pub async fn chunks(&self, chunk_size: usize) -> io::Result<Chunks<'_>> {
+        Ok(match self {
+            TransferContent::Memory(bytes, _) => Chunks::Memory(bytes.chunks(chunk_size)),
+            TransferContent::Spooled(spooled) => Chunks::Spooled {
+                file: File::open(&spooled.path).await?,
+                chunk_size,
+            },
+        })
+    }
+}
+
+pub enum Chunks<'a> {
+    Memory(std::slice::Chunks<'a, u8>),
+    Spooled { file: File, chunk_size: usize },
+}
+
+impl Chunks<'_> {
+    pub async fn next(&mut self) -> io::Result<Option<Vec<u8>>> {
+        match self {
+            Chunks::Memory(chunks) => Ok(chunks.next().map(<[u8]>::to_vec)),
+            Chunks::Spooled { file, chunk_size } => {
+                let mut chunk = Vec::with_capacity(*chunk_size);
+                file.take(*chunk_size as u64)
+                    .read_to_end(&mut chunk)
+                    .await?;
+                Ok((!chunk.is_empty()).then_some(chunk))
+            }
+        }
+    }
+}
+
+// Removes spool files a stopped process left behind. Younger files are kept in case another
+// process shares the directory.
+pub async fn sweep_orphans(dir: &Path, grace: Duration, now: SystemTime) -> io::Result<usize> {
+    let mut entries = match tokio::fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(err),
+    };
+    let mut removed = 0;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if path.extension() != Some(OsStr::new(SPOOL_EXTENSION)) {
+            continue;
+        }
+        let modified = entry.metadata().await?.modified()?;
+        if now.duration_since(modified).unwrap_or_default() < grace {
+            continue;
+        }
+        tokio::fs::remove_file(&path).await?;
+        removed += 1;
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::scratch_dir;
+
+    fn spool_dir() -> PathBuf {
+        scratch_dir().join("spool")
+    }
+
+    fn settings(memory_threshold_bytes: u64, max_memory_bytes: u64) -> TransferSpoolSettings {
+        TransferSpoolSettings {
+            memory_threshold_bytes,
+            max_memory_bytes,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn uploads_spool_once_the_memory_ceiling_is_reached() {
+        let spool = Arc::new(TransferSpool::default());
+        let settings = settings(1000, 2500);
+        let dir = spool_dir();
+        let payload = STANDARD.encode([7u8; 900]);
+
+        let first = spool.decode(&settings, &dir, &payload).await.unwrap();
+        let second = spool.decode(&settings, &dir, &payload).await.unwrap();
+        let third = spool.decode(&settings, &dir, &payload).await.unwrap();
+        assert_eq!(
+            (first.is_spooled(), second.is_spooled(), third.is_spooled()),
+            (false, false, true)
+        );
+        let usage = spool.usage(&settings);
+        assert_eq!(
+            (usage.memory_bytes, usage.spooled_files, usage.spooled_bytes),
+            (1800, 1, 900)
+        );
+
+        // Releasing an in-memory upload makes room for the next one.
+        drop(first);
+        let fourth = spool.decode(&settings, &dir, &payload).await.unwrap();
+        assert!(!fourth.is_spooled());
+        let oversized = STANDARD.encode([7u8; 1001]);
+        assert!(spool
+            .decode(&settings, &dir, &oversized)
+            .await
+            .unwrap()
+            .is_spooled());
+
+        let mut chunks = third.chunks(512).await.unwrap();
+        let (mut read, mut sizes) = (Vec::new(), Vec::new());
+        while let Some(chunk) = chunks.next().await.unwrap() {
+            sizes.push(chunk.len());
+            read.extend(chunk);
+        }
+        assert_eq!((read, sizes), (vec![7u8; 900], vec![512, 388]));
+        assert_eq!(
+            third.offer("x.bin").await.unwrap(),
+            TransferOffer::for_bytes("x.bin", &[7u8; 900])
+        );
+
+        drop((second, third, fourth));
+        let usage = spool.usage(&settings);
+        assert_eq!(
+            (usage.memory_bytes, usage.spooled_files, usage.spooled_bytes),
+            (0, 0, 0)
+        );
+        assert_eq!((usage.memory_uploads, usage.spooled_uploads), (3, 2));
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn malformed_payloads_leave_no_spool_file_behind() {
+        let spool = Arc::new(TransferSpool::default());
+        let dir = spool_dir();
+        let payload = format!("{}!!!!", STANDARD.encode([1u8; 300]));
+        let refused = spool.decode(&settings(10, 100), &dir, &payload).await;
+        assert!(matches!(refused, Err(SpoolError::InvalidBase64)));
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        assert_eq!(spool.usage(&settings(10, 100)).spooled_files, 0);
+    }
+}
@@ -0,0 +1,338 @@
+﻿use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use retasync_storage::{
+    payload_digest, CanonicalTimestamp, EntityRecord, EntitySummary, RetasyncStorage, StorageError,
+    SyncConflict,
+};
+use serde_json::Value;
+use uuid::Uuid;
+
+type Result<T> = std::result::Result<T, StorageError>;
+
+// The entity data the entity routes, entity sync and the entity list handlers read and write.
+// `AppState` holds the node's `RetasyncStorage` here unless a host supplies its own with
+// `AppState::with_store`; the rest of the node's data stays in `RetasyncStorage`.
+#[async_trait]
+pub trait ControlPlaneStore: Send + Sync {
+    async fn get_entity(&self, entity_type: &str, entity_id: &str) -> Result<Option<EntityRecord>>;
+
+    async fn get_entity_summary(
+        &self,
+        entity_type: &str,
+        entity_id: &str,
+    ) -> Result<Option<EntitySummary>>;
+
+    // Entities of one type whose id starts with `prefix`, tombstones included, ordered by id.
+    async fn list_entities(&self, entity_type: &str, prefix: &str) -> Result<Vec<EntityRecord>>;
+
+    // Stores `entity` as given, version included, as replication does.
+    async fn put_entity(&self, entity: &EntityRecord) -> Result<()>;
+
+    // Writes `record` as the version after `expected_version`, where 0 creates the entity. Any
+    // other version is refused with `StorageError::VersionConflict`.
+    async fn update_entity(
+        &self,
+        entity_type: &str,
+        entity_id: &str,
+        record: &Value,
+        expected_version: i64,
+    ) -> Result<EntityRecord>;
+
+    async fn soft_delete_entity(
+        &self,
+        entity_type: &str,
+        entity_id: &str,
+        expected_version: i64,
+    ) -> Result<EntityRecord>;
+
+    async fn record_sync_conflict(
+        &self,
+        peer_identity: &str,
+        local: &EntityRecord,
+        remote: &EntityRecord,
+        kept: &str,
+    ) -> Result<SyncConflict>;
+
+    async fn list_sync_conflicts(&self, entity_type: &str) -> Result<Vec<SyncConflict>>;
+
+    async fn last_entity_sync(
+        &self,
+        entity_type: &str,
+        peer_identity: &str,
+    ) -> Result<Option<DateTime<Utc>>>;
+
+    async fn set_last_entity_sync(
+        &self,
+        entity_type: &str,
+        peer_identity: &str,
+        at: DateTime<Utc>,
+    ) -> Result<()>;
+}
+
+#[async_trait]
+impl ControlPlaneStore for RetasyncStorage {
+    async fn get_entity(&self, entity_type: &str, entity_id: &str) -> Result<Option<EntityRecord>> {
+        RetasyncStorage::get_entity(self, entity_type, entity_id).await
+    }
+
+    async fn get_entity_summary(
+        &self,
+        entity_type: &str,
+        entity_id: &str,
+    ) -> Result<Option<EntitySummary>> {
+        RetasyncStorage::get_entity_summary(self, entity_type, entity_id).await
+    }
+
+    async fn list_entities(&self, entity_type: &str, prefix: &str) -> Result<Vec<EntityRecord>> {
+        RetasyncStorage::list_entities(self, entity_type, prefix).await
+    }
+
+    async fn put_entity(&self, entity: &EntityRecord) -> Result<()> {
+        RetasyncStorage::put_entity(self, entity).await
+    }
+
+    async fn update_entity(
+        &self,
+        entity_type: &str,
+        entity_id: &str,
+        record: &Value,
+        expected_version: i64,
+    ) -> Result<EntityRecord> {
+        RetasyncStorage::update_entity(self, entity_type, entity_id, record, expected_version).await
+    }
+
+    async fn soft_delete_entity(
+        &self,
+        entity_type: &str,
+        entity_id: &str,
+        expected_version: i64,
+    ) -> Result<EntityRecord> {
+        RetasyncStorage::soft_delete_entity(self, entity_type, entity_id, expected_version).await
+    }
+
+    async fn record_sync_conflict(
+        &self,
+        peer_identity: &str,
+        local: &EntityRecord,
+        remote: &EntityRecord,
+        kept: &str,
+    ) -> Result<SyncConflict> {
+        RetasyncStorage::record_sync_conflict(self, peer_identity, local, remote, kept).await
+    }
+
+    async fn list_sync_conflicts(&self, entity_type: &str) -> Result<Vec<SyncConflict>> {
+        RetasyncStorage::list_sync_conflicts(self, entity_type).await
+    }
+
+    async fn last_entity_sync(
+        &self,
+        entity_type: &str,
+        peer_identity: &str,
+    ) -> Result<Option<DateTime<Utc>>> {
+        RetasyncStorage::last_entity_sync(self, entity_type, peer_identity).await
+    }
+
+    async fn set_last_entity_sync(
+        &self,
+        entity_type: &str,
+        peer_identity: &str,
+        at: DateTime<Utc>,
+    ) -> Result<()> {
+        RetasyncStorage::set_last_entity_sync(self, entity_type, peer_identity, at).await
+    }
+}
+
+// Keeps everything in memory and loses it with the process; for tests and hosts that keep no
+// entity data of their own.
+#[derive(Debug, Default)]
+pub struct InMemoryStore {
+    entities: Mutex<BTreeMap<(String, String), EntityRecord>>,
+    conflicts: Mutex<Vec<SyncConflict>>,
+    last_syncs: Mutex<BTreeMap<(String, String), DateTime<Utc>>>,
+}
+
+impl InMemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn entities(&self) -> std::sync::MutexGuard<'_, BTreeMap<(String, String), EntityRecord>> {
+        self.entities
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn entity_key(entity_type: &str, entity_id: &str) -> (String, String) {
+    (entity_type.to_string(), entity_id.to_string())
+}
+
+// What the store would hold instead of a write based on `expected_version`.
+fn version_conflict(current: Option<&EntityRecord>, expected_version: i64) -> StorageError {
+    match current {
+        Some(current) => StorageError::VersionConflict {
+            expected: expected_version,
+            current: Box::new(current.clone()),
+        },
+        None => StorageError::not_found("entity"),
+    }
+}
+
+#[async_trait]
+impl ControlPlaneStore for InMemoryStore {
+    async fn get_entity(&self, entity_type: &str, entity_id: &str) -> Result<Option<EntityRecord>> {
+        Ok(self
+            .entities()
+            .get(&entity_key(entity_type, entity_id))
+            .cloned())
+    }
+
+    async fn get_entity_summary(
+        &self,
+        entity_type: &str,
+        entity_id: &str,
+    ) -> Result<Option<EntitySummary>> {
+        let entity = ControlPlaneStore::get_entity(self, entity_type, entity_id).await?;
+        Ok(entity.map(|entity| {
+            let (record_bytes, record_sha256) = payload_digest(&entity.record.to_string());
+            EntitySummary {
+                entity_type: entity.entity_type,
+                entity_id: entity.entity_id,
+                updated_at: entity.updated_at,
+                version: entity.version,
+                deleted_at: entity.deleted_at,
+                record_bytes: Some(record_bytes),
+                record_sha256: Some(record_sha256),
+            }
+        }))
+    }
+
+    async fn list_entities(&self, entity_type: &str, prefix: &str) -> Result<Vec<EntityRecord>> {
+        Ok(self
+            .entities()
+            .values()
+            .filter(|entity| {
+                entity.entity_type == entity_type && entity.entity_id.starts_with(prefix)
+            })
+            .cloned()
+            .collect())
+    }
+
+    async fn put_entity(&self, entity: &EntityRecord) -> Result<()> {
+        self.entities().insert(
+            entity_key(&entity.entity_type, &entity.entity_id),
+            entity.clone(),
+        );
+        Ok(())
+    }
+
+    async fn update_entity(
+        &self,
+        entity_type: &str,
+        entity_id: &str,
+        record: &Value,
+        expected_version: i64,
+    ) -> Result<EntityRecord> {
+        let mut entities = self.entities();
+        let key = entity_key(entity_type, entity_id);
+        let current_version = entities.get(&key).map_or(0, |current| current.version);
+        if current_version != expected_version {
+            return Err(version_conflict(entities.get(&key), expected_version));
+        }
+        let entity = EntityRecord {
+            entity_type: entity_type.to_string(),
+            entity_id: entity_id.to_string(),
+            updated_at: CanonicalTimestamp::now().into(),
+            record: record.clone(),
+            version: expected_version + 1,
+            deleted_at: None,
+        };
+        entities.insert(key, entity.clone());
+        Ok(entity)
+    }
+
+    async fn soft_delete_entity(
+        &self,
+        entity_type: &str,
+        entity_id: &str,
+        expected_version: i64,
+    ) -> Result<EntityRecord> {
+        let mut entities = self.entities();
+        let key = entity_key(entity_type, entity_id);
+        let Some(entity) = entities
+            .get_mut(&key)
+            .filter(|entity| entity.version == expected_version && entity.deleted_at.is_none())
+        else {
+            return Err(version_conflict(entities.get(&key), expected_version));
+        };
+        let deleted_at = CanonicalTimestamp::now().into();
+        entity.updated_at = deleted_at;
+        entity.deleted_at = Some(deleted_at);
+        entity.version += 1;
+        Ok(entity.clone())
+    }
+
+    async fn record_sync_conflict(
+        &self,
+        peer_identity: &str,
+        local: &EntityRecord,
+        remote: &EntityRecord,
+        kept: &str,
+    ) -> Result<SyncConflict> {
+        let conflict = SyncConflict {
+            conflict_id: Uuid::now_v7().to_string(),
+            entity_type: local.entity_type.clone(),
+            entity_id: local.entity_id.clone(),
+            peer_identity: peer_identity.to_string(),
+            local: local.clone(),
+            remote: remote.clone(),
+            kept: kept.to_string(),
+            detected_at: CanonicalTimestamp::now().to_string(),
+        };
+        self.conflicts
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .push(conflict.clone());
+        Ok(conflict)
+    }
+
+    async fn list_sync_conflicts(&self, entity_type: &str) -> Result<Vec<SyncConflict>> {
+        Ok(self
+            .conflicts
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .iter()
+            .filter(|conflict| conflict.entity_type == entity_type)
+            .cloned()
+            .collect())
+    }
+
+    async fn last_entity_sync(
+        &self,
+        entity_type: &str,
+        peer_identity: &str,
+    ) -> Result<Option<DateTime<Utc>>> {
+        Ok(self
+            .last_syncs
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .get(&entity_key(entity_type, peer_identity))
+            .copied())
+    }
+
+    async fn set_last_entity_sync(
+        &self,
+        entity_type: &str,
+        peer_identity: &str,
+        at: DateTime<Utc>,
+    ) -> Result<()> {
+        self.last_syncs
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .insert(entity_key(entity_type, peer_identity), at);
+        Ok(())
+    }
+}
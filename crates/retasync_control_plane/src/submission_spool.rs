@@ -0,0 +1,585 @@
+﻿use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use retasync_contract::{decode_canonical, encode_canonical, CodecError};
+use retasync_storage::{CanonicalTimestamp, JobRecord, SubmissionSource};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use tokio::fs::{self, OpenOptions};
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::{Mutex, MutexGuard};
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+use crate::app::drain_submission_spool;
+use crate::labels::Labels;
+use crate::AppState;
+
+// What a job accepted into the spool reports until storage takes it.
+pub const SPOOLED_STATUS: &str = "spooled";
+
+const SPOOL_EXTENSION: &str = "submissions";
+// A record is its body length as a big-endian u32, the SHA-256 of the body, then the body.
+const LENGTH_BYTES: usize = 4;
+const CHECKSUM_BYTES: usize = 32;
+const HEADER_BYTES: usize = LENGTH_BYTES + CHECKSUM_BYTES;
+
+// The `[submission_spool]` section: where submissions wait while storage is unavailable.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SubmissionSpoolSettings {
+    // Off sends the plain 503 when storage cannot take a submission.
+    pub enabled: bool,
+    // Defaults to `<database>.submissions` beside the database file.
+    pub path: Option<String>,
+    // Once the spool file is this large, submissions storage cannot take are refused with 503.
+    pub max_bytes: u64,
+    pub drain_interval_ms: u64,
+}
+
+impl Default for SubmissionSpoolSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            path: None,
+            max_bytes: 16 * 1024 * 1024,
+            drain_interval_ms: 1000,
+        }
+    }
+}
+
+impl SubmissionSpoolSettings {
+    pub fn path_for(&self, database_path: &Path) -> PathBuf {
+        match &self.path {
+            Some(path) => PathBuf::from(path),
+            None => {
+                let mut path = database_path.as_os_str().to_owned();
+                path.push(".");
+                path.push(SPOOL_EXTENSION);
+                PathBuf::from(path)
+            }
+        }
+    }
+
+    pub fn drain_interval(&self) -> Duration {
+        Duration::from_millis(self.drain_interval_ms.max(100))
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum SubmissionSpoolError {
+    #[error("submission spool holds {bytes} of its {max_bytes} bytes")]
+    Full { bytes: u64, max_bytes: u64 },
+    #[error("submission spool file: {0}")]
+    Io(#[from] io::Error),
+    #[error("submission spool record: {0}")]
+    Codec(#[from] CodecError),
+}
+
+// A submission as it waits in the spool: everything storage would have recorded for it,
+// under the job id and submission time its client was already given.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SpooledSubmission {
+    pub job_id: String,
+    pub submitted_at: CanonicalTimestamp,
+    pub operation: String,
+    #[serde(default)]
+    pub requested_operation: Option<String>,
+    pub payload: Value,
+    pub dispatch: Value,
+    #[serde(default)]
+    pub submitted_by: Option<String>,
+    pub source: SubmissionSource,
+    #[serde(default)]
+    pub labels: Labels,
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+    #[serde(default)]
+    pub idempotency_key: Option<String>,
+}
+
+impl SpooledSubmission {
+    // The job as a client sees it before storage has it.
+    pub fn job_record(&self) -> JobRecord {
+        let submitted_at = self.submitted_at.to_string();
+        JobRecord {
+            job_id: self.job_id.clone(),
+            operation: self.operation.clone(),
+            status: SPOOLED_STATUS.to_string(),
+            payload_json: self.payload.to_string(),
+            submitted_at: submitted_at.clone(),
+            updated_at: submitted_at,
+            failure_reason: None,
+            dispatch_json: Some(self.dispatch.to_string()),
+            requested_operation: self.requested_operation.clone(),
+        }
+    }
+}
+
+// What `/v1/node/status` reports under `submission_spool`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SubmissionSpoolStatus {
+    pub depth: u64,
+    pub bytes: u64,
+    pub max_bytes: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub oldest_submitted_at: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub oldest_age_secs: Option<i64>,
+}
+
+// One spooled submission as the in-memory index keeps it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpoolEntry {
+    pub job_id: String,
+    pub operation: String,
+    pub submitted_at: CanonicalTimestamp,
+    pub idempotency_key: Option<String>,
+    // Offset just past the entry's record.
+    end: u64,
+}
+
+impl SpoolEntry {
+    fn of(submission: &SpooledSubmission, end: u64) -> Self {
+        Self {
+            job_id: submission.job_id.clone(),
+            operation: submission.operation.clone(),
+            submitted_at: submission.submitted_at,
+            idempotency_key: submission.idempotency_key.clone(),
+            end,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct SpoolIndex {
+    loaded: bool,
+    entries: Vec<SpoolEntry>,
+    bytes: u64,
+}
+
+// Submissions accepted while storage could not take them, in an append-only file of
+// length-prefixed canonical MessagePack records. Each record carries the SHA-256 of its body,
+// so a write torn by a crash costs only the record it was writing. The file is read once, on
+// first use; after that an index in memory answers lookups and status.
+#[derive(Debug)]
+pub struct SubmissionSpool {
+    path: PathBuf,
+    index: Mutex<SpoolIndex>,
+    // Bytes the next append writes before failing, as a full disk would leave them.
+    #[cfg(test)]
+    torn_write: std::sync::Mutex<Option<usize>>,
+}
+
+impl SubmissionSpool {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            index: Mutex::new(SpoolIndex::default()),
+            #[cfg(test)]
+            torn_write: std::sync::Mutex::default(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Cuts a torn or corrupt tail off the file the first time it is read.
+    async fn loaded(&self) -> Result<MutexGuard<'_, SpoolIndex>, SubmissionSpoolError> {
+        let mut index = self.index.lock().await;
+        if index.loaded {
+            return Ok(index);
+        }
+        let bytes = read_or_empty(&self.path).await?;
+        let (records, valid) = read_records(&bytes);
+        if valid < bytes.len() {
+            warn!(
+                path = %self.path.display(),
+                dropped_bytes = bytes.len() - valid,
+                "dropped a torn record from the end of the submission spool"
+            );
+            let file = OpenOptions::new().write(true).open(&self.path).await?;
+            file.set_len(valid as u64).await?;
+            file.sync_data().await?;
+        }
+        index.entries = records
+            .iter()
+            .map(|(submission, end)| SpoolEntry::of(submission, *end))
+            .collect();
+        index.bytes = valid as u64;
+        index.loaded = true;
+        Ok(index)
+    }
+
+    // Writes every submission or none: they go out in one write, synced before this returns.
+    // A write that fails partway is cut back off, so the next append follows the last whole
+    // record rather than the fragment.
+    pub async fn append(
+        &self,
+        submissions: &[SpooledSubmission],
+        max_bytes: u64,
+    ) -> Result<(), SubmissionSpoolError> {
+        let mut index = self.loaded().await?;
+        let mut frames = Vec::new();
+        let mut entries = Vec::with_capacity(submissions.len());
+        for submission in submissions {
+            frames.extend(frame(&encode_canonical(submission)?));
+            entries.push(SpoolEntry::of(submission, index.bytes + frames.len() as u64));
+        }
+        let bytes = index.bytes + frames.len() as u64;
+        if bytes > max_bytes {
+            return Err(SubmissionSpoolError::Full {
+                bytes: index.bytes,
+                max_bytes,
+            });
+        }
+        if let Some(parent) = self.path.parent().filter(|parent| !parent.as_os_str().is_empty())
+        {
+            fs::create_dir_all(parent).await?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(&self.path)
+            .await?;
+        if let Err(err) = self.write_frames(&mut file, index.bytes, &frames).await {
+            file.set_len(index.bytes).await?;
+            file.sync_data().await?;
+            return Err(err.into());
+        }
+        index.entries.extend(entries);
+        index.bytes = bytes;
+        Ok(())
+    }
+
+    // Writes `frames` at `offset`, dropping anything a failed write left past them.
+    async fn write_frames(
+        &self,
+        file: &mut fs::File,
+        offset: u64,
+        frames: &[u8],
+    ) -> io::Result<()> {
+        file.seek(io::SeekFrom::Start(offset)).await?;
+        #[cfg(test)]
+        let torn = self
+            .torn_write
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .take();
+        #[cfg(test)]
+        if let Some(written) = torn {
+            file.write_all(&frames[..written.min(frames.len())]).await?;
+            return Err(io::Error::other("injected torn write"));
+        }
+        file.write_all(frames).await?;
+        file.set_len(offset + frames.len() as u64).await?;
+        file.sync_data().await
+    }
+
+    pub async fn is_empty(&self) -> Result<bool, SubmissionSpoolError> {
+        Ok(self.loaded().await?.entries.is_empty())
+    }
+
+    pub async fn get(
+        &self,
+        job_id: &str,
+    ) -> Result<Option<SpooledSubmission>, SubmissionSpoolError> {
+        if !self.loaded().await?.entries.iter().any(|entry| entry.job_id == job_id) {
+            return Ok(None);
+        }
+        let pending = self.pending().await?;
+        Ok(pending
+            .into_iter()
+            .map(|(submission, _)| submission)
+            .find(|submission| submission.job_id == job_id))
+    }
+
+    pub async fn find_key(&self, key: &str) -> Result<Option<SpoolEntry>, SubmissionSpoolError> {
+        let index = self.loaded().await?;
+        Ok(index
+            .entries
+            .iter()
+            .find(|entry| entry.idempotency_key.as_deref() == Some(key))
+            .cloned())
+    }
+
+    pub async fn status(
+        &self,
+        max_bytes: u64,
+        now: CanonicalTimestamp,
+    ) -> Result<SubmissionSpoolStatus, SubmissionSpoolError> {
+        let index = self.loaded().await?;
+        let oldest = index.entries.first().map(|entry| entry.submitted_at);
+        Ok(SubmissionSpoolStatus {
+            depth: index.entries.len() as u64,
+            bytes: index.bytes,
+            max_bytes,
+            oldest_submitted_at: oldest.map(|at| at.to_string()),
+            oldest_age_secs: oldest.map(|at| {
+                (now.as_datetime() - at.as_datetime()).num_seconds().max(0)
+            }),
+        })
+    }
+
+    // Every spooled submission, oldest first, each with the offset `release` takes to drop
+    // it and everything before it.
+    pub async fn pending(&self) -> Result<Vec<(SpooledSubmission, u64)>, SubmissionSpoolError> {
+        let index = self.loaded().await?;
+        if index.entries.is_empty() {
+            return Ok(Vec::new());
+        }
+        let bytes = fs::read(&self.path).await?;
+        let end = (index.bytes as usize).min(bytes.len());
+        Ok(read_records(&bytes[..end]).0)
+    }
+
+    // Drops the records before `through`; whatever was appended since `pending` stays.
+    pub async fn release(&self, through: u64) -> Result<(), SubmissionSpoolError> {
+        let mut index = self.loaded().await?;
+        let through = through.min(index.bytes);
+        if through == 0 {
+            return Ok(());
+        }
+        if through == index.bytes {
+            match fs::remove_file(&self.path).await {
+                Ok(()) => {}
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err.into()),
+            }
+        } else {
+            let bytes = fs::read(&self.path).await?;
+            let rest = &bytes[through as usize..index.bytes as usize];
+            let mut staging = self.path.as_os_str().to_owned();
+            staging.push(".tmp");
+            let staging = PathBuf::from(staging);
+            let mut file = fs::File::create(&staging).await?;
+            file.write_all(rest).await?;
+            file.sync_data().await?;
+            fs::rename(&staging, &self.path).await?;
+        }
+        index.entries.retain(|entry| entry.end > through);
+        for entry in &mut index.entries {
+            entry.end -= through;
+        }
+        index.bytes -= through;
+        Ok(())
+    }
+}
+
+fn frame(body: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(HEADER_BYTES + body.len());
+    frame.extend((body.len() as u32).to_be_bytes());
+    frame.extend(Sha256::digest(body));
+    frame.extend(body);
+    frame
+}
+
+// The records up to the first one cut short or failing its checksum, each with the offset
+// just past it, and how many bytes they span together.
+fn read_records(bytes: &[u8]) -> (Vec<(SpooledSubmission, u64)>, usize) {
+    let mut records = Vec::new();
+    let mut offset = 0;
+    while let Some(header) = bytes.get(offset..offset + HEADER_BYTES) {
+        let (length, checksum) = header.split_at(LENGTH_BYTES);
+        let length = u32::from_be_bytes(length.try_into().expect("four length bytes")) as usize;
+        let start = offset + HEADER_BYTES;
+        let Some(body) = bytes.get(start..start + length) else {
+            break;
+        };
+        if Sha256::digest(body).as_slice() != checksum {
+            break;
+        }
+        let Ok(submission) = decode_canonical::<SpooledSubmission>(body) else {
+            break;
+        };
+        offset = start + length;
+        records.push((submission, offset as u64));
+    }
+    (records, offset)
+}
+
+async fn read_or_empty(path: &Path) -> io::Result<Vec<u8>> {
+    match fs::read(path).await {
+        Ok(bytes) => Ok(bytes),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(err) => Err(err),
+    }
+}
+
+pub fn spawn_submission_spool_drain(state: AppState, period: Duration) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(period);
+        loop {
+            ticker.tick().await;
+            match drain_submission_spool(&state).await {
+                Ok(0) => {}
+                Ok(stored) => info!(stored, "stored spooled submissions"),
+                Err(err) => error!(error = %err, "submission spool drain failed"),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::test_support::scratch_dir;
+
+    fn scratch() -> PathBuf {
+        scratch_dir().join("node.sqlite.submissions")
+    }
+
+    fn submission(operation: &str, key: Option<&str>) -> SpooledSubmission {
+        SpooledSubmission {
+            job_id: Uuid::now_v7().to_string(),
+            submitted_at: CanonicalTimestamp::now(),
+            operation: operation.to_string(),
+            requested_operation: None,
+            payload: json!({ "operation": operation, "note": null }),
+            dispatch: json!({ "destination_identity": "aa00000000000000000000000000000a" }),
+            submitted_by: Some("anonymous-loopback".to_string()),
+            source: SubmissionSource::default(),
+            labels: Labels::from([("team".to_string(), "north".to_string())]),
+            depends_on: Vec::new(),
+            idempotency_key: key.map(str::to_string),
+        }
+    }
+
+    #[tokio::test]
+    async fn spooled_submissions_come_back_in_order_until_released() {
+        let path = scratch();
+        let spool = SubmissionSpool::new(&path);
+        let first = submission("mesh.ping", Some("k-1"));
+        let second = submission("event.create", None);
+        spool.append(&[first.clone(), second.clone()], u64::MAX).await.unwrap();
+
+        let pending = spool.pending().await.unwrap();
+        assert_eq!(pending.len(), 2);
+        assert_eq!(pending[0].0, first);
+        assert_eq!(pending[1].0, second);
+        assert_eq!(spool.find_key("k-1").await.unwrap().unwrap().job_id, first.job_id);
+
+        // One appended after `pending` survives the release of what it returned.
+        let third = submission("event.update", None);
+        spool.append(std::slice::from_ref(&third), u64::MAX).await.unwrap();
+        spool.release(pending[1].1).await.unwrap();
+        let reopened = SubmissionSpool::new(&path);
+        let left = reopened.pending().await.unwrap();
+        assert_eq!(left.len(), 1);
+        assert_eq!(left[0].0, third);
+        assert!(reopened.find_key("k-1").await.unwrap().is_none());
+
+        reopened.release(left[0].1).await.unwrap();
+        assert!(reopened.is_empty().await.unwrap());
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn a_torn_final_record_is_dropped_and_the_rest_kept() {
+        let path = scratch();
+        let spool = SubmissionSpool::new(&path);
+        let (first, second) = (submission("mesh.ping", None), submission("event.create", None));
+        spool.append(&[first.clone(), second], u64::MAX).await.unwrap();
+        let whole = std::fs::metadata(&path).unwrap().len();
+        let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(whole - 5).unwrap();
+        drop(file);
+
+        let reopened = SubmissionSpool::new(&path);
+        let pending = reopened.pending().await.unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].0, first);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), pending[0].1);
+
+        // Appends after recovery land after the last whole record.
+        let third = submission("event.update", None);
+        reopened.append(std::slice::from_ref(&third), u64::MAX).await.unwrap();
+        let pending = SubmissionSpool::new(&path).pending().await.unwrap();
+        assert_eq!(pending.len(), 2);
+        assert_eq!(pending[1].0, third);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_failed_append_leaves_no_fragment_for_later_records_to_follow() {
+        let path = scratch();
+        let spool = SubmissionSpool::new(&path);
+        let first = submission("mesh.ping", None);
+        spool.append(std::slice::from_ref(&first), u64::MAX).await.unwrap();
+        let whole = std::fs::metadata(&path).unwrap().len();
+
+        *spool.torn_write.lock().unwrap() = Some(HEADER_BYTES + 3);
+        let err = spool.append(&[submission("event.create", None)], u64::MAX).await;
+        assert!(matches!(err, Err(SubmissionSpoolError::Io(_))), "{err:?}");
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), whole);
+
+        let second = submission("event.update", None);
+        spool.append(std::slice::from_ref(&second), u64::MAX).await.unwrap();
+        let pending = SubmissionSpool::new(&path).pending().await.unwrap();
+        let kept: Vec<_> = pending.into_iter().map(|(submission, _)| submission).collect();
+        assert_eq!(kept, [first, second]);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_record_failing_its_checksum_ends_the_spool() {
+        let path = scratch();
+        let spool = SubmissionSpool::new(&path);
+        spool.append(&[submission("mesh.ping", None)], u64::MAX).await.unwrap();
+        let mut bytes = std::fs::read(&path).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        std::fs::write(&path, &bytes).unwrap();
+
+        let reopened = SubmissionSpool::new(&path);
+        assert!(reopened.is_empty().await.unwrap());
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 0);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn appends_past_the_cap_are_refused_whole() {
+        let path = scratch();
+        let spool = SubmissionSpool::new(&path);
+        let one = submission("mesh.ping", None);
+        spool.append(std::slice::from_ref(&one), u64::MAX).await.unwrap();
+        let used = std::fs::metadata(&path).unwrap().len();
+
+        let err = spool
+            .append(&[submission("mesh.ping", None), submission("mesh.ping", None)], used * 2)
+            .await
+            .unwrap_err();
+        let SubmissionSpoolError::Full { bytes, max_bytes } = err else {
+            panic!("expected a full spool, got {err}");
+        };
+        assert_eq!((bytes, max_bytes), (used, used * 2));
+        let status = spool.status(used * 2, one.submitted_at).await.unwrap();
+        assert_eq!((status.depth, status.bytes, status.oldest_age_secs), (1, used, Some(0)));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn the_default_path_sits_beside_the_database() {
+        let settings = SubmissionSpoolSettings::default();
+        assert_eq!(
+            settings.path_for(Path::new("/var/lib/retasync/node.sqlite")),
+            PathBuf::from("/var/lib/retasync/node.sqlite.submissions")
+        );
+        let configured = SubmissionSpoolSettings {
+            path: Some("/spool/submissions".to_string()),
+            ..SubmissionSpoolSettings::default()
+        };
+        assert_eq!(
+            configured.path_for(Path::new("node.sqlite")),
+            PathBuf::from("/spool/submissions")
+        );
+    }
+}
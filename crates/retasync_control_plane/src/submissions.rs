@@ -0,0 +1,174 @@
+﻿use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_MAX_BATCH_SIZE: usize = 100;
+// Past this many clients, budgets that have refilled completely are dropped.
+const MAX_IDLE_BUDGETS: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SubmissionSettings {
+    pub max_batch_size: usize,
+    // Command jobs accepted per minute and client across single and batch submissions; 0
+    // disables the limit.
+    pub rate_per_minute: u32,
+}
+
+impl Default for SubmissionSettings {
+    fn default() -> Self {
+        Self {
+            max_batch_size: DEFAULT_MAX_BATCH_SIZE,
+            rate_per_minute: 0,
+        }
+    }
+}
+
+// Token bucket holding up to one minute of submissions, refilled continuously.
+#[derive(Debug, Default)]
+pub struct SubmissionBudget {
+    state: Option<(f64, Instant)>,
+}
+
+impl SubmissionBudget {
+    // Grants up to `requested` submissions and charges only for what it grants.
+    pub fn take(&mut self, rate_per_minute: u32, requested: usize, now: Instant) -> usize {
+        if rate_per_minute == 0 {
+            self.state = None;
+            return requested;
+        }
+        let tokens = self.tokens(rate_per_minute, now);
+        let granted = requested.min(tokens.floor() as usize);
+        self.state = Some((tokens - granted as f64, now));
+        granted
+    }
+
+    // What `take` would grant right now, without charging for it; `None` when unlimited.
+    pub fn available(&self, rate_per_minute: u32, now: Instant) -> Option<usize> {
+        (rate_per_minute != 0).then(|| self.tokens(rate_per_minute, now).floor() as usize)
+    }
+
+    // How long until the bucket holds a whole submission again; zero when it already does.
+    pub fn refill_in(&self, rate_per_minute: u32, now: Instant) -> Duration {
+        if rate_per_minute == 0 {
+            return Duration::ZERO;
+        }
+        let missing = 1.0 - self.tokens(rate_per_minute, now);
+        Duration::from_secs_f64(missing.max(0.0) * 60.0 / f64::from(rate_per_minute))
+    }
+
+    fn tokens(&self, rate_per_minute: u32, now: Instant) -> f64 {
+        let capacity = f64::from(rate_per_minute);
+        match self.state {
+            Some((tokens, refilled_at)) => {
+                let elapsed = now.saturating_duration_since(refilled_at).as_secs_f64();
+                (tokens + elapsed * capacity / 60.0).min(capacity)
+            }
+            None => capacity,
+        }
+    }
+}
+
+// One budget per client, keyed by `attribution::client_key`, so a client that spends its own
+// leaves everyone else's alone.
+#[derive(Debug, Default)]
+pub struct ClientBudgets {
+    budgets: HashMap<String, SubmissionBudget>,
+}
+
+impl ClientBudgets {
+    pub fn take(
+        &mut self,
+        client: &str,
+        rate_per_minute: u32,
+        requested: usize,
+        now: Instant,
+    ) -> usize {
+        if rate_per_minute == 0 {
+            self.budgets.clear();
+            return requested;
+        }
+        if self.budgets.len() >= MAX_IDLE_BUDGETS && !self.budgets.contains_key(client) {
+            let capacity = rate_per_minute as usize;
+            self.budgets
+                .retain(|_, budget| budget.available(rate_per_minute, now) != Some(capacity));
+        }
+        self.budgets
+            .entry(client.to_string())
+            .or_default()
+            .take(rate_per_minute, requested, now)
+    }
+
+    pub fn available(&self, client: &str, rate_per_minute: u32, now: Instant) -> Option<usize> {
+        match self.budgets.get(client) {
+            Some(budget) => budget.available(rate_per_minute, now),
+            None => SubmissionBudget::default().available(rate_per_minute, now),
+        }
+    }
+
+    pub fn refill_in(&self, client: &str, rate_per_minute: u32, now: Instant) -> Duration {
+        self.budgets
+            .get(client)
+            .map_or(Duration::ZERO, |budget| budget.refill_in(rate_per_minute, now))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ClientBudgets, SubmissionBudget};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn budget_grants_what_is_left_and_refills_over_time() {
+        let mut budget = SubmissionBudget::default();
+        let start = Instant::now();
+        assert_eq!(budget.take(0, 500, start), 500);
+
+        assert_eq!(budget.take(60, 45, start), 45);
+        assert_eq!(budget.take(60, 30, start), 15);
+        assert_eq!(budget.take(60, 1, start), 0);
+        assert_eq!(budget.take(60, 30, start + Duration::from_secs(10)), 10);
+        assert_eq!(budget.take(60, 100, start + Duration::from_secs(600)), 60);
+    }
+
+    #[test]
+    fn available_reports_without_charging() {
+        let mut budget = SubmissionBudget::default();
+        let start = Instant::now();
+        assert_eq!(budget.available(0, start), None);
+        assert_eq!(budget.available(60, start), Some(60));
+        assert_eq!(budget.take(60, 50, start), 50);
+        assert_eq!(budget.available(60, start), Some(10));
+        assert_eq!(budget.available(60, start), Some(10));
+        assert_eq!(budget.take(60, 10, start), 10);
+    }
+
+    #[test]
+    fn refill_in_is_the_time_to_the_next_whole_submission() {
+        let mut budget = SubmissionBudget::default();
+        let start = Instant::now();
+        assert_eq!(budget.refill_in(0, start), Duration::ZERO);
+        assert_eq!(budget.take(30, 30, start), 30);
+        assert_eq!(budget.refill_in(30, start), Duration::from_secs(2));
+        let later = start + Duration::from_millis(500);
+        let left = budget.refill_in(30, later).as_secs_f64();
+        assert!((left - 1.5).abs() < 1e-6, "{left}");
+        assert_eq!(budget.refill_in(30, start + Duration::from_secs(2)), Duration::ZERO);
+    }
+
+    #[test]
+    fn each_client_spends_its_own_budget() {
+        let mut budgets = ClientBudgets::default();
+        let start = Instant::now();
+        assert_eq!(budgets.take("ops-console", 10, 10, start), 10);
+        assert_eq!(budgets.take("ops-console", 10, 1, start), 0);
+        assert_eq!(budgets.available("ops-console", 10, start), Some(0));
+        assert_eq!(budgets.available("tak-bridge", 10, start), Some(10));
+        assert_eq!(budgets.take("tak-bridge", 10, 4, start), 4);
+        assert_eq!(
+            budgets.take("ops-console", 10, 5, start + Duration::from_secs(30)),
+            5
+        );
+    }
+}
@@ -0,0 +1,75 @@
+﻿use std::cell::RefCell;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use retasync_mesh_bridge::{InMemoryRpcMeshBridge, RpcMeshBridge};
+use retasync_storage::{RetasyncStorage, StorageConfig, DEFAULT_READ_POOL_SIZE};
+use serde_json::{json, Value};
+use tempfile::TempDir;
+
+use crate::{AppState, NodeConfig};
+
+thread_local! {
+    // Scratch directories for test databases and files. Each test runs on a thread of its own,
+    // so they are removed when the test that made them finishes.
+    static SCRATCH: RefCell<Vec<TempDir>> = const { RefCell::new(Vec::new()) };
+}
+
+// An empty directory that lasts until the calling test finishes.
+pub fn scratch_dir() -> PathBuf {
+    let dir = tempfile::Builder::new()
+        .prefix("retasync-test-")
+        .tempdir()
+        .expect("scratch directory");
+    let path = dir.path().to_path_buf();
+    SCRATCH.with(|scratch| scratch.borrow_mut().push(dir));
+    path
+}
+
+// A database path no other test uses, in its own scratch directory.
+pub fn scratch_sqlite() -> String {
+    scratch_dir()
+        .join("node.sqlite")
+        .to_string_lossy()
+        .into_owned()
+}
+
+pub async fn test_storage(sqlite_path: &str) -> RetasyncStorage {
+    RetasyncStorage::connect(&StorageConfig {
+        sqlite_path: sqlite_path.to_string(),
+        encryption_key_path: None,
+        read_pool_size: DEFAULT_READ_POOL_SIZE,
+    })
+    .await
+    .expect("storage")
+}
+
+// The smallest config a node loads, with each key of `overrides` laid over it.
+pub fn node_config(overrides: Value) -> NodeConfig {
+    let mut config = json!({
+        "rpc_endpoint": "tcp://127.0.0.1:31337",
+        "http_bind": "127.0.0.1:8080",
+        "http_auth_token": null,
+        "sqlite_path": "test.sqlite",
+        "acl_mode": "allowlist",
+        "prefer_link": true
+    });
+    if let (Some(config), Value::Object(overrides)) = (config.as_object_mut(), overrides) {
+        config.extend(overrides);
+    }
+    serde_json::from_value(config).expect("node config")
+}
+
+// A node on `bridge` with a fresh database and `node_config(overrides)`.
+pub async fn node_state(bridge: Arc<dyn RpcMeshBridge>, overrides: Value) -> AppState {
+    let sqlite_path = scratch_sqlite();
+    let storage = test_storage(&sqlite_path).await;
+    let mut node_config = node_config(overrides);
+    node_config.sqlite_path = sqlite_path;
+    AppState::new(storage, bridge, node_config, "asyncapi: 3.0.0\n".to_string(), false)
+}
+
+// A node on an in-memory bridge with a fresh database and the default config.
+pub async fn test_state() -> AppState {
+    node_state(Arc::new(InMemoryRpcMeshBridge::new(true, true)), json!({})).await
+}
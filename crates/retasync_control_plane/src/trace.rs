@@ -0,0 +1,226 @@
+﻿use chrono::{DateTime, Utc};
+use retasync_contract::{HopRecord, TransferHint};
+use retasync_mesh_bridge::TransportSelection;
+use serde::{Deserialize, Serialize};
+
+pub const TRACING_ENABLED_FIELD: &str = "tracing_enabled";
+pub const COMMAND_FORWARDED_EVENT: &str = "inbound.command.forwarded";
+pub const FORWARD_FAILED_ERROR: &str = "forward_failed";
+pub const DEFAULT_MAX_TRACE_HOPS: usize = 16;
+// The origin and the newest hop are always kept.
+const MIN_TRACE_HOPS: usize = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RoutingSettings {
+    // Commands addressed to one of these identities, or to any other node with "*", are
+    // relayed on instead of handled here.
+    pub relay_destinations: Vec<String>,
+    pub max_trace_hops: usize,
+}
+
+impl Default for RoutingSettings {
+    fn default() -> Self {
+        Self {
+            relay_destinations: Vec::new(),
+            max_trace_hops: DEFAULT_MAX_TRACE_HOPS,
+        }
+    }
+}
+
+impl RoutingSettings {
+    pub fn relays(&self, destination: &str) -> bool {
+        self.relay_destinations
+            .iter()
+            .any(|relayed| relayed == "*" || relayed == destination)
+    }
+}
+
+pub fn transport_hint(transport: &TransportSelection) -> TransferHint {
+    match transport {
+        TransportSelection::Link => TransferHint::Link,
+        TransportSelection::Lxmf => TransferHint::Lxmf,
+    }
+}
+
+// Once the trace is full the oldest hop after the origin goes, so both ends of the path stay
+// visible however long it is.
+pub fn push_hop(
+    trace: &mut Vec<HopRecord>,
+    truncated: &mut bool,
+    hop: HopRecord,
+    max_hops: usize,
+) {
+    let max_hops = max_hops.max(MIN_TRACE_HOPS);
+    while trace.len() >= max_hops {
+        trace.remove(1);
+        *truncated = true;
+    }
+    trace.push(hop);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LegKind {
+    // On the wire between two nodes.
+    Transit,
+    // Inside one node, from receipt to sending the envelope or its result on.
+    Processing,
+    // Between two recorded hops with dropped hops in between.
+    Gap,
+    // From the answering node back to the origin.
+    Return,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TraceLeg {
+    pub kind: LegKind,
+    pub from: String,
+    pub to: String,
+    pub started_at: DateTime<Utc>,
+    pub ended_at: DateTime<Utc>,
+    pub latency_ms: i64,
+    // The leg ends before it starts, so the two clocks disagree.
+    pub clock_skew: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TraceTimeline {
+    pub job_id: String,
+    pub hops: Vec<HopRecord>,
+    pub legs: Vec<TraceLeg>,
+    pub returned_at: Option<DateTime<Utc>>,
+    pub round_trip_ms: Option<i64>,
+    pub truncated: bool,
+    pub clock_skew: bool,
+}
+
+fn leg(
+    kind: LegKind,
+    from: (&str, DateTime<Utc>),
+    to: (&str, DateTime<Utc>),
+) -> TraceLeg {
+    let latency_ms = (to.1 - from.1).num_milliseconds();
+    TraceLeg {
+        kind,
+        from: from.0.to_string(),
+        to: to.0.to_string(),
+        started_at: from.1,
+        ended_at: to.1,
+        latency_ms,
+        clock_skew: latency_ms < 0,
+    }
+}
+
+// Latencies come from timestamps taken on different nodes, so a negative one is reported as
+// skew rather than hidden.
+pub fn timeline(
+    job_id: &str,
+    hops: Vec<HopRecord>,
+    truncated: bool,
+    returned_at: Option<DateTime<Utc>>,
+) -> TraceTimeline {
+    let mut legs = Vec::new();
+    let mut last: Option<(&str, DateTime<Utc>)> = None;
+    for (index, hop) in hops.iter().enumerate() {
+        if let (Some(from), Some(received_at)) = (last, hop.received_at) {
+            let kind = if truncated && index == 1 {
+                LegKind::Gap
+            } else {
+                LegKind::Transit
+            };
+            legs.push(leg(kind, from, (&hop.identity, received_at)));
+        }
+        if let (Some(received_at), Some(forwarded_at)) = (hop.received_at, hop.forwarded_at) {
+            legs.push(leg(
+                LegKind::Processing,
+                (&hop.identity, received_at),
+                (&hop.identity, forwarded_at),
+            ));
+        }
+        if let Some(at) = hop.forwarded_at.or(hop.received_at) {
+            last = Some((&hop.identity, at));
+        }
+    }
+    let origin = hops.first();
+    if let (Some(origin), Some(from), Some(returned_at)) = (origin, last, returned_at) {
+        if hops.len() > 1 {
+            legs.push(leg(LegKind::Return, from, (&origin.identity, returned_at)));
+        }
+    }
+
+    let round_trip_ms = origin
+        .and_then(|origin| origin.forwarded_at)
+        .zip(returned_at)
+        .map(|(sent_at, returned_at)| (returned_at - sent_at).num_milliseconds());
+    TraceTimeline {
+        job_id: job_id.to_string(),
+        clock_skew: legs.iter().any(|leg| leg.clock_skew),
+        hops,
+        legs,
+        returned_at,
+        round_trip_ms,
+        truncated,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{push_hop, timeline, LegKind};
+    use chrono::{DateTime, Duration, Utc};
+    use retasync_contract::HopRecord;
+
+    fn hop(identity: &str, received_at: Option<i64>, forwarded_at: Option<i64>) -> HopRecord {
+        let base = DateTime::parse_from_rfc3339("2026-03-01T10:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        HopRecord {
+            identity: format!("{identity:0<32}").parse().unwrap(),
+            received_at: received_at.map(|ms| base + Duration::milliseconds(ms)),
+            forwarded_at: forwarded_at.map(|ms| base + Duration::milliseconds(ms)),
+            transport: None,
+            note: None,
+        }
+    }
+
+    #[test]
+    fn backwards_hops_are_flagged_as_clock_skew() {
+        let hops = vec![
+            hop("a", None, Some(0)),
+            // This node's clock runs 40ms behind the origin's.
+            hop("b", Some(-40), Some(-30)),
+            hop("c", Some(25), Some(35)),
+        ];
+        let returned_at = hops[0].forwarded_at.map(|sent| sent + Duration::milliseconds(60));
+        let timeline = timeline("job", hops, false, returned_at);
+
+        let legs: Vec<(LegKind, i64, bool)> = timeline
+            .legs
+            .iter()
+            .map(|leg| (leg.kind, leg.latency_ms, leg.clock_skew))
+            .collect();
+        assert_eq!(
+            legs,
+            [
+                (LegKind::Transit, -40, true),
+                (LegKind::Processing, 10, false),
+                (LegKind::Transit, 55, false),
+                (LegKind::Processing, 10, false),
+                (LegKind::Return, 25, false),
+            ]
+        );
+        assert!(timeline.clock_skew);
+        assert_eq!(timeline.round_trip_ms, Some(60));
+    }
+
+    #[test]
+    fn full_traces_keep_the_origin_and_the_newest_hops() {
+        let (mut trace, mut truncated) = (Vec::new(), false);
+        for identity in ["a", "b", "c", "d", "e"] {
+            push_hop(&mut trace, &mut truncated, hop(identity, None, None), 3);
+        }
+        let kept: Vec<&str> = trace.iter().map(|hop| &hop.identity[..1]).collect();
+        assert_eq!(kept, ["a", "d", "e"]);
+        assert!(truncated);
+    }
+}
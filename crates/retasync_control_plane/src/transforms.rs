@@ -0,0 +1,473 @@
+﻿use std::collections::BTreeMap;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::{Arc, RwLock};
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use crate::app::event_type_matches;
+use crate::crash::forget_caught_panic;
+use crate::dispatch::{is_operation_pattern, local_identity};
+use crate::NodeConfig;
+
+pub const TRANSFORM_TRACE_INCLUDE: &str = "transform_trace";
+
+// Hook run over a payload between the HTTP submission and the envelope (or, on the result
+// stage, between the peer's reply and the stored result). Errors fail the job.
+pub trait PayloadTransform: Send + Sync {
+    fn transform(&self, operation: &str, payload: Value) -> Result<Value, TransformError>;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransformError {
+    pub transform: String,
+    pub message: String,
+}
+
+impl TransformError {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            transform: String::new(),
+            message: message.into(),
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        "payload_transform_failed"
+    }
+
+    pub fn reason(&self) -> String {
+        format!("{}: {}: {}", self.code(), self.transform, self.message)
+    }
+
+    pub fn detail(&self) -> Value {
+        json!({ "transform": self.transform, "error": self.message })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransformStage {
+    #[default]
+    Command,
+    Result,
+}
+
+impl TransformStage {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TransformStage::Command => "command",
+            TransformStage::Result => "result",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransformKind {
+    Inject,
+    Remove,
+    Rename,
+}
+
+// One `[[transforms]]` entry. Fields are addressed by JSON pointer; `fields` is used by
+// `inject`, `pointers` by `remove` and `renames` (pointer to the new key name) by `rename`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TransformSettings {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub kind: TransformKind,
+    #[serde(default)]
+    pub stage: TransformStage,
+    #[serde(default = "all_operations")]
+    pub operations: Vec<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub fields: BTreeMap<String, Value>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pointers: Vec<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub renames: BTreeMap<String, String>,
+}
+
+fn all_operations() -> Vec<String> {
+    vec!["*".to_string()]
+}
+
+impl TransformSettings {
+    fn label(&self, index: usize) -> String {
+        self.name
+            .clone()
+            .unwrap_or_else(|| format!("transforms[{index}]"))
+    }
+}
+
+pub fn validate_transforms(transforms: &[TransformSettings]) -> Result<(), String> {
+    for (index, settings) in transforms.iter().enumerate() {
+        let label = settings.label(index);
+        if settings.operations.is_empty() {
+            return Err(format!("{label}: operations must not be empty"));
+        }
+        if let Some(pattern) = settings
+            .operations
+            .iter()
+            .find(|pattern| !is_operation_pattern(pattern))
+        {
+            return Err(format!(
+                "{label}: operation pattern {pattern:?} is malformed"
+            ));
+        }
+        let pointers: Vec<&String> = match settings.kind {
+            TransformKind::Inject => settings.fields.keys().collect(),
+            TransformKind::Remove => settings.pointers.iter().collect(),
+            TransformKind::Rename => settings.renames.keys().collect(),
+        };
+        if pointers.is_empty() {
+            return Err(format!(
+                "{label}: {:?} transform has nothing to do",
+                settings.kind
+            ));
+        }
+        if let Some(pointer) = pointers
+            .iter()
+            .find(|pointer| split_pointer(pointer).is_none())
+        {
+            return Err(format!(
+                "{label}: {pointer:?} is not a JSON pointer to a field"
+            ));
+        }
+        if let Some(key) = settings.renames.values().find(|key| key.is_empty()) {
+            return Err(format!("{label}: rename target {key:?} is empty"));
+        }
+    }
+    Ok(())
+}
+
+// Sets each field, creating missing parent objects. String values may use `{node_identity}`,
+// `{operation}` and `{now}`.
+pub struct InjectFields {
+    fields: Vec<(String, Value)>,
+    node_identity: String,
+}
+
+impl PayloadTransform for InjectFields {
+    fn transform(&self, operation: &str, mut payload: Value) -> Result<Value, TransformError> {
+        let now = Utc::now().to_rfc3339();
+        for (pointer, value) in &self.fields {
+            let value = render(
+                value,
+                &[
+                    ("{node_identity}", &self.node_identity),
+                    ("{operation}", operation),
+                    ("{now}", &now),
+                ],
+            );
+            let (parents, key) = split_pointer(pointer).expect("validated pointer");
+            let mut target = &mut payload;
+            for parent in parents {
+                let Value::Object(object) = target else {
+                    return Err(TransformError::new(format!(
+                        "{pointer}: parent is not an object"
+                    )));
+                };
+                target = object
+                    .entry(parent)
+                    .or_insert_with(|| Value::Object(Map::new()));
+            }
+            let Value::Object(object) = target else {
+                return Err(TransformError::new(format!(
+                    "{pointer}: parent is not an object"
+                )));
+            };
+            object.insert(key, value);
+        }
+        Ok(payload)
+    }
+}
+
+// Drops each field that is present; missing fields are not an error.
+pub struct RemoveFields {
+    pointers: Vec<String>,
+}
+
+impl PayloadTransform for RemoveFields {
+    fn transform(&self, _operation: &str, mut payload: Value) -> Result<Value, TransformError> {
+        for pointer in &self.pointers {
+            if let Some((parent, key)) = parent_of(&mut payload, pointer) {
+                match parent {
+                    Value::Object(object) => {
+                        object.remove(&key);
+                    }
+                    Value::Array(items) => {
+                        if let Ok(index) = key.parse::<usize>() {
+                            if index < items.len() {
+                                items.remove(index);
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(payload)
+    }
+}
+
+// Moves each field to a new key under the same parent; refuses to clobber an existing key.
+pub struct RenameFields {
+    renames: Vec<(String, String)>,
+}
+
+impl PayloadTransform for RenameFields {
+    fn transform(&self, _operation: &str, mut payload: Value) -> Result<Value, TransformError> {
+        for (pointer, new_key) in &self.renames {
+            let Some((Value::Object(object), key)) = parent_of(&mut payload, pointer) else {
+                continue;
+            };
+            if key == *new_key || !object.contains_key(&key) {
+                continue;
+            }
+            if object.contains_key(new_key) {
+                return Err(TransformError::new(format!(
+                    "{pointer}: renaming to {new_key:?} would overwrite an existing field"
+                )));
+            }
+            let value = object.remove(&key).expect("checked key");
+            object.insert(new_key.clone(), value);
+        }
+        Ok(payload)
+    }
+}
+
+struct RegisteredTransform {
+    name: String,
+    stage: TransformStage,
+    operations: Vec<String>,
+    transform: Arc<dyn PayloadTransform>,
+}
+
+impl RegisteredTransform {
+    fn applies_to(&self, stage: TransformStage, operation: &str) -> bool {
+        self.stage == stage
+            && self
+                .operations
+                .iter()
+                .any(|pattern| event_type_matches(pattern, operation))
+    }
+}
+
+#[derive(Default)]
+struct Registered {
+    configured: Vec<RegisteredTransform>,
+    custom: Vec<RegisteredTransform>,
+}
+
+// Transforms from config run in file order, then those registered in code in registration
+// order. Reconfiguring replaces only the former.
+#[derive(Default)]
+pub struct TransformRegistry {
+    registered: RwLock<Registered>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transformed {
+    pub applied: Vec<String>,
+    pub payload: Value,
+}
+
+impl TransformRegistry {
+    pub fn new(config: &NodeConfig) -> Self {
+        let registry = Self::default();
+        registry.configure(config);
+        registry
+    }
+
+    pub fn configure(&self, config: &NodeConfig) {
+        let node_identity = local_identity(config);
+        let configured = config
+            .transforms
+            .iter()
+            .enumerate()
+            .map(|(index, settings)| RegisteredTransform {
+                name: settings.label(index),
+                stage: settings.stage,
+                operations: settings.operations.clone(),
+                transform: built_in(settings, &node_identity),
+            })
+            .collect();
+        self.write().configured = configured;
+    }
+
+    pub fn register(
+        &self,
+        name: &str,
+        stage: TransformStage,
+        operations: &[&str],
+        transform: Arc<dyn PayloadTransform>,
+    ) {
+        self.write().custom.push(RegisteredTransform {
+            name: name.to_string(),
+            stage,
+            operations: operations
+                .iter()
+                .map(|pattern| pattern.to_string())
+                .collect(),
+            transform,
+        });
+    }
+
+    // A panicking transform is reported as a failed one, so it fails the job, not the worker.
+    pub fn apply(
+        &self,
+        stage: TransformStage,
+        operation: &str,
+        mut payload: Value,
+    ) -> Result<Transformed, TransformError> {
+        let registered = self
+            .registered
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut applied = Vec::new();
+        for entry in registered.configured.iter().chain(&registered.custom) {
+            if !entry.applies_to(stage, operation) {
+                continue;
+            }
+            let outcome = catch_unwind(AssertUnwindSafe(|| {
+                entry.transform.transform(operation, payload.clone())
+            }))
+            .unwrap_or_else(|_| {
+                forget_caught_panic();
+                Err(TransformError::new("transform panicked"))
+            });
+            payload = outcome.map_err(|error| TransformError {
+                transform: entry.name.clone(),
+                ..error
+            })?;
+            applied.push(entry.name.clone());
+        }
+        Ok(Transformed { applied, payload })
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, Registered> {
+        self.registered
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn built_in(settings: &TransformSettings, node_identity: &str) -> Arc<dyn PayloadTransform> {
+    match settings.kind {
+        TransformKind::Inject => Arc::new(InjectFields {
+            fields: settings
+                .fields
+                .iter()
+                .map(|(pointer, value)| (pointer.clone(), value.clone()))
+                .collect(),
+            node_identity: node_identity.to_string(),
+        }),
+        TransformKind::Remove => Arc::new(RemoveFields {
+            pointers: settings.pointers.clone(),
+        }),
+        TransformKind::Rename => Arc::new(RenameFields {
+            renames: settings
+                .renames
+                .iter()
+                .map(|(pointer, key)| (pointer.clone(), key.clone()))
+                .collect(),
+        }),
+    }
+}
+
+fn render(value: &Value, placeholders: &[(&str, &str)]) -> Value {
+    match value {
+        Value::String(text) => Value::String(
+            placeholders
+                .iter()
+                .fold(text.clone(), |text, (placeholder, replacement)| {
+                    text.replace(placeholder, replacement)
+                }),
+        ),
+        Value::Array(items) => Value::Array(
+            items
+                .iter()
+                .map(|item| render(item, placeholders))
+                .collect(),
+        ),
+        Value::Object(object) => Value::Object(
+            object
+                .iter()
+                .map(|(key, item)| (key.clone(), render(item, placeholders)))
+                .collect(),
+        ),
+        other => other.clone(),
+    }
+}
+
+// Splits `/a/b/c` into its unescaped parent tokens and final key; the root pointer has no key.
+pub(crate) fn split_pointer(pointer: &str) -> Option<(Vec<String>, String)> {
+    let mut tokens: Vec<String> = pointer
+        .strip_prefix('/')?
+        .split('/')
+        .map(|token| token.replace("~1", "/").replace("~0", "~"))
+        .collect();
+    let key = tokens.pop()?;
+    Some((tokens, key))
+}
+
+pub(crate) fn parent_of<'a>(
+    payload: &'a mut Value,
+    pointer: &str,
+) -> Option<(&'a mut Value, String)> {
+    let (parents, key) = split_pointer(pointer)?;
+    let mut target = payload;
+    for parent in parents {
+        target = match target {
+            Value::Object(object) => object.get_mut(&parent)?,
+            Value::Array(items) => items.get_mut(parent.parse::<usize>().ok()?)?,
+            _ => return None,
+        };
+    }
+    Some((target, key))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{validate_transforms, TransformSettings};
+
+    fn settings(value: serde_json::Value) -> Vec<TransformSettings> {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn settings_default_to_every_command_and_validate_pointers() {
+        let transforms = settings(json!([
+            {
+                "name": "stamp",
+                "kind": "inject",
+                "operations": ["telemetry.*"],
+                "fields": { "/meta/origin": "{node_identity}" }
+            },
+            { "kind": "rename", "stage": "result", "renames": { "/cs": "callsign" } }
+        ]));
+        assert_eq!(transforms[1].operations, vec!["*"]);
+        assert!(validate_transforms(&transforms).is_ok());
+
+        let bad_pointer = settings(json!([{ "kind": "remove", "pointers": ["client_only"] }]));
+        assert!(validate_transforms(&bad_pointer)
+            .unwrap_err()
+            .contains("not a JSON pointer"));
+        let empty = settings(json!([{ "kind": "inject" }]));
+        assert!(validate_transforms(&empty)
+            .unwrap_err()
+            .contains("nothing to do"));
+        let bad_pattern = settings(json!([
+            { "kind": "remove", "pointers": ["/x"], "operations": ["a.*.b"] }
+        ]));
+        assert!(validate_transforms(&bad_pattern)
+            .unwrap_err()
+            .contains("malformed"));
+    }
+}
@@ -0,0 +1,86 @@
+﻿use retasync_storage::{JobRecord, TransferRecord};
+use retasync_transfer::TransferProgress;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::app::LogLine;
+use crate::diagnostics::CheckResult;
+use crate::health::Availability;
+use crate::inbound::QueueSummary;
+
+// Self-contained page: no external scripts or styles, so it works on an offline mesh.
+pub const STATUS_PAGE: &str = include_str!("ui/status.html");
+pub const SNAPSHOT_JOBS: i64 = 20;
+pub const SNAPSHOT_TRANSFERS: i64 = 20;
+pub const SNAPSHOT_LOG_LINES: usize = 50;
+
+// Everything the status page renders, in one response so a load costs one request.
+#[derive(Debug, Serialize)]
+pub struct UiSnapshot {
+    pub generated_at: String,
+    pub node: NodeSummary,
+    pub queue: QueueSummary,
+    pub jobs: Vec<JobSummary>,
+    pub transfers: Vec<TransferSummary>,
+    pub logs: Vec<LogLine>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct NodeSummary {
+    pub ready: bool,
+    pub daemon_connected: bool,
+    pub checks: Vec<CheckResult>,
+    pub availability_last_hour: Option<Availability>,
+    pub capabilities_digest: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JobSummary {
+    pub job_id: String,
+    pub operation: String,
+    pub status: String,
+    pub submitted_at: String,
+    pub updated_at: String,
+    pub failure_reason: Option<String>,
+}
+
+impl From<JobRecord> for JobSummary {
+    fn from(job: JobRecord) -> Self {
+        Self {
+            job_id: job.job_id,
+            operation: job.operation,
+            status: job.status,
+            submitted_at: job.submitted_at,
+            updated_at: job.updated_at,
+            failure_reason: job.failure_reason,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct TransferSummary {
+    pub transfer_id: String,
+    pub job_id: Option<String>,
+    pub status: String,
+    pub file_name: Option<String>,
+    pub updated_at: String,
+    pub failure_reason: Option<String>,
+    pub progress: Option<TransferProgress>,
+}
+
+impl TransferSummary {
+    pub fn new(record: TransferRecord, progress: Option<TransferProgress>) -> Self {
+        let file_name = serde_json::from_str::<Value>(&record.metadata_json)
+            .ok()
+            .and_then(|metadata| metadata["file_name"].as_str().map(str::to_string));
+        Self {
+            transfer_id: record.transfer_id,
+            job_id: record.job_id,
+            status: record.status,
+            file_name,
+            updated_at: record.updated_at,
+            failure_reason: record.failure_reason,
+            progress,
+        }
+    }
+}
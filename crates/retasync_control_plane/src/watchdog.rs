@@ -0,0 +1,510 @@
+﻿use std::time::Duration;
+
+use chrono::Utc;
+use serde_json::json;
+use tokio::task::JoinHandle;
+use retasync_storage::IntegrityReport;
+use tracing::{error, warn};
+
+use crate::app::{emit, publish, stall_cutoff, write_log};
+use crate::archive::apply_retention;
+use crate::clock::seen_message_horizon_secs;
+use crate::crash::trim_crash_reports;
+use crate::feed::trim_event_feed;
+use crate::health::compact_health_history;
+use crate::leases::{recover_lost_job, recover_orphaned_job, Recovery};
+use crate::mute::Traffic;
+use crate::replay::prune_seen_messages;
+use crate::AppState;
+
+#[cfg(feature = "transfers")]
+pub fn spawn_transfer_watchdog(state: AppState, period: Duration) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(period);
+        loop {
+            ticker.tick().await;
+            if let Err(err) = check_stalled_transfers(&state).await {
+                error!(error = %err, "transfer stall check failed");
+            }
+        }
+    })
+}
+
+// A transfer held by a mute is not stalled.
+pub async fn check_stalled_transfers(state: &AppState) -> anyhow::Result<usize> {
+    if state.mute.blocks(Traffic::Transfers) {
+        return Ok(0);
+    }
+    let cutoff = stall_cutoff(state).await;
+    let stalled = state
+        .storage
+        .with_tx(move |tx| {
+            Box::pin(async move {
+                let stalled = tx.claim_stalled_transfers(&cutoff).await?;
+                for transfer_id in &stalled {
+                    let event = json!({ "transfer_id": transfer_id, "last_chunk_before": cutoff });
+                    tx.append_feed_event("transfer.stalled", &event).await?;
+                }
+                Ok(stalled)
+            })
+        })
+        .await?;
+    for transfer_id in &stalled {
+        warn!(transfer_id = %transfer_id, "transfer stalled");
+    }
+    publish(state).await;
+    Ok(stalled.len())
+}
+
+pub fn spawn_job_watchdog(state: AppState, period: Duration) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(period);
+        loop {
+            ticker.tick().await;
+            if let Err(err) = recover_expired_jobs(&state).await {
+                error!(error = %err, "job lease check failed");
+            }
+        }
+    })
+}
+
+// Returns how many jobs were requeued for another worker.
+pub async fn recover_expired_jobs(state: &AppState) -> anyhow::Result<usize> {
+    let leases = state.storage.claim_expired_job_leases(Utc::now()).await?;
+    let mut requeued = 0;
+    for lease in &leases {
+        warn!(
+            job_id = %lease.job_id,
+            worker_id = %lease.worker_id,
+            attempt = lease.attempt,
+            "job lease expired"
+        );
+        if recover_lost_job(state, lease, "lease expired").await? == Recovery::Requeued {
+            requeued += 1;
+        }
+    }
+    Ok(requeued)
+}
+
+// Recovers, at startup, the jobs whose workers died with the previous run, without waiting for
+// their leases to expire. `crash_id` names the crash that ended that run, if one was found.
+pub async fn recover_orphaned_jobs(
+    state: &AppState,
+    crash_id: Option<&str>,
+) -> anyhow::Result<usize> {
+    let cause = match crash_id {
+        Some(crash_id) => format!("process crashed (crash {crash_id})"),
+        None => "process restarted".to_string(),
+    };
+    let leases = state.storage.claim_orphaned_job_leases(&cause).await?;
+    let mut requeued = 0;
+    for lease in &leases {
+        warn!(
+            job_id = %lease.job_id,
+            worker_id = %lease.worker_id,
+            attempt = lease.attempt,
+            crash_id,
+            "job orphaned by the previous run"
+        );
+        if recover_orphaned_job(state, lease, &cause, crash_id).await? == Recovery::Requeued {
+            requeued += 1;
+        }
+    }
+    Ok(requeued)
+}
+
+pub fn spawn_allowlist_expiry(state: AppState, period: Duration) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(period);
+        loop {
+            ticker.tick().await;
+            if let Err(err) = expire_allowlist_entries(&state).await {
+                error!(error = %err, "allowlist expiry check failed");
+            }
+        }
+    })
+}
+
+pub async fn expire_allowlist_entries(state: &AppState) -> anyhow::Result<usize> {
+    let now = Utc::now();
+    let expired = state
+        .storage
+        .with_tx(move |tx| {
+            Box::pin(async move {
+                let expired = tx.expire_allowlist(now).await?;
+                for identity_hash in &expired {
+                    let event = json!({ "identity_hash": identity_hash });
+                    tx.append_feed_event("security.allowlist.expired", &event)
+                        .await?;
+                }
+                Ok(expired)
+            })
+        })
+        .await?;
+    for identity_hash in &expired {
+        warn!(identity_hash = %identity_hash, "allowlist entry expired");
+    }
+    publish(state).await;
+    Ok(expired.len())
+}
+
+pub fn spawn_retention(state: AppState, period: Duration) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(period);
+        loop {
+            ticker.tick().await;
+            if let Err(err) = compact_health_history(&state.storage, Utc::now()).await {
+                error!(error = %err, "health history downsampling failed");
+            }
+            let settings = state.node_config.read().await.retention.clone();
+            if let Err(err) = apply_retention(&state.storage, &settings, Utc::now()).await {
+                error!(error = %err, "retention run failed; expired rows kept");
+            }
+            let max_age_secs = seen_message_horizon_secs(&state).await;
+            if let Err(err) = prune_seen_messages(&state.storage, max_age_secs, Utc::now()).await {
+                error!(error = %err, "seen message pruning failed");
+            }
+            let feed = state.node_config.read().await.event_feed.clone();
+            if let Err(err) = trim_event_feed(&state.storage, &feed, Utc::now()).await {
+                error!(error = %err, "event feed trim failed");
+            }
+            if let Err(err) = trim_crash_reports(&state).await {
+                error!(error = %err, "crash report trim failed");
+            }
+            let inbox = state.node_config.read().await.notifications.clone();
+            if let Err(err) = state
+                .storage
+                .trim_notifications(inbox.retention_hours, inbox.max_unacked)
+                .await
+            {
+                error!(error = %err, "notification inbox trim failed");
+            }
+        }
+    })
+}
+
+const INTEGRITY_BATCH_SIZE: i64 = 500;
+
+pub fn spawn_integrity_check(state: AppState, period: Duration) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(period);
+        loop {
+            ticker.tick().await;
+            if let Err(err) = run_integrity_check(&state).await {
+                error!(error = %err, "storage integrity check failed");
+            }
+        }
+    })
+}
+
+pub async fn run_integrity_check(state: &AppState) -> anyhow::Result<IntegrityReport> {
+    let report = state.storage.check_integrity(INTEGRITY_BATCH_SIZE).await?;
+    if report.scanned > 0 {
+        let level = if report.quarantined > 0 { "warn" } else { "info" };
+        write_log(
+            state,
+            level,
+            &format!(
+                "storage.integrity.completed scanned={} quarantined={}",
+                report.scanned, report.quarantined
+            ),
+        )
+        .await;
+        emit(state, "storage.integrity.completed", serde_json::to_value(&report)?).await;
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_stalled_transfers, recover_orphaned_jobs, spawn_job_watchdog};
+    use crate::app::publish;
+    use crate::crash::{ingest_crash_reports, write_crash_marker, CrashMarker, TASK_PANIC};
+    use crate::dispatch::resolve_dispatch;
+    use crate::error::JobProcessingError;
+    use crate::leases::{spawn_leased, JobWatchdogSettings, WORKER_LOST_REASON};
+    use crate::test_support::node_state;
+    use crate::AppState;
+    use retasync_mesh_bridge::{BridgeError, InMemoryRpcMeshBridge};
+    use retasync_storage::StorageError;
+    use retasync_transfer::TransferProgress;
+    use serde_json::json;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    async fn test_state() -> AppState {
+        node_state(
+            Arc::new(InMemoryRpcMeshBridge::new(true, true)),
+            json!({ "transfer_stall_after_secs": 0 }),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn stall_fires_once_and_clears_on_new_chunk() {
+        let state = test_state().await;
+        let mut events = state.sse_bus.subscribe();
+        let transfer = state
+            .storage
+            .create_transfer_with_progress(
+                None,
+                json!({ "file_name": "map.png" }),
+                TransferProgress::new(100, 50),
+            )
+            .await
+            .unwrap();
+        let transfer_id = transfer.transfer_id.as_str();
+        state
+            .storage
+            .update_transfer_status(transfer_id, "running", None)
+            .await
+            .unwrap();
+        state.storage.record_transfer_chunk(transfer_id, 50).await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+
+        assert_eq!(check_stalled_transfers(&state).await.unwrap(), 1);
+        assert_eq!(check_stalled_transfers(&state).await.unwrap(), 0);
+        let event = events.try_recv().expect("stall event");
+        assert_eq!(event.event_type, "transfer.stalled");
+        assert!(events.try_recv().is_err());
+
+        state.storage.record_transfer_chunk(transfer_id, 50).await.unwrap();
+        state.node_config.write().await.transfer_stall_after_secs = 60;
+        let cutoff = super::stall_cutoff(&state).await;
+        let progress = state
+            .storage
+            .get_transfer_progress(transfer_id, &cutoff)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!progress.stalled);
+        assert_eq!(check_stalled_transfers(&state).await.unwrap(), 0);
+    }
+
+    async fn running_job(state: &AppState, max_attempts: u32) -> String {
+        let payload = json!({ "uid": "evt-1" });
+        let config = state.node_config.read().await.clone();
+        let mut dispatch = resolve_dispatch(&config, "event.create", &payload).unwrap();
+        dispatch.delivery.max_attempts = max_attempts;
+        let job = state.storage.create_job("event.create", payload).await.unwrap();
+        let dispatch = serde_json::to_value(&dispatch).unwrap();
+        state.storage.set_job_dispatch(&job.job_id, &dispatch).await.unwrap();
+        state
+            .storage
+            .update_job_status(&job.job_id, "running", None)
+            .await
+            .unwrap();
+        publish(state).await;
+        job.job_id
+    }
+
+    async fn settled_status(state: &AppState, job_id: &str) -> String {
+        for _ in 0..200 {
+            let job = state.storage.get_job(job_id).await.unwrap().unwrap();
+            if matches!(job.status.as_str(), "success" | "failed") {
+                return job.status;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("job {job_id} never settled");
+    }
+
+    #[tokio::test]
+    async fn lost_worker_is_requeued_within_grace_and_completes_elsewhere() {
+        let state = test_state().await;
+        let settings = JobWatchdogSettings {
+            lease_interval_ms: 20,
+            lease_grace_ms: 80,
+        };
+        state.node_config.write().await.job_watchdog = settings.clone();
+        let job_id = running_job(&state, 2).await;
+        let mut events = state.sse_bus.subscribe();
+
+        let doomed = spawn_leased(state.clone(), job_id.clone(), std::future::pending());
+        while state.storage.get_job_lease(&job_id).await.unwrap().is_none() {
+            tokio::time::sleep(Duration::from_millis(2)).await;
+        }
+        doomed.abort();
+        let died = Instant::now();
+        let watchdog = spawn_job_watchdog(state.clone(), settings.lease_interval());
+
+        let requeued = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let event = events.recv().await.unwrap();
+                if event.event_type == "job.status.changed" && event.data["status"] == "queued" {
+                    return event.data;
+                }
+            }
+        })
+        .await
+        .expect("requeue event");
+        // The lease outlives its last renewal by interval + grace; the watchdog scans once per
+        // interval on top of that, and the rest is scheduling slack.
+        assert!(died.elapsed() < Duration::from_millis(20 + 80 + 20 + 250));
+        assert_eq!(requeued["reason"], WORKER_LOST_REASON);
+        assert_eq!(requeued["attempt"], 1);
+
+        assert_eq!(settled_status(&state, &job_id).await, "success");
+        while state.storage.get_job_lease(&job_id).await.unwrap().is_some() {
+            tokio::time::sleep(Duration::from_millis(2)).await;
+        }
+        watchdog.abort();
+    }
+
+    #[tokio::test]
+    async fn worker_stops_once_its_lease_is_taken() {
+        let state = test_state().await;
+        state.node_config.write().await.job_watchdog = JobWatchdogSettings {
+            lease_interval_ms: 20,
+            lease_grace_ms: 80,
+        };
+        let job_id = running_job(&state, 2).await;
+        let (running, stopped) = tokio::sync::oneshot::channel::<()>();
+        let worker = spawn_leased(state.clone(), job_id.clone(), async move {
+            let _running = running;
+            std::future::pending().await
+        });
+        while state.storage.get_job_lease(&job_id).await.unwrap().is_none() {
+            tokio::time::sleep(Duration::from_millis(2)).await;
+        }
+        sqlx::query("UPDATE job_leases SET worker_id = 'watchdog' WHERE job_id = ?")
+            .bind(&job_id)
+            .execute(state.storage.pool())
+            .await
+            .unwrap();
+
+        let stopped = tokio::time::timeout(Duration::from_secs(5), stopped).await;
+        assert!(stopped.expect("worker stopped").is_err());
+        tokio::time::timeout(Duration::from_secs(5), worker).await.unwrap().unwrap();
+        // The lease is the watchdog's now, so the job is left for it to redeliver.
+        let lease = state.storage.get_job_lease(&job_id).await.unwrap().unwrap();
+        assert_eq!(lease.worker_id, "watchdog");
+    }
+
+    #[tokio::test]
+    async fn panicked_worker_fails_job_without_waiting_for_expiry() {
+        let state = test_state().await;
+        let job_id = running_job(&state, 1).await;
+        let worker = spawn_leased(state.clone(), job_id.clone(), async {
+            panic!("worker crashed");
+        });
+        worker.await.unwrap();
+
+        let job = state.storage.get_job(&job_id).await.unwrap().unwrap();
+        assert_eq!(job.status, "failed");
+        assert_eq!(job.failure_reason.as_deref(), Some(WORKER_LOST_REASON));
+        assert_eq!(state.storage.get_job_lease(&job_id).await.unwrap(), None);
+        // The process survived, so the panic is a task report rather than a crash.
+        let reports = state.storage.list_crash_reports(10).await.unwrap();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].kind, TASK_PANIC);
+        assert_eq!(reports[0].message, "worker crashed");
+        assert_eq!(reports[0].job_id.as_deref(), Some(job_id.as_str()));
+    }
+
+    #[tokio::test]
+    async fn caught_worker_panic_takes_back_the_hook_file() {
+        let state = test_state().await;
+        let job_id = running_job(&state, 1).await;
+        let dir = state
+            .node_config
+            .read()
+            .await
+            .crash_reports
+            .dir_for(state.storage.database_path());
+        let message = format!("worker {job_id} crashed");
+        // What the hook writes as the worker panics.
+        let marker = CrashMarker::new(message.clone(), Some("src/worker.rs:1:1".to_string()), None);
+        let crash_id = marker.crash_id.clone();
+        let file = write_crash_marker(&dir, marker).unwrap();
+        let worker = spawn_leased(state.clone(), job_id.clone(), async move {
+            panic!("{message}");
+        });
+        worker.await.unwrap();
+
+        assert!(!file.exists());
+        let report = state.storage.get_crash_report(&crash_id).await.unwrap().unwrap();
+        assert_eq!(report.kind, TASK_PANIC);
+        assert_eq!(report.location.as_deref(), Some("src/worker.rs:1:1"));
+        assert_eq!(ingest_crash_reports(&state).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn orphaned_jobs_are_failed_with_the_crash_that_interrupted_them() {
+        let state = test_state().await;
+        let doomed = running_job(&state, 1).await;
+        let retried = running_job(&state, 2).await;
+        for job_id in [&doomed, &retried] {
+            // Leases the previous run was still renewing when it died.
+            let expires_at = chrono::Utc::now() + chrono::Duration::minutes(5);
+            state
+                .storage
+                .acquire_job_lease(job_id, "dead-worker", expires_at)
+                .await
+                .unwrap();
+        }
+        let dir = state
+            .node_config
+            .read()
+            .await
+            .crash_reports
+            .dir_for(state.storage.database_path());
+        let marker = CrashMarker::new("stack overflow".to_string(), None, None);
+        write_crash_marker(&dir, marker).unwrap();
+
+        let crash = ingest_crash_reports(&state).await.unwrap().expect("crash");
+        let requeued = recover_orphaned_jobs(&state, Some(&crash.crash_id))
+            .await
+            .unwrap();
+        assert_eq!(requeued, 1);
+
+        let job = state.storage.get_job(&doomed).await.unwrap().unwrap();
+        assert_eq!(job.status, "failed");
+        assert_eq!(
+            job.failure_reason,
+            Some(format!("{WORKER_LOST_REASON}: crash {}", crash.crash_id))
+        );
+        assert_eq!(state.storage.get_job_lease(&doomed).await.unwrap(), None);
+        assert_eq!(settled_status(&state, &retried).await, "success");
+    }
+
+    #[tokio::test]
+    async fn terminal_processing_error_fails_job_without_retrying() {
+        let state = test_state().await;
+        let job_id = running_job(&state, 3).await;
+        let worker = spawn_leased(state.clone(), job_id.clone(), async {
+            Err(JobProcessingError::Bridge(BridgeError::InvalidPayload(
+                "bad field".to_string(),
+            )))
+        });
+        worker.await.unwrap();
+
+        let job = state.storage.get_job(&job_id).await.unwrap().unwrap();
+        assert_eq!(job.status, "failed");
+        assert_eq!(job.failure_reason.as_deref(), Some("bridge_invalid_payload"));
+        assert_eq!(state.storage.get_job_lease(&job_id).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn retryable_processing_error_requeues_job() {
+        let state = test_state().await;
+        let job_id = running_job(&state, 2).await;
+        let mut events = state.sse_bus.subscribe();
+        let worker = spawn_leased(state.clone(), job_id.clone(), async {
+            Err(JobProcessingError::Storage(StorageError::from_sqlx(
+                "insert job message",
+                sqlx::Error::PoolTimedOut,
+            )))
+        });
+        worker.await.unwrap();
+
+        let requeued = loop {
+            let event = events.recv().await.unwrap();
+            if event.event_type == "job.status.changed" && event.data["status"] == "queued" {
+                break event.data;
+            }
+        };
+        assert_eq!(requeued["reason"], "storage_connection");
+        assert_eq!(settled_status(&state, &job_id).await, "success");
+    }
+}
@@ -0,0 +1,40 @@
+﻿use serde::{Deserialize, Serialize};
+
+// Subscriptions, their delivery queue and the worker that posts to them. Without the feature the
+// `[webhooks]` section still parses, so one config file serves either build.
+#[cfg(feature = "webhooks")]
+mod delivery;
+
+#[cfg(feature = "webhooks")]
+pub use delivery::{
+    cache_event, deliver_due, event_body, event_matches, events_of, new_subscription, selects,
+    sign, spawn_webhook_dispatcher, WebhookRequest, WebhookView, ATTEMPT_HEADER, DELIVERY_HEADER,
+    EVENT_HEADER, INVALID_EVENT_FILTER_ERROR, INVALID_WEBHOOK_URL_ERROR, SIGNATURE_HEADER,
+    WEBHOOK_DISABLED_EVENT, WEBHOOK_NOT_FOUND_ERROR,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebhookSettings {
+    // Delivery settings of subscriptions created without their own.
+    pub timeout_ms: u64,
+    pub max_retries: u32,
+    // Wait before the first retry, doubled for each one after it up to an hour.
+    pub backoff_ms: u64,
+    // A subscription is disabled after this many failed attempts in a row; 0 never disables.
+    pub disable_after_failures: u32,
+    // Attempts kept per subscription for its delivery history.
+    pub history: usize,
+}
+
+impl Default for WebhookSettings {
+    fn default() -> Self {
+        Self {
+            timeout_ms: 5000,
+            max_retries: 5,
+            backoff_ms: 1000,
+            disable_after_failures: 20,
+            history: 100,
+        }
+    }
+}
@@ -0,0 +1,875 @@
+﻿use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use retasync_contract::MeshEventEnvelope;
+use retasync_storage::{
+    CachedEvent, CanonicalTimestamp, WebhookAttempt, WebhookDelivery, WebhookSubscription,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::task::JoinHandle;
+use tracing::{error, warn};
+use uuid::Uuid;
+
+use super::WebhookSettings;
+use crate::app::publish;
+use crate::coalescing::CoalescingWindow;
+use crate::dispatch::is_operation_pattern;
+use crate::AppState;
+
+pub const WEBHOOK_DISABLED_EVENT: &str = "webhook.disabled";
+pub const SIGNATURE_HEADER: &str = "x-retasync-signature";
+pub const EVENT_HEADER: &str = "x-retasync-event";
+pub const DELIVERY_HEADER: &str = "x-retasync-delivery";
+pub const ATTEMPT_HEADER: &str = "x-retasync-attempt";
+pub const INVALID_WEBHOOK_URL_ERROR: &str = "invalid_webhook_url";
+pub const INVALID_EVENT_FILTER_ERROR: &str = "invalid_event_filter";
+pub const WEBHOOK_NOT_FOUND_ERROR: &str = "webhook_not_found";
+const DELIVERY_BATCH: i64 = 32;
+const SECRET_BYTES: usize = 32;
+const MAX_BACKOFF: Duration = Duration::from_secs(3600);
+// Only the status line of an answer is read.
+const MAX_STATUS_LINE_BYTES: u64 = 1024;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// Body of `POST /v1/webhooks`. A subscription without a secret is given one, returned once.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookRequest {
+    pub url: String,
+    #[serde(default = "every_event")]
+    pub events: Vec<String>,
+    pub source_identity: Option<String>,
+    pub secret: Option<String>,
+    pub timeout_ms: Option<u64>,
+    pub max_retries: Option<u32>,
+    pub backoff_ms: Option<u64>,
+}
+
+fn every_event() -> Vec<String> {
+    vec!["*".to_string()]
+}
+
+// A subscription as the API shows it, without its secret.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WebhookView {
+    pub subscription_id: String,
+    pub url: String,
+    pub events: Vec<String>,
+    pub source_identity: Option<String>,
+    pub timeout_ms: i64,
+    pub max_retries: i64,
+    pub backoff_ms: i64,
+    pub enabled: bool,
+    pub consecutive_failures: i64,
+    pub disabled_at: Option<String>,
+    pub created_at: String,
+    pub pending_deliveries: i64,
+}
+
+impl WebhookView {
+    pub fn new(subscription: WebhookSubscription, pending_deliveries: i64) -> Self {
+        Self {
+            events: events_of(&subscription),
+            subscription_id: subscription.subscription_id,
+            url: subscription.url,
+            source_identity: subscription.source_identity,
+            timeout_ms: subscription.timeout_ms,
+            max_retries: subscription.max_retries,
+            backoff_ms: subscription.backoff_ms,
+            enabled: subscription.enabled,
+            consecutive_failures: subscription.consecutive_failures,
+            disabled_at: subscription.disabled_at,
+            created_at: subscription.created_at,
+            pending_deliveries,
+        }
+    }
+}
+
+// Refuses the request by returning an error code. `source_identity` has been validated by the
+// caller already.
+pub fn new_subscription(
+    request: WebhookRequest,
+    source_identity: Option<String>,
+    settings: &WebhookSettings,
+    now: DateTime<Utc>,
+) -> anyhow::Result<Result<WebhookSubscription, &'static str>> {
+    if Target::parse(&request.url).is_none() {
+        return Ok(Err(INVALID_WEBHOOK_URL_ERROR));
+    }
+    if request.events.is_empty() || !request.events.iter().all(|p| is_operation_pattern(p)) {
+        return Ok(Err(INVALID_EVENT_FILTER_ERROR));
+    }
+    let secret = match request.secret.filter(|secret| !secret.is_empty()) {
+        Some(secret) => secret,
+        None => {
+            let mut bytes = [0u8; SECRET_BYTES];
+            getrandom::getrandom(&mut bytes)
+                .map_err(|err| anyhow::anyhow!("generate webhook secret: {err}"))?;
+            URL_SAFE_NO_PAD.encode(bytes)
+        }
+    };
+    let millis = |value: u64| i64::try_from(value).unwrap_or(i64::MAX);
+    Ok(Ok(WebhookSubscription {
+        subscription_id: Uuid::now_v7().to_string(),
+        url: request.url,
+        events_json: serde_json::to_string(&request.events)?,
+        source_identity,
+        secret,
+        timeout_ms: millis(request.timeout_ms.unwrap_or(settings.timeout_ms).max(1)),
+        max_retries: i64::from(request.max_retries.unwrap_or(settings.max_retries)),
+        backoff_ms: millis(request.backoff_ms.unwrap_or(settings.backoff_ms)),
+        enabled: true,
+        consecutive_failures: 0,
+        disabled_at: None,
+        created_at: CanonicalTimestamp::from(now).to_string(),
+    }))
+}
+
+pub fn events_of(subscription: &WebhookSubscription) -> Vec<String> {
+    serde_json::from_str(&subscription.events_json).unwrap_or_default()
+}
+
+// Patterns read as operation patterns do: `a.b` matches only itself, `a.*` anything under `a.`
+// and `*` everything.
+pub fn event_matches(pattern: &str, event: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => event.starts_with(prefix),
+        None => pattern == event,
+    }
+}
+
+pub fn selects(subscription: &WebhookSubscription, event: &str, source_identity: &str) -> bool {
+    subscription.enabled
+        && subscription
+            .source_identity
+            .as_deref()
+            .is_none_or(|wanted| wanted == source_identity)
+        && events_of(subscription)
+            .iter()
+            .any(|pattern| event_matches(pattern, event))
+}
+
+// Value of the signature header: `sha256=` and the hex HMAC-SHA256 of the body under the
+// subscription's secret.
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(body);
+    let tag: String = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    format!("sha256={tag}")
+}
+
+// What a subscription is sent for a mesh event.
+pub fn event_body(envelope: &MeshEventEnvelope<Value>, received_at: DateTime<Utc>) -> Value {
+    json!({
+        "event": envelope.event,
+        "message_id": envelope.message_id,
+        "source_identity": envelope.source_identity,
+        "sent_at": envelope.sent_at,
+        "received_at": received_at,
+        "payload": envelope.payload,
+    })
+}
+
+// Caches a mesh event and queues it for every subscription it matches in one transaction, so
+// a cached event is never missing from a queue. An event cached already is not queued again.
+pub async fn cache_event(
+    state: &AppState,
+    envelope: &MeshEventEnvelope<Value>,
+) -> anyhow::Result<()> {
+    let source_identity = envelope.source_identity.to_string();
+    let subscriptions: Vec<String> = state
+        .storage
+        .list_webhook_subscriptions()
+        .await?
+        .into_iter()
+        .filter(|subscription| selects(subscription, &envelope.event, &source_identity))
+        .map(|subscription| subscription.subscription_id)
+        .collect();
+    let cached = serde_json::to_value(envelope)?;
+    let body = event_body(envelope, Utc::now());
+    let (message_id, event_name, sent_at) =
+        (envelope.message_id.clone(), envelope.event.clone(), envelope.sent_at);
+    state
+        .storage
+        .with_tx(move |tx| {
+            Box::pin(async move {
+                let event = CachedEvent {
+                    event_id: &message_id,
+                    event_name: &event_name,
+                    source_identity: &source_identity,
+                    sent_at,
+                    payload: &cached,
+                };
+                if !tx.cache_event(&event).await? {
+                    return Ok(());
+                }
+                for subscription_id in &subscriptions {
+                    let delivery_id = Uuid::now_v7().to_string();
+                    tx.queue_webhook_delivery(
+                        &delivery_id,
+                        subscription_id,
+                        &message_id,
+                        &event_name,
+                        &body,
+                    )
+                    .await?;
+                }
+                Ok(())
+            })
+        })
+        .await?;
+    Ok(())
+}
+
+// Makes one attempt at every delivery due by `now` and returns how many were made.
+pub async fn deliver_due(state: &AppState, now: DateTime<Utc>) -> anyhow::Result<usize> {
+    let (settings, window_ms) = {
+        let config = state.node_config.read().await;
+        (config.webhooks.clone(), config.event_coalescing.window_ms)
+    };
+    let subscriptions: HashMap<String, WebhookSubscription> = state
+        .storage
+        .list_webhook_subscriptions()
+        .await?
+        .into_iter()
+        .map(|subscription| (subscription.subscription_id.clone(), subscription))
+        .collect();
+    let enabled: Vec<String> = subscriptions
+        .values()
+        .filter(|subscription| subscription.enabled)
+        .map(|subscription| subscription.subscription_id.clone())
+        .collect();
+    state.event_subscribers.sync_webhooks(&enabled, window_ms);
+    let due = state.storage.due_webhook_deliveries(now, DELIVERY_BATCH).await?;
+    if due.is_empty() {
+        return Ok(0);
+    }
+    let due = coalesce(state, due, window_ms, now).await?;
+    let mut disabled = HashSet::new();
+    let mut attempted = 0;
+    for delivery in due {
+        let Some(subscription) = subscriptions.get(&delivery.subscription_id) else {
+            continue;
+        };
+        if disabled.contains(&delivery.subscription_id) {
+            continue;
+        }
+        attempted += 1;
+        if attempt(state, subscription, delivery, &settings).await? {
+            disabled.insert(subscription.subscription_id.clone());
+        }
+    }
+    Ok(attempted)
+}
+
+// Holds a subscription's first attempts until the oldest of them has waited out the window,
+// then sends only the latest per subject and drops the rest unsent. Retries go out as due.
+async fn coalesce(
+    state: &AppState,
+    due: Vec<WebhookDelivery>,
+    window_ms: u64,
+    now: DateTime<Utc>,
+) -> anyhow::Result<Vec<WebhookDelivery>> {
+    let window = chrono::Duration::milliseconds(i64::try_from(window_ms).unwrap_or(i64::MAX));
+    let mut windows: BTreeMap<String, Option<CoalescingWindow<WebhookDelivery>>> =
+        BTreeMap::new();
+    for delivery in due.iter().filter(|delivery| delivery.attempts == 0) {
+        // Due deliveries come longest waiting first, so the first one opened the window.
+        let held = windows.entry(delivery.subscription_id.clone()).or_insert_with(|| {
+            let opened = CanonicalTimestamp::parse(&delivery.queued_at)
+                .map(|queued_at| queued_at.as_datetime())
+                .unwrap_or(now);
+            (window_ms == 0 || opened + window <= now).then(CoalescingWindow::default)
+        });
+        if let Some(held) = held {
+            held.push(delivery.clone());
+        }
+    }
+    let mut sent = HashSet::new();
+    for (subscription_id, held) in windows {
+        let Some(mut held) = held else {
+            continue;
+        };
+        let (delivered, collapsed) = held.drain();
+        let superseded: Vec<String> =
+            collapsed.into_iter().map(|delivery| delivery.delivery_id).collect();
+        state.storage.drop_webhook_deliveries(&superseded).await?;
+        state
+            .event_subscribers
+            .note_webhook(&subscription_id, delivered.len(), superseded.len() as u64);
+        sent.extend(delivered.into_iter().map(|delivery| delivery.delivery_id));
+    }
+    Ok(due
+        .into_iter()
+        .filter(|delivery| delivery.attempts > 0 || sent.contains(&delivery.delivery_id))
+        .collect())
+}
+
+// Returns whether the attempt got the subscription disabled.
+async fn attempt(
+    state: &AppState,
+    subscription: &WebhookSubscription,
+    delivery: WebhookDelivery,
+    settings: &WebhookSettings,
+) -> anyhow::Result<bool> {
+    let number = delivery.attempts + 1;
+    let attempted_at = Utc::now();
+    let started = Instant::now();
+    let headers = [
+        ("Content-Type", "application/json".to_string()),
+        (EVENT_HEADER, delivery.event_name.clone()),
+        (DELIVERY_HEADER, delivery.delivery_id.clone()),
+        (ATTEMPT_HEADER, number.to_string()),
+        (
+            SIGNATURE_HEADER,
+            sign(&subscription.secret, delivery.body_json.as_bytes()),
+        ),
+    ];
+    let timeout = Duration::from_millis(subscription.timeout_ms.max(1) as u64);
+    let answer = post(&subscription.url, &headers, delivery.body_json.as_bytes(), timeout).await;
+    let duration_ms = started.elapsed().as_millis() as i64;
+    let (status_code, error) = match answer {
+        Ok(status) if (200..300).contains(&status) => (Some(i64::from(status)), None),
+        Ok(status) => (Some(i64::from(status)), Some(format!("answered {status}"))),
+        Err(err) => (None, Some(err)),
+    };
+    let retry_at = (error.is_some() && number <= subscription.max_retries)
+        .then(|| attempted_at + backoff(subscription.backoff_ms, number));
+    let outcome = match (&error, retry_at) {
+        (None, _) => "delivered",
+        (Some(_), Some(_)) => "retrying",
+        (Some(_), None) => "failed",
+    };
+    if let Some(error) = &error {
+        warn!(
+            subscription_id = %subscription.subscription_id,
+            delivery_id = %delivery.delivery_id,
+            attempt = number,
+            outcome,
+            error = %error,
+            "webhook delivery failed"
+        );
+    }
+    let record = WebhookAttempt {
+        delivery_id: delivery.delivery_id,
+        message_id: delivery.message_id,
+        event_name: delivery.event_name,
+        attempt: number,
+        attempted_at: CanonicalTimestamp::from(attempted_at).to_string(),
+        outcome: outcome.to_string(),
+        status_code,
+        error: error.clone(),
+        duration_ms,
+    };
+    let failures = state
+        .storage
+        .record_webhook_attempt(
+            &subscription.subscription_id,
+            &record,
+            retry_at,
+            settings.history as i64,
+        )
+        .await?;
+    let threshold = i64::from(settings.disable_after_failures);
+    if threshold == 0 || failures < threshold {
+        return Ok(false);
+    }
+    disable(state, subscription, failures, error).await
+}
+
+// Doubles from `backoff_ms` after each failed attempt, up to an hour.
+fn backoff(backoff_ms: i64, attempt: i64) -> chrono::Duration {
+    let factor = 1u64 << (attempt - 1).clamp(0, 20);
+    let delay = Duration::from_millis((backoff_ms.max(0) as u64).saturating_mul(factor));
+    chrono::Duration::from_std(delay.min(MAX_BACKOFF)).unwrap_or_else(|_| chrono::Duration::zero())
+}
+
+async fn disable(
+    state: &AppState,
+    subscription: &WebhookSubscription,
+    failures: i64,
+    last_error: Option<String>,
+) -> anyhow::Result<bool> {
+    let subscription_id = subscription.subscription_id.clone();
+    let event = json!({
+        "subscription_id": subscription.subscription_id,
+        "url": subscription.url,
+        "consecutive_failures": failures,
+        "last_error": last_error,
+    });
+    let disabled = state
+        .storage
+        .with_tx(move |tx| {
+            Box::pin(async move {
+                let disabled = tx
+                    .disable_webhook_subscription(&subscription_id, Utc::now())
+                    .await?;
+                if disabled {
+                    tx.append_feed_event(WEBHOOK_DISABLED_EVENT, &event).await?;
+                }
+                Ok(disabled)
+            })
+        })
+        .await?;
+    if disabled {
+        warn!(
+            subscription_id = %subscription.subscription_id,
+            consecutive_failures = failures,
+            "webhook subscription disabled"
+        );
+        publish(state).await;
+    }
+    Ok(disabled)
+}
+
+pub fn spawn_webhook_dispatcher(state: AppState, period: Duration) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(period);
+        loop {
+            ticker.tick().await;
+            if let Err(err) = deliver_due(&state, Utc::now()).await {
+                error!(error = %err, "webhook delivery pass failed");
+            }
+        }
+    })
+}
+
+// Where an `http://host[:port][/path]` URL points. Plain HTTP only: a consumer that needs TLS
+// puts a terminating proxy in front of itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Target {
+    authority: String,
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl Target {
+    fn parse(url: &str) -> Option<Self> {
+        if url.chars().any(|c| c.is_whitespace() || c.is_control()) {
+            return None;
+        }
+        let rest = url.strip_prefix("http://")?;
+        let (authority, path) = match rest.find(['/', '?', '#']) {
+            Some(index) => rest.split_at(index),
+            None => (rest, "/"),
+        };
+        let path = path.split('#').next().unwrap_or_default();
+        let path = match path {
+            "" => "/".to_string(),
+            path if path.starts_with('?') => format!("/{path}"),
+            path => path.to_string(),
+        };
+        if authority.is_empty() || authority.contains('@') {
+            return None;
+        }
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => (host, port.parse().ok()?),
+            _ => (authority, 80),
+        };
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        if host.is_empty() {
+            return None;
+        }
+        Some(Self {
+            authority: authority.to_string(),
+            host: host.to_string(),
+            port,
+            path,
+        })
+    }
+}
+
+// Sends one POST and returns the answer's status code, or why there was none in time.
+async fn post(
+    url: &str,
+    headers: &[(&str, String)],
+    body: &[u8],
+    timeout: Duration,
+) -> Result<u16, String> {
+    let target = Target::parse(url).ok_or_else(|| format!("{url} is not an http:// URL"))?;
+    let exchange = async {
+        let mut stream = TcpStream::connect((target.host.as_str(), target.port))
+            .await
+            .map_err(|err| format!("connect to {}: {err}", target.authority))?;
+        let mut request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: retasyncd/{}\r\n",
+            target.path,
+            target.authority,
+            env!("CARGO_PKG_VERSION")
+        );
+        for (name, value) in headers {
+            request.push_str(&format!("{name}: {value}\r\n"));
+        }
+        request.push_str(&format!(
+            "Content-Length: {}\r\nConnection: close\r\n\r\n",
+            body.len()
+        ));
+        stream
+            .write_all(request.as_bytes())
+            .await
+            .map_err(|err| format!("send request: {err}"))?;
+        stream
+            .write_all(body)
+            .await
+            .map_err(|err| format!("send body: {err}"))?;
+        let mut status_line = String::new();
+        BufReader::new(stream)
+            .take(MAX_STATUS_LINE_BYTES)
+            .read_line(&mut status_line)
+            .await
+            .map_err(|err| format!("read answer: {err}"))?;
+        status_line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|code| code.parse().ok())
+            .ok_or_else(|| format!("malformed HTTP answer {:?}", status_line.trim_end()))
+    };
+    tokio::time::timeout(timeout, exchange)
+        .await
+        .unwrap_or_else(|_| Err(format!("no answer within {}ms", timeout.as_millis())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        cache_event, deliver_due, event_matches, new_subscription, sign, HmacSha256, Target,
+        WebhookRequest, ATTEMPT_HEADER, EVENT_HEADER,
+        SIGNATURE_HEADER, WEBHOOK_DISABLED_EVENT,
+    };
+    use crate::coalescing::WEBHOOK_SUBSCRIBER;
+    use crate::test_support::{node_config, scratch_sqlite, test_storage};
+    use crate::AppState;
+    use chrono::{Duration as ChronoDuration, Utc};
+    use hmac::Mac;
+    use retasync_contract::MeshEventEnvelope;
+    use retasync_mesh_bridge::InMemoryRpcMeshBridge;
+
+    use serde_json::{json, Value};
+    use std::collections::{BTreeMap, VecDeque};
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+    use uuid::Uuid;
+
+    const ALPHA: &str = "a1a1a1a1a1a1a1a1a1a1a1a1a1a1a1a1";
+    const BRAVO: &str = "b2b2b2b2b2b2b2b2b2b2b2b2b2b2b2b2";
+
+    #[derive(Debug, Clone)]
+    struct Received {
+        headers: BTreeMap<String, String>,
+        body: Vec<u8>,
+    }
+
+    // A local HTTP consumer answering with the queued statuses, then 200s.
+    #[derive(Clone)]
+    struct Receiver {
+        url: String,
+        answers: Arc<Mutex<VecDeque<u16>>>,
+        received: Arc<Mutex<Vec<Received>>>,
+    }
+
+    impl Receiver {
+        async fn start(answers: &[u16]) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let receiver = Self {
+                url: format!("http://{}/hooks/mesh", listener.local_addr().unwrap()),
+                answers: Arc::new(Mutex::new(answers.iter().copied().collect())),
+                received: Arc::default(),
+            };
+            let serving = receiver.clone();
+            tokio::spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    let mut reader = BufReader::new(stream);
+                    let mut headers = BTreeMap::new();
+                    let mut line = String::new();
+                    reader.read_line(&mut line).await.unwrap();
+                    loop {
+                        line.clear();
+                        reader.read_line(&mut line).await.unwrap();
+                        let Some((name, value)) = line.trim_end().split_once(':') else {
+                            break;
+                        };
+                        headers.insert(name.to_ascii_lowercase(), value.trim().to_string());
+                    }
+                    let length = headers["content-length"].parse().unwrap();
+                    let mut body = vec![0; length];
+                    reader.read_exact(&mut body).await.unwrap();
+                    serving.received.lock().unwrap().push(Received { headers, body });
+                    let status = serving.answers.lock().unwrap().pop_front().unwrap_or(200);
+                    let answer = format!("HTTP/1.1 {status} X\r\nContent-Length: 0\r\n\r\n");
+                    let _ = reader.into_inner().write_all(answer.as_bytes()).await;
+                }
+            });
+            receiver
+        }
+
+        fn received(&self) -> Vec<Received> {
+            self.received.lock().unwrap().clone()
+        }
+    }
+
+    async fn node(sqlite_path: &str, webhooks: Value) -> AppState {
+        let mut node_config = node_config(json!({ "webhooks": webhooks }));
+        node_config.sqlite_path = sqlite_path.to_string();
+        let bridge = Arc::new(InMemoryRpcMeshBridge::new(true, true));
+        let storage = test_storage(sqlite_path).await;
+        AppState::new(storage, bridge, node_config, "asyncapi: 3.0.0\n".to_string(), false)
+    }
+
+    async fn subscribe(state: &AppState, request: Value) -> String {
+        let request: WebhookRequest = serde_json::from_value(request).unwrap();
+        let source_identity = request.source_identity.clone();
+        let settings = state.node_config.read().await.webhooks.clone();
+        let subscription = new_subscription(request, source_identity, &settings, Utc::now())
+            .unwrap()
+            .unwrap();
+        state.storage.create_webhook_subscription(&subscription).await.unwrap();
+        subscription.subscription_id
+    }
+
+    fn event(name: &str, source: &str) -> MeshEventEnvelope<Value> {
+        MeshEventEnvelope {
+            message_id: Uuid::now_v7().to_string(),
+            event: name.to_string(),
+            sent_at: Utc::now(),
+            source_identity: source.parse().unwrap(),
+            destination_identity: "c0c0c0c0c0c0c0c0c0c0c0c0c0c0c0c0".parse().unwrap(),
+            content_type: "application/msgpack".to_string(),
+            payload: json!({ "uid": "eam-1", "status": "red" }),
+            ttl_ms: None,
+            transport_hint: None,
+        }
+    }
+
+    fn job_status(job_id: &str, status: &str) -> MeshEventEnvelope<Value> {
+        let mut envelope = event("job.status.changed", ALPHA);
+        envelope.payload = json!({ "job_id": job_id, "status": status });
+        envelope
+    }
+
+    fn later() -> chrono::DateTime<Utc> {
+        Utc::now() + ChronoDuration::hours(2)
+    }
+
+    #[tokio::test]
+    async fn events_are_queued_for_the_subscriptions_they_match() {
+        assert!(event_matches("*", "node.ping"));
+        assert!(event_matches("emergency_action_message.*", "emergency_action_message.create"));
+        assert!(!event_matches("emergency_action_message.*", "emergency_action_messages.x"));
+        assert!(!event_matches("event.create", "event.created"));
+        assert!(Target::parse("https://example.org/hook").is_none());
+        assert!(Target::parse("http://user@example.org/").is_none());
+        let target = Target::parse("http://[::1]:9000?x=1").unwrap();
+        assert_eq!((target.host.as_str(), target.port), ("::1", 9000));
+        assert_eq!(target.path, "/?x=1");
+
+        let state = node(&scratch_sqlite(), json!({})).await;
+        let url = "http://127.0.0.1:9/unused";
+        let eam = subscribe(&state, json!({ "url": url, "events": ["emergency_action_message.*"] }))
+            .await;
+        let from_alpha = subscribe(&state, json!({ "url": url, "source_identity": ALPHA })).await;
+        for envelope in [
+            event("emergency_action_message.create", ALPHA),
+            event("emergency_action_message.update", BRAVO),
+            event("event.create", BRAVO),
+        ] {
+            cache_event(&state, &envelope).await.unwrap();
+            // A redelivered event is cached and queued once.
+            cache_event(&state, &envelope).await.unwrap();
+        }
+
+        let pending = |id: String| {
+            let storage = state.storage.clone();
+            async move { storage.count_pending_webhook_deliveries(&id).await.unwrap() }
+        };
+        assert_eq!(pending(eam).await, 2);
+        assert_eq!(pending(from_alpha).await, 1);
+    }
+
+    #[tokio::test]
+    async fn deliveries_are_signed_with_the_subscription_secret() {
+        let receiver = Receiver::start(&[]).await;
+        let state = node(&scratch_sqlite(), json!({})).await;
+        let request = json!({ "url": receiver.url, "secret": "s3cret" });
+        subscribe(&state, request).await;
+        let envelope = event("emergency_action_message.create", ALPHA);
+        cache_event(&state, &envelope).await.unwrap();
+
+        assert_eq!(deliver_due(&state, Utc::now()).await.unwrap(), 1);
+        let received = receiver.received();
+        assert_eq!(received.len(), 1);
+        let delivery = &received[0];
+        let signature = delivery.headers[SIGNATURE_HEADER].clone();
+        let tag = signature.strip_prefix("sha256=").unwrap();
+        let tag: Vec<u8> = (0..tag.len())
+            .step_by(2)
+            .map(|at| u8::from_str_radix(&tag[at..at + 2], 16).unwrap())
+            .collect();
+        let mut mac = HmacSha256::new_from_slice(b"s3cret").unwrap();
+        mac.update(&delivery.body);
+        mac.verify_slice(&tag).expect("signature verifies");
+        assert_ne!(sign("other", &delivery.body), signature);
+
+        let body: Value = serde_json::from_slice(&delivery.body).unwrap();
+        assert_eq!(body["event"], "emergency_action_message.create");
+        assert_eq!(body["message_id"], envelope.message_id);
+        assert_eq!(body["source_identity"], ALPHA);
+        assert_eq!(body["payload"]["status"], "red");
+        assert_eq!(delivery.headers[EVENT_HEADER], "emergency_action_message.create");
+        assert_eq!(deliver_due(&state, later()).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn failed_deliveries_are_retried_until_they_succeed() {
+        let receiver = Receiver::start(&[503, 500]).await;
+        let state = node(&scratch_sqlite(), json!({})).await;
+        let request = json!({ "url": receiver.url, "max_retries": 3, "backoff_ms": 60_000 });
+        let id = subscribe(&state, request).await;
+        cache_event(&state, &event("event.create", ALPHA)).await.unwrap();
+
+        assert_eq!(deliver_due(&state, Utc::now()).await.unwrap(), 1);
+        // The retry waits out its backoff.
+        assert_eq!(deliver_due(&state, Utc::now()).await.unwrap(), 0);
+        assert_eq!(deliver_due(&state, later()).await.unwrap(), 1);
+        let failing = state.storage.get_webhook_subscription(&id).await.unwrap().unwrap();
+        assert_eq!(failing.consecutive_failures, 2);
+        assert_eq!(deliver_due(&state, later()).await.unwrap(), 1);
+
+        let attempts: Vec<_> = receiver
+            .received()
+            .iter()
+            .map(|received| received.headers[ATTEMPT_HEADER].clone())
+            .collect();
+        assert_eq!(attempts, ["1", "2", "3"]);
+        let history = state.storage.list_webhook_attempts(&id, 10).await.unwrap();
+        let outcomes: Vec<_> = history.iter().map(|attempt| attempt.outcome.as_str()).collect();
+        assert_eq!(outcomes, ["delivered", "retrying", "retrying"]);
+        assert_eq!(history[1].status_code, Some(500));
+        assert_eq!(state.storage.count_pending_webhook_deliveries(&id).await.unwrap(), 0);
+        let recovered = state.storage.get_webhook_subscription(&id).await.unwrap().unwrap();
+        assert_eq!(recovered.consecutive_failures, 0);
+    }
+
+    #[tokio::test]
+    async fn pending_deliveries_survive_a_restart() {
+        let sqlite_path = scratch_sqlite();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        drop(listener);
+        let before = node(&sqlite_path, json!({})).await;
+        let id = subscribe(&before, json!({ "url": url, "backoff_ms": 0 })).await;
+        cache_event(&before, &event("event.create", ALPHA)).await.unwrap();
+        // Nobody listens yet.
+        assert_eq!(deliver_due(&before, Utc::now()).await.unwrap(), 1);
+        drop(before);
+
+        let after = node(&sqlite_path, json!({})).await;
+        assert_eq!(after.storage.count_pending_webhook_deliveries(&id).await.unwrap(), 1);
+        let receiver = Receiver::start(&[]).await;
+        sqlx::query("UPDATE webhook_subscriptions SET url = ?")
+            .bind(&receiver.url)
+            .execute(after.storage.pool())
+            .await
+            .unwrap();
+        assert_eq!(deliver_due(&after, later()).await.unwrap(), 1);
+        assert_eq!(receiver.received().len(), 1);
+        assert_eq!(receiver.received()[0].headers[ATTEMPT_HEADER], "2");
+        assert_eq!(after.storage.count_pending_webhook_deliveries(&id).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn subscriptions_failing_too_often_are_disabled() {
+        let receiver = Receiver::start(&[500; 8]).await;
+        let state = node(&scratch_sqlite(), json!({ "disable_after_failures": 3 })).await;
+        let mut events = state.sse_bus.subscribe();
+        let request = json!({ "url": receiver.url, "max_retries": 0 });
+        let id = subscribe(&state, request).await;
+        for _ in 0..4 {
+            cache_event(&state, &event("event.create", ALPHA)).await.unwrap();
+        }
+
+        assert_eq!(deliver_due(&state, Utc::now()).await.unwrap(), 3);
+        assert_eq!(receiver.received().len(), 3);
+        let disabled = state.storage.get_webhook_subscription(&id).await.unwrap().unwrap();
+        assert!(!disabled.enabled && disabled.disabled_at.is_some());
+        assert_eq!(disabled.consecutive_failures, 3);
+        let disabled = events.try_recv().expect("disabled event");
+        assert_eq!(disabled.event_type, WEBHOOK_DISABLED_EVENT);
+        assert_eq!(disabled.data["subscription_id"], id.as_str());
+        assert_eq!(disabled.data["last_error"], "answered 500");
+
+        // The rest stays queued, and nothing new is queued for it.
+        cache_event(&state, &event("event.create", ALPHA)).await.unwrap();
+        assert_eq!(deliver_due(&state, later()).await.unwrap(), 0);
+        assert_eq!(state.storage.count_pending_webhook_deliveries(&id).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn queued_statuses_collapse_to_the_latest_per_job_within_the_window() {
+        let receiver = Receiver::start(&[]).await;
+        let state = node(&scratch_sqlite(), json!({})).await;
+        state.node_config.write().await.event_coalescing.window_ms = 1000;
+        let id = subscribe(&state, json!({ "url": receiver.url })).await;
+        for (job_id, status) in [
+            ("j-1", "queued"),
+            ("j-1", "dispatched"),
+            ("j-2", "queued"),
+            ("j-1", "success"),
+        ] {
+            cache_event(&state, &job_status(job_id, status)).await.unwrap();
+        }
+        cache_event(&state, &event("event.create", ALPHA)).await.unwrap();
+
+        // Nothing goes out before the window closes.
+        assert_eq!(deliver_due(&state, Utc::now()).await.unwrap(), 0);
+        let subscribers = state.event_subscribers.list();
+        assert_eq!(subscribers.len(), 1);
+        let subscriber = &subscribers[0];
+        assert_eq!((subscriber.kind.as_str(), subscriber.window_ms), (WEBHOOK_SUBSCRIBER, 1000));
+
+        let closed = Utc::now() + ChronoDuration::seconds(2);
+        assert_eq!(deliver_due(&state, closed).await.unwrap(), 4);
+        let delivered: Vec<Value> = receiver
+            .received()
+            .iter()
+            .map(|received| serde_json::from_slice::<Value>(&received.body).unwrap())
+            .map(|body| {
+                let payload = &body["payload"];
+                json!([body["event"], payload["job_id"], payload["status"]])
+            })
+            .collect();
+        assert_eq!(
+            delivered,
+            [
+                json!(["job.status.changed", "j-1", "dispatched"]),
+                json!(["job.status.changed", "j-2", "queued"]),
+                json!(["job.status.changed", "j-1", "success"]),
+                json!(["event.create", null, "red"]),
+            ]
+        );
+        // The superseded status is dropped unsent.
+        assert_eq!(state.storage.count_pending_webhook_deliveries(&id).await.unwrap(), 0);
+        let subscriber = &state.event_subscribers.list()[0];
+        assert_eq!((subscriber.delivered, subscriber.collapsed), (4, 1));
+        assert_eq!(state.event_subscribers.metrics().collapsed, 1);
+
+        state.storage.delete_webhook_subscription(&id).await.unwrap();
+        assert_eq!(deliver_due(&state, later()).await.unwrap(), 0);
+        assert!(state.event_subscribers.list().is_empty());
+    }
+}
@@ -1,27 +1,58 @@
-﻿use async_trait::async_trait;
+﻿use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
 use chrono::Utc;
 use retasync_contract::{
-    CorrelationId, MeshCommandEnvelope, MeshEventEnvelope, MeshResultEnvelope, MeshTransferEnvelope,
-    TransferHint,
+    MeshCommandEnvelope, MeshEventEnvelope, MeshResultEnvelope, MeshTransferEnvelope, TransferHint,
 };
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use thiserror::Error;
-use tracing::info;
 use uuid::Uuid;
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+use crate::layers::CallMetrics;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct BridgeReceipt {
     pub message_id: String,
     pub accepted_at: String,
     pub transport: TransportSelection,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum TransportSelection {
     Link,
     Lxmf,
 }
 
+// Connection pool counters for bridges that hold daemon connections.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransportStatus {
+    pub endpoint: String,
+    pub pool_size: usize,
+    pub connected: usize,
+    pub in_flight: u64,
+    pub reconnects: u64,
+    pub timeouts: u64,
+}
+
+// What the daemon did with a request to stop delivering a message it was handed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CancelOutcome {
+    Cancelled,
+    AlreadyDelivered,
+    NotFound,
+    // The bridge has no way to recall a message once it is sent.
+    Unsupported,
+}
+
+// Prefix of a daemon error saying the destination has no path or link right now.
+pub const PEER_UNREACHABLE_ERROR: &str = "peer_unreachable";
+
 #[derive(Debug, Error)]
 pub enum BridgeError {
     #[error("daemon RPC unavailable")]
@@ -30,6 +61,10 @@ pub enum BridgeError {
     SendFailed(String),
     #[error("invalid payload: {0}")]
     InvalidPayload(String),
+    // The daemon is fine but the destination can't be reached directly; store-and-forward
+    // over LXMF may still deliver.
+    #[error("peer unreachable: {0}")]
+    PeerUnreachable(String),
 }
 
 #[async_trait]
@@ -52,12 +87,76 @@ pub trait RpcMeshBridge: Send + Sync {
     async fn query_receipt(&self, message_id: &str) -> Result<Option<BridgeReceipt>, BridgeError>;
 
     async fn poll_events(&self, limit: usize) -> Result<Vec<MeshEventEnvelope<Value>>, BridgeError>;
+
+    async fn poll_commands(
+        &self,
+        limit: usize,
+    ) -> Result<Vec<MeshCommandEnvelope<Value>>, BridgeError>;
+
+    async fn send_result(
+        &self,
+        envelope: MeshResultEnvelope<Value>,
+    ) -> Result<BridgeReceipt, BridgeError>;
+
+    async fn set_inbound_backpressure(&self, enabled: bool) -> Result<(), BridgeError>;
+
+    // Transfer chunks addressed to this node. Bridges that only send transfers have none.
+    async fn poll_transfers(
+        &self,
+        _limit: usize,
+    ) -> Result<Vec<MeshTransferEnvelope<Value>>, BridgeError> {
+        Ok(Vec::new())
+    }
+
+    // The transport a send with this hint would use. Bridges without routing knowledge honour
+    // an explicit link hint and otherwise assume LXMF.
+    fn planned_transport(&self, hint: Option<TransferHint>) -> TransportSelection {
+        match hint {
+            Some(TransferHint::Link) => TransportSelection::Link,
+            _ => TransportSelection::Lxmf,
+        }
+    }
+
+    // Asks the daemon to stop delivering a message, such as an LXMF envelope still propagating.
+    async fn cancel_message(&self, _message_id: &str) -> Result<CancelOutcome, BridgeError> {
+        Ok(CancelOutcome::Unsupported)
+    }
+
+    fn transport_status(&self) -> Option<TransportStatus> {
+        None
+    }
+
+    // Per-method counters from a metrics layer; wrappers forward it from their inner bridge.
+    fn call_metrics(&self) -> Option<BTreeMap<String, CallMetrics>> {
+        None
+    }
+}
+
+// What the in-memory bridge answers commands with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MockResults {
+    // `{"status": "accepted", "transport": ...}` for every operation.
+    #[default]
+    Accepted,
+    // The command payload, as a peer that stored the record would return it.
+    Echo,
+    // The command payload with every string replaced by its length, so a result schema that
+    // accepts the payload's shape rejects each of its fields.
+    Invalid,
 }
 
 #[derive(Debug, Clone)]
 pub struct InMemoryRpcMeshBridge {
     pub prefer_link: bool,
     pub link_available: bool,
+    inbound: Arc<Mutex<VecDeque<MeshCommandEnvelope<Value>>>>,
+    events: Arc<Mutex<VecDeque<MeshEventEnvelope<Value>>>>,
+    results: Arc<Mutex<Vec<MeshResultEnvelope<Value>>>>,
+    backpressure: Arc<AtomicBool>,
+    // Whether each message handed over has been delivered: commands are answered on the spot,
+    // while events and transfer chunks stay in flight until cancelled.
+    dispatched: Arc<Mutex<HashMap<String, bool>>>,
+    answers: Arc<Mutex<MockResults>>,
 }
 
 impl InMemoryRpcMeshBridge {
@@ -65,9 +164,54 @@ impl InMemoryRpcMeshBridge {
         Self {
             prefer_link,
             link_available,
+            inbound: Arc::new(Mutex::new(VecDeque::new())),
+            events: Arc::new(Mutex::new(VecDeque::new())),
+            results: Arc::new(Mutex::new(Vec::new())),
+            backpressure: Arc::new(AtomicBool::new(false)),
+            dispatched: Arc::new(Mutex::new(HashMap::new())),
+            answers: Arc::new(Mutex::new(MockResults::default())),
         }
     }
 
+    pub fn set_results(&self, answers: MockResults) {
+        *lock(&self.answers) = answers;
+    }
+
+    pub fn inject_command(&self, envelope: MeshCommandEnvelope<Value>) {
+        lock(&self.inbound).push_back(envelope);
+    }
+
+    pub fn inject_event(&self, envelope: MeshEventEnvelope<Value>) {
+        lock(&self.events).push_back(envelope);
+    }
+
+    pub fn pending_inbound(&self) -> usize {
+        lock(&self.inbound).len()
+    }
+
+    pub fn sent_results(&self) -> Vec<MeshResultEnvelope<Value>> {
+        lock(&self.results).clone()
+    }
+
+    pub fn inbound_backpressure(&self) -> bool {
+        self.backpressure.load(Ordering::SeqCst)
+    }
+
+    pub fn in_flight(&self) -> Vec<String> {
+        let dispatched = lock(&self.dispatched);
+        let mut in_flight: Vec<String> = dispatched
+            .iter()
+            .filter(|(_, delivered)| !**delivered)
+            .map(|(message_id, _)| message_id.clone())
+            .collect();
+        in_flight.sort();
+        in_flight
+    }
+
+    fn dispatch(&self, message_id: &str, delivered: bool) {
+        lock(&self.dispatched).insert(message_id.to_string(), delivered);
+    }
+
     pub fn select_transport(&self, hint: Option<TransferHint>) -> TransportSelection {
         match hint {
             Some(TransferHint::Link) if self.link_available => TransportSelection::Link,
@@ -92,13 +236,18 @@ impl RpcMeshBridge for InMemoryRpcMeshBridge {
         }
 
         let transport = self.select_transport(envelope.transport_hint.clone());
-        info!(
-            operation = %envelope.operation,
-            message_id = %envelope.message_id,
-            transport = ?transport,
-            "dispatching command with at-most-once semantics"
-        );
-
+        self.dispatch(&envelope.message_id, true);
+        let payload = match *lock(&self.answers) {
+            MockResults::Accepted => serde_json::json!({
+                "status": "accepted",
+                "transport": match transport {
+                    TransportSelection::Link => "link",
+                    TransportSelection::Lxmf => "lxmf",
+                }
+            }),
+            MockResults::Echo => envelope.payload,
+            MockResults::Invalid => garble(envelope.payload),
+        };
         Ok(MeshResultEnvelope {
             message_id: Uuid::now_v7().to_string(),
             correlation_id: envelope.message_id,
@@ -107,18 +256,14 @@ impl RpcMeshBridge for InMemoryRpcMeshBridge {
             source_identity: envelope.destination_identity,
             destination_identity: envelope.source_identity,
             content_type: "application/msgpack".to_string(),
-            payload: serde_json::json!({
-                "status": "accepted",
-                "transport": match transport {
-                    TransportSelection::Link => "link",
-                    TransportSelection::Lxmf => "lxmf",
-                }
-            }),
+            payload,
             ttl_ms: envelope.ttl_ms,
             transport_hint: Some(match transport {
                 TransportSelection::Link => TransferHint::Link,
                 TransportSelection::Lxmf => TransferHint::Lxmf,
             }),
+            trace: Vec::new(),
+            trace_truncated: false,
         })
     }
 
@@ -127,6 +272,7 @@ impl RpcMeshBridge for InMemoryRpcMeshBridge {
         envelope: MeshEventEnvelope<Value>,
     ) -> Result<BridgeReceipt, BridgeError> {
         let transport = self.select_transport(envelope.transport_hint);
+        self.dispatch(&envelope.message_id, false);
         Ok(BridgeReceipt {
             message_id: envelope.message_id,
             accepted_at: Utc::now().to_rfc3339(),
@@ -139,6 +285,7 @@ impl RpcMeshBridge for InMemoryRpcMeshBridge {
         envelope: MeshTransferEnvelope<Value>,
     ) -> Result<BridgeReceipt, BridgeError> {
         let transport = self.select_transport(envelope.transport_hint);
+        self.dispatch(&envelope.message_id, false);
         Ok(BridgeReceipt {
             message_id: envelope.message_id,
             accepted_at: Utc::now().to_rfc3339(),
@@ -166,7 +313,75 @@ impl RpcMeshBridge for InMemoryRpcMeshBridge {
         }))
     }
 
-    async fn poll_events(&self, _limit: usize) -> Result<Vec<MeshEventEnvelope<Value>>, BridgeError> {
-        Ok(Vec::new())
+    async fn poll_events(&self, limit: usize) -> Result<Vec<MeshEventEnvelope<Value>>, BridgeError> {
+        let mut events = lock(&self.events);
+        let take = limit.min(events.len());
+        Ok(events.drain(..take).collect())
+    }
+
+    async fn poll_commands(
+        &self,
+        limit: usize,
+    ) -> Result<Vec<MeshCommandEnvelope<Value>>, BridgeError> {
+        // Under backpressure, leave commands un-acked on the mesh side.
+        if self.inbound_backpressure() {
+            return Ok(Vec::new());
+        }
+        let mut inbound = lock(&self.inbound);
+        let take = limit.min(inbound.len());
+        Ok(inbound.drain(..take).collect())
+    }
+
+    async fn send_result(
+        &self,
+        envelope: MeshResultEnvelope<Value>,
+    ) -> Result<BridgeReceipt, BridgeError> {
+        let transport = self.select_transport(envelope.transport_hint.clone());
+        let receipt = BridgeReceipt {
+            message_id: envelope.message_id.clone(),
+            accepted_at: Utc::now().to_rfc3339(),
+            transport,
+        };
+        lock(&self.results).push(envelope);
+        Ok(receipt)
+    }
+
+    fn planned_transport(&self, hint: Option<TransferHint>) -> TransportSelection {
+        self.select_transport(hint)
     }
+
+    async fn cancel_message(&self, message_id: &str) -> Result<CancelOutcome, BridgeError> {
+        let mut dispatched = lock(&self.dispatched);
+        Ok(match dispatched.get(message_id) {
+            None => CancelOutcome::NotFound,
+            Some(true) => CancelOutcome::AlreadyDelivered,
+            Some(false) => {
+                dispatched.remove(message_id);
+                CancelOutcome::Cancelled
+            }
+        })
+    }
+
+    async fn set_inbound_backpressure(&self, enabled: bool) -> Result<(), BridgeError> {
+        self.backpressure.store(enabled, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+fn garble(value: Value) -> Value {
+    match value {
+        Value::String(text) => Value::from(text.len()),
+        Value::Array(items) => items.into_iter().map(garble).collect(),
+        Value::Object(object) => Value::Object(
+            object
+                .into_iter()
+                .map(|(key, value)| (key, garble(value)))
+                .collect(),
+        ),
+        other => other,
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
 }
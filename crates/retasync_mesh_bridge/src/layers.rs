@@ -0,0 +1,530 @@
+﻿use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use async_trait::async_trait;
+use retasync_contract::{
+    MeshCommandEnvelope, MeshEventEnvelope, MeshResultEnvelope, MeshTransferEnvelope, TransferHint,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::{debug, info, warn};
+
+use crate::bridge::{
+    BridgeError, BridgeReceipt, CancelOutcome, RpcMeshBridge, TransportSelection, TransportStatus,
+};
+use crate::simulation::BridgeMethod;
+
+// Names accepted in `bridge.layers`, listed outermost first.
+pub const BRIDGE_LAYER_NAMES: [&str; 3] = ["logging", "metrics", "recording"];
+
+pub trait BridgeLayer {
+    fn wrap(self, inner: Arc<dyn RpcMeshBridge>) -> Arc<dyn RpcMeshBridge>;
+}
+
+type Wrap = Box<dyn FnOnce(Arc<dyn RpcMeshBridge>) -> Arc<dyn RpcMeshBridge> + Send>;
+
+pub struct BridgeStack;
+
+impl BridgeStack {
+    pub fn builder() -> BridgeStackBuilder {
+        BridgeStackBuilder::default()
+    }
+}
+
+#[derive(Default)]
+pub struct BridgeStackBuilder {
+    layers: Vec<Wrap>,
+}
+
+impl BridgeStackBuilder {
+    // Layers are declared outermost first: the first one added sees every call before the rest.
+    pub fn layer<L>(mut self, layer: L) -> Self
+    where
+        L: BridgeLayer + Send + 'static,
+    {
+        self.layers.push(Box::new(move |inner| layer.wrap(inner)));
+        self
+    }
+
+    pub fn build(self, transport: Arc<dyn RpcMeshBridge>) -> Arc<dyn RpcMeshBridge> {
+        self.layers
+            .into_iter()
+            .rev()
+            .fold(transport, |inner, wrap| wrap(inner))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LoggingLayer;
+
+impl BridgeLayer for LoggingLayer {
+    fn wrap(self, inner: Arc<dyn RpcMeshBridge>) -> Arc<dyn RpcMeshBridge> {
+        Arc::new(LoggingBridge { inner })
+    }
+}
+
+struct LoggingBridge {
+    inner: Arc<dyn RpcMeshBridge>,
+}
+
+fn log_outcome<T>(method: BridgeMethod, result: &Result<T, BridgeError>) {
+    if let Err(err) = result {
+        warn!(method = method.name(), error = %err, "bridge call failed");
+    }
+}
+
+#[async_trait]
+impl RpcMeshBridge for LoggingBridge {
+    async fn send_command(
+        &self,
+        envelope: MeshCommandEnvelope<Value>,
+    ) -> Result<MeshResultEnvelope<Value>, BridgeError> {
+        info!(
+            operation = %envelope.operation,
+            message_id = %envelope.message_id,
+            transport = ?self.inner.planned_transport(envelope.transport_hint.clone()),
+            "dispatching command with at-most-once semantics"
+        );
+        let result = self.inner.send_command(envelope).await;
+        log_outcome(BridgeMethod::SendCommand, &result);
+        result
+    }
+
+    async fn publish_event(
+        &self,
+        envelope: MeshEventEnvelope<Value>,
+    ) -> Result<BridgeReceipt, BridgeError> {
+        debug!(event = %envelope.event, message_id = %envelope.message_id, "publishing event");
+        let result = self.inner.publish_event(envelope).await;
+        log_outcome(BridgeMethod::PublishEvent, &result);
+        result
+    }
+
+    async fn start_transfer(
+        &self,
+        envelope: MeshTransferEnvelope<Value>,
+    ) -> Result<BridgeReceipt, BridgeError> {
+        info!(
+            operation = %envelope.operation,
+            message_id = %envelope.message_id,
+            "starting transfer"
+        );
+        let result = self.inner.start_transfer(envelope).await;
+        log_outcome(BridgeMethod::StartTransfer, &result);
+        result
+    }
+
+    async fn query_receipt(&self, message_id: &str) -> Result<Option<BridgeReceipt>, BridgeError> {
+        let result = self.inner.query_receipt(message_id).await;
+        log_outcome(BridgeMethod::QueryReceipt, &result);
+        result
+    }
+
+    async fn poll_events(&self, limit: usize) -> Result<Vec<MeshEventEnvelope<Value>>, BridgeError> {
+        let result = self.inner.poll_events(limit).await;
+        log_outcome(BridgeMethod::PollEvents, &result);
+        result
+    }
+
+    async fn poll_commands(
+        &self,
+        limit: usize,
+    ) -> Result<Vec<MeshCommandEnvelope<Value>>, BridgeError> {
+        let result = self.inner.poll_commands(limit).await;
+        log_outcome(BridgeMethod::PollCommands, &result);
+        result
+    }
+
+    async fn send_result(
+        &self,
+        envelope: MeshResultEnvelope<Value>,
+    ) -> Result<BridgeReceipt, BridgeError> {
+        debug!(
+            operation = %envelope.operation,
+            correlation_id = %envelope.correlation_id,
+            "sending result"
+        );
+        let result = self.inner.send_result(envelope).await;
+        log_outcome(BridgeMethod::SendResult, &result);
+        result
+    }
+
+    async fn set_inbound_backpressure(&self, enabled: bool) -> Result<(), BridgeError> {
+        info!(enabled, "inbound backpressure changed");
+        self.inner.set_inbound_backpressure(enabled).await
+    }
+
+    async fn poll_transfers(
+        &self,
+        limit: usize,
+    ) -> Result<Vec<MeshTransferEnvelope<Value>>, BridgeError> {
+        self.inner.poll_transfers(limit).await
+    }
+
+    async fn cancel_message(&self, message_id: &str) -> Result<CancelOutcome, BridgeError> {
+        self.inner.cancel_message(message_id).await
+    }
+
+    fn planned_transport(&self, hint: Option<TransferHint>) -> TransportSelection {
+        self.inner.planned_transport(hint)
+    }
+
+    fn transport_status(&self) -> Option<TransportStatus> {
+        self.inner.transport_status()
+    }
+
+    fn call_metrics(&self) -> Option<BTreeMap<String, CallMetrics>> {
+        self.inner.call_metrics()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CallMetrics {
+    pub calls: u64,
+    pub errors: u64,
+    pub total_latency_ms: u64,
+    pub max_latency_ms: u64,
+}
+
+// Per-method call counters, shared between the layer and whoever reports them.
+#[derive(Debug, Default)]
+pub struct BridgeMetrics {
+    calls: Mutex<BTreeMap<&'static str, CallMetrics>>,
+}
+
+impl BridgeMetrics {
+    pub fn snapshot(&self) -> BTreeMap<String, CallMetrics> {
+        lock(&self.calls)
+            .iter()
+            .map(|(method, metrics)| (method.to_string(), metrics.clone()))
+            .collect()
+    }
+
+    fn observe(&self, method: BridgeMethod, started: Instant, failed: bool) {
+        let elapsed_ms = started.elapsed().as_millis() as u64;
+        let mut calls = lock(&self.calls);
+        let metrics = calls.entry(method.name()).or_default();
+        metrics.calls += 1;
+        metrics.errors += u64::from(failed);
+        metrics.total_latency_ms += elapsed_ms;
+        metrics.max_latency_ms = metrics.max_latency_ms.max(elapsed_ms);
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MetricsLayer {
+    metrics: Arc<BridgeMetrics>,
+}
+
+impl MetricsLayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn metrics(&self) -> Arc<BridgeMetrics> {
+        self.metrics.clone()
+    }
+}
+
+impl BridgeLayer for MetricsLayer {
+    fn wrap(self, inner: Arc<dyn RpcMeshBridge>) -> Arc<dyn RpcMeshBridge> {
+        Arc::new(MetricsBridge {
+            inner,
+            metrics: self.metrics,
+        })
+    }
+}
+
+struct MetricsBridge {
+    inner: Arc<dyn RpcMeshBridge>,
+    metrics: Arc<BridgeMetrics>,
+}
+
+impl MetricsBridge {
+    fn observe<T>(
+        &self,
+        method: BridgeMethod,
+        started: Instant,
+        result: Result<T, BridgeError>,
+    ) -> Result<T, BridgeError> {
+        self.metrics.observe(method, started, result.is_err());
+        result
+    }
+}
+
+#[async_trait]
+impl RpcMeshBridge for MetricsBridge {
+    async fn send_command(
+        &self,
+        envelope: MeshCommandEnvelope<Value>,
+    ) -> Result<MeshResultEnvelope<Value>, BridgeError> {
+        let started = Instant::now();
+        let result = self.inner.send_command(envelope).await;
+        self.observe(BridgeMethod::SendCommand, started, result)
+    }
+
+    async fn publish_event(
+        &self,
+        envelope: MeshEventEnvelope<Value>,
+    ) -> Result<BridgeReceipt, BridgeError> {
+        let started = Instant::now();
+        let result = self.inner.publish_event(envelope).await;
+        self.observe(BridgeMethod::PublishEvent, started, result)
+    }
+
+    async fn start_transfer(
+        &self,
+        envelope: MeshTransferEnvelope<Value>,
+    ) -> Result<BridgeReceipt, BridgeError> {
+        let started = Instant::now();
+        let result = self.inner.start_transfer(envelope).await;
+        self.observe(BridgeMethod::StartTransfer, started, result)
+    }
+
+    async fn query_receipt(&self, message_id: &str) -> Result<Option<BridgeReceipt>, BridgeError> {
+        let started = Instant::now();
+        let result = self.inner.query_receipt(message_id).await;
+        self.observe(BridgeMethod::QueryReceipt, started, result)
+    }
+
+    async fn poll_events(&self, limit: usize) -> Result<Vec<MeshEventEnvelope<Value>>, BridgeError> {
+        let started = Instant::now();
+        let result = self.inner.poll_events(limit).await;
+        self.observe(BridgeMethod::PollEvents, started, result)
+    }
+
+    async fn poll_commands(
+        &self,
+        limit: usize,
+    ) -> Result<Vec<MeshCommandEnvelope<Value>>, BridgeError> {
+        let started = Instant::now();
+        let result = self.inner.poll_commands(limit).await;
+        self.observe(BridgeMethod::PollCommands, started, result)
+    }
+
+    async fn send_result(
+        &self,
+        envelope: MeshResultEnvelope<Value>,
+    ) -> Result<BridgeReceipt, BridgeError> {
+        let started = Instant::now();
+        let result = self.inner.send_result(envelope).await;
+        self.observe(BridgeMethod::SendResult, started, result)
+    }
+
+    async fn set_inbound_backpressure(&self, enabled: bool) -> Result<(), BridgeError> {
+        self.inner.set_inbound_backpressure(enabled).await
+    }
+
+    async fn poll_transfers(
+        &self,
+        limit: usize,
+    ) -> Result<Vec<MeshTransferEnvelope<Value>>, BridgeError> {
+        self.inner.poll_transfers(limit).await
+    }
+
+    async fn cancel_message(&self, message_id: &str) -> Result<CancelOutcome, BridgeError> {
+        self.inner.cancel_message(message_id).await
+    }
+
+    fn planned_transport(&self, hint: Option<TransferHint>) -> TransportSelection {
+        self.inner.planned_transport(hint)
+    }
+
+    fn transport_status(&self) -> Option<TransportStatus> {
+        self.inner.transport_status()
+    }
+
+    fn call_metrics(&self) -> Option<BTreeMap<String, CallMetrics>> {
+        Some(self.metrics.snapshot())
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BridgeLayer, BridgeStack, LoggingLayer, MetricsLayer};
+    use crate::bridge::{
+        BridgeError, BridgeReceipt, InMemoryRpcMeshBridge, RpcMeshBridge, TransportSelection,
+    };
+    use async_trait::async_trait;
+    use chrono::Utc;
+    use retasync_contract::{
+        MeshCommandEnvelope, MeshEventEnvelope, MeshResultEnvelope, MeshTransferEnvelope,
+    };
+    use serde_json::{json, Value};
+    use std::sync::{Arc, Mutex};
+
+    fn command(operation: &str) -> MeshCommandEnvelope<Value> {
+        MeshCommandEnvelope {
+            message_id: "msg-1".to_string(),
+            operation: operation.to_string(),
+            sent_at: Utc::now(),
+            source_identity: "a".repeat(32).parse().unwrap(),
+            destination_identity: "b".repeat(32).parse().unwrap(),
+            content_type: "application/json".to_string(),
+            payload: json!({}),
+            ttl_ms: None,
+            transport_hint: None,
+            trace: Vec::new(),
+            trace_truncated: false,
+            meta: None,
+        }
+    }
+
+    // Records entry and exit of every call so a test can see the order layers ran in.
+    struct ProbeLayer {
+        name: &'static str,
+        calls: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl BridgeLayer for ProbeLayer {
+        fn wrap(self, inner: Arc<dyn RpcMeshBridge>) -> Arc<dyn RpcMeshBridge> {
+            Arc::new(ProbeBridge { layer: self, inner })
+        }
+    }
+
+    struct ProbeBridge {
+        layer: ProbeLayer,
+        inner: Arc<dyn RpcMeshBridge>,
+    }
+
+    impl ProbeBridge {
+        fn note(&self, mark: &str, method: &str) {
+            let entry = format!("{}{mark}{method}", self.layer.name);
+            self.layer.calls.lock().unwrap().push(entry);
+        }
+    }
+
+    #[async_trait]
+    impl RpcMeshBridge for ProbeBridge {
+        async fn send_command(
+            &self,
+            envelope: MeshCommandEnvelope<Value>,
+        ) -> Result<MeshResultEnvelope<Value>, BridgeError> {
+            self.note(">", "send_command");
+            let result = self.inner.send_command(envelope).await;
+            self.note("<", "send_command");
+            result
+        }
+
+        async fn publish_event(
+            &self,
+            envelope: MeshEventEnvelope<Value>,
+        ) -> Result<BridgeReceipt, BridgeError> {
+            self.inner.publish_event(envelope).await
+        }
+
+        async fn start_transfer(
+            &self,
+            envelope: MeshTransferEnvelope<Value>,
+        ) -> Result<BridgeReceipt, BridgeError> {
+            self.inner.start_transfer(envelope).await
+        }
+
+        async fn query_receipt(
+            &self,
+            message_id: &str,
+        ) -> Result<Option<BridgeReceipt>, BridgeError> {
+            self.inner.query_receipt(message_id).await
+        }
+
+        async fn poll_events(
+            &self,
+            limit: usize,
+        ) -> Result<Vec<MeshEventEnvelope<Value>>, BridgeError> {
+            self.note(">", "poll_events");
+            self.inner.poll_events(limit).await
+        }
+
+        async fn poll_commands(
+            &self,
+            limit: usize,
+        ) -> Result<Vec<MeshCommandEnvelope<Value>>, BridgeError> {
+            self.inner.poll_commands(limit).await
+        }
+
+        async fn send_result(
+            &self,
+            envelope: MeshResultEnvelope<Value>,
+        ) -> Result<BridgeReceipt, BridgeError> {
+            self.inner.send_result(envelope).await
+        }
+
+        async fn set_inbound_backpressure(&self, enabled: bool) -> Result<(), BridgeError> {
+            self.inner.set_inbound_backpressure(enabled).await
+        }
+    }
+
+    #[tokio::test]
+    async fn metrics_layer_counts_calls_and_errors_per_method() {
+        let layer = MetricsLayer::new();
+        let metrics = layer.metrics();
+        let bridge = layer.wrap(Arc::new(InMemoryRpcMeshBridge::new(false, false)));
+
+        bridge.send_command(command("event.create")).await.unwrap();
+        assert!(bridge.send_command(command(" ")).await.is_err());
+        bridge.poll_events(10).await.unwrap();
+
+        let snapshot = metrics.snapshot();
+        assert_eq!((snapshot["send_command"].calls, snapshot["send_command"].errors), (2, 1));
+        assert_eq!(snapshot["poll_events"].calls, 1);
+        assert!(!snapshot.contains_key("send_result"));
+        assert_eq!(bridge.call_metrics(), Some(snapshot));
+    }
+
+    #[tokio::test]
+    async fn logging_layer_is_transparent() {
+        let inner = Arc::new(InMemoryRpcMeshBridge::new(true, true));
+        let bridge = LoggingLayer.wrap(inner.clone());
+
+        assert_eq!(bridge.planned_transport(None), TransportSelection::Link);
+        let result = bridge.send_command(command("event.create")).await.unwrap();
+        assert_eq!(result.payload["transport"], "link");
+        bridge.set_inbound_backpressure(true).await.unwrap();
+        assert!(inner.inbound_backpressure());
+        assert!(matches!(
+            bridge.send_command(command("")).await,
+            Err(BridgeError::InvalidPayload(_))
+        ));
+        assert_eq!(bridge.call_metrics(), None);
+    }
+
+    #[tokio::test]
+    async fn stacks_run_layers_outermost_first() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let probe = |name| ProbeLayer {
+            name,
+            calls: calls.clone(),
+        };
+        let metrics = MetricsLayer::new();
+        let handle = metrics.metrics();
+        let bridge = BridgeStack::builder()
+            .layer(probe("outer"))
+            .layer(metrics)
+            .layer(probe("middle"))
+            .layer(probe("inner"))
+            .build(Arc::new(InMemoryRpcMeshBridge::new(false, false)));
+
+        bridge.send_command(command("event.create")).await.unwrap();
+        bridge.poll_events(1).await.unwrap();
+        assert_eq!(
+            *calls.lock().unwrap(),
+            [
+                "outer>send_command",
+                "middle>send_command",
+                "inner>send_command",
+                "inner<send_command",
+                "middle<send_command",
+                "outer<send_command",
+                "outer>poll_events",
+                "middle>poll_events",
+                "inner>poll_events",
+            ]
+        );
+        assert_eq!(handle.snapshot()["send_command"].calls, 1);
+    }
+}
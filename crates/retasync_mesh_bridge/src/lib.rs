@@ -1,5 +1,33 @@
 ﻿mod bridge;
+mod layers;
+mod loopback;
+mod peers;
+mod replay;
+mod simulation;
+mod tcp;
 
 pub use bridge::{
-    BridgeError, BridgeReceipt, InMemoryRpcMeshBridge, RpcMeshBridge, TransportSelection,
+    BridgeError, BridgeReceipt, CancelOutcome, InMemoryRpcMeshBridge, MockResults, RpcMeshBridge,
+    TransportSelection, TransportStatus, PEER_UNREACHABLE_ERROR,
+};
+pub use layers::{
+    BridgeLayer, BridgeMetrics, BridgeStack, BridgeStackBuilder, CallMetrics, LoggingLayer,
+    MetricsLayer, BRIDGE_LAYER_NAMES,
+};
+pub use loopback::{LoopbackMeshBridge, DEFAULT_LOOPBACK_REPLY_TIMEOUT};
+pub use peers::{
+    ClockEstimate, ClockSample, ClockSampleSource, Compatibility, PeerCapabilities, PeerClock,
+    PeerDirectory, PeerHandshake, CLOCK_SAMPLE_HISTORY,
+};
+pub use replay::{
+    read_recording, summarize, BridgeRecord, CallStats, RecordedOutcome, RecordingBridge,
+    RecordingError, RecordingLayer, RecordingSummary, ReplayBridge, ReplayMatching,
+    RECORD_CHANNEL_CAPACITY,
+};
+pub use simulation::{LossProfile, PartitionWindow, SimulatedMeshBridge, SimulationProfile};
+pub use tcp::{
+    read_rpc_frame, rpc_frame_limits, write_rpc_frame, RpcFrame, TcpPoolSettings,
+    TcpRpcMeshBridge,
+    DEFAULT_PING_INTERVAL_SECS, DEFAULT_POOL_SIZE, DEFAULT_REQUEST_TIMEOUT_SECS,
+    MAX_RPC_FRAME_BYTES,
 };
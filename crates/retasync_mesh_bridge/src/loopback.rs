@@ -0,0 +1,160 @@
+﻿use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::Utc;
+use retasync_contract::{
+    MeshCommandEnvelope, MeshEventEnvelope, MeshResultEnvelope, MeshTransferEnvelope, TransferHint,
+};
+use serde_json::Value;
+use tokio::sync::oneshot;
+
+use crate::bridge::{BridgeError, BridgeReceipt, RpcMeshBridge, TransportSelection};
+
+pub const DEFAULT_LOOPBACK_REPLY_TIMEOUT: Duration = Duration::from_secs(30);
+
+type Waiters = HashMap<String, oneshot::Sender<MeshResultEnvelope<Value>>>;
+
+#[derive(Debug, Default)]
+struct Mailbox {
+    commands: Mutex<VecDeque<MeshCommandEnvelope<Value>>>,
+    events: Mutex<VecDeque<MeshEventEnvelope<Value>>>,
+    transfers: Mutex<VecDeque<MeshTransferEnvelope<Value>>>,
+    waiters: Mutex<Waiters>,
+}
+
+// One end of an in-process link between two nodes: commands, events and transfer chunks sent
+// here are polled by the other end, and the other end's `send_result` completes the pending
+// `send_command`.
+#[derive(Debug, Clone)]
+pub struct LoopbackMeshBridge {
+    local: Arc<Mailbox>,
+    remote: Arc<Mailbox>,
+    reply_timeout: Duration,
+}
+
+impl LoopbackMeshBridge {
+    pub fn pair() -> (Self, Self) {
+        let (a, b) = (Arc::new(Mailbox::default()), Arc::new(Mailbox::default()));
+        (
+            Self {
+                local: a.clone(),
+                remote: b.clone(),
+                reply_timeout: DEFAULT_LOOPBACK_REPLY_TIMEOUT,
+            },
+            Self {
+                local: b,
+                remote: a,
+                reply_timeout: DEFAULT_LOOPBACK_REPLY_TIMEOUT,
+            },
+        )
+    }
+
+    pub fn with_reply_timeout(mut self, reply_timeout: Duration) -> Self {
+        self.reply_timeout = reply_timeout;
+        self
+    }
+
+    fn receipt(message_id: String) -> BridgeReceipt {
+        BridgeReceipt {
+            message_id,
+            accepted_at: Utc::now().to_rfc3339(),
+            transport: TransportSelection::Link,
+        }
+    }
+}
+
+#[async_trait]
+impl RpcMeshBridge for LoopbackMeshBridge {
+    async fn send_command(
+        &self,
+        envelope: MeshCommandEnvelope<Value>,
+    ) -> Result<MeshResultEnvelope<Value>, BridgeError> {
+        let (tx, rx) = oneshot::channel();
+        let message_id = envelope.message_id.clone();
+        lock(&self.local.waiters).insert(message_id.clone(), tx);
+        lock(&self.remote.commands).push_back(envelope);
+
+        match tokio::time::timeout(self.reply_timeout, rx).await {
+            Ok(Ok(result)) => Ok(result),
+            Ok(Err(_)) => Err(BridgeError::SendFailed("loopback peer dropped".to_string())),
+            Err(_) => {
+                lock(&self.local.waiters).remove(&message_id);
+                Err(BridgeError::SendFailed(format!(
+                    "no result for {message_id} within {:?}",
+                    self.reply_timeout
+                )))
+            }
+        }
+    }
+
+    async fn publish_event(
+        &self,
+        envelope: MeshEventEnvelope<Value>,
+    ) -> Result<BridgeReceipt, BridgeError> {
+        let message_id = envelope.message_id.clone();
+        lock(&self.remote.events).push_back(envelope);
+        Ok(Self::receipt(message_id))
+    }
+
+    async fn start_transfer(
+        &self,
+        envelope: MeshTransferEnvelope<Value>,
+    ) -> Result<BridgeReceipt, BridgeError> {
+        let message_id = envelope.message_id.clone();
+        lock(&self.remote.transfers).push_back(envelope);
+        Ok(Self::receipt(message_id))
+    }
+
+    async fn query_receipt(&self, _message_id: &str) -> Result<Option<BridgeReceipt>, BridgeError> {
+        Ok(None)
+    }
+
+    async fn poll_events(&self, limit: usize) -> Result<Vec<MeshEventEnvelope<Value>>, BridgeError> {
+        let mut events = lock(&self.local.events);
+        let take = limit.min(events.len());
+        Ok(events.drain(..take).collect())
+    }
+
+    async fn poll_commands(
+        &self,
+        limit: usize,
+    ) -> Result<Vec<MeshCommandEnvelope<Value>>, BridgeError> {
+        let mut commands = lock(&self.local.commands);
+        let take = limit.min(commands.len());
+        Ok(commands.drain(..take).collect())
+    }
+
+    async fn send_result(
+        &self,
+        envelope: MeshResultEnvelope<Value>,
+    ) -> Result<BridgeReceipt, BridgeError> {
+        let message_id = envelope.message_id.clone();
+        if let Some(waiter) = lock(&self.remote.waiters).remove(&envelope.correlation_id) {
+            let _ = waiter.send(envelope);
+        }
+        Ok(Self::receipt(message_id))
+    }
+
+    async fn poll_transfers(
+        &self,
+        limit: usize,
+    ) -> Result<Vec<MeshTransferEnvelope<Value>>, BridgeError> {
+        let mut transfers = lock(&self.local.transfers);
+        let take = limit.min(transfers.len());
+        Ok(transfers.drain(..take).collect())
+    }
+
+    async fn set_inbound_backpressure(&self, _enabled: bool) -> Result<(), BridgeError> {
+        Ok(())
+    }
+
+    fn planned_transport(&self, _hint: Option<TransferHint>) -> TransportSelection {
+        TransportSelection::Link
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
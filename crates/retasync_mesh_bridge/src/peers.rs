@@ -0,0 +1,348 @@
+﻿use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, RwLock};
+
+use chrono::{DateTime, Utc};
+use retasync_contract::{
+    encode_canonical, CodecError, Compression, IdentityHash, CONTENT_TYPE_MSGPACK,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerCapabilities {
+    #[serde(default)]
+    pub compression: Vec<Compression>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Compatibility {
+    Compatible,
+    Degraded,
+    Incompatible,
+}
+
+// Outcome of the last `node.hello` exchange with a peer; `hello` is the peer's payload as sent.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PeerHandshake {
+    pub compatibility: Compatibility,
+    pub hello: Value,
+    pub checked_at: String,
+}
+
+// Raw samples kept per peer for `GET /v1/peers/{hash}/clock`.
+pub const CLOCK_SAMPLE_HISTORY: usize = 32;
+// Each sample is replaced by the median of the latest few before smoothing, so a lone wild
+// timestamp never reaches the estimate.
+const CLOCK_MEDIAN_WINDOW: usize = 5;
+const CLOCK_SMOOTHING: f64 = 0.2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClockSampleSource {
+    // A result answering one of our commands; transit is half the measured round trip.
+    Result,
+    // An uncorrelated envelope; transit is the configured guess.
+    Event,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClockSample {
+    pub observed_at: DateTime<Utc>,
+    // The peer's `sent_at` minus our receive time less transit: positive when its clock is ahead.
+    pub offset_ms: i64,
+    pub transit_ms: i64,
+    pub source: ClockSampleSource,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClockEstimate {
+    pub offset_ms: i64,
+    pub samples: u64,
+    // Median distance of the latest samples from their median; lower is more certain.
+    pub spread_ms: i64,
+    pub drifting: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PeerClock {
+    #[serde(flatten)]
+    pub estimate: ClockEstimate,
+    pub recent: VecDeque<ClockSample>,
+    #[serde(skip)]
+    smoothed_ms: f64,
+}
+
+impl PeerClock {
+    pub fn record(&mut self, sample: ClockSample) -> &ClockEstimate {
+        self.recent.push_back(sample);
+        while self.recent.len() > CLOCK_SAMPLE_HISTORY {
+            self.recent.pop_front();
+        }
+        let mut window: Vec<i64> = self
+            .recent
+            .iter()
+            .rev()
+            .take(CLOCK_MEDIAN_WINDOW)
+            .map(|sample| sample.offset_ms)
+            .collect();
+        let middle = median(&mut window);
+        let mut deviations: Vec<i64> = window
+            .iter()
+            .map(|offset| (offset - middle).abs())
+            .collect();
+
+        self.smoothed_ms = if self.estimate.samples == 0 {
+            middle as f64
+        } else {
+            self.smoothed_ms + CLOCK_SMOOTHING * (middle as f64 - self.smoothed_ms)
+        };
+        self.estimate.offset_ms = self.smoothed_ms.round() as i64;
+        self.estimate.spread_ms = median(&mut deviations);
+        self.estimate.samples += 1;
+        &self.estimate
+    }
+}
+
+fn median(values: &mut [i64]) -> i64 {
+    values.sort_unstable();
+    match values.len() {
+        0 => 0,
+        len if len % 2 == 1 => values[len / 2],
+        len => (values[len / 2 - 1] + values[len / 2]) / 2,
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PeerDirectory {
+    peers: Arc<RwLock<BTreeMap<IdentityHash, PeerCapabilities>>>,
+    handshakes: Arc<RwLock<BTreeMap<IdentityHash, PeerHandshake>>>,
+    last_seen: Arc<RwLock<BTreeMap<IdentityHash, DateTime<Utc>>>>,
+    clocks: Arc<RwLock<BTreeMap<IdentityHash, PeerClock>>>,
+}
+
+impl PeerDirectory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_capabilities(&self, identity_hash: &IdentityHash, capabilities: PeerCapabilities) {
+        self.peers
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .insert(identity_hash.clone(), capabilities);
+    }
+
+    pub fn capabilities(&self, identity_hash: &str) -> Option<PeerCapabilities> {
+        self.peers
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .get(identity_hash)
+            .cloned()
+    }
+
+    pub fn list(&self) -> BTreeMap<IdentityHash, PeerCapabilities> {
+        self.peers
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    pub fn record_handshake(&self, identity_hash: &IdentityHash, handshake: PeerHandshake) {
+        self.handshakes
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .insert(identity_hash.clone(), handshake);
+    }
+
+    pub fn handshake(&self, identity_hash: &str) -> Option<PeerHandshake> {
+        self.handshakes
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .get(identity_hash)
+            .cloned()
+    }
+
+    pub fn handshakes(&self) -> BTreeMap<IdentityHash, PeerHandshake> {
+        self.handshakes
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    // Anything heard from the peer counts: inbound commands, events, results, and handshakes.
+    pub fn record_contact(&self, identity_hash: &IdentityHash, at: DateTime<Utc>) {
+        let mut last_seen = self
+            .last_seen
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let seen = last_seen.entry(identity_hash.clone()).or_insert(at);
+        *seen = (*seen).max(at);
+    }
+
+    pub fn last_seen(&self, identity_hash: &str) -> Option<DateTime<Utc>> {
+        self.last_seen
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .get(identity_hash)
+            .copied()
+    }
+
+    pub fn contacts(&self) -> BTreeMap<IdentityHash, DateTime<Utc>> {
+        self.last_seen
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    pub fn record_clock_sample(
+        &self,
+        identity_hash: &IdentityHash,
+        sample: ClockSample,
+    ) -> ClockEstimate {
+        self.clocks
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .entry(identity_hash.clone())
+            .or_default()
+            .record(sample)
+            .clone()
+    }
+
+    // Returns whether the flag changed, so a drift is reported once per excursion.
+    pub fn mark_clock_drift(&self, identity_hash: &str, drifting: bool) -> bool {
+        let mut clocks = self
+            .clocks
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        match clocks.get_mut(identity_hash) {
+            Some(clock) if clock.estimate.drifting != drifting => {
+                clock.estimate.drifting = drifting;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn clock(&self, identity_hash: &str) -> Option<PeerClock> {
+        self.clocks
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .get(identity_hash)
+            .cloned()
+    }
+
+    pub fn clock_estimates(&self) -> BTreeMap<IdentityHash, ClockEstimate> {
+        self.clocks
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .iter()
+            .map(|(identity_hash, clock)| (identity_hash.clone(), clock.estimate.clone()))
+            .collect()
+    }
+
+    pub fn is_known(&self, identity_hash: &str) -> bool {
+        self.capabilities(identity_hash).is_some()
+            || self.handshake(identity_hash).is_some()
+            || self.last_seen(identity_hash).is_some()
+    }
+
+    pub fn negotiate_content_type(
+        &self,
+        destination_identity: &str,
+        payload: &Value,
+        threshold: usize,
+    ) -> Result<String, CodecError> {
+        let compression = self
+            .capabilities(destination_identity)
+            .and_then(|capabilities| capabilities.compression.first().copied());
+
+        match compression {
+            Some(compression) if encode_canonical(payload)?.len() > threshold => {
+                Ok(compression.content_type().to_string())
+            }
+            _ => Ok(CONTENT_TYPE_MSGPACK.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        ClockSample, ClockSampleSource, PeerCapabilities, PeerDirectory, CLOCK_SAMPLE_HISTORY,
+    };
+    use chrono::Utc;
+    use retasync_contract::{Compression, CONTENT_TYPE_MSGPACK};
+    use serde_json::json;
+
+    const KNOWN_PEER: &str = "a1b2c3d4e5f60718293a4b5c6d7e8f90";
+
+    #[test]
+    fn unknown_peers_fall_back_to_uncompressed() {
+        let directory = PeerDirectory::new();
+        directory.set_capabilities(
+            &KNOWN_PEER.parse().unwrap(),
+            PeerCapabilities {
+                compression: vec![Compression::Zstd],
+            },
+        );
+        let payload = json!({ "summary": "x".repeat(8192) });
+
+        assert_eq!(
+            directory
+                .negotiate_content_type("unknown-peer", &payload, 4096)
+                .unwrap(),
+            CONTENT_TYPE_MSGPACK
+        );
+        assert_eq!(
+            directory
+                .negotiate_content_type(KNOWN_PEER, &payload, 4096)
+                .unwrap(),
+            Compression::Zstd.content_type()
+        );
+        assert_eq!(
+            directory
+                .negotiate_content_type(KNOWN_PEER, &json!({ "uid": "evt-1" }), 4096)
+                .unwrap(),
+            CONTENT_TYPE_MSGPACK
+        );
+    }
+
+    fn sample(offset_ms: i64) -> ClockSample {
+        ClockSample {
+            observed_at: Utc::now(),
+            offset_ms,
+            transit_ms: 150,
+            source: ClockSampleSource::Result,
+        }
+    }
+
+    #[test]
+    fn clock_estimate_converges_and_ignores_outliers() {
+        let directory = PeerDirectory::new();
+        let peer = KNOWN_PEER.parse().unwrap();
+        // A peer 9s ahead, measured with a few hundred milliseconds of jitter.
+        let jitter = [-300, 120, 250, -80, 40, -210, 310, 0];
+        for round in 0..40 {
+            directory.record_clock_sample(&peer, sample(9_000 + jitter[round % jitter.len()]));
+        }
+        let settled = directory.clock(KNOWN_PEER).unwrap().estimate;
+        assert!((settled.offset_ms - 9_000).abs() < 150, "{settled:?}");
+        assert!(settled.spread_ms < 400);
+        assert_eq!(settled.samples, 40);
+
+        // A timestamp an hour out, then one from the other direction, barely move it.
+        directory.record_clock_sample(&peer, sample(3_600_000));
+        directory.record_clock_sample(&peer, sample(9_050));
+        directory.record_clock_sample(&peer, sample(-3_600_000));
+        let estimate = directory.record_clock_sample(&peer, sample(8_980));
+        assert!((estimate.offset_ms - 9_000).abs() < 250, "{estimate:?}");
+        let clock = directory.clock(KNOWN_PEER).unwrap();
+        assert_eq!(clock.recent.len(), CLOCK_SAMPLE_HISTORY);
+
+        assert!(directory.mark_clock_drift(KNOWN_PEER, true));
+        assert!(!directory.mark_clock_drift(KNOWN_PEER, true));
+        assert!(directory.clock_estimates()[KNOWN_PEER].drifting);
+        assert!(!directory.mark_clock_drift("unknown", true));
+    }
+}
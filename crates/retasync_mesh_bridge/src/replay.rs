@@ -0,0 +1,614 @@
+﻿use std::collections::{BTreeMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use retasync_contract::{
+    decode_canonical, encode_canonical, CodecError, MeshCommandEnvelope, MeshEventEnvelope,
+    MeshResultEnvelope, MeshTransferEnvelope, TransferHint,
+};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use thiserror::Error;
+use tokio::sync::oneshot;
+use tracing::{error, warn};
+
+use crate::bridge::{
+    BridgeError, BridgeReceipt, CancelOutcome, RpcMeshBridge, TransportSelection, TransportStatus,
+};
+use crate::layers::{BridgeLayer, CallMetrics};
+use crate::simulation::BridgeMethod;
+
+pub const RECORD_CHANNEL_CAPACITY: usize = 1024;
+
+#[derive(Debug, Error)]
+pub enum RecordingError {
+    #[error("recording I/O failed: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid recording frame: {0}")]
+    Codec(#[from] CodecError),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum RecordedOutcome {
+    Ok { response: Vec<u8> },
+    Err { kind: String, message: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BridgeRecord {
+    pub method: String,
+    pub operation: Option<String>,
+    pub recorded_at: DateTime<Utc>,
+    pub request: Vec<u8>,
+    pub outcome: RecordedOutcome,
+}
+
+impl BridgeRecord {
+    pub fn is_error(&self) -> bool {
+        matches!(self.outcome, RecordedOutcome::Err { .. })
+    }
+}
+
+// Frames are a big-endian u32 length followed by the canonical msgpack record.
+pub fn read_recording(path: &Path) -> Result<Vec<BridgeRecord>, RecordingError> {
+    let bytes = std::fs::read(path)?;
+    let mut records = Vec::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let Some(header) = bytes.get(offset..offset + 4) else {
+            warn!(path = %path.display(), offset, "recording ends with a truncated frame");
+            break;
+        };
+        let len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let Some(frame) = bytes.get(offset + 4..offset + 4 + len) else {
+            warn!(path = %path.display(), offset, "recording ends with a truncated frame");
+            break;
+        };
+        records.push(decode_canonical(frame)?);
+        offset += 4 + len;
+    }
+    Ok(records)
+}
+
+fn write_frame(writer: &mut impl Write, record: &BridgeRecord) -> Result<(), RecordingError> {
+    let frame = encode_canonical(record)?;
+    writer.write_all(&(frame.len() as u32).to_be_bytes())?;
+    writer.write_all(&frame)?;
+    writer.flush()?;
+    Ok(())
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct CallStats {
+    pub calls: usize,
+    pub errors: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct RecordingSummary {
+    pub records: usize,
+    pub errors: usize,
+    pub error_rate: f64,
+    pub first_at: Option<DateTime<Utc>>,
+    pub last_at: Option<DateTime<Utc>>,
+    pub duration_ms: i64,
+    pub calls: BTreeMap<String, CallStats>,
+}
+
+pub fn summarize(records: &[BridgeRecord]) -> RecordingSummary {
+    let mut summary = RecordingSummary {
+        records: records.len(),
+        first_at: records.iter().map(|record| record.recorded_at).min(),
+        last_at: records.iter().map(|record| record.recorded_at).max(),
+        ..RecordingSummary::default()
+    };
+    for record in records {
+        let key = match &record.operation {
+            Some(operation) => format!("{} {operation}", record.method),
+            None => record.method.clone(),
+        };
+        let stats = summary.calls.entry(key).or_default();
+        stats.calls += 1;
+        if record.is_error() {
+            stats.errors += 1;
+            summary.errors += 1;
+        }
+    }
+    if let (Some(first), Some(last)) = (summary.first_at, summary.last_at) {
+        summary.duration_ms = (last - first).num_milliseconds();
+    }
+    if summary.records > 0 {
+        summary.error_rate = summary.errors as f64 / summary.records as f64;
+    }
+    summary
+}
+
+fn error_parts(error: &BridgeError) -> (&'static str, String) {
+    match error {
+        BridgeError::DaemonUnavailable => ("daemon_unavailable", String::new()),
+        BridgeError::SendFailed(message) => ("send_failed", message.clone()),
+        BridgeError::InvalidPayload(message) => ("invalid_payload", message.clone()),
+        BridgeError::PeerUnreachable(message) => ("peer_unreachable", message.clone()),
+    }
+}
+
+fn restore_error(kind: &str, message: &str) -> BridgeError {
+    match kind {
+        "daemon_unavailable" => BridgeError::DaemonUnavailable,
+        "invalid_payload" => BridgeError::InvalidPayload(message.to_string()),
+        "peer_unreachable" => BridgeError::PeerUnreachable(message.to_string()),
+        _ => BridgeError::SendFailed(message.to_string()),
+    }
+}
+
+enum WriterMessage {
+    Record(Box<BridgeRecord>),
+    Flush(oneshot::Sender<()>),
+}
+
+pub struct RecordingBridge {
+    inner: Arc<dyn RpcMeshBridge>,
+    sender: SyncSender<WriterMessage>,
+    dropped: AtomicU64,
+}
+
+// Opens the recording up front so a bad path fails before the stack is assembled.
+pub struct RecordingLayer {
+    sender: SyncSender<WriterMessage>,
+}
+
+impl RecordingLayer {
+    pub fn open(path: &Path) -> Result<Self, RecordingError> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let (sender, receiver) = sync_channel(RECORD_CHANNEL_CAPACITY);
+        let display = path.display().to_string();
+        std::thread::Builder::new()
+            .name("bridge-recorder".to_string())
+            .spawn(move || write_records(file, receiver, &display))?;
+        Ok(Self { sender })
+    }
+
+    fn attach(self, inner: Arc<dyn RpcMeshBridge>) -> RecordingBridge {
+        RecordingBridge {
+            inner,
+            sender: self.sender,
+            dropped: AtomicU64::new(0),
+        }
+    }
+}
+
+impl BridgeLayer for RecordingLayer {
+    fn wrap(self, inner: Arc<dyn RpcMeshBridge>) -> Arc<dyn RpcMeshBridge> {
+        Arc::new(self.attach(inner))
+    }
+}
+
+impl RecordingBridge {
+    pub fn start(inner: Arc<dyn RpcMeshBridge>, path: &Path) -> Result<Self, RecordingError> {
+        Ok(RecordingLayer::open(path)?.attach(inner))
+    }
+
+    pub fn dropped_records(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    pub async fn flush(&self) {
+        let (done, flushed) = oneshot::channel();
+        if self.sender.send(WriterMessage::Flush(done)).is_ok() {
+            let _ = flushed.await;
+        }
+    }
+
+    // Never blocks the caller: when the writer falls behind the record is counted and dropped.
+    fn record<T: Serialize>(
+        &self,
+        method: BridgeMethod,
+        operation: Option<&str>,
+        request: Vec<u8>,
+        result: &Result<T, BridgeError>,
+    ) {
+        let outcome = match result {
+            Ok(response) => match encode_canonical(response) {
+                Ok(response) => RecordedOutcome::Ok { response },
+                Err(err) => RecordedOutcome::Err {
+                    kind: "unrecordable".to_string(),
+                    message: err.to_string(),
+                },
+            },
+            Err(err) => {
+                let (kind, message) = error_parts(err);
+                RecordedOutcome::Err {
+                    kind: kind.to_string(),
+                    message,
+                }
+            }
+        };
+        let record = BridgeRecord {
+            method: method.name().to_string(),
+            operation: operation.map(str::to_string),
+            recorded_at: Utc::now(),
+            request,
+            outcome,
+        };
+        if self
+            .sender
+            .try_send(WriterMessage::Record(Box::new(record)))
+            .is_err()
+        {
+            let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+            if dropped.is_power_of_two() {
+                warn!(dropped, "bridge recorder is behind; dropping records");
+            }
+        }
+    }
+}
+
+fn write_records(file: File, receiver: Receiver<WriterMessage>, path: &str) {
+    let mut writer = BufWriter::new(file);
+    for message in receiver {
+        match message {
+            WriterMessage::Record(record) => {
+                if let Err(err) = write_frame(&mut writer, &record) {
+                    error!(path, error = %err, "failed to write bridge record");
+                }
+            }
+            WriterMessage::Flush(done) => {
+                let _ = done.send(());
+            }
+        }
+    }
+}
+
+fn request_bytes<T: Serialize>(request: &T) -> Vec<u8> {
+    encode_canonical(request).unwrap_or_default()
+}
+
+#[async_trait]
+impl RpcMeshBridge for RecordingBridge {
+    async fn send_command(
+        &self,
+        envelope: MeshCommandEnvelope<Value>,
+    ) -> Result<MeshResultEnvelope<Value>, BridgeError> {
+        let operation = envelope.operation.clone();
+        let request = request_bytes(&envelope);
+        let result = self.inner.send_command(envelope).await;
+        self.record(BridgeMethod::SendCommand, Some(&operation), request, &result);
+        result
+    }
+
+    async fn publish_event(
+        &self,
+        envelope: MeshEventEnvelope<Value>,
+    ) -> Result<BridgeReceipt, BridgeError> {
+        let event = envelope.event.clone();
+        let request = request_bytes(&envelope);
+        let result = self.inner.publish_event(envelope).await;
+        self.record(BridgeMethod::PublishEvent, Some(&event), request, &result);
+        result
+    }
+
+    async fn start_transfer(
+        &self,
+        envelope: MeshTransferEnvelope<Value>,
+    ) -> Result<BridgeReceipt, BridgeError> {
+        let operation = envelope.operation.clone();
+        let request = request_bytes(&envelope);
+        let result = self.inner.start_transfer(envelope).await;
+        self.record(BridgeMethod::StartTransfer, Some(&operation), request, &result);
+        result
+    }
+
+    async fn query_receipt(&self, message_id: &str) -> Result<Option<BridgeReceipt>, BridgeError> {
+        let result = self.inner.query_receipt(message_id).await;
+        self.record(
+            BridgeMethod::QueryReceipt,
+            None,
+            request_bytes(&message_id),
+            &result,
+        );
+        result
+    }
+
+    // Empty polls are not recorded; replay treats a missing poll record as "nothing arrived".
+    async fn poll_events(&self, limit: usize) -> Result<Vec<MeshEventEnvelope<Value>>, BridgeError> {
+        let result = self.inner.poll_events(limit).await;
+        if !matches!(&result, Ok(events) if events.is_empty()) {
+            self.record(BridgeMethod::PollEvents, None, request_bytes(&limit), &result);
+        }
+        result
+    }
+
+    async fn poll_commands(
+        &self,
+        limit: usize,
+    ) -> Result<Vec<MeshCommandEnvelope<Value>>, BridgeError> {
+        let result = self.inner.poll_commands(limit).await;
+        if !matches!(&result, Ok(commands) if commands.is_empty()) {
+            self.record(BridgeMethod::PollCommands, None, request_bytes(&limit), &result);
+        }
+        result
+    }
+
+    async fn send_result(
+        &self,
+        envelope: MeshResultEnvelope<Value>,
+    ) -> Result<BridgeReceipt, BridgeError> {
+        let operation = envelope.operation.clone();
+        let request = request_bytes(&envelope);
+        let result = self.inner.send_result(envelope).await;
+        self.record(BridgeMethod::SendResult, Some(&operation), request, &result);
+        result
+    }
+
+    async fn set_inbound_backpressure(&self, enabled: bool) -> Result<(), BridgeError> {
+        self.inner.set_inbound_backpressure(enabled).await
+    }
+
+    async fn poll_transfers(
+        &self,
+        limit: usize,
+    ) -> Result<Vec<MeshTransferEnvelope<Value>>, BridgeError> {
+        self.inner.poll_transfers(limit).await
+    }
+
+    async fn cancel_message(&self, message_id: &str) -> Result<CancelOutcome, BridgeError> {
+        self.inner.cancel_message(message_id).await
+    }
+
+    fn planned_transport(&self, hint: Option<TransferHint>) -> TransportSelection {
+        self.inner.planned_transport(hint)
+    }
+
+    fn transport_status(&self) -> Option<TransportStatus> {
+        self.inner.transport_status()
+    }
+
+    fn call_metrics(&self) -> Option<BTreeMap<String, CallMetrics>> {
+        self.inner.call_metrics()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReplayMatching {
+    // The next recorded call must match exactly; anything else is a replay divergence.
+    #[default]
+    Strict,
+    // Take the next unconsumed record with the same method and operation.
+    Lenient,
+}
+
+pub struct ReplayBridge {
+    matching: ReplayMatching,
+    records: Mutex<VecDeque<BridgeRecord>>,
+}
+
+impl ReplayBridge {
+    pub fn new(records: Vec<BridgeRecord>, matching: ReplayMatching) -> Self {
+        Self {
+            matching,
+            records: Mutex::new(records.into()),
+        }
+    }
+
+    pub fn open(path: &Path, matching: ReplayMatching) -> Result<Self, RecordingError> {
+        Ok(Self::new(read_recording(path)?, matching))
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.lock_records().len()
+    }
+
+    fn lock_records(&self) -> std::sync::MutexGuard<'_, VecDeque<BridgeRecord>> {
+        self.records.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn take(
+        &self,
+        method: BridgeMethod,
+        operation: Option<&str>,
+    ) -> Result<Option<BridgeRecord>, BridgeError> {
+        let matches = |record: &BridgeRecord| {
+            record.method == method.name() && record.operation.as_deref() == operation
+        };
+        let mut records = self.lock_records();
+        match self.matching {
+            ReplayMatching::Strict => match records.front() {
+                Some(record) if matches(record) => Ok(records.pop_front()),
+                Some(record) => Err(BridgeError::SendFailed(format!(
+                    "replay diverged: expected {} {:?}, got {} {:?}",
+                    record.method,
+                    record.operation,
+                    method.name(),
+                    operation
+                ))),
+                None => Ok(None),
+            },
+            ReplayMatching::Lenient => Ok(records
+                .iter()
+                .position(matches)
+                .and_then(|index| records.remove(index))),
+        }
+    }
+
+    fn replay<T: DeserializeOwned>(
+        &self,
+        method: BridgeMethod,
+        operation: Option<&str>,
+    ) -> Result<T, BridgeError> {
+        let record = self.take(method, operation)?.ok_or_else(|| {
+            BridgeError::SendFailed(format!(
+                "replay exhausted for {} {:?}",
+                method.name(),
+                operation
+            ))
+        })?;
+        decode_outcome(&record)
+    }
+
+    fn replay_poll<T: DeserializeOwned>(&self, method: BridgeMethod) -> Result<Vec<T>, BridgeError> {
+        let pending = {
+            let records = self.lock_records();
+            match self.matching {
+                ReplayMatching::Strict => records
+                    .front()
+                    .is_some_and(|record| record.method == method.name()),
+                ReplayMatching::Lenient => {
+                    records.iter().any(|record| record.method == method.name())
+                }
+            }
+        };
+        if !pending {
+            return Ok(Vec::new());
+        }
+        self.replay(method, None)
+    }
+}
+
+fn decode_outcome<T: DeserializeOwned>(record: &BridgeRecord) -> Result<T, BridgeError> {
+    match &record.outcome {
+        RecordedOutcome::Ok { response } => decode_canonical(response).map_err(|err| {
+            BridgeError::InvalidPayload(format!("recorded {} response: {err}", record.method))
+        }),
+        RecordedOutcome::Err { kind, message } => Err(restore_error(kind, message)),
+    }
+}
+
+#[async_trait]
+impl RpcMeshBridge for ReplayBridge {
+    async fn send_command(
+        &self,
+        envelope: MeshCommandEnvelope<Value>,
+    ) -> Result<MeshResultEnvelope<Value>, BridgeError> {
+        self.replay(BridgeMethod::SendCommand, Some(&envelope.operation))
+    }
+
+    async fn publish_event(
+        &self,
+        envelope: MeshEventEnvelope<Value>,
+    ) -> Result<BridgeReceipt, BridgeError> {
+        self.replay(BridgeMethod::PublishEvent, Some(&envelope.event))
+    }
+
+    async fn start_transfer(
+        &self,
+        envelope: MeshTransferEnvelope<Value>,
+    ) -> Result<BridgeReceipt, BridgeError> {
+        self.replay(BridgeMethod::StartTransfer, Some(&envelope.operation))
+    }
+
+    async fn query_receipt(&self, _message_id: &str) -> Result<Option<BridgeReceipt>, BridgeError> {
+        self.replay(BridgeMethod::QueryReceipt, None)
+    }
+
+    async fn poll_events(&self, _limit: usize) -> Result<Vec<MeshEventEnvelope<Value>>, BridgeError> {
+        self.replay_poll(BridgeMethod::PollEvents)
+    }
+
+    async fn poll_commands(
+        &self,
+        _limit: usize,
+    ) -> Result<Vec<MeshCommandEnvelope<Value>>, BridgeError> {
+        self.replay_poll(BridgeMethod::PollCommands)
+    }
+
+    async fn send_result(
+        &self,
+        envelope: MeshResultEnvelope<Value>,
+    ) -> Result<BridgeReceipt, BridgeError> {
+        self.replay(BridgeMethod::SendResult, Some(&envelope.operation))
+    }
+
+    async fn set_inbound_backpressure(&self, _enabled: bool) -> Result<(), BridgeError> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_recording, summarize, RecordingBridge, ReplayBridge, ReplayMatching};
+    use crate::{
+        InMemoryRpcMeshBridge, LossProfile, RpcMeshBridge, SimulatedMeshBridge, SimulationProfile,
+    };
+    use chrono::Utc;
+    use retasync_contract::MeshCommandEnvelope;
+    use serde_json::{json, Value};
+    use std::sync::Arc;
+    use uuid::Uuid;
+
+    fn command(operation: &str) -> MeshCommandEnvelope<Value> {
+        MeshCommandEnvelope {
+            message_id: Uuid::now_v7().to_string(),
+            operation: operation.to_string(),
+            sent_at: Utc::now(),
+            source_identity: "local-node".parse().unwrap(),
+            destination_identity: "mesh".parse().unwrap(),
+            content_type: "application/msgpack".to_string(),
+            payload: json!({ "uid": "evt" }),
+            ttl_ms: None,
+            transport_hint: None,
+            trace: Vec::new(),
+            trace_truncated: false,
+            meta: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn recorded_session_replays_in_both_modes() {
+        let path = std::env::temp_dir().join(format!("retasync-{}.rec", Uuid::now_v7()));
+        let lossy = SimulatedMeshBridge::new(
+            Arc::new(InMemoryRpcMeshBridge::new(true, true)),
+            SimulationProfile {
+                seed: 11,
+                loss: LossProfile {
+                    send_command: 0.4,
+                    ..LossProfile::default()
+                },
+                ..SimulationProfile::default()
+            },
+        );
+        let recorder = RecordingBridge::start(Arc::new(lossy), &path).expect("recorder");
+        let mut original = Vec::new();
+        for operation in ["event.create", "event.update", "event.create", "event.delete"] {
+            let result = recorder.send_command(command(operation)).await;
+            original.push(result.map(|result| result.message_id).map_err(|err| err.to_string()));
+        }
+        assert!(recorder.poll_events(8).await.unwrap().is_empty());
+        recorder.flush().await;
+        assert_eq!(recorder.dropped_records(), 0);
+
+        let records = read_recording(&path).expect("recording");
+        let summary = summarize(&records);
+        assert_eq!(summary.records, 4);
+        assert_eq!(summary.calls["send_command event.create"].calls, 2);
+        assert!(summary.errors > 0 && summary.errors < 4);
+
+        let strict = ReplayBridge::new(records.clone(), ReplayMatching::Strict);
+        let mut replayed = Vec::new();
+        for operation in ["event.create", "event.update", "event.create", "event.delete"] {
+            let result = strict.send_command(command(operation)).await;
+            replayed.push(result.map(|result| result.message_id).map_err(|err| err.to_string()));
+        }
+        assert_eq!(replayed, original);
+        assert_eq!(strict.remaining(), 0);
+
+        let strict = ReplayBridge::new(records.clone(), ReplayMatching::Strict);
+        let diverged = strict.send_command(command("event.delete")).await.unwrap_err();
+        assert!(diverged.to_string().contains("replay diverged"));
+
+        let lenient = ReplayBridge::new(records, ReplayMatching::Lenient);
+        let delete = lenient.send_command(command("event.delete")).await;
+        assert_eq!(
+            delete.map(|result| result.message_id).map_err(|err| err.to_string()),
+            original[3]
+        );
+        assert_eq!(lenient.remaining(), 3);
+    }
+}
@@ -0,0 +1,352 @@
+﻿#[cfg(feature = "profile-files")]
+use std::path::Path;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use retasync_contract::{
+    MeshCommandEnvelope, MeshEventEnvelope, MeshResultEnvelope, MeshTransferEnvelope, TransferHint,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::debug;
+
+use crate::bridge::{
+    BridgeError, BridgeReceipt, CancelOutcome, RpcMeshBridge, TransportSelection, TransportStatus,
+};
+use crate::layers::CallMetrics;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LossProfile {
+    pub send_command: f64,
+    pub publish_event: f64,
+    pub start_transfer: f64,
+    pub query_receipt: f64,
+    pub poll_events: f64,
+    pub poll_commands: f64,
+    pub send_result: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PartitionWindow {
+    pub period_ms: u64,
+    pub duration_ms: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SimulationProfile {
+    pub seed: u64,
+    pub base_latency_ms: u64,
+    pub jitter_ms: u64,
+    pub loss: LossProfile,
+    pub partition: Option<PartitionWindow>,
+    pub bandwidth_bytes_per_sec: Option<u64>,
+}
+
+#[cfg(feature = "profile-files")]
+impl SimulationProfile {
+    pub fn from_file(path: &Path) -> Result<Self, BridgeError> {
+        let source = std::fs::read_to_string(path).map_err(|err| {
+            BridgeError::InvalidPayload(format!(
+                "failed reading simulation profile {}: {err}",
+                path.display()
+            ))
+        })?;
+
+        let is_yaml = matches!(
+            path.extension().and_then(|ext| ext.to_str()),
+            Some("yaml") | Some("yml")
+        );
+        if is_yaml {
+            serde_yaml::from_str(&source).map_err(|err| {
+                BridgeError::InvalidPayload(format!("invalid simulation profile YAML: {err}"))
+            })
+        } else {
+            toml::from_str(&source).map_err(|err| {
+                BridgeError::InvalidPayload(format!("invalid simulation profile TOML: {err}"))
+            })
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum BridgeMethod {
+    SendCommand,
+    PublishEvent,
+    StartTransfer,
+    QueryReceipt,
+    PollEvents,
+    PollCommands,
+    SendResult,
+}
+
+impl BridgeMethod {
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            BridgeMethod::SendCommand => "send_command",
+            BridgeMethod::PublishEvent => "publish_event",
+            BridgeMethod::StartTransfer => "start_transfer",
+            BridgeMethod::QueryReceipt => "query_receipt",
+            BridgeMethod::PollEvents => "poll_events",
+            BridgeMethod::PollCommands => "poll_commands",
+            BridgeMethod::SendResult => "send_result",
+        }
+    }
+
+    fn loss(self, loss: &LossProfile) -> f64 {
+        match self {
+            BridgeMethod::SendCommand => loss.send_command,
+            BridgeMethod::PublishEvent => loss.publish_event,
+            BridgeMethod::StartTransfer => loss.start_transfer,
+            BridgeMethod::QueryReceipt => loss.query_receipt,
+            BridgeMethod::PollEvents => loss.poll_events,
+            BridgeMethod::PollCommands => loss.poll_commands,
+            BridgeMethod::SendResult => loss.send_result,
+        }
+    }
+}
+
+// SplitMix64: tiny, seedable, and stable across platforms and releases.
+#[derive(Debug, Clone)]
+struct SimRng(u64);
+
+impl SimRng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[derive(Debug)]
+struct SimulationState {
+    profile: SimulationProfile,
+    rng: SimRng,
+    started: Instant,
+}
+
+pub struct SimulatedMeshBridge {
+    inner: Arc<dyn RpcMeshBridge>,
+    state: Mutex<SimulationState>,
+}
+
+impl SimulatedMeshBridge {
+    pub fn new(inner: Arc<dyn RpcMeshBridge>, profile: SimulationProfile) -> Self {
+        Self {
+            inner,
+            state: Mutex::new(SimulationState {
+                rng: SimRng(profile.seed),
+                profile,
+                started: Instant::now(),
+            }),
+        }
+    }
+
+    pub fn profile(&self) -> SimulationProfile {
+        self.lock_state().profile.clone()
+    }
+
+    pub fn set_profile(&self, profile: SimulationProfile) {
+        let mut state = self.lock_state();
+        state.rng = SimRng(profile.seed);
+        state.started = Instant::now();
+        state.profile = profile;
+    }
+
+    fn lock_state(&self) -> std::sync::MutexGuard<'_, SimulationState> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    async fn degrade(&self, method: BridgeMethod, payload_bytes: usize) -> Result<(), BridgeError> {
+        let (delay, partitioned, lost) = {
+            let mut state = self.lock_state();
+            let profile = state.profile.clone();
+
+            let jitter = if profile.jitter_ms > 0 {
+                state.rng.next_u64() % (profile.jitter_ms + 1)
+            } else {
+                0
+            };
+            let transfer_delay = match (method, profile.bandwidth_bytes_per_sec) {
+                (BridgeMethod::StartTransfer, Some(bps)) if bps > 0 => {
+                    payload_bytes as u64 * 1000 / bps
+                }
+                _ => 0,
+            };
+
+            let partitioned = profile.partition.as_ref().is_some_and(|window| {
+                window.period_ms > 0
+                    && (state.started.elapsed().as_millis() as u64) % window.period_ms
+                        < window.duration_ms
+            });
+            let lost = state.rng.next_f64() < method.loss(&profile.loss);
+
+            (
+                Duration::from_millis(profile.base_latency_ms + jitter + transfer_delay),
+                partitioned,
+                lost,
+            )
+        };
+
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+        if partitioned {
+            debug!(method = ?method, "simulated partition window");
+            return Err(BridgeError::DaemonUnavailable);
+        }
+        if lost {
+            debug!(method = ?method, "simulated message loss");
+            return Err(BridgeError::SendFailed("simulated message loss".to_string()));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl RpcMeshBridge for SimulatedMeshBridge {
+    async fn send_command(
+        &self,
+        envelope: MeshCommandEnvelope<Value>,
+    ) -> Result<MeshResultEnvelope<Value>, BridgeError> {
+        self.degrade(BridgeMethod::SendCommand, 0).await?;
+        self.inner.send_command(envelope).await
+    }
+
+    async fn publish_event(
+        &self,
+        envelope: MeshEventEnvelope<Value>,
+    ) -> Result<BridgeReceipt, BridgeError> {
+        self.degrade(BridgeMethod::PublishEvent, 0).await?;
+        self.inner.publish_event(envelope).await
+    }
+
+    async fn start_transfer(
+        &self,
+        envelope: MeshTransferEnvelope<Value>,
+    ) -> Result<BridgeReceipt, BridgeError> {
+        let payload_bytes = serde_json::to_vec(&envelope.payload)
+            .map(|bytes| bytes.len())
+            .unwrap_or_default();
+        self.degrade(BridgeMethod::StartTransfer, payload_bytes)
+            .await?;
+        self.inner.start_transfer(envelope).await
+    }
+
+    async fn query_receipt(&self, message_id: &str) -> Result<Option<BridgeReceipt>, BridgeError> {
+        self.degrade(BridgeMethod::QueryReceipt, 0).await?;
+        self.inner.query_receipt(message_id).await
+    }
+
+    async fn poll_events(&self, limit: usize) -> Result<Vec<MeshEventEnvelope<Value>>, BridgeError> {
+        self.degrade(BridgeMethod::PollEvents, 0).await?;
+        self.inner.poll_events(limit).await
+    }
+
+    async fn poll_commands(
+        &self,
+        limit: usize,
+    ) -> Result<Vec<MeshCommandEnvelope<Value>>, BridgeError> {
+        self.degrade(BridgeMethod::PollCommands, 0).await?;
+        self.inner.poll_commands(limit).await
+    }
+
+    async fn send_result(
+        &self,
+        envelope: MeshResultEnvelope<Value>,
+    ) -> Result<BridgeReceipt, BridgeError> {
+        self.degrade(BridgeMethod::SendResult, 0).await?;
+        self.inner.send_result(envelope).await
+    }
+
+    async fn set_inbound_backpressure(&self, enabled: bool) -> Result<(), BridgeError> {
+        self.inner.set_inbound_backpressure(enabled).await
+    }
+
+    async fn poll_transfers(
+        &self,
+        limit: usize,
+    ) -> Result<Vec<MeshTransferEnvelope<Value>>, BridgeError> {
+        self.inner.poll_transfers(limit).await
+    }
+
+    async fn cancel_message(&self, message_id: &str) -> Result<CancelOutcome, BridgeError> {
+        self.inner.cancel_message(message_id).await
+    }
+
+    fn planned_transport(&self, hint: Option<TransferHint>) -> TransportSelection {
+        self.inner.planned_transport(hint)
+    }
+
+    fn transport_status(&self) -> Option<TransportStatus> {
+        self.inner.transport_status()
+    }
+
+    fn call_metrics(&self) -> Option<BTreeMap<String, CallMetrics>> {
+        self.inner.call_metrics()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LossProfile, SimulatedMeshBridge, SimulationProfile};
+    use crate::{InMemoryRpcMeshBridge, RpcMeshBridge};
+    use std::sync::Arc;
+
+    async fn outcomes(seed: u64) -> Vec<bool> {
+        let bridge = SimulatedMeshBridge::new(
+            Arc::new(InMemoryRpcMeshBridge::new(true, true)),
+            SimulationProfile {
+                seed,
+                loss: LossProfile {
+                    query_receipt: 0.5,
+                    ..LossProfile::default()
+                },
+                ..SimulationProfile::default()
+            },
+        );
+
+        let mut out = Vec::new();
+        for _ in 0..32 {
+            out.push(bridge.query_receipt("probe").await.is_ok());
+        }
+        out
+    }
+
+    #[tokio::test]
+    async fn seeded_profiles_are_reproducible() {
+        let first = outcomes(42).await;
+        assert_eq!(first, outcomes(42).await);
+        assert!(first.contains(&true) && first.contains(&false));
+    }
+
+    #[test]
+    fn profile_parses_from_toml() {
+        let profile: SimulationProfile = toml::from_str(
+            r#"
+seed = 7
+base_latency_ms = 250
+jitter_ms = 100
+
+[loss]
+send_command = 0.2
+
+[partition]
+period_ms = 60000
+duration_ms = 5000
+"#,
+        )
+        .expect("profile");
+        assert_eq!(profile.loss.send_command, 0.2);
+        assert_eq!(profile.partition.expect("partition").duration_ms, 5000);
+    }
+}
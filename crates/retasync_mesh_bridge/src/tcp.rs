@@ -0,0 +1,687 @@
+﻿use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, Weak};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use retasync_contract::{
+    decode_canonical_with_limits, encode_canonical, CodecLimits, MeshCommandEnvelope,
+    MeshEventEnvelope, MeshResultEnvelope, MeshTransferEnvelope,
+};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+use tokio::sync::oneshot;
+use tokio::task::AbortHandle;
+use tracing::{debug, info, warn};
+
+use crate::bridge::{
+    BridgeError, BridgeReceipt, CancelOutcome, RpcMeshBridge, TransportStatus,
+    PEER_UNREACHABLE_ERROR,
+};
+
+// Daemon RPC over TCP. Both directions carry the same frames:
+//
+//   +----------------------+-------------------------------------------------+
+//   | length: u32, BE      | canonical msgpack map                           |
+//   +----------------------+-------------------------------------------------+
+//     request_id: u64     chosen by the bridge, echoed by the daemon
+//     method:     string  `send_command`, `poll_events`, ..., or `ping`
+//                         (`cancel_message` answers `cancelled`, `already_delivered`,
+//                         `not_found` or `unsupported`)
+//     body:       any     request arguments or response value (nil if absent)
+//     error:      string  set instead of `body` when the daemon rejects a request; one starting
+//                         with `peer_unreachable` means the destination has no path
+//
+// Responses may come back in any order; the bridge matches them to waiting requests by
+// `request_id`, so several requests can be in flight on one connection.
+
+pub const DEFAULT_POOL_SIZE: usize = 2;
+pub const DEFAULT_REQUEST_TIMEOUT_SECS: u64 = 30;
+pub const DEFAULT_PING_INTERVAL_SECS: u64 = 15;
+pub const MAX_RPC_FRAME_BYTES: usize = 16 * 1024 * 1024;
+// Levels a payload sits below the frame root: frame, body list, envelope.
+const RPC_FRAME_NESTING: usize = 3;
+const PING_METHOD: &str = "ping";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TcpPoolSettings {
+    pub pool_size: usize,
+    pub request_timeout: Duration,
+    // Connections idle for this long are pinged; zero disables health checks.
+    pub ping_interval: Duration,
+    // Limits for the payloads inside daemon frames, checked before a frame is decoded.
+    pub codec_limits: CodecLimits,
+}
+
+impl Default for TcpPoolSettings {
+    fn default() -> Self {
+        Self {
+            pool_size: DEFAULT_POOL_SIZE,
+            request_timeout: Duration::from_secs(DEFAULT_REQUEST_TIMEOUT_SECS),
+            ping_interval: Duration::from_secs(DEFAULT_PING_INTERVAL_SECS),
+            codec_limits: CodecLimits::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RpcFrame {
+    pub request_id: u64,
+    pub method: String,
+    #[serde(default)]
+    pub body: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+// Frame limits for payloads held to `limits`: the frame may be as large as any RPC frame, and
+// as deep as a payload plus the envelope around it.
+pub fn rpc_frame_limits(limits: &CodecLimits) -> CodecLimits {
+    CodecLimits {
+        max_bytes: MAX_RPC_FRAME_BYTES,
+        max_depth: limits.max_depth.saturating_add(RPC_FRAME_NESTING),
+        ..*limits
+    }
+}
+
+// Hostile lengths and nesting are refused from the raw bytes, before anything is decoded.
+pub async fn read_rpc_frame(
+    reader: &mut (impl AsyncRead + Unpin),
+    limits: &CodecLimits,
+) -> std::io::Result<RpcFrame> {
+    let len = reader.read_u32().await? as usize;
+    if len > MAX_RPC_FRAME_BYTES {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("RPC frame of {len} bytes exceeds {MAX_RPC_FRAME_BYTES}"),
+        ));
+    }
+    let mut frame = vec![0; len];
+    reader.read_exact(&mut frame).await?;
+    decode_canonical_with_limits(&frame, limits)
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err.to_string()))
+}
+
+pub async fn write_rpc_frame(
+    writer: &mut (impl AsyncWrite + Unpin),
+    frame: &RpcFrame,
+) -> std::io::Result<()> {
+    let bytes = encode_canonical(frame)
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err.to_string()))?;
+    let mut buffer = Vec::with_capacity(4 + bytes.len());
+    buffer.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+    buffer.extend_from_slice(&bytes);
+    writer.write_all(&buffer).await?;
+    writer.flush().await
+}
+
+struct Connection {
+    writer: tokio::sync::Mutex<OwnedWriteHalf>,
+    pending: Mutex<HashMap<u64, oneshot::Sender<RpcFrame>>>,
+    alive: AtomicBool,
+    last_used: Mutex<Instant>,
+    // The task reading responses, stopped with the connection so a replaced one does not keep
+    // its socket open.
+    reader: OnceLock<AbortHandle>,
+}
+
+impl Connection {
+    async fn open(addr: &str, limits: CodecLimits) -> Result<Arc<Self>, BridgeError> {
+        let stream = TcpStream::connect(addr).await.map_err(|err| {
+            warn!(addr, error = %err, "daemon RPC connect failed");
+            BridgeError::DaemonUnavailable
+        })?;
+        let _ = stream.set_nodelay(true);
+        let (reader, writer) = stream.into_split();
+        let connection = Arc::new(Self {
+            writer: tokio::sync::Mutex::new(writer),
+            pending: Mutex::new(HashMap::new()),
+            alive: AtomicBool::new(true),
+            last_used: Mutex::new(Instant::now()),
+            reader: OnceLock::new(),
+        });
+        let reader = tokio::spawn(read_responses(
+            reader,
+            Arc::downgrade(&connection),
+            rpc_frame_limits(&limits),
+        ));
+        let _ = connection.reader.set(reader.abort_handle());
+        Ok(connection)
+    }
+
+    fn is_alive(&self) -> bool {
+        self.alive.load(Ordering::SeqCst)
+    }
+
+    // Fails every request still waiting on this connection and stops reading from it.
+    fn close(&self) {
+        self.alive.store(false, Ordering::SeqCst);
+        lock(&self.pending).clear();
+        if let Some(reader) = self.reader.get() {
+            reader.abort();
+        }
+    }
+
+    fn idle_for(&self) -> Duration {
+        lock(&self.last_used).elapsed()
+    }
+
+    async fn send(&self, frame: &RpcFrame) -> std::io::Result<()> {
+        *lock(&self.last_used) = Instant::now();
+        write_rpc_frame(&mut *self.writer.lock().await, frame).await
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        if let Some(reader) = self.reader.get() {
+            reader.abort();
+        }
+    }
+}
+
+async fn read_responses(
+    mut reader: OwnedReadHalf,
+    connection: Weak<Connection>,
+    limits: CodecLimits,
+) {
+    loop {
+        let frame = read_rpc_frame(&mut reader, &limits).await;
+        let Some(connection) = connection.upgrade() else {
+            return;
+        };
+        match frame {
+            Ok(frame) => {
+                let waiter = lock(&connection.pending).remove(&frame.request_id);
+                match waiter {
+                    Some(waiter) => {
+                        let _ = waiter.send(frame);
+                    }
+                    None => debug!(
+                        request_id = frame.request_id,
+                        method = %frame.method,
+                        "discarding daemon response nobody is waiting for"
+                    ),
+                }
+            }
+            Err(err) => {
+                debug!(error = %err, "daemon RPC connection closed");
+                connection.close();
+                return;
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct Slot {
+    connection: Mutex<Option<Arc<Connection>>>,
+    dial: tokio::sync::Mutex<()>,
+    dialed: AtomicBool,
+}
+
+impl Slot {
+    fn current(&self) -> Option<Arc<Connection>> {
+        lock(&self.connection)
+            .as_ref()
+            .filter(|connection| connection.is_alive())
+            .cloned()
+    }
+}
+
+struct Pool {
+    addr: String,
+    settings: TcpPoolSettings,
+    slots: Vec<Slot>,
+    next_slot: AtomicUsize,
+    next_request_id: AtomicU64,
+    in_flight: AtomicU64,
+    reconnects: AtomicU64,
+    timeouts: AtomicU64,
+}
+
+impl Pool {
+    async fn connection(&self, slot: &Slot) -> Result<Arc<Connection>, BridgeError> {
+        if let Some(connection) = slot.current() {
+            return Ok(connection);
+        }
+        let _dial = slot.dial.lock().await;
+        if let Some(connection) = slot.current() {
+            return Ok(connection);
+        }
+        let connection = Connection::open(&self.addr, self.settings.codec_limits).await?;
+        if slot.dialed.swap(true, Ordering::SeqCst) {
+            self.reconnects.fetch_add(1, Ordering::Relaxed);
+            info!(addr = %self.addr, "reconnected to daemon RPC");
+        }
+        let replaced = lock(&slot.connection).replace(connection.clone());
+        if let Some(replaced) = replaced {
+            replaced.close();
+        }
+        Ok(connection)
+    }
+
+    async fn request(&self, method: &str, body: Value) -> Result<Value, BridgeError> {
+        let index = self.next_slot.fetch_add(1, Ordering::Relaxed) % self.slots.len();
+        let connection = self.connection(&self.slots[index]).await?;
+        self.request_on(&connection, method, body).await
+    }
+
+    async fn request_on(
+        &self,
+        connection: &Connection,
+        method: &str,
+        body: Value,
+    ) -> Result<Value, BridgeError> {
+        let request_id = self.next_request_id.fetch_add(1, Ordering::Relaxed);
+        let (sender, receiver) = oneshot::channel();
+        lock(&connection.pending).insert(request_id, sender);
+        if !connection.is_alive() {
+            lock(&connection.pending).remove(&request_id);
+            return Err(BridgeError::DaemonUnavailable);
+        }
+
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        let response = self
+            .exchange(connection, request_id, method, body, receiver)
+            .await;
+        self.in_flight.fetch_sub(1, Ordering::Relaxed);
+        let frame = response?;
+        match frame.error {
+            Some(message) if message.starts_with(PEER_UNREACHABLE_ERROR) => {
+                Err(BridgeError::PeerUnreachable(message))
+            }
+            Some(message) => Err(BridgeError::SendFailed(message)),
+            None => Ok(frame.body),
+        }
+    }
+
+    async fn exchange(
+        &self,
+        connection: &Connection,
+        request_id: u64,
+        method: &str,
+        body: Value,
+        receiver: oneshot::Receiver<RpcFrame>,
+    ) -> Result<RpcFrame, BridgeError> {
+        let frame = RpcFrame {
+            request_id,
+            method: method.to_string(),
+            body,
+            error: None,
+        };
+        if let Err(err) = connection.send(&frame).await {
+            warn!(method, error = %err, "daemon RPC write failed");
+            connection.close();
+            return Err(BridgeError::DaemonUnavailable);
+        }
+
+        match tokio::time::timeout(self.settings.request_timeout, receiver).await {
+            Ok(Ok(frame)) => Ok(frame),
+            Ok(Err(_)) => Err(BridgeError::DaemonUnavailable),
+            Err(_) => {
+                // The connection stays up; a late answer finds no waiter and is dropped.
+                lock(&connection.pending).remove(&request_id);
+                self.timeouts.fetch_add(1, Ordering::Relaxed);
+                Err(BridgeError::SendFailed(format!(
+                    "daemon RPC {method} timed out after {}ms",
+                    self.settings.request_timeout.as_millis()
+                )))
+            }
+        }
+    }
+
+    // Pings connections that have been idle for a full interval and replaces any that fail or
+    // have dropped since they were last used.
+    async fn check_idle(&self) {
+        for slot in &self.slots {
+            let connection = match slot.current() {
+                Some(connection) => connection,
+                None if slot.dialed.load(Ordering::SeqCst) => {
+                    let _ = self.connection(slot).await;
+                    continue;
+                }
+                None => continue,
+            };
+            if connection.idle_for() < self.settings.ping_interval {
+                continue;
+            }
+            if let Err(err) = self.request_on(&connection, PING_METHOD, Value::Null).await {
+                warn!(addr = %self.addr, error = %err, "daemon RPC health check failed");
+                connection.close();
+                let _ = self.connection(slot).await;
+            }
+        }
+    }
+}
+
+async fn keep_alive(pool: Weak<Pool>, interval: Duration) {
+    loop {
+        tokio::time::sleep(interval).await;
+        let Some(pool) = pool.upgrade() else {
+            return;
+        };
+        pool.check_idle().await;
+    }
+}
+
+// Connections are opened on first use. Must be created inside a Tokio runtime when health
+// checks are enabled.
+pub struct TcpRpcMeshBridge {
+    pool: Arc<Pool>,
+}
+
+impl TcpRpcMeshBridge {
+    pub fn new(addr: impl Into<String>, settings: TcpPoolSettings) -> Self {
+        let pool = Arc::new(Pool {
+            addr: addr.into(),
+            settings,
+            slots: (0..settings.pool_size.max(1)).map(|_| Slot::default()).collect(),
+            next_slot: AtomicUsize::new(0),
+            next_request_id: AtomicU64::new(1),
+            in_flight: AtomicU64::new(0),
+            reconnects: AtomicU64::new(0),
+            timeouts: AtomicU64::new(0),
+        });
+        if !settings.ping_interval.is_zero() {
+            tokio::spawn(keep_alive(Arc::downgrade(&pool), settings.ping_interval));
+        }
+        Self { pool }
+    }
+
+    async fn call<T: DeserializeOwned>(&self, method: &str, body: Value) -> Result<T, BridgeError> {
+        let response = self.pool.request(method, body).await?;
+        serde_json::from_value(response)
+            .map_err(|err| BridgeError::InvalidPayload(format!("{method} response: {err}")))
+    }
+}
+
+fn to_body(value: &impl Serialize) -> Result<Value, BridgeError> {
+    serde_json::to_value(value).map_err(|err| BridgeError::InvalidPayload(err.to_string()))
+}
+
+#[async_trait]
+impl RpcMeshBridge for TcpRpcMeshBridge {
+    async fn send_command(
+        &self,
+        envelope: MeshCommandEnvelope<Value>,
+    ) -> Result<MeshResultEnvelope<Value>, BridgeError> {
+        self.call("send_command", to_body(&envelope)?).await
+    }
+
+    async fn publish_event(
+        &self,
+        envelope: MeshEventEnvelope<Value>,
+    ) -> Result<BridgeReceipt, BridgeError> {
+        self.call("publish_event", to_body(&envelope)?).await
+    }
+
+    async fn start_transfer(
+        &self,
+        envelope: MeshTransferEnvelope<Value>,
+    ) -> Result<BridgeReceipt, BridgeError> {
+        self.call("start_transfer", to_body(&envelope)?).await
+    }
+
+    async fn query_receipt(&self, message_id: &str) -> Result<Option<BridgeReceipt>, BridgeError> {
+        self.call("query_receipt", json!({ "message_id": message_id }))
+            .await
+    }
+
+    async fn poll_events(&self, limit: usize) -> Result<Vec<MeshEventEnvelope<Value>>, BridgeError> {
+        self.call("poll_events", json!({ "limit": limit })).await
+    }
+
+    async fn poll_commands(
+        &self,
+        limit: usize,
+    ) -> Result<Vec<MeshCommandEnvelope<Value>>, BridgeError> {
+        self.call("poll_commands", json!({ "limit": limit })).await
+    }
+
+    async fn send_result(
+        &self,
+        envelope: MeshResultEnvelope<Value>,
+    ) -> Result<BridgeReceipt, BridgeError> {
+        self.call("send_result", to_body(&envelope)?).await
+    }
+
+    async fn set_inbound_backpressure(&self, enabled: bool) -> Result<(), BridgeError> {
+        self.call::<Value>("set_inbound_backpressure", json!({ "enabled": enabled }))
+            .await
+            .map(|_| ())
+    }
+
+    async fn cancel_message(&self, message_id: &str) -> Result<CancelOutcome, BridgeError> {
+        self.call("cancel_message", json!({ "message_id": message_id }))
+            .await
+    }
+
+    fn transport_status(&self) -> Option<TransportStatus> {
+        let pool = &self.pool;
+        Some(TransportStatus {
+            endpoint: pool.addr.clone(),
+            pool_size: pool.slots.len(),
+            connected: pool.slots.iter().filter(|slot| slot.current().is_some()).count(),
+            in_flight: pool.in_flight.load(Ordering::Relaxed),
+            reconnects: pool.reconnects.load(Ordering::Relaxed),
+            timeouts: pool.timeouts.load(Ordering::Relaxed),
+        })
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        read_rpc_frame, rpc_frame_limits, write_rpc_frame, Connection, RpcFrame, TcpPoolSettings,
+        TcpRpcMeshBridge,
+    };
+    use crate::bridge::{BridgeError, CancelOutcome, RpcMeshBridge, TransportSelection};
+    use retasync_contract::CodecLimits;
+    use serde_json::json;
+    use std::time::Duration;
+    use tokio::net::{TcpListener, TcpStream};
+
+    fn settings(request_timeout: Duration) -> TcpPoolSettings {
+        TcpPoolSettings {
+            pool_size: 1,
+            request_timeout,
+            ping_interval: Duration::ZERO,
+            ..TcpPoolSettings::default()
+        }
+    }
+
+    async fn fake_daemon() -> (TcpListener, String) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        (listener, addr)
+    }
+
+    // Answers a `query_receipt` with a receipt for the message it asked about.
+    async fn answer(stream: &mut TcpStream, request: &RpcFrame) {
+        let receipt = json!({
+            "message_id": request.body["message_id"],
+            "accepted_at": "2026-01-01T00:00:00Z",
+            "transport": "lxmf",
+        });
+        let response = RpcFrame {
+            request_id: request.request_id,
+            method: request.method.clone(),
+            body: receipt,
+            error: None,
+        };
+        write_rpc_frame(stream, &response).await.unwrap();
+    }
+
+    async fn receipt_for(bridge: &TcpRpcMeshBridge, message_id: &str) -> String {
+        let receipt = bridge.query_receipt(message_id).await.unwrap().expect("receipt");
+        assert_eq!(receipt.transport, TransportSelection::Lxmf);
+        receipt.message_id
+    }
+
+    #[tokio::test]
+    async fn pipelined_responses_may_arrive_out_of_order() {
+        let (listener, addr) = fake_daemon().await;
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let first = read_rpc_frame(&mut stream, &CodecLimits::default()).await.unwrap();
+            let second = read_rpc_frame(&mut stream, &CodecLimits::default()).await.unwrap();
+            answer(&mut stream, &second).await;
+            answer(&mut stream, &first).await;
+            let _ = read_rpc_frame(&mut stream, &CodecLimits::default()).await;
+        });
+
+        let bridge = TcpRpcMeshBridge::new(addr, settings(Duration::from_secs(5)));
+        let (first, second) = tokio::join!(receipt_for(&bridge, "a"), receipt_for(&bridge, "b"));
+        assert_eq!((first.as_str(), second.as_str()), ("a", "b"));
+        let status = bridge.transport_status().unwrap();
+        assert_eq!((status.in_flight, status.connected), (0, 1));
+    }
+
+    #[tokio::test]
+    async fn late_response_after_timeout_is_discarded() {
+        let (listener, addr) = fake_daemon().await;
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let slow = read_rpc_frame(&mut stream, &CodecLimits::default()).await.unwrap();
+            let next = read_rpc_frame(&mut stream, &CodecLimits::default()).await.unwrap();
+            answer(&mut stream, &slow).await;
+            answer(&mut stream, &next).await;
+        });
+
+        let bridge = TcpRpcMeshBridge::new(addr, settings(Duration::from_millis(200)));
+        let err = bridge.query_receipt("slow").await.unwrap_err();
+        assert!(
+            matches!(err, BridgeError::SendFailed(ref message) if message.contains("timed out"))
+        );
+        assert_eq!(receipt_for(&bridge, "next").await, "next");
+
+        let status = bridge.transport_status().unwrap();
+        assert_eq!((status.timeouts, status.reconnects), (1, 0));
+    }
+
+    #[tokio::test]
+    async fn dropped_connection_is_replaced_on_next_request() {
+        let (listener, addr) = fake_daemon().await;
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            read_rpc_frame(&mut stream, &CodecLimits::default()).await.unwrap();
+            drop(stream);
+
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let request = read_rpc_frame(&mut stream, &CodecLimits::default()).await.unwrap();
+            answer(&mut stream, &request).await;
+        });
+
+        let bridge = TcpRpcMeshBridge::new(addr, settings(Duration::from_secs(5)));
+        let err = bridge.query_receipt("lost").await.unwrap_err();
+        assert!(matches!(err, BridgeError::DaemonUnavailable));
+        assert_eq!(receipt_for(&bridge, "retried").await, "retried");
+        assert_eq!(bridge.transport_status().unwrap().reconnects, 1);
+    }
+
+    #[tokio::test]
+    async fn closing_a_connection_stops_its_reader() {
+        let (listener, addr) = fake_daemon().await;
+        let daemon = tokio::spawn(async move {
+            // Keeps the socket open without ever answering.
+            let (stream, _) = listener.accept().await.unwrap();
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            drop(stream);
+        });
+
+        let connection = Connection::open(&addr, CodecLimits::default()).await.unwrap();
+        let reader = connection.reader.get().expect("reader").clone();
+        assert!(!reader.is_finished());
+        connection.close();
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+        while !reader.is_finished() {
+            assert!(tokio::time::Instant::now() < deadline, "reader still running");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        daemon.abort();
+    }
+
+    #[tokio::test]
+    async fn unreachable_destination_is_reported_apart_from_other_errors() {
+        let (listener, addr) = fake_daemon().await;
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            for error in ["peer_unreachable: no path to abcd", "queue full"] {
+                let request = read_rpc_frame(&mut stream, &CodecLimits::default()).await.unwrap();
+                let response = RpcFrame {
+                    request_id: request.request_id,
+                    method: request.method,
+                    body: serde_json::Value::Null,
+                    error: Some(error.to_string()),
+                };
+                write_rpc_frame(&mut stream, &response).await.unwrap();
+            }
+        });
+
+        let bridge = TcpRpcMeshBridge::new(addr, settings(Duration::from_secs(5)));
+        let err = bridge.query_receipt("a").await.unwrap_err();
+        assert!(
+            matches!(err, BridgeError::PeerUnreachable(ref message) if message.contains("abcd"))
+        );
+        let err = bridge.query_receipt("b").await.unwrap_err();
+        assert!(matches!(err, BridgeError::SendFailed(ref message) if message == "queue full"));
+    }
+
+    #[tokio::test]
+    async fn cancel_outcome_is_read_from_the_daemon_reply() {
+        let (listener, addr) = fake_daemon().await;
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let request = read_rpc_frame(&mut stream, &CodecLimits::default()).await.unwrap();
+            assert_eq!(request.method, "cancel_message");
+            assert_eq!(request.body["message_id"], "m-1");
+            let response = RpcFrame {
+                request_id: request.request_id,
+                method: request.method,
+                body: json!("already_delivered"),
+                error: None,
+            };
+            write_rpc_frame(&mut stream, &response).await.unwrap();
+        });
+
+        let bridge = TcpRpcMeshBridge::new(addr, settings(Duration::from_secs(5)));
+        let outcome = bridge.cancel_message("m-1").await.unwrap();
+        assert_eq!(outcome, CancelOutcome::AlreadyDelivered);
+    }
+
+    #[tokio::test]
+    async fn frames_nested_past_the_payload_limit_are_refused_unread() {
+        let limits = CodecLimits {
+            max_depth: 4,
+            ..CodecLimits::default()
+        };
+        let frame = |depth: usize| {
+            let payload = (0..depth).fold(json!("leaf"), |inner, _| json!([inner]));
+            RpcFrame {
+                request_id: 1,
+                method: "poll_commands".to_string(),
+                body: json!([{ "payload": payload }]),
+                error: None,
+            }
+        };
+        let (mut client, mut daemon) = tokio::io::duplex(4096);
+        write_rpc_frame(&mut daemon, &frame(4)).await.unwrap();
+        write_rpc_frame(&mut daemon, &frame(5)).await.unwrap();
+
+        let frame_limits = rpc_frame_limits(&limits);
+        assert!(read_rpc_frame(&mut client, &frame_limits).await.is_ok());
+        let err = read_rpc_frame(&mut client, &frame_limits)
+            .await
+            .expect_err("too deep");
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,188 @@
+﻿use std::path::Path;
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    XChaCha20Poly1305, XNonce,
+};
+
+use crate::error::{Result, StorageError};
+
+// Ciphertext starts with a control byte. Without a key, `seal` stores plaintext that starts with
+// that byte behind `PLAINTEXT_ESCAPE`, so a stored value is never mistaken for ciphertext because
+// of what its text says.
+const CIPHERTEXT_MARKER: char = '\u{1}';
+pub(crate) const CIPHERTEXT_PREFIX: &str = "\u{1}enc:v2:";
+const PLAINTEXT_ESCAPE: &str = "\u{1}raw:";
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 24;
+
+#[derive(Clone)]
+pub struct EncryptedColumn {
+    cipher: XChaCha20Poly1305,
+}
+
+impl std::fmt::Debug for EncryptedColumn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EncryptedColumn").finish_non_exhaustive()
+    }
+}
+
+impl EncryptedColumn {
+    pub fn new(key: &[u8]) -> Result<Self> {
+        if key.len() != KEY_LEN {
+            return Err(StorageError::Encryption(format!(
+                "storage encryption key must be {KEY_LEN} bytes, got {}",
+                key.len()
+            )));
+        }
+        let cipher = XChaCha20Poly1305::new_from_slice(key)
+            .map_err(|_| StorageError::Encryption("invalid storage encryption key".to_string()))?;
+        Ok(Self { cipher })
+    }
+
+    pub fn from_key_file(path: &Path) -> Result<Self> {
+        let raw = std::fs::read(path).map_err(|err| {
+            StorageError::Encryption(format!(
+                "failed reading encryption key {}: {err}",
+                path.display()
+            ))
+        })?;
+        if raw.len() == KEY_LEN {
+            return Self::new(&raw);
+        }
+
+        let not_base64 =
+            || StorageError::Encryption(format!("encryption key {} is not base64", path.display()));
+        let text = String::from_utf8(raw).map_err(|_| not_base64())?;
+        let decoded = STANDARD.decode(text.trim()).map_err(|_| not_base64())?;
+        Self::new(&decoded)
+    }
+
+    pub fn is_encrypted(value: &str) -> bool {
+        value.starts_with(CIPHERTEXT_PREFIX)
+    }
+
+    pub fn encrypt(&self, plaintext: &str) -> Result<String> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, plaintext.as_bytes())
+            .map_err(|_| StorageError::Encryption("failed encrypting column value".to_string()))?;
+
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&ciphertext);
+        Ok(format!("{CIPHERTEXT_PREFIX}{}", STANDARD.encode(sealed)))
+    }
+
+    pub fn decrypt(&self, value: &str) -> Result<String> {
+        let encoded = value
+            .strip_prefix(CIPHERTEXT_PREFIX)
+            .ok_or_else(|| StorageError::corrupt("column", "column value is not encrypted"))?;
+        let sealed = STANDARD.decode(encoded).map_err(|err| StorageError::Corrupt {
+            table: "column".to_string(),
+            detail: "encrypted column value is not valid base64".to_string(),
+            source: Some(Box::new(err)),
+        })?;
+        if sealed.len() < NONCE_LEN {
+            return Err(StorageError::corrupt(
+                "column",
+                "encrypted column value is truncated",
+            ));
+        }
+
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let plaintext = self
+            .cipher
+            .decrypt(XNonce::from_slice(nonce), ciphertext)
+            .map_err(|_| {
+                StorageError::corrupt(
+                    "column",
+                    "failed decrypting column value: wrong key or corrupted data",
+                )
+            })?;
+        String::from_utf8(plaintext).map_err(|err| StorageError::Corrupt {
+            table: "column".to_string(),
+            detail: "decrypted column value is not UTF-8".to_string(),
+            source: Some(Box::new(err)),
+        })
+    }
+}
+
+pub(crate) fn seal(cipher: Option<&EncryptedColumn>, plaintext: &str) -> Result<String> {
+    match cipher {
+        Some(cipher) => cipher.encrypt(plaintext),
+        None if plaintext.starts_with(CIPHERTEXT_MARKER) => {
+            Ok(format!("{PLAINTEXT_ESCAPE}{plaintext}"))
+        }
+        None => Ok(plaintext.to_string()),
+    }
+}
+
+pub(crate) fn open(cipher: Option<&EncryptedColumn>, stored: &str) -> Result<String> {
+    match cipher {
+        Some(cipher) if EncryptedColumn::is_encrypted(stored) => cipher.decrypt(stored),
+        None if EncryptedColumn::is_encrypted(stored) => {
+            Err(StorageError::Encryption(
+                "encountered encrypted column value but no storage encryption key is configured"
+                    .to_string(),
+            ))
+        }
+        _ => Ok(stored
+            .strip_prefix(PLAINTEXT_ESCAPE)
+            .unwrap_or(stored)
+            .to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{open, seal, EncryptedColumn};
+
+    #[test]
+    fn round_trip_uses_fresh_nonces() {
+        let column = EncryptedColumn::new(&[7u8; 32]).expect("key");
+        let first = column.encrypt(r#"{"callsign":"ALPHA-1"}"#).expect("encrypt");
+        let second = column.encrypt(r#"{"callsign":"ALPHA-1"}"#).expect("encrypt");
+
+        assert!(EncryptedColumn::is_encrypted(&first));
+        assert_ne!(first, second);
+        assert_eq!(
+            column.decrypt(&first).expect("decrypt"),
+            r#"{"callsign":"ALPHA-1"}"#
+        );
+    }
+
+    #[test]
+    fn wrong_key_fails() {
+        let column = EncryptedColumn::new(&[7u8; 32]).expect("key");
+        let other = EncryptedColumn::new(&[9u8; 32]).expect("key");
+        let sealed = column.encrypt("secret").expect("encrypt");
+
+        assert!(other.decrypt(&sealed).is_err());
+    }
+
+    #[test]
+    fn plaintext_that_looks_like_ciphertext_stays_plaintext() {
+        let column = EncryptedColumn::new(&[7u8; 32]).expect("key");
+
+        assert!(!EncryptedColumn::is_encrypted("enc:v1:AAAA"));
+        assert_eq!(open(Some(&column), "enc:v1:AAAA").expect("open"), "enc:v1:AAAA");
+    }
+
+    #[test]
+    fn plaintext_starting_with_the_marker_byte_round_trips() {
+        let column = EncryptedColumn::new(&[7u8; 32]).expect("key");
+
+        for text in ["\u{1}enc:v2:AAAA", "\u{1}raw:note", "\u{1}"] {
+            let stored = seal(None, text).expect("seal");
+            assert!(!EncryptedColumn::is_encrypted(&stored));
+            assert_eq!(open(None, &stored).expect("open"), text);
+            assert_eq!(open(Some(&column), &stored).expect("open"), text);
+
+            let sealed = seal(Some(&column), text).expect("seal");
+            assert!(EncryptedColumn::is_encrypted(&sealed));
+            assert_eq!(open(Some(&column), &sealed).expect("open"), text);
+        }
+    }
+}
@@ -0,0 +1,254 @@
+﻿use std::error::Error as StdError;
+
+use thiserror::Error;
+
+use crate::EntityRecord;
+
+pub type Result<T, E = StorageError> = std::result::Result<T, E>;
+pub type BoxError = Box<dyn StdError + Send + Sync>;
+
+// SQLite primary result codes; sqlx reports the extended code, whose low byte is the primary.
+const SQLITE_BUSY: i64 = 5;
+const SQLITE_LOCKED: i64 = 6;
+const SQLITE_CORRUPT: i64 = 11;
+const SQLITE_CONSTRAINT: i64 = 19;
+const SQLITE_NOTADB: i64 = 26;
+
+// Every storage failure, classified so callers can decide what to do without reading the
+// message. The message is the context of the failed operation; the cause stays reachable
+// through `source()`.
+#[derive(Debug, Error)]
+pub enum StorageError {
+    #[error("{what} not found")]
+    NotFound { what: String },
+    // A unique, primary key or other constraint refused the write.
+    #[error("{context}")]
+    Conflict {
+        context: String,
+        #[source]
+        source: sqlx::Error,
+    },
+    // A versioned write was based on a version the row has since moved past.
+    #[error("{}/{} is at version {}, not {expected}", current.entity_type, current.entity_id, current.version)]
+    VersionConflict {
+        expected: i64,
+        current: Box<EntityRecord>,
+    },
+    // Reserving a transfer's bytes would take a quota subject past its limit.
+    #[error("{subject_kind} {subject} quota exceeded: {used_bytes} of {limit_bytes} bytes used")]
+    QuotaExceeded {
+        subject_kind: String,
+        subject: String,
+        limit_bytes: i64,
+        used_bytes: i64,
+        requested_bytes: i64,
+    },
+    // A value could not be encoded for storage or a stored value could not be decoded.
+    #[error("{context}")]
+    Serialization {
+        context: String,
+        #[source]
+        source: BoxError,
+    },
+    // The pool is closed or exhausted, or the database file could not be reached.
+    #[error("{context}")]
+    Connection {
+        context: String,
+        #[source]
+        source: sqlx::Error,
+    },
+    // Stored bytes that can no longer be read back, such as a column that fails to decrypt.
+    #[error("{detail} in {table}")]
+    Corrupt {
+        table: String,
+        detail: String,
+        #[source]
+        source: Option<BoxError>,
+    },
+    // Another writer held the database lock past the busy timeout.
+    #[error("{context}")]
+    Busy {
+        context: String,
+        #[source]
+        source: sqlx::Error,
+    },
+    // Any other failure reported by SQLite, usually a bug in the statement itself.
+    #[error("{context}")]
+    Database {
+        context: String,
+        #[source]
+        source: sqlx::Error,
+    },
+    // The encryption key is unusable or disagrees with the database.
+    #[error("{0}")]
+    Encryption(String),
+}
+
+impl StorageError {
+    pub fn not_found(what: impl Into<String>) -> Self {
+        StorageError::NotFound { what: what.into() }
+    }
+
+    pub fn serialization(context: impl Into<String>, source: impl Into<BoxError>) -> Self {
+        StorageError::Serialization {
+            context: context.into(),
+            source: source.into(),
+        }
+    }
+
+    pub fn corrupt(table: impl Into<String>, detail: impl Into<String>) -> Self {
+        StorageError::Corrupt {
+            table: table.into(),
+            detail: detail.into(),
+            source: None,
+        }
+    }
+
+    // Names the table a corrupt value was read from once the caller knows it.
+    pub(crate) fn in_table(self, table: &str) -> Self {
+        match self {
+            StorageError::Corrupt { detail, source, .. } => StorageError::Corrupt {
+                table: table.to_string(),
+                detail,
+                source,
+            },
+            other => other,
+        }
+    }
+
+    pub fn from_sqlx(context: impl Into<String>, source: sqlx::Error) -> Self {
+        let context = context.into();
+        match &source {
+            sqlx::Error::RowNotFound => StorageError::NotFound { what: context },
+            sqlx::Error::PoolClosed
+            | sqlx::Error::PoolTimedOut
+            | sqlx::Error::Io(_)
+            | sqlx::Error::Tls(_)
+            | sqlx::Error::WorkerCrashed => StorageError::Connection { context, source },
+            sqlx::Error::ColumnDecode { .. } | sqlx::Error::Decode(_) => {
+                StorageError::Serialization {
+                    context,
+                    source: Box::new(source),
+                }
+            }
+            sqlx::Error::Database(database) => {
+                let code = database
+                    .code()
+                    .and_then(|code| code.parse::<i64>().ok())
+                    .map(|code| code & 0xff);
+                match code {
+                    Some(SQLITE_BUSY | SQLITE_LOCKED) => StorageError::Busy { context, source },
+                    Some(SQLITE_CONSTRAINT) => StorageError::Conflict { context, source },
+                    Some(SQLITE_CORRUPT | SQLITE_NOTADB) => StorageError::Corrupt {
+                        table: "database".to_string(),
+                        detail: context,
+                        source: Some(Box::new(source)),
+                    },
+                    _ => StorageError::Database { context, source },
+                }
+            }
+            _ => StorageError::Database { context, source },
+        }
+    }
+
+    // Worth trying again later: the database was busy or briefly out of reach. Everything
+    // else fails the same way on every attempt.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            StorageError::Busy { .. } | StorageError::Connection { .. }
+        )
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            StorageError::NotFound { .. } => "not_found",
+            StorageError::Conflict { .. } => "conflict",
+            StorageError::VersionConflict { .. } => "version_conflict",
+            StorageError::QuotaExceeded { .. } => "quota_exceeded",
+            StorageError::Serialization { .. } => "serialization",
+            StorageError::Connection { .. } => "connection",
+            StorageError::Corrupt { .. } => "corrupt",
+            StorageError::Busy { .. } => "busy",
+            StorageError::Database { .. } => "database",
+            StorageError::Encryption(_) => "encryption",
+        }
+    }
+}
+
+// Errors with no operation to name still land in the right variant.
+impl From<sqlx::Error> for StorageError {
+    fn from(source: sqlx::Error) -> Self {
+        StorageError::from_sqlx("sqlite query", source)
+    }
+}
+
+impl From<serde_json::Error> for StorageError {
+    fn from(source: serde_json::Error) -> Self {
+        StorageError::serialization("json value", source)
+    }
+}
+
+// Names the operation that failed, the way `anyhow::Context` would, while keeping the
+// failure's classification.
+pub(crate) trait Context<T> {
+    fn context(self, context: impl Into<String>) -> Result<T>;
+
+    fn with_context<C: Into<String>>(self, context: impl FnOnce() -> C) -> Result<T>;
+}
+
+pub(crate) trait Classify {
+    fn classify(self, context: String) -> StorageError;
+}
+
+impl Classify for sqlx::Error {
+    fn classify(self, context: String) -> StorageError {
+        StorageError::from_sqlx(context, self)
+    }
+}
+
+impl Classify for serde_json::Error {
+    fn classify(self, context: String) -> StorageError {
+        StorageError::serialization(context, self)
+    }
+}
+
+impl Classify for std::string::FromUtf8Error {
+    fn classify(self, context: String) -> StorageError {
+        StorageError::serialization(context, self)
+    }
+}
+
+impl Classify for chrono::ParseError {
+    fn classify(self, context: String) -> StorageError {
+        StorageError::serialization(context, self)
+    }
+}
+
+// An already classified failure keeps its own, more specific message.
+impl Classify for StorageError {
+    fn classify(self, _context: String) -> StorageError {
+        self
+    }
+}
+
+// A lookup that came back empty is a missing row; the context names what was looked for.
+impl<T> Context<T> for Option<T> {
+    fn context(self, context: impl Into<String>) -> Result<T> {
+        self.ok_or_else(|| StorageError::not_found(context))
+    }
+
+    fn with_context<C: Into<String>>(self, context: impl FnOnce() -> C) -> Result<T> {
+        self.ok_or_else(|| StorageError::not_found(context()))
+    }
+}
+
+impl<T, E: Classify> Context<T> for std::result::Result<T, E> {
+    fn context(self, context: impl Into<String>) -> Result<T> {
+        self.map_err(|err| err.classify(context.into()))
+    }
+
+    fn with_context<C: Into<String>>(self, context: impl FnOnce() -> C) -> Result<T> {
+        self.map_err(|err| err.classify(context().into()))
+    }
+}
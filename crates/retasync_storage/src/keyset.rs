@@ -0,0 +1,202 @@
+﻿use serde::{Deserialize, Serialize};
+use sqlx::query::QueryAs;
+use sqlx::sqlite::SqliteArguments;
+use sqlx::Sqlite;
+
+use crate::error::{Result, StorageError};
+
+// One column of a row's sort key.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum KeyValue {
+    Integer(i64),
+    Text(String),
+}
+
+impl From<&str> for KeyValue {
+    fn from(value: &str) -> Self {
+        KeyValue::Text(value.to_string())
+    }
+}
+
+impl From<String> for KeyValue {
+    fn from(value: String) -> Self {
+        KeyValue::Text(value)
+    }
+}
+
+impl From<i64> for KeyValue {
+    fn from(value: i64) -> Self {
+        KeyValue::Integer(value)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PageDirection {
+    #[default]
+    Forward,
+    Backward,
+}
+
+// Where a page of a listing starts: just past `key` in `direction`, or at the head of the
+// listing without one. Paging by key rather than offset keeps pages stable while rows are
+// inserted, and a unique key keeps rows that share a timestamp from repeating or going missing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keyset {
+    pub key: Option<Vec<KeyValue>>,
+    pub direction: PageDirection,
+    pub limit: i64,
+}
+
+impl Keyset {
+    pub fn first(limit: i64) -> Self {
+        Self {
+            key: None,
+            direction: PageDirection::Forward,
+            limit,
+        }
+    }
+}
+
+// A listing's sort order: key columns that are unique together, all sorted the same way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SortOrder {
+    pub columns: &'static [&'static str],
+    pub descending: bool,
+}
+
+impl SortOrder {
+    pub const fn descending(columns: &'static [&'static str]) -> Self {
+        Self {
+            columns,
+            descending: true,
+        }
+    }
+
+    pub const fn ascending(columns: &'static [&'static str]) -> Self {
+        Self {
+            columns,
+            descending: false,
+        }
+    }
+
+    // `submitted_at desc, transfer_id desc`, for responses to say how they are sorted.
+    pub fn describe(&self) -> String {
+        let way = if self.descending { "desc" } else { "asc" };
+        self.columns
+            .iter()
+            .map(|column| format!("{column} {way}"))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    // Paging backward walks the listing in reverse, nearest the key first.
+    fn walks_descending(&self, direction: PageDirection) -> bool {
+        self.descending != (direction == PageDirection::Backward)
+    }
+
+    pub(crate) fn order_by(&self, direction: PageDirection) -> String {
+        let way = if self.walks_descending(direction) {
+            "DESC"
+        } else {
+            "ASC"
+        };
+        let columns: Vec<_> = self
+            .columns
+            .iter()
+            .map(|column| format!("{column} {way}"))
+            .collect();
+        format!("ORDER BY {}", columns.join(", "))
+    }
+
+    // The rows strictly past the key, as `(a < ? OR (a = ? AND b < ?))`, or `1` without a key.
+    // `bind` supplies the placeholders in the same order.
+    pub(crate) fn predicate(&self, page: &Keyset) -> Result<String> {
+        let Some(key) = &page.key else {
+            return Ok("1".to_string());
+        };
+        if key.len() != self.columns.len() {
+            return Err(StorageError::serialization(
+                "keyset",
+                format!(
+                    "{} key values for the {} columns of {}",
+                    key.len(),
+                    self.columns.len(),
+                    self.describe()
+                ),
+            ));
+        }
+        let past = if self.walks_descending(page.direction) {
+            "<"
+        } else {
+            ">"
+        };
+        let branches: Vec<_> = (0..self.columns.len())
+            .map(|last| {
+                let mut terms: Vec<_> = self.columns[..last]
+                    .iter()
+                    .map(|column| format!("{column} = ?"))
+                    .collect();
+                terms.push(format!("{} {past} ?", self.columns[last]));
+                terms.join(" AND ")
+            })
+            .collect();
+        Ok(format!("({})", branches.join(" OR ")))
+    }
+
+    pub(crate) fn bind<'q, O>(
+        &self,
+        mut query: QueryAs<'q, Sqlite, O, SqliteArguments<'q>>,
+        page: &'q Keyset,
+    ) -> QueryAs<'q, Sqlite, O, SqliteArguments<'q>> {
+        let Some(key) = &page.key else {
+            return query;
+        };
+        for last in 0..key.len() {
+            for value in &key[..=last] {
+                query = match value {
+                    KeyValue::Integer(value) => query.bind(*value),
+                    KeyValue::Text(value) => query.bind(value.as_str()),
+                };
+            }
+        }
+        query
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORDER: SortOrder = SortOrder::descending(&["received_at", "sha256", "size_bytes"]);
+
+    #[test]
+    fn predicate_compares_each_key_prefix() {
+        let page = Keyset {
+            key: Some(vec!["t".into(), "s".into(), 3.into()]),
+            direction: PageDirection::Forward,
+            limit: 10,
+        };
+        assert_eq!(
+            ORDER.predicate(&page).unwrap(),
+            "(received_at < ? OR received_at = ? AND sha256 < ? OR \
+             received_at = ? AND sha256 = ? AND size_bytes < ?)"
+        );
+        let back = Keyset {
+            direction: PageDirection::Backward,
+            ..page
+        };
+        assert!(ORDER.predicate(&back).unwrap().starts_with("(received_at > ?"));
+        assert_eq!(
+            ORDER.order_by(PageDirection::Backward),
+            "ORDER BY received_at ASC, sha256 ASC, size_bytes ASC"
+        );
+        assert_eq!(ORDER.predicate(&Keyset::first(10)).unwrap(), "1");
+        let short = Keyset {
+            key: Some(vec!["t".into()]),
+            ..Keyset::first(10)
+        };
+        assert!(ORDER.predicate(&short).is_err());
+    }
+}
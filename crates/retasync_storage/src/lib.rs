@@ -1,5 +1,30 @@
-﻿mod repository;
+﻿mod encryption;
+mod error;
+mod keyset;
+mod repository;
+mod timestamp;
 
+pub use encryption::EncryptedColumn;
+pub use error::{BoxError, StorageError};
+pub use keyset::{KeyValue, Keyset, PageDirection, SortOrder};
 pub use repository::{
-    JobRecord, JobResultRecord, NodeConfigRevision, RetasyncStorage, StorageConfig, TransferRecord,
+    entity_label_subject, payload_digest, AggregateCount, AllowlistEntry, BundleMember, CachedEvent,
+    CachedSummary, ClientActivity, CompletedTransfer, CrashReport, CursorAhead,
+    DeliveryReceiptRecord, DispatchAttempt, DispatchAttemptError, DispatchAttemptSummary,
+    EntityRecord, EntitySummary, EventGrouping, FeatureFlagRecord, FeedBounds, FeedEvent,
+    FeedIntegrity, FleetNode, FleetReport, HealthSample,
+    IdentityHashIssue, InboundRecord, IndexRebuild, IntegrityReport, IntegrityStats, JobDependency,
+    JobExport, JobExportChunk, JobGrouping, JobHold, JobLease, JobRecord, JobResultCheck,
+    JobResultDigest, JobResultPart, JobResultRecord, JobSummary, JobTrace, JobTransformTrace,
+    LabelKey,
+    NodeConfigRevision,
+    NotificationCursor, NotificationRecord, OrphanedRows, OutboxEntry, PayloadTable, PoolStats,
+    PoolUsage, QuarantinedRow, QuotaClaim, QuotaOverride, QuotaReservation, QuotaUsage,
+    ReceivedFile, RetasyncStorage,
+    SeenMessage, StorageConfig, StorageTx, SubmissionSource, SyncConflict, TransferDedup,
+    TransferRecord, TxFuture, VersionedPayload, WebhookAttempt, WebhookDelivery,
+    WebhookSubscription, CRASH_REPORT_ORDER, DEFAULT_READ_POOL_SIZE, FEED_JOB_EVENT,
+    FLEET_NODE_ORDER, LABEL_SUBJECT_ENTITY, LABEL_SUBJECT_JOB, LABEL_SUBJECT_TRANSFER,
+    RECEIVED_FILE_ORDER, TRANSFER_ORDER,
 };
+pub use timestamp::CanonicalTimestamp;
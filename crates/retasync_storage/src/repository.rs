@@ -1,23 +1,675 @@
-﻿use anyhow::{Context, Result};
-use chrono::Utc;
+﻿use base64::{engine::general_purpose::STANDARD, Engine as _};
+use chrono::{DateTime, DurationRound, Utc};
+use retasync_contract::IdentityHash;
+use retasync_transfer::TransferProgress;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
-use sqlx::{FromRow, SqlitePool};
+use sha2::{Digest, Sha256};
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions};
+use sqlx::query::QueryAs;
+use sqlx::sqlite::SqliteArguments;
+use sqlx::{FromRow, Row, Sqlite, SqliteConnection, SqlitePool, Transaction};
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
 use std::str::FromStr;
-use tracing::info;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
 use uuid::Uuid;
 
+use crate::encryption::{open, seal, EncryptedColumn, CIPHERTEXT_PREFIX};
+use crate::error::{Context, Result, StorageError};
+use crate::keyset::{Keyset, SortOrder};
+use crate::timestamp::{CanonicalTimestamp, CANONICAL_GLOB};
+
 const SCHEMA_SQL: &str = include_str!("sql/schema.sql");
+// Kept out of schema.sql, whose statements are split on `;`. Every write to `jobs.status` goes
+// through one of these, so a job event can never be missing from the feed or ahead of the row.
+// A status change publishes the payload its transaction staged in `job_event_details`, if any.
+// They are recreated on every start, so a changed trigger reaches existing databases.
+const FEED_TRIGGERS: [(&str, &str); 2] = [
+    (
+        "event_feed_job_created",
+        "CREATE TRIGGER event_feed_job_created AFTER INSERT ON jobs BEGIN INSERT INTO event_feed(event_type, payload_json, emitted_at) VALUES ('job.status.changed', json_object('job_id', NEW.job_id, 'status', NEW.status), NEW.updated_at); END",
+    ),
+    (
+        "event_feed_job_status",
+        "CREATE TRIGGER event_feed_job_status AFTER UPDATE OF status ON jobs WHEN NEW.status IS NOT OLD.status BEGIN INSERT INTO event_feed(event_type, payload_json, emitted_at) VALUES ('job.status.changed', COALESCE((SELECT payload_json FROM job_event_details WHERE job_id = NEW.job_id), CASE WHEN NEW.failure_reason IS NULL THEN json_object('job_id', NEW.job_id, 'status', NEW.status) ELSE json_object('job_id', NEW.job_id, 'status', NEW.status, 'reason', NEW.failure_reason) END), NEW.updated_at); DELETE FROM job_event_details WHERE job_id = NEW.job_id; END",
+    ),
+];
+pub const FEED_JOB_EVENT: &str = "job.status.changed";
+// Sort orders of the listings that page by keyset.
+pub const TRANSFER_ORDER: SortOrder = SortOrder::descending(&["submitted_at", "transfer_id"]);
+pub const RECEIVED_FILE_ORDER: SortOrder =
+    SortOrder::descending(&["received_at", "sha256", "size_bytes"]);
+pub const CRASH_REPORT_ORDER: SortOrder = SortOrder::descending(&["occurred_at", "crash_id"]);
+pub const FLEET_NODE_ORDER: SortOrder = SortOrder::ascending(&["identity_hash"]);
+// What a row of `labels` is attached to.
+pub const LABEL_SUBJECT_JOB: &str = "job";
+pub const LABEL_SUBJECT_TRANSFER: &str = "transfer";
+pub const LABEL_SUBJECT_ENTITY: &str = "entity";
+const FEED_TRIMMED_THROUGH_KEY: &str = "event_feed.trimmed_through";
+const FEED_PUBLISHED_THROUGH_KEY: &str = "event_feed.published_through";
+const ENCRYPTION_CANARY_KEY: &str = "encryption_canary";
+const ENCRYPTION_CANARY_VALUE: &str = "retasync-storage-key-check";
+const INTEGRITY_HIGH_WATER_PREFIX: &str = "integrity_rowid.";
+const ENTITY_SYNC_PREFIX: &str = "entity_sync.";
+const NODE_MUTE_KEY: &str = "node.mute";
+const NODE_CONFIG_APPLY_KEY: &str = "node.config_apply";
+
+// JSON columns the integrity checker scans: (table, key column, JSON columns).
+const INTEGRITY_TABLES: [(&str, &str, &[&str]); 8] = [
+    ("jobs", "job_id", &["payload_json", "dispatch_json"]),
+    ("job_results", "job_id", &["result_json"]),
+    ("transfers", "transfer_id", &["metadata_json"]),
+    ("cached_events", "event_id", &["payload_json"]),
+    ("cached_messages", "message_id", &["payload_json"]),
+    ("entities", "entity_key", &["record_json"]),
+    ("sync_conflicts", "conflict_id", &["local_json", "remote_json"]),
+    ("outbox", "message_id", &["envelope_json"]),
+];
+const REENCRYPT_BATCH_SIZE: i64 = 200;
+pub const DEFAULT_READ_POOL_SIZE: u32 = 4;
+// Reads wait this long on a locked database before failing; exports may wait longer, since a
+// chunk only ever holds its read transaction for one statement.
+const READ_BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+const EXPORT_BUSY_TIMEOUT: Duration = Duration::from_secs(30);
+// Cap on the WAL file left behind after a checkpoint; SQLite otherwise keeps it at its
+// high-water size.
+const WAL_SIZE_LIMIT_BYTES: u64 = 16 * 1024 * 1024;
+
+// Every column holding a timestamp: (table, column).
+const TIMESTAMP_COLUMNS: [(&str, &str); 60] = [
+    ("jobs", "submitted_at"),
+    ("jobs", "updated_at"),
+    ("jobs", "transit_expires_at"),
+    ("job_attempts", "started_at"),
+    ("job_attempts", "finished_at"),
+    ("job_leases", "lease_expires_at"),
+    ("job_results", "completed_at"),
+    ("cached_events", "received_at"),
+    ("cached_events", "sent_at"),
+    ("cached_messages", "received_at"),
+    ("transfers", "submitted_at"),
+    ("transfers", "updated_at"),
+    ("acl_allowlist", "created_at"),
+    ("acl_allowlist", "expires_at"),
+    ("acl_allowlist", "approved_at"),
+    ("acl_denylist", "created_at"),
+    ("node_config_revisions", "created_at"),
+    ("notifications", "created_at"),
+    ("event_feed", "emitted_at"),
+    ("notification_cursors", "updated_at"),
+    ("transfer_progress", "last_chunk_at"),
+    ("job_messages", "sent_at"),
+    ("dispatch_attempts", "started_at"),
+    ("dispatch_attempts", "finished_at"),
+    ("job_traces", "returned_at"),
+    ("job_transforms", "recorded_at"),
+    ("job_result_digests", "recorded_at"),
+    ("job_result_parts", "received_at"),
+    ("health_samples", "sampled_at"),
+    ("entities", "updated_at"),
+    ("sync_conflicts", "detected_at"),
+    ("transfer_dedup", "decided_at"),
+    ("received_files", "received_at"),
+    ("quota_usage", "used_at"),
+    ("quota_reservations", "reserved_at"),
+    ("quota_overrides", "updated_at"),
+    ("feature_flags", "updated_at"),
+    ("seen_messages", "sent_at"),
+    ("seen_messages", "received_at"),
+    ("quarantine", "quarantined_at"),
+    ("payload_migrations_applied", "applied_at"),
+    ("client_rejections", "bucket_start"),
+    ("outbox", "queued_at"),
+    ("outbox", "exported_at"),
+    ("crash_reports", "occurred_at"),
+    ("crash_reports", "recorded_at"),
+    ("identity_hash_issues", "detected_at"),
+    ("fleet_nodes", "received_at"),
+    ("fleet_nodes", "stale_since"),
+    ("fleet_reports", "received_at"),
+    ("webhook_subscriptions", "created_at"),
+    ("webhook_subscriptions", "disabled_at"),
+    ("webhook_deliveries", "next_attempt_at"),
+    ("webhook_deliveries", "queued_at"),
+    ("webhook_attempts", "attempted_at"),
+    ("inbound_records", "received_at"),
+    ("inbound_records", "answered_at"),
+    ("delivery_receipts", "recorded_at"),
+    ("job_result_checks", "checked_at"),
+    ("job_holds", "held_at"),
+];
+const TIMESTAMPS_CANONICAL_KEY: &str = "timestamps_canonical";
+const IDENTITY_HASHES_NORMALIZED_KEY: &str = "identity_hashes_normalized";
+// Tables keyed by a peer identity hash, normalized once by `normalize_identity_hashes`.
+const IDENTITY_HASH_TABLES: &[&str] = &["acl_allowlist", "acl_denylist"];
+
+// Rows kept per job or transfer: (table, column, parent table, parent column).
+const DERIVED_REFERENCES: [(&str, &str, &str, &str); 18] = [
+    ("job_attempts", "job_id", "jobs", "job_id"),
+    ("job_leases", "job_id", "jobs", "job_id"),
+    ("job_results", "job_id", "jobs", "job_id"),
+    ("job_messages", "job_id", "jobs", "job_id"),
+    ("dispatch_attempts", "job_id", "jobs", "job_id"),
+    ("job_traces", "job_id", "jobs", "job_id"),
+    ("job_transforms", "job_id", "jobs", "job_id"),
+    ("job_result_digests", "job_id", "jobs", "job_id"),
+    ("job_result_checks", "job_id", "jobs", "job_id"),
+    ("job_holds", "job_id", "jobs", "job_id"),
+    ("job_dependencies", "job_id", "jobs", "job_id"),
+    ("job_dependencies", "depends_on", "jobs", "job_id"),
+    ("job_result_parts", "job_id", "jobs", "job_id"),
+    ("job_event_details", "job_id", "jobs", "job_id"),
+    ("transfer_progress", "transfer_id", "transfers", "transfer_id"),
+    ("transfer_dedup", "transfer_id", "transfers", "transfer_id"),
+    ("bundle_members", "bundle_id", "transfers", "transfer_id"),
+    ("quota_reservations", "transfer_id", "transfers", "transfer_id"),
+];
+
+// Columns added after a table first shipped: (table, column, definition).
+const ADDED_COLUMNS: [(&str, &str, &str); 38] = [
+    ("acl_allowlist", "role", "TEXT NOT NULL DEFAULT 'peer'"),
+    ("acl_allowlist", "status", "TEXT NOT NULL DEFAULT 'active'"),
+    ("acl_allowlist", "expires_at", "TEXT"),
+    ("acl_allowlist", "approved_at", "TEXT"),
+    ("jobs", "dispatch_json", "TEXT"),
+    ("jobs", "requested_operation", "TEXT"),
+    ("jobs", "idempotency_key", "TEXT"),
+    ("jobs", "submitted_by", "TEXT"),
+    ("jobs", "transit_expires_at", "TEXT"),
+    ("transfers", "job_id", "TEXT REFERENCES jobs(job_id)"),
+    ("cached_events", "source_identity", "TEXT"),
+    ("cached_events", "sent_at", "TEXT"),
+    ("received_files", "bundle_id", "TEXT"),
+    ("jobs", "contract_version", "TEXT"),
+    ("entities", "contract_version", "TEXT"),
+    ("cached_events", "contract_version", "TEXT"),
+    ("received_files", "media_type", "TEXT"),
+    ("received_files", "quarantine_reason", "TEXT"),
+    ("received_files", "content_base64", "TEXT"),
+    ("entities", "version", "INTEGER NOT NULL DEFAULT 1"),
+    ("entities", "deleted_at", "TEXT"),
+    ("jobs", "source_token", "TEXT"),
+    ("jobs", "source_addr", "TEXT"),
+    ("jobs", "source_client_id", "TEXT"),
+    ("transfers", "source_token", "TEXT"),
+    ("transfers", "source_addr", "TEXT"),
+    ("transfers", "source_client_id", "TEXT"),
+    ("node_config_revisions", "reason", "TEXT"),
+    ("jobs", "payload_bytes", "INTEGER"),
+    ("jobs", "payload_sha256", "TEXT"),
+    ("cached_events", "payload_bytes", "INTEGER"),
+    ("cached_events", "payload_sha256", "TEXT"),
+    ("cached_messages", "payload_bytes", "INTEGER"),
+    ("cached_messages", "payload_sha256", "TEXT"),
+    ("entities", "payload_bytes", "INTEGER"),
+    ("entities", "payload_sha256", "TEXT"),
+    ("node_config_revisions", "note", "TEXT"),
+    ("acl_allowlist", "public_key", "TEXT"),
+];
+
+// Tables that keep `payload_bytes` and `payload_sha256` next to a payload: (table, payload column).
+const DIGESTED_PAYLOADS: [(&str, &str); 4] = [
+    ("jobs", "payload_json"),
+    ("cached_events", "payload_json"),
+    ("cached_messages", "payload_json"),
+    ("entities", "record_json"),
+];
+
+// (table, primary key, encrypted column)
+const ENCRYPTED_COLUMNS: [(&str, &str, &str); 16] = [
+    ("jobs", "job_id", "payload_json"),
+    ("cached_events", "event_id", "payload_json"),
+    ("cached_messages", "message_id", "payload_json"),
+    ("transfers", "transfer_id", "metadata_json"),
+    ("acl_allowlist", "identity_hash", "note"),
+    ("entities", "entity_key", "record_json"),
+    ("sync_conflicts", "conflict_id", "local_json"),
+    ("sync_conflicts", "conflict_id", "remote_json"),
+    ("job_transforms", "trace_id", "before_json"),
+    ("job_transforms", "trace_id", "after_json"),
+    ("received_files", "sha256", "content_base64"),
+    ("outbox", "message_id", "envelope_json"),
+    ("webhook_subscriptions", "subscription_id", "secret"),
+    ("webhook_deliveries", "delivery_id", "body_json"),
+    ("inbound_records", "message_id", "envelope_json"),
+    ("inbound_records", "message_id", "result_json"),
+];
 
 #[derive(Debug, Clone)]
 pub struct StorageConfig {
     pub sqlite_path: String,
+    pub encryption_key_path: Option<String>,
+    pub read_pool_size: u32,
 }
 
+// Writes go through a single connection, so they are serialized in the process and never
+// fight over the write lock; list, get, aggregate and export queries use a separate pool of
+// read-only connections that WAL mode lets run alongside them.
 #[derive(Debug, Clone)]
 pub struct RetasyncStorage {
     pool: SqlitePool,
+    read_pool: SqlitePool,
+    database_path: PathBuf,
+    cipher: Option<EncryptedColumn>,
+    integrity: Arc<IntegrityCounters>,
+    // Stamped on every job, entity and cached event written, so payload migrations know
+    // which shape each record has. Shared by every clone, so a contract reload restamps them all.
+    contract_version: Arc<std::sync::RwLock<Option<String>>>,
+    // Recorded in the feed by every transaction run through this handle; see `with_event`.
+    events: Vec<Arc<PendingEvent>>,
+    // Payload bodies read by `get_job`, `get_entity` and the cached event and message lists.
+    payload_reads: Arc<AtomicU64>,
+}
+
+#[derive(Debug)]
+struct PendingEvent {
+    event_type: String,
+    data: Value,
+}
+
+impl PendingEvent {
+    // Job status events ride on the feed row their status change writes.
+    fn status_of_job(&self) -> Option<&str> {
+        if self.event_type != FEED_JOB_EVENT {
+            return None;
+        }
+        self.data.get("job_id").and_then(Value::as_str)
+    }
+}
+
+#[derive(Debug, Default)]
+struct IntegrityCounters {
+    skipped_rows: AtomicU64,
+    scanned_rows: AtomicU64,
+    quarantined_rows: AtomicU64,
+}
+
+// Totals since start: rows left out of listings, and rows checked or quarantined by scans.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IntegrityStats {
+    pub skipped_rows: u64,
+    pub scanned_rows: u64,
+    pub quarantined_rows: u64,
+}
+
+// Connections open and checked out of one pool right now, against its limit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PoolUsage {
+    pub open: u32,
+    pub in_use: u32,
+    pub max: u32,
+}
+
+// `wal_bytes` is the checkpoint lag: what the WAL holds that a checkpoint has not yet written
+// back, or has but could not reset because a reader still needed it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PoolStats {
+    pub read: PoolUsage,
+    pub write: PoolUsage,
+    pub wal_bytes: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IntegrityReport {
+    pub scanned: u64,
+    pub quarantined: u64,
+    pub quarantined_by_table: BTreeMap<String, u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuarantinedRow {
+    pub id: i64,
+    pub source_table: String,
+    pub row_key: String,
+    pub column_name: String,
+    pub raw: Vec<u8>,
+    pub reason: String,
+    pub quarantined_at: String,
+}
+
+// Rows per (UTC bucket start in epoch seconds, group) from the aggregate queries.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AggregateCount {
+    pub bucket_start: i64,
+    pub group: String,
+    pub count: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventGrouping {
+    Event,
+    Source,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobGrouping {
+    Status,
+    Operation,
+}
+
+// One stored version of a replicated entity, such as an emergency action message.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EntityRecord {
+    pub entity_type: String,
+    pub entity_id: String,
+    pub updated_at: DateTime<Utc>,
+    pub record: Value,
+    // Starts at 1 and goes up by one with every local change, replicated along with the record.
+    #[serde(default = "first_entity_version")]
+    pub version: i64,
+    // A soft-deleted entity stays stored, so its deletion replicates like any other change.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+// An entity less its record.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EntitySummary {
+    pub entity_type: String,
+    pub entity_id: String,
+    pub updated_at: DateTime<Utc>,
+    pub version: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime<Utc>>,
+    pub record_bytes: Option<i64>,
+    pub record_sha256: Option<String>,
+}
+
+fn first_entity_version() -> i64 {
+    1
+}
+
+// Tables whose payloads follow the contract, and so may need migrating when it changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PayloadTable {
+    Jobs,
+    Entities,
+    CachedEvents,
+}
+
+impl PayloadTable {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PayloadTable::Jobs => "jobs",
+            PayloadTable::Entities => "entities",
+            PayloadTable::CachedEvents => "cached_events",
+        }
+    }
+
+    // (key column, kind column, payload column)
+    fn columns(self) -> (&'static str, &'static str, &'static str) {
+        match self {
+            PayloadTable::Jobs => ("job_id", "operation", "payload_json"),
+            PayloadTable::Entities => ("entity_key", "entity_type", "record_json"),
+            PayloadTable::CachedEvents => ("event_id", "event_name", "payload_json"),
+        }
+    }
+}
+
+// A stored payload and the contract version it was written under; `None` for rows written
+// before versions were recorded. `kind` is the job's operation, the entity type or the
+// event name.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VersionedPayload {
+    pub table: PayloadTable,
+    pub key: String,
+    pub kind: String,
+    pub contract_version: Option<String>,
+    pub payload: Value,
+}
+
+// What the destination said when offered a transfer's content hash before the upload.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TransferDedup {
+    pub transfer_id: String,
+    pub sha256: String,
+    pub outcome: String,
+    pub bytes_saved: i64,
+    pub remote_ack: Option<Value>,
+    pub decided_at: String,
+}
+
+// A file this node holds a complete copy of, looked up by content when a peer offers it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, FromRow)]
+pub struct ReceivedFile {
+    pub sha256: String,
+    pub size_bytes: i64,
+    pub file_name: String,
+    pub source_identity: String,
+    pub received_at: String,
+    // The inbound bundle transfer the file arrived in, if it came as a bundle member.
+    pub bundle_id: Option<String>,
+    pub media_type: Option<String>,
+    pub quarantine_reason: Option<String>,
+}
+
+// One file of a bundle transfer, in packing order. `bundle_id` is the transfer id on whichever
+// node holds the row, so sender and receiver each list the members under their own transfer.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, FromRow)]
+pub struct BundleMember {
+    pub position: i64,
+    pub name: String,
+    pub media_type: String,
+    pub size_bytes: i64,
+    pub sha256: String,
+    pub status: String,
+}
+
+// Bytes charged to one quota subject by the transfers that succeeded at `used_at`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, FromRow)]
+pub struct QuotaUsage {
+    pub subject_kind: String,
+    pub subject: String,
+    pub used_at: String,
+    pub bytes: i64,
+}
+
+// Bytes held for a transfer that has not finished yet.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, FromRow)]
+pub struct QuotaReservation {
+    pub transfer_id: String,
+    pub subject_kind: String,
+    pub subject: String,
+    pub bytes: i64,
+    pub reserved_at: String,
+}
+
+// A subject a transfer is charged to, with its limit; `None` tracks usage without a cap.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuotaClaim {
+    pub subject_kind: String,
+    pub subject: String,
+    pub limit_bytes: Option<i64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, FromRow)]
+pub struct QuotaOverride {
+    pub subject_kind: String,
+    pub subject: String,
+    pub max_bytes: i64,
+    pub note: Option<String>,
+    pub updated_at: String,
+}
+
+// The first receipt of an inbound command envelope, kept to refuse replays of it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, FromRow)]
+pub struct SeenMessage {
+    pub source_identity: String,
+    pub message_id: String,
+    pub sent_at: String,
+    pub received_at: String,
+}
+
+// An envelope waiting for a courier: a command the mesh took for store-and-forward, or the
+// answer to a command that arrived in a bundle. `status` is `pending` until a bundle carries it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OutboxEntry {
+    pub message_id: String,
+    // `command` or `result`.
+    pub kind: String,
+    pub job_id: Option<String>,
+    pub operation: String,
+    pub destination_identity: String,
+    pub envelope: Value,
+    pub status: String,
+    pub queued_at: String,
+    pub bundle_id: Option<String>,
+    pub exported_at: Option<String>,
+}
+
+#[derive(FromRow)]
+struct OutboxRow {
+    message_id: String,
+    kind: String,
+    job_id: Option<String>,
+    operation: String,
+    destination_identity: String,
+    envelope_json: Vec<u8>,
+    status: String,
+    queued_at: String,
+    bundle_id: Option<String>,
+    exported_at: Option<String>,
+}
+
+// The worker currently running a job; it renews `lease_expires_at` until it lets go.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, FromRow)]
+pub struct JobLease {
+    pub job_id: String,
+    pub worker_id: String,
+    // 1-based count of workers that have taken this job, this one included.
+    pub attempt: i64,
+    pub lease_expires_at: String,
+}
+
+// A panic: `process` for one that took the node down, recorded at the next start, or `task` for
+// a worker panic the process survived.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, FromRow)]
+pub struct CrashReport {
+    pub crash_id: String,
+    pub kind: String,
+    pub occurred_at: String,
+    pub message: String,
+    pub thread: Option<String>,
+    pub location: Option<String>,
+    pub backtrace: Option<String>,
+    pub version: String,
+    pub uptime_secs: Option<i64>,
+    // The job whose worker panicked, for `task` reports.
+    pub job_id: Option<String>,
+    pub recorded_at: String,
+}
+
+// The latest status report a node sent this node as fleet collector.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, FromRow)]
+pub struct FleetNode {
+    pub identity_hash: String,
+    pub report_json: String,
+    // The reporting interval the node announced, which its staleness is measured against.
+    pub interval_secs: i64,
+    pub received_at: String,
+    // Set when the node went quiet for too long; its next report clears it.
+    pub stale_since: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, FromRow)]
+pub struct FleetReport {
+    pub report_json: String,
+    pub received_at: String,
+}
+
+// An external HTTP consumer of the mesh events this node receives.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, FromRow)]
+pub struct WebhookSubscription {
+    pub subscription_id: String,
+    pub url: String,
+    // Event name patterns, any of which selects an event.
+    pub events_json: String,
+    pub source_identity: Option<String>,
+    // Signs every body sent to the subscription.
+    pub secret: String,
+    pub timeout_ms: i64,
+    pub max_retries: i64,
+    pub backoff_ms: i64,
+    pub enabled: bool,
+    // Failed attempts since the last delivered one.
+    pub consecutive_failures: i64,
+    pub disabled_at: Option<String>,
+    pub created_at: String,
+}
+
+// An event still owed to a subscription.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, FromRow)]
+pub struct WebhookDelivery {
+    pub delivery_id: String,
+    pub subscription_id: String,
+    pub message_id: String,
+    pub event_name: String,
+    pub body_json: String,
+    // Attempts made so far.
+    pub attempts: i64,
+    pub next_attempt_at: String,
+    pub queued_at: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, FromRow)]
+pub struct WebhookAttempt {
+    pub delivery_id: String,
+    pub message_id: String,
+    pub event_name: String,
+    // 1 for the first attempt at the delivery.
+    pub attempt: i64,
+    pub attempted_at: String,
+    // `delivered`, `retrying` or `failed`, the last when the delivery was given up on.
+    pub outcome: String,
+    pub status_code: Option<i64>,
+    pub error: Option<String>,
+    pub duration_ms: i64,
+}
+
+// An inbound command a built-in handler answered, and the answer it gave.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, FromRow)]
+pub struct InboundRecord {
+    pub message_id: String,
+    pub operation: String,
+    pub source_identity: String,
+    pub envelope_json: String,
+    pub result_json: String,
+    pub received_at: String,
+    pub answered_at: String,
+}
+
+// A receipt a peer signed for a command or transfer this node sent. `reason` says why an
+// unverified receipt could not be checked.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, FromRow)]
+pub struct DeliveryReceiptRecord {
+    pub message_id: String,
+    pub job_id: Option<String>,
+    pub transfer_id: Option<String>,
+    pub receiver_identity: String,
+    pub receipt_json: String,
+    pub verified: bool,
+    pub reason: Option<String>,
+    pub recorded_at: String,
+}
+
+// Both versions of an entity that diverged between two nodes, and which one was kept.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SyncConflict {
+    pub conflict_id: String,
+    pub entity_type: String,
+    pub entity_id: String,
+    pub peer_identity: String,
+    pub local: EntityRecord,
+    pub remote: EntityRecord,
+    pub kept: String,
+    pub detected_at: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
@@ -29,6 +681,118 @@ pub struct JobRecord {
     pub submitted_at: String,
     pub updated_at: String,
     pub failure_reason: Option<String>,
+    pub dispatch_json: Option<String>,
+    // The name the client submitted when it was an alias of `operation`.
+    pub requested_operation: Option<String>,
+}
+
+// A job as `get_job` reads it, less the payload; its size and digest stand in for it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, FromRow)]
+pub struct JobSummary {
+    pub job_id: String,
+    pub operation: String,
+    pub status: String,
+    pub submitted_at: String,
+    pub updated_at: String,
+    pub failure_reason: Option<String>,
+    pub dispatch_json: Option<String>,
+    pub requested_operation: Option<String>,
+    pub payload_bytes: Option<i64>,
+    pub payload_sha256: Option<String>,
+}
+
+impl From<&JobRecord> for JobSummary {
+    fn from(record: &JobRecord) -> Self {
+        let (payload_bytes, payload_sha256) = payload_digest(&record.payload_json);
+        Self {
+            job_id: record.job_id.clone(),
+            operation: record.operation.clone(),
+            status: record.status.clone(),
+            submitted_at: record.submitted_at.clone(),
+            updated_at: record.updated_at.clone(),
+            failure_reason: record.failure_reason.clone(),
+            dispatch_json: record.dispatch_json.clone(),
+            requested_operation: record.requested_operation.clone(),
+            payload_bytes: Some(payload_bytes),
+            payload_sha256: Some(payload_sha256),
+        }
+    }
+}
+
+// A cached event or message less its payload. `name` is the event name or the operation.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, FromRow)]
+pub struct CachedSummary {
+    pub id: String,
+    pub name: String,
+    pub received_at: String,
+    pub payload_bytes: Option<i64>,
+    pub payload_sha256: Option<String>,
+}
+
+// A job at one end of a dependency edge, with its current status.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, FromRow)]
+pub struct JobDependency {
+    pub job_id: String,
+    pub status: String,
+}
+
+// Who submitted a job or transfer: the token label (`anonymous-loopback` without one), the
+// caller's address, and the id the client gave itself in `X-Client-Id`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, FromRow)]
+pub struct SubmissionSource {
+    pub token_label: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remote_addr: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+}
+
+// Jobs and standalone transfers submitted under one source, how many of them failed, and the
+// bytes its transfers have sent.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, FromRow)]
+pub struct ClientActivity {
+    #[sqlx(flatten)]
+    pub source: SubmissionSource,
+    pub submissions: i64,
+    pub failures: i64,
+    pub bytes: i64,
+}
+
+// A job as exported, with its submission source when it was recorded and its labels.
+#[derive(Debug, Clone, Serialize)]
+pub struct JobExport {
+    #[serde(flatten)]
+    pub job: JobRecord,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<SubmissionSource>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+}
+
+// A label key in use, how many distinct values it has and how many subjects carry it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, FromRow)]
+pub struct LabelKey {
+    pub key: String,
+    pub values: i64,
+    pub subjects: i64,
+}
+
+// One chunk of a full job export, and the rowid to continue after; `None` once the table is
+// exhausted.
+#[derive(Debug, Clone)]
+pub struct JobExportChunk {
+    pub jobs: Vec<JobExport>,
+    pub next_after: Option<i64>,
+}
+
+#[derive(FromRow)]
+struct ExportedJob {
+    rowid: i64,
+    #[sqlx(flatten)]
+    job: JobRecord,
+    source_token: Option<String>,
+    source_addr: Option<String>,
+    source_client_id: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
@@ -38,6 +802,106 @@ pub struct JobResultRecord {
     pub completed_at: String,
 }
 
+// The hops a traced job's command passed through. `returned_at` is set once the result carrying
+// the full trace arrives; until then only the origin hop is known.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, FromRow)]
+pub struct JobTrace {
+    pub job_id: String,
+    pub hops_json: String,
+    pub truncated: bool,
+    pub returned_at: Option<String>,
+}
+
+// How a job's result compared with the one its submitter held; see `job_result_digests`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, FromRow)]
+pub struct JobResultDigest {
+    pub job_id: String,
+    pub result_digest: String,
+    pub unchanged: bool,
+    pub source: String,
+    pub recorded_at: String,
+}
+
+// A result that did not match the contract's result schema. `policy` is what the node did with
+// it, `flag` or `quarantine`, and `errors_json` holds the violations with their JSON pointers.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, FromRow)]
+pub struct JobResultCheck {
+    pub job_id: String,
+    pub schema_name: String,
+    pub policy: String,
+    pub errors_json: String,
+    pub checked_at: String,
+}
+
+// An operator hold on a job that had not been dispatched yet.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, FromRow)]
+pub struct JobHold {
+    pub job_id: String,
+    pub previous_status: String,
+    pub held_by: String,
+    pub reason: String,
+    pub held_at: String,
+}
+
+// A job payload before and after the configured transforms ran over it; `stage` is `command`
+// for the outgoing payload and `result` for the reply.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JobTransformTrace {
+    pub job_id: String,
+    pub stage: String,
+    pub applied: Vec<String>,
+    pub before: Value,
+    pub after: Value,
+    pub recorded_at: String,
+}
+
+// One bridge call made for a job. `attempt` counts from 1 per message, across the direct
+// sends and the store-and-forward one that may follow them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, FromRow)]
+pub struct DispatchAttempt {
+    pub message_id: String,
+    pub job_id: String,
+    pub destination_identity: String,
+    pub operation: String,
+    pub attempt: i64,
+    pub transport_planned: String,
+    // The transport the result came back over; unset when the call failed.
+    pub transport_used: Option<String>,
+    pub started_at: String,
+    pub finished_at: String,
+    // `delivered`, `retried`, `escalated` or `failed`; only the first and last end a dispatch.
+    pub outcome: String,
+    pub error_variant: Option<String>,
+    pub error_detail: Option<String>,
+    pub escalated: bool,
+}
+
+// What a job's detail says of its attempt log.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DispatchAttemptSummary {
+    pub attempts: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<DispatchAttemptError>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, FromRow)]
+pub struct DispatchAttemptError {
+    pub attempt: i64,
+    pub variant: String,
+    pub detail: Option<String>,
+    pub finished_at: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobResultPart {
+    pub job_id: String,
+    pub sequence: u32,
+    #[serde(rename = "final")]
+    pub is_final: bool,
+    pub data: Value,
+    pub received_at: String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
 pub struct TransferRecord {
     pub transfer_id: String,
@@ -46,6 +910,83 @@ pub struct TransferRecord {
     pub submitted_at: String,
     pub updated_at: String,
     pub failure_reason: Option<String>,
+    pub job_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct AllowlistEntry {
+    pub identity_hash: String,
+    pub note: Option<String>,
+    pub role: String,
+    pub status: String,
+    pub expires_at: Option<String>,
+    pub created_at: String,
+    pub approved_at: Option<String>,
+    // Base64 X25519 key the peer's sealed payloads are opened with and ours are sealed for.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_key: Option<String>,
+}
+
+// A stored identity that is not a hash, left in place by the normalization migration.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, FromRow)]
+pub struct IdentityHashIssue {
+    pub table_name: String,
+    pub identity_hash: String,
+    pub defect: String,
+    pub detected_at: String,
+}
+
+// Raw samples have a resolution of 0 and a count of 1; downsampled rows carry how many raw
+// samples they cover, how many of those were ok and the mean latency of the ok probes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, FromRow)]
+pub struct HealthSample {
+    pub sampled_at: String,
+    pub resolution_secs: i64,
+    pub sample_count: i64,
+    pub bridge_ok: i64,
+    pub bridge_latency_ms: i64,
+    pub storage_ok: i64,
+    pub storage_latency_ms: i64,
+    pub queued_jobs: i64,
+}
+
+impl HealthSample {
+    pub fn raw(
+        at: DateTime<Utc>,
+        bridge: (bool, i64),
+        storage: (bool, i64),
+        queued_jobs: i64,
+    ) -> Self {
+        Self {
+            sampled_at: CanonicalTimestamp::from(at).to_string(),
+            resolution_secs: 0,
+            sample_count: 1,
+            bridge_ok: bridge.0 as i64,
+            bridge_latency_ms: bridge.1,
+            storage_ok: storage.0 as i64,
+            storage_latency_ms: storage.1,
+            queued_jobs,
+        }
+    }
+
+    pub fn aggregate(at: DateTime<Utc>, resolution_secs: i64) -> Self {
+        Self {
+            sampled_at: CanonicalTimestamp::from(at).to_string(),
+            resolution_secs,
+            sample_count: 0,
+            bridge_ok: 0,
+            bridge_latency_ms: 0,
+            storage_ok: 0,
+            storage_latency_ms: 0,
+            queued_jobs: 0,
+        }
+    }
+
+    pub fn sampled_at(&self) -> Result<DateTime<Utc>> {
+        Ok(DateTime::parse_from_rfc3339(&self.sampled_at)
+            .with_context(|| format!("parse health sample time {}", self.sampled_at))?
+            .with_timezone(&Utc))
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
@@ -53,305 +994,8061 @@ pub struct NodeConfigRevision {
     pub revision_id: i64,
     pub config_json: String,
     pub created_at: String,
+    pub reason: Option<String>,
+    pub note: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NotificationRecord {
+    pub seq: i64,
+    pub event_type: String,
+    pub data: Value,
+    pub created_at: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FeedEvent {
+    pub seq: i64,
+    pub event_type: String,
+    pub data: Value,
+    pub emitted_at: String,
+}
+
+// `head_seq` is the last sequence number handed out; everything up to `trimmed_through` has
+// been removed by retention.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeedBounds {
+    pub head_seq: i64,
+    pub trimmed_through: i64,
+}
+
+// The event feed as a storage rebuild finds it. Every sequence number after `trimmed_through`
+// up to the head should still have its row; `gaps` are the inclusive runs that don't.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeedIntegrity {
+    pub head_seq: i64,
+    pub max_seq: i64,
+    pub trimmed_through: i64,
+    pub published_through: i64,
+    pub gaps: Vec<(i64, i64)>,
+}
+
+// A notification cursor acknowledging past the last notification ever handed out.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, FromRow)]
+pub struct CursorAhead {
+    pub token_label: String,
+    pub acked_seq: i64,
+    pub head_seq: i64,
+}
+
+// A transfer that completed, with the bytes it was charged against the quotas.
+#[derive(Debug, Clone)]
+pub struct CompletedTransfer {
+    pub transfer: TransferRecord,
+    pub bytes: i64,
+    pub source: Option<SubmissionSource>,
+}
+
+// Rows of a derived table whose job or transfer is gone, as left by deleting one by hand.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OrphanedRows {
+    pub table: String,
+    pub column: String,
+    pub rows: u64,
+}
+
+// SQLite's `quick_check` findings either side of a `REINDEX`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexRebuild {
+    pub indexes: u64,
+    pub problems_before: Vec<String>,
+    pub problems_after: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FeatureFlagRecord {
+    pub name: String,
+    pub enabled: bool,
+    pub variant: Option<Value>,
+    pub updated_at: String,
+    pub updated_by: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct NotificationCursor {
+    pub token_label: String,
+    pub acked_seq: i64,
+    pub dropped: i64,
+    pub updated_at: String,
 }
 
 impl RetasyncStorage {
     pub async fn connect(config: &StorageConfig) -> Result<Self> {
-        let uri = normalize_sqlite_uri(&config.sqlite_path);
+        let cipher = load_cipher(config.encryption_key_path.as_deref())?;
+        let storage =
+            Self::open_unchecked(&config.sqlite_path, cipher, config.read_pool_size).await?;
+        storage.verify_encryption_state().await?;
+        Ok(storage)
+    }
+
+    async fn open_unchecked(
+        sqlite_path: &str,
+        cipher: Option<EncryptedColumn>,
+        read_pool_size: u32,
+    ) -> Result<Self> {
+        let uri = normalize_sqlite_uri(sqlite_path);
         let options = SqliteConnectOptions::from_str(&uri)
-            .with_context(|| format!("invalid sqlite URI: {}", uri))?
-            .create_if_missing(true);
+            .with_context(|| format!("invalid sqlite URI: {}", uri))?;
+        let database_path = options.get_filename().to_path_buf();
 
+        // The write connection is never closed for idleness: the last connection to close
+        // removes the WAL, and read-only connections cannot recreate it.
         let pool = SqlitePoolOptions::new()
-            .max_connections(5)
-            .connect_with(options)
+            .min_connections(1)
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect_with(
+                options
+                    .clone()
+                    .create_if_missing(true)
+                    .journal_mode(SqliteJournalMode::Wal)
+                    .pragma("journal_size_limit", WAL_SIZE_LIMIT_BYTES.to_string()),
+            )
             .await
             .context("failed to connect sqlite pool")?;
+        // Opened after the write pool so the database and its WAL exist.
+        let read_pool = SqlitePoolOptions::new()
+            .max_connections(read_pool_size.max(1))
+            .connect_with(
+                options
+                    .read_only(true)
+                    .busy_timeout(READ_BUSY_TIMEOUT)
+                    .pragma("query_only", "ON"),
+            )
+            .await
+            .context("failed to connect sqlite read pool")?;
 
-        let storage = Self { pool };
+        let storage = Self {
+            pool,
+            read_pool,
+            database_path,
+            cipher,
+            integrity: Arc::default(),
+            contract_version: Arc::default(),
+            events: Vec::new(),
+            payload_reads: Arc::default(),
+        };
         storage.migrate().await?;
         Ok(storage)
     }
 
-    pub fn pool(&self) -> &SqlitePool {
-        &self.pool
+    pub async fn ping(&self) -> Result<()> {
+        sqlx::query_scalar::<_, i64>("SELECT 1")
+            .fetch_one(&self.pool)
+            .await
+            .context("sqlite ping")?;
+        Ok(())
     }
 
-    pub async fn migrate(&self) -> Result<()> {
-        for statement in SCHEMA_SQL.split(';') {
-            let sql = statement.trim();
-            if sql.is_empty() {
-                continue;
-            }
-            sqlx::query(sql)
-                .execute(&self.pool)
-                .await
-                .with_context(|| format!("migration failed for statement: {sql}"))?;
+    // Waits for checked-out connections to come back and closes them all; any later query
+    // through this handle or its clones fails.
+    pub async fn close(&self) {
+        self.read_pool.close().await;
+        self.pool.close().await;
+    }
+
+    pub fn database_path(&self) -> &Path {
+        &self.database_path
+    }
+
+    pub fn pool_stats(&self) -> PoolStats {
+        let mut wal_path = self.database_path.clone().into_os_string();
+        wal_path.push("-wal");
+        PoolStats {
+            read: pool_usage(&self.read_pool),
+            write: pool_usage(&self.pool),
+            wal_bytes: std::fs::metadata(wal_path).map_or(0, |meta| meta.len()),
         }
-        info!("retasync sqlite schema ready");
-        Ok(())
     }
 
-    pub async fn create_job(&self, operation: &str, payload: Value) -> Result<JobRecord> {
-        let now = Utc::now().to_rfc3339();
-        let job_id = Uuid::now_v7().to_string();
-        let payload_json = serde_json::to_string(&payload).context("serialize job payload")?;
+    pub async fn pending_migrations(sqlite_path: &str) -> Result<Vec<String>> {
+        let uri = normalize_sqlite_uri(sqlite_path);
+        let options = SqliteConnectOptions::from_str(&uri)
+            .with_context(|| format!("invalid sqlite URI: {}", uri))?
+            .read_only(true);
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect_with(options)
+            .await
+            .context("failed to open sqlite database")?;
 
-        sqlx::query(
-            "INSERT INTO jobs(job_id, operation, status, payload_json, submitted_at, updated_at) VALUES (?, ?, ?, ?, ?, ?)",
+        let existing = sqlx::query_scalar::<_, String>(
+            "SELECT name FROM sqlite_master WHERE type = 'table'",
         )
-        .bind(&job_id)
-        .bind(operation)
-        .bind("queued")
-        .bind(&payload_json)
-        .bind(&now)
-        .bind(&now)
-        .execute(&self.pool)
+        .fetch_all(&pool)
         .await
-        .context("insert job")?;
+        .context("query sqlite tables")?;
 
-        self.get_job(&job_id)
-            .await?
-            .context("job missing after insert")
+        let mut pending: Vec<String> = schema_tables()
+            .into_iter()
+            .filter(|table| !existing.contains(table))
+            .collect();
+        for (table, column, _) in ADDED_COLUMNS {
+            if existing.iter().any(|name| name == table)
+                && !table_has_column(&pool, table, column).await?
+            {
+                pending.push(format!("{table}.{column}"));
+            }
+        }
+        pool.close().await;
+        Ok(pending)
     }
 
-    pub async fn update_job_status(
-        &self,
-        job_id: &str,
-        status: &str,
-        failure_reason: Option<&str>,
-    ) -> Result<()> {
-        let now = Utc::now().to_rfc3339();
-        sqlx::query("UPDATE jobs SET status = ?, updated_at = ?, failure_reason = ? WHERE job_id = ?")
-            .bind(status)
-            .bind(now)
-            .bind(failure_reason)
-            .bind(job_id)
-            .execute(&self.pool)
-            .await
-            .with_context(|| format!("update job status for {job_id}"))?;
+    pub fn with_contract_version(mut self, version: Option<&str>) -> Self {
+        self.contract_version = Arc::new(std::sync::RwLock::new(version.map(str::to_string)));
+        self
+    }
 
-        Ok(())
+    // A handle whose transactions also record `event_type` in the feed, so the event commits or
+    // rolls back with the change the call makes. A job status event names its job in `job_id`
+    // and becomes the payload of the feed row the status change writes, so a call that leaves
+    // the status alone records nothing; a conditional write that matches nothing records no
+    // other event either. Chained calls record every event, in order. Meant for one call;
+    // every transaction run through the handle records the events again.
+    pub fn with_event(&self, event_type: &str, data: Value) -> Self {
+        let mut handle = self.clone();
+        handle.events.push(Arc::new(PendingEvent {
+            event_type: event_type.to_string(),
+            data,
+        }));
+        handle
     }
 
-    pub async fn insert_job_result(&self, job_id: &str, result: Value) -> Result<()> {
-        let completed_at = Utc::now().to_rfc3339();
-        let result_json = serde_json::to_string(&result).context("serialize job result")?;
+    pub fn set_contract_version(&self, version: Option<&str>) {
+        *self
+            .contract_version
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = version.map(str::to_string);
+    }
 
-        sqlx::query(
-            "INSERT INTO job_results(job_id, result_json, completed_at) VALUES (?, ?, ?) ON CONFLICT(job_id) DO UPDATE SET result_json = excluded.result_json, completed_at = excluded.completed_at",
-        )
-        .bind(job_id)
-        .bind(result_json)
-        .bind(completed_at)
-        .execute(&self.pool)
-        .await
-        .with_context(|| format!("insert job result for {job_id}"))?;
+    pub fn contract_version(&self) -> Option<String> {
+        self.contract_version
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.cipher.is_some()
+    }
 
+    async fn verify_encryption_state(&self) -> Result<()> {
+        let canary = self.read_encryption_canary().await?;
+        match (&self.cipher, canary) {
+            (Some(cipher), Some(stored)) => {
+                let matches = cipher
+                    .decrypt(&stored)
+                    .map(|value| value == ENCRYPTION_CANARY_VALUE)
+                    .unwrap_or(false);
+                if !matches {
+                    return Err(StorageError::Encryption(
+                        "storage encryption key does not match this database".to_string(),
+                    ));
+                }
+            }
+            (Some(cipher), None) => {
+                if self.count_plaintext_rows().await? > 0 {
+                    return Err(StorageError::Encryption(
+                        "database contains unencrypted rows; run `retasyncd encrypt-db` before enabling storage.encryption_key_path".to_string(),
+                    ));
+                }
+                self.write_encryption_canary(cipher).await?;
+            }
+            (None, Some(_)) => {
+                return Err(StorageError::Encryption(
+                    "database is encrypted; configure storage.encryption_key_path to open it"
+                        .to_string(),
+                ));
+            }
+            (None, None) => {}
+        }
         Ok(())
     }
 
-    pub async fn get_job(&self, job_id: &str) -> Result<Option<JobRecord>> {
-        sqlx::query_as::<_, JobRecord>(
-            "SELECT job_id, operation, status, payload_json, submitted_at, updated_at, failure_reason FROM jobs WHERE job_id = ?",
-        )
-        .bind(job_id)
-        .fetch_optional(&self.pool)
-        .await
-        .with_context(|| format!("query job {job_id}"))
+    async fn read_encryption_canary(&self) -> Result<Option<String>> {
+        sqlx::query_scalar::<_, String>("SELECT value FROM storage_meta WHERE key = ?")
+            .bind(ENCRYPTION_CANARY_KEY)
+            .fetch_optional(&self.read_pool)
+            .await
+            .context("query encryption canary")
     }
 
-    pub async fn get_job_result(&self, job_id: &str) -> Result<Option<JobResultRecord>> {
-        sqlx::query_as::<_, JobResultRecord>(
-            "SELECT job_id, result_json, completed_at FROM job_results WHERE job_id = ?",
-        )
-        .bind(job_id)
-        .fetch_optional(&self.pool)
-        .await
-        .with_context(|| format!("query job result {job_id}"))
+    async fn write_encryption_canary(&self, cipher: &EncryptedColumn) -> Result<()> {
+        write_encryption_canary(&self.pool, cipher).await
     }
 
-    pub async fn list_cached_events(&self, limit: i64) -> Result<Vec<Value>> {
-        let rows = sqlx::query_scalar::<_, String>(
-            "SELECT payload_json FROM cached_events ORDER BY received_at DESC LIMIT ?",
-        )
-        .bind(limit)
-        .fetch_all(&self.pool)
-        .await
-        .context("query cached events")?;
+    async fn count_plaintext_rows(&self) -> Result<i64> {
+        let mut total = 0;
+        for (table, _, column) in ENCRYPTED_COLUMNS {
+            let sql = format!(
+                "SELECT COUNT(*) FROM {table} WHERE {column} IS NOT NULL AND substr({column}, 1, length(?1)) <> ?1"
+            );
+            let count = sqlx::query_scalar::<_, i64>(&sql)
+                .bind(CIPHERTEXT_PREFIX)
+                .fetch_one(&self.read_pool)
+                .await
+                .with_context(|| format!("count plaintext rows in {table}"))?;
+            total += count;
+        }
+        Ok(total)
+    }
 
-        rows.into_iter()
-            .map(|row| serde_json::from_str::<Value>(&row).context("parse cached event payload"))
-            .collect()
+    pub async fn encrypt_database(sqlite_path: &str, key_path: &str) -> Result<u64> {
+        let cipher = EncryptedColumn::from_key_file(Path::new(key_path))?;
+        let storage = Self::open_unchecked(sqlite_path, None, 1).await?;
+        if storage.read_encryption_canary().await?.is_some() {
+            return Err(StorageError::Encryption(
+                "database is already encrypted; use `retasyncd rotate-db-key` to change keys"
+                    .to_string(),
+            ));
+        }
+
+        let rewritten = storage.reencrypt_columns(None, &cipher).await?;
+        info!(rows = rewritten, "retasync sqlite database encrypted");
+        Ok(rewritten)
     }
 
-    pub async fn list_cached_messages(&self, limit: i64) -> Result<Vec<Value>> {
-        let rows = sqlx::query_scalar::<_, String>(
-            "SELECT payload_json FROM cached_messages ORDER BY received_at DESC LIMIT ?",
-        )
-        .bind(limit)
-        .fetch_all(&self.pool)
-        .await
-        .context("query cached messages")?;
+    pub async fn rotate_encryption_key(
+        sqlite_path: &str,
+        old_key_path: &str,
+        new_key_path: &str,
+    ) -> Result<u64> {
+        let old_cipher = EncryptedColumn::from_key_file(Path::new(old_key_path))?;
+        let new_cipher = EncryptedColumn::from_key_file(Path::new(new_key_path))?;
+        let storage = Self::open_unchecked(sqlite_path, Some(old_cipher.clone()), 1).await?;
+        if storage.read_encryption_canary().await?.is_none() {
+            return Err(StorageError::Encryption(
+                "database is not encrypted; run `retasyncd encrypt-db` first".to_string(),
+            ));
+        }
+        storage.verify_encryption_state().await?;
 
-        rows.into_iter()
-            .map(|row| serde_json::from_str::<Value>(&row).context("parse cached message payload"))
-            .collect()
+        let rewritten = storage
+            .reencrypt_columns(Some(&old_cipher), &new_cipher)
+            .await?;
+        info!(rows = rewritten, "retasync sqlite encryption key rotated");
+        Ok(rewritten)
     }
 
-    pub async fn list_allowlist(&self) -> Result<Vec<String>> {
-        sqlx::query_scalar::<_, String>(
-            "SELECT identity_hash FROM acl_allowlist ORDER BY identity_hash ASC",
+    // Rewrites every encrypted column and the canary in one transaction, so an interrupted run
+    // leaves the database entirely under the old key and can simply be started again.
+    async fn reencrypt_columns(
+        &self,
+        old_cipher: Option<&EncryptedColumn>,
+        new_cipher: &EncryptedColumn,
+    ) -> Result<u64> {
+        let mut tx = self.pool.begin().await.context("begin re-encryption")?;
+        let mut rewritten = 0;
+        for (table, key_column, column) in ENCRYPTED_COLUMNS {
+            let select_sql = format!(
+                "SELECT {key_column}, {column} FROM {table} WHERE {column} IS NOT NULL AND {key_column} > ? ORDER BY {key_column} ASC LIMIT ?"
+            );
+            let update_sql = format!("UPDATE {table} SET {column} = ? WHERE {key_column} = ?");
+            let mut cursor = String::new();
+
+            loop {
+                let rows = sqlx::query_as::<_, (String, String)>(&select_sql)
+                    .bind(&cursor)
+                    .bind(REENCRYPT_BATCH_SIZE)
+                    .fetch_all(&mut *tx)
+                    .await
+                    .with_context(|| format!("select {table} batch for re-encryption"))?;
+                let Some((last_key, _)) = rows.last() else {
+                    break;
+                };
+                cursor = last_key.clone();
+
+                for (row_key, stored) in &rows {
+                    let plaintext = open(old_cipher, stored).map_err(|err| err.in_table(table))?;
+                    sqlx::query(&update_sql)
+                        .bind(new_cipher.encrypt(&plaintext)?)
+                        .bind(row_key)
+                        .execute(&mut *tx)
+                        .await
+                        .with_context(|| format!("rewrite {table}.{column} for {row_key}"))?;
+                    rewritten += 1;
+                }
+            }
+        }
+        write_encryption_canary(&mut *tx, new_cipher).await?;
+        tx.commit().await.context("commit re-encryption")?;
+        Ok(rewritten)
+    }
+
+    fn seal(&self, plaintext: &str) -> Result<String> {
+        seal(self.cipher.as_ref(), plaintext)
+    }
+
+    fn open(&self, stored: &str) -> Result<String> {
+        open(self.cipher.as_ref(), stored)
+    }
+
+    pub fn pool(&self) -> &SqlitePool {
+        &self.pool
+    }
+
+    pub async fn migrate(&self) -> Result<()> {
+        for statement in SCHEMA_SQL.split(';') {
+            let sql = statement.trim();
+            if sql.is_empty() {
+                continue;
+            }
+            sqlx::query(sql)
+                .execute(&self.pool)
+                .await
+                .with_context(|| format!("migration failed for statement: {sql}"))?;
+        }
+        for (table, column, definition) in ADDED_COLUMNS {
+            if !table_has_column(&self.pool, table, column).await? {
+                sqlx::query(&format!("ALTER TABLE {table} ADD COLUMN {column} {definition}"))
+                    .execute(&self.pool)
+                    .await
+                    .with_context(|| format!("add column {table}.{column}"))?;
+            }
+        }
+        self.index_idempotency_keys().await?;
+        for (name, trigger) in FEED_TRIGGERS {
+            sqlx::query(&format!("DROP TRIGGER IF EXISTS {name}"))
+                .execute(&self.pool)
+                .await
+                .context("drop event feed trigger")?;
+            sqlx::query(trigger)
+                .execute(&self.pool)
+                .await
+                .context("create event feed trigger")?;
+        }
+        // A database from before events were published from the feed starts at its head rather
+        // than replaying its history to live subscribers.
+        sqlx::query(
+            "INSERT OR IGNORE INTO storage_meta(key, value) SELECT ?, CAST(COALESCE((SELECT seq FROM sqlite_sequence WHERE name = 'event_feed'), 0) AS TEXT)",
         )
-        .fetch_all(&self.pool)
+        .bind(FEED_PUBLISHED_THROUGH_KEY)
+        .execute(&self.pool)
         .await
-        .context("query allowlist")
+        .context("initialize event feed publish point")?;
+        self.canonicalize_timestamps().await?;
+        self.normalize_identity_hashes().await?;
+        self.fill_payload_digests().await?;
+        info!("retasync sqlite schema ready");
+        Ok(())
     }
 
-    pub async fn add_allowlist(&self, identity_hash: &str, note: Option<&str>) -> Result<()> {
-        let now = Utc::now().to_rfc3339();
+    // One job per idempotency key. A database from before the index keeps each key on the first
+    // job that used it.
+    async fn index_idempotency_keys(&self) -> Result<()> {
+        let indexed = sqlx::query_scalar::<_, String>(
+            "SELECT name FROM sqlite_master WHERE type = 'index' AND name = 'idx_jobs_idempotency_key'",
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .context("query idempotency key index")?;
+        if indexed.is_some() {
+            return Ok(());
+        }
+        let mut tx = self.pool.begin().await.context("begin idempotency key index")?;
         sqlx::query(
-            "INSERT INTO acl_allowlist(identity_hash, note, created_at) VALUES (?, ?, ?) ON CONFLICT(identity_hash) DO UPDATE SET note = excluded.note",
+            "UPDATE jobs SET idempotency_key = NULL WHERE idempotency_key IS NOT NULL AND rowid NOT IN (SELECT MIN(rowid) FROM jobs WHERE idempotency_key IS NOT NULL GROUP BY idempotency_key)",
         )
-        .bind(identity_hash)
-        .bind(note)
-        .bind(now)
-        .execute(&self.pool)
+        .execute(&mut *tx)
         .await
-        .with_context(|| format!("insert allowlist identity {identity_hash}"))?;
+        .context("clear repeated idempotency keys")?;
+        sqlx::query(
+            "CREATE UNIQUE INDEX idx_jobs_idempotency_key ON jobs(idempotency_key) WHERE idempotency_key IS NOT NULL",
+        )
+        .execute(&mut *tx)
+        .await
+        .context("create idempotency key index")?;
+        tx.commit().await.context("commit idempotency key index")?;
         Ok(())
     }
 
-    pub async fn delete_allowlist(&self, identity_hash: &str) -> Result<bool> {
-        let result = sqlx::query("DELETE FROM acl_allowlist WHERE identity_hash = ?")
-            .bind(identity_hash)
-            .execute(&self.pool)
+    // Rewrites timestamps stored before every write went through `CanonicalTimestamp`, which
+    // mixed offsets and precisions and so did not sort as text. Runs once per database.
+    async fn canonicalize_timestamps(&self) -> Result<()> {
+        let done = sqlx::query_scalar::<_, String>("SELECT value FROM storage_meta WHERE key = ?")
+            .bind(TIMESTAMPS_CANONICAL_KEY)
+            .fetch_optional(&self.pool)
             .await
-            .with_context(|| format!("delete allowlist identity {identity_hash}"))?;
-        Ok(result.rows_affected() > 0)
+            .context("query timestamp migration marker")?;
+        if done.is_some() {
+            return Ok(());
+        }
+        let mut tx = self.pool.begin().await.context("begin timestamp migration")?;
+        let mut rewritten = 0;
+        for (table, column) in TIMESTAMP_COLUMNS {
+            let rows = sqlx::query_as::<_, (i64, String)>(&format!(
+                "SELECT rowid, {column} FROM {table} WHERE {column} IS NOT NULL AND {column} NOT GLOB ?"
+            ))
+            .bind(CANONICAL_GLOB)
+            .fetch_all(&mut *tx)
+            .await
+            .with_context(|| format!("scan {table}.{column} timestamps"))?;
+            for (rowid, raw) in rows {
+                let Ok(canonical) = CanonicalTimestamp::parse(&raw) else {
+                    warn!(table, column, rowid, value = %raw, "leaving unparseable timestamp");
+                    continue;
+                };
+                // Two legacy spellings of one instant can collide on a key; the later row wins.
+                sqlx::query(&format!("UPDATE OR REPLACE {table} SET {column} = ? WHERE rowid = ?"))
+                    .bind(canonical)
+                    .bind(rowid)
+                    .execute(&mut *tx)
+                    .await
+                    .with_context(|| format!("rewrite {table}.{column} timestamp"))?;
+                rewritten += 1;
+            }
+        }
+        sqlx::query(
+            "INSERT INTO storage_meta(key, value) VALUES (?, ?) ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+        )
+        .bind(TIMESTAMPS_CANONICAL_KEY)
+        .bind(CanonicalTimestamp::now())
+        .execute(&mut *tx)
+        .await
+        .context("write timestamp migration marker")?;
+        tx.commit().await.context("commit timestamp migration")?;
+        if rewritten > 0 {
+            info!(rewritten, "normalized legacy timestamps");
+        }
+        Ok(())
     }
 
-    pub async fn append_node_config_revision(&self, config_json: &str) -> Result<NodeConfigRevision> {
-        let now = Utc::now().to_rfc3339();
-        sqlx::query("INSERT INTO node_config_revisions(config_json, created_at) VALUES (?, ?)")
-            .bind(config_json)
-            .bind(&now)
-            .execute(&self.pool)
+    // Digests payloads stored before their size and hash were kept. A payload that does not
+    // open is left without one.
+    async fn fill_payload_digests(&self) -> Result<()> {
+        let mut filled = 0;
+        for (table, column) in DIGESTED_PAYLOADS {
+            let rows = sqlx::query_as::<_, (i64, String)>(&format!(
+                "SELECT rowid, {column} FROM {table} WHERE payload_sha256 IS NULL"
+            ))
+            .fetch_all(&self.pool)
             .await
-            .context("insert node config revision")?;
+            .with_context(|| format!("scan undigested {table} payloads"))?;
+            for (rowid, stored) in rows {
+                let Ok(payload_json) = self.open(&stored) else {
+                    warn!(table, rowid, "leaving undigested payload that does not open");
+                    continue;
+                };
+                let (payload_bytes, payload_sha256) = payload_digest(&payload_json);
+                sqlx::query(&format!(
+                    "UPDATE {table} SET payload_bytes = ?, payload_sha256 = ? WHERE rowid = ?"
+                ))
+                .bind(payload_bytes)
+                .bind(payload_sha256)
+                .bind(rowid)
+                .execute(&self.pool)
+                .await
+                .with_context(|| format!("digest {table} payload"))?;
+                filled += 1;
+            }
+        }
+        if filled > 0 {
+            info!(filled, "digested stored payloads");
+        }
+        Ok(())
+    }
 
-        sqlx::query_as::<_, NodeConfigRevision>(
-            "SELECT revision_id, config_json, created_at FROM node_config_revisions ORDER BY revision_id DESC LIMIT 1",
+    // Trims and lowercases identity hashes stored before they were validated, so entries that
+    // differ only in case or whitespace match at enforcement. A variant whose normalized form is
+    // already stored is merged into it; one that is not a hash at all is kept and recorded in
+    // `identity_hash_issues`. Runs once per database.
+    async fn normalize_identity_hashes(&self) -> Result<()> {
+        let done = sqlx::query_scalar::<_, String>("SELECT value FROM storage_meta WHERE key = ?")
+            .bind(IDENTITY_HASHES_NORMALIZED_KEY)
+            .fetch_optional(&self.pool)
+            .await
+            .context("query identity hash migration marker")?;
+        if done.is_some() {
+            return Ok(());
+        }
+        let mut tx = self.pool.begin().await.context("begin identity hash migration")?;
+        let (mut rewritten, mut merged, mut invalid) = (0, 0, 0);
+        for table in IDENTITY_HASH_TABLES {
+            let rows = sqlx::query_as::<_, (i64, String)>(&format!(
+                "SELECT rowid, identity_hash FROM {table} ORDER BY rowid ASC"
+            ))
+            .fetch_all(&mut *tx)
+            .await
+            .with_context(|| format!("scan {table} identity hashes"))?;
+            for (rowid, raw) in rows {
+                let hash = match IdentityHash::parse(&raw) {
+                    Ok(hash) if hash.as_str() == raw => continue,
+                    Ok(hash) => hash,
+                    Err(err) => {
+                        warn!(table, value = %raw, defect = err.code(), "identity is not a hash");
+                        sqlx::query(
+                            "INSERT OR REPLACE INTO identity_hash_issues(table_name, identity_hash, defect, detected_at) VALUES (?, ?, ?, ?)",
+                        )
+                        .bind(table)
+                        .bind(&raw)
+                        .bind(err.code())
+                        .bind(CanonicalTimestamp::now())
+                        .execute(&mut *tx)
+                        .await
+                        .with_context(|| format!("record {table} identity issue"))?;
+                        invalid += 1;
+                        continue;
+                    }
+                };
+                let updated = sqlx::query(&format!(
+                    "UPDATE OR IGNORE {table} SET identity_hash = ? WHERE rowid = ?"
+                ))
+                .bind(hash.as_str())
+                .bind(rowid)
+                .execute(&mut *tx)
+                .await
+                .with_context(|| format!("normalize {table} identity hash"))?;
+                if updated.rows_affected() > 0 {
+                    rewritten += 1;
+                    continue;
+                }
+                // The normalized entry already exists and is the one enforcement looks up.
+                sqlx::query(&format!("DELETE FROM {table} WHERE rowid = ?"))
+                    .bind(rowid)
+                    .execute(&mut *tx)
+                    .await
+                    .with_context(|| format!("merge {table} identity hash"))?;
+                merged += 1;
+            }
+        }
+        sqlx::query(
+            "INSERT INTO storage_meta(key, value) VALUES (?, ?) ON CONFLICT(key) DO UPDATE SET value = excluded.value",
         )
-        .fetch_one(&self.pool)
+        .bind(IDENTITY_HASHES_NORMALIZED_KEY)
+        .bind(CanonicalTimestamp::now())
+        .execute(&mut *tx)
         .await
-        .context("query latest node config revision")
+        .context("write identity hash migration marker")?;
+        tx.commit().await.context("commit identity hash migration")?;
+        if rewritten + merged + invalid > 0 {
+            info!(rewritten, merged, invalid, "normalized legacy identity hashes");
+        }
+        Ok(())
     }
 
-    pub async fn get_transfer(&self, transfer_id: &str) -> Result<Option<TransferRecord>> {
-        sqlx::query_as::<_, TransferRecord>(
-            "SELECT transfer_id, status, metadata_json, submitted_at, updated_at, failure_reason FROM transfers WHERE transfer_id = ?",
+    // Identities the normalization migration could not repair that are still stored.
+    pub async fn identity_hash_issues(&self) -> Result<Vec<IdentityHashIssue>> {
+        sqlx::query_as::<_, IdentityHashIssue>(
+            "SELECT table_name, identity_hash, defect, detected_at FROM identity_hash_issues WHERE (table_name = 'acl_allowlist' AND identity_hash IN (SELECT identity_hash FROM acl_allowlist)) OR (table_name = 'acl_denylist' AND identity_hash IN (SELECT identity_hash FROM acl_denylist)) ORDER BY table_name ASC, identity_hash ASC",
         )
-        .bind(transfer_id)
-        .fetch_optional(&self.pool)
+        .fetch_all(&self.read_pool)
         .await
-        .with_context(|| format!("query transfer {transfer_id}"))
+        .context("query identity hash issues")
     }
 
-    pub async fn create_transfer(&self, metadata: Value) -> Result<TransferRecord> {
-        let transfer_id = Uuid::now_v7().to_string();
-        let now = Utc::now().to_rfc3339();
-        let metadata_json = serde_json::to_string(&metadata).context("serialize transfer metadata")?;
+    // Runs `work` inside one sqlx transaction: it commits only if `work` succeeds, so callers
+    // never observe half of a multi-record change.
+    pub async fn with_tx<T, F>(&self, work: F) -> Result<T>
+    where
+        T: Send,
+        F: for<'t> FnOnce(&'t mut StorageTx) -> TxFuture<'t, T> + Send,
+    {
+        let mut tx = StorageTx {
+            tx: self.pool.begin().await.context("begin transaction")?,
+            cipher: self.cipher.clone(),
+            contract_version: self.contract_version(),
+            staged_job_events: false,
+            event_withheld: false,
+        };
+        for event in &self.events {
+            if let Some(job_id) = event.status_of_job() {
+                tx.stage_job_event(job_id, &event.data).await?;
+            }
+        }
+        let value = work(&mut tx).await?;
+        if !tx.event_withheld {
+            for event in self.events.iter().filter(|event| event.status_of_job().is_none()) {
+                tx.append_feed_event(&event.event_type, &event.data).await?;
+            }
+        }
+        // Left over for a job whose status did not change.
+        if tx.staged_job_events {
+            sqlx::query("DELETE FROM job_event_details")
+                .execute(&mut *tx.tx)
+                .await
+                .context("clear staged job events")?;
+        }
+        bump_write_sequence(&mut *tx.tx).await?;
+        tx.tx.commit().await.context("commit transaction")?;
+        Ok(value)
+    }
 
-        sqlx::query(
-            "INSERT INTO transfers(transfer_id, status, metadata_json, submitted_at, updated_at) VALUES (?, ?, ?, ?, ?)",
+    // The last write sequence this node's readers can see. Every `with_tx` commit advances it
+    // by one, so a client holding a sequence from a write knows a read at or past it includes
+    // that write.
+    pub async fn write_sequence(&self) -> Result<i64> {
+        Ok(
+            sqlx::query_scalar::<_, i64>("SELECT seq FROM write_sequence WHERE id = 1")
+                .fetch_optional(&self.read_pool)
+                .await
+                .context("query write sequence")?
+                .unwrap_or(0),
         )
-        .bind(&transfer_id)
-        .bind("queued")
-        .bind(&metadata_json)
-        .bind(&now)
-        .bind(&now)
-        .execute(&self.pool)
-        .await
-        .context("insert transfer")?;
+    }
 
-        self.get_transfer(&transfer_id)
-            .await?
-            .context("transfer missing after insert")
+    pub async fn create_job(&self, operation: &str, payload: Value) -> Result<JobRecord> {
+        let operation = operation.to_string();
+        self.with_tx(move |tx| Box::pin(async move { tx.create_job(&operation, payload).await }))
+            .await
     }
 
-    pub async fn update_transfer_status(
+    pub async fn update_job_status(
         &self,
-        transfer_id: &str,
+        job_id: &str,
         status: &str,
         failure_reason: Option<&str>,
     ) -> Result<()> {
-        let now = Utc::now().to_rfc3339();
-        sqlx::query("UPDATE transfers SET status = ?, updated_at = ?, failure_reason = ? WHERE transfer_id = ?")
-            .bind(status)
-            .bind(now)
-            .bind(failure_reason)
-            .bind(transfer_id)
-            .execute(&self.pool)
+        let (job_id, status) = (job_id.to_string(), status.to_string());
+        let failure_reason = failure_reason.map(str::to_string);
+        self.with_tx(move |tx| {
+            Box::pin(async move {
+                tx.update_job_status(&job_id, &status, failure_reason.as_deref())
+                    .await
+            })
+        })
+        .await
+    }
+
+    pub async fn set_job_dispatch(&self, job_id: &str, dispatch: &Value) -> Result<()> {
+        let (job_id, dispatch) = (job_id.to_string(), dispatch.clone());
+        self.with_tx(move |tx| {
+            Box::pin(async move { tx.set_job_dispatch(&job_id, &dispatch).await })
+        })
+        .await
+    }
+
+    pub async fn set_job_requested_operation(&self, job_id: &str, operation: &str) -> Result<()> {
+        let (job_id, operation) = (job_id.to_string(), operation.to_string());
+        self.with_tx(move |tx| {
+            Box::pin(async move { tx.set_job_requested_operation(&job_id, &operation).await })
+        })
+        .await
+    }
+
+    pub async fn find_job_by_idempotency_key(&self, key: &str) -> Result<Option<String>> {
+        fetch_job_id_by_idempotency_key(&self.read_pool, key).await
+    }
+
+    pub async fn insert_job_result(&self, job_id: &str, result: Value) -> Result<()> {
+        let job_id = job_id.to_string();
+        self.with_tx(move |tx| Box::pin(async move { tx.insert_job_result(&job_id, result).await }))
             .await
-        .with_context(|| format!("update transfer {transfer_id}"))?;
-        Ok(())
     }
 
-    pub async fn purge_expired(
+    // Moves a job that has not finished yet to its final status, storing `result` with it;
+    // returns false, writing nothing, when another writer finished the job first.
+    pub async fn finish_job(
         &self,
-        job_retention_hours: i64,
-        cache_retention_hours: i64,
-        transfer_retention_days: i64,
-    ) -> Result<()> {
-        sqlx::query(
-            "DELETE FROM job_results WHERE completed_at < datetime('now', '-' || ? || ' hours')",
+        job_id: &str,
+        result: Option<Value>,
+        status: &str,
+        failure_reason: Option<&str>,
+    ) -> Result<bool> {
+        let (job_id, status) = (job_id.to_string(), status.to_string());
+        let failure_reason = failure_reason.map(str::to_string);
+        self.with_tx(move |tx| {
+            Box::pin(async move {
+                if !tx
+                    .finish_job(&job_id, &status, failure_reason.as_deref())
+                    .await?
+                {
+                    return Ok(false);
+                }
+                if let Some(result) = result {
+                    tx.insert_job_result(&job_id, result).await?;
+                }
+                Ok(true)
+            })
+        })
+        .await
+    }
+
+    // An attachment that could not be sent fails together with the ones still queued behind
+    // it, so the job can settle instead of waiting on transfers nobody will send.
+    pub async fn fail_job_transfers(
+        &self,
+        job_id: &str,
+        transfer_id: &str,
+        reason: &str,
+    ) -> Result<u64> {
+        let (job_id, transfer_id) = (job_id.to_string(), transfer_id.to_string());
+        let reason = reason.to_string();
+        self.with_tx(move |tx| {
+            Box::pin(async move {
+                let failed = tx.fail_job_transfer(&job_id, &transfer_id, &reason).await?;
+                Ok(failed + tx.fail_queued_transfers(&job_id, "job_attachment_failed").await?)
+            })
+        })
+        .await
+    }
+
+    // Linked transfers that never started are failed along with the job.
+    pub async fn fail_job(&self, job_id: &str, reason: &str) -> Result<u64> {
+        let (job_id, reason) = (job_id.to_string(), reason.to_string());
+        self.with_tx(move |tx| {
+            Box::pin(async move {
+                tx.update_job_status(&job_id, "failed", Some(&reason)).await?;
+                tx.fail_queued_transfers(&job_id, "job_failed").await
+            })
+        })
+        .await
+    }
+
+    // Only a job that has not finished can be cancelled; its queued transfers go with it.
+    pub async fn cancel_job(&self, job_id: &str) -> Result<bool> {
+        let job_id = job_id.to_string();
+        self.with_tx(move |tx| {
+            Box::pin(async move {
+                if !tx.cancel_job(&job_id).await? {
+                    return Ok(false);
+                }
+                tx.fail_queued_transfers(&job_id, "job_cancelled").await?;
+                Ok(true)
+            })
+        })
+        .await
+    }
+
+    pub async fn get_job(&self, job_id: &str) -> Result<Option<JobRecord>> {
+        let record = fetch_job(&self.read_pool, job_id).await?;
+        self.count_payload_reads(usize::from(record.is_some()));
+        record
+            .map(|record| open_job(self.cipher.as_ref(), record))
+            .transpose()
+    }
+
+    // The job without its payload, which is not selected.
+    pub async fn get_job_summary(&self, job_id: &str) -> Result<Option<JobSummary>> {
+        sqlx::query_as::<_, JobSummary>(
+            "SELECT job_id, operation, status, submitted_at, updated_at, failure_reason, dispatch_json, requested_operation, payload_bytes, payload_sha256 FROM jobs WHERE job_id = ?",
         )
-        .bind(job_retention_hours)
-        .execute(&self.pool)
+        .bind(job_id)
+        .fetch_optional(&self.read_pool)
         .await
-        .context("purge expired job_results")?;
+        .with_context(|| format!("query job summary {job_id}"))
+    }
 
-        sqlx::query(
-            "DELETE FROM jobs WHERE updated_at < datetime('now', '-' || ? || ' hours')",
+    pub fn payload_reads(&self) -> u64 {
+        self.payload_reads.load(Ordering::Relaxed)
+    }
+
+    fn count_payload_reads(&self, rows: usize) {
+        self.payload_reads.fetch_add(rows as u64, Ordering::Relaxed);
+    }
+
+    // Newest first, for dashboards that show what the node did last.
+    pub async fn list_recent_jobs(&self, limit: i64) -> Result<Vec<JobRecord>> {
+        let records = sqlx::query_as::<_, JobRecord>(
+            "SELECT job_id, operation, status, payload_json, submitted_at, updated_at, failure_reason, dispatch_json, requested_operation FROM jobs ORDER BY submitted_at DESC, job_id DESC LIMIT ?",
         )
-        .bind(job_retention_hours)
-        .execute(&self.pool)
+        .bind(limit)
+        .fetch_all(&self.read_pool)
         .await
-        .context("purge expired jobs")?;
+        .context("query recent jobs")?;
+        records
+            .into_iter()
+            .map(|record| open_job(self.cipher.as_ref(), record))
+            .collect()
+    }
 
-        sqlx::query(
-            "DELETE FROM cached_events WHERE received_at < datetime('now', '-' || ? || ' hours')",
+    // `None` for jobs recorded before sources were, as for unknown jobs.
+    pub async fn get_job_source(&self, job_id: &str) -> Result<Option<SubmissionSource>> {
+        sqlx::query_as::<_, SubmissionSource>(
+            "SELECT source_token AS token_label, source_addr AS remote_addr, source_client_id AS client_id FROM jobs WHERE job_id = ? AND source_token IS NOT NULL",
         )
-        .bind(cache_retention_hours)
-        .execute(&self.pool)
+        .bind(job_id)
+        .fetch_optional(&self.read_pool)
         .await
-        .context("purge expired cached_events")?;
+        .with_context(|| format!("query source of job {job_id}"))
+    }
 
-        sqlx::query(
-            "DELETE FROM cached_messages WHERE received_at < datetime('now', '-' || ? || ' hours')",
+    pub async fn get_transfer_source(&self, transfer_id: &str) -> Result<Option<SubmissionSource>> {
+        sqlx::query_as::<_, SubmissionSource>(
+            "SELECT source_token AS token_label, source_addr AS remote_addr, source_client_id AS client_id FROM transfers WHERE transfer_id = ? AND source_token IS NOT NULL",
         )
-        .bind(cache_retention_hours)
-        .execute(&self.pool)
+        .bind(transfer_id)
+        .fetch_optional(&self.read_pool)
         .await
-        .context("purge expired cached_messages")?;
+        .with_context(|| format!("query source of transfer {transfer_id}"))
+    }
+
+    // Activity per source for jobs and transfers submitted after `since`. Attachments count
+    // toward the bytes of their job's source but are not submissions of their own.
+    pub async fn list_client_activity(&self, since: &str) -> Result<Vec<ClientActivity>> {
+        let since = CanonicalTimestamp::parse(since)?;
+        sqlx::query_as::<_, ClientActivity>(
+            "SELECT source_token AS token_label, source_addr AS remote_addr, source_client_id AS client_id, SUM(submissions) AS submissions, SUM(failures) AS failures, SUM(bytes) AS bytes FROM (\
+             SELECT source_token, source_addr, source_client_id, 1 AS submissions, status = 'failed' AS failures, 0 AS bytes FROM jobs WHERE submitted_at > ? AND source_token IS NOT NULL \
+             UNION ALL SELECT t.source_token, t.source_addr, t.source_client_id, t.job_id IS NULL, t.job_id IS NULL AND t.status = 'failed', COALESCE(p.bytes_sent, 0) FROM transfers t LEFT JOIN transfer_progress p ON p.transfer_id = t.transfer_id WHERE t.submitted_at > ? AND t.source_token IS NOT NULL\
+             ) GROUP BY source_token, source_addr, source_client_id ORDER BY source_token, source_addr, source_client_id",
+        )
+        .bind(since)
+        .bind(since)
+        .fetch_all(&self.read_pool)
+        .await
+        .context("aggregate client activity")
+    }
 
+    // Counts submissions the rate limit refused, per client and hour.
+    pub async fn record_client_rejections(
+        &self,
+        client: &str,
+        bucket_start: &str,
+        rejected: i64,
+    ) -> Result<()> {
         sqlx::query(
-            "DELETE FROM transfers WHERE updated_at < datetime('now', '-' || ? || ' days')",
+            "INSERT INTO client_rejections(client, bucket_start, rejected) VALUES (?, ?, ?) ON CONFLICT(client, bucket_start) DO UPDATE SET rejected = rejected + excluded.rejected",
         )
-        .bind(transfer_retention_days)
+        .bind(client)
+        .bind(CanonicalTimestamp::parse(bucket_start)?)
+        .bind(rejected)
         .execute(&self.pool)
         .await
-        .context("purge expired transfers")?;
-
+        .with_context(|| format!("record rate limit rejections for {client}"))?;
         Ok(())
     }
-}
 
-fn normalize_sqlite_uri(raw: &str) -> String {
-    if raw.starts_with("sqlite:") {
-        raw.to_string()
-    } else {
-        format!("sqlite://{raw}")
+    // Rejections per client in buckets that started at or after `since`.
+    pub async fn list_client_rejections(&self, since: &str) -> Result<Vec<(String, i64)>> {
+        sqlx::query_as::<_, (String, i64)>(
+            "SELECT client, SUM(rejected) FROM client_rejections WHERE bucket_start >= ? GROUP BY client ORDER BY client",
+        )
+        .bind(CanonicalTimestamp::parse(since)?)
+        .fetch_all(&self.read_pool)
+        .await
+        .context("list rate limit rejections")
+    }
+
+    pub async fn purge_client_rejections(&self, through: &str) -> Result<u64> {
+        let purged = sqlx::query("DELETE FROM client_rejections WHERE bucket_start <= ?")
+            .bind(CanonicalTimestamp::parse(through)?)
+            .execute(&self.pool)
+            .await
+            .context("purge rate limit rejections")?;
+        Ok(purged.rows_affected())
+    }
+
+    // The label of whoever submitted the job; `None` for jobs recorded before submitters were.
+    pub async fn get_job_submitter(&self, job_id: &str) -> Result<Option<String>> {
+        let submitter = sqlx::query_scalar::<_, Option<String>>(
+            "SELECT submitted_by FROM jobs WHERE job_id = ?",
+        )
+        .bind(job_id)
+        .fetch_optional(&self.read_pool)
+        .await
+        .with_context(|| format!("query submitter of job {job_id}"))?;
+        Ok(submitter.flatten())
+    }
+
+    pub async fn get_labels(
+        &self,
+        subject_type: &str,
+        subject_id: &str,
+    ) -> Result<BTreeMap<String, String>> {
+        let rows = sqlx::query_as::<_, (String, String)>(
+            "SELECT key, value FROM labels WHERE subject_type = ? AND subject_id = ?",
+        )
+        .bind(subject_type)
+        .bind(subject_id)
+        .fetch_all(&self.read_pool)
+        .await
+        .with_context(|| format!("query labels of {subject_type} {subject_id}"))?;
+        Ok(rows.into_iter().collect())
+    }
+
+    // Adds `labels` to a subject, replacing the values of keys it already has.
+    pub async fn put_labels(
+        &self,
+        subject_type: &str,
+        subject_id: &str,
+        labels: &BTreeMap<String, String>,
+    ) -> Result<()> {
+        let (subject_type, subject_id) = (subject_type.to_string(), subject_id.to_string());
+        let labels = labels.clone();
+        self.with_tx(move |tx| {
+            Box::pin(async move { tx.put_labels(&subject_type, &subject_id, &labels).await })
+        })
+        .await
+    }
+
+    // Every key in use on subjects of `subject_type`, or on any subject, in key order.
+    pub async fn label_keys(&self, subject_type: Option<&str>) -> Result<Vec<LabelKey>> {
+        sqlx::query_as::<_, LabelKey>(
+            "SELECT key, COUNT(DISTINCT value) AS \"values\", COUNT(*) AS subjects FROM labels WHERE ?1 IS NULL OR subject_type = ?1 GROUP BY key ORDER BY key",
+        )
+        .bind(subject_type)
+        .fetch_all(&self.read_pool)
+        .await
+        .context("query label keys")
+    }
+
+    pub async fn add_job_dependency(&self, job_id: &str, depends_on: &str) -> Result<()> {
+        write_job_dependency(&self.pool, job_id, depends_on).await
+    }
+
+    // The jobs `job_id` waits on.
+    pub async fn list_job_dependencies(&self, job_id: &str) -> Result<Vec<JobDependency>> {
+        sqlx::query_as::<_, JobDependency>(
+            "SELECT j.job_id, j.status FROM job_dependencies d JOIN jobs j ON j.job_id = d.depends_on WHERE d.job_id = ? ORDER BY j.job_id",
+        )
+        .bind(job_id)
+        .fetch_all(&self.read_pool)
+        .await
+        .with_context(|| format!("query dependencies of job {job_id}"))
+    }
+
+    // The jobs waiting on `job_id`.
+    pub async fn list_job_dependents(&self, job_id: &str) -> Result<Vec<JobDependency>> {
+        sqlx::query_as::<_, JobDependency>(
+            "SELECT j.job_id, j.status FROM job_dependencies d JOIN jobs j ON j.job_id = d.job_id WHERE d.depends_on = ? ORDER BY j.job_id",
+        )
+        .bind(job_id)
+        .fetch_all(&self.read_pool)
+        .await
+        .with_context(|| format!("query dependents of job {job_id}"))
+    }
+
+    // Moves a job out of `waiting`; false when something else already has, so a job released
+    // by two parents finishing at once is only ever released once.
+    pub async fn settle_waiting_job(
+        &self,
+        job_id: &str,
+        status: &str,
+        failure_reason: Option<&str>,
+    ) -> Result<bool> {
+        let (job_id, status) = (job_id.to_string(), status.to_string());
+        let failure_reason = failure_reason.map(str::to_string);
+        self.with_tx(move |tx| {
+            Box::pin(async move {
+                tx.settle_waiting_job(&job_id, &status, failure_reason.as_deref())
+                    .await
+            })
+        })
+        .await
+    }
+
+    // Puts a job the circuit breaker parked back in the queue; false when it has left `deferred`
+    // already, for instance by being cancelled.
+    pub async fn requeue_deferred_job(&self, job_id: &str) -> Result<bool> {
+        let job_id = job_id.to_string();
+        self.with_tx(move |tx| Box::pin(async move { tx.requeue_deferred_job(&job_id).await }))
+            .await
+    }
+
+    // Parks a queued or deferred job in `held` until it is released; None, with nothing
+    // changed, when the job is in neither state. Returns the status the job was held from.
+    pub async fn hold_job(
+        &self,
+        job_id: &str,
+        held_by: &str,
+        reason: &str,
+    ) -> Result<Option<String>> {
+        let (job_id, held_by, reason) =
+            (job_id.to_string(), held_by.to_string(), reason.to_string());
+        self.with_tx(move |tx| {
+            Box::pin(async move { tx.hold_job(&job_id, &held_by, &reason).await })
+        })
+        .await
+    }
+
+    // Puts a held job back in the status it was held from, which it returns; None when the job
+    // is not held.
+    pub async fn release_job_hold(&self, job_id: &str) -> Result<Option<String>> {
+        let job_id = job_id.to_string();
+        self.with_tx(move |tx| Box::pin(async move { tx.release_job_hold(&job_id).await }))
+            .await
+    }
+
+    // Jobs still held, oldest hold first.
+    pub async fn list_job_holds(&self) -> Result<Vec<JobHold>> {
+        sqlx::query_as::<_, JobHold>(
+            "SELECT h.job_id, h.previous_status, h.held_by, h.reason, h.held_at FROM job_holds h JOIN jobs j ON j.job_id = h.job_id WHERE j.status = 'held' ORDER BY h.held_at ASC, h.job_id ASC",
+        )
+        .fetch_all(&self.read_pool)
+        .await
+        .context("query job holds")
+    }
+
+    // Oldest first.
+    pub async fn list_jobs_with_status(&self, status: &str, limit: i64) -> Result<Vec<JobRecord>> {
+        let records = sqlx::query_as::<_, JobRecord>(
+            "SELECT job_id, operation, status, payload_json, submitted_at, updated_at, failure_reason, dispatch_json, requested_operation FROM jobs WHERE status = ? ORDER BY submitted_at ASC, job_id ASC LIMIT ?",
+        )
+        .bind(status)
+        .bind(limit)
+        .fetch_all(&self.read_pool)
+        .await
+        .with_context(|| format!("list {status} jobs"))?;
+        records
+            .into_iter()
+            .map(|record| open_job(self.cipher.as_ref(), record))
+            .collect()
+    }
+
+    // Each chunk is a statement of its own, so no read transaction spans the export and WAL
+    // checkpoints are not held back by it. Updates keep a row's rowid, so a job is exported
+    // exactly once however often it changes meanwhile.
+    // Only jobs carrying every one of `labels` are exported.
+    pub async fn export_jobs(
+        &self,
+        after_rowid: i64,
+        limit: i64,
+        labels: &[(String, String)],
+    ) -> Result<JobExportChunk> {
+        let limit = limit.max(1);
+        let mut conn = self
+            .read_pool
+            .acquire()
+            .await
+            .context("acquire export connection")?;
+        set_busy_timeout(&mut conn, EXPORT_BUSY_TIMEOUT).await?;
+        let rows = export_job_rows(&mut conn, after_rowid, limit, labels).await;
+        set_busy_timeout(&mut conn, READ_BUSY_TIMEOUT).await?;
+        let (rows, mut labelled) = rows?;
+
+        let next_after = match rows.last() {
+            Some(last) if rows.len() as i64 == limit => Some(last.rowid),
+            _ => None,
+        };
+        let jobs = rows
+            .into_iter()
+            .map(|row| {
+                let source = row.source_token.map(|token_label| SubmissionSource {
+                    token_label,
+                    remote_addr: row.source_addr,
+                    client_id: row.source_client_id,
+                });
+                let labels = labelled.remove(&row.job.job_id).unwrap_or_default();
+                let job = open_job(self.cipher.as_ref(), row.job)?;
+                Ok(JobExport {
+                    job,
+                    source,
+                    labels,
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(JobExportChunk { jobs, next_after })
+    }
+
+    pub async fn get_job_result(&self, job_id: &str) -> Result<Option<JobResultRecord>> {
+        sqlx::query_as::<_, JobResultRecord>(
+            "SELECT job_id, result_json, completed_at FROM job_results WHERE job_id = ?",
+        )
+        .bind(job_id)
+        .fetch_optional(&self.read_pool)
+        .await
+        .with_context(|| format!("query job result {job_id}"))
+    }
+
+    pub async fn record_job_message(&self, message_id: &str, job_id: &str) -> Result<()> {
+        sqlx::query("INSERT INTO job_messages(message_id, job_id, sent_at) VALUES (?, ?, ?)")
+            .bind(message_id)
+            .bind(job_id)
+            .bind(CanonicalTimestamp::now())
+            .execute(&self.pool)
+            .await
+            .with_context(|| format!("insert job message {message_id}"))?;
+        Ok(())
+    }
+
+    // A single insert, so it is cheap enough to run around every bridge call.
+    pub async fn record_dispatch_attempt(&self, attempt: &DispatchAttempt) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO dispatch_attempts(message_id, job_id, destination_identity, operation, attempt, transport_planned, transport_used, started_at, finished_at, outcome, error_variant, error_detail, escalated) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(&attempt.message_id)
+        .bind(&attempt.job_id)
+        .bind(&attempt.destination_identity)
+        .bind(&attempt.operation)
+        .bind(attempt.attempt)
+        .bind(&attempt.transport_planned)
+        .bind(&attempt.transport_used)
+        .bind(&attempt.started_at)
+        .bind(&attempt.finished_at)
+        .bind(&attempt.outcome)
+        .bind(&attempt.error_variant)
+        .bind(&attempt.error_detail)
+        .bind(attempt.escalated)
+        .execute(&self.pool)
+        .await
+        .with_context(|| {
+            format!("record attempt {} of message {}", attempt.attempt, attempt.message_id)
+        })?;
+        Ok(())
+    }
+
+    // Oldest first, over every message the job was sent as.
+    pub async fn list_dispatch_attempts(&self, job_id: &str) -> Result<Vec<DispatchAttempt>> {
+        sqlx::query_as::<_, DispatchAttempt>(
+            "SELECT message_id, job_id, destination_identity, operation, attempt, transport_planned, transport_used, started_at, finished_at, outcome, error_variant, error_detail, escalated FROM dispatch_attempts WHERE job_id = ? ORDER BY started_at, rowid",
+        )
+        .bind(job_id)
+        .fetch_all(&self.read_pool)
+        .await
+        .with_context(|| format!("list dispatch attempts of job {job_id}"))
+    }
+
+    pub async fn dispatch_attempt_summary(&self, job_id: &str) -> Result<DispatchAttemptSummary> {
+        let attempts =
+            sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM dispatch_attempts WHERE job_id = ?")
+                .bind(job_id)
+                .fetch_one(&self.read_pool)
+                .await
+                .with_context(|| format!("count dispatch attempts of job {job_id}"))?;
+        let last_error = sqlx::query_as::<_, DispatchAttemptError>(
+            "SELECT attempt, error_variant AS variant, error_detail AS detail, finished_at FROM dispatch_attempts WHERE job_id = ? AND error_variant IS NOT NULL ORDER BY started_at DESC, rowid DESC LIMIT 1",
+        )
+        .bind(job_id)
+        .fetch_optional(&self.read_pool)
+        .await
+        .with_context(|| format!("find last dispatch error of job {job_id}"))?;
+        Ok(DispatchAttemptSummary {
+            attempts,
+            last_error,
+        })
+    }
+
+    // Oldest first; a job has more than one message once it was retried or escalated.
+    pub async fn list_job_messages(&self, job_id: &str) -> Result<Vec<String>> {
+        sqlx::query_scalar::<_, String>(
+            "SELECT message_id FROM job_messages WHERE job_id = ? ORDER BY sent_at, rowid",
+        )
+        .bind(job_id)
+        .fetch_all(&self.read_pool)
+        .await
+        .with_context(|| format!("query messages for job {job_id}"))
+    }
+
+    pub async fn job_for_message(&self, message_id: &str) -> Result<Option<String>> {
+        sqlx::query_scalar::<_, String>("SELECT job_id FROM job_messages WHERE message_id = ?")
+            .bind(message_id)
+            .fetch_optional(&self.read_pool)
+            .await
+            .with_context(|| format!("query job for message {message_id}"))
+    }
+
+    pub async fn save_job_trace(
+        &self,
+        job_id: &str,
+        hops: &Value,
+        truncated: bool,
+        returned_at: Option<&str>,
+    ) -> Result<()> {
+        let hops_json = serde_json::to_string(hops).context("serialize job trace")?;
+        sqlx::query(
+            "INSERT INTO job_traces(job_id, hops_json, truncated, returned_at) VALUES (?, ?, ?, ?) ON CONFLICT(job_id) DO UPDATE SET hops_json = excluded.hops_json, truncated = excluded.truncated, returned_at = excluded.returned_at",
+        )
+        .bind(job_id)
+        .bind(hops_json)
+        .bind(truncated)
+        .bind(returned_at.map(CanonicalTimestamp::parse).transpose()?)
+        .execute(&self.pool)
+        .await
+        .with_context(|| format!("save trace for job {job_id}"))?;
+        Ok(())
+    }
+
+    pub async fn get_job_trace(&self, job_id: &str) -> Result<Option<JobTrace>> {
+        sqlx::query_as::<_, JobTrace>(
+            "SELECT job_id, hops_json, truncated, returned_at FROM job_traces WHERE job_id = ?",
+        )
+        .bind(job_id)
+        .fetch_optional(&self.read_pool)
+        .await
+        .with_context(|| format!("query trace for job {job_id}"))
+    }
+
+    pub async fn record_job_result_digest(
+        &self,
+        job_id: &str,
+        result_digest: &str,
+        unchanged: bool,
+        source: &str,
+    ) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO job_result_digests(job_id, result_digest, unchanged, source, recorded_at) VALUES (?, ?, ?, ?, ?) ON CONFLICT(job_id) DO UPDATE SET result_digest = excluded.result_digest, unchanged = excluded.unchanged, source = excluded.source, recorded_at = excluded.recorded_at",
+        )
+        .bind(job_id)
+        .bind(result_digest)
+        .bind(unchanged)
+        .bind(source)
+        .bind(CanonicalTimestamp::now())
+        .execute(&self.pool)
+        .await
+        .with_context(|| format!("record result digest for job {job_id}"))?;
+        Ok(())
+    }
+
+    pub async fn get_job_result_digest(&self, job_id: &str) -> Result<Option<JobResultDigest>> {
+        sqlx::query_as::<_, JobResultDigest>(
+            "SELECT job_id, result_digest, unchanged, source, recorded_at FROM job_result_digests WHERE job_id = ?",
+        )
+        .bind(job_id)
+        .fetch_optional(&self.read_pool)
+        .await
+        .with_context(|| format!("query result digest for job {job_id}"))
+    }
+
+    pub async fn record_job_result_check(
+        &self,
+        job_id: &str,
+        schema_name: &str,
+        policy: &str,
+        errors: &Value,
+    ) -> Result<()> {
+        let (job_id, schema_name, policy) =
+            (job_id.to_string(), schema_name.to_string(), policy.to_string());
+        let errors = errors.clone();
+        self.with_tx(move |tx| {
+            Box::pin(async move {
+                tx.record_job_result_check(&job_id, &schema_name, &policy, &errors)
+                    .await
+            })
+        })
+        .await
+    }
+
+    pub async fn get_job_result_check(&self, job_id: &str) -> Result<Option<JobResultCheck>> {
+        sqlx::query_as::<_, JobResultCheck>(
+            "SELECT job_id, schema_name, policy, errors_json, checked_at FROM job_result_checks WHERE job_id = ?",
+        )
+        .bind(job_id)
+        .fetch_optional(&self.read_pool)
+        .await
+        .with_context(|| format!("query result check for job {job_id}"))
+    }
+
+    pub async fn save_job_transform(
+        &self,
+        job_id: &str,
+        stage: &str,
+        applied: &[String],
+        before: &Value,
+        after: &Value,
+    ) -> Result<()> {
+        let applied_json =
+            serde_json::to_string(applied).context("serialize applied transforms")?;
+        let before_json =
+            serde_json::to_string(before).context("serialize pre-transform payload")?;
+        let after_json =
+            serde_json::to_string(after).context("serialize post-transform payload")?;
+        sqlx::query(
+            "INSERT INTO job_transforms(trace_id, job_id, stage, applied_json, before_json, after_json, recorded_at) VALUES (?, ?, ?, ?, ?, ?, ?) ON CONFLICT(trace_id) DO UPDATE SET applied_json = excluded.applied_json, before_json = excluded.before_json, after_json = excluded.after_json, recorded_at = excluded.recorded_at",
+        )
+        .bind(format!("{job_id}/{stage}"))
+        .bind(job_id)
+        .bind(stage)
+        .bind(applied_json)
+        .bind(self.seal(&before_json)?)
+        .bind(self.seal(&after_json)?)
+        .bind(CanonicalTimestamp::now())
+        .execute(&self.pool)
+        .await
+        .with_context(|| format!("save {stage} transform trace for job {job_id}"))?;
+        Ok(())
+    }
+
+    pub async fn list_job_transforms(&self, job_id: &str) -> Result<Vec<JobTransformTrace>> {
+        let rows = sqlx::query_as::<_, (String, String, Vec<u8>, Vec<u8>, String)>(
+            "SELECT stage, applied_json, CAST(before_json AS BLOB), CAST(after_json AS BLOB), recorded_at FROM job_transforms WHERE job_id = ? ORDER BY recorded_at ASC, stage ASC",
+        )
+        .bind(job_id)
+        .fetch_all(&self.read_pool)
+        .await
+        .with_context(|| format!("query transform traces for job {job_id}"))?;
+
+        let mut traces = Vec::with_capacity(rows.len());
+        for (stage, applied_json, before, after, recorded_at) in rows {
+            let key = format!("{job_id}/{stage}");
+            let before = self.parse_listed("job_transforms", &key, before);
+            let after = self.parse_listed("job_transforms", &key, after);
+            let (Some(before), Some(after)) = (before, after) else {
+                continue;
+            };
+            traces.push(JobTransformTrace {
+                job_id: job_id.to_string(),
+                stage,
+                applied: serde_json::from_str(&applied_json)
+                    .context("decode applied transforms")?,
+                before,
+                after,
+                recorded_at,
+            });
+        }
+        Ok(traces)
+    }
+
+    // Returns false when the part was already stored, so redelivered parts are idempotent.
+    pub async fn insert_job_result_part(
+        &self,
+        job_id: &str,
+        sequence: u32,
+        is_final: bool,
+        data: &Value,
+    ) -> Result<bool> {
+        let (job_id, data) = (job_id.to_string(), data.clone());
+        self.with_tx(move |tx| {
+            Box::pin(async move {
+                tx.insert_job_result_part(&job_id, sequence, is_final, &data)
+                    .await
+            })
+        })
+        .await
+    }
+
+    pub async fn list_job_result_parts(&self, job_id: &str) -> Result<Vec<JobResultPart>> {
+        let rows = sqlx::query_as::<_, (u32, bool, Vec<u8>, String)>(
+            "SELECT sequence, is_final, CAST(payload_json AS BLOB), received_at FROM job_result_parts WHERE job_id = ? ORDER BY sequence ASC",
+        )
+        .bind(job_id)
+        .fetch_all(&self.read_pool)
+        .await
+        .with_context(|| format!("query result parts for job {job_id}"))?;
+
+        Ok(rows
+            .into_iter()
+            .filter_map(|(sequence, is_final, payload_json, received_at)| {
+                let key = format!("{job_id}/{sequence}");
+                Some(JobResultPart {
+                    job_id: job_id.to_string(),
+                    sequence,
+                    is_final,
+                    data: self.parse_listed("job_result_parts", &key, payload_json)?,
+                    received_at,
+                })
+            })
+            .collect())
+    }
+
+    pub async fn list_stalled_streams(&self, idle_before: &str) -> Result<Vec<String>> {
+        sqlx::query_scalar::<_, String>(
+            "SELECT j.job_id FROM jobs j WHERE j.status = 'streaming' AND COALESCE((SELECT MAX(p.received_at) FROM job_result_parts p WHERE p.job_id = j.job_id), j.updated_at) < ?",
+        )
+        .bind(CanonicalTimestamp::parse(idle_before)?)
+        .fetch_all(&self.read_pool)
+        .await
+        .context("query stalled result streams")
+    }
+
+    // The mesh holds the command for store-and-forward; the job fails if no result arrives by
+    // `expires_at`. A job a result already finished is left alone.
+    pub async fn mark_job_in_transit(
+        &self,
+        job_id: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<bool> {
+        let job_id = job_id.to_string();
+        self.with_tx(move |tx| {
+            Box::pin(async move { tx.mark_job_in_transit(&job_id, expires_at).await })
+        })
+        .await
+    }
+
+    pub async fn list_expired_in_transit(&self, now: DateTime<Utc>) -> Result<Vec<String>> {
+        sqlx::query_scalar::<_, String>(
+            "SELECT job_id FROM jobs WHERE status = 'in_transit' AND transit_expires_at <= ? ORDER BY transit_expires_at",
+        )
+        .bind(CanonicalTimestamp::from(now))
+        .fetch_all(&self.read_pool)
+        .await
+        .context("query expired in-transit jobs")
+    }
+
+    // A damaged row is logged, counted and left out rather than failing the whole listing.
+    fn parse_listed(&self, table: &str, key: &str, stored: Vec<u8>) -> Option<Value> {
+        match self.parse_stored(stored) {
+            Ok(value) => Some(value),
+            Err(err) => {
+                self.integrity.skipped_rows.fetch_add(1, Ordering::Relaxed);
+                warn!(table, key, error = %format!("{err:#}"), "skipping unreadable row");
+                None
+            }
+        }
+    }
+
+    fn parse_stored(&self, stored: Vec<u8>) -> Result<Value> {
+        let stored = String::from_utf8(stored).context("stored JSON is not UTF-8")?;
+        serde_json::from_str(&self.open(&stored)?).context("stored JSON does not parse")
+    }
+
+    pub fn integrity_stats(&self) -> IntegrityStats {
+        IntegrityStats {
+            skipped_rows: self.integrity.skipped_rows.load(Ordering::Relaxed),
+            scanned_rows: self.integrity.scanned_rows.load(Ordering::Relaxed),
+            quarantined_rows: self.integrity.quarantined_rows.load(Ordering::Relaxed),
+        }
+    }
+
+    // Checks the JSON columns of rows added since the previous scan, `batch_size` rows at a
+    // time, and moves rows that do not parse into `quarantine`. The high-water mark only moves
+    // past rows that stay, since SQLite reuses the rowid of a deleted last row.
+    pub async fn check_integrity(&self, batch_size: i64) -> Result<IntegrityReport> {
+        let mut report = IntegrityReport::default();
+        for (table, key_column, columns) in INTEGRITY_TABLES {
+            let mut high_water = self.integrity_high_water(table).await?;
+            let selected: Vec<String> = columns
+                .iter()
+                .map(|column| format!("CAST({column} AS BLOB)"))
+                .collect();
+            let sql = format!(
+                "SELECT rowid, {key_column}, {} FROM {table} WHERE rowid > ? ORDER BY rowid LIMIT ?",
+                selected.join(", ")
+            );
+            loop {
+                let rows = sqlx::query(&sql)
+                    .bind(high_water)
+                    .bind(batch_size)
+                    .fetch_all(&self.read_pool)
+                    .await
+                    .with_context(|| format!("scan {table}"))?;
+                for row in &rows {
+                    let rowid: i64 = row.try_get(0)?;
+                    let key: String = row.try_get(1)?;
+                    let mut damaged = Vec::new();
+                    for (index, column) in columns.iter().enumerate() {
+                        let Some(stored) = row.try_get::<Option<Vec<u8>>, _>(index + 2)? else {
+                            continue;
+                        };
+                        if let Err(err) = self.parse_stored(stored.clone()) {
+                            damaged.push((*column, stored, format!("{err:#}")));
+                        }
+                    }
+                    report.scanned += 1;
+                    if damaged.is_empty() {
+                        high_water = rowid;
+                        continue;
+                    }
+                    warn!(table, key, "quarantining row with unreadable JSON");
+                    self.quarantine_row(table, &key, damaged).await?;
+                    report.quarantined += 1;
+                    *report
+                        .quarantined_by_table
+                        .entry(table.to_string())
+                        .or_default() += 1;
+                }
+                self.set_integrity_high_water(table, high_water).await?;
+                if (rows.len() as i64) < batch_size {
+                    break;
+                }
+            }
+        }
+        self.integrity
+            .scanned_rows
+            .fetch_add(report.scanned, Ordering::Relaxed);
+        self.integrity
+            .quarantined_rows
+            .fetch_add(report.quarantined, Ordering::Relaxed);
+        Ok(report)
+    }
+
+    async fn integrity_high_water(&self, table: &str) -> Result<i64> {
+        let stored = sqlx::query_scalar::<_, String>("SELECT value FROM storage_meta WHERE key = ?")
+            .bind(format!("{INTEGRITY_HIGH_WATER_PREFIX}{table}"))
+            .fetch_optional(&self.read_pool)
+            .await
+            .context("query integrity high-water mark")?;
+        Ok(stored.and_then(|value| value.parse().ok()).unwrap_or(0))
+    }
+
+    async fn set_integrity_high_water(&self, table: &str, rowid: i64) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO storage_meta(key, value) VALUES (?, ?) ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+        )
+        .bind(format!("{INTEGRITY_HIGH_WATER_PREFIX}{table}"))
+        .bind(rowid.to_string())
+        .execute(&self.pool)
+        .await
+        .context("write integrity high-water mark")?;
+        Ok(())
+    }
+
+    // Copies the damaged columns into `quarantine` and deletes the row together with the rows
+    // that depend on it; transfers of a quarantined job are detached rather than deleted.
+    async fn quarantine_row(
+        &self,
+        table: &str,
+        key: &str,
+        damaged: Vec<(&str, Vec<u8>, String)>,
+    ) -> Result<()> {
+        let mut tx = self.pool.begin().await.context("begin quarantine")?;
+        let quarantined_at = CanonicalTimestamp::now().to_string();
+        for (column, raw, reason) in damaged {
+            sqlx::query(
+                "INSERT INTO quarantine(source_table, row_key, column_name, raw, reason, quarantined_at) VALUES (?, ?, ?, ?, ?, ?)",
+            )
+            .bind(table)
+            .bind(key)
+            .bind(column)
+            .bind(raw)
+            .bind(reason)
+            .bind(&quarantined_at)
+            .execute(&mut *tx)
+            .await
+            .with_context(|| format!("quarantine {table} row {key}"))?;
+        }
+        let dependents: &[&str] = match table {
+            "jobs" => &[
+                "DELETE FROM job_attempts WHERE job_id = ?",
+                "DELETE FROM job_leases WHERE job_id = ?",
+                "DELETE FROM job_results WHERE job_id = ?",
+                "DELETE FROM job_result_parts WHERE job_id = ?",
+                "DELETE FROM job_messages WHERE job_id = ?",
+                "DELETE FROM dispatch_attempts WHERE job_id = ?",
+                "DELETE FROM job_traces WHERE job_id = ?",
+                "DELETE FROM job_transforms WHERE job_id = ?",
+                "DELETE FROM job_result_digests WHERE job_id = ?",
+                "DELETE FROM job_result_checks WHERE job_id = ?",
+                "DELETE FROM job_holds WHERE job_id = ?",
+                "DELETE FROM job_dependencies WHERE job_id = ?1 OR depends_on = ?1",
+                "DELETE FROM labels WHERE subject_type = 'job' AND subject_id = ?",
+                "DELETE FROM delivery_receipts WHERE job_id = ?",
+                "UPDATE transfers SET job_id = NULL WHERE job_id = ?",
+            ],
+            "transfers" => &[
+                "DELETE FROM transfer_progress WHERE transfer_id = ?",
+                "DELETE FROM transfer_dedup WHERE transfer_id = ?",
+                "DELETE FROM bundle_members WHERE bundle_id = ?",
+                "DELETE FROM labels WHERE subject_type = 'transfer' AND subject_id = ?",
+                "DELETE FROM delivery_receipts WHERE transfer_id = ?",
+            ],
+            _ => &[],
+        };
+        for sql in dependents {
+            sqlx::query(sql)
+                .bind(key)
+                .execute(&mut *tx)
+                .await
+                .with_context(|| format!("detach {table} row {key}"))?;
+        }
+        let key_column = INTEGRITY_TABLES
+            .iter()
+            .find(|(name, _, _)| *name == table)
+            .map(|(_, key_column, _)| *key_column)
+            .with_context(|| format!("integrity-checked table {table}"))?;
+        sqlx::query(&format!("DELETE FROM {table} WHERE {key_column} = ?"))
+            .bind(key)
+            .execute(&mut *tx)
+            .await
+            .with_context(|| format!("remove {table} row {key}"))?;
+        tx.commit().await.context("commit quarantine")?;
+        Ok(())
+    }
+
+    // Keeps a result the job refused for inspection; the job itself stays, failed.
+    pub async fn quarantine_job_result(
+        &self,
+        job_id: &str,
+        raw: &[u8],
+        reason: &str,
+    ) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO quarantine(source_table, row_key, column_name, raw, reason, quarantined_at) VALUES ('job_results', ?, 'result_json', ?, ?, ?)",
+        )
+        .bind(job_id)
+        .bind(raw)
+        .bind(reason)
+        .bind(CanonicalTimestamp::now())
+        .execute(&self.pool)
+        .await
+        .with_context(|| format!("quarantine result of job {job_id}"))?;
+        Ok(())
+    }
+
+    pub async fn list_quarantine(&self, limit: i64) -> Result<Vec<QuarantinedRow>> {
+        sqlx::query_as::<_, (i64, String, String, String, Vec<u8>, String, String)>(
+            "SELECT id, source_table, row_key, column_name, raw, reason, quarantined_at FROM quarantine ORDER BY id LIMIT ?",
+        )
+        .bind(limit)
+        .fetch_all(&self.read_pool)
+        .await
+        .context("query quarantine")
+        .map(|rows| {
+            rows.into_iter()
+                .map(
+                    |(id, source_table, row_key, column_name, raw, reason, quarantined_at)| {
+                        QuarantinedRow {
+                            id,
+                            source_table,
+                            row_key,
+                            column_name,
+                            raw,
+                            reason,
+                            quarantined_at,
+                        }
+                    },
+                )
+                .collect()
+        })
+    }
+
+    pub async fn purge_quarantine(&self) -> Result<u64> {
+        let result = sqlx::query("DELETE FROM quarantine")
+            .execute(&self.pool)
+            .await
+            .context("purge quarantine")?;
+        Ok(result.rows_affected())
+    }
+
+    pub async fn list_cached_events(&self, limit: i64) -> Result<Vec<Value>> {
+        let rows = sqlx::query_as::<_, (String, Vec<u8>)>(
+            "SELECT event_id, CAST(payload_json AS BLOB) FROM cached_events ORDER BY received_at DESC LIMIT ?",
+        )
+        .bind(limit)
+        .fetch_all(&self.read_pool)
+        .await
+        .context("query cached events")?;
+        self.count_payload_reads(rows.len());
+
+        Ok(rows
+            .into_iter()
+            .filter_map(|(key, row)| self.parse_listed("cached_events", &key, row))
+            .collect())
+    }
+
+    pub async fn list_cached_messages(&self, limit: i64) -> Result<Vec<Value>> {
+        let rows = sqlx::query_as::<_, (String, Vec<u8>)>(
+            "SELECT message_id, CAST(payload_json AS BLOB) FROM cached_messages ORDER BY received_at DESC LIMIT ?",
+        )
+        .bind(limit)
+        .fetch_all(&self.read_pool)
+        .await
+        .context("query cached messages")?;
+        self.count_payload_reads(rows.len());
+
+        Ok(rows
+            .into_iter()
+            .filter_map(|(key, row)| self.parse_listed("cached_messages", &key, row))
+            .collect())
+    }
+
+    // The rows `list_cached_events` would return, without selecting their payloads.
+    pub async fn list_cached_event_summaries(&self, limit: i64) -> Result<Vec<CachedSummary>> {
+        sqlx::query_as::<_, CachedSummary>(
+            "SELECT event_id AS id, event_name AS name, received_at, payload_bytes, payload_sha256 FROM cached_events ORDER BY received_at DESC LIMIT ?",
+        )
+        .bind(limit)
+        .fetch_all(&self.read_pool)
+        .await
+        .context("query cached event summaries")
+    }
+
+    pub async fn list_cached_message_summaries(&self, limit: i64) -> Result<Vec<CachedSummary>> {
+        sqlx::query_as::<_, CachedSummary>(
+            "SELECT message_id AS id, operation AS name, received_at, payload_bytes, payload_sha256 FROM cached_messages ORDER BY received_at DESC LIMIT ?",
+        )
+        .bind(limit)
+        .fetch_all(&self.read_pool)
+        .await
+        .context("query cached message summaries")
+    }
+
+    pub async fn cache_message(
+        &self,
+        message_id: &str,
+        operation: &str,
+        payload: &Value,
+    ) -> Result<()> {
+        let (message_id, operation) = (message_id.to_string(), operation.to_string());
+        let payload = payload.clone();
+        self.with_tx(move |tx| {
+            Box::pin(async move { tx.cache_message(&message_id, &operation, &payload).await })
+        })
+        .await
+    }
+
+    // A redelivered command keeps the record of its first answer.
+    pub async fn record_inbound(&self, record: &InboundRecord) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO inbound_records(message_id, operation, source_identity, envelope_json, result_json, received_at, answered_at) VALUES (?, ?, ?, ?, ?, ?, ?) ON CONFLICT(message_id) DO NOTHING",
+        )
+        .bind(&record.message_id)
+        .bind(&record.operation)
+        .bind(&record.source_identity)
+        .bind(self.seal(&record.envelope_json)?)
+        .bind(self.seal(&record.result_json)?)
+        .bind(CanonicalTimestamp::parse(&record.received_at)?)
+        .bind(CanonicalTimestamp::parse(&record.answered_at)?)
+        .execute(&self.pool)
+        .await
+        .with_context(|| format!("insert inbound record {}", record.message_id))?;
+        Ok(())
+    }
+
+    // Stores a receipt, replacing an earlier unverified one for the same message but never a
+    // verified one. Returns whether the receipt was stored.
+    pub async fn record_delivery_receipt(&self, record: &DeliveryReceiptRecord) -> Result<bool> {
+        let record = record.clone();
+        self.with_tx(move |tx| Box::pin(async move { tx.record_delivery_receipt(&record).await }))
+            .await
+    }
+
+    pub async fn delivery_receipt_for_job(
+        &self,
+        job_id: &str,
+    ) -> Result<Option<DeliveryReceiptRecord>> {
+        sqlx::query_as::<_, DeliveryReceiptRecord>(
+            "SELECT message_id, job_id, transfer_id, receiver_identity, receipt_json, verified, reason, recorded_at FROM delivery_receipts WHERE job_id = ? ORDER BY verified DESC, recorded_at DESC LIMIT 1",
+        )
+        .bind(job_id)
+        .fetch_optional(&self.read_pool)
+        .await
+        .with_context(|| format!("query delivery receipt of job {job_id}"))
+    }
+
+    pub async fn delivery_receipt_for_transfer(
+        &self,
+        transfer_id: &str,
+    ) -> Result<Option<DeliveryReceiptRecord>> {
+        sqlx::query_as::<_, DeliveryReceiptRecord>(
+            "SELECT message_id, job_id, transfer_id, receiver_identity, receipt_json, verified, reason, recorded_at FROM delivery_receipts WHERE transfer_id = ? ORDER BY verified DESC, recorded_at DESC LIMIT 1",
+        )
+        .bind(transfer_id)
+        .fetch_optional(&self.read_pool)
+        .await
+        .with_context(|| format!("query delivery receipt of transfer {transfer_id}"))
+    }
+
+    // Inbound records received at or after `since`, in the order they arrived. Reads the
+    // database through a read-only connection and never migrates it, so the database of a
+    // running node can be read without any chance of writing to it. A database from before
+    // recording existed has no records.
+    pub async fn read_inbound_records(
+        config: &StorageConfig,
+        since: DateTime<Utc>,
+    ) -> Result<Vec<InboundRecord>> {
+        let cipher = load_cipher(config.encryption_key_path.as_deref())?;
+        let pool = open_read_only(config).await?;
+        let recorded = sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = 'inbound_records'",
+        )
+        .fetch_one(&pool)
+        .await
+        .context("query sqlite tables")?;
+        let records = if recorded == 0 {
+            Vec::new()
+        } else {
+            sqlx::query_as::<_, InboundRecord>(
+                "SELECT message_id, operation, source_identity, envelope_json, result_json, received_at, answered_at FROM inbound_records WHERE received_at >= ? ORDER BY received_at, rowid",
+            )
+            .bind(CanonicalTimestamp::from(since))
+            .fetch_all(&pool)
+            .await
+            .context("list inbound records")?
+        };
+        pool.close().await;
+
+        records
+            .into_iter()
+            .map(|mut record| {
+                let opened = |stored: &str| {
+                    open(cipher.as_ref(), stored).map_err(|err| err.in_table("inbound_records"))
+                };
+                record.envelope_json = opened(&record.envelope_json)?;
+                record.result_json = opened(&record.result_json)?;
+                Ok(record)
+            })
+            .collect()
+    }
+
+    // Identities on the allowlist and active at `at`, read like `read_inbound_records`.
+    pub async fn read_allowlist(config: &StorageConfig, at: DateTime<Utc>) -> Result<Vec<String>> {
+        let pool = open_read_only(config).await?;
+        let active = sqlx::query_scalar::<_, String>(
+            "SELECT identity_hash FROM acl_allowlist WHERE status = 'active' AND (expires_at IS NULL OR expires_at > ?) ORDER BY identity_hash",
+        )
+        .bind(CanonicalTimestamp::from(at))
+        .fetch_all(&pool)
+        .await
+        .context("list allowlist");
+        pool.close().await;
+        active
+    }
+
+    pub async fn cache_event(
+        &self,
+        event_id: &str,
+        event_name: &str,
+        source_identity: &str,
+        sent_at: DateTime<Utc>,
+        payload: &Value,
+    ) -> Result<()> {
+        let event = CachedEvent {
+            event_id,
+            event_name,
+            source_identity,
+            sent_at,
+            payload,
+        };
+        write_cached_event(&self.pool, self.cipher.as_ref(), self.contract_version(), &event)
+            .await?;
+        Ok(())
+    }
+
+    // Events cached before sent_at was recorded fall back to their arrival time.
+    pub async fn aggregate_cached_events(
+        &self,
+        grouping: EventGrouping,
+        bucket_secs: i64,
+        since: i64,
+        until: i64,
+    ) -> Result<Vec<AggregateCount>> {
+        let group = match grouping {
+            EventGrouping::Event => "event_name",
+            EventGrouping::Source => "COALESCE(source_identity, 'unknown')",
+        };
+        self.aggregate(
+            "cached_events",
+            "COALESCE(sent_at, received_at)",
+            group,
+            bucket_secs,
+            since,
+            until,
+        )
+        .await
+    }
+
+    pub async fn aggregate_jobs(
+        &self,
+        grouping: JobGrouping,
+        bucket_secs: i64,
+        since: i64,
+        until: i64,
+    ) -> Result<Vec<AggregateCount>> {
+        let group = match grouping {
+            JobGrouping::Status => "status",
+            JobGrouping::Operation => "operation",
+        };
+        self.aggregate("jobs", "submitted_at", group, bucket_secs, since, until)
+            .await
+    }
+
+    // Buckets are aligned to the Unix epoch, so they fall on UTC boundaries. The window is
+    // compared against the stored canonical text, so an index on the column can serve it.
+    async fn aggregate(
+        &self,
+        table: &str,
+        time_column: &str,
+        group: &str,
+        bucket_secs: i64,
+        since: i64,
+        until: i64,
+    ) -> Result<Vec<AggregateCount>> {
+        let epoch = format!("CAST(strftime('%s', {time_column}) AS INTEGER)");
+        let bound = |secs: i64| {
+            DateTime::from_timestamp(secs, 0)
+                .map(CanonicalTimestamp::from)
+                .with_context(|| format!("aggregate window bound {secs} is out of range"))
+        };
+        let rows = sqlx::query_as::<_, (i64, String, i64)>(&format!(
+            "SELECT ({epoch} / ?1) * ?1 AS bucket, {group} AS grp, COUNT(*) FROM {table} WHERE {time_column} >= ?2 AND {time_column} < ?3 GROUP BY bucket, grp ORDER BY bucket, grp"
+        ))
+        .bind(bucket_secs.max(1))
+        .bind(bound(since)?)
+        .bind(bound(until)?)
+        .fetch_all(&self.read_pool)
+        .await
+        .with_context(|| format!("aggregate {table}"))?;
+        Ok(rows
+            .into_iter()
+            .map(|(bucket_start, group, count)| AggregateCount {
+                bucket_start,
+                group,
+                count,
+            })
+            .collect())
+    }
+
+    // Stores `entity` as given, version included. Replication and imports write through here;
+    // local edits go through `update_entity` and `soft_delete_entity`.
+    pub async fn put_entity(&self, entity: &EntityRecord) -> Result<()> {
+        let record_json = serde_json::to_string(&entity.record).context("serialize entity")?;
+        let (payload_bytes, payload_sha256) = payload_digest(&record_json);
+        sqlx::query(
+            "INSERT INTO entities(entity_key, entity_type, entity_id, updated_at, record_json, contract_version, version, deleted_at, payload_bytes, payload_sha256) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?) ON CONFLICT(entity_key) DO UPDATE SET updated_at = excluded.updated_at, record_json = excluded.record_json, contract_version = excluded.contract_version, version = excluded.version, deleted_at = excluded.deleted_at, payload_bytes = excluded.payload_bytes, payload_sha256 = excluded.payload_sha256",
+        )
+        .bind(entity_key(&entity.entity_type, &entity.entity_id))
+        .bind(&entity.entity_type)
+        .bind(&entity.entity_id)
+        .bind(CanonicalTimestamp::from(entity.updated_at))
+        .bind(self.seal(&record_json)?)
+        .bind(self.contract_version())
+        .bind(entity.version)
+        .bind(entity.deleted_at.map(CanonicalTimestamp::from))
+        .bind(payload_bytes)
+        .bind(payload_sha256)
+        .execute(&self.pool)
+        .await
+        .with_context(|| format!("upsert entity {}/{}", entity.entity_type, entity.entity_id))?;
+        Ok(())
+    }
+
+    // Writes `record` as the version after `expected_version`, where 0 creates the entity. A
+    // write based on any other version is refused with `VersionConflict`, which carries the
+    // entity as it now stands. Updating a soft-deleted entity restores it.
+    pub async fn update_entity(
+        &self,
+        entity_type: &str,
+        entity_id: &str,
+        record: &Value,
+        expected_version: i64,
+    ) -> Result<EntityRecord> {
+        let record_json = serde_json::to_string(record).context("serialize entity")?;
+        let updated_at = CanonicalTimestamp::now();
+        let key = entity_key(entity_type, entity_id);
+        let sealed = self.seal(&record_json)?;
+        let (payload_bytes, payload_sha256) = payload_digest(&record_json);
+        let written = if expected_version == 0 {
+            sqlx::query(
+                "INSERT INTO entities(entity_key, entity_type, entity_id, updated_at, record_json, contract_version, version, payload_bytes, payload_sha256) VALUES (?, ?, ?, ?, ?, ?, 1, ?, ?) ON CONFLICT(entity_key) DO NOTHING",
+            )
+            .bind(&key)
+            .bind(entity_type)
+            .bind(entity_id)
+            .bind(updated_at)
+            .bind(&sealed)
+            .bind(self.contract_version())
+            .bind(payload_bytes)
+            .bind(&payload_sha256)
+            .execute(&self.pool)
+            .await
+        } else {
+            sqlx::query(
+                "UPDATE entities SET updated_at = ?, record_json = ?, contract_version = ?, version = version + 1, deleted_at = NULL, payload_bytes = ?, payload_sha256 = ? WHERE entity_key = ? AND version = ?",
+            )
+            .bind(updated_at)
+            .bind(&sealed)
+            .bind(self.contract_version())
+            .bind(payload_bytes)
+            .bind(&payload_sha256)
+            .bind(&key)
+            .bind(expected_version)
+            .execute(&self.pool)
+            .await
+        }
+        .with_context(|| format!("update entity {entity_type}/{entity_id}"))?;
+        if written.rows_affected() == 0 {
+            return Err(self
+                .version_conflict(entity_type, entity_id, expected_version)
+                .await);
+        }
+        Ok(EntityRecord {
+            entity_type: entity_type.to_string(),
+            entity_id: entity_id.to_string(),
+            updated_at: updated_at.into(),
+            record: record.clone(),
+            version: expected_version + 1,
+            deleted_at: None,
+        })
+    }
+
+    // Marks the entity deleted as the version after `expected_version`, refusing a stale
+    // version the same way `update_entity` does.
+    pub async fn soft_delete_entity(
+        &self,
+        entity_type: &str,
+        entity_id: &str,
+        expected_version: i64,
+    ) -> Result<EntityRecord> {
+        let deleted_at = CanonicalTimestamp::now();
+        let written = sqlx::query(
+            "UPDATE entities SET updated_at = ?1, deleted_at = ?1, version = version + 1 WHERE entity_key = ?2 AND version = ?3 AND deleted_at IS NULL",
+        )
+        .bind(deleted_at)
+        .bind(entity_key(entity_type, entity_id))
+        .bind(expected_version)
+        .execute(&self.pool)
+        .await
+        .with_context(|| format!("delete entity {entity_type}/{entity_id}"))?;
+        if written.rows_affected() == 0 {
+            return Err(self
+                .version_conflict(entity_type, entity_id, expected_version)
+                .await);
+        }
+        self.get_entity(entity_type, entity_id)
+            .await?
+            .with_context(|| format!("entity {entity_type}/{entity_id}"))
+    }
+
+    // Why a versioned write matched no row: the entity is missing, or stands at another version.
+    async fn version_conflict(
+        &self,
+        entity_type: &str,
+        entity_id: &str,
+        expected_version: i64,
+    ) -> StorageError {
+        match self.get_entity(entity_type, entity_id).await {
+            Ok(Some(current)) => StorageError::VersionConflict {
+                expected: expected_version,
+                current: Box::new(current),
+            },
+            Ok(None) => StorageError::not_found(format!("entity {entity_type}/{entity_id}")),
+            Err(err) => err,
+        }
+    }
+
+    // Records not written under `current_version`. Only jobs that have not been dispatched yet
+    // are listed; the payload of a job in flight or finished is history.
+    pub async fn list_versioned_payloads(
+        &self,
+        table: PayloadTable,
+        current_version: &str,
+    ) -> Result<Vec<VersionedPayload>> {
+        let (key_column, kind_column, payload_column) = table.columns();
+        let pending = match table {
+            PayloadTable::Jobs => " AND status IN ('queued', 'waiting', 'deferred')",
+            PayloadTable::Entities | PayloadTable::CachedEvents => "",
+        };
+        let rows = sqlx::query_as::<_, (String, String, Option<String>, Vec<u8>)>(&format!(
+            "SELECT {key_column}, {kind_column}, contract_version, CAST({payload_column} AS BLOB) FROM {table} WHERE (contract_version IS NULL OR contract_version != ?){pending} ORDER BY rowid",
+            table = table.as_str(),
+        ))
+        .bind(current_version)
+        .fetch_all(&self.read_pool)
+        .await
+        .with_context(|| format!("query versioned {}", table.as_str()))?;
+
+        let mut records = Vec::with_capacity(rows.len());
+        for (key, kind, contract_version, stored) in rows {
+            let Some(payload) = self.parse_listed(table.as_str(), &key, stored) else {
+                continue;
+            };
+            records.push(VersionedPayload {
+                table,
+                key,
+                kind,
+                contract_version,
+                payload,
+            });
+        }
+        Ok(records)
+    }
+
+    // Replaces the payload with its migrated shape and records each migration that produced
+    // it, in one transaction.
+    pub async fn save_migrated_payload(
+        &self,
+        record: &VersionedPayload,
+        migrations: &[String],
+        to_version: &str,
+    ) -> Result<()> {
+        let (key_column, _, payload_column) = record.table.columns();
+        let table = record.table.as_str();
+        let payload_json =
+            serde_json::to_string(&record.payload).context("serialize migrated payload")?;
+        let (payload_bytes, payload_sha256) = payload_digest(&payload_json);
+        let now = CanonicalTimestamp::now();
+        let mut tx = self.pool.begin().await.context("begin payload migration")?;
+        sqlx::query(&format!(
+            "UPDATE {table} SET {payload_column} = ?, contract_version = ?, payload_bytes = ?, payload_sha256 = ? WHERE {key_column} = ?"
+        ))
+        .bind(self.seal(&payload_json)?)
+        .bind(to_version)
+        .bind(payload_bytes)
+        .bind(payload_sha256)
+        .bind(&record.key)
+        .execute(&mut *tx)
+        .await
+        .with_context(|| format!("migrate {table} row {}", record.key))?;
+        for migration in migrations {
+            sqlx::query(
+                "INSERT OR IGNORE INTO payload_migrations_applied(migration, record_table, record_key, from_version, to_version, applied_at) VALUES (?, ?, ?, ?, ?, ?)",
+            )
+            .bind(migration)
+            .bind(table)
+            .bind(&record.key)
+            .bind(&record.contract_version)
+            .bind(to_version)
+            .bind(now)
+            .execute(&mut *tx)
+            .await
+            .with_context(|| format!("record migration {migration} of {table} row {}", record.key))?;
+        }
+        tx.commit().await.context("commit payload migration")?;
+        Ok(())
+    }
+
+    pub async fn applied_payload_migrations(
+        &self,
+        table: PayloadTable,
+        key: &str,
+    ) -> Result<Vec<String>> {
+        sqlx::query_scalar::<_, String>(
+            "SELECT migration FROM payload_migrations_applied WHERE record_table = ? AND record_key = ? ORDER BY applied_at, migration",
+        )
+        .bind(table.as_str())
+        .bind(key)
+        .fetch_all(&self.read_pool)
+        .await
+        .with_context(|| format!("query migrations applied to {} row {key}", table.as_str()))
+    }
+
+    pub async fn get_entity(
+        &self,
+        entity_type: &str,
+        entity_id: &str,
+    ) -> Result<Option<EntityRecord>> {
+        let row = sqlx::query_as::<_, (String, Vec<u8>, i64, Option<String>)>(
+            "SELECT updated_at, CAST(record_json AS BLOB), version, deleted_at FROM entities WHERE entity_key = ?",
+        )
+        .bind(entity_key(entity_type, entity_id))
+        .fetch_optional(&self.read_pool)
+        .await
+        .with_context(|| format!("query entity {entity_type}/{entity_id}"))?;
+        let Some((updated_at, stored, version, deleted_at)) = row else {
+            return Ok(None);
+        };
+        self.count_payload_reads(1);
+        Ok(Some(EntityRecord {
+            entity_type: entity_type.to_string(),
+            entity_id: entity_id.to_string(),
+            updated_at: parse_timestamp(&updated_at)?,
+            record: self.parse_stored(stored)?,
+            version,
+            deleted_at: deleted_at.as_deref().map(parse_timestamp).transpose()?,
+        }))
+    }
+
+    // The entity without its record, which is not selected.
+    pub async fn get_entity_summary(
+        &self,
+        entity_type: &str,
+        entity_id: &str,
+    ) -> Result<Option<EntitySummary>> {
+        let row = sqlx::query_as::<_, (String, i64, Option<String>, Option<i64>, Option<String>)>(
+            "SELECT updated_at, version, deleted_at, payload_bytes, payload_sha256 FROM entities WHERE entity_key = ?",
+        )
+        .bind(entity_key(entity_type, entity_id))
+        .fetch_optional(&self.read_pool)
+        .await
+        .with_context(|| format!("query entity summary {entity_type}/{entity_id}"))?;
+        let Some((updated_at, version, deleted_at, record_bytes, record_sha256)) = row else {
+            return Ok(None);
+        };
+        Ok(Some(EntitySummary {
+            entity_type: entity_type.to_string(),
+            entity_id: entity_id.to_string(),
+            updated_at: parse_timestamp(&updated_at)?,
+            version,
+            deleted_at: deleted_at.as_deref().map(parse_timestamp).transpose()?,
+            record_bytes,
+            record_sha256,
+        }))
+    }
+
+    // Entities of one type whose id starts with `prefix`, ordered by id.
+    pub async fn list_entities(
+        &self,
+        entity_type: &str,
+        prefix: &str,
+    ) -> Result<Vec<EntityRecord>> {
+        let rows = sqlx::query_as::<_, (String, String, String, Vec<u8>, i64, Option<String>)>(
+            "SELECT entity_key, entity_id, updated_at, CAST(record_json AS BLOB), version, deleted_at FROM entities WHERE entity_type = ? AND substr(entity_id, 1, ?) = ? ORDER BY entity_id ASC",
+        )
+        .bind(entity_type)
+        .bind(prefix.chars().count() as i64)
+        .bind(prefix)
+        .fetch_all(&self.read_pool)
+        .await
+        .with_context(|| format!("query {entity_type} entities"))?;
+
+        let mut entities = Vec::with_capacity(rows.len());
+        for (key, entity_id, updated_at, stored, version, deleted_at) in rows {
+            let Some(record) = self.parse_listed("entities", &key, stored) else {
+                continue;
+            };
+            entities.push(EntityRecord {
+                entity_type: entity_type.to_string(),
+                entity_id,
+                updated_at: parse_timestamp(&updated_at)?,
+                record,
+                version,
+                deleted_at: deleted_at.as_deref().map(parse_timestamp).transpose()?,
+            });
+        }
+        Ok(entities)
+    }
+
+    pub async fn record_sync_conflict(
+        &self,
+        peer_identity: &str,
+        local: &EntityRecord,
+        remote: &EntityRecord,
+        kept: &str,
+    ) -> Result<SyncConflict> {
+        let conflict = SyncConflict {
+            conflict_id: Uuid::now_v7().to_string(),
+            entity_type: local.entity_type.clone(),
+            entity_id: local.entity_id.clone(),
+            peer_identity: peer_identity.to_string(),
+            local: local.clone(),
+            remote: remote.clone(),
+            kept: kept.to_string(),
+            detected_at: CanonicalTimestamp::now().to_string(),
+        };
+        let local_json = serde_json::to_string(local).context("serialize local version")?;
+        let remote_json = serde_json::to_string(remote).context("serialize remote version")?;
+        sqlx::query(
+            "INSERT INTO sync_conflicts(conflict_id, entity_type, entity_id, peer_identity, local_json, remote_json, kept, detected_at) VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(&conflict.conflict_id)
+        .bind(&conflict.entity_type)
+        .bind(&conflict.entity_id)
+        .bind(peer_identity)
+        .bind(self.seal(&local_json)?)
+        .bind(self.seal(&remote_json)?)
+        .bind(kept)
+        .bind(CanonicalTimestamp::parse(&conflict.detected_at)?)
+        .execute(&self.pool)
+        .await
+        .with_context(|| format!("record sync conflict for {}", conflict.entity_id))?;
+        Ok(conflict)
+    }
+
+    // When the last converged sync of `entity_type` with `peer_identity` started.
+    pub async fn last_entity_sync(
+        &self,
+        entity_type: &str,
+        peer_identity: &str,
+    ) -> Result<Option<DateTime<Utc>>> {
+        let stored = sqlx::query_scalar::<_, String>("SELECT value FROM storage_meta WHERE key = ?")
+            .bind(format!("{ENTITY_SYNC_PREFIX}{peer_identity}.{entity_type}"))
+            .fetch_optional(&self.read_pool)
+            .await
+            .context("query last entity sync")?;
+        stored.as_deref().map(parse_timestamp).transpose()
+    }
+
+    pub async fn set_last_entity_sync(
+        &self,
+        entity_type: &str,
+        peer_identity: &str,
+        at: DateTime<Utc>,
+    ) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO storage_meta(key, value) VALUES (?, ?) ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+        )
+        .bind(format!("{ENTITY_SYNC_PREFIX}{peer_identity}.{entity_type}"))
+        .bind(CanonicalTimestamp::from(at))
+        .execute(&self.pool)
+        .await
+        .context("write last entity sync")?;
+        Ok(())
+    }
+
+    pub async fn list_sync_conflicts(&self, entity_type: &str) -> Result<Vec<SyncConflict>> {
+        let rows = sqlx::query_as::<_, (String, String, String, Vec<u8>, Vec<u8>, String, String)>(
+            "SELECT conflict_id, entity_id, peer_identity, CAST(local_json AS BLOB), CAST(remote_json AS BLOB), kept, detected_at FROM sync_conflicts WHERE entity_type = ? ORDER BY conflict_id ASC",
+        )
+        .bind(entity_type)
+        .fetch_all(&self.read_pool)
+        .await
+        .with_context(|| format!("query {entity_type} sync conflicts"))?;
+
+        let mut conflicts = Vec::with_capacity(rows.len());
+        for (conflict_id, entity_id, peer_identity, local, remote, kept, detected_at) in rows {
+            let local = self.parse_listed("sync_conflicts", &conflict_id, local);
+            let remote = self.parse_listed("sync_conflicts", &conflict_id, remote);
+            let (Some(local), Some(remote)) = (local, remote) else {
+                continue;
+            };
+            conflicts.push(SyncConflict {
+                conflict_id,
+                entity_type: entity_type.to_string(),
+                entity_id,
+                peer_identity,
+                local: serde_json::from_value(local).context("decode local version")?,
+                remote: serde_json::from_value(remote).context("decode remote version")?,
+                kept,
+                detected_at,
+            });
+        }
+        Ok(conflicts)
+    }
+
+    pub async fn cached_events_fingerprint(&self) -> Result<(i64, i64)> {
+        self.cache_fingerprint("cached_events").await
+    }
+
+    pub async fn cached_messages_fingerprint(&self) -> Result<(i64, i64)> {
+        self.cache_fingerprint("cached_messages").await
+    }
+
+    async fn cache_fingerprint(&self, table: &str) -> Result<(i64, i64)> {
+        let sql = format!("SELECT COALESCE(MAX(rowid), 0), COUNT(*) FROM {table}");
+        sqlx::query_as::<_, (i64, i64)>(&sql)
+            .fetch_one(&self.read_pool)
+            .await
+            .with_context(|| format!("query {table} fingerprint"))
+    }
+
+    pub async fn list_allowlist(&self) -> Result<Vec<String>> {
+        sqlx::query_scalar::<_, String>(
+            "SELECT identity_hash FROM acl_allowlist ORDER BY identity_hash ASC",
+        )
+        .fetch_all(&self.read_pool)
+        .await
+        .context("query allowlist")
+    }
+
+    pub async fn add_allowlist(
+        &self,
+        identity_hash: &IdentityHash,
+        note: Option<&str>,
+    ) -> Result<()> {
+        self.put_allowlist_entry(identity_hash, note, "peer", "active", None)
+            .await
+            .map(|_| ())
+    }
+
+    pub async fn put_allowlist_entry(
+        &self,
+        identity_hash: &IdentityHash,
+        note: Option<&str>,
+        role: &str,
+        status: &str,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<AllowlistEntry> {
+        let (hash, role, status) = (
+            identity_hash.clone(),
+            role.to_string(),
+            status.to_string(),
+        );
+        let note = note.map(str::to_string);
+        self.with_tx(move |tx| {
+            Box::pin(async move {
+                tx.put_allowlist_entry(&hash, note.as_deref(), &role, &status, expires_at)
+                    .await
+            })
+        })
+        .await?;
+
+        self.get_allowlist_entry(identity_hash)
+            .await?
+            .context("allowlist entry after insert")
+    }
+
+    pub async fn get_allowlist_entry(
+        &self,
+        identity_hash: &IdentityHash,
+    ) -> Result<Option<AllowlistEntry>> {
+        let entry = sqlx::query_as::<_, AllowlistEntry>(
+            "SELECT identity_hash, note, role, status, expires_at, created_at, approved_at, public_key FROM acl_allowlist WHERE identity_hash = ?",
+        )
+        .bind(identity_hash.as_str())
+        .fetch_optional(&self.read_pool)
+        .await
+        .with_context(|| format!("query allowlist identity {identity_hash}"))?;
+        entry.map(|entry| self.open_allowlist_entry(entry)).transpose()
+    }
+
+    pub async fn list_allowlist_entries(
+        &self,
+        status: Option<&str>,
+        expiring_before: Option<DateTime<Utc>>,
+    ) -> Result<Vec<AllowlistEntry>> {
+        let entries = sqlx::query_as::<_, AllowlistEntry>(
+            "SELECT identity_hash, note, role, status, expires_at, created_at, approved_at, public_key FROM acl_allowlist WHERE (? IS NULL OR status = ?) AND (? IS NULL OR (expires_at IS NOT NULL AND expires_at <= ?)) ORDER BY identity_hash ASC",
+        )
+        .bind(status)
+        .bind(status)
+        .bind(expiring_before.map(CanonicalTimestamp::from))
+        .bind(expiring_before.map(CanonicalTimestamp::from))
+        .fetch_all(&self.read_pool)
+        .await
+        .context("query allowlist entries")?;
+        entries
+            .into_iter()
+            .map(|entry| self.open_allowlist_entry(entry))
+            .collect()
+    }
+
+    pub async fn approve_allowlist(
+        &self,
+        identity_hash: &IdentityHash,
+    ) -> Result<Option<AllowlistEntry>> {
+        let hash = identity_hash.clone();
+        let approved = self
+            .with_tx(move |tx| Box::pin(async move { tx.approve_allowlist(&hash).await }))
+            .await?;
+        if !approved {
+            return Ok(None);
+        }
+        self.get_allowlist_entry(identity_hash).await
+    }
+
+    pub async fn expire_allowlist(&self, now: DateTime<Utc>) -> Result<Vec<String>> {
+        self.with_tx(move |tx| Box::pin(async move { tx.expire_allowlist(now).await }))
+            .await
+    }
+
+    pub async fn is_allowlisted(
+        &self,
+        identity_hash: &IdentityHash,
+        at: DateTime<Utc>,
+    ) -> Result<bool> {
+        let found = sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM acl_allowlist WHERE identity_hash = ? AND status = 'active' AND (expires_at IS NULL OR expires_at > ?)",
+        )
+        .bind(identity_hash.as_str())
+        .bind(CanonicalTimestamp::from(at))
+        .fetch_one(&self.read_pool)
+        .await
+        .with_context(|| format!("check allowlist identity {identity_hash}"))?;
+        Ok(found > 0)
+    }
+
+    // False when the identity has no allowlist entry. `None` clears the key.
+    pub async fn set_allowlist_public_key(
+        &self,
+        identity_hash: &IdentityHash,
+        public_key: Option<&str>,
+    ) -> Result<bool> {
+        let (hash, public_key) = (identity_hash.clone(), public_key.map(str::to_string));
+        self.with_tx(move |tx| {
+            Box::pin(async move {
+                tx.set_allowlist_public_key(&hash, public_key.as_deref())
+                    .await
+            })
+        })
+        .await
+    }
+
+    fn open_allowlist_entry(&self, mut entry: AllowlistEntry) -> Result<AllowlistEntry> {
+        entry.note = entry.note.map(|note| self.open(&note)).transpose()?;
+        Ok(entry)
+    }
+
+    pub async fn delete_allowlist(&self, identity_hash: &IdentityHash) -> Result<bool> {
+        let identity_hash = identity_hash.clone();
+        self.with_tx(move |tx| Box::pin(async move { tx.delete_allowlist(&identity_hash).await }))
+            .await
+    }
+
+    pub async fn append_node_config_revision(&self, config_json: &str) -> Result<NodeConfigRevision> {
+        let config_json = config_json.to_string();
+        self.with_tx(move |tx| {
+            Box::pin(async move { tx.append_node_config_revision(&config_json, None).await })
+        })
+        .await
+    }
+
+    // A revision that says what the node did to arrive at it, one line per change.
+    pub async fn note_node_config_revision(
+        &self,
+        config_json: &str,
+        reason: &str,
+        note: &str,
+    ) -> Result<NodeConfigRevision> {
+        let now = CanonicalTimestamp::now().to_string();
+        let revision_id = sqlx::query(
+            "INSERT INTO node_config_revisions(config_json, created_at, reason, note) VALUES (?, ?, ?, ?)",
+        )
+        .bind(config_json)
+        .bind(&now)
+        .bind(reason)
+        .bind(note)
+        .execute(&self.pool)
+        .await
+        .context("insert node config revision")?
+        .last_insert_rowid();
+        Ok(NodeConfigRevision {
+            revision_id,
+            config_json: config_json.to_string(),
+            created_at: now,
+            reason: Some(reason.to_string()),
+            note: Some(note.to_string()),
+        })
+    }
+
+    pub async fn list_node_config_revisions(&self, limit: i64) -> Result<Vec<NodeConfigRevision>> {
+        sqlx::query_as::<_, NodeConfigRevision>(
+            "SELECT revision_id, config_json, created_at, reason, note FROM node_config_revisions ORDER BY revision_id DESC LIMIT ?",
+        )
+        .bind(limit)
+        .fetch_all(&self.read_pool)
+        .await
+        .context("list node config revisions")
+    }
+
+    // The staged or applied config change the control plane stored, or `None` when there is
+    // none.
+    pub async fn node_config_apply(&self) -> Result<Option<Value>> {
+        sqlx::query_scalar::<_, String>("SELECT value FROM storage_meta WHERE key = ?")
+            .bind(NODE_CONFIG_APPLY_KEY)
+            .fetch_optional(&self.pool)
+            .await
+            .context("query node config apply")?
+            .map(|raw| serde_json::from_str(&raw).context("parse node config apply"))
+            .transpose()
+    }
+
+    pub async fn set_node_config_apply(&self, apply: Option<&Value>) -> Result<()> {
+        let apply = apply.cloned();
+        self.with_tx(move |tx| {
+            Box::pin(async move { tx.set_node_config_apply(apply.as_ref()).await })
+        })
+        .await
+    }
+
+    // Appends a revision and replaces the stored config change in one transaction, so a crash
+    // never leaves a config installed without the state that would revert it.
+    pub async fn record_node_config_apply(
+        &self,
+        config_json: &str,
+        reason: &str,
+        apply: Option<&Value>,
+    ) -> Result<NodeConfigRevision> {
+        let (config_json, reason) = (config_json.to_string(), reason.to_string());
+        let apply = apply.cloned();
+        self.with_tx(move |tx| {
+            Box::pin(async move {
+                tx.set_node_config_apply(apply.as_ref()).await?;
+                tx.append_node_config_revision(&config_json, Some(&reason)).await
+            })
+        })
+        .await
+    }
+
+    pub async fn get_transfer(&self, transfer_id: &str) -> Result<Option<TransferRecord>> {
+        fetch_transfer(&self.read_pool, transfer_id)
+            .await?
+            .map(|record| open_transfer(self.cipher.as_ref(), record))
+            .transpose()
+    }
+
+    pub async fn create_transfer(&self, metadata: Value) -> Result<TransferRecord> {
+        self.with_tx(move |tx| Box::pin(async move { tx.create_transfer(&metadata).await }))
+            .await
+    }
+
+    pub async fn create_transfer_with_progress(
+        &self,
+        job_id: Option<&str>,
+        metadata: Value,
+        progress: TransferProgress,
+    ) -> Result<TransferRecord> {
+        let job_id = job_id.map(str::to_string);
+        self.with_tx(move |tx| {
+            Box::pin(async move {
+                tx.create_transfer_with_progress(job_id.as_deref(), &metadata, &progress)
+                    .await
+            })
+        })
+        .await
+    }
+
+    pub async fn update_transfer_status(
+        &self,
+        transfer_id: &str,
+        status: &str,
+        failure_reason: Option<&str>,
+    ) -> Result<()> {
+        let (transfer_id, status) = (transfer_id.to_string(), status.to_string());
+        let failure_reason = failure_reason.map(str::to_string);
+        self.with_tx(move |tx| {
+            Box::pin(async move {
+                tx.update_transfer_status(&transfer_id, &status, failure_reason.as_deref())
+                    .await
+            })
+        })
+        .await
+    }
+
+    // Stops a transfer that is still queued or sending; returns false once it has settled.
+    pub async fn cancel_transfer(&self, transfer_id: &str) -> Result<bool> {
+        let transfer_id = transfer_id.to_string();
+        self.with_tx(move |tx| Box::pin(async move { tx.cancel_transfer(&transfer_id).await }))
+            .await
+    }
+
+    pub async fn append_notification(
+        &self,
+        event_type: &str,
+        data: &Value,
+    ) -> Result<NotificationRecord> {
+        let created_at = CanonicalTimestamp::now().to_string();
+        let payload_json = serde_json::to_string(data).context("serialize notification payload")?;
+        let result = sqlx::query(
+            "INSERT INTO notifications(event_type, payload_json, created_at) VALUES (?, ?, ?)",
+        )
+        .bind(event_type)
+        .bind(payload_json)
+        .bind(&created_at)
+        .execute(&self.pool)
+        .await
+        .with_context(|| format!("insert notification {event_type}"))?;
+
+        Ok(NotificationRecord {
+            seq: result.last_insert_rowid(),
+            event_type: event_type.to_string(),
+            data: data.clone(),
+            created_at,
+        })
+    }
+
+    pub async fn list_notifications(
+        &self,
+        after_seq: i64,
+        limit: i64,
+    ) -> Result<Vec<NotificationRecord>> {
+        let rows = sqlx::query_as::<_, (i64, String, Vec<u8>, String)>(
+            "SELECT seq, event_type, CAST(payload_json AS BLOB), created_at FROM notifications WHERE seq > ? ORDER BY seq ASC LIMIT ?",
+        )
+        .bind(after_seq)
+        .bind(limit)
+        .fetch_all(&self.read_pool)
+        .await
+        .context("query notifications")?;
+
+        Ok(rows
+            .into_iter()
+            .filter_map(|(seq, event_type, payload_json, created_at)| {
+                Some(NotificationRecord {
+                    seq,
+                    event_type,
+                    data: self.parse_listed("notifications", &seq.to_string(), payload_json)?,
+                    created_at,
+                })
+            })
+            .collect())
+    }
+
+    pub async fn notification_cursor(&self, token_label: &str) -> Result<NotificationCursor> {
+        sqlx::query(
+            "INSERT INTO notification_cursors(token_label, acked_seq, dropped, updated_at) VALUES (?, 0, 0, ?) ON CONFLICT(token_label) DO NOTHING",
+        )
+        .bind(token_label)
+        .bind(CanonicalTimestamp::now())
+        .execute(&self.pool)
+        .await
+        .with_context(|| format!("insert notification cursor {token_label}"))?;
+
+        sqlx::query_as::<_, NotificationCursor>(
+            "SELECT token_label, acked_seq, dropped, updated_at FROM notification_cursors WHERE token_label = ?",
+        )
+        .bind(token_label)
+        .fetch_one(&self.pool)
+        .await
+        .with_context(|| format!("query notification cursor {token_label}"))
+    }
+
+    pub async fn ack_notifications(
+        &self,
+        token_label: &str,
+        up_to_seq: i64,
+    ) -> Result<NotificationCursor> {
+        self.notification_cursor(token_label).await?;
+        sqlx::query(
+            "UPDATE notification_cursors SET acked_seq = MAX(acked_seq, MIN(?, (SELECT COALESCE(MAX(seq), 0) FROM notifications))), dropped = 0, updated_at = ? WHERE token_label = ?",
+        )
+        .bind(up_to_seq)
+        .bind(CanonicalTimestamp::now())
+        .bind(token_label)
+        .execute(&self.pool)
+        .await
+        .with_context(|| format!("ack notifications for {token_label}"))?;
+
+        self.notification_cursor(token_label).await
+    }
+
+    // Caps one consumer's unacked backlog at `max_unacked` rows, counting the rows it skips as
+    // dropped so the next read shows an overflow marker.
+    pub async fn cap_notification_cursor(
+        &self,
+        token_label: &str,
+        max_unacked: i64,
+    ) -> Result<NotificationCursor> {
+        self.notification_cursor(token_label).await?;
+        cap_notification_cursors(&self.pool, max_unacked, Some(token_label)).await?;
+        self.notification_cursor(token_label).await
+    }
+
+    // Caps every consumer's backlog, then drops rows no consumer can still read: those past the
+    // newest `max_unacked`, and acked ones older than the retention window.
+    pub async fn trim_notifications(&self, retention_hours: i64, max_unacked: i64) -> Result<u64> {
+        let mut tx = self.pool.begin().await.context("begin notification trim")?;
+        cap_notification_cursors(&mut *tx, max_unacked, None).await?;
+
+        let overflowed = sqlx::query(
+            "DELETE FROM notifications WHERE seq <= (SELECT seq FROM notifications ORDER BY seq DESC LIMIT 1 OFFSET ?)",
+        )
+        .bind(max_unacked.max(0))
+        .execute(&mut *tx)
+        .await
+        .context("trim notification backlog")?;
+
+        let expired = sqlx::query(
+            "DELETE FROM notifications WHERE created_at < ? AND seq <= (SELECT MIN(acked_seq) FROM notification_cursors)",
+        )
+        .bind(CanonicalTimestamp::from(Utc::now() - chrono::Duration::hours(retention_hours)))
+        .execute(&mut *tx)
+        .await
+        .context("purge acked notifications")?;
+
+        tx.commit().await.context("commit notification trim")?;
+        Ok(overflowed.rows_affected() + expired.rows_affected())
+    }
+
+    pub async fn append_feed_event(&self, event_type: &str, data: &Value) -> Result<FeedEvent> {
+        write_feed_event(&self.pool, event_type, data).await
+    }
+
+    pub async fn list_feed_events(&self, after_seq: i64, limit: i64) -> Result<Vec<FeedEvent>> {
+        let rows = sqlx::query_as::<_, (i64, String, Vec<u8>, String)>(
+            "SELECT seq, event_type, CAST(payload_json AS BLOB), emitted_at FROM event_feed WHERE seq > ? ORDER BY seq ASC LIMIT ?",
+        )
+        .bind(after_seq)
+        .bind(limit)
+        .fetch_all(&self.read_pool)
+        .await
+        .context("query event feed")?;
+
+        // An unreadable payload still takes its place, so consumers never see a hole.
+        Ok(rows
+            .into_iter()
+            .map(|(seq, event_type, payload_json, emitted_at)| FeedEvent {
+                seq,
+                event_type,
+                data: self
+                    .parse_listed("event_feed", &seq.to_string(), payload_json)
+                    .unwrap_or(Value::Null),
+                emitted_at,
+            })
+            .collect())
+    }
+
+    // Read from `sqlite_sequence`, so the head survives a trim that empties the table.
+    pub async fn feed_bounds(&self) -> Result<FeedBounds> {
+        let head_seq = sqlx::query_scalar::<_, i64>(
+            "SELECT COALESCE((SELECT seq FROM sqlite_sequence WHERE name = 'event_feed'), 0)",
+        )
+        .fetch_one(&self.read_pool)
+        .await
+        .context("query event feed head")?;
+        let trimmed_through =
+            sqlx::query_scalar::<_, String>("SELECT value FROM storage_meta WHERE key = ?")
+                .bind(FEED_TRIMMED_THROUGH_KEY)
+                .fetch_optional(&self.read_pool)
+                .await
+                .context("query event feed trim point")?
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(0);
+        Ok(FeedBounds {
+            head_seq,
+            trimmed_through,
+        })
+    }
+
+    // The last feed row pushed to live subscribers, so publishing resumes there after a restart.
+    pub async fn feed_published_through(&self) -> Result<i64> {
+        Ok(
+            sqlx::query_scalar::<_, String>("SELECT value FROM storage_meta WHERE key = ?")
+                .bind(FEED_PUBLISHED_THROUGH_KEY)
+                .fetch_optional(&self.pool)
+                .await
+                .context("query event feed publish point")?
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(0),
+        )
+    }
+
+    pub async fn set_feed_published_through(&self, seq: i64) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO storage_meta(key, value) VALUES (?, ?) ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+        )
+        .bind(FEED_PUBLISHED_THROUGH_KEY)
+        .bind(seq.to_string())
+        .execute(&self.pool)
+        .await
+        .context("record event feed publish point")?;
+        Ok(())
+    }
+
+    // Drops events emitted before `cutoff`. Only a prefix of the feed is ever removed, and the
+    // trim point moves with it in the same transaction.
+    pub async fn trim_event_feed(&self, cutoff: DateTime<Utc>) -> Result<u64> {
+        let mut tx = self.pool.begin().await.context("begin event feed trim")?;
+        let through = sqlx::query_scalar::<_, Option<i64>>(
+            "SELECT MAX(seq) FROM event_feed WHERE seq < COALESCE((SELECT MIN(seq) FROM event_feed WHERE emitted_at >= ?), (SELECT MAX(seq) + 1 FROM event_feed))",
+        )
+        .bind(CanonicalTimestamp::from(cutoff))
+        .fetch_one(&mut *tx)
+        .await
+        .context("query event feed trim point")?;
+        let Some(through) = through else {
+            return Ok(0);
+        };
+        let trimmed = sqlx::query("DELETE FROM event_feed WHERE seq <= ?")
+            .bind(through)
+            .execute(&mut *tx)
+            .await
+            .context("trim event feed")?;
+        sqlx::query(
+            "INSERT INTO storage_meta(key, value) VALUES (?, ?) ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+        )
+        .bind(FEED_TRIMMED_THROUGH_KEY)
+        .bind(through.to_string())
+        .execute(&mut *tx)
+        .await
+        .context("record event feed trim point")?;
+        tx.commit().await.context("commit event feed trim")?;
+        Ok(trimmed.rows_affected())
+    }
+
+    // Read in one transaction, so rows appended meanwhile can't show up as a gap.
+    pub async fn check_event_feed(&self) -> Result<FeedIntegrity> {
+        let mut tx = self.pool.begin().await.context("begin event feed check")?;
+        let head_seq = sqlx::query_scalar::<_, i64>(
+            "SELECT COALESCE((SELECT seq FROM sqlite_sequence WHERE name = 'event_feed'), 0)",
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .context("query event feed head")?;
+        let trimmed_through = read_feed_mark(&mut *tx, FEED_TRIMMED_THROUGH_KEY).await?;
+        let published_through = read_feed_mark(&mut *tx, FEED_PUBLISHED_THROUGH_KEY).await?;
+        let (min_seq, max_seq) = sqlx::query_as::<_, (Option<i64>, Option<i64>)>(
+            "SELECT MIN(seq), MAX(seq) FROM event_feed WHERE seq > ?",
+        )
+        .bind(trimmed_through)
+        .fetch_one(&mut *tx)
+        .await
+        .context("query event feed extent")?;
+        let inner = sqlx::query_as::<_, (i64, i64)>(
+            "SELECT seq + 1, next - 1 FROM (SELECT seq, LEAD(seq) OVER (ORDER BY seq) AS next FROM event_feed WHERE seq > ?) WHERE next > seq + 1 ORDER BY seq",
+        )
+        .bind(trimmed_through)
+        .fetch_all(&mut *tx)
+        .await
+        .context("query event feed gaps")?;
+        tx.commit().await.context("commit event feed check")?;
+
+        let top = head_seq.max(max_seq.unwrap_or(0));
+        let mut gaps = Vec::new();
+        match (min_seq, max_seq) {
+            (Some(min_seq), Some(max_seq)) => {
+                if min_seq > trimmed_through + 1 {
+                    gaps.push((trimmed_through + 1, min_seq - 1));
+                }
+                gaps.extend(inner);
+                if top > max_seq {
+                    gaps.push((max_seq + 1, top));
+                }
+            }
+            _ if top > trimmed_through => gaps.push((trimmed_through + 1, top)),
+            _ => {}
+        }
+        Ok(FeedIntegrity {
+            head_seq,
+            max_seq: max_seq.unwrap_or(0),
+            trimmed_through,
+            published_through,
+            gaps,
+        })
+    }
+
+    // Only ever raised: the head is the last sequence number handed out.
+    pub async fn raise_event_feed_head(&self, seq: i64) -> Result<()> {
+        let mut tx = self.pool.begin().await.context("begin event feed head update")?;
+        let updated =
+            sqlx::query("UPDATE sqlite_sequence SET seq = MAX(seq, ?) WHERE name = 'event_feed'")
+                .bind(seq)
+                .execute(&mut *tx)
+                .await
+                .context("raise event feed head")?;
+        if updated.rows_affected() == 0 {
+            sqlx::query("INSERT INTO sqlite_sequence(name, seq) VALUES ('event_feed', ?)")
+                .bind(seq)
+                .execute(&mut *tx)
+                .await
+                .context("record event feed head")?;
+        }
+        tx.commit().await.context("commit event feed head update")?;
+        Ok(())
+    }
+
+    // Moves every row after the trim point onto consecutive sequence numbers, oldest first, and
+    // sets the head and publish point to match. Consumers holding a sequence number past a gap
+    // will skip or repeat events, which is why callers must ask for it explicitly.
+    pub async fn renumber_event_feed(&self) -> Result<u64> {
+        let mut tx = self.pool.begin().await.context("begin event feed renumbering")?;
+        let trimmed_through = read_feed_mark(&mut *tx, FEED_TRIMMED_THROUGH_KEY).await?;
+        let published_through = read_feed_mark(&mut *tx, FEED_PUBLISHED_THROUGH_KEY).await?;
+        let seqs = sqlx::query_scalar::<_, i64>("SELECT seq FROM event_feed WHERE seq > ? ORDER BY seq")
+            .bind(trimmed_through)
+            .fetch_all(&mut *tx)
+            .await
+            .context("query event feed rows")?;
+        let mut moved = 0;
+        let mut published = trimmed_through;
+        for (offset, seq) in seqs.iter().enumerate() {
+            let renumbered = trimmed_through + 1 + offset as i64;
+            if *seq <= published_through {
+                published = renumbered;
+            }
+            if renumbered == *seq {
+                continue;
+            }
+            sqlx::query("UPDATE event_feed SET seq = ? WHERE seq = ?")
+                .bind(renumbered)
+                .bind(seq)
+                .execute(&mut *tx)
+                .await
+                .with_context(|| format!("renumber event feed row {seq}"))?;
+            moved += 1;
+        }
+        let head = trimmed_through + seqs.len() as i64;
+        sqlx::query("UPDATE sqlite_sequence SET seq = ? WHERE name = 'event_feed'")
+            .bind(head)
+            .execute(&mut *tx)
+            .await
+            .context("reset event feed head")?;
+        sqlx::query(
+            "INSERT INTO storage_meta(key, value) VALUES (?, ?) ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+        )
+        .bind(FEED_PUBLISHED_THROUGH_KEY)
+        .bind(published.to_string())
+        .execute(&mut *tx)
+        .await
+        .context("move event feed publish point")?;
+        tx.commit().await.context("commit event feed renumbering")?;
+        Ok(moved)
+    }
+
+    // The head is read from `sqlite_sequence`, since retention may have emptied the table.
+    pub async fn list_cursors_ahead(&self) -> Result<Vec<CursorAhead>> {
+        sqlx::query_as::<_, CursorAhead>(
+            "SELECT token_label, acked_seq, head_seq FROM notification_cursors, (SELECT COALESCE((SELECT seq FROM sqlite_sequence WHERE name = 'notifications'), (SELECT MAX(seq) FROM notifications), 0) AS head_seq) WHERE acked_seq > head_seq ORDER BY token_label",
+        )
+        .fetch_all(&self.pool)
+        .await
+        .context("query notification cursors ahead of the head")
+    }
+
+    pub async fn rewind_notification_cursor(&self, token_label: &str, seq: i64) -> Result<()> {
+        sqlx::query(
+            "UPDATE notification_cursors SET acked_seq = ?, updated_at = ? WHERE token_label = ? AND acked_seq > ?",
+        )
+        .bind(seq)
+        .bind(CanonicalTimestamp::now())
+        .bind(token_label)
+        .bind(seq)
+        .execute(&self.pool)
+        .await
+        .with_context(|| format!("rewind notification cursor {token_label}"))?;
+        Ok(())
+    }
+
+    pub async fn init_transfer_progress(
+        &self,
+        transfer_id: &str,
+        progress: &TransferProgress,
+    ) -> Result<()> {
+        write_transfer_progress(&self.pool, transfer_id, progress).await
+    }
+
+    pub async fn record_transfer_chunk(&self, transfer_id: &str, bytes: u64) -> Result<()> {
+        let transfer_id = transfer_id.to_string();
+        self.with_tx(move |tx| {
+            Box::pin(async move { tx.record_transfer_chunk(&transfer_id, bytes).await })
+        })
+        .await
+    }
+
+    pub async fn get_transfer_progress(
+        &self,
+        transfer_id: &str,
+        stalled_before: &str,
+    ) -> Result<Option<TransferProgress>> {
+        let row = sqlx::query_as::<_, (i64, i64, i64, i64, Option<String>, bool)>(
+            "SELECT p.bytes_total, p.bytes_sent, p.chunks_total, p.chunks_sent, p.last_chunk_at, t.status = 'running' AND COALESCE(p.last_chunk_at, t.updated_at) < ? FROM transfer_progress p JOIN transfers t ON t.transfer_id = p.transfer_id WHERE p.transfer_id = ?",
+        )
+        .bind(CanonicalTimestamp::parse(stalled_before)?)
+        .bind(transfer_id)
+        .fetch_optional(&self.read_pool)
+        .await
+        .with_context(|| format!("query transfer progress {transfer_id}"))?;
+
+        Ok(row.map(
+            |(bytes_total, bytes_sent, chunks_total, chunks_sent, last_chunk_at, stalled)| {
+                TransferProgress {
+                    bytes_total: bytes_total as u64,
+                    bytes_sent: bytes_sent as u64,
+                    chunks_total: chunks_total as u64,
+                    chunks_sent: chunks_sent as u64,
+                    last_chunk_at,
+                    stalled,
+                }
+            },
+        ))
+    }
+
+    pub async fn list_job_transfers(&self, job_id: &str) -> Result<Vec<TransferRecord>> {
+        let records = sqlx::query_as::<_, TransferRecord>(
+            "SELECT transfer_id, status, metadata_json, submitted_at, updated_at, failure_reason, job_id FROM transfers WHERE job_id = ? ORDER BY submitted_at, transfer_id",
+        )
+        .bind(job_id)
+        .fetch_all(&self.read_pool)
+        .await
+        .with_context(|| format!("query transfers for job {job_id}"))?;
+
+        records
+            .into_iter()
+            .map(|mut record| {
+                record.metadata_json = self.open(&record.metadata_json)?;
+                Ok(record)
+            })
+            .collect()
+    }
+
+    pub async fn list_transfers(
+        &self,
+        stalled_before: Option<&str>,
+        limit: i64,
+    ) -> Result<Vec<TransferRecord>> {
+        self.list_transfers_page(stalled_before, &[], &Keyset::first(limit))
+            .await
+    }
+
+    // In `TRANSFER_ORDER`, nearest the page's key first; only transfers carrying every one of
+    // `labels` are listed.
+    pub async fn list_transfers_page(
+        &self,
+        stalled_before: Option<&str>,
+        labels: &[(String, String)],
+        page: &Keyset,
+    ) -> Result<Vec<TransferRecord>> {
+        let predicate = TRANSFER_ORDER.predicate(page)?;
+        let order_by = TRANSFER_ORDER.order_by(page.direction);
+        let labelled = label_filter("transfer_id", labels);
+        let records = match stalled_before {
+            Some(cutoff) => {
+                let sql = format!(
+                    "SELECT * FROM (SELECT t.transfer_id, t.status, t.metadata_json, t.submitted_at, t.updated_at, t.failure_reason, t.job_id FROM transfers t JOIN transfer_progress p ON p.transfer_id = t.transfer_id WHERE t.status = 'running' AND COALESCE(p.last_chunk_at, t.updated_at) < ?) WHERE {predicate}{labelled} {order_by} LIMIT ?"
+                );
+                let query = sqlx::query_as::<_, TransferRecord>(&sql)
+                    .bind(CanonicalTimestamp::parse(cutoff)?);
+                let query = TRANSFER_ORDER.bind(query, page);
+                bind_label_filter(query, LABEL_SUBJECT_TRANSFER, labels)
+                    .bind(page.limit)
+                    .fetch_all(&self.read_pool)
+                    .await
+                    .context("query stalled transfers")?
+            }
+            None => {
+                let sql = format!(
+                    "SELECT transfer_id, status, metadata_json, submitted_at, updated_at, failure_reason, job_id FROM transfers WHERE {predicate}{labelled} {order_by} LIMIT ?"
+                );
+                let query = TRANSFER_ORDER.bind(sqlx::query_as::<_, TransferRecord>(&sql), page);
+                bind_label_filter(query, LABEL_SUBJECT_TRANSFER, labels)
+                    .bind(page.limit)
+                    .fetch_all(&self.read_pool)
+                    .await
+                    .context("query transfers")?
+            }
+        };
+
+        records
+            .into_iter()
+            .map(|mut record| {
+                record.metadata_json = self.open(&record.metadata_json)?;
+                Ok(record)
+            })
+            .collect()
+    }
+
+    pub async fn record_transfer_dedup(&self, dedup: &TransferDedup) -> Result<()> {
+        let remote_ack = dedup.remote_ack.as_ref().map(Value::to_string);
+        sqlx::query(
+            "INSERT OR REPLACE INTO transfer_dedup(transfer_id, sha256, outcome, bytes_saved, remote_ack_json, decided_at) VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(&dedup.transfer_id)
+        .bind(&dedup.sha256)
+        .bind(&dedup.outcome)
+        .bind(dedup.bytes_saved)
+        .bind(remote_ack)
+        .bind(CanonicalTimestamp::parse(&dedup.decided_at)?)
+        .execute(&self.pool)
+        .await
+        .with_context(|| format!("record dedup outcome of transfer {}", dedup.transfer_id))?;
+        Ok(())
+    }
+
+    pub async fn get_transfer_dedup(&self, transfer_id: &str) -> Result<Option<TransferDedup>> {
+        let row = sqlx::query_as::<_, (String, String, i64, Option<String>, String)>(
+            "SELECT sha256, outcome, bytes_saved, remote_ack_json, decided_at FROM transfer_dedup WHERE transfer_id = ?",
+        )
+        .bind(transfer_id)
+        .fetch_optional(&self.read_pool)
+        .await
+        .with_context(|| format!("query dedup outcome of transfer {transfer_id}"))?;
+        let Some((sha256, outcome, bytes_saved, remote_ack, decided_at)) = row else {
+            return Ok(None);
+        };
+        Ok(Some(TransferDedup {
+            transfer_id: transfer_id.to_string(),
+            sha256,
+            outcome,
+            bytes_saved,
+            remote_ack: remote_ack
+                .as_deref()
+                .map(serde_json::from_str)
+                .transpose()
+                .context("parse transfer dedup acknowledgment")?,
+            decided_at,
+        }))
+    }
+
+    // Records a file without its content, as when a peer reports one this node already holds.
+    pub async fn record_received_file(&self, file: &ReceivedFile) -> Result<()> {
+        write_received_file(&self.pool, None, file, None).await
+    }
+
+    // The receiving side of a bundle: one transfer row for the bundle, its members, and a
+    // `received_files` row for each member that arrived intact, all or nothing.
+    pub async fn record_received_bundle(
+        &self,
+        metadata: Value,
+        status: &str,
+        failure_reason: Option<&str>,
+        members: Vec<BundleMember>,
+        files: Vec<(ReceivedFile, Vec<u8>)>,
+    ) -> Result<TransferRecord> {
+        let status = status.to_string();
+        let failure_reason = failure_reason.map(str::to_string);
+        self.with_tx(move |tx| {
+            Box::pin(async move {
+                tx.record_received_bundle(
+                    &metadata,
+                    &status,
+                    failure_reason.as_deref(),
+                    &members,
+                    files,
+                )
+                .await
+            })
+        })
+        .await
+    }
+
+    pub async fn list_bundle_members(&self, bundle_id: &str) -> Result<Vec<BundleMember>> {
+        sqlx::query_as::<_, BundleMember>(
+            "SELECT position, name, media_type, size_bytes, sha256, status FROM bundle_members WHERE bundle_id = ? ORDER BY position",
+        )
+        .bind(bundle_id)
+        .fetch_all(&self.read_pool)
+        .await
+        .with_context(|| format!("query members of bundle {bundle_id}"))
+    }
+
+    pub async fn find_received_file(
+        &self,
+        sha256: &str,
+        size_bytes: i64,
+    ) -> Result<Option<ReceivedFile>> {
+        sqlx::query_as::<_, ReceivedFile>(
+            "SELECT sha256, size_bytes, file_name, source_identity, received_at, bundle_id, media_type, quarantine_reason FROM received_files WHERE sha256 = ? AND size_bytes = ?",
+        )
+        .bind(sha256)
+        .bind(size_bytes)
+        .fetch_optional(&self.read_pool)
+        .await
+        .with_context(|| format!("query received file {sha256}"))
+    }
+
+    // Newest first; quarantined files only when asked for.
+    pub async fn list_received_files(
+        &self,
+        include_quarantined: bool,
+        limit: i64,
+    ) -> Result<Vec<ReceivedFile>> {
+        self.list_received_files_page(include_quarantined, &Keyset::first(limit))
+            .await
+    }
+
+    // In `RECEIVED_FILE_ORDER`, nearest the page's key first.
+    pub async fn list_received_files_page(
+        &self,
+        include_quarantined: bool,
+        page: &Keyset,
+    ) -> Result<Vec<ReceivedFile>> {
+        let sql = format!(
+            "SELECT sha256, size_bytes, file_name, source_identity, received_at, bundle_id, media_type, quarantine_reason FROM received_files WHERE (? OR quarantine_reason IS NULL) AND {} {} LIMIT ?",
+            RECEIVED_FILE_ORDER.predicate(page)?,
+            RECEIVED_FILE_ORDER.order_by(page.direction)
+        );
+        let query = sqlx::query_as::<_, ReceivedFile>(&sql).bind(include_quarantined);
+        RECEIVED_FILE_ORDER
+            .bind(query, page)
+            .bind(page.limit)
+            .fetch_all(&self.read_pool)
+            .await
+            .context("query received files")
+    }
+
+    // The file and its content, which is `None` for a file recorded without one.
+    pub async fn received_file_content(
+        &self,
+        sha256: &str,
+    ) -> Result<Option<(ReceivedFile, Option<Vec<u8>>)>> {
+        let row = sqlx::query(
+            "SELECT sha256, size_bytes, file_name, source_identity, received_at, bundle_id, media_type, quarantine_reason, content_base64 FROM received_files WHERE sha256 = ? ORDER BY received_at DESC LIMIT 1",
+        )
+        .bind(sha256)
+        .fetch_optional(&self.read_pool)
+        .await
+        .with_context(|| format!("query received file {sha256}"))?;
+        let Some(row) = row else {
+            return Ok(None);
+        };
+        let file = ReceivedFile::from_row(&row).context("read received file")?;
+        let content = row
+            .try_get::<Option<String>, _>("content_base64")
+            .context("read received file content")?
+            .map(|stored| -> Result<Vec<u8>> {
+                STANDARD
+                    .decode(self.open(&stored)?)
+                    .map_err(|err| StorageError::corrupt("received_files", err.to_string()))
+            })
+            .transpose()?;
+        Ok(Some((file, content)))
+    }
+
+    // Adds `bytes` to the subject's hourly bucket starting at `used_at`.
+    pub async fn record_quota_usage(
+        &self,
+        subject_kind: &str,
+        subject: &str,
+        used_at: &str,
+        bytes: i64,
+    ) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO quota_usage(subject_kind, subject, used_at, bytes) VALUES (?, ?, ?, ?) ON CONFLICT(subject_kind, subject, used_at) DO UPDATE SET bytes = bytes + excluded.bytes",
+        )
+        .bind(subject_kind)
+        .bind(subject)
+        .bind(CanonicalTimestamp::parse(used_at)?)
+        .bind(bytes)
+        .execute(&self.pool)
+        .await
+        .with_context(|| format!("record quota usage for {subject_kind} {subject}"))?;
+        Ok(())
+    }
+
+    // Usage charged after `since`, oldest first within each subject.
+    pub async fn list_quota_usage(&self, since: &str) -> Result<Vec<QuotaUsage>> {
+        sqlx::query_as::<_, QuotaUsage>(
+            "SELECT subject_kind, subject, used_at, bytes FROM quota_usage WHERE used_at > ? ORDER BY subject_kind, subject, used_at",
+        )
+        .bind(CanonicalTimestamp::parse(since)?)
+        .fetch_all(&self.read_pool)
+        .await
+        .context("list quota usage")
+    }
+
+    pub async fn list_subject_quota_usage(
+        &self,
+        subject_kind: &str,
+        subject: &str,
+        since: &str,
+    ) -> Result<Vec<QuotaUsage>> {
+        sqlx::query_as::<_, QuotaUsage>(
+            "SELECT subject_kind, subject, used_at, bytes FROM quota_usage WHERE subject_kind = ? AND subject = ? AND used_at > ? ORDER BY used_at",
+        )
+        .bind(subject_kind)
+        .bind(subject)
+        .bind(CanonicalTimestamp::parse(since)?)
+        .fetch_all(&self.read_pool)
+        .await
+        .with_context(|| format!("list quota usage for {subject_kind} {subject}"))
+    }
+
+    pub async fn list_quota_reservations(&self) -> Result<Vec<QuotaReservation>> {
+        sqlx::query_as::<_, QuotaReservation>(
+            "SELECT transfer_id, subject_kind, subject, bytes, reserved_at FROM quota_reservations ORDER BY subject_kind, subject, reserved_at",
+        )
+        .fetch_all(&self.read_pool)
+        .await
+        .context("list quota reservations")
+    }
+
+    pub async fn reserved_quota_bytes(&self, subject_kind: &str, subject: &str) -> Result<i64> {
+        sqlx::query_scalar::<_, i64>(
+            "SELECT COALESCE(SUM(bytes), 0) FROM quota_reservations WHERE subject_kind = ? AND subject = ?",
+        )
+        .bind(subject_kind)
+        .bind(subject)
+        .fetch_one(&self.read_pool)
+        .await
+        .with_context(|| format!("sum quota reservations for {subject_kind} {subject}"))
+    }
+
+    // Transfers that succeeded after `since`, by when they did. Sources are those recorded at
+    // submission; older transfers have none.
+    pub async fn list_completed_transfers(&self, since: &str) -> Result<Vec<CompletedTransfer>> {
+        let rows = sqlx::query(
+            "SELECT t.transfer_id, t.status, t.metadata_json, t.submitted_at, t.updated_at, t.failure_reason, t.job_id, COALESCE(p.bytes_total, 0) AS bytes, t.source_token, t.source_addr, t.source_client_id FROM transfers t LEFT JOIN transfer_progress p ON p.transfer_id = t.transfer_id WHERE t.status = 'success' AND t.updated_at > ? ORDER BY t.updated_at, t.transfer_id",
+        )
+        .bind(CanonicalTimestamp::parse(since)?)
+        .fetch_all(&self.read_pool)
+        .await
+        .context("query completed transfers")?;
+        rows.into_iter()
+            .map(|row| {
+                let transfer = open_transfer(
+                    self.cipher.as_ref(),
+                    TransferRecord::from_row(&row).context("read completed transfer")?,
+                )?;
+                let source = row
+                    .try_get::<Option<String>, _>("source_token")
+                    .context("read transfer source")?
+                    .map(|token_label| -> Result<SubmissionSource> {
+                        Ok(SubmissionSource {
+                            token_label,
+                            remote_addr: row.try_get("source_addr").context("read transfer source")?,
+                            client_id: row
+                                .try_get("source_client_id")
+                                .context("read transfer source")?,
+                        })
+                    })
+                    .transpose()?;
+                Ok(CompletedTransfer {
+                    transfer,
+                    bytes: row.try_get("bytes").context("read transfer size")?,
+                    source,
+                })
+            })
+            .collect()
+    }
+
+    // Replaces all usage charged after `after` with `usage`, in one transaction.
+    pub async fn replace_quota_usage(&self, after: &str, usage: &[QuotaUsage]) -> Result<()> {
+        let mut tx = self.pool.begin().await.context("begin quota usage rebuild")?;
+        sqlx::query("DELETE FROM quota_usage WHERE used_at > ?")
+            .bind(CanonicalTimestamp::parse(after)?)
+            .execute(&mut *tx)
+            .await
+            .context("clear quota usage")?;
+        for charge in usage {
+            sqlx::query(
+                "INSERT INTO quota_usage(subject_kind, subject, used_at, bytes) VALUES (?, ?, ?, ?)",
+            )
+            .bind(&charge.subject_kind)
+            .bind(&charge.subject)
+            .bind(CanonicalTimestamp::parse(&charge.used_at)?)
+            .bind(charge.bytes)
+            .execute(&mut *tx)
+            .await
+            .context("insert quota usage")?;
+        }
+        tx.commit().await.context("commit quota usage rebuild")?;
+        Ok(())
+    }
+
+    pub async fn purge_quota_usage(&self, through: &str) -> Result<u64> {
+        let purged = sqlx::query("DELETE FROM quota_usage WHERE used_at <= ?")
+            .bind(CanonicalTimestamp::parse(through)?)
+            .execute(&self.pool)
+            .await
+            .context("purge quota usage")?;
+        Ok(purged.rows_affected())
+    }
+
+    pub async fn put_quota_override(
+        &self,
+        subject_kind: &str,
+        subject: &str,
+        max_bytes: i64,
+        note: Option<&str>,
+    ) -> Result<QuotaOverride> {
+        let (subject_kind, subject) = (subject_kind.to_string(), subject.to_string());
+        let note = note.map(str::to_string);
+        self.with_tx(move |tx| {
+            Box::pin(async move {
+                tx.put_quota_override(&subject_kind, &subject, max_bytes, note.as_deref())
+                    .await
+            })
+        })
+        .await
+    }
+
+    pub async fn get_quota_override(
+        &self,
+        subject_kind: &str,
+        subject: &str,
+    ) -> Result<Option<QuotaOverride>> {
+        sqlx::query_as::<_, QuotaOverride>(
+            "SELECT subject_kind, subject, max_bytes, note, updated_at FROM quota_overrides WHERE subject_kind = ? AND subject = ?",
+        )
+        .bind(subject_kind)
+        .bind(subject)
+        .fetch_optional(&self.read_pool)
+        .await
+        .with_context(|| format!("query quota override for {subject_kind} {subject}"))
+    }
+
+    pub async fn list_quota_overrides(&self) -> Result<Vec<QuotaOverride>> {
+        sqlx::query_as::<_, QuotaOverride>(
+            "SELECT subject_kind, subject, max_bytes, note, updated_at FROM quota_overrides ORDER BY subject_kind, subject",
+        )
+        .fetch_all(&self.read_pool)
+        .await
+        .context("list quota overrides")
+    }
+
+    // The mute window the control plane stored, or `None` while the node is not muted.
+    pub async fn node_mute(&self) -> Result<Option<Value>> {
+        sqlx::query_scalar::<_, String>("SELECT value FROM storage_meta WHERE key = ?")
+            .bind(NODE_MUTE_KEY)
+            .fetch_optional(&self.pool)
+            .await
+            .context("query node mute")?
+            .map(|raw| serde_json::from_str(&raw).context("parse node mute"))
+            .transpose()
+    }
+
+    pub async fn set_node_mute(&self, mute: Option<&Value>) -> Result<()> {
+        let mute = mute.cloned();
+        self.with_tx(move |tx| Box::pin(async move { tx.set_node_mute(mute.as_ref()).await }))
+            .await
+    }
+
+    // Adds the flags that have no row yet; flags already stored keep their value.
+    pub async fn seed_feature_flags(
+        &self,
+        defaults: &[(&str, bool)],
+        seeded_by: &str,
+    ) -> Result<u64> {
+        let mut tx = self.pool.begin().await.context("begin feature flag seed")?;
+        let mut seeded = 0;
+        for (name, enabled) in defaults {
+            seeded += sqlx::query(
+                "INSERT INTO feature_flags(name, enabled, variant_json, updated_at, updated_by) VALUES (?, ?, NULL, ?, ?) ON CONFLICT(name) DO NOTHING",
+            )
+            .bind(name)
+            .bind(enabled)
+            .bind(CanonicalTimestamp::now())
+            .bind(seeded_by)
+            .execute(&mut *tx)
+            .await
+            .with_context(|| format!("seed feature flag {name}"))?
+            .rows_affected();
+        }
+        tx.commit().await.context("commit feature flag seed")?;
+        Ok(seeded)
+    }
+
+    pub async fn list_feature_flags(&self) -> Result<Vec<FeatureFlagRecord>> {
+        let rows = sqlx::query_as::<_, (String, bool, Option<String>, String, String)>(
+            "SELECT name, enabled, variant_json, updated_at, updated_by FROM feature_flags ORDER BY name",
+        )
+        .fetch_all(&self.read_pool)
+        .await
+        .context("list feature flags")?;
+        rows.into_iter().map(feature_flag_from_row).collect()
+    }
+
+    pub async fn put_feature_flag(
+        &self,
+        name: &str,
+        enabled: bool,
+        variant: Option<&Value>,
+        updated_by: &str,
+    ) -> Result<FeatureFlagRecord> {
+        let (name, updated_by) = (name.to_string(), updated_by.to_string());
+        let variant = variant.cloned();
+        self.with_tx(move |tx| {
+            Box::pin(async move {
+                tx.put_feature_flag(&name, enabled, variant.as_ref(), &updated_by)
+                    .await
+            })
+        })
+        .await
+    }
+
+    // Records the first receipt of an envelope. The insert is the check, so of two consumers
+    // racing on the same envelope exactly one gets `None`; the other gets the earlier receipt.
+    pub async fn record_seen_message(
+        &self,
+        source_identity: &str,
+        message_id: &str,
+        sent_at: &str,
+        received_at: &str,
+    ) -> Result<Option<SeenMessage>> {
+        let inserted = sqlx::query(
+            "INSERT INTO seen_messages(source_identity, message_id, sent_at, received_at) VALUES (?, ?, ?, ?) ON CONFLICT(source_identity, message_id) DO NOTHING",
+        )
+        .bind(source_identity)
+        .bind(message_id)
+        .bind(CanonicalTimestamp::parse(sent_at)?)
+        .bind(CanonicalTimestamp::parse(received_at)?)
+        .execute(&self.pool)
+        .await
+        .with_context(|| format!("record seen message {message_id} from {source_identity}"))?;
+        if inserted.rows_affected() == 1 {
+            return Ok(None);
+        }
+        sqlx::query_as::<_, SeenMessage>(
+            "SELECT source_identity, message_id, sent_at, received_at FROM seen_messages WHERE source_identity = ? AND message_id = ?",
+        )
+        .bind(source_identity)
+        .bind(message_id)
+        .fetch_one(&self.pool)
+        .await
+        .map(Some)
+        .with_context(|| format!("query seen message {message_id} from {source_identity}"))
+    }
+
+    pub async fn purge_seen_messages(&self, sent_before: &str) -> Result<u64> {
+        let purged = sqlx::query("DELETE FROM seen_messages WHERE sent_at < ?")
+            .bind(CanonicalTimestamp::parse(sent_before)?)
+            .execute(&self.pool)
+            .await
+            .context("purge seen messages")?;
+        Ok(purged.rows_affected())
+    }
+
+    pub async fn queue_outbox(
+        &self,
+        kind: &str,
+        message_id: &str,
+        operation: &str,
+        destination_identity: &str,
+        envelope: &Value,
+    ) -> Result<()> {
+        let kind = kind.to_string();
+        let message_id = message_id.to_string();
+        let operation = operation.to_string();
+        let destination_identity = destination_identity.to_string();
+        let envelope = envelope.clone();
+        self.with_tx(move |tx| {
+            Box::pin(async move {
+                tx.queue_outbox(
+                    &kind,
+                    &message_id,
+                    None,
+                    &operation,
+                    &destination_identity,
+                    &envelope,
+                )
+                .await
+            })
+        })
+        .await
+    }
+
+    // Pending entries oldest first. A command is left out once its job is no longer in
+    // transit, since a result has already settled it.
+    pub async fn list_pending_outbox(
+        &self,
+        since: Option<DateTime<Utc>>,
+    ) -> Result<Vec<OutboxEntry>> {
+        let rows = sqlx::query_as::<_, OutboxRow>(
+            "SELECT message_id, kind, job_id, operation, destination_identity, CAST(envelope_json AS BLOB) AS envelope_json, status, queued_at, bundle_id, exported_at FROM outbox WHERE status = 'pending' AND (?1 IS NULL OR queued_at >= ?1) AND (job_id IS NULL OR job_id IN (SELECT job_id FROM jobs WHERE status = 'in_transit')) ORDER BY queued_at, message_id",
+        )
+        .bind(since.map(CanonicalTimestamp::from))
+        .fetch_all(&self.read_pool)
+        .await
+        .context("query pending outbox")?;
+        Ok(rows
+            .into_iter()
+            .filter_map(|row| {
+                let envelope = self.parse_listed("outbox", &row.message_id, row.envelope_json)?;
+                Some(OutboxEntry {
+                    message_id: row.message_id,
+                    kind: row.kind,
+                    job_id: row.job_id,
+                    operation: row.operation,
+                    destination_identity: row.destination_identity,
+                    envelope,
+                    status: row.status,
+                    queued_at: row.queued_at,
+                    bundle_id: row.bundle_id,
+                    exported_at: row.exported_at,
+                })
+            })
+            .collect())
+    }
+
+    // Cached event envelopes sent at or after `since`, oldest first, for callers paging forward
+    // through the cache by advancing `since` to the last envelope's `sent_at`.
+    pub async fn list_cached_events_sent_since(
+        &self,
+        since: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<Vec<Value>> {
+        let rows = sqlx::query_as::<_, (String, Vec<u8>)>(
+            "SELECT event_id, CAST(payload_json AS BLOB) FROM cached_events WHERE sent_at >= ? ORDER BY sent_at, event_id LIMIT ?",
+        )
+        .bind(CanonicalTimestamp::from(since))
+        .bind(limit)
+        .fetch_all(&self.read_pool)
+        .await
+        .context("query cached events sent since")?;
+        self.count_payload_reads(rows.len());
+
+        Ok(rows
+            .into_iter()
+            .filter_map(|(key, row)| self.parse_listed("cached_events", &key, row))
+            .collect())
+    }
+
+    // Cached event envelopes received at or after `since`, oldest first.
+    pub async fn list_cached_events_since(
+        &self,
+        since: Option<DateTime<Utc>>,
+        limit: i64,
+    ) -> Result<Vec<Value>> {
+        let rows = sqlx::query_as::<_, (String, Vec<u8>)>(
+            "SELECT event_id, CAST(payload_json AS BLOB) FROM cached_events WHERE ?1 IS NULL OR received_at >= ?1 ORDER BY received_at, event_id LIMIT ?2",
+        )
+        .bind(since.map(CanonicalTimestamp::from))
+        .bind(limit)
+        .fetch_all(&self.read_pool)
+        .await
+        .context("query cached events since")?;
+
+        Ok(rows
+            .into_iter()
+            .filter_map(|(key, row)| self.parse_listed("cached_events", &key, row))
+            .collect())
+    }
+
+    pub async fn claim_stalled_transfers(&self, stalled_before: &str) -> Result<Vec<String>> {
+        let stalled_before = stalled_before.to_string();
+        self.with_tx(move |tx| {
+            Box::pin(async move { tx.claim_stalled_transfers(&stalled_before).await })
+        })
+        .await
+    }
+
+    // Every lease opens a `job_attempts` row, so the attempt count survives lost workers.
+    pub async fn acquire_job_lease(
+        &self,
+        job_id: &str,
+        worker_id: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<JobLease> {
+        let mut tx = self.pool.begin().await.context("begin job lease")?;
+        let attempt = sqlx::query_scalar::<_, i64>(
+            "SELECT COALESCE(MAX(attempt_no), 0) + 1 FROM job_attempts WHERE job_id = ?",
+        )
+        .bind(job_id)
+        .fetch_one(&mut *tx)
+        .await
+        .with_context(|| format!("count attempts for job {job_id}"))?;
+        sqlx::query(
+            "INSERT INTO job_attempts(job_id, attempt_no, started_at, status) VALUES (?, ?, ?, 'running')",
+        )
+        .bind(job_id)
+        .bind(attempt)
+        .bind(CanonicalTimestamp::now())
+        .execute(&mut *tx)
+        .await
+        .with_context(|| format!("record attempt {attempt} for job {job_id}"))?;
+        let lease = sqlx::query_as::<_, JobLease>(
+            "INSERT INTO job_leases(job_id, worker_id, attempt, lease_expires_at) VALUES (?, ?, ?, ?) ON CONFLICT(job_id) DO UPDATE SET worker_id = excluded.worker_id, attempt = excluded.attempt, lease_expires_at = excluded.lease_expires_at RETURNING job_id, worker_id, attempt, lease_expires_at",
+        )
+        .bind(job_id)
+        .bind(worker_id)
+        .bind(attempt)
+        .bind(CanonicalTimestamp::from(expires_at))
+        .fetch_one(&mut *tx)
+        .await
+        .with_context(|| format!("lease job {job_id}"))?;
+        tx.commit().await.context("commit job lease")?;
+        Ok(lease)
+    }
+
+    // False once the lease has been claimed by the watchdog or taken by another worker.
+    pub async fn renew_job_lease(
+        &self,
+        job_id: &str,
+        worker_id: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<bool> {
+        let renewed = sqlx::query(
+            "UPDATE job_leases SET lease_expires_at = ? WHERE job_id = ? AND worker_id = ?",
+        )
+        .bind(CanonicalTimestamp::from(expires_at))
+        .bind(job_id)
+        .bind(worker_id)
+        .execute(&self.pool)
+        .await
+        .with_context(|| format!("renew lease on job {job_id}"))?;
+        Ok(renewed.rows_affected() > 0)
+    }
+
+    // Closes the worker's attempt with `outcome`; false if the lease was no longer its own.
+    pub async fn release_job_lease(
+        &self,
+        lease: &JobLease,
+        outcome: &str,
+        diagnostic: Option<&str>,
+    ) -> Result<bool> {
+        let mut tx = self.pool.begin().await.context("begin lease release")?;
+        let released = sqlx::query("DELETE FROM job_leases WHERE job_id = ? AND worker_id = ?")
+            .bind(&lease.job_id)
+            .bind(&lease.worker_id)
+            .execute(&mut *tx)
+            .await
+            .with_context(|| format!("release lease on job {}", lease.job_id))?
+            .rows_affected()
+            > 0;
+        if released {
+            close_job_attempt(&mut tx, lease, outcome, diagnostic).await?;
+        }
+        tx.commit().await.context("commit lease release")?;
+        Ok(released)
+    }
+
+    // Removes expired leases and marks their attempts lost in one step, so a lease is
+    // recovered by exactly one watchdog pass.
+    pub async fn claim_expired_job_leases(&self, now: DateTime<Utc>) -> Result<Vec<JobLease>> {
+        let mut tx = self.pool.begin().await.context("begin lease claim")?;
+        let leases = sqlx::query_as::<_, JobLease>(
+            "DELETE FROM job_leases WHERE lease_expires_at < ? RETURNING job_id, worker_id, attempt, lease_expires_at",
+        )
+        .bind(CanonicalTimestamp::from(now))
+        .fetch_all(&mut *tx)
+        .await
+        .context("claim expired job leases")?;
+        for lease in &leases {
+            close_job_attempt(&mut tx, lease, "lost", Some("lease expired")).await?;
+        }
+        tx.commit().await.context("commit lease claim")?;
+        Ok(leases)
+    }
+
+    // Removes every lease at once. Only for startup, before any worker of this process has
+    // taken a lease: whatever is left belongs to a process that is gone.
+    pub async fn claim_orphaned_job_leases(&self, diagnostic: &str) -> Result<Vec<JobLease>> {
+        let mut tx = self.pool.begin().await.context("begin orphaned lease claim")?;
+        let leases = sqlx::query_as::<_, JobLease>(
+            "DELETE FROM job_leases RETURNING job_id, worker_id, attempt, lease_expires_at",
+        )
+        .fetch_all(&mut *tx)
+        .await
+        .context("claim orphaned job leases")?;
+        for lease in &leases {
+            close_job_attempt(&mut tx, lease, "lost", Some(diagnostic)).await?;
+        }
+        tx.commit().await.context("commit orphaned lease claim")?;
+        Ok(leases)
+    }
+
+    pub async fn get_job_lease(&self, job_id: &str) -> Result<Option<JobLease>> {
+        sqlx::query_as::<_, JobLease>(
+            "SELECT job_id, worker_id, attempt, lease_expires_at FROM job_leases WHERE job_id = ?",
+        )
+        .bind(job_id)
+        .fetch_optional(&self.read_pool)
+        .await
+        .with_context(|| format!("query lease on job {job_id}"))
+    }
+
+    // False when a report with this id is already stored, so loading the same crash file twice
+    // records it once.
+    pub async fn record_crash_report(&self, report: &CrashReport) -> Result<bool> {
+        let report = report.clone();
+        self.with_tx(move |tx| Box::pin(async move { tx.record_crash_report(&report).await }))
+            .await
+    }
+
+    // Newest first.
+    pub async fn list_crash_reports(&self, limit: i64) -> Result<Vec<CrashReport>> {
+        self.list_crash_reports_page(&Keyset::first(limit)).await
+    }
+
+    // In `CRASH_REPORT_ORDER`, nearest the page's key first.
+    pub async fn list_crash_reports_page(&self, page: &Keyset) -> Result<Vec<CrashReport>> {
+        let sql = format!(
+            "SELECT crash_id, kind, occurred_at, message, thread, location, backtrace, version, uptime_secs, job_id, recorded_at FROM crash_reports WHERE {} {} LIMIT ?",
+            CRASH_REPORT_ORDER.predicate(page)?,
+            CRASH_REPORT_ORDER.order_by(page.direction)
+        );
+        CRASH_REPORT_ORDER
+            .bind(sqlx::query_as::<_, CrashReport>(&sql), page)
+            .bind(page.limit)
+            .fetch_all(&self.read_pool)
+            .await
+            .context("list crash reports")
+    }
+
+    pub async fn get_crash_report(&self, crash_id: &str) -> Result<Option<CrashReport>> {
+        sqlx::query_as::<_, CrashReport>(
+            "SELECT crash_id, kind, occurred_at, message, thread, location, backtrace, version, uptime_secs, job_id, recorded_at FROM crash_reports WHERE crash_id = ?",
+        )
+        .bind(crash_id)
+        .fetch_optional(&self.read_pool)
+        .await
+        .with_context(|| format!("query crash report {crash_id}"))
+    }
+
+    // Keeps the newest `keep` reports.
+    pub async fn trim_crash_reports(&self, keep: i64) -> Result<u64> {
+        let trimmed = sqlx::query(
+            "DELETE FROM crash_reports WHERE crash_id NOT IN (SELECT crash_id FROM crash_reports ORDER BY occurred_at DESC, crash_id DESC LIMIT ?)",
+        )
+        .bind(keep.max(0))
+        .execute(&self.pool)
+        .await
+        .context("trim crash reports")?;
+        Ok(trimmed.rows_affected())
+    }
+
+    // Replaces the node's latest report and appends it to its history, of which the newest
+    // `keep_history` reports are kept.
+    pub async fn record_fleet_report(
+        &self,
+        identity_hash: &IdentityHash,
+        report: &Value,
+        interval_secs: i64,
+        received_at: DateTime<Utc>,
+        keep_history: i64,
+    ) -> Result<()> {
+        let report_json = serde_json::to_string(report).context("encode fleet report")?;
+        let received_at = CanonicalTimestamp::from(received_at);
+        let mut tx = self.pool.begin().await.context("begin fleet report")?;
+        sqlx::query(
+            "INSERT INTO fleet_nodes(identity_hash, report_json, interval_secs, received_at, stale_since) VALUES (?, ?, ?, ?, NULL) ON CONFLICT(identity_hash) DO UPDATE SET report_json = excluded.report_json, interval_secs = excluded.interval_secs, received_at = excluded.received_at, stale_since = NULL",
+        )
+        .bind(identity_hash.as_str())
+        .bind(&report_json)
+        .bind(interval_secs)
+        .bind(received_at)
+        .execute(&mut *tx)
+        .await
+        .with_context(|| format!("record fleet node {identity_hash}"))?;
+        sqlx::query(
+            "INSERT INTO fleet_reports(identity_hash, report_json, received_at) VALUES (?, ?, ?)",
+        )
+        .bind(identity_hash.as_str())
+        .bind(&report_json)
+        .bind(received_at)
+        .execute(&mut *tx)
+        .await
+        .with_context(|| format!("append fleet report from {identity_hash}"))?;
+        sqlx::query(
+            "DELETE FROM fleet_reports WHERE identity_hash = ?1 AND rowid NOT IN (SELECT rowid FROM fleet_reports WHERE identity_hash = ?1 ORDER BY received_at DESC, rowid DESC LIMIT ?2)",
+        )
+        .bind(identity_hash.as_str())
+        .bind(keep_history.max(0))
+        .execute(&mut *tx)
+        .await
+        .with_context(|| format!("trim fleet reports from {identity_hash}"))?;
+        tx.commit().await.context("commit fleet report")?;
+        Ok(())
+    }
+
+    pub async fn list_fleet_nodes(&self) -> Result<Vec<FleetNode>> {
+        sqlx::query_as::<_, FleetNode>(
+            "SELECT identity_hash, report_json, interval_secs, received_at, stale_since FROM fleet_nodes ORDER BY identity_hash",
+        )
+        .fetch_all(&self.read_pool)
+        .await
+        .context("list fleet nodes")
+    }
+
+    // In `FLEET_NODE_ORDER`, nearest the page's key first.
+    pub async fn list_fleet_nodes_page(&self, page: &Keyset) -> Result<Vec<FleetNode>> {
+        let sql = format!(
+            "SELECT identity_hash, report_json, interval_secs, received_at, stale_since FROM fleet_nodes WHERE {} {} LIMIT ?",
+            FLEET_NODE_ORDER.predicate(page)?,
+            FLEET_NODE_ORDER.order_by(page.direction)
+        );
+        FLEET_NODE_ORDER
+            .bind(sqlx::query_as::<_, FleetNode>(&sql), page)
+            .bind(page.limit)
+            .fetch_all(&self.read_pool)
+            .await
+            .context("list fleet nodes")
+    }
+
+    pub async fn get_fleet_node(&self, identity_hash: &IdentityHash) -> Result<Option<FleetNode>> {
+        sqlx::query_as::<_, FleetNode>(
+            "SELECT identity_hash, report_json, interval_secs, received_at, stale_since FROM fleet_nodes WHERE identity_hash = ?",
+        )
+        .bind(identity_hash.as_str())
+        .fetch_optional(&self.read_pool)
+        .await
+        .with_context(|| format!("query fleet node {identity_hash}"))
+    }
+
+    // Newest first.
+    pub async fn list_fleet_reports(
+        &self,
+        identity_hash: &IdentityHash,
+        limit: i64,
+    ) -> Result<Vec<FleetReport>> {
+        sqlx::query_as::<_, FleetReport>(
+            "SELECT report_json, received_at FROM fleet_reports WHERE identity_hash = ? ORDER BY received_at DESC, rowid DESC LIMIT ?",
+        )
+        .bind(identity_hash.as_str())
+        .bind(limit)
+        .fetch_all(&self.read_pool)
+        .await
+        .with_context(|| format!("list fleet reports from {identity_hash}"))
+    }
+
+    // False when the node has reported again since `received_at` or was flagged already, so a
+    // node goes stale once per silence.
+    pub async fn mark_fleet_node_stale(
+        &self,
+        identity_hash: &str,
+        received_at: &str,
+        now: DateTime<Utc>,
+    ) -> Result<bool> {
+        let (identity_hash, received_at) = (identity_hash.to_string(), received_at.to_string());
+        self.with_tx(move |tx| {
+            Box::pin(async move {
+                tx.mark_fleet_node_stale(&identity_hash, &received_at, now)
+                    .await
+            })
+        })
+        .await
+    }
+
+    pub async fn create_webhook_subscription(
+        &self,
+        subscription: &WebhookSubscription,
+    ) -> Result<()> {
+        let id = &subscription.subscription_id;
+        sqlx::query(
+            "INSERT INTO webhook_subscriptions(subscription_id, url, events_json, source_identity, secret, timeout_ms, max_retries, backoff_ms, enabled, consecutive_failures, disabled_at, created_at) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(id)
+        .bind(&subscription.url)
+        .bind(&subscription.events_json)
+        .bind(&subscription.source_identity)
+        .bind(self.seal(&subscription.secret)?)
+        .bind(subscription.timeout_ms)
+        .bind(subscription.max_retries)
+        .bind(subscription.backoff_ms)
+        .bind(subscription.enabled)
+        .bind(subscription.consecutive_failures)
+        .bind(&subscription.disabled_at)
+        .bind(&subscription.created_at)
+        .execute(&self.pool)
+        .await
+        .with_context(|| format!("insert webhook subscription {id}"))?;
+        Ok(())
+    }
+
+    pub async fn list_webhook_subscriptions(&self) -> Result<Vec<WebhookSubscription>> {
+        sqlx::query_as::<_, WebhookSubscription>(
+            "SELECT subscription_id, url, events_json, source_identity, secret, timeout_ms, max_retries, backoff_ms, enabled, consecutive_failures, disabled_at, created_at FROM webhook_subscriptions ORDER BY created_at, subscription_id",
+        )
+        .fetch_all(&self.read_pool)
+        .await
+        .context("list webhook subscriptions")?
+        .into_iter()
+        .map(|subscription| self.open_webhook_subscription(subscription))
+        .collect()
+    }
+
+    pub async fn get_webhook_subscription(
+        &self,
+        subscription_id: &str,
+    ) -> Result<Option<WebhookSubscription>> {
+        sqlx::query_as::<_, WebhookSubscription>(
+            "SELECT subscription_id, url, events_json, source_identity, secret, timeout_ms, max_retries, backoff_ms, enabled, consecutive_failures, disabled_at, created_at FROM webhook_subscriptions WHERE subscription_id = ?",
+        )
+        .bind(subscription_id)
+        .fetch_optional(&self.read_pool)
+        .await
+        .with_context(|| format!("query webhook subscription {subscription_id}"))?
+        .map(|subscription| self.open_webhook_subscription(subscription))
+        .transpose()
+    }
+
+    fn open_webhook_subscription(
+        &self,
+        mut subscription: WebhookSubscription,
+    ) -> Result<WebhookSubscription> {
+        subscription.secret = self
+            .open(&subscription.secret)
+            .map_err(|err| err.in_table("webhook_subscriptions"))?;
+        Ok(subscription)
+    }
+
+    // Drops the subscription with its pending deliveries and history.
+    pub async fn delete_webhook_subscription(&self, subscription_id: &str) -> Result<bool> {
+        let mut tx = self.pool.begin().await.context("begin webhook delete")?;
+        for table in ["webhook_deliveries", "webhook_attempts"] {
+            sqlx::query(&format!("DELETE FROM {table} WHERE subscription_id = ?"))
+                .bind(subscription_id)
+                .execute(&mut *tx)
+                .await
+                .with_context(|| format!("delete {table} of {subscription_id}"))?;
+        }
+        let deleted = sqlx::query("DELETE FROM webhook_subscriptions WHERE subscription_id = ?")
+            .bind(subscription_id)
+            .execute(&mut *tx)
+            .await
+            .with_context(|| format!("delete webhook subscription {subscription_id}"))?;
+        tx.commit().await.context("commit webhook delete")?;
+        Ok(deleted.rows_affected() == 1)
+    }
+
+    pub async fn count_pending_webhook_deliveries(&self, subscription_id: &str) -> Result<i64> {
+        sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM webhook_deliveries WHERE subscription_id = ?",
+        )
+        .bind(subscription_id)
+        .fetch_one(&self.read_pool)
+        .await
+        .with_context(|| format!("count pending deliveries of {subscription_id}"))
+    }
+
+    // Drops queued deliveries that will never be sent, e.g. ones superseded by a newer event.
+    pub async fn drop_webhook_deliveries(&self, delivery_ids: &[String]) -> Result<u64> {
+        let mut tx = self.pool.begin().await.context("begin webhook delivery drop")?;
+        let mut dropped = 0;
+        for delivery_id in delivery_ids {
+            dropped += sqlx::query("DELETE FROM webhook_deliveries WHERE delivery_id = ?")
+                .bind(delivery_id)
+                .execute(&mut *tx)
+                .await
+                .with_context(|| format!("drop webhook delivery {delivery_id}"))?
+                .rows_affected();
+        }
+        tx.commit().await.context("commit webhook delivery drop")?;
+        Ok(dropped)
+    }
+
+    // Deliveries to enabled subscriptions whose next attempt is due, longest waiting first.
+    pub async fn due_webhook_deliveries(
+        &self,
+        now: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<Vec<WebhookDelivery>> {
+        sqlx::query_as::<_, WebhookDelivery>(
+            "SELECT d.delivery_id, d.subscription_id, d.message_id, d.event_name, d.body_json, d.attempts, d.next_attempt_at, d.queued_at FROM webhook_deliveries d JOIN webhook_subscriptions s ON s.subscription_id = d.subscription_id WHERE s.enabled = 1 AND d.next_attempt_at <= ? ORDER BY d.next_attempt_at, d.queued_at, d.delivery_id LIMIT ?",
+        )
+        .bind(CanonicalTimestamp::from(now))
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+        .context("list due webhook deliveries")?
+        .into_iter()
+        .map(|mut delivery| {
+            delivery.body_json = self
+                .open(&delivery.body_json)
+                .map_err(|err| err.in_table("webhook_deliveries"))?;
+            Ok(delivery)
+        })
+        .collect()
+    }
+
+    // Records an attempt at a delivery and returns the subscription's failed attempts in a row.
+    // A delivered attempt resets that count and drops the delivery, as does a failed one
+    // without `retry_at`; otherwise the delivery waits until `retry_at`. The newest
+    // `keep_history` attempts of the subscription are kept.
+    pub async fn record_webhook_attempt(
+        &self,
+        subscription_id: &str,
+        attempt: &WebhookAttempt,
+        retry_at: Option<DateTime<Utc>>,
+        keep_history: i64,
+    ) -> Result<i64> {
+        let delivery_id = &attempt.delivery_id;
+        let mut tx = self.pool.begin().await.context("begin webhook attempt")?;
+        sqlx::query(
+            "INSERT INTO webhook_attempts(subscription_id, delivery_id, message_id, event_name, attempt, attempted_at, outcome, status_code, error, duration_ms) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(subscription_id)
+        .bind(delivery_id)
+        .bind(&attempt.message_id)
+        .bind(&attempt.event_name)
+        .bind(attempt.attempt)
+        .bind(&attempt.attempted_at)
+        .bind(&attempt.outcome)
+        .bind(attempt.status_code)
+        .bind(&attempt.error)
+        .bind(attempt.duration_ms)
+        .execute(&mut *tx)
+        .await
+        .with_context(|| format!("record webhook attempt at {delivery_id}"))?;
+        sqlx::query(
+            "DELETE FROM webhook_attempts WHERE subscription_id = ?1 AND rowid NOT IN (SELECT rowid FROM webhook_attempts WHERE subscription_id = ?1 ORDER BY attempted_at DESC, rowid DESC LIMIT ?2)",
+        )
+        .bind(subscription_id)
+        .bind(keep_history.max(0))
+        .execute(&mut *tx)
+        .await
+        .with_context(|| format!("trim webhook attempts of {subscription_id}"))?;
+        match retry_at {
+            Some(retry_at) if attempt.outcome != "delivered" => {
+                sqlx::query(
+                    "UPDATE webhook_deliveries SET attempts = ?, next_attempt_at = ? WHERE delivery_id = ?",
+                )
+                .bind(attempt.attempt)
+                .bind(CanonicalTimestamp::from(retry_at))
+                .bind(delivery_id)
+                .execute(&mut *tx)
+                .await
+                .with_context(|| format!("reschedule webhook delivery {delivery_id}"))?;
+            }
+            _ => {
+                sqlx::query("DELETE FROM webhook_deliveries WHERE delivery_id = ?")
+                    .bind(delivery_id)
+                    .execute(&mut *tx)
+                    .await
+                    .with_context(|| format!("settle webhook delivery {delivery_id}"))?;
+            }
+        }
+        let failures = sqlx::query_scalar::<_, i64>(
+            "UPDATE webhook_subscriptions SET consecutive_failures = CASE WHEN ? THEN 0 ELSE consecutive_failures + 1 END WHERE subscription_id = ? RETURNING consecutive_failures",
+        )
+        .bind(attempt.outcome == "delivered")
+        .bind(subscription_id)
+        .fetch_optional(&mut *tx)
+        .await
+        .with_context(|| format!("count failures of {subscription_id}"))?
+        .unwrap_or(0);
+        tx.commit().await.context("commit webhook attempt")?;
+        Ok(failures)
+    }
+
+    // Newest first.
+    pub async fn list_webhook_attempts(
+        &self,
+        subscription_id: &str,
+        limit: i64,
+    ) -> Result<Vec<WebhookAttempt>> {
+        sqlx::query_as::<_, WebhookAttempt>(
+            "SELECT delivery_id, message_id, event_name, attempt, attempted_at, outcome, status_code, error, duration_ms FROM webhook_attempts WHERE subscription_id = ? ORDER BY attempted_at DESC, rowid DESC LIMIT ?",
+        )
+        .bind(subscription_id)
+        .bind(limit)
+        .fetch_all(&self.read_pool)
+        .await
+        .with_context(|| format!("list webhook attempts of {subscription_id}"))
+    }
+
+    // Jobs not yet sent, queued, waiting on a dependency or deferred by a circuit breaker,
+    // counted per operation.
+    pub async fn count_pending_jobs_by_operation(&self) -> Result<BTreeMap<String, i64>> {
+        let rows = sqlx::query_as::<_, (String, i64)>(
+            "SELECT operation, COUNT(*) FROM jobs WHERE status IN ('queued', 'waiting', 'deferred') GROUP BY operation",
+        )
+        .fetch_all(&self.read_pool)
+        .await
+        .context("count pending jobs by operation")?;
+        Ok(rows.into_iter().collect())
+    }
+
+    pub async fn count_live_job_leases(&self, now: DateTime<Utc>) -> Result<i64> {
+        sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM job_leases WHERE lease_expires_at >= ?")
+            .bind(CanonicalTimestamp::from(now))
+            .fetch_one(&self.read_pool)
+            .await
+            .context("count live job leases")
+    }
+
+    // Keeps the later of the stored and the given contact time.
+    pub async fn record_peer_contact(&self, identity_hash: &str, at: DateTime<Utc>) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO peer_contacts(identity_hash, last_seen) VALUES (?1, ?2) ON CONFLICT(identity_hash) DO UPDATE SET last_seen = MAX(last_seen, excluded.last_seen)",
+        )
+        .bind(identity_hash)
+        .bind(CanonicalTimestamp::from(at))
+        .execute(&self.pool)
+        .await
+        .with_context(|| format!("record contact with {identity_hash}"))?;
+        Ok(())
+    }
+
+    // The recorded last contact with every peer, loaded into the peer directory at startup.
+    pub async fn list_peer_last_seen(&self) -> Result<Vec<(String, CanonicalTimestamp)>> {
+        sqlx::query_as::<_, (String, CanonicalTimestamp)>(
+            "SELECT identity_hash, last_seen FROM peer_contacts ORDER BY identity_hash",
+        )
+        .fetch_all(&self.read_pool)
+        .await
+        .context("query peer last seen")
+    }
+
+    // When each peer was last heard from, as the stored traffic shows it: envelopes it sent,
+    // events cached from it, dispatches it answered and the contacts recorded as they happened.
+    pub async fn list_peer_contacts(&self) -> Result<Vec<(String, String)>> {
+        sqlx::query_as::<_, (String, String)>(
+            "SELECT identity_hash, MAX(seen_at) FROM (\
+             SELECT source_identity AS identity_hash, received_at AS seen_at FROM seen_messages \
+             UNION ALL SELECT identity_hash, last_seen FROM peer_contacts \
+             UNION ALL SELECT source_identity, received_at FROM cached_events WHERE source_identity IS NOT NULL \
+             UNION ALL SELECT destination_identity, finished_at FROM dispatch_attempts WHERE outcome = 'delivered'\
+             ) GROUP BY identity_hash ORDER BY identity_hash",
+        )
+        .fetch_all(&self.read_pool)
+        .await
+        .context("query peer contacts")
+    }
+
+    // Deletes, one table at a time, the rows left pointing at a job or transfer that is gone.
+    pub async fn purge_orphaned_rows(&self) -> Result<Vec<OrphanedRows>> {
+        let mut purged = Vec::new();
+        for (table, column, parent, parent_column) in DERIVED_REFERENCES {
+            let deleted = sqlx::query(&format!(
+                "DELETE FROM {table} WHERE {column} NOT IN (SELECT {parent_column} FROM {parent})"
+            ))
+            .execute(&self.pool)
+            .await
+            .with_context(|| format!("purge orphaned {table} rows"))?;
+            if deleted.rows_affected() > 0 {
+                purged.push(OrphanedRows {
+                    table: table.to_string(),
+                    column: column.to_string(),
+                    rows: deleted.rows_affected(),
+                });
+            }
+        }
+        Ok(purged)
+    }
+
+    pub async fn rebuild_indexes(&self) -> Result<IndexRebuild> {
+        let problems_before = self.quick_check().await?;
+        let indexes = sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM sqlite_master WHERE type = 'index'",
+        )
+        .fetch_one(&self.pool)
+        .await
+        .context("count indexes")?;
+        sqlx::query("REINDEX")
+            .execute(&self.pool)
+            .await
+            .context("rebuild indexes")?;
+        Ok(IndexRebuild {
+            indexes: indexes as u64,
+            problems_before,
+            problems_after: self.quick_check().await?,
+        })
+    }
+
+    async fn quick_check(&self) -> Result<Vec<String>> {
+        let findings = sqlx::query_scalar::<_, String>("PRAGMA quick_check")
+            .fetch_all(&self.pool)
+            .await
+            .context("run quick_check")?;
+        Ok(findings.into_iter().filter(|finding| finding != "ok").collect())
+    }
+
+    pub async fn count_jobs_with_status(&self, status: &str) -> Result<i64> {
+        sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM jobs WHERE status = ?")
+            .bind(status)
+            .fetch_one(&self.read_pool)
+            .await
+            .with_context(|| format!("count {status} jobs"))
+    }
+
+    pub async fn insert_health_sample(&self, sample: &HealthSample) -> Result<()> {
+        insert_health_sample(&self.pool, sample).await
+    }
+
+    pub async fn list_health_samples(
+        &self,
+        since: DateTime<Utc>,
+        until: DateTime<Utc>,
+    ) -> Result<Vec<HealthSample>> {
+        sqlx::query_as::<_, HealthSample>(
+            "SELECT sampled_at, resolution_secs, sample_count, bridge_ok, bridge_latency_ms, storage_ok, storage_latency_ms, queued_jobs FROM health_samples WHERE sampled_at >= ? AND sampled_at < ? ORDER BY sampled_at ASC",
+        )
+        .bind(CanonicalTimestamp::from(since))
+        .bind(CanonicalTimestamp::from(until))
+        .fetch_all(&self.read_pool)
+        .await
+        .context("query health samples")
+    }
+
+    // Swaps raw samples older than `raw_before` for their aggregates and drops anything older
+    // than `keep_after`, in one transaction so history never has a gap or a double count.
+    pub async fn compact_health_samples(
+        &self,
+        raw_before: DateTime<Utc>,
+        aggregates: &[HealthSample],
+        keep_after: DateTime<Utc>,
+    ) -> Result<u64> {
+        let mut tx = self.pool.begin().await.context("begin health compaction")?;
+        for aggregate in aggregates {
+            insert_health_sample(&mut *tx, aggregate).await?;
+        }
+        let raw = sqlx::query(
+            "DELETE FROM health_samples WHERE resolution_secs = 0 AND sampled_at < ?",
+        )
+        .bind(CanonicalTimestamp::from(raw_before))
+        .execute(&mut *tx)
+        .await
+        .context("delete downsampled health samples")?;
+        sqlx::query("DELETE FROM health_samples WHERE sampled_at < ?")
+            .bind(CanonicalTimestamp::from(keep_after))
+            .execute(&mut *tx)
+            .await
+            .context("delete expired health samples")?;
+        tx.commit().await.context("commit health compaction")?;
+        Ok(raw.rows_affected())
+    }
+
+    // Cutoffs are worked out here rather than with SQLite's `datetime('now')`, whose
+    // `YYYY-MM-DD HH:MM:SS` text does not compare correctly with stored RFC 3339 strings.
+    pub async fn purge_expired(
+        &self,
+        job_retention_hours: i64,
+        cache_retention_hours: i64,
+        transfer_retention_days: i64,
+    ) -> Result<()> {
+        let now = Utc::now();
+        let job_cutoff = CanonicalTimestamp::from(now - chrono::Duration::hours(job_retention_hours));
+        let transfer_cutoff =
+            CanonicalTimestamp::from(now - chrono::Duration::days(transfer_retention_days));
+        sqlx::query(
+            "DELETE FROM job_results WHERE completed_at < ?",
+        )
+        .bind(job_cutoff)
+        .execute(&self.pool)
+        .await
+        .context("purge expired job_results")?;
+
+        sqlx::query(
+            "DELETE FROM job_result_parts WHERE received_at < ?",
+        )
+        .bind(job_cutoff)
+        .execute(&self.pool)
+        .await
+        .context("purge expired job_result_parts")?;
+
+        sqlx::query(
+            "DELETE FROM job_messages WHERE sent_at < ?",
+        )
+        .bind(job_cutoff)
+        .execute(&self.pool)
+        .await
+        .context("purge expired job_messages")?;
+
+        sqlx::query(
+            "DELETE FROM dispatch_attempts WHERE finished_at < ?",
+        )
+        .bind(job_cutoff)
+        .execute(&self.pool)
+        .await
+        .context("purge expired dispatch_attempts")?;
+
+        sqlx::query(
+            "DELETE FROM job_traces WHERE job_id IN (SELECT job_id FROM jobs WHERE updated_at < ?)",
+        )
+        .bind(job_cutoff)
+        .execute(&self.pool)
+        .await
+        .context("purge expired job_traces")?;
+
+        sqlx::query(
+            "DELETE FROM job_transforms WHERE job_id IN (SELECT job_id FROM jobs WHERE updated_at < ?)",
+        )
+        .bind(job_cutoff)
+        .execute(&self.pool)
+        .await
+        .context("purge expired job_transforms")?;
+
+        sqlx::query(
+            "DELETE FROM job_result_digests WHERE job_id IN (SELECT job_id FROM jobs WHERE updated_at < ?)",
+        )
+        .bind(job_cutoff)
+        .execute(&self.pool)
+        .await
+        .context("purge expired job_result_digests")?;
+
+        sqlx::query(
+            "DELETE FROM job_result_checks WHERE job_id IN (SELECT job_id FROM jobs WHERE updated_at < ?)",
+        )
+        .bind(job_cutoff)
+        .execute(&self.pool)
+        .await
+        .context("purge expired job_result_checks")?;
+
+        sqlx::query(
+            "DELETE FROM job_holds WHERE job_id IN (SELECT job_id FROM jobs WHERE updated_at < ?)",
+        )
+        .bind(job_cutoff)
+        .execute(&self.pool)
+        .await
+        .context("purge expired job_holds")?;
+
+        sqlx::query(
+            "DELETE FROM job_dependencies WHERE job_id IN (SELECT job_id FROM jobs WHERE updated_at < ?1) OR depends_on IN (SELECT job_id FROM jobs WHERE updated_at < ?1)",
+        )
+        .bind(job_cutoff)
+        .execute(&self.pool)
+        .await
+        .context("purge expired job_dependencies")?;
+
+        sqlx::query(
+            "DELETE FROM labels WHERE subject_type = 'job' AND subject_id IN (SELECT job_id FROM jobs WHERE updated_at < ?)",
+        )
+        .bind(job_cutoff)
+        .execute(&self.pool)
+        .await
+        .context("purge expired job labels")?;
+
+        sqlx::query(
+            "DELETE FROM delivery_receipts WHERE job_id IN (SELECT job_id FROM jobs WHERE updated_at < ?)",
+        )
+        .bind(job_cutoff)
+        .execute(&self.pool)
+        .await
+        .context("purge expired job delivery_receipts")?;
+
+        sqlx::query(
+            "DELETE FROM jobs WHERE updated_at < ?",
+        )
+        .bind(job_cutoff)
+        .execute(&self.pool)
+        .await
+        .context("purge expired jobs")?;
+
+        self.purge_expired_caches(cache_retention_hours).await?;
+
+        sqlx::query(
+            "DELETE FROM bundle_members WHERE bundle_id IN (SELECT transfer_id FROM transfers WHERE updated_at < ?)",
+        )
+        .bind(transfer_cutoff)
+        .execute(&self.pool)
+        .await
+        .context("purge expired bundle_members")?;
+
+        sqlx::query(
+            "DELETE FROM labels WHERE subject_type = 'transfer' AND subject_id IN (SELECT transfer_id FROM transfers WHERE updated_at < ?)",
+        )
+        .bind(transfer_cutoff)
+        .execute(&self.pool)
+        .await
+        .context("purge expired transfer labels")?;
+
+        sqlx::query(
+            "DELETE FROM delivery_receipts WHERE transfer_id IN (SELECT transfer_id FROM transfers WHERE updated_at < ?)",
+        )
+        .bind(transfer_cutoff)
+        .execute(&self.pool)
+        .await
+        .context("purge expired transfer delivery_receipts")?;
+
+        sqlx::query(
+            "DELETE FROM transfers WHERE updated_at < ?",
+        )
+        .bind(transfer_cutoff)
+        .execute(&self.pool)
+        .await
+        .context("purge expired transfers")?;
+
+        Ok(())
+    }
+
+    pub async fn purge_expired_caches(&self, cache_retention_hours: i64) -> Result<()> {
+        let cutoff =
+            CanonicalTimestamp::from(Utc::now() - chrono::Duration::hours(cache_retention_hours));
+        sqlx::query(
+            "DELETE FROM cached_events WHERE received_at < ?",
+        )
+        .bind(cutoff)
+        .execute(&self.pool)
+        .await
+        .context("purge expired cached_events")?;
+
+        sqlx::query(
+            "DELETE FROM cached_messages WHERE received_at < ?",
+        )
+        .bind(cutoff)
+        .execute(&self.pool)
+        .await
+        .context("purge expired cached_messages")?;
+
+        sqlx::query(
+            "DELETE FROM inbound_records WHERE received_at < ?",
+        )
+        .bind(cutoff)
+        .execute(&self.pool)
+        .await
+        .context("purge expired inbound_records")?;
+
+        Ok(())
+    }
+
+    // Jobs last updated before the cutoff, oldest first. Jobs that still own transfers wait
+    // until those transfers have been purged.
+    pub async fn list_expired_jobs(
+        &self,
+        updated_before: &str,
+        limit: i64,
+    ) -> Result<Vec<JobRecord>> {
+        let records = sqlx::query_as::<_, JobRecord>(
+            "SELECT job_id, operation, status, payload_json, submitted_at, updated_at, failure_reason, dispatch_json, requested_operation FROM jobs j WHERE updated_at < ? AND NOT EXISTS (SELECT 1 FROM transfers t WHERE t.job_id = j.job_id) ORDER BY updated_at, job_id LIMIT ?",
+        )
+        .bind(CanonicalTimestamp::parse(updated_before)?)
+        .bind(limit)
+        .fetch_all(&self.read_pool)
+        .await
+        .context("query expired jobs")?;
+
+        records
+            .into_iter()
+            .map(|record| open_job(self.cipher.as_ref(), record))
+            .collect()
+    }
+
+    pub async fn list_expired_transfers(
+        &self,
+        updated_before: &str,
+        limit: i64,
+    ) -> Result<Vec<TransferRecord>> {
+        let records = sqlx::query_as::<_, TransferRecord>(
+            "SELECT transfer_id, status, metadata_json, submitted_at, updated_at, failure_reason, job_id FROM transfers WHERE updated_at < ? ORDER BY updated_at, transfer_id LIMIT ?",
+        )
+        .bind(CanonicalTimestamp::parse(updated_before)?)
+        .bind(limit)
+        .fetch_all(&self.read_pool)
+        .await
+        .context("query expired transfers")?;
+
+        records
+            .into_iter()
+            .map(|record| open_transfer(self.cipher.as_ref(), record))
+            .collect()
+    }
+
+    // Deletes jobs and every row hanging off them in one transaction.
+    pub async fn purge_jobs(&self, job_ids: &[String]) -> Result<u64> {
+        let mut tx = self.pool.begin().await.context("begin job purge")?;
+        let mut purged = 0;
+        for job_id in job_ids {
+            for table in [
+                "job_attempts",
+                "job_leases",
+                "job_results",
+                "job_result_parts",
+                "job_messages",
+                "dispatch_attempts",
+                "job_traces",
+                "job_transforms",
+                "job_result_digests",
+                "job_result_checks",
+                "job_holds",
+                "outbox",
+                "delivery_receipts",
+            ] {
+                sqlx::query(&format!("DELETE FROM {table} WHERE job_id = ?"))
+                    .bind(job_id)
+                    .execute(&mut *tx)
+                    .await
+                    .with_context(|| format!("purge {table} for job {job_id}"))?;
+            }
+            sqlx::query("DELETE FROM job_dependencies WHERE job_id = ?1 OR depends_on = ?1")
+                .bind(job_id)
+                .execute(&mut *tx)
+                .await
+                .with_context(|| format!("purge job_dependencies for job {job_id}"))?;
+            sqlx::query("DELETE FROM labels WHERE subject_type = ? AND subject_id = ?")
+                .bind(LABEL_SUBJECT_JOB)
+                .bind(job_id)
+                .execute(&mut *tx)
+                .await
+                .with_context(|| format!("purge labels for job {job_id}"))?;
+            purged += sqlx::query("DELETE FROM jobs WHERE job_id = ?")
+                .bind(job_id)
+                .execute(&mut *tx)
+                .await
+                .with_context(|| format!("purge job {job_id}"))?
+                .rows_affected();
+        }
+        tx.commit().await.context("commit job purge")?;
+        Ok(purged)
+    }
+
+    pub async fn purge_transfers(&self, transfer_ids: &[String]) -> Result<u64> {
+        let mut tx = self.pool.begin().await.context("begin transfer purge")?;
+        let mut purged = 0;
+        for transfer_id in transfer_ids {
+            for (table, key_column) in [
+                ("transfer_progress", "transfer_id"),
+                ("transfer_dedup", "transfer_id"),
+                ("bundle_members", "bundle_id"),
+                ("delivery_receipts", "transfer_id"),
+                ("quota_reservations", "transfer_id"),
+            ] {
+                sqlx::query(&format!("DELETE FROM {table} WHERE {key_column} = ?"))
+                    .bind(transfer_id)
+                    .execute(&mut *tx)
+                    .await
+                    .with_context(|| format!("purge {table} for transfer {transfer_id}"))?;
+            }
+            sqlx::query("DELETE FROM labels WHERE subject_type = ? AND subject_id = ?")
+                .bind(LABEL_SUBJECT_TRANSFER)
+                .bind(transfer_id)
+                .execute(&mut *tx)
+                .await
+                .with_context(|| format!("purge labels for transfer {transfer_id}"))?;
+            purged += sqlx::query("DELETE FROM transfers WHERE transfer_id = ?")
+                .bind(transfer_id)
+                .execute(&mut *tx)
+                .await
+                .with_context(|| format!("purge transfer {transfer_id}"))?
+                .rows_affected();
+        }
+        tx.commit().await.context("commit transfer purge")?;
+        Ok(purged)
+    }
+}
+
+pub type TxFuture<'t, T> = Pin<Box<dyn Future<Output = Result<T>> + Send + 't>>;
+
+// Handle passed to `RetasyncStorage::with_tx`; every write goes through the open transaction.
+pub struct StorageTx {
+    tx: Transaction<'static, Sqlite>,
+    cipher: Option<EncryptedColumn>,
+    contract_version: Option<String>,
+    staged_job_events: bool,
+    // Set by a conditional write that changed nothing, so the handle's events are not recorded.
+    event_withheld: bool,
+}
+
+impl StorageTx {
+    pub async fn put_feature_flag(
+        &mut self,
+        name: &str,
+        enabled: bool,
+        variant: Option<&Value>,
+        updated_by: &str,
+    ) -> Result<FeatureFlagRecord> {
+        write_feature_flag(&mut *self.tx, name, enabled, variant, updated_by).await
+    }
+
+    pub async fn append_feed_event(&mut self, event_type: &str, data: &Value) -> Result<FeedEvent> {
+        write_feed_event(&mut *self.tx, event_type, data).await
+    }
+
+    // Makes `data` the payload of the feed row the job's next status change in this
+    // transaction writes, in place of the bare status.
+    pub async fn stage_job_event(&mut self, job_id: &str, data: &Value) -> Result<()> {
+        sqlx::query("INSERT OR REPLACE INTO job_event_details(job_id, payload_json) VALUES (?, ?)")
+            .bind(job_id)
+            .bind(data.to_string())
+            .execute(&mut *self.tx)
+            .await
+            .with_context(|| format!("stage status event for job {job_id}"))?;
+        self.staged_job_events = true;
+        Ok(())
+    }
+
+    // A report loaded before is left alone and records no event.
+    pub async fn record_crash_report(&mut self, report: &CrashReport) -> Result<bool> {
+        let inserted = sqlx::query(
+            "INSERT INTO crash_reports(crash_id, kind, occurred_at, message, thread, location, backtrace, version, uptime_secs, job_id, recorded_at) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?) ON CONFLICT(crash_id) DO NOTHING",
+        )
+        .bind(&report.crash_id)
+        .bind(&report.kind)
+        .bind(CanonicalTimestamp::parse(&report.occurred_at)?)
+        .bind(&report.message)
+        .bind(&report.thread)
+        .bind(&report.location)
+        .bind(&report.backtrace)
+        .bind(&report.version)
+        .bind(report.uptime_secs)
+        .bind(&report.job_id)
+        .bind(CanonicalTimestamp::parse(&report.recorded_at)?)
+        .execute(&mut *self.tx)
+        .await
+        .with_context(|| format!("record crash report {}", report.crash_id))?;
+        let inserted = inserted.rows_affected() == 1;
+        self.event_withheld |= !inserted;
+        Ok(inserted)
+    }
+
+    pub async fn record_received_bundle(
+        &mut self,
+        metadata: &Value,
+        status: &str,
+        failure_reason: Option<&str>,
+        members: &[BundleMember],
+        files: Vec<(ReceivedFile, Vec<u8>)>,
+    ) -> Result<TransferRecord> {
+        let cipher = self.cipher.clone();
+        let transfer_id = insert_transfer(&mut *self.tx, cipher.as_ref(), None, metadata).await?;
+        sqlx::query("UPDATE transfers SET status = ?, failure_reason = ? WHERE transfer_id = ?")
+            .bind(status)
+            .bind(failure_reason)
+            .bind(&transfer_id)
+            .execute(&mut *self.tx)
+            .await
+            .with_context(|| format!("set status of received bundle {transfer_id}"))?;
+        self.add_bundle_members(&transfer_id, members).await?;
+        for (mut file, content) in files {
+            file.bundle_id = Some(transfer_id.clone());
+            write_received_file(&mut *self.tx, cipher.as_ref(), &file, Some(&content)).await?;
+        }
+        let record = fetch_transfer(&mut *self.tx, &transfer_id)
+            .await?
+            .context("transfer after insert")?;
+        open_transfer(cipher.as_ref(), record)
+    }
+
+    pub async fn record_job_result_check(
+        &mut self,
+        job_id: &str,
+        schema_name: &str,
+        policy: &str,
+        errors: &Value,
+    ) -> Result<()> {
+        let errors_json = serde_json::to_string(errors).context("serialize result violations")?;
+        sqlx::query(
+            "INSERT INTO job_result_checks(job_id, schema_name, policy, errors_json, checked_at) VALUES (?, ?, ?, ?, ?) ON CONFLICT(job_id) DO UPDATE SET schema_name = excluded.schema_name, policy = excluded.policy, errors_json = excluded.errors_json, checked_at = excluded.checked_at",
+        )
+        .bind(job_id)
+        .bind(schema_name)
+        .bind(policy)
+        .bind(errors_json)
+        .bind(CanonicalTimestamp::now())
+        .execute(&mut *self.tx)
+        .await
+        .with_context(|| format!("record result check for job {job_id}"))?;
+        Ok(())
+    }
+
+    // A part already stored is left alone and records no event.
+    pub async fn insert_job_result_part(
+        &mut self,
+        job_id: &str,
+        sequence: u32,
+        is_final: bool,
+        data: &Value,
+    ) -> Result<bool> {
+        let payload_json = serde_json::to_string(data).context("serialize job result part")?;
+        let result = sqlx::query(
+            "INSERT INTO job_result_parts(job_id, sequence, is_final, payload_json, received_at) VALUES (?, ?, ?, ?, ?) ON CONFLICT(job_id, sequence) DO NOTHING",
+        )
+        .bind(job_id)
+        .bind(sequence)
+        .bind(is_final)
+        .bind(payload_json)
+        .bind(CanonicalTimestamp::now())
+        .execute(&mut *self.tx)
+        .await
+        .with_context(|| format!("insert result part {sequence} for job {job_id}"))?;
+        let inserted = result.rows_affected() > 0;
+        self.event_withheld |= !inserted;
+        Ok(inserted)
+    }
+
+    pub async fn cache_message(
+        &mut self,
+        message_id: &str,
+        operation: &str,
+        payload: &Value,
+    ) -> Result<()> {
+        let payload_json = serde_json::to_string(payload).context("serialize cached message")?;
+        let (payload_bytes, payload_sha256) = payload_digest(&payload_json);
+        sqlx::query(
+            "INSERT INTO cached_messages(message_id, operation, payload_json, received_at, payload_bytes, payload_sha256) VALUES (?, ?, ?, ?, ?, ?) ON CONFLICT(message_id) DO NOTHING",
+        )
+        .bind(message_id)
+        .bind(operation)
+        .bind(seal(self.cipher.as_ref(), &payload_json)?)
+        .bind(CanonicalTimestamp::now())
+        .bind(payload_bytes)
+        .bind(payload_sha256)
+        .execute(&mut *self.tx)
+        .await
+        .with_context(|| format!("insert cached message {message_id}"))?;
+        Ok(())
+    }
+
+    pub async fn record_delivery_receipt(&mut self, record: &DeliveryReceiptRecord) -> Result<bool> {
+        let stored = sqlx::query(
+            "INSERT INTO delivery_receipts(message_id, job_id, transfer_id, receiver_identity, receipt_json, verified, reason, recorded_at) VALUES (?, ?, ?, ?, ?, ?, ?, ?) ON CONFLICT(message_id) DO UPDATE SET receiver_identity = excluded.receiver_identity, receipt_json = excluded.receipt_json, verified = excluded.verified, reason = excluded.reason, recorded_at = excluded.recorded_at WHERE delivery_receipts.verified = 0",
+        )
+        .bind(&record.message_id)
+        .bind(&record.job_id)
+        .bind(&record.transfer_id)
+        .bind(&record.receiver_identity)
+        .bind(&record.receipt_json)
+        .bind(record.verified)
+        .bind(&record.reason)
+        .bind(CanonicalTimestamp::parse(&record.recorded_at)?)
+        .execute(&mut *self.tx)
+        .await
+        .with_context(|| format!("insert delivery receipt {}", record.message_id))?
+        .rows_affected();
+        Ok(stored > 0)
+    }
+
+    pub async fn put_quota_override(
+        &mut self,
+        subject_kind: &str,
+        subject: &str,
+        max_bytes: i64,
+        note: Option<&str>,
+    ) -> Result<QuotaOverride> {
+        sqlx::query_as::<_, QuotaOverride>(
+            "INSERT INTO quota_overrides(subject_kind, subject, max_bytes, note, updated_at) VALUES (?, ?, ?, ?, ?) ON CONFLICT(subject_kind, subject) DO UPDATE SET max_bytes = excluded.max_bytes, note = excluded.note, updated_at = excluded.updated_at RETURNING subject_kind, subject, max_bytes, note, updated_at",
+        )
+        .bind(subject_kind)
+        .bind(subject)
+        .bind(max_bytes)
+        .bind(note)
+        .bind(CanonicalTimestamp::now())
+        .fetch_one(&mut *self.tx)
+        .await
+        .with_context(|| format!("put quota override for {subject_kind} {subject}"))
+    }
+
+    // A node already marked, or one that reported since, is left alone and records no event.
+    pub async fn mark_fleet_node_stale(
+        &mut self,
+        identity_hash: &str,
+        received_at: &str,
+        now: DateTime<Utc>,
+    ) -> Result<bool> {
+        let marked = sqlx::query(
+            "UPDATE fleet_nodes SET stale_since = ? WHERE identity_hash = ? AND received_at = ? AND stale_since IS NULL",
+        )
+        .bind(CanonicalTimestamp::from(now))
+        .bind(identity_hash)
+        .bind(received_at)
+        .execute(&mut *self.tx)
+        .await
+        .with_context(|| format!("mark fleet node {identity_hash} stale"))?;
+        let marked = marked.rows_affected() == 1;
+        self.event_withheld |= !marked;
+        Ok(marked)
+    }
+
+    pub async fn append_node_config_revision(
+        &mut self,
+        config_json: &str,
+        reason: Option<&str>,
+    ) -> Result<NodeConfigRevision> {
+        let now = CanonicalTimestamp::now().to_string();
+        let revision_id = sqlx::query(
+            "INSERT INTO node_config_revisions(config_json, created_at, reason) VALUES (?, ?, ?)",
+        )
+        .bind(config_json)
+        .bind(&now)
+        .bind(reason)
+        .execute(&mut *self.tx)
+        .await
+        .context("insert node config revision")?
+        .last_insert_rowid();
+        Ok(NodeConfigRevision {
+            revision_id,
+            config_json: config_json.to_string(),
+            created_at: now,
+            reason: reason.map(str::to_string),
+            note: None,
+        })
+    }
+
+    pub async fn set_node_config_apply(&mut self, apply: Option<&Value>) -> Result<()> {
+        let Some(apply) = apply else {
+            sqlx::query("DELETE FROM storage_meta WHERE key = ?")
+                .bind(NODE_CONFIG_APPLY_KEY)
+                .execute(&mut *self.tx)
+                .await
+                .context("clear node config apply")?;
+            return Ok(());
+        };
+        sqlx::query(
+            "INSERT INTO storage_meta(key, value) VALUES (?, ?) ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+        )
+        .bind(NODE_CONFIG_APPLY_KEY)
+        .bind(serde_json::to_string(apply).context("serialize node config apply")?)
+        .execute(&mut *self.tx)
+        .await
+        .context("store node config apply")?;
+        Ok(())
+    }
+
+    pub async fn set_node_mute(&mut self, mute: Option<&Value>) -> Result<()> {
+        let Some(mute) = mute else {
+            sqlx::query("DELETE FROM storage_meta WHERE key = ?")
+                .bind(NODE_MUTE_KEY)
+                .execute(&mut *self.tx)
+                .await
+                .context("clear node mute")?;
+            return Ok(());
+        };
+        sqlx::query(
+            "INSERT INTO storage_meta(key, value) VALUES (?, ?) ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+        )
+        .bind(NODE_MUTE_KEY)
+        .bind(serde_json::to_string(mute).context("serialize node mute")?)
+        .execute(&mut *self.tx)
+        .await
+        .context("store node mute")?;
+        Ok(())
+    }
+
+    pub async fn create_job(&mut self, operation: &str, payload: Value) -> Result<JobRecord> {
+        let job_id = Uuid::now_v7().to_string();
+        self.insert_job(&job_id, &CanonicalTimestamp::now(), operation, &payload)
+            .await
+    }
+
+    // Stores a job under the id and submission time it was given before storage saw it, such
+    // as a spooled submission's; `None` when a job with that id is already stored.
+    pub async fn create_job_as(
+        &mut self,
+        job_id: &str,
+        submitted_at: &CanonicalTimestamp,
+        operation: &str,
+        payload: Value,
+    ) -> Result<Option<JobRecord>> {
+        if fetch_job(&mut *self.tx, job_id).await?.is_some() {
+            return Ok(None);
+        }
+        self.insert_job(job_id, submitted_at, operation, &payload)
+            .await
+            .map(Some)
+    }
+
+    async fn insert_job(
+        &mut self,
+        job_id: &str,
+        submitted_at: &CanonicalTimestamp,
+        operation: &str,
+        payload: &Value,
+    ) -> Result<JobRecord> {
+        insert_job(
+            &mut *self.tx,
+            self.cipher.as_ref(),
+            self.contract_version.as_deref(),
+            job_id,
+            submitted_at,
+            operation,
+            payload,
+        )
+        .await?;
+        let record = fetch_job(&mut *self.tx, job_id)
+            .await?
+            .context("job after insert")?;
+        open_job(self.cipher.as_ref(), record)
+    }
+
+    pub async fn update_job_status(
+        &mut self,
+        job_id: &str,
+        status: &str,
+        failure_reason: Option<&str>,
+    ) -> Result<()> {
+        write_job_status(&mut *self.tx, job_id, status, failure_reason).await
+    }
+
+    pub async fn set_job_dispatch(&mut self, job_id: &str, dispatch: &Value) -> Result<()> {
+        write_job_dispatch(&mut *self.tx, job_id, dispatch).await
+    }
+
+    pub async fn finish_job(
+        &mut self,
+        job_id: &str,
+        status: &str,
+        failure_reason: Option<&str>,
+    ) -> Result<bool> {
+        let finished = sqlx::query(
+            "UPDATE jobs SET status = ?, updated_at = ?, failure_reason = ? WHERE job_id = ? AND status NOT IN ('success', 'partial_failure', 'failed', 'cancelled')",
+        )
+        .bind(status)
+        .bind(CanonicalTimestamp::now())
+        .bind(failure_reason)
+        .bind(job_id)
+        .execute(&mut *self.tx)
+        .await
+        .with_context(|| format!("finish job {job_id}"))?;
+        Ok(finished.rows_affected() > 0)
+    }
+
+    pub async fn cancel_job(&mut self, job_id: &str) -> Result<bool> {
+        let cancelled = sqlx::query(
+            "UPDATE jobs SET status = 'cancelled', updated_at = ?, failure_reason = 'cancelled' WHERE job_id = ? AND status NOT IN ('success', 'partial_failure', 'failed', 'cancelled')",
+        )
+        .bind(CanonicalTimestamp::now())
+        .bind(job_id)
+        .execute(&mut *self.tx)
+        .await
+        .with_context(|| format!("cancel job {job_id}"))?;
+        Ok(cancelled.rows_affected() > 0)
+    }
+
+    pub async fn set_job_requested_operation(
+        &mut self,
+        job_id: &str,
+        operation: &str,
+    ) -> Result<()> {
+        write_job_requested_operation(&mut *self.tx, job_id, operation).await
+    }
+
+    pub async fn set_job_idempotency_key(&mut self, job_id: &str, key: &str) -> Result<()> {
+        sqlx::query("UPDATE jobs SET idempotency_key = ? WHERE job_id = ?")
+            .bind(key)
+            .bind(job_id)
+            .execute(&mut *self.tx)
+            .await
+            .with_context(|| format!("record idempotency key for job {job_id}"))?;
+        Ok(())
+    }
+
+    pub async fn find_job_by_idempotency_key(&mut self, key: &str) -> Result<Option<String>> {
+        fetch_job_id_by_idempotency_key(&mut *self.tx, key).await
+    }
+
+    pub async fn set_job_submitted_by(&mut self, job_id: &str, submitted_by: &str) -> Result<()> {
+        sqlx::query("UPDATE jobs SET submitted_by = ? WHERE job_id = ?")
+            .bind(submitted_by)
+            .bind(job_id)
+            .execute(&mut *self.tx)
+            .await
+            .with_context(|| format!("record submitter of job {job_id}"))?;
+        Ok(())
+    }
+
+    pub async fn put_labels(
+        &mut self,
+        subject_type: &str,
+        subject_id: &str,
+        labels: &BTreeMap<String, String>,
+    ) -> Result<()> {
+        for (key, value) in labels {
+            sqlx::query(
+                "INSERT INTO labels(subject_type, subject_id, key, value) VALUES (?, ?, ?, ?) ON CONFLICT(subject_type, subject_id, key) DO UPDATE SET value = excluded.value",
+            )
+            .bind(subject_type)
+            .bind(subject_id)
+            .bind(key)
+            .bind(value)
+            .execute(&mut *self.tx)
+            .await
+            .with_context(|| format!("label {subject_type} {subject_id} with {key}"))?;
+        }
+        Ok(())
+    }
+
+    // Leaves the subject with exactly `labels`, answering the ones it had before.
+    pub async fn replace_labels(
+        &mut self,
+        subject_type: &str,
+        subject_id: &str,
+        labels: &BTreeMap<String, String>,
+    ) -> Result<BTreeMap<String, String>> {
+        let previous = sqlx::query_as::<_, (String, String)>(
+            "DELETE FROM labels WHERE subject_type = ? AND subject_id = ? RETURNING key, value",
+        )
+        .bind(subject_type)
+        .bind(subject_id)
+        .fetch_all(&mut *self.tx)
+        .await
+        .with_context(|| format!("clear labels of {subject_type} {subject_id}"))?;
+        self.put_labels(subject_type, subject_id, labels).await?;
+        Ok(previous.into_iter().collect())
+    }
+
+    pub async fn set_job_source(&mut self, job_id: &str, source: &SubmissionSource) -> Result<()> {
+        sqlx::query(
+            "UPDATE jobs SET source_token = ?, source_addr = ?, source_client_id = ? WHERE job_id = ?",
+        )
+        .bind(&source.token_label)
+        .bind(&source.remote_addr)
+        .bind(&source.client_id)
+        .bind(job_id)
+        .execute(&mut *self.tx)
+        .await
+        .with_context(|| format!("record source of job {job_id}"))?;
+        Ok(())
+    }
+
+    pub async fn set_transfer_source(
+        &mut self,
+        transfer_id: &str,
+        source: &SubmissionSource,
+    ) -> Result<()> {
+        sqlx::query(
+            "UPDATE transfers SET source_token = ?, source_addr = ?, source_client_id = ? WHERE transfer_id = ?",
+        )
+        .bind(&source.token_label)
+        .bind(&source.remote_addr)
+        .bind(&source.client_id)
+        .bind(transfer_id)
+        .execute(&mut *self.tx)
+        .await
+        .with_context(|| format!("record source of transfer {transfer_id}"))?;
+        Ok(())
+    }
+
+    pub async fn add_job_dependency(&mut self, job_id: &str, depends_on: &str) -> Result<()> {
+        write_job_dependency(&mut *self.tx, job_id, depends_on).await
+    }
+
+    pub async fn insert_job_result(&mut self, job_id: &str, result: Value) -> Result<()> {
+        write_job_result(&mut *self.tx, job_id, &result).await
+    }
+
+    pub async fn create_transfer(&mut self, metadata: &Value) -> Result<TransferRecord> {
+        let transfer_id =
+            insert_transfer(&mut *self.tx, self.cipher.as_ref(), None, metadata).await?;
+        let record = fetch_transfer(&mut *self.tx, &transfer_id)
+            .await?
+            .context("transfer after insert")?;
+        open_transfer(self.cipher.as_ref(), record)
+    }
+
+    // A transfer that succeeds is charged its reserved bytes in the hour it succeeded; one that
+    // ends any other way gives them back.
+    pub async fn update_transfer_status(
+        &mut self,
+        transfer_id: &str,
+        status: &str,
+        failure_reason: Option<&str>,
+    ) -> Result<()> {
+        let now = CanonicalTimestamp::now();
+        let updated = sqlx::query("UPDATE transfers SET status = ?, updated_at = ?, failure_reason = ? WHERE transfer_id = ? AND status != 'cancelled'")
+            .bind(status)
+            .bind(now)
+            .bind(failure_reason)
+            .bind(transfer_id)
+            .execute(&mut *self.tx)
+            .await
+            .with_context(|| format!("update transfer {transfer_id}"))?;
+        if updated.rows_affected() == 0 || matches!(status, "queued" | "running") {
+            return Ok(());
+        }
+        if status == "success" {
+            sqlx::query(
+                "INSERT INTO quota_usage(subject_kind, subject, used_at, bytes) SELECT subject_kind, subject, ?, bytes FROM quota_reservations WHERE transfer_id = ? ON CONFLICT(subject_kind, subject, used_at) DO UPDATE SET bytes = bytes + excluded.bytes",
+            )
+            .bind(quota_bucket(now))
+            .bind(transfer_id)
+            .execute(&mut *self.tx)
+            .await
+            .with_context(|| format!("charge quota usage for transfer {transfer_id}"))?;
+        }
+        self.release_quota_reservation(transfer_id).await
+    }
+
+    // Holds `bytes` for `transfer_id` against every claim, unless that would take a limited
+    // subject's usage in the window, plus what is already held, past its limit.
+    pub async fn reserve_quota(
+        &mut self,
+        transfer_id: &str,
+        claims: &[QuotaClaim],
+        bytes: i64,
+        window_start: &str,
+    ) -> Result<()> {
+        let window_start = CanonicalTimestamp::parse(window_start)?;
+        for claim in claims {
+            let Some(limit_bytes) = claim.limit_bytes else {
+                continue;
+            };
+            let used_bytes = sqlx::query_scalar::<_, i64>(
+                "SELECT (SELECT COALESCE(SUM(bytes), 0) FROM quota_usage WHERE subject_kind = ?1 AND subject = ?2 AND used_at > ?3) + (SELECT COALESCE(SUM(bytes), 0) FROM quota_reservations WHERE subject_kind = ?1 AND subject = ?2)",
+            )
+            .bind(&claim.subject_kind)
+            .bind(&claim.subject)
+            .bind(window_start)
+            .fetch_one(&mut *self.tx)
+            .await
+            .with_context(|| format!("sum quota usage for {} {}", claim.subject_kind, claim.subject))?;
+            if used_bytes.saturating_add(bytes) > limit_bytes {
+                return Err(StorageError::QuotaExceeded {
+                    subject_kind: claim.subject_kind.clone(),
+                    subject: claim.subject.clone(),
+                    limit_bytes,
+                    used_bytes,
+                    requested_bytes: bytes,
+                });
+            }
+        }
+        let now = CanonicalTimestamp::now();
+        for claim in claims {
+            sqlx::query(
+                "INSERT INTO quota_reservations(transfer_id, subject_kind, subject, bytes, reserved_at) VALUES (?, ?, ?, ?, ?)",
+            )
+            .bind(transfer_id)
+            .bind(&claim.subject_kind)
+            .bind(&claim.subject)
+            .bind(bytes)
+            .bind(now)
+            .execute(&mut *self.tx)
+            .await
+            .with_context(|| format!("reserve quota for transfer {transfer_id}"))?;
+        }
+        Ok(())
+    }
+
+    async fn release_quota_reservation(&mut self, transfer_id: &str) -> Result<()> {
+        sqlx::query("DELETE FROM quota_reservations WHERE transfer_id = ?")
+            .bind(transfer_id)
+            .execute(&mut *self.tx)
+            .await
+            .with_context(|| format!("release quota reservation for transfer {transfer_id}"))?;
+        Ok(())
+    }
+
+    pub async fn record_transfer_chunk(&mut self, transfer_id: &str, bytes: u64) -> Result<()> {
+        let result = sqlx::query(
+            "UPDATE transfer_progress SET bytes_sent = bytes_sent + ?, chunks_sent = chunks_sent + 1, last_chunk_at = ?, stall_notified = 0 WHERE transfer_id = ?",
+        )
+        .bind(bytes as i64)
+        .bind(CanonicalTimestamp::now())
+        .bind(transfer_id)
+        .execute(&mut *self.tx)
+        .await
+        .with_context(|| format!("record transfer chunk for {transfer_id}"))?;
+        if result.rows_affected() == 0 {
+            return Err(StorageError::not_found(format!(
+                "transfer progress for {transfer_id}"
+            )));
+        }
+        Ok(())
+    }
+
+    // Marks running transfers with no chunk since `stalled_before` as reported, once each.
+    pub async fn claim_stalled_transfers(&mut self, stalled_before: &str) -> Result<Vec<String>> {
+        sqlx::query_scalar::<_, String>(
+            "UPDATE transfer_progress SET stall_notified = 1 WHERE stall_notified = 0 AND transfer_id IN (SELECT p.transfer_id FROM transfer_progress p JOIN transfers t ON t.transfer_id = p.transfer_id WHERE t.status = 'running' AND COALESCE(p.last_chunk_at, t.updated_at) < ?) RETURNING transfer_id",
+        )
+        .bind(CanonicalTimestamp::parse(stalled_before)?)
+        .fetch_all(&mut *self.tx)
+        .await
+        .context("claim stalled transfers")
+    }
+
+    pub async fn cancel_transfer(&mut self, transfer_id: &str) -> Result<bool> {
+        let cancelled = sqlx::query(
+            "UPDATE transfers SET status = 'cancelled', updated_at = ?, failure_reason = 'cancelled' WHERE transfer_id = ? AND status IN ('queued', 'running')",
+        )
+        .bind(CanonicalTimestamp::now())
+        .bind(transfer_id)
+        .execute(&mut *self.tx)
+        .await
+        .with_context(|| format!("cancel transfer {transfer_id}"))?;
+        if cancelled.rows_affected() == 0 {
+            return Ok(false);
+        }
+        self.release_quota_reservation(transfer_id).await?;
+        Ok(true)
+    }
+
+    pub async fn create_transfer_with_progress(
+        &mut self,
+        job_id: Option<&str>,
+        metadata: &Value,
+        progress: &TransferProgress,
+    ) -> Result<TransferRecord> {
+        let transfer_id =
+            insert_transfer(&mut *self.tx, self.cipher.as_ref(), job_id, metadata).await?;
+        write_transfer_progress(&mut *self.tx, &transfer_id, progress).await?;
+        let record = fetch_transfer(&mut *self.tx, &transfer_id)
+            .await?
+            .context("transfer after insert")?;
+        open_transfer(self.cipher.as_ref(), record)
+    }
+
+    pub async fn add_bundle_members(
+        &mut self,
+        bundle_id: &str,
+        members: &[BundleMember],
+    ) -> Result<()> {
+        for member in members {
+            sqlx::query(
+                "INSERT INTO bundle_members(bundle_id, position, name, media_type, size_bytes, sha256, status) VALUES (?, ?, ?, ?, ?, ?, ?)",
+            )
+            .bind(bundle_id)
+            .bind(member.position)
+            .bind(&member.name)
+            .bind(&member.media_type)
+            .bind(member.size_bytes)
+            .bind(&member.sha256)
+            .bind(&member.status)
+            .execute(&mut *self.tx)
+            .await
+            .with_context(|| format!("record member {} of bundle {bundle_id}", member.name))?;
+        }
+        Ok(())
+    }
+
+    // Fails one of a job's transfers that has not settled yet.
+    pub async fn fail_job_transfer(
+        &mut self,
+        job_id: &str,
+        transfer_id: &str,
+        reason: &str,
+    ) -> Result<u64> {
+        let failed = sqlx::query(
+            "UPDATE transfers SET status = 'failed', updated_at = ?, failure_reason = ? WHERE job_id = ? AND transfer_id = ? AND status IN ('queued', 'running')",
+        )
+        .bind(CanonicalTimestamp::now())
+        .bind(reason)
+        .bind(job_id)
+        .bind(transfer_id)
+        .execute(&mut *self.tx)
+        .await
+        .with_context(|| format!("fail transfer {transfer_id} of job {job_id}"))?;
+        Ok(failed.rows_affected())
+    }
+
+    pub async fn fail_queued_transfers(&mut self, job_id: &str, reason: &str) -> Result<u64> {
+        let failed = sqlx::query(
+            "UPDATE transfers SET status = 'failed', updated_at = ?, failure_reason = ? WHERE job_id = ? AND status = 'queued'",
+        )
+        .bind(CanonicalTimestamp::now())
+        .bind(reason)
+        .bind(job_id)
+        .execute(&mut *self.tx)
+        .await
+        .with_context(|| format!("fail queued transfers for job {job_id}"))?;
+        Ok(failed.rows_affected())
+    }
+
+    pub async fn settle_waiting_job(
+        &mut self,
+        job_id: &str,
+        status: &str,
+        failure_reason: Option<&str>,
+    ) -> Result<bool> {
+        self.move_job_from("waiting", job_id, status, failure_reason).await
+    }
+
+    pub async fn requeue_deferred_job(&mut self, job_id: &str) -> Result<bool> {
+        self.move_job_from("deferred", job_id, "queued", None).await
+    }
+
+    pub async fn hold_job(
+        &mut self,
+        job_id: &str,
+        held_by: &str,
+        reason: &str,
+    ) -> Result<Option<String>> {
+        let status: Option<String> = sqlx::query_scalar("SELECT status FROM jobs WHERE job_id = ?")
+            .bind(job_id)
+            .fetch_optional(&mut *self.tx)
+            .await
+            .with_context(|| format!("query status of job {job_id}"))?;
+        let Some(status) = status.filter(|status| matches!(status.as_str(), "queued" | "deferred"))
+        else {
+            return Ok(None);
+        };
+        if !self.move_job_from(&status, job_id, "held", None).await? {
+            return Ok(None);
+        }
+        sqlx::query(
+            "INSERT INTO job_holds(job_id, previous_status, held_by, reason, held_at) VALUES (?, ?, ?, ?, ?) ON CONFLICT(job_id) DO UPDATE SET previous_status = excluded.previous_status, held_by = excluded.held_by, reason = excluded.reason, held_at = excluded.held_at",
+        )
+        .bind(job_id)
+        .bind(&status)
+        .bind(held_by)
+        .bind(reason)
+        .bind(CanonicalTimestamp::now())
+        .execute(&mut *self.tx)
+        .await
+        .with_context(|| format!("hold job {job_id}"))?;
+        Ok(Some(status))
+    }
+
+    pub async fn release_job_hold(&mut self, job_id: &str) -> Result<Option<String>> {
+        let previous: Option<String> = sqlx::query_scalar(
+            "SELECT h.previous_status FROM job_holds h JOIN jobs j ON j.job_id = h.job_id WHERE h.job_id = ? AND j.status = 'held'",
+        )
+        .bind(job_id)
+        .fetch_optional(&mut *self.tx)
+        .await
+        .with_context(|| format!("query hold on job {job_id}"))?;
+        let Some(previous) = previous else {
+            return Ok(None);
+        };
+        if !self.move_job_from("held", job_id, &previous, None).await? {
+            return Ok(None);
+        }
+        sqlx::query("DELETE FROM job_holds WHERE job_id = ?")
+            .bind(job_id)
+            .execute(&mut *self.tx)
+            .await
+            .with_context(|| format!("release hold on job {job_id}"))?;
+        Ok(Some(previous))
+    }
+
+    async fn move_job_from(
+        &mut self,
+        from: &str,
+        job_id: &str,
+        status: &str,
+        failure_reason: Option<&str>,
+    ) -> Result<bool> {
+        let settled = sqlx::query(
+            "UPDATE jobs SET status = ?, updated_at = ?, failure_reason = ? WHERE job_id = ? AND status = ?",
+        )
+        .bind(status)
+        .bind(CanonicalTimestamp::now())
+        .bind(failure_reason)
+        .bind(job_id)
+        .bind(from)
+        .execute(&mut *self.tx)
+        .await
+        .with_context(|| format!("settle {from} job {job_id}"))?;
+        Ok(settled.rows_affected() > 0)
+    }
+
+    pub async fn mark_job_in_transit(
+        &mut self,
+        job_id: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<bool> {
+        let updated = sqlx::query(
+            "UPDATE jobs SET status = 'in_transit', updated_at = ?, transit_expires_at = ? WHERE job_id = ? AND status NOT IN ('success', 'partial_failure', 'failed', 'cancelled')",
+        )
+        .bind(CanonicalTimestamp::now().to_string())
+        .bind(CanonicalTimestamp::from(expires_at))
+        .bind(job_id)
+        .execute(&mut *self.tx)
+        .await
+        .with_context(|| format!("mark job {job_id} in transit"))?;
+        Ok(updated.rows_affected() > 0)
+    }
+
+    // Queuing an envelope twice keeps the first row.
+    pub async fn queue_outbox(
+        &mut self,
+        kind: &str,
+        message_id: &str,
+        job_id: Option<&str>,
+        operation: &str,
+        destination_identity: &str,
+        envelope: &Value,
+    ) -> Result<()> {
+        let envelope_json = serde_json::to_string(envelope).context("serialize outbox envelope")?;
+        sqlx::query(
+            "INSERT INTO outbox(message_id, kind, job_id, operation, destination_identity, envelope_json, status, queued_at) VALUES (?, ?, ?, ?, ?, ?, 'pending', ?) ON CONFLICT(message_id) DO NOTHING",
+        )
+        .bind(message_id)
+        .bind(kind)
+        .bind(job_id)
+        .bind(operation)
+        .bind(destination_identity)
+        .bind(seal(self.cipher.as_ref(), &envelope_json)?)
+        .bind(CanonicalTimestamp::now())
+        .execute(&mut *self.tx)
+        .await
+        .with_context(|| format!("queue outbox envelope {message_id}"))?;
+        Ok(())
+    }
+
+    // False when the event was cached already.
+    pub async fn cache_event(&mut self, event: &CachedEvent<'_>) -> Result<bool> {
+        let contract_version = self.contract_version.clone();
+        write_cached_event(&mut *self.tx, self.cipher.as_ref(), contract_version, event).await
+    }
+
+    // Queuing the same message twice for a subscription keeps the first delivery.
+    pub async fn queue_webhook_delivery(
+        &mut self,
+        delivery_id: &str,
+        subscription_id: &str,
+        message_id: &str,
+        event_name: &str,
+        body: &Value,
+    ) -> Result<()> {
+        let body_json = serde_json::to_string(body).context("serialize webhook body")?;
+        let now = CanonicalTimestamp::now();
+        sqlx::query(
+            "INSERT INTO webhook_deliveries(delivery_id, subscription_id, message_id, event_name, body_json, attempts, next_attempt_at, queued_at) VALUES (?, ?, ?, ?, ?, 0, ?, ?) ON CONFLICT(subscription_id, message_id) DO NOTHING",
+        )
+        .bind(delivery_id)
+        .bind(subscription_id)
+        .bind(message_id)
+        .bind(event_name)
+        .bind(seal(self.cipher.as_ref(), &body_json)?)
+        .bind(now)
+        .bind(now)
+        .execute(&mut *self.tx)
+        .await
+        .with_context(|| format!("queue webhook delivery {delivery_id}"))?;
+        Ok(())
+    }
+
+    // False when the subscription is gone or disabled already. Its pending deliveries stay.
+    pub async fn disable_webhook_subscription(
+        &mut self,
+        subscription_id: &str,
+        now: DateTime<Utc>,
+    ) -> Result<bool> {
+        let disabled = sqlx::query(
+            "UPDATE webhook_subscriptions SET enabled = 0, disabled_at = ? WHERE subscription_id = ? AND enabled = 1",
+        )
+        .bind(CanonicalTimestamp::from(now))
+        .bind(subscription_id)
+        .execute(&mut *self.tx)
+        .await
+        .with_context(|| format!("disable webhook subscription {subscription_id}"))?;
+        Ok(disabled.rows_affected() == 1)
+    }
+
+    // Only pending entries move, so an entry is carried by at most one bundle.
+    pub async fn mark_outbox_exported(
+        &mut self,
+        message_ids: &[String],
+        bundle_id: &str,
+    ) -> Result<Vec<String>> {
+        let mut exported = Vec::new();
+        let now = CanonicalTimestamp::now();
+        for message_id in message_ids {
+            let updated = sqlx::query(
+                "UPDATE outbox SET status = 'exported', bundle_id = ?, exported_at = ? WHERE message_id = ? AND status = 'pending'",
+            )
+            .bind(bundle_id)
+            .bind(now)
+            .bind(message_id)
+            .execute(&mut *self.tx)
+            .await
+            .with_context(|| format!("mark outbox envelope {message_id} exported"))?;
+            if updated.rows_affected() > 0 {
+                exported.push(message_id.clone());
+            }
+        }
+        Ok(exported)
+    }
+
+    pub async fn put_allowlist_entry(
+        &mut self,
+        identity_hash: &IdentityHash,
+        note: Option<&str>,
+        role: &str,
+        status: &str,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<()> {
+        let note = note
+            .map(|note| seal(self.cipher.as_ref(), note))
+            .transpose()?;
+        sqlx::query(
+            "INSERT INTO acl_allowlist(identity_hash, note, created_at, role, status, expires_at, approved_at) VALUES (?, ?, ?, ?, ?, ?, NULL) ON CONFLICT(identity_hash) DO UPDATE SET note = excluded.note, role = excluded.role, status = excluded.status, expires_at = excluded.expires_at, approved_at = NULL",
+        )
+        .bind(identity_hash.as_str())
+        .bind(note)
+        .bind(CanonicalTimestamp::now())
+        .bind(role)
+        .bind(status)
+        .bind(expires_at.map(CanonicalTimestamp::from))
+        .execute(&mut *self.tx)
+        .await
+        .with_context(|| format!("insert allowlist identity {identity_hash}"))?;
+        Ok(())
+    }
+
+    // False unless the entry was pending.
+    pub async fn approve_allowlist(&mut self, identity_hash: &IdentityHash) -> Result<bool> {
+        let approved = sqlx::query(
+            "UPDATE acl_allowlist SET status = 'active', approved_at = ? WHERE identity_hash = ? AND status = 'pending'",
+        )
+        .bind(CanonicalTimestamp::now())
+        .bind(identity_hash.as_str())
+        .execute(&mut *self.tx)
+        .await
+        .with_context(|| format!("approve allowlist identity {identity_hash}"))?;
+        Ok(approved.rows_affected() > 0)
+    }
+
+    pub async fn set_allowlist_public_key(
+        &mut self,
+        identity_hash: &IdentityHash,
+        public_key: Option<&str>,
+    ) -> Result<bool> {
+        let result = sqlx::query("UPDATE acl_allowlist SET public_key = ? WHERE identity_hash = ?")
+            .bind(public_key)
+            .bind(identity_hash.as_str())
+            .execute(&mut *self.tx)
+            .await
+            .with_context(|| format!("set allowlist key for {identity_hash}"))?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    // Entries are kept with an `expired` status rather than deleted so the history survives.
+    pub async fn expire_allowlist(&mut self, now: DateTime<Utc>) -> Result<Vec<String>> {
+        sqlx::query_scalar::<_, String>(
+            "UPDATE acl_allowlist SET status = 'expired' WHERE status IN ('active', 'pending') AND expires_at IS NOT NULL AND expires_at <= ? RETURNING identity_hash",
+        )
+        .bind(CanonicalTimestamp::from(now))
+        .fetch_all(&mut *self.tx)
+        .await
+        .context("expire allowlist entries")
+    }
+
+    pub async fn delete_allowlist(&mut self, identity_hash: &IdentityHash) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM acl_allowlist WHERE identity_hash = ?")
+            .bind(identity_hash.as_str())
+            .execute(&mut *self.tx)
+            .await
+            .with_context(|| format!("delete allowlist identity {identity_hash}"))?;
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+async fn bump_write_sequence<'e, E>(executor: E) -> Result<i64>
+where
+    E: sqlx::Executor<'e, Database = Sqlite>,
+{
+    sqlx::query_scalar::<_, i64>(
+        "UPDATE write_sequence SET seq = seq + 1 WHERE id = 1 RETURNING seq",
+    )
+    .fetch_one(executor)
+    .await
+    .context("bump write sequence")
+}
+
+async fn insert_job<'e, E>(
+    executor: E,
+    cipher: Option<&EncryptedColumn>,
+    contract_version: Option<&str>,
+    job_id: &str,
+    submitted_at: &CanonicalTimestamp,
+    operation: &str,
+    payload: &Value,
+) -> Result<()>
+where
+    E: sqlx::Executor<'e, Database = Sqlite>,
+{
+    let now = CanonicalTimestamp::now().to_string();
+    let payload_json = serde_json::to_string(payload).context("serialize job payload")?;
+    let (payload_bytes, payload_sha256) = payload_digest(&payload_json);
+    let payload_json = seal(cipher, &payload_json)?;
+
+    sqlx::query(
+        "INSERT INTO jobs(job_id, operation, status, payload_json, submitted_at, updated_at, contract_version, payload_bytes, payload_sha256) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(job_id)
+    .bind(operation)
+    .bind("queued")
+    .bind(&payload_json)
+    .bind(submitted_at.to_string())
+    .bind(&now)
+    .bind(contract_version)
+    .bind(payload_bytes)
+    .bind(payload_sha256)
+    .execute(executor)
+    .await
+    .context("insert job")?;
+    Ok(())
+}
+
+async fn write_feed_event<'e, E>(executor: E, event_type: &str, data: &Value) -> Result<FeedEvent>
+where
+    E: sqlx::Executor<'e, Database = Sqlite>,
+{
+    let emitted_at = CanonicalTimestamp::now().to_string();
+    let payload_json = serde_json::to_string(data).context("serialize feed event payload")?;
+    let result = sqlx::query(
+        "INSERT INTO event_feed(event_type, payload_json, emitted_at) VALUES (?, ?, ?)",
+    )
+    .bind(event_type)
+    .bind(payload_json)
+    .bind(&emitted_at)
+    .execute(executor)
+    .await
+    .with_context(|| format!("append feed event {event_type}"))?;
+    Ok(FeedEvent {
+        seq: result.last_insert_rowid(),
+        event_type: event_type.to_string(),
+        data: data.clone(),
+        emitted_at,
+    })
+}
+
+// A mesh event on its way into the event cache.
+pub struct CachedEvent<'a> {
+    pub event_id: &'a str,
+    pub event_name: &'a str,
+    pub source_identity: &'a str,
+    pub sent_at: DateTime<Utc>,
+    pub payload: &'a Value,
+}
+
+async fn write_cached_event<'e, E>(
+    executor: E,
+    cipher: Option<&EncryptedColumn>,
+    contract_version: Option<String>,
+    event: &CachedEvent<'_>,
+) -> Result<bool>
+where
+    E: sqlx::Executor<'e, Database = Sqlite>,
+{
+    let payload_json = serde_json::to_string(event.payload).context("serialize cached event")?;
+    let (payload_bytes, payload_sha256) = payload_digest(&payload_json);
+    let inserted = sqlx::query(
+        "INSERT INTO cached_events(event_id, event_name, payload_json, received_at, source_identity, sent_at, contract_version, payload_bytes, payload_sha256) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?) ON CONFLICT(event_id) DO NOTHING",
+    )
+    .bind(event.event_id)
+    .bind(event.event_name)
+    .bind(seal(cipher, &payload_json)?)
+    .bind(CanonicalTimestamp::now())
+    .bind(event.source_identity)
+    .bind(CanonicalTimestamp::from(event.sent_at))
+    .bind(contract_version)
+    .bind(payload_bytes)
+    .bind(payload_sha256)
+    .execute(executor)
+    .await
+    .with_context(|| format!("insert cached event {}", event.event_id))?;
+    Ok(inserted.rows_affected() == 1)
+}
+
+async fn write_feature_flag<'e, E>(
+    executor: E,
+    name: &str,
+    enabled: bool,
+    variant: Option<&Value>,
+    updated_by: &str,
+) -> Result<FeatureFlagRecord>
+where
+    E: sqlx::Executor<'e, Database = Sqlite>,
+{
+    let variant_json = variant
+        .map(serde_json::to_string)
+        .transpose()
+        .context("serialize feature flag variant")?;
+    let row = sqlx::query_as::<_, (String, bool, Option<String>, String, String)>(
+        "INSERT INTO feature_flags(name, enabled, variant_json, updated_at, updated_by) VALUES (?, ?, ?, ?, ?) ON CONFLICT(name) DO UPDATE SET enabled = excluded.enabled, variant_json = excluded.variant_json, updated_at = excluded.updated_at, updated_by = excluded.updated_by RETURNING name, enabled, variant_json, updated_at, updated_by",
+    )
+    .bind(name)
+    .bind(enabled)
+    .bind(variant_json)
+    .bind(CanonicalTimestamp::now())
+    .bind(updated_by)
+    .fetch_one(executor)
+    .await
+    .with_context(|| format!("put feature flag {name}"))?;
+    feature_flag_from_row(row)
+}
+
+fn feature_flag_from_row(
+    (name, enabled, variant_json, updated_at, updated_by): (
+        String,
+        bool,
+        Option<String>,
+        String,
+        String,
+    ),
+) -> Result<FeatureFlagRecord> {
+    let variant = variant_json
+        .map(|json| serde_json::from_str(&json))
+        .transpose()
+        .with_context(|| format!("parse feature flag variant for {name}"))?;
+    Ok(FeatureFlagRecord {
+        name,
+        enabled,
+        variant,
+        updated_at,
+        updated_by,
+    })
+}
+
+async fn fetch_job<'e, E>(executor: E, job_id: &str) -> Result<Option<JobRecord>>
+where
+    E: sqlx::Executor<'e, Database = Sqlite>,
+{
+    sqlx::query_as::<_, JobRecord>(
+        "SELECT job_id, operation, status, payload_json, submitted_at, updated_at, failure_reason, dispatch_json, requested_operation FROM jobs WHERE job_id = ?",
+    )
+    .bind(job_id)
+    .fetch_optional(executor)
+    .await
+    .with_context(|| format!("query job {job_id}"))
+}
+
+// Byte length and hex SHA-256 of a payload's plaintext JSON.
+pub fn payload_digest(payload_json: &str) -> (i64, String) {
+    let digest = Sha256::digest(payload_json.as_bytes());
+    (payload_json.len() as i64, format!("{digest:x}"))
+}
+
+fn open_job(cipher: Option<&EncryptedColumn>, mut record: JobRecord) -> Result<JobRecord> {
+    record.payload_json = open(cipher, &record.payload_json).map_err(|err| err.in_table("jobs"))?;
+    Ok(record)
+}
+
+async fn write_job_status<'e, E>(
+    executor: E,
+    job_id: &str,
+    status: &str,
+    failure_reason: Option<&str>,
+) -> Result<()>
+where
+    E: sqlx::Executor<'e, Database = Sqlite>,
+{
+    let now = CanonicalTimestamp::now().to_string();
+    // A cancelled job keeps its status whatever a worker still in flight reports afterwards.
+    sqlx::query(
+        "UPDATE jobs SET status = ?, updated_at = ?, failure_reason = ? WHERE job_id = ? AND status != 'cancelled'",
+    )
+    .bind(status)
+    .bind(now)
+    .bind(failure_reason)
+    .bind(job_id)
+    .execute(executor)
+    .await
+    .with_context(|| format!("update job status for {job_id}"))?;
+    Ok(())
+}
+
+async fn write_job_dispatch<'e, E>(executor: E, job_id: &str, dispatch: &Value) -> Result<()>
+where
+    E: sqlx::Executor<'e, Database = Sqlite>,
+{
+    let dispatch_json = serde_json::to_string(dispatch).context("serialize job dispatch")?;
+    sqlx::query("UPDATE jobs SET dispatch_json = ? WHERE job_id = ?")
+        .bind(dispatch_json)
+        .bind(job_id)
+        .execute(executor)
+        .await
+        .with_context(|| format!("record dispatch for job {job_id}"))?;
+    Ok(())
+}
+
+async fn write_job_requested_operation<'e, E>(
+    executor: E,
+    job_id: &str,
+    operation: &str,
+) -> Result<()>
+where
+    E: sqlx::Executor<'e, Database = Sqlite>,
+{
+    sqlx::query("UPDATE jobs SET requested_operation = ? WHERE job_id = ?")
+        .bind(operation)
+        .bind(job_id)
+        .execute(executor)
+        .await
+        .with_context(|| format!("record requested operation for job {job_id}"))?;
+    Ok(())
+}
+
+async fn write_job_dependency<'e, E>(executor: E, job_id: &str, depends_on: &str) -> Result<()>
+where
+    E: sqlx::Executor<'e, Database = Sqlite>,
+{
+    sqlx::query("INSERT OR IGNORE INTO job_dependencies(job_id, depends_on) VALUES (?, ?)")
+        .bind(job_id)
+        .bind(depends_on)
+        .execute(executor)
+        .await
+        .with_context(|| format!("record dependency of job {job_id} on {depends_on}"))?;
+    Ok(())
+}
+
+async fn fetch_job_id_by_idempotency_key<'e, E>(executor: E, key: &str) -> Result<Option<String>>
+where
+    E: sqlx::Executor<'e, Database = Sqlite>,
+{
+    sqlx::query_scalar::<_, String>("SELECT job_id FROM jobs WHERE idempotency_key = ? LIMIT 1")
+        .bind(key)
+        .fetch_optional(executor)
+        .await
+        .context("query job by idempotency key")
+}
+
+async fn write_job_result<'e, E>(executor: E, job_id: &str, result: &Value) -> Result<()>
+where
+    E: sqlx::Executor<'e, Database = Sqlite>,
+{
+    let completed_at = CanonicalTimestamp::now().to_string();
+    let result_json = serde_json::to_string(result).context("serialize job result")?;
+
+    sqlx::query(
+        "INSERT INTO job_results(job_id, result_json, completed_at) VALUES (?, ?, ?) ON CONFLICT(job_id) DO UPDATE SET result_json = excluded.result_json, completed_at = excluded.completed_at",
+    )
+    .bind(job_id)
+    .bind(result_json)
+    .bind(completed_at)
+    .execute(executor)
+    .await
+    .with_context(|| format!("insert job result for {job_id}"))?;
+    Ok(())
+}
+
+async fn insert_transfer<'e, E>(
+    executor: E,
+    cipher: Option<&EncryptedColumn>,
+    job_id: Option<&str>,
+    metadata: &Value,
+) -> Result<String>
+where
+    E: sqlx::Executor<'e, Database = Sqlite>,
+{
+    let transfer_id = Uuid::now_v7().to_string();
+    let now = CanonicalTimestamp::now().to_string();
+    let metadata_json = serde_json::to_string(metadata).context("serialize transfer metadata")?;
+    let metadata_json = seal(cipher, &metadata_json)?;
+
+    sqlx::query(
+        "INSERT INTO transfers(transfer_id, status, metadata_json, submitted_at, updated_at, job_id) VALUES (?, ?, ?, ?, ?, ?)",
+    )
+    .bind(&transfer_id)
+    .bind("queued")
+    .bind(&metadata_json)
+    .bind(&now)
+    .bind(&now)
+    .bind(job_id)
+    .execute(executor)
+    .await
+    .context("insert transfer")?;
+    Ok(transfer_id)
+}
+
+// A row written without content keeps the content, media type and verdict already held.
+async fn write_received_file<'e, E>(
+    executor: E,
+    cipher: Option<&EncryptedColumn>,
+    file: &ReceivedFile,
+    content: Option<&[u8]>,
+) -> Result<()>
+where
+    E: sqlx::Executor<'e, Database = Sqlite>,
+{
+    let content_base64 = content
+        .map(|content| seal(cipher, &STANDARD.encode(content)))
+        .transpose()?;
+    sqlx::query(
+        "INSERT INTO received_files(sha256, size_bytes, file_name, source_identity, received_at, bundle_id, media_type, quarantine_reason, content_base64) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?) ON CONFLICT(sha256, size_bytes) DO UPDATE SET file_name = excluded.file_name, source_identity = excluded.source_identity, received_at = excluded.received_at, bundle_id = excluded.bundle_id, media_type = COALESCE(excluded.media_type, received_files.media_type), quarantine_reason = CASE WHEN excluded.content_base64 IS NULL THEN received_files.quarantine_reason ELSE excluded.quarantine_reason END, content_base64 = COALESCE(excluded.content_base64, received_files.content_base64)",
+    )
+    .bind(&file.sha256)
+    .bind(file.size_bytes)
+    .bind(&file.file_name)
+    .bind(&file.source_identity)
+    .bind(CanonicalTimestamp::parse(&file.received_at)?)
+    .bind(&file.bundle_id)
+    .bind(&file.media_type)
+    .bind(&file.quarantine_reason)
+    .bind(content_base64)
+    .execute(executor)
+    .await
+    .with_context(|| format!("record received file {}", file.sha256))?;
+    Ok(())
+}
+
+async fn read_feed_mark<'e, E>(executor: E, key: &str) -> Result<i64>
+where
+    E: sqlx::Executor<'e, Database = Sqlite>,
+{
+    Ok(
+        sqlx::query_scalar::<_, String>("SELECT value FROM storage_meta WHERE key = ?")
+            .bind(key)
+            .fetch_optional(executor)
+            .await
+            .with_context(|| format!("query {key}"))?
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(0),
+    )
+}
+
+async fn write_encryption_canary<'e, E>(executor: E, cipher: &EncryptedColumn) -> Result<()>
+where
+    E: sqlx::Executor<'e, Database = Sqlite>,
+{
+    let sealed = cipher.encrypt(ENCRYPTION_CANARY_VALUE)?;
+    sqlx::query(
+        "INSERT INTO storage_meta(key, value) VALUES (?, ?) ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+    )
+    .bind(ENCRYPTION_CANARY_KEY)
+    .bind(sealed)
+    .execute(executor)
+    .await
+    .context("write encryption canary")?;
+    Ok(())
+}
+
+// Moves each cursor (or just `token_label`'s) with more than `max_unacked` unacked rows up to
+// the newest `max_unacked`, adding what it skipped to `dropped`.
+async fn cap_notification_cursors<'e, E>(
+    executor: E,
+    max_unacked: i64,
+    token_label: Option<&str>,
+) -> Result<()>
+where
+    E: sqlx::Executor<'e, Database = Sqlite>,
+{
+    sqlx::query(
+        "UPDATE notification_cursors SET dropped = dropped + (SELECT COUNT(*) FROM notifications WHERE seq > notification_cursors.acked_seq) - ?1, acked_seq = (SELECT seq FROM notifications WHERE seq > notification_cursors.acked_seq ORDER BY seq DESC LIMIT 1 OFFSET ?1), updated_at = ?2 WHERE (SELECT COUNT(*) FROM notifications WHERE seq > notification_cursors.acked_seq) > ?1 AND (?3 IS NULL OR token_label = ?3)",
+    )
+    .bind(max_unacked.max(0))
+    .bind(CanonicalTimestamp::now())
+    .bind(token_label)
+    .execute(executor)
+    .await
+    .context("cap notification cursors")?;
+    Ok(())
+}
+
+async fn fetch_transfer<'e, E>(executor: E, transfer_id: &str) -> Result<Option<TransferRecord>>
+where
+    E: sqlx::Executor<'e, Database = Sqlite>,
+{
+    sqlx::query_as::<_, TransferRecord>(
+        "SELECT transfer_id, status, metadata_json, submitted_at, updated_at, failure_reason, job_id FROM transfers WHERE transfer_id = ?",
+    )
+    .bind(transfer_id)
+    .fetch_optional(executor)
+    .await
+    .with_context(|| format!("query transfer {transfer_id}"))
+}
+
+fn open_transfer(
+    cipher: Option<&EncryptedColumn>,
+    mut record: TransferRecord,
+) -> Result<TransferRecord> {
+    record.metadata_json =
+        open(cipher, &record.metadata_json).map_err(|err| err.in_table("transfers"))?;
+    Ok(record)
+}
+
+async fn write_transfer_progress<'e, E>(
+    executor: E,
+    transfer_id: &str,
+    progress: &TransferProgress,
+) -> Result<()>
+where
+    E: sqlx::Executor<'e, Database = Sqlite>,
+{
+    sqlx::query(
+        "INSERT INTO transfer_progress(transfer_id, bytes_total, bytes_sent, chunks_total, chunks_sent, last_chunk_at) VALUES (?, ?, ?, ?, ?, ?) ON CONFLICT(transfer_id) DO UPDATE SET bytes_total = excluded.bytes_total, bytes_sent = excluded.bytes_sent, chunks_total = excluded.chunks_total, chunks_sent = excluded.chunks_sent, last_chunk_at = excluded.last_chunk_at, stall_notified = 0",
+    )
+    .bind(transfer_id)
+    .bind(progress.bytes_total as i64)
+    .bind(progress.bytes_sent as i64)
+    .bind(progress.chunks_total as i64)
+    .bind(progress.chunks_sent as i64)
+    .bind(
+        progress
+            .last_chunk_at
+            .as_deref()
+            .map(CanonicalTimestamp::parse)
+            .transpose()?,
+    )
+    .execute(executor)
+    .await
+    .with_context(|| format!("insert transfer progress for {transfer_id}"))?;
+    Ok(())
+}
+
+fn schema_tables() -> Vec<String> {
+    SCHEMA_SQL
+        .split(';')
+        .filter_map(|statement| {
+            statement
+                .trim()
+                .strip_prefix("CREATE TABLE IF NOT EXISTS ")
+                .and_then(|rest| rest.split_whitespace().next())
+                .map(str::to_string)
+        })
+        .collect()
+}
+
+async fn table_has_column(pool: &SqlitePool, table: &str, column: &str) -> Result<bool> {
+    let found = sqlx::query_scalar::<_, i64>(
+        "SELECT COUNT(*) FROM pragma_table_info(?) WHERE name = ?",
+    )
+    .bind(table)
+    .bind(column)
+    .fetch_one(pool)
+    .await
+    .with_context(|| format!("inspect columns of {table}"))?;
+    Ok(found > 0)
+}
+
+// Fixed-width UTC timestamps so range and expiry checks can be plain string compares.
+async fn close_job_attempt(
+    tx: &mut Transaction<'_, Sqlite>,
+    lease: &JobLease,
+    outcome: &str,
+    diagnostic: Option<&str>,
+) -> Result<()> {
+    sqlx::query(
+        "UPDATE job_attempts SET finished_at = ?, status = ?, diagnostic = ? WHERE job_id = ? AND attempt_no = ?",
+    )
+    .bind(CanonicalTimestamp::now())
+    .bind(outcome)
+    .bind(diagnostic)
+    .bind(&lease.job_id)
+    .bind(lease.attempt)
+    .execute(&mut **tx)
+    .await
+    .with_context(|| format!("close attempt {} of job {}", lease.attempt, lease.job_id))?;
+    Ok(())
+}
+
+fn parse_timestamp(raw: &str) -> Result<DateTime<Utc>> {
+    Ok(CanonicalTimestamp::parse(raw)?.as_datetime())
+}
+
+// Quota usage is kept per subject and hour, so a charge lands in the bucket its hour starts.
+fn quota_bucket(at: CanonicalTimestamp) -> CanonicalTimestamp {
+    let at = at.as_datetime();
+    CanonicalTimestamp::from(at.duration_trunc(chrono::Duration::hours(1)).unwrap_or(at))
+}
+
+fn entity_key(entity_type: &str, entity_id: &str) -> String {
+    format!("{entity_type}/{entity_id}")
+}
+
+// The `subject_id` an entity's labels are stored under.
+pub fn entity_label_subject(entity_type: &str, entity_id: &str) -> String {
+    entity_key(entity_type, entity_id)
+}
+
+// One ` AND {column} IN (...)` per label, so only subjects carrying all of them match. Each is
+// answered from `idx_labels_lookup`; `bind_label_filter` binds its parameters.
+fn label_filter(column: &str, labels: &[(String, String)]) -> String {
+    let clause = format!(
+        " AND {column} IN (SELECT subject_id FROM labels WHERE subject_type = ? AND key = ? AND value = ?)"
+    );
+    clause.repeat(labels.len())
+}
+
+fn bind_label_filter<'q, O>(
+    mut query: QueryAs<'q, Sqlite, O, SqliteArguments<'q>>,
+    subject_type: &'q str,
+    labels: &'q [(String, String)],
+) -> QueryAs<'q, Sqlite, O, SqliteArguments<'q>> {
+    for (key, value) in labels {
+        query = query.bind(subject_type).bind(key.as_str()).bind(value.as_str());
+    }
+    query
+}
+
+// The chunk of jobs after `after_rowid`, and the labels of the jobs in it by job id.
+async fn export_job_rows(
+    conn: &mut SqliteConnection,
+    after_rowid: i64,
+    limit: i64,
+    labels: &[(String, String)],
+) -> Result<(Vec<ExportedJob>, BTreeMap<String, BTreeMap<String, String>>)> {
+    let sql = format!(
+        "SELECT rowid, job_id, operation, status, payload_json, submitted_at, updated_at, failure_reason, dispatch_json, requested_operation, source_token, source_addr, source_client_id FROM jobs WHERE rowid > ?{} ORDER BY rowid LIMIT ?",
+        label_filter("job_id", labels)
+    );
+    let query = sqlx::query_as::<_, ExportedJob>(&sql).bind(after_rowid);
+    let rows = bind_label_filter(query, LABEL_SUBJECT_JOB, labels)
+        .bind(limit)
+        .fetch_all(&mut *conn)
+        .await
+        .context("query job export chunk")?;
+    let (Some(first), Some(last)) = (rows.first(), rows.last()) else {
+        return Ok((rows, BTreeMap::new()));
+    };
+    let labelled = sqlx::query_as::<_, (String, String, String)>(
+        "SELECT l.subject_id, l.key, l.value FROM labels l JOIN jobs j ON j.job_id = l.subject_id WHERE l.subject_type = ? AND j.rowid BETWEEN ? AND ?",
+    )
+    .bind(LABEL_SUBJECT_JOB)
+    .bind(first.rowid)
+    .bind(last.rowid)
+    .fetch_all(&mut *conn)
+    .await
+    .context("query labels of job export chunk")?;
+    let mut by_job: BTreeMap<String, BTreeMap<String, String>> = BTreeMap::new();
+    for (job_id, key, value) in labelled {
+        by_job.entry(job_id).or_default().insert(key, value);
+    }
+    Ok((rows, by_job))
+}
+
+async fn insert_health_sample<'e, E>(executor: E, sample: &HealthSample) -> Result<()>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Sqlite>,
+{
+    sqlx::query(
+        "INSERT INTO health_samples(sampled_at, resolution_secs, sample_count, bridge_ok, bridge_latency_ms, storage_ok, storage_latency_ms, queued_jobs) VALUES (?, ?, ?, ?, ?, ?, ?, ?) ON CONFLICT(resolution_secs, sampled_at) DO NOTHING",
+    )
+    .bind(CanonicalTimestamp::parse(&sample.sampled_at)?)
+    .bind(sample.resolution_secs)
+    .bind(sample.sample_count)
+    .bind(sample.bridge_ok)
+    .bind(sample.bridge_latency_ms)
+    .bind(sample.storage_ok)
+    .bind(sample.storage_latency_ms)
+    .bind(sample.queued_jobs)
+    .execute(executor)
+    .await
+    .context("insert health sample")?;
+    Ok(())
+}
+
+fn load_cipher(key_path: Option<&str>) -> Result<Option<EncryptedColumn>> {
+    key_path
+        .map(|path| EncryptedColumn::from_key_file(Path::new(path)))
+        .transpose()
+}
+
+async fn set_busy_timeout(conn: &mut SqliteConnection, timeout: Duration) -> Result<()> {
+    sqlx::query(&format!("PRAGMA busy_timeout = {}", timeout.as_millis()))
+        .execute(conn)
+        .await
+        .context("set busy_timeout")?;
+    Ok(())
+}
+
+fn pool_usage(pool: &SqlitePool) -> PoolUsage {
+    let open = pool.size();
+    let idle = u32::try_from(pool.num_idle()).unwrap_or(open);
+    PoolUsage {
+        open,
+        in_use: open.saturating_sub(idle),
+        max: pool.options().get_max_connections(),
+    }
+}
+
+// A single read-only connection that never migrates, for reading another node's database.
+async fn open_read_only(config: &StorageConfig) -> Result<SqlitePool> {
+    let uri = normalize_sqlite_uri(&config.sqlite_path);
+    let options = SqliteConnectOptions::from_str(&uri)
+        .with_context(|| format!("invalid sqlite URI: {}", uri))?
+        .read_only(true)
+        .busy_timeout(READ_BUSY_TIMEOUT)
+        .pragma("query_only", "ON");
+    SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect_with(options)
+        .await
+        .context("failed to open sqlite database")
+}
+
+fn normalize_sqlite_uri(raw: &str) -> String {
+    if raw.starts_with("sqlite:") {
+        raw.to_string()
+    } else {
+        format!("sqlite://{raw}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        bind_label_filter, label_filter, payload_digest, quota_bucket, CanonicalTimestamp,
+        EntityRecord, QuotaClaim, RetasyncStorage, StorageConfig, SubmissionSource,
+        DEFAULT_READ_POOL_SIZE, LABEL_SUBJECT_JOB, LABEL_SUBJECT_TRANSFER,
+    };
+    use crate::error::{Result, StorageError};
+    use crate::{EncryptedColumn, Keyset};
+    use chrono::{Duration, TimeZone, Utc};
+    use retasync_contract::IdentityHash;
+    use retasync_transfer::TransferProgress;
+    use serde_json::json;
+    use std::collections::BTreeMap;
+    use uuid::Uuid;
+
+    const PARTNER: &str = "a1b2c3d4e5f60718293a4b5c6d7e8f90";
+    const WAITING: &str = "c0ffee00c0ffee00c0ffee00c0ffee00";
+
+    fn hash(value: &str) -> IdentityHash {
+        IdentityHash::parse(value).unwrap()
+    }
+
+    fn temp_path(suffix: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("retasync-{}-{suffix}", Uuid::now_v7()))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn write_key(byte: u8) -> String {
+        let path = temp_path("key");
+        std::fs::write(&path, [byte; 32]).expect("write key");
+        path
+    }
+
+    fn config(sqlite_path: &str, key_path: Option<&str>) -> StorageConfig {
+        StorageConfig {
+            sqlite_path: sqlite_path.to_string(),
+            encryption_key_path: key_path.map(str::to_string),
+            read_pool_size: DEFAULT_READ_POOL_SIZE,
+        }
+    }
+
+    async fn raw_payload(storage: &RetasyncStorage, job_id: &str) -> String {
+        sqlx::query_scalar::<_, String>("SELECT payload_json FROM jobs WHERE job_id = ?")
+            .bind(job_id)
+            .fetch_one(storage.pool())
+            .await
+            .expect("raw payload")
+    }
+
+    #[tokio::test]
+    async fn encrypted_round_trip_hides_plaintext() {
+        let db = temp_path("db.sqlite");
+        let key = write_key(1);
+        let storage = RetasyncStorage::connect(&config(&db, Some(&key)))
+            .await
+            .expect("connect");
+
+        let job = storage
+            .create_job("event.create", json!({ "uid": "evt-1" }))
+            .await
+            .expect("create job");
+
+        assert_eq!(job.payload_json, r#"{"uid":"evt-1"}"#);
+        assert!(EncryptedColumn::is_encrypted(&raw_payload(&storage, &job.job_id).await));
+    }
+
+    #[tokio::test]
+    async fn summaries_leave_encrypted_payloads_unread_and_legacy_rows_get_digests() {
+        let db = temp_path("db.sqlite");
+        let key = write_key(4);
+        let storage = RetasyncStorage::connect(&config(&db, Some(&key)))
+            .await
+            .expect("connect");
+        let job = storage
+            .create_job("event.create", json!({ "uid": "evt-3" }))
+            .await
+            .expect("create job");
+        storage
+            .cache_event("evt-3", "event.created", "aa", Utc::now(), &json!({ "uid": "evt-3" }))
+            .await
+            .expect("cache event");
+
+        let summary = storage.get_job_summary(&job.job_id).await.unwrap().unwrap();
+        let (bytes, sha256) = payload_digest(r#"{"uid":"evt-3"}"#);
+        assert_eq!((summary.payload_bytes, summary.payload_sha256), (Some(bytes), Some(sha256)));
+        let events = storage.list_cached_event_summaries(10).await.unwrap();
+        assert_eq!(events[0].payload_bytes, Some(bytes));
+        assert_eq!(storage.payload_reads(), 0);
+        storage.get_job(&job.job_id).await.unwrap();
+        assert_eq!(storage.payload_reads(), 1);
+
+        sqlx::query("UPDATE jobs SET payload_bytes = NULL, payload_sha256 = NULL")
+            .execute(storage.pool())
+            .await
+            .unwrap();
+        storage.migrate().await.expect("migrate");
+        let summary = storage.get_job_summary(&job.job_id).await.unwrap().unwrap();
+        assert_eq!(summary.payload_bytes, Some(bytes));
+    }
+
+    #[tokio::test]
+    async fn wrong_key_fails_fast() {
+        let db = temp_path("db.sqlite");
+        let key = write_key(1);
+        let wrong = write_key(2);
+        RetasyncStorage::connect(&config(&db, Some(&key)))
+            .await
+            .expect("connect");
+
+        let err = RetasyncStorage::connect(&config(&db, Some(&wrong)))
+            .await
+            .expect_err("wrong key must fail");
+        assert!(err.to_string().contains("does not match"));
+
+        let err = RetasyncStorage::connect(&config(&db, None))
+            .await
+            .expect_err("missing key must fail");
+        assert!(err.to_string().contains("encryption_key_path"));
+    }
+
+    #[tokio::test]
+    async fn plaintext_database_migrates_in_place() {
+        let db = temp_path("db.sqlite");
+        let key = write_key(3);
+        let plain = RetasyncStorage::connect(&config(&db, None))
+            .await
+            .expect("connect");
+        let job = plain
+            .create_job("event.create", json!({ "uid": "evt-2" }))
+            .await
+            .expect("create job");
+        plain.pool().close().await;
+
+        assert!(RetasyncStorage::connect(&config(&db, Some(&key)))
+            .await
+            .is_err());
+
+        let rewritten = RetasyncStorage::encrypt_database(&db, &key)
+            .await
+            .expect("encrypt");
+        assert_eq!(rewritten, 1);
+
+        let storage = RetasyncStorage::connect(&config(&db, Some(&key)))
+            .await
+            .expect("connect encrypted");
+        let loaded = storage.get_job(&job.job_id).await.expect("get").expect("job");
+        assert_eq!(loaded.payload_json, r#"{"uid":"evt-2"}"#);
+        assert!(EncryptedColumn::is_encrypted(&raw_payload(&storage, &job.job_id).await));
+    }
+
+    #[tokio::test]
+    async fn plaintext_that_looks_encrypted_is_still_migrated() {
+        let db = temp_path("db.sqlite");
+        let key = write_key(6);
+        let plain = RetasyncStorage::connect(&config(&db, None))
+            .await
+            .expect("connect");
+        plain
+            .add_allowlist(&hash(PARTNER), Some("enc:v1: field team"))
+            .await
+            .expect("allowlist");
+        plain.pool().close().await;
+
+        assert!(RetasyncStorage::connect(&config(&db, Some(&key)))
+            .await
+            .is_err());
+        let rewritten = RetasyncStorage::encrypt_database(&db, &key)
+            .await
+            .expect("encrypt");
+        assert_eq!(rewritten, 1);
+
+        let storage = RetasyncStorage::connect(&config(&db, Some(&key)))
+            .await
+            .expect("connect encrypted");
+        let entry = storage
+            .get_allowlist_entry(&hash(PARTNER))
+            .await
+            .expect("allowlist")
+            .expect("entry");
+        assert_eq!(entry.note.as_deref(), Some("enc:v1: field team"));
+    }
+
+    #[tokio::test]
+    async fn failed_rotation_leaves_every_row_under_the_old_key() {
+        let db = temp_path("db.sqlite");
+        let old_key = write_key(7);
+        let new_key = write_key(8);
+        let storage = RetasyncStorage::connect(&config(&db, Some(&old_key)))
+            .await
+            .expect("connect");
+        let first = storage
+            .create_job("event.create", json!({ "uid": "evt-4" }))
+            .await
+            .expect("create job");
+        let second = storage
+            .create_job("event.create", json!({ "uid": "evt-5" }))
+            .await
+            .expect("create job");
+        sqlx::query("UPDATE jobs SET payload_json = char(1) || 'enc:v2:AAAA' WHERE job_id = ?")
+            .bind(&second.job_id)
+            .execute(storage.pool())
+            .await
+            .unwrap();
+        storage.pool().close().await;
+
+        RetasyncStorage::rotate_encryption_key(&db, &old_key, &new_key)
+            .await
+            .expect_err("corrupt row stops rotation");
+
+        let reopened = RetasyncStorage::connect(&config(&db, Some(&old_key)))
+            .await
+            .expect("old key still opens the database");
+        let loaded = reopened.get_job(&first.job_id).await.expect("get").expect("job");
+        assert_eq!(loaded.payload_json, r#"{"uid":"evt-4"}"#);
+    }
+
+    #[tokio::test]
+    async fn notification_caps_apply_per_consumer() {
+        let db = temp_path("db.sqlite");
+        let storage = RetasyncStorage::connect(&config(&db, None))
+            .await
+            .expect("connect");
+        storage.notification_cursor("lagging").await.unwrap();
+        storage.notification_cursor("current").await.unwrap();
+        for idx in 0..5 {
+            storage
+                .append_notification("job.status.changed", &json!({ "job_id": idx }))
+                .await
+                .unwrap();
+        }
+        let latest = storage.list_notifications(0, 10).await.unwrap()[4].seq;
+        storage.ack_notifications("current", latest - 1).await.unwrap();
+
+        let capped = storage.cap_notification_cursor("lagging", 2).await.unwrap();
+        assert_eq!((capped.acked_seq, capped.dropped), (latest - 2, 3));
+        assert_eq!(storage.list_notifications(capped.acked_seq, 10).await.unwrap().len(), 2);
+
+        storage.ack_notifications("lagging", latest).await.unwrap();
+        assert_eq!(storage.trim_notifications(72, 2).await.unwrap(), 3);
+        let current = storage.notification_cursor("current").await.unwrap();
+        assert_eq!((current.acked_seq, current.dropped), (latest - 1, 0));
+        assert_eq!(storage.list_notifications(0, 10).await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn rotation_reencrypts_rows() {
+        let db = temp_path("db.sqlite");
+        let old_key = write_key(4);
+        let new_key = write_key(5);
+        let storage = RetasyncStorage::connect(&config(&db, Some(&old_key)))
+            .await
+            .expect("connect");
+        let job = storage
+            .create_job("event.create", json!({ "uid": "evt-3" }))
+            .await
+            .expect("create job");
+        storage
+            .add_allowlist(&hash(PARTNER), Some("field team"))
+            .await
+            .expect("allowlist");
+        storage.pool().close().await;
+
+        let rewritten = RetasyncStorage::rotate_encryption_key(&db, &old_key, &new_key)
+            .await
+            .expect("rotate");
+        assert_eq!(rewritten, 2);
+
+        assert!(RetasyncStorage::connect(&config(&db, Some(&old_key)))
+            .await
+            .is_err());
+        let rotated = RetasyncStorage::connect(&config(&db, Some(&new_key)))
+            .await
+            .expect("connect with new key");
+        let loaded = rotated.get_job(&job.job_id).await.expect("get").expect("job");
+        assert_eq!(loaded.payload_json, r#"{"uid":"evt-3"}"#);
+    }
+
+    #[tokio::test]
+    async fn transfer_progress_advances_per_chunk() {
+        let db = temp_path("db.sqlite");
+        let storage = RetasyncStorage::connect(&config(&db, None))
+            .await
+            .expect("connect");
+        let transfer = storage
+            .create_transfer(json!({ "file_name": "map.png" }))
+            .await
+            .expect("create transfer");
+        storage
+            .init_transfer_progress(&transfer.transfer_id, &TransferProgress::new(100, 40))
+            .await
+            .expect("init progress");
+
+        for (chunk, expected_bytes) in [(40, 40), (40, 80), (20, 100)] {
+            storage
+                .record_transfer_chunk(&transfer.transfer_id, chunk)
+                .await
+                .expect("record chunk");
+            let progress = storage
+                .get_transfer_progress(&transfer.transfer_id, "1970-01-01T00:00:00Z")
+                .await
+                .expect("progress")
+                .expect("progress row");
+            assert_eq!(progress.bytes_sent, expected_bytes);
+            assert!(progress.last_chunk_at.is_some());
+        }
+
+        let progress = storage
+            .get_transfer_progress(&transfer.transfer_id, "1970-01-01T00:00:00Z")
+            .await
+            .expect("progress")
+            .expect("progress row");
+        assert_eq!((progress.chunks_sent, progress.chunks_total), (3, 3));
+        assert!(progress.is_complete());
+    }
+
+    #[tokio::test]
+    async fn quota_reservations_hold_bytes_until_the_transfer_ends() {
+        let db = temp_path("db.sqlite");
+        let storage = RetasyncStorage::connect(&config(&db, None))
+            .await
+            .expect("connect");
+        let claims = [QuotaClaim {
+            subject_kind: "destination".to_string(),
+            subject: PARTNER.to_string(),
+            limit_bytes: Some(100),
+        }];
+        let reserve = |bytes: i64| {
+            let claims = claims.to_vec();
+            storage.with_tx(move |tx| {
+                Box::pin(async move {
+                    let transfer = tx
+                        .create_transfer_with_progress(None, &json!({}), &TransferProgress::new(1, 1))
+                        .await?;
+                    tx.reserve_quota(&transfer.transfer_id, &claims, bytes, "2000-01-01T00:00:00Z")
+                        .await?;
+                    Ok(transfer.transfer_id)
+                })
+            })
+        };
+
+        let first = reserve(60).await.expect("first fits");
+        // Still in flight, so its bytes are held and a second 60 would overrun the limit.
+        match reserve(60).await {
+            Err(StorageError::QuotaExceeded { used_bytes, requested_bytes, .. }) => {
+                assert_eq!((used_bytes, requested_bytes), (60, 60));
+            }
+            other => panic!("expected quota_exceeded, got {other:?}"),
+        }
+        // The refused transaction rolled back with its transfer.
+        assert_eq!(storage.list_quota_reservations().await.unwrap().len(), 1);
+
+        storage.update_transfer_status(&first, "failed", Some("link lost")).await.unwrap();
+        assert_eq!(storage.reserved_quota_bytes("destination", PARTNER).await.unwrap(), 0);
+
+        let second = reserve(60).await.expect("the failed transfer gave its bytes back");
+        storage.update_transfer_status(&second, "success", None).await.unwrap();
+        let charged = storage
+            .list_subject_quota_usage("destination", PARTNER, "2000-01-01T00:00:00Z")
+            .await
+            .unwrap();
+        let transfer = storage.get_transfer(&second).await.unwrap().unwrap();
+        assert_eq!(charged.len(), 1);
+        // Charged to the hour the transfer succeeded in.
+        let hour = quota_bucket(CanonicalTimestamp::parse(&transfer.updated_at).unwrap());
+        assert_eq!((charged[0].bytes, charged[0].used_at.clone()), (60, hour.to_string()));
+        assert!(storage.list_quota_reservations().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn client_activity_groups_jobs_and_transfers_by_source() {
+        let db = temp_path("db.sqlite");
+        let storage = RetasyncStorage::connect(&config(&db, None))
+            .await
+            .expect("connect");
+        let bridge = SubmissionSource {
+            token_label: "ops".to_string(),
+            remote_addr: Some("10.0.0.7".to_string()),
+            client_id: Some("tak-bridge".to_string()),
+        };
+        let local = SubmissionSource {
+            token_label: "anonymous-loopback".to_string(),
+            remote_addr: None,
+            client_id: None,
+        };
+
+        let mut bridge_jobs = Vec::new();
+        for _ in 0..2 {
+            let source = bridge.clone();
+            let job = storage
+                .with_tx(move |tx| {
+                    Box::pin(async move {
+                        let job = tx.create_job("event.create", json!({})).await?;
+                        tx.set_job_source(&job.job_id, &source).await?;
+                        Ok(job)
+                    })
+                })
+                .await
+                .expect("job");
+            bridge_jobs.push(job);
+        }
+        storage
+            .update_job_status(&bridge_jobs[0].job_id, "failed", Some("peer gone"))
+            .await
+            .expect("fail job");
+        let attachment = storage
+            .create_transfer_with_progress(
+                Some(&bridge_jobs[1].job_id),
+                json!({ "file_name": "map.png" }),
+                TransferProgress::new(100, 100),
+            )
+            .await
+            .expect("attachment");
+        storage
+            .record_transfer_chunk(&attachment.transfer_id, 100)
+            .await
+            .expect("chunk");
+        let upload = storage
+            .create_transfer_with_progress(
+                None,
+                json!({ "file_name": "route.kml" }),
+                TransferProgress::new(60, 60),
+            )
+            .await
+            .expect("upload");
+        storage
+            .record_transfer_chunk(&upload.transfer_id, 60)
+            .await
+            .expect("chunk");
+        let (attachment_id, upload_id) = (attachment.transfer_id, upload.transfer_id);
+        let (bridge_source, local_source) = (bridge.clone(), local.clone());
+        storage
+            .with_tx(move |tx| {
+                Box::pin(async move {
+                    tx.set_transfer_source(&attachment_id, &bridge_source).await?;
+                    tx.set_transfer_source(&upload_id, &local_source).await
+                })
+            })
+            .await
+            .expect("transfer sources");
+        storage
+            .create_job("event.create", json!({}))
+            .await
+            .expect("unattributed job");
+
+        let activity = storage
+            .list_client_activity("1970-01-01T00:00:00Z")
+            .await
+            .expect("activity");
+        let rows: Vec<_> = activity
+            .iter()
+            .map(|row| (row.source.clone(), row.submissions, row.failures, row.bytes))
+            .collect();
+        assert_eq!(rows, [(local, 1, 0, 60), (bridge, 2, 1, 100)]);
+        assert_eq!(
+            storage.get_job_source(&bridge_jobs[1].job_id).await.unwrap(),
+            Some(rows[1].0.clone())
+        );
+
+        let hour = "2026-01-01T10:00:00Z";
+        storage.record_client_rejections("tak-bridge", hour, 2).await.unwrap();
+        storage.record_client_rejections("tak-bridge", hour, 3).await.unwrap();
+        let rejections = storage.list_client_rejections(hour).await.unwrap();
+        assert_eq!(rejections, [("tak-bridge".to_string(), 5)]);
+        assert_eq!(storage.purge_client_rejections(hour).await.unwrap(), 1);
+        assert!(storage.list_client_rejections(hour).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn allowlist_expiry_is_enforced_at_the_boundary() {
+        let db = temp_path("db.sqlite");
+        let storage = RetasyncStorage::connect(&config(&db, None))
+            .await
+            .expect("connect");
+        let expires_at = Utc.with_ymd_and_hms(2030, 1, 1, 12, 0, 0).unwrap();
+        let (partner, waiting) = (hash(PARTNER), hash(WAITING));
+        storage
+            .put_allowlist_entry(&partner, None, "relay", "active", Some(expires_at))
+            .await
+            .expect("put entry");
+        storage
+            .put_allowlist_entry(&waiting, None, "peer", "pending", None)
+            .await
+            .expect("put pending");
+
+        let just_before = expires_at - Duration::microseconds(1);
+        assert!(storage.is_allowlisted(&partner, just_before).await.unwrap());
+        assert!(!storage.is_allowlisted(&partner, expires_at).await.unwrap());
+        assert!(!storage.is_allowlisted(&waiting, just_before).await.unwrap());
+
+        assert!(storage.expire_allowlist(just_before).await.unwrap().is_empty());
+        assert_eq!(storage.expire_allowlist(expires_at).await.unwrap(), [PARTNER]);
+        let entry = storage
+            .get_allowlist_entry(&partner)
+            .await
+            .unwrap()
+            .expect("expired entry is kept");
+        assert_eq!((entry.status.as_str(), entry.role.as_str()), ("expired", "relay"));
+    }
+
+    #[tokio::test]
+    async fn legacy_allowlist_rows_gain_defaults() {
+        let db = temp_path("db.sqlite");
+        {
+            let pool = sqlx::SqlitePool::connect(&format!("sqlite://{db}?mode=rwc"))
+                .await
+                .expect("legacy pool");
+            sqlx::query(
+                "CREATE TABLE acl_allowlist (identity_hash TEXT PRIMARY KEY, note TEXT, created_at TEXT NOT NULL)",
+            )
+            .execute(&pool)
+            .await
+            .expect("legacy table");
+            sqlx::query(
+                "INSERT INTO acl_allowlist VALUES ('a1b2c3d4e5f60718293a4b5c6d7e8f90', NULL, '2024-01-01T00:00:00Z')",
+            )
+            .execute(&pool)
+            .await
+            .expect("legacy row");
+            pool.close().await;
+        }
+
+        let pending = RetasyncStorage::pending_migrations(&db).await.expect("pending");
+        assert!(pending.contains(&"acl_allowlist.status".to_string()));
+
+        let storage = RetasyncStorage::connect(&config(&db, None))
+            .await
+            .expect("connect");
+        let entry = storage
+            .get_allowlist_entry(&hash(PARTNER))
+            .await
+            .unwrap()
+            .expect("legacy entry");
+        assert_eq!((entry.status.as_str(), entry.role.as_str()), ("active", "peer"));
+        assert!(entry.expires_at.is_none());
+    }
+
+    #[tokio::test]
+    async fn legacy_identity_hashes_are_normalized_once_and_strays_reported() {
+        let db = temp_path("db.sqlite");
+        {
+            let pool = sqlx::SqlitePool::connect(&format!("sqlite://{db}?mode=rwc"))
+                .await
+                .expect("legacy pool");
+            for table in ["acl_allowlist", "acl_denylist"] {
+                sqlx::query(&format!(
+                    "CREATE TABLE {table} (identity_hash TEXT PRIMARY KEY, note TEXT, created_at TEXT NOT NULL)"
+                ))
+                .execute(&pool)
+                .await
+                .expect("legacy table");
+            }
+            let rows = [
+                ("acl_allowlist", "A1B2C3D4E5F60718293A4B5C6D7E8F90"),
+                ("acl_allowlist", "c0ffee00c0ffee00c0ffee00c0ffee00"),
+                ("acl_allowlist", " C0FFEE00C0FFEE00C0FFEE00C0FFEE00 "),
+                ("acl_allowlist", "Bob's laptop"),
+                ("acl_allowlist", ""),
+                ("acl_denylist", "ABC"),
+            ];
+            for (table, identity) in rows {
+                sqlx::query(&format!(
+                    "INSERT INTO {table} VALUES (?, NULL, '2024-01-01T00:00:00Z')"
+                ))
+                .bind(identity)
+                .execute(&pool)
+                .await
+                .expect("legacy row");
+            }
+            pool.close().await;
+        }
+
+        let storage = RetasyncStorage::connect(&config(&db, None))
+            .await
+            .expect("connect");
+        assert_eq!(
+            storage.list_allowlist().await.unwrap(),
+            ["", "Bob's laptop", PARTNER, WAITING]
+        );
+        let at = Utc::now();
+        let mixed_case = IdentityHash::parse("A1B2C3D4E5F60718293A4B5C6D7E8F90").unwrap();
+        assert!(storage.is_allowlisted(&mixed_case, at).await.unwrap());
+        assert!(storage.is_allowlisted(&hash(WAITING), at).await.unwrap());
+
+        let issues: Vec<_> = storage
+            .identity_hash_issues()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|issue| (issue.table_name, issue.identity_hash, issue.defect))
+            .collect();
+        let issue = |table: &str, identity: &str, defect: &str| {
+            (table.to_string(), identity.to_string(), defect.to_string())
+        };
+        assert_eq!(
+            issues,
+            [
+                issue("acl_allowlist", "", "empty"),
+                issue("acl_allowlist", "Bob's laptop", "non_hex"),
+                issue("acl_denylist", "ABC", "wrong_length"),
+            ]
+        );
+        storage.pool().close().await;
+
+        // Reopening skips the migration, and an issue is dropped once its row is gone.
+        let storage = RetasyncStorage::connect(&config(&db, None))
+            .await
+            .expect("reconnect");
+        sqlx::query("DELETE FROM acl_denylist")
+            .execute(storage.pool())
+            .await
+            .unwrap();
+        assert_eq!(storage.identity_hash_issues().await.unwrap().len(), 2);
+    }
+
+    // Stands in for a crash between statements: the trigger aborts the first write to `table`
+    // after earlier statements of the same operation have already run.
+    async fn inject_fault(storage: &RetasyncStorage, event: &str, table: &str) -> String {
+        let trigger = format!("injected_fault_{}_{table}", event.to_lowercase());
+        sqlx::query(&format!(
+            "CREATE TRIGGER {trigger} BEFORE {event} ON {table} BEGIN SELECT RAISE(ABORT, 'injected fault'); END"
+        ))
+        .execute(storage.pool())
+        .await
+        .expect("create fault trigger");
+        trigger
+    }
+
+    async fn clear_fault(storage: &RetasyncStorage, trigger: &str) {
+        sqlx::query(&format!("DROP TRIGGER {trigger}"))
+            .execute(storage.pool())
+            .await
+            .expect("drop fault trigger");
+    }
+
+    #[tokio::test]
+    async fn composite_writes_leave_no_partial_state_on_fault() {
+        let db = temp_path("db.sqlite");
+        let storage = RetasyncStorage::connect(&config(&db, None))
+            .await
+            .expect("connect");
+
+        let job = storage.create_job("event.create", json!({})).await.unwrap();
+        let fault = inject_fault(&storage, "UPDATE", "jobs").await;
+        assert!(storage
+            .finish_job(&job.job_id, Some(json!({ "ok": true })), "success", None)
+            .await
+            .is_err());
+        assert!(storage.get_job_result(&job.job_id).await.unwrap().is_none());
+        assert_eq!(storage.get_job(&job.job_id).await.unwrap().unwrap().status, "queued");
+        clear_fault(&storage, &fault).await;
+        storage
+            .finish_job(&job.job_id, Some(json!({ "ok": true })), "success", None)
+            .await
+            .unwrap();
+        assert!(storage.get_job_result(&job.job_id).await.unwrap().is_some());
+        assert_eq!(storage.get_job(&job.job_id).await.unwrap().unwrap().status, "success");
+
+        let parent = storage.create_job("event.create", json!({})).await.unwrap();
+        let progress = TransferProgress::new(10, 10);
+        let fault = inject_fault(&storage, "INSERT", "transfer_progress").await;
+        assert!(storage
+            .create_transfer_with_progress(Some(&parent.job_id), json!({}), progress.clone())
+            .await
+            .is_err());
+        assert!(storage.list_job_transfers(&parent.job_id).await.unwrap().is_empty());
+        clear_fault(&storage, &fault).await;
+        let transfer = storage
+            .create_transfer_with_progress(Some(&parent.job_id), json!({}), progress)
+            .await
+            .unwrap();
+        assert!(storage
+            .get_transfer_progress(&transfer.transfer_id, "1970-01-01T00:00:00Z")
+            .await
+            .unwrap()
+            .is_some());
+
+        let fault = inject_fault(&storage, "UPDATE", "transfers").await;
+        assert!(storage.fail_job(&parent.job_id, "link_down").await.is_err());
+        assert_eq!(storage.get_job(&parent.job_id).await.unwrap().unwrap().status, "queued");
+        clear_fault(&storage, &fault).await;
+        assert_eq!(storage.fail_job(&parent.job_id, "link_down").await.unwrap(), 1);
+        let failed = storage.get_transfer(&transfer.transfer_id).await.unwrap().unwrap();
+        assert_eq!(
+            (failed.status.as_str(), failed.failure_reason.as_deref()),
+            ("failed", Some("job_failed"))
+        );
+
+        // Without a transaction the result lands while the status update is lost.
+        let legacy = storage.create_job("event.create", json!({})).await.unwrap();
+        inject_fault(&storage, "UPDATE", "jobs").await;
+        storage
+            .insert_job_result(&legacy.job_id, json!({ "ok": true }))
+            .await
+            .unwrap();
+        assert!(storage
+            .update_job_status(&legacy.job_id, "success", None)
+            .await
+            .is_err());
+        assert!(storage.get_job_result(&legacy.job_id).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn malformed_json_is_skipped_then_quarantined() {
+        let db = temp_path("db.sqlite");
+        let storage = RetasyncStorage::connect(&config(&db, None)).await.unwrap();
+        storage
+            .cache_event(
+                "evt-good",
+                "event.created",
+                "peer-a",
+                Utc::now(),
+                &json!({ "uid": "good" }),
+            )
+            .await
+            .unwrap();
+        sqlx::query(
+            "INSERT INTO cached_events(event_id, event_name, payload_json, received_at) VALUES ('evt-bad', 'event.created', '{\"uid\":', '2026-01-01T00:00:00Z')",
+        )
+        .execute(storage.pool())
+        .await
+        .unwrap();
+        let job = storage.create_job("event.create", json!({})).await.unwrap();
+        storage.insert_job_result(&job.job_id, json!({})).await.unwrap();
+        sqlx::query("UPDATE jobs SET payload_json = 'not json' WHERE job_id = ?")
+            .bind(&job.job_id)
+            .execute(storage.pool())
+            .await
+            .unwrap();
+
+        assert_eq!(storage.list_cached_events(10).await.unwrap(), [json!({ "uid": "good" })]);
+        assert_eq!(storage.integrity_stats().skipped_rows, 1);
+
+        let report = storage.check_integrity(1).await.unwrap();
+        assert_eq!((report.scanned, report.quarantined), (3, 2));
+        assert_eq!(report.quarantined_by_table["jobs"], 1);
+        assert!(storage.get_job(&job.job_id).await.unwrap().is_none());
+        let quarantined = storage.list_quarantine(10).await.unwrap();
+        let sources: Vec<_> = quarantined
+            .iter()
+            .map(|row| (row.source_table.as_str(), row.row_key.as_str()))
+            .collect();
+        assert_eq!(sources, [("jobs", job.job_id.as_str()), ("cached_events", "evt-bad")]);
+        assert_eq!(quarantined[0].raw, b"not json");
+
+        storage
+            .cache_event("evt-later", "event.created", "peer-a", Utc::now(), &json!({}))
+            .await
+            .unwrap();
+        let report = storage.check_integrity(100).await.unwrap();
+        assert_eq!((report.scanned, report.quarantined), (1, 0));
+        assert_eq!(storage.integrity_stats().scanned_rows, 4);
+        assert_eq!(storage.purge_quarantine().await.unwrap(), 2);
+        assert!(storage.list_quarantine(10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn encrypted_entities_list_by_id_prefix_and_keep_conflicts() {
+        let db = temp_path("db.sqlite");
+        let key = write_key(1);
+        let storage = RetasyncStorage::connect(&config(&db, Some(&key)))
+            .await
+            .expect("connect");
+        let at = Utc.with_ymd_and_hms(2026, 5, 1, 8, 0, 0).unwrap();
+        let entity = |id: &str, status: &str| EntityRecord {
+            entity_type: "eam".to_string(),
+            entity_id: id.to_string(),
+            updated_at: at,
+            record: json!({ "status": status }),
+            version: 1,
+            deleted_at: None,
+        };
+        for id in ["a1", "a2", "b1"] {
+            storage.put_entity(&entity(id, "green")).await.unwrap();
+        }
+        let newer = EntityRecord {
+            updated_at: at + Duration::minutes(5),
+            ..entity("a2", "red")
+        };
+        storage.put_entity(&newer).await.unwrap();
+
+        let listed = storage.list_entities("eam", "a").await.unwrap();
+        assert_eq!(listed, [entity("a1", "green"), newer.clone()]);
+        assert_eq!(storage.get_entity("eam", "a2").await.unwrap(), Some(newer.clone()));
+        assert_eq!(storage.get_entity("eam", "a").await.unwrap(), None);
+        assert_eq!(storage.list_entities("eam", "").await.unwrap().len(), 3);
+        assert!(storage.list_entities("task", "").await.unwrap().is_empty());
+        let raw: String = sqlx::query_scalar("SELECT record_json FROM entities LIMIT 1")
+            .fetch_one(storage.pool())
+            .await
+            .unwrap();
+        assert!(EncryptedColumn::is_encrypted(&raw));
+
+        let conflict = storage
+            .record_sync_conflict("peer-a", &newer, &entity("a2", "amber"), "local")
+            .await
+            .unwrap();
+        assert_eq!(storage.list_sync_conflicts("eam").await.unwrap(), [conflict]);
+    }
+
+    #[tokio::test]
+    async fn stale_entity_versions_are_refused_with_the_current_record() {
+        let db = temp_path("db.sqlite");
+        let storage = RetasyncStorage::connect(&config(&db, None))
+            .await
+            .expect("connect");
+        let created = storage
+            .update_entity("eam", "7", &json!({ "status": "green" }), 0)
+            .await
+            .unwrap();
+        assert_eq!(created.version, 1);
+        let err = storage
+            .update_entity("eam", "7", &json!({ "status": "blue" }), 0)
+            .await
+            .expect_err("already created");
+        assert!(matches!(err, StorageError::VersionConflict { expected: 0, .. }), "{err:?}");
+
+        // Two operators edit version 1; the second write is refused instead of overwriting.
+        let first = storage
+            .update_entity("eam", "7", &json!({ "status": "red" }), 1)
+            .await
+            .unwrap();
+        assert_eq!(first.version, 2);
+        let err = storage
+            .update_entity("eam", "7", &json!({ "status": "amber" }), 1)
+            .await
+            .expect_err("stale version");
+        assert_eq!(err.kind(), "version_conflict");
+        let StorageError::VersionConflict { current, .. } = err else {
+            panic!("{err:?}");
+        };
+        assert_eq!(*current, first);
+        assert_eq!(storage.get_entity("eam", "7").await.unwrap(), Some(first));
+
+        let err = storage.soft_delete_entity("eam", "7", 1).await.expect_err("stale");
+        assert!(matches!(err, StorageError::VersionConflict { .. }), "{err:?}");
+        let deleted = storage.soft_delete_entity("eam", "7", 2).await.unwrap();
+        assert_eq!((deleted.version, deleted.record["status"].as_str()), (3, Some("red")));
+        assert!(deleted.deleted_at.is_some());
+        assert_eq!(storage.list_entities("eam", "").await.unwrap(), [deleted]);
+        let err = storage.soft_delete_entity("eam", "8", 1).await.expect_err("missing");
+        assert!(matches!(err, StorageError::NotFound { .. }), "{err:?}");
+    }
+
+    #[tokio::test]
+    async fn expired_job_leases_are_claimed_once_and_count_attempts() {
+        let db = temp_path("db.sqlite");
+        let storage = RetasyncStorage::connect(&config(&db, None))
+            .await
+            .expect("connect");
+        let job = storage.create_job("event.create", json!({})).await.unwrap();
+        let now = Utc::now();
+        let first = storage
+            .acquire_job_lease(&job.job_id, "worker-a", now + Duration::seconds(5))
+            .await
+            .unwrap();
+        assert_eq!((first.worker_id.as_str(), first.attempt), ("worker-a", 1));
+        assert!(storage
+            .renew_job_lease(&job.job_id, "worker-a", now + Duration::seconds(10))
+            .await
+            .unwrap());
+        assert!(storage.claim_expired_job_leases(now).await.unwrap().is_empty());
+
+        let claimed = storage
+            .claim_expired_job_leases(now + Duration::seconds(11))
+            .await
+            .unwrap();
+        assert_eq!(claimed.len(), 1);
+        assert_eq!(claimed[0].job_id, job.job_id);
+        assert!(storage
+            .claim_expired_job_leases(now + Duration::seconds(11))
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(!storage
+            .renew_job_lease(&job.job_id, "worker-a", now + Duration::seconds(20))
+            .await
+            .unwrap());
+        assert!(!storage.release_job_lease(&first, "done", None).await.unwrap());
+
+        let second = storage
+            .acquire_job_lease(&job.job_id, "worker-b", now + Duration::seconds(5))
+            .await
+            .unwrap();
+        assert_eq!(second.attempt, 2);
+        assert_eq!(storage.get_job_lease(&job.job_id).await.unwrap(), Some(second.clone()));
+        assert!(storage.release_job_lease(&second, "done", None).await.unwrap());
+        assert_eq!(storage.get_job_lease(&job.job_id).await.unwrap(), None);
+        let attempts: Vec<(i64, String)> = sqlx::query_as(
+            "SELECT attempt_no, status FROM job_attempts WHERE job_id = ? ORDER BY attempt_no",
+        )
+        .bind(&job.job_id)
+        .fetch_all(&storage.pool)
+        .await
+        .unwrap();
+        assert_eq!(attempts, [(1, "lost".to_string()), (2, "done".to_string())]);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn exports_stay_complete_while_job_updates_run() {
+        const JOBS: usize = 600;
+        // Twice SQLite's auto-checkpoint threshold of 1000 pages at the default page size.
+        const WAL_BOUND: u64 = 2 * 1000 * 4096;
+        let storage = RetasyncStorage::connect(&config(&temp_path("export.sqlite"), None))
+            .await
+            .unwrap();
+        let mut job_ids = Vec::new();
+        for n in 0..JOBS {
+            let job = storage.create_job("event.create", json!({ "n": n })).await.unwrap();
+            job_ids.push(job.job_id);
+        }
+
+        let writer = {
+            let (storage, job_ids) = (storage.clone(), job_ids.clone());
+            tokio::spawn(async move {
+                for status in ["running", "succeeded", "running", "failed"] {
+                    for job_id in &job_ids {
+                        storage.update_job_status(job_id, status, None).await?;
+                    }
+                }
+                anyhow::Ok(())
+            })
+        };
+        let (mut exported, mut after, mut max_wal) = (Vec::new(), 0, 0);
+        loop {
+            let chunk = storage.export_jobs(after, 25, &[]).await.unwrap();
+            exported.extend(chunk.jobs.into_iter().map(|export| export.job));
+            max_wal = max_wal.max(storage.pool_stats().wal_bytes);
+            match chunk.next_after {
+                Some(next) => after = next,
+                None => break,
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(2)).await;
+        }
+        writer.await.unwrap().expect("no update fails during the export");
+        max_wal = max_wal.max(storage.pool_stats().wal_bytes);
+
+        let ids: Vec<&str> = exported.iter().map(|job| job.job_id.as_str()).collect();
+        assert_eq!(ids, job_ids.iter().map(String::as_str).collect::<Vec<_>>());
+        for (n, job) in exported.iter().enumerate() {
+            assert_eq!(job.payload_json, json!({ "n": n }).to_string());
+            assert!(["queued", "running", "succeeded", "failed"].contains(&job.status.as_str()));
+        }
+        assert!(max_wal <= WAL_BOUND, "WAL grew to {max_wal} bytes");
+        let pools = storage.pool_stats();
+        assert_eq!((pools.read.max, pools.write.max), (DEFAULT_READ_POOL_SIZE, 1));
+    }
+
+    #[tokio::test]
+    async fn label_filters_need_every_label_and_are_answered_from_the_index() {
+        let storage = RetasyncStorage::connect(&config(&temp_path("labels.sqlite"), None))
+            .await
+            .unwrap();
+        let label = |pairs: &[(&str, &str)]| -> BTreeMap<String, String> {
+            pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+        };
+        let mut transfers = Vec::new();
+        for labels in [
+            label(&[("exercise", "redfish-day2"), ("casualty", "7")]),
+            label(&[("exercise", "redfish-day2"), ("casualty", "8")]),
+            label(&[("exercise", "redfish-day1"), ("casualty", "7")]),
+            label(&[]),
+        ] {
+            let transfer = storage.create_transfer(json!({})).await.unwrap();
+            storage
+                .put_labels(LABEL_SUBJECT_TRANSFER, &transfer.transfer_id, &labels)
+                .await
+                .unwrap();
+            transfers.push(transfer.transfer_id);
+        }
+        let job = storage.create_job("event.create", json!({})).await.unwrap();
+        storage
+            .put_labels(LABEL_SUBJECT_JOB, &job.job_id, &label(&[("casualty", "7")]))
+            .await
+            .unwrap();
+
+        let filter = |pairs: &[(&str, &str)]| -> Vec<(String, String)> {
+            pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+        };
+        let listed = |filter: Vec<(String, String)>| {
+            let storage = storage.clone();
+            async move {
+                let mut ids: Vec<String> = storage
+                    .list_transfers_page(None, &filter, &Keyset::first(10))
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|record| record.transfer_id)
+                    .collect();
+                ids.sort();
+                ids
+            }
+        };
+        let sorted = |mut ids: Vec<String>| {
+            ids.sort();
+            ids
+        };
+        let both = filter(&[("exercise", "redfish-day2"), ("casualty", "7")]);
+        assert_eq!(listed(both.clone()).await, [transfers[0].clone()]);
+        assert_eq!(
+            listed(filter(&[("exercise", "redfish-day2")])).await,
+            sorted(vec![transfers[0].clone(), transfers[1].clone()])
+        );
+        assert_eq!(
+            listed(filter(&[("casualty", "7")])).await,
+            sorted(vec![transfers[0].clone(), transfers[2].clone()])
+        );
+        assert!(listed(filter(&[("casualty", "9")])).await.is_empty());
+        assert_eq!(listed(Vec::new()).await.len(), 4);
+
+        let exported = storage.export_jobs(0, 10, &filter(&[("casualty", "7")])).await.unwrap();
+        assert_eq!(exported.jobs.len(), 1);
+        assert_eq!(exported.jobs[0].labels, label(&[("casualty", "7")]));
+        let exported = storage.export_jobs(0, 10, &both).await.unwrap();
+        assert!(exported.jobs.is_empty());
+
+        let sql = format!(
+            "EXPLAIN QUERY PLAN SELECT transfer_id FROM transfers WHERE 1 = 1{}",
+            label_filter("transfer_id", &both)
+        );
+        let plan: Vec<String> = bind_label_filter(
+            sqlx::query_as::<_, (i64, i64, i64, String)>(&sql),
+            LABEL_SUBJECT_TRANSFER,
+            &both,
+        )
+        .fetch_all(&storage.read_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|(_, _, _, detail)| detail)
+        .collect();
+        let lookups = plan
+            .iter()
+            .filter(|detail| detail.contains("USING COVERING INDEX idx_labels_lookup"))
+            .count();
+        assert_eq!(lookups, 2, "{plan:?}");
+        assert!(!plan.iter().any(|detail| detail.starts_with("SCAN labels")), "{plan:?}");
+
+        let keys = storage.label_keys(None).await.unwrap();
+        let math: Vec<(&str, i64, i64)> = keys
+            .iter()
+            .map(|key| (key.key.as_str(), key.values, key.subjects))
+            .collect();
+        assert_eq!(math, [("casualty", 2, 4), ("exercise", 2, 3)]);
+        let keys = storage.label_keys(Some(LABEL_SUBJECT_JOB)).await.unwrap();
+        assert_eq!((keys.len(), keys[0].values, keys[0].subjects), (1, 1, 1));
+
+        let previous = storage
+            .with_tx({
+                let transfer_id = transfers[0].clone();
+                move |tx| {
+                    Box::pin(async move {
+                        let labels = BTreeMap::from([("casualty".to_string(), "9".to_string())]);
+                        tx.replace_labels(LABEL_SUBJECT_TRANSFER, &transfer_id, &labels)
+                            .await
+                    })
+                }
+            })
+            .await
+            .unwrap();
+        assert_eq!(previous, label(&[("exercise", "redfish-day2"), ("casualty", "7")]));
+        storage.purge_transfers(&transfers[..1]).await.unwrap();
+        assert!(storage
+            .get_labels(LABEL_SUBJECT_TRANSFER, &transfers[0])
+            .await
+            .unwrap()
+            .is_empty());
+        storage.purge_jobs(std::slice::from_ref(&job.job_id)).await.unwrap();
+        assert!(storage.label_keys(Some(LABEL_SUBJECT_JOB)).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn legacy_timestamps_are_normalized_and_compared_as_instants() {
+        let db = temp_path("db.sqlite");
+        let storage = RetasyncStorage::connect(&config(&db, None))
+            .await
+            .expect("connect");
+        let pool = storage.pool();
+        let recent = (Utc::now() - Duration::hours(1))
+            .with_timezone(&chrono::FixedOffset::west_opt(10 * 3600).unwrap())
+            .to_rfc3339_opts(chrono::SecondsFormat::Micros, false);
+        for (job_id, at) in [
+            ("old-offset", "2020-01-01T05:00:00+05:00"),
+            ("old-sqlite", "2020-01-02 00:00:00"),
+            ("recent", recent.as_str()),
+        ] {
+            sqlx::query(
+                "INSERT INTO jobs(job_id, operation, status, payload_json, submitted_at, updated_at) VALUES (?, 'event.create', 'success', '{}', ?2, ?2)",
+            )
+            .bind(job_id)
+            .bind(at)
+            .execute(pool)
+            .await
+            .expect("legacy job");
+        }
+        // As text `10:00+02:00` sorts last, but the instant it names (08:00Z) comes first.
+        for (transfer_id, at) in [
+            ("t-early", "2026-03-01T10:00:00+02:00"),
+            ("t-middle", "2026-03-01T08:30:00.123456Z"),
+            ("t-late", "2026-03-01T09:00:00Z"),
+        ] {
+            sqlx::query(
+                "INSERT INTO transfers(transfer_id, status, metadata_json, submitted_at, updated_at) VALUES (?, 'completed', '{}', ?2, ?2)",
+            )
+            .bind(transfer_id)
+            .bind(at)
+            .execute(pool)
+            .await
+            .expect("legacy transfer");
+        }
+        sqlx::query("DELETE FROM storage_meta WHERE key = 'timestamps_canonical'")
+            .execute(pool)
+            .await
+            .expect("clear marker");
+        storage.migrate().await.expect("migrate");
+
+        let stored = sqlx::query_scalar::<_, String>("SELECT submitted_at FROM transfers")
+            .fetch_all(pool)
+            .await
+            .expect("stored");
+        assert!(stored.contains(&"2026-03-01T08:00:00.000Z".to_string()));
+        assert!(stored.contains(&"2026-03-01T08:30:00.123Z".to_string()));
+
+        let ids = |records: Vec<super::TransferRecord>| {
+            records.into_iter().map(|record| record.transfer_id).collect::<Vec<_>>()
+        };
+        let page = storage.list_transfers(None, 10).await.expect("list");
+        assert_eq!(ids(page), ["t-late", "t-middle", "t-early"]);
+        let page = crate::Keyset {
+            key: Some(vec!["2026-03-01T09:00:00.000Z".into(), "t-late".into()]),
+            ..crate::Keyset::first(10)
+        };
+        let after = storage.list_transfers_page(None, &[], &page).await.expect("list after");
+        assert_eq!(ids(after), ["t-middle", "t-early"]);
+
+        storage.purge_expired(24, 24, 36_500).await.expect("purge");
+        assert!(storage.get_job("old-offset").await.unwrap().is_none());
+        assert!(storage.get_job("old-sqlite").await.unwrap().is_none());
+        let kept = storage.get_job("recent").await.unwrap().expect("recent job");
+        assert!(kept.updated_at.ends_with('Z'));
+        assert_eq!(kept.updated_at.len(), "2026-03-01T08:00:00.000Z".len());
+    }
+
+    async fn feed_statuses(storage: &RetasyncStorage, job_id: &str) -> Vec<String> {
+        storage
+            .list_feed_events(0, 1000)
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|event| event.data["job_id"] == job_id)
+            .map(|event| event.data["status"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn job_status_events_commit_with_the_status_change() {
+        let db = temp_path("db.sqlite");
+        let storage = RetasyncStorage::connect(&config(&db, None))
+            .await
+            .expect("connect");
+        let job = storage.create_job("event.create", json!({})).await.unwrap();
+        assert_eq!(feed_statuses(&storage, &job.job_id).await, ["queued"]);
+
+        // A status change that cannot reach the feed does not happen at all.
+        let fault = inject_fault(&storage, "INSERT", "event_feed").await;
+        assert!(storage
+            .update_job_status(&job.job_id, "running", None)
+            .await
+            .is_err());
+        assert!(storage.fail_job(&job.job_id, "link_down").await.is_err());
+        assert_eq!(storage.get_job(&job.job_id).await.unwrap().unwrap().status, "queued");
+        assert_eq!(feed_statuses(&storage, &job.job_id).await, ["queued"]);
+        clear_fault(&storage, &fault).await;
+
+        storage
+            .update_job_status(&job.job_id, "running", None)
+            .await
+            .unwrap();
+        storage
+            .update_job_status(&job.job_id, "running", None)
+            .await
+            .unwrap();
+        storage.fail_job(&job.job_id, "link_down").await.unwrap();
+        assert_eq!(
+            feed_statuses(&storage, &job.job_id).await,
+            ["queued", "running", "failed"]
+        );
+        let events = storage.list_feed_events(0, 10).await.unwrap();
+        assert_eq!(events[2].data["reason"], "link_down");
+        assert_eq!(events[2].event_type, super::FEED_JOB_EVENT);
+    }
+
+    #[tokio::test]
+    async fn events_recorded_with_a_change_commit_and_roll_back_with_it() {
+        let db = temp_path("db.sqlite");
+        let storage = RetasyncStorage::connect(&config(&db, None))
+            .await
+            .expect("connect");
+        assert_eq!(storage.feed_published_through().await.unwrap(), 0);
+        let job = storage.create_job("event.create", json!({})).await.unwrap();
+        let transfer = storage.create_transfer(json!({})).await.unwrap();
+        let head = storage.feed_bounds().await.unwrap().head_seq;
+
+        let running = json!({ "transfer_id": transfer.transfer_id, "status": "running" });
+        let fault = inject_fault(&storage, "UPDATE", "transfers").await;
+        assert!(storage
+            .with_event("transfer.progress", running.clone())
+            .update_transfer_status(&transfer.transfer_id, "running", None)
+            .await
+            .is_err());
+        clear_fault(&storage, &fault).await;
+        assert_eq!(storage.feed_bounds().await.unwrap().head_seq, head);
+        storage
+            .with_event("transfer.progress", running.clone())
+            .update_transfer_status(&transfer.transfer_id, "running", None)
+            .await
+            .unwrap();
+
+        // A job event replaces the payload of the row its status change writes.
+        let failed = json!({ "job_id": job.job_id, "status": "failed", "reason": "link_down" });
+        storage
+            .with_event(super::FEED_JOB_EVENT, failed.clone())
+            .fail_job(&job.job_id, "destination unreachable")
+            .await
+            .unwrap();
+        // No status change, no event, and nothing left staged for the next one.
+        storage
+            .with_event(super::FEED_JOB_EVENT, json!({ "job_id": job.job_id, "stale": true }))
+            .update_job_status(&job.job_id, "failed", None)
+            .await
+            .unwrap();
+        storage
+            .update_job_status(&job.job_id, "cancelled", Some("cancelled"))
+            .await
+            .unwrap();
+        // Chained events commit together; a conditional write that matched nothing records none.
+        let part = json!({ "job_id": job.job_id, "sequence": 0 });
+        for _ in 0..2 {
+            storage
+                .with_event("job.result.partial", part.clone())
+                .with_event("job.result.noted", json!({}))
+                .insert_job_result_part(&job.job_id, 0, false, &json!({}))
+                .await
+                .unwrap();
+        }
+
+        let events = storage.list_feed_events(head, 10).await.unwrap();
+        let recorded: Vec<_> = events
+            .iter()
+            .map(|event| (event.event_type.as_str(), event.data.clone()))
+            .collect();
+        assert_eq!(
+            recorded,
+            [
+                ("transfer.progress", running),
+                (super::FEED_JOB_EVENT, failed),
+                (
+                    super::FEED_JOB_EVENT,
+                    json!({ "job_id": job.job_id, "status": "cancelled", "reason": "cancelled" })
+                ),
+                ("job.result.partial", part),
+                ("job.result.noted", json!({})),
+            ]
+        );
+
+        storage.set_feed_published_through(head + 1).await.unwrap();
+        assert_eq!(storage.feed_published_through().await.unwrap(), head + 1);
+    }
+
+    #[tokio::test]
+    async fn cancelled_jobs_ignore_later_status_writes() {
+        let db = temp_path("db.sqlite");
+        let storage = RetasyncStorage::connect(&config(&db, None))
+            .await
+            .expect("connect");
+        let job = storage.create_job("event.create", json!({})).await.unwrap();
+        let transfer = storage
+            .create_transfer_with_progress(
+                Some(&job.job_id),
+                json!({}),
+                TransferProgress::new(4, 1),
+            )
+            .await
+            .unwrap();
+
+        assert!(storage.cancel_job(&job.job_id).await.unwrap());
+        assert!(!storage.cancel_job(&job.job_id).await.unwrap());
+        storage
+            .update_job_status(&job.job_id, "success", None)
+            .await
+            .unwrap();
+        assert!(!storage
+            .mark_job_in_transit(&job.job_id, Utc::now() + Duration::hours(1))
+            .await
+            .unwrap());
+        assert_eq!(
+            storage.get_job(&job.job_id).await.unwrap().unwrap().status,
+            "cancelled"
+        );
+        assert_eq!(
+            feed_statuses(&storage, &job.job_id).await,
+            ["queued", "cancelled"]
+        );
+        let transfer = storage
+            .get_transfer(&transfer.transfer_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(transfer.failure_reason.as_deref(), Some("job_cancelled"));
+
+        let finished = storage.create_job("event.create", json!({})).await.unwrap();
+        storage
+            .fail_job(&finished.job_id, "link_down")
+            .await
+            .unwrap();
+        assert!(!storage.cancel_job(&finished.job_id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn idempotency_keys_name_one_job() {
+        let db = temp_path("db.sqlite");
+        let storage = RetasyncStorage::connect(&config(&db, None))
+            .await
+            .expect("connect");
+        // A database from before the index, holding the same key twice.
+        sqlx::query("DROP INDEX idx_jobs_idempotency_key")
+            .execute(storage.pool())
+            .await
+            .unwrap();
+        let mut jobs = Vec::new();
+        for _ in 0..2 {
+            let job = storage.create_job("event.create", json!({})).await.unwrap();
+            sqlx::query("UPDATE jobs SET idempotency_key = 'k' WHERE job_id = ?")
+                .bind(&job.job_id)
+                .execute(storage.pool())
+                .await
+                .unwrap();
+            jobs.push(job.job_id);
+        }
+        storage.migrate().await.unwrap();
+        assert_eq!(
+            storage.find_job_by_idempotency_key("k").await.unwrap(),
+            Some(jobs[0].clone())
+        );
+
+        let second = jobs[1].clone();
+        let reused = storage
+            .with_tx(move |tx| {
+                Box::pin(async move { tx.set_job_idempotency_key(&second, "k").await })
+            })
+            .await;
+        assert!(reused.is_err());
+    }
+
+    #[tokio::test]
+    async fn peer_contacts_keep_the_latest_sighting() {
+        let db = temp_path("db.sqlite");
+        let storage = RetasyncStorage::connect(&config(&db, None))
+            .await
+            .expect("connect");
+        let later = chrono::DateTime::from_timestamp(1_772_000_000, 0).unwrap();
+        let earlier = later - chrono::Duration::hours(1);
+        storage.record_peer_contact("peer-a", later).await.unwrap();
+        storage.record_peer_contact("peer-a", earlier).await.unwrap();
+        let seen = storage.list_peer_last_seen().await.unwrap();
+        assert_eq!(seen.len(), 1);
+        assert_eq!(seen[0].0, "peer-a");
+        assert_eq!(seen[0].1, CanonicalTimestamp::from(later));
+        let contacts = storage.list_peer_contacts().await.unwrap();
+        assert!(contacts.iter().any(|(identity, _)| identity == "peer-a"));
+    }
+
+    #[tokio::test]
+    async fn jobs_finish_once_and_keep_their_first_result() {
+        let db = temp_path("db.sqlite");
+        let storage = RetasyncStorage::connect(&config(&db, None))
+            .await
+            .expect("connect");
+        let job = storage.create_job("event.create", json!({})).await.unwrap();
+        let first = json!({ "status": "accepted" });
+        assert!(storage
+            .finish_job(&job.job_id, Some(first.clone()), "success", None)
+            .await
+            .unwrap());
+        assert!(!storage
+            .finish_job(&job.job_id, Some(json!({ "late": true })), "partial_failure", None)
+            .await
+            .unwrap());
+        assert_eq!(storage.get_job(&job.job_id).await.unwrap().unwrap().status, "success");
+        let stored = storage.get_job_result(&job.job_id).await.unwrap().unwrap();
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&stored.result_json).unwrap(), first);
+
+        let cancelled = storage.create_job("event.create", json!({})).await.unwrap();
+        assert!(storage.cancel_job(&cancelled.job_id).await.unwrap());
+        assert!(!storage
+            .finish_job(&cancelled.job_id, None, "success", None)
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn a_failed_attachment_fails_the_transfers_queued_behind_it() {
+        let db = temp_path("db.sqlite");
+        let storage = RetasyncStorage::connect(&config(&db, None))
+            .await
+            .expect("connect");
+        let job = storage.create_job("event.create", json!({})).await.unwrap();
+        let mut transfers = Vec::new();
+        for _ in 0..3 {
+            let transfer = storage
+                .create_transfer_with_progress(
+                    Some(&job.job_id),
+                    json!({}),
+                    TransferProgress::new(4, 1),
+                )
+                .await
+                .unwrap();
+            transfers.push(transfer.transfer_id);
+        }
+        storage
+            .update_transfer_status(&transfers[0], "success", None)
+            .await
+            .unwrap();
+        storage
+            .update_transfer_status(&transfers[1], "running", None)
+            .await
+            .unwrap();
+
+        let failed = storage
+            .fail_job_transfers(&job.job_id, &transfers[1], "link_down")
+            .await
+            .unwrap();
+        assert_eq!(failed, 2);
+        let mut reasons = Vec::new();
+        for transfer_id in &transfers {
+            let transfer = storage.get_transfer(transfer_id).await.unwrap().unwrap();
+            reasons.push((transfer.status, transfer.failure_reason));
+        }
+        assert_eq!(
+            reasons,
+            [
+                ("success".to_string(), None),
+                ("failed".to_string(), Some("link_down".to_string())),
+                ("failed".to_string(), Some("job_attachment_failed".to_string())),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn write_sequence_advances_once_per_committed_write() {
+        let db = temp_path("db.sqlite");
+        let storage = RetasyncStorage::connect(&config(&db, None))
+            .await
+            .expect("connect");
+        let start = storage.write_sequence().await.unwrap();
+
+        let job = storage.create_job("event.create", json!({})).await.unwrap();
+        assert_eq!(storage.write_sequence().await.unwrap(), start + 1);
+        // A composite of several records is still one write.
+        storage
+            .finish_job(&job.job_id, Some(json!({ "ok": true })), "success", None)
+            .await
+            .unwrap();
+        assert_eq!(storage.write_sequence().await.unwrap(), start + 2);
+
+        let failed: Result<()> = storage
+            .with_tx(|tx| {
+                Box::pin(async move {
+                    tx.create_job("event.create", json!({})).await?;
+                    Err(StorageError::not_found("abandon"))
+                })
+            })
+            .await;
+        assert!(failed.is_err());
+        assert_eq!(storage.write_sequence().await.unwrap(), start + 2);
+
+        storage.close().await;
+        let reopened = RetasyncStorage::connect(&config(&db, None))
+            .await
+            .expect("reconnect");
+        assert_eq!(reopened.write_sequence().await.unwrap(), start + 2);
+    }
+
+    #[tokio::test]
+    async fn jobs_keep_the_id_and_time_they_were_given_before_storage() {
+        let db = temp_path("db.sqlite");
+        let storage = RetasyncStorage::connect(&config(&db, None))
+            .await
+            .expect("connect");
+        let job_id = Uuid::now_v7().to_string();
+        let submitted_at = CanonicalTimestamp::parse("2026-03-01T10:00:00.000Z").unwrap();
+        let create = |job_id: String| {
+            let storage = storage.clone();
+            async move {
+                storage
+                    .with_tx(move |tx| {
+                        Box::pin(async move {
+                            tx.create_job_as(&job_id, &submitted_at, "event.create", json!({}))
+                                .await
+                        })
+                    })
+                    .await
+                    .unwrap()
+            }
+        };
+
+        let job = create(job_id.clone()).await.expect("first store");
+        assert_eq!(job.job_id, job_id);
+        assert_eq!(job.submitted_at, "2026-03-01T10:00:00.000Z");
+        assert_eq!(job.status, "queued");
+        assert!(create(job_id.clone()).await.is_none());
+        let stored = storage.get_job(&job_id).await.unwrap().expect("stored job");
+        assert_eq!(stored.submitted_at, "2026-03-01T10:00:00.000Z");
+    }
+
+    #[tokio::test]
+    async fn holds_survive_a_reopen_and_release_to_the_status_they_came_from() {
+        let db = temp_path("db.sqlite");
+        let storage = RetasyncStorage::connect(&config(&db, None))
+            .await
+            .expect("connect");
+        let queued = storage.create_job("event.create", json!({})).await.unwrap();
+        let deferred = storage.create_job("event.create", json!({})).await.unwrap();
+        storage.update_job_status(&deferred.job_id, "deferred", None).await.unwrap();
+        let running = storage.create_job("event.create", json!({})).await.unwrap();
+        storage.update_job_status(&running.job_id, "running", None).await.unwrap();
+
+        for job in [&queued, &deferred] {
+            assert!(storage.hold_job(&job.job_id, "ops", "incident").await.unwrap().is_some());
+        }
+        assert_eq!(storage.hold_job(&running.job_id, "ops", "late").await.unwrap(), None);
+        assert_eq!(storage.hold_job(&queued.job_id, "ops", "twice").await.unwrap(), None);
+        drop(storage);
+
+        let storage = RetasyncStorage::connect(&config(&db, None))
+            .await
+            .expect("reopen");
+        let holds = storage.list_job_holds().await.unwrap();
+        let held: Vec<_> = holds
+            .iter()
+            .map(|hold| (hold.job_id.as_str(), hold.previous_status.as_str()))
+            .collect();
+        assert_eq!(
+            held,
+            [(queued.job_id.as_str(), "queued"), (deferred.job_id.as_str(), "deferred")]
+        );
+        assert_eq!((holds[0].held_by.as_str(), holds[0].reason.as_str()), ("ops", "incident"));
+        assert_eq!(storage.get_job(&queued.job_id).await.unwrap().unwrap().status, "held");
+
+        let released = storage.release_job_hold(&deferred.job_id).await.unwrap();
+        assert_eq!(released.as_deref(), Some("deferred"));
+        assert_eq!(storage.get_job(&deferred.job_id).await.unwrap().unwrap().status, "deferred");
+        assert_eq!(storage.release_job_hold(&deferred.job_id).await.unwrap(), None);
+        assert_eq!(storage.list_job_holds().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn result_checks_go_with_their_job_and_quarantined_results_stay() {
+        let db = temp_path("db.sqlite");
+        let storage = RetasyncStorage::connect(&config(&db, None))
+            .await
+            .expect("connect");
+        let job = storage.create_job("event.create", json!({})).await.unwrap();
+        let errors = json!([{ "pointer": "/uid", "message": "expected string, got integer" }]);
+        storage
+            .record_job_result_check(&job.job_id, "EventCreateResult", "quarantine", &errors)
+            .await
+            .unwrap();
+        storage
+            .quarantine_job_result(&job.job_id, br#"{"uid":7}"#, "/uid: expected string")
+            .await
+            .unwrap();
+
+        let check = storage.get_job_result_check(&job.job_id).await.unwrap().unwrap();
+        assert_eq!(check.schema_name, "EventCreateResult");
+        assert_eq!(check.policy, "quarantine");
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&check.errors_json).unwrap(), errors);
+        let quarantined = storage.list_quarantine(10).await.unwrap();
+        assert_eq!(quarantined[0].source_table, "job_results");
+        assert_eq!(quarantined[0].row_key, job.job_id);
+        assert_eq!(quarantined[0].raw, br#"{"uid":7}"#);
+
+        assert_eq!(storage.purge_jobs(std::slice::from_ref(&job.job_id)).await.unwrap(), 1);
+        assert!(storage.get_job_result_check(&job.job_id).await.unwrap().is_none());
+        assert_eq!(storage.list_quarantine(10).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn event_feed_pages_without_gaps_under_concurrent_writers() {
+        let db = temp_path("db.sqlite");
+        let storage = RetasyncStorage::connect(&config(&db, None))
+            .await
+            .expect("connect");
+        let writers: Vec<_> = (0..8)
+            .map(|writer| {
+                let storage = storage.clone();
+                tokio::spawn(async move {
+                    for n in 0..20 {
+                        if n % 2 == 0 {
+                            let data = json!({ "writer": writer, "n": n });
+                            storage
+                                .append_feed_event("test.event", &data)
+                                .await
+                                .unwrap();
+                        } else {
+                            storage.create_job("event.create", json!({})).await.unwrap();
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        let mut seen = Vec::new();
+        let mut after_seq = 0;
+        while seen.len() < 160 {
+            let page = storage.list_feed_events(after_seq, 7).await.unwrap();
+            for event in &page {
+                assert_eq!(event.seq, after_seq + 1, "gap after {after_seq}");
+                after_seq = event.seq;
+                seen.push(event.seq);
+            }
+            tokio::task::yield_now().await;
+        }
+        for writer in writers {
+            writer.await.unwrap();
+        }
+        assert_eq!(seen, (1..=160).collect::<Vec<i64>>());
+        assert_eq!(storage.feed_bounds().await.unwrap().head_seq, 160);
+    }
+
+    #[tokio::test]
+    async fn trimming_the_feed_moves_the_trim_point() {
+        let db = temp_path("db.sqlite");
+        let storage = RetasyncStorage::connect(&config(&db, None))
+            .await
+            .expect("connect");
+        for n in 0..5 {
+            storage
+                .append_feed_event("test.event", &json!({ "n": n }))
+                .await
+                .unwrap();
+        }
+        sqlx::query("UPDATE event_feed SET emitted_at = '2026-01-01T00:00:00.000Z' WHERE seq <= 3")
+            .execute(storage.pool())
+            .await
+            .unwrap();
+
+        let cutoff = Utc.with_ymd_and_hms(2026, 2, 1, 0, 0, 0).unwrap();
+        assert_eq!(storage.trim_event_feed(cutoff).await.unwrap(), 3);
+        let bounds = storage.feed_bounds().await.unwrap();
+        assert_eq!((bounds.head_seq, bounds.trimmed_through), (5, 3));
+        assert_eq!(storage.list_feed_events(0, 10).await.unwrap()[0].seq, 4);
+
+        // Emptying the feed keeps numbering where it was.
+        assert_eq!(
+            storage
+                .trim_event_feed(Utc::now() + Duration::hours(1))
+                .await
+                .unwrap(),
+            2
+        );
+        let next = storage
+            .append_feed_event("test.event", &json!({}))
+            .await
+            .unwrap();
+        assert_eq!(next.seq, 6);
+        let bounds = storage.feed_bounds().await.unwrap();
+        assert_eq!((bounds.head_seq, bounds.trimmed_through), (6, 5));
+    }
+
+    #[tokio::test]
+    async fn failures_are_classified_by_cause() {
+        let db = temp_path("db.sqlite");
+        let key = write_key(1);
+        let storage = RetasyncStorage::connect(&config(&db, Some(&key)))
+            .await
+            .expect("connect");
+
+        let err = storage
+            .record_transfer_chunk("missing-transfer", 16)
+            .await
+            .expect_err("missing row");
+        assert!(matches!(err, StorageError::NotFound { .. }), "{err:?}");
+        assert!(!err.is_transient());
+
+        let job = storage.create_job("event.create", json!({})).await.unwrap();
+        storage.record_job_message("msg-1", &job.job_id).await.unwrap();
+        let err = storage
+            .record_job_message("msg-1", &job.job_id)
+            .await
+            .expect_err("duplicate key");
+        assert!(matches!(err, StorageError::Conflict { .. }), "{err:?}");
+        assert!(!err.is_transient());
+
+        sqlx::query("INSERT INTO storage_meta(key, value) VALUES ('node.mute', '{\"scope\":')")
+            .execute(storage.pool())
+            .await
+            .unwrap();
+        let err = storage.node_mute().await.expect_err("malformed stored JSON");
+        assert!(matches!(err, StorageError::Serialization { .. }), "{err:?}");
+        assert!(!err.is_transient());
+
+        sqlx::query("UPDATE jobs SET payload_json = char(1) || 'enc:v2:AAAA' WHERE job_id = ?")
+            .bind(&job.job_id)
+            .execute(storage.pool())
+            .await
+            .unwrap();
+        let err = storage.get_job(&job.job_id).await.expect_err("truncated ciphertext");
+        assert!(
+            matches!(&err, StorageError::Corrupt { table, .. } if table == "jobs"),
+            "{err:?}"
+        );
+        assert!(!err.is_transient());
+
+        storage.close().await;
+        let err = storage
+            .create_job("event.create", json!({}))
+            .await
+            .expect_err("closed pool");
+        assert!(matches!(err, StorageError::Connection { .. }), "{err:?}");
+        assert!(err.is_transient());
     }
 }
@@ -5,7 +5,19 @@
     payload_json TEXT NOT NULL,
     submitted_at TEXT NOT NULL,
     updated_at TEXT NOT NULL,
-    failure_reason TEXT
+    failure_reason TEXT,
+    dispatch_json TEXT,
+    requested_operation TEXT,
+    idempotency_key TEXT,
+    submitted_by TEXT,
+    transit_expires_at TEXT,
+    contract_version TEXT,
+    source_token TEXT,
+    source_addr TEXT,
+    source_client_id TEXT,
+    -- Size and SHA-256 of the plaintext payload JSON, for readers that leave it out.
+    payload_bytes INTEGER,
+    payload_sha256 TEXT
 );
 
 CREATE TABLE IF NOT EXISTS job_attempts (
@@ -19,6 +31,14 @@ CREATE TABLE IF NOT EXISTS job_attempts (
     FOREIGN KEY(job_id) REFERENCES jobs(job_id)
 );
 
+CREATE TABLE IF NOT EXISTS job_leases (
+    job_id TEXT PRIMARY KEY,
+    worker_id TEXT NOT NULL,
+    attempt INTEGER NOT NULL,
+    lease_expires_at TEXT NOT NULL,
+    FOREIGN KEY(job_id) REFERENCES jobs(job_id)
+);
+
 CREATE TABLE IF NOT EXISTS job_results (
     job_id TEXT PRIMARY KEY,
     result_json TEXT NOT NULL,
@@ -30,14 +50,21 @@ CREATE TABLE IF NOT EXISTS cached_events (
     event_id TEXT PRIMARY KEY,
     event_name TEXT NOT NULL,
     payload_json TEXT NOT NULL,
-    received_at TEXT NOT NULL
+    received_at TEXT NOT NULL,
+    source_identity TEXT,
+    sent_at TEXT,
+    contract_version TEXT,
+    payload_bytes INTEGER,
+    payload_sha256 TEXT
 );
 
 CREATE TABLE IF NOT EXISTS cached_messages (
     message_id TEXT PRIMARY KEY,
     operation TEXT NOT NULL,
     payload_json TEXT NOT NULL,
-    received_at TEXT NOT NULL
+    received_at TEXT NOT NULL,
+    payload_bytes INTEGER,
+    payload_sha256 TEXT
 );
 
 CREATE TABLE IF NOT EXISTS transfers (
@@ -46,13 +73,22 @@ CREATE TABLE IF NOT EXISTS transfers (
     metadata_json TEXT NOT NULL,
     submitted_at TEXT NOT NULL,
     updated_at TEXT NOT NULL,
-    failure_reason TEXT
+    failure_reason TEXT,
+    job_id TEXT REFERENCES jobs(job_id),
+    source_token TEXT,
+    source_addr TEXT,
+    source_client_id TEXT
 );
 
 CREATE TABLE IF NOT EXISTS acl_allowlist (
     identity_hash TEXT PRIMARY KEY,
     note TEXT,
-    created_at TEXT NOT NULL
+    created_at TEXT NOT NULL,
+    role TEXT NOT NULL DEFAULT 'peer',
+    status TEXT NOT NULL DEFAULT 'active',
+    expires_at TEXT,
+    approved_at TEXT,
+    public_key TEXT
 );
 
 CREATE TABLE IF NOT EXISTS acl_denylist (
@@ -64,5 +100,478 @@ CREATE TABLE IF NOT EXISTS acl_denylist (
 CREATE TABLE IF NOT EXISTS node_config_revisions (
     revision_id INTEGER PRIMARY KEY AUTOINCREMENT,
     config_json TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    -- Why the node wrote it when not a plain update, such as `unconfirmed_apply`.
+    reason TEXT,
+    note TEXT
+);
+
+CREATE TABLE IF NOT EXISTS storage_meta (
+    key TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+);
+
+-- One row: the global write sequence, bumped by every `with_tx` commit in the same
+-- transaction as the write. Clients echo it back as a read-your-writes token.
+CREATE TABLE IF NOT EXISTS write_sequence (
+    id INTEGER PRIMARY KEY CHECK (id = 1),
+    seq INTEGER NOT NULL
+);
+
+INSERT OR IGNORE INTO write_sequence(id, seq) VALUES (1, 0);
+
+CREATE TABLE IF NOT EXISTS notifications (
+    seq INTEGER PRIMARY KEY AUTOINCREMENT,
+    event_type TEXT NOT NULL,
+    payload_json TEXT NOT NULL,
     created_at TEXT NOT NULL
 );
+
+-- Every lifecycle event in emission order. Job status rows are written by triggers (see
+-- FEED_TRIGGERS in repository.rs) and other state changes append theirs in the transaction
+-- making them, so an event commits or rolls back with the change itself. Live subscribers are
+-- fed from here, after the commit.
+CREATE TABLE IF NOT EXISTS event_feed (
+    seq INTEGER PRIMARY KEY AUTOINCREMENT,
+    event_type TEXT NOT NULL,
+    payload_json TEXT NOT NULL,
+    emitted_at TEXT NOT NULL
+);
+
+-- The payload the next status change of a job publishes, staged by the transaction making
+-- that change and consumed by the feed trigger. Never outlives its transaction.
+CREATE TABLE IF NOT EXISTS job_event_details (
+    job_id TEXT PRIMARY KEY,
+    payload_json TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS notification_cursors (
+    token_label TEXT PRIMARY KEY,
+    acked_seq INTEGER NOT NULL,
+    dropped INTEGER NOT NULL DEFAULT 0,
+    updated_at TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS transfer_progress (
+    transfer_id TEXT PRIMARY KEY,
+    bytes_total INTEGER NOT NULL,
+    bytes_sent INTEGER NOT NULL DEFAULT 0,
+    chunks_total INTEGER NOT NULL,
+    chunks_sent INTEGER NOT NULL DEFAULT 0,
+    last_chunk_at TEXT,
+    stall_notified INTEGER NOT NULL DEFAULT 0,
+    FOREIGN KEY(transfer_id) REFERENCES transfers(transfer_id)
+);
+
+CREATE TABLE IF NOT EXISTS job_messages (
+    message_id TEXT PRIMARY KEY,
+    job_id TEXT NOT NULL,
+    sent_at TEXT NOT NULL,
+    FOREIGN KEY(job_id) REFERENCES jobs(job_id)
+);
+
+-- One row per bridge call made for a job, written once when the call returns. `outcome`
+-- says what followed it: delivered, retried, escalated or failed.
+CREATE TABLE IF NOT EXISTS dispatch_attempts (
+    message_id TEXT NOT NULL,
+    job_id TEXT NOT NULL,
+    destination_identity TEXT NOT NULL,
+    operation TEXT NOT NULL,
+    attempt INTEGER NOT NULL,
+    transport_planned TEXT NOT NULL,
+    transport_used TEXT,
+    started_at TEXT NOT NULL,
+    finished_at TEXT NOT NULL,
+    outcome TEXT NOT NULL,
+    error_variant TEXT,
+    error_detail TEXT,
+    escalated INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY(message_id, attempt),
+    FOREIGN KEY(job_id) REFERENCES jobs(job_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_dispatch_attempts_job ON dispatch_attempts(job_id, started_at);
+
+CREATE TABLE IF NOT EXISTS job_traces (
+    job_id TEXT PRIMARY KEY,
+    hops_json TEXT NOT NULL,
+    truncated INTEGER NOT NULL DEFAULT 0,
+    returned_at TEXT,
+    FOREIGN KEY(job_id) REFERENCES jobs(job_id)
+);
+
+CREATE TABLE IF NOT EXISTS job_transforms (
+    trace_id TEXT PRIMARY KEY,
+    job_id TEXT NOT NULL,
+    stage TEXT NOT NULL,
+    applied_json TEXT NOT NULL,
+    before_json TEXT NOT NULL,
+    after_json TEXT NOT NULL,
+    recorded_at TEXT NOT NULL,
+    FOREIGN KEY(job_id) REFERENCES jobs(job_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_job_transforms_job ON job_transforms(job_id);
+
+-- The canonical digest of a job's result. `unchanged` jobs succeeded without a stored result
+-- because the caller already held one with this digest. `source` is `mesh` or `cache`.
+CREATE TABLE IF NOT EXISTS job_result_digests (
+    job_id TEXT PRIMARY KEY,
+    result_digest TEXT NOT NULL,
+    unchanged INTEGER NOT NULL DEFAULT 0,
+    source TEXT NOT NULL,
+    recorded_at TEXT NOT NULL,
+    FOREIGN KEY(job_id) REFERENCES jobs(job_id)
+);
+
+-- Results that broke the contract's result schema and what the node did about them: `policy` is
+-- `flag` or `quarantine`, `errors_json` the violations with the JSON pointer of each.
+CREATE TABLE IF NOT EXISTS job_result_checks (
+    job_id TEXT PRIMARY KEY,
+    schema_name TEXT NOT NULL,
+    policy TEXT NOT NULL,
+    errors_json TEXT NOT NULL,
+    checked_at TEXT NOT NULL,
+    FOREIGN KEY(job_id) REFERENCES jobs(job_id)
+);
+
+-- Operator holds on jobs that have not been dispatched. `previous_status` is where a release puts
+-- the job back, `queued` or `deferred`.
+CREATE TABLE IF NOT EXISTS job_holds (
+    job_id TEXT PRIMARY KEY,
+    previous_status TEXT NOT NULL,
+    held_by TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    held_at TEXT NOT NULL,
+    FOREIGN KEY(job_id) REFERENCES jobs(job_id)
+);
+
+CREATE TABLE IF NOT EXISTS job_dependencies (
+    job_id TEXT NOT NULL,
+    depends_on TEXT NOT NULL,
+    PRIMARY KEY (job_id, depends_on),
+    FOREIGN KEY(job_id) REFERENCES jobs(job_id),
+    FOREIGN KEY(depends_on) REFERENCES jobs(job_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_job_dependencies_depends_on ON job_dependencies(depends_on);
+
+CREATE TABLE IF NOT EXISTS job_result_parts (
+    job_id TEXT NOT NULL,
+    sequence INTEGER NOT NULL,
+    is_final INTEGER NOT NULL,
+    payload_json TEXT NOT NULL,
+    received_at TEXT NOT NULL,
+    PRIMARY KEY(job_id, sequence),
+    FOREIGN KEY(job_id) REFERENCES jobs(job_id)
+);
+
+CREATE TABLE IF NOT EXISTS health_samples (
+    sampled_at TEXT NOT NULL,
+    resolution_secs INTEGER NOT NULL,
+    sample_count INTEGER NOT NULL,
+    bridge_ok INTEGER NOT NULL,
+    bridge_latency_ms INTEGER NOT NULL,
+    storage_ok INTEGER NOT NULL,
+    storage_latency_ms INTEGER NOT NULL,
+    queued_jobs INTEGER NOT NULL,
+    PRIMARY KEY(resolution_secs, sampled_at)
+);
+
+CREATE TABLE IF NOT EXISTS entities (
+    entity_key TEXT PRIMARY KEY,
+    entity_type TEXT NOT NULL,
+    entity_id TEXT NOT NULL,
+    updated_at TEXT NOT NULL,
+    record_json TEXT NOT NULL,
+    contract_version TEXT,
+    -- Bumped by every local change. Writes name the version they were based on.
+    version INTEGER NOT NULL DEFAULT 1,
+    deleted_at TEXT,
+    payload_bytes INTEGER,
+    payload_sha256 TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_entities_type_id ON entities(entity_type, entity_id);
+
+CREATE TABLE IF NOT EXISTS sync_conflicts (
+    conflict_id TEXT PRIMARY KEY,
+    entity_type TEXT NOT NULL,
+    entity_id TEXT NOT NULL,
+    peer_identity TEXT NOT NULL,
+    local_json TEXT NOT NULL,
+    remote_json TEXT NOT NULL,
+    kept TEXT NOT NULL,
+    detected_at TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS transfer_dedup (
+    transfer_id TEXT PRIMARY KEY,
+    sha256 TEXT NOT NULL,
+    outcome TEXT NOT NULL,
+    bytes_saved INTEGER NOT NULL,
+    remote_ack_json TEXT,
+    decided_at TEXT NOT NULL,
+    FOREIGN KEY(transfer_id) REFERENCES transfers(transfer_id)
+);
+
+CREATE TABLE IF NOT EXISTS received_files (
+    sha256 TEXT NOT NULL,
+    size_bytes INTEGER NOT NULL,
+    file_name TEXT NOT NULL,
+    source_identity TEXT NOT NULL,
+    received_at TEXT NOT NULL,
+    bundle_id TEXT,
+    media_type TEXT,
+    -- Set when the file failed the inbound content policy. Such files are kept but hidden.
+    quarantine_reason TEXT,
+    content_base64 TEXT,
+    PRIMARY KEY (sha256, size_bytes)
+);
+
+CREATE TABLE IF NOT EXISTS bundle_members (
+    bundle_id TEXT NOT NULL,
+    position INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    media_type TEXT NOT NULL,
+    size_bytes INTEGER NOT NULL,
+    sha256 TEXT NOT NULL,
+    status TEXT NOT NULL,
+    PRIMARY KEY (bundle_id, position),
+    FOREIGN KEY(bundle_id) REFERENCES transfers(transfer_id)
+);
+
+CREATE TABLE IF NOT EXISTS quota_usage (
+    subject_kind TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    used_at TEXT NOT NULL,
+    bytes INTEGER NOT NULL,
+    PRIMARY KEY (subject_kind, subject, used_at)
+);
+
+-- Bytes admitted for a transfer still in flight. They count against the quotas until the
+-- transfer succeeds, when they move into `quota_usage`, or ends any other way.
+CREATE TABLE IF NOT EXISTS quota_reservations (
+    transfer_id TEXT NOT NULL,
+    subject_kind TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    bytes INTEGER NOT NULL,
+    reserved_at TEXT NOT NULL,
+    PRIMARY KEY (transfer_id, subject_kind)
+);
+
+-- Submissions the rate limit refused, per client and hour.
+CREATE TABLE IF NOT EXISTS client_rejections (
+    client TEXT NOT NULL,
+    bucket_start TEXT NOT NULL,
+    rejected INTEGER NOT NULL,
+    PRIMARY KEY (client, bucket_start)
+);
+
+CREATE TABLE IF NOT EXISTS quota_overrides (
+    subject_kind TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    max_bytes INTEGER NOT NULL,
+    note TEXT,
+    updated_at TEXT NOT NULL,
+    PRIMARY KEY (subject_kind, subject)
+);
+
+CREATE TABLE IF NOT EXISTS feature_flags (
+    name TEXT PRIMARY KEY,
+    enabled INTEGER NOT NULL,
+    variant_json TEXT,
+    updated_at TEXT NOT NULL,
+    updated_by TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS seen_messages (
+    source_identity TEXT NOT NULL,
+    message_id TEXT NOT NULL,
+    sent_at TEXT NOT NULL,
+    received_at TEXT NOT NULL,
+    PRIMARY KEY (source_identity, message_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_seen_messages_sent_at ON seen_messages(sent_at);
+
+CREATE TABLE IF NOT EXISTS quarantine (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    source_table TEXT NOT NULL,
+    row_key TEXT NOT NULL,
+    column_name TEXT NOT NULL,
+    raw BLOB NOT NULL,
+    reason TEXT NOT NULL,
+    quarantined_at TEXT NOT NULL
+);
+
+-- Every record a payload migration rewrote, so no migration ever runs twice on one record.
+CREATE TABLE IF NOT EXISTS payload_migrations_applied (
+    migration TEXT NOT NULL,
+    record_table TEXT NOT NULL,
+    record_key TEXT NOT NULL,
+    from_version TEXT,
+    to_version TEXT NOT NULL,
+    applied_at TEXT NOT NULL,
+    PRIMARY KEY(migration, record_table, record_key)
+);
+
+-- Envelopes waiting for a courier between disconnected meshes. Commands are queued when the
+-- mesh takes them for store-and-forward, results when a bundled command is answered.
+CREATE TABLE IF NOT EXISTS outbox (
+    message_id TEXT PRIMARY KEY,
+    kind TEXT NOT NULL,
+    job_id TEXT,
+    operation TEXT NOT NULL,
+    destination_identity TEXT NOT NULL,
+    envelope_json TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'pending',
+    queued_at TEXT NOT NULL,
+    bundle_id TEXT,
+    exported_at TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_outbox_status ON outbox(status, queued_at);
+
+-- Panics: process crashes written by the panic hook and loaded at the next start, and worker
+-- panics the process survived, recorded as they are caught.
+CREATE TABLE IF NOT EXISTS crash_reports (
+    crash_id TEXT PRIMARY KEY,
+    kind TEXT NOT NULL,
+    occurred_at TEXT NOT NULL,
+    message TEXT NOT NULL,
+    thread TEXT,
+    location TEXT,
+    backtrace TEXT,
+    version TEXT NOT NULL,
+    uptime_secs INTEGER,
+    job_id TEXT,
+    recorded_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_crash_reports_occurred_at ON crash_reports(occurred_at);
+
+-- Stored identity hashes the one-time normalization could not turn into a hash, kept for an
+-- operator to fix rather than dropped.
+CREATE TABLE IF NOT EXISTS identity_hash_issues (
+    table_name TEXT NOT NULL,
+    identity_hash TEXT NOT NULL,
+    defect TEXT NOT NULL,
+    detected_at TEXT NOT NULL,
+    PRIMARY KEY (table_name, identity_hash)
+);
+
+-- The latest status report each node sent this node as fleet collector, and whether the
+-- node has been flagged stale since.
+CREATE TABLE IF NOT EXISTS fleet_nodes (
+    identity_hash TEXT PRIMARY KEY,
+    report_json TEXT NOT NULL,
+    interval_secs INTEGER NOT NULL,
+    received_at TEXT NOT NULL,
+    stale_since TEXT
+);
+
+-- Earlier reports per node, trimmed to the configured history on every write.
+CREATE TABLE IF NOT EXISTS fleet_reports (
+    identity_hash TEXT NOT NULL,
+    report_json TEXT NOT NULL,
+    received_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_fleet_reports_node ON fleet_reports(identity_hash, received_at);
+
+-- External HTTP consumers of the mesh events this node receives. `secret` signs every body
+-- sent to the subscription, and failures count up until a delivery succeeds.
+CREATE TABLE IF NOT EXISTS webhook_subscriptions (
+    subscription_id TEXT PRIMARY KEY,
+    url TEXT NOT NULL,
+    events_json TEXT NOT NULL,
+    source_identity TEXT,
+    secret TEXT NOT NULL,
+    timeout_ms INTEGER NOT NULL,
+    max_retries INTEGER NOT NULL,
+    backoff_ms INTEGER NOT NULL,
+    enabled INTEGER NOT NULL DEFAULT 1,
+    consecutive_failures INTEGER NOT NULL DEFAULT 0,
+    disabled_at TEXT,
+    created_at TEXT NOT NULL
+);
+
+-- Events waiting to reach a subscription, removed once delivered or given up on.
+CREATE TABLE IF NOT EXISTS webhook_deliveries (
+    delivery_id TEXT PRIMARY KEY,
+    subscription_id TEXT NOT NULL,
+    message_id TEXT NOT NULL,
+    event_name TEXT NOT NULL,
+    body_json TEXT NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at TEXT NOT NULL,
+    queued_at TEXT NOT NULL,
+    UNIQUE (subscription_id, message_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_webhook_deliveries_due ON webhook_deliveries(next_attempt_at);
+
+-- Every delivery attempt, trimmed to the configured history per subscription on every write.
+CREATE TABLE IF NOT EXISTS webhook_attempts (
+    subscription_id TEXT NOT NULL,
+    delivery_id TEXT NOT NULL,
+    message_id TEXT NOT NULL,
+    event_name TEXT NOT NULL,
+    attempt INTEGER NOT NULL,
+    attempted_at TEXT NOT NULL,
+    outcome TEXT NOT NULL,
+    status_code INTEGER,
+    error TEXT,
+    duration_ms INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_webhook_attempts_subscription ON webhook_attempts(subscription_id, attempted_at);
+
+-- Inbound commands a built-in handler answered, with the answer, kept while
+-- `inbound.record_inbound` is on so `retasyncd replay-jobs` can run them again.
+CREATE TABLE IF NOT EXISTS inbound_records (
+    message_id TEXT PRIMARY KEY,
+    operation TEXT NOT NULL,
+    source_identity TEXT NOT NULL,
+    envelope_json TEXT NOT NULL,
+    result_json TEXT NOT NULL,
+    received_at TEXT NOT NULL,
+    answered_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_inbound_records_received_at ON inbound_records(received_at);
+
+-- Operator labels on jobs, transfers and entities. An entity's `subject_id` is its entity_key.
+CREATE TABLE IF NOT EXISTS labels (
+    subject_type TEXT NOT NULL,
+    subject_id TEXT NOT NULL,
+    key TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (subject_type, subject_id, key)
+);
+
+-- Answers `key = value` filters without scanning the subjects they narrow.
+CREATE INDEX IF NOT EXISTS idx_labels_lookup ON labels(subject_type, key, value, subject_id);
+
+-- Delivery receipts peers signed for commands and transfers this node sent, linked to the job or
+-- transfer they close. `receipt_json` is the receipt as received, so it can be verified again.
+CREATE TABLE IF NOT EXISTS delivery_receipts (
+    message_id TEXT PRIMARY KEY,
+    job_id TEXT,
+    transfer_id TEXT,
+    receiver_identity TEXT NOT NULL,
+    receipt_json TEXT NOT NULL,
+    verified INTEGER NOT NULL,
+    reason TEXT,
+    recorded_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_delivery_receipts_job ON delivery_receipts(job_id);
+CREATE INDEX IF NOT EXISTS idx_delivery_receipts_transfer ON delivery_receipts(transfer_id);
+
+-- When each peer was last heard from, so liveness checks survive a restart.
+CREATE TABLE IF NOT EXISTS peer_contacts (
+    identity_hash TEXT PRIMARY KEY,
+    last_seen TEXT NOT NULL
+);
@@ -0,0 +1,151 @@
+﻿use std::fmt;
+use std::str::FromStr;
+
+use chrono::{DateTime, NaiveDateTime, SecondsFormat, SubsecRound, Utc};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sqlx::encode::IsNull;
+use sqlx::error::BoxDynError;
+use sqlx::sqlite::{Sqlite, SqliteArgumentValue, SqliteTypeInfo, SqliteValueRef};
+use sqlx::{Decode, Encode, Type};
+
+use crate::error::{Context, Result, StorageError};
+
+// The one shape every stored timestamp takes: RFC 3339 in UTC with a `Z` suffix and exactly
+// millisecond precision, such as `2026-03-01T10:00:00.000Z`. Strings in this shape sort in the
+// same order as the instants they name, so SQL can compare and order them as plain text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CanonicalTimestamp(DateTime<Utc>);
+
+// SQLite GLOB pattern matching exactly the canonical shape.
+pub(crate) const CANONICAL_GLOB: &str =
+    "[0-9][0-9][0-9][0-9]-[0-9][0-9]-[0-9][0-9]T[0-9][0-9]:[0-9][0-9]:[0-9][0-9].[0-9][0-9][0-9]Z";
+
+impl CanonicalTimestamp {
+    pub fn now() -> Self {
+        Self::from(Utc::now())
+    }
+
+    // Accepts RFC 3339 with any offset and precision, and SQLite's own `YYYY-MM-DD HH:MM:SS`,
+    // which is taken to be UTC.
+    pub fn parse(raw: &str) -> Result<Self> {
+        let raw = raw.trim();
+        if let Ok(parsed) = DateTime::parse_from_rfc3339(raw) {
+            return Ok(Self::from(parsed.with_timezone(&Utc)));
+        }
+        let naive = NaiveDateTime::parse_from_str(raw, "%Y-%m-%d %H:%M:%S%.f")
+            .with_context(|| format!("{raw:?} is not an RFC 3339 timestamp"))?;
+        Ok(Self::from(naive.and_utc()))
+    }
+
+    pub fn as_datetime(&self) -> DateTime<Utc> {
+        self.0
+    }
+}
+
+impl From<DateTime<Utc>> for CanonicalTimestamp {
+    // Finer precision is dropped so that equal strings always mean equal instants.
+    fn from(at: DateTime<Utc>) -> Self {
+        Self(at.trunc_subsecs(3))
+    }
+}
+
+impl From<CanonicalTimestamp> for DateTime<Utc> {
+    fn from(at: CanonicalTimestamp) -> Self {
+        at.0
+    }
+}
+
+impl fmt::Display for CanonicalTimestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0.to_rfc3339_opts(SecondsFormat::Millis, true))
+    }
+}
+
+impl FromStr for CanonicalTimestamp {
+    type Err = StorageError;
+
+    fn from_str(raw: &str) -> Result<Self> {
+        Self::parse(raw)
+    }
+}
+
+impl Serialize for CanonicalTimestamp {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for CanonicalTimestamp {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let raw = String::deserialize(deserializer)?;
+        Self::parse(&raw).map_err(serde::de::Error::custom)
+    }
+}
+
+impl Type<Sqlite> for CanonicalTimestamp {
+    fn type_info() -> SqliteTypeInfo {
+        <String as Type<Sqlite>>::type_info()
+    }
+
+    fn compatible(ty: &SqliteTypeInfo) -> bool {
+        <String as Type<Sqlite>>::compatible(ty)
+    }
+}
+
+impl<'q> Encode<'q, Sqlite> for CanonicalTimestamp {
+    fn encode_by_ref(
+        &self,
+        args: &mut Vec<SqliteArgumentValue<'q>>,
+    ) -> Result<IsNull, BoxDynError> {
+        Encode::<Sqlite>::encode(self.to_string(), args)
+    }
+}
+
+impl<'r> Decode<'r, Sqlite> for CanonicalTimestamp {
+    fn decode(value: SqliteValueRef<'r>) -> Result<Self, BoxDynError> {
+        let raw = <&str as Decode<Sqlite>>::decode(value)?;
+        Ok(Self::parse(raw)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CanonicalTimestamp;
+    use chrono::{DateTime, Duration, Utc};
+    use proptest::prelude::*;
+
+    #[test]
+    fn offsets_and_precisions_collapse_to_one_form() {
+        for raw in [
+            "2026-03-01T12:00:00.000Z",
+            "2026-03-01T12:00:00Z",
+            "2026-03-01T14:00:00+02:00",
+            "2026-03-01T07:00:00.000400-05:00",
+            "2026-03-01 12:00:00",
+        ] {
+            let parsed = CanonicalTimestamp::parse(raw).unwrap();
+            assert_eq!(parsed.to_string(), "2026-03-01T12:00:00.000Z", "{raw}");
+        }
+        assert!(CanonicalTimestamp::parse("yesterday").is_err());
+        assert!(CanonicalTimestamp::parse("2026-03-01").is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn canonical_strings_sort_like_instants(
+            a in -2_000_000_000_000i64..8_000_000_000_000i64,
+            b in -2_000_000_000_000i64..8_000_000_000_000i64,
+            offset_minutes in -14 * 60i32..=14 * 60i32,
+        ) {
+            let epoch = DateTime::<Utc>::UNIX_EPOCH;
+            let a = CanonicalTimestamp::from(epoch + Duration::milliseconds(a));
+            let b = CanonicalTimestamp::from(epoch + Duration::milliseconds(b));
+            prop_assert_eq!(a.to_string().cmp(&b.to_string()), a.cmp(&b));
+
+            // Rendering in another offset and parsing back lands on the same instant.
+            let zone = chrono::FixedOffset::east_opt(offset_minutes * 60).unwrap();
+            let shifted = a.as_datetime().with_timezone(&zone).to_rfc3339();
+            prop_assert_eq!(CanonicalTimestamp::parse(&shifted).unwrap(), a);
+        }
+    }
+}
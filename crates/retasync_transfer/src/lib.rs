@@ -1,5 +1,7 @@
 ﻿use serde::{Deserialize, Serialize};
 
+pub const DEFAULT_CHUNK_SIZE: usize = 32 * 1024;
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
 pub enum TransferStatus {
@@ -7,6 +9,8 @@ pub enum TransferStatus {
     Running,
     Success,
     Failed,
+    // The destination already held the content, so nothing was sent.
+    SkippedDuplicate,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -15,6 +19,13 @@ pub struct TransferUploadRequest {
     pub file_name: String,
     pub media_type: String,
     pub payload_base64: String,
+    // Offer the content hash first so a destination that already has the file can skip it.
+    #[serde(default = "default_dedup")]
+    pub dedup: bool,
+}
+
+fn default_dedup() -> bool {
+    true
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -25,3 +36,29 @@ pub struct TransferRecord {
     pub updated_at: String,
     pub failure_reason: Option<String>,
 }
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TransferProgress {
+    pub bytes_total: u64,
+    pub bytes_sent: u64,
+    pub chunks_total: u64,
+    pub chunks_sent: u64,
+    pub last_chunk_at: Option<String>,
+    #[serde(default)]
+    pub stalled: bool,
+}
+
+impl TransferProgress {
+    pub fn new(bytes_total: u64, chunk_size: usize) -> Self {
+        Self {
+            bytes_total,
+            chunks_total: bytes_total.div_ceil(chunk_size.max(1) as u64),
+            ..Self::default()
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.chunks_sent >= self.chunks_total && self.bytes_sent >= self.bytes_total
+    }
+}
+
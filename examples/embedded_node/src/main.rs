@@ -0,0 +1,70 @@
+﻿use std::sync::Arc;
+
+use axum::{
+    extract::Request,
+    http::StatusCode,
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use retasync_control_plane::{embedded_router, runtime::ControlPlaneRuntime, AppState, NodeConfig};
+use retasync_mesh_bridge::InMemoryRpcMeshBridge;
+use retasync_storage::{RetasyncStorage, StorageConfig, DEFAULT_READ_POOL_SIZE};
+use serde_json::json;
+
+const BIND: &str = "127.0.0.1:8090";
+const HOST_KEY: &str = "host-secret";
+const CONTRACT: &str = include_str!("../../../contracts/retasyncapi-v1.asyncapi.yaml");
+
+// The host's own auth sits in front of everything, the mesh routes included.
+async fn host_auth(request: Request, next: Next) -> Response {
+    let key = request.headers().get("x-host-key");
+    if key.and_then(|value| value.to_str().ok()) != Some(HOST_KEY) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    next.run(request).await
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let storage = RetasyncStorage::connect(&StorageConfig {
+        sqlite_path: "embedded_node.sqlite".to_string(),
+        encryption_key_path: None,
+        read_pool_size: DEFAULT_READ_POOL_SIZE,
+    })
+    .await?;
+    let config: NodeConfig = serde_json::from_value(json!({
+        "rpc_endpoint": "in-memory",
+        "http_bind": BIND,
+        "sqlite_path": "embedded_node.sqlite",
+        "acl_mode": "allowlist",
+        "prefer_link": true,
+    }))?;
+    let bridge = Arc::new(InMemoryRpcMeshBridge::new(true, true));
+    // The host authenticates callers itself, so the node does not ask for a bearer token.
+    let state = AppState::new(storage, bridge, config, CONTRACT.to_string(), false);
+
+    let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+    let runtime = ControlPlaneRuntime::new(state.clone())
+        .start(async move {
+            let _ = stopped.await;
+        })
+        .await?;
+
+    let app = Router::new()
+        .route("/", get(|| async { "host application" }))
+        .merge(embedded_router(state, "/mesh"))
+        .layer(middleware::from_fn(host_auth));
+    let listener = tokio::net::TcpListener::bind(BIND).await?;
+    println!("serving the host on http://{BIND}/ and the node under /mesh");
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async {
+            let _ = tokio::signal::ctrl_c().await;
+        })
+        .await?;
+
+    let _ = stop.send(());
+    runtime.await?;
+    Ok(())
+}
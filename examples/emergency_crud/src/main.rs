@@ -1,25 +1,27 @@
 ﻿use chrono::Utc;
-use retasync_contract::{encode_canonical, MeshCommandEnvelope};
-use serde_json::json;
+use retasync_contract::schemas::EmergencyActionMessage;
+use retasync_contract::{encode_canonical, IdentityHash, MeshCommandEnvelope, LOCAL_NODE_IDENTITY};
 use uuid::Uuid;
 
 fn main() {
-    let payload = json!({
-        "callsign": "ALPHA-1",
-        "groupName": "North Team",
-        "medicalStatus": "green"
-    });
+    let payload = serde_json::to_value(EmergencyActionMessage::example())
+        .expect("failed to serialize example message");
 
     let envelope = MeshCommandEnvelope {
         message_id: Uuid::now_v7().to_string(),
         operation: "emergency_action_message.create".to_string(),
         sent_at: Utc::now(),
-        source_identity: "local-node".to_string(),
-        destination_identity: "remote-node".to_string(),
+        source_identity: IdentityHash::lenient(LOCAL_NODE_IDENTITY),
+        destination_identity: "a1b2c3d4e5f60718293a4b5c6d7e8f90"
+            .parse()
+            .expect("valid identity hash"),
         content_type: "application/msgpack".to_string(),
         payload,
         ttl_ms: Some(30_000),
         transport_hint: None,
+        trace: Vec::new(),
+        trace_truncated: false,
+        meta: None,
     };
 
     let encoded = encode_canonical(&envelope).expect("failed to encode envelope");
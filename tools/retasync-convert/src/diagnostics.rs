@@ -0,0 +1,144 @@
+﻿use serde::Serialize;
+
+pub const UNMAPPABLE_OPERATION_ID: &str = "RA1001";
+pub const MISSING_OPERATION_ID: &str = "RA1002";
+pub const DUPLICATE_OPERATION_ID: &str = "RA1003";
+pub const MISSING_PROFILE_OPERATION: &str = "RA2002";
+pub const DEPRECATED_WITHOUT_SUNSET: &str = "RA2003";
+pub const UNKNOWN_SUNSET_OPERATION: &str = "RA2004";
+pub const UNKNOWN_RENAME_TARGET: &str = "RA2005";
+pub const RENAME_CYCLE: &str = "RA2006";
+pub const RENAMED_COMMAND_DECLARED: &str = "RA2007";
+pub const UNKNOWN_DELIVERY_OPERATION: &str = "RA2008";
+pub const INVALID_DELIVERY_POLICY: &str = "RA2009";
+pub const UNRESOLVABLE_SCHEMA_REF: &str = "RA3001";
+pub const EXTERNAL_SCHEMA_REF: &str = "RA3002";
+pub const SCHEMA_NAME_CASE: &str = "RA4001";
+pub const PROPERTY_NAME_CASE: &str = "RA4002";
+pub const UNSORTED_OPERATION_LIST: &str = "RA4003";
+pub const UNLISTED_CHANNEL_OPERATION: &str = "RA4004";
+pub const OPERATION_NAME_FORMAT: &str = "RA4005";
+pub const MISSING_SCHEMA_DESCRIPTION: &str = "RA4006";
+pub const INVALID_ENUM: &str = "RA4007";
+pub const UNFORMATTED_TIMESTAMP: &str = "RA4008";
+pub const UNDEFINED_REQUIRED_PROPERTY: &str = "RA4009";
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Error,
+    Warning,
+    Info,
+}
+
+impl Severity {
+    pub fn label(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Info => "info",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SourceLocation {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub method: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub operation_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pointer: Option<String>,
+}
+
+impl SourceLocation {
+    fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if let (Some(method), Some(path)) = (&self.method, &self.path) {
+            parts.push(format!("{} {}", method.to_ascii_uppercase(), path));
+        }
+        if let Some(operation_id) = &self.operation_id {
+            parts.push(format!("operationId {operation_id}"));
+        }
+        if let Some(pointer) = &self.pointer {
+            parts.push(format!("at {pointer}"));
+        }
+        parts.join(", ")
+    }
+}
+
+// A JSON pointer segment for `segment`, as `SourceLocation::pointer` spells them.
+pub fn escape_pointer_segment(segment: &str) -> String {
+    segment.replace('~', "~0").replace('/', "~1")
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Diagnostic {
+    pub code: &'static str,
+    pub severity: Severity,
+    pub message: String,
+    pub location: SourceLocation,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub suggestion: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DiagnosticSummary {
+    pub errors: usize,
+    pub warnings: usize,
+    pub infos: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DiagnosticsReport {
+    pub summary: DiagnosticSummary,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl DiagnosticsReport {
+    pub fn new(mut diagnostics: Vec<Diagnostic>) -> Self {
+        diagnostics.sort_by(|a, b| a.severity.cmp(&b.severity).then(a.code.cmp(b.code)));
+
+        let mut summary = DiagnosticSummary::default();
+        for diagnostic in &diagnostics {
+            match diagnostic.severity {
+                Severity::Error => summary.errors += 1,
+                Severity::Warning => summary.warnings += 1,
+                Severity::Info => summary.infos += 1,
+            }
+        }
+
+        Self {
+            summary,
+            diagnostics,
+        }
+    }
+
+    pub fn print_console(&self) {
+        for severity in [Severity::Error, Severity::Warning, Severity::Info] {
+            let group: Vec<&Diagnostic> = self
+                .diagnostics
+                .iter()
+                .filter(|diagnostic| diagnostic.severity == severity)
+                .collect();
+            if group.is_empty() {
+                continue;
+            }
+
+            println!("{} ({}):", severity.label(), group.len());
+            for diagnostic in group {
+                println!(
+                    "  [{}] {} ({})",
+                    diagnostic.code,
+                    diagnostic.message,
+                    diagnostic.location.describe()
+                );
+                if let Some(suggestion) = &diagnostic.suggestion {
+                    println!("         suggestion: {suggestion}");
+                }
+            }
+        }
+    }
+}
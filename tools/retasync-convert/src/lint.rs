@@ -0,0 +1,919 @@
+﻿use std::collections::BTreeSet;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use serde_yaml::{Mapping, Value};
+
+use crate::diagnostics::{
+    self, escape_pointer_segment, Diagnostic, DiagnosticsReport, Severity, SourceLocation,
+};
+
+// Where a renamed property keeps the name older peers may still send.
+pub const PROPERTY_ALIAS_KEY: &str = "x-retasync-alias";
+const OPERATION_LISTS: [&str; 2] = ["commands", "events"];
+const SCHEMA_BRANCHES: [&str; 3] = ["oneOf", "anyOf", "allOf"];
+
+type Check = fn(&Value, &Rule, &mut Vec<Diagnostic>);
+type Fix = fn(&mut Value, &Rule, &mut Vec<Diagnostic>);
+
+// One project convention a contract is held to. Rules with a fix only ever make changes that keep
+// the document meaning the same on the wire, or announce with a warning where they cannot.
+pub struct Rule {
+    pub id: &'static str,
+    pub name: &'static str,
+    pub severity: Severity,
+    pub summary: &'static str,
+    check: Check,
+    fix: Option<Fix>,
+}
+
+impl Rule {
+    pub fn autofixable(&self) -> bool {
+        self.fix.is_some()
+    }
+
+    fn diagnostic(
+        &self,
+        message: String,
+        pointer: String,
+        suggestion: Option<String>,
+    ) -> Diagnostic {
+        Diagnostic {
+            code: self.id,
+            severity: self.severity,
+            message,
+            location: SourceLocation {
+                pointer: Some(pointer),
+                ..SourceLocation::default()
+            },
+            suggestion: suggestion.or_else(|| {
+                self.autofixable()
+                    .then(|| "run `retasync-convert lint --fix`".to_string())
+            }),
+        }
+    }
+}
+
+// What a fix changed. It is already done, so it is reported as info whatever the rule's severity.
+fn applied_fix(rule: &Rule, message: String, pointer: String) -> Diagnostic {
+    Diagnostic {
+        severity: Severity::Info,
+        suggestion: None,
+        ..rule.diagnostic(message, pointer, None)
+    }
+}
+
+impl std::fmt::Debug for Rule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Rule")
+            .field("id", &self.id)
+            .field("name", &self.name)
+            .field("severity", &self.severity)
+            .field("autofixable", &self.autofixable())
+            .finish()
+    }
+}
+
+pub const RULES: &[Rule] = &[
+    Rule {
+        id: diagnostics::SCHEMA_NAME_CASE,
+        name: "schema-name-case",
+        severity: Severity::Error,
+        summary: "component schema and message names are PascalCase",
+        check: check_schema_names,
+        fix: None,
+    },
+    Rule {
+        id: diagnostics::PROPERTY_NAME_CASE,
+        name: "property-name-case",
+        severity: Severity::Warning,
+        summary: "payload property names are snake_case",
+        check: check_property_names,
+        fix: Some(fix_property_names),
+    },
+    Rule {
+        id: diagnostics::UNSORTED_OPERATION_LIST,
+        name: "sorted-operation-lists",
+        severity: Severity::Info,
+        summary: "x-retasync operation lists are sorted and name each operation once",
+        check: check_sorted_lists,
+        fix: Some(fix_sorted_lists),
+    },
+    Rule {
+        id: diagnostics::UNLISTED_CHANNEL_OPERATION,
+        name: "channel-operations-listed",
+        severity: Severity::Error,
+        summary: "every operation x-retasync.channels routes is in the operation lists",
+        check: check_channel_operations,
+        fix: Some(fix_channel_operations),
+    },
+    Rule {
+        id: diagnostics::OPERATION_NAME_FORMAT,
+        name: "operation-name-format",
+        severity: Severity::Error,
+        summary: "operations and events are namespaced snake_case, like event.create",
+        check: check_operation_names,
+        fix: None,
+    },
+    Rule {
+        id: diagnostics::MISSING_SCHEMA_DESCRIPTION,
+        name: "schema-description",
+        severity: Severity::Info,
+        summary: "component schemas say what they are for",
+        check: check_schema_descriptions,
+        fix: None,
+    },
+    Rule {
+        id: diagnostics::INVALID_ENUM,
+        name: "enum-values",
+        severity: Severity::Error,
+        summary: "enums list at least one value, each a distinct scalar",
+        check: check_enums,
+        fix: None,
+    },
+    Rule {
+        id: diagnostics::UNFORMATTED_TIMESTAMP,
+        name: "timestamp-format",
+        severity: Severity::Warning,
+        summary: "timestamp strings (*_at, *At, timestamp) declare format: date-time",
+        check: check_timestamps,
+        fix: None,
+    },
+    Rule {
+        id: diagnostics::UNDEFINED_REQUIRED_PROPERTY,
+        name: "required-properties-defined",
+        severity: Severity::Error,
+        summary: "every name in required is a declared property",
+        check: check_required,
+        fix: None,
+    },
+];
+
+#[derive(Debug, Clone, Default)]
+pub struct LintOptions {
+    // Comma-separated rule ids or names; a leading `-` excludes one. Only excludes, or none at
+    // all, start from every rule.
+    pub rules: Option<String>,
+    // Runs only the rules whose findings are errors, as `cargo xtask codegen --lint` does.
+    pub errors_only: bool,
+    pub fix: bool,
+}
+
+pub fn select_rules(options: &LintOptions) -> Result<Vec<&'static Rule>> {
+    let mut included = BTreeSet::new();
+    let mut excluded = BTreeSet::new();
+    for entry in options.rules.iter().flat_map(|spec| spec.split(',')) {
+        let entry = entry.trim();
+        if entry.is_empty() {
+            continue;
+        }
+        let (target, name) = match entry.strip_prefix('-') {
+            Some(name) => (&mut excluded, name),
+            None => (&mut included, entry),
+        };
+        let Some(rule) = RULES.iter().find(|rule| rule.id == name || rule.name == name) else {
+            bail!("unknown lint rule {name:?}");
+        };
+        target.insert(rule.id);
+    }
+
+    Ok(RULES
+        .iter()
+        .filter(|rule| included.is_empty() || included.contains(rule.id))
+        .filter(|rule| !excluded.contains(rule.id))
+        .filter(|rule| !options.errors_only || rule.severity == Severity::Error)
+        .collect())
+}
+
+pub fn lint(doc: &Value, rules: &[&Rule]) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    for rule in rules {
+        (rule.check)(doc, rule, &mut diagnostics);
+    }
+    diagnostics
+}
+
+// Applies every selected fix, returning what each one changed.
+pub fn fix(doc: &mut Value, rules: &[&Rule]) -> Vec<Diagnostic> {
+    let mut applied = Vec::new();
+    for rule in rules {
+        if let Some(fix) = rule.fix {
+            fix(doc, rule, &mut applied);
+        }
+    }
+    applied
+}
+
+fn parse(source: &str) -> Result<Value> {
+    serde_yaml::from_str(source.trim_start_matches('\u{feff}'))
+        .context("failed to parse AsyncAPI YAML")
+}
+
+pub fn lint_source(source: &str, rules: &[&Rule]) -> Result<DiagnosticsReport> {
+    Ok(DiagnosticsReport::new(lint(&parse(source)?, rules)))
+}
+
+// The fixed document, or None when no fix changed anything and the source should be left exactly
+// as it is. Mappings keep their key order; comments and quoting are not kept.
+pub fn fix_source(source: &str, rules: &[&Rule]) -> Result<(Option<String>, Vec<Diagnostic>)> {
+    let original = parse(source)?;
+    let mut doc = original.clone();
+    let applied = fix(&mut doc, rules);
+    if doc == original {
+        return Ok((None, applied));
+    }
+    let mut rendered = serde_yaml::to_string(&doc).context("serialize fixed contract")?;
+    if source.starts_with('\u{feff}') {
+        rendered.insert(0, '\u{feff}');
+    }
+    Ok((Some(rendered), applied))
+}
+
+// Lints the contract at `input`, fixing it in place first with `options.fix`. The report holds
+// what the fixes changed followed by whatever is still wrong.
+pub fn run(input: &Path, options: &LintOptions) -> Result<DiagnosticsReport> {
+    let rules = select_rules(options)?;
+    let mut source = std::fs::read_to_string(input)
+        .with_context(|| format!("failed to read {}", input.display()))?;
+    let mut diagnostics = Vec::new();
+    if options.fix {
+        let (fixed, applied) = fix_source(&source, &rules)?;
+        if let Some(fixed) = fixed {
+            std::fs::write(input, &fixed)
+                .with_context(|| format!("failed writing {}", input.display()))?;
+            source = fixed;
+        }
+        diagnostics.extend(applied);
+    }
+    diagnostics.extend(lint_source(&source, &rules)?.diagnostics);
+    Ok(DiagnosticsReport::new(diagnostics))
+}
+
+pub fn pascal_to_snake(input: &str) -> String {
+    let mut out = String::new();
+    for (idx, ch) in input.chars().enumerate() {
+        if ch.is_uppercase() {
+            if idx > 0 {
+                out.push('_');
+            }
+            for low in ch.to_lowercase() {
+                out.push(low);
+            }
+        } else {
+            out.push(ch);
+        }
+    }
+    out
+}
+
+fn is_pascal_case(name: &str) -> bool {
+    name.starts_with(|ch: char| ch.is_ascii_uppercase())
+        && name.chars().all(|ch| ch.is_ascii_alphanumeric())
+}
+
+fn is_snake_case(name: &str) -> bool {
+    name.starts_with(|ch: char| ch.is_ascii_lowercase())
+        && name
+            .chars()
+            .all(|ch| ch.is_ascii_lowercase() || ch.is_ascii_digit() || ch == '_')
+}
+
+// `entity.action`, each dot-separated segment snake_case.
+fn is_operation_name(name: &str) -> bool {
+    let segments: Vec<&str> = name.split('.').collect();
+    segments.len() >= 2 && segments.iter().all(|segment| is_snake_case(segment))
+}
+
+fn component<'a>(doc: &'a Value, kind: &str) -> Option<&'a Mapping> {
+    doc.get("components")?.get(kind)?.as_mapping()
+}
+
+fn operation_list<'a>(doc: &'a Value, list: &str) -> Option<&'a [Value]> {
+    doc.get("x-retasync")?
+        .get("operations")?
+        .get(list)?
+        .as_sequence()
+        .map(Vec::as_slice)
+}
+
+fn operation_list_mut<'a>(doc: &'a mut Value, list: &str) -> Option<&'a mut Vec<Value>> {
+    doc.get_mut("x-retasync")?
+        .get_mut("operations")?
+        .get_mut(list)?
+        .as_sequence_mut()
+}
+
+// Every schema in components.schemas and every schema nested in one, with its pointer.
+fn schema_nodes(doc: &Value) -> Vec<(String, &Value)> {
+    let mut nodes = Vec::new();
+    for (name, schema) in component(doc, "schemas").into_iter().flatten() {
+        if let Some(name) = name.as_str() {
+            let pointer = format!("/components/schemas/{}", escape_pointer_segment(name));
+            collect_schema_nodes(schema, pointer, &mut nodes);
+        }
+    }
+    nodes
+}
+
+fn collect_schema_nodes<'a>(node: &'a Value, pointer: String, out: &mut Vec<(String, &'a Value)>) {
+    let Some(schema) = node.as_mapping() else {
+        return;
+    };
+    out.push((pointer.clone(), node));
+    if let Some(properties) = schema.get("properties").and_then(Value::as_mapping) {
+        for (name, definition) in properties {
+            if let Some(name) = name.as_str() {
+                let child = format!("{pointer}/properties/{}", escape_pointer_segment(name));
+                collect_schema_nodes(definition, child, out);
+            }
+        }
+    }
+    for key in ["items", "additionalProperties"] {
+        if let Some(child) = schema.get(key) {
+            collect_schema_nodes(child, format!("{pointer}/{key}"), out);
+        }
+    }
+    for key in SCHEMA_BRANCHES {
+        let branches = schema.get(key).and_then(Value::as_sequence);
+        for (idx, child) in branches.into_iter().flatten().enumerate() {
+            collect_schema_nodes(child, format!("{pointer}/{key}/{idx}"), out);
+        }
+    }
+}
+
+fn properties(schema: &Value) -> impl Iterator<Item = (&str, &Value)> {
+    schema
+        .get("properties")
+        .and_then(Value::as_mapping)
+        .into_iter()
+        .flatten()
+        .filter_map(|(name, definition)| Some((name.as_str()?, definition)))
+}
+
+fn check_schema_names(doc: &Value, rule: &Rule, out: &mut Vec<Diagnostic>) {
+    for kind in ["schemas", "messages"] {
+        let names = component(doc, kind).into_iter().flatten();
+        for name in names.filter_map(|(name, _)| name.as_str()) {
+            if !is_pascal_case(name) {
+                out.push(rule.diagnostic(
+                    format!("{kind} name {name} is not PascalCase"),
+                    format!("/components/{kind}/{}", escape_pointer_segment(name)),
+                    Some("rename it and every $ref to it".to_string()),
+                ));
+            }
+        }
+    }
+}
+
+fn check_property_names(doc: &Value, rule: &Rule, out: &mut Vec<Diagnostic>) {
+    for (pointer, schema) in schema_nodes(doc) {
+        for (name, _) in properties(schema) {
+            if !is_snake_case(name) {
+                out.push(rule.diagnostic(
+                    format!("property {name} is not snake_case"),
+                    format!("{pointer}/properties/{}", escape_pointer_segment(name)),
+                    None,
+                ));
+            }
+        }
+    }
+}
+
+fn fix_property_names(doc: &mut Value, rule: &Rule, applied: &mut Vec<Diagnostic>) {
+    let Some(schemas) = doc
+        .get_mut("components")
+        .and_then(|components| components.get_mut("schemas"))
+        .and_then(Value::as_mapping_mut)
+    else {
+        return;
+    };
+    for (name, schema) in schemas.iter_mut() {
+        if let Some(name) = name.as_str() {
+            let pointer = format!("/components/schemas/{}", escape_pointer_segment(name));
+            rename_properties(schema, pointer, rule, applied);
+        }
+    }
+}
+
+// Renames the non-snake_case properties of `node` and the schemas nested in it in place, keeping
+// each one where it was and recording the old name on it. Names whose snake_case form is taken,
+// or is still not snake_case, are left for someone to decide on.
+fn rename_properties(
+    node: &mut Value,
+    pointer: String,
+    rule: &Rule,
+    applied: &mut Vec<Diagnostic>,
+) {
+    let Some(schema) = node.as_mapping_mut() else {
+        return;
+    };
+    let mut renamed = Vec::new();
+    if let Some(Value::Mapping(properties)) = schema.get_mut("properties") {
+        let taken: BTreeSet<String> = properties
+            .keys()
+            .filter_map(|name| name.as_str().map(str::to_string))
+            .collect();
+        for (name, definition) in std::mem::take(properties) {
+            let Some(old) = name.as_str().filter(|old| !is_snake_case(old)) else {
+                properties.insert(name, definition);
+                continue;
+            };
+            let new = pascal_to_snake(old);
+            if !is_snake_case(&new) || taken.contains(&new) {
+                properties.insert(name, definition);
+                continue;
+            }
+            let mut definition = definition;
+            if let Some(definition) = definition.as_mapping_mut() {
+                definition.insert(PROPERTY_ALIAS_KEY.into(), old.into());
+            }
+            properties.insert(new.as_str().into(), definition);
+            renamed.push((old.to_string(), new));
+        }
+    }
+
+    for (old, new) in &renamed {
+        if let Some(required) = schema.get_mut("required").and_then(Value::as_sequence_mut) {
+            for name in required.iter_mut().filter(|name| name.as_str() == Some(old)) {
+                *name = new.as_str().into();
+            }
+        }
+        // codegen refuses examples naming properties the schema does not declare.
+        if let Some(example) = schema.get_mut("example").and_then(Value::as_mapping_mut) {
+            rename_key(example, old, new);
+        }
+        if let Some(examples) = schema.get_mut("examples").and_then(Value::as_sequence_mut) {
+            for example in examples.iter_mut().filter_map(Value::as_mapping_mut) {
+                rename_key(example, old, new);
+            }
+        }
+        applied.push(Diagnostic {
+            severity: Severity::Warning,
+            ..rule.diagnostic(
+                format!(
+                    "renamed property {old} to {new}; peers still sending {old} need the alias \
+                     recorded in {PROPERTY_ALIAS_KEY}"
+                ),
+                format!("{pointer}/properties/{}", escape_pointer_segment(new)),
+                Some(format!("accept {old} as an alias of {new} until every peer is updated")),
+            )
+        });
+    }
+
+    if let Some(Value::Mapping(properties)) = schema.get_mut("properties") {
+        for (name, definition) in properties.iter_mut() {
+            if let Some(name) = name.as_str() {
+                let child = format!("{pointer}/properties/{}", escape_pointer_segment(name));
+                rename_properties(definition, child, rule, applied);
+            }
+        }
+    }
+    for key in ["items", "additionalProperties"] {
+        if let Some(child) = schema.get_mut(key) {
+            rename_properties(child, format!("{pointer}/{key}"), rule, applied);
+        }
+    }
+    for key in SCHEMA_BRANCHES {
+        if let Some(branches) = schema.get_mut(key).and_then(Value::as_sequence_mut) {
+            for (idx, child) in branches.iter_mut().enumerate() {
+                rename_properties(child, format!("{pointer}/{key}/{idx}"), rule, applied);
+            }
+        }
+    }
+}
+
+fn rename_key(mapping: &mut Mapping, old: &str, new: &str) {
+    for (key, value) in std::mem::take(mapping) {
+        match key.as_str() {
+            Some(key) if key == old => mapping.insert(new.into(), value),
+            _ => mapping.insert(key, value),
+        };
+    }
+}
+
+fn sorted_unique(list: &[Value]) -> Vec<Value> {
+    let mut names: Vec<&str> = list.iter().filter_map(Value::as_str).collect();
+    names.sort_unstable();
+    names.dedup();
+    names.into_iter().map(Value::from).collect()
+}
+
+fn check_sorted_lists(doc: &Value, rule: &Rule, out: &mut Vec<Diagnostic>) {
+    for list in OPERATION_LISTS {
+        let Some(entries) = operation_list(doc, list) else {
+            continue;
+        };
+        // Entries that are not strings are operation-name-format's to report.
+        let names: Vec<Value> = entries.iter().filter(|entry| entry.is_string()).cloned().collect();
+        if names != sorted_unique(&names) {
+            out.push(rule.diagnostic(
+                format!("x-retasync.operations.{list} is not sorted or names an operation twice"),
+                format!("/x-retasync/operations/{list}"),
+                None,
+            ));
+        }
+    }
+}
+
+fn fix_sorted_lists(doc: &mut Value, rule: &Rule, applied: &mut Vec<Diagnostic>) {
+    for list in OPERATION_LISTS {
+        let Some(entries) = operation_list_mut(doc, list) else {
+            continue;
+        };
+        if entries.iter().any(|entry| !entry.is_string()) {
+            continue;
+        }
+        let sorted = sorted_unique(entries);
+        if *entries != sorted {
+            *entries = sorted;
+            applied.push(applied_fix(
+                rule,
+                format!("sorted x-retasync.operations.{list}"),
+                format!("/x-retasync/operations/{list}"),
+            ));
+        }
+    }
+}
+
+// Operations x-retasync.channels routes that its operation lists leave out, per list.
+fn unlisted_channel_operations(doc: &Value) -> Vec<(&'static str, Vec<String>)> {
+    OPERATION_LISTS
+        .into_iter()
+        .map(|list| {
+            let listed = operation_list(doc, list).unwrap_or_default();
+            let routed = doc
+                .get("x-retasync")
+                .and_then(|extension| extension.get("channels"))
+                .and_then(|channels| channels.get(list))
+                .and_then(Value::as_mapping);
+            let missing = routed
+                .into_iter()
+                .flatten()
+                .filter_map(|(operation, _)| operation.as_str())
+                .filter(|operation| !listed.iter().any(|entry| entry.as_str() == Some(operation)))
+                .map(str::to_string)
+                .collect();
+            (list, missing)
+        })
+        .collect()
+}
+
+fn check_channel_operations(doc: &Value, rule: &Rule, out: &mut Vec<Diagnostic>) {
+    for (list, missing) in unlisted_channel_operations(doc) {
+        for operation in missing {
+            out.push(rule.diagnostic(
+                format!(
+                    "x-retasync.channels.{list} routes {operation} but \
+                     x-retasync.operations.{list} does not list it"
+                ),
+                format!("/x-retasync/channels/{list}/{}", escape_pointer_segment(&operation)),
+                None,
+            ));
+        }
+    }
+}
+
+fn fix_channel_operations(doc: &mut Value, rule: &Rule, applied: &mut Vec<Diagnostic>) {
+    for (list, missing) in unlisted_channel_operations(doc) {
+        if missing.is_empty() {
+            continue;
+        }
+        let Some(extension) = doc.get_mut("x-retasync").and_then(Value::as_mapping_mut) else {
+            continue;
+        };
+        let operations = extension
+            .entry("operations".into())
+            .or_insert_with(|| Value::Mapping(Mapping::new()));
+        let Some(operations) = operations.as_mapping_mut() else {
+            continue;
+        };
+        let entries = operations
+            .entry(list.into())
+            .or_insert_with(|| Value::Sequence(Vec::new()));
+        let Some(entries) = entries.as_sequence_mut() else {
+            continue;
+        };
+        // A sorted list stays sorted; any other keeps its order with the additions at the end.
+        let sorted = entries.iter().all(Value::is_string) && *entries == sorted_unique(entries);
+        for operation in missing {
+            entries.push(operation.as_str().into());
+            applied.push(applied_fix(
+                rule,
+                format!("added {operation} to x-retasync.operations.{list}"),
+                format!("/x-retasync/operations/{list}"),
+            ));
+        }
+        if sorted {
+            *entries = sorted_unique(entries);
+        }
+    }
+}
+
+fn check_operation_names(doc: &Value, rule: &Rule, out: &mut Vec<Diagnostic>) {
+    for list in OPERATION_LISTS {
+        for (idx, entry) in operation_list(doc, list).unwrap_or_default().iter().enumerate() {
+            let pointer = format!("/x-retasync/operations/{list}/{idx}");
+            match entry.as_str() {
+                Some(name) if is_operation_name(name) => {}
+                Some(name) => out.push(rule.diagnostic(
+                    format!("{name} is not a namespaced snake_case name like event.create"),
+                    pointer,
+                    None,
+                )),
+                None => out.push(rule.diagnostic(
+                    format!("x-retasync.operations.{list} holds an entry that is not a name"),
+                    pointer,
+                    None,
+                )),
+            }
+        }
+    }
+}
+
+fn check_schema_descriptions(doc: &Value, rule: &Rule, out: &mut Vec<Diagnostic>) {
+    for (name, schema) in component(doc, "schemas").into_iter().flatten() {
+        let Some(name) = name.as_str() else {
+            continue;
+        };
+        if schema.get("description").is_none() && schema.get("$ref").is_none() {
+            out.push(rule.diagnostic(
+                format!("schema {name} has no description"),
+                format!("/components/schemas/{}", escape_pointer_segment(name)),
+                None,
+            ));
+        }
+    }
+}
+
+fn check_enums(doc: &Value, rule: &Rule, out: &mut Vec<Diagnostic>) {
+    for (pointer, schema) in schema_nodes(doc) {
+        let Some(values) = schema.get("enum") else {
+            continue;
+        };
+        let problem = match values.as_sequence() {
+            None => Some("is not a list of values".to_string()),
+            Some(values) if values.is_empty() => Some("lists no values".to_string()),
+            Some(values) => {
+                let mut seen = Vec::new();
+                values.iter().find_map(|value| {
+                    if matches!(value, Value::Mapping(_) | Value::Sequence(_) | Value::Tagged(_)) {
+                        return Some("holds a value that is not a scalar".to_string());
+                    }
+                    if seen.contains(&value) {
+                        let shown = serde_yaml::to_string(value).unwrap_or_default();
+                        return Some(format!("lists {} twice", shown.trim_end()));
+                    }
+                    seen.push(value);
+                    None
+                })
+            }
+        };
+        if let Some(problem) = problem {
+            out.push(rule.diagnostic(format!("enum {problem}"), format!("{pointer}/enum"), None));
+        }
+    }
+}
+
+fn is_timestamp_name(name: &str) -> bool {
+    name.ends_with("_at") || name.ends_with("At") || name == "timestamp"
+}
+
+fn check_timestamps(doc: &Value, rule: &Rule, out: &mut Vec<Diagnostic>) {
+    for (pointer, schema) in schema_nodes(doc) {
+        for (name, definition) in properties(schema) {
+            let plain_string = definition.get("type").and_then(Value::as_str) == Some("string")
+                && definition.get("format").is_none();
+            if is_timestamp_name(name) && plain_string {
+                out.push(rule.diagnostic(
+                    format!("{name} is a plain string; timestamps are RFC 3339"),
+                    format!("{pointer}/properties/{}", escape_pointer_segment(name)),
+                    Some("add `format: date-time`".to_string()),
+                ));
+            }
+        }
+    }
+}
+
+fn check_required(doc: &Value, rule: &Rule, out: &mut Vec<Diagnostic>) {
+    for (pointer, schema) in schema_nodes(doc) {
+        let Some(required) = schema.get("required").and_then(Value::as_sequence) else {
+            continue;
+        };
+        // Without properties of its own, a schema may be composed; nothing to check against.
+        if schema.get("properties").is_none() {
+            continue;
+        }
+        let declared: Vec<&str> = properties(schema).map(|(name, _)| name).collect();
+        for (idx, name) in required.iter().enumerate() {
+            let name = name.as_str().unwrap_or_default();
+            if !declared.contains(&name) {
+                out.push(rule.diagnostic(
+                    format!("required names {name:?}, which is not a declared property"),
+                    format!("{pointer}/required/{idx}"),
+                    None,
+                ));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLEAN: &str = "asyncapi: \"3.0.0\"
+info:
+  title: Fixture
+  version: \"1.0.0\"
+components:
+  schemas:
+    Event:
+      description: A reported event.
+      type: object
+      required: [uid]
+      properties:
+        uid:
+          type: string
+        status:
+          type: string
+          enum: [open, closed]
+        occurred_at:
+          type: string
+          format: date-time
+x-retasync:
+  operations:
+    commands:
+      - event.create
+      - event.list
+    events:
+      - event.created
+";
+
+    // The two commands out of order.
+    const UNSORTED: (&str, &str) = (
+        "- event.create\n      - event.list",
+        "- event.list\n      - event.create",
+    );
+
+    fn only(id: &str) -> Vec<&'static Rule> {
+        select_rules(&LintOptions {
+            rules: Some(id.to_string()),
+            ..LintOptions::default()
+        })
+        .unwrap()
+    }
+
+    fn codes(source: &str, rules: &[&Rule]) -> Vec<&'static str> {
+        lint_source(source, rules)
+            .unwrap()
+            .diagnostics
+            .into_iter()
+            .map(|diagnostic| diagnostic.code)
+            .collect()
+    }
+
+    fn all_rules() -> Vec<&'static Rule> {
+        select_rules(&LintOptions::default()).unwrap()
+    }
+
+    #[test]
+    fn each_rule_fires_on_a_minimal_fixture() {
+        assert_eq!(codes(CLEAN, &all_rules()), Vec::<&str>::new());
+
+        let cases = [
+            (diagnostics::SCHEMA_NAME_CASE, CLEAN.replace("    Event:", "    event_record:")),
+            (diagnostics::PROPERTY_NAME_CASE, CLEAN.replace("occurred_at:", "occurredAt:")),
+            (
+                diagnostics::UNSORTED_OPERATION_LIST,
+                CLEAN.replace(UNSORTED.0, UNSORTED.1),
+            ),
+            (
+                diagnostics::UNLISTED_CHANNEL_OPERATION,
+                format!("{CLEAN}  channels:\n    commands:\n      event.delete: event/commands\n"),
+            ),
+            (diagnostics::OPERATION_NAME_FORMAT, CLEAN.replace("event.created", "EventCreated")),
+            (
+                diagnostics::MISSING_SCHEMA_DESCRIPTION,
+                CLEAN.replace("      description: A reported event.\n", ""),
+            ),
+            (diagnostics::INVALID_ENUM, CLEAN.replace("[open, closed]", "[open, open]")),
+            (
+                diagnostics::UNFORMATTED_TIMESTAMP,
+                CLEAN.replace("          format: date-time\n", ""),
+            ),
+            (diagnostics::UNDEFINED_REQUIRED_PROPERTY, CLEAN.replace("[uid]", "[uid, title]")),
+        ];
+        assert_eq!(cases.len(), RULES.len());
+        for (id, source) in &cases {
+            assert_eq!(codes(source, &only(id)), [*id], "{id}");
+        }
+        assert_eq!(
+            codes(&CLEAN.replace("[open, closed]", "[]"), &only("enum-values")),
+            [diagnostics::INVALID_ENUM]
+        );
+
+        let selected = select_rules(&LintOptions {
+            rules: Some("-RA4006, -timestamp-format".to_string()),
+            errors_only: true,
+            ..LintOptions::default()
+        })
+        .unwrap();
+        let ids: Vec<&str> = selected.iter().map(|rule| rule.id).collect();
+        assert_eq!(ids, ["RA4001", "RA4004", "RA4005", "RA4007", "RA4009"]);
+        assert!(select_rules(&LintOptions {
+            rules: Some("no-such-rule".to_string()),
+            ..LintOptions::default()
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn fixes_rename_in_place_and_a_second_run_changes_nothing() {
+        let source = format!(
+            "\u{feff}{}  channels:\n    commands:\n      event.delete: event/commands\n",
+            CLEAN
+                .replace("required: [uid]", "required: [uid, occurredAt]")
+                .replace("occurred_at:", "occurredAt:")
+                .replace("        uid:", "        uid:\n          example: evt-1")
+                .replace(UNSORTED.0, UNSORTED.1)
+        )
+        .replace(
+            "      properties:\n",
+            "      example:\n        uid: evt-1\n        occurredAt: \"2026-01-01T00:00:00Z\"\n\
+             \x20     properties:\n",
+        );
+
+        let (fixed, applied) = fix_source(&source, &all_rules()).unwrap();
+        let fixed = fixed.expect("fixes change the document");
+        assert!(fixed.starts_with('\u{feff}'));
+        let applied: Vec<(&str, Severity)> = applied
+            .iter()
+            .map(|diagnostic| (diagnostic.code, diagnostic.severity))
+            .collect();
+        assert_eq!(
+            applied,
+            [
+                (diagnostics::PROPERTY_NAME_CASE, Severity::Warning),
+                (diagnostics::UNSORTED_OPERATION_LIST, Severity::Info),
+                (diagnostics::UNLISTED_CHANNEL_OPERATION, Severity::Info),
+            ]
+        );
+
+        let doc = parse(&fixed).unwrap();
+        let event = &doc["components"]["schemas"]["Event"];
+        let names: Vec<&str> = properties(event).map(|(name, _)| name).collect();
+        assert_eq!(names, ["uid", "status", "occurred_at"]);
+        assert_eq!(event["properties"]["occurred_at"][PROPERTY_ALIAS_KEY], "occurredAt");
+        assert_eq!(event["required"], parse("[uid, occurred_at]").unwrap());
+        assert!(event["example"].get("occurred_at").is_some());
+        assert_eq!(
+            doc["x-retasync"]["operations"]["commands"],
+            parse("[event.create, event.delete, event.list]").unwrap()
+        );
+        assert_eq!(codes(&fixed, &all_rules()), Vec::<&str>::new());
+
+        let (again, applied) = fix_source(&fixed, &all_rules()).unwrap();
+        assert_eq!(again, None);
+        assert!(applied.is_empty());
+    }
+
+    #[test]
+    fn a_document_with_nothing_to_fix_is_left_byte_for_byte() {
+        let contract = include_str!("../../../contracts/retasyncapi-v1.asyncapi.yaml");
+        // The shipped contract keeps camelCase payloads for existing peers and lists operations
+        // in CRUD order; with those two rules off nothing else has a fix to make.
+        let rules = select_rules(&LintOptions {
+            rules: Some("-property-name-case,-sorted-operation-lists".to_string()),
+            ..LintOptions::default()
+        })
+        .unwrap();
+        let (fixed, applied) = fix_source(contract, &rules).unwrap();
+        assert_eq!(fixed, None);
+        assert!(applied.is_empty());
+        assert_eq!(lint_source(contract, &rules).unwrap().summary.errors, 0);
+
+        let per_entity =
+            include_str!("../tests/fixtures/emergency-management.per-entity.asyncapi.yaml");
+        assert_eq!(fix_source(per_entity, &all_rules()).unwrap().0, None);
+
+        // A fix elsewhere in the document leaves every other mapping in its written order.
+        let (fixed, _) = fix_source(contract, &all_rules()).unwrap();
+        let (before, after) = (parse(contract).unwrap(), parse(&fixed.unwrap()).unwrap());
+        let keys = |doc: &Value, path: &[&str]| -> Vec<String> {
+            let node = path.iter().fold(doc, |node, key| &node[*key]);
+            node.as_mapping()
+                .unwrap()
+                .keys()
+                .map(|key| key.as_str().unwrap().to_string())
+                .collect()
+        };
+        for path in [&[][..], &["components", "schemas"], &["channels"], &["info"]] {
+            assert_eq!(keys(&before, path), keys(&after, path), "{path:?}");
+        }
+        let snake: Vec<String> = keys(&before, &["components", "schemas", "Event", "properties"])
+            .iter()
+            .map(|name| pascal_to_snake(name))
+            .collect();
+        assert_eq!(keys(&after, &["components", "schemas", "Event", "properties"]), snake);
+    }
+}
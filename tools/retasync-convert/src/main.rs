@@ -1,10 +1,18 @@
-﻿use std::collections::BTreeSet;
-use std::path::PathBuf;
+﻿mod typescript;
 
-use anyhow::{Context, Result};
-use clap::{Parser, Subcommand};
-use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use chrono::NaiveDate;
+use clap::{Parser, Subcommand, ValueEnum};
+use retasync_convert::diagnostics::{
+    self, escape_pointer_segment, Diagnostic, DiagnosticsReport, Severity, SourceLocation,
+};
+use retasync_convert::lint::{self, pascal_to_snake, LintOptions};
+use serde::{Deserialize, Serialize};
 use serde_yaml::Value;
+use typescript::SCHEMA_REF_PREFIX;
 
 #[derive(Debug, Parser)]
 #[command(author, version, about = "OpenAPI to AsyncAPI converter")]
@@ -22,19 +30,125 @@ enum Commands {
         output: PathBuf,
         #[arg(long)]
         profile: Option<String>,
+        #[arg(long)]
+        deny_warnings: bool,
+        #[arg(long = "sunset", value_name = "OP=YYYY-MM-DD", value_parser = parse_sunset)]
+        sunsets: Vec<(String, NaiveDate)>,
+        #[arg(long = "rename", value_name = "OLD=NEW", value_parser = parse_rename)]
+        renames: Vec<(String, String)>,
+        #[arg(long = "renames", value_name = "FILE")]
+        rename_file: Option<PathBuf>,
+        #[arg(long = "delivery", value_name = "FILE")]
+        delivery_file: Option<PathBuf>,
+        #[arg(long = "channel-style", value_enum, default_value_t = ChannelStyle::Generic)]
+        channel_style: ChannelStyle,
+    },
+    Typescript {
+        #[arg(long = "in")]
+        input: PathBuf,
+        #[arg(long = "out")]
+        output: PathBuf,
+    },
+    Lint {
+        #[arg(long = "in", required_unless_present = "list_rules")]
+        input: Option<PathBuf>,
+        #[arg(long)]
+        fix: bool,
+        #[arg(long, value_name = "ID|NAME,-ID|NAME")]
+        rules: Option<String>,
+        #[arg(long)]
+        errors_only: bool,
+        #[arg(long)]
+        json: bool,
+        #[arg(long)]
+        deny_warnings: bool,
+        #[arg(long)]
+        list_rules: bool,
     },
 }
 
+// How commands, results and events are laid out over channels; the control plane reads the
+// same `x-retasync.channel_style` to route.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+enum ChannelStyle {
+    // `commands/{operation}`, `results/{operation}` and `events/{event}` for everything.
+    #[default]
+    Generic,
+    // `<entity>/commands`, `<entity>/results` and `<entity>/events` for each entity.
+    PerEntity,
+}
+
+impl ChannelStyle {
+    fn is_generic(&self) -> bool {
+        *self == ChannelStyle::Generic
+    }
+
+    fn command_address(self, command: &str) -> String {
+        match self {
+            ChannelStyle::Generic => format!("commands/{command}"),
+            ChannelStyle::PerEntity => format!("{}/commands", entity_of(command)),
+        }
+    }
+
+    fn event_address(self, event: &str) -> String {
+        match self {
+            ChannelStyle::Generic => format!("events/{event}"),
+            ChannelStyle::PerEntity => format!("{}/events", entity_of(event)),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize)]
 struct MappingRow {
     operation_id: String,
     command_operation: String,
+    channel: String,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    deprecated: bool,
 }
 
-#[derive(Debug, Clone, Serialize)]
-struct WarningRow {
-    operation_id: String,
-    reason: String,
+#[derive(Debug, Clone)]
+struct SourceOperation {
+    path: String,
+    method: String,
+    operation_id: Option<String>,
+    deprecated: bool,
+    // The body schema of the first success response that has one.
+    result: Option<serde_json::Value>,
+}
+
+impl SourceOperation {
+    fn location(&self) -> SourceLocation {
+        SourceLocation {
+            path: Some(self.path.clone()),
+            method: Some(self.method.clone()),
+            operation_id: self.operation_id.clone(),
+            pointer: Some(format!(
+                "/paths/{}/{}",
+                escape_pointer_segment(&self.path),
+                self.method
+            )),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Conversion {
+    mappings: Vec<MappingRow>,
+    diagnostics: Vec<Diagnostic>,
+    commands: BTreeSet<String>,
+    deprecations: BTreeMap<String, Option<NaiveDate>>,
+    aliases: BTreeMap<String, String>,
+    results: ResultSchemas,
+}
+
+// What each command answers with: the schema name per command, and the component schemas to
+// carry into the contract, the `<Operation>Result` ones and every source schema they refer to.
+#[derive(Debug, Default)]
+struct ResultSchemas {
+    operations: BTreeMap<String, String>,
+    schemas: serde_json::Map<String, serde_json::Value>,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -60,12 +174,54 @@ struct AsyncApiInfo {
 #[derive(Debug, Clone, Serialize)]
 struct RetasyncExtension {
     operations: RetasyncOperations,
+    #[serde(skip_serializing_if = "ChannelStyle::is_generic")]
+    channel_style: ChannelStyle,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    channels: Option<ChannelAddresses>,
+}
+
+// The resolved address of every command and event, so nothing has to re-derive it.
+#[derive(Debug, Clone, Serialize)]
+struct ChannelAddresses {
+    commands: BTreeMap<String, String>,
+    events: BTreeMap<String, String>,
 }
 
 #[derive(Debug, Clone, Serialize)]
 struct RetasyncOperations {
     commands: Vec<String>,
     events: Vec<String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    deprecated: BTreeMap<String, DeprecatedOperation>,
+    #[serde(rename = "x-retasync-aliases", skip_serializing_if = "BTreeMap::is_empty")]
+    aliases: BTreeMap<String, String>,
+    #[serde(rename = "x-retasync-delivery", skip_serializing_if = "BTreeMap::is_empty")]
+    delivery: BTreeMap<String, DeliveryPolicy>,
+    #[serde(rename = "x-retasync-results", skip_serializing_if = "BTreeMap::is_empty")]
+    results: BTreeMap<String, String>,
+}
+
+// Per-operation delivery defaults read from the `--delivery` profile and copied into the contract.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DeliveryPolicy {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_attempts: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    backoff_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timeout_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    idempotent: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    store_and_forward: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct DeprecatedOperation {
+    deprecated: bool,
+    #[serde(rename = "x-retasync-sunset", skip_serializing_if = "Option::is_none")]
+    sunset: Option<String>,
 }
 
 fn main() -> Result<()> {
@@ -75,47 +231,147 @@ fn main() -> Result<()> {
             input,
             output,
             profile,
-        } => run_openapi_conversion(input, output, profile),
+            deny_warnings,
+            sunsets,
+            renames,
+            rename_file,
+            delivery_file,
+            channel_style,
+        } => {
+            let mut aliases = match rename_file {
+                Some(path) => read_renames(&path)?,
+                None => BTreeMap::new(),
+            };
+            aliases.extend(renames);
+            let delivery = match delivery_file {
+                Some(path) => read_delivery(&path)?,
+                None => BTreeMap::new(),
+            };
+            run_openapi_conversion(
+                input,
+                output,
+                profile,
+                deny_warnings,
+                sunsets.into_iter().collect(),
+                aliases,
+                delivery,
+                channel_style,
+            )
+        }
+        Commands::Typescript { input, output } => {
+            let source = std::fs::read_to_string(&input)
+                .with_context(|| format!("failed to read {}", input.display()))?;
+            let rendered = typescript::render_typescript(&source)?;
+            std::fs::write(&output, rendered)
+                .with_context(|| format!("failed writing {}", output.display()))?;
+            println!("TypeScript: {}", output.display());
+            Ok(())
+        }
+        Commands::Lint {
+            input,
+            fix,
+            rules,
+            errors_only,
+            json,
+            deny_warnings,
+            list_rules,
+        } => {
+            let options = LintOptions {
+                rules,
+                errors_only,
+                fix,
+            };
+            match input {
+                Some(input) if !list_rules => run_lint(&input, &options, json, deny_warnings),
+                _ => print_lint_rules(&options),
+            }
+        }
     }
 }
 
-fn run_openapi_conversion(input: PathBuf, output: PathBuf, profile: Option<String>) -> Result<()> {
-    let source = std::fs::read_to_string(&input)
-        .with_context(|| format!("failed to read {}", input.display()))?;
-    let doc: Value = serde_yaml::from_str(&source).context("failed to parse OpenAPI YAML")?;
+fn run_lint(input: &Path, options: &LintOptions, json: bool, deny_warnings: bool) -> Result<()> {
+    let report = lint::run(input, options)?;
+    if json {
+        let rendered = serde_json::to_string_pretty(&report).context("serialize diagnostics")?;
+        println!("{rendered}");
+    } else {
+        report.print_console();
+        println!(
+            "Linted {}: {} error(s), {} warning(s), {} info(s)",
+            input.display(),
+            report.summary.errors,
+            report.summary.warnings,
+            report.summary.infos
+        );
+    }
 
-    let operation_ids = extract_operation_ids(&doc);
-    let mut mappings = Vec::new();
-    let mut warnings = Vec::new();
-    let mut commands = BTreeSet::new();
+    if report.summary.errors > 0 {
+        bail!("lint produced {} error diagnostic(s)", report.summary.errors);
+    }
+    if deny_warnings && report.summary.warnings > 0 {
+        bail!(
+            "lint produced {} warning diagnostic(s) and --deny-warnings is set",
+            report.summary.warnings
+        );
+    }
+    Ok(())
+}
 
-    for operation_id in operation_ids {
-        match map_operation_id(&operation_id) {
-            Some(mapped) => {
-                commands.insert(mapped.clone());
-                mappings.push(MappingRow {
-                    operation_id,
-                    command_operation: mapped,
-                });
-            }
-            None => warnings.push(WarningRow {
-                operation_id,
-                reason: "Unable to infer action/entity from operationId".to_string(),
-            }),
-        }
+fn print_lint_rules(options: &LintOptions) -> Result<()> {
+    for rule in lint::select_rules(options)? {
+        let fixable = if rule.autofixable() { "fixable" } else { "" };
+        println!(
+            "{} {:<28} {:<8} {:<8} {}",
+            rule.id,
+            rule.name,
+            rule.severity.label(),
+            fixable,
+            rule.summary
+        );
     }
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn run_openapi_conversion(
+    input: PathBuf,
+    output: PathBuf,
+    profile: Option<String>,
+    deny_warnings: bool,
+    sunsets: BTreeMap<String, NaiveDate>,
+    renames: BTreeMap<String, String>,
+    delivery: BTreeMap<String, DeliveryPolicy>,
+    channel_style: ChannelStyle,
+) -> Result<()> {
+    let source = std::fs::read_to_string(&input)
+        .with_context(|| format!("failed to read {}", input.display()))?;
+    let doc: Value = serde_yaml::from_str(&source).context("failed to parse OpenAPI YAML")?;
 
-    if let Some(profile_name) = profile
+    let profile_name = profile
         .as_deref()
-        .or_else(|| detect_profile_from_path(&input))
-    {
-        apply_profile(profile_name, &mappings, &mut warnings)?;
-    }
+        .or_else(|| detect_profile_from_path(&input));
+    let Conversion {
+        mappings,
+        mut diagnostics,
+        commands,
+        deprecations,
+        aliases,
+        results,
+    } = convert(&doc, profile_name, &sunsets, renames, channel_style)?;
+    check_delivery(&delivery, &commands, &mut diagnostics);
 
     let events = derive_events(&commands);
     let commands_vec = commands.into_iter().collect::<Vec<_>>();
 
-    let rendered = render_asyncapi(&commands_vec, &events)?;
+    let rendered = render_asyncapi(
+        &commands_vec,
+        &events,
+        &deprecations,
+        &aliases,
+        &delivery,
+        &results,
+        channel_style,
+    )?;
     std::fs::write(&output, rendered)
         .with_context(|| format!("failed writing {}", output.display()))?;
 
@@ -126,7 +382,8 @@ fn run_openapi_conversion(input: PathBuf, output: PathBuf, profile: Option<Strin
     std::fs::write(&mapping_path, mapping_json)
         .with_context(|| format!("failed writing {}", mapping_path.display()))?;
 
-    let warnings_json = serde_json::to_string_pretty(&warnings).context("serialize warnings")?;
+    let report = DiagnosticsReport::new(diagnostics);
+    let warnings_json = serde_json::to_string_pretty(&report).context("serialize diagnostics")?;
     std::fs::write(&warning_path, warnings_json)
         .with_context(|| format!("failed writing {}", warning_path.display()))?;
 
@@ -138,41 +395,403 @@ fn run_openapi_conversion(input: PathBuf, output: PathBuf, profile: Option<Strin
     println!("AsyncAPI: {}", output.display());
     println!("Mapping report: {}", mapping_path.display());
     println!("Warnings report: {}", warning_path.display());
+    report.print_console();
+
+    if report.summary.errors > 0 {
+        bail!(
+            "conversion produced {} error diagnostic(s)",
+            report.summary.errors
+        );
+    }
+    if deny_warnings && report.summary.warnings > 0 {
+        bail!(
+            "conversion produced {} warning diagnostic(s) and --deny-warnings is set",
+            report.summary.warnings
+        );
+    }
 
     Ok(())
 }
 
-fn extract_operation_ids(doc: &Value) -> Vec<String> {
+// `sunsets` is keyed by operationId or by the mapped command name; `renames` maps old command
+// names to the ones clients should use now.
+fn convert(
+    doc: &Value,
+    profile_name: Option<&str>,
+    sunsets: &BTreeMap<String, NaiveDate>,
+    renames: BTreeMap<String, String>,
+    channel_style: ChannelStyle,
+) -> Result<Conversion> {
+    let mut mappings = Vec::new();
+    let mut diagnostics = Vec::new();
+    let mut commands = BTreeSet::new();
+    let mut deprecations = BTreeMap::new();
+    let mut seen_operation_ids = BTreeSet::new();
+    let mut used_sunsets = BTreeSet::new();
+    let mut results = ResultSchemas::default();
+
+    for operation in extract_operations(doc) {
+        let Some(operation_id) = operation.operation_id.clone() else {
+            diagnostics.push(Diagnostic {
+                code: diagnostics::MISSING_OPERATION_ID,
+                severity: Severity::Warning,
+                message: "operation has no operationId and was dropped from the contract"
+                    .to_string(),
+                location: operation.location(),
+                suggestion: Some(
+                    "add an operationId such as CreateFoo, ListFoo or RetrieveFoo".to_string(),
+                ),
+            });
+            continue;
+        };
+
+        if !seen_operation_ids.insert(operation_id.clone()) {
+            diagnostics.push(Diagnostic {
+                code: diagnostics::DUPLICATE_OPERATION_ID,
+                severity: Severity::Warning,
+                message: format!("operationId {operation_id} is declared more than once"),
+                location: operation.location(),
+                suggestion: Some("give each operation a unique operationId".to_string()),
+            });
+            continue;
+        }
+
+        match map_operation_id(&operation_id) {
+            Some(mapped) => {
+                let sunset_key = [&operation_id, &mapped]
+                    .into_iter()
+                    .find(|key| sunsets.contains_key(key.as_str()));
+                let sunset = sunset_key.and_then(|key| sunsets.get(key.as_str()).copied());
+                used_sunsets.extend(sunset_key.cloned());
+                let deprecated = operation.deprecated || sunset.is_some();
+                if deprecated {
+                    if sunset.is_none() {
+                        diagnostics.push(Diagnostic {
+                            code: diagnostics::DEPRECATED_WITHOUT_SUNSET,
+                            severity: Severity::Info,
+                            message: format!("{mapped} is deprecated but has no sunset date"),
+                            location: operation.location(),
+                            suggestion: Some(format!("pass --sunset {mapped}=YYYY-MM-DD")),
+                        });
+                    }
+                    deprecations.insert(mapped.clone(), sunset);
+                }
+                if let Some(schema) = &operation.result {
+                    let name = format!("{}Result", snake_to_pascal(&mapped));
+                    copy_referenced_schemas(doc, schema, &mut results.schemas)?;
+                    results.schemas.insert(name.clone(), schema.clone());
+                    results.operations.insert(mapped.clone(), name);
+                }
+                commands.insert(mapped.clone());
+                mappings.push(MappingRow {
+                    operation_id,
+                    channel: channel_style.command_address(&mapped),
+                    command_operation: mapped,
+                    deprecated,
+                });
+            }
+            None => diagnostics.push(Diagnostic {
+                code: diagnostics::UNMAPPABLE_OPERATION_ID,
+                severity: Severity::Warning,
+                message: "unable to infer action/entity from operationId; operation dropped"
+                    .to_string(),
+                suggestion: Some(suggest_operation_id(&operation_id)),
+                location: operation.location(),
+            }),
+        }
+    }
+
+    for operation in sunsets.keys().filter(|key| !used_sunsets.contains(*key)) {
+        diagnostics.push(Diagnostic {
+            code: diagnostics::UNKNOWN_SUNSET_OPERATION,
+            severity: Severity::Warning,
+            message: format!("--sunset names {operation}, which is not in the contract"),
+            location: SourceLocation {
+                operation_id: Some(operation.clone()),
+                ..SourceLocation::default()
+            },
+            suggestion: None,
+        });
+    }
+
+    check_renames(&renames, &commands, &mut diagnostics);
+
+    if let Some(profile_name) = profile_name {
+        apply_profile(profile_name, &mappings, &mut diagnostics)?;
+    }
+
+    check_schema_refs(doc, &mut diagnostics)?;
+
+    Ok(Conversion {
+        mappings,
+        diagnostics,
+        commands,
+        deprecations,
+        aliases: renames,
+        results,
+    })
+}
+
+// Every rename chain has to end at a command in the contract; the runtime refuses cycles.
+fn check_renames(
+    renames: &BTreeMap<String, String>,
+    commands: &BTreeSet<String>,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    for old in renames.keys() {
+        let location = SourceLocation {
+            operation_id: Some(old.clone()),
+            ..SourceLocation::default()
+        };
+        let mut chain = vec![old.as_str()];
+        let mut current = old.as_str();
+        while let Some(next) = renames.get(current) {
+            if chain.contains(&next.as_str()) {
+                chain.push(next);
+                break;
+            }
+            chain.push(next);
+            current = next;
+        }
+        if commands.contains(old) {
+            diagnostics.push(Diagnostic {
+                code: diagnostics::RENAMED_COMMAND_DECLARED,
+                severity: Severity::Error,
+                message: format!("{old} is renamed but the source still declares it"),
+                location,
+                suggestion: Some(format!("drop the rename of {old} or the operation itself")),
+            });
+        } else if renames.contains_key(current) {
+            diagnostics.push(Diagnostic {
+                code: diagnostics::RENAME_CYCLE,
+                severity: Severity::Error,
+                message: format!("renames form a cycle: {}", chain.join(" -> ")),
+                location,
+                suggestion: Some("drop one of the renames in the cycle".to_string()),
+            });
+        } else if !commands.contains(current) {
+            diagnostics.push(Diagnostic {
+                code: diagnostics::UNKNOWN_RENAME_TARGET,
+                severity: Severity::Warning,
+                message: format!("{old} is renamed to {current}, which is not in the contract"),
+                location,
+                suggestion: None,
+            });
+        }
+    }
+}
+
+// The runtime refuses a contract whose delivery policy retries a non-idempotent operation.
+fn check_delivery(
+    delivery: &BTreeMap<String, DeliveryPolicy>,
+    commands: &BTreeSet<String>,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    for (operation, policy) in delivery {
+        let location = SourceLocation {
+            operation_id: Some(operation.clone()),
+            ..SourceLocation::default()
+        };
+        let invalid = match (policy.max_attempts, policy.idempotent) {
+            (Some(0), _) => Some("max_attempts must be at least 1"),
+            (Some(attempts), Some(false)) if attempts > 1 => {
+                Some("a non-idempotent operation cannot be retried")
+            }
+            _ => None,
+        };
+        if let Some(reason) = invalid {
+            diagnostics.push(Diagnostic {
+                code: diagnostics::INVALID_DELIVERY_POLICY,
+                severity: Severity::Error,
+                message: format!("delivery policy for {operation}: {reason}"),
+                location,
+                suggestion: Some("set max_attempts to 1 or mark it idempotent".to_string()),
+            });
+        } else if !commands.contains(operation) {
+            diagnostics.push(Diagnostic {
+                code: diagnostics::UNKNOWN_DELIVERY_OPERATION,
+                severity: Severity::Warning,
+                message: format!("delivery policy names {operation}, which is not in the contract"),
+                location,
+                suggestion: None,
+            });
+        }
+    }
+}
+
+fn extract_operations(doc: &Value) -> Vec<SourceOperation> {
     let mut out = Vec::new();
     let Some(paths) = doc.get("paths").and_then(Value::as_mapping) else {
         return out;
     };
 
-    for path_item in paths.values() {
+    for (path, path_item) in paths {
         let Some(methods) = path_item.as_mapping() else {
             continue;
         };
+        let path = path.as_str().unwrap_or_default().to_string();
 
         for method in ["get", "put", "post", "delete", "patch", "head", "options", "trace"] {
-            let Some(op) = methods.get(&Value::from(method)) else {
+            let Some(op) = methods.get(Value::from(method)) else {
                 continue;
             };
 
-            if let Some(operation_id) = op
-                .as_mapping()
-                .and_then(|mapping| mapping.get(&Value::from("operationId")))
+            let field = |name: &str| op.as_mapping().and_then(|op| op.get(Value::from(name)));
+            let operation_id = field("operationId")
                 .and_then(Value::as_str)
-            {
-                out.push(operation_id.to_string());
-            }
+                .map(str::to_string);
+            out.push(SourceOperation {
+                path: path.clone(),
+                method: method.to_string(),
+                operation_id,
+                deprecated: field("deprecated").and_then(Value::as_bool).unwrap_or(false),
+                result: field("responses").and_then(success_schema),
+            });
         }
     }
 
-    out.sort();
-    out.dedup();
+    out.sort_by(|a, b| {
+        a.operation_id
+            .cmp(&b.operation_id)
+            .then_with(|| a.path.cmp(&b.path))
+            .then_with(|| a.method.cmp(&b.method))
+    });
     out
 }
 
+// Success responses in status order; `200:` is an integer key unless it is quoted. JSON bodies
+// are preferred over any other media type.
+fn success_schema(responses: &Value) -> Option<serde_json::Value> {
+    let mut success: Vec<(String, &Value)> = responses
+        .as_mapping()?
+        .iter()
+        .filter_map(|(status, response)| {
+            let status = match status {
+                Value::Number(number) => number.to_string(),
+                other => other.as_str()?.to_string(),
+            };
+            status.starts_with('2').then_some((status, response))
+        })
+        .collect();
+    success.sort_by(|a, b| a.0.cmp(&b.0));
+    success.into_iter().find_map(|(_, response)| {
+        let content = response.get("content")?.as_mapping()?;
+        let body = content
+            .get(Value::from("application/json"))
+            .or_else(|| content.values().next())?;
+        serde_json::to_value(body.get("schema")?).ok()
+    })
+}
+
+// Copies the source component schemas `schema` refers to, and the ones those refer to, under
+// their own names; refs that do not resolve were already reported by `check_schema_refs`.
+fn copy_referenced_schemas(
+    doc: &Value,
+    schema: &serde_json::Value,
+    into: &mut serde_json::Map<String, serde_json::Value>,
+) -> Result<()> {
+    let source = doc
+        .get("components")
+        .and_then(|components| components.get("schemas"))
+        .map(serde_json::to_value)
+        .transpose()
+        .context("convert OpenAPI component schemas")?
+        .unwrap_or_default();
+    let mut pending = vec![schema.clone()];
+    while let Some(next) = pending.pop() {
+        let mut refs = Vec::new();
+        collect_refs(&next, String::new(), &mut refs);
+        for (_, reference) in refs {
+            let Some(name) = reference.strip_prefix(SCHEMA_REF_PREFIX) else {
+                continue;
+            };
+            if into.contains_key(name) {
+                continue;
+            }
+            if let Some(target) = source.get(name) {
+                into.insert(name.to_string(), target.clone());
+                pending.push(target.clone());
+            }
+        }
+    }
+    Ok(())
+}
+
+fn suggest_operation_id(operation_id: &str) -> String {
+    let entity = operation_id
+        .char_indices()
+        .skip(1)
+        .find(|(_, ch)| ch.is_uppercase())
+        .map(|(idx, _)| &operation_id[idx..])
+        .unwrap_or_default();
+
+    if entity.is_empty() {
+        "prefix the operationId with one of Create, List, Put, Retrieve, Delete or Stream"
+            .to_string()
+    } else {
+        format!(
+            "rename to one of Create{entity}, List{entity}, Put{entity}, Retrieve{entity}, Delete{entity} or Stream{entity}"
+        )
+    }
+}
+
+fn check_schema_refs(doc: &Value, diagnostics: &mut Vec<Diagnostic>) -> Result<()> {
+    let root = serde_json::to_value(doc).context("convert OpenAPI document for ref checks")?;
+    let mut refs = Vec::new();
+    collect_refs(&root, String::new(), &mut refs);
+
+    for (pointer, reference) in refs {
+        let location = SourceLocation {
+            pointer: Some(pointer),
+            ..SourceLocation::default()
+        };
+        match reference.strip_prefix('#') {
+            Some(target) => {
+                if root.pointer(target).is_none() {
+                    diagnostics.push(Diagnostic {
+                        code: diagnostics::UNRESOLVABLE_SCHEMA_REF,
+                        severity: Severity::Error,
+                        message: format!("$ref {reference} does not resolve within the document"),
+                        location,
+                        suggestion: None,
+                    });
+                }
+            }
+            None => diagnostics.push(Diagnostic {
+                code: diagnostics::EXTERNAL_SCHEMA_REF,
+                severity: Severity::Info,
+                message: format!("external $ref {reference} was not followed"),
+                location,
+                suggestion: Some("inline or bundle external schemas before converting".to_string()),
+            }),
+        }
+    }
+
+    Ok(())
+}
+
+fn collect_refs(value: &serde_json::Value, pointer: String, out: &mut Vec<(String, String)>) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, item) in map {
+                let child = format!("{pointer}/{}", escape_pointer_segment(key));
+                match (key.as_str(), item) {
+                    ("$ref", serde_json::Value::String(reference)) => {
+                        out.push((child, reference.clone()))
+                    }
+                    _ => collect_refs(item, child, out),
+                }
+            }
+        }
+        serde_json::Value::Array(items) => {
+            for (idx, item) in items.iter().enumerate() {
+                collect_refs(item, format!("{pointer}/{idx}"), out);
+            }
+        }
+        _ => {}
+    }
+}
+
 fn map_operation_id(operation_id: &str) -> Option<String> {
     const PREFIXES: [(&str, &str); 6] = [
         ("Create", "create"),
@@ -214,7 +833,162 @@ fn derive_events(commands: &BTreeSet<String>) -> Vec<String> {
     events.into_iter().collect()
 }
 
-fn render_asyncapi(commands: &[String], events: &[String]) -> Result<String> {
+fn render_asyncapi(
+    commands: &[String],
+    events: &[String],
+    deprecations: &BTreeMap<String, Option<NaiveDate>>,
+    aliases: &BTreeMap<String, String>,
+    delivery: &BTreeMap<String, DeliveryPolicy>,
+    results: &ResultSchemas,
+    channel_style: ChannelStyle,
+) -> Result<String> {
+    let (channels, operations, messages, payloads) = match channel_style {
+        ChannelStyle::Generic => generic_channels()?,
+        ChannelStyle::PerEntity => per_entity_channels(commands, events)?,
+    };
+
+    let mut schemas = serde_json::json!({
+        "MeshCommandEnvelope": {
+            "type": "object",
+            "required": ["message_id", "operation", "sent_at", "source_identity", "destination_identity", "content_type", "payload"],
+            "properties": {
+                "message_id": {"type": "string"},
+                "operation": {"type": "string"},
+                "sent_at": {"type": "string", "format": "date-time"},
+                "source_identity": {"type": "string"},
+                "destination_identity": {"type": "string"},
+                "content_type": {"type": "string", "const": "application/msgpack"},
+                "payload": {"type": "object"},
+                "meta": {"$ref": "#/components/schemas/EnvelopeMeta"}
+            }
+        },
+        "EnvelopeMeta": envelope_meta_schema(),
+        "MeshResultEnvelope": {
+            "type": "object",
+            "required": ["message_id", "correlation_id", "operation", "sent_at", "source_identity", "destination_identity", "content_type", "payload"],
+            "properties": {
+                "message_id": {"type": "string"},
+                "correlation_id": {"type": "string"},
+                "operation": {"type": "string"},
+                "sent_at": {"type": "string", "format": "date-time"},
+                "source_identity": {"type": "string"},
+                "destination_identity": {"type": "string"},
+                "content_type": {"type": "string", "const": "application/msgpack"},
+                "payload": {"type": "object"}
+            }
+        },
+        "MeshEventEnvelope": {
+            "type": "object",
+            "required": ["message_id", "event", "sent_at", "source_identity", "destination_identity", "content_type", "payload"],
+            "properties": {
+                "message_id": {"type": "string"},
+                "event": {"type": "string"},
+                "sent_at": {"type": "string", "format": "date-time"},
+                "source_identity": {"type": "string"},
+                "destination_identity": {"type": "string"},
+                "content_type": {"type": "string", "const": "application/msgpack"},
+                "payload": {"type": "object"}
+            }
+        }
+    });
+    if let Some(schemas) = schemas.as_object_mut() {
+        schemas.extend(payloads);
+        // A source schema never replaces one the layout needs.
+        for (name, schema) in &results.schemas {
+            schemas.entry(name.clone()).or_insert_with(|| schema.clone());
+        }
+    }
+    let components = serde_yaml::to_value(serde_json::json!({
+        "messages": messages,
+        "schemas": schemas
+    }))?;
+
+    let channel_addresses = (!channel_style.is_generic()).then(|| ChannelAddresses {
+        commands: commands
+            .iter()
+            .map(|command| (command.clone(), channel_style.command_address(command)))
+            .collect(),
+        events: events
+            .iter()
+            .map(|event| (event.clone(), channel_style.event_address(event)))
+            .collect(),
+    });
+
+    let doc = ConverterOutput {
+        asyncapi: "3.0.0".to_string(),
+        info: AsyncApiInfo {
+            title: "Reticulum AsyncAPI Contract".to_string(),
+            version: "1.0.0".to_string(),
+            description: "Generated from OpenAPI source using retasync-convert".to_string(),
+        },
+        default_content_type: "application/msgpack".to_string(),
+        channels,
+        operations,
+        components: components
+            .as_mapping()
+            .cloned()
+            .context("internal error building components mapping")?,
+        retasync: RetasyncExtension {
+            operations: RetasyncOperations {
+                commands: commands.to_vec(),
+                events: events.to_vec(),
+                deprecated: deprecations
+                    .iter()
+                    .map(|(command, sunset)| {
+                        let entry = DeprecatedOperation {
+                            deprecated: true,
+                            sunset: sunset.map(|date| date.to_string()),
+                        };
+                        (command.clone(), entry)
+                    })
+                    .collect(),
+                aliases: aliases.clone(),
+                delivery: delivery.clone(),
+                results: results.operations.clone(),
+            },
+            channel_style,
+            channels: channel_addresses,
+        },
+    };
+
+    serde_yaml::to_string(&doc).context("serialize AsyncAPI YAML")
+}
+
+// The reserved metadata block every generated contract carries on its command envelope. The
+// limits are the ones `retasync_contract::EnvelopeMeta::validate` enforces.
+fn envelope_meta_schema() -> serde_json::Value {
+    serde_json::json!({
+        "type": "object",
+        "properties": {
+            "priority": {"type": "string", "enum": ["routine", "priority", "immediate", "flash"]},
+            "trace_id": {"type": "string", "minLength": 1, "maxLength": 128},
+            "client_id": {"type": "string", "minLength": 1, "maxLength": 128},
+            "client_version": {"type": "string", "minLength": 1, "maxLength": 128},
+            "if_result_digest_differs": {"type": "string", "pattern": "^[0-9a-f]{64}$"},
+            "custom": {
+                "type": "object",
+                "maxProperties": 16,
+                "propertyNames": {"pattern": "^[a-z0-9_-]{1,64}$"},
+                "additionalProperties": {
+                    "type": "string",
+                    "minLength": 1,
+                    "maxLength": 256
+                }
+            }
+        }
+    })
+}
+
+// Channels, operations, messages and extra schemas for one channel style.
+type ChannelLayout = (
+    serde_yaml::Mapping,
+    serde_yaml::Mapping,
+    serde_json::Value,
+    serde_json::Map<String, serde_json::Value>,
+);
+
+// Every command, result and event multiplexed through one channel each.
+fn generic_channels() -> Result<ChannelLayout> {
     let mut channels = serde_yaml::Mapping::new();
     channels.insert(
         Value::from("commandChannel"),
@@ -279,94 +1053,196 @@ fn render_asyncapi(commands: &[String], events: &[String]) -> Result<String> {
         }))?,
     );
 
-    let components = serde_yaml::to_value(serde_json::json!({
-        "messages": {
-            "MeshCommand": {
-                "name": "MeshCommand",
-                "contentType": "application/msgpack",
-                "payload": { "$ref": "#/components/schemas/MeshCommandEnvelope" }
-            },
-            "MeshResult": {
-                "name": "MeshResult",
-                "contentType": "application/msgpack",
-                "payload": { "$ref": "#/components/schemas/MeshResultEnvelope" }
-            },
-            "MeshEvent": {
-                "name": "MeshEvent",
-                "contentType": "application/msgpack",
-                "payload": { "$ref": "#/components/schemas/MeshEventEnvelope" }
-            }
+    let messages = serde_json::json!({
+        "MeshCommand": {
+            "name": "MeshCommand",
+            "contentType": "application/msgpack",
+            "payload": { "$ref": "#/components/schemas/MeshCommandEnvelope" }
         },
-        "schemas": {
-            "MeshCommandEnvelope": {
-                "type": "object",
-                "required": ["message_id", "operation", "sent_at", "source_identity", "destination_identity", "content_type", "payload"],
-                "properties": {
-                    "message_id": {"type": "string"},
-                    "operation": {"type": "string"},
-                    "sent_at": {"type": "string", "format": "date-time"},
-                    "source_identity": {"type": "string"},
-                    "destination_identity": {"type": "string"},
-                    "content_type": {"type": "string", "const": "application/msgpack"},
-                    "payload": {"type": "object"}
-                }
-            },
-            "MeshResultEnvelope": {
-                "type": "object",
-                "required": ["message_id", "correlation_id", "operation", "sent_at", "source_identity", "destination_identity", "content_type", "payload"],
-                "properties": {
-                    "message_id": {"type": "string"},
-                    "correlation_id": {"type": "string"},
-                    "operation": {"type": "string"},
-                    "sent_at": {"type": "string", "format": "date-time"},
-                    "source_identity": {"type": "string"},
-                    "destination_identity": {"type": "string"},
-                    "content_type": {"type": "string", "const": "application/msgpack"},
-                    "payload": {"type": "object"}
-                }
-            },
-            "MeshEventEnvelope": {
+        "MeshResult": {
+            "name": "MeshResult",
+            "contentType": "application/msgpack",
+            "payload": { "$ref": "#/components/schemas/MeshResultEnvelope" }
+        },
+        "MeshEvent": {
+            "name": "MeshEvent",
+            "contentType": "application/msgpack",
+            "payload": { "$ref": "#/components/schemas/MeshEventEnvelope" }
+        }
+    });
+
+    Ok((channels, operations, messages, serde_json::Map::new()))
+}
+
+struct EntityChannel {
+    suffix: &'static str,
+    segment: &'static str,
+    action: &'static str,
+    description: &'static str,
+    // The envelope field naming the operation or event.
+    name_field: &'static str,
+    envelope: &'static str,
+}
+
+const COMMAND_CHANNEL: EntityChannel = EntityChannel {
+    suffix: "Command",
+    segment: "commands",
+    action: "send",
+    description: "Mesh command submissions",
+    name_field: "operation",
+    envelope: "MeshCommandEnvelope",
+};
+const RESULT_CHANNEL: EntityChannel = EntityChannel {
+    suffix: "Result",
+    segment: "results",
+    action: "receive",
+    description: "Mesh command results",
+    name_field: "operation",
+    envelope: "MeshResultEnvelope",
+};
+const EVENT_CHANNEL: EntityChannel = EntityChannel {
+    suffix: "Event",
+    segment: "events",
+    action: "receive",
+    description: "Mesh event publications",
+    name_field: "event",
+    envelope: "MeshEventEnvelope",
+};
+
+// A command, result and event channel per entity, with messages narrowed to that entity's
+// operations. Command payloads reference `<Operation>Payload` schemas, the names codegen gives
+// the typed per-operation payloads.
+fn per_entity_channels(commands: &[String], events: &[String]) -> Result<ChannelLayout> {
+    let mut entities: BTreeMap<&str, (Vec<&str>, Vec<&str>)> = BTreeMap::new();
+    for command in commands {
+        entities
+            .entry(entity_of(command))
+            .or_default()
+            .0
+            .push(command);
+    }
+    for event in events {
+        entities.entry(entity_of(event)).or_default().1.push(event);
+    }
+
+    let mut channels = serde_yaml::Mapping::new();
+    let mut operations = serde_yaml::Mapping::new();
+    let mut messages = serde_json::Map::new();
+    let mut payloads = serde_json::Map::new();
+    for (entity, (entity_commands, entity_events)) in entities {
+        let pascal = snake_to_pascal(entity);
+        let camel = format!("{}{}", pascal[..1].to_ascii_lowercase(), &pascal[1..]);
+        let mut kinds = Vec::new();
+        if !entity_commands.is_empty() {
+            let payload_refs: Vec<_> = entity_commands
+                .iter()
+                .map(|command| {
+                    let name = format!("{}Payload", snake_to_pascal(command));
+                    let schema = serde_json::json!({
+                        "type": "object",
+                        "description": format!("Payload of {command}")
+                    });
+                    payloads.insert(name.clone(), schema);
+                    serde_json::json!({ "$ref": format!("#/components/schemas/{name}") })
+                })
+                .collect();
+            let payload = serde_json::json!({ "oneOf": payload_refs });
+            kinds.push((&COMMAND_CHANNEL, entity_commands.clone(), payload));
+            let payload = serde_json::json!({ "type": "object" });
+            kinds.push((&RESULT_CHANNEL, entity_commands, payload));
+        }
+        if !entity_events.is_empty() {
+            let payload = serde_json::json!({ "type": "object" });
+            kinds.push((&EVENT_CHANNEL, entity_events, payload));
+        }
+
+        for (kind, names, payload) in kinds {
+            let channel = format!("{camel}{}s", kind.suffix);
+            let message = format!("{pascal}{}", kind.suffix);
+            let message_key = format!("{camel}{}", kind.suffix);
+            channels.insert(
+                Value::from(channel.as_str()),
+                serde_yaml::to_value(serde_json::json!({
+                    "address": format!("{entity}/{}", kind.segment),
+                    "description": format!("{} for {entity}", kind.description),
+                    "messages": {
+                        message_key.as_str(): {
+                            "$ref": format!("#/components/messages/{message}")
+                        }
+                    }
+                }))?,
+            );
+            operations.insert(
+                Value::from(format!("{}{pascal}{}", kind.action, kind.suffix)),
+                serde_yaml::to_value(serde_json::json!({
+                    "action": kind.action,
+                    "channel": { "$ref": format!("#/channels/{channel}") },
+                    "messages": [{
+                        "$ref": format!("#/channels/{channel}/messages/{message_key}")
+                    }]
+                }))?,
+            );
+            let narrowed = serde_json::json!({
                 "type": "object",
-                "required": ["message_id", "event", "sent_at", "source_identity", "destination_identity", "content_type", "payload"],
                 "properties": {
-                    "message_id": {"type": "string"},
-                    "event": {"type": "string"},
-                    "sent_at": {"type": "string", "format": "date-time"},
-                    "source_identity": {"type": "string"},
-                    "destination_identity": {"type": "string"},
-                    "content_type": {"type": "string", "const": "application/msgpack"},
-                    "payload": {"type": "object"}
+                    kind.name_field: { "type": "string", "enum": names },
+                    "payload": payload
                 }
-            }
+            });
+            messages.insert(
+                message.clone(),
+                serde_json::json!({
+                    "name": message,
+                    "contentType": "application/msgpack",
+                    "payload": {
+                        "allOf": [
+                            { "$ref": format!("#/components/schemas/{}", kind.envelope) },
+                            narrowed
+                        ]
+                    }
+                }),
+            );
         }
-    }))?;
+    }
 
-    let doc = ConverterOutput {
-        asyncapi: "3.0.0".to_string(),
-        info: AsyncApiInfo {
-            title: "Reticulum AsyncAPI Contract".to_string(),
-            version: "1.0.0".to_string(),
-            description: "Generated from OpenAPI source using retasync-convert".to_string(),
-        },
-        default_content_type: "application/msgpack".to_string(),
-        channels,
-        operations,
-        components: components
-            .as_mapping()
-            .cloned()
-            .context("internal error building components mapping")?,
-        retasync: RetasyncExtension {
-            operations: RetasyncOperations {
-                commands: commands.to_vec(),
-                events: events.to_vec(),
-            },
-        },
-    };
+    Ok((channels, operations, messages.into(), payloads))
+}
 
-    serde_yaml::to_string(&doc).context("serialize AsyncAPI YAML")
+fn parse_sunset(raw: &str) -> Result<(String, NaiveDate), String> {
+    let (operation, date) = raw
+        .split_once('=')
+        .ok_or_else(|| format!("expected OP=YYYY-MM-DD, got {raw}"))?;
+    let date = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map_err(|err| format!("invalid sunset date {date}: {err}"))?;
+    Ok((operation.to_string(), date))
 }
 
-fn detect_profile_from_path(path: &PathBuf) -> Option<&'static str> {
+fn parse_rename(raw: &str) -> Result<(String, String), String> {
+    match raw.split_once('=') {
+        Some((old, new)) if !old.is_empty() && !new.is_empty() => {
+            Ok((old.to_string(), new.to_string()))
+        }
+        _ => Err(format!("expected OLD=NEW, got {raw}")),
+    }
+}
+
+// A YAML mapping of old command names to new ones.
+fn read_renames(path: &Path) -> Result<BTreeMap<String, String>> {
+    let source = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    serde_yaml::from_str(&source).with_context(|| format!("invalid renames in {}", path.display()))
+}
+
+// A YAML mapping of command names to `max_attempts`, `backoff_ms`, `timeout_ms`, `idempotent`,
+// `store_and_forward`.
+fn read_delivery(path: &Path) -> Result<BTreeMap<String, DeliveryPolicy>> {
+    let source = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    serde_yaml::from_str(&source)
+        .with_context(|| format!("invalid delivery profile in {}", path.display()))
+}
+
+fn detect_profile_from_path(path: &Path) -> Option<&'static str> {
     let candidate = path.file_name()?.to_str()?;
     if candidate.contains("EmergencyActionMessageManagement-OAS") {
         Some("emergency-management")
@@ -378,7 +1254,7 @@ fn detect_profile_from_path(path: &PathBuf) -> Option<&'static str> {
 fn apply_profile(
     profile_name: &str,
     mappings: &[MappingRow],
-    warnings: &mut Vec<WarningRow>,
+    diagnostics: &mut Vec<Diagnostic>,
 ) -> Result<()> {
     if profile_name != "emergency-management" {
         anyhow::bail!(
@@ -401,32 +1277,423 @@ fn apply_profile(
         "StreamNotifications",
     ];
 
-    let present: BTreeSet<&str> = mappings.iter().map(|row| row.operation_id.as_str()).collect();
+    let present: BTreeSet<&str> = mappings
+        .iter()
+        .map(|row| row.operation_id.as_str())
+        .collect();
     for operation in expected {
         if !present.contains(operation) {
-            warnings.push(WarningRow {
-                operation_id: operation.to_string(),
-                reason: "missing operation required by emergency-management profile".to_string(),
+            diagnostics.push(Diagnostic {
+                code: diagnostics::MISSING_PROFILE_OPERATION,
+                severity: Severity::Warning,
+                message: "missing operation required by emergency-management profile".to_string(),
+                location: SourceLocation {
+                    operation_id: Some(operation.to_string()),
+                    ..SourceLocation::default()
+                },
+                suggestion: Some(format!("add an operation with operationId {operation}")),
             });
         }
     }
 
     Ok(())
 }
+// The part of `emergency_action_message.create` before the action.
+fn entity_of(operation: &str) -> &str {
+    operation
+        .rsplit_once('.')
+        .map_or(operation, |(entity, _)| entity)
+}
 
-fn pascal_to_snake(input: &str) -> String {
-    let mut out = String::new();
-    for (idx, ch) in input.chars().enumerate() {
-        if ch.is_uppercase() {
-            if idx > 0 {
-                out.push('_');
-            }
-            for low in ch.to_lowercase() {
-                out.push(low);
+// Matches the names codegen gives operations, so `<Operation>Payload` lines up with its types.
+fn snake_to_pascal(input: &str) -> String {
+    input
+        .split(['.', '_'])
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| {
+            let mut chars = segment.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect::<String>(),
+                None => String::new(),
             }
-        } else {
-            out.push(ch);
-        }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        check_delivery, convert, derive_events, diagnostics, parse_rename, parse_sunset,
+        render_asyncapi, typescript, ChannelStyle, DeliveryPolicy, Severity,
+    };
+    use std::collections::BTreeMap;
+
+    fn codes(source: &str, profile: Option<&str>) -> Vec<(&'static str, Severity)> {
+        let doc = serde_yaml::from_str(source).expect("yaml");
+        convert(
+            &doc,
+            profile,
+            &BTreeMap::new(),
+            BTreeMap::new(),
+            ChannelStyle::Generic,
+        )
+        .expect("convert")
+        .diagnostics
+        .into_iter()
+        .map(|diagnostic| (diagnostic.code, diagnostic.severity))
+        .collect()
+    }
+
+    #[test]
+    fn flags_unmappable_and_missing_operation_ids() {
+        let found = codes(
+            r#"
+paths:
+  /events/search:
+    get:
+      operationId: SearchEvent
+  /events/bulk:
+    post:
+      summary: no id
+"#,
+            None,
+        );
+        assert!(found.contains(&(diagnostics::UNMAPPABLE_OPERATION_ID, Severity::Warning)));
+        assert!(found.contains(&(diagnostics::MISSING_OPERATION_ID, Severity::Warning)));
+    }
+
+    #[test]
+    fn flags_missing_profile_operations() {
+        let found = codes(
+            r#"
+paths:
+  /events:
+    post:
+      operationId: CreateEvent
+"#,
+            Some("emergency-management"),
+        );
+        assert!(found.contains(&(diagnostics::MISSING_PROFILE_OPERATION, Severity::Warning)));
+    }
+
+    #[test]
+    fn flags_unresolvable_refs_as_errors() {
+        let found = codes(
+            r##"
+paths:
+  /events:
+    post:
+      operationId: CreateEvent
+      requestBody:
+        content:
+          application/json:
+            schema:
+              $ref: "#/components/schemas/Missing"
+components:
+  schemas: {}
+"##,
+            None,
+        );
+        assert_eq!(
+            found,
+            vec![(diagnostics::UNRESOLVABLE_SCHEMA_REF, Severity::Error)]
+        );
+    }
+
+    #[test]
+    fn deprecated_operations_carry_sunset_extension() {
+        let doc = serde_yaml::from_str(
+            r#"
+paths:
+  /events/stream:
+    get:
+      operationId: StreamEvent
+      deprecated: true
+  /notifications/stream:
+    get:
+      operationId: StreamNotifications
+      deprecated: true
+  /events:
+    post:
+      operationId: CreateEvent
+"#,
+        )
+        .expect("yaml");
+        let sunsets = BTreeMap::from([
+            parse_sunset("event.stream=2026-09-01").unwrap(),
+            parse_sunset("RetrieveMissing=2026-01-01").unwrap(),
+        ]);
+        assert!(parse_sunset("event.stream").is_err());
+
+        let conversion =
+            convert(&doc, None, &sunsets, BTreeMap::new(), ChannelStyle::Generic).expect("convert");
+        let found: Vec<_> = conversion
+            .diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.code)
+            .collect();
+        assert!(found.contains(&diagnostics::DEPRECATED_WITHOUT_SUNSET));
+        assert!(found.contains(&diagnostics::UNKNOWN_SUNSET_OPERATION));
+
+        let commands: Vec<String> = conversion.commands.iter().cloned().collect();
+        let rendered = render_asyncapi(
+            &commands,
+            &derive_events(&conversion.commands),
+            &conversion.deprecations,
+            &conversion.aliases,
+            &BTreeMap::new(),
+            &conversion.results,
+            ChannelStyle::Generic,
+        )
+        .unwrap();
+        let contract: serde_yaml::Value = serde_yaml::from_str(&rendered).unwrap();
+        let deprecated = &contract["x-retasync"]["operations"]["deprecated"];
+        assert_eq!(deprecated["event.stream"]["deprecated"], true);
+        assert_eq!(
+            deprecated["event.stream"]["x-retasync-sunset"],
+            "2026-09-01"
+        );
+        assert_eq!(deprecated["notifications.stream"]["deprecated"], true);
+        assert!(deprecated["notifications.stream"]
+            .get("x-retasync-sunset")
+            .is_none());
+        assert!(deprecated.get("event.create").is_none());
+    }
+
+    #[test]
+    fn renames_become_aliases() {
+        let doc = serde_yaml::from_str(
+            r#"
+paths:
+  /events/{uid}:
+    put:
+      operationId: PutEvent
+"#,
+        )
+        .expect("yaml");
+        let renames = BTreeMap::from([
+            parse_rename("event.modify=event.put").unwrap(),
+            parse_rename("event.gone=event.missing").unwrap(),
+        ]);
+        assert!(parse_rename("event.modify").is_err());
+
+        let conversion =
+            convert(&doc, None, &BTreeMap::new(), renames, ChannelStyle::Generic).expect("convert");
+        let found: Vec<_> = conversion
+            .diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.code)
+            .collect();
+        assert_eq!(found, [diagnostics::UNKNOWN_RENAME_TARGET]);
+
+        let commands: Vec<String> = conversion.commands.iter().cloned().collect();
+        let rendered = render_asyncapi(
+            &commands,
+            &derive_events(&conversion.commands),
+            &conversion.deprecations,
+            &conversion.aliases,
+            &BTreeMap::new(),
+            &conversion.results,
+            ChannelStyle::Generic,
+        )
+        .unwrap();
+        let contract: serde_yaml::Value = serde_yaml::from_str(&rendered).unwrap();
+        let aliases = &contract["x-retasync"]["operations"]["x-retasync-aliases"];
+        assert_eq!(aliases["event.modify"], "event.put");
+
+        let shadowing = BTreeMap::from([parse_rename("event.put=event.update").unwrap()]);
+        let conversion = convert(
+            &doc,
+            None,
+            &BTreeMap::new(),
+            shadowing,
+            ChannelStyle::Generic,
+        )
+        .expect("convert");
+        assert_eq!(
+            conversion.diagnostics[0].code,
+            diagnostics::RENAMED_COMMAND_DECLARED
+        );
+
+        let cyclic = BTreeMap::from([
+            parse_rename("event.a=event.b").unwrap(),
+            parse_rename("event.b=event.a").unwrap(),
+        ]);
+        let conversion =
+            convert(&doc, None, &BTreeMap::new(), cyclic, ChannelStyle::Generic).expect("convert");
+        assert!(conversion
+            .diagnostics
+            .iter()
+            .all(|diagnostic| diagnostic.code == diagnostics::RENAME_CYCLE));
+    }
+
+    #[test]
+    fn delivery_profile_is_checked_and_rendered() {
+        let doc = serde_yaml::from_str(
+            r#"
+paths:
+  /events:
+    post:
+      operationId: CreateEvent
+  /events/{uid}:
+    delete:
+      operationId: DeleteEvent
+"#,
+        )
+        .expect("yaml");
+        let delivery: BTreeMap<String, DeliveryPolicy> = serde_yaml::from_str(
+            "event.create: { max_attempts: 4, backoff_ms: 500 }\n\
+             event.delete: { max_attempts: 3, idempotent: false }\n\
+             event.missing: { timeout_ms: 1000 }\n",
+        )
+        .unwrap();
+        let conversion = convert(
+            &doc,
+            None,
+            &BTreeMap::new(),
+            BTreeMap::new(),
+            ChannelStyle::Generic,
+        )
+        .expect("convert");
+        let mut found = Vec::new();
+        check_delivery(&delivery, &conversion.commands, &mut found);
+        let found: Vec<_> = found.iter().map(|diagnostic| diagnostic.code).collect();
+        assert_eq!(
+            found,
+            [
+                diagnostics::INVALID_DELIVERY_POLICY,
+                diagnostics::UNKNOWN_DELIVERY_OPERATION
+            ]
+        );
+
+        let commands: Vec<String> = conversion.commands.iter().cloned().collect();
+        let rendered = render_asyncapi(
+            &commands,
+            &derive_events(&conversion.commands),
+            &conversion.deprecations,
+            &conversion.aliases,
+            &delivery,
+            &conversion.results,
+            ChannelStyle::Generic,
+        )
+        .unwrap();
+        let contract: serde_yaml::Value = serde_yaml::from_str(&rendered).unwrap();
+        let policies = &contract["x-retasync"]["operations"]["x-retasync-delivery"];
+        assert_eq!(policies["event.create"]["max_attempts"], 4);
+        assert!(policies["event.create"].get("timeout_ms").is_none());
+        assert!(serde_yaml::from_str::<DeliveryPolicy>("retries: 2").is_err());
+    }
+
+    fn render_fixture(channel_style: ChannelStyle) -> (String, Vec<super::MappingRow>) {
+        let source = include_str!("../tests/fixtures/emergency-management.openapi.yaml");
+        let doc = serde_yaml::from_str(source).expect("yaml");
+        let conversion = convert(
+            &doc,
+            Some("emergency-management"),
+            &BTreeMap::new(),
+            BTreeMap::new(),
+            channel_style,
+        )
+        .expect("convert");
+        assert!(conversion.diagnostics.is_empty());
+        let commands: Vec<String> = conversion.commands.iter().cloned().collect();
+        let rendered = render_asyncapi(
+            &commands,
+            &derive_events(&conversion.commands),
+            &conversion.deprecations,
+            &conversion.aliases,
+            &BTreeMap::new(),
+            &conversion.results,
+            channel_style,
+        )
+        .unwrap();
+        (rendered, conversion.mappings)
+    }
+
+    #[test]
+    fn generic_channel_style_output_is_unchanged() {
+        let (rendered, mappings) = render_fixture(ChannelStyle::Generic);
+        assert_eq!(
+            rendered,
+            include_str!("../tests/fixtures/emergency-management.asyncapi.yaml"),
+            "generic output must not change"
+        );
+        let create = mappings
+            .iter()
+            .find(|row| row.operation_id == "CreateEvent")
+            .unwrap();
+        assert_eq!(create.channel, "commands/event.create");
+    }
+
+    #[test]
+    fn per_entity_channel_style_matches_snapshot() {
+        let (rendered, mappings) = render_fixture(ChannelStyle::PerEntity);
+        assert_eq!(
+            rendered,
+            include_str!("../tests/fixtures/emergency-management.per-entity.asyncapi.yaml"),
+            "regenerate tests/fixtures/emergency-management.per-entity.asyncapi.yaml"
+        );
+        let create = mappings
+            .iter()
+            .find(|row| row.operation_id == "CreateEmergencyActionMessage")
+            .unwrap();
+        assert_eq!(create.channel, "emergency_action_message/commands");
+
+        let contract: serde_yaml::Value = serde_yaml::from_str(&rendered).unwrap();
+        assert!(contract["channels"].get("commandChannel").is_none());
+        let channel = &contract["channels"]["eventCommands"];
+        assert_eq!(channel["address"], "event/commands");
+        let message = &contract["components"]["messages"]["EventCommand"]["payload"]["allOf"][1];
+        assert_eq!(
+            message["properties"]["payload"]["oneOf"][0]["$ref"],
+            "#/components/schemas/EventCreatePayload"
+        );
+        assert_eq!(contract["x-retasync"]["channel_style"], "per-entity");
+        let events = &contract["x-retasync"]["channels"]["events"];
+        assert_eq!(events["event.created"], "event/events");
+        typescript::render_typescript(&rendered).expect("typescript");
+    }
+
+    #[test]
+    fn success_responses_become_result_schemas() {
+        let (rendered, _) = render_fixture(ChannelStyle::Generic);
+        let contract: serde_yaml::Value = serde_yaml::from_str(&rendered).unwrap();
+        let results = &contract["x-retasync"]["operations"]["x-retasync-results"];
+        assert_eq!(results["event.create"], "EventCreateResult");
+        assert_eq!(
+            results["emergency_action_message.list"],
+            "EmergencyActionMessageListResult"
+        );
+        // A 204 has no body to check, and the stream declares no response at all.
+        assert!(results.get("event.delete").is_none());
+        assert!(results.get("notifications.stream").is_none());
+        let schemas = &contract["components"]["schemas"];
+        assert_eq!(schemas["EventCreateResult"]["$ref"], "#/components/schemas/Event");
+        assert_eq!(schemas["EventListResult"]["items"]["$ref"], "#/components/schemas/Event");
+        // Reached only through `Event`, and still carried along.
+        assert_eq!(schemas["Location"]["required"][0], "lat");
+
+        let doc = serde_yaml::from_str(
+            r#"
+paths:
+  /Event:
+    post:
+      operationId: CreateEvent
+      responses:
+        202:
+          content:
+            text/plain: { schema: { type: string } }
+            application/json: { schema: { type: object, required: [uid] } }
+        200:
+          content:
+            application/json: { schema: { type: object, required: [id] } }
+"#,
+        )
+        .expect("yaml");
+        let conversion =
+            convert(&doc, None, &BTreeMap::new(), BTreeMap::new(), ChannelStyle::Generic)
+                .expect("convert");
+        let created = &conversion.results.schemas["EventCreateResult"];
+        assert_eq!(created["required"][0], "id");
     }
-    out
 }
@@ -0,0 +1,428 @@
+﻿use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::{anyhow, Context, Result};
+use serde_yaml::{Mapping, Value};
+
+// Lines are laid out the way prettier prints them at its default width, so running prettier
+// over the output leaves it unchanged.
+const PRINT_WIDTH: usize = 80;
+pub(crate) const SCHEMA_REF_PREFIX: &str = "#/components/schemas/";
+const ENVELOPE_SUFFIX: &str = "Envelope";
+
+#[derive(Debug, Clone, PartialEq)]
+enum TsType {
+    Named(String),
+    Union(Vec<String>),
+    Array(Box<TsType>),
+}
+
+impl TsType {
+    fn named(name: &str) -> Self {
+        TsType::Named(name.to_string())
+    }
+
+    fn render(&self) -> String {
+        match self {
+            TsType::Named(name) => name.clone(),
+            TsType::Union(parts) => parts.join(" | "),
+            TsType::Array(item) => match item.as_ref() {
+                TsType::Union(parts) if parts.len() > 1 => format!("({})[]", item.render()),
+                _ => format!("{}[]", item.render()),
+            },
+        }
+    }
+
+    fn parts(self) -> Vec<String> {
+        match self {
+            TsType::Union(parts) => parts,
+            other => vec![other.render()],
+        }
+    }
+}
+
+struct Declarations {
+    names: BTreeMap<String, String>,
+    used: BTreeSet<String>,
+    rendered: BTreeMap<String, String>,
+}
+
+pub fn render_typescript(source: &str) -> Result<String> {
+    let doc: Value = serde_yaml::from_str(source.trim_start_matches('\u{feff}'))
+        .context("failed to parse AsyncAPI YAML")?;
+    let empty = Mapping::new();
+    let schemas = doc
+        .get("components")
+        .and_then(|components| components.get("schemas"))
+        .and_then(Value::as_mapping)
+        .unwrap_or(&empty);
+
+    let mut declarations = Declarations {
+        names: BTreeMap::new(),
+        used: BTreeSet::new(),
+        rendered: BTreeMap::new(),
+    };
+    for key in schemas.keys() {
+        let name = key
+            .as_str()
+            .ok_or_else(|| anyhow!("components.schemas keys must be strings"))?;
+        let ts_name = declarations.claim(&type_name(name));
+        declarations.names.insert(name.to_string(), ts_name);
+    }
+    for (key, schema) in schemas {
+        let name = key.as_str().unwrap_or_default();
+        let ts_name = declarations.names[name].clone();
+        declarations.declare(&ts_name, schema)?;
+    }
+
+    let mut out = String::new();
+    out.push_str("// Generated by retasync-convert typescript. Do not edit manually.\n");
+    let operations = doc
+        .get("x-retasync")
+        .and_then(|extension| extension.get("operations"));
+    for (list, constant, alias) in [
+        ("commands", "COMMAND_OPERATIONS", "CommandOperation"),
+        ("events", "EVENT_OPERATIONS", "EventOperation"),
+    ] {
+        let names: Vec<String> = operations
+            .and_then(|operations| operations.get(list))
+            .and_then(Value::as_sequence)
+            .map(|items| items.iter().filter_map(Value::as_str).map(quote).collect())
+            .unwrap_or_default();
+        out.push('\n');
+        out.push_str(&const_array(constant, &names));
+        out.push_str(&format!(
+            "export type {alias} = (typeof {constant})[number];\n"
+        ));
+    }
+    for declaration in declarations.rendered.values() {
+        out.push('\n');
+        out.push_str(declaration);
+    }
+    Ok(out)
+}
+
+fn const_array(constant: &str, items: &[String]) -> String {
+    let single = format!("export const {constant} = [{}] as const;\n", items.join(", "));
+    if items.is_empty() || single.trim_end().len() <= PRINT_WIDTH {
+        return single;
+    }
+    let mut out = format!("export const {constant} = [\n");
+    for item in items {
+        out.push_str(&format!("  {item},\n"));
+    }
+    out.push_str("] as const;\n");
+    out
+}
+
+impl Declarations {
+    // Reserves a declaration name, numbering it when sanitizing made two names collide.
+    fn claim(&mut self, base: &str) -> String {
+        let mut candidate = base.to_string();
+        let mut suffix = 2;
+        while !self.used.insert(candidate.clone()) {
+            candidate = format!("{base}{suffix}");
+            suffix += 1;
+        }
+        candidate
+    }
+
+    fn declare(&mut self, name: &str, schema: &Value) -> Result<()> {
+        match schema.get("properties").and_then(Value::as_mapping) {
+            Some(properties) => self.interface(name, schema, properties),
+            None => {
+                let ty = self.ts_type(name, schema)?;
+                let declaration = format!("export type {name} = {};\n", ty.render());
+                self.rendered.insert(name.to_string(), declaration);
+                Ok(())
+            }
+        }
+    }
+
+    fn interface(&mut self, name: &str, schema: &Value, properties: &Mapping) -> Result<()> {
+        let required: BTreeSet<&str> = schema
+            .get("required")
+            .and_then(Value::as_sequence)
+            .map(|items| items.iter().filter_map(Value::as_str).collect())
+            .unwrap_or_default();
+        // Envelopes mirror the Rust types, whose payload is left to the caller.
+        let generic = name.ends_with(ENVELOPE_SUFFIX)
+            && properties.contains_key(Value::from("payload"));
+
+        let mut out = String::new();
+        if let Some(description) = schema.get("description").and_then(Value::as_str) {
+            out.push_str(&doc_comment("", description));
+        }
+        let parameters = if generic { "<T = unknown>" } else { "" };
+        out.push_str(&format!("export interface {name}{parameters} {{\n"));
+        for (property, definition) in properties {
+            let property = property
+                .as_str()
+                .ok_or_else(|| anyhow!("schema {name} has a non-string property name"))?;
+            let ty = if generic && property == "payload" {
+                TsType::named("T")
+            } else {
+                self.ts_type(&format!("{name}{}", type_name(property)), definition)
+                    .with_context(|| format!("schema {name} property {property}"))?
+            };
+            let description = definition.get("description").and_then(Value::as_str);
+            let date_time = definition.get("format").and_then(Value::as_str) == Some("date-time");
+            match (description, date_time) {
+                (Some(description), true) => {
+                    out.push_str(&doc_comment("  ", &format!("{description} (ISO 8601)")))
+                }
+                (Some(description), false) => out.push_str(&doc_comment("  ", description)),
+                (None, true) => out.push_str(&doc_comment("  ", "ISO 8601")),
+                (None, false) => {}
+            }
+            let optional = if required.contains(property) { "" } else { "?" };
+            out.push_str(&member(&property_key(property), optional, ty));
+        }
+        out.push_str("}\n");
+        self.rendered.insert(name.to_string(), out);
+        Ok(())
+    }
+
+    fn ts_type(&mut self, nested_name: &str, definition: &Value) -> Result<TsType> {
+        if let Some(reference) = definition.get("$ref").and_then(Value::as_str) {
+            let target = reference
+                .strip_prefix(SCHEMA_REF_PREFIX)
+                .ok_or_else(|| anyhow!("unsupported $ref {reference}"))?;
+            let name = self
+                .names
+                .get(target)
+                .ok_or_else(|| anyhow!("unresolved $ref {reference}"))?;
+            return Ok(TsType::named(name));
+        }
+
+        let alternatives = definition
+            .get("oneOf")
+            .or_else(|| definition.get("anyOf"))
+            .and_then(Value::as_sequence);
+        if let Some(alternatives) = alternatives {
+            let mut parts = Vec::new();
+            for (index, alternative) in alternatives.iter().enumerate() {
+                let variant = format!("{nested_name}Variant{}", index + 1);
+                for part in self.ts_type(&variant, alternative)?.parts() {
+                    if !parts.contains(&part) {
+                        parts.push(part);
+                    }
+                }
+            }
+            return Ok(TsType::Union(parts));
+        }
+
+        if let Some(value) = definition.get("const") {
+            return Ok(TsType::Union(vec![literal(value)?]));
+        }
+        if let Some(members) = definition.get("enum").and_then(Value::as_sequence) {
+            let parts = members.iter().map(literal).collect::<Result<Vec<_>>>()?;
+            return Ok(TsType::Union(parts));
+        }
+
+        if definition.get("properties").and_then(Value::as_mapping).is_some() {
+            let name = self.claim(nested_name);
+            self.declare(&name, definition)?;
+            return Ok(TsType::Named(name));
+        }
+
+        Ok(match definition.get("type").and_then(Value::as_str) {
+            Some("string") => TsType::named("string"),
+            Some("integer" | "number") => TsType::named("number"),
+            Some("boolean") => TsType::named("boolean"),
+            Some("null") => TsType::named("null"),
+            Some("array") => {
+                let item = match definition.get("items") {
+                    Some(items) => self.ts_type(&format!("{nested_name}Item"), items)?,
+                    None => TsType::named("unknown"),
+                };
+                TsType::Array(Box::new(item))
+            }
+            Some("object") => match definition.get("additionalProperties") {
+                Some(values) if values.is_mapping() => {
+                    let value = self.ts_type(&format!("{nested_name}Value"), values)?;
+                    TsType::Named(format!("Record<string, {}>", value.render()))
+                }
+                _ => TsType::named("Record<string, unknown>"),
+            },
+            _ => TsType::named("unknown"),
+        })
+    }
+}
+
+// Prettier breaks a union that overflows the line onto one leading `|` per member.
+fn member(key: &str, optional: &str, ty: TsType) -> String {
+    let single = format!("  {key}{optional}: {};\n", ty.render());
+    match ty {
+        TsType::Union(parts) if parts.len() > 1 && single.trim_end().len() > PRINT_WIDTH => {
+            let mut out = format!("  {key}{optional}:\n");
+            let last = parts.len() - 1;
+            for (index, part) in parts.iter().enumerate() {
+                let end = if index == last { ";" } else { "" };
+                out.push_str(&format!("    | {part}{end}\n"));
+            }
+            out
+        }
+        _ => single,
+    }
+}
+
+fn doc_comment(indent: &str, text: &str) -> String {
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    format!("{indent}/** {} */\n", text.replace("*/", "* /"))
+}
+
+fn literal(value: &Value) -> Result<String> {
+    match value {
+        Value::String(text) => Ok(quote(text)),
+        Value::Number(number) => Ok(number.to_string()),
+        Value::Bool(flag) => Ok(flag.to_string()),
+        Value::Null => Ok("null".to_string()),
+        other => Err(anyhow!("unsupported literal {other:?}")),
+    }
+}
+
+fn quote(text: &str) -> String {
+    serde_json::to_string(text).unwrap_or_else(|_| format!("\"{text}\""))
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|first| first.is_ascii_alphabetic() || first == '_' || first == '$')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$')
+}
+
+fn property_key(property: &str) -> String {
+    if is_identifier(property) {
+        property.to_string()
+    } else {
+        quote(property)
+    }
+}
+
+// PascalCase built from the name's alphanumeric runs, so `emergency-action.v2` becomes
+// `EmergencyActionV2`; a leading digit gets an underscore.
+fn type_name(name: &str) -> String {
+    let mut out = String::new();
+    for segment in name.split(|c: char| !c.is_ascii_alphanumeric()) {
+        let mut chars = segment.chars();
+        if let Some(first) = chars.next() {
+            out.push(first.to_ascii_uppercase());
+            out.push_str(chars.as_str());
+        }
+    }
+    if out.is_empty() {
+        "Schema".to_string()
+    } else if out.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("_{out}")
+    } else {
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_identifier, render_typescript};
+
+    const CONTRACT: &str = include_str!("../../../contracts/retasyncapi-v1.asyncapi.yaml");
+    const SNAPSHOT: &str = include_str!("../tests/fixtures/retasyncapi-v1.d.ts");
+
+    // A structural check standing in for a TypeScript parser: every line has to be one of the
+    // forms the emitter writes, and braces and brackets have to balance.
+    fn assert_declaration_syntax(source: &str) {
+        let mut depth = 0i32;
+        for line in source.lines() {
+            let trimmed = line.trim();
+            let ok = trimmed.is_empty()
+                || (trimmed.starts_with("//") && !trimmed.contains("*/"))
+                || (trimmed.starts_with("/** ") && trimmed.ends_with(" */"))
+                || trimmed == "}"
+                || trimmed == "] as const;"
+                || (trimmed.starts_with("| ") && !trimmed.ends_with(','))
+                || (trimmed.starts_with('"') && trimmed.ends_with("\","))
+                || (trimmed.starts_with("export interface ") && trimmed.ends_with(" {"))
+                || (trimmed.starts_with("export type ") && trimmed.ends_with(';'))
+                || (trimmed.starts_with("export const ")
+                    && (trimmed.ends_with("= [") || trimmed.ends_with("] as const;")))
+                || {
+                    let (key, ty) = trimmed.split_once(':').unwrap_or(("", ""));
+                    let key = key.strip_suffix('?').unwrap_or(key);
+                    let quoted = key.len() > 1 && key.starts_with('"') && key.ends_with('"');
+                    (is_identifier(key) || quoted)
+                        && (ty.ends_with(';') || ty.is_empty())
+                        && line.starts_with("  ")
+                };
+            assert!(ok, "unexpected line: {line:?}");
+            depth += line.matches(['{', '[', '(']).count() as i32;
+            depth -= line.matches(['}', ']', ')']).count() as i32;
+            assert!(depth >= 0, "unbalanced at {line:?}");
+        }
+        assert_eq!(depth, 0, "unbalanced declarations");
+    }
+
+    #[test]
+    fn contract_types_match_snapshot() {
+        let rendered = render_typescript(CONTRACT).expect("rendered");
+        assert_eq!(rendered, SNAPSHOT, "regenerate tests/fixtures/retasyncapi-v1.d.ts");
+        assert_declaration_syntax(&rendered);
+        assert_eq!(render_typescript(CONTRACT).unwrap(), rendered);
+    }
+
+    #[test]
+    fn sanitizes_names_and_recurses_into_nested_schemas() {
+        let rendered = render_typescript(
+            r##"
+components:
+  schemas:
+    3d-point:
+      type: object
+      required: [x]
+      properties:
+        x:
+          type: number
+        tags:
+          type: array
+          items:
+            type: string
+            enum: [a, b]
+        meta-data:
+          type: object
+          properties:
+            seenAt:
+              type: string
+              format: date-time
+    route.v2:
+      type: object
+      properties:
+        points:
+          type: array
+          items:
+            $ref: '#/components/schemas/3d-point'
+        kind:
+          oneOf:
+            - $ref: '#/components/schemas/3d-point'
+            - type: string
+x-retasync:
+  operations:
+    commands: [route.create]
+"##,
+        )
+        .expect("rendered");
+        assert_declaration_syntax(&rendered);
+        assert!(rendered.contains(
+            "export const COMMAND_OPERATIONS = [\"route.create\"] as const;\n"
+        ));
+        assert!(rendered.contains("export const EVENT_OPERATIONS = [] as const;\n"));
+        assert!(rendered.contains(
+            "export interface _3dPoint {\n  x: number;\n  tags?: (\"a\" | \"b\")[];\n  \
+             \"meta-data\"?: _3dPointMetaData;\n}\n"
+        ));
+        assert!(rendered.contains(
+            "export interface _3dPointMetaData {\n  /** ISO 8601 */\n  seenAt?: string;\n}\n"
+        ));
+        assert!(rendered.contains(
+            "export interface RouteV2 {\n  points?: _3dPoint[];\n  kind?: _3dPoint | string;\n}\n"
+        ));
+    }
+}
@@ -0,0 +1,183 @@
+// Generated by retasync-convert typescript. Do not edit manually.
+
+export const COMMAND_OPERATIONS = [
+  "emergency_action_message.create",
+  "emergency_action_message.list",
+  "emergency_action_message.put",
+  "emergency_action_message.retrieve",
+  "emergency_action_message.delete",
+  "event.create",
+  "event.list",
+  "event.put",
+  "event.retrieve",
+  "event.delete",
+  "transfer.upload",
+] as const;
+export type CommandOperation = (typeof COMMAND_OPERATIONS)[number];
+
+export const EVENT_OPERATIONS = [
+  "emergency_action_message.created",
+  "emergency_action_message.updated",
+  "emergency_action_message.deleted",
+  "event.created",
+  "event.updated",
+  "event.deleted",
+  "transfer.progress",
+  "transfer.completed",
+  "transfer.failed",
+] as const;
+export type EventOperation = (typeof EVENT_OPERATIONS)[number];
+
+export interface EmergencyActionMessage {
+  callsign: string;
+  groupName?: string;
+  commsMethod?: string;
+  medicalStatus?: string;
+  commsStatus?: string;
+  preparednessStatus?: string;
+  mobilityStatus?: string;
+  securityCapability?: string;
+  personnelStatus?: string;
+  summary?: string;
+}
+
+/** Operational metadata about a command, kept apart from its payload. Payload keys named _meta, _priority, _trace_id, _client_id or _client_version are refused. */
+export interface EnvelopeMeta {
+  priority?: "routine" | "priority" | "immediate" | "flash";
+  /** Caller's correlation id for logs across systems. */
+  trace_id?: string;
+  client_id?: string;
+  client_version?: string;
+  /** Canonical digest of the result the caller already holds. A handler that supports it answers {"status": "not_modified"} instead of a result with the same digest. */
+  if_result_digest_differs?: string;
+  custom?: Record<string, string>;
+  /** Operator labels for the job. A node applies them to the records it creates in answer to the command. */
+  labels?: Record<string, string>;
+}
+
+export interface Event {
+  uid: string;
+  title?: string;
+  detail?: string;
+  eventType?: string;
+  location?: string;
+  /** ISO 8601 */
+  occurredAt?: string;
+}
+
+export interface HopRecord {
+  /** Identity hash of the node that handled the envelope. */
+  identity: string;
+  /** ISO 8601 */
+  received_at?: string;
+  /** When the node sent the envelope on, or sent the result for it. (ISO 8601) */
+  forwarded_at?: string;
+  transport?: "link" | "lxmf";
+  note?: string;
+}
+
+export interface MeshCommandEnvelope<T = unknown> {
+  /** UUIDv7 identifier. */
+  message_id: string;
+  /** Namespaced snake_case command operation. */
+  operation: string;
+  /** ISO 8601 */
+  sent_at: string;
+  /** Identity hash of the sender, 32 lowercase hex characters. */
+  source_identity: string;
+  /** Identity hash of the recipient, 32 lowercase hex characters. */
+  destination_identity: string;
+  /** Payload encoding; compressed variants are only sent to peers advertising support. */
+  content_type:
+    | "application/msgpack"
+    | "application/msgpack+zstd"
+    | "application/msgpack+gzip";
+  payload: T;
+  ttl_ms?: number;
+  transport_hint?: "link" | "lxmf";
+  /** Hops that handled the envelope, present only when the submitter enabled tracing. */
+  trace?: HopRecord[];
+  /** Set once hops were dropped to keep the trace under the relay cap. */
+  trace_truncated?: boolean;
+  meta?: EnvelopeMeta;
+}
+
+export interface MeshEventEnvelope<T = unknown> {
+  message_id: string;
+  /** Namespaced snake_case event name. */
+  event: string;
+  /** ISO 8601 */
+  sent_at: string;
+  /** Identity hash of the sender, 32 lowercase hex characters. */
+  source_identity: string;
+  /** Identity hash of the recipient, 32 lowercase hex characters. */
+  destination_identity: string;
+  content_type: "application/msgpack";
+  payload: T;
+  ttl_ms?: number;
+  transport_hint?: "link" | "lxmf";
+}
+
+export interface MeshResultEnvelope<T = unknown> {
+  message_id: string;
+  correlation_id: string;
+  operation: string;
+  /** ISO 8601 */
+  sent_at: string;
+  /** Identity hash of the sender, 32 lowercase hex characters. */
+  source_identity: string;
+  /** Identity hash of the recipient, 32 lowercase hex characters. */
+  destination_identity: string;
+  content_type: "application/msgpack";
+  payload: T;
+  ttl_ms?: number;
+  transport_hint?: "link" | "lxmf";
+  /** Hops that handled the envelope, present only when the submitter enabled tracing. */
+  trace?: HopRecord[];
+  /** Set once hops were dropped to keep the trace under the relay cap. */
+  trace_truncated?: boolean;
+}
+
+export interface MeshTransferEnvelope<T = unknown> {
+  message_id: string;
+  correlation_id?: string;
+  operation: string;
+  /** ISO 8601 */
+  sent_at: string;
+  /** Identity hash of the sender, 32 lowercase hex characters. */
+  source_identity: string;
+  /** Identity hash of the recipient, 32 lowercase hex characters. */
+  destination_identity: string;
+  /** Payload encoding; compressed variants are only sent to peers advertising support. */
+  content_type:
+    | "application/msgpack"
+    | "application/msgpack+zstd"
+    | "application/msgpack+gzip";
+  direction: "upload" | "download";
+  payload: T;
+  ttl_ms?: number;
+  transport_hint?: "link" | "lxmf";
+}
+
+export interface TransferCompletion {
+  transfer_id: string;
+  status: "success" | "failed";
+  checksum_sha256?: string;
+  reason?: string;
+}
+
+export interface TransferProgress {
+  transfer_id: string;
+  status: "queued" | "running" | "success" | "failed";
+  bytes_sent?: number;
+  bytes_total?: number;
+  reason?: string;
+}
+
+export interface TransferUploadRequest {
+  /** Identity hash of the recipient, 32 lowercase hex characters. */
+  destination_identity: string;
+  file_name: string;
+  media_type: string;
+  payload_base64: string;
+}
@@ -0,0 +1,491 @@
+﻿use std::collections::BTreeSet;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::Utc;
+use retasync_codegen::render_contracts_module;
+use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
+
+pub const CONTRACT_PATH: &str = "contracts/retasyncapi-v1.asyncapi.yaml";
+pub const CHANGELOG_PATH: &str = "contracts/CHANGELOG.yaml";
+pub const GENERATED_PATH: &str = "crates/retasync_contract/src/generated/contracts.rs";
+pub const VECTORS_PATH: &str = "contracts/vectors";
+
+// Committed output that no longer matches what the contract renders. Kept apart from other
+// errors so `--check` can exit with its own status.
+#[derive(Debug)]
+pub struct Drift(pub String);
+
+impl fmt::Display for Drift {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Drift {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BumpLevel {
+    Major,
+    Minor,
+    Patch,
+}
+
+impl BumpLevel {
+    pub fn parse(raw: &str) -> Result<Self> {
+        match raw {
+            "major" => Ok(Self::Major),
+            "minor" => Ok(Self::Minor),
+            "patch" => Ok(Self::Patch),
+            other => bail!("unknown bump level {other}; expected major, minor, or patch"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ContractVersion {
+    major: u64,
+    minor: u64,
+    patch: u64,
+}
+
+impl ContractVersion {
+    pub fn parse(raw: &str) -> Result<Self> {
+        let parts: Vec<&str> = raw.trim().split('.').collect();
+        let [major, minor, patch] = parts.as_slice() else {
+            bail!("contract version {raw} is not MAJOR.MINOR.PATCH");
+        };
+        let number = |part: &str| {
+            part.parse::<u64>()
+                .with_context(|| format!("contract version {raw} is not MAJOR.MINOR.PATCH"))
+        };
+        Ok(Self {
+            major: number(major)?,
+            minor: number(minor)?,
+            patch: number(patch)?,
+        })
+    }
+
+    pub fn bump(self, level: BumpLevel) -> Self {
+        match level {
+            BumpLevel::Major => Self {
+                major: self.major + 1,
+                minor: 0,
+                patch: 0,
+            },
+            BumpLevel::Minor => Self {
+                minor: self.minor + 1,
+                patch: 0,
+                ..self
+            },
+            BumpLevel::Patch => Self {
+                patch: self.patch + 1,
+                ..self
+            },
+        }
+    }
+}
+
+impl fmt::Display for ContractVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChangeSummary {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub schemas_added: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub schemas_removed: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub schemas_changed: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub operations_added: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub operations_removed: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChangelogEntry {
+    pub version: String,
+    pub date: String,
+    pub note: String,
+    #[serde(default)]
+    pub changes: ChangeSummary,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Changelog {
+    #[serde(default)]
+    pub entries: Vec<ChangelogEntry>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Workspace {
+    root: PathBuf,
+}
+
+impl Workspace {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    // The nearest directory at or above `start` whose Cargo.toml has a `[workspace]` table.
+    pub fn locate(start: &Path) -> Result<Self> {
+        for dir in start.ancestors() {
+            let manifest = dir.join("Cargo.toml");
+            let Ok(source) = std::fs::read_to_string(&manifest) else {
+                continue;
+            };
+            let parsed: toml::Table = toml::from_str(strip_bom(&source))
+                .with_context(|| format!("invalid manifest {}", manifest.display()))?;
+            if parsed.contains_key("workspace") {
+                return Ok(Self::new(dir));
+            }
+        }
+        bail!("no workspace Cargo.toml at or above {}", start.display())
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn contract_path(&self) -> PathBuf {
+        self.root.join(CONTRACT_PATH)
+    }
+
+    pub fn changelog_path(&self) -> PathBuf {
+        self.root.join(CHANGELOG_PATH)
+    }
+
+    pub fn generated_path(&self) -> PathBuf {
+        self.root.join(GENERATED_PATH)
+    }
+
+    pub fn vectors_path(&self) -> PathBuf {
+        self.root.join(VECTORS_PATH)
+    }
+
+    fn read(&self, path: &Path) -> Result<String> {
+        std::fs::read_to_string(path).with_context(|| format!("failed reading {}", path.display()))
+    }
+
+    fn load_changelog(&self) -> Result<Changelog> {
+        let path = self.changelog_path();
+        if !path.exists() {
+            return Ok(Changelog::default());
+        }
+        serde_yaml::from_str(strip_bom(&self.read(&path)?))
+            .with_context(|| format!("invalid changelog {}", path.display()))
+    }
+
+    fn baseline(&self, baseline: Option<&Path>) -> Result<String> {
+        if let Some(path) = baseline {
+            return self.read(path);
+        }
+
+        let output = Command::new("git")
+            .arg("show")
+            .arg(format!("HEAD:{CONTRACT_PATH}"))
+            .current_dir(&self.root)
+            .output()
+            .context("failed running git show")?;
+        if !output.status.success() {
+            bail!(
+                "failed reading {CONTRACT_PATH} at HEAD: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        String::from_utf8(output.stdout).context("contract at HEAD is not UTF-8")
+    }
+}
+
+pub fn bump(
+    workspace: &Workspace,
+    level: BumpLevel,
+    note: &str,
+    baseline: Option<&Path>,
+) -> Result<ChangelogEntry> {
+    let contract_path = workspace.contract_path();
+    let generated_path = workspace.generated_path();
+    let source = workspace.read(&contract_path)?;
+
+    let existing = workspace.read(&generated_path)?;
+    if !same_text(&existing, &render_contracts_module(&source)?) {
+        return Err(Drift(format!(
+            "generated contracts drift detected in {}: run `cargo xtask codegen` and commit before bumping",
+            generated_path.display()
+        ))
+        .into());
+    }
+
+    let current = contract_version(&source)?;
+    let next = current.bump(level);
+    let entry = ChangelogEntry {
+        version: next.to_string(),
+        date: Utc::now().format("%Y-%m-%d").to_string(),
+        note: note.to_string(),
+        changes: summarize_changes(&workspace.baseline(baseline)?, &source)?,
+    };
+
+    let bumped = replace_info_version(&source, &next.to_string())?;
+    let mut changelog = workspace.load_changelog()?;
+    changelog.entries.push(entry.clone());
+    let rendered = render_contracts_module(&bumped)?;
+
+    std::fs::write(&contract_path, &bumped)
+        .with_context(|| format!("failed writing {}", contract_path.display()))?;
+    std::fs::write(
+        workspace.changelog_path(),
+        serde_yaml::to_string(&changelog).context("serialize changelog")?,
+    )
+    .with_context(|| format!("failed writing {}", workspace.changelog_path().display()))?;
+    write_generated(&generated_path, &rendered)?;
+    Ok(entry)
+}
+
+pub fn check(workspace: &Workspace, baseline: Option<&Path>) -> Result<()> {
+    let source = workspace.read(&workspace.contract_path())?;
+    let baseline = workspace.baseline(baseline)?;
+    if same_text(&source, &baseline) {
+        return Ok(());
+    }
+
+    let current = contract_version(&source)?;
+    let previous = contract_version(&baseline)?;
+    if current <= previous {
+        bail!(
+            "contract changed but info.version is still {current}: run `cargo xtask contracts bump`"
+        );
+    }
+
+    let changelog = workspace.load_changelog()?;
+    match changelog.entries.last() {
+        Some(entry) if ContractVersion::parse(&entry.version)? == current => Ok(()),
+        Some(entry) => bail!(
+            "contract version {current} does not match the last changelog entry {}",
+            entry.version
+        ),
+        None => bail!("contract version {current} has no entry in {CHANGELOG_PATH}"),
+    }
+}
+
+pub fn write_generated(path: &Path, rendered: &str) -> Result<()> {
+    let keep_bom = std::fs::read_to_string(path)
+        .map(|existing| existing.starts_with('\u{feff}'))
+        .unwrap_or(false);
+    let contents = if keep_bom {
+        format!("\u{feff}{rendered}")
+    } else {
+        rendered.to_string()
+    };
+    std::fs::write(path, contents).with_context(|| format!("failed writing {}", path.display()))
+}
+
+pub fn same_text(left: &str, right: &str) -> bool {
+    normalize_newlines(left) == normalize_newlines(right)
+}
+
+fn normalize_newlines(input: &str) -> String {
+    strip_bom(input).replace("\r\n", "\n")
+}
+
+fn strip_bom(input: &str) -> &str {
+    input.trim_start_matches('\u{feff}')
+}
+
+fn parse_doc(source: &str) -> Result<Value> {
+    serde_yaml::from_str(strip_bom(source)).context("failed parsing AsyncAPI YAML")
+}
+
+pub(crate) fn contract_version(source: &str) -> Result<ContractVersion> {
+    let doc = parse_doc(source)?;
+    let version = doc
+        .get("info")
+        .and_then(|info| info.get("version"))
+        .and_then(Value::as_str)
+        .ok_or_else(|| anyhow!("contract is missing info.version"))?;
+    ContractVersion::parse(version)
+}
+
+fn replace_info_version(source: &str, version: &str) -> Result<String> {
+    let mut in_info = false;
+    let mut replaced = false;
+    let mut out = Vec::new();
+
+    for line in source.split('\n') {
+        let bare = strip_bom(line);
+        if !bare.starts_with(' ') && !bare.trim().is_empty() {
+            in_info = bare.trim_end() == "info:";
+        }
+
+        let indent = line.len() - line.trim_start().len();
+        if in_info && !replaced && indent == 2 && line.trim_start().starts_with("version:") {
+            let quote = if line.contains('"') { "\"" } else { "" };
+            let eol = if line.ends_with('\r') { "\r" } else { "" };
+            out.push(format!("  version: {quote}{version}{quote}{eol}"));
+            replaced = true;
+        } else {
+            out.push(line.to_string());
+        }
+    }
+
+    if !replaced {
+        bail!("contract is missing info.version");
+    }
+    Ok(out.join("\n"))
+}
+
+fn summarize_changes(baseline: &str, current: &str) -> Result<ChangeSummary> {
+    let before = parse_doc(baseline)?;
+    let after = parse_doc(current)?;
+
+    let schemas = |doc: &Value| {
+        doc.get("components")
+            .and_then(|components| components.get("schemas"))
+            .and_then(Value::as_mapping)
+            .cloned()
+            .unwrap_or_default()
+    };
+    let (old_schemas, new_schemas) = (schemas(&before), schemas(&after));
+
+    let mut summary = ChangeSummary::default();
+    for (name, schema) in &new_schemas {
+        let name = yaml_key(name);
+        match old_schemas.get(name.as_str()) {
+            None => summary.schemas_added.push(name),
+            Some(previous) if previous != schema => summary.schemas_changed.push(name),
+            Some(_) => {}
+        }
+    }
+    for name in old_schemas.keys() {
+        if !new_schemas.contains_key(name) {
+            summary.schemas_removed.push(yaml_key(name));
+        }
+    }
+
+    let (old_ops, new_ops) = (operation_names(&before), operation_names(&after));
+    summary.operations_added = new_ops.difference(&old_ops).cloned().collect();
+    summary.operations_removed = old_ops.difference(&new_ops).cloned().collect();
+    Ok(summary)
+}
+
+fn operation_names(doc: &Value) -> BTreeSet<String> {
+    let mut names = BTreeSet::new();
+    if let Some(operations) = doc.get("operations").and_then(Value::as_mapping) {
+        names.extend(operations.keys().map(yaml_key));
+    }
+    let extension = doc.get("x-retasync").and_then(|ext| ext.get("operations"));
+    for kind in ["commands", "events"] {
+        if let Some(items) = extension
+            .and_then(|ops| ops.get(kind))
+            .and_then(Value::as_sequence)
+        {
+            names.extend(items.iter().filter_map(Value::as_str).map(str::to_string));
+        }
+    }
+    names
+}
+
+fn yaml_key(key: &Value) -> String {
+    key.as_str()
+        .map(str::to_string)
+        .unwrap_or_else(|| serde_yaml::to_string(key).unwrap_or_default().trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bump, check, BumpLevel, Changelog, Workspace};
+    use retasync_codegen::render_contracts_module;
+    use std::path::PathBuf;
+
+    const BASELINE: &str = "\u{feff}asyncapi: \"3.0.0\"
+info:
+  title: Fixture
+  version: \"1.2.3\"
+components:
+  schemas:
+    Event:
+      type: object
+x-retasync:
+  operations:
+    commands:
+      - event.create
+";
+
+    fn fixture() -> (Workspace, PathBuf) {
+        let root = std::env::temp_dir().join(format!(
+            "xtask-contracts-{}-{}",
+            std::process::id(),
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_nanos()
+        ));
+        let workspace = Workspace::new(&root);
+        std::fs::create_dir_all(workspace.contract_path().parent().unwrap()).unwrap();
+        std::fs::create_dir_all(workspace.generated_path().parent().unwrap()).unwrap();
+
+        let edited = BASELINE
+            .replace("      type: object\n", "      type: object\n    Signature:\n      type: string\n")
+            .replace("      - event.create\n", "      - event.create\n      - event.sign\n");
+        std::fs::write(workspace.contract_path(), &edited).unwrap();
+        std::fs::write(
+            workspace.generated_path(),
+            render_contracts_module(&edited).unwrap(),
+        )
+        .unwrap();
+
+        let baseline = root.join("baseline.yaml");
+        std::fs::write(&baseline, BASELINE).unwrap();
+        (workspace, baseline)
+    }
+
+    #[test]
+    fn bump_updates_version_changelog_and_generated_code() {
+        let (workspace, baseline) = fixture();
+        assert!(check(&workspace, Some(&baseline)).is_err());
+
+        let entry = bump(
+            &workspace,
+            BumpLevel::Minor,
+            "added signature field",
+            Some(&baseline),
+        )
+        .expect("bump");
+        assert_eq!(entry.version, "1.3.0");
+        assert_eq!(entry.changes.schemas_added, vec!["Signature".to_string()]);
+        assert_eq!(entry.changes.operations_added, vec!["event.sign".to_string()]);
+
+        let contract = std::fs::read_to_string(workspace.contract_path()).unwrap();
+        assert!(contract.starts_with('\u{feff}'));
+        assert!(contract.contains("  version: \"1.3.0\"\n"));
+
+        let changelog: Changelog = serde_yaml::from_str(
+            &std::fs::read_to_string(workspace.changelog_path()).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(changelog.entries, vec![entry]);
+
+        let generated = std::fs::read_to_string(workspace.generated_path()).unwrap();
+        assert_eq!(generated, render_contracts_module(&contract).unwrap());
+        assert!(generated.contains("EventSign"));
+
+        check(&workspace, Some(&baseline)).expect("check passes after bump");
+    }
+
+    #[test]
+    fn bump_refuses_generated_drift() {
+        let (workspace, baseline) = fixture();
+        std::fs::write(workspace.generated_path(), "// stale\n").unwrap();
+
+        let err = bump(&workspace, BumpLevel::Patch, "noop", Some(&baseline))
+            .expect_err("drift must fail");
+        assert!(err.to_string().contains("drift"));
+    }
+}
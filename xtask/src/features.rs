@@ -0,0 +1,259 @@
+﻿use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+
+// The minimal client may be at most this fraction of the full daemon's size.
+pub const DEFAULT_MAX_SIZE_RATIO: f64 = 0.25;
+
+const CLIENT_PACKAGE: &str = "retasync_cli";
+const MINIMAL_BINARY: &str = "retasync-client";
+const FULL_BINARY: &str = "retasyncd";
+
+// One way of building part of the workspace, checked with the same build, clippy and test runs
+// CI gives the default build, plus the dependencies it promises to leave out.
+#[derive(Debug, Clone, Copy)]
+pub struct FeatureSet {
+    pub name: &'static str,
+    // Empty for the whole workspace.
+    pub packages: &'static [&'static str],
+    pub default_features: bool,
+    pub features: &'static [&'static str],
+    // Packages `cargo tree` must not find among the normal dependencies.
+    pub forbidden: &'static [&'static str],
+}
+
+pub const FEATURE_SETS: &[FeatureSet] = &[
+    FeatureSet {
+        name: "default",
+        packages: &[],
+        default_features: true,
+        features: &[],
+        forbidden: &[],
+    },
+    FeatureSet {
+        name: "minimal",
+        packages: &["retasync_contract", "retasync_mesh_bridge", CLIENT_PACKAGE],
+        default_features: false,
+        features: &["retasync_cli/minimal-client"],
+        forbidden: &[
+            "axum",
+            "hyper",
+            "retasync_control_plane",
+            "retasync_storage",
+            "serde_yaml",
+            "sqlx",
+        ],
+    },
+    FeatureSet {
+        name: "contract",
+        packages: &["retasync_contract"],
+        default_features: false,
+        features: &[],
+        forbidden: &["anyhow", "async-trait", "axum", "futures", "serde_yaml", "sqlx", "tokio"],
+    },
+    FeatureSet {
+        name: "control-plane-core",
+        packages: &["retasync_control_plane"],
+        default_features: false,
+        features: &[],
+        forbidden: &["tempfile"],
+    },
+];
+
+impl FeatureSet {
+    pub fn named(name: &str) -> Result<&'static Self> {
+        match FEATURE_SETS.iter().find(|set| set.name == name) {
+            Some(set) => Ok(set),
+            None => {
+                let known: Vec<_> = FEATURE_SETS.iter().map(|set| set.name).collect();
+                bail!("unknown feature set {name:?}; expected one of {}", known.join(", "))
+            }
+        }
+    }
+
+    // The package and feature arguments every cargo run for this set shares.
+    pub fn selection(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if self.packages.is_empty() {
+            args.push("--workspace".to_string());
+        }
+        for package in self.packages {
+            args.extend(["-p".to_string(), package.to_string()]);
+        }
+        if !self.default_features {
+            args.push("--no-default-features".to_string());
+        }
+        if !self.features.is_empty() {
+            args.extend(["--features".to_string(), self.features.join(",")]);
+        }
+        args
+    }
+
+    pub fn check(&self, root: &Path, run_tests: bool) -> Result<()> {
+        let selection = self.selection();
+        cargo(root, &["build"], &selection, &[])?;
+        cargo(root, &["clippy", "--all-targets"], &selection, &["--", "-D", "warnings"])?;
+        if run_tests {
+            cargo(root, &["test"], &selection, &[])?;
+        }
+        if !self.forbidden.is_empty() {
+            let tree = cargo_output(
+                root,
+                &["tree", "-e", "normal", "--prefix", "none", "--format", "{p}"],
+                &selection,
+            )?;
+            let found = forbidden_in_tree(&tree, self.forbidden);
+            if !found.is_empty() {
+                bail!("feature set {} pulls in {}", self.name, found.join(", "));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Names from `cargo tree --prefix none --format {p}` output, one `name version [...]` per line.
+pub fn forbidden_in_tree(tree: &str, forbidden: &[&str]) -> Vec<String> {
+    let present: BTreeSet<&str> =
+        tree.lines().filter_map(|line| line.split_whitespace().next()).collect();
+    forbidden
+        .iter()
+        .filter(|name| present.contains(*name))
+        .map(|name| name.to_string())
+        .collect()
+}
+
+pub fn check_size_ratio(minimal: u64, full: u64, max_ratio: f64) -> Result<f64> {
+    if full == 0 {
+        bail!("the full {FULL_BINARY} binary is empty");
+    }
+    let ratio = minimal as f64 / full as f64;
+    if ratio > max_ratio {
+        bail!(
+            "{MINIMAL_BINARY} is {minimal} bytes, {:.0}% of the {full}-byte {FULL_BINARY}; \
+             at most {:.0}% is allowed",
+            ratio * 100.0,
+            max_ratio * 100.0
+        );
+    }
+    Ok(ratio)
+}
+
+// Release builds of both binaries, each with only the features it needs.
+pub fn check_binary_sizes(root: &Path, max_ratio: f64) -> Result<()> {
+    let minimal = release_binary(
+        root,
+        MINIMAL_BINARY,
+        &["--no-default-features", "--features", "minimal-client"],
+    )?;
+    let full = release_binary(root, FULL_BINARY, &[])?;
+    let size = |path: &Path| -> Result<u64> {
+        Ok(std::fs::metadata(path)
+            .with_context(|| format!("failed reading {}", path.display()))?
+            .len())
+    };
+    let (minimal_bytes, full_bytes) = (size(&minimal)?, size(&full)?);
+    let ratio = check_size_ratio(minimal_bytes, full_bytes, max_ratio)?;
+    println!(
+        "{MINIMAL_BINARY} is {minimal_bytes} bytes, {:.0}% of {FULL_BINARY} at {full_bytes}",
+        ratio * 100.0
+    );
+    Ok(())
+}
+
+fn release_binary(root: &Path, binary: &str, features: &[&str]) -> Result<PathBuf> {
+    let mut args = vec!["-p", CLIENT_PACKAGE, "--bin", binary];
+    args.extend(features);
+    let args: Vec<String> = args.into_iter().map(str::to_string).collect();
+    let output = cargo_output(
+        root,
+        &["build", "--release", "--message-format=json-render-diagnostics"],
+        &args,
+    )?;
+    let executable = output
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+        .filter(|message| message["target"]["name"] == binary)
+        .find_map(|message| message["executable"].as_str().map(PathBuf::from));
+    executable.with_context(|| format!("cargo build did not report a {binary} executable"))
+}
+
+fn cargo_command(root: &Path, subcommand: &[&str], selection: &[String]) -> Command {
+    let mut command =
+        Command::new(std::env::var_os("CARGO").unwrap_or_else(|| "cargo".into()));
+    command.args(subcommand).args(selection).current_dir(root);
+    command
+}
+
+fn cargo(root: &Path, subcommand: &[&str], selection: &[String], trailing: &[&str]) -> Result<()> {
+    let mut command = cargo_command(root, subcommand, selection);
+    command.args(trailing);
+    let status = command
+        .status()
+        .with_context(|| format!("failed running cargo {}", subcommand.join(" ")))?;
+    if !status.success() {
+        bail!("cargo {} {} failed", subcommand.join(" "), selection.join(" "));
+    }
+    Ok(())
+}
+
+fn cargo_output(root: &Path, subcommand: &[&str], selection: &[String]) -> Result<String> {
+    let output = cargo_command(root, subcommand, selection)
+        .output()
+        .with_context(|| format!("failed running cargo {}", subcommand.join(" ")))?;
+    if !output.status.success() {
+        bail!(
+            "cargo {} {} failed: {}",
+            subcommand.join(" "),
+            selection.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    String::from_utf8(output.stdout).context("cargo output is not UTF-8")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sets_select_their_packages_and_features() {
+        assert_eq!(FeatureSet::named("default").unwrap().selection(), ["--workspace"]);
+        assert_eq!(
+            FeatureSet::named("minimal").unwrap().selection(),
+            [
+                "-p",
+                "retasync_contract",
+                "-p",
+                "retasync_mesh_bridge",
+                "-p",
+                "retasync_cli",
+                "--no-default-features",
+                "--features",
+                "retasync_cli/minimal-client",
+            ]
+        );
+        let err = FeatureSet::named("tiny").unwrap_err();
+        assert!(err.to_string().contains("default, minimal"), "{err:#}");
+    }
+
+    #[test]
+    fn forbidden_packages_are_found_by_name_only() {
+        let tree = "retasync_cli v0.1.0 (/work/crates/retasync_cli)\n\
+                    axum v0.8.4\n\
+                    axum-core v0.5.2\n\
+                    tokio v1.47.0 (*)\n";
+        assert_eq!(forbidden_in_tree(tree, &["axum", "sqlx"]), ["axum"]);
+        assert!(forbidden_in_tree(tree, &["hyper", "axum-cor"]).is_empty());
+    }
+
+    #[test]
+    fn size_ratio_fails_above_the_limit() {
+        assert_eq!(check_size_ratio(25, 100, 0.5).unwrap(), 0.25);
+        let err = check_size_ratio(60, 100, 0.5).unwrap_err();
+        assert!(err.to_string().contains("60% of the 100-byte retasyncd"), "{err:#}");
+        assert!(check_size_ratio(1, 0, 0.5).is_err());
+    }
+}
@@ -1,58 +1,359 @@
-﻿use std::path::PathBuf;
+﻿use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 
 use anyhow::{bail, Context, Result};
 use retasync_codegen::render_contracts_module;
+use retasync_convert::lint::{self, LintOptions};
 
-fn main() -> Result<()> {
+mod contracts;
+mod features;
+mod vectors;
+
+use contracts::{BumpLevel, Drift, Workspace};
+use features::{FeatureSet, DEFAULT_MAX_SIZE_RATIO, FEATURE_SETS};
+
+// Exit statuses, so CI can tell stale committed output from a run that could not compare at all.
+const EXIT_DRIFT: u8 = 1;
+const EXIT_FAILED: u8 = 2;
+
+fn main() -> ExitCode {
     let args: Vec<String> = std::env::args().collect();
-    if args.len() < 2 || args[1] != "codegen" {
-        print_usage();
-        return Ok(());
+    // `cargo run -p xtask` points this at xtask/ wherever it was invoked from; a bare binary
+    // searches from the current directory instead.
+    let start = match std::env::var_os("CARGO_MANIFEST_DIR") {
+        Some(dir) => PathBuf::from(dir),
+        None => std::env::current_dir().unwrap_or_default(),
+    };
+
+    match run(&args, &start) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {err:#}");
+            ExitCode::from(exit_status(&err))
+        }
     }
+}
 
-    let check_mode = args.iter().any(|arg| arg == "--check");
+fn run(args: &[String], start: &Path) -> Result<()> {
+    match args.get(1).map(String::as_str) {
+        Some("codegen") => codegen(start, args),
+        Some("vectors") => contract_vectors(start, args),
+        Some("contracts") if args.get(2).map(String::as_str) == Some("bump") => {
+            contracts_bump(&Workspace::locate(start)?, args)
+        }
+        Some("features") if args.get(2).map(String::as_str) == Some("size") => {
+            feature_sizes(&Workspace::locate(start)?, args)
+        }
+        Some("features") => feature_sets(&Workspace::locate(start)?, args),
+        _ => {
+            print_usage();
+            Ok(())
+        }
+    }
+}
 
-    let workspace_root = PathBuf::from(
-        std::env::var("CARGO_MANIFEST_DIR")
-            .context("CARGO_MANIFEST_DIR not set")?
-            .trim_end_matches("\\xtask")
-            .trim_end_matches("/xtask"),
-    );
+fn exit_status(err: &anyhow::Error) -> u8 {
+    if err.downcast_ref::<Drift>().is_some() {
+        EXIT_DRIFT
+    } else {
+        EXIT_FAILED
+    }
+}
 
-    let contract_path = workspace_root.join("contracts/retasyncapi-v1.asyncapi.yaml");
-    let generated_path = workspace_root.join("crates/retasync_contract/src/generated/contracts.rs");
+fn codegen(start: &Path, args: &[String]) -> Result<()> {
+    let check_mode = args.iter().any(|arg| arg == "--check");
+    let contract_path = path_flag(args, "--contract", start, Workspace::contract_path)?;
+    let generated_path = path_flag(args, "--out", start, Workspace::generated_path)?;
 
     let contract_source = std::fs::read_to_string(&contract_path)
         .with_context(|| format!("failed reading {}", contract_path.display()))?;
+    if args.iter().any(|arg| arg == "--lint") {
+        lint_contract(&contract_path, &contract_source)?;
+    }
     let rendered = render_contracts_module(&contract_source)?;
 
     if check_mode {
         let existing = std::fs::read_to_string(&generated_path)
             .with_context(|| format!("failed reading {}", generated_path.display()))?;
 
-        if normalize_newlines(&existing) != normalize_newlines(&rendered) {
-            bail!(
+        if !contracts::same_text(&existing, &rendered) {
+            return Err(Drift(format!(
                 "generated contracts drift detected: run `cargo xtask codegen` to refresh {}",
                 generated_path.display()
-            );
+            ))
+            .into());
         }
 
         println!("codegen check passed");
         return Ok(());
     }
 
-    std::fs::write(&generated_path, rendered)
-        .with_context(|| format!("failed writing {}", generated_path.display()))?;
+    if let Some(parent) = generated_path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+    {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("failed creating {}", parent.display()))?;
+    }
+    contracts::write_generated(&generated_path, &rendered)?;
     println!("generated {}", generated_path.display());
     Ok(())
 }
 
-fn normalize_newlines(input: &str) -> String {
-    input
-        .trim_start_matches('\u{feff}')
-        .replace("\r\n", "\n")
+// Holds the contract to the lint rules whose findings are errors before anything is generated
+// from it; warnings and infos are left to `retasync-convert lint`.
+fn lint_contract(contract_path: &Path, contract_source: &str) -> Result<()> {
+    let rules = lint::select_rules(&LintOptions {
+        errors_only: true,
+        ..LintOptions::default()
+    })?;
+    let report = lint::lint_source(contract_source, &rules)?;
+    if report.summary.errors > 0 {
+        report.print_console();
+        bail!(
+            "contract lint found {} error(s) in {}",
+            report.summary.errors,
+            contract_path.display()
+        );
+    }
+    Ok(())
+}
+
+fn contracts_bump(workspace: &Workspace, args: &[String]) -> Result<()> {
+    let baseline = flag_value(args, "--baseline").map(PathBuf::from);
+
+    if args.iter().any(|arg| arg == "--check") {
+        contracts::check(workspace, baseline.as_deref())?;
+        println!("contract version check passed");
+        return Ok(());
+    }
+
+    let level = BumpLevel::parse(flag_value(args, "--level").unwrap_or("patch"))?;
+    let Some(note) = flag_value(args, "--note") else {
+        bail!("contracts bump requires --note \"<what changed>\"");
+    };
+
+    let entry = contracts::bump(workspace, level, note, baseline.as_deref())?;
+    println!(
+        "bumped contract to {} and recorded {}",
+        entry.version,
+        contracts::CHANGELOG_PATH
+    );
+    Ok(())
+}
+
+fn contract_vectors(start: &Path, args: &[String]) -> Result<()> {
+    let contract_path = path_flag(args, "--contract", start, Workspace::contract_path)?;
+    let vectors_path = path_flag(args, "--out", start, Workspace::vectors_path)?;
+    let contract_source = std::fs::read_to_string(&contract_path)
+        .with_context(|| format!("failed reading {}", contract_path.display()))?;
+    let rendered = vectors::render(&contract_source)?;
+
+    if args.iter().any(|arg| arg == "--check") {
+        vectors::check(&vectors_path, &rendered)?;
+        println!("contract vectors check passed");
+        return Ok(());
+    }
+
+    vectors::write(&vectors_path, &rendered)?;
+    println!(
+        "wrote {} contract vector files to {}",
+        rendered.len(),
+        vectors_path.display()
+    );
+    Ok(())
+}
+
+// Every set unless `--set` names one; `--no-test` stops after build and clippy.
+fn feature_sets(workspace: &Workspace, args: &[String]) -> Result<()> {
+    let sets = match flag_value(args, "--set") {
+        Some(name) => vec![FeatureSet::named(name)?],
+        None => FEATURE_SETS.iter().collect(),
+    };
+    let run_tests = !args.iter().any(|arg| arg == "--no-test");
+    for set in sets {
+        println!("checking feature set {}", set.name);
+        set.check(workspace.root(), run_tests)?;
+    }
+    println!("feature set checks passed");
+    Ok(())
+}
+
+fn feature_sizes(workspace: &Workspace, args: &[String]) -> Result<()> {
+    let max_ratio = match flag_value(args, "--max-ratio") {
+        Some(raw) => raw
+            .parse()
+            .with_context(|| format!("--max-ratio {raw:?} is not a number"))?,
+        None => DEFAULT_MAX_SIZE_RATIO,
+    };
+    features::check_binary_sizes(workspace.root(), max_ratio)
+}
+
+fn flag_value<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|arg| arg == flag)
+        .and_then(|idx| args.get(idx + 1))
+        .map(String::as_str)
+}
+
+// The path given with `flag`, relative to the invocation directory, or the default location in
+// the workspace found from `start`. With every path given no workspace is needed at all.
+fn path_flag(
+    args: &[String],
+    flag: &str,
+    start: &Path,
+    default: fn(&Workspace) -> PathBuf,
+) -> Result<PathBuf> {
+    match flag_value(args, flag) {
+        Some(path) => Ok(PathBuf::from(path)),
+        None => Ok(default(&Workspace::locate(start)?)),
+    }
 }
 
 fn print_usage() {
-    eprintln!("Usage: cargo xtask codegen [--check]");
+    eprintln!(
+        "Usage: cargo xtask codegen [--check] [--lint] [--contract <file>] [--out <file>]"
+    );
+    eprintln!("       cargo xtask vectors [--check] [--contract <file>] [--out <dir>]");
+    eprintln!(
+        "       cargo xtask contracts bump --level <major|minor|patch> --note <text> [--baseline <file>]"
+    );
+    eprintln!("       cargo xtask contracts bump --check [--baseline <file>]");
+    eprintln!("       cargo xtask features [--set <name>] [--no-test]");
+    eprintln!("       cargo xtask features size [--max-ratio <fraction>]");
+    eprintln!("--check exits {EXIT_DRIFT} on drift and {EXIT_FAILED} when generation fails");
+    eprintln!("--lint fails codegen with {EXIT_FAILED} when the contract breaks a lint error rule");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use contracts::{CONTRACT_PATH, GENERATED_PATH};
+    use retasync_codegen::contract_digest;
+
+    const CONTRACT: &str = "asyncapi: \"3.0.0\"
+info:
+  title: Fixture
+  version: \"1.0.0\"
+x-retasync:
+  operations:
+    commands:
+      - event.create
+";
+
+    fn temp_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "xtask-main-{}-{}/{name}",
+            std::process::id(),
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_nanos()
+        ))
+    }
+
+    // A workspace whose own directory name ends in "xtask", with a member crate of that name
+    // nested under it as the invocation directory.
+    fn fixture() -> (PathBuf, PathBuf) {
+        let root = temp_dir("mesh-xtask");
+        let nested = root.join("tools/xtask");
+        std::fs::create_dir_all(&nested).unwrap();
+        std::fs::create_dir_all(root.join(CONTRACT_PATH).parent().unwrap()).unwrap();
+        std::fs::write(
+            root.join("Cargo.toml"),
+            "\u{feff}[workspace]\nmembers = [\"tools/xtask\"]\n",
+        )
+        .unwrap();
+        std::fs::write(nested.join("Cargo.toml"), "[package]\nname = \"xtask\"\n").unwrap();
+        std::fs::write(root.join(CONTRACT_PATH), CONTRACT).unwrap();
+        (root, nested)
+    }
+
+    fn args(list: &[&str]) -> Vec<String> {
+        std::iter::once("xtask")
+            .chain(list.iter().copied())
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn codegen_finds_the_workspace_from_a_nested_member_and_reports_drift() {
+        let (root, nested) = fixture();
+        assert_eq!(
+            Workspace::locate(&nested).unwrap().contract_path(),
+            root.join(CONTRACT_PATH)
+        );
+
+        run(&args(&["codegen"]), &nested).unwrap();
+        let generated = std::fs::read_to_string(root.join(GENERATED_PATH)).unwrap();
+        let header = format!("// Source contract sha256: {}", contract_digest(CONTRACT));
+        assert_eq!(generated.lines().nth(1), Some(header.as_str()));
+        run(&args(&["codegen", "--check"]), &nested).unwrap();
+
+        // The header changes with the contract even when nothing it generates does.
+        std::fs::write(root.join(CONTRACT_PATH), CONTRACT.replace("1.0.0", "1.0.1")).unwrap();
+        let err = run(&args(&["codegen", "--check"]), &nested).unwrap_err();
+        assert_eq!(exit_status(&err), EXIT_DRIFT, "{err:#}");
+
+        std::fs::write(root.join(CONTRACT_PATH), "info: [unclosed").unwrap();
+        let err = run(&args(&["codegen", "--check"]), &nested).unwrap_err();
+        assert_eq!(exit_status(&err), EXIT_FAILED, "{err:#}");
+        std::fs::remove_dir_all(root.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn explicit_paths_generate_and_check_outside_any_workspace() {
+        let (root, _) = fixture();
+        let outside = temp_dir("scratch");
+        let out = outside.join("experiments/contracts.rs");
+        let contract = root.join(CONTRACT_PATH);
+        let explicit = |extra: &[&str]| {
+            let mut list = vec!["codegen", "--contract", contract.to_str().unwrap()];
+            list.extend(["--out", out.to_str().unwrap()]);
+            list.extend(extra);
+            args(&list)
+        };
+
+        run(&explicit(&[]), &outside).unwrap();
+        assert!(!root.join(GENERATED_PATH).exists());
+        run(&explicit(&["--check"]), &outside).unwrap();
+
+        // Without every path given, a directory outside any workspace has no defaults.
+        let err = run(
+            &args(&["codegen", "--out", out.to_str().unwrap()]),
+            &outside,
+        )
+        .unwrap_err();
+        assert_eq!(exit_status(&err), EXIT_FAILED);
+        assert!(err.to_string().contains("no workspace"), "{err:#}");
+
+        std::fs::write(&out, "// stale\n").unwrap();
+        let err = run(&explicit(&["--check"]), &outside).unwrap_err();
+        assert_eq!(exit_status(&err), EXIT_DRIFT);
+        assert!(err.to_string().contains(out.to_str().unwrap()), "{err:#}");
+        std::fs::remove_dir_all(root.parent().unwrap()).unwrap();
+        std::fs::remove_dir_all(outside.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn lint_runs_before_codegen_only_when_asked() {
+        let (root, nested) = fixture();
+        std::fs::write(
+            root.join(CONTRACT_PATH),
+            CONTRACT.replace("event.create", "EventCreate"),
+        )
+        .unwrap();
+
+        let err = run(&args(&["codegen", "--lint"]), &nested).unwrap_err();
+        assert_eq!(exit_status(&err), EXIT_FAILED);
+        assert!(err.to_string().contains("contract lint found 1 error(s)"), "{err:#}");
+        assert!(!root.join(GENERATED_PATH).exists());
+
+        // Rules whose findings are warnings or infos never stop codegen.
+        let unsorted = CONTRACT.replace("- event.create", "- event.list\n      - event.create");
+        std::fs::write(root.join(CONTRACT_PATH), unsorted).unwrap();
+        run(&args(&["codegen", "--lint"]), &nested).unwrap();
+        run(&args(&["codegen", "--lint", "--check"]), &nested).unwrap();
+        std::fs::remove_dir_all(root.parent().unwrap()).unwrap();
+    }
 }
@@ -0,0 +1,266 @@
+﻿// Contract test vectors: one fully populated envelope per command and event, stored as JSON
+// alongside its canonical MessagePack encoding and digest so other implementations can check
+// their codecs byte for byte. Layout under `contracts/vectors/`:
+//
+//   index.json                     contract version, encoding, and one entry per vector
+//   commands/<operation>.json      the envelope as JSON
+//   commands/<operation>.msgpack.hex  `encode_canonical` output, lowercase hex
+//   commands/<operation>.sha256    `canonical_digest` output
+//   events/...                     the same for events
+//
+// Every file is rendered deterministically and ends with a newline.
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use retasync_codegen::{sample_envelopes, SampleKind};
+use retasync_contract::{canonical_digest, encode_canonical};
+use serde_json::json;
+
+use crate::contracts::{contract_version, same_text, Drift};
+
+pub const INDEX_FORMAT: u32 = 1;
+pub const ENCODING: &str = "msgpack-named-sorted-keys";
+
+// Relative path (always `/`-separated) to file contents.
+pub type RenderedVectors = BTreeMap<String, String>;
+
+pub fn render(contract_source: &str) -> Result<RenderedVectors> {
+    let version = contract_version(contract_source)?;
+    let mut files = RenderedVectors::new();
+    let mut entries = Vec::new();
+
+    for sample in sample_envelopes(contract_source)? {
+        let dir = match sample.kind {
+            SampleKind::Command => "commands",
+            SampleKind::Event => "events",
+        };
+        let stem = format!("{dir}/{}", sample.operation);
+        let encoded = encode_canonical(&sample.envelope)
+            .with_context(|| format!("failed encoding {}", sample.operation))?;
+        let digest = canonical_digest(&sample.envelope)
+            .with_context(|| format!("failed hashing {}", sample.operation))?;
+
+        files.insert(format!("{stem}.json"), pretty(&sample.envelope)?);
+        files.insert(
+            format!("{stem}.msgpack.hex"),
+            format!("{}\n", to_hex(&encoded)),
+        );
+        files.insert(format!("{stem}.sha256"), format!("{digest}\n"));
+        entries.push(json!({
+            "kind": sample.kind.as_str(),
+            "operation": sample.operation,
+            "payload_schema": sample.payload_schema,
+            "json": format!("{stem}.json"),
+            "msgpack_hex": format!("{stem}.msgpack.hex"),
+            "sha256": digest,
+            "encoded_len": encoded.len(),
+        }));
+    }
+
+    files.insert(
+        "index.json".to_string(),
+        pretty(&json!({
+            "format": INDEX_FORMAT,
+            "contract_version": version.to_string(),
+            "generator": "cargo xtask vectors",
+            "encoding": ENCODING,
+            "vectors": entries,
+        }))?,
+    );
+    Ok(files)
+}
+
+// Writes every rendered file and removes ones the contract no longer produces.
+pub fn write(dir: &Path, rendered: &RenderedVectors) -> Result<()> {
+    for stale in existing_files(dir)?
+        .into_iter()
+        .filter(|path| !rendered.contains_key(path))
+    {
+        let path = dir.join(&stale);
+        std::fs::remove_file(&path)
+            .with_context(|| format!("failed removing {}", path.display()))?;
+    }
+    for (relative, contents) in rendered {
+        let path = dir.join(relative);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("failed creating {}", parent.display()))?;
+        }
+        std::fs::write(&path, contents)
+            .with_context(|| format!("failed writing {}", path.display()))?;
+    }
+    Ok(())
+}
+
+pub fn check(dir: &Path, rendered: &RenderedVectors) -> Result<()> {
+    let existing = existing_files(dir)?;
+    let mut problems = Vec::new();
+    for (relative, contents) in rendered {
+        let path = dir.join(relative);
+        match std::fs::read_to_string(&path) {
+            Ok(committed) if same_text(&committed, contents) => {}
+            Ok(_) => problems.push(format!("changed: {relative}")),
+            Err(_) => problems.push(format!("missing: {relative}")),
+        }
+    }
+    problems.extend(
+        existing
+            .iter()
+            .filter(|path| !rendered.contains_key(*path))
+            .map(|path| format!("unexpected: {path}")),
+    );
+
+    if !problems.is_empty() {
+        return Err(Drift(format!(
+            "contract vectors drift detected: run `cargo xtask vectors` to refresh {}\n  {}",
+            dir.display(),
+            problems.join("\n  ")
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+fn pretty(value: &serde_json::Value) -> Result<String> {
+    Ok(format!("{}\n", serde_json::to_string_pretty(value)?))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn existing_files(dir: &Path) -> Result<Vec<String>> {
+    let mut files = Vec::new();
+    if dir.exists() {
+        collect_files(dir, &PathBuf::new(), &mut files)?;
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn collect_files(root: &Path, relative: &Path, files: &mut Vec<String>) -> Result<()> {
+    let dir = root.join(relative);
+    for entry in
+        std::fs::read_dir(&dir).with_context(|| format!("failed reading {}", dir.display()))?
+    {
+        let entry = entry?;
+        let path = relative.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            collect_files(root, &path, files)?;
+        } else {
+            let parts: Vec<String> = path
+                .components()
+                .map(|part| part.as_os_str().to_string_lossy().into_owned())
+                .collect();
+            files.push(parts.join("/"));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contracts::CONTRACT_PATH;
+    use retasync_contract::{decode_canonical, MeshCommandEnvelope, MeshEventEnvelope};
+
+    fn contract() -> String {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("..");
+        std::fs::read_to_string(root.join(CONTRACT_PATH)).unwrap()
+    }
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "xtask-vectors-{}-{}",
+            std::process::id(),
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_nanos()
+        ))
+    }
+
+    fn from_hex(hex: &str) -> Vec<u8> {
+        let hex = hex.trim();
+        (0..hex.len())
+            .step_by(2)
+            .map(|idx| u8::from_str_radix(&hex[idx..idx + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn rendering_is_deterministic_and_covers_every_operation() {
+        let source = contract();
+        let first = render(&source).unwrap();
+        assert_eq!(first, render(&source).unwrap());
+
+        let index: serde_json::Value = serde_json::from_str(&first["index.json"]).unwrap();
+        let entries = index["vectors"].as_array().unwrap();
+        assert_eq!(entries.len(), sample_envelopes(&source).unwrap().len());
+        assert_eq!(first.len(), entries.len() * 3 + 1);
+        assert!(first.values().all(|contents| contents.ends_with('\n')));
+    }
+
+    #[test]
+    fn check_reports_perturbed_missing_and_unexpected_files() {
+        let rendered = render(&contract()).unwrap();
+        let dir = temp_dir();
+        write(&dir, &rendered).unwrap();
+        check(&dir, &rendered).unwrap();
+
+        let hex = rendered
+            .keys()
+            .find(|path| path.ends_with(".msgpack.hex"))
+            .unwrap()
+            .clone();
+        let mut perturbed = rendered[&hex].clone();
+        perturbed.replace_range(
+            0..2,
+            if perturbed.starts_with("00") {
+                "01"
+            } else {
+                "00"
+            },
+        );
+        std::fs::write(dir.join(&hex), perturbed).unwrap();
+        std::fs::write(dir.join("commands/retired.op.json"), "{}\n").unwrap();
+        std::fs::remove_file(dir.join("index.json")).unwrap();
+
+        let message = check(&dir, &rendered).unwrap_err().to_string();
+        assert!(message.contains(&format!("changed: {hex}")), "{message}");
+        assert!(message.contains("missing: index.json"), "{message}");
+        assert!(
+            message.contains("unexpected: commands/retired.op.json"),
+            "{message}"
+        );
+
+        write(&dir, &rendered).unwrap();
+        check(&dir, &rendered).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn msgpack_vectors_decode_back_to_their_json() {
+        let rendered = render(&contract()).unwrap();
+        let index: serde_json::Value = serde_json::from_str(&rendered["index.json"]).unwrap();
+        for entry in index["vectors"].as_array().unwrap() {
+            let json: serde_json::Value =
+                serde_json::from_str(&rendered[entry["json"].as_str().unwrap()]).unwrap();
+            let bytes = from_hex(&rendered[entry["msgpack_hex"].as_str().unwrap()]);
+            let decoded: serde_json::Value = decode_canonical(&bytes).unwrap();
+            assert_eq!(decoded, json, "{}", entry["operation"]);
+            assert_eq!(canonical_digest(&decoded).unwrap(), entry["sha256"]);
+
+            // The vectors must also be valid envelopes for this crate's own types.
+            match entry["kind"].as_str().unwrap() {
+                "command" => {
+                    decode_canonical::<MeshCommandEnvelope<serde_json::Value>>(&bytes).unwrap();
+                }
+                _ => {
+                    decode_canonical::<MeshEventEnvelope<serde_json::Value>>(&bytes).unwrap();
+                }
+            }
+        }
+    }
+}